@@ -0,0 +1,275 @@
+// Admin page: tables over the JSON API, with the session cookie for
+// authentication and the double-submitted CSRF token on every call that
+// changes something. Data only ever reaches the page through textContent.
+"use strict";
+
+const PAGE_SIZE = 25;
+let csrfToken = null;
+
+function setStatus(message, isError) {
+  const status = document.getElementById("status");
+  status.textContent = message;
+  status.className = isError ? "error" : "";
+}
+
+async function fetchCsrfToken() {
+  const response = await fetch("/admin/csrf-token", { credentials: "same-origin" });
+  const body = await response.json();
+  csrfToken = body.data.token;
+}
+
+async function api(method, path, body) {
+  const options = { method, credentials: "same-origin", headers: {} };
+  if (method !== "GET") {
+    if (!csrfToken) {
+      await fetchCsrfToken();
+    }
+    options.headers["X-CSRF-Token"] = csrfToken;
+  }
+  if (body !== undefined) {
+    options.headers["Content-Type"] = "application/json";
+    options.body = JSON.stringify(body);
+  }
+
+  const response = await fetch(path, options);
+  if (response.status === 401) {
+    window.location.assign("/admin/login");
+    throw new Error("Signed out");
+  }
+  const text = await response.text();
+  const data = text ? JSON.parse(text) : {};
+  if (!response.ok) {
+    throw new Error(data.error || data.message || "Request failed (" + response.status + ")");
+  }
+  return data;
+}
+
+function cell(text) {
+  const td = document.createElement("td");
+  td.textContent = text === null || text === undefined ? "" : String(text);
+  return td;
+}
+
+function actionButton(label, action, id) {
+  const button = document.createElement("button");
+  button.type = "button";
+  button.textContent = label;
+  button.dataset.action = action;
+  button.dataset.id = String(id);
+  return button;
+}
+
+function actionsCell(buttons) {
+  const td = document.createElement("td");
+  td.className = "actions";
+  buttons.forEach((button) => td.appendChild(button));
+  return td;
+}
+
+function formatTime(value) {
+  return value ? new Date(value).toLocaleString() : "";
+}
+
+// A paged table in one of the page's sections. `load(page)` resolves to
+// `{ rows, hasMore }`.
+class Panel {
+  constructor(id, load) {
+    this.section = document.getElementById(id);
+    this.load = load;
+    this.page = 1;
+    this.hasMore = false;
+  }
+
+  get present() {
+    return this.section !== null && !this.section.classList.contains("disabled");
+  }
+
+  async refresh() {
+    if (!this.present) {
+      return;
+    }
+    const { rows, hasMore } = await this.load(this.page);
+    const tbody = this.section.querySelector("tbody");
+    if (tbody) {
+      tbody.replaceChildren(...rows);
+    }
+    this.hasMore = hasMore;
+    const label = this.section.querySelector(".page-label");
+    if (label) {
+      label.textContent = "Page " + this.page;
+    }
+    const prev = this.section.querySelector('[data-page="prev"]');
+    const next = this.section.querySelector('[data-page="next"]');
+    if (prev) {
+      prev.disabled = this.page <= 1;
+    }
+    if (next) {
+      next.disabled = !this.hasMore;
+    }
+  }
+}
+
+const panels = {
+  items: new Panel("items", async (page) => {
+    const body = await api("GET", "/api/items?page=" + page + "&page_size=" + PAGE_SIZE);
+    const rows = body.data.items.map((item) => {
+      const tr = document.createElement("tr");
+      tr.append(
+        cell(item.id),
+        cell(item.name),
+        cell((item.tags || []).join(", ")),
+        cell(formatTime(item.updated_at)),
+        actionsCell([actionButton("Delete", "delete-item", item.id)])
+      );
+      return tr;
+    });
+    return { rows, hasMore: body.data.count === PAGE_SIZE };
+  }),
+
+  trash: new Panel("trash", async (page) => {
+    const offset = (page - 1) * PAGE_SIZE;
+    const body = await api("GET", "/api/items/trash?limit=" + PAGE_SIZE + "&offset=" + offset);
+    const rows = body.data.items.map((item) => {
+      const tr = document.createElement("tr");
+      tr.append(
+        cell(item.id),
+        cell(item.name),
+        cell(formatTime(item.deleted_at)),
+        actionsCell([actionButton("Restore", "restore-item", item.id)])
+      );
+      return tr;
+    });
+    return { rows, hasMore: body.data.has_more };
+  }),
+
+  jobs: new Panel("jobs", async (page) => {
+    const offset = (page - 1) * PAGE_SIZE;
+    const [list, stats] = await Promise.all([
+      api("GET", "/api/jobs?limit=" + PAGE_SIZE + "&offset=" + offset),
+      api("GET", "/api/jobs/stats"),
+    ]);
+    const s = stats.data;
+    panels.jobs.section.querySelector(".stats").textContent =
+      s.pending_jobs + " pending, " + s.running_jobs + " running, " + s.completed_jobs +
+      " completed, " + s.failed_jobs + " failed; " + s.active_workers + " workers";
+    const rows = list.data.jobs.map((job) => {
+      const buttons = [];
+      if (job.status === "Failed" || job.status === "Cancelled") {
+        buttons.push(actionButton("Retry", "retry-job", job.id));
+      }
+      if (job.status === "Pending" || job.status === "Running" || job.status === "Retrying") {
+        buttons.push(actionButton("Cancel", "cancel-job", job.id));
+      }
+      const tr = document.createElement("tr");
+      tr.append(
+        cell(job.id),
+        cell(job.job_type),
+        cell(job.status),
+        cell(formatTime(job.created_at)),
+        cell(job.error_message),
+        actionsCell(buttons)
+      );
+      return tr;
+    });
+    return { rows, hasMore: offset + list.data.jobs.length < list.data.total };
+  }),
+
+  users: new Panel("users", async (page) => {
+    const offset = (page - 1) * PAGE_SIZE;
+    const body = await api("GET", "/api/admin/users?limit=" + PAGE_SIZE + "&offset=" + offset);
+    const rows = body.data.users.map((user) => {
+      const tr = document.createElement("tr");
+      const toggle = user.is_active
+        ? actionButton("Deactivate", "deactivate-user", user.id)
+        : actionButton("Activate", "activate-user", user.id);
+      tr.append(cell(user.id), cell(user.username), cell(user.role), cell(user.is_active ? "yes" : "no"), actionsCell([toggle]));
+      return tr;
+    });
+    return { rows, hasMore: offset + body.data.users.length < body.data.total };
+  }),
+
+  cache: new Panel("cache", async () => {
+    const body = await api("GET", "/api/cache/stats");
+    const s = body.cache_stats;
+    if (s) {
+      panels.cache.section.querySelector(".stats").textContent =
+        s.current_size + " of " + s.max_size + " entries; " + s.hits + " hits, " + s.misses +
+        " misses, " + s.evictions + " evictions";
+    }
+    return { rows: [], hasMore: false };
+  }),
+};
+
+// What each button does, and which panels to reload afterwards.
+const actions = {
+  "delete-item": { run: (id) => api("DELETE", "/api/items/" + id), refresh: ["items", "trash"], done: "Item moved to the trash" },
+  "restore-item": { run: (id) => api("POST", "/api/items/trash/" + id + "/restore"), refresh: ["items", "trash"], done: "Item restored" },
+  "retry-job": { run: (id) => api("POST", "/api/jobs/" + id + "/retry"), refresh: ["jobs"], done: "Job queued again" },
+  "cancel-job": { run: (id) => api("DELETE", "/api/jobs/" + id + "/cancel"), refresh: ["jobs"], done: "Job cancelled" },
+  "cleanup-jobs": { run: () => api("POST", "/api/jobs/cleanup"), refresh: ["jobs"], done: "Old jobs cleaned up" },
+  "activate-user": { run: (id) => api("PATCH", "/api/admin/users/" + id, { is_active: true }), refresh: ["users"], done: "User activated" },
+  "deactivate-user": { run: (id) => api("PATCH", "/api/admin/users/" + id, { is_active: false }), refresh: ["users"], done: "User deactivated" },
+  "clear-cache": { run: () => api("POST", "/api/cache/clear"), refresh: ["cache"], done: "Cache cleared" },
+};
+
+async function refresh(names) {
+  for (const name of names) {
+    try {
+      await panels[name].refresh();
+    } catch (error) {
+      setStatus("Couldn't load " + name + ": " + error.message, true);
+    }
+  }
+}
+
+async function signOut() {
+  try {
+    await api("DELETE", "/admin/session");
+  } finally {
+    window.location.assign("/admin/login");
+  }
+}
+
+document.addEventListener("click", async (event) => {
+  const button = event.target.closest("button");
+  if (!button) {
+    return;
+  }
+
+  const section = button.closest("section");
+  if (button.dataset.page && section && panels[section.id]) {
+    const panel = panels[section.id];
+    panel.page = Math.max(1, panel.page + (button.dataset.page === "next" ? 1 : -1));
+    await refresh([section.id]);
+    return;
+  }
+
+  if (button.dataset.action === "sign-out") {
+    await signOut();
+    return;
+  }
+
+  const action = actions[button.dataset.action];
+  if (!action) {
+    return;
+  }
+  button.disabled = true;
+  try {
+    await action.run(button.dataset.id);
+    setStatus(action.done, false);
+    await refresh(action.refresh);
+  } catch (error) {
+    setStatus(error.message, true);
+  } finally {
+    button.disabled = false;
+  }
+});
+
+document.addEventListener("DOMContentLoaded", async () => {
+  try {
+    await fetchCsrfToken();
+  } catch (error) {
+    setStatus("Couldn't get a CSRF token: " + error.message, true);
+  }
+  await refresh(Object.keys(panels));
+});
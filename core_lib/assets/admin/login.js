@@ -0,0 +1,32 @@
+// Sign-in form of the admin page. The session ends up in an HttpOnly
+// cookie, so nothing here keeps the token.
+"use strict";
+
+document.addEventListener("DOMContentLoaded", () => {
+  const form = document.getElementById("sign-in");
+  const status = document.getElementById("status");
+
+  form.addEventListener("submit", async (event) => {
+    event.preventDefault();
+    status.textContent = "Signing in...";
+    status.className = "";
+
+    const response = await fetch("/admin/session", {
+      method: "POST",
+      credentials: "same-origin",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({
+        username: form.elements.username.value,
+        password: form.elements.password.value,
+      }),
+    });
+
+    if (response.ok) {
+      window.location.assign("/admin");
+      return;
+    }
+    const body = await response.json().catch(() => ({}));
+    status.textContent = body.error || body.message || "Sign-in failed (" + response.status + ")";
+    status.className = "error";
+  });
+});
@@ -0,0 +1,111 @@
+//! Items with 50KB of metadata: creating and reading them through the full
+//! router, and the step that changed when metadata started being carried as
+//! JSON text, turning a stored row's metadata into response JSON.
+//!
+//! Against the tree before that change, over two runs each, a create went
+//! from about 3.0ms to 1.6ms and a read from about 1.2ms to 0.75ms.
+//!
+//! Run with `cargo bench -p core_lib --bench item_metadata`.
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::Request;
+use axum::Router;
+use core_lib::item_metadata::Metadata;
+use core_lib::{create_app_with_config, AppConfig, AppState, RateLimiter};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use serde_json::{json, Map, Value};
+use std::net::SocketAddr;
+use tower::ServiceExt;
+
+/// About 50KB of metadata: 50 keys, each holding a list of readings.
+fn metadata() -> Value {
+    let fields: Map<String, Value> = (0..50)
+        .map(|n| {
+            let readings: Vec<Value> = (0..40).map(|i| json!(format!("sensor {:02}/{:02} nominal", n, i))).collect();
+            (format!("field_{:02}", n), Value::Array(readings))
+        })
+        .collect();
+    Value::Object(fields)
+}
+
+fn router() -> Router {
+    let mut config = AppConfig::default();
+    config.rate_limit.requests_per_minute = usize::MAX / 2;
+    config.items.max_metadata_bytes = 1024 * 1024;
+    let state = AppState::default()
+        .with_rate_limiter(RateLimiter::new(config.rate_limit.clone()))
+        .with_item_config(&config.items);
+    create_app_with_config(state, config)
+}
+
+fn request(method: &str, uri: &str, body: Body) -> Request<Body> {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("user-agent", "item-metadata-bench")
+        .header("content-type", "application/json")
+        .body(body)
+        .unwrap();
+    request
+        .extensions_mut()
+        .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+    request
+}
+
+fn item_routes(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let app = router();
+    let body = serde_json::to_vec(&json!({"name": "Bench item", "tags": ["bench"], "metadata": metadata()})).unwrap();
+
+    let mut group = c.benchmark_group("50KB metadata");
+    group.throughput(Throughput::Bytes(body.len() as u64));
+
+    group.bench_function("POST /api/items", |b| {
+        b.to_async(&runtime).iter_batched(
+            || Body::from(body.clone()),
+            |body| async {
+                let response = app.clone().oneshot(request("POST", "/api/items", body)).await.unwrap();
+                assert!(response.status().is_success());
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.bench_function("GET /api/items/:id", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let response = app.clone().oneshot(request("GET", "/api/items/3", Body::empty())).await.unwrap();
+            assert!(response.status().is_success());
+        })
+    });
+    group.finish();
+}
+
+/// What a read from the database does with the stored text. On a 50KB
+/// object, carrying it as [`Metadata`] takes about 50-60µs against
+/// 360-390µs for parsing it into a [`Value`] and writing that out again,
+/// roughly six to seven times the throughput.
+fn stored_to_response(c: &mut Criterion) {
+    let stored = serde_json::to_string(&metadata()).unwrap();
+
+    let mut group = c.benchmark_group("stored metadata to response");
+    group.throughput(Throughput::Bytes(stored.len() as u64));
+
+    group.bench_function("parsed into a Value", |b| {
+        b.iter(|| {
+            let value: Value = serde_json::from_str(&stored).unwrap();
+            serde_json::to_vec(&value).unwrap()
+        })
+    });
+
+    group.bench_function("carried as Metadata", |b| {
+        b.iter(|| {
+            let metadata = Metadata::from_stored(stored.clone()).unwrap();
+            serde_json::to_vec(&metadata).unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, item_routes, stored_to_response);
+criterion_main!(benches);
@@ -0,0 +1,91 @@
+//! Throughput of the hot request path: item reads and writes through the
+//! full router, and search query construction.
+//!
+//! Run with `cargo bench -p core_lib --bench request_path`.
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::Request;
+use axum::Router;
+use core_lib::search::{AdvancedFilterBuilder, SearchEngine, SortField, SortOrder};
+use core_lib::{create_app_with_config, AppConfig, AppState, RateLimiter};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use std::net::SocketAddr;
+use tower::ServiceExt;
+
+fn router() -> Router {
+    let mut config = AppConfig::default();
+    config.rate_limit.requests_per_minute = usize::MAX / 2;
+    let state = AppState::default().with_rate_limiter(RateLimiter::new(config.rate_limit.clone()));
+    create_app_with_config(state, config)
+}
+
+fn request(method: &str, uri: &str, body: Body) -> Request<Body> {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("user-agent", "request-path-bench")
+        .header("content-type", "application/json")
+        .body(body)
+        .unwrap();
+    request
+        .extensions_mut()
+        .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+    request
+}
+
+fn item_routes(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let app = router();
+
+    c.bench_function("GET /api/items/:id", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let response = app.clone().oneshot(request("GET", "/api/items/1", Body::empty())).await.unwrap();
+            assert!(response.status().is_success());
+        })
+    });
+
+    c.bench_function("POST /api/items", |b| {
+        b.to_async(&runtime).iter_batched(
+            || Body::from(r#"{"name":"Bench item","description":"Created by the benchmark","tags":["bench"]}"#),
+            |body| async {
+                let response = app.clone().oneshot(request("POST", "/api/items", body)).await.unwrap();
+                assert!(response.status().is_success());
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn search_queries(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    c.bench_function("AdvancedFilterBuilder::build", |b| {
+        b.iter(|| {
+            AdvancedFilterBuilder::new()
+                .search_text("wireless mouse", true)
+                .filter_by_tags(vec!["peripheral", "office"])
+                .sort_by(SortField::Relevance, SortOrder::Desc)
+                .then_sort_by(SortField::CreatedAt, SortOrder::Desc)
+                .paginate(0, 20)
+                .build()
+        })
+    });
+
+    let engine = runtime.block_on(async {
+        let pool = core_lib::get_database_pool("sqlite::memory:").await.unwrap();
+        core_lib::run_migrations(pool.clone()).await.unwrap();
+        SearchEngine::new(pool)
+    });
+    let query = AdvancedFilterBuilder::new()
+        .search_text("sample", false)
+        .filter_by_tags(vec!["sample"])
+        .paginate(0, 20)
+        .build();
+    c.bench_function("SearchEngine::search", |b| {
+        b.to_async(&runtime).iter(|| async { engine.search(&query).await.unwrap() })
+    });
+}
+
+criterion_group!(benches, item_routes, search_queries);
+criterion_main!(benches);
@@ -0,0 +1,50 @@
+//! Finding items by tag in the in-memory store: scanning every item against
+//! looking the tags up in the store's tag index, at 50k items.
+//!
+//! Run with `cargo bench -p core_lib --bench tag_index`.
+
+use core_lib::store::DataStore;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const ITEMS: usize = 50_000;
+
+/// 50k items spread over 500 tags, two tags each.
+fn store() -> DataStore {
+    let store = DataStore::empty();
+    for n in 0..ITEMS {
+        let tags = vec![format!("tag-{}", n % 500), format!("group-{}", n % 7)];
+        store.create_item(format!("Item {}", n), None, tags, None).unwrap();
+    }
+    store
+}
+
+fn tag_lookups(c: &mut Criterion) {
+    let store = store();
+    let tags = vec!["tag-42".to_string(), "tag-314".to_string()];
+
+    c.bench_function("tag lookup, scan 50k items", |b| {
+        b.iter(|| {
+            let items: Vec<_> = store
+                .get_items(None, None)
+                .unwrap()
+                .into_iter()
+                .filter(|item| item.tags.iter().any(|tag| tags.contains(tag)))
+                .collect();
+            assert_eq!(items.len(), 200);
+        })
+    });
+
+    c.bench_function("tag lookup, index 50k items", |b| {
+        b.iter(|| {
+            let items = store.get_by_tags(&tags, None, None).unwrap();
+            assert_eq!(items.len(), 200);
+        })
+    });
+
+    c.bench_function("count_by_tag, index 50k items", |b| {
+        b.iter(|| assert_eq!(store.count_by_tag("group-3").unwrap(), 7_143))
+    });
+}
+
+criterion_group!(benches, tag_lookups);
+criterion_main!(benches);
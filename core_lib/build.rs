@@ -0,0 +1,14 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    #[cfg(feature = "grpc")]
+    {
+        // A protoc on PATH is not required; the vendored one is used unless
+        // PROTOC points elsewhere.
+        if std::env::var_os("PROTOC").is_none() {
+            let protoc = protoc_bin_vendored::protoc_bin_path().expect("no vendored protoc for this platform");
+            std::env::set_var("PROTOC", protoc);
+        }
+        tonic_build::compile_protos("proto/items.proto").expect("failed to compile proto/items.proto");
+    }
+}
@@ -0,0 +1,129 @@
+//! In-memory audit log for security-relevant events
+
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+const DEFAULT_CAPACITY: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub timestamp: DateTime<Utc>,
+    pub action: String,
+    pub actor: Option<String>,
+    pub target: Option<String>,
+    pub details: serde_json::Value,
+}
+
+impl AuditEvent {
+    pub fn new(action: &str) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            action: action.to_string(),
+            actor: None,
+            target: None,
+            details: serde_json::Value::Null,
+        }
+    }
+
+    pub fn with_actor(mut self, actor: impl Into<String>) -> Self {
+        self.actor = Some(actor.into());
+        self
+    }
+
+    pub fn with_target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+/// Bounded audit trail. Every event is also emitted on the `audit` tracing
+/// target so it survives in the regular log output after being evicted here.
+#[derive(Clone)]
+pub struct AuditLog {
+    events: Arc<RwLock<VecDeque<AuditEvent>>>,
+    capacity: usize,
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            events: Arc::new(RwLock::new(VecDeque::with_capacity(capacity.min(DEFAULT_CAPACITY)))),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn record(&self, event: AuditEvent) {
+        tracing::info!(
+            target: "audit",
+            action = %event.action,
+            actor = ?event.actor,
+            target_id = ?event.target,
+            details = %event.details,
+            "audit event"
+        );
+
+        let mut events = self.events.write();
+        if events.len() >= self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Returns the most recent events first, optionally filtered by action.
+    pub fn recent(&self, action: Option<&str>, limit: usize) -> Vec<AuditEvent> {
+        self.events
+            .read()
+            .iter()
+            .rev()
+            .filter(|event| action.is_none_or(|a| event.action == a))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.read().is_empty()
+    }
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_log_is_bounded() {
+        let log = AuditLog::with_capacity(2);
+        log.record(AuditEvent::new("first"));
+        log.record(AuditEvent::new("second"));
+        log.record(AuditEvent::new("third").with_target("127.0.0.1"));
+
+        assert_eq!(log.len(), 2);
+        let recent = log.recent(None, 10);
+        assert_eq!(recent[0].action, "third");
+        assert_eq!(recent[0].target.as_deref(), Some("127.0.0.1"));
+        assert_eq!(recent[1].action, "second");
+        assert_eq!(log.recent(Some("second"), 10).len(), 1);
+    }
+}
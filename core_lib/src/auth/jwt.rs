@@ -1,21 +1,33 @@
 use crate::auth::models::{JwtClaims, User, UserRole};
+use crate::clock::{SharedClock, SystemClock};
 use crate::error::AppError;
-use chrono::{Duration, Utc};
+use chrono::Duration;
 use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use std::env;
 
+/// Seconds past `exp` a token is still accepted, to allow for clock skew.
+const EXPIRY_LEEWAY_SECONDS: i64 = 60;
+
+#[derive(Clone)]
 pub struct JwtService {
     encoding_key: EncodingKey,
     decoding_key: DecodingKey,
     access_token_expiry: Duration,
     refresh_token_expiry: Duration,
+    clock: SharedClock,
 }
 
 impl JwtService {
+    /// Signs with the `JWT_SECRET` environment variable, or a built-in
+    /// development secret when it is unset.
     pub fn new() -> Result<Self, AppError> {
         let secret = env::var("JWT_SECRET")
             .unwrap_or_else(|_| "1a9e1a1d8f3e9613a555adea1881bbd1".to_string()); // I'm keeping my jwt-secret here. You would want to keep this in a .env file when dealing with serious environments for security reasons.
         
+        Self::with_secret(&secret)
+    }
+
+    pub fn with_secret(secret: &str) -> Result<Self, AppError> {
         if secret.len() < 32 {
             return Err(AppError::Authentication(
                 "JWT secret must be at least 32 characters long".to_string(),
@@ -30,49 +42,109 @@ impl JwtService {
             decoding_key,
             access_token_expiry: Duration::hours(1),
             refresh_token_expiry: Duration::days(7),
+            clock: SystemClock::shared(),
         })
     }
 
-    pub fn generate_access_token(&self, user: &User) -> Result<String, AppError> {
-        let now = Utc::now();
-        let exp = (now + self.access_token_expiry).timestamp() as usize;
-        let iat = now.timestamp() as usize;
+    pub fn with_token_expiry(mut self, access_token_expiry: Duration, refresh_token_expiry: Duration) -> Self {
+        self.access_token_expiry = access_token_expiry;
+        self.refresh_token_expiry = refresh_token_expiry;
+        self
+    }
 
-        let claims = JwtClaims {
-            sub: user.id.to_string(),
-            username: user.username.clone(),
-            role: user.role.clone(),
-            exp,
-            iat,
-            token_type: "access".to_string(),
-        };
+    /// Clock used for issue and expiry times, both when signing and when
+    /// validating.
+    pub fn clock(&self) -> &SharedClock {
+        &self.clock
+    }
 
-        encode(&Header::default(), &claims, &self.encoding_key)
-            .map_err(|e| AppError::Authentication(format!("Failed to generate access token: {}", e)))
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn generate_access_token(&self, user: &User) -> Result<String, AppError> {
+        self.generate_access_token_for_session(user, None)
     }
 
     pub fn generate_refresh_token(&self, user: &User) -> Result<String, AppError> {
-        let now = Utc::now();
-        let exp = (now + self.refresh_token_expiry).timestamp() as usize;
-        let iat = now.timestamp() as usize;
+        self.generate_refresh_token_for_session(user, None)
+    }
 
+    /// Issues an access token bound to `session_id`, so revoking the session
+    /// invalidates the token before it expires. The user is taken to have
+    /// just entered their password.
+    pub fn generate_access_token_for_session(&self, user: &User, session_id: Option<&str>) -> Result<String, AppError> {
+        let now = self.clock.now().timestamp() as usize;
+        self.sign(user, "access", self.access_token_expiry, session_id, Some(now), None)
+    }
+
+    pub fn generate_refresh_token_for_session(&self, user: &User, session_id: Option<&str>) -> Result<String, AppError> {
+        let now = self.clock.now().timestamp() as usize;
+        self.sign(user, "refresh", self.refresh_token_expiry, session_id, Some(now), None)
+    }
+
+    /// Issues the first refresh token of a session, identified by
+    /// `token_id` so that its rotation can be tracked.
+    pub fn generate_tracked_refresh_token(&self, user: &User, session_id: &str, token_id: &str) -> Result<String, AppError> {
+        let now = self.clock.now().timestamp() as usize;
+        self.sign(user, "refresh", self.refresh_token_expiry, Some(session_id), Some(now), Some(token_id))
+    }
+
+    /// Issues an access token in exchange for the refresh token with
+    /// `refresh_claims`, keeping its session and authentication time.
+    pub fn refresh_access_token(&self, user: &User, refresh_claims: &JwtClaims) -> Result<String, AppError> {
+        self.sign(user, "access", self.access_token_expiry, refresh_claims.sid.as_deref(), refresh_claims.auth_time, None)
+    }
+
+    /// Issues the refresh token `token_id` to replace the one with
+    /// `refresh_claims`, keeping its session and authentication time.
+    pub fn rotate_refresh_token(&self, user: &User, refresh_claims: &JwtClaims, token_id: &str) -> Result<String, AppError> {
+        self.sign(
+            user,
+            "refresh",
+            self.refresh_token_expiry,
+            refresh_claims.sid.as_deref(),
+            refresh_claims.auth_time,
+            Some(token_id),
+        )
+    }
+
+    fn sign(
+        &self,
+        user: &User,
+        token_type: &str,
+        expiry: Duration,
+        session_id: Option<&str>,
+        auth_time: Option<usize>,
+        token_id: Option<&str>,
+    ) -> Result<String, AppError> {
+        let now = self.clock.now();
         let claims = JwtClaims {
             sub: user.id.to_string(),
             username: user.username.clone(),
             role: user.role.clone(),
-            exp,
-            iat,
-            token_type: "refresh".to_string(),
+            exp: (now + expiry).timestamp() as usize,
+            iat: now.timestamp() as usize,
+            token_type: token_type.to_string(),
+            sid: session_id.map(str::to_string),
+            tenant: user.tenant(),
+            unverified: !user.email_verified,
+            auth_time,
+            jti: token_id.map(str::to_string),
         };
 
         encode(&Header::default(), &claims, &self.encoding_key)
-            .map_err(|e| AppError::Authentication(format!("Failed to generate refresh token: {}", e)))
+            .map_err(|e| AppError::Authentication(format!("Failed to generate {} token: {}", token_type, e)))
     }
 
     pub fn validate_token(&self, token: &str) -> Result<JwtClaims, AppError> {
-        let validation = Validation::new(Algorithm::HS256);
+        // Expiry is checked against our clock below rather than the system
+        // time jsonwebtoken would use.
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.validate_exp = false;
         
-        decode::<JwtClaims>(token, &self.decoding_key, &validation)
+        let claims = decode::<JwtClaims>(token, &self.decoding_key, &validation)
             .map(|data| data.claims)
             .map_err(|e| match e.kind() {
                 jsonwebtoken::errors::ErrorKind::ExpiredSignature => {
@@ -82,7 +154,13 @@ impl JwtService {
                     AppError::Authentication("Invalid token".to_string())
                 }
                 _ => AppError::Authentication(format!("Token validation failed: {}", e)),
-            })
+            })?;
+
+        if (claims.exp as i64) + EXPIRY_LEEWAY_SECONDS < self.clock.now().timestamp() {
+            return Err(AppError::Authentication("Token has expired".to_string()));
+        }
+
+        Ok(claims)
     }
 
     pub fn validate_access_token(&self, token: &str) -> Result<JwtClaims, AppError> {
@@ -126,12 +204,6 @@ impl JwtService {
     }
 }
 
-impl Clone for JwtService {
-    fn clone(&self) -> Self {
-        Self::new().expect("Failed to clone JWT service")
-    }
-}
-
 impl std::fmt::Debug for JwtService {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("JwtService")
@@ -1,6 +1,7 @@
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use sqlx::FromRow;
+use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
@@ -20,6 +21,29 @@ impl std::fmt::Display for UserRole {
     }
 }
 
+impl UserRole {
+    /// Space-separated OAuth-style scopes reported when a token is
+    /// introspected.
+    pub fn scope(&self) -> &'static str {
+        match self {
+            UserRole::Admin => "read write admin",
+            UserRole::User => "read write",
+            UserRole::ReadOnly => "read",
+        }
+    }
+
+    /// Whether this role carries everything `required` allows: admins can
+    /// do anything, and users anything read-only users can.
+    pub fn grants(&self, required: &UserRole) -> bool {
+        matches!(
+            (self, required),
+            (UserRole::Admin, _)
+                | (UserRole::User, UserRole::User | UserRole::ReadOnly)
+                | (UserRole::ReadOnly, UserRole::ReadOnly)
+        )
+    }
+}
+
 impl std::str::FromStr for UserRole {
     type Err = String;
 
@@ -44,9 +68,20 @@ pub struct User {
     pub created_at: DateTime<Utc>,
     pub last_login: Option<DateTime<Utc>>,
     pub is_active: bool,
+    pub namespace: String,
+    #[sqlx(skip)]
+    pub profile: UserProfile,
+    /// An address the user asked to change to, waiting for confirmation.
+    pub pending_email: Option<String>,
+    pub email_verified: bool,
 }
 
 impl User {
+    /// The tenant claim for this user's tokens.
+    pub fn tenant(&self) -> Option<String> {
+        (self.namespace != crate::tenancy::DEFAULT_NAMESPACE).then(|| self.namespace.clone())
+    }
+
     pub fn get_role(&self) -> Result<UserRole, String> {
         self.role.parse()
     }
@@ -56,6 +91,46 @@ impl User {
     }
 }
 
+/// The parts of a user's account they manage themselves.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub display_name: Option<String>,
+    /// An image file the user uploaded.
+    pub avatar_file_id: Option<Uuid>,
+    /// An IANA time zone name such as `Europe/Berlin`.
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub notification_preferences: NotificationPreferences,
+}
+
+/// Which optional notifications a user receives. Messages needed to act on
+/// the account, such as email confirmations, are always sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationPreferences {
+    pub new_device_login: bool,
+    pub password_changed: bool,
+}
+
+impl Default for NotificationPreferences {
+    fn default() -> Self {
+        Self {
+            new_device_login: true,
+            password_changed: true,
+        }
+    }
+}
+
+impl NotificationPreferences {
+    pub fn allows(&self, template_id: &str) -> bool {
+        match template_id {
+            crate::notifications::NEW_DEVICE_LOGIN => self.new_device_login,
+            crate::notifications::PASSWORD_CHANGED => self.password_changed,
+            _ => true,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserResponse {
     pub id: i64,
@@ -65,6 +140,11 @@ pub struct UserResponse {
     pub created_at: DateTime<Utc>,
     pub last_login: Option<DateTime<Utc>>,
     pub is_active: bool,
+    pub email_verified: bool,
+    #[serde(flatten)]
+    pub profile: UserProfile,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_email: Option<String>,
 }
 
 impl From<User> for UserResponse {
@@ -78,6 +158,9 @@ impl From<User> for UserResponse {
             created_at: user.created_at,
             last_login: user.last_login,
             is_active: user.is_active,
+            email_verified: user.email_verified,
+            profile: user.profile,
+            pending_email: user.pending_email,
         }
     }
 }
@@ -96,6 +179,62 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ReauthenticateRequest {
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+/// A self-service change to the signed-in user's account. Absent fields are
+/// left alone and `null` clears an optional one.
+#[derive(Debug, Default, Deserialize)]
+pub struct UpdateProfileRequest {
+    #[serde(default, deserialize_with = "nullable")]
+    pub display_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub avatar_file_id: Option<Option<Uuid>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub timezone: Option<Option<String>>,
+    pub notification_preferences: Option<NotificationPreferences>,
+    pub email: Option<String>,
+    /// Required to change `email`.
+    pub current_password: Option<String>,
+    /// Accepted only so that attempts to set them are refused rather than
+    /// silently ignored.
+    pub role: Option<serde_json::Value>,
+    pub is_active: Option<serde_json::Value>,
+}
+
+/// Reads a present field as `Some`, so that an explicit `null` can be told
+/// apart from a missing field.
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmEmailRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResendVerificationRequest {
+    pub email: String,
+}
+
 #[derive(Debug, Serialize)]
 pub struct LoginResponse {
     pub access_token: String,
@@ -108,10 +247,64 @@ pub struct LoginResponse {
 #[derive(Debug, Serialize)]
 pub struct RefreshTokenResponse {
     pub access_token: String,
+    /// The refresh token replacing the one presented, which can't be used
+    /// again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
     pub token_type: String,
     pub expires_in: i64,
 }
 
+/// An RFC 7662 introspection request, sent form-encoded.
+#[derive(Debug, Deserialize)]
+pub struct IntrospectionRequest {
+    pub token: String,
+    /// Accepted for compatibility; both token types are always tried.
+    #[serde(default)]
+    pub token_type_hint: Option<String>,
+}
+
+/// An RFC 7662 introspection response. Inactive tokens get `active: false`
+/// and nothing else, whatever the reason.
+#[derive(Debug, Default, Serialize)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<usize>,
+}
+
+impl IntrospectionResponse {
+    pub fn inactive() -> Self {
+        Self::default()
+    }
+
+    pub fn active(claims: &JwtClaims) -> Self {
+        let scope = claims.role.parse::<UserRole>().ok().map(|role| role.scope().to_string());
+        Self {
+            active: true,
+            sub: Some(claims.sub.clone()),
+            username: Some(claims.username.clone()),
+            role: Some(claims.role.clone()),
+            scope,
+            token_type: Some(claims.token_type.clone()),
+            exp: Some(claims.exp),
+            iat: Some(claims.iat),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JwtClaims {
     pub sub: String,
@@ -120,4 +313,77 @@ pub struct JwtClaims {
     pub exp: usize,
     pub iat: usize,
     pub token_type: String,
+    /// Session the token belongs to. Tokens issued before sessions were
+    /// tracked have none and cannot be revoked individually.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// Namespace of a user outside the default one. Absent for single-tenant
+    /// deployments.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Set while the user's email address is unverified. Tokens issued
+    /// before verification keep it until refreshed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unverified: bool,
+    /// When the user last entered their password, as a Unix time. Refreshing
+    /// keeps it, so only signing in or re-authenticating moves it forward.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_time: Option<usize>,
+    /// Identifies a refresh token within its session, so that one already
+    /// exchanged is recognised if it comes back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+}
+
+/// What became of a refresh token presented for rotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshRotation {
+    /// It was current and has been replaced.
+    Rotated,
+    /// It had already been replaced, so someone else holds a copy.
+    Replayed,
+    /// It was never issued for the session.
+    Unknown,
+}
+
+/// A login on one device: one row per refresh token family.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub id: String,
+    pub user_id: i64,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Where a login came from, recorded on the session it creates.
+#[derive(Debug, Clone, Default)]
+pub struct SessionClient {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    pub current: bool,
+}
+
+impl SessionResponse {
+    pub fn from_session(session: Session, current_session_id: Option<&str>) -> Self {
+        Self {
+            current: current_session_id == Some(session.id.as_str()),
+            id: session.id,
+            user_agent: session.user_agent,
+            ip_address: session.ip_address,
+            created_at: session.created_at,
+            last_used_at: session.last_used_at,
+        }
+    }
 }
\ No newline at end of file
@@ -1,29 +1,74 @@
-use crate::auth::models::{CreateUserRequest, User, UserRole};
+use crate::auth::models::{CreateUserRequest, NotificationPreferences, RefreshRotation, Session, SessionClient, User, UserProfile, UserRole};
+use crate::database::{InstrumentedPool, SortSpec, UpdateUserInput, USER_SORT};
 use crate::error::AppError;
+use crate::list_preferences::ListPreferences;
 use async_trait::async_trait;
-use chrono::Utc;
-use sqlx::{Row, SqlitePool};
+use chrono::{DateTime, SecondsFormat, Utc};
+use sqlx::{sqlite::SqliteRow, Row, SqlitePool};
+use uuid::Uuid;
 
 #[async_trait]
 pub trait UserRepositoryTrait {
-    async fn create_user(&self, request: &CreateUserRequest, password_hash: &str) -> Result<User, AppError>;
+    async fn create_user(&self, request: &CreateUserRequest, password_hash: &str, email_verified: bool) -> Result<User, AppError>;
     async fn get_user_by_id(&self, id: i64) -> Result<Option<User>, AppError>;
     async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, AppError>;
     async fn get_user_by_email(&self, email: &str) -> Result<Option<User>, AppError>;
     async fn update_last_login(&self, user_id: i64) -> Result<(), AppError>;
     async fn update_user_status(&self, user_id: i64, is_active: bool) -> Result<(), AppError>;
     async fn list_users(&self, limit: Option<i64>, offset: Option<i64>) -> Result<Vec<User>, AppError>;
+    /// Users of the current namespace whose username contains `username`,
+    /// ignoring case, with the total number of matches.
+    /// Users whose username contains `username`, ignoring case, by username
+    /// unless `sort` says otherwise.
+    async fn search_users(&self, username: &str, sort: Option<&SortSpec>, limit: i64, offset: i64) -> Result<(Vec<User>, u64), AppError>;
+    /// Deletes a user, refusing with a conflict to delete the last active
+    /// admin.
     async fn delete_user(&self, user_id: i64) -> Result<(), AppError>;
+    async fn update_password_hash(&self, user_id: i64, password_hash: &str) -> Result<(), AppError>;
+    async fn update_user(&self, user_id: i64, input: &UpdateUserInput) -> Result<User, AppError>;
+    /// The user's list preferences, or `None` when there is no such user.
+    async fn get_list_preferences(&self, user_id: i64) -> Result<Option<ListPreferences>, AppError>;
+    /// Replaces the user's list preferences.
+    async fn set_list_preferences(&self, user_id: i64, preferences: &ListPreferences) -> Result<(), AppError>;
+    /// Changes a user's role and active status, refusing with a conflict
+    /// when no active admin would be left. The check and the change are one
+    /// statement, so concurrent demotions cannot both pass it.
+    async fn update_user_access(&self, user_id: i64, role: Option<&str>, is_active: Option<bool>) -> Result<User, AppError>;
+    /// Records an email change waiting for confirmation, replacing any
+    /// earlier one. `token_hash` is the SHA-256 of the token sent to `email`.
+    async fn set_pending_email(&self, user_id: i64, email: &str, token_hash: &str, expires_at: DateTime<Utc>) -> Result<(), AppError>;
+    /// Applies the pending email change whose token hashes to `token_hash`,
+    /// if it hasn't expired, returning the updated user.
+    async fn confirm_pending_email(&self, user_id: i64, token_hash: &str) -> Result<Option<User>, AppError>;
+    /// Records the verification token for an unverified user, replacing any
+    /// earlier one. `token_hash` is the SHA-256 of the token sent to them.
+    async fn set_verification_token(&self, user_id: i64, token_hash: &str, expires_at: DateTime<Utc>) -> Result<(), AppError>;
+    /// Marks the user whose token hashes to `token_hash` verified, if the
+    /// token is still valid at `now`, and forgets the token.
+    async fn verify_email(&self, token_hash: &str, now: DateTime<Utc>) -> Result<Option<User>, AppError>;
+    async fn create_session(&self, user_id: i64, client: &SessionClient) -> Result<Session, AppError>;
+    async fn list_sessions(&self, user_id: i64, include_revoked: bool) -> Result<Vec<Session>, AppError>;
+    /// Bumps `last_used_at` and reports whether the session is still active.
+    async fn touch_session(&self, session_id: &str) -> Result<bool, AppError>;
+    async fn revoke_session(&self, user_id: i64, session_id: &str) -> Result<bool, AppError>;
+    /// Revokes every active session of the user except `keep`, returning the
+    /// revoked session ids.
+    async fn revoke_other_sessions(&self, user_id: i64, keep: Option<&str>) -> Result<Vec<String>, AppError>;
+    /// Records the first refresh token issued for a session.
+    async fn record_refresh_token(&self, session_id: &str, token_id: &str) -> Result<(), AppError>;
+    /// Replaces refresh token `token_id` of the session with `next_id`,
+    /// linked to it as its predecessor, if `token_id` is still current.
+    async fn rotate_refresh_token(&self, session_id: &str, token_id: &str, next_id: &str) -> Result<RefreshRotation, AppError>;
 }
 
 #[derive(Clone)]
 pub struct UserRepository {
-    pool: SqlitePool,
+    pool: InstrumentedPool,
 }
 
 impl UserRepository {
     pub fn new(pool: SqlitePool) -> Self {
-        Self { pool }
+        Self { pool: pool.into() }
     }
 
     pub async fn ensure_tables_exist(&self) -> Result<(), AppError> {
@@ -37,7 +82,19 @@ impl UserRepository {
                 role TEXT NOT NULL DEFAULT 'user',
                 created_at TEXT NOT NULL,
                 last_login TEXT,
-                is_active BOOLEAN NOT NULL DEFAULT 1
+                is_active BOOLEAN NOT NULL DEFAULT 1,
+                namespace TEXT NOT NULL DEFAULT 'default',
+                display_name TEXT,
+                avatar_file_id TEXT,
+                timezone TEXT,
+                notification_preferences TEXT,
+                list_preferences TEXT,
+                pending_email TEXT,
+                pending_email_token TEXT,
+                pending_email_expires_at TEXT,
+                email_verified BOOLEAN NOT NULL DEFAULT 1,
+                verification_token TEXT,
+                verification_expires_at TEXT
             )
             "#,
         )
@@ -55,20 +112,147 @@ impl UserRepository {
             .await
             .map_err(|e| AppError::Database(format!("Failed to create email index: {}", e)))?;
 
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS user_sessions (
+                id TEXT PRIMARY KEY,
+                user_id INTEGER NOT NULL,
+                user_agent TEXT,
+                ip_address TEXT,
+                created_at TEXT NOT NULL,
+                last_used_at TEXT NOT NULL,
+                revoked_at TEXT,
+                FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to create user_sessions table: {}", e)))?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_user_sessions_user_id ON user_sessions(user_id)")
+            .execute(&self.pool)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to create session user index: {}", e)))?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS refresh_tokens (
+                id TEXT PRIMARY KEY,
+                session_id TEXT NOT NULL,
+                parent_id TEXT,
+                issued_at TEXT NOT NULL,
+                rotated_at TEXT,
+                FOREIGN KEY (session_id) REFERENCES user_sessions(id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to create refresh_tokens table: {}", e)))?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_refresh_tokens_session_id ON refresh_tokens(session_id)")
+            .execute(&self.pool)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to create refresh token session index: {}", e)))?;
+
         Ok(())
     }
+
+    /// Why a guarded change to `user_id` touched no row.
+    async fn last_admin_or_missing(&self, user_id: i64) -> AppError {
+        match self.get_user_by_id(user_id).await {
+            Ok(Some(_)) => AppError::Conflict("The last active admin cannot be demoted, deactivated or deleted".to_string()),
+            Ok(None) => AppError::NotFound("User not found".to_string()),
+            Err(e) => e,
+        }
+    }
+}
+
+const USER_COLUMNS: &str = "id, username, email, password_hash, role, created_at, last_login, is_active, namespace, \
+    display_name, avatar_file_id, timezone, notification_preferences, pending_email, email_verified";
+
+/// True for a row that another active admin would outlive, for use in a
+/// statement on `users`.
+const ANOTHER_ACTIVE_ADMIN: &str =
+    "EXISTS (SELECT 1 FROM users AS other WHERE other.id != users.id AND other.role = 'admin' AND other.is_active)";
+
+/// Token expiry times are written in this one format so that they compare
+/// correctly as text.
+fn expiry_timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn user_from_row(row: &SqliteRow) -> Result<User, AppError> {
+    let created_at: String = row.get("created_at");
+    let last_login: Option<String> = row.get("last_login");
+    let avatar_file_id: Option<String> = row.get("avatar_file_id");
+    let notification_preferences: Option<String> = row.get("notification_preferences");
+
+    Ok(User {
+        id: row.get("id"),
+        username: row.get("username"),
+        email: row.get("email"),
+        password_hash: row.get("password_hash"),
+        role: row.get("role"),
+        created_at: created_at.parse().map_err(|e| {
+            AppError::Database(format!("Failed to parse created_at: {}", e))
+        })?,
+        last_login: last_login.map(|s| s.parse()).transpose().map_err(|e| {
+            AppError::Database(format!("Failed to parse last_login: {}", e))
+        })?,
+        is_active: row.get("is_active"),
+        namespace: row.get("namespace"),
+        profile: UserProfile {
+            display_name: row.get("display_name"),
+            avatar_file_id: avatar_file_id.map(|s| s.parse()).transpose().map_err(|e| {
+                AppError::Database(format!("Failed to parse avatar_file_id: {}", e))
+            })?,
+            timezone: row.get("timezone"),
+            notification_preferences: notification_preferences
+                .map(|s| serde_json::from_str::<NotificationPreferences>(&s))
+                .transpose()
+                .map_err(|e| AppError::Database(format!("Failed to parse notification_preferences: {}", e)))?
+                .unwrap_or_default(),
+        },
+        pending_email: row.get("pending_email"),
+        email_verified: row.get("email_verified"),
+    })
+}
+
+fn session_from_row(row: &SqliteRow) -> Result<Session, AppError> {
+    let created_at: String = row.get("created_at");
+    let last_used_at: String = row.get("last_used_at");
+    let revoked_at: Option<String> = row.get("revoked_at");
+
+    Ok(Session {
+        id: row.get("id"),
+        user_id: row.get("user_id"),
+        user_agent: row.get("user_agent"),
+        ip_address: row.get("ip_address"),
+        created_at: created_at.parse().map_err(|e| {
+            AppError::Database(format!("Failed to parse created_at: {}", e))
+        })?,
+        last_used_at: last_used_at.parse().map_err(|e| {
+            AppError::Database(format!("Failed to parse last_used_at: {}", e))
+        })?,
+        revoked_at: revoked_at.map(|s| s.parse()).transpose().map_err(|e| {
+            AppError::Database(format!("Failed to parse revoked_at: {}", e))
+        })?,
+    })
 }
 
 #[async_trait]
 impl UserRepositoryTrait for UserRepository {
-    async fn create_user(&self, request: &CreateUserRequest, password_hash: &str) -> Result<User, AppError> {
+    async fn create_user(&self, request: &CreateUserRequest, password_hash: &str, email_verified: bool) -> Result<User, AppError> {
         let now = Utc::now();
         let role = request.role.as_ref().unwrap_or(&UserRole::User).to_string();
+        let namespace = crate::tenancy::current_or_default();
 
         let result = sqlx::query(
             r#"
-            INSERT INTO users (username, email, password_hash, role, created_at, is_active)
-            VALUES (?, ?, ?, ?, ?, ?)
+            INSERT INTO users (username, email, password_hash, role, created_at, is_active, namespace, email_verified)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
             "#,
         )
         .bind(&request.username)
@@ -77,6 +261,8 @@ impl UserRepositoryTrait for UserRepository {
         .bind(&role)
         .bind(now.to_rfc3339())
         .bind(true)
+        .bind(&namespace)
+        .bind(email_verified)
         .execute(&self.pool)
         .await
         .map_err(|e| {
@@ -104,103 +290,41 @@ impl UserRepositoryTrait for UserRepository {
             created_at: now,
             last_login: None,
             is_active: true,
+            namespace,
+            profile: UserProfile::default(),
+            pending_email: None,
+            email_verified,
         })
     }
 
     async fn get_user_by_id(&self, id: i64) -> Result<Option<User>, AppError> {
-        let row = sqlx::query(
-            "SELECT id, username, email, password_hash, role, created_at, last_login, is_active FROM users WHERE id = ?"
-        )
-        .bind(id)
-        .fetch_optional(&self.pool)
-        .await
-        .map_err(|e| AppError::Database(format!("Failed to get user by ID: {}", e)))?;
-
-        if let Some(row) = row {
-            let created_at: String = row.get("created_at");
-            let last_login: Option<String> = row.get("last_login");
-
-            Ok(Some(User {
-                id: row.get("id"),
-                username: row.get("username"),
-                email: row.get("email"),
-                password_hash: row.get("password_hash"),
-                role: row.get("role"),
-                created_at: created_at.parse().map_err(|e| {
-                    AppError::Database(format!("Failed to parse created_at: {}", e))
-                })?,
-                last_login: last_login.map(|s| s.parse()).transpose().map_err(|e| {
-                    AppError::Database(format!("Failed to parse last_login: {}", e))
-                })?,
-                is_active: row.get("is_active"),
-            }))
-        } else {
-            Ok(None)
-        }
+        let row = sqlx::query(&format!("SELECT {} FROM users WHERE id = ?", USER_COLUMNS))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to get user by ID: {}", e)))?;
+
+        row.as_ref().map(user_from_row).transpose()
     }
 
     async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, AppError> {
-        let row = sqlx::query(
-            "SELECT id, username, email, password_hash, role, created_at, last_login, is_active FROM users WHERE username = ?"
-        )
-        .bind(username)
-        .fetch_optional(&self.pool)
-        .await
-        .map_err(|e| AppError::Database(format!("Failed to get user by username: {}", e)))?;
-
-        if let Some(row) = row {
-            let created_at: String = row.get("created_at");
-            let last_login: Option<String> = row.get("last_login");
-
-            Ok(Some(User {
-                id: row.get("id"),
-                username: row.get("username"),
-                email: row.get("email"),
-                password_hash: row.get("password_hash"),
-                role: row.get("role"),
-                created_at: created_at.parse().map_err(|e| {
-                    AppError::Database(format!("Failed to parse created_at: {}", e))
-                })?,
-                last_login: last_login.map(|s| s.parse()).transpose().map_err(|e| {
-                    AppError::Database(format!("Failed to parse last_login: {}", e))
-                })?,
-                is_active: row.get("is_active"),
-            }))
-        } else {
-            Ok(None)
-        }
+        let row = sqlx::query(&format!("SELECT {} FROM users WHERE username = ?", USER_COLUMNS))
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to get user by username: {}", e)))?;
+
+        row.as_ref().map(user_from_row).transpose()
     }
 
     async fn get_user_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
-        let row = sqlx::query(
-            "SELECT id, username, email, password_hash, role, created_at, last_login, is_active FROM users WHERE email = ?"
-        )
-        .bind(email)
-        .fetch_optional(&self.pool)
-        .await
-        .map_err(|e| AppError::Database(format!("Failed to get user by email: {}", e)))?;
-
-        if let Some(row) = row {
-            let created_at: String = row.get("created_at");
-            let last_login: Option<String> = row.get("last_login");
-
-            Ok(Some(User {
-                id: row.get("id"),
-                username: row.get("username"),
-                email: row.get("email"),
-                password_hash: row.get("password_hash"),
-                role: row.get("role"),
-                created_at: created_at.parse().map_err(|e| {
-                    AppError::Database(format!("Failed to parse created_at: {}", e))
-                })?,
-                last_login: last_login.map(|s| s.parse()).transpose().map_err(|e| {
-                    AppError::Database(format!("Failed to parse last_login: {}", e))
-                })?,
-                is_active: row.get("is_active"),
-            }))
-        } else {
-            Ok(None)
-        }
+        let row = sqlx::query(&format!("SELECT {} FROM users WHERE email = ?", USER_COLUMNS))
+            .bind(email)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to get user by email: {}", e)))?;
+
+        row.as_ref().map(user_from_row).transpose()
     }
 
     async fn update_last_login(&self, user_id: i64) -> Result<(), AppError> {
@@ -215,6 +339,39 @@ impl UserRepositoryTrait for UserRepository {
         Ok(())
     }
 
+    async fn get_list_preferences(&self, user_id: i64) -> Result<Option<ListPreferences>, AppError> {
+        let row = sqlx::query("SELECT list_preferences FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to get list preferences: {}", e)))?;
+
+        row.map(|row| {
+            let preferences: Option<String> = row.get("list_preferences");
+            preferences
+                .map(|s| serde_json::from_str::<ListPreferences>(&s))
+                .transpose()
+                .map_err(|e| AppError::Database(format!("Failed to parse list_preferences: {}", e)))
+                .map(Option::unwrap_or_default)
+        })
+        .transpose()
+    }
+
+    async fn set_list_preferences(&self, user_id: i64, preferences: &ListPreferences) -> Result<(), AppError> {
+        let result = sqlx::query("UPDATE users SET list_preferences = ? WHERE id = ?")
+            .bind(serde_json::to_string(preferences)?)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to update list preferences: {}", e)))?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound("User not found".to_string()));
+        }
+
+        Ok(())
+    }
+
     async fn update_user_status(&self, user_id: i64, is_active: bool) -> Result<(), AppError> {
         sqlx::query("UPDATE users SET is_active = ? WHERE id = ?")
             .bind(is_active)
@@ -230,46 +387,144 @@ impl UserRepositoryTrait for UserRepository {
         let limit = limit.unwrap_or(50);
         let offset = offset.unwrap_or(0);
 
-        let rows = sqlx::query(
-            "SELECT id, username, email, password_hash, role, created_at, last_login, is_active 
-             FROM users ORDER BY created_at DESC LIMIT ? OFFSET ?"
-        )
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM users ORDER BY created_at DESC, id DESC LIMIT ? OFFSET ?",
+            USER_COLUMNS
+        ))
         .bind(limit)
         .bind(offset)
         .fetch_all(&self.pool)
         .await
         .map_err(|e| AppError::Database(format!("Failed to list users: {}", e)))?;
 
-        let mut users = Vec::new();
-        for row in rows {
-            let created_at: String = row.get("created_at");
-            let last_login: Option<String> = row.get("last_login");
-
-            users.push(User {
-                id: row.get("id"),
-                username: row.get("username"),
-                email: row.get("email"),
-                password_hash: row.get("password_hash"),
-                role: row.get("role"),
-                created_at: created_at.parse().map_err(|e| {
-                    AppError::Database(format!("Failed to parse created_at: {}", e))
-                })?,
-                last_login: last_login.map(|s| s.parse()).transpose().map_err(|e| {
-                    AppError::Database(format!("Failed to parse last_login: {}", e))
-                })?,
-                is_active: row.get("is_active"),
-            });
-        }
+        rows.iter().map(user_from_row).collect()
+    }
+
+    async fn search_users(&self, username: &str, sort: Option<&SortSpec>, limit: i64, offset: i64) -> Result<(Vec<User>, u64), AppError> {
+        let order_by = match sort {
+            Some(sort) => USER_SORT.order_by(Some(sort))?,
+            None => "username, id".to_string(),
+        };
+        let pattern = format!("%{}%", crate::database::escape_like(&username.to_lowercase()));
+        let namespace = crate::tenancy::current();
+
+        let total: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM users WHERE lower(username) LIKE ?1 ESCAPE '\\' AND namespace = COALESCE(?2, namespace)"
+        )
+        .bind(&pattern)
+        .bind(&namespace)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to count users: {}", e)))?;
+
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM users WHERE lower(username) LIKE ?1 ESCAPE '\\' AND namespace = COALESCE(?2, namespace)
+             ORDER BY {} LIMIT ?3 OFFSET ?4",
+            USER_COLUMNS, order_by
+        ))
+        .bind(&pattern)
+        .bind(&namespace)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to search users: {}", e)))?;
 
-        Ok(users)
+        let users = rows.iter().map(user_from_row).collect::<Result<Vec<_>, _>>()?;
+        Ok((users, total as u64))
     }
 
     async fn delete_user(&self, user_id: i64) -> Result<(), AppError> {
-        let result = sqlx::query("DELETE FROM users WHERE id = ?")
+        let result = sqlx::query(&format!(
+            "DELETE FROM users WHERE id = ? AND (NOT (role = 'admin' AND is_active) OR {})",
+            ANOTHER_ACTIVE_ADMIN
+        ))
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to delete user: {}", e)))?;
+
+        if result.rows_affected() == 0 {
+            return Err(self.last_admin_or_missing(user_id).await);
+        }
+
+        Ok(())
+    }
+
+    async fn update_password_hash(&self, user_id: i64, password_hash: &str) -> Result<(), AppError> {
+        let result = sqlx::query("UPDATE users SET password_hash = ? WHERE id = ?")
+            .bind(password_hash)
             .bind(user_id)
             .execute(&self.pool)
             .await
-            .map_err(|e| AppError::Database(format!("Failed to delete user: {}", e)))?;
+            .map_err(|e| AppError::Database(format!("Failed to update password: {}", e)))?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound("User not found".to_string()));
+        }
+
+        Ok(())
+    }
+
+    async fn update_user(&self, user_id: i64, input: &UpdateUserInput) -> Result<User, AppError> {
+        let assignments = input.assignments();
+        if assignments.is_empty() {
+            return self
+                .get_user_by_id(user_id)
+                .await?
+                .ok_or_else(|| AppError::NotFound("User not found".to_string()));
+        }
+
+        let query = format!(
+            "UPDATE users SET {} WHERE id = ? RETURNING {}",
+            assignments.join(", "),
+            USER_COLUMNS
+        );
+        let row = input
+            .bind(sqlx::query(&query))
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        user_from_row(&row)
+    }
+
+    async fn update_user_access(&self, user_id: i64, role: Option<&str>, is_active: Option<bool>) -> Result<User, AppError> {
+        let row = sqlx::query(&format!(
+            "UPDATE users SET role = COALESCE(?1, role), is_active = COALESCE(?2, is_active)
+             WHERE id = ?3 AND (
+                 NOT (role = 'admin' AND is_active)
+                 OR (COALESCE(?1, role) = 'admin' AND COALESCE(?2, is_active))
+                 OR {}
+             )
+             RETURNING {}",
+            ANOTHER_ACTIVE_ADMIN, USER_COLUMNS
+        ))
+        .bind(role)
+        .bind(is_active)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to update user access: {}", e)))?;
+
+        match row {
+            Some(row) => user_from_row(&row),
+            None => Err(self.last_admin_or_missing(user_id).await),
+        }
+    }
+
+    async fn set_pending_email(&self, user_id: i64, email: &str, token_hash: &str, expires_at: DateTime<Utc>) -> Result<(), AppError> {
+        let result = sqlx::query(
+            "UPDATE users SET pending_email = ?, pending_email_token = ?, pending_email_expires_at = ? WHERE id = ?"
+        )
+        .bind(email)
+        .bind(token_hash)
+        .bind(expiry_timestamp(expires_at))
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to record email change: {}", e)))?;
 
         if result.rows_affected() == 0 {
             return Err(AppError::NotFound("User not found".to_string()));
@@ -277,4 +532,190 @@ impl UserRepositoryTrait for UserRepository {
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    async fn confirm_pending_email(&self, user_id: i64, token_hash: &str) -> Result<Option<User>, AppError> {
+        let row = sqlx::query(&format!(
+            "UPDATE users
+             SET email = pending_email, pending_email = NULL, pending_email_token = NULL, pending_email_expires_at = NULL
+             WHERE id = ? AND pending_email IS NOT NULL AND pending_email_token = ? AND pending_email_expires_at > ?
+             RETURNING {}",
+            USER_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(token_hash)
+        .bind(expiry_timestamp(Utc::now()))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(user_from_row).transpose()
+    }
+
+    async fn set_verification_token(&self, user_id: i64, token_hash: &str, expires_at: DateTime<Utc>) -> Result<(), AppError> {
+        let result = sqlx::query(
+            "UPDATE users SET verification_token = ?, verification_expires_at = ? WHERE id = ? AND NOT email_verified"
+        )
+        .bind(token_hash)
+        .bind(expiry_timestamp(expires_at))
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to record verification token: {}", e)))?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound("Unverified user not found".to_string()));
+        }
+
+        Ok(())
+    }
+
+    async fn verify_email(&self, token_hash: &str, now: DateTime<Utc>) -> Result<Option<User>, AppError> {
+        let row = sqlx::query(&format!(
+            "UPDATE users
+             SET email_verified = 1, verification_token = NULL, verification_expires_at = NULL
+             WHERE verification_token = ? AND NOT email_verified AND verification_expires_at > ?
+             RETURNING {}",
+            USER_COLUMNS
+        ))
+        .bind(token_hash)
+        .bind(expiry_timestamp(now))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(user_from_row).transpose()
+    }
+
+    async fn create_session(&self, user_id: i64, client: &SessionClient) -> Result<Session, AppError> {
+        let now = Utc::now();
+        let session = Session {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            user_agent: client.user_agent.clone(),
+            ip_address: client.ip_address.clone(),
+            created_at: now,
+            last_used_at: now,
+            revoked_at: None,
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO user_sessions (id, user_id, user_agent, ip_address, created_at, last_used_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&session.id)
+        .bind(user_id)
+        .bind(&session.user_agent)
+        .bind(&session.ip_address)
+        .bind(now.to_rfc3339())
+        .bind(now.to_rfc3339())
+        .execute(&self.pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to create session: {}", e)))?;
+
+        Ok(session)
+    }
+
+    async fn list_sessions(&self, user_id: i64, include_revoked: bool) -> Result<Vec<Session>, AppError> {
+        let rows = sqlx::query(
+            "SELECT id, user_id, user_agent, ip_address, created_at, last_used_at, revoked_at
+             FROM user_sessions WHERE user_id = ? AND (? OR revoked_at IS NULL) ORDER BY last_used_at DESC"
+        )
+        .bind(user_id)
+        .bind(include_revoked)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to list sessions: {}", e)))?;
+
+        rows.iter().map(session_from_row).collect()
+    }
+
+    async fn touch_session(&self, session_id: &str) -> Result<bool, AppError> {
+        let result = sqlx::query("UPDATE user_sessions SET last_used_at = ? WHERE id = ? AND revoked_at IS NULL")
+            .bind(Utc::now().to_rfc3339())
+            .bind(session_id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to update session: {}", e)))?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn revoke_session(&self, user_id: i64, session_id: &str) -> Result<bool, AppError> {
+        let result = sqlx::query(
+            "UPDATE user_sessions SET revoked_at = ? WHERE id = ? AND user_id = ? AND revoked_at IS NULL"
+        )
+        .bind(Utc::now().to_rfc3339())
+        .bind(session_id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to revoke session: {}", e)))?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn revoke_other_sessions(&self, user_id: i64, keep: Option<&str>) -> Result<Vec<String>, AppError> {
+        let rows = sqlx::query(
+            "UPDATE user_sessions SET revoked_at = ?
+             WHERE user_id = ? AND revoked_at IS NULL AND id IS NOT ?
+             RETURNING id"
+        )
+        .bind(Utc::now().to_rfc3339())
+        .bind(user_id)
+        .bind(keep)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to revoke sessions: {}", e)))?;
+
+        Ok(rows.iter().map(|row| row.get("id")).collect())
+    }
+
+    async fn record_refresh_token(&self, session_id: &str, token_id: &str) -> Result<(), AppError> {
+        sqlx::query("INSERT INTO refresh_tokens (id, session_id, issued_at) VALUES (?, ?, ?)")
+            .bind(token_id)
+            .bind(session_id)
+            .bind(Utc::now().to_rfc3339())
+            .execute(&self.pool)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to record refresh token: {}", e)))?;
+
+        Ok(())
+    }
+
+    async fn rotate_refresh_token(&self, session_id: &str, token_id: &str, next_id: &str) -> Result<RefreshRotation, AppError> {
+        let now = Utc::now().to_rfc3339();
+        let mut tx = self.pool.begin().await?;
+
+        let rotated = sqlx::query(
+            "UPDATE refresh_tokens SET rotated_at = ? WHERE id = ? AND session_id = ? AND rotated_at IS NULL"
+        )
+        .bind(&now)
+        .bind(token_id)
+        .bind(session_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to rotate refresh token: {}", e)))?;
+
+        if rotated.rows_affected() == 0 {
+            let known = sqlx::query("SELECT 1 FROM refresh_tokens WHERE id = ? AND session_id = ?")
+                .bind(token_id)
+                .bind(session_id)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(|e| AppError::Database(format!("Failed to look up refresh token: {}", e)))?;
+            return Ok(if known.is_some() { RefreshRotation::Replayed } else { RefreshRotation::Unknown });
+        }
+
+        sqlx::query("INSERT INTO refresh_tokens (id, session_id, parent_id, issued_at) VALUES (?, ?, ?, ?)")
+            .bind(next_id)
+            .bind(session_id)
+            .bind(token_id)
+            .bind(&now)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to record refresh token: {}", e)))?;
+        tx.commit().await?;
+
+        Ok(RefreshRotation::Rotated)
+    }
+}
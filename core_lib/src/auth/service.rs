@@ -1,21 +1,96 @@
 use crate::auth::jwt::JwtService;
 use crate::auth::models::{
-    CreateUserRequest, JwtClaims, LoginRequest, LoginResponse, RefreshTokenResponse,
-    UserResponse,
+    ChangePasswordRequest, CreateUserRequest, JwtClaims, LoginRequest, LoginResponse, RefreshRotation,
+    RefreshTokenResponse, Session, SessionClient, SessionResponse, UpdateProfileRequest, User, UserResponse, UserRole,
 };
+use crate::audit::{AuditEvent, AuditLog};
 use crate::auth::repository::{UserRepository, UserRepositoryTrait};
+use crate::config::{AuthConfig, UnverifiedUserPolicy};
+use crate::database::UpdateUserInput;
 use crate::error::AppError;
+use crate::list_preferences::ListPreferences;
+use crate::metrics::MetricsSink;
+use crate::net::IpCidr;
+use crate::notifications::{
+    NotificationDispatcher, EMAIL_CHANGED, EMAIL_CHANGE_CONFIRMATION, EMAIL_VERIFICATION, NEW_DEVICE_LOGIN,
+    PASSWORD_CHANGED, REFRESH_TOKEN_REUSED, REGISTRATION_WELCOME,
+};
+use crate::validation::{rules, unicode};
+use chrono::{DateTime, Utc};
 use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
-    Argon2,
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
+    Algorithm, Argon2, Params, Version,
 };
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use regex::Regex;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::IpAddr;
 use std::sync::Arc;
+use uuid::Uuid;
+use std::time::{Duration, Instant};
+
+/// How long a session check is trusted before the database is consulted
+/// again. Bounds both the write rate for `last_used_at` and how long a
+/// session revoked by another process keeps working.
+const SESSION_TOUCH_INTERVAL: Duration = Duration::from_secs(60);
+const SESSION_CACHE_PRUNE_THRESHOLD: usize = 1024;
+
+/// How long a requested email change can be confirmed.
+const EMAIL_CONFIRMATION_HOURS: i64 = 24;
+
+const MAX_DISPLAY_NAME_LENGTH: usize = 100;
+const MAX_TIMEZONE_LENGTH: usize = 64;
+
+lazy_static! {
+    static ref TIMEZONE_REGEX: Regex =
+        Regex::new(r"^(UTC|[A-Z][A-Za-z_]+(/[A-Za-z0-9_+\-]+){1,2})$").unwrap();
+}
+
+/// How new registrations are verified, when they are.
+#[derive(Debug, Clone, Copy)]
+struct EmailVerification {
+    policy: UnverifiedUserPolicy,
+    token_ttl: chrono::Duration,
+    resend_interval: chrono::Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SessionCheck {
+    checked_at: Instant,
+    active: bool,
+}
+
+/// Identifies a device for new-login notifications: the user agent plus the
+/// client's network (/24 for IPv4, /48 for IPv6), so that address churn
+/// within one network does not look like a new device.
+fn device_fingerprint(user_agent: Option<&str>, ip_address: Option<&str>) -> String {
+    let network = ip_address
+        .and_then(|ip| ip.parse::<IpAddr>().ok())
+        .and_then(|ip| IpCidr::new(ip, if ip.is_ipv4() { 24 } else { 48 }).ok())
+        .map(|cidr| cidr.to_string())
+        .unwrap_or_default();
+
+    format!("{}|{}", user_agent.unwrap_or_default(), network)
+}
 
 #[derive(Clone)]
 pub struct AuthService {
     user_repository: Arc<dyn UserRepositoryTrait + Send + Sync>,
     jwt_service: Arc<JwtService>,
     argon2: Argon2<'static>,
+    session_checks: Arc<Mutex<HashMap<String, SessionCheck>>>,
+    notifications: Option<NotificationDispatcher>,
+    metrics: Option<Arc<dyn MetricsSink>>,
+    audit_log: Option<AuditLog>,
+    verification: Option<EmailVerification>,
+    /// When a verification email was last asked for, by lowercased address.
+    verification_resends: Arc<Mutex<HashMap<String, DateTime<Utc>>>>,
 }
 
 impl AuthService {
@@ -24,14 +99,74 @@ impl AuthService {
             user_repository: Arc::new(user_repository),
             jwt_service: Arc::new(jwt_service),
             argon2: Argon2::default(),
+            session_checks: Arc::new(Mutex::new(HashMap::new())),
+            notifications: None,
+            metrics: None,
+            audit_log: None,
+            verification: None,
+            verification_resends: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
+    /// Hashes new passwords with Argon2id using `params`.
+    pub fn with_argon2_params(mut self, params: Params) -> Self {
+        self.argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<dyn MetricsSink>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Where security events such as refresh token reuse are recorded.
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    pub fn with_notifications(mut self, dispatcher: NotificationDispatcher) -> Self {
+        self.notifications = Some(dispatcher);
+        self
+    }
+
+    /// Leaves new registrations unverified until the token emailed to them
+    /// is presented, restricting them meanwhile as `config` says. Needs
+    /// notifications to deliver the token.
+    pub fn with_email_verification(mut self, config: &AuthConfig) -> Self {
+        self.verification = Some(EmailVerification {
+            policy: config.unverified_users,
+            token_ttl: chrono::Duration::hours(config.verification_token_hours as i64),
+            resend_interval: chrono::Duration::seconds(config.verification_resend_seconds as i64),
+        });
+        self
+    }
+
+    /// What unverified users may do, or `None` when registrations aren't
+    /// verified at all.
+    pub fn unverified_user_policy(&self) -> Option<UnverifiedUserPolicy> {
+        self.verification.map(|verification| verification.policy)
+    }
+
     pub fn jwt_service(&self) -> &JwtService {
         &self.jwt_service
     }
 
+    /// Creates an account through self-registration. It starts unverified
+    /// when email verification is on, and the token is sent to its address.
     pub async fn register_user(&self, request: CreateUserRequest) -> Result<UserResponse, AppError> {
+        self.create_account(request, self.verification.is_none()).await
+    }
+
+    /// Creates an account whose email address is taken as verified, for
+    /// accounts set up by an administrator.
+    pub async fn register_verified_user(&self, request: CreateUserRequest) -> Result<UserResponse, AppError> {
+        self.create_account(request, true).await
+    }
+
+    async fn create_account(&self, mut request: CreateUserRequest, email_verified: bool) -> Result<UserResponse, AppError> {
+        request.username = unicode::normalize_line(&request.username);
+        request.email = unicode::normalize_line(&request.email);
         self.validate_registration_request(&request)?;
 
         if let Some(_) = self.user_repository.get_user_by_username(&request.username).await? {
@@ -44,12 +179,24 @@ impl AuthService {
 
         let password_hash = self.hash_password(&request.password)?;
 
-        let user = self.user_repository.create_user(&request, &password_hash).await?;
+        let user = self.user_repository.create_user(&request, &password_hash, email_verified).await?;
+
+        if user.email_verified {
+            self.notify(REGISTRATION_WELCOME, &user, json!({})).await;
+        } else {
+            self.send_verification(&user).await?;
+        }
 
         Ok(UserResponse::from(user))
     }
 
     pub async fn login(&self, request: LoginRequest) -> Result<LoginResponse, AppError> {
+        self.login_with_client(request, &SessionClient::default()).await
+    }
+
+    /// Logs in and opens a session recording the client's user agent and IP.
+    pub async fn login_with_client(&self, mut request: LoginRequest, client: &SessionClient) -> Result<LoginResponse, AppError> {
+        request.username = unicode::normalize_line(&request.username);
         self.validate_login_request(&request)?;
 
         let user = self
@@ -66,10 +213,34 @@ impl AuthService {
             return Err(AppError::Authentication("Invalid credentials".to_string()));
         }
 
+        if !user.email_verified && self.unverified_user_policy() == Some(UnverifiedUserPolicy::BlockLogin) {
+            return Err(AppError::Authorization(
+                "Verify your email address before signing in".to_string(),
+            ));
+        }
+
+        if self.needs_rehash(&user.password_hash) {
+            self.rehash_password(user.id, &request.password).await;
+        }
+
         self.user_repository.update_last_login(user.id).await?;
 
-        let access_token = self.jwt_service.generate_access_token(&user)?;
-        let refresh_token = self.jwt_service.generate_refresh_token(&user)?;
+        let is_new_device = self.is_new_device(user.id, client).await;
+        let session = self.user_repository.create_session(user.id, client).await?;
+        self.record_session_check(&session.id, true);
+
+        if is_new_device {
+            let context = json!({
+                "user_agent": client.user_agent.as_deref().unwrap_or("unknown"),
+                "network": client.ip_address.as_deref().unwrap_or("unknown"),
+            });
+            self.notify(NEW_DEVICE_LOGIN, &user, context).await;
+        }
+
+        let token_id = Uuid::new_v4().to_string();
+        self.user_repository.record_refresh_token(&session.id, &token_id).await?;
+        let access_token = self.jwt_service.generate_access_token_for_session(&user, Some(&session.id))?;
+        let refresh_token = self.jwt_service.generate_tracked_refresh_token(&user, &session.id, &token_id)?;
 
         Ok(LoginResponse {
             access_token,
@@ -80,6 +251,10 @@ impl AuthService {
         })
     }
 
+    /// Exchanges a refresh token for an access token and a new refresh
+    /// token. Presenting a refresh token that was already exchanged means
+    /// two parties hold it, so its session is revoked and everyone using it
+    /// has to sign in again.
     pub async fn refresh_token(&self, refresh_token: &str) -> Result<RefreshTokenResponse, AppError> {
         let claims = self.jwt_service.validate_refresh_token(refresh_token)?;
 
@@ -96,10 +271,39 @@ impl AuthService {
             return Err(AppError::Authentication("Account is disabled".to_string()));
         }
 
-        let access_token = self.jwt_service.generate_access_token(&user)?;
+        if let Some(session_id) = claims.sid.as_deref() {
+            if !self.user_repository.touch_session(session_id).await? {
+                self.record_session_check(session_id, false);
+                return Err(AppError::Authentication("Session has been revoked".to_string()));
+            }
+            self.record_session_check(session_id, true);
+        }
+
+        let refresh_token = match (claims.sid.as_deref(), claims.jti.as_deref()) {
+            (Some(session_id), Some(token_id)) => {
+                let next_id = Uuid::new_v4().to_string();
+                match self.user_repository.rotate_refresh_token(session_id, token_id, &next_id).await? {
+                    RefreshRotation::Rotated => Some(self.jwt_service.rotate_refresh_token(&user, &claims, &next_id)?),
+                    RefreshRotation::Replayed => {
+                        self.revoke_replayed_session(&user, session_id).await?;
+                        return Err(AppError::Authentication(
+                            "Refresh token has already been used; sign in again".to_string(),
+                        ));
+                    }
+                    RefreshRotation::Unknown => {
+                        return Err(AppError::Authentication("Invalid refresh token".to_string()));
+                    }
+                }
+            }
+            // Issued before refresh tokens were rotated; these are exchanged
+            // as they always were until they expire.
+            _ => None,
+        };
+        let access_token = self.jwt_service.refresh_access_token(&user, &claims)?;
 
         Ok(RefreshTokenResponse {
             access_token,
+            refresh_token,
             token_type: "Bearer".to_string(),
             expires_in: self.jwt_service.get_access_token_expiry_seconds(),
         })
@@ -121,14 +325,453 @@ impl AuthService {
             return Err(AppError::Authentication("Account is disabled".to_string()));
         }
 
+        self.check_session(&claims).await?;
+
         Ok(claims)
     }
 
+    /// Checks a token of either type as the endpoints that accept it would,
+    /// including its session, for services that receive our tokens but
+    /// don't hold the secret. `None` covers every reason a token isn't
+    /// usable; only storage failures are errors.
+    pub async fn introspect(&self, token: &str) -> Result<Option<JwtClaims>, AppError> {
+        let claims = match self
+            .jwt_service
+            .validate_access_token(token)
+            .or_else(|_| self.jwt_service.validate_refresh_token(token))
+        {
+            Ok(claims) => claims,
+            Err(_) => return Ok(None),
+        };
+        let Ok(user_id) = claims.sub.parse::<i64>() else {
+            return Ok(None);
+        };
+        match self.user_repository.get_user_by_id(user_id).await? {
+            Some(user) if user.is_active => {}
+            _ => return Ok(None),
+        }
+
+        match self.check_session(&claims).await {
+            Ok(()) => Ok(Some(claims)),
+            Err(AppError::Authentication(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Rejects tokens whose session has been revoked. The database is
+    /// consulted, and `last_used_at` bumped, at most once per
+    /// [`SESSION_TOUCH_INTERVAL`] per session.
+    pub async fn check_session(&self, claims: &JwtClaims) -> Result<(), AppError> {
+        let Some(session_id) = claims.sid.as_deref() else {
+            return Ok(());
+        };
+
+        let cached = self.session_checks.lock().get(session_id).copied();
+        let active = match cached {
+            Some(check) if check.checked_at.elapsed() < SESSION_TOUCH_INTERVAL => check.active,
+            _ => {
+                let active = self.user_repository.touch_session(session_id).await?;
+                self.record_session_check(session_id, active);
+                active
+            }
+        };
+
+        if active {
+            Ok(())
+        } else {
+            Err(AppError::Authentication("Session has been revoked".to_string()))
+        }
+    }
+
+    pub async fn list_sessions(&self, user_id: i64, current_session_id: Option<&str>) -> Result<Vec<SessionResponse>, AppError> {
+        let sessions = self.user_repository.list_sessions(user_id, false).await?;
+        Ok(sessions
+            .into_iter()
+            .map(|session| SessionResponse::from_session(session, current_session_id))
+            .collect())
+    }
+
+    pub async fn revoke_session(&self, user_id: i64, session_id: &str) -> Result<(), AppError> {
+        if !self.user_repository.revoke_session(user_id, session_id).await? {
+            return Err(AppError::NotFound("Session not found".to_string()));
+        }
+        self.record_session_check(session_id, false);
+        Ok(())
+    }
+
+    /// Revokes every session of the user except the current one and returns
+    /// how many were revoked.
+    pub async fn revoke_other_sessions(&self, user_id: i64, current_session_id: Option<&str>) -> Result<usize, AppError> {
+        let revoked = self.user_repository.revoke_other_sessions(user_id, current_session_id).await?;
+        for session_id in &revoked {
+            self.record_session_check(session_id, false);
+        }
+        Ok(revoked.len())
+    }
+
+    /// Issues a new access token for the current session once the user has
+    /// entered their password again, for endpoints behind
+    /// [`require_recent_auth`](crate::middleware::auth::require_recent_auth).
+    pub async fn reauthenticate(&self, user_id: i64, session_id: Option<&str>, password: &str) -> Result<RefreshTokenResponse, AppError> {
+        let user = self
+            .user_repository
+            .get_user_by_id(user_id)
+            .await?
+            .ok_or_else(|| AppError::Authentication("User not found".to_string()))?;
+
+        if !user.is_active {
+            return Err(AppError::Authentication("Account is disabled".to_string()));
+        }
+
+        if !self.verify_password(password, &user.password_hash)? {
+            return Err(AppError::Authentication("Invalid credentials".to_string()));
+        }
+
+        Ok(RefreshTokenResponse {
+            access_token: self.jwt_service.generate_access_token_for_session(&user, session_id)?,
+            refresh_token: None,
+            token_type: "Bearer".to_string(),
+            expires_in: self.jwt_service.get_access_token_expiry_seconds(),
+        })
+    }
+
+    /// Changes a user's role or active status on an admin's behalf,
+    /// returning the user before and after. Their sessions are revoked when
+    /// either changes, so tokens carrying the old role stop working. Leaving
+    /// no active admin is refused with a conflict.
+    pub async fn change_user_access(
+        &self,
+        user_id: i64,
+        role: Option<UserRole>,
+        is_active: Option<bool>,
+    ) -> Result<(UserResponse, UserResponse), AppError> {
+        let before = self
+            .user_repository
+            .get_user_by_id(user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        let role = role.map(|role| role.to_string());
+        let after = self
+            .user_repository
+            .update_user_access(user_id, role.as_deref(), is_active)
+            .await?;
+
+        if after.role != before.role || after.is_active != before.is_active {
+            self.revoke_other_sessions(user_id, None).await?;
+        }
+
+        Ok((UserResponse::from(before), UserResponse::from(after)))
+    }
+
+    /// Deletes a user and revokes their sessions, returning the deleted
+    /// user. Deleting the last active admin is refused with a conflict.
+    pub async fn delete_user(&self, user_id: i64) -> Result<UserResponse, AppError> {
+        let user = self
+            .user_repository
+            .get_user_by_id(user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        self.user_repository.delete_user(user_id).await?;
+        self.revoke_other_sessions(user_id, None).await?;
+
+        Ok(UserResponse::from(user))
+    }
+
+    /// Ends the session a reused refresh token belongs to and raises the
+    /// alarm: an audit event, a security metric and an email to the user.
+    async fn revoke_replayed_session(&self, user: &User, session_id: &str) -> Result<(), AppError> {
+        self.user_repository.revoke_session(user.id, session_id).await?;
+        self.record_session_check(session_id, false);
+        tracing::warn!("Refresh token reused for session {} of user {}; session revoked", session_id, user.id);
+
+        if let Some(metrics) = &self.metrics {
+            metrics.record_security_event("refresh_token_reused");
+        }
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.record(
+                AuditEvent::new("auth.refresh_token_reused")
+                    .with_actor(user.username.clone())
+                    .with_target(session_id)
+                    .with_details(json!({"user_id": user.id})),
+            );
+        }
+        self.notify(REFRESH_TOKEN_REUSED, user, json!({})).await;
+        Ok(())
+    }
+
+    fn record_session_check(&self, session_id: &str, active: bool) {
+        let mut checks = self.session_checks.lock();
+        if checks.len() >= SESSION_CACHE_PRUNE_THRESHOLD {
+            checks.retain(|_, check| check.checked_at.elapsed() < SESSION_TOUCH_INTERVAL);
+        }
+        checks.insert(session_id.to_string(), SessionCheck { checked_at: Instant::now(), active });
+    }
+
+    pub async fn change_password(&self, user_id: i64, request: ChangePasswordRequest) -> Result<(), AppError> {
+        let user = self
+            .user_repository
+            .get_user_by_id(user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        if !self.verify_password(&request.current_password, &user.password_hash)? {
+            return Err(AppError::Authentication("Current password is incorrect".to_string()));
+        }
+
+        self.validate_password_strength(&request.new_password)?;
+        let password_hash = self.hash_password(&request.new_password)?;
+        self.user_repository.update_password_hash(user.id, &password_hash).await?;
+
+        self.notify(PASSWORD_CHANGED, &user, json!({})).await;
+
+        Ok(())
+    }
+
+    /// Applies a self-service profile change. An email change needs the
+    /// current password; when notifications are enabled the new address only
+    /// takes effect once confirmed with the token sent to it, and until then
+    /// it is reported as `pending_email`. Avatar ownership is checked by the
+    /// caller, which has access to file storage.
+    pub async fn update_profile(&self, user_id: i64, request: UpdateProfileRequest) -> Result<UserResponse, AppError> {
+        if request.role.is_some() || request.is_active.is_some() {
+            return Err(AppError::Authorization(
+                "Role and active status cannot be changed through the profile".to_string(),
+            ));
+        }
+
+        let user = self
+            .user_repository
+            .get_user_by_id(user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        let mut input = UpdateUserInput::default();
+        if let Some(display_name) = request.display_name {
+            input.display_name = Some(display_name.map(|name| self.validate_display_name(&name)).transpose()?);
+        }
+        if let Some(timezone) = request.timezone {
+            input.timezone = Some(timezone.map(|zone| self.validate_timezone(&zone)).transpose()?);
+        }
+        if let Some(avatar_file_id) = request.avatar_file_id {
+            input.avatar_file_id = Some(avatar_file_id.map(|id| id.to_string()));
+        }
+        if let Some(preferences) = request.notification_preferences {
+            input.notification_preferences = Some(serde_json::to_string(&preferences)?);
+        }
+
+        let new_email = request
+            .email
+            .map(|email| unicode::normalize_line(&email))
+            .filter(|email| *email != user.email);
+        if let Some(email) = &new_email {
+            let password = request.current_password.as_deref().ok_or_else(|| {
+                AppError::BadRequest("current_password is required to change email".to_string())
+            })?;
+            if !self.verify_password(password, &user.password_hash)? {
+                return Err(AppError::Authentication("Current password is incorrect".to_string()));
+            }
+            self.validate_email_format(email)?;
+            if self.user_repository.get_user_by_email(email).await?.is_some() {
+                return Err(AppError::Conflict("Email already exists".to_string()));
+            }
+            if self.notifications.is_none() {
+                input.email = Some(email.clone());
+            }
+        }
+
+        let mut user = self.user_repository.update_user(user.id, &input).await?;
+
+        if let Some(email) = new_email.filter(|_| self.notifications.is_some()) {
+            let token = new_token();
+            let expires_at = chrono::Utc::now() + chrono::Duration::hours(EMAIL_CONFIRMATION_HOURS);
+            self.user_repository
+                .set_pending_email(user.id, &email, &hash_token(&token), expires_at)
+                .await?;
+
+            let context = json!({ "new_email": email, "token": token });
+            self.notify_to(EMAIL_CHANGE_CONFIRMATION, &user, &email, context).await;
+            user.pending_email = Some(email);
+        }
+
+        Ok(UserResponse::from(user))
+    }
+
+    /// The defaults the user keeps for the item, file and job lists.
+    pub async fn list_preferences(&self, user_id: i64) -> Result<ListPreferences, AppError> {
+        self.user_repository
+            .get_list_preferences(user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))
+    }
+
+    /// Replaces the user's list defaults with `preferences`, which the
+    /// caller has checked against the pagination limits.
+    pub async fn set_list_preferences(&self, user_id: i64, preferences: ListPreferences) -> Result<ListPreferences, AppError> {
+        self.user_repository.set_list_preferences(user_id, &preferences).await?;
+        Ok(preferences)
+    }
+
+    /// Applies the user's pending email change if `token` is the one sent to
+    /// the new address, and tells the old address about it.
+    pub async fn confirm_email_change(&self, user_id: i64, token: &str) -> Result<UserResponse, AppError> {
+        let previous = self
+            .user_repository
+            .get_user_by_id(user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        let user = self
+            .user_repository
+            .confirm_pending_email(user_id, &hash_token(token.trim()))
+            .await?
+            .ok_or_else(|| AppError::BadRequest("Invalid or expired confirmation token".to_string()))?;
+
+        self.notify_to(EMAIL_CHANGED, &user, &previous.email, json!({ "new_email": user.email })).await;
+
+        Ok(UserResponse::from(user))
+    }
+
+    /// Marks the account `token` was sent to verified. Each token works once,
+    /// and only until it expires. Tokens issued before this keep the
+    /// `unverified` claim until refreshed.
+    pub async fn verify_email(&self, token: &str) -> Result<UserResponse, AppError> {
+        let now = self.jwt_service.clock().now();
+        let user = self
+            .user_repository
+            .verify_email(&hash_token(token.trim()), now)
+            .await?
+            .ok_or_else(|| AppError::BadRequest("Invalid or expired verification token".to_string()))?;
+
+        tracing::info!("User {} verified their email address", user.id);
+        Ok(UserResponse::from(user))
+    }
+
+    /// Sends a fresh verification token to `email` if it belongs to an
+    /// unverified account, replacing the previous one. Whether it does is not
+    /// reported, so the endpoint can't be used to discover addresses; asking
+    /// again for the same address too soon is refused either way.
+    pub async fn resend_verification(&self, email: &str) -> Result<(), AppError> {
+        let Some(verification) = self.verification else {
+            return Ok(());
+        };
+        let email = unicode::normalize_line(email);
+        let now = self.jwt_service.clock().now();
+
+        {
+            let mut resends = self.verification_resends.lock();
+            if let Some(last) = resends.get(&email.to_lowercase()) {
+                let wait = verification.resend_interval - (now - *last);
+                if wait > chrono::Duration::zero() {
+                    return Err(AppError::RateLimit(format!(
+                        "Wait {} seconds before asking for another verification email",
+                        wait.num_seconds().max(1)
+                    )));
+                }
+            }
+            if resends.len() >= SESSION_CACHE_PRUNE_THRESHOLD {
+                resends.retain(|_, last| now - *last < verification.resend_interval);
+            }
+            resends.insert(email.to_lowercase(), now);
+        }
+
+        if let Some(user) = self.user_repository.get_user_by_email(&email).await? {
+            if !user.email_verified && user.is_active {
+                self.send_verification(&user).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Issues a verification token for `user` and emails it to them.
+    async fn send_verification(&self, user: &User) -> Result<(), AppError> {
+        let Some(verification) = self.verification else {
+            return Ok(());
+        };
+        let token = new_token();
+        let expires_at = self.jwt_service.clock().now() + verification.token_ttl;
+        self.user_repository
+            .set_verification_token(user.id, &hash_token(&token), expires_at)
+            .await?;
+
+        let context = json!({ "token": token, "hours": verification.token_ttl.num_hours() });
+        self.notify(EMAIL_VERIFICATION, user, context).await;
+        Ok(())
+    }
+
+    /// A login is from a new device when the user has signed in before but
+    /// never with this fingerprint. The first login after registration is
+    /// covered by the welcome message instead.
+    async fn is_new_device(&self, user_id: i64, client: &SessionClient) -> bool {
+        if self.notifications.is_none() {
+            return false;
+        }
+
+        let fingerprint = device_fingerprint(client.user_agent.as_deref(), client.ip_address.as_deref());
+        let fingerprint_of = |session: &Session| {
+            device_fingerprint(session.user_agent.as_deref(), session.ip_address.as_deref())
+        };
+
+        match self.user_repository.list_sessions(user_id, true).await {
+            Ok(sessions) => !sessions.is_empty() && !sessions.iter().any(|session| fingerprint_of(session) == fingerprint),
+            Err(e) => {
+                tracing::warn!("Skipping new-device check for user {}: {}", user_id, e);
+                false
+            }
+        }
+    }
+
+    /// Queues `template_id` for `user`, adding the user's name and the current
+    /// time to `context`.
+    async fn notify(&self, template_id: &str, user: &User, context: serde_json::Value) {
+        self.notify_to(template_id, user, &user.email, context).await;
+    }
+
+    /// [`notify`](Self::notify), delivered to `recipient` rather than the
+    /// user's current address. Skipped if the user turned `template_id` off.
+    async fn notify_to(&self, template_id: &str, user: &User, recipient: &str, mut context: serde_json::Value) {
+        let Some(dispatcher) = &self.notifications else {
+            return;
+        };
+        if !user.profile.notification_preferences.allows(template_id) {
+            return;
+        }
+
+        if let Some(fields) = context.as_object_mut() {
+            fields.insert("username".to_string(), json!(user.username));
+            fields.insert("email".to_string(), json!(user.email));
+            fields.insert("time".to_string(), json!(chrono::Utc::now().to_rfc2822()));
+        }
+        dispatcher.dispatch(template_id, recipient, context).await;
+    }
+
     pub async fn get_user_by_id(&self, user_id: i64) -> Result<Option<UserResponse>, AppError> {
         let user = self.user_repository.get_user_by_id(user_id).await?;
         Ok(user.map(UserResponse::from))
     }
 
+    pub async fn get_user_by_username(&self, username: &str) -> Result<Option<UserResponse>, AppError> {
+        let user = self.user_repository.get_user_by_username(username).await?;
+        Ok(user.map(UserResponse::from))
+    }
+
+    /// Users whose username contains `username`, with the total number of
+    /// matches.
+    pub async fn search_users(
+        &self,
+        username: &str,
+        sort: Option<&crate::database::SortSpec>,
+        limit: u64,
+        offset: u64,
+    ) -> Result<(Vec<UserResponse>, u64), AppError> {
+        let (users, total) = self
+            .user_repository
+            .search_users(username, sort, limit as i64, offset as i64)
+            .await?;
+        Ok((users.into_iter().map(UserResponse::from).collect(), total))
+    }
+
     fn hash_password(&self, password: &str) -> Result<String, AppError> {
         let salt = SaltString::generate(&mut OsRng);
         let password_hash = self
@@ -139,6 +782,45 @@ impl AuthService {
         Ok(password_hash.to_string())
     }
 
+    /// True when `hash` was made with a different algorithm, version or cost
+    /// than new hashes use. Unparseable hashes are left alone; they already
+    /// fail verification.
+    fn needs_rehash(&self, hash: &str) -> bool {
+        let Ok(parsed) = PasswordHash::new(hash) else {
+            return false;
+        };
+        let Ok(params) = Params::try_from(&parsed) else {
+            return true;
+        };
+        let current = self.argon2.params();
+
+        parsed.algorithm != Algorithm::Argon2id.ident()
+            || parsed.version != Some(Version::V0x13.into())
+            || params.m_cost() != current.m_cost()
+            || params.t_cost() != current.t_cost()
+            || params.p_cost() != current.p_cost()
+    }
+
+    /// Replaces a user's stored hash with one made with the current
+    /// parameters. Runs after a successful login, so failures are logged and
+    /// the login proceeds with the old hash.
+    async fn rehash_password(&self, user_id: i64, password: &str) {
+        let result = match self.hash_password(password) {
+            Ok(hash) => self.user_repository.update_password_hash(user_id, &hash).await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(()) => {
+                tracing::info!("Upgraded password hash for user {}", user_id);
+                if let Some(metrics) = &self.metrics {
+                    metrics.record_password_rehash();
+                }
+            }
+            Err(e) => tracing::warn!("Failed to upgrade password hash for user {}: {}", user_id, e),
+        }
+    }
+
     fn verify_password(&self, password: &str, hash: &str) -> Result<bool, AppError> {
         let parsed_hash = PasswordHash::new(hash)
             .map_err(|e| AppError::Authentication(format!("Invalid password hash: {}", e)))?;
@@ -154,13 +836,14 @@ impl AuthService {
             return Err(AppError::BadRequest("Username cannot be empty".to_string()));
         }
 
-        if request.username.len() < 3 {
+        let username_length = unicode::text_length(&request.username);
+        if username_length < 3 {
             return Err(AppError::BadRequest(
                 "Username must be at least 3 characters long".to_string(),
             ));
         }
 
-        if request.username.len() > 50 {
+        if username_length > 50 {
             return Err(AppError::BadRequest(
                 "Username cannot be longer than 50 characters".to_string(),
             ));
@@ -182,6 +865,34 @@ impl AuthService {
         Ok(())
     }
 
+    fn validate_display_name(&self, display_name: &str) -> Result<String, AppError> {
+        let display_name = unicode::normalize_line(display_name);
+        if display_name.trim().is_empty() {
+            return Err(AppError::BadRequest("Display name cannot be empty".to_string()));
+        }
+        if unicode::text_length(&display_name) > MAX_DISPLAY_NAME_LENGTH {
+            return Err(AppError::BadRequest(format!(
+                "Display name cannot be longer than {} characters",
+                MAX_DISPLAY_NAME_LENGTH
+            )));
+        }
+        if rules::validate_no_xss(&display_name).is_err() {
+            return Err(AppError::BadRequest("Display name contains invalid characters".to_string()));
+        }
+        Ok(display_name)
+    }
+
+    /// Accepts names shaped like IANA time zones (`UTC`, `America/New_York`,
+    /// `America/Argentina/Buenos_Aires`). There is no zone database to check
+    /// them against, so clients should offer a list rather than free text.
+    fn validate_timezone(&self, timezone: &str) -> Result<String, AppError> {
+        let timezone = timezone.trim();
+        if timezone.len() > MAX_TIMEZONE_LENGTH || !TIMEZONE_REGEX.is_match(timezone) {
+            return Err(AppError::BadRequest(format!("Unknown time zone: {}", timezone)));
+        }
+        Ok(timezone.to_string())
+    }
+
     fn validate_login_request(&self, request: &LoginRequest) -> Result<(), AppError> {
         if request.username.trim().is_empty() {
             return Err(AppError::BadRequest("Username cannot be empty".to_string()));
@@ -314,4 +1025,17 @@ impl AuthService {
     fn is_valid_email(&self, email: &str) -> bool {
         self.validate_email_format(email).is_ok()
     }
-}
\ No newline at end of file
+}
+
+/// A random token for a user to prove they can read an email address.
+fn new_token() -> String {
+    let mut token = [0u8; 32];
+    OsRng.fill_bytes(&mut token);
+    hex::encode(token)
+}
+
+/// Email confirmation and verification tokens are stored hashed, like
+/// passwords, so a leaked table cannot be used to confirm changes.
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
@@ -2,7 +2,7 @@
 mod tests {
     use crate::auth::{
         jwt::JwtService,
-        models::{CreateUserRequest, LoginRequest, User, UserRole},
+        models::{CreateUserRequest, LoginRequest, SessionClient, User, UserRole},
         repository::{UserRepository, UserRepositoryTrait},
         service::AuthService,
     };
@@ -38,6 +38,10 @@ mod tests {
             created_at: Utc::now(),
             last_login: None,
             is_active: true,
+            namespace: "default".to_string(),
+            profile: Default::default(),
+            pending_email: None,
+            email_verified: true,
         };
 
         let access_token = jwt_service.generate_access_token(&user).unwrap();
@@ -52,6 +56,39 @@ mod tests {
         assert_eq!(claims.token_type, "refresh");
     }
 
+    #[tokio::test]
+    async fn test_jwt_expiry_follows_injected_clock() {
+        use crate::clock::MockClock;
+
+        assert!(JwtService::with_secret("too-short").is_err());
+
+        let clock = MockClock::default();
+        let jwt_service = JwtService::with_secret("e4b1c0d2a9f8e7d6c5b4a3f2e1d0c9b8a7")
+            .unwrap()
+            .with_clock(clock.shared());
+        let user = User {
+            id: 7,
+            username: "clockuser".to_string(),
+            email: "clock@example.com".to_string(),
+            password_hash: "hash".to_string(),
+            role: "user".to_string(),
+            created_at: Utc::now(),
+            last_login: None,
+            is_active: true,
+            namespace: "default".to_string(),
+            profile: Default::default(),
+            pending_email: None,
+            email_verified: true,
+        };
+
+        let token = jwt_service.generate_access_token(&user).unwrap();
+        assert!(jwt_service.clone().validate_access_token(&token).is_ok());
+
+        clock.advance(std::time::Duration::from_secs(3600 + 61));
+        let err = jwt_service.validate_access_token(&token).unwrap_err();
+        assert!(err.to_string().contains("Token has expired"));
+    }
+
     #[tokio::test]
     async fn test_user_repository_create_and_get() {
         let pool = setup_test_db().await;
@@ -64,7 +101,7 @@ mod tests {
             role: Some(UserRole::User),
         };
 
-        let user = user_repo.create_user(&create_request, "hashed_password").await.unwrap();
+        let user = user_repo.create_user(&create_request, "hashed_password", true).await.unwrap();
         assert_eq!(user.username, "testuser");
         assert_eq!(user.email, "test@example.com");
         assert_eq!(user.role, "user");
@@ -155,4 +192,496 @@ mod tests {
 
         assert!("invalid".parse::<UserRole>().is_err());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_session_revocation() {
+        env::set_var("JWT_SECRET", "fcb2e0cf59920daee6d502b120f27c5a7cb86385");
+        let pool = setup_test_db().await;
+        let jwt_service = JwtService::new().unwrap();
+        let auth_service = AuthService::new(UserRepository::new(pool), jwt_service);
+
+        let user = auth_service
+            .register_user(CreateUserRequest {
+                username: "sessionuser".to_string(),
+                email: "session@example.com".to_string(),
+                password: "StrongTest123!".to_string(),
+                role: Some(UserRole::User),
+            })
+            .await
+            .unwrap();
+
+        let login = |agent: &str| {
+            let client = SessionClient {
+                user_agent: Some(agent.to_string()),
+                ip_address: Some("192.0.2.10".to_string()),
+            };
+            let auth_service = auth_service.clone();
+            async move {
+                let request = LoginRequest {
+                    username: "sessionuser".to_string(),
+                    password: "StrongTest123!".to_string(),
+                };
+                auth_service.login_with_client(request, &client).await.unwrap()
+            }
+        };
+        let laptop = login("laptop").await;
+        let phone = login("phone").await;
+        let tablet = login("tablet").await;
+
+        let laptop_claims = auth_service.validate_token(&laptop.access_token).await.unwrap();
+        let laptop_session = laptop_claims.sid.clone().unwrap();
+        let sessions = auth_service.list_sessions(user.id, Some(&laptop_session)).await.unwrap();
+        assert_eq!(sessions.len(), 3);
+        assert_eq!(sessions.iter().filter(|s| s.current).count(), 1);
+        assert!(sessions.iter().any(|s| s.user_agent.as_deref() == Some("phone")));
+
+        let phone_session = auth_service.validate_token(&phone.access_token).await.unwrap().sid.unwrap();
+        auth_service.revoke_session(user.id, &phone_session).await.unwrap();
+        assert!(auth_service.validate_token(&phone.access_token).await.is_err());
+        assert!(auth_service.refresh_token(&phone.refresh_token).await.is_err());
+        assert!(matches!(
+            auth_service.revoke_session(user.id, &phone_session).await,
+            Err(crate::error::AppError::NotFound(_))
+        ));
+
+        let revoked = auth_service.revoke_other_sessions(user.id, Some(&laptop_session)).await.unwrap();
+        assert_eq!(revoked, 1);
+        assert!(auth_service.validate_token(&tablet.access_token).await.is_err());
+        assert!(auth_service.validate_token(&laptop.access_token).await.is_ok());
+        assert!(auth_service.refresh_token(&laptop.refresh_token).await.is_ok());
+        assert_eq!(auth_service.list_sessions(user.id, None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_replayed_refresh_token_revokes_its_session() {
+        env::set_var("JWT_SECRET", "fcb2e0cf59920daee6d502b120f27c5a7cb86385");
+        let pool = setup_test_db().await;
+        let audit_log = crate::audit::AuditLog::new();
+        let metrics = std::sync::Arc::new(crate::metrics::MetricsCollector::new());
+        let auth_service = AuthService::new(UserRepository::new(pool), JwtService::new().unwrap())
+            .with_audit_log(audit_log.clone())
+            .with_metrics(metrics.clone());
+
+        auth_service
+            .register_user(CreateUserRequest {
+                username: "rotator".to_string(),
+                email: "rotator@example.com".to_string(),
+                password: "StrongTest123!".to_string(),
+                role: None,
+            })
+            .await
+            .unwrap();
+        let login = || {
+            auth_service.login(LoginRequest {
+                username: "rotator".to_string(),
+                password: "StrongTest123!".to_string(),
+            })
+        };
+        let victim = login().await.unwrap();
+        let other_device = login().await.unwrap();
+
+        // The victim rotates twice; the thief copied the first token.
+        let stolen = victim.refresh_token.clone();
+        let first = auth_service.refresh_token(&victim.refresh_token).await.unwrap();
+        let second = auth_service
+            .refresh_token(first.refresh_token.as_deref().unwrap())
+            .await
+            .unwrap();
+        let latest = second.refresh_token.unwrap();
+        assert_ne!(latest, stolen);
+        assert!(auth_service.validate_token(&second.access_token).await.is_ok());
+
+        let err = auth_service.refresh_token(&stolen).await.unwrap_err();
+        assert!(err.to_string().contains("already been used"), "{}", err);
+
+        // Every token of the family is dead, not just the replayed one.
+        assert!(auth_service.refresh_token(&latest).await.is_err());
+        assert!(auth_service.validate_token(&second.access_token).await.is_err());
+        assert!(auth_service.validate_token(&victim.access_token).await.is_err());
+        assert!(auth_service.refresh_token(first.refresh_token.as_deref().unwrap()).await.is_err());
+
+        // Sessions on other devices carry on.
+        assert!(auth_service.validate_token(&other_device.access_token).await.is_ok());
+        assert!(auth_service.refresh_token(&other_device.refresh_token).await.is_ok());
+
+        let events = audit_log.recent(Some("auth.refresh_token_reused"), 10);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].actor.as_deref(), Some("rotator"));
+        assert_eq!(metrics.get_snapshot(0).security_events.get("refresh_token_reused"), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn test_account_notifications_are_queued() {
+        use crate::jobs::{JobListParams, JobQueue, JobRepository, JobType};
+        use crate::notifications::{NotificationDispatcher, NEW_DEVICE_LOGIN, PASSWORD_CHANGED, REGISTRATION_WELCOME};
+
+        env::set_var("JWT_SECRET", "fcb2e0cf59920daee6d502b120f27c5a7cb86385");
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        UserRepository::new(pool.clone()).ensure_tables_exist().await.unwrap();
+        let job_repository = JobRepository::new(pool.clone());
+        job_repository.create_table().await.unwrap();
+        let job_queue = JobQueue::new(job_repository);
+
+        let auth_service = AuthService::new(UserRepository::new(pool), JwtService::new().unwrap())
+            .with_notifications(NotificationDispatcher::new(job_queue.clone()));
+
+        let user = auth_service
+            .register_user(CreateUserRequest {
+                username: "notifyuser".to_string(),
+                email: "notify@example.com".to_string(),
+                password: "StrongTest123!".to_string(),
+                role: None,
+            })
+            .await
+            .unwrap();
+
+        let login = |agent: &str, ip: &str| {
+            let client = SessionClient {
+                user_agent: Some(agent.to_string()),
+                ip_address: Some(ip.to_string()),
+            };
+            let auth_service = auth_service.clone();
+            async move {
+                let request = LoginRequest {
+                    username: "notifyuser".to_string(),
+                    password: "StrongTest123!".to_string(),
+                };
+                auth_service.login_with_client(request, &client).await.unwrap();
+            }
+        };
+        login("laptop", "192.0.2.10").await;
+        login("laptop", "192.0.2.77").await;
+        login("phone", "192.0.2.10").await;
+
+        let change = |current: &str| crate::auth::models::ChangePasswordRequest {
+            current_password: current.to_string(),
+            new_password: "EvenStronger456!".to_string(),
+        };
+        assert!(matches!(
+            auth_service.change_password(user.id, change("WrongPass123!")).await,
+            Err(crate::error::AppError::Authentication(_))
+        ));
+        auth_service.change_password(user.id, change("StrongTest123!")).await.unwrap();
+        let laptop = SessionClient {
+            user_agent: Some("laptop".to_string()),
+            ip_address: Some("192.0.2.10".to_string()),
+        };
+        let relogin = LoginRequest {
+            username: "notifyuser".to_string(),
+            password: "EvenStronger456!".to_string(),
+        };
+        assert!(auth_service.login_with_client(relogin, &laptop).await.is_ok());
+
+        let mut jobs = Vec::new();
+        for job in job_queue.list_jobs(JobListParams::default()).await.unwrap().jobs {
+            jobs.push(job_queue.get_job_status(job.id).await.unwrap().unwrap());
+        }
+        let mut templates: Vec<String> = jobs
+            .iter()
+            .filter(|job| job.job_type == JobType::Notification)
+            .map(|job| job.payload["template_id"].as_str().unwrap().to_string())
+            .collect();
+        templates.sort();
+        let mut expected = vec![REGISTRATION_WELCOME, NEW_DEVICE_LOGIN, PASSWORD_CHANGED];
+        expected.sort();
+        assert_eq!(templates, expected);
+        assert!(jobs.iter().all(|job| job.payload["recipient"] == "notify@example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_email_change_needs_password_and_confirmation() {
+        use crate::auth::models::{NotificationPreferences, UpdateProfileRequest};
+        use crate::error::AppError;
+        use crate::jobs::{Job, JobListParams, JobQueue, JobRepository, JobType};
+        use crate::notifications::{NotificationDispatcher, EMAIL_CHANGED, EMAIL_CHANGE_CONFIRMATION};
+
+        env::set_var("JWT_SECRET", "fcb2e0cf59920daee6d502b120f27c5a7cb86385");
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        UserRepository::new(pool.clone()).ensure_tables_exist().await.unwrap();
+        let job_repository = JobRepository::new(pool.clone());
+        job_repository.create_table().await.unwrap();
+        let job_queue = JobQueue::new(job_repository);
+        let auth_service = AuthService::new(UserRepository::new(pool), JwtService::new().unwrap())
+            .with_notifications(NotificationDispatcher::new(job_queue.clone()));
+
+        let user = auth_service
+            .register_user(CreateUserRequest {
+                username: "mover".to_string(),
+                email: "old@example.com".to_string(),
+                password: "StrongTest123!".to_string(),
+                role: None,
+            })
+            .await
+            .unwrap();
+        let notifications = || async {
+            let mut jobs: Vec<Job> = Vec::new();
+            for job in job_queue.list_jobs(JobListParams::default()).await.unwrap().jobs {
+                jobs.push(job_queue.get_job_status(job.id).await.unwrap().unwrap());
+            }
+            jobs.retain(|job| job.job_type == JobType::Notification);
+            jobs
+        };
+        let change_email = |password: Option<&str>| UpdateProfileRequest {
+            email: Some("new@example.com".to_string()),
+            current_password: password.map(str::to_string),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            auth_service.update_profile(user.id, change_email(None)).await,
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            auth_service.update_profile(user.id, change_email(Some("WrongPass123!"))).await,
+            Err(AppError::Authentication(_))
+        ));
+
+        let pending = auth_service
+            .update_profile(user.id, change_email(Some("StrongTest123!")))
+            .await
+            .unwrap();
+        assert_eq!(pending.email, "old@example.com");
+        assert_eq!(pending.pending_email.as_deref(), Some("new@example.com"));
+
+        let sent = notifications().await;
+        let confirmation = sent
+            .iter()
+            .find(|job| job.payload["template_id"] == EMAIL_CHANGE_CONFIRMATION)
+            .expect("confirmation queued");
+        assert_eq!(confirmation.payload["recipient"], "new@example.com");
+        let token = confirmation.payload["context"]["token"].as_str().unwrap().to_string();
+
+        assert!(matches!(
+            auth_service.confirm_email_change(user.id, "not-the-token").await,
+            Err(AppError::BadRequest(_))
+        ));
+        let confirmed = auth_service.confirm_email_change(user.id, &token).await.unwrap();
+        assert_eq!(confirmed.email, "new@example.com");
+        assert_eq!(confirmed.pending_email, None);
+        assert!(auth_service.confirm_email_change(user.id, &token).await.is_err());
+
+        let sent = notifications().await;
+        let notice = sent.iter().find(|job| job.payload["template_id"] == EMAIL_CHANGED).expect("notice queued");
+        assert_eq!(notice.payload["recipient"], "old@example.com");
+
+        // Turned-off notifications are not queued.
+        let quiet = UpdateProfileRequest {
+            notification_preferences: Some(NotificationPreferences {
+                new_device_login: true,
+                password_changed: false,
+            }),
+            ..Default::default()
+        };
+        auth_service.update_profile(user.id, quiet).await.unwrap();
+        let change = crate::auth::models::ChangePasswordRequest {
+            current_password: "StrongTest123!".to_string(),
+            new_password: "EvenStronger456!".to_string(),
+        };
+        auth_service.change_password(user.id, change).await.unwrap();
+        assert_eq!(notifications().await.len(), sent.len());
+    }
+
+    #[tokio::test]
+    async fn test_email_verification_tokens_expire_and_work_once() {
+        use crate::clock::MockClock;
+        use crate::config::{AuthConfig, UnverifiedUserPolicy};
+        use crate::error::AppError;
+        use crate::jobs::{JobListParams, JobQueue, JobRepository};
+        use crate::notifications::{NotificationDispatcher, EMAIL_VERIFICATION};
+        use std::time::Duration;
+
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        UserRepository::new(pool.clone()).ensure_tables_exist().await.unwrap();
+        let job_repository = JobRepository::new(pool.clone());
+        job_repository.create_table().await.unwrap();
+        let job_queue = JobQueue::new(job_repository);
+        let clock = MockClock::default();
+        let jwt_service = JwtService::with_secret("e4b1c0d2a9f8e7d6c5b4a3f2e1d0c9b8a7")
+            .unwrap()
+            .with_clock(clock.shared());
+        let config = AuthConfig {
+            unverified_users: UnverifiedUserPolicy::BlockLogin,
+            ..Default::default()
+        };
+        let auth_service = AuthService::new(UserRepository::new(pool), jwt_service)
+            .with_notifications(NotificationDispatcher::new(job_queue.clone()))
+            .with_email_verification(&config);
+
+        let register = |username: &str| CreateUserRequest {
+            username: username.to_string(),
+            email: format!("{}@example.com", username),
+            password: "StrongTest123!".to_string(),
+            role: None,
+        };
+        let login = |username: &str| LoginRequest {
+            username: username.to_string(),
+            password: "StrongTest123!".to_string(),
+        };
+        let sent_tokens = || async {
+            let mut tokens = Vec::new();
+            for job in job_queue.list_jobs(JobListParams::default()).await.unwrap().jobs {
+                let job = job_queue.get_job_status(job.id).await.unwrap().unwrap();
+                if job.payload["template_id"] == EMAIL_VERIFICATION {
+                    tokens.push(job.payload["context"]["token"].as_str().unwrap().to_string());
+                }
+            }
+            tokens
+        };
+
+        let user = auth_service.register_user(register("pending")).await.unwrap();
+        assert!(!user.email_verified);
+        assert!(matches!(auth_service.login(login("pending")).await, Err(AppError::Authorization(_))));
+
+        // An expired token is refused; asking again too soon is rate limited.
+        let expired = sent_tokens().await.pop().expect("verification queued");
+        clock.advance(Duration::from_secs(25 * 3600));
+        assert!(matches!(auth_service.verify_email(&expired).await, Err(AppError::BadRequest(_))));
+        auth_service.resend_verification("pending@example.com").await.unwrap();
+        assert!(matches!(
+            auth_service.resend_verification("Pending@example.com").await,
+            Err(AppError::RateLimit(_))
+        ));
+        // Unknown addresses are answered the same way.
+        auth_service.resend_verification("nobody@example.com").await.unwrap();
+
+        let tokens = sent_tokens().await;
+        assert_eq!(tokens.len(), 2);
+        let token = tokens.into_iter().find(|token| *token != expired).unwrap();
+        assert!(matches!(auth_service.verify_email(&expired).await, Err(AppError::BadRequest(_))));
+        let verified = auth_service.verify_email(&token).await.unwrap();
+        assert!(verified.email_verified);
+        assert!(matches!(auth_service.verify_email(&token).await, Err(AppError::BadRequest(_))));
+
+        let login = auth_service.login(login("pending")).await.unwrap();
+        let claims = auth_service.jwt_service().validate_access_token(&login.access_token).unwrap();
+        assert!(!claims.unverified);
+
+        let admin_made = auth_service.register_verified_user(register("invited")).await.unwrap();
+        assert!(admin_made.email_verified);
+    }
+
+    #[tokio::test]
+    async fn test_last_admin_survives_concurrent_demotion() {
+        use crate::error::AppError;
+
+        env::set_var("JWT_SECRET", "a3f9c07e21d84b56e9a0c1d2e3f4a5b6c7d8e9f0");
+        let temp_file = tempfile::NamedTempFile::new().unwrap();
+        let pool = crate::database::get_database_pool(&format!("sqlite:{}", temp_file.path().display()))
+            .await
+            .unwrap();
+        let user_repo = UserRepository::new(pool.clone());
+        user_repo.ensure_tables_exist().await.unwrap();
+        let auth_service = AuthService::new(UserRepository::new(pool), JwtService::new().unwrap());
+
+        let mut admins = Vec::new();
+        for name in ["root", "ops", "backup"] {
+            let request = CreateUserRequest {
+                username: name.to_string(),
+                email: format!("{name}@example.com"),
+                password: "unused".to_string(),
+                role: Some(UserRole::Admin),
+            };
+            admins.push(user_repo.create_user(&request, "hash", true).await.unwrap().id);
+        }
+
+        let attempts = admins.iter().map(|&id| {
+            let auth_service = auth_service.clone();
+            tokio::spawn(async move { auth_service.change_user_access(id, Some(UserRole::User), None).await })
+        });
+        let results: Vec<_> = futures_util::future::join_all(attempts)
+            .await
+            .into_iter()
+            .map(|joined| joined.unwrap())
+            .collect();
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 2);
+        assert_eq!(results.iter().filter(|r| matches!(r, Err(AppError::Conflict(_)))).count(), 1);
+
+        let mut remaining = Vec::new();
+        for &id in &admins {
+            let user = user_repo.get_user_by_id(id).await.unwrap().unwrap();
+            if user.role == "admin" && user.is_active {
+                remaining.push(id);
+            }
+        }
+        assert_eq!(remaining.len(), 1);
+        let last = remaining[0];
+
+        assert!(matches!(
+            auth_service.change_user_access(last, None, Some(false)).await,
+            Err(AppError::Conflict(_))
+        ));
+        assert!(matches!(auth_service.delete_user(last).await, Err(AppError::Conflict(_))));
+        assert!(matches!(
+            auth_service.change_user_access(9999, Some(UserRole::User), None).await,
+            Err(AppError::NotFound(_))
+        ));
+
+        let promoted = admins.iter().copied().find(|&id| id != last).unwrap();
+        let (before, after) = auth_service.change_user_access(promoted, Some(UserRole::Admin), None).await.unwrap();
+        assert_eq!(before.role, UserRole::User);
+        assert_eq!(after.role, UserRole::Admin);
+        let (_, after) = auth_service.change_user_access(last, None, Some(false)).await.unwrap();
+        assert!(!after.is_active);
+    }
+
+    #[tokio::test]
+    async fn test_legacy_password_hash_upgraded_on_login() {
+        use argon2::{
+            password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString},
+            Algorithm, Argon2, Params, Version,
+        };
+
+        env::set_var("JWT_SECRET", "fcb2e0cf59920daee6d502b120f27c5a7cb86385");
+        let pool = setup_test_db().await;
+        let user_repo = UserRepository::new(pool.clone());
+        let metrics = std::sync::Arc::new(crate::metrics::MetricsCollector::new());
+        let auth_service = AuthService::new(UserRepository::new(pool), JwtService::new().unwrap())
+            .with_argon2_params(Params::new(8192, 2, 1, None).unwrap())
+            .with_metrics(metrics.clone());
+
+        let legacy = Argon2::new(Algorithm::Argon2i, Version::V0x13, Params::new(4096, 1, 1, None).unwrap());
+        let legacy_hash = legacy
+            .hash_password(b"LegacyPass123!", &SaltString::generate(&mut OsRng))
+            .unwrap()
+            .to_string();
+        let request = CreateUserRequest {
+            username: "legacyuser".to_string(),
+            email: "legacy@example.com".to_string(),
+            password: "LegacyPass123!".to_string(),
+            role: None,
+        };
+        let user = user_repo.create_user(&request, &legacy_hash, true).await.unwrap();
+
+        let login = || {
+            auth_service.login(LoginRequest {
+                username: "legacyuser".to_string(),
+                password: "LegacyPass123!".to_string(),
+            })
+        };
+        assert!(login().await.is_ok());
+
+        let upgraded = user_repo.get_user_by_id(user.id).await.unwrap().unwrap().password_hash;
+        assert_ne!(upgraded, legacy_hash);
+        let parsed = PasswordHash::new(&upgraded).unwrap();
+        assert_eq!(parsed.algorithm, Algorithm::Argon2id.ident());
+        assert_eq!(Params::try_from(&parsed).unwrap().m_cost(), 8192);
+        assert_eq!(metrics.get_snapshot(0).password_rehashes, 1);
+
+        assert!(login().await.is_ok());
+        let after_second_login = user_repo.get_user_by_id(user.id).await.unwrap().unwrap().password_hash;
+        assert_eq!(after_second_login, upgraded);
+        assert_eq!(metrics.get_snapshot(0).password_rehashes, 1);
+    }
+}
@@ -1,11 +1,16 @@
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use lru::LruCache;
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 use tracing::{debug, warn};
+use crate::clock::{SharedClock, SystemClock};
 use crate::config::CacheConfig;
+use crate::middleware::coalesce::RequestCoalescer;
 
 #[derive(Debug, Clone)]
 pub struct CacheEntry {
@@ -17,7 +22,10 @@ pub struct CacheEntry {
 
 impl CacheEntry {
     pub fn new(data: serde_json::Value, ttl: Option<Duration>) -> Self {
-        let now = Utc::now();
+        Self::new_at(data, ttl, Utc::now())
+    }
+
+    pub fn new_at(data: serde_json::Value, ttl: Option<Duration>, now: DateTime<Utc>) -> Self {
         let expires_at = ttl.map(|duration| {
             now + chrono::Duration::from_std(duration).unwrap_or(chrono::Duration::seconds(300))
         });
@@ -31,11 +39,11 @@ impl CacheEntry {
     }
 
     pub fn is_expired(&self) -> bool {
-        if let Some(expires_at) = self.expires_at {
-            Utc::now() > expires_at
-        } else {
-            false
-        }
+        self.is_expired_at(Utc::now())
+    }
+
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| now > expires_at)
     }
 
     pub fn increment_access(&mut self) {
@@ -100,6 +108,28 @@ pub struct CacheManager {
     config: CacheConfig,
     stats: Arc<RwLock<CacheStats>>,
     last_cleanup: Arc<RwLock<Instant>>,
+    /// Keys stored under each dependency tag, so one mutation can drop every
+    /// entry derived from the affected resource.
+    tags: Arc<RwLock<HashMap<String, HashSet<String>>>>,
+    /// Keys being computed by [`get_or_compute`](Self::get_or_compute), each
+    /// held by the caller computing it.
+    flights: Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>,
+    /// Bumped by every invalidation, so a value computed across one is not
+    /// stored.
+    invalidations: Arc<AtomicU64>,
+    /// Shares one response between identical concurrent GETs, unless
+    /// `coalesce_requests` is off.
+    coalescer: Option<RequestCoalescer>,
+    last_clear: Arc<Mutex<Option<CacheClear>>>,
+    clock: SharedClock,
+}
+
+/// Who last cleared the whole cache through the API, and when.
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheClear {
+    pub at: DateTime<Utc>,
+    pub actor: String,
+    pub entries_removed: usize,
 }
 
 impl Clone for CacheManager {
@@ -109,6 +139,12 @@ impl Clone for CacheManager {
             config: self.config.clone(),
             stats: Arc::clone(&self.stats),
             last_cleanup: Arc::clone(&self.last_cleanup),
+            tags: Arc::clone(&self.tags),
+            flights: Arc::clone(&self.flights),
+            invalidations: Arc::clone(&self.invalidations),
+            coalescer: self.coalescer.clone(),
+            last_clear: Arc::clone(&self.last_clear),
+            clock: Arc::clone(&self.clock),
         }
     }
 }
@@ -120,19 +156,39 @@ impl CacheManager {
         )));
         let stats = Arc::new(RwLock::new(CacheStats::new(config.max_size)));
         let last_cleanup = Arc::new(RwLock::new(Instant::now()));
+        let coalescer = config
+            .coalesce_requests
+            .then(|| RequestCoalescer::new(Duration::from_secs(config.coalesce_timeout_seconds)));
 
         Self {
             cache,
             config,
             stats,
             last_cleanup,
+            tags: Arc::new(RwLock::new(HashMap::new())),
+            flights: Arc::new(Mutex::new(HashMap::new())),
+            invalidations: Arc::new(AtomicU64::new(0)),
+            coalescer,
+            last_clear: Arc::new(Mutex::new(None)),
+            clock: SystemClock::shared(),
         }
     }
 
+    /// Clock that entry expiry is measured against.
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        *self.last_cleanup.write() = clock.instant();
+        self.clock = clock;
+        self
+    }
+
     pub fn default() -> Self {
         Self::new(CacheConfig::default())
     }
 
+    pub fn request_coalescer(&self) -> Option<&RequestCoalescer> {
+        self.coalescer.as_ref()
+    }
+
     pub fn generate_key(&self, prefix: &str, components: &[&str]) -> String {
         let mut key = prefix.to_string();
         for component in components {
@@ -151,7 +207,7 @@ impl CacheManager {
         let mut cache = self.cache.write();
         
         if let Some(entry) = cache.get_mut(key) {
-            if entry.is_expired() {
+            if entry.is_expired_at(self.clock.now()) {
                 cache.pop(key);
                 if self.config.enable_stats {
                     self.stats.write().record_miss();
@@ -202,7 +258,7 @@ impl CacheManager {
         T: Serialize,
     {
         let data = serde_json::to_value(value)?;
-        let entry = CacheEntry::new(data, ttl);
+        let entry = CacheEntry::new_at(data, ttl, self.clock.now());
 
         let mut cache = self.cache.write();
         let was_evicted = cache.put(key.to_string(), entry).is_some();
@@ -219,6 +275,89 @@ impl CacheManager {
         Ok(())
     }
 
+    /// Stores `value` and records it under each of `tags` for
+    /// [`invalidate_tag`](Self::invalidate_tag).
+    pub fn set_with_tags<T>(&self, key: &str, value: &T, ttl: Option<Duration>, tags: &[String]) -> Result<(), serde_json::Error>
+    where
+        T: Serialize,
+    {
+        self.set_with_ttl(key, value, ttl)?;
+
+        let mut index = self.tags.write();
+        for tag in tags {
+            index.entry(tag.clone()).or_default().insert(key.to_string());
+        }
+        Ok(())
+    }
+
+    /// The value cached under `key`, or else the result of `compute`, stored
+    /// with `ttl` and `tags`. Callers missing the same key at the same time
+    /// wait for one computation rather than each running it; `refresh`
+    /// computes even when a value is cached. Alongside the value is whether
+    /// it came from the cache.
+    pub async fn get_or_compute<T, E, F, Fut>(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        tags: &[String],
+        refresh: bool,
+        compute: F,
+    ) -> Result<(T, bool), E>
+    where
+        T: Serialize + for<'de> Deserialize<'de>,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if !refresh {
+            if let Some(value) = self.get(key) {
+                return Ok((value, true));
+            }
+        }
+
+        let flight = self.flights.lock().entry(key.to_string()).or_default().clone();
+        let _computing = flight.lock().await;
+        // Whoever held the flight before us may have just stored the value.
+        if !refresh {
+            if let Some(value) = self.get(key) {
+                return Ok((value, true));
+            }
+        }
+
+        let generation = self.invalidations.load(Ordering::SeqCst);
+        let result = compute().await;
+        if let Ok(value) = &result {
+            if self.invalidations.load(Ordering::SeqCst) == generation {
+                if let Err(e) = self.set_with_tags(key, value, ttl, tags) {
+                    warn!("Failed to cache value for key {}: {}", key, e);
+                }
+            } else {
+                debug!("Not caching {}: invalidated while it was computed", key);
+            }
+        }
+        self.flights.lock().remove(key);
+
+        result.map(|value| (value, false))
+    }
+
+    /// Removes every entry stored under `tag` and returns how many were
+    /// still cached.
+    pub fn invalidate_tag(&self, tag: &str) -> usize {
+        self.invalidations.fetch_add(1, Ordering::SeqCst);
+        let Some(keys) = self.tags.write().remove(tag) else {
+            return 0;
+        };
+
+        let mut cache = self.cache.write();
+        let removed_count = keys.iter().filter(|key| cache.pop(key.as_str()).is_some()).count();
+
+        if self.config.enable_stats {
+            self.stats.write().update_size(cache.len());
+        }
+
+        debug!("Invalidated {} cache entries tagged {}", removed_count, tag);
+        removed_count
+    }
+
     pub fn remove(&self, key: &str) -> bool {
         let mut cache = self.cache.write();
         let removed = cache.pop(key).is_some();
@@ -235,8 +374,10 @@ impl CacheManager {
     }
 
     pub fn clear(&self) {
+        self.invalidations.fetch_add(1, Ordering::SeqCst);
         let mut cache = self.cache.write();
         cache.clear();
+        self.tags.write().clear();
         
         if self.config.enable_stats {
             self.stats.write().update_size(0);
@@ -245,7 +386,36 @@ impl CacheManager {
         debug!("Cleared all cache entries");
     }
 
+    /// Clears the cache on behalf of `actor` unless it was cleared less than
+    /// `clear_cooldown_seconds` ago and `force` is false, in which case the
+    /// time left is returned. Returns the number of entries removed.
+    pub fn clear_by(&self, actor: &str, force: bool) -> std::result::Result<usize, Duration> {
+        let mut last_clear = self.last_clear.lock();
+        let now = self.clock.now();
+        if let (false, Some(last)) = (force, last_clear.as_ref()) {
+            let cooldown = Duration::from_secs(self.config.clear_cooldown_seconds);
+            let elapsed = (now - last.at).to_std().unwrap_or_default();
+            if elapsed < cooldown {
+                return Err(cooldown - elapsed);
+            }
+        }
+
+        let entries_removed = self.len();
+        self.clear();
+        *last_clear = Some(CacheClear {
+            at: now,
+            actor: actor.to_string(),
+            entries_removed,
+        });
+        Ok(entries_removed)
+    }
+
+    pub fn last_clear(&self) -> Option<CacheClear> {
+        self.last_clear.lock().clone()
+    }
+
     pub fn invalidate_pattern(&self, pattern: &str) {
+        self.invalidations.fetch_add(1, Ordering::SeqCst);
         let mut cache = self.cache.write();
         let keys_to_remove: Vec<String> = cache
             .iter()
@@ -295,7 +465,7 @@ impl CacheManager {
     fn cleanup_expired_if_needed(&self) {
         const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
         
-        let now = Instant::now();
+        let now = self.clock.instant();
         let mut last_cleanup = self.last_cleanup.write();
         
         if now.duration_since(*last_cleanup) > CLEANUP_INTERVAL {
@@ -308,9 +478,10 @@ impl CacheManager {
     fn cleanup_expired(&self) {
         let mut cache = self.cache.write();
         let mut expired_keys = Vec::new();
+        let now = self.clock.now();
 
         for (key, entry) in cache.iter() {
-            if entry.is_expired() {
+            if entry.is_expired_at(now) {
                 expired_keys.push(key.clone());
             }
         }
@@ -319,6 +490,13 @@ impl CacheManager {
             cache.pop(&key);
         }
 
+        // Entries also leave through LRU eviction and expiry, so drop index
+        // references to keys that are no longer cached.
+        self.tags.write().retain(|_, keys| {
+            keys.retain(|key| cache.contains(key));
+            !keys.is_empty()
+        });
+
         if self.config.enable_stats {
             self.stats.write().update_size(cache.len());
         }
@@ -356,6 +534,7 @@ mod tests {
             default_ttl_seconds: 1,
             cleanup_interval_seconds: 60,
             enable_stats: true,
+            ..CacheConfig::default()
         };
         let cache = CacheManager::new(config);
         
@@ -369,6 +548,26 @@ mod tests {
         assert_eq!(expired, None);
     }
 
+    #[test]
+    fn test_cache_ttl_follows_injected_clock() {
+        let clock = crate::clock::MockClock::default();
+        let cache = CacheManager::new(CacheConfig {
+            max_size: 100,
+            default_ttl_seconds: 30,
+            cleanup_interval_seconds: 60,
+            enable_stats: true,
+            ..CacheConfig::default()
+        })
+        .with_clock(clock.shared());
+
+        cache.set("ttl_key", &"ttl_value").unwrap();
+        clock.advance(Duration::from_secs(29));
+        assert_eq!(cache.get::<String>("ttl_key"), Some("ttl_value".to_string()));
+
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(cache.get::<String>("ttl_key"), None);
+    }
+
     #[test]
     fn test_cache_key_generation() {
         let cache = CacheManager::default();
@@ -396,6 +595,26 @@ mod tests {
         assert_eq!(other, Some("other_profile".to_string()));
     }
 
+    #[test]
+    fn test_cache_tag_invalidation() {
+        let cache = CacheManager::default();
+        let tags = |tags: &[&str]| tags.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+
+        cache.set_with_tags("list?page=1", &"page 1", None, &tags(&["items"])).unwrap();
+        cache.set_with_tags("list?page=2", &"page 2", None, &tags(&["items"])).unwrap();
+        cache.set_with_tags("item/4", &"item 4", None, &tags(&["items:4"])).unwrap();
+        cache.set_with_tags("item/42", &"item 42", None, &tags(&["items:42"])).unwrap();
+
+        assert_eq!(cache.invalidate_tag("items:4"), 1);
+        assert_eq!(cache.get::<String>("item/4"), None);
+        assert_eq!(cache.get::<String>("item/42"), Some("item 42".to_string()));
+
+        assert_eq!(cache.invalidate_tag("items"), 2);
+        assert_eq!(cache.get::<String>("list?page=1"), None);
+        assert_eq!(cache.get::<String>("list?page=2"), None);
+        assert_eq!(cache.invalidate_tag("items"), 0);
+    }
+
     #[test]
     fn test_cache_stats() {
         let cache = CacheManager::default();
@@ -413,4 +632,58 @@ mod tests {
         assert_eq!(stats.total_requests, 3);
         assert!((stats.hit_rate - 0.6666666666666666).abs() < 0.0001);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_get_or_compute_runs_concurrent_misses_once() {
+        use std::sync::atomic::AtomicUsize;
+
+        let cache = CacheManager::default();
+        let computations = Arc::new(AtomicUsize::new(0));
+        let requests = (0..8).map(|_| {
+            let cache = cache.clone();
+            let computations = computations.clone();
+            tokio::spawn(async move {
+                cache
+                    .get_or_compute("stats", None, &[], false, || async {
+                        computations.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        Ok::<_, String>(42)
+                    })
+                    .await
+            })
+        });
+        let results = futures_util::future::join_all(requests).await;
+
+        assert_eq!(computations.load(Ordering::SeqCst), 1);
+        let misses = results.iter().filter(|result| matches!(result, Ok(Ok((42, false))))).count();
+        let hits = results.iter().filter(|result| matches!(result, Ok(Ok((42, true))))).count();
+        assert_eq!((misses, hits), (1, 7));
+
+        let refreshed = cache.get_or_compute("stats", None, &[], true, || async { Ok::<_, String>(43) }).await;
+        assert_eq!(refreshed, Ok((43, false)));
+        assert_eq!(cache.get::<i32>("stats"), Some(43));
+
+        let failed = cache.get_or_compute::<i32, _, _, _>("other", None, &[], false, || async { Err("down".to_string()) }).await;
+        assert_eq!(failed, Err("down".to_string()));
+        assert!(!cache.contains_key("other"));
+    }
+
+    #[tokio::test]
+    async fn test_get_or_compute_skips_values_invalidated_while_computing() {
+        let cache = CacheManager::default();
+        let tags = vec!["items".to_string()];
+
+        let computed = cache
+            .get_or_compute("stats", None, &tags, false, || async {
+                cache.invalidate_tag("items");
+                Ok::<_, String>(1)
+            })
+            .await;
+        assert_eq!(computed, Ok((1, false)));
+        assert!(!cache.contains_key("stats"));
+
+        let computed = cache.get_or_compute("stats", None, &tags, false, || async { Ok::<_, String>(2) }).await;
+        assert_eq!(computed, Ok((2, false)));
+        assert_eq!(cache.invalidate_tag("items"), 1);
+    }
+}
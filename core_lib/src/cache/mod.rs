@@ -1,3 +1,3 @@
 pub mod memory;
 
-pub use memory::{CacheManager, CacheEntry, CacheStats};
\ No newline at end of file
+pub use memory::{CacheClear, CacheManager, CacheEntry, CacheStats};
\ No newline at end of file
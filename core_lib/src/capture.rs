@@ -0,0 +1,484 @@
+//! Recording of request/response pairs for debugging
+//!
+//! When a client reports a strange response, an admin can start a capture
+//! through `POST /api/admin/captures`, optionally narrowed to a path prefix
+//! or a user. Until it is stopped or its duration runs out, every matching
+//! exchange is kept with its headers (credentials masked) and bodies (cut
+//! at `capture.max_body_bytes`, item secrets masked), the oldest dropped
+//! beyond `capture.max_entries`. `GET /api/admin/captures` returns them as
+//! a HAR log. Starting and stopping are recorded in the audit log.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::audit::{AuditEvent, AuditLog};
+use crate::config::CaptureConfig;
+use crate::error::{AppError, Result};
+
+/// Replaces the value of a redacted header.
+const REDACTED: &str = "[REDACTED]";
+
+/// A running capture and what it records.
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureSession {
+    pub id: Uuid,
+    pub started_by: String,
+    pub started_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// Only requests whose path starts with this are recorded.
+    pub path_prefix: Option<String>,
+    /// Only requests by this user are recorded.
+    pub user: Option<String>,
+}
+
+impl CaptureSession {
+    pub fn matches(&self, path: &str, user: Option<&str>) -> bool {
+        self.path_prefix.as_deref().is_none_or(|prefix| path.starts_with(prefix))
+            && self.user.as_deref().is_none_or(|wanted| user == Some(wanted))
+    }
+}
+
+/// What a capture should record, as given when starting it.
+#[derive(Debug, Clone, Default)]
+pub struct CaptureRequest {
+    pub duration_seconds: Option<u64>,
+    pub path_prefix: Option<String>,
+    pub user: Option<String>,
+}
+
+/// The start of a request or response body, up to the size cap.
+#[derive(Debug, Clone, Default)]
+pub struct CapturedBody {
+    bytes: Vec<u8>,
+    /// Bytes seen in total, including those past the cap.
+    size: u64,
+    /// Set for bodies that are never recorded, such as login requests.
+    omitted: bool,
+}
+
+impl CapturedBody {
+    pub fn omitted() -> Self {
+        Self {
+            omitted: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn push(&mut self, chunk: &[u8], cap: usize) {
+        self.size += chunk.len() as u64;
+        if !self.omitted && self.bytes.len() < cap {
+            let room = cap - self.bytes.len();
+            self.bytes.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        }
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        !self.omitted && self.size > self.bytes.len() as u64
+    }
+
+    /// The recorded bytes as text, with item secrets masked in JSON bodies.
+    fn text(&self) -> String {
+        if let Ok(mut value) = serde_json::from_slice::<Value>(&self.bytes) {
+            if crate::item_secrets::redact_json(&mut value) {
+                return value.to_string();
+            }
+        }
+        String::from_utf8_lossy(&self.bytes).into_owned()
+    }
+
+    fn comment(&self) -> &'static str {
+        if self.omitted {
+            "body not recorded"
+        } else if self.is_truncated() {
+            "body truncated"
+        } else {
+            ""
+        }
+    }
+}
+
+/// One recorded request and its response.
+#[derive(Debug, Clone)]
+pub struct CapturedExchange {
+    pub started_at: DateTime<Utc>,
+    pub duration: Duration,
+    pub method: String,
+    pub url: String,
+    pub http_version: String,
+    pub user: Option<String>,
+    pub request_headers: Vec<(String, String)>,
+    pub request_body: CapturedBody,
+    pub status: u16,
+    pub response_headers: Vec<(String, String)>,
+    pub response_body: CapturedBody,
+}
+
+struct CaptureInner {
+    config: CaptureConfig,
+    session: RwLock<Option<CaptureSession>>,
+    exchanges: Mutex<VecDeque<CapturedExchange>>,
+    audit_log: AuditLog,
+}
+
+/// The capture state shared by the middleware and the admin endpoints.
+#[derive(Clone)]
+pub struct CaptureRecorder {
+    inner: Arc<CaptureInner>,
+}
+
+impl CaptureRecorder {
+    pub fn new(config: &CaptureConfig, audit_log: AuditLog) -> Self {
+        Self {
+            inner: Arc::new(CaptureInner {
+                config: config.clone(),
+                session: RwLock::new(None),
+                exchanges: Mutex::new(VecDeque::new()),
+                audit_log,
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &CaptureConfig {
+        &self.inner.config
+    }
+
+    /// Starts a capture on behalf of `actor`, discarding what the previous
+    /// one recorded. It stops by itself once its duration has passed.
+    pub fn start(&self, actor: &str, request: CaptureRequest) -> Result<CaptureSession> {
+        let config = &self.inner.config;
+        let duration = request.duration_seconds.unwrap_or(config.default_duration_seconds);
+        if duration == 0 || duration > config.max_duration_seconds {
+            return Err(AppError::BadRequest(format!(
+                "Capture duration must be between 1 and {} seconds",
+                config.max_duration_seconds
+            )));
+        }
+
+        let session = {
+            let mut current = self.inner.session.write();
+            if let Some(running) = current.as_ref().filter(|running| running.expires_at > Utc::now()) {
+                return Err(AppError::BadRequest(format!(
+                    "A capture started by {} is already running until {}",
+                    running.started_by, running.expires_at
+                )));
+            }
+            let now = Utc::now();
+            let session = CaptureSession {
+                id: Uuid::new_v4(),
+                started_by: actor.to_string(),
+                started_at: now,
+                expires_at: now + chrono::Duration::seconds(duration as i64),
+                path_prefix: request.path_prefix.filter(|prefix| !prefix.is_empty()),
+                user: request.user.filter(|user| !user.is_empty()),
+            };
+            self.inner.exchanges.lock().clear();
+            *current = Some(session.clone());
+            session
+        };
+
+        info!("Request capture started by {} for {} seconds", actor, duration);
+        self.inner.audit_log.record(
+            AuditEvent::new("capture.started")
+                .with_actor(actor)
+                .with_target(session.id.to_string())
+                .with_details(json!({
+                    "expires_at": session.expires_at,
+                    "path_prefix": session.path_prefix,
+                    "user": session.user,
+                })),
+        );
+
+        let recorder = self.clone();
+        let id = session.id;
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(duration)).await;
+            recorder.end(id, None, "expired");
+        });
+
+        Ok(session)
+    }
+
+    /// Stops the running capture on behalf of `actor`, keeping what it
+    /// recorded.
+    pub fn stop(&self, actor: &str) -> Option<CaptureSession> {
+        let id = self.inner.session.read().as_ref()?.id;
+        self.end(id, Some(actor), "stopped")
+    }
+
+    /// The running capture, if any. One past its expiry is ended here so
+    /// that nothing more is recorded even before its timer fires.
+    pub fn active(&self) -> Option<CaptureSession> {
+        let session = self.inner.session.read().clone()?;
+        if session.expires_at <= Utc::now() {
+            self.end(session.id, None, "expired");
+            return None;
+        }
+        Some(session)
+    }
+
+    /// Ends capture `id` if it is still the running one, so that each
+    /// capture is audited as ended once.
+    fn end(&self, id: Uuid, actor: Option<&str>, reason: &str) -> Option<CaptureSession> {
+        let session = {
+            let mut current = self.inner.session.write();
+            if current.as_ref().is_none_or(|session| session.id != id) {
+                return None;
+            }
+            current.take()?
+        };
+
+        let recorded = self.inner.exchanges.lock().len();
+        info!("Request capture {} ({}) after recording {} exchanges", reason, session.id, recorded);
+        let mut event = AuditEvent::new("capture.stopped")
+            .with_target(session.id.to_string())
+            .with_details(json!({ "reason": reason, "recorded": recorded }));
+        if let Some(actor) = actor {
+            event = event.with_actor(actor);
+        }
+        self.inner.audit_log.record(event);
+        Some(session)
+    }
+
+    pub fn record(&self, exchange: CapturedExchange) {
+        let mut exchanges = self.inner.exchanges.lock();
+        while exchanges.len() >= self.inner.config.max_entries.max(1) {
+            exchanges.pop_front();
+        }
+        exchanges.push_back(exchange);
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.exchanges.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.exchanges.lock().is_empty()
+    }
+
+    /// `headers` as name/value pairs, with those in `redact_headers` masked.
+    pub fn redact(&self, headers: &HeaderMap) -> Vec<(String, String)> {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let redacted = self
+                    .inner
+                    .config
+                    .redact_headers
+                    .iter()
+                    .any(|denied| name.as_str().eq_ignore_ascii_case(denied));
+                let value = if redacted {
+                    REDACTED.to_string()
+                } else {
+                    String::from_utf8_lossy(value.as_bytes()).to_string()
+                };
+                (name.to_string(), value)
+            })
+            .collect()
+    }
+
+    /// The recorded exchanges as a HAR 1.2 log, oldest first.
+    pub fn to_har(&self, creator: &str, version: &str) -> Value {
+        let session = self.inner.session.read().clone();
+        let entries: Vec<Value> = self.inner.exchanges.lock().iter().map(har_entry).collect();
+        json!({
+            "log": {
+                "version": "1.2",
+                "creator": { "name": creator, "version": version },
+                "comment": match session {
+                    Some(session) => format!("Capture {} running until {}", session.id, session.expires_at),
+                    None => "No capture running".to_string(),
+                },
+                "entries": entries,
+            }
+        })
+    }
+}
+
+fn har_headers(headers: &[(String, String)]) -> Vec<Value> {
+    headers
+        .iter()
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect()
+}
+
+fn header_value<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn har_entry(exchange: &CapturedExchange) -> Value {
+    let time = exchange.duration.as_secs_f64() * 1000.0;
+    let query: Vec<Value> = exchange
+        .url
+        .split_once('?')
+        .map(|(_, query)| {
+            url_pairs(query)
+                .map(|(name, value)| json!({ "name": name, "value": value }))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut request = json!({
+        "method": exchange.method,
+        "url": exchange.url,
+        "httpVersion": exchange.http_version,
+        "cookies": [],
+        "headers": har_headers(&exchange.request_headers),
+        "queryString": query,
+        "headersSize": -1,
+        "bodySize": exchange.request_body.size,
+    });
+    if exchange.request_body.size > 0 || exchange.request_body.omitted {
+        request["postData"] = json!({
+            "mimeType": header_value(&exchange.request_headers, "content-type").unwrap_or_default(),
+            "text": exchange.request_body.text(),
+            "comment": exchange.request_body.comment(),
+        });
+    }
+
+    json!({
+        "startedDateTime": exchange.started_at.to_rfc3339(),
+        "time": time,
+        "comment": exchange.user.as_ref().map(|user| format!("user: {}", user)).unwrap_or_default(),
+        "request": request,
+        "response": {
+            "status": exchange.status,
+            "statusText": axum::http::StatusCode::from_u16(exchange.status)
+                .ok()
+                .and_then(|status| status.canonical_reason())
+                .unwrap_or_default(),
+            "httpVersion": exchange.http_version,
+            "cookies": [],
+            "headers": har_headers(&exchange.response_headers),
+            "content": {
+                "size": exchange.response_body.size,
+                "mimeType": header_value(&exchange.response_headers, "content-type").unwrap_or_default(),
+                "text": exchange.response_body.text(),
+                "comment": exchange.response_body.comment(),
+            },
+            "redirectURL": header_value(&exchange.response_headers, "location").unwrap_or_default(),
+            "headersSize": -1,
+            "bodySize": exchange.response_body.size,
+        },
+        "cache": {},
+        "timings": { "send": 0, "wait": time, "receive": 0 },
+    })
+}
+
+/// The raw name/value pairs of a query string.
+fn url_pairs(query: &str) -> impl Iterator<Item = (&str, &str)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorder(max_entries: usize) -> CaptureRecorder {
+        let config = CaptureConfig {
+            enabled: true,
+            max_entries,
+            max_body_bytes: 4,
+            ..CaptureConfig::default()
+        };
+        CaptureRecorder::new(&config, AuditLog::new())
+    }
+
+    fn exchange(url: &str) -> CapturedExchange {
+        let mut body = CapturedBody::default();
+        body.push(b"hello world", 4);
+        CapturedExchange {
+            started_at: Utc::now(),
+            duration: Duration::from_millis(5),
+            method: "GET".to_string(),
+            url: url.to_string(),
+            http_version: "HTTP/1.1".to_string(),
+            user: None,
+            request_headers: vec![("authorization".to_string(), REDACTED.to_string())],
+            request_body: CapturedBody::default(),
+            status: 200,
+            response_headers: vec![("content-type".to_string(), "text/plain".to_string())],
+            response_body: body,
+        }
+    }
+
+    #[test]
+    fn test_captured_body_is_capped() {
+        let mut body = CapturedBody::default();
+        body.push(b"abc", 4);
+        body.push(b"defg", 4);
+        assert_eq!(body.bytes, b"abcd");
+        assert_eq!(body.size, 7);
+        assert!(body.is_truncated());
+
+        let mut omitted = CapturedBody::omitted();
+        omitted.push(b"secret", 4);
+        assert!(omitted.bytes.is_empty());
+        assert!(!omitted.is_truncated());
+    }
+
+    #[test]
+    fn test_session_filters() {
+        let session = CaptureSession {
+            id: Uuid::new_v4(),
+            started_by: "admin".to_string(),
+            started_at: Utc::now(),
+            expires_at: Utc::now(),
+            path_prefix: Some("/api/items".to_string()),
+            user: Some("alice".to_string()),
+        };
+        assert!(session.matches("/api/items/1", Some("alice")));
+        assert!(!session.matches("/api/items/1", Some("bob")));
+        assert!(!session.matches("/api/items/1", None));
+        assert!(!session.matches("/health", Some("alice")));
+    }
+
+    #[tokio::test]
+    async fn test_recorder_is_bounded_and_exports_har() {
+        let recorder = recorder(2);
+        recorder.start("admin", CaptureRequest::default()).unwrap();
+        assert!(recorder.start("admin", CaptureRequest::default()).is_err());
+        for url in ["/a", "/b?x=1&y", "/c"] {
+            recorder.record(exchange(url));
+        }
+        assert_eq!(recorder.len(), 2);
+
+        let har = recorder.to_har("test", "0.1.0");
+        let entries = har["log"]["entries"].as_array().unwrap();
+        assert_eq!(entries[0]["request"]["url"], "/b?x=1&y");
+        assert_eq!(entries[0]["request"]["queryString"][1]["name"], "y");
+        assert_eq!(entries[0]["response"]["content"]["text"], "hell");
+        assert_eq!(entries[0]["response"]["content"]["comment"], "body truncated");
+        assert_eq!(entries[0]["response"]["statusText"], "OK");
+
+        assert!(recorder.stop("admin").is_some());
+        assert!(recorder.stop("admin").is_none());
+        assert!(recorder.active().is_none());
+        assert_eq!(recorder.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_durations_past_the_limit() {
+        let recorder = recorder(2);
+        let too_long = CaptureRequest {
+            duration_seconds: Some(recorder.config().max_duration_seconds + 1),
+            ..CaptureRequest::default()
+        };
+        assert!(recorder.start("admin", too_long).is_err());
+        assert!(recorder.active().is_none());
+    }
+}
@@ -0,0 +1,216 @@
+//! Item change feed
+//!
+//! Every item create, update and delete is recorded with a sequence number
+//! that only ever grows, so clients can replay what changed since the last
+//! sequence they saw instead of fetching every item again. The database
+//! writes records in the same transaction as the item (see
+//! [`ItemRepository`](crate::database::ItemRepository)); the in-memory store
+//! keeps a [`ChangeLog`]. Old records are dropped according to
+//! [`ChangeFeedConfig`], after which clients behind them must resync.
+
+use crate::config::ChangeFeedConfig;
+use crate::error::{AppError, Result};
+use crate::store::Item;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+
+pub const DEFAULT_CHANGES_LIMIT: usize = 100;
+pub const MAX_CHANGES_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ChangesQuery {
+    /// Last sequence number the client has seen; changes after it are
+    /// returned. Defaults to 0, the start of the feed.
+    pub since: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeOp {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl ChangeOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeOp::Created => "created",
+            ChangeOp::Updated => "updated",
+            ChangeOp::Deleted => "deleted",
+        }
+    }
+
+    pub fn parse(op: &str) -> Option<Self> {
+        match op {
+            "created" => Some(ChangeOp::Created),
+            "updated" => Some(ChangeOp::Updated),
+            "deleted" => Some(ChangeOp::Deleted),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ItemChange {
+    pub seq: u64,
+    pub op: ChangeOp,
+    pub item_id: u64,
+    /// The item as the change left it; `None` is the tombstone of a delete.
+    pub item: Option<Item>,
+    pub changed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangePage {
+    pub changes: Vec<ItemChange>,
+    /// `since` for the next request: the last sequence number returned, or
+    /// the requested one when nothing changed.
+    pub next_since: u64,
+    pub has_more: bool,
+}
+
+impl ChangePage {
+    /// Page of up to `limit` changes from `changes`, which the backend
+    /// fetched with one extra entry to tell whether more follow.
+    pub fn new(since: u64, mut changes: Vec<ItemChange>, limit: usize) -> Self {
+        let has_more = changes.len() > limit;
+        changes.truncate(limit);
+        let next_since = changes.last().map(|change| change.seq).unwrap_or(since);
+
+        Self {
+            changes,
+            next_since,
+            has_more,
+        }
+    }
+}
+
+/// Fails with [`AppError::Gone`] when changes after `since` have already
+/// been dropped, given the oldest sequence number still held and the
+/// newest one assigned.
+pub fn ensure_retained(since: u64, oldest: u64, latest: u64) -> Result<()> {
+    if since.saturating_add(1) >= oldest {
+        return Ok(());
+    }
+
+    Err(AppError::Gone(format!(
+        "Changes after sequence {} are no longer retained (oldest is {}); fetch all items again and continue from sequence {}",
+        since, oldest, latest
+    )))
+}
+
+/// Changes held by the in-memory store, oldest first.
+#[derive(Debug)]
+pub struct ChangeLog {
+    entries: VecDeque<ItemChange>,
+    latest: u64,
+    retention: ChangeFeedConfig,
+}
+
+impl Default for ChangeLog {
+    fn default() -> Self {
+        Self::new(ChangeFeedConfig::default())
+    }
+}
+
+impl ChangeLog {
+    pub fn new(retention: ChangeFeedConfig) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            latest: 0,
+            retention,
+        }
+    }
+
+    pub fn set_retention(&mut self, retention: ChangeFeedConfig) {
+        self.retention = retention;
+        self.prune(Utc::now());
+    }
+
+    /// Appends a change and returns its sequence number.
+    pub fn record(&mut self, op: ChangeOp, item_id: u64, item: Option<Item>) -> u64 {
+        let now = Utc::now();
+        self.latest += 1;
+        self.entries.push_back(ItemChange {
+            seq: self.latest,
+            op,
+            item_id,
+            item,
+            changed_at: now,
+        });
+        self.prune(now);
+        self.latest
+    }
+
+    pub fn since(&self, since: u64, limit: usize) -> Result<ChangePage> {
+        let oldest = self.entries.front().map(|change| change.seq).unwrap_or(self.latest + 1);
+        ensure_retained(since, oldest, self.latest)?;
+
+        let changes = self
+            .entries
+            .iter()
+            .skip_while(|change| change.seq <= since)
+            .take(limit + 1)
+            .cloned()
+            .collect();
+        Ok(ChangePage::new(since, changes, limit))
+    }
+
+    /// Number of retained changes to any of `item_ids`.
+    pub fn count_for(&self, item_ids: &HashSet<u64>) -> usize {
+        self.entries.iter().filter(|change| item_ids.contains(&change.item_id)).count()
+    }
+
+    /// Drops every change to one of `item_ids`, returning how many there
+    /// were. Sequence numbers are not reused.
+    pub fn forget(&mut self, item_ids: &HashSet<u64>) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|change| !item_ids.contains(&change.item_id));
+        before - self.entries.len()
+    }
+
+    fn prune(&mut self, now: DateTime<Utc>) {
+        let cutoff = now - Duration::hours(self.retention.retention_hours as i64);
+        while self
+            .entries
+            .front()
+            .is_some_and(|change| self.entries.len() > self.retention.max_entries || change.changed_at < cutoff)
+        {
+            self.entries.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_change_log_pages_and_expires() {
+        let mut log = ChangeLog::new(ChangeFeedConfig {
+            retention_hours: 1,
+            max_entries: 3,
+        });
+        for id in 1..=5 {
+            log.record(ChangeOp::Deleted, id, None);
+        }
+
+        let page = log.since(2, 2).unwrap();
+        assert_eq!(page.changes.iter().map(|c| c.seq).collect::<Vec<_>>(), vec![3, 4]);
+        assert_eq!(page.next_since, 4);
+        assert!(page.has_more);
+
+        let page = log.since(page.next_since, 2).unwrap();
+        assert_eq!(page.changes.len(), 1);
+        assert!(!page.has_more);
+
+        let page = log.since(5, 10).unwrap();
+        assert!(page.changes.is_empty());
+        assert_eq!(page.next_since, 5);
+
+        assert!(matches!(log.since(1, 10), Err(AppError::Gone(_))));
+    }
+}
@@ -0,0 +1,130 @@
+//! Injectable time source for token expiry, cache TTLs, rate limit windows
+//! and job retry backoff
+
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    /// Wall-clock time, for timestamps that are stored or sent to clients.
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Monotonic time, for measuring intervals.
+    fn instant(&self) -> Instant;
+
+    /// Completes once `duration` has passed on this clock.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The real clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl SystemClock {
+    pub fn shared() -> SharedClock {
+        Arc::new(SystemClock)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+#[derive(Debug)]
+struct MockState {
+    start: DateTime<Utc>,
+    start_instant: Instant,
+    elapsed: Duration,
+}
+
+/// A clock that only moves when [`advance`](Self::advance)d. Sleeping on it
+/// advances it by the requested duration and completes immediately, so
+/// backoff delays cost no real time. Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MockState {
+                start,
+                start_instant: Instant::now(),
+                elapsed: Duration::ZERO,
+            })),
+        }
+    }
+
+    pub fn shared(&self) -> SharedClock {
+        Arc::new(self.clone())
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.state.lock().elapsed += duration;
+    }
+
+    /// Total time advanced since the clock was created.
+    pub fn elapsed(&self) -> Duration {
+        self.state.lock().elapsed
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new(Utc::now())
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        let state = self.state.lock();
+        state.start + chrono::Duration::from_std(state.elapsed).unwrap_or(chrono::Duration::MAX)
+    }
+
+    fn instant(&self) -> Instant {
+        let state = self.state.lock();
+        state.start_instant + state.elapsed
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        self.advance(duration);
+        Box::pin(tokio::task::yield_now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[tokio::test]
+    async fn test_mock_clock_moves_only_when_advanced() {
+        let start = Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+        let clock = MockClock::new(start);
+        let shared = clock.shared();
+        let before = shared.instant();
+
+        assert_eq!(shared.now(), start);
+        clock.advance(Duration::from_secs(90));
+        shared.sleep(Duration::from_secs(30)).await;
+
+        assert_eq!(shared.now(), start + chrono::Duration::minutes(2));
+        assert_eq!(shared.instant().duration_since(before), Duration::from_secs(120));
+        assert_eq!(clock.elapsed(), Duration::from_secs(120));
+    }
+}
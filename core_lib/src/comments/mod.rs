@@ -0,0 +1,84 @@
+//! Discussion attached to items: comments under `/api/items/{id}/comments`
+//!
+//! Comments live only in the database, next to the item they belong to. An
+//! item in the trash keeps its comments so that restoring it brings them
+//! back; purging it removes them for good.
+
+pub mod repository;
+pub mod service;
+
+pub use repository::CommentRepository;
+pub use service::CommentService;
+
+use crate::validation::{ContextValidatable, Sanitizable, SecurityValidator, ValidationContext, ValidationResult, Validatable, unicode};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+/// A comment on an item. `author` is the username at the time of writing;
+/// `author_id` is cleared if the account is deleted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Comment {
+    pub id: i64,
+    pub item_id: u64,
+    pub author_id: Option<i64>,
+    pub author: String,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+    pub edited_at: Option<DateTime<Utc>>,
+}
+
+/// The body of a new comment, or the replacement body of an edited one.
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct CommentRequest {
+    #[validate(length(min = 1, max = 2000, message = "Comment must be between 1 and 2000 characters"))]
+    pub body: String,
+}
+
+impl ContextValidatable for CommentRequest {
+    fn validate_with_context(&self, context: &ValidationContext) -> ValidationResult {
+        let mut result = self.validate_comprehensive();
+        if self.body.trim().is_empty() {
+            result.add_error("body", "Comment cannot be blank");
+        }
+        SecurityValidator::validate_field(&mut result, context, "comment.body", "body", &self.body, "Comment contains invalid characters");
+        result
+    }
+}
+
+impl Sanitizable for CommentRequest {
+    fn sanitize_with_context(&mut self, context: &ValidationContext) {
+        self.body = unicode::normalize_multiline(&self.body);
+        SecurityValidator::sanitize_field(context, "comment.body", &mut self.body);
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CommentListQuery {
+    pub page: Option<u32>,
+    pub page_size: Option<u32>,
+}
+
+impl CommentListQuery {
+    pub const DEFAULT_PAGE_SIZE: u32 = 50;
+    pub const MAX_PAGE_SIZE: u32 = 200;
+
+    pub fn page(&self) -> u32 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    pub fn page_size(&self) -> u32 {
+        self.page_size.unwrap_or(Self::DEFAULT_PAGE_SIZE).clamp(1, Self::MAX_PAGE_SIZE)
+    }
+}
+
+/// One page of an item's comments, oldest first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommentPage {
+    pub item_id: u64,
+    pub comments: Vec<Comment>,
+    pub page: u32,
+    pub page_size: u32,
+    /// Comments on the item across every page.
+    pub total: u64,
+}
@@ -0,0 +1,251 @@
+use chrono::{DateTime, Utc};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Row, SqlitePool};
+use std::collections::HashMap;
+
+use super::Comment;
+use crate::database::InstrumentedPool;
+use crate::error::{AppError, Result};
+
+const COMMENT_COLUMNS: &str = "id, item_id, author_id, author, body, created_at, edited_at";
+
+/// A comment to insert; the id is assigned by the database.
+#[derive(Debug, Clone)]
+pub struct NewComment {
+    pub item_id: u64,
+    pub author_id: i64,
+    pub author: String,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Stores comments. Callers check that the item exists and is visible in
+/// the current namespace; the repository itself only knows item ids.
+#[derive(Clone)]
+pub struct CommentRepository {
+    pool: InstrumentedPool,
+}
+
+impl CommentRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool: pool.into() }
+    }
+
+    pub async fn create(&self, comment: NewComment) -> Result<Comment> {
+        let result = sqlx::query(
+            "INSERT INTO item_comments (item_id, author_id, author, body, created_at) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(comment.item_id as i64)
+        .bind(comment.author_id)
+        .bind(&comment.author)
+        .bind(&comment.body)
+        .bind(comment.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        self.get(comment.item_id, result.last_insert_rowid())
+            .await?
+            .ok_or_else(|| AppError::Database("Created comment could not be read back".to_string()))
+    }
+
+    pub async fn get(&self, item_id: u64, id: i64) -> Result<Option<Comment>> {
+        let row = sqlx::query(&format!(
+            "SELECT {} FROM item_comments WHERE id = ? AND item_id = ?",
+            COMMENT_COLUMNS
+        ))
+        .bind(id)
+        .bind(item_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        row.map(|row| comment_from_row(&row)).transpose()
+    }
+
+    /// A page of the item's comments, oldest first.
+    pub async fn list(&self, item_id: u64, limit: u32, offset: u64) -> Result<Vec<Comment>> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM item_comments WHERE item_id = ? ORDER BY created_at, id LIMIT ? OFFSET ?",
+            COMMENT_COLUMNS
+        ))
+        .bind(item_id as i64)
+        .bind(limit as i64)
+        .bind(offset as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter().map(comment_from_row).collect()
+    }
+
+    pub async fn count(&self, item_id: u64) -> Result<u64> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM item_comments WHERE item_id = ?")
+            .bind(item_id as i64)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count.max(0) as u64)
+    }
+
+    /// Comment counts for each of `item_ids`, in one grouped query. Items
+    /// without comments are left out.
+    pub async fn counts(&self, item_ids: &[u64]) -> Result<HashMap<u64, u64>> {
+        if item_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let sql = format!(
+            "SELECT item_id, COUNT(*) AS count FROM item_comments WHERE item_id IN ({}) GROUP BY item_id",
+            vec!["?"; item_ids.len()].join(", ")
+        );
+        let mut query = sqlx::query(&sql);
+        for id in item_ids {
+            query = query.bind(*id as i64);
+        }
+
+        query
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(|row| {
+                Ok((
+                    row.try_get::<i64, _>("item_id")? as u64,
+                    row.try_get::<i64, _>("count")?.max(0) as u64,
+                ))
+            })
+            .collect()
+    }
+
+    /// Replaces the comment's body. Returns the comment as updated, if it
+    /// exists.
+    pub async fn update(&self, item_id: u64, id: i64, body: &str, edited_at: DateTime<Utc>) -> Result<Option<Comment>> {
+        let result = sqlx::query("UPDATE item_comments SET body = ?, edited_at = ? WHERE id = ? AND item_id = ?")
+            .bind(body)
+            .bind(edited_at)
+            .bind(id)
+            .bind(item_id as i64)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+        self.get(item_id, id).await
+    }
+
+    /// Returns whether the comment existed.
+    pub async fn delete(&self, item_id: u64, id: i64) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM item_comments WHERE id = ? AND item_id = ?")
+            .bind(id)
+            .bind(item_id as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+fn comment_from_row(row: &SqliteRow) -> Result<Comment> {
+    Ok(Comment {
+        id: row.try_get("id")?,
+        item_id: row.try_get::<i64, _>("item_id")? as u64,
+        author_id: row.try_get("author_id")?,
+        author: row.try_get("author")?,
+        body: row.try_get("body")?,
+        created_at: row.try_get("created_at")?,
+        edited_at: row.try_get("edited_at")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{get_database_pool, run_migrations, CreateItemInput, ItemRepository, Repository};
+    use tempfile::NamedTempFile;
+
+    async fn setup() -> (NamedTempFile, SqlitePool, ItemRepository) {
+        let file = NamedTempFile::new().unwrap();
+        let pool = get_database_pool(&format!("sqlite:{}", file.path().display())).await.unwrap();
+        run_migrations(pool.clone()).await.unwrap();
+        sqlx::query("INSERT INTO users (id, username, email, password_hash) VALUES (1, 'alice', 'alice@example.com', 'x')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        (file, pool.clone(), ItemRepository::new(pool))
+    }
+
+    async fn create_item(items: &ItemRepository, name: &str) -> u64 {
+        items
+            .create(CreateItemInput {
+                name: name.to_string(),
+                description: None,
+                tags: Vec::new(),
+                metadata: None,
+                created_by: None,
+            })
+            .await
+            .unwrap()
+            .id
+    }
+
+    fn new_comment(item_id: u64, body: &str) -> NewComment {
+        NewComment {
+            item_id,
+            author_id: 1,
+            author: "alice".to_string(),
+            body: body.to_string(),
+            created_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_comment_crud_and_grouped_counts() {
+        let (_file, pool, items) = setup().await;
+        let repository = CommentRepository::new(pool);
+        let first = create_item(&items, "first").await;
+        let second = create_item(&items, "second").await;
+        let quiet = create_item(&items, "quiet").await;
+
+        let comment = repository.create(new_comment(first, "one")).await.unwrap();
+        repository.create(new_comment(first, "two")).await.unwrap();
+        repository.create(new_comment(second, "three")).await.unwrap();
+        assert_eq!(comment.item_id, first);
+        assert_eq!(comment.edited_at, None);
+
+        let page = repository.list(first, 1, 1).await.unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].body, "two");
+        assert_eq!(repository.count(first).await.unwrap(), 2);
+
+        let counts = repository.counts(&[first, second, quiet]).await.unwrap();
+        assert_eq!(counts.get(&first), Some(&2));
+        assert_eq!(counts.get(&second), Some(&1));
+        assert_eq!(counts.get(&quiet), None);
+
+        // Ids are only found under the item they belong to.
+        assert!(repository.get(second, comment.id).await.unwrap().is_none());
+        assert!(repository.update(second, comment.id, "moved", Utc::now()).await.unwrap().is_none());
+
+        let edited = repository.update(first, comment.id, "one, edited", Utc::now()).await.unwrap().unwrap();
+        assert_eq!(edited.body, "one, edited");
+        assert!(edited.edited_at.is_some());
+
+        assert!(!repository.delete(second, comment.id).await.unwrap());
+        assert!(repository.delete(first, comment.id).await.unwrap());
+        assert_eq!(repository.count(first).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_purging_an_item_removes_its_comments() {
+        let (_file, pool, items) = setup().await;
+        let repository = CommentRepository::new(pool);
+        let doomed = create_item(&items, "doomed").await;
+        let kept = create_item(&items, "kept").await;
+        repository.create(new_comment(doomed, "bye")).await.unwrap();
+        repository.create(new_comment(kept, "hi")).await.unwrap();
+
+        items.delete(doomed as i64).await.unwrap();
+        assert_eq!(repository.count(doomed).await.unwrap(), 1, "the trash keeps comments");
+
+        let report = items
+            .purge_deleted(Utc::now() + chrono::Duration::seconds(1), 10, false)
+            .await
+            .unwrap();
+        assert_eq!(report.comments_removed, 1);
+        assert_eq!(repository.count(doomed).await.unwrap(), 0);
+        assert_eq!(repository.count(kept).await.unwrap(), 1);
+    }
+}
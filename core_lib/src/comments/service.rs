@@ -0,0 +1,97 @@
+use super::repository::{CommentRepository, NewComment};
+use super::{Comment, CommentListQuery, CommentPage};
+use crate::clock::SharedClock;
+use crate::error::{AppError, Result};
+use crate::middleware::auth::AuthUser;
+use crate::services::ItemService;
+use std::collections::HashMap;
+
+/// Comments on the items the caller can see. Every operation first looks
+/// the item up through the item service, so items in the trash or in
+/// another namespace have no comments as far as callers are concerned.
+#[derive(Clone)]
+pub struct CommentService {
+    repository: CommentRepository,
+    items: ItemService,
+    clock: SharedClock,
+}
+
+impl CommentService {
+    pub fn new(repository: CommentRepository, items: ItemService, clock: SharedClock) -> Self {
+        Self { repository, items, clock }
+    }
+
+    /// Adds a comment by `author`. The body is expected to have been
+    /// validated already.
+    pub async fn add(&self, item_id: u64, author: &AuthUser, body: String) -> Result<Comment> {
+        self.items.get_item(item_id).await?;
+        self.repository
+            .create(NewComment {
+                item_id,
+                author_id: author.user_id,
+                author: author.username.clone(),
+                body,
+                created_at: self.clock.now(),
+            })
+            .await
+    }
+
+    pub async fn list(&self, item_id: u64, query: &CommentListQuery) -> Result<CommentPage> {
+        self.items.get_item(item_id).await?;
+
+        let page = query.page();
+        let page_size = query.page_size();
+        let offset = (page as u64 - 1) * page_size as u64;
+        let comments = self.repository.list(item_id, page_size, offset).await?;
+        let total = self.repository.count(item_id).await?;
+
+        Ok(CommentPage { item_id, comments, page, page_size, total })
+    }
+
+    /// Replaces the body of a comment. Only its author or an admin may.
+    pub async fn edit(&self, item_id: u64, id: i64, user: &AuthUser, body: String) -> Result<Comment> {
+        self.editable(item_id, id, user).await?;
+        self.repository
+            .update(item_id, id, &body, self.clock.now())
+            .await?
+            .ok_or_else(|| Self::not_found(item_id, id))
+    }
+
+    /// Deletes a comment. Only its author or an admin may.
+    pub async fn delete(&self, item_id: u64, id: i64, user: &AuthUser) -> Result<Comment> {
+        let comment = self.editable(item_id, id, user).await?;
+        if !self.repository.delete(item_id, id).await? {
+            return Err(Self::not_found(item_id, id));
+        }
+        Ok(comment)
+    }
+
+    /// Comment counts for each of `item_ids`, zero for items without any.
+    pub async fn counts(&self, item_ids: &[u64]) -> Result<HashMap<u64, u64>> {
+        let mut counts = self.repository.counts(item_ids).await?;
+        for id in item_ids {
+            counts.entry(*id).or_insert(0);
+        }
+        Ok(counts)
+    }
+
+    async fn editable(&self, item_id: u64, id: i64, user: &AuthUser) -> Result<Comment> {
+        self.items.get_item(item_id).await?;
+        let comment = self
+            .repository
+            .get(item_id, id)
+            .await?
+            .ok_or_else(|| Self::not_found(item_id, id))?;
+
+        if !user.is_admin() && comment.author_id != Some(user.user_id) {
+            return Err(AppError::Authorization(
+                "Only the author or an admin can change a comment".to_string(),
+            ));
+        }
+        Ok(comment)
+    }
+
+    fn not_found(item_id: u64, id: i64) -> AppError {
+        AppError::NotFound(format!("Comment {} not found on item {}", id, item_id))
+    }
+}
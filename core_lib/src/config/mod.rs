@@ -1,3 +1,5 @@
+pub mod reload;
 pub mod settings;
 
-pub use settings::*;
\ No newline at end of file
+pub use reload::spawn_config_watcher;
+pub use settings::*;
@@ -0,0 +1,95 @@
+//! Polling watcher that reloads the configuration file when it changes
+
+use super::AppConfig;
+use crate::supervisor::Supervisor;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Checks `path` every `interval` and calls `on_reload` with the freshly
+/// loaded configuration whenever the file's modification time changes. A
+/// file that fails to parse or validate is logged and skipped, leaving the
+/// running configuration in place. The watcher is supervised as
+/// `config_watcher`.
+pub fn spawn_config_watcher<F>(
+    supervisor: &Supervisor,
+    path: impl Into<PathBuf>,
+    interval: Duration,
+    on_reload: F,
+) -> JoinHandle<()>
+where
+    F: Fn(AppConfig) + Send + Sync + 'static,
+{
+    let path = path.into();
+    let on_reload = Arc::new(on_reload);
+
+    supervisor.spawn("config_watcher", move || {
+        let path = path.clone();
+        let on_reload = on_reload.clone();
+        async move {
+            let mut last_modified = modified_at(&path);
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+
+                let modified = modified_at(&path);
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+
+                match AppConfig::load_from(&path) {
+                    Ok(config) => {
+                        tracing::info!("Configuration file {} changed; applying reloadable settings", path.display());
+                        on_reload(config);
+                    }
+                    Err(e) => {
+                        tracing::warn!("Ignoring invalid configuration in {}: {}", path.display(), e);
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_watcher_reloads_changed_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "[rate_limit]\nrequests_per_minute = 10\n").unwrap();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let handle = spawn_config_watcher(&Supervisor::new(), path.clone(), Duration::from_millis(20), move |config| {
+            sink.lock().unwrap().push(config.rate_limit.requests_per_minute);
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(seen.lock().unwrap().is_empty());
+
+        std::fs::write(&path, "[rate_limit]\nrequests_per_minute = 0\n").unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(5)).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(seen.lock().unwrap().is_empty(), "invalid config must not be applied");
+
+        std::fs::write(&path, "[rate_limit]\nrequests_per_minute = 25\n").unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(*seen.lock().unwrap(), vec![25]);
+
+        handle.abort();
+    }
+}
@@ -1,5 +1,6 @@
 use config::{Config, ConfigError, Environment, File};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,8 +15,38 @@ pub struct AppConfig {
     pub cors: CorsConfig,
     pub rate_limit: RateLimitConfig,
     pub logging: LoggingConfig,
+    pub validation: ValidationConfig,
+    pub security: SecurityConfig,
+    pub timeouts: TimeoutConfig,
+    pub notifications: NotificationConfig,
+    pub metrics: MetricsConfig,
+    pub health: HealthConfig,
+    pub load_shedding: LoadSheddingConfig,
+    pub concurrency: ConcurrencyConfig,
+    pub webhooks: WebhookConfig,
+    pub outbound: OutboundConfig,
+    pub graphql: GraphqlConfig,
+    pub batch: BatchConfig,
+    pub snapshots: SnapshotConfig,
+    pub changes: ChangeFeedConfig,
+    pub items: ItemConfig,
+    /// Empty by default, which the config builder drops, hence the default.
+    #[serde(default)]
+    pub item_schema: ItemSchemaConfig,
+    pub item_secrets: ItemSecretsConfig,
+    pub trash: TrashConfig,
+    pub integrity: IntegrityConfig,
+    pub duplicates: DuplicateConfig,
+    pub suggest: SuggestConfig,
+    pub search_export: SearchExportConfig,
+    pub search: SearchConfig,
+    pub pagination: PaginationConfig,
+    pub capture: CaptureConfig,
+    pub features: FeaturesConfig,
 }
 
+pub const CONFIG_FILE: &str = "config.toml";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub host: String,
@@ -23,6 +54,89 @@ pub struct ServerConfig {
     pub max_connections: usize,
     pub request_timeout_seconds: u64,
     pub shutdown_timeout_seconds: u64,
+    /// How often config.toml is checked for changes that can be applied
+    /// without a restart. Zero disables reloading.
+    #[serde(default = "default_config_reload_interval_seconds")]
+    pub config_reload_interval_seconds: u64,
+    /// Accept HTTP/2 as well as HTTP/1.1: negotiated through ALPN when TLS
+    /// is enabled, and by prior knowledge (h2c) on plain connections.
+    #[serde(default = "default_http2")]
+    pub http2: bool,
+    /// Streams a single HTTP/2 connection may have open at once.
+    #[serde(default = "default_http2_max_concurrent_streams")]
+    pub http2_max_concurrent_streams: u32,
+    /// HTTP/1.1 connections waiting longer than this for the next request
+    /// are closed, and idle HTTP/2 connections are pinged at this interval
+    /// and closed if the ping goes unanswered. Zero disables keep-alive.
+    #[serde(default = "default_keep_alive_timeout_seconds")]
+    pub keep_alive_timeout_seconds: u64,
+    /// Largest request head accepted. At least 8192.
+    #[serde(default = "default_max_header_size_bytes")]
+    pub max_header_size_bytes: usize,
+    /// Further addresses serving the same routes, each `ip:port` or
+    /// `unix:/path/to.sock`.
+    #[serde(default)]
+    pub additional_listeners: Vec<String>,
+    /// Serve TCP listeners over TLS. Unix sockets are always plain.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// Port on `host` serving the gRPC item API, in servers built with the
+    /// `grpc` feature. Unset disables it.
+    #[serde(default)]
+    pub grpc_port: Option<u16>,
+    /// Names this server in the `X-Served-By` header, to tell instances
+    /// behind a load balancer apart. Defaults to the hostname.
+    #[serde(default = "default_instance_id")]
+    pub instance_id: String,
+    /// Stop at startup when a configured subsystem fails, rather than
+    /// falling back (to the in-memory store for the database). Unset means
+    /// on when `RUST_ENV` is `production`.
+    #[serde(default)]
+    pub strict_startup: Option<bool>,
+}
+
+impl ServerConfig {
+    pub fn strict_startup(&self) -> bool {
+        self.strict_startup
+            .unwrap_or_else(|| crate::startup::strict_by_default(std::env::var("RUST_ENV").ok().as_deref()))
+    }
+}
+
+fn default_instance_id() -> String {
+    sysinfo::System::host_name()
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+fn default_config_reload_interval_seconds() -> u64 {
+    10
+}
+
+fn default_http2() -> bool {
+    true
+}
+
+fn default_http2_max_concurrent_streams() -> u32 {
+    100
+}
+
+fn default_keep_alive_timeout_seconds() -> u64 {
+    60
+}
+
+fn default_max_header_size_bytes() -> usize {
+    16 * 1024
+}
+
+/// Smallest `max_header_size_bytes` the HTTP/1 parser supports.
+pub const MIN_HEADER_SIZE_BYTES: usize = 8192;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first.
+    pub cert_path: PathBuf,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1).
+    pub key_path: PathBuf,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,6 +146,14 @@ pub struct DatabaseConfig {
     pub min_connections: u32,
     pub connection_timeout_seconds: u64,
     pub migrate_on_start: bool,
+    /// Queries taking at least this long are logged with their sanitized
+    /// SQL. Zero disables the slow query log.
+    #[serde(default = "default_slow_query_threshold_ms")]
+    pub slow_query_threshold_ms: u64,
+}
+
+fn default_slow_query_threshold_ms() -> u64 {
+    200
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -42,6 +164,106 @@ pub struct AuthConfig {
     pub password_min_length: usize,
     pub max_login_attempts: u32,
     pub lockout_duration_minutes: u64,
+    /// Argon2id memory cost in KiB. Stored hashes made with other parameters
+    /// are upgraded on the user's next successful login.
+    #[serde(default = "default_password_hash_memory_kib")]
+    pub password_hash_memory_kib: u32,
+    #[serde(default = "default_password_hash_iterations")]
+    pub password_hash_iterations: u32,
+    #[serde(default = "default_password_hash_parallelism")]
+    pub password_hash_parallelism: u32,
+    /// What users who haven't verified their email address may do.
+    /// Registrations are only left unverified when notifications are
+    /// enabled, since the token is sent by email.
+    #[serde(default)]
+    pub unverified_users: UnverifiedUserPolicy,
+    #[serde(default = "default_verification_token_hours")]
+    pub verification_token_hours: u64,
+    /// Shortest wait between two verification emails to one address.
+    #[serde(default = "default_verification_resend_seconds")]
+    pub verification_resend_seconds: u64,
+    /// Services allowed to call `POST /auth/introspect`, each identified by
+    /// the key it sends in the `X-API-Key` header.
+    #[serde(default)]
+    pub introspection_clients: Vec<ServiceCredential>,
+    /// Introspections allowed per minute for each client.
+    #[serde(default = "default_introspect_requests_per_minute")]
+    pub introspect_requests_per_minute: usize,
+    /// `GET /auth/validate` checks allowed per minute from each address.
+    #[serde(default = "default_validate_requests_per_minute")]
+    pub validate_requests_per_minute: usize,
+    /// Sign-in attempts allowed per minute from each address, on top of the
+    /// global rate limit.
+    #[serde(default = "default_login_requests_per_minute")]
+    pub login_requests_per_minute: usize,
+    /// Token refreshes allowed per minute from each address, on top of the
+    /// global rate limit.
+    #[serde(default = "default_refresh_requests_per_minute")]
+    pub refresh_requests_per_minute: usize,
+}
+
+/// A sibling service and the key it authenticates with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceCredential {
+    pub name: String,
+    pub key: String,
+}
+
+/// What a user whose email address isn't verified yet may do.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnverifiedUserPolicy {
+    /// Everything a verified user may.
+    Allow,
+    /// Sign in and read, but not create, change or delete items.
+    #[default]
+    BlockItemWrites,
+    /// Nothing; signing in is refused until the address is verified.
+    BlockLogin,
+}
+
+fn default_verification_token_hours() -> u64 {
+    24
+}
+
+fn default_verification_resend_seconds() -> u64 {
+    60
+}
+
+fn default_introspect_requests_per_minute() -> usize {
+    600
+}
+
+fn default_validate_requests_per_minute() -> usize {
+    3000
+}
+
+fn default_login_requests_per_minute() -> usize {
+    10
+}
+
+fn default_refresh_requests_per_minute() -> usize {
+    30
+}
+
+/// Smallest Argon2id settings accepted at all; anything lower is rejected by
+/// validation rather than merely warned about.
+const MIN_PASSWORD_HASH_MEMORY_KIB: u32 = 4096;
+
+/// OWASP's Argon2id recommendations trade memory for iterations; all of them
+/// cost at least this many KiB-passes.
+const OWASP_PASSWORD_HASH_COST: u64 = 7168 * 5;
+
+fn default_password_hash_memory_kib() -> u32 {
+    19456
+}
+
+fn default_password_hash_iterations() -> u32 {
+    2
+}
+
+fn default_password_hash_parallelism() -> u32 {
+    1
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -50,65 +272,1562 @@ pub struct FileConfig {
     pub max_file_size_mb: u64,
     pub allowed_extensions: Vec<String>,
     pub temp_dir: PathBuf,
+    /// Free space on the upload volume below which the files health check
+    /// reports degraded.
+    #[serde(default = "default_min_free_space_mb")]
+    pub min_free_space_mb: u64,
+    #[serde(default)]
+    pub cache_control: FileCacheControlConfig,
+    #[serde(default)]
+    pub reconcile: FileReconcileConfig,
+}
+
+fn default_min_free_space_mb() -> u64 {
+    512
+}
+
+/// `Cache-Control` sent when serving or downloading a file, by the class of
+/// its content type.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileCacheControlConfig {
+    /// `image/*`. Stored files never change, so these can be cached long.
+    pub images: String,
+    /// `text/*` and `application/*`, such as PDFs and office documents.
+    pub documents: String,
+    /// Everything else.
+    pub other: String,
+}
+
+impl Default for FileCacheControlConfig {
+    fn default() -> Self {
+        Self {
+            images: "public, max-age=31536000, immutable".to_string(),
+            documents: "private, no-store".to_string(),
+            other: "public, max-age=3600".to_string(),
+        }
+    }
+}
+
+impl FileCacheControlConfig {
+    pub fn for_content_type(&self, content_type: &str) -> &str {
+        let content_type = content_type.trim().to_ascii_lowercase();
+        if content_type.starts_with("image/") {
+            &self.images
+        } else if content_type.starts_with("text/") || content_type.starts_with("application/") {
+            &self.documents
+        } else {
+            &self.other
+        }
+    }
+}
+
+/// Reconciliation of the upload directory with the `files` table, run as a
+/// `FileReconciliation` job every `interval_minutes` (0 disables it).
+/// Stored files no row points at are only deleted once older than
+/// `orphan_min_age_hours`, so uploads still being recorded are left alone.
+/// Both sides are scanned `batch_size` entries at a time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileReconcileConfig {
+    pub interval_minutes: u64,
+    pub orphan_min_age_hours: u64,
+    pub batch_size: usize,
+}
+
+impl Default for FileReconcileConfig {
+    fn default() -> Self {
+        Self {
+            interval_minutes: 1440,
+            orphan_min_age_hours: 24,
+            batch_size: 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheConfig {
+    pub max_size: usize,
+    pub default_ttl_seconds: u64,
+    pub cleanup_interval_seconds: u64,
+    pub enable_stats: bool,
+    /// Let identical concurrent GETs share one handler run and its response.
+    #[serde(default = "default_coalesce_requests")]
+    pub coalesce_requests: bool,
+    /// How long a coalesced request waits for the one it joined before
+    /// running itself.
+    #[serde(default = "default_coalesce_timeout_seconds")]
+    pub coalesce_timeout_seconds: u64,
+    /// Least time between two clears of the whole cache through
+    /// `POST /api/cache/clear`, unless the request passes `force=true`.
+    #[serde(default = "default_clear_cooldown_seconds")]
+    pub clear_cooldown_seconds: u64,
+}
+
+fn default_coalesce_requests() -> bool {
+    true
+}
+
+fn default_coalesce_timeout_seconds() -> u64 {
+    10
+}
+
+fn default_clear_cooldown_seconds() -> u64 {
+    60
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobConfig {
+    /// Run the background job queue. Notifications are delivered as jobs,
+    /// so they are not sent while it is disabled.
+    pub enabled: bool,
+    pub max_workers: usize,
+    pub queue_size: usize,
+    pub job_timeout_seconds: u64,
+    pub retry_attempts: u32,
+    pub retry_delay_seconds: u64,
+    /// Largest job result kept in the jobs table; larger ones are written
+    /// to file storage and the job keeps a reference.
+    #[serde(default = "default_max_inline_result_bytes")]
+    pub max_inline_result_bytes: usize,
+}
+
+fn default_max_inline_result_bytes() -> usize {
+    64 * 1024
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebSocketConfig {
+    pub max_connections: usize,
+    pub ping_interval_seconds: u64,
+    pub pong_timeout_seconds: u64,
+    pub message_buffer_size: usize,
+    pub max_messages_per_second: u32,
+    pub max_rate_limit_violations: u32,
+    pub outbound_queue_size: usize,
+    pub slow_consumer_policy: SlowConsumerPolicy,
+    /// Accept the deprecated `?token=` query parameter. Tokens in URLs end up
+    /// in access logs; prefer the `bearer` subprotocol or an `Authenticate`
+    /// message.
+    pub allow_query_token: bool,
+    /// Close connections that have not authenticated within the grace period.
+    pub require_authentication: bool,
+    /// Seconds after connecting during which an `Authenticate` message is
+    /// accepted.
+    pub auth_grace_period_seconds: u64,
+    /// Item events raised within this many milliseconds of one another are
+    /// merged into a single summary event. 0 delivers every event.
+    #[serde(default = "default_coalesce_window_ms")]
+    pub coalesce_window_ms: u64,
+    /// Most item ids listed in a summary event.
+    #[serde(default = "default_coalesce_max_ids")]
+    pub coalesce_max_ids: usize,
+    /// Item event types, such as `ItemDeleted`, that are always delivered
+    /// one by one. Job events are never merged.
+    #[serde(default)]
+    pub coalesce_exempt_events: Vec<String>,
+    /// Least time between two `UploadProgress` events for one upload.
+    #[serde(default = "default_upload_progress_interval_ms")]
+    pub upload_progress_interval_ms: u64,
+    /// Recent events kept per topic for clients resuming after a reconnect.
+    /// 0 keeps none, so every resume asks the client to resync.
+    #[serde(default = "default_replay_buffer_size")]
+    pub replay_buffer_size: usize,
+    /// Seconds a kept event stays available for resuming.
+    #[serde(default = "default_replay_max_age_seconds")]
+    pub replay_max_age_seconds: u64,
+}
+
+/// Item event types that coalescing can merge.
+pub const COALESCABLE_EVENTS: &[&str] = &["ItemCreated", "ItemUpdated", "ItemDeleted"];
+
+fn default_coalesce_window_ms() -> u64 {
+    250
+}
+
+fn default_coalesce_max_ids() -> usize {
+    100
+}
+
+fn default_upload_progress_interval_ms() -> u64 {
+    250
+}
+
+fn default_replay_buffer_size() -> usize {
+    1000
+}
+
+fn default_replay_max_age_seconds() -> u64 {
+    300
+}
+
+/// What to do when a WebSocket client's outbound queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlowConsumerPolicy {
+    /// Discard the oldest queued event to make room for the new one.
+    DropOldest,
+    /// Close the connection; the client is expected to reconnect and resync.
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub exposed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    pub max_age_seconds: u64,
+    pub enable_permissive_mode: bool,
+    /// Rules for specific path prefixes; the longest matching prefix wins and
+    /// unset fields fall back to the global values above.
+    #[serde(default)]
+    pub routes: Vec<CorsRouteConfig>,
+}
+
+/// CORS policy for one path prefix. Origins accept the same patterns as the
+/// global list: an exact origin, `*`, a `*.` subdomain wildcard such as
+/// `https://*.partner.example`, or `regex:` followed by a regular expression.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorsRouteConfig {
+    pub path: String,
+    pub allowed_origins: Vec<String>,
+    #[serde(default)]
+    pub allowed_methods: Option<Vec<String>>,
+    #[serde(default)]
+    pub allowed_headers: Option<Vec<String>>,
+    #[serde(default)]
+    pub exposed_headers: Option<Vec<String>>,
+    #[serde(default)]
+    pub allow_credentials: Option<bool>,
+    #[serde(default)]
+    pub max_age_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    pub enable: bool,
+    pub requests_per_minute: usize,
+    pub burst_size: usize,
+    pub enable_user_based_limits: bool,
+    pub user_requests_per_minute: usize,
+    pub admin_requests_per_minute: usize,
+    pub cleanup_interval_seconds: u64,
+    #[serde(default)]
+    pub backend: RateLimitBackend,
+    /// Connection string for the shared backend. Falls back to the main
+    /// database URL when unset.
+    #[serde(default)]
+    pub shared_store_url: Option<String>,
+    /// Longest a request waits on the shared backend before falling back to
+    /// local limiting.
+    #[serde(default = "default_shared_store_timeout_ms")]
+    pub shared_store_timeout_ms: u64,
+    /// Client networks (CIDR notation) that are never rate limited.
+    #[serde(default)]
+    pub exempt_cidrs: Vec<String>,
+    /// Path prefixes that are never rate limited.
+    #[serde(default)]
+    pub exempt_paths: Vec<String>,
+    #[serde(default)]
+    pub tiers: Vec<RateLimitTier>,
+    #[serde(default)]
+    pub api_keys: Vec<ApiKeyTier>,
+}
+
+/// A named rate with the user roles it applies to. A tier named `default`,
+/// `user` or `admin` replaces the built-in rate of that name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitTier {
+    pub name: String,
+    pub requests_per_minute: usize,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+/// An API key accepted in the `X-API-Key` header and the tier it is billed to.
+/// Each key gets its own bucket, identified by `name` in logs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyTier {
+    pub name: String,
+    pub key: String,
+    pub tier: String,
+}
+
+/// Where rate limit buckets are stored. `memory` keeps them per process;
+/// `sqlite` shares them between processes using the same database file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitBackend {
+    #[default]
+    Memory,
+    Sqlite,
+}
+
+fn default_shared_store_timeout_ms() -> u64 {
+    100
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    pub level: String,
+    pub format: String,
+    pub include_request_id: bool,
+    pub include_user_info: bool,
+    pub include_timing: bool,
+    pub log_request_body: bool,
+    pub log_response_body: bool,
+    pub max_body_size: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldPolicy {
+    Strict,
+    Sanitize,
+    Off,
+}
+
+/// What list, search and export endpoints do with query parameters they
+/// don't accept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownParamsPolicy {
+    /// Log them and name them in `X-Unknown-Params`.
+    #[default]
+    Warn,
+    /// Answer 400, suggesting the closest accepted names.
+    Reject,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidationConfig {
+    pub default_policy: FieldPolicy,
+    #[serde(default)]
+    pub field_policies: HashMap<String, FieldPolicy>,
+    #[serde(default)]
+    pub unknown_query_params: UnknownParamsPolicy,
+    /// Most headers a request may carry before it is refused with 431.
+    #[serde(default = "default_max_header_count")]
+    pub max_header_count: usize,
+    /// Most bytes of header names and values a request may carry before it
+    /// is refused with 431.
+    #[serde(default = "default_max_header_bytes")]
+    pub max_header_bytes: usize,
+}
+
+fn default_max_header_count() -> usize {
+    100
+}
+
+fn default_max_header_bytes() -> usize {
+    16 * 1024
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityConfig {
+    pub enable_anomaly_blocking: bool,
+    pub anomaly_threshold: u32,
+    pub anomaly_window_seconds: u64,
+    pub block_duration_seconds: u64,
+    pub max_user_agent_length: usize,
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+}
+
+/// Per-route handler budgets and slow-request reporting. Routes without an
+/// override use `server.request_timeout_seconds`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeoutConfig {
+    pub slow_request_threshold_ms: u64,
+    /// Longest a streaming response may go without sending data.
+    pub stream_idle_timeout_seconds: u64,
+    #[serde(default)]
+    pub routes: Vec<RouteTimeout>,
+}
+
+/// Timeout for every path under `path`; the longest matching prefix wins.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteTimeout {
+    pub path: String,
+    pub timeout_seconds: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsBackend {
+    /// Keeps metrics in the process for /api/metrics and the dashboard.
+    Memory,
+    /// Sends every measurement to a statsd server over UDP.
+    Statsd,
+}
+
+/// Where metrics go, and the response time history kept by the in-memory
+/// backend. Both history limits are fixed, so memory use does not depend
+/// on request rate.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    /// Backends that receive every measurement. Without `memory`,
+    /// /api/metrics and the dashboard have nothing to report.
+    #[serde(default = "default_metrics_backends")]
+    pub backends: Vec<MetricsBackend>,
+    /// `host:port` of the statsd server.
+    #[serde(default = "default_statsd_address")]
+    pub statsd_address: String,
+    /// Prepended, with a dot, to every statsd metric name.
+    #[serde(default = "default_statsd_prefix")]
+    pub statsd_prefix: String,
+    /// Most recent individual response times kept.
+    pub response_time_capacity: usize,
+    /// Minutes of per-minute aggregates kept for time-window queries.
+    pub history_window_minutes: u64,
+    /// How often hourly request totals are written to the database so the
+    /// heatmap survives restarts. Zero keeps them in memory only.
+    pub traffic_persist_interval_seconds: u64,
+}
+
+fn default_metrics_backends() -> Vec<MetricsBackend> {
+    vec![MetricsBackend::Memory]
+}
+
+fn default_statsd_address() -> String {
+    "127.0.0.1:8125".to_string()
+}
+
+fn default_statsd_prefix() -> String {
+    "rust_http_server".to_string()
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            backends: default_metrics_backends(),
+            statsd_address: default_statsd_address(),
+            statsd_prefix: default_statsd_prefix(),
+            response_time_capacity: 1000,
+            history_window_minutes: 60,
+            traffic_persist_interval_seconds: 60,
+        }
+    }
+}
+
+impl MetricsConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.response_time_capacity == 0 {
+            return Err(ConfigError::Message(
+                "Metrics response time capacity must be greater than 0".to_string(),
+            ));
+        }
+
+        if !(1..=24 * 60).contains(&self.history_window_minutes) {
+            return Err(ConfigError::Message(
+                "Metrics history window must be between 1 and 1440 minutes".to_string(),
+            ));
+        }
+
+        if self.backends.is_empty() {
+            return Err(ConfigError::Message(
+                "At least one metrics backend must be configured".to_string(),
+            ));
+        }
+
+        if self.backends.contains(&MetricsBackend::Statsd) && self.statsd_address.trim().is_empty() {
+            return Err(ConfigError::Message(
+                "Metrics statsd_address is required when the statsd backend is enabled".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Background health checking, failure debouncing and the transition log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthConfig {
+    /// How often every health check runs in the background. Zero runs checks
+    /// only when /health is requested.
+    pub check_interval_seconds: u64,
+    /// Consecutive failed checks before a component is reported unhealthy;
+    /// earlier failures report it as degraded.
+    pub failure_threshold: u32,
+    /// Per-component overrides of `failure_threshold`, keyed by check name.
+    #[serde(default)]
+    pub component_failure_thresholds: HashMap<String, u32>,
+    /// Transitions kept in memory for /api/health/history.
+    pub history_capacity: usize,
+    /// Also store transitions in the database so they survive restarts.
+    pub persist_history: bool,
+    /// How long a single check may run before its component is reported
+    /// unhealthy with a timeout.
+    #[serde(default = "default_health_check_timeout_ms")]
+    pub check_timeout_ms: u64,
+    /// Per-component overrides of `check_timeout_ms`, keyed by check name.
+    #[serde(default)]
+    pub component_check_timeouts_ms: HashMap<String, u64>,
+    /// Bound on a whole run of the checks, which run concurrently, so that
+    /// /health answers within it whatever the components do.
+    #[serde(default = "default_health_overall_timeout_ms")]
+    pub overall_timeout_ms: u64,
+    /// How long /health answers with the last full result instead of
+    /// running the checks again. Zero runs them for every request.
+    #[serde(default = "default_health_cache_ttl_ms")]
+    pub cache_ttl_ms: u64,
+}
+
+fn default_health_check_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_health_overall_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_health_cache_ttl_ms() -> u64 {
+    1000
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            check_interval_seconds: 30,
+            failure_threshold: 3,
+            component_failure_thresholds: HashMap::new(),
+            history_capacity: 500,
+            persist_history: true,
+            check_timeout_ms: default_health_check_timeout_ms(),
+            component_check_timeouts_ms: HashMap::new(),
+            overall_timeout_ms: default_health_overall_timeout_ms(),
+            cache_ttl_ms: default_health_cache_ttl_ms(),
+        }
+    }
+}
+
+impl HealthConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.failure_threshold == 0 {
+            return Err(ConfigError::Message(
+                "Health failure threshold must be at least 1".to_string(),
+            ));
+        }
+
+        if let Some(component) = self
+            .component_failure_thresholds
+            .iter()
+            .find_map(|(component, threshold)| (*threshold == 0).then_some(component))
+        {
+            return Err(ConfigError::Message(format!(
+                "Health failure threshold for '{}' must be at least 1",
+                component
+            )));
+        }
+
+        if self.history_capacity == 0 {
+            return Err(ConfigError::Message(
+                "Health history capacity must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.check_timeout_ms == 0 || self.overall_timeout_ms == 0 {
+            return Err(ConfigError::Message(
+                "Health check timeouts must be greater than 0".to_string(),
+            ));
+        }
+
+        if let Some(component) = self
+            .component_check_timeouts_ms
+            .iter()
+            .find_map(|(component, timeout)| (*timeout == 0).then_some(component))
+        {
+            return Err(ConfigError::Message(format!(
+                "Health check timeout for '{}' must be greater than 0",
+                component
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Thresholds beyond which requests are rejected with 503 before reaching a
+/// handler. Reads are shed at the lower thresholds and everything except
+/// health checks and admin endpoints at the higher ones.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadSheddingConfig {
+    pub enable: bool,
+    /// Requests in flight beyond which API reads are shed.
+    pub read_in_flight_limit: u64,
+    /// Requests in flight beyond which writes are shed as well.
+    pub write_in_flight_limit: u64,
+    /// p99 latency of recent responses beyond which API reads are shed.
+    pub read_p99_latency_ms: u64,
+    /// p99 latency of recent responses beyond which writes are shed as well.
+    pub write_p99_latency_ms: u64,
+    /// How far back response times count towards the p99 latency.
+    pub latency_window_seconds: u64,
+    pub retry_after_seconds: u64,
+}
+
+impl Default for LoadSheddingConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            read_in_flight_limit: 256,
+            write_in_flight_limit: 512,
+            read_p99_latency_ms: 2000,
+            write_p99_latency_ms: 5000,
+            latency_window_seconds: 10,
+            retry_after_seconds: 5,
+        }
+    }
+}
+
+impl LoadSheddingConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.read_in_flight_limit == 0 || self.read_p99_latency_ms == 0 {
+            return Err(ConfigError::Message(
+                "Load shedding thresholds must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.write_in_flight_limit < self.read_in_flight_limit
+            || self.write_p99_latency_ms < self.read_p99_latency_ms
+        {
+            return Err(ConfigError::Message(
+                "Load shedding write thresholds must not be below the read thresholds".to_string(),
+            ));
+        }
+
+        if self.latency_window_seconds == 0 {
+            return Err(ConfigError::Message(
+                "Load shedding latency window must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Per-client concurrency limits. `server.max_connections` caps requests
+/// in flight across all clients; beyond it requests are rejected with 503.
+/// Each client address may have `per_client_limit` requests in flight and
+/// `per_client_queue` more waiting, in arrival order, for up to
+/// `queue_timeout_ms`; anything beyond the queue is rejected with 429.
+/// WebSocket and server-sent event connections count against the client's
+/// limit but never wait in its queue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConcurrencyConfig {
+    pub enable: bool,
+    pub per_client_limit: usize,
+    pub per_client_queue: usize,
+    pub queue_timeout_ms: u64,
+    pub retry_after_seconds: u64,
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            per_client_limit: 32,
+            per_client_queue: 16,
+            queue_timeout_ms: 2000,
+            retry_after_seconds: 1,
+        }
+    }
+}
+
+impl ConcurrencyConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.per_client_limit == 0 {
+            return Err(ConfigError::Message(
+                "Concurrency per-client limit must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Outbound webhooks for item events. Deliveries run as background jobs, so
+/// they need the job queue, and `jobs.retry_delay_seconds` is the base of
+/// the backoff between attempts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub enabled: bool,
+    /// Attempts per event, including the first.
+    pub max_attempts: u32,
+    /// Failed attempts in a row after which a subscription is disabled.
+    pub max_consecutive_failures: u32,
+    pub timeout_seconds: u64,
+    /// Networks that webhooks may target although they are private,
+    /// loopback or link-local, e.g. `10.0.0.0/8` for internal consumers.
+    /// Added to `outbound.allowed_private_cidrs`.
+    #[serde(default)]
+    pub allowed_private_cidrs: Vec<String>,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_attempts: 5,
+            max_consecutive_failures: 10,
+            timeout_seconds: 10,
+            allowed_private_cidrs: Vec::new(),
+        }
+    }
+}
+
+impl WebhookConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_attempts == 0 || self.max_consecutive_failures == 0 {
+            return Err(ConfigError::Message(
+                "Webhook max attempts and max consecutive failures must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.timeout_seconds == 0 {
+            return Err(ConfigError::Message(
+                "Webhook timeout must be greater than 0".to_string(),
+            ));
+        }
+
+        for cidr in &self.allowed_private_cidrs {
+            cidr.parse::<crate::net::IpCidr>().map_err(|e| {
+                ConfigError::Message(format!("Invalid webhook allowed CIDR '{}': {}", cidr, e))
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Limits on every outbound HTTP call the server makes (webhook deliveries
+/// among them), through [`crate::outbound::OutboundHttp`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboundConfig {
+    pub connect_timeout_ms: u64,
+    /// Limit on each attempt, from connecting to reading the response.
+    /// Features may set their own.
+    pub request_timeout_seconds: u64,
+    /// Redirects followed, each checked like the original destination.
+    pub max_redirects: u32,
+    /// Retries after connection failures, 429 and 5xx, for features that do
+    /// not retry on their own.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubling for each one after.
+    pub retry_backoff_ms: u64,
+    pub max_response_bytes: usize,
+    /// Failures in a row after which a destination is not called for
+    /// `circuit_open_seconds`.
+    pub circuit_failure_threshold: u32,
+    pub circuit_open_seconds: u64,
+    /// Networks that may be called although they are private, loopback or
+    /// link-local, e.g. `10.0.0.0/8` for internal services.
+    #[serde(default)]
+    pub allowed_private_cidrs: Vec<String>,
+}
+
+impl Default for OutboundConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_ms: 3000,
+            request_timeout_seconds: 15,
+            max_redirects: 3,
+            max_retries: 2,
+            retry_backoff_ms: 200,
+            max_response_bytes: 1024 * 1024,
+            circuit_failure_threshold: 5,
+            circuit_open_seconds: 30,
+            allowed_private_cidrs: Vec::new(),
+        }
+    }
+}
+
+impl OutboundConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.connect_timeout_ms == 0 || self.request_timeout_seconds == 0 {
+            return Err(ConfigError::Message(
+                "Outbound connect and request timeouts must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.circuit_failure_threshold == 0 {
+            return Err(ConfigError::Message(
+                "Outbound circuit failure threshold must be greater than 0".to_string(),
+            ));
+        }
+
+        for cidr in &self.allowed_private_cidrs {
+            cidr.parse::<crate::net::IpCidr>().map_err(|e| {
+                ConfigError::Message(format!("Invalid outbound allowed CIDR '{}': {}", cidr, e))
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `POST /api/graphql`, served when the server is built with the `graphql`
+/// feature. The depth and complexity limits bound the work one query can
+/// ask for; a list field counts its children once per requested row.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphqlConfig {
+    pub enabled: bool,
+    pub max_depth: usize,
+    pub max_complexity: usize,
+}
+
+impl Default for GraphqlConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_depth: 8,
+            max_complexity: 2000,
+        }
+    }
+}
+
+impl GraphqlConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_depth == 0 || self.max_complexity == 0 {
+            return Err(ConfigError::Message(
+                "GraphQL max depth and max complexity must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// `POST /api/batch`, which runs several item, file and job requests in one
+/// round trip. Sub-requests go through the full middleware stack, so each
+/// one counts against the caller's rate limit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchConfig {
+    pub enabled: bool,
+    /// Most sub-requests one batch may contain.
+    pub max_requests: usize,
+    /// Sub-requests of one batch running at the same time.
+    pub max_parallelism: usize,
+    /// Wall-clock budget for the whole batch.
+    pub timeout_seconds: u64,
+    /// Accept item creates, updates and deletes as well as reads.
+    pub allow_mutations: bool,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_requests: 20,
+            max_parallelism: 4,
+            timeout_seconds: 10,
+            allow_mutations: false,
+        }
+    }
+}
+
+impl BatchConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_requests == 0 || self.max_parallelism == 0 {
+            return Err(ConfigError::Message(
+                "Batch max requests and max parallelism must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.timeout_seconds == 0 {
+            return Err(ConfigError::Message(
+                "Batch timeout must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// `POST /api/admin/export` and `POST /api/admin/import`. Archives being
+/// imported wait in `files.temp_dir` until their job picks them up.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotConfig {
+    /// Largest archive `POST /api/admin/import` accepts.
+    pub max_archive_size_mb: u64,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            max_archive_size_mb: 256,
+        }
+    }
+}
+
+impl SnapshotConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_archive_size_mb == 0 {
+            return Err(ConfigError::Message(
+                "Snapshot max archive size must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// The item change log behind `GET /api/items/changes`. Entries are dropped
+/// once they are older than `retention_hours` or beyond the newest
+/// `max_entries`, whichever comes first; clients asking for a dropped
+/// sequence number are told to resync.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeFeedConfig {
+    pub retention_hours: u64,
+    pub max_entries: usize,
+}
+
+impl Default for ChangeFeedConfig {
+    fn default() -> Self {
+        Self {
+            retention_hours: 168,
+            max_entries: 100_000,
+        }
+    }
+}
+
+impl ChangeFeedConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.retention_hours == 0 {
+            return Err(ConfigError::Message(
+                "Change feed retention hours must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.max_entries == 0 {
+            return Err(ConfigError::Message(
+                "Change feed max entries must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Item writes. Items carry a version that every update increments; with
+/// `require_version`, updates that do not say which version they were made
+/// against are refused instead of overwriting whatever is there. Metadata
+/// over any of the `max_metadata_*` limits is refused with 413 whichever
+/// API writes it. With `unique_names`, no two items outside the trash share
+/// a name within the chosen scope; see [`crate::item_names`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ItemConfig {
+    pub require_version: bool,
+    /// Most bytes an item's metadata may take serialized.
+    #[serde(default = "default_max_metadata_bytes")]
+    pub max_metadata_bytes: usize,
+    /// Most levels of arrays and objects metadata may nest.
+    #[serde(default = "default_max_metadata_depth")]
+    pub max_metadata_depth: usize,
+    /// Most keys across all the objects in an item's metadata.
+    #[serde(default = "default_max_metadata_keys")]
+    pub max_metadata_keys: usize,
+    /// Deprecated: repeat the `X-Data-Source` header as a `source` field in
+    /// item lists and store statistics. Clients should read the header.
+    #[serde(default = "default_source_in_body")]
+    pub source_in_body: bool,
+    #[serde(default)]
+    pub unique_names: NameScope,
+    /// Whether names differing only in the case of ASCII letters count as
+    /// different while `unique_names` is on.
+    #[serde(default)]
+    pub unique_names_case_sensitive: bool,
+}
+
+/// Where item names must be unique.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NameScope {
+    /// Names may repeat.
+    #[default]
+    Off,
+    /// Across every namespace.
+    Global,
+    /// Within each namespace.
+    Namespace,
+}
+
+fn default_source_in_body() -> bool {
+    true
+}
+
+fn default_max_metadata_bytes() -> usize {
+    10 * 1024
+}
+
+fn default_max_metadata_depth() -> usize {
+    16
+}
+
+fn default_max_metadata_keys() -> usize {
+    256
+}
+
+impl Default for ItemConfig {
+    fn default() -> Self {
+        Self {
+            require_version: false,
+            max_metadata_bytes: default_max_metadata_bytes(),
+            max_metadata_depth: default_max_metadata_depth(),
+            max_metadata_keys: default_max_metadata_keys(),
+            source_in_body: default_source_in_body(),
+            unique_names: NameScope::Off,
+            unique_names_case_sensitive: false,
+        }
+    }
+}
+
+impl ItemConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_metadata_bytes == 0 || self.max_metadata_depth == 0 || self.max_metadata_keys == 0 {
+            return Err(ConfigError::Message(
+                "Item metadata limits must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Metadata keys items must carry, set per deployment. Only writes are
+/// checked: after the schema changes, items written under the old one are
+/// still served as they are, and the `SchemaValidation` job lists them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ItemSchemaConfig {
+    #[serde(default)]
+    pub fields: BTreeMap<String, MetadataFieldSchema>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetadataFieldSchema {
+    #[serde(rename = "type")]
+    pub field_type: MetadataFieldType,
+    /// Whether the key must be present and not null.
+    #[serde(default = "default_metadata_field_required")]
+    pub required: bool,
+    /// The allowed values of an `enum` field.
+    #[serde(default)]
+    pub values: Vec<String>,
+    /// Shown next to the field in forms built from the schema.
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+fn default_metadata_field_required() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataFieldType {
+    String,
+    Number,
+    Enum,
+    /// A calendar date (`2024-01-31`) or an RFC 3339 timestamp.
+    Date,
+}
+
+impl ItemSchemaConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for (key, field) in &self.fields {
+            if key.trim().is_empty() || key.contains('.') {
+                return Err(ConfigError::Message(format!(
+                    "Item schema field '{}' must be a non-empty metadata key without dots",
+                    key
+                )));
+            }
+
+            match field.field_type {
+                MetadataFieldType::Enum if field.values.is_empty() => {
+                    return Err(ConfigError::Message(format!(
+                        "Item schema field '{}' is an enum but lists no values",
+                        key
+                    )));
+                }
+                MetadataFieldType::Enum => {}
+                _ if !field.values.is_empty() => {
+                    return Err(ConfigError::Message(format!(
+                        "Item schema field '{}' lists values but is not an enum",
+                        key
+                    )));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Encryption of what items keep under `metadata.secret`. With `enabled`,
+/// secrets are sealed with AES-256-GCM under `key` (64 hex characters) or
+/// the key held in `key_file`. Secrets sealed under a retired key stay
+/// readable while it is listed in `previous_keys` or `previous_key_files`,
+/// until a `SecretRekey` job has sealed them again. Users in `read_roles`
+/// hold `items:read_secrets`; everyone else is shown `"***"`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ItemSecretsConfig {
+    pub enabled: bool,
+    #[serde(default)]
+    pub key: Option<String>,
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+    #[serde(default)]
+    pub previous_keys: Vec<String>,
+    #[serde(default)]
+    pub previous_key_files: Vec<PathBuf>,
+    pub read_roles: Vec<String>,
+}
+
+impl Default for ItemSecretsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            key: None,
+            key_file: None,
+            previous_keys: Vec::new(),
+            previous_key_files: Vec::new(),
+            read_roles: vec!["admin".to_string()],
+        }
+    }
+}
+
+impl ItemSecretsConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for role in &self.read_roles {
+            role.parse::<crate::auth::models::UserRole>()
+                .map_err(|e| ConfigError::Message(format!("Item secrets read role: {}", e)))?;
+        }
+
+        if !self.enabled {
+            return Ok(());
+        }
+
+        match (&self.key, &self.key_file) {
+            (Some(_), Some(_)) => {
+                return Err(ConfigError::Message(
+                    "Item secrets take either a key or a key file, not both".to_string(),
+                ));
+            }
+            (None, None) => {
+                return Err(ConfigError::Message(
+                    "Item secrets are enabled but neither a key nor a key file is set".to_string(),
+                ));
+            }
+            _ => {}
+        }
+
+        for key in self.key.iter().chain(&self.previous_keys) {
+            if key.len() != 64 || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(ConfigError::Message(
+                    "Item secret keys must be 64 hex characters (32 bytes)".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Deleted items stay in the trash for `retention_days` before a purge,
+/// run every `purge_interval_minutes` as a job, removes them for good.
+/// Purges delete at most `batch_size` items per transaction.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashConfig {
+    pub retention_days: u32,
+    pub purge_interval_minutes: u64,
+    pub batch_size: usize,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self {
+            retention_days: 30,
+            purge_interval_minutes: 60,
+            batch_size: 500,
+        }
+    }
+}
+
+impl TrashConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.batch_size == 0 {
+            return Err(ConfigError::Message(
+                "Trash purge batch size must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks of the references between tables; see [`crate::integrity`]. The
+/// check at startup gives up after `startup_budget_ms` and leaves the rest
+/// to a job. Every query reads at most `batch_size` rows.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntegrityConfig {
+    pub startup: IntegrityMode,
+    pub startup_budget_ms: u64,
+    pub batch_size: usize,
+}
+
+/// What the integrity check does when the server starts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegrityMode {
+    /// No check.
+    Off,
+    /// Report broken references without changing anything.
+    #[default]
+    Warn,
+    /// Repair them as well.
+    Repair,
+}
+
+impl Default for IntegrityConfig {
+    fn default() -> Self {
+        Self {
+            startup: IntegrityMode::Warn,
+            startup_budget_ms: 2000,
+            batch_size: 1000,
+        }
+    }
+}
+
+impl IntegrityConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.batch_size == 0 {
+            return Err(ConfigError::Message(
+                "Integrity check batch size must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Near-duplicate detection. Up to `candidate_limit` items sharing a name
+/// term with the candidate are taken from the full-text index and scored
+/// between 0 and 1; those scoring at least `min_similarity` are returned,
+/// best first, at most `max_results` of them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateConfig {
+    pub min_similarity: f64,
+    pub max_results: usize,
+    pub candidate_limit: usize,
+}
+
+impl Default for DuplicateConfig {
+    fn default() -> Self {
+        Self {
+            min_similarity: 0.6,
+            max_results: 10,
+            candidate_limit: 50,
+        }
+    }
+}
+
+impl DuplicateConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(0.0..=1.0).contains(&self.min_similarity) {
+            return Err(ConfigError::Message(
+                "Duplicate min similarity must be between 0 and 1".to_string(),
+            ));
+        }
+
+        if self.max_results == 0 || self.candidate_limit < self.max_results {
+            return Err(ConfigError::Message(
+                "Duplicate max results must be greater than 0 and no more than the candidate limit".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Typeahead suggestions. Prefixes shorter than `min_length` characters get
+/// no suggestions. Up to `max_limit` suggestions per field and prefix are
+/// kept for `cache_ttl_seconds` in an LRU of `cache_size` entries, cleared
+/// whenever an item is written.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuggestConfig {
+    pub min_length: usize,
+    pub default_limit: usize,
+    pub max_limit: usize,
+    pub cache_size: usize,
+    pub cache_ttl_seconds: u64,
+}
+
+impl Default for SuggestConfig {
+    fn default() -> Self {
+        Self {
+            min_length: 2,
+            default_limit: 10,
+            max_limit: 50,
+            cache_size: 1024,
+            cache_ttl_seconds: 30,
+        }
+    }
+}
+
+impl SuggestConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.default_limit == 0 || self.max_limit < self.default_limit {
+            return Err(ConfigError::Message(
+                "Suggest default limit must be greater than 0 and no more than the max limit".to_string(),
+            ));
+        }
+
+        if self.cache_size == 0 {
+            return Err(ConfigError::Message("Suggest cache size must be greater than 0".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Full-text ranking. A match counts `name_weight`, `tags_weight` or
+/// `description_weight` times over depending on its column. With a
+/// `recency_weight` above zero, newer items get up to that fraction more
+/// relevance, the boost halving every `recency_half_life_days`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchConfig {
+    pub name_weight: f64,
+    pub tags_weight: f64,
+    pub description_weight: f64,
+    pub recency_weight: f64,
+    pub recency_half_life_days: f64,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            name_weight: 10.0,
+            tags_weight: 5.0,
+            description_weight: 1.0,
+            recency_weight: 0.0,
+            recency_half_life_days: 30.0,
+        }
+    }
+}
+
+impl SearchConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let weights = [self.name_weight, self.tags_weight, self.description_weight, self.recency_weight];
+        if weights.iter().any(|weight| !weight.is_finite() || *weight < 0.0) {
+            return Err(ConfigError::Message("Search weights must be zero or more".to_string()));
+        }
+
+        if !self.recency_half_life_days.is_finite() || self.recency_half_life_days <= 0.0 {
+            return Err(ConfigError::Message("Search recency half-life must be greater than 0".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Search result export. Results of up to `direct_limit` items are returned
+/// in the response; larger ones are written to a file by a background job,
+/// which reads them `page_size` items at a time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchExportConfig {
+    pub direct_limit: u64,
+    pub page_size: u64,
+}
+
+impl Default for SearchExportConfig {
+    fn default() -> Self {
+        Self {
+            direct_limit: 1000,
+            page_size: 500,
+        }
+    }
+}
+
+impl SearchExportConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.page_size == 0 {
+            return Err(ConfigError::Message("Search export page size must be greater than 0".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Entries on a page of a list that doesn't say, wherever it is listed.
+pub const DEFAULT_PAGE_SIZE: u64 = 50;
+
+/// Paging of the item, search, job and file lists. A list asked for no
+/// particular size gets `default_page_size` entries; asking for more than
+/// `max_page_size` is refused with a 400 naming the maximum, or, with
+/// `clamp_oversized`, quietly cut down to it as lists used to. `GET
+/// /api/items/export` exports at most `max_export_items` items.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaginationConfig {
+    pub default_page_size: u64,
+    pub max_page_size: u64,
+    pub max_export_items: u64,
+    #[serde(default)]
+    pub clamp_oversized: bool,
+}
+
+impl Default for PaginationConfig {
+    fn default() -> Self {
+        Self {
+            default_page_size: DEFAULT_PAGE_SIZE,
+            max_page_size: 100,
+            max_export_items: 10_000,
+            clamp_oversized: false,
+        }
+    }
+}
+
+impl PaginationConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.default_page_size == 0 || self.max_page_size == 0 {
+            return Err(ConfigError::Message("Page sizes must be greater than 0".to_string()));
+        }
+
+        if self.default_page_size > self.max_page_size {
+            return Err(ConfigError::Message(
+                "Default page size cannot exceed the maximum page size".to_string(),
+            ));
+        }
+
+        if self.max_export_items == 0 {
+            return Err(ConfigError::Message("Max export items must be greater than 0".to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Entries per page for a list asked for `requested` in parameter
+    /// `field`: the default when it doesn't say, and an error naming the
+    /// maximum when it asks for more, unless oversized pages are clamped.
+    pub fn page_size(&self, field: &str, requested: Option<u64>) -> Result<u64, crate::validation::ValidationError> {
+        let out_of_range = |message: String| crate::validation::ValidationError::field(field, "range", message);
+        match requested {
+            None => Ok(self.default_page_size),
+            Some(0) => Err(out_of_range(format!("{} must be at least 1", field))),
+            Some(size) if size <= self.max_page_size => Ok(size),
+            Some(_) if self.clamp_oversized => Ok(self.max_page_size),
+            Some(_) => Err(out_of_range(format!("{} must be at most {}", field, self.max_page_size))),
+        }
+    }
+}
+
+/// Recording of full request/response pairs for debugging, started by an
+/// admin for a bounded time. Only with `enabled` is the recording middleware
+/// installed at all. At most `max_entries` exchanges are kept, each body cut
+/// at `max_body_bytes`, and headers named in `redact_headers` are masked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureConfig {
+    pub enabled: bool,
+    pub max_entries: usize,
+    pub max_body_bytes: usize,
+    pub default_duration_seconds: u64,
+    pub max_duration_seconds: u64,
+    pub redact_headers: Vec<String>,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_entries: 500,
+            max_body_bytes: 16 * 1024,
+            default_duration_seconds: 300,
+            max_duration_seconds: 3600,
+            redact_headers: vec![
+                "authorization".to_string(),
+                "cookie".to_string(),
+                "set-cookie".to_string(),
+                "proxy-authorization".to_string(),
+                "x-api-key".to_string(),
+                "x-bundle-passphrase".to_string(),
+            ],
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CacheConfig {
-    pub max_size: usize,
-    pub default_ttl_seconds: u64,
-    pub cleanup_interval_seconds: u64,
-    pub enable_stats: bool,
+impl CaptureConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_entries == 0 {
+            return Err(ConfigError::Message("Capture max entries must be greater than 0".to_string()));
+        }
+
+        if self.default_duration_seconds == 0 || self.default_duration_seconds > self.max_duration_seconds {
+            return Err(ConfigError::Message(
+                "Capture default duration must be between 1 second and the max duration".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
+/// Which optional subsystems start switched on. Admins can switch them at
+/// runtime through `POST /api/admin/features/{name}`; with a database, that
+/// choice is kept and wins over these until it is changed back.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct JobConfig {
-    pub max_workers: usize,
-    pub queue_size: usize,
-    pub job_timeout_seconds: u64,
-    pub retry_attempts: u32,
-    pub retry_delay_seconds: u64,
+pub struct FeaturesConfig {
+    pub jobs: bool,
+    pub websockets: bool,
+    pub search: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct WebSocketConfig {
-    pub max_connections: usize,
-    pub ping_interval_seconds: u64,
-    pub pong_timeout_seconds: u64,
-    pub message_buffer_size: usize,
+impl Default for FeaturesConfig {
+    fn default() -> Self {
+        Self {
+            jobs: true,
+            websockets: true,
+            search: true,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CorsConfig {
-    pub allowed_origins: Vec<String>,
-    pub allowed_methods: Vec<String>,
-    pub allowed_headers: Vec<String>,
-    pub exposed_headers: Vec<String>,
-    pub allow_credentials: bool,
-    pub max_age_seconds: u64,
-    pub enable_permissive_mode: bool,
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifierBackend {
+    /// Writes rendered notifications to the log instead of sending them.
+    #[default]
+    Log,
+    Smtp,
 }
 
+/// Account notifications (welcome, new-device login, password changed).
+/// Delivery runs as background jobs, so `jobs.retry_attempts` and
+/// `jobs.retry_delay_seconds` govern retries.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RateLimitConfig {
-    pub enable: bool,
-    pub requests_per_minute: usize,
-    pub burst_size: usize,
-    pub enable_user_based_limits: bool,
-    pub user_requests_per_minute: usize,
-    pub admin_requests_per_minute: usize,
-    pub cleanup_interval_seconds: u64,
+pub struct NotificationConfig {
+    pub enabled: bool,
+    pub backend: NotifierBackend,
+    pub from_address: String,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub smtp_timeout_seconds: u64,
+    /// Directory of `<template_id>.txt` files overriding the built-in
+    /// templates. The first line is `Subject: ...`, the rest is the body.
+    #[serde(default)]
+    pub templates_dir: Option<PathBuf>,
+    /// Inline templates; these take precedence over `templates_dir`.
+    #[serde(default)]
+    pub templates: Vec<NotificationTemplate>,
 }
 
+/// Subject and body with `{{placeholder}}` substitutions.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LoggingConfig {
-    pub level: String,
-    pub format: String,
-    pub include_request_id: bool,
-    pub include_user_info: bool,
-    pub include_timing: bool,
-    pub log_request_body: bool,
-    pub log_response_body: bool,
-    pub max_body_size: usize,
+pub struct NotificationTemplate {
+    pub id: String,
+    pub subject: String,
+    pub body: String,
 }
 
 impl Default for AppConfig {
@@ -124,6 +1843,32 @@ impl Default for AppConfig {
             cors: CorsConfig::default(),
             rate_limit: RateLimitConfig::default(),
             logging: LoggingConfig::default(),
+            validation: ValidationConfig::default(),
+            security: SecurityConfig::default(),
+            timeouts: TimeoutConfig::default(),
+            notifications: NotificationConfig::default(),
+            metrics: MetricsConfig::default(),
+            health: HealthConfig::default(),
+            load_shedding: LoadSheddingConfig::default(),
+            concurrency: ConcurrencyConfig::default(),
+            webhooks: WebhookConfig::default(),
+            outbound: OutboundConfig::default(),
+            graphql: GraphqlConfig::default(),
+            batch: BatchConfig::default(),
+            snapshots: SnapshotConfig::default(),
+            changes: ChangeFeedConfig::default(),
+            items: ItemConfig::default(),
+            item_schema: ItemSchemaConfig::default(),
+            item_secrets: ItemSecretsConfig::default(),
+            trash: TrashConfig::default(),
+            integrity: IntegrityConfig::default(),
+            duplicates: DuplicateConfig::default(),
+            suggest: SuggestConfig::default(),
+            search_export: SearchExportConfig::default(),
+            search: SearchConfig::default(),
+            pagination: PaginationConfig::default(),
+            capture: CaptureConfig::default(),
+            features: FeaturesConfig::default(),
         }
     }
 }
@@ -136,7 +1881,54 @@ impl Default for ServerConfig {
             max_connections: 1000,
             request_timeout_seconds: 30,
             shutdown_timeout_seconds: 10,
+            config_reload_interval_seconds: default_config_reload_interval_seconds(),
+            http2: default_http2(),
+            http2_max_concurrent_streams: default_http2_max_concurrent_streams(),
+            keep_alive_timeout_seconds: default_keep_alive_timeout_seconds(),
+            max_header_size_bytes: default_max_header_size_bytes(),
+            additional_listeners: Vec::new(),
+            tls: None,
+            grpc_port: None,
+            instance_id: default_instance_id(),
+            strict_startup: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.http2_max_concurrent_streams == 0 {
+            return Err(ConfigError::Message(
+                "HTTP/2 max concurrent streams must be greater than 0".to_string(),
+            ));
         }
+
+        if self.max_header_size_bytes < MIN_HEADER_SIZE_BYTES {
+            return Err(ConfigError::Message(format!(
+                "Max header size must be at least {} bytes",
+                MIN_HEADER_SIZE_BYTES
+            )));
+        }
+
+        for listener in &self.additional_listeners {
+            listener
+                .parse::<crate::server::ListenAddr>()
+                .map_err(ConfigError::Message)?;
+        }
+
+        if self.grpc_port.is_some_and(|grpc_port| grpc_port != 0 && grpc_port == self.port) {
+            return Err(ConfigError::Message(
+                "gRPC port must differ from the HTTP port".to_string(),
+            ));
+        }
+
+        if self.instance_id.is_empty() || axum::http::HeaderValue::from_str(&self.instance_id).is_err() {
+            return Err(ConfigError::Message(
+                "Instance id must be non-empty and usable as a header value".to_string(),
+            ));
+        }
+
+        Ok(())
     }
 }
 
@@ -148,6 +1940,20 @@ impl Default for DatabaseConfig {
             min_connections: 1,
             connection_timeout_seconds: 30,
             migrate_on_start: true,
+            slow_query_threshold_ms: default_slow_query_threshold_ms(),
+        }
+    }
+}
+
+impl DatabaseConfig {
+    /// File backing a SQLite `url`, or `None` for in-memory databases.
+    pub fn sqlite_path(&self) -> Option<PathBuf> {
+        let path = self.url.strip_prefix("sqlite://").or_else(|| self.url.strip_prefix("sqlite:"))?;
+        let path = path.split('?').next().unwrap_or_default();
+        if path.is_empty() || path == ":memory:" {
+            None
+        } else {
+            Some(PathBuf::from(path))
         }
     }
 }
@@ -161,7 +1967,90 @@ impl Default for AuthConfig {
             password_min_length: 8,
             max_login_attempts: 5,
             lockout_duration_minutes: 15,
+            password_hash_memory_kib: default_password_hash_memory_kib(),
+            password_hash_iterations: default_password_hash_iterations(),
+            password_hash_parallelism: default_password_hash_parallelism(),
+            unverified_users: UnverifiedUserPolicy::default(),
+            verification_token_hours: default_verification_token_hours(),
+            verification_resend_seconds: default_verification_resend_seconds(),
+            introspection_clients: Vec::new(),
+            introspect_requests_per_minute: default_introspect_requests_per_minute(),
+            validate_requests_per_minute: default_validate_requests_per_minute(),
+            login_requests_per_minute: default_login_requests_per_minute(),
+            refresh_requests_per_minute: default_refresh_requests_per_minute(),
+        }
+    }
+}
+
+impl AuthConfig {
+    /// Argon2 parameters for new password hashes.
+    pub fn argon2_params(&self) -> Result<argon2::Params, ConfigError> {
+        argon2::Params::new(
+            self.password_hash_memory_kib,
+            self.password_hash_iterations,
+            self.password_hash_parallelism,
+            None,
+        )
+        .map_err(|e| ConfigError::Message(format!("Invalid password hashing parameters: {}", e)))
+    }
+
+    /// True when the hashing cost is below every OWASP-recommended Argon2id
+    /// configuration.
+    pub fn is_below_recommended_hash_cost(&self) -> bool {
+        let cost = self.password_hash_memory_kib as u64 * self.password_hash_iterations as u64;
+        self.password_hash_memory_kib < 7168 || cost < OWASP_PASSWORD_HASH_COST
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.password_hash_memory_kib < MIN_PASSWORD_HASH_MEMORY_KIB {
+            return Err(ConfigError::Message(format!(
+                "Password hash memory must be at least {} KiB",
+                MIN_PASSWORD_HASH_MEMORY_KIB
+            )));
+        }
+
+        if self.password_hash_iterations == 0 || self.password_hash_parallelism == 0 {
+            return Err(ConfigError::Message(
+                "Password hash iterations and parallelism must be greater than 0".to_string(),
+            ));
+        }
+
+        self.argon2_params()?;
+
+        if self.is_below_recommended_hash_cost() {
+            tracing::warn!(
+                "Password hashing parameters (m={} KiB, t={}, p={}) are below OWASP recommendations",
+                self.password_hash_memory_kib,
+                self.password_hash_iterations,
+                self.password_hash_parallelism
+            );
+        }
+
+        if self.verification_token_hours == 0 {
+            return Err(ConfigError::Message(
+                "Verification token lifetime must be greater than 0".to_string(),
+            ));
+        }
+
+        for client in &self.introspection_clients {
+            if client.key.is_empty() {
+                return Err(ConfigError::Message(format!("Introspection client '{}' has an empty key", client.name)));
+            }
+        }
+
+        if self.introspect_requests_per_minute == 0 || self.validate_requests_per_minute == 0 {
+            return Err(ConfigError::Message(
+                "Token check rate limits must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.login_requests_per_minute == 0 || self.refresh_requests_per_minute == 0 {
+            return Err(ConfigError::Message(
+                "Sign-in and refresh rate limits must be greater than 0".to_string(),
+            ));
         }
+
+        Ok(())
     }
 }
 
@@ -181,6 +2070,9 @@ impl Default for FileConfig {
                 "docx".to_string(),
             ],
             temp_dir: PathBuf::from("./temp"),
+            min_free_space_mb: default_min_free_space_mb(),
+            cache_control: FileCacheControlConfig::default(),
+            reconcile: FileReconcileConfig::default(),
         }
     }
 }
@@ -192,6 +2084,9 @@ impl Default for CacheConfig {
             default_ttl_seconds: 3600,
             cleanup_interval_seconds: 300,
             enable_stats: true,
+            coalesce_requests: default_coalesce_requests(),
+            coalesce_timeout_seconds: default_coalesce_timeout_seconds(),
+            clear_cooldown_seconds: default_clear_cooldown_seconds(),
         }
     }
 }
@@ -199,11 +2094,13 @@ impl Default for CacheConfig {
 impl Default for JobConfig {
     fn default() -> Self {
         Self {
+            enabled: true,
             max_workers: 4,
             queue_size: 1000,
             job_timeout_seconds: 300,
             retry_attempts: 3,
             retry_delay_seconds: 60,
+            max_inline_result_bytes: default_max_inline_result_bytes(),
         }
     }
 }
@@ -215,6 +2112,19 @@ impl Default for WebSocketConfig {
             ping_interval_seconds: 30,
             pong_timeout_seconds: 10,
             message_buffer_size: 1024,
+            max_messages_per_second: 20,
+            max_rate_limit_violations: 3,
+            outbound_queue_size: 256,
+            slow_consumer_policy: SlowConsumerPolicy::DropOldest,
+            allow_query_token: true,
+            require_authentication: false,
+            auth_grace_period_seconds: 5,
+            coalesce_window_ms: default_coalesce_window_ms(),
+            coalesce_max_ids: default_coalesce_max_ids(),
+            coalesce_exempt_events: Vec::new(),
+            upload_progress_interval_ms: default_upload_progress_interval_ms(),
+            replay_buffer_size: default_replay_buffer_size(),
+            replay_max_age_seconds: default_replay_max_age_seconds(),
         }
     }
 }
@@ -251,16 +2161,22 @@ impl Default for CorsConfig {
                 "origin".to_string(),
                 "referer".to_string(),
                 "cache-control".to_string(),
+                "x-api-key".to_string(),
+                "x-upload-id".to_string(),
             ],
             exposed_headers: vec![
                 "x-request-id".to_string(),
                 "x-response-time".to_string(),
                 "x-ratelimit-limit".to_string(),
                 "x-ratelimit-remaining".to_string(),
+                "x-ratelimit-tier".to_string(),
+                "x-upload-id".to_string(),
+                "x-unknown-params".to_string(),
             ],
             allow_credentials: true,
             max_age_seconds: 3600,
             enable_permissive_mode: false,
+            routes: Vec::new(),
         }
     }
 }
@@ -275,7 +2191,97 @@ impl Default for RateLimitConfig {
             user_requests_per_minute: 100,
             admin_requests_per_minute: 200,
             cleanup_interval_seconds: 300,
+            backend: RateLimitBackend::Memory,
+            shared_store_url: None,
+            shared_store_timeout_ms: default_shared_store_timeout_ms(),
+            exempt_cidrs: Vec::new(),
+            exempt_paths: vec![
+                "/health".to_string(),
+                "/ready".to_string(),
+                "/live".to_string(),
+                "/auth/introspect".to_string(),
+                "/auth/validate".to_string(),
+            ],
+            tiers: Vec::new(),
+            api_keys: Vec::new(),
+        }
+    }
+}
+
+impl CorsConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let check_origins = |scope: &str, origins: &[String], allow_credentials: bool| {
+            for origin in origins {
+                let pattern = origin.parse::<crate::middleware::cors::OriginPattern>().map_err(|e| {
+                    ConfigError::Message(format!("Invalid CORS origin '{}' for {}: {}", origin, scope, e))
+                })?;
+                if allow_credentials && pattern.is_any() {
+                    return Err(ConfigError::Message(format!(
+                        "CORS for {} cannot allow credentials with a wildcard '*' origin",
+                        scope
+                    )));
+                }
+            }
+            Ok(())
+        };
+
+        if !self.enable_permissive_mode {
+            check_origins("all routes", &self.allowed_origins, self.allow_credentials)?;
+        }
+
+        for route in &self.routes {
+            if !route.path.starts_with('/') {
+                return Err(ConfigError::Message(format!(
+                    "CORS route path '{}' must start with '/'",
+                    route.path
+                )));
+            }
+            let allow_credentials = route.allow_credentials.unwrap_or(self.allow_credentials);
+            check_origins(&route.path, &route.allowed_origins, allow_credentials)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl RateLimitConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for cidr in &self.exempt_cidrs {
+            cidr.parse::<crate::net::IpCidr>().map_err(|e| {
+                ConfigError::Message(format!("Invalid rate limit exempt CIDR '{}': {}", cidr, e))
+            })?;
+        }
+
+        let mut tier_names = std::collections::HashSet::new();
+        for tier in &self.tiers {
+            if !tier_names.insert(tier.name.as_str()) {
+                return Err(ConfigError::Message(format!(
+                    "Rate limit tier '{}' is defined more than once",
+                    tier.name
+                )));
+            }
+            if tier.requests_per_minute == 0 {
+                return Err(ConfigError::Message(format!(
+                    "Rate limit tier '{}' must allow at least one request per minute",
+                    tier.name
+                )));
+            }
         }
+
+        for api_key in &self.api_keys {
+            let builtin = ["default", "user", "admin"].contains(&api_key.tier.as_str());
+            if !builtin && !tier_names.contains(api_key.tier.as_str()) {
+                return Err(ConfigError::Message(format!(
+                    "API key '{}' references unknown rate limit tier '{}'",
+                    api_key.name, api_key.tier
+                )));
+            }
+            if api_key.key.is_empty() {
+                return Err(ConfigError::Message(format!("API key '{}' has an empty key", api_key.name)));
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -294,13 +2300,134 @@ impl Default for LoggingConfig {
     }
 }
 
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            default_policy: FieldPolicy::Strict,
+            field_policies: HashMap::new(),
+            unknown_query_params: UnknownParamsPolicy::Warn,
+            max_header_count: default_max_header_count(),
+            max_header_bytes: default_max_header_bytes(),
+        }
+    }
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            enable_anomaly_blocking: true,
+            anomaly_threshold: 10,
+            anomaly_window_seconds: 300,
+            block_duration_seconds: 900,
+            max_user_agent_length: 512,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        let route = |path: &str, timeout_seconds| RouteTimeout {
+            path: path.to_string(),
+            timeout_seconds,
+        };
+
+        Self {
+            slow_request_threshold_ms: 1000,
+            stream_idle_timeout_seconds: 60,
+            routes: vec![
+                route("/health", 5),
+                route("/ready", 5),
+                route("/live", 5),
+                route("/api/items/export", 120),
+                route("/api/v1/items/export", 120),
+                route("/api/v2/items/export", 120),
+                route("/api/jobs/bulk-import", 120),
+                route("/api/jobs/bulk-export", 120),
+            ],
+        }
+    }
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            backend: NotifierBackend::Log,
+            from_address: "no-reply@localhost".to_string(),
+            smtp_host: "localhost".to_string(),
+            smtp_port: 25,
+            smtp_timeout_seconds: 10,
+            templates_dir: None,
+            templates: Vec::new(),
+        }
+    }
+}
+
+impl NotificationConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        if !self.from_address.contains('@') {
+            return Err(ConfigError::Message(format!(
+                "Notification from_address '{}' is not an email address",
+                self.from_address
+            )));
+        }
+
+        if self.backend == NotifierBackend::Smtp && self.smtp_host.trim().is_empty() {
+            return Err(ConfigError::Message(
+                "Notification smtp_host cannot be empty when the smtp backend is selected".to_string(),
+            ));
+        }
+
+        for template in &self.templates {
+            if template.id.trim().is_empty() {
+                return Err(ConfigError::Message(
+                    "Notification template id cannot be empty".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ValidationConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_header_count == 0 || self.max_header_bytes == 0 {
+            return Err(ConfigError::Message(
+                "Validation header limits must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Looks up the policy for a field key such as `item.description`,
+    /// falling back to the default policy when no override is configured.
+    pub fn policy_for(&self, field: &str) -> FieldPolicy {
+        self.field_policies
+            .get(field)
+            .copied()
+            .unwrap_or(self.default_policy)
+    }
+}
+
 impl AppConfig {
     pub fn load() -> Result<Self, ConfigError> {
+        Self::load_from(CONFIG_FILE)
+    }
+
+    pub fn load_from(path: impl AsRef<std::path::Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
         let mut builder = Config::builder()
             .add_source(Config::try_from(&AppConfig::default())?);
 
-        if std::path::Path::new("config.toml").exists() {
-            builder = builder.add_source(File::with_name("config.toml"));
+        if path.exists() {
+            builder = builder.add_source(File::from(path));
         }
 
         builder = builder.add_source(
@@ -328,6 +2455,8 @@ impl AppConfig {
             ));
         }
 
+        self.server.validate()?;
+
         if self.database.url.is_empty() {
             return Err(ConfigError::Message(
                 "Database URL cannot be empty".to_string(),
@@ -356,19 +2485,34 @@ impl AppConfig {
             ));
         }
 
+        self.auth.validate()?;
+        self.validation.validate()?;
+
         if self.files.max_file_size_mb == 0 {
             return Err(ConfigError::Message(
                 "Max file size must be greater than 0".to_string(),
             ));
         }
 
+        if self.files.reconcile.batch_size == 0 {
+            return Err(ConfigError::Message(
+                "File reconciliation batch size must be greater than 0".to_string(),
+            ));
+        }
+
         if self.cache.max_size == 0 {
             return Err(ConfigError::Message(
                 "Cache max size must be greater than 0".to_string(),
             ));
         }
 
-        if self.jobs.max_workers == 0 {
+        if self.cache.coalesce_requests && self.cache.coalesce_timeout_seconds == 0 {
+            return Err(ConfigError::Message(
+                "Cache coalesce timeout must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.jobs.enabled && self.jobs.max_workers == 0 {
             return Err(ConfigError::Message(
                 "Job max workers must be greater than 0".to_string(),
             ));
@@ -380,6 +2524,25 @@ impl AppConfig {
             ));
         }
 
+        if self.websocket.replay_buffer_size > 0 && self.websocket.replay_max_age_seconds == 0 {
+            return Err(ConfigError::Message(
+                "WebSocket replay max age must be greater than 0 when the replay buffer is enabled".to_string(),
+            ));
+        }
+
+        if let Some(event) = self
+            .websocket
+            .coalesce_exempt_events
+            .iter()
+            .find(|event| !COALESCABLE_EVENTS.contains(&event.as_str()))
+        {
+            return Err(ConfigError::Message(format!(
+                "WebSocket coalesce_exempt_events entry '{}' is not one of {}",
+                event,
+                COALESCABLE_EVENTS.join(", ")
+            )));
+        }
+
         if self.rate_limit.enable && self.rate_limit.requests_per_minute == 0 {
             return Err(ConfigError::Message(
                 "Rate limit requests per minute must be greater than 0".to_string(),
@@ -392,6 +2555,37 @@ impl AppConfig {
             ));
         }
 
+        self.cors.validate()?;
+        self.rate_limit.validate()?;
+        self.notifications.validate()?;
+        self.metrics.validate()?;
+        self.health.validate()?;
+        self.load_shedding.validate()?;
+        self.concurrency.validate()?;
+        self.webhooks.validate()?;
+        self.outbound.validate()?;
+        self.graphql.validate()?;
+        self.batch.validate()?;
+        self.snapshots.validate()?;
+        self.changes.validate()?;
+        self.items.validate()?;
+        self.item_schema.validate()?;
+        self.item_secrets.validate()?;
+        self.trash.validate()?;
+        self.integrity.validate()?;
+        self.duplicates.validate()?;
+        self.suggest.validate()?;
+        self.search_export.validate()?;
+        self.search.validate()?;
+        self.pagination.validate()?;
+        self.capture.validate()?;
+
+        if self.security.enable_anomaly_blocking && self.security.anomaly_threshold == 0 {
+            return Err(ConfigError::Message(
+                "Security anomaly threshold must be greater than 0".to_string(),
+            ));
+        }
+
         if !["debug", "info", "warn", "error"].contains(&self.logging.level.as_str()) {
             return Err(ConfigError::Message(
                 "Logging level must be one of: debug, info, warn, error".to_string(),
@@ -416,6 +2610,15 @@ impl AppConfig {
     pub fn bind_address(&self) -> String {
         format!("{}:{}", self.server.host, self.server.port)
     }
+
+    /// Paths whose filesystems the system monitor reports on.
+    pub fn storage_paths(&self) -> Vec<PathBuf> {
+        self.database
+            .sqlite_path()
+            .into_iter()
+            .chain(std::iter::once(self.files.upload_dir.clone()))
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -425,6 +2628,7 @@ mod tests {
     #[test]
     fn test_default_config() {
         let config = AppConfig::default();
+        assert!(!config.auth.is_below_recommended_hash_cost());
         assert_eq!(config.server.host, "127.0.0.1");
         assert_eq!(config.server.port, 3000);
         assert_eq!(config.database.url, "sqlite:./data.db");
@@ -448,6 +2652,76 @@ mod tests {
         config = AppConfig::default();
         config.auth.password_min_length = 3;
         assert!(config.validate().is_err());
+
+        config = AppConfig::default();
+        config.auth.password_hash_memory_kib = 1024;
+        assert!(config.validate().is_err());
+
+        config = AppConfig::default();
+        config.auth.password_hash_iterations = 0;
+        assert!(config.validate().is_err());
+
+        config = AppConfig::default();
+        config.server.max_header_size_bytes = 4096;
+        assert!(config.validate().is_err());
+
+        config = AppConfig::default();
+        config.websocket.coalesce_exempt_events = vec!["JobCompleted".to_string()];
+        assert!(config.validate().is_err());
+
+        config = AppConfig::default();
+        config.websocket.replay_max_age_seconds = 0;
+        assert!(config.validate().is_err());
+        config.websocket.replay_buffer_size = 0;
+        assert!(config.validate().is_ok());
+
+        config = AppConfig::default();
+        config.server.additional_listeners = vec!["localhost".to_string()];
+        assert!(config.validate().is_err());
+        config.server.additional_listeners = vec!["unix:/tmp/app.sock".to_string(), "[::1]:3001".to_string()];
+        assert!(config.validate().is_ok());
+
+        config = AppConfig::default();
+        config.server.grpc_port = Some(config.server.port);
+        assert!(config.validate().is_err());
+        config.server.grpc_port = Some(50051);
+        assert!(config.validate().is_ok());
+
+        config = AppConfig::default();
+        config.metrics.backends.clear();
+        assert!(config.validate().is_err());
+        config.metrics.backends = vec![MetricsBackend::Statsd];
+        config.metrics.statsd_address = " ".to_string();
+        assert!(config.validate().is_err());
+        config.metrics.statsd_address = "statsd:8125".to_string();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_page_size_policy() {
+        let mut config = PaginationConfig {
+            default_page_size: 20,
+            max_page_size: 100,
+            ..PaginationConfig::default()
+        };
+        assert_eq!(config.page_size("limit", None).unwrap(), 20);
+        assert_eq!(config.page_size("limit", Some(100)).unwrap(), 100);
+        assert!(config.page_size("limit", Some(0)).is_err());
+        let refused = config.page_size("page_size", Some(101)).unwrap_err();
+        assert!(refused.to_string().contains("page_size must be at most 100"), "{}", refused);
+
+        config.clamp_oversized = true;
+        assert_eq!(config.page_size("limit", Some(5000)).unwrap(), 100);
+    }
+
+    #[test]
+    fn test_storage_paths() {
+        let mut config = AppConfig::default();
+        config.database.url = "sqlite:./data/app.db?mode=rwc".to_string();
+        assert_eq!(config.storage_paths(), vec![PathBuf::from("./data/app.db"), PathBuf::from("./uploads")]);
+
+        config.database.url = "sqlite::memory:".to_string();
+        assert_eq!(config.storage_paths(), vec![PathBuf::from("./uploads")]);
     }
 
     #[test]
@@ -497,6 +2771,30 @@ mod tests {
         assert!(config.database.max_connections > 0);
     }
 
+    #[test]
+    fn test_item_schema_loading() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "[item_schema.fields.department]\ntype = \"enum\"\nvalues = [\"sales\", \"support\"]\n\n\
+             [item_schema.fields.due]\ntype = \"date\"\nrequired = false\n",
+        )
+        .unwrap();
+
+        let config = AppConfig::load_from(&path).unwrap();
+        let department = &config.item_schema.fields["department"];
+        assert_eq!(department.field_type, MetadataFieldType::Enum);
+        assert!(department.required);
+        assert_eq!(department.values, ["sales", "support"]);
+        assert!(!config.item_schema.fields["due"].required);
+
+        std::fs::write(&path, "[item_schema.fields.department]\ntype = \"enum\"\n").unwrap();
+        assert!(AppConfig::load_from(&path).is_err());
+        std::fs::write(&path, "[item_schema.fields.cost]\ntype = \"number\"\nvalues = [\"1\"]\n").unwrap();
+        assert!(AppConfig::load_from(&path).is_err());
+    }
+
     #[test]
     fn test_directory_creation() {
         let config = AppConfig::default();
@@ -1,16 +1,17 @@
 use sqlx::{SqlitePool, sqlite::SqlitePoolOptions, Row};
 use std::time::Duration;
 use tracing::{info, error};
+use super::InstrumentedPool;
 use crate::error::{AppError, Result};
 
 #[derive(Clone)]
 pub struct DatabaseManager {
-    pool: SqlitePool,
+    pool: InstrumentedPool,
 }
 
 impl DatabaseManager {
     pub fn new(pool: SqlitePool) -> Self {
-        Self { pool }
+        Self { pool: pool.into() }
     }
 
     pub fn pool(&self) -> &SqlitePool {
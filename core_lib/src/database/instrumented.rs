@@ -0,0 +1,333 @@
+//! Per-request query accounting and slow query logging
+
+use crate::transaction::{self, PoolTransaction, SharedTransaction};
+use futures_util::{future::BoxFuture, stream::{self, BoxStream}, Stream, StreamExt};
+use sqlx::{
+    sqlite::{SqliteQueryResult, SqliteRow, SqliteStatement, SqliteTypeInfo},
+    Describe, Either, Execute, Executor, Sqlite, SqlitePool,
+};
+use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Longest SQL text included in a slow query log line.
+const MAX_LOGGED_SQL_LEN: usize = 500;
+
+/// Threshold in milliseconds above which a single query is logged; zero
+/// disables the log. Set once at startup from [`DatabaseConfig`].
+///
+/// [`DatabaseConfig`]: crate::config::DatabaseConfig
+static SLOW_QUERY_THRESHOLD_MS: AtomicU64 = AtomicU64::new(0);
+
+pub fn set_slow_query_threshold(threshold: Duration) {
+    SLOW_QUERY_THRESHOLD_MS.store(threshold.as_millis() as u64, Ordering::Relaxed);
+}
+
+tokio::task_local! {
+    static QUERY_STATS: Arc<QueryStats>;
+}
+
+/// Queries issued and time spent in the database while serving one request.
+#[derive(Debug, Default)]
+pub struct QueryStats {
+    queries: AtomicU64,
+    db_time_us: AtomicU64,
+}
+
+impl QueryStats {
+    /// Runs `future` with queries made through [`InstrumentedPool`] counted
+    /// against `stats`.
+    pub async fn scope<F: std::future::Future>(stats: Arc<QueryStats>, future: F) -> F::Output {
+        QUERY_STATS.scope(stats, future).await
+    }
+
+    pub fn queries(&self) -> u64 {
+        self.queries.load(Ordering::Relaxed)
+    }
+
+    pub fn db_time(&self) -> Duration {
+        Duration::from_micros(self.db_time_us.load(Ordering::Relaxed))
+    }
+
+    pub fn snapshot(&self) -> QueryStatsSnapshot {
+        QueryStatsSnapshot {
+            queries: self.queries(),
+            db_time: self.db_time(),
+        }
+    }
+
+    fn record(&self, elapsed: Duration) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        self.db_time_us.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Query totals for a finished request, attached to the response so the
+/// access log can report them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct QueryStatsSnapshot {
+    pub queries: u64,
+    pub db_time: Duration,
+}
+
+/// Collapses whitespace, replaces string and numeric literals with `?` and
+/// truncates, so slow query logs never carry values inlined into the SQL.
+pub fn sanitize_sql(sql: &str) -> String {
+    let mut sanitized = String::with_capacity(sql.len().min(MAX_LOGGED_SQL_LEN));
+    let mut chars = sql.chars().peekable();
+    let mut previous_word_char = false;
+
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            // Skip to the closing quote; '' is an escaped quote.
+            while let Some(next) = chars.next() {
+                if next == '\'' && chars.next_if_eq(&'\'').is_none() {
+                    break;
+                }
+            }
+            sanitized.push('?');
+            previous_word_char = false;
+        } else if c.is_ascii_digit() && !previous_word_char {
+            while chars.next_if(|next| next.is_ascii_alphanumeric() || *next == '.').is_some() {}
+            sanitized.push('?');
+            previous_word_char = false;
+        } else if c.is_whitespace() {
+            while chars.next_if(|next| next.is_whitespace()).is_some() {}
+            if !sanitized.is_empty() {
+                sanitized.push(' ');
+            }
+            previous_word_char = false;
+        } else {
+            sanitized.push(c);
+            previous_word_char = c.is_alphanumeric() || c == '_';
+        }
+    }
+
+    let trimmed_len = sanitized.trim_end().len();
+    sanitized.truncate(trimmed_len);
+    if sanitized.len() > MAX_LOGGED_SQL_LEN {
+        let mut end = MAX_LOGGED_SQL_LEN;
+        while !sanitized.is_char_boundary(end) {
+            end -= 1;
+        }
+        sanitized.truncate(end);
+        sanitized.push_str("...");
+    }
+    sanitized
+}
+
+/// Times one query from issue until its result is consumed or dropped.
+struct QueryTimer<'q> {
+    sql: &'q str,
+    start: Instant,
+}
+
+impl<'q> QueryTimer<'q> {
+    fn start(sql: &'q str) -> Self {
+        Self { sql, start: Instant::now() }
+    }
+}
+
+impl Drop for QueryTimer<'_> {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        let _ = QUERY_STATS.try_with(|stats| stats.record(elapsed));
+
+        let threshold_ms = SLOW_QUERY_THRESHOLD_MS.load(Ordering::Relaxed);
+        if threshold_ms > 0 && elapsed >= Duration::from_millis(threshold_ms) {
+            tracing::warn!(
+                duration_ms = elapsed.as_millis() as u64,
+                sql = %sanitize_sql(self.sql),
+                "Slow query"
+            );
+        }
+    }
+}
+
+struct TimedStream<'e, T> {
+    inner: BoxStream<'e, T>,
+    _timer: QueryTimer<'e>,
+}
+
+impl<T> Stream for TimedStream<'_, T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+/// A [`SqlitePool`] whose queries are counted against the current request
+/// and checked against the slow query threshold. Repositories hold one in
+/// place of the plain pool; it derefs to the pool for everything else.
+///
+/// Inside a [request transaction](crate::transaction) on the same database,
+/// queries run in that transaction and [`begin`](Self::begin) opens a
+/// savepoint in it.
+#[derive(Clone, Debug)]
+pub struct InstrumentedPool(SqlitePool);
+
+impl InstrumentedPool {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self(pool)
+    }
+
+    /// Starts a transaction, or a savepoint in the request's transaction.
+    pub async fn begin(&self) -> Result<PoolTransaction, sqlx::Error> {
+        PoolTransaction::begin(&self.0).await
+    }
+}
+
+impl From<SqlitePool> for InstrumentedPool {
+    fn from(pool: SqlitePool) -> Self {
+        Self::new(pool)
+    }
+}
+
+impl Deref for InstrumentedPool {
+    type Target = SqlitePool;
+
+    fn deref(&self) -> &SqlitePool {
+        &self.0
+    }
+}
+
+impl<'p> Executor<'p> for &'_ InstrumentedPool {
+    type Database = Sqlite;
+
+    fn fetch_many<'e, 'q: 'e, E>(
+        self,
+        query: E,
+    ) -> BoxStream<'e, Result<Either<SqliteQueryResult, SqliteRow>, sqlx::Error>>
+    where
+        E: Execute<'q, Sqlite> + 'q,
+    {
+        let timer = QueryTimer::start(query.sql());
+        let inner = match transaction::database(&self.0) {
+            Some(transaction) => fetch_many_in(transaction, query),
+            None => self.0.fetch_many(query),
+        };
+        Box::pin(TimedStream { inner, _timer: timer })
+    }
+
+    fn fetch_optional<'e, 'q: 'e, E>(
+        self,
+        query: E,
+    ) -> BoxFuture<'e, Result<Option<SqliteRow>, sqlx::Error>>
+    where
+        E: Execute<'q, Sqlite> + 'q,
+    {
+        let timer = QueryTimer::start(query.sql());
+        let result = match transaction::database(&self.0) {
+            Some(transaction) => fetch_optional_in(transaction, query),
+            None => self.0.fetch_optional(query),
+        };
+        Box::pin(async move {
+            let result = result.await;
+            drop(timer);
+            result
+        })
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [SqliteTypeInfo],
+    ) -> BoxFuture<'e, Result<SqliteStatement<'q>, sqlx::Error>> {
+        self.0.prepare_with(sql, parameters)
+    }
+
+    fn describe<'e, 'q: 'e>(self, sql: &'q str) -> BoxFuture<'e, Result<Describe<Sqlite>, sqlx::Error>> {
+        self.0.describe(sql)
+    }
+}
+
+/// Runs `query` in the request's transaction. Its rows are collected
+/// before they are handed on, so that the transaction is not held while the
+/// caller consumes them.
+fn fetch_many_in<'e, 'q: 'e, E>(
+    transaction: SharedTransaction,
+    query: E,
+) -> BoxStream<'e, Result<Either<SqliteQueryResult, SqliteRow>, sqlx::Error>>
+where
+    E: Execute<'q, Sqlite> + 'q,
+{
+    Box::pin(
+        stream::once(async move {
+            let mut transaction = transaction.lock_owned().await;
+            let results: Vec<_> = match transaction.as_deref_mut() {
+                Some(conn) => conn.fetch_many(query).collect().await,
+                None => vec![Err(sqlx::Error::PoolClosed)],
+            };
+            stream::iter(results)
+        })
+        .flatten(),
+    )
+}
+
+fn fetch_optional_in<'e, 'q: 'e, E>(
+    transaction: SharedTransaction,
+    query: E,
+) -> BoxFuture<'e, Result<Option<SqliteRow>, sqlx::Error>>
+where
+    E: Execute<'q, Sqlite> + 'q,
+{
+    Box::pin(async move {
+        let mut transaction = transaction.lock_owned().await;
+        match transaction.as_deref_mut() {
+            Some(conn) => conn.fetch_optional(query).await,
+            None => Err(sqlx::Error::PoolClosed),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::Row;
+
+    #[test]
+    fn test_sanitize_sql_strips_literals() {
+        let sql = "SELECT *\n  FROM items\n  WHERE name = 'O''Brien' AND price > 10.5 AND item_2 = ?  ";
+        assert_eq!(
+            sanitize_sql(sql),
+            "SELECT * FROM items WHERE name = ? AND price > ? AND item_2 = ?"
+        );
+
+        let long = format!("SELECT {}", "column_name, ".repeat(100));
+        let sanitized = sanitize_sql(&long);
+        assert_eq!(sanitized.len(), MAX_LOGGED_SQL_LEN + 3);
+        assert!(sanitized.ends_with("..."));
+    }
+
+    #[tokio::test]
+    async fn test_queries_are_counted_per_scope() {
+        let pool = InstrumentedPool::new(
+            sqlx::sqlite::SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect("sqlite::memory:")
+                .await
+                .unwrap(),
+        );
+
+        // Outside a scope queries still run, they just are not attributed.
+        sqlx::query("CREATE TABLE t (v INTEGER)").execute(&pool).await.unwrap();
+
+        let stats = Arc::new(QueryStats::default());
+        let total = QueryStats::scope(stats.clone(), async {
+            sqlx::query("INSERT INTO t (v) VALUES (1), (2)").execute(&pool).await.unwrap();
+            let rows = sqlx::query("SELECT v FROM t").fetch_all(&pool).await.unwrap();
+            let row = sqlx::query("SELECT SUM(v) AS total FROM t").fetch_one(&pool).await.unwrap();
+            assert_eq!(rows.len(), 2);
+            row.get::<i64, _>("total")
+        })
+        .await;
+
+        assert_eq!(total, 3);
+        assert_eq!(stats.queries(), 3);
+        assert!(stats.db_time() > Duration::ZERO);
+    }
+}
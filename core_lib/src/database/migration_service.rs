@@ -2,6 +2,7 @@ use crate::{
     database::{ItemRepository, CreateItemInput, Repository},
     store::{DataStore, Item},
     error::Result,
+    item_metadata::Metadata,
 };
 use tracing::{info, warn, error};
 
@@ -73,7 +74,7 @@ impl MigrationService {
             name: item.name.clone(),
             description: item.description.clone(),
             tags: item.tags.clone(),
-            metadata: item.metadata.clone(),
+            metadata: item.metadata.as_ref().map(Metadata::to_value),
             created_by: None,
         };
 
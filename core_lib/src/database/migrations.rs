@@ -302,6 +302,383 @@ impl MigrationManager {
                     "#.to_string(),
                 ],
             },
+            Migration {
+                version: 7,
+                name: "create_user_sessions_table".to_string(),
+                checksum: "user_sessions_v1".to_string(),
+                sql_statements: vec![
+                    r#"
+                    CREATE TABLE IF NOT EXISTS user_sessions (
+                        id TEXT PRIMARY KEY,
+                        user_id INTEGER NOT NULL,
+                        user_agent TEXT,
+                        ip_address TEXT,
+                        created_at TEXT NOT NULL,
+                        last_used_at TEXT NOT NULL,
+                        revoked_at TEXT,
+                        FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+                    )
+                    "#.to_string(),
+                    r#"
+                    CREATE INDEX IF NOT EXISTS idx_user_sessions_user_id ON user_sessions(user_id)
+                    "#.to_string(),
+                ],
+            },
+            Migration {
+                version: 8,
+                name: "create_health_transitions_table".to_string(),
+                checksum: "health_transitions_v1".to_string(),
+                sql_statements: vec![
+                    r#"
+                    CREATE TABLE IF NOT EXISTS health_transitions (
+                        id INTEGER PRIMARY KEY AUTOINCREMENT,
+                        component TEXT NOT NULL,
+                        from_status TEXT NOT NULL,
+                        to_status TEXT NOT NULL,
+                        message TEXT NOT NULL,
+                        error TEXT,
+                        previous_state_duration_ms INTEGER NOT NULL,
+                        occurred_at TEXT NOT NULL
+                    )
+                    "#.to_string(),
+                    r#"
+                    CREATE INDEX IF NOT EXISTS idx_health_transitions_component ON health_transitions(component, occurred_at)
+                    "#.to_string(),
+                    r#"
+                    CREATE INDEX IF NOT EXISTS idx_health_transitions_occurred_at ON health_transitions(occurred_at)
+                    "#.to_string(),
+                ],
+            },
+            Migration {
+                version: 9,
+                name: "create_traffic_hourly_table".to_string(),
+                checksum: "traffic_hourly_v1".to_string(),
+                sql_statements: vec![
+                    r#"
+                    CREATE TABLE IF NOT EXISTS traffic_hourly (
+                        hour_start TEXT PRIMARY KEY,
+                        requests INTEGER NOT NULL,
+                        errors INTEGER NOT NULL,
+                        total_duration_ms INTEGER NOT NULL,
+                        updated_at TEXT NOT NULL
+                    )
+                    "#.to_string(),
+                ],
+            },
+            Migration {
+                version: 10,
+                name: "create_webhooks_tables".to_string(),
+                checksum: "webhooks_v1".to_string(),
+                sql_statements: vec![
+                    r#"
+                    CREATE TABLE IF NOT EXISTS webhooks (
+                        id INTEGER PRIMARY KEY AUTOINCREMENT,
+                        url TEXT NOT NULL,
+                        secret TEXT NOT NULL,
+                        events TEXT NOT NULL,
+                        tags TEXT NOT NULL,
+                        enabled INTEGER NOT NULL DEFAULT 1,
+                        consecutive_failures INTEGER NOT NULL DEFAULT 0,
+                        created_by INTEGER,
+                        created_at TEXT NOT NULL,
+                        disabled_at TEXT
+                    )
+                    "#.to_string(),
+                    r#"
+                    CREATE TABLE IF NOT EXISTS webhook_deliveries (
+                        id INTEGER PRIMARY KEY AUTOINCREMENT,
+                        webhook_id INTEGER NOT NULL,
+                        delivery_id TEXT NOT NULL,
+                        event TEXT NOT NULL,
+                        attempt INTEGER NOT NULL,
+                        status_code INTEGER,
+                        success INTEGER NOT NULL,
+                        error TEXT,
+                        duration_ms INTEGER NOT NULL,
+                        attempted_at TEXT NOT NULL,
+                        FOREIGN KEY (webhook_id) REFERENCES webhooks(id) ON DELETE CASCADE
+                    )
+                    "#.to_string(),
+                    r#"
+                    CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_webhook_id ON webhook_deliveries(webhook_id, id)
+                    "#.to_string(),
+                ],
+            },
+            Migration {
+                version: 11,
+                name: "add_namespaces".to_string(),
+                checksum: "namespaces_v1".to_string(),
+                sql_statements: vec![
+                    "ALTER TABLE items ADD COLUMN namespace TEXT NOT NULL DEFAULT 'default'".to_string(),
+                    "ALTER TABLE files ADD COLUMN namespace TEXT NOT NULL DEFAULT 'default'".to_string(),
+                    "ALTER TABLE jobs ADD COLUMN namespace TEXT NOT NULL DEFAULT 'default'".to_string(),
+                    "ALTER TABLE users ADD COLUMN namespace TEXT NOT NULL DEFAULT 'default'".to_string(),
+                    "ALTER TABLE webhooks ADD COLUMN namespace TEXT NOT NULL DEFAULT 'default'".to_string(),
+                    "CREATE INDEX IF NOT EXISTS idx_items_namespace ON items(namespace)".to_string(),
+                    "CREATE INDEX IF NOT EXISTS idx_files_namespace ON files(namespace)".to_string(),
+                    "CREATE INDEX IF NOT EXISTS idx_jobs_namespace ON jobs(namespace)".to_string(),
+                ],
+            },
+            Migration {
+                version: 12,
+                name: "create_item_changes_table".to_string(),
+                checksum: "item_changes_v1".to_string(),
+                sql_statements: vec![
+                    r#"
+                    CREATE TABLE IF NOT EXISTS item_changes (
+                        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                        op TEXT NOT NULL,
+                        item_id INTEGER NOT NULL,
+                        item TEXT,
+                        namespace TEXT NOT NULL DEFAULT 'default',
+                        changed_at TEXT NOT NULL
+                    )
+                    "#.to_string(),
+                    "CREATE INDEX IF NOT EXISTS idx_item_changes_changed_at ON item_changes(changed_at)".to_string(),
+                ],
+            },
+            Migration {
+                version: 13,
+                name: "add_item_versions".to_string(),
+                checksum: "item_versions_v1".to_string(),
+                sql_statements: vec![
+                    "ALTER TABLE items ADD COLUMN version INTEGER NOT NULL DEFAULT 1".to_string(),
+                ],
+            },
+            Migration {
+                version: 14,
+                name: "add_item_trash".to_string(),
+                checksum: "item_trash_v1".to_string(),
+                sql_statements: vec![
+                    "ALTER TABLE items ADD COLUMN deleted_at TEXT".to_string(),
+                    "CREATE INDEX IF NOT EXISTS idx_items_deleted_at ON items(deleted_at)".to_string(),
+                ],
+            },
+            Migration {
+                version: 15,
+                name: "add_fts_prefix_index".to_string(),
+                checksum: "fts_prefix_v1".to_string(),
+                sql_statements: vec![
+                    "DROP TABLE items_fts".to_string(),
+                    r#"
+                    CREATE VIRTUAL TABLE items_fts USING fts5(
+                        name,
+                        description,
+                        content='items',
+                        content_rowid='id',
+                        prefix='2 3 4'
+                    )
+                    "#.to_string(),
+                    "INSERT INTO items_fts(items_fts) VALUES('rebuild')".to_string(),
+                ],
+            },
+            Migration {
+                version: 16,
+                name: "add_fts_tags_column".to_string(),
+                checksum: "fts_tags_v1".to_string(),
+                sql_statements: vec![
+                    "DROP TRIGGER IF EXISTS items_fts_insert".to_string(),
+                    "DROP TRIGGER IF EXISTS items_fts_delete".to_string(),
+                    "DROP TRIGGER IF EXISTS items_fts_update".to_string(),
+                    "DROP TABLE items_fts".to_string(),
+                    r#"
+                    CREATE VIRTUAL TABLE items_fts USING fts5(
+                        name,
+                        description,
+                        tags,
+                        content='items',
+                        content_rowid='id',
+                        prefix='2 3 4'
+                    )
+                    "#.to_string(),
+                    r#"
+                    CREATE TRIGGER items_fts_insert AFTER INSERT ON items BEGIN
+                        INSERT INTO items_fts(rowid, name, description, tags)
+                        VALUES (new.id, new.name, new.description, new.tags);
+                    END
+                    "#.to_string(),
+                    r#"
+                    CREATE TRIGGER items_fts_delete AFTER DELETE ON items BEGIN
+                        INSERT INTO items_fts(items_fts, rowid, name, description, tags)
+                        VALUES('delete', old.id, old.name, old.description, old.tags);
+                    END
+                    "#.to_string(),
+                    r#"
+                    CREATE TRIGGER items_fts_update AFTER UPDATE ON items BEGIN
+                        INSERT INTO items_fts(items_fts, rowid, name, description, tags)
+                        VALUES('delete', old.id, old.name, old.description, old.tags);
+                        INSERT INTO items_fts(rowid, name, description, tags)
+                        VALUES (new.id, new.name, new.description, new.tags);
+                    END
+                    "#.to_string(),
+                    "INSERT INTO items_fts(items_fts) VALUES('rebuild')".to_string(),
+                ],
+            },
+            Migration {
+                version: 17,
+                name: "add_file_hashes".to_string(),
+                checksum: "file_hashes_v1".to_string(),
+                sql_statements: vec![
+                    "ALTER TABLE files ADD COLUMN sha256 TEXT".to_string(),
+                ],
+            },
+            Migration {
+                version: 18,
+                name: "add_file_missing_at".to_string(),
+                checksum: "file_missing_at_v1".to_string(),
+                sql_statements: vec![
+                    "ALTER TABLE files ADD COLUMN missing_at TEXT".to_string(),
+                ],
+            },
+            Migration {
+                version: 19,
+                name: "add_job_result_files".to_string(),
+                checksum: "job_result_files_v1".to_string(),
+                sql_statements: vec![
+                    "ALTER TABLE jobs ADD COLUMN result_size INTEGER".to_string(),
+                    "ALTER TABLE jobs ADD COLUMN result_file_id TEXT".to_string(),
+                    "ALTER TABLE jobs ADD COLUMN result_content_type TEXT".to_string(),
+                ],
+            },
+            Migration {
+                version: 20,
+                name: "add_user_profiles".to_string(),
+                checksum: "user_profiles_v1".to_string(),
+                sql_statements: vec![
+                    "ALTER TABLE users ADD COLUMN display_name TEXT".to_string(),
+                    "ALTER TABLE users ADD COLUMN avatar_file_id TEXT".to_string(),
+                    "ALTER TABLE users ADD COLUMN timezone TEXT".to_string(),
+                    "ALTER TABLE users ADD COLUMN notification_preferences TEXT".to_string(),
+                    "ALTER TABLE users ADD COLUMN pending_email TEXT".to_string(),
+                    "ALTER TABLE users ADD COLUMN pending_email_token TEXT".to_string(),
+                    "ALTER TABLE users ADD COLUMN pending_email_expires_at TEXT".to_string(),
+                ],
+            },
+            Migration {
+                version: 21,
+                name: "add_email_verification".to_string(),
+                checksum: "email_verification_v1".to_string(),
+                sql_statements: vec![
+                    // Accounts that predate verification count as verified.
+                    "ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT 1".to_string(),
+                    "ALTER TABLE users ADD COLUMN verification_token TEXT".to_string(),
+                    "ALTER TABLE users ADD COLUMN verification_expires_at TEXT".to_string(),
+                    "CREATE INDEX IF NOT EXISTS idx_users_verification_token ON users(verification_token)".to_string(),
+                ],
+            },
+            Migration {
+                version: 22,
+                name: "create_refresh_tokens_table".to_string(),
+                checksum: "refresh_tokens_v1".to_string(),
+                sql_statements: vec![
+                    r#"
+                    CREATE TABLE IF NOT EXISTS refresh_tokens (
+                        id TEXT PRIMARY KEY,
+                        session_id TEXT NOT NULL,
+                        parent_id TEXT,
+                        issued_at TEXT NOT NULL,
+                        rotated_at TEXT,
+                        FOREIGN KEY (session_id) REFERENCES user_sessions(id) ON DELETE CASCADE
+                    )
+                    "#.to_string(),
+                    "CREATE INDEX IF NOT EXISTS idx_refresh_tokens_session_id ON refresh_tokens(session_id)".to_string(),
+                ],
+            },
+            Migration {
+                version: 23,
+                name: "create_item_comments_table".to_string(),
+                checksum: "item_comments_v1".to_string(),
+                sql_statements: vec![
+                    r#"
+                    CREATE TABLE IF NOT EXISTS item_comments (
+                        id INTEGER PRIMARY KEY AUTOINCREMENT,
+                        item_id INTEGER NOT NULL,
+                        author_id INTEGER,
+                        author TEXT NOT NULL,
+                        body TEXT NOT NULL,
+                        created_at TEXT NOT NULL,
+                        edited_at TEXT,
+                        FOREIGN KEY (item_id) REFERENCES items(id) ON DELETE CASCADE,
+                        FOREIGN KEY (author_id) REFERENCES users(id) ON DELETE SET NULL
+                    )
+                    "#.to_string(),
+                    "CREATE INDEX IF NOT EXISTS idx_item_comments_item_id ON item_comments(item_id, created_at)".to_string(),
+                ],
+            },
+            Migration {
+                version: 24,
+                name: "create_files_fts_table".to_string(),
+                checksum: "files_fts_v1".to_string(),
+                sql_statements: vec![
+                    "ALTER TABLE files ADD COLUMN content_index TEXT".to_string(),
+                    "CREATE VIRTUAL TABLE IF NOT EXISTS files_fts USING fts5(file_id UNINDEXED, content)".to_string(),
+                ],
+            },
+            Migration {
+                version: 25,
+                name: "create_job_attempts_table".to_string(),
+                checksum: "job_attempts_v1".to_string(),
+                sql_statements: vec![
+                    "ALTER TABLE jobs ADD COLUMN queued_at TEXT".to_string(),
+                    r#"
+                    CREATE TABLE IF NOT EXISTS job_attempts (
+                        job_id TEXT NOT NULL,
+                        attempt INTEGER NOT NULL,
+                        queued_at TEXT NOT NULL,
+                        started_at TEXT NOT NULL,
+                        finished_at TEXT,
+                        outcome TEXT NOT NULL,
+                        error TEXT,
+                        queue_wait_ms INTEGER NOT NULL,
+                        duration_ms INTEGER,
+                        phases TEXT NOT NULL DEFAULT '{}',
+                        PRIMARY KEY (job_id, attempt)
+                    )
+                    "#.to_string(),
+                ],
+            },
+            Migration {
+                version: 26,
+                name: "create_feature_switches_table".to_string(),
+                checksum: "feature_switches_v1".to_string(),
+                sql_statements: vec![
+                    r#"
+                    CREATE TABLE IF NOT EXISTS feature_switches (
+                        name TEXT PRIMARY KEY,
+                        enabled BOOLEAN NOT NULL,
+                        changed_by TEXT NOT NULL,
+                        changed_at TEXT NOT NULL
+                    )
+                    "#.to_string(),
+                ],
+            },
+            Migration {
+                version: 27,
+                name: "create_public_assets_table".to_string(),
+                checksum: "public_assets_v1".to_string(),
+                sql_statements: vec![
+                    r#"
+                    CREATE TABLE IF NOT EXISTS public_assets (
+                        file_id TEXT PRIMARY KEY,
+                        sha256 TEXT NOT NULL,
+                        extension TEXT NOT NULL,
+                        content_type TEXT NOT NULL,
+                        published_by INTEGER NOT NULL,
+                        published_at TEXT NOT NULL,
+                        unpublished_at TEXT
+                    )
+                    "#.to_string(),
+                    "CREATE INDEX IF NOT EXISTS idx_public_assets_sha256 ON public_assets(sha256)".to_string(),
+                ],
+            },
+            Migration {
+                version: 28,
+                name: "add_list_preferences".to_string(),
+                checksum: "list_preferences_v1".to_string(),
+                sql_statements: vec![
+                    "ALTER TABLE users ADD COLUMN list_preferences TEXT".to_string(),
+                ],
+            },
         ]
     }
 
@@ -375,6 +752,6 @@ mod tests {
         assert_eq!(table_count, 4);
         
         let history = migration_manager.get_migration_history().await.unwrap();
-        assert_eq!(history.len(), 6);
+        assert_eq!(history.len(), 28);
     }
 }
\ No newline at end of file
@@ -1,11 +1,21 @@
 pub mod connection;
+pub mod instrumented;
 pub mod migrations;
 pub mod models;
 pub mod repository;
+pub mod sort;
 pub mod migration_service;
 
 pub use connection::{DatabaseManager, get_database_pool};
+pub use instrumented::{InstrumentedPool, QueryStats, QueryStatsSnapshot};
 pub use migrations::{MigrationManager, run_migrations};
 pub use models::*;
-pub use repository::{Repository, ItemRepository, UserRepository, ListParams, SortOrder, CreateItemInput, UpdateItemInput, CreateUserInput, UpdateUserInput};
-pub use migration_service::{MigrationService, MigrationResult, MigrationVerification};
\ No newline at end of file
+pub use repository::{Repository, ItemRepository, UserRepository, ListParams, SortOrder, ITEM_SORT, USER_SORT, CreateItemInput, UpdateItemInput, CreateUserInput, UpdateUserInput};
+pub use sort::{SortFields, SortKey, SortSpec};
+pub use migration_service::{MigrationService, MigrationResult, MigrationVerification};
+
+/// `text` escaped for a `LIKE` pattern with `ESCAPE '\'`, so that `%`
+/// and `_` in it match themselves.
+pub(crate) fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
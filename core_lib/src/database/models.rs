@@ -12,6 +12,7 @@ pub struct DbItem {
     pub tags: String,
     pub metadata: String,
     pub created_by: Option<i64>,
+    pub version: i64,
 }
 
 impl DbItem {
@@ -23,7 +24,8 @@ impl DbItem {
             created_at: self.created_at,
             updated_at: self.updated_at,
             tags: serde_json::from_str(&self.tags).unwrap_or_default(),
-            metadata: serde_json::from_str(&self.metadata).ok(),
+            metadata: crate::item_metadata::Metadata::from_stored(self.metadata.clone()).ok(),
+            version: self.version as u64,
         }
     }
 
@@ -36,9 +38,10 @@ impl DbItem {
             updated_at: item.updated_at,
             tags: serde_json::to_string(&item.tags).unwrap_or_else(|_| "[]".to_string()),
             metadata: item.metadata.as_ref()
-                .map(|m| serde_json::to_string(m).unwrap_or_else(|_| "{}".to_string()))
+                .map(|m| m.as_str().to_string())
                 .unwrap_or_else(|| "{}".to_string()),
             created_by,
+            version: item.version as i64,
         }
     }
 }
@@ -53,6 +56,11 @@ pub struct DbUser {
     pub created_at: DateTime<Utc>,
     pub last_login: Option<DateTime<Utc>>,
     pub is_active: bool,
+    pub display_name: Option<String>,
+    pub avatar_file_id: Option<String>,
+    pub timezone: Option<String>,
+    /// JSON, or `None` for the defaults.
+    pub notification_preferences: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -242,13 +250,15 @@ mod tests {
             created_at: Utc::now(),
             updated_at: Utc::now(),
             tags: vec!["tag1".to_string(), "tag2".to_string()],
-            metadata: Some(serde_json::json!({"key": "value"})),
+            metadata: Some(serde_json::json!({"key": "value"}).into()),
+            version: 3,
         };
 
         let db_item = DbItem::from_api_item(&api_item, Some(1));
         assert_eq!(db_item.name, api_item.name);
         assert_eq!(db_item.description, api_item.description);
         assert_eq!(db_item.created_by, Some(1));
+        assert_eq!(db_item.version, 3);
 
         let converted_back = db_item.to_api_item();
         assert_eq!(converted_back.name, api_item.name);
@@ -1,9 +1,58 @@
 use async_trait::async_trait;
-use sqlx::{SqlitePool, Row};
-use chrono::Utc;
+use sqlx::{SqliteConnection, SqlitePool, Row};
+use chrono::{DateTime, Utc};
+use super::InstrumentedPool;
+use crate::changes::{self, ChangeOp, ChangePage, ItemChange};
+use crate::config::{ChangeFeedConfig, DEFAULT_PAGE_SIZE};
 use crate::error::{AppError, Result};
 use crate::database::models::*;
+use crate::database::sort::{SortFields, SortSpec};
+use crate::models::items::{
+    daily_counts, CreatorCount, DescriptionStats, ItemStats, MetadataStats, StatsBreakdowns, TagCount, TagRewrite,
+    TagStats, VersionConflict, STATS_DAILY_DAYS,
+};
+use crate::item_metadata::Metadata;
+use crate::item_names::{DuplicateName, NameRule};
+use crate::item_transform::{MetadataBatch, MetadataOutcome};
 use crate::store::Item;
+use crate::transaction::PoolTransaction;
+use crate::trash::PurgeReport;
+
+/// Table-valued source of each item's tags, for joining against `items`.
+/// Rows with malformed tag JSON contribute no tags.
+const ITEM_TAGS_SOURCE: &str = "json_each(CASE WHEN json_valid(items.tags) THEN items.tags ELSE '[]' END) AS tag";
+
+/// What item listings can be sorted on.
+pub const ITEM_SORT: SortFields = SortFields {
+    fields: &[
+        ("id", "id"),
+        ("name", "name"),
+        ("created_at", "created_at"),
+        ("updated_at", "updated_at"),
+        ("version", "version"),
+    ],
+    default: ("created_at", SortOrder::Desc),
+    tiebreak: "id",
+};
+
+/// What user listings can be sorted on.
+pub const USER_SORT: SortFields = SortFields {
+    fields: &[
+        ("id", "id"),
+        ("username", "username"),
+        ("email", "email"),
+        ("role", "role"),
+        ("created_at", "created_at"),
+        ("last_login", "last_login"),
+    ],
+    default: ("created_at", SortOrder::Desc),
+    tiebreak: "id",
+};
+
+/// Restricts a query on `items` to the current namespace, leaving out items
+/// in the trash. Binds [`tenancy::current`](crate::tenancy::current), which
+/// is `NULL` outside a request so that every namespace matches.
+const ITEM_NAMESPACE_FILTER: &str = "items.namespace = COALESCE(?, items.namespace) AND items.deleted_at IS NULL";
 
 #[async_trait]
 pub trait Repository<T> {
@@ -23,22 +72,21 @@ pub trait Repository<T> {
 pub struct ListParams {
     pub limit: Option<i64>,
     pub offset: Option<i64>,
-    pub sort_by: Option<String>,
-    pub sort_order: Option<SortOrder>,
+    /// The listing's own default order when `None`.
+    pub sort: Option<SortSpec>,
 }
 
 impl Default for ListParams {
     fn default() -> Self {
         Self {
-            limit: Some(50),
+            limit: Some(DEFAULT_PAGE_SIZE as i64),
             offset: Some(0),
-            sort_by: None,
-            sort_order: Some(SortOrder::Asc),
+            sort: None,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SortOrder {
     Asc,
     Desc,
@@ -55,31 +103,62 @@ impl std::fmt::Display for SortOrder {
 
 #[derive(Clone)]
 pub struct ItemRepository {
-    pool: SqlitePool,
+    pool: InstrumentedPool,
+    change_feed: ChangeFeedConfig,
 }
 
 impl ItemRepository {
     pub fn new(pool: SqlitePool) -> Self {
-        Self { pool }
+        Self {
+            pool: pool.into(),
+            change_feed: ChangeFeedConfig::default(),
+        }
+    }
+
+    /// Retention of the `item_changes` log written alongside every item
+    /// change.
+    pub fn with_change_feed(mut self, config: &ChangeFeedConfig) -> Self {
+        self.change_feed = config.clone();
+        self
     }
 
-    pub async fn begin_transaction(&self) -> Result<sqlx::Transaction<'_, sqlx::Sqlite>> {
+    pub async fn begin_transaction(&self) -> Result<PoolTransaction> {
         self.pool.begin().await.map_err(AppError::from)
     }
 
+    /// Opens a transaction for an item write with the database's write lock
+    /// already held. A deferred transaction only asks for the lock at its
+    /// first write, and a write to `items` first reads the full-text index
+    /// through its triggers, and SQLite refuses the lock outright, without
+    /// waiting out the busy timeout, when another connection has written
+    /// since that read. A write to `item_changes` that changes nothing takes the
+    /// lock before anything is read.
+    async fn begin_write(&self) -> Result<PoolTransaction> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM item_changes WHERE 0").execute(&mut *tx).await?;
+        Ok(tx)
+    }
+
+    /// The database items are kept in, for opening a
+    /// [request transaction](crate::transaction) on it.
+    pub(crate) fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
     pub async fn search(&self, query: &str, params: ListParams) -> Result<Vec<Item>> {
-        let limit = params.limit.unwrap_or(50);
+        let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE as i64);
         let offset = params.offset.unwrap_or(0);
 
         let rows = sqlx::query(r#"
-            SELECT i.id, i.name, i.description, i.created_at, i.updated_at, i.tags, i.metadata, i.created_by
+            SELECT i.id, i.name, i.description, i.created_at, i.updated_at, i.tags, i.metadata, i.created_by, i.version
             FROM items i
             JOIN items_fts fts ON i.id = fts.rowid
-            WHERE items_fts MATCH ?
-            ORDER BY rank
+            WHERE items_fts MATCH ? AND i.namespace = COALESCE(?, i.namespace) AND i.deleted_at IS NULL
+            ORDER BY rank, i.id
             LIMIT ? OFFSET ?
         "#)
         .bind(query)
+        .bind(crate::tenancy::current())
         .bind(limit)
         .bind(offset)
         .fetch_all(&self.pool)
@@ -97,6 +176,7 @@ impl ItemRepository {
                 tags: row.try_get("tags").unwrap_or_else(|_| "[]".to_string()),
                 metadata: row.try_get("metadata").unwrap_or_else(|_| "{}".to_string()),
                 created_by: row.try_get("created_by").unwrap_or(None),
+                version: row.try_get("version").unwrap_or(1),
             };
             items.push(db_item.to_api_item());
         }
@@ -104,26 +184,28 @@ impl ItemRepository {
         Ok(items)
     }
 
+    /// Items carrying any of `tags`, in id order.
     pub async fn get_by_tags(&self, tags: &[String], params: ListParams) -> Result<Vec<Item>> {
-        let limit = params.limit.unwrap_or(50);
-        let offset = params.offset.unwrap_or(0);
-
-        let tag_conditions: Vec<String> = tags.iter()
-            .map(|tag| format!("JSON_EXTRACT(tags, '$[*]') LIKE '%{}%'", tag))
-            .collect();
-        let where_clause = tag_conditions.join(" OR ");
+        if tags.is_empty() {
+            return Ok(Vec::new());
+        }
 
         let query = format!(r#"
-            SELECT id, name, description, created_at, updated_at, tags, metadata, created_by
+            SELECT id, name, description, created_at, updated_at, tags, metadata, created_by, version
             FROM items
-            WHERE {}
-            ORDER BY created_at DESC
+            WHERE EXISTS (SELECT 1 FROM {} WHERE tag.value IN ({})) AND {}
+            ORDER BY id
             LIMIT ? OFFSET ?
-        "#, where_clause);
+        "#, ITEM_TAGS_SOURCE, vec!["?"; tags.len()].join(", "), ITEM_NAMESPACE_FILTER);
 
-        let rows = sqlx::query(&query)
-            .bind(limit)
-            .bind(offset)
+        let mut query = sqlx::query(&query);
+        for tag in tags {
+            query = query.bind(tag);
+        }
+        let rows = query
+            .bind(crate::tenancy::current())
+            .bind(params.limit.unwrap_or(-1))
+            .bind(params.offset.unwrap_or(0))
             .fetch_all(&self.pool)
             .await
             .map_err(AppError::from)?;
@@ -139,6 +221,7 @@ impl ItemRepository {
                 tags: row.try_get("tags").unwrap_or_else(|_| "[]".to_string()),
                 metadata: row.try_get("metadata").unwrap_or_else(|_| "{}".to_string()),
                 created_by: row.try_get("created_by").unwrap_or(None),
+                version: row.try_get("version").unwrap_or(1),
             };
             items.push(db_item.to_api_item());
         }
@@ -146,8 +229,398 @@ impl ItemRepository {
         Ok(items)
     }
 
-    async fn create_item_internal(&self, input: &CreateItemInput) -> Result<Item> {
+    /// Number of items carrying `tag`.
+    pub async fn count_by_tag(&self, tag: &str) -> Result<u64> {
+        let count: i64 = sqlx::query_scalar(&format!(
+            "SELECT COUNT(DISTINCT items.id) FROM items, {} WHERE tag.value = ? AND {}",
+            ITEM_TAGS_SOURCE, ITEM_NAMESPACE_FILTER
+        ))
+        .bind(tag)
+        .bind(crate::tenancy::current())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count.max(0) as u64)
+    }
+
+    /// The live item other than `exclude` whose name clashes with `name`
+    /// under `rule`. A new item is checked against the current namespace.
+    pub async fn find_by_name(&self, rule: &NameRule, name: &str, exclude: Option<i64>) -> Result<Option<i64>> {
+        let id = sqlx::query_scalar(&rule.lookup_query())
+            .bind(name)
+            .bind(exclude)
+            .bind(crate::tenancy::current_or_default())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(id)
+    }
+
+    /// Creates the unique index for `rule` and drops those of other rules.
+    /// Returns false, leaving the index out, when stored items already
+    /// break the rule.
+    pub async fn ensure_name_index(&self, rule: &NameRule) -> Result<bool> {
+        for drop in rule.drop_other_indexes() {
+            sqlx::query(&drop).execute(&self.pool).await?;
+        }
+        let Some(create) = rule.create_index() else {
+            return Ok(true);
+        };
+
+        match sqlx::query(&create).execute(&self.pool).await.map_err(AppError::from) {
+            Ok(_) => Ok(true),
+            Err(AppError::Conflict(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Live items sharing a name under `rule`, in every namespace.
+    pub async fn duplicate_names(&self, rule: &NameRule) -> Result<Vec<DuplicateName>> {
+        let rows = sqlx::query(&rule.duplicates_query()).fetch_all(&self.pool).await?;
+
+        rows.iter()
+            .map(|row| {
+                let ids: String = row.try_get("ids")?;
+                let mut item_ids: Vec<u64> = ids.split(',').filter_map(|id| id.parse().ok()).collect();
+                item_ids.sort_unstable();
+                Ok(DuplicateName {
+                    name: row.try_get("name")?,
+                    namespace: row.try_get("namespace")?,
+                    item_ids,
+                })
+            })
+            .collect()
+    }
+
+    /// Tags in use with the number of items carrying each, most used first,
+    /// counted with one grouped query.
+    pub async fn tag_counts(&self, limit: Option<usize>) -> Result<Vec<TagCount>> {
+        let rows = sqlx::query(&format!(
+            r#"
+            SELECT tag.value AS tag, COUNT(*) AS count
+            FROM items, {}
+            WHERE {}
+            GROUP BY tag.value
+            ORDER BY count DESC, tag.value
+            LIMIT ?
+            "#,
+            ITEM_TAGS_SOURCE, ITEM_NAMESPACE_FILTER
+        ))
+        .bind(crate::tenancy::current())
+        .bind(limit.map_or(-1, |limit| limit as i64))
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| -> Result<TagCount> {
+                Ok(TagCount {
+                    tag: row.try_get("tag")?,
+                    count: row.try_get::<i64, _>("count")?.max(0) as u64,
+                })
+            })
+            .collect()
+    }
+
+    /// Tags in use starting with `prefix`, ignoring case, most used first.
+    pub async fn tags_with_prefix(&self, prefix: &str, limit: usize) -> Result<Vec<TagCount>> {
+        let pattern = format!("{}%", super::escape_like(&prefix.to_lowercase()));
+        let rows = sqlx::query(&format!(
+            r#"
+            SELECT tag.value AS tag, COUNT(*) AS count
+            FROM items, {}
+            WHERE {} AND lower(tag.value) LIKE ? ESCAPE '\'
+            GROUP BY tag.value
+            ORDER BY count DESC, tag.value
+            LIMIT ?
+            "#,
+            ITEM_TAGS_SOURCE, ITEM_NAMESPACE_FILTER
+        ))
+        .bind(crate::tenancy::current())
+        .bind(pattern)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| -> Result<TagCount> {
+                Ok(TagCount {
+                    tag: row.try_get("tag")?,
+                    count: row.try_get::<i64, _>("count")?.max(0) as u64,
+                })
+            })
+            .collect()
+    }
+
+    /// Applies `rewrite` to every item in the current namespace carrying one
+    /// of its source tags, in a single transaction. Returns the changed items
+    /// as rewritten; on a dry run nothing is written and they are returned
+    /// as they would be.
+    pub async fn rewrite_tags(&self, rewrite: &TagRewrite, dry_run: bool) -> Result<Vec<Item>> {
+        let tag_value = if rewrite.case_insensitive() { "lower(tag.value)" } else { "tag.value" };
+        let placeholders = vec!["?"; rewrite.sources().len()].join(", ");
+        let select = format!(
+            r#"
+            SELECT id, name, description, created_at, updated_at, tags, metadata, created_by, version, namespace
+            FROM items
+            WHERE {} AND EXISTS (SELECT 1 FROM {} WHERE {} IN ({}))
+            ORDER BY id
+            "#,
+            ITEM_NAMESPACE_FILTER, ITEM_TAGS_SOURCE, tag_value, placeholders
+        );
+
+        let mut tx = self.begin_write().await?;
+        let mut query = sqlx::query(&select).bind(crate::tenancy::current());
+        for source in rewrite.sources() {
+            query = query.bind(if rewrite.case_insensitive() {
+                source.to_ascii_lowercase()
+            } else {
+                source.clone()
+            });
+        }
+        let rows = query.fetch_all(&mut *tx).await?;
+
         let now = Utc::now();
+        let mut changed = Vec::new();
+        for row in &rows {
+            let mut item = item_from_row(row);
+            let Some(tags) = rewrite.apply(&item.tags) else {
+                continue;
+            };
+            if !dry_run {
+                let tags_json = serde_json::to_string(&tags)?;
+                sqlx::query("UPDATE items SET tags = ?, updated_at = ?, version = version + 1 WHERE id = ?")
+                    .bind(&tags_json)
+                    .bind(now)
+                    .bind(item.id as i64)
+                    .execute(&mut *tx)
+                    .await?;
+                item.updated_at = now;
+                item.tags = tags;
+                item.version += 1;
+                let namespace: String = row.try_get("namespace")?;
+                self.record_change(&mut tx, ChangeOp::Updated, item.id as i64, Some(&item), &namespace)
+                    .await?;
+            } else {
+                item.tags = tags;
+            }
+            changed.push(item);
+        }
+
+        if dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        Ok(changed)
+    }
+
+    /// Runs `edit` over up to `limit` items in the current namespace with
+    /// ids above `after_id`, in one transaction, and stores the metadata it
+    /// changes. Each item is written only if still at the version read, so
+    /// a concurrent write is never overwritten. On a dry run nothing is
+    /// written.
+    pub async fn transform_metadata(
+        &self,
+        after_id: i64,
+        limit: i64,
+        dry_run: bool,
+        edit: &(dyn Fn(&Item) -> MetadataOutcome + Send + Sync),
+    ) -> Result<MetadataBatch> {
+        let select = format!(
+            r#"
+            SELECT id, name, description, created_at, updated_at, tags, metadata, created_by, version, namespace
+            FROM items
+            WHERE {} AND id > ?
+            ORDER BY id
+            LIMIT ?
+            "#,
+            ITEM_NAMESPACE_FILTER
+        );
+
+        let mut tx = self.begin_write().await?;
+        let rows = sqlx::query(&select)
+            .bind(crate::tenancy::current())
+            .bind(after_id)
+            .bind(limit)
+            .fetch_all(&mut *tx)
+            .await?;
+
+        let now = Utc::now();
+        let mut batch = MetadataBatch {
+            scanned: rows.len(),
+            ..MetadataBatch::default()
+        };
+        for row in &rows {
+            let before = item_from_row(row);
+            batch.last_id = Some(before.id);
+            let metadata = match edit(&before) {
+                MetadataOutcome::Skipped => continue,
+                MetadataOutcome::Unchanged => {
+                    batch.unchanged += 1;
+                    continue;
+                }
+                MetadataOutcome::Failed(error) => {
+                    batch.failed.push((before.id, error));
+                    continue;
+                }
+                MetadataOutcome::Changed(metadata) => metadata,
+            };
+
+            let mut after = before.clone();
+            after.metadata = Some(Metadata::from_value(&metadata));
+            if !dry_run {
+                let updated = sqlx::query(
+                    "UPDATE items SET metadata = ?, updated_at = ?, version = version + 1 WHERE id = ? AND version = ?",
+                )
+                .bind(serde_json::to_string(&after.metadata)?)
+                .bind(now)
+                .bind(before.id as i64)
+                .bind(before.version as i64)
+                .execute(&mut *tx)
+                .await?;
+                if updated.rows_affected() != 1 {
+                    batch.failed.push((before.id, "Item changed while being transformed".to_string()));
+                    continue;
+                }
+                after.updated_at = now;
+                after.version += 1;
+                let namespace: String = row.try_get("namespace")?;
+                self.record_change(&mut tx, ChangeOp::Updated, after.id as i64, Some(&after), &namespace)
+                    .await?;
+            }
+            batch.changed.push((before, after));
+        }
+
+        if dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        Ok(batch)
+    }
+
+    /// Item statistics computed with grouped queries, so no item rows are
+    /// loaded. Only the sections selected in `breakdowns` are queried.
+    pub async fn stats(&self, breakdowns: &StatsBreakdowns, top: usize) -> Result<ItemStats> {
+        let mut stats = ItemStats::new(self.count().await?.max(0) as u64, "database");
+
+        if breakdowns.tags {
+            let unique_tags: i64 = sqlx::query_scalar(&format!(
+                "SELECT COUNT(DISTINCT tag.value) FROM items, {} WHERE {}",
+                ITEM_TAGS_SOURCE, ITEM_NAMESPACE_FILTER
+            ))
+            .bind(crate::tenancy::current())
+            .fetch_one(&self.pool)
+            .await?;
+
+            stats.tags = Some(TagStats {
+                unique_tags: unique_tags.max(0) as u64,
+                top: self.tag_counts(Some(top)).await?,
+            });
+        }
+
+        if breakdowns.creators {
+            let rows = sqlx::query(&format!(
+                r#"
+                SELECT items.created_by AS created_by, users.username AS username, COUNT(*) AS count
+                FROM items
+                LEFT JOIN users ON users.id = items.created_by
+                WHERE {}
+                GROUP BY items.created_by
+                ORDER BY count DESC, items.created_by
+                LIMIT ?
+                "#,
+                ITEM_NAMESPACE_FILTER
+            ))
+            .bind(crate::tenancy::current())
+            .bind(top as i64)
+            .fetch_all(&self.pool)
+            .await?;
+
+            stats.creators = Some(
+                rows.iter()
+                    .map(|row| -> Result<CreatorCount> {
+                        Ok(CreatorCount {
+                            created_by: row.try_get("created_by")?,
+                            username: row.try_get("username")?,
+                            count: row.try_get::<i64, _>("count")?.max(0) as u64,
+                        })
+                    })
+                    .collect::<Result<_>>()?,
+            );
+        }
+
+        if breakdowns.daily {
+            let today = Utc::now().date_naive();
+            let first_day = today - chrono::Duration::days(STATS_DAILY_DAYS - 1);
+            // created_at is stored as RFC 3339 text, so its first ten
+            // characters are the UTC date.
+            let rows = sqlx::query(&format!(
+                r#"
+                SELECT substr(created_at, 1, 10) AS day, COUNT(*) AS count
+                FROM items
+                WHERE created_at >= ? AND {}
+                GROUP BY day
+                "#,
+                ITEM_NAMESPACE_FILTER
+            ))
+            .bind(first_day.format("%Y-%m-%d").to_string())
+            .bind(crate::tenancy::current())
+            .fetch_all(&self.pool)
+            .await?;
+
+            let mut counts = std::collections::HashMap::new();
+            for row in &rows {
+                let day: String = row.try_get("day")?;
+                if let Ok(date) = chrono::NaiveDate::parse_from_str(&day, "%Y-%m-%d") {
+                    counts.insert(date, row.try_get::<i64, _>("count")?.max(0) as u64);
+                }
+            }
+            stats.created_per_day = Some(daily_counts(today, &counts));
+        }
+
+        if breakdowns.metadata || breakdowns.descriptions {
+            let row = sqlx::query(&format!(
+                r#"
+                SELECT
+                    COUNT(CASE WHEN description IS NOT NULL AND trim(description) <> '' THEN 1 END) AS with_description,
+                    COUNT(CASE WHEN metadata NOT IN ('{{}}', 'null') THEN 1 END) AS with_metadata,
+                    AVG(CASE WHEN metadata NOT IN ('{{}}', 'null') THEN length(CAST(metadata AS BLOB)) END) AS average_metadata_size
+                FROM items
+                WHERE {}
+                "#,
+                ITEM_NAMESPACE_FILTER
+            ))
+            .bind(crate::tenancy::current())
+            .fetch_one(&self.pool)
+            .await?;
+
+            if breakdowns.metadata {
+                stats.metadata = Some(MetadataStats {
+                    items_with_metadata: row.try_get::<i64, _>("with_metadata")?.max(0) as u64,
+                    average_size_bytes: row.try_get::<Option<f64>, _>("average_metadata_size")?.unwrap_or(0.0),
+                });
+            }
+            if breakdowns.descriptions {
+                let with_description = row.try_get::<i64, _>("with_description")?.max(0) as u64;
+                stats.descriptions = Some(DescriptionStats {
+                    with_description,
+                    without_description: stats.total_items.saturating_sub(with_description),
+                });
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Creates an item with a chosen id and creation time, as fixtures do
+    /// so that their ids stay the same from one run to the next.
+    pub async fn create_with_id(&self, id: i64, created_at: DateTime<Utc>, input: CreateItemInput) -> Result<Item> {
+        self.create_item_internal(&input, Some((id, created_at))).await
+    }
+
+    async fn create_item_internal(&self, input: &CreateItemInput, fixed: Option<(i64, DateTime<Utc>)>) -> Result<Item> {
+        let now = fixed.map(|(_, created_at)| created_at).unwrap_or_else(Utc::now);
         let tags_json = serde_json::to_string(&input.tags)
             .unwrap_or_else(|_| "[]".to_string());
         let metadata_json = input.metadata
@@ -155,11 +628,15 @@ impl ItemRepository {
             .map(|m| serde_json::to_string(m).unwrap_or_else(|_| "{}".to_string()))
             .unwrap_or_else(|| "{}".to_string());
 
+        let namespace = crate::tenancy::current_or_default();
+
+        let mut tx = self.begin_write().await?;
         let row = sqlx::query(r#"
-            INSERT INTO items (name, description, created_at, updated_at, tags, metadata, created_by)
-            VALUES (?, ?, ?, ?, ?, ?, ?)
-            RETURNING id, name, description, created_at, updated_at, tags, metadata, created_by
+            INSERT INTO items (id, name, description, created_at, updated_at, tags, metadata, created_by, namespace)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING id, name, description, created_at, updated_at, tags, metadata, created_by, version
         "#)
+        .bind(fixed.map(|(id, _)| id))
         .bind(&input.name)
         .bind(&input.description)
         .bind(now)
@@ -167,7 +644,8 @@ impl ItemRepository {
         .bind(&tags_json)
         .bind(&metadata_json)
         .bind(input.created_by)
-        .fetch_one(&self.pool)
+        .bind(&namespace)
+        .fetch_one(&mut *tx)
         .await
         .map_err(AppError::from)?;
 
@@ -180,12 +658,272 @@ impl ItemRepository {
             tags: row.try_get("tags").unwrap_or_else(|_| "[]".to_string()),
             metadata: row.try_get("metadata").unwrap_or_else(|_| "{}".to_string()),
             created_by: row.try_get("created_by").unwrap_or(None),
+            version: row.try_get("version").unwrap_or(1),
         };
 
-        Ok(db_item.to_api_item())
+        let item = db_item.to_api_item();
+        self.record_change(&mut tx, ChangeOp::Created, db_item.id, Some(&item), &namespace)
+            .await?;
+        tx.commit().await?;
+        Ok(item)
+    }
+
+    /// Up to `limit` changes after sequence number `since`, in the current
+    /// namespace.
+    pub async fn changes_since(&self, since: u64, limit: usize) -> Result<ChangePage> {
+        let latest: i64 = sqlx::query_scalar("SELECT seq FROM sqlite_sequence WHERE name = 'item_changes'")
+            .fetch_optional(&self.pool)
+            .await?
+            .unwrap_or(0);
+        let oldest: Option<i64> = sqlx::query_scalar("SELECT MIN(seq) FROM item_changes")
+            .fetch_one(&self.pool)
+            .await?;
+        changes::ensure_retained(since, oldest.unwrap_or(latest + 1) as u64, latest as u64)?;
+
+        let rows = sqlx::query(
+            r#"
+            SELECT seq, op, item_id, item, changed_at
+            FROM item_changes
+            WHERE seq > ? AND namespace = COALESCE(?, namespace)
+            ORDER BY seq
+            LIMIT ?
+            "#,
+        )
+        .bind(since as i64)
+        .bind(crate::tenancy::current())
+        .bind((limit + 1) as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let changes = rows
+            .iter()
+            .map(|row| {
+                let op: String = row.try_get("op")?;
+                let item: Option<String> = row.try_get("item")?;
+                Ok(ItemChange {
+                    seq: row.try_get::<i64, _>("seq")? as u64,
+                    op: ChangeOp::parse(&op)
+                        .ok_or_else(|| AppError::Database(format!("Unknown item change operation {}", op)))?,
+                    item_id: row.try_get::<i64, _>("item_id")? as u64,
+                    item: item.map(|item| serde_json::from_str(&item)).transpose()?,
+                    changed_at: row.try_get::<DateTime<Utc>, _>("changed_at")?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(ChangePage::new(since, changes, limit))
+    }
+
+    /// Permanently removes items deleted before `cutoff`, in any namespace,
+    /// together with their change log entries and comments, unlinking their
+    /// files. Each
+    /// batch of up to `batch_size` items is its own transaction so that a
+    /// large backlog never holds the write lock for long. A dry run only
+    /// counts what would be removed.
+    pub async fn purge_deleted(&self, cutoff: DateTime<Utc>, batch_size: usize, dry_run: bool) -> Result<PurgeReport> {
+        let mut report = PurgeReport::new(cutoff, dry_run);
+
+        if dry_run {
+            let row = sqlx::query(
+                r#"
+                SELECT
+                    (SELECT COUNT(*) FROM items WHERE deleted_at < ?) AS items,
+                    (SELECT COUNT(*) FROM files WHERE item_id IN (SELECT id FROM items WHERE deleted_at < ?)) AS files,
+                    (SELECT COUNT(*) FROM item_changes WHERE item_id IN (SELECT id FROM items WHERE deleted_at < ?)) AS changes,
+                    (SELECT COUNT(*) FROM item_comments WHERE item_id IN (SELECT id FROM items WHERE deleted_at < ?)) AS comments
+                "#,
+            )
+            .bind(cutoff)
+            .bind(cutoff)
+            .bind(cutoff)
+            .bind(cutoff)
+            .fetch_one(&self.pool)
+            .await?;
+
+            report.items = row.try_get::<i64, _>("items")?.max(0) as u64;
+            report.files_detached = row.try_get::<i64, _>("files")?.max(0) as u64;
+            report.changes_removed = row.try_get::<i64, _>("changes")?.max(0) as u64;
+            report.comments_removed = row.try_get::<i64, _>("comments")?.max(0) as u64;
+            report.batches = report.items.div_ceil(batch_size as u64);
+            return Ok(report);
+        }
+
+        loop {
+            let mut tx = self.begin_write().await?;
+            let ids: Vec<i64> = sqlx::query_scalar(
+                "SELECT id FROM items WHERE deleted_at < ? ORDER BY deleted_at, id LIMIT ?",
+            )
+            .bind(cutoff)
+            .bind(batch_size as i64)
+            .fetch_all(&mut *tx)
+            .await?;
+            if ids.is_empty() {
+                break;
+            }
+
+            // Items go last: deleting them fires the trigger that drops their
+            // full-text rows.
+            report.files_detached += execute_for_ids(&mut tx, "UPDATE files SET item_id = NULL WHERE item_id IN", &ids).await?;
+            report.changes_removed += execute_for_ids(&mut tx, "DELETE FROM item_changes WHERE item_id IN", &ids).await?;
+            report.comments_removed += execute_for_ids(&mut tx, "DELETE FROM item_comments WHERE item_id IN", &ids).await?;
+            report.items += execute_for_ids(&mut tx, "DELETE FROM items WHERE id IN", &ids).await?;
+            tx.commit().await?;
+            report.batches += 1;
+
+            if ids.len() < batch_size {
+                break;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Items in the current namespace's trash, most recently deleted first,
+    /// with when they were deleted.
+    pub async fn list_trashed(&self, limit: i64, offset: i64) -> Result<Vec<(Item, DateTime<Utc>)>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, name, description, created_at, updated_at, tags, metadata, created_by, version, deleted_at
+            FROM items
+            WHERE namespace = COALESCE(?, namespace) AND deleted_at IS NOT NULL
+            ORDER BY deleted_at DESC, id DESC
+            LIMIT ? OFFSET ?
+            "#,
+        )
+        .bind(crate::tenancy::current())
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| Ok((item_from_row(row), row.try_get::<DateTime<Utc>, _>("deleted_at")?)))
+            .collect()
+    }
+
+    /// Takes an item out of the trash, recording it as created again.
+    pub async fn restore(&self, id: i64) -> Result<Item> {
+        let mut tx = self.begin_write().await?;
+        let row = sqlx::query(
+            r#"
+            UPDATE items SET deleted_at = NULL
+            WHERE id = ? AND namespace = COALESCE(?, namespace) AND deleted_at IS NOT NULL
+            RETURNING id, name, description, created_at, updated_at, tags, metadata, created_by, version, namespace
+            "#,
+        )
+        .bind(id)
+        .bind(crate::tenancy::current())
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(row) = row else {
+            return Err(AppError::NotFound(format!("Item with id {} is not in the trash", id)));
+        };
+        let item = item_from_row(&row);
+        let namespace: String = row.try_get("namespace")?;
+        self.record_change(&mut tx, ChangeOp::Created, id, Some(&item), &namespace)
+            .await?;
+        tx.commit().await?;
+
+        Ok(item)
+    }
+
+    /// Up to `limit` items with ids above `after_id` whose metadata holds a
+    /// secret, in id order, from every namespace and the trash.
+    pub async fn items_with_secrets(&self, after_id: i64, limit: i64) -> Result<Vec<Item>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, name, description, created_at, updated_at, tags, metadata, created_by, version
+            FROM items
+            WHERE id > ?
+              AND CASE WHEN json_valid(metadata) THEN json_extract(metadata, '$.secret') END IS NOT NULL
+            ORDER BY id
+            LIMIT ?
+            "#,
+        )
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(item_from_row).collect())
+    }
+
+    /// Stores `metadata` for the item if it is still at `version`, without
+    /// counting it as a change: the version, update time and change feed
+    /// are left alone. Returns whether the item was still at that version.
+    pub async fn replace_metadata(&self, id: i64, version: u64, metadata: &serde_json::Value) -> Result<bool> {
+        let result = sqlx::query("UPDATE items SET metadata = ? WHERE id = ? AND version = ?")
+            .bind(serde_json::to_string(metadata)?)
+            .bind(id)
+            .bind(version as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Appends to `item_changes` inside the transaction that made the change,
+    /// then drops entries outside the configured retention.
+    async fn record_change(
+        &self,
+        conn: &mut SqliteConnection,
+        op: ChangeOp,
+        item_id: i64,
+        item: Option<&Item>,
+        namespace: &str,
+    ) -> Result<()> {
+        let now = Utc::now();
+        let item_json = item.map(serde_json::to_string).transpose()?;
+        let seq = sqlx::query(
+            "INSERT INTO item_changes (op, item_id, item, namespace, changed_at) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(op.as_str())
+        .bind(item_id)
+        .bind(item_json)
+        .bind(namespace)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?
+        .last_insert_rowid();
+
+        let cutoff = now - chrono::Duration::hours(self.change_feed.retention_hours as i64);
+        sqlx::query("DELETE FROM item_changes WHERE changed_at < ? OR seq <= ?")
+            .bind(cutoff)
+            .bind(seq - self.change_feed.max_entries as i64)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
     }
 }
 
+/// Runs `statement`, which ends in `IN`, against the list of `ids` and
+/// returns the number of rows it affected.
+async fn execute_for_ids(conn: &mut SqliteConnection, statement: &str, ids: &[i64]) -> Result<u64> {
+    let sql = format!("{} ({})", statement, vec!["?"; ids.len()].join(", "));
+    let mut query = sqlx::query(&sql);
+    for id in ids {
+        query = query.bind(id);
+    }
+    Ok(query.execute(conn).await?.rows_affected())
+}
+
+fn item_from_row(row: &sqlx::sqlite::SqliteRow) -> Item {
+    DbItem {
+        id: row.try_get("id").unwrap_or(0),
+        name: row.try_get("name").unwrap_or_default(),
+        description: row.try_get("description").unwrap_or(None),
+        created_at: row.try_get("created_at").unwrap_or_else(|_| Utc::now()),
+        updated_at: row.try_get("updated_at").unwrap_or_else(|_| Utc::now()),
+        tags: row.try_get("tags").unwrap_or_else(|_| "[]".to_string()),
+        metadata: row.try_get("metadata").unwrap_or_else(|_| "{}".to_string()),
+        created_by: row.try_get("created_by").unwrap_or(None),
+        version: row.try_get("version").unwrap_or(1),
+    }
+    .to_api_item()
+}
+
 #[derive(Debug, Clone)]
 pub struct CreateItemInput {
     pub name: String,
@@ -201,6 +939,8 @@ pub struct UpdateItemInput {
     pub description: Option<String>,
     pub tags: Vec<String>,
     pub metadata: Option<serde_json::Value>,
+    /// When set, the update only applies to the item at this version.
+    pub expected_version: Option<u64>,
 }
 
 #[async_trait]
@@ -210,16 +950,17 @@ impl Repository<Item> for ItemRepository {
     type UpdateInput = UpdateItemInput;
 
     async fn create(&self, input: Self::CreateInput) -> Result<Item> {
-        self.create_item_internal(&input).await
+        self.create_item_internal(&input, None).await
     }
 
     async fn get_by_id(&self, id: Self::Id) -> Result<Option<Item>> {
-        let row = sqlx::query(r#"
-            SELECT id, name, description, created_at, updated_at, tags, metadata, created_by
+        let row = sqlx::query(&format!(r#"
+            SELECT id, name, description, created_at, updated_at, tags, metadata, created_by, version
             FROM items
-            WHERE id = ?
-        "#)
+            WHERE id = ? AND {}
+        "#, ITEM_NAMESPACE_FILTER))
         .bind(id)
+        .bind(crate::tenancy::current())
         .fetch_optional(&self.pool)
         .await
         .map_err(AppError::from)?;
@@ -235,6 +976,7 @@ impl Repository<Item> for ItemRepository {
                     tags: row.try_get("tags").unwrap_or_else(|_| "[]".to_string()),
                     metadata: row.try_get("metadata").unwrap_or_else(|_| "{}".to_string()),
                     created_by: row.try_get("created_by").unwrap_or(None),
+                    version: row.try_get("version").unwrap_or(1),
                 };
                 Ok(Some(db_item.to_api_item()))
             }
@@ -247,25 +989,47 @@ impl Repository<Item> for ItemRepository {
         let tags_json = serde_json::to_string(&input.tags)
             .unwrap_or_else(|_| "[]".to_string());
         let metadata_json = input.metadata
-            .map(|m| serde_json::to_string(&m).unwrap_or_else(|_| "{}".to_string()))
+            .as_ref()
+            .map(|m| serde_json::to_string(m).unwrap_or_else(|_| "{}".to_string()))
             .unwrap_or_else(|| "{}".to_string());
 
-        let row = sqlx::query(r#"
+        let mut tx = self.begin_write().await?;
+        let row = sqlx::query(&format!(r#"
             UPDATE items 
-            SET name = ?, description = ?, updated_at = ?, tags = ?, metadata = ?
-            WHERE id = ?
-            RETURNING id, name, description, created_at, updated_at, tags, metadata, created_by
-        "#)
+            SET name = ?, description = ?, updated_at = ?, tags = ?, metadata = ?, version = version + 1
+            WHERE id = ? AND {} AND version = COALESCE(?, version)
+            RETURNING id, name, description, created_at, updated_at, tags, metadata, created_by, version, namespace
+        "#, ITEM_NAMESPACE_FILTER))
         .bind(&input.name)
         .bind(&input.description)
         .bind(now)
         .bind(&tags_json)
         .bind(&metadata_json)
         .bind(id)
-        .fetch_one(&self.pool)
+        .bind(crate::tenancy::current())
+        .bind(input.expected_version.map(|version| version as i64))
+        .fetch_optional(&mut *tx)
         .await
         .map_err(AppError::from)?;
 
+        // Nothing matched: the item is gone, or another write got there first.
+        let Some(row) = row else {
+            tx.rollback().await?;
+            let current = self
+                .get_by_id(id)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("Item with id {} not found", id)))?;
+            let proposed = Item {
+                name: input.name,
+                description: input.description,
+                tags: input.tags,
+                metadata: input.metadata.map(Metadata::from),
+                ..current.clone()
+            };
+            let expected = input.expected_version.unwrap_or(current.version);
+            return Err(AppError::VersionConflict(Box::new(VersionConflict::new(expected, current, &proposed))));
+        };
+
         let db_item = DbItem {
             id: row.try_get("id").unwrap_or(0),
             name: row.try_get("name").unwrap_or_default(),
@@ -275,46 +1039,57 @@ impl Repository<Item> for ItemRepository {
             tags: row.try_get("tags").unwrap_or_else(|_| "[]".to_string()),
             metadata: row.try_get("metadata").unwrap_or_else(|_| "{}".to_string()),
             created_by: row.try_get("created_by").unwrap_or(None),
+            version: row.try_get("version").unwrap_or(1),
         };
 
-        Ok(db_item.to_api_item())
+        let item = db_item.to_api_item();
+        let namespace: String = row.try_get("namespace")?;
+        self.record_change(&mut tx, ChangeOp::Updated, id, Some(&item), &namespace)
+            .await?;
+        tx.commit().await?;
+        Ok(item)
     }
 
+    /// Moves the item to the trash; [`purge_deleted`](Self::purge_deleted)
+    /// removes it for good later.
     async fn delete(&self, id: Self::Id) -> Result<()> {
-        let result = sqlx::query("DELETE FROM items WHERE id = ?")
-            .bind(id)
-            .execute(&self.pool)
-            .await
-            .map_err(AppError::from)?;
+        let mut tx = self.begin_write().await?;
+        let namespace: Option<String> = sqlx::query_scalar(&format!(
+            "UPDATE items SET deleted_at = ? WHERE id = ? AND {} RETURNING namespace",
+            ITEM_NAMESPACE_FILTER
+        ))
+        .bind(Utc::now())
+        .bind(id)
+        .bind(crate::tenancy::current())
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::from)?;
 
-        if result.rows_affected() == 0 {
+        let Some(namespace) = namespace else {
             return Err(AppError::NotFound(format!("Item with id {} not found", id)));
-        }
+        };
+        self.record_change(&mut tx, ChangeOp::Deleted, id, None, &namespace)
+            .await?;
+        tx.commit().await?;
 
         Ok(())
     }
 
     async fn list(&self, params: ListParams) -> Result<Vec<Item>> {
-        let limit = params.limit.unwrap_or(49);
+        let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE as i64);
         let offset = params.offset.unwrap_or(0);
-        let sort_by = params.sort_by.as_deref().unwrap_or("created_at");
-        let sort_order = params.sort_order.as_ref().unwrap_or(&SortOrder::Desc);
-
-        let allowed_sort_fields = ["id", "name", "created_at", "updated_at"];
-        let safe_sort_by = if allowed_sort_fields.contains(&sort_by) {
-            sort_by
-        } else {
-            "created_at"
-        };
+        let order_by = ITEM_SORT.order_by(params.sort.as_ref())?;
 
         let query = format!(r#"
-            SELECT id, name, description, created_at, updated_at, tags, metadata, created_by
+            SELECT id, name, description, created_at, updated_at, tags, metadata, created_by, version
             FROM items
-            ORDER BY {} {}
+            WHERE {}
+            ORDER BY {}
             LIMIT ? OFFSET ?
-        "#, safe_sort_by, sort_order);
+        "#, ITEM_NAMESPACE_FILTER, order_by);
 
         let rows = sqlx::query(&query)
+            .bind(crate::tenancy::current())
             .bind(limit)
             .bind(offset)
             .fetch_all(&self.pool)
@@ -335,6 +1110,7 @@ impl Repository<Item> for ItemRepository {
                 tags: row.try_get("tags").unwrap_or_else(|_| "[]".to_string()),
                 metadata: row.try_get("metadata").unwrap_or_else(|_| "{}".to_string()),
                 created_by: row.try_get("created_by").unwrap_or(None),
+                version: row.try_get("version").unwrap_or(1),
             };
             items.push(db_item.to_api_item());
         }
@@ -343,7 +1119,8 @@ impl Repository<Item> for ItemRepository {
     }
 
     async fn count(&self) -> Result<i64> {
-        let row = sqlx::query("SELECT COUNT(*) as count FROM items")
+        let row = sqlx::query(&format!("SELECT COUNT(*) as count FROM items WHERE {}", ITEM_NAMESPACE_FILTER))
+            .bind(crate::tenancy::current())
             .fetch_one(&self.pool)
             .await
             .map_err(AppError::from)?;
@@ -354,70 +1131,52 @@ impl Repository<Item> for ItemRepository {
 
 #[derive(Clone)]
 pub struct UserRepository {
-    pool: SqlitePool,
+    pool: InstrumentedPool,
+}
+
+const USER_COLUMNS: &str = "id, username, email, password_hash, role, created_at, last_login, is_active, \
+    display_name, avatar_file_id, timezone, notification_preferences";
+
+fn db_user_from_row(row: &sqlx::sqlite::SqliteRow) -> DbUser {
+    DbUser {
+        id: row.try_get("id").unwrap_or(0),
+        username: row.try_get("username").unwrap_or_default(),
+        email: row.try_get("email").unwrap_or_default(),
+        password_hash: row.try_get("password_hash").unwrap_or_default(),
+        role: row.try_get("role").unwrap_or_else(|_| "User".to_string()),
+        created_at: row.try_get("created_at").unwrap_or_else(|_| Utc::now()),
+        last_login: row.try_get("last_login").unwrap_or(None),
+        is_active: row.try_get("is_active").unwrap_or(true),
+        display_name: row.try_get("display_name").unwrap_or(None),
+        avatar_file_id: row.try_get("avatar_file_id").unwrap_or(None),
+        timezone: row.try_get("timezone").unwrap_or(None),
+        notification_preferences: row.try_get("notification_preferences").unwrap_or(None),
+    }
 }
 
 impl UserRepository {
     pub fn new(pool: SqlitePool) -> Self {
-        Self { pool }
+        Self { pool: pool.into() }
     }
 
     pub async fn get_by_username(&self, username: &str) -> Result<Option<DbUser>> {
-        let row = sqlx::query(r#"
-            SELECT id, username, email, password_hash, role, created_at, last_login, is_active
-            FROM users
-            WHERE username = ?
-        "#)
-        .bind(username)
-        .fetch_optional(&self.pool)
-        .await
-        .map_err(AppError::from)?;
+        let row = sqlx::query(&format!("SELECT {} FROM users WHERE username = ?", USER_COLUMNS))
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(AppError::from)?;
 
-        match row {
-            Some(row) => {
-                let user = DbUser {
-                    id: row.try_get("id").unwrap_or(0),
-                    username: row.try_get("username").unwrap_or_default(),
-                    email: row.try_get("email").unwrap_or_default(),
-                    password_hash: row.try_get("password_hash").unwrap_or_default(),
-                    role: row.try_get("role").unwrap_or_else(|_| "User".to_string()),
-                    created_at: row.try_get("created_at").unwrap_or_else(|_| Utc::now()),
-                    last_login: row.try_get("last_login").unwrap_or(None),
-                    is_active: row.try_get("is_active").unwrap_or(true),
-                };
-                Ok(Some(user))
-            }
-            None => Ok(None),
-        }
+        Ok(row.as_ref().map(db_user_from_row))
     }
 
     pub async fn get_by_email(&self, email: &str) -> Result<Option<DbUser>> {
-        let row = sqlx::query(r#"
-            SELECT id, username, email, password_hash, role, created_at, last_login, is_active
-            FROM users
-            WHERE email = ?
-        "#)
-        .bind(email)
-        .fetch_optional(&self.pool)
-        .await
-        .map_err(AppError::from)?;
+        let row = sqlx::query(&format!("SELECT {} FROM users WHERE email = ?", USER_COLUMNS))
+            .bind(email)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(AppError::from)?;
 
-        match row {
-            Some(row) => {
-                let user = DbUser {
-                    id: row.try_get("id").unwrap_or(0),
-                    username: row.try_get("username").unwrap_or_default(),
-                    email: row.try_get("email").unwrap_or_default(),
-                    password_hash: row.try_get("password_hash").unwrap_or_default(),
-                    role: row.try_get("role").unwrap_or_else(|_| "User".to_string()),
-                    created_at: row.try_get("created_at").unwrap_or_else(|_| Utc::now()),
-                    last_login: row.try_get("last_login").unwrap_or(None),
-                    is_active: row.try_get("is_active").unwrap_or(true),
-                };
-                Ok(Some(user))
-            }
-            None => Ok(None),
-        }
+        Ok(row.as_ref().map(db_user_from_row))
     }
 
     pub async fn update_last_login(&self, user_id: i64) -> Result<()> {
@@ -440,11 +1199,76 @@ pub struct CreateUserInput {
     pub role: UserRole,
 }
 
-#[derive(Debug, Clone)]
+/// A change to a user. `None` leaves a field alone; for the optional profile
+/// fields, `Some(None)` clears it.
+#[derive(Debug, Clone, Default)]
 pub struct UpdateUserInput {
     pub email: Option<String>,
     pub role: Option<UserRole>,
     pub is_active: Option<bool>,
+    pub display_name: Option<Option<String>>,
+    pub avatar_file_id: Option<Option<String>>,
+    pub timezone: Option<Option<String>>,
+    /// Stored as JSON.
+    pub notification_preferences: Option<String>,
+}
+
+impl UpdateUserInput {
+    /// The `SET` assignments for the fields being changed, in the order
+    /// [`bind`](Self::bind) binds them.
+    pub fn assignments(&self) -> Vec<&'static str> {
+        let mut parts = Vec::new();
+        if self.email.is_some() {
+            parts.push("email = ?");
+        }
+        if self.role.is_some() {
+            parts.push("role = ?");
+        }
+        if self.is_active.is_some() {
+            parts.push("is_active = ?");
+        }
+        if self.display_name.is_some() {
+            parts.push("display_name = ?");
+        }
+        if self.avatar_file_id.is_some() {
+            parts.push("avatar_file_id = ?");
+        }
+        if self.timezone.is_some() {
+            parts.push("timezone = ?");
+        }
+        if self.notification_preferences.is_some() {
+            parts.push("notification_preferences = ?");
+        }
+        parts
+    }
+
+    pub fn bind<'q>(
+        &'q self,
+        mut query: sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>>,
+    ) -> sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>> {
+        if let Some(email) = &self.email {
+            query = query.bind(email);
+        }
+        if let Some(role) = &self.role {
+            query = query.bind(role.to_string());
+        }
+        if let Some(is_active) = self.is_active {
+            query = query.bind(is_active);
+        }
+        if let Some(display_name) = &self.display_name {
+            query = query.bind(display_name);
+        }
+        if let Some(avatar_file_id) = &self.avatar_file_id {
+            query = query.bind(avatar_file_id);
+        }
+        if let Some(timezone) = &self.timezone {
+            query = query.bind(timezone);
+        }
+        if let Some(preferences) = &self.notification_preferences {
+            query = query.bind(preferences);
+        }
+        query
+    }
 }
 
 #[async_trait]
@@ -456,11 +1280,11 @@ impl Repository<DbUser> for UserRepository {
     async fn create(&self, input: Self::CreateInput) -> Result<DbUser> {
         let now = Utc::now();
 
-        let row = sqlx::query(r#"
+        let row = sqlx::query(&format!(r#"
             INSERT INTO users (username, email, password_hash, role, created_at, is_active)
             VALUES (?, ?, ?, ?, ?, ?)
-            RETURNING id, username, email, password_hash, role, created_at, last_login, is_active
-        "#)
+            RETURNING {}
+        "#, USER_COLUMNS))
         .bind(&input.username)
         .bind(&input.email)
         .bind(&input.password_hash)
@@ -471,111 +1295,43 @@ impl Repository<DbUser> for UserRepository {
         .await
         .map_err(AppError::from)?;
 
-        let user = DbUser {
-            id: row.try_get("id").unwrap_or(0),
-            username: row.try_get("username").unwrap_or_default(),
-            email: row.try_get("email").unwrap_or_default(),
-            password_hash: row.try_get("password_hash").unwrap_or_default(),
-            role: row.try_get("role").unwrap_or_else(|_| "User".to_string()),
-            created_at: row.try_get("created_at").unwrap_or(now),
-            last_login: row.try_get("last_login").unwrap_or(None),
-            is_active: row.try_get("is_active").unwrap_or(true),
-        };
-
-        Ok(user)
+        Ok(db_user_from_row(&row))
     }
 
     async fn get_by_id(&self, id: Self::Id) -> Result<Option<DbUser>> {
-        let row = sqlx::query(r#"
-            SELECT id, username, email, password_hash, role, created_at, last_login, is_active
-            FROM users
-            WHERE id = ?
-        "#)
-        .bind(id)
-        .fetch_optional(&self.pool)
-        .await
-        .map_err(AppError::from)?;
+        let row = sqlx::query(&format!("SELECT {} FROM users WHERE id = ?", USER_COLUMNS))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(AppError::from)?;
 
-        match row {
-            Some(row) => {
-                let user = DbUser {
-                    id: row.try_get("id").unwrap_or(0),
-                    username: row.try_get("username").unwrap_or_default(),
-                    email: row.try_get("email").unwrap_or_default(),
-                    password_hash: row.try_get("password_hash").unwrap_or_default(),
-                    role: row.try_get("role").unwrap_or_else(|_| "User".to_string()),
-                    created_at: row.try_get("created_at").unwrap_or_else(|_| Utc::now()),
-                    last_login: row.try_get("last_login").unwrap_or(None),
-                    is_active: row.try_get("is_active").unwrap_or(true),
-                };
-                Ok(Some(user))
-            }
-            None => Ok(None),
-        }
+        Ok(row.as_ref().map(db_user_from_row))
     }
 
     async fn update(&self, id: Self::Id, input: Self::UpdateInput) -> Result<DbUser> {
-        let mut query_parts = Vec::new();
-        let mut bind_values: Vec<Box<dyn sqlx::Encode<'_, sqlx::Sqlite> + Send + '_>> = Vec::new();
-
-        if let Some(email) = &input.email {
-            query_parts.push("email = ?");
-            bind_values.push(Box::new(email));
-        }
-
-        if let Some(role) = &input.role {
-            query_parts.push("role = ?");
-            bind_values.push(Box::new(role.to_string()));
-        }
-
-        if let Some(is_active) = input.is_active {
-            query_parts.push("is_active = ?");
-            bind_values.push(Box::new(is_active));
-        }
-
-        if query_parts.is_empty() {
+        let assignments = input.assignments();
+        if assignments.is_empty() {
             return self.get_by_id(id).await?.ok_or_else(|| {
                 AppError::NotFound(format!("User with id {} not found", id))
             });
         }
 
         let query = format!(r#"
-            UPDATE users 
+            UPDATE users
             SET {}
             WHERE id = ?
-            RETURNING id, username, email, password_hash, role, created_at, last_login, is_active
-        "#, query_parts.join(", "));
+            RETURNING {}
+        "#, assignments.join(", "), USER_COLUMNS);
 
-        let mut query_builder = sqlx::query(&query);
-        
-        if let Some(email) = &input.email {
-            query_builder = query_builder.bind(email);
-        }
-        if let Some(role) = &input.role {
-            query_builder = query_builder.bind(role.to_string());
-        }
-        if let Some(is_active) = input.is_active {
-            query_builder = query_builder.bind(is_active);
-        }
-        query_builder = query_builder.bind(id);
-
-        let row = query_builder
-            .fetch_one(&self.pool)
+        let row = input
+            .bind(sqlx::query(&query))
+            .bind(id)
+            .fetch_optional(&self.pool)
             .await
-            .map_err(AppError::from)?;
-
-        let user = DbUser {
-            id: row.try_get("id").unwrap_or(0),
-            username: row.try_get("username").unwrap_or_default(),
-            email: row.try_get("email").unwrap_or_default(),
-            password_hash: row.try_get("password_hash").unwrap_or_default(),
-            role: row.try_get("role").unwrap_or_else(|_| "User".to_string()),
-            created_at: row.try_get("created_at").unwrap_or_else(|_| Utc::now()),
-            last_login: row.try_get("last_login").unwrap_or(None),
-            is_active: row.try_get("is_active").unwrap_or(true),
-        };
+            .map_err(AppError::from)?
+            .ok_or_else(|| AppError::NotFound(format!("User with id {} not found", id)))?;
 
-        Ok(user)
+        Ok(db_user_from_row(&row))
     }
 
     async fn delete(&self, id: Self::Id) -> Result<()> {
@@ -595,15 +1351,14 @@ impl Repository<DbUser> for UserRepository {
     async fn list(&self, params: ListParams) -> Result<Vec<DbUser>> {
         let limit = params.limit.unwrap_or(50);
         let offset = params.offset.unwrap_or(0);
-        let sort_by = params.sort_by.as_deref().unwrap_or("created_at");
-        let sort_order = params.sort_order.as_ref().unwrap_or(&SortOrder::Desc);
+        let order_by = USER_SORT.order_by(params.sort.as_ref())?;
 
         let query = format!(r#"
-            SELECT id, username, email, password_hash, role, created_at, last_login, is_active
+            SELECT {}
             FROM users
-            ORDER BY {} {}
+            ORDER BY {}
             LIMIT ? OFFSET ?
-        "#, sort_by, sort_order);
+        "#, USER_COLUMNS, order_by);
 
         let rows = sqlx::query(&query)
             .bind(limit)
@@ -612,22 +1367,7 @@ impl Repository<DbUser> for UserRepository {
             .await
             .map_err(AppError::from)?;
 
-        let mut users = Vec::new();
-        for row in rows {
-            let user = DbUser {
-                id: row.try_get("id").unwrap_or(0),
-                username: row.try_get("username").unwrap_or_default(),
-                email: row.try_get("email").unwrap_or_default(),
-                password_hash: row.try_get("password_hash").unwrap_or_default(),
-                role: row.try_get("role").unwrap_or_else(|_| "User".to_string()),
-                created_at: row.try_get("created_at").unwrap_or_else(|_| Utc::now()),
-                last_login: row.try_get("last_login").unwrap_or(None),
-                is_active: row.try_get("is_active").unwrap_or(true),
-            };
-            users.push(user);
-        }
-
-        Ok(users)
+        Ok(rows.iter().map(db_user_from_row).collect())
     }
 
     async fn count(&self) -> Result<i64> {
@@ -646,19 +1386,21 @@ mod tests {
     use tempfile::NamedTempFile;
     use crate::database::{get_database_pool, run_migrations};
 
-    async fn setup_test_db() -> SqlitePool {
+    /// A migrated database, with the file holding it, which is deleted when
+    /// dropped.
+    async fn setup_test_db() -> (SqlitePool, NamedTempFile) {
         let temp_file = NamedTempFile::new().unwrap();
         let database_url = format!("sqlite:{}", temp_file.path().display());
         
         let pool = get_database_pool(&database_url).await.unwrap();
         run_migrations(pool.clone()).await.unwrap();
         
-        pool
+        (pool, temp_file)
     }
 
     #[tokio::test]
     async fn test_item_repository_crud() {
-        let pool = setup_test_db().await;
+        let (pool, _db) = setup_test_db().await;
         let repo = ItemRepository::new(pool);
 
         let create_input = CreateItemInput {
@@ -685,11 +1427,13 @@ mod tests {
             description: Some("Updated Description".to_string()),
             tags: vec!["updated".to_string()],
             metadata: None,
+            expected_version: None,
         };
 
         let updated_item = repo.update(created_item.id as i64, update_input).await.unwrap();
         assert_eq!(updated_item.name, "Updated Item");
         assert_eq!(updated_item.tags, vec!["updated"]);
+        assert_eq!(updated_item.version, created_item.version + 1);
 
         let items = repo.list(ListParams::default()).await.unwrap();
         assert_eq!(items.len(), 1);
@@ -704,7 +1448,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_user_repository_crud() {
-        let pool = setup_test_db().await;
+        let (pool, _db) = setup_test_db().await;
         let repo = UserRepository::new(pool);
 
         let create_input = CreateUserInput {
@@ -733,9 +1477,40 @@ mod tests {
         assert_eq!(count, 1);
     }
 
+    #[tokio::test]
+    async fn test_list_sort() {
+        let (pool, _db) = setup_test_db().await;
+        let repo = ItemRepository::new(pool.clone());
+        for name in ["b", "c", "a"] {
+            repo.create(CreateItemInput {
+                name: name.to_string(),
+                description: None,
+                tags: vec![],
+                metadata: None,
+                created_by: None,
+            })
+            .await
+            .unwrap();
+        }
+
+        let list = |sort: &str| ListParams {
+            sort: Some(SortSpec::parse(sort).unwrap()),
+            ..ListParams::default()
+        };
+        let names: Vec<String> = repo.list(list("name")).await.unwrap().into_iter().map(|item| item.name).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        let names: Vec<String> = repo.list(list("-id")).await.unwrap().into_iter().map(|item| item.name).collect();
+        assert_eq!(names, ["a", "c", "b"]);
+
+        let users = UserRepository::new(pool.clone());
+        assert!(users.list(list("username; DROP TABLE users")).await.is_err());
+        assert!(users.list(list("password_hash")).await.is_err());
+        assert_eq!(users.count().await.unwrap(), 0);
+    }
+
     #[tokio::test]
     async fn test_transaction_support() {
-        let pool = setup_test_db().await;
+        let (pool, _db) = setup_test_db().await;
         let repo = ItemRepository::new(pool);
 
         let mut tx = repo.begin_transaction().await.unwrap();
@@ -768,4 +1543,52 @@ mod tests {
         let count = repo.count().await.unwrap();
         assert_eq!(count, 0);
     }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_writes() {
+        let (pool, _db) = setup_test_db().await;
+        let repo = ItemRepository::new(pool);
+
+        let creates = (0..10).map(|i| {
+            let repo = repo.clone();
+            tokio::spawn(async move {
+                repo.create(CreateItemInput {
+                    name: format!("Concurrent {}", i),
+                    description: None,
+                    tags: vec![],
+                    metadata: None,
+                    created_by: None,
+                })
+                .await
+            })
+        });
+        let created: Vec<Item> = futures_util::future::join_all(creates)
+            .await
+            .into_iter()
+            .map(|result| result.unwrap().unwrap())
+            .collect();
+
+        let updates = created.iter().map(|item| {
+            let repo = repo.clone();
+            let id = item.id as i64;
+            tokio::spawn(async move {
+                repo.update(
+                    id,
+                    UpdateItemInput {
+                        name: format!("Updated {}", id),
+                        description: None,
+                        tags: vec![],
+                        metadata: None,
+                        expected_version: None,
+                    },
+                )
+                .await
+            })
+        });
+        for result in futures_util::future::join_all(updates).await {
+            result.unwrap().unwrap();
+        }
+
+        assert_eq!(repo.count().await.unwrap(), 10);
+    }
 }
\ No newline at end of file
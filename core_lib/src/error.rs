@@ -18,6 +18,21 @@ pub enum AppError {
     #[error("Not found: {0}")]
     NotFound(String),
 
+    #[error("Gone: {0}")]
+    Gone(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Version conflict: item {} is at version {}, not {}", .0.item_id, .0.current_version, .0.expected_version)]
+    VersionConflict(Box<crate::models::items::VersionConflict>),
+
+    #[error("Name conflict: item {} is already named {:?}", .0.item_id, .0.name)]
+    NameConflict(Box<crate::item_names::NameConflict>),
+
+    #[error("Precondition required: {0}")]
+    PreconditionRequired(String),
+
     #[error("Internal server error")]
     InternalServerError,
 
@@ -46,10 +61,7 @@ pub enum AppError {
     JsonError(#[from] serde_json::Error),
 
     #[error("Validation error: {0}")]
-    Validation(String),
-
-    #[error("File validation error: {0}")]
-    FileValidation(String),
+    Validation(#[from] crate::validation::ValidationError),
 
     #[error("Security validation error: {0}")]
     SecurityValidation(String),
@@ -60,9 +72,33 @@ pub enum AppError {
     #[error("Configuration error: {0}")]
     Configuration(String),
 
+    #[error("Item secret unavailable: {0}")]
+    SecretUnavailable(String),
+
     #[error("Rate limit exceeded: {0}")]
     RateLimit(String),
 
+    #[error("Unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+
+    #[error("Request headers too large: {0}")]
+    HeadersTooLarge(String),
+
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
+
+    #[error("Ambiguous body length: {0}")]
+    AmbiguousBodyLength(String),
+
+    #[error("Insufficient storage: {0}")]
+    InsufficientStorage(String),
+
+    #[error("Not implemented: {0}")]
+    NotImplemented(String),
+
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(String),
+
     #[error("Middleware error: {0}")]
     Middleware(String),
 
@@ -75,6 +111,11 @@ impl IntoResponse for AppError {
         let (status, error_message) = match self {
             AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
             AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            AppError::Gone(msg) => (StatusCode::GONE, msg),
+            AppError::Conflict(msg) => (StatusCode::CONFLICT, msg),
+            AppError::VersionConflict(conflict) => return version_conflict_response(&conflict),
+            AppError::NameConflict(conflict) => return name_conflict_response(&conflict),
+            AppError::PreconditionRequired(msg) => (StatusCode::PRECONDITION_REQUIRED, msg),
             AppError::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized".to_string()),
             AppError::Authentication(msg) => (StatusCode::UNAUTHORIZED, msg),
             AppError::Authorization(msg) => (StatusCode::FORBIDDEN, msg),
@@ -101,8 +142,7 @@ impl IntoResponse for AppError {
                 tracing::error!("JSON error: {:?}", err);
                 (StatusCode::BAD_REQUEST, "Invalid JSON data".to_string())
             }
-            AppError::Validation(msg) => (StatusCode::BAD_REQUEST, msg),
-            AppError::FileValidation(msg) => (StatusCode::BAD_REQUEST, msg),
+            AppError::Validation(err) => (StatusCode::BAD_REQUEST, err.to_string()),
             AppError::SecurityValidation(msg) => {
                 tracing::warn!("Security validation failed: {}", msg);
                 (StatusCode::BAD_REQUEST, "Request failed security validation".to_string())
@@ -115,7 +155,18 @@ impl IntoResponse for AppError {
                 tracing::error!("Configuration error: {}", msg);
                 (StatusCode::INTERNAL_SERVER_ERROR, "Configuration error".to_string())
             }
+            AppError::SecretUnavailable(msg) => {
+                tracing::error!("Item secret unavailable: {}", msg);
+                (StatusCode::INTERNAL_SERVER_ERROR, msg)
+            }
             AppError::RateLimit(msg) => (StatusCode::TOO_MANY_REQUESTS, msg),
+            AppError::InsufficientStorage(msg) => (StatusCode::INSUFFICIENT_STORAGE, msg),
+            AppError::NotImplemented(msg) => (StatusCode::NOT_IMPLEMENTED, msg),
+            AppError::ServiceUnavailable(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
+            AppError::UnsupportedMediaType(msg) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, msg),
+            AppError::HeadersTooLarge(msg) => (StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, msg),
+            AppError::PayloadTooLarge(msg) => (StatusCode::PAYLOAD_TOO_LARGE, msg),
+            AppError::AmbiguousBodyLength(msg) => (StatusCode::BAD_REQUEST, msg),
             AppError::Middleware(msg) => {
                 tracing::error!("Middleware error: {}", msg);
                 (StatusCode::INTERNAL_SERVER_ERROR, "Middleware error".to_string())
@@ -135,12 +186,45 @@ impl IntoResponse for AppError {
     }
 }
 
+/// 409 carrying what the client needs to merge: the item as it is now and
+/// the fields its write would have changed.
+fn version_conflict_response(conflict: &crate::models::items::VersionConflict) -> Response {
+    let status = StatusCode::CONFLICT;
+    let body = Json(json!({
+        "error": format!(
+            "Item {} has changed since version {}; merge with the current version and retry",
+            conflict.item_id, conflict.expected_version
+        ),
+        "status": status.as_u16(),
+        "current_version": conflict.current_version,
+        "changed_fields": conflict.changed_fields,
+        "current": crate::item_secrets::redacted(&conflict.current),
+    }));
+
+    (status, body).into_response()
+}
+
+/// 409 naming the item that already has the name.
+fn name_conflict_response(conflict: &crate::item_names::NameConflict) -> Response {
+    let status = StatusCode::CONFLICT;
+    let body = Json(json!({
+        "error": format!("Item {} is already named {:?}; item names must be unique", conflict.item_id, conflict.name),
+        "status": status.as_u16(),
+        "conflicting_id": conflict.item_id,
+    }));
+
+    (status, body).into_response()
+}
+
 impl From<sqlx::Error> for AppError {
     fn from(err: sqlx::Error) -> Self {
         match err {
             sqlx::Error::RowNotFound => AppError::NotFound("Resource not found".to_string()),
             sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
-                AppError::BadRequest("Resource already exists".to_string())
+                AppError::Conflict("Resource already exists".to_string())
+            }
+            sqlx::Error::Database(db_err) if db_err.is_foreign_key_violation() => {
+                AppError::Conflict("Resource is referenced by or refers to another resource".to_string())
             }
             _ => AppError::Database(err.to_string()),
         }
@@ -0,0 +1,568 @@
+//! In-process domain events
+//!
+//! Writes announce what they changed by publishing a [`DomainEvent`] on the
+//! state's [`EventBus`] once the data is stored; the side effects that
+//! follow a write live in [`EventSubscriber`]s registered at startup, so a
+//! new side effect is a new subscriber rather than a change to every
+//! handler. The built-in subscribers drop cached responses and suggestions,
+//! broadcast item changes over WebSockets, queue webhooks and record
+//! deletions and uploads in the audit log.
+//!
+//! Published inside a transaction, events are delivered once it commits and
+//! never when it is rolled back. Delivery runs in the publishing task:
+//! subscribers that drop caches go first, then the others in the order they
+//! were registered. Events about the same entity are delivered one
+//! publication at a time, in the order they were published. A subscriber
+//! that fails, or panics, is logged and counted without affecting the write
+//! or the other subscribers; the counts are listed by `GET /api/admin/events`.
+//! Subscribers must not publish events themselves.
+
+use crate::audit::{AuditEvent, AuditLog};
+use crate::cache::CacheManager;
+use crate::error::{AppError, Result};
+use crate::files::FileMetadata;
+use crate::item_secrets;
+use crate::jobs::Job;
+use crate::search::SearchEngine;
+use crate::store::Item;
+use crate::transaction;
+use crate::webhooks::{WebhookEvent, WebhookService};
+use crate::websocket::{WebSocketEvent, WebSocketManager};
+use futures_util::FutureExt;
+use parking_lot::RwLock;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Mutex as AsyncMutex;
+use tracing::warn;
+
+/// Locks serializing delivery per entity; entities sharing one only wait
+/// for each other.
+const ENTITY_LOCKS: usize = 64;
+
+/// Something that happened to the stored data.
+#[derive(Debug, Clone)]
+pub enum DomainEvent {
+    ItemCreated(Item),
+    ItemUpdated(Item),
+    /// `item` is the item as it was, when it was read before deletion.
+    ItemDeleted { id: u64, item: Option<Item> },
+    FileUploaded(FileMetadata),
+    JobCompleted(Job),
+}
+
+impl DomainEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ItemCreated(_) => "item.created",
+            Self::ItemUpdated(_) => "item.updated",
+            Self::ItemDeleted { .. } => "item.deleted",
+            Self::FileUploaded(_) => "file.uploaded",
+            Self::JobCompleted(_) => "job.completed",
+        }
+    }
+
+    /// The entity the event is about, such as `item:3`.
+    pub fn entity(&self) -> String {
+        match self {
+            Self::ItemCreated(item) | Self::ItemUpdated(item) => format!("item:{}", item.id),
+            Self::ItemDeleted { id, .. } => format!("item:{}", id),
+            Self::FileUploaded(file) => format!("file:{}", file.id),
+            Self::JobCompleted(job) => format!("job:{}", job.id),
+        }
+    }
+
+    /// The id of the item the event is about, for item events.
+    pub fn item_id(&self) -> Option<u64> {
+        match self {
+            Self::ItemCreated(item) | Self::ItemUpdated(item) => Some(item.id),
+            Self::ItemDeleted { id, .. } => Some(*id),
+            _ => None,
+        }
+    }
+
+    fn lock_index(&self) -> usize {
+        let mut hasher = DefaultHasher::new();
+        self.entity().hash(&mut hasher);
+        (hasher.finish() % ENTITY_LOCKS as u64) as usize
+    }
+}
+
+/// When a subscriber is delivered events relative to the others.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// Drops cached data, before anyone is told of the change.
+    Invalidate,
+    #[default]
+    Notify,
+}
+
+/// A side effect of writes. `handle` is given every event of one
+/// publication together, so that work shared by them, such as dropping a
+/// list cache, can be done once.
+#[async_trait::async_trait]
+pub trait EventSubscriber: Send + Sync {
+    /// Identifies the subscriber; subscribing another with the same name
+    /// replaces it.
+    fn name(&self) -> &str;
+
+    fn stage(&self) -> Stage {
+        Stage::Notify
+    }
+
+    async fn handle(&self, events: &[DomainEvent]) -> Result<()>;
+}
+
+/// Deliveries to one subscriber.
+#[derive(Debug, Clone, Serialize)]
+pub struct SubscriberStats {
+    pub name: String,
+    /// Events handled without error.
+    pub delivered: u64,
+    /// Events whose delivery failed or panicked.
+    pub failed: u64,
+}
+
+struct Registered {
+    subscriber: Arc<dyn EventSubscriber>,
+    delivered: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// Delivers published events to the registered subscribers.
+#[derive(Clone)]
+pub struct EventBus {
+    subscribers: Arc<RwLock<Vec<Arc<Registered>>>>,
+    entity_locks: Arc<[AsyncMutex<()>]>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self {
+            subscribers: Arc::new(RwLock::new(Vec::new())),
+            entity_locks: (0..ENTITY_LOCKS).map(|_| AsyncMutex::new(())).collect(),
+        }
+    }
+
+    /// Registers `subscriber`, replacing any with the same name.
+    pub fn subscribe(&self, subscriber: impl EventSubscriber + 'static) {
+        let registered = Arc::new(Registered {
+            subscriber: Arc::new(subscriber),
+            delivered: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        });
+        let mut subscribers = self.subscribers.write();
+        let name = registered.subscriber.name();
+        match subscribers.iter().position(|existing| existing.subscriber.name() == name) {
+            Some(index) => subscribers[index] = registered,
+            None => subscribers.push(registered),
+        }
+        subscribers.sort_by_key(|registered| registered.subscriber.stage());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.read().is_empty()
+    }
+
+    pub fn stats(&self) -> Vec<SubscriberStats> {
+        self.subscribers
+            .read()
+            .iter()
+            .map(|registered| SubscriberStats {
+                name: registered.subscriber.name().to_string(),
+                delivered: registered.delivered.load(Ordering::Relaxed),
+                failed: registered.failed.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Publishes `event`, delivering it once the current transaction
+    /// commits, or straight away outside one.
+    pub async fn publish(&self, event: DomainEvent) {
+        self.publish_all(vec![event]).await;
+    }
+
+    /// Publishes several events together, so each subscriber handles them
+    /// in one call.
+    pub async fn publish_all(&self, events: Vec<DomainEvent>) {
+        if events.is_empty() {
+            return;
+        }
+        let bus = self.clone();
+        transaction::after_commit(async move { bus.deliver(events).await }).await;
+    }
+
+    async fn deliver(&self, events: Vec<DomainEvent>) {
+        let mut indexes: Vec<usize> = events.iter().map(DomainEvent::lock_index).collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        // Taken in index order, so that two publications cannot deadlock.
+        let mut guards = Vec::with_capacity(indexes.len());
+        for index in indexes {
+            guards.push(self.entity_locks[index].lock().await);
+        }
+
+        let subscribers = self.subscribers.read().clone();
+        for registered in subscribers {
+            let name = registered.subscriber.name();
+            let outcome = AssertUnwindSafe(registered.subscriber.handle(&events))
+                .catch_unwind()
+                .await
+                .unwrap_or_else(|_| Err(AppError::Other(anyhow::anyhow!("the subscriber panicked"))));
+            match outcome {
+                Ok(()) => {
+                    registered.delivered.fetch_add(events.len() as u64, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    registered.failed.fetch_add(events.len() as u64, Ordering::Relaxed);
+                    warn!("Event subscriber {} failed to handle {}: {}", name, events[0].name(), e);
+                }
+            }
+        }
+    }
+}
+
+/// Drops cached responses for changed items, and item lists once per
+/// publication.
+pub struct CacheInvalidation {
+    cache_manager: CacheManager,
+}
+
+impl CacheInvalidation {
+    pub fn new(cache_manager: CacheManager) -> Self {
+        Self { cache_manager }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventSubscriber for CacheInvalidation {
+    fn name(&self) -> &str {
+        "cache"
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::Invalidate
+    }
+
+    async fn handle(&self, events: &[DomainEvent]) -> Result<()> {
+        let mut items_changed = false;
+        for event in events {
+            match event {
+                DomainEvent::ItemUpdated(item) => self.cache_manager.invalidate_item_cache(item.id),
+                DomainEvent::ItemDeleted { id, .. } => self.cache_manager.invalidate_item_cache(*id),
+                _ => {}
+            }
+            items_changed |= event.item_id().is_some();
+        }
+        if items_changed {
+            self.cache_manager.invalidate_items_cache();
+            self.cache_manager.invalidate_search_cache();
+        }
+        Ok(())
+    }
+}
+
+/// Drops cached search suggestions, which any item write may change.
+pub struct SearchCacheInvalidation {
+    search_engine: SearchEngine,
+}
+
+impl SearchCacheInvalidation {
+    pub fn new(search_engine: SearchEngine) -> Self {
+        Self { search_engine }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventSubscriber for SearchCacheInvalidation {
+    fn name(&self) -> &str {
+        "search_cache"
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::Invalidate
+    }
+
+    async fn handle(&self, events: &[DomainEvent]) -> Result<()> {
+        if events.iter().any(|event| event.item_id().is_some()) {
+            self.search_engine.invalidate_suggestions();
+        }
+        Ok(())
+    }
+}
+
+/// Broadcasts item changes, with secrets redacted, and uploads to WebSocket
+/// clients.
+pub struct WebSocketBroadcast {
+    manager: WebSocketManager,
+}
+
+impl WebSocketBroadcast {
+    pub fn new(manager: WebSocketManager) -> Self {
+        Self { manager }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventSubscriber for WebSocketBroadcast {
+    fn name(&self) -> &str {
+        "websocket"
+    }
+
+    async fn handle(&self, events: &[DomainEvent]) -> Result<()> {
+        for event in events {
+            let event = match event {
+                DomainEvent::ItemCreated(item) => WebSocketEvent::ItemCreated(item_secrets::redacted(item)),
+                DomainEvent::ItemUpdated(item) => WebSocketEvent::ItemUpdated(item_secrets::redacted(item)),
+                DomainEvent::ItemDeleted { id, .. } => WebSocketEvent::ItemDeleted(*id),
+                DomainEvent::FileUploaded(file) => WebSocketEvent::Custom(serde_json::json!({
+                    "type": "file_uploaded",
+                    "file": {
+                        "id": file.id,
+                        "filename": file.original_filename,
+                        "size": file.size,
+                        "item_id": file.item_id
+                    }
+                })),
+                DomainEvent::JobCompleted(_) => continue,
+            };
+            self.manager.broadcast(event).await;
+        }
+        Ok(())
+    }
+}
+
+/// Queues webhook deliveries for item changes. Deletions are only sent
+/// when the item as it was is known.
+pub struct WebhookPublisher {
+    webhooks: WebhookService,
+}
+
+impl WebhookPublisher {
+    pub fn new(webhooks: WebhookService) -> Self {
+        Self { webhooks }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventSubscriber for WebhookPublisher {
+    fn name(&self) -> &str {
+        "webhooks"
+    }
+
+    async fn handle(&self, events: &[DomainEvent]) -> Result<()> {
+        for event in events {
+            let (event, item) = match event {
+                DomainEvent::ItemCreated(item) => (WebhookEvent::ItemCreated, item),
+                DomainEvent::ItemUpdated(item) => (WebhookEvent::ItemUpdated, item),
+                DomainEvent::ItemDeleted { item: Some(item), .. } => (WebhookEvent::ItemDeleted, item),
+                _ => continue,
+            };
+            self.webhooks.publish_item(event, &item_secrets::redacted(item)).await;
+        }
+        Ok(())
+    }
+}
+
+/// Records item deletions and file uploads in the audit log.
+pub struct AuditTrail {
+    audit_log: AuditLog,
+}
+
+impl AuditTrail {
+    pub fn new(audit_log: AuditLog) -> Self {
+        Self { audit_log }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventSubscriber for AuditTrail {
+    fn name(&self) -> &str {
+        "audit"
+    }
+
+    async fn handle(&self, events: &[DomainEvent]) -> Result<()> {
+        for event in events {
+            let details = match event {
+                DomainEvent::ItemDeleted { item, .. } => {
+                    serde_json::json!({ "name": item.as_ref().map(|item| item.name.as_str()) })
+                }
+                DomainEvent::FileUploaded(file) => serde_json::json!({
+                    "filename": file.original_filename,
+                    "content_type": file.content_type,
+                    "size": file.size,
+                    "uploaded_by": file.uploaded_by,
+                }),
+                _ => continue,
+            };
+            self.audit_log
+                .record(AuditEvent::new(event.name()).with_target(event.entity()).with_details(details));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+
+    /// Records what it is given, failing or panicking on request.
+    struct Recorder {
+        name: &'static str,
+        stage: Stage,
+        seen: Arc<Mutex<Vec<String>>>,
+        fail: bool,
+        panic: bool,
+    }
+
+    impl Recorder {
+        fn new(name: &'static str, seen: &Arc<Mutex<Vec<String>>>) -> Self {
+            Self {
+                name,
+                stage: Stage::Notify,
+                seen: seen.clone(),
+                fail: false,
+                panic: false,
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl EventSubscriber for Recorder {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn stage(&self) -> Stage {
+            self.stage
+        }
+
+        async fn handle(&self, events: &[DomainEvent]) -> Result<()> {
+            for event in events {
+                self.seen.lock().push(format!("{} {} {}", self.name, event.name(), event.entity()));
+            }
+            if self.panic {
+                panic!("subscriber bug");
+            }
+            match self.fail {
+                true => Err(AppError::ServiceUnavailable("unavailable".to_string())),
+                false => Ok(()),
+            }
+        }
+    }
+
+    fn item(id: u64) -> Item {
+        Item {
+            id,
+            name: format!("Item {}", id),
+            description: None,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+            tags: Vec::new(),
+            metadata: None,
+            version: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failing_subscribers_are_counted_and_isolated() {
+        let bus = EventBus::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        bus.subscribe(Recorder { fail: true, ..Recorder::new("failing", &seen) });
+        bus.subscribe(Recorder { panic: true, ..Recorder::new("panicking", &seen) });
+        bus.subscribe(Recorder::new("last", &seen));
+        bus.subscribe(Recorder {
+            stage: Stage::Invalidate,
+            ..Recorder::new("first", &seen)
+        });
+
+        bus.publish_all(vec![DomainEvent::ItemCreated(item(1)), DomainEvent::ItemDeleted { id: 2, item: None }])
+            .await;
+
+        assert_eq!(
+            *seen.lock(),
+            [
+                "first item.created item:1",
+                "first item.deleted item:2",
+                "failing item.created item:1",
+                "failing item.deleted item:2",
+                "panicking item.created item:1",
+                "panicking item.deleted item:2",
+                "last item.created item:1",
+                "last item.deleted item:2",
+            ]
+        );
+        let stats: Vec<_> = bus.stats().into_iter().map(|s| (s.name, s.delivered, s.failed)).collect();
+        assert_eq!(
+            stats,
+            [
+                ("first".to_string(), 2, 0),
+                ("failing".to_string(), 0, 2),
+                ("panicking".to_string(), 0, 2),
+                ("last".to_string(), 2, 0),
+            ]
+        );
+
+        // A subscriber with a taken name replaces the old one.
+        bus.subscribe(Recorder::new("failing", &seen));
+        assert_eq!(bus.stats()[1].failed, 0);
+        assert_eq!(bus.stats().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_events_about_one_entity_are_delivered_in_order() {
+        /// Takes its time over odd versions, so that deliveries would
+        /// overtake each other if they were not serialized.
+        struct Slow(Arc<Mutex<Vec<u64>>>);
+
+        #[async_trait::async_trait]
+        impl EventSubscriber for Slow {
+            fn name(&self) -> &str {
+                "slow"
+            }
+
+            async fn handle(&self, events: &[DomainEvent]) -> Result<()> {
+                for event in events {
+                    if let DomainEvent::ItemUpdated(item) = event {
+                        if item.version % 2 == 1 {
+                            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                        }
+                        self.0.lock().push(item.version);
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        let bus = EventBus::new();
+        let versions = Arc::new(Mutex::new(Vec::new()));
+        bus.subscribe(Slow(versions.clone()));
+
+        let mut tasks = Vec::new();
+        for version in 1..=6 {
+            let bus = bus.clone();
+            tasks.push(tokio::spawn(async move {
+                bus.publish(DomainEvent::ItemUpdated(Item { version, ..item(7) })).await;
+            }));
+            // Published in order, delivered concurrently.
+            tokio::task::yield_now().await;
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let versions = versions.lock().clone();
+        assert_eq!(versions.len(), 6);
+        assert!(versions.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", versions);
+    }
+}
@@ -1,5 +1,7 @@
 //! Custom extractors for better request handling
 
 pub mod json;
+pub mod query;
 
-pub use json::UnicodeJson;
\ No newline at end of file
+pub use json::UnicodeJson;
+pub use query::{QueryParams, StrictQuery};
\ No newline at end of file
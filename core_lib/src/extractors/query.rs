@@ -0,0 +1,261 @@
+//! Query string extractor that notices parameters nobody reads
+//!
+//! serde skips query parameters a handler doesn't declare, so a typo like
+//! `?page_sise=10` is silently ignored. [`StrictQuery`] deserializes like
+//! [`Query`] and also compares the parameter names against those the
+//! target accepts. What happens to unknown ones follows
+//! `validation.unknown_query_params`: they are logged and named in the
+//! `X-Unknown-Params` header, or the request is refused with 400 listing
+//! them and the closest accepted names.
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::{rejection::QueryRejection, FromRequestParts, Query, Request},
+    http::{request::Parts, HeaderValue, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use parking_lot::Mutex;
+use serde::de::{self, DeserializeOwned, Deserializer, Visitor};
+use tracing::warn;
+
+use crate::config::UnknownParamsPolicy;
+use crate::error::AppError;
+use crate::search::similarity::levenshtein;
+use crate::validation::{FieldValidationError, ValidationError};
+use crate::AppState;
+
+pub const UNKNOWN_PARAMS_HEADER: &str = "x-unknown-params";
+
+/// Accepted on every endpoint, read by middleware rather than handlers.
+const GLOBAL_PARAMS: &[&str] = &["envelope"];
+
+/// Most suggestions offered for one unknown parameter.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// A query string type [`StrictQuery`] can check.
+///
+/// The default [`param_names`](QueryParams::param_names) reads the field
+/// names of a struct deriving `Deserialize`; types that deserialize some
+/// other way, such as with `#[serde(flatten)]`, list their names instead.
+pub trait QueryParams: DeserializeOwned {
+    /// The parameter names this type accepts.
+    fn param_names() -> Vec<&'static str> {
+        struct_fields::<Self>()
+    }
+
+    fn from_uri(uri: &Uri) -> Result<Self, QueryRejection> {
+        Query::<Self>::try_from_uri(uri).map(|Query(value)| value)
+    }
+}
+
+/// Two query types read from the same query string, for handlers that take
+/// both.
+impl<A: QueryParams, B: QueryParams> QueryParams for (A, B) {
+    fn param_names() -> Vec<&'static str> {
+        let mut names = A::param_names();
+        names.extend(B::param_names());
+        names
+    }
+
+    fn from_uri(uri: &Uri) -> Result<Self, QueryRejection> {
+        Ok((A::from_uri(uri)?, B::from_uri(uri)?))
+    }
+}
+
+/// [`Query`] that also reports parameters `T` doesn't accept.
+#[derive(Debug, Clone)]
+pub struct StrictQuery<T>(pub T);
+
+#[async_trait]
+impl<T> FromRequestParts<AppState> for StrictQuery<T>
+where
+    T: QueryParams + Send,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let unknown = unknown_params(&parts.uri, &T::param_names());
+        if !unknown.is_empty() {
+            match state.validation_config.unknown_query_params {
+                UnknownParamsPolicy::Reject => {
+                    return Err(AppError::from(unknown_params_error(&unknown, &T::param_names())).into_response());
+                }
+                UnknownParamsPolicy::Warn => {
+                    warn!("Unknown query parameters on {}: {}", parts.uri.path(), unknown.join(", "));
+                    if let Some(reported) = parts.extensions.get::<ReportedParams>() {
+                        reported.0.lock().extend(unknown);
+                    }
+                }
+            }
+        }
+
+        T::from_uri(&parts.uri)
+            .map(StrictQuery)
+            .map_err(IntoResponse::into_response)
+    }
+}
+
+/// Where [`StrictQuery`] leaves the unknown parameters of a request for
+/// [`unknown_params_header`] to report.
+#[derive(Clone, Default)]
+struct ReportedParams(Arc<Mutex<Vec<String>>>);
+
+/// Names the unknown parameters [`StrictQuery`] let through in
+/// `X-Unknown-Params`. Layered on the routes using it rather than the whole
+/// router, so other requests don't pay for it.
+pub async fn unknown_params_header(mut request: Request, next: Next) -> Response {
+    let reported = ReportedParams::default();
+    request.extensions_mut().insert(reported.clone());
+    let mut response = next.run(request).await;
+
+    let names = std::mem::take(&mut *reported.0.lock());
+    if !names.is_empty() {
+        if let Ok(value) = HeaderValue::from_str(&names.join(", ")) {
+            response.headers_mut().insert(UNKNOWN_PARAMS_HEADER, value);
+        }
+    }
+    response
+}
+
+/// The parameters in `uri` that aren't in `known`, each once, in order of
+/// name. Array-style names such as `tags[]` or `tags[0]` count as `tags`.
+fn unknown_params(uri: &Uri, known: &[&str]) -> Vec<String> {
+    let Ok(Query(pairs)) = Query::<Vec<(String, String)>>::try_from_uri(uri) else {
+        return Vec::new();
+    };
+    pairs
+        .into_iter()
+        .map(|(name, _)| name.split('[').next().unwrap_or_default().to_string())
+        .filter(|name| !name.is_empty())
+        .filter(|name| !known.contains(&name.as_str()) && !GLOBAL_PARAMS.contains(&name.as_str()))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// The accepted names within a few edits of `name`, closest first.
+fn suggestions<'a>(name: &str, known: &[&'a str]) -> Vec<&'a str> {
+    let max_distance = 2.max(name.chars().count() / 3);
+    let mut close: Vec<(usize, &str)> = known
+        .iter()
+        .map(|candidate| (levenshtein(name, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    close.sort();
+    close.into_iter().take(MAX_SUGGESTIONS).map(|(_, candidate)| candidate).collect()
+}
+
+fn unknown_params_error(unknown: &[String], known: &[&str]) -> ValidationError {
+    let fields = unknown
+        .iter()
+        .map(|name| {
+            let message = match suggestions(name, known).as_slice() {
+                [] => "Unknown query parameter".to_string(),
+                close => format!("Unknown query parameter; did you mean {}?", close.join(" or ")),
+            };
+            FieldValidationError {
+                field: name.clone(),
+                value: None,
+                errors: vec![message],
+                error_codes: vec!["unknown_parameter".to_string()],
+            }
+        })
+        .collect();
+    ValidationError::Fields {
+        summary: "Unknown query parameters".to_string(),
+        fields,
+    }
+}
+
+/// The field names a derived `Deserialize` gives for `T`, read by handing
+/// it a deserializer that only records them. Empty when `T` doesn't
+/// deserialize as a struct.
+fn struct_fields<T: DeserializeOwned>() -> Vec<&'static str> {
+    struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> Deserializer<'de> for FieldNames<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("fields recorded"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields.to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Listing {
+        page: Option<u32>,
+        page_size: Option<u32>,
+        tags: Option<Vec<String>>,
+    }
+
+    impl QueryParams for Listing {}
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Format {
+        format: Option<String>,
+    }
+
+    impl QueryParams for Format {}
+
+    #[test]
+    fn test_param_names_from_serde() {
+        assert_eq!(Listing::param_names(), vec!["page", "page_size", "tags"]);
+        assert_eq!(<(Listing, Format)>::param_names(), vec!["page", "page_size", "tags", "format"]);
+    }
+
+    #[test]
+    fn test_unknown_params() {
+        let known = Listing::param_names();
+        let unknown = |uri: &str| unknown_params(&uri.parse().unwrap(), &known);
+
+        assert!(unknown("/items?page=2&page_size=10&envelope=false").is_empty());
+        assert_eq!(unknown("/items?page_sise=10&page=1"), vec!["page_sise"]);
+        assert_eq!(unknown("/items?sort=a&sort=b&pge=1"), vec!["pge", "sort"]);
+        assert!(unknown("/items?tags[]=a&tags[]=b&tags%5B0%5D=c").is_empty());
+        assert_eq!(unknown("/items?tag[]=a"), vec!["tag"]);
+        assert!(unknown("/items").is_empty());
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let known = Listing::param_names();
+        assert_eq!(suggestions("page_sise", &known), vec!["page_size"]);
+        assert_eq!(suggestions("pag", &known), vec!["page", "tags"]);
+        assert!(suggestions("completely_unrelated", &known).is_empty());
+
+        let error = unknown_params_error(&["page_sise".to_string()], &known);
+        assert!(error.to_string().contains("did you mean page_size?"), "{}", error);
+    }
+}
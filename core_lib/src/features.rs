@@ -0,0 +1,291 @@
+//! Switches for turning optional subsystems off while the server runs
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use chrono::SecondsFormat;
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use tokio::sync::Notify;
+
+use crate::clock::{SharedClock, SystemClock};
+use crate::config::FeaturesConfig;
+use crate::error::{AppError, Result};
+
+/// A subsystem that can be switched off without a redeploy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Subsystem {
+    /// Job submissions are refused with 503 and workers stop taking jobs;
+    /// queued jobs wait until the switch is turned back on.
+    Jobs,
+    /// WebSocket upgrades and event streams are refused with 503 and nothing
+    /// is broadcast. Open connections stay open.
+    Websockets,
+    /// Searches skip the full-text index and filter items in memory.
+    Search,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 3] = [Subsystem::Jobs, Subsystem::Websockets, Subsystem::Search];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Subsystem::Jobs => "jobs",
+            Subsystem::Websockets => "websockets",
+            Subsystem::Search => "search",
+        }
+    }
+}
+
+impl std::fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for Subsystem {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Subsystem::ALL
+            .into_iter()
+            .find(|subsystem| subsystem.name() == s)
+            .ok_or_else(|| {
+                AppError::NotFound(format!(
+                    "Unknown feature '{}'; expected one of {}",
+                    s,
+                    Subsystem::ALL.map(|subsystem| subsystem.name()).join(", ")
+                ))
+            })
+    }
+}
+
+/// Whether one subsystem is on. Clones share the state, so the subsystem
+/// holds a clone and sees every change at once.
+#[derive(Debug, Clone)]
+pub struct FeatureSwitch {
+    enabled: Arc<AtomicBool>,
+    changed: Arc<Notify>,
+}
+
+impl FeatureSwitch {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled: Arc::new(AtomicBool::new(enabled)),
+            changed: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    /// Returns whether the switch was on before.
+    pub fn set(&self, enabled: bool) -> bool {
+        let was_enabled = self.enabled.swap(enabled, Ordering::AcqRel);
+        self.changed.notify_waiters();
+        was_enabled
+    }
+
+    /// Waits until the switch is on, returning at once if it already is.
+    pub async fn wait_until_enabled(&self) {
+        loop {
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            if self.is_enabled() {
+                return;
+            }
+            changed.await;
+        }
+    }
+}
+
+impl Default for FeatureSwitch {
+    fn default() -> Self {
+        Self::new(true)
+    }
+}
+
+/// The switch of every [`Subsystem`], starting as `features` in the config
+/// says. With a [`FeatureStore`], a change made at runtime is saved and
+/// outlasts restarts, taking precedence over the config until it is
+/// changed back.
+#[derive(Clone, Default)]
+pub struct FeatureSwitches {
+    jobs: FeatureSwitch,
+    websockets: FeatureSwitch,
+    search: FeatureSwitch,
+    store: Option<FeatureStore>,
+}
+
+impl FeatureSwitches {
+    pub fn from_config(config: &FeaturesConfig) -> Self {
+        Self {
+            jobs: FeatureSwitch::new(config.jobs),
+            websockets: FeatureSwitch::new(config.websockets),
+            search: FeatureSwitch::new(config.search),
+            store: None,
+        }
+    }
+
+    /// Saves changes in `store`, and applies the ones it already holds.
+    pub async fn with_store(mut self, store: FeatureStore) -> Result<Self> {
+        for (subsystem, enabled) in store.load().await? {
+            self.switch(subsystem).set(enabled);
+        }
+        self.store = Some(store);
+        Ok(self)
+    }
+
+    pub fn switch(&self, subsystem: Subsystem) -> &FeatureSwitch {
+        match subsystem {
+            Subsystem::Jobs => &self.jobs,
+            Subsystem::Websockets => &self.websockets,
+            Subsystem::Search => &self.search,
+        }
+    }
+
+    pub fn is_enabled(&self, subsystem: Subsystem) -> bool {
+        self.switch(subsystem).is_enabled()
+    }
+
+    /// Turns `subsystem` on or off, saving the change when there is a
+    /// store. Returns whether it was on before.
+    pub async fn set(&self, subsystem: Subsystem, enabled: bool, changed_by: &str) -> Result<bool> {
+        if let Some(store) = &self.store {
+            store.save(subsystem, enabled, changed_by).await?;
+        }
+        Ok(self.switch(subsystem).set(enabled))
+    }
+
+    /// Whether each subsystem is on, by name.
+    pub fn states(&self) -> BTreeMap<&'static str, bool> {
+        Subsystem::ALL
+            .into_iter()
+            .map(|subsystem| (subsystem.name(), self.is_enabled(subsystem)))
+            .collect()
+    }
+
+    /// The subsystems that are off.
+    pub fn disabled(&self) -> Vec<Subsystem> {
+        Subsystem::ALL
+            .into_iter()
+            .filter(|subsystem| !self.is_enabled(*subsystem))
+            .collect()
+    }
+}
+
+/// Keeps switch changes in the `feature_switches` table.
+#[derive(Clone)]
+pub struct FeatureStore {
+    pool: SqlitePool,
+    clock: SharedClock,
+}
+
+impl FeatureStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self {
+            pool,
+            clock: SystemClock::shared(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Saved states. Rows naming a subsystem this build doesn't have are
+    /// skipped.
+    pub async fn load(&self) -> Result<Vec<(Subsystem, bool)>> {
+        let rows = sqlx::query("SELECT name, enabled FROM feature_switches")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut states = Vec::new();
+        for row in rows {
+            let name: String = row.try_get("name")?;
+            let enabled: bool = row.try_get("enabled")?;
+            match name.parse() {
+                Ok(subsystem) => states.push((subsystem, enabled)),
+                Err(_) => tracing::warn!("Ignoring saved state of unknown feature '{}'", name),
+            }
+        }
+        Ok(states)
+    }
+
+    pub async fn save(&self, subsystem: Subsystem, enabled: bool, changed_by: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO feature_switches (name, enabled, changed_by, changed_at)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT(name) DO UPDATE SET
+                enabled = excluded.enabled,
+                changed_by = excluded.changed_by,
+                changed_at = excluded.changed_at
+            "#,
+        )
+        .bind(subsystem.name())
+        .bind(enabled)
+        .bind(changed_by)
+        .bind(self.clock.now().to_rfc3339_opts(SecondsFormat::Micros, true))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::run_migrations;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_wait_until_enabled_returns_once_switched_on() {
+        let switch = FeatureSwitch::new(false);
+        let waiter = tokio::spawn({
+            let switch = switch.clone();
+            async move { switch.wait_until_enabled().await }
+        });
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+
+        assert!(!switch.set(true));
+        tokio::time::timeout(Duration::from_secs(1), waiter).await.unwrap().unwrap();
+        tokio::time::timeout(Duration::from_secs(1), switch.wait_until_enabled()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_saved_states_override_the_config() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        run_migrations(pool.clone()).await.unwrap();
+        let config = FeaturesConfig { jobs: true, websockets: false, search: true };
+
+        let switches = FeatureSwitches::from_config(&config)
+            .with_store(FeatureStore::new(pool.clone()))
+            .await
+            .unwrap();
+        assert_eq!(switches.disabled(), vec![Subsystem::Websockets]);
+        assert!(switches.set(Subsystem::Jobs, false, "admin").await.unwrap());
+        assert!(!switches.set(Subsystem::Websockets, true, "admin").await.unwrap());
+
+        let restarted = FeatureSwitches::from_config(&config)
+            .with_store(FeatureStore::new(pool))
+            .await
+            .unwrap();
+        assert_eq!(restarted.disabled(), vec![Subsystem::Jobs]);
+        assert!(restarted.is_enabled(Subsystem::Websockets));
+    }
+
+    #[test]
+    fn test_subsystems_parse_by_name() {
+        assert_eq!("websockets".parse::<Subsystem>().unwrap(), Subsystem::Websockets);
+        assert!(matches!("files".parse::<Subsystem>(), Err(AppError::NotFound(_))));
+    }
+}
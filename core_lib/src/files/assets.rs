@@ -0,0 +1,101 @@
+//! Public, content-addressed URLs for uploaded images
+//!
+//! Publishing a file gives it the URL `/public/assets/{sha256}.{ext}`,
+//! named by the hash of its contents. The URL never changes while the
+//! contents don't, and a re-upload of the same image gets the same URL, so
+//! it is served without authentication and cached as immutable. Once every
+//! file with that content is unpublished or deleted, the URL answers 410.
+//!
+//! Only raster images may be published: browsers render them inline
+//! without running anything in them, unlike SVG or PDF. Two files can only
+//! share a URL when their contents are byte for byte the same; if they ever
+//! differ, publishing fails and serving refuses rather than guessing.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Path that public assets are served under.
+pub const PUBLIC_ASSETS_PATH: &str = "/public/assets";
+
+/// `Cache-Control` for public assets, whose contents never change.
+pub const PUBLIC_ASSET_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Content types that may be published, with the extension of their URLs.
+const PUBLISHABLE_TYPES: &[(&str, &str)] = &[
+    ("image/jpeg", "jpg"),
+    ("image/png", "png"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+];
+
+/// The extension of the public URL of a file of `content_type`, or `None`
+/// when such files may not be published.
+pub fn extension_for(content_type: &str) -> Option<&'static str> {
+    let content_type = content_type.trim().to_ascii_lowercase();
+    PUBLISHABLE_TYPES
+        .iter()
+        .find(|(publishable, _)| *publishable == content_type)
+        .map(|(_, extension)| *extension)
+}
+
+/// The content types that may be published.
+pub fn publishable_types() -> impl Iterator<Item = &'static str> {
+    PUBLISHABLE_TYPES.iter().map(|(content_type, _)| *content_type)
+}
+
+/// Splits an asset name such as `{sha256}.png` into its hash and
+/// extension. Names that could not have been published are `None`.
+pub fn parse_asset_name(name: &str) -> Option<(&str, &str)> {
+    let (sha256, extension) = name.split_once('.')?;
+    let is_hash = sha256.len() == 64 && sha256.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+    let known = PUBLISHABLE_TYPES.iter().any(|(_, known)| *known == extension);
+    (is_hash && known).then_some((sha256, extension))
+}
+
+/// A file's publication under its content hash.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PublicAsset {
+    pub file_id: Uuid,
+    pub sha256: String,
+    pub extension: String,
+    pub content_type: String,
+    pub published_by: u64,
+    pub published_at: DateTime<Utc>,
+    pub unpublished_at: Option<DateTime<Utc>>,
+}
+
+impl PublicAsset {
+    pub fn url(&self) -> String {
+        format!("{}/{}.{}", PUBLIC_ASSETS_PATH, self.sha256, self.extension)
+    }
+
+    pub fn is_published(&self) -> bool {
+        self.unpublished_at.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_raster_images_are_publishable() {
+        assert_eq!(extension_for("image/png"), Some("png"));
+        assert_eq!(extension_for("Image/JPEG"), Some("jpg"));
+        assert_eq!(extension_for("image/svg+xml"), None);
+        assert_eq!(extension_for("application/pdf"), None);
+        assert_eq!(extension_for("text/plain"), None);
+    }
+
+    #[test]
+    fn test_parse_asset_name() {
+        let hash = "ab".repeat(32);
+        assert_eq!(parse_asset_name(&format!("{}.png", hash)), Some((hash.as_str(), "png")));
+        assert_eq!(parse_asset_name(&format!("{}.svg", hash)), None);
+        assert_eq!(parse_asset_name(&format!("{}.png", "AB".repeat(32))), None);
+        assert_eq!(parse_asset_name(&format!("{}.png", &hash[1..])), None);
+        assert_eq!(parse_asset_name(&format!("{}.png.png", hash)), None);
+        assert_eq!(parse_asset_name(&hash), None);
+    }
+}
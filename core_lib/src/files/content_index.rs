@@ -0,0 +1,212 @@
+//! Full-text index of file contents
+//!
+//! Uploads whose type has extractable text get a `FileTextExtraction` job
+//! that stores the text in `files_fts`, where `GET /api/files?search_content=true`
+//! and `content:` search terms look for it. Plain text, CSV and JSON are
+//! read as they are; PDFs need the `pdf` feature. A file whose text cannot
+//! be extracted is marked `not_indexed` and is otherwise unaffected.
+//! `POST /api/admin/files/reindex` extracts every file again.
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::audit::{AuditEvent, AuditLog};
+use crate::error::{AppError, Result};
+use super::manager::FileManager;
+
+/// Most bytes of text kept per file. Text beyond it is not searchable.
+const MAX_INDEXED_TEXT: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentIndexStatus {
+    /// Waiting for its extraction job.
+    Pending,
+    Indexed,
+    /// Extraction failed; the file is not found by content searches.
+    NotIndexed,
+}
+
+impl ContentIndexStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentIndexStatus::Pending => "pending",
+            ContentIndexStatus::Indexed => "indexed",
+            ContentIndexStatus::NotIndexed => "not_indexed",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(ContentIndexStatus::Pending),
+            "indexed" => Some(ContentIndexStatus::Indexed),
+            "not_indexed" => Some(ContentIndexStatus::NotIndexed),
+            _ => None,
+        }
+    }
+}
+
+/// `content_type` without parameters such as `charset`, in lower case.
+fn essence(content_type: &str) -> String {
+    content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase()
+}
+
+/// Whether files of `content_type` have text to extract.
+pub fn is_indexable(content_type: &str) -> bool {
+    let essence = essence(content_type);
+    matches!(essence.as_str(), "text/plain" | "text/csv" | "application/json")
+        || (cfg!(feature = "pdf") && essence == "application/pdf")
+}
+
+/// The searchable text of `data`, cut to [`MAX_INDEXED_TEXT`] bytes.
+pub fn extract_text(content_type: &str, data: &[u8]) -> Result<String> {
+    let mut text = match essence(content_type).as_str() {
+        "text/plain" | "text/csv" => utf8(data)?,
+        "application/json" => {
+            serde_json::from_slice::<serde_json::Value>(data)
+                .map_err(|e| AppError::BadRequest(format!("Invalid JSON: {}", e)))?;
+            utf8(data)?
+        }
+        #[cfg(feature = "pdf")]
+        "application/pdf" => pdf_extract::extract_text_from_mem(data)
+            .map_err(|e| AppError::BadRequest(format!("Unreadable PDF: {}", e)))?,
+        other => return Err(AppError::BadRequest(format!("No text can be extracted from {}", other))),
+    };
+
+    if text.len() > MAX_INDEXED_TEXT {
+        let mut end = MAX_INDEXED_TEXT;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    Ok(text)
+}
+
+fn utf8(data: &[u8]) -> Result<String> {
+    String::from_utf8(data.to_vec()).map_err(|_| AppError::BadRequest("Text is not valid UTF-8".to_string()))
+}
+
+/// Counts from extracting every file again.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReindexReport {
+    pub files_scanned: u64,
+    pub indexed: u64,
+    pub not_indexed: u64,
+}
+
+/// Extracts the text of stored files into the index.
+#[derive(Clone)]
+pub struct ContentIndexer {
+    files: FileManager,
+    audit_log: AuditLog,
+}
+
+impl ContentIndexer {
+    const PAGE_SIZE: usize = 500;
+
+    pub fn new(files: FileManager, audit_log: AuditLog) -> Self {
+        Self { files, audit_log }
+    }
+
+    /// Indexes the text of file `file_id`, replacing what was indexed for
+    /// it before. A failed extraction is recorded on the file and is not an
+    /// error; `None` means the file is gone or has no extractable type.
+    pub async fn index_file(&self, file_id: Uuid) -> Result<Option<ContentIndexStatus>> {
+        let repository = self.files.repository();
+        let Some(metadata) = self.files.get_file_metadata(file_id).await? else {
+            return Ok(None);
+        };
+        if !is_indexable(&metadata.content_type) {
+            repository.clear_content(file_id, None).await?;
+            return Ok(None);
+        }
+
+        let extracted = match self.files.get_file_data(file_id).await {
+            Ok(Some((_, data))) => {
+                let content_type = metadata.content_type.clone();
+                // Extractors may panic on malformed input; that counts as
+                // a failed extraction.
+                tokio::task::spawn_blocking(move || extract_text(&content_type, &data))
+                    .await
+                    .unwrap_or_else(|_| Err(AppError::BadRequest("Text extraction failed".to_string())))
+            }
+            Ok(None) => return Ok(None),
+            Err(e) => Err(e),
+        };
+
+        match extracted {
+            Ok(text) => {
+                repository.store_content(file_id, &text).await?;
+                Ok(Some(ContentIndexStatus::Indexed))
+            }
+            Err(e) => {
+                warn!("Could not extract the text of file {}: {}", file_id, e);
+                repository.clear_content(file_id, Some(ContentIndexStatus::NotIndexed)).await?;
+                Ok(Some(ContentIndexStatus::NotIndexed))
+            }
+        }
+    }
+
+    /// Extracts the text of every file with an extractable type again, on
+    /// behalf of `actor`.
+    pub async fn reindex_all(&self, actor: &str) -> Result<ReindexReport> {
+        let repository = self.files.repository();
+        let mut report = ReindexReport::default();
+        let mut after = None;
+
+        loop {
+            let rows = repository.content_types_after(after, Self::PAGE_SIZE).await?;
+            let Some((last, _)) = rows.last() else {
+                break;
+            };
+            after = Some(*last);
+
+            for (file_id, content_type) in rows {
+                if !is_indexable(&content_type) {
+                    continue;
+                }
+                report.files_scanned += 1;
+                match self.index_file(file_id).await? {
+                    Some(ContentIndexStatus::Indexed) => report.indexed += 1,
+                    Some(_) => report.not_indexed += 1,
+                    None => {}
+                }
+            }
+        }
+
+        info!(
+            "File content reindex by {}: {} of {} files indexed",
+            actor, report.indexed, report.files_scanned
+        );
+        self.audit_log.record(
+            AuditEvent::new("files.reindexed")
+                .with_actor(actor)
+                .with_details(serde_json::to_value(&report)?),
+        );
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_text() {
+        assert!(is_indexable("text/plain; charset=utf-8"));
+        assert!(is_indexable("application/json"));
+        assert!(!is_indexable("image/png"));
+
+        assert_eq!(extract_text("text/csv", b"clause,7.2").unwrap(), "clause,7.2");
+        assert!(extract_text("application/json", b"{\"clause\":").is_err());
+        assert!(extract_text("text/plain", &[0xff, 0xfe, 0x00]).is_err());
+        assert!(extract_text("image/png", b"png").is_err());
+
+        let long = "é".repeat(MAX_INDEXED_TEXT);
+        let text = extract_text("text/plain", long.as_bytes()).unwrap();
+        assert!(text.len() <= MAX_INDEXED_TEXT && text.len() > MAX_INDEXED_TEXT - 2);
+    }
+}
@@ -3,18 +3,42 @@ use std::fs;
 use chrono::{Utc, Datelike};
 use uuid::Uuid;
 use tokio::fs as async_fs;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+use sha2::{Digest, Sha256};
+
+use crate::config::{FileCacheControlConfig, FileReconcileConfig};
 use crate::error::{AppError, Result};
+use crate::ids::{RandomIds, SharedIdGenerator};
+use crate::monitoring::SystemMonitor;
+use super::assets::{self, PublicAsset};
 use super::models::{File, FileUpload, FileMetadata, FileListQuery};
 use super::repository::{FileRepository, FileRepositoryTrait};
+use super::reconcile::LastReconciliation;
+use super::upload::IncomingFile;
 use super::validation::{FileValidator, FileValidationConfig};
 
+/// Directory under the storage path that uploads are written to until they
+/// have been received in full.
+const INCOMING_DIR: &str = ".incoming";
+
+/// Directory under the storage path holding job results too large to keep
+/// on the job row. They have no `files` row.
+const JOB_RESULTS_DIR: &str = ".job-results";
+
+/// Directories under the storage path that aren't uploads, skipped by
+/// reconciliation.
+pub(super) const RESERVED_DIRS: &[&str] = &[INCOMING_DIR, JOB_RESULTS_DIR];
+
 #[derive(Clone)]
 pub struct FileManagerConfig {
     pub storage_path: PathBuf,
     pub validation: FileValidationConfig,
     pub create_subdirectories: bool,
+    /// Free bytes below which the storage is reported as running low.
+    pub min_free_space: u64,
+    pub cache_control: FileCacheControlConfig,
+    pub reconcile: FileReconcileConfig,
 }
 
 impl Default for FileManagerConfig {
@@ -23,6 +47,9 @@ impl Default for FileManagerConfig {
             storage_path: PathBuf::from("uploads"),
             validation: FileValidationConfig::default(),
             create_subdirectories: true,
+            min_free_space: 512 * 1024 * 1024,
+            cache_control: FileCacheControlConfig::default(),
+            reconcile: FileReconcileConfig::default(),
         }
     }
 }
@@ -32,6 +59,8 @@ pub struct FileManager {
     config: FileManagerConfig,
     repository: FileRepository,
     validator: FileValidator,
+    ids: SharedIdGenerator,
+    last_reconciliation: LastReconciliation,
 }
 
 impl FileManager {
@@ -42,8 +71,16 @@ impl FileManager {
             config,
             repository,
             validator,
+            ids: RandomIds::shared(),
+            last_reconciliation: LastReconciliation::default(),
         }
     }
+
+    /// Source of the ids that stored files are named by.
+    pub fn with_id_generator(mut self, ids: SharedIdGenerator) -> Self {
+        self.ids = ids;
+        self
+    }
     
     pub fn with_default_config(repository: FileRepository) -> Self {
         Self::new(FileManagerConfig::default(), repository)
@@ -64,10 +101,169 @@ impl FileManager {
             &upload.original_filename,
             &upload.content_type,
             &upload.data,
-        ).map_err(|e| AppError::BadRequest(e.to_string()))?;
+        )?;
+        
+        self.store_generated(upload).await
+    }
+
+    /// Stores a file the server produced itself, such as an export, without
+    /// the checks applied to uploads.
+    pub async fn store_generated(&self, upload: FileUpload) -> Result<FileMetadata> {
+        self.ensure_space(upload.data.len() as u64)?;
+
+        let file_id = self.ids.uuid();
+        let (filename, storage_path) = self.prepare_path(file_id, &upload.original_filename).await?;
+        
+        let mut file = async_fs::File::create(&storage_path).await?;
+        file.write_all(&upload.data).await?;
+        file.sync_all().await?;
+        
+        let file_record = File {
+            id: file_id,
+            filename: filename.clone(),
+            original_filename: upload.original_filename,
+            content_type: upload.content_type,
+            size: upload.data.len() as u64,
+            path: storage_path.to_string_lossy().to_string(),
+            uploaded_by: upload.uploaded_by,
+            created_at: Utc::now(),
+            item_id: upload.item_id,
+            sha256: Some(hex::encode(Sha256::digest(&upload.data))),
+            missing_at: None,
+            content_index: None,
+        };
         
-        let file_id = Uuid::new_v4();
-        let file_extension = Path::new(&upload.original_filename)
+        let stored_file = self.repository.create(&file_record).await?;
+        
+        Ok(stored_file.into())
+    }
+
+    /// Where a file the server generates can be written, for files too
+    /// large to build in memory. [`store_generated_file`](Self::store_generated_file)
+    /// then moves it into storage.
+    pub async fn generated_file_path(&self) -> Result<PathBuf> {
+        let incoming_dir = self.config.storage_path.join(INCOMING_DIR);
+        if !incoming_dir.exists() {
+            async_fs::create_dir_all(&incoming_dir).await?;
+        }
+        Ok(incoming_dir.join(format!("{}.part", self.ids.uuid())))
+    }
+
+    /// Stores the file the server wrote to `path`, as [`store_generated`](Self::store_generated)
+    /// does for one held in memory. The file is moved, not copied.
+    pub async fn store_generated_file(
+        &self,
+        path: &Path,
+        original_filename: &str,
+        content_type: &str,
+        uploaded_by: u64,
+    ) -> Result<FileMetadata> {
+        let mut file = async_fs::File::open(path).await?;
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+        let mut chunk = vec![0; 64 * 1024];
+        loop {
+            let read = file.read(&mut chunk).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&chunk[..read]);
+            size += read as u64;
+        }
+        drop(file);
+
+        let file_id = self.ids.uuid();
+        let (filename, storage_path) = self.prepare_path(file_id, original_filename).await?;
+        async_fs::rename(path, &storage_path).await?;
+
+        let file_record = File {
+            id: file_id,
+            filename,
+            original_filename: original_filename.to_string(),
+            content_type: content_type.to_string(),
+            size,
+            path: storage_path.to_string_lossy().to_string(),
+            uploaded_by,
+            created_at: Utc::now(),
+            item_id: None,
+            sha256: Some(hex::encode(hasher.finalize())),
+            missing_at: None,
+            content_index: None,
+        };
+
+        match self.repository.create(&file_record).await {
+            Ok(stored_file) => Ok(stored_file.into()),
+            Err(e) => {
+                let _ = async_fs::remove_file(&storage_path).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Starts an upload that is written to storage as it arrives. The name
+    /// and content type are checked now, the content chunk by chunk.
+    pub async fn begin_upload(
+        &self,
+        original_filename: &str,
+        content_type: &str,
+        uploaded_by: u64,
+        item_id: Option<u64>,
+    ) -> Result<IncomingFile> {
+        self.validator.validate_name_and_type(original_filename, content_type)?;
+
+        let incoming_dir = self.config.storage_path.join(INCOMING_DIR);
+        if !incoming_dir.exists() {
+            async_fs::create_dir_all(&incoming_dir).await?;
+        }
+        let file_id = self.ids.uuid();
+        let temp_path = incoming_dir.join(format!("{}.part", file_id));
+
+        IncomingFile::create(
+            file_id,
+            original_filename,
+            content_type,
+            uploaded_by,
+            item_id,
+            temp_path,
+            self.validator.clone(),
+        )
+        .await
+    }
+
+    /// Moves a fully received upload into place and records it.
+    pub async fn finish_upload(&self, mut incoming: IncomingFile) -> Result<FileMetadata> {
+        let (size, sha256) = incoming.complete().await?;
+        let (filename, storage_path) = self.prepare_path(incoming.id, &incoming.original_filename).await?;
+        async_fs::rename(&incoming.temp_path, &storage_path).await?;
+
+        let file_record = File {
+            id: incoming.id,
+            filename,
+            original_filename: incoming.original_filename.clone(),
+            content_type: incoming.content_type.clone(),
+            size,
+            path: storage_path.to_string_lossy().to_string(),
+            uploaded_by: incoming.uploaded_by,
+            created_at: Utc::now(),
+            item_id: incoming.item_id,
+            sha256: Some(sha256),
+            missing_at: None,
+            content_index: None,
+        };
+
+        match self.repository.create(&file_record).await {
+            Ok(stored_file) => Ok(stored_file.into()),
+            Err(e) => {
+                let _ = async_fs::remove_file(&storage_path).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// The name a new file is stored under and where it goes, creating the
+    /// month's subdirectory when needed.
+    async fn prepare_path(&self, file_id: Uuid, original_filename: &str) -> Result<(String, PathBuf)> {
+        let file_extension = Path::new(original_filename)
             .extension()
             .and_then(|ext| ext.to_str())
             .unwrap_or("");
@@ -91,28 +287,83 @@ impl FileManager {
         } else {
             self.config.storage_path.join(&filename)
         };
-        
-        let mut file = async_fs::File::create(&storage_path).await?;
-        file.write_all(&upload.data).await?;
-        file.sync_all().await?;
-        
-        let file_record = File {
-            id: file_id,
-            filename: filename.clone(),
-            original_filename: upload.original_filename,
-            content_type: upload.content_type,
-            size: upload.data.len() as u64,
-            path: storage_path.to_string_lossy().to_string(),
-            uploaded_by: upload.uploaded_by,
-            created_at: Utc::now(),
-            item_id: upload.item_id,
-        };
-        
-        let stored_file = self.repository.create(&file_record).await?;
-        
-        Ok(stored_file.into())
+
+        Ok((filename, storage_path))
     }
     
+    /// Writes a job result to storage, returning the id it is kept under.
+    pub async fn store_job_result(&self, data: &[u8]) -> Result<Uuid> {
+        self.ensure_space(data.len() as u64)?;
+
+        let results_dir = self.config.storage_path.join(JOB_RESULTS_DIR);
+        if !results_dir.exists() {
+            async_fs::create_dir_all(&results_dir).await?;
+        }
+
+        let result_id = self.ids.uuid();
+        let mut file = async_fs::File::create(self.job_result_path(result_id)).await?;
+        file.write_all(data).await?;
+        file.sync_all().await?;
+        Ok(result_id)
+    }
+
+    /// Where the job result stored as `result_id` is kept.
+    pub fn job_result_path(&self, result_id: Uuid) -> PathBuf {
+        self.config
+            .storage_path
+            .join(JOB_RESULTS_DIR)
+            .join(format!("{}.json", result_id))
+    }
+
+    /// Deletes a stored job result. One that is already gone is not an error.
+    pub async fn delete_job_result(&self, result_id: Uuid) -> Result<()> {
+        match async_fs::remove_file(self.job_result_path(result_id)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn storage_path(&self) -> &Path {
+        &self.config.storage_path
+    }
+
+    pub fn min_free_space(&self) -> u64 {
+        self.config.min_free_space
+    }
+
+    pub fn reconcile_config(&self) -> &FileReconcileConfig {
+        &self.config.reconcile
+    }
+
+    /// The report of the latest reconciliation, shared by every clone of
+    /// this manager.
+    pub fn last_reconciliation(&self) -> &LastReconciliation {
+        &self.last_reconciliation
+    }
+
+    pub(crate) fn repository(&self) -> &FileRepository {
+        &self.repository
+    }
+
+    /// `Cache-Control` for serving a file of `content_type`.
+    pub fn cache_control(&self, content_type: &str) -> &str {
+        self.config.cache_control.for_content_type(content_type)
+    }
+
+    /// Fails with [`AppError::InsufficientStorage`] when the storage volume
+    /// has fewer than `size` bytes free. Passes when its free space is
+    /// unknown.
+    pub fn ensure_space(&self, size: u64) -> Result<()> {
+        match SystemMonitor::disk_usage_for(&self.config.storage_path) {
+            Some(disk) if disk.available_bytes < size => Err(AppError::InsufficientStorage(format!(
+                "{} bytes needed but only {} bytes free on {}",
+                size, disk.available_bytes, disk.mount_point
+            ))),
+            _ => Ok(()),
+        }
+    }
+
     pub async fn get_file_metadata(&self, file_id: Uuid) -> Result<Option<FileMetadata>> {
         match self.repository.get_by_id(file_id).await? {
             Some(file) => Ok(Some(file.into())),
@@ -149,6 +400,7 @@ impl FileManager {
         }
         
         self.repository.delete(file_id).await?;
+        self.repository.unpublish_asset(file_id, Utc::now()).await?;
         
         Ok(())
     }
@@ -158,6 +410,13 @@ impl FileManager {
         Ok(files.into_iter().map(|f| f.into()).collect())
     }
     
+    /// Files matching `query`, each with its best matching passage when
+    /// `query.content` is set.
+    pub async fn list_files_with_snippets(&self, query: FileListQuery) -> Result<Vec<(FileMetadata, Option<String>)>> {
+        let files = self.repository.list_with_snippets(&query).await?;
+        Ok(files.into_iter().map(|(file, snippet)| (file.into(), snippet)).collect())
+    }
+    
     pub async fn count_files(&self, query: FileListQuery) -> Result<u64> {
         self.repository.count(&query).await
     }
@@ -179,6 +438,101 @@ impl FileManager {
         Ok(updated_file.into())
     }
     
+    /// Publishes file `file_id` at the public URL of its contents. Fails
+    /// when its type may not be published, when it no longer matches its
+    /// recorded hash, and when a file already published under the same hash
+    /// has different contents.
+    pub async fn publish(&self, file_id: Uuid, published_by: u64) -> Result<PublicAsset> {
+        let file = self.repository.get_by_id(file_id).await?
+            .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+        if file.missing_at.is_some() {
+            return Err(AppError::Gone(format!("File {} is missing from storage", file_id)));
+        }
+        let extension = assets::extension_for(&file.content_type)
+            .filter(|_| self.config.validation.allowed_content_types.contains(&file.content_type))
+            .ok_or_else(|| {
+                AppError::BadRequest(format!(
+                    "Files of type {} cannot be published; only {} can",
+                    file.content_type,
+                    assets::publishable_types().collect::<Vec<_>>().join(", ")
+                ))
+            })?;
+
+        let data = self.read_stored(&file.path).await?;
+        let sha256 = hex::encode(Sha256::digest(&data));
+        if file.sha256.as_ref().is_some_and(|recorded| *recorded != sha256) {
+            tracing::error!("File {} no longer matches its recorded hash", file_id);
+            return Err(AppError::Conflict(format!("File {} no longer matches its recorded hash", file_id)));
+        }
+        for (other, path) in self.repository.assets_with_hash(&sha256).await? {
+            let Some(path) = path.filter(|_| other.file_id != file_id) else {
+                continue;
+            };
+            if self.read_stored(&path).await? != data {
+                tracing::error!("Files {} and {} share hash {} but differ", file_id, other.file_id, sha256);
+                return Err(AppError::Conflict(format!(
+                    "A different file is already published under hash {}",
+                    sha256
+                )));
+            }
+        }
+
+        let asset = PublicAsset {
+            file_id,
+            sha256,
+            extension: extension.to_string(),
+            content_type: file.content_type,
+            published_by,
+            published_at: Utc::now(),
+            unpublished_at: None,
+        };
+        self.repository.publish_asset(&asset).await?;
+        Ok(asset)
+    }
+
+    /// Withdraws the publication of file `file_id`. Its URL answers 410 once
+    /// no other file with the same contents is published.
+    pub async fn unpublish(&self, file_id: Uuid) -> Result<PublicAsset> {
+        if self.repository.get_by_id(file_id).await?.is_none() {
+            return Err(AppError::NotFound("File not found".to_string()));
+        }
+        self.repository.unpublish_asset(file_id, Utc::now()).await?
+            .ok_or_else(|| AppError::NotFound(format!("File {} is not published", file_id)))
+    }
+
+    /// The public asset named `name`, such as `{sha256}.png`, with its
+    /// contents. Assets no longer published are [`AppError::Gone`]. Contents
+    /// that do not hash to the name are never returned.
+    pub async fn public_asset(&self, name: &str) -> Result<(PublicAsset, Vec<u8>)> {
+        let not_found = || AppError::NotFound("Asset not found".to_string());
+        let (sha256, extension) = assets::parse_asset_name(name).ok_or_else(not_found)?;
+        let candidates: Vec<_> = self.repository.assets_with_hash(sha256).await?
+            .into_iter()
+            .filter(|(asset, _)| asset.extension == extension)
+            .collect();
+        if candidates.is_empty() {
+            return Err(not_found());
+        }
+
+        let (asset, path) = candidates
+            .into_iter()
+            .find_map(|(asset, path)| Some((asset.clone(), path?)).filter(|_| asset.is_published()))
+            .ok_or_else(|| AppError::Gone(format!("Asset {} is no longer published", name)))?;
+        let data = self.read_stored(&path).await?;
+        if hex::encode(Sha256::digest(&data)) != sha256 {
+            tracing::error!("File {} published as {} no longer matches its hash", asset.file_id, name);
+            return Err(AppError::InternalServerError);
+        }
+        Ok((asset, data))
+    }
+
+    async fn read_stored(&self, path: &str) -> Result<Vec<u8>> {
+        async_fs::read(path).await.map_err(|e| {
+            tracing::error!("Failed to read file {}: {}", path, e);
+            AppError::InternalServerError
+        })
+    }
+
     pub async fn cleanup_orphaned_files(&self) -> Result<u64> {
         let mut cleaned_count = 0;
         
@@ -301,6 +655,8 @@ mod tests {
             storage_path: temp_dir.path().to_path_buf(),
             validation: FileValidationConfig::default(),
             create_subdirectories: false,
+            min_free_space: 0,
+            ..FileManagerConfig::default()
         };
         
         let manager = FileManager::new(config, repository);
@@ -1,9 +1,17 @@
+pub mod assets;
+pub mod content_index;
 pub mod manager;
 pub mod models;
+pub mod reconcile;
 pub mod repository;
+pub mod upload;
 pub mod validation;
 
+pub use assets::PublicAsset;
+pub use content_index::{ContentIndexStatus, ContentIndexer, ReindexReport};
 pub use manager::{FileManager, FileManagerConfig};
 pub use models::{File, FileMetadata, FileUpload, FileListQuery};
+pub use reconcile::{FileReconciler, LastReconciliation, ReconcileReport};
 pub use repository::{FileRepository, FileRepositoryTrait};
-pub use validation::{FileValidator, ValidationError};
\ No newline at end of file
+pub use upload::IncomingFile;
+pub use validation::FileValidator;
\ No newline at end of file
@@ -2,6 +2,8 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use super::content_index::ContentIndexStatus;
+
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct File {
     pub id: Uuid,
@@ -13,6 +15,18 @@ pub struct File {
     pub uploaded_by: u64,
     pub created_at: DateTime<Utc>,
     pub item_id: Option<u64>,
+    /// Hex SHA-256 of the contents. Files stored before hashes were
+    /// recorded have none.
+    #[sqlx(default)]
+    pub sha256: Option<String>,
+    /// When reconciliation found the stored file gone. Such files are
+    /// answered with 410 instead of being read.
+    #[sqlx(default)]
+    pub missing_at: Option<DateTime<Utc>>,
+    /// Whether the text of the file is searchable. Files of types without
+    /// extractable text have none.
+    #[sqlx(default)]
+    pub content_index: Option<ContentIndexStatus>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,6 +39,9 @@ pub struct FileMetadata {
     pub uploaded_by: u64,
     pub created_at: DateTime<Utc>,
     pub item_id: Option<u64>,
+    pub sha256: Option<String>,
+    pub missing_at: Option<DateTime<Utc>>,
+    pub content_index: Option<ContentIndexStatus>,
 }
 
 impl From<File> for FileMetadata {
@@ -38,6 +55,9 @@ impl From<File> for FileMetadata {
             uploaded_by: file.uploaded_by,
             created_at: file.created_at,
             item_id: file.item_id,
+            sha256: file.sha256,
+            missing_at: file.missing_at,
+            content_index: file.content_index,
         }
     }
 }
@@ -56,6 +76,23 @@ pub struct FileListQuery {
     pub item_id: Option<u64>,
     pub content_type: Option<String>,
     pub uploaded_by: Option<u64>,
+    /// Only files whose original name contains this, ignoring case.
+    #[serde(default)]
+    pub filename: Option<String>,
+    /// Words to find in the extracted text of files, in the search query
+    /// language. Only used with `search_content`.
+    #[serde(default)]
+    pub q: Option<String>,
+    #[serde(default)]
+    pub search_content: bool,
+    /// FTS5 expression over `files_fts` built from `q`. Matching files are
+    /// listed best match first.
+    #[serde(skip)]
+    pub content: Option<String>,
+    /// Fields to sort by, such as `-size,filename`. Without one, files are
+    /// listed newest first, or best match first when searching content.
+    #[serde(default)]
+    pub sort: Option<crate::database::SortSpec>,
     pub limit: Option<u64>,
     pub offset: Option<u64>,
 }
@@ -66,7 +103,12 @@ impl Default for FileListQuery {
             item_id: None,
             content_type: None,
             uploaded_by: None,
-            limit: Some(50),
+            filename: None,
+            q: None,
+            search_content: false,
+            content: None,
+            sort: None,
+            limit: Some(crate::config::DEFAULT_PAGE_SIZE),
             offset: Some(0),
         }
     }
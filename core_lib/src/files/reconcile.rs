@@ -0,0 +1,256 @@
+//! Reconciliation of stored files with the `files` table
+//!
+//! Over time the upload directory gains files that no row points at (a row
+//! deleted without its file, a crash between writing a file and recording
+//! it) and the table gains rows whose file is gone. [`FileReconciler`] finds
+//! both, `batch_size` entries at a time, and unless asked for a dry run
+//! repairs them: orphaned files older than `orphan_min_age_hours` are
+//! deleted, and dangling rows are marked missing so that serving them
+//! answers 410. It runs as a recurring `FileReconciliation` job, or on
+//! demand through `POST /api/admin/files/reconcile`.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tokio::fs as async_fs;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::audit::{AuditEvent, AuditLog};
+use crate::error::Result;
+use super::manager::{FileManager, RESERVED_DIRS};
+
+/// Most orphaned files and dangling rows listed by name in a report.
+const MAX_SAMPLES: usize = 20;
+
+/// What a reconciliation found and repaired. On a dry run the repair counts
+/// are what it would have repaired.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReconcileReport {
+    pub dry_run: bool,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub files_scanned: u64,
+    pub rows_scanned: u64,
+    /// Stored files no row points at.
+    pub orphaned_files: u64,
+    pub orphaned_bytes: u64,
+    pub orphaned_files_deleted: u64,
+    /// Orphaned files younger than the safety age, left in place.
+    pub orphaned_files_too_recent: u64,
+    /// Rows whose file is gone, including ones marked on an earlier run.
+    pub dangling_rows: u64,
+    pub dangling_rows_marked: u64,
+    /// Rows marked missing whose file is back, and are unmarked.
+    pub rows_restored: u64,
+    pub orphaned_file_samples: Vec<String>,
+    pub dangling_row_samples: Vec<Uuid>,
+}
+
+impl ReconcileReport {
+    fn new(dry_run: bool) -> Self {
+        let now = Utc::now();
+        Self {
+            dry_run,
+            started_at: now,
+            finished_at: now,
+            files_scanned: 0,
+            rows_scanned: 0,
+            orphaned_files: 0,
+            orphaned_bytes: 0,
+            orphaned_files_deleted: 0,
+            orphaned_files_too_recent: 0,
+            dangling_rows: 0,
+            dangling_rows_marked: 0,
+            rows_restored: 0,
+            orphaned_file_samples: Vec::new(),
+            dangling_row_samples: Vec::new(),
+        }
+    }
+}
+
+/// The report of the most recent reconciliation, shared with the files
+/// health check.
+#[derive(Debug, Clone, Default)]
+pub struct LastReconciliation(Arc<RwLock<Option<ReconcileReport>>>);
+
+impl LastReconciliation {
+    pub fn get(&self) -> Option<ReconcileReport> {
+        self.0.read().clone()
+    }
+
+    fn set(&self, report: ReconcileReport) {
+        *self.0.write() = Some(report);
+    }
+}
+
+/// A stored file waiting for its batch to be looked up.
+struct Candidate {
+    path: String,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Reconciles a [`FileManager`]'s storage with its table, recording every
+/// run in the audit log.
+#[derive(Clone)]
+pub struct FileReconciler {
+    files: FileManager,
+    audit_log: AuditLog,
+}
+
+impl FileReconciler {
+    pub fn new(files: FileManager, audit_log: AuditLog) -> Self {
+        Self { files, audit_log }
+    }
+
+    /// Scans both sides on behalf of `actor`, repairing what it finds
+    /// unless `dry_run` is set.
+    pub async fn reconcile(&self, dry_run: bool, actor: &str) -> Result<ReconcileReport> {
+        let mut report = ReconcileReport::new(dry_run);
+        self.scan_storage(&mut report).await?;
+        self.scan_rows(&mut report).await?;
+        report.finished_at = Utc::now();
+
+        info!(
+            "File reconciliation by {} (dry_run: {}): {} of {} files orphaned ({} deleted), {} of {} rows dangling ({} marked, {} restored)",
+            actor, dry_run, report.orphaned_files, report.files_scanned, report.orphaned_files_deleted,
+            report.dangling_rows, report.rows_scanned, report.dangling_rows_marked, report.rows_restored
+        );
+        self.audit_log.record(
+            AuditEvent::new("files.reconciled")
+                .with_actor(actor)
+                .with_details(serde_json::to_value(&report)?),
+        );
+        self.files.last_reconciliation().set(report.clone());
+
+        Ok(report)
+    }
+
+    /// Walks the storage directory depth first, leaving out the directories
+    /// that don't hold uploads. Only directories still to visit and the
+    /// current batch are held in memory.
+    async fn scan_storage(&self, report: &mut ReconcileReport) -> Result<()> {
+        let batch_size = self.files.reconcile_config().batch_size.max(1);
+        let root = self.files.storage_path().to_path_buf();
+        let reserved: Vec<PathBuf> = RESERVED_DIRS.iter().map(|dir| root.join(dir)).collect();
+        let mut dirs: Vec<PathBuf> = vec![root];
+        let mut batch = Vec::with_capacity(batch_size);
+
+        while let Some(dir) = dirs.pop() {
+            let mut entries = match async_fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let file_type = entry.file_type().await?;
+                if file_type.is_dir() {
+                    if !reserved.contains(&entry.path()) {
+                        dirs.push(entry.path());
+                    }
+                    continue;
+                }
+                if !file_type.is_file() {
+                    continue;
+                }
+                let metadata = entry.metadata().await?;
+                batch.push(Candidate {
+                    path: entry.path().to_string_lossy().to_string(),
+                    size: metadata.len(),
+                    modified: metadata.modified().ok(),
+                });
+                if batch.len() >= batch_size {
+                    self.check_files(&mut batch, report).await?;
+                }
+            }
+        }
+        self.check_files(&mut batch, report).await
+    }
+
+    async fn check_files(&self, batch: &mut Vec<Candidate>, report: &mut ReconcileReport) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let paths: Vec<String> = batch.iter().map(|candidate| candidate.path.clone()).collect();
+        let known = self.files.repository().known_paths(&paths).await?;
+        let min_age = Duration::from_secs(self.files.reconcile_config().orphan_min_age_hours * 3600);
+        let now = SystemTime::now();
+
+        for candidate in batch.drain(..) {
+            report.files_scanned += 1;
+            if known.contains(&candidate.path) {
+                continue;
+            }
+            report.orphaned_files += 1;
+            report.orphaned_bytes += candidate.size;
+            if report.orphaned_file_samples.len() < MAX_SAMPLES {
+                report.orphaned_file_samples.push(candidate.path.clone());
+            }
+
+            // A file of unknown age is treated as new.
+            let old_enough = candidate
+                .modified
+                .and_then(|modified| now.duration_since(modified).ok())
+                .is_some_and(|age| age >= min_age);
+            if !old_enough {
+                report.orphaned_files_too_recent += 1;
+                continue;
+            }
+            if !report.dry_run {
+                if let Err(e) = async_fs::remove_file(&candidate.path).await {
+                    warn!("Failed to delete orphaned file {}: {}", candidate.path, e);
+                    continue;
+                }
+            }
+            report.orphaned_files_deleted += 1;
+        }
+        Ok(())
+    }
+
+    /// Pages through the table by id.
+    async fn scan_rows(&self, report: &mut ReconcileReport) -> Result<()> {
+        let batch_size = self.files.reconcile_config().batch_size.max(1);
+        let repository = self.files.repository();
+        let mut after = None;
+
+        loop {
+            let rows = repository.stored_paths_after(after, batch_size).await?;
+            let Some(last) = rows.last() else {
+                return Ok(());
+            };
+            after = Some(last.id);
+
+            for row in rows {
+                report.rows_scanned += 1;
+                // A file whose presence cannot be checked is assumed present.
+                let exists = async_fs::try_exists(&row.path).await.unwrap_or(true);
+                match (exists, row.missing_at) {
+                    (false, missing_at) => {
+                        report.dangling_rows += 1;
+                        if report.dangling_row_samples.len() < MAX_SAMPLES {
+                            report.dangling_row_samples.push(row.id);
+                        }
+                        if missing_at.is_none() {
+                            if !report.dry_run {
+                                repository.set_missing(row.id, Some(Utc::now())).await?;
+                            }
+                            report.dangling_rows_marked += 1;
+                        }
+                    }
+                    (true, Some(_)) => {
+                        if !report.dry_run {
+                            repository.set_missing(row.id, None).await?;
+                        }
+                        report.rows_restored += 1;
+                    }
+                    (true, None) => {}
+                }
+            }
+        }
+    }
+}
@@ -1,11 +1,28 @@
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use sqlx::{SqlitePool, Row};
+use sqlx::{sqlite::SqliteRow, SqlitePool, Row};
+use std::collections::HashSet;
 use uuid::Uuid;
 
+use crate::database::{InstrumentedPool, SortFields, SortOrder};
 use crate::error::{AppError, Result};
+use super::assets::PublicAsset;
+use super::content_index::ContentIndexStatus;
 use super::models::{File, FileListQuery};
 
+/// What file listings can be sorted on.
+pub const FILE_SORT: SortFields = SortFields {
+    fields: &[
+        ("id", "files.id"),
+        ("filename", "files.original_filename"),
+        ("content_type", "files.content_type"),
+        ("size", "files.size"),
+        ("created_at", "files.created_at"),
+    ],
+    default: ("created_at", SortOrder::Desc),
+    tiebreak: "files.id",
+};
+
 #[async_trait]
 pub trait FileRepositoryTrait: Send + Sync {
     async fn create(&self, file: &File) -> Result<File>;
@@ -19,12 +36,12 @@ pub trait FileRepositoryTrait: Send + Sync {
 
 #[derive(Clone)]
 pub struct FileRepository {
-    pool: SqlitePool,
+    pool: InstrumentedPool,
 }
 
 impl FileRepository {
     pub fn new(pool: SqlitePool) -> Self {
-        Self { pool }
+        Self { pool: pool.into() }
     }
     
     pub async fn create_table(&self) -> Result<()> {
@@ -40,6 +57,10 @@ impl FileRepository {
                 uploaded_by INTEGER NOT NULL,
                 created_at TEXT NOT NULL,
                 item_id INTEGER,
+                namespace TEXT NOT NULL DEFAULT 'default',
+                sha256 TEXT,
+                missing_at TEXT,
+                content_index TEXT,
                 FOREIGN KEY (uploaded_by) REFERENCES users (id),
                 FOREIGN KEY (item_id) REFERENCES items (id) ON DELETE SET NULL
             )
@@ -63,9 +84,355 @@ impl FileRepository {
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_files_created_at ON files (created_at)")
             .execute(&self.pool)
             .await?;
+
+        sqlx::query("CREATE VIRTUAL TABLE IF NOT EXISTS files_fts USING fts5(file_id UNINDEXED, content)")
+            .execute(&self.pool)
+            .await?;
+
+        sqlx::query(PUBLIC_ASSETS_TABLE)
+            .execute(&self.pool)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_public_assets_sha256 ON public_assets (sha256)")
+            .execute(&self.pool)
+            .await?;
         
         Ok(())
     }
+
+    /// Which of `paths` some row points at, in any namespace.
+    pub async fn known_paths(&self, paths: &[String]) -> Result<HashSet<String>> {
+        if paths.is_empty() {
+            return Ok(HashSet::new());
+        }
+        let placeholders = vec!["?"; paths.len()].join(", ");
+        let sql = format!("SELECT path FROM files WHERE path IN ({})", placeholders);
+        let mut query = sqlx::query_scalar::<_, String>(&sql);
+        for path in paths {
+            query = query.bind(path);
+        }
+        Ok(query.fetch_all(&self.pool).await?.into_iter().collect())
+    }
+
+    /// Up to `limit` rows in any namespace whose id sorts after `after`, for
+    /// walking the whole table a page at a time.
+    pub async fn stored_paths_after(&self, after: Option<Uuid>, limit: usize) -> Result<Vec<StoredPath>> {
+        let rows = sqlx::query(
+            "SELECT id, path, missing_at FROM files WHERE id > COALESCE(?1, '') ORDER BY id LIMIT ?2",
+        )
+        .bind(after.map(|id| id.to_string()))
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(StoredPath {
+                    id: Uuid::parse_str(&row.get::<String, _>("id"))
+                        .map_err(|e| AppError::BadRequest(format!("Invalid UUID: {}", e)))?,
+                    path: row.get("path"),
+                    missing_at: parse_missing_at(row)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Records that the file of row `id` is gone, or with `None` that it is
+    /// back.
+    pub async fn set_missing(&self, id: Uuid, missing_at: Option<DateTime<Utc>>) -> Result<()> {
+        sqlx::query("UPDATE files SET missing_at = ?2 WHERE id = ?1")
+            .bind(id.to_string())
+            .bind(missing_at.map(|at| at.to_rfc3339()))
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Marks row `id` as waiting for its text to be extracted.
+    pub async fn set_content_pending(&self, id: Uuid) -> Result<()> {
+        sqlx::query("UPDATE files SET content_index = ?2 WHERE id = ?1")
+            .bind(id.to_string())
+            .bind(ContentIndexStatus::Pending.as_str())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Replaces the indexed text of row `id` with `text`.
+    pub async fn store_content(&self, id: Uuid, text: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM files_fts WHERE file_id = ?1")
+            .bind(id.to_string())
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("INSERT INTO files_fts (file_id, content) VALUES (?1, ?2)")
+            .bind(id.to_string())
+            .bind(text)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE files SET content_index = ?2 WHERE id = ?1")
+            .bind(id.to_string())
+            .bind(ContentIndexStatus::Indexed.as_str())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Removes the indexed text of row `id`, recording `status` instead.
+    pub async fn clear_content(&self, id: Uuid, status: Option<ContentIndexStatus>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM files_fts WHERE file_id = ?1")
+            .bind(id.to_string())
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE files SET content_index = ?2 WHERE id = ?1")
+            .bind(id.to_string())
+            .bind(status.map(|status| status.as_str()))
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Ids and content types of up to `limit` rows in any namespace whose
+    /// id sorts after `after`.
+    pub async fn content_types_after(&self, after: Option<Uuid>, limit: usize) -> Result<Vec<(Uuid, String)>> {
+        let rows = sqlx::query(
+            "SELECT id, content_type FROM files WHERE id > COALESCE(?1, '') ORDER BY id LIMIT ?2",
+        )
+        .bind(after.map(|id| id.to_string()))
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                let id = Uuid::parse_str(&row.get::<String, _>("id"))
+                    .map_err(|e| AppError::BadRequest(format!("Invalid UUID: {}", e)))?;
+                Ok((id, row.get("content_type")))
+            })
+            .collect()
+    }
+
+    /// Publishes row `asset.file_id` under its hash, replacing any earlier
+    /// publication of it.
+    pub async fn publish_asset(&self, asset: &PublicAsset) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO public_assets (file_id, sha256, extension, content_type, published_by, published_at, unpublished_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, NULL)
+            ON CONFLICT (file_id) DO UPDATE SET
+                sha256 = excluded.sha256,
+                extension = excluded.extension,
+                content_type = excluded.content_type,
+                published_by = excluded.published_by,
+                published_at = excluded.published_at,
+                unpublished_at = NULL
+            "#,
+        )
+        .bind(asset.file_id.to_string())
+        .bind(&asset.sha256)
+        .bind(&asset.extension)
+        .bind(&asset.content_type)
+        .bind(asset.published_by as i64)
+        .bind(asset.published_at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Withdraws the publication of row `file_id`, returning it, or `None`
+    /// when the file is not published.
+    pub async fn unpublish_asset(&self, file_id: Uuid, at: DateTime<Utc>) -> Result<Option<PublicAsset>> {
+        let updated = sqlx::query("UPDATE public_assets SET unpublished_at = ?2 WHERE file_id = ?1 AND unpublished_at IS NULL")
+            .bind(file_id.to_string())
+            .bind(at.to_rfc3339())
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        if updated == 0 {
+            return Ok(None);
+        }
+
+        let row = sqlx::query(&format!("SELECT {} FROM public_assets WHERE file_id = ?1", PUBLIC_ASSET_COLUMNS))
+            .bind(file_id.to_string())
+            .fetch_one(&self.pool)
+            .await?;
+        public_asset_from_row(&row).map(Some)
+    }
+
+    /// Every publication, past or present, under `sha256`, in any
+    /// namespace, each with the stored path of its file while the file
+    /// exists and is not missing. Anyone may fetch a public asset, so
+    /// these are looked up across tenants.
+    pub async fn assets_with_hash(&self, sha256: &str) -> Result<Vec<(PublicAsset, Option<String>)>> {
+        let rows = sqlx::query(&format!(
+            "SELECT {}, files.path FROM public_assets LEFT JOIN files ON files.id = public_assets.file_id AND files.missing_at IS NULL WHERE public_assets.sha256 = ?1 ORDER BY public_assets.published_at",
+            PUBLIC_ASSET_COLUMNS
+        ))
+        .bind(sha256)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| Ok((public_asset_from_row(row)?, row.get("path"))))
+            .collect()
+    }
+
+    /// Like [`FileRepositoryTrait::list`], with each file's best matching
+    /// passage when `query.content` is set.
+    pub async fn list_with_snippets(&self, query: &FileListQuery) -> Result<Vec<(File, Option<String>)>> {
+        let mut sql = match query.content {
+            Some(_) => "SELECT id, filename, original_filename, content_type, size, path, uploaded_by, created_at, item_id, sha256, missing_at, content_index, snippet(files_fts, 1, '[', ']', '…', 16) AS snippet FROM files_fts JOIN files ON files.id = files_fts.file_id WHERE files_fts MATCH ? AND namespace = COALESCE(?, namespace)",
+            None => "SELECT id, filename, original_filename, content_type, size, path, uploaded_by, created_at, item_id, sha256, missing_at, content_index, NULL AS snippet FROM files WHERE namespace = COALESCE(?, namespace)",
+        }
+        .to_string();
+        let mut conditions = Vec::new();
+        
+        if query.item_id.is_some() {
+            conditions.push("item_id = ?".to_string());
+        }
+        
+        if query.content_type.is_some() {
+            conditions.push("content_type = ?".to_string());
+        }
+        
+        if query.uploaded_by.is_some() {
+            conditions.push("uploaded_by = ?".to_string());
+        }
+        
+        if query.filename.is_some() {
+            conditions.push("lower(original_filename) LIKE ? ESCAPE '\\'".to_string());
+        }
+        
+        if !conditions.is_empty() {
+            sql.push_str(" AND ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        
+        let order_by = FILE_SORT.order_by(query.sort.as_ref())?;
+        if query.content.is_some() && query.sort.is_none() {
+            sql.push_str(&format!(" ORDER BY files_fts.rank, {}", order_by));
+        } else {
+            sql.push_str(&format!(" ORDER BY {}", order_by));
+        }
+        
+        if let Some(limit) = query.limit {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+        
+        if let Some(offset) = query.offset {
+            sql.push_str(&format!(" OFFSET {}", offset));
+        }
+        
+        let mut query_builder = sqlx::query(&sql);
+        
+        if let Some(ref content) = query.content {
+            query_builder = query_builder.bind(content);
+        }
+        
+        query_builder = query_builder.bind(crate::tenancy::current());
+        
+        if let Some(item_id) = query.item_id {
+            query_builder = query_builder.bind(item_id as i64);
+        }
+        
+        if let Some(ref content_type) = query.content_type {
+            query_builder = query_builder.bind(content_type);
+        }
+        
+        if let Some(uploaded_by) = query.uploaded_by {
+            query_builder = query_builder.bind(uploaded_by as i64);
+        }
+        
+        if let Some(ref filename) = query.filename {
+            query_builder = query_builder.bind(format!("%{}%", crate::database::escape_like(&filename.to_lowercase())));
+        }
+        
+        let rows = query_builder.fetch_all(&self.pool).await?;
+        
+        let mut files = Vec::new();
+        for row in rows {
+            let file = File {
+                id: Uuid::parse_str(&row.get::<String, _>("id"))
+                    .map_err(|e| AppError::BadRequest(format!("Invalid UUID: {}", e)))?,
+                filename: row.get("filename"),
+                original_filename: row.get("original_filename"),
+                content_type: row.get("content_type"),
+                size: row.get::<i64, _>("size") as u64,
+                path: row.get("path"),
+                uploaded_by: row.get::<i64, _>("uploaded_by") as u64,
+                created_at: DateTime::parse_from_rfc3339(&row.get::<String, _>("created_at"))
+                    .map_err(|e| AppError::BadRequest(format!("Invalid datetime: {}", e)))?
+                    .with_timezone(&Utc),
+                item_id: row.get::<Option<i64>, _>("item_id").map(|id| id as u64),
+                sha256: row.get("sha256"),
+                missing_at: parse_missing_at(&row)?,
+                content_index: parse_content_index(&row),
+            };
+            files.push((file, row.get("snippet")));
+        }
+        
+        Ok(files)
+    }
+}
+
+/// Where a row says its file is stored.
+#[derive(Debug, Clone)]
+pub struct StoredPath {
+    pub id: Uuid,
+    pub path: String,
+    pub missing_at: Option<DateTime<Utc>>,
+}
+
+/// Creates the table of files published under their content hash.
+const PUBLIC_ASSETS_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS public_assets (
+        file_id TEXT PRIMARY KEY,
+        sha256 TEXT NOT NULL,
+        extension TEXT NOT NULL,
+        content_type TEXT NOT NULL,
+        published_by INTEGER NOT NULL,
+        published_at TEXT NOT NULL,
+        unpublished_at TEXT
+    )
+"#;
+
+const PUBLIC_ASSET_COLUMNS: &str = "public_assets.file_id, public_assets.sha256, public_assets.extension, public_assets.content_type, public_assets.published_by, public_assets.published_at, public_assets.unpublished_at";
+
+fn public_asset_from_row(row: &SqliteRow) -> Result<PublicAsset> {
+    let parse_time = |value: String| {
+        DateTime::parse_from_rfc3339(&value)
+            .map(|at| at.with_timezone(&Utc))
+            .map_err(|e| AppError::BadRequest(format!("Invalid datetime: {}", e)))
+    };
+    Ok(PublicAsset {
+        file_id: Uuid::parse_str(&row.get::<String, _>("file_id"))
+            .map_err(|e| AppError::BadRequest(format!("Invalid UUID: {}", e)))?,
+        sha256: row.get("sha256"),
+        extension: row.get("extension"),
+        content_type: row.get("content_type"),
+        published_by: row.get::<i64, _>("published_by") as u64,
+        published_at: parse_time(row.get("published_at"))?,
+        unpublished_at: row.get::<Option<String>, _>("unpublished_at").map(parse_time).transpose()?,
+    })
+}
+
+fn parse_missing_at(row: &SqliteRow) -> Result<Option<DateTime<Utc>>> {
+    row.get::<Option<String>, _>("missing_at")
+        .map(|at| {
+            DateTime::parse_from_rfc3339(&at)
+                .map(|at| at.with_timezone(&Utc))
+                .map_err(|e| AppError::BadRequest(format!("Invalid datetime: {}", e)))
+        })
+        .transpose()
+}
+
+fn parse_content_index(row: &SqliteRow) -> Option<ContentIndexStatus> {
+    row.get::<Option<String>, _>("content_index")
+        .as_deref()
+        .and_then(ContentIndexStatus::parse)
 }
 
 #[async_trait]
@@ -73,8 +440,8 @@ impl FileRepositoryTrait for FileRepository {
     async fn create(&self, file: &File) -> Result<File> {
         sqlx::query(
             r#"
-            INSERT INTO files (id, filename, original_filename, content_type, size, path, uploaded_by, created_at, item_id)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            INSERT INTO files (id, filename, original_filename, content_type, size, path, uploaded_by, created_at, item_id, namespace, sha256)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
             "#,
         )
         .bind(file.id.to_string())
@@ -86,6 +453,8 @@ impl FileRepositoryTrait for FileRepository {
         .bind(file.uploaded_by as i64)
         .bind(file.created_at.to_rfc3339())
         .bind(file.item_id.map(|id| id as i64))
+        .bind(crate::tenancy::current_or_default())
+        .bind(&file.sha256)
         .execute(&self.pool)
         .await?;
         
@@ -94,9 +463,10 @@ impl FileRepositoryTrait for FileRepository {
     
     async fn get_by_id(&self, id: Uuid) -> Result<Option<File>> {
         let row = sqlx::query(
-            "SELECT id, filename, original_filename, content_type, size, path, uploaded_by, created_at, item_id FROM files WHERE id = ?1"
+            "SELECT id, filename, original_filename, content_type, size, path, uploaded_by, created_at, item_id, sha256, missing_at, content_index FROM files WHERE id = ?1 AND namespace = COALESCE(?2, namespace)"
         )
         .bind(id.to_string())
+        .bind(crate::tenancy::current())
         .fetch_optional(&self.pool)
         .await?;
         
@@ -115,6 +485,9 @@ impl FileRepositoryTrait for FileRepository {
                         .map_err(|e| AppError::BadRequest(format!("Invalid datetime: {}", e)))?
                         .with_timezone(&Utc),
                     item_id: row.get::<Option<i64>, _>("item_id").map(|id| id as u64),
+                    sha256: row.get("sha256"),
+                    missing_at: parse_missing_at(&row)?,
+                    content_index: parse_content_index(&row),
                 };
                 Ok(Some(file))
             }
@@ -128,7 +501,7 @@ impl FileRepositoryTrait for FileRepository {
             UPDATE files 
             SET filename = ?2, original_filename = ?3, content_type = ?4, size = ?5, 
                 path = ?6, uploaded_by = ?7, created_at = ?8, item_id = ?9
-            WHERE id = ?1
+            WHERE id = ?1 AND namespace = COALESCE(?10, namespace)
             "#,
         )
         .bind(file.id.to_string())
@@ -140,6 +513,7 @@ impl FileRepositoryTrait for FileRepository {
         .bind(file.uploaded_by as i64)
         .bind(file.created_at.to_rfc3339())
         .bind(file.item_id.map(|id| id as i64))
+        .bind(crate::tenancy::current())
         .execute(&self.pool)
         .await?
         .rows_affected();
@@ -152,8 +526,9 @@ impl FileRepositoryTrait for FileRepository {
     }
     
     async fn delete(&self, id: Uuid) -> Result<()> {
-        let rows_affected = sqlx::query("DELETE FROM files WHERE id = ?1")
+        let rows_affected = sqlx::query("DELETE FROM files WHERE id = ?1 AND namespace = COALESCE(?2, namespace)")
             .bind(id.to_string())
+            .bind(crate::tenancy::current())
             .execute(&self.pool)
             .await?
             .rows_affected();
@@ -161,77 +536,18 @@ impl FileRepositoryTrait for FileRepository {
         if rows_affected == 0 {
             return Err(AppError::NotFound("File not found".to_string()));
         }
+
+        sqlx::query("DELETE FROM files_fts WHERE file_id = ?1")
+            .bind(id.to_string())
+            .execute(&self.pool)
+            .await?;
         
         Ok(())
     }
     
     async fn list(&self, query: &FileListQuery) -> Result<Vec<File>> {
-        let mut sql = "SELECT id, filename, original_filename, content_type, size, path, uploaded_by, created_at, item_id FROM files WHERE 1=1".to_string();
-        let mut conditions = Vec::new();
-        
-        if query.item_id.is_some() {
-            conditions.push("item_id = ?".to_string());
-        }
-        
-        if query.content_type.is_some() {
-            conditions.push("content_type = ?".to_string());
-        }
-        
-        if query.uploaded_by.is_some() {
-            conditions.push("uploaded_by = ?".to_string());
-        }
-        
-        if !conditions.is_empty() {
-            sql.push_str(" AND ");
-            sql.push_str(&conditions.join(" AND "));
-        }
-        
-        sql.push_str(" ORDER BY created_at DESC");
-        
-        if let Some(limit) = query.limit {
-            sql.push_str(&format!(" LIMIT {}", limit));
-        }
-        
-        if let Some(offset) = query.offset {
-            sql.push_str(&format!(" OFFSET {}", offset));
-        }
-        
-        let mut query_builder = sqlx::query(&sql);
-        
-        if let Some(item_id) = query.item_id {
-            query_builder = query_builder.bind(item_id as i64);
-        }
-        
-        if let Some(ref content_type) = query.content_type {
-            query_builder = query_builder.bind(content_type);
-        }
-        
-        if let Some(uploaded_by) = query.uploaded_by {
-            query_builder = query_builder.bind(uploaded_by as i64);
-        }
-        
-        let rows = query_builder.fetch_all(&self.pool).await?;
-        
-        let mut files = Vec::new();
-        for row in rows {
-            let file = File {
-                id: Uuid::parse_str(&row.get::<String, _>("id"))
-                    .map_err(|e| AppError::BadRequest(format!("Invalid UUID: {}", e)))?,
-                filename: row.get("filename"),
-                original_filename: row.get("original_filename"),
-                content_type: row.get("content_type"),
-                size: row.get::<i64, _>("size") as u64,
-                path: row.get("path"),
-                uploaded_by: row.get::<i64, _>("uploaded_by") as u64,
-                created_at: DateTime::parse_from_rfc3339(&row.get::<String, _>("created_at"))
-                    .map_err(|e| AppError::BadRequest(format!("Invalid datetime: {}", e)))?
-                    .with_timezone(&Utc),
-                item_id: row.get::<Option<i64>, _>("item_id").map(|id| id as u64),
-            };
-            files.push(file);
-        }
-        
-        Ok(files)
+        let files = self.list_with_snippets(query).await?;
+        Ok(files.into_iter().map(|(file, _)| file).collect())
     }
     
     async fn get_by_item_id(&self, item_id: u64) -> Result<Vec<File>> {
@@ -243,7 +559,11 @@ impl FileRepositoryTrait for FileRepository {
     }
     
     async fn count(&self, query: &FileListQuery) -> Result<u64> {
-        let mut sql = "SELECT COUNT(*) as count FROM files WHERE 1=1".to_string();
+        let mut sql = match query.content {
+            Some(_) => "SELECT COUNT(*) as count FROM files_fts JOIN files ON files.id = files_fts.file_id WHERE files_fts MATCH ? AND namespace = COALESCE(?, namespace)",
+            None => "SELECT COUNT(*) as count FROM files WHERE namespace = COALESCE(?, namespace)",
+        }
+        .to_string();
         let mut conditions = Vec::new();
         
         if query.item_id.is_some() {
@@ -258,6 +578,10 @@ impl FileRepositoryTrait for FileRepository {
             conditions.push("uploaded_by = ?".to_string());
         }
         
+        if query.filename.is_some() {
+            conditions.push("lower(original_filename) LIKE ? ESCAPE '\\'".to_string());
+        }
+        
         if !conditions.is_empty() {
             sql.push_str(" AND ");
             sql.push_str(&conditions.join(" AND "));
@@ -265,6 +589,12 @@ impl FileRepositoryTrait for FileRepository {
         
         let mut query_builder = sqlx::query(&sql);
         
+        if let Some(ref content) = query.content {
+            query_builder = query_builder.bind(content);
+        }
+        
+        query_builder = query_builder.bind(crate::tenancy::current());
+        
         if let Some(item_id) = query.item_id {
             query_builder = query_builder.bind(item_id as i64);
         }
@@ -277,6 +607,10 @@ impl FileRepositoryTrait for FileRepository {
             query_builder = query_builder.bind(uploaded_by as i64);
         }
         
+        if let Some(ref filename) = query.filename {
+            query_builder = query_builder.bind(format!("%{}%", crate::database::escape_like(&filename.to_lowercase())));
+        }
+        
         let row = query_builder.fetch_one(&self.pool).await?;
         let count = row.get::<i64, _>("count") as u64;
         
@@ -357,6 +691,9 @@ mod tests {
             uploaded_by: 1,
             created_at: Utc::now(),
             item_id: None,
+            sha256: Some("ab".repeat(32)),
+            missing_at: None,
+            content_index: None,
         };
         
         let created = repo.create(&file).await.unwrap();
@@ -364,6 +701,7 @@ mod tests {
         
         let retrieved = repo.get_by_id(file.id).await.unwrap().unwrap();
         assert_eq!(retrieved.filename, file.filename);
+        assert_eq!(retrieved.sha256, file.sha256);
         
         let mut updated_file = retrieved.clone();
         updated_file.filename = "updated.txt".to_string();
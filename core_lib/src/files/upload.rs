@@ -0,0 +1,96 @@
+use std::path::PathBuf;
+
+use sha2::{Digest, Sha256};
+use tokio::fs as async_fs;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::error::Result;
+use super::validation::{ContentScanner, FileValidator};
+
+/// An upload being written to storage as it arrives, checked chunk by chunk
+/// instead of after buffering the whole body. Finish it with
+/// [`FileManager::finish_upload`](super::FileManager::finish_upload);
+/// dropping it discards what was written.
+pub struct IncomingFile {
+    pub(super) id: Uuid,
+    pub(super) original_filename: String,
+    pub(super) content_type: String,
+    pub(super) uploaded_by: u64,
+    pub(super) item_id: Option<u64>,
+    pub(super) temp_path: PathBuf,
+    file: Option<async_fs::File>,
+    hasher: Sha256,
+    scanner: ContentScanner,
+    validator: FileValidator,
+}
+
+impl IncomingFile {
+    pub(super) async fn create(
+        id: Uuid,
+        original_filename: &str,
+        content_type: &str,
+        uploaded_by: u64,
+        item_id: Option<u64>,
+        temp_path: PathBuf,
+        validator: FileValidator,
+    ) -> Result<Self> {
+        let file = async_fs::File::create(&temp_path).await?;
+        Ok(Self {
+            id,
+            original_filename: original_filename.to_string(),
+            content_type: content_type.to_string(),
+            uploaded_by,
+            item_id,
+            temp_path,
+            file: Some(file),
+            hasher: Sha256::new(),
+            scanner: ContentScanner::new(content_type),
+            validator,
+        })
+    }
+
+    /// Checks and stores the next chunk. Fails as soon as the upload is too
+    /// large or its content is rejected.
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.scanner.push(&self.validator, chunk)?;
+        self.hasher.update(chunk);
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(chunk).await?;
+        }
+        Ok(())
+    }
+
+    /// Bytes received so far.
+    pub fn size(&self) -> u64 {
+        self.scanner.size()
+    }
+
+    /// The first bytes of the upload, for signature checks.
+    pub fn head(&self) -> &[u8] {
+        self.scanner.head()
+    }
+
+    /// Runs the checks that need the whole file and flushes it to disk,
+    /// returning its size and SHA-256.
+    pub(super) async fn complete(&mut self) -> Result<(u64, String)> {
+        let scanner = std::mem::replace(&mut self.scanner, ContentScanner::new(&self.content_type));
+        let size = scanner.size();
+        scanner.finish(&self.validator)?;
+        if let Some(mut file) = self.file.take() {
+            file.flush().await?;
+            file.sync_all().await?;
+        }
+        let sha256 = hex::encode(std::mem::take(&mut self.hasher).finalize());
+        Ok((size, sha256))
+    }
+}
+
+impl Drop for IncomingFile {
+    fn drop(&mut self) {
+        // After a successful finish the file has been moved away.
+        if self.temp_path.exists() {
+            let _ = std::fs::remove_file(&self.temp_path);
+        }
+    }
+}
@@ -1,26 +1,5 @@
 use std::collections::HashSet;
-use thiserror::Error;
-
-#[derive(Error, Debug)]
-pub enum ValidationError {
-    #[error("File too large: {size} bytes (max: {max_size} bytes)")]
-    FileTooLarge { size: u64, max_size: u64 },
-    
-    #[error("Invalid file type: {content_type} (allowed: {allowed:?})")]
-    InvalidFileType { content_type: String, allowed: Vec<String> },
-    
-    #[error("Filename too long: {length} characters (max: {max_length})")]
-    FilenameTooLong { length: usize, max_length: usize },
-    
-    #[error("Invalid filename: {filename}")]
-    InvalidFilename { filename: String },
-    
-    #[error("Empty file not allowed")]
-    EmptyFile,
-    
-    #[error("Suspicious file content detected")]
-    SuspiciousContent,
-}
+use crate::validation::ValidationError;
 
 #[derive(Debug, Clone)]
 pub struct FileValidationConfig {
@@ -102,15 +81,27 @@ impl FileValidator {
         Ok(())
     }
     
+    /// The checks that need no content, run before an upload is streamed.
+    pub fn validate_name_and_type(&self, filename: &str, content_type: &str) -> Result<(), ValidationError> {
+        self.validate_filename(filename)?;
+        self.validate_content_type(content_type)
+    }
+
+    pub fn max_file_size(&self) -> u64 {
+        self.config.max_file_size
+    }
+
     fn validate_filename(&self, filename: &str) -> Result<(), ValidationError> {
-        if filename.len() > self.config.max_filename_length {
+        let length = crate::validation::unicode::text_length(filename);
+        if length > self.config.max_filename_length {
             return Err(ValidationError::FilenameTooLong {
-                length: filename.len(),
+                length,
                 max_length: self.config.max_filename_length,
             });
         }
         
-        if filename.contains('\0') || filename.contains('/') || filename.contains('\\') {
+        if filename.contains('\0') || filename.contains('/') || filename.contains('\\')
+            || filename.chars().any(|c| c.is_control() || crate::validation::unicode::is_bidi_control(c)) {
             return Err(ValidationError::InvalidFilename {
                 filename: filename.to_string(),
             });
@@ -182,6 +173,121 @@ impl FileValidator {
     }
 }
 
+/// Bytes of the previous chunk scanned again with the next one, so that a
+/// pattern split across two chunks is still found.
+const SCAN_OVERLAP: usize = 1024;
+
+/// Runs the content checks of [`FileValidator::validate_upload`], and the
+/// script check applied to text uploads, over a file that arrives in chunks.
+pub(crate) struct ContentScanner {
+    content_type: String,
+    head: Vec<u8>,
+    tail: Vec<u8>,
+    size: u64,
+    /// Whether everything so far is UTF-8; script patterns only count in text.
+    is_text: bool,
+    /// The start of a character split across two chunks.
+    partial_char: Vec<u8>,
+    text_tail: String,
+    script_found: bool,
+}
+
+impl ContentScanner {
+    /// Bytes of the start of the file kept for signature checks.
+    pub const HEAD_LEN: usize = 16;
+
+    pub fn new(content_type: &str) -> Self {
+        Self {
+            content_type: content_type.to_string(),
+            head: Vec::with_capacity(Self::HEAD_LEN),
+            tail: Vec::new(),
+            size: 0,
+            is_text: true,
+            partial_char: Vec::new(),
+            text_tail: String::new(),
+            script_found: false,
+        }
+    }
+
+    /// The first [`Self::HEAD_LEN`] bytes seen.
+    pub fn head(&self) -> &[u8] {
+        &self.head
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn push(&mut self, validator: &FileValidator, chunk: &[u8]) -> Result<(), ValidationError> {
+        self.size += chunk.len() as u64;
+        if self.size > validator.config.max_file_size {
+            return Err(ValidationError::FileTooLarge {
+                size: self.size,
+                max_size: validator.config.max_file_size,
+            });
+        }
+
+        if self.head.len() < Self::HEAD_LEN {
+            let wanted = (Self::HEAD_LEN - self.head.len()).min(chunk.len());
+            self.head.extend_from_slice(&chunk[..wanted]);
+            if self.head.len() == Self::HEAD_LEN && validator.config.check_magic_bytes {
+                validator.validate_magic_bytes(&self.content_type, &self.head)?;
+            }
+        }
+
+        let mut window = std::mem::take(&mut self.tail);
+        window.extend_from_slice(chunk);
+        validator.check_suspicious_content(&window)?;
+        self.tail = window.split_off(window.len().saturating_sub(SCAN_OVERLAP));
+
+        if self.is_text {
+            self.scan_text(chunk);
+        }
+        Ok(())
+    }
+
+    fn scan_text(&mut self, chunk: &[u8]) {
+        let mut bytes = std::mem::take(&mut self.partial_char);
+        bytes.extend_from_slice(chunk);
+        let valid_up_to = match std::str::from_utf8(&bytes) {
+            Ok(_) => bytes.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => {
+                self.is_text = false;
+                self.text_tail.clear();
+                return;
+            }
+        };
+        self.partial_char = bytes.split_off(valid_up_to);
+
+        let mut window = std::mem::take(&mut self.text_tail);
+        window.push_str(std::str::from_utf8(&bytes).unwrap_or_default());
+        if crate::validation::rules::validate_no_xss(&window).is_err() {
+            self.script_found = true;
+        }
+        let mut start = window.len().saturating_sub(SCAN_OVERLAP);
+        while !window.is_char_boundary(start) {
+            start += 1;
+        }
+        self.text_tail = window.split_off(start);
+    }
+
+    /// Checks that need the whole file: it is not empty, and a text file
+    /// holds no scripts.
+    pub fn finish(self, validator: &FileValidator) -> Result<(), ValidationError> {
+        if self.size == 0 {
+            return Err(ValidationError::EmptyFile);
+        }
+        if self.head.len() < Self::HEAD_LEN && validator.config.check_magic_bytes {
+            validator.validate_magic_bytes(&self.content_type, &self.head)?;
+        }
+        if self.is_text && self.partial_char.is_empty() && self.script_found {
+            return Err(ValidationError::SuspiciousContent);
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -202,6 +308,18 @@ mod tests {
         let long_name = "a".repeat(300);
         assert!(validator.validate_filename(&long_name).is_err());
     }
+
+    #[test]
+    fn test_validate_filename_unicode() {
+        let validator = FileValidator::with_default_config();
+
+        assert!(validator.validate_filename("報告書.pdf").is_ok());
+        assert!(validator.validate_filename(&format!("{}.txt", "名".repeat(200))).is_ok());
+
+        assert!(validator.validate_filename("invoice\u{202E}fdp.exe").is_err());
+        assert!(validator.validate_filename("report\u{2066}.txt").is_err());
+        assert!(validator.validate_filename("tab\tname.txt").is_err());
+    }
     
     #[test]
     fn test_validate_content_type() {
@@ -240,4 +358,34 @@ mod tests {
         let js_data = b"javascript:alert('xss')";
         assert!(validator.check_suspicious_content(js_data).is_err());
     }
+
+    #[test]
+    fn test_content_scanner_across_chunks() {
+        let validator = FileValidator::with_default_config();
+        let scan = |content_type: &str, chunks: &[&[u8]]| {
+            let mut scanner = ContentScanner::new(content_type);
+            for chunk in chunks {
+                scanner.push(&validator, chunk)?;
+            }
+            scanner.finish(&validator)
+        };
+
+        assert!(scan("text/plain", &[b"Plain ", b"text ", b"in chunks"]).is_ok());
+        assert!(matches!(scan("text/plain", &[b"a <scr", b"ipt> b"]), Err(ValidationError::SuspiciousContent)));
+        assert!(matches!(scan("text/plain", &[b"<a onc", b"lick=1>"]), Err(ValidationError::SuspiciousContent)));
+        // A split multi-byte character keeps the file text.
+        let text = "caf\u{e9} <a onclick=1>".as_bytes();
+        assert!(scan("text/plain", &[&text[..4], &text[4..]]).is_err());
+        // Script patterns do not count in binary content.
+        assert!(scan("application/octet-stream", &[b"\xff\xfe", b" onclick=1"]).is_ok());
+
+        assert!(matches!(scan("image/png", &[b"\x89PN", b"G\r\n\x1a\n", b"rest of the image"]), Ok(())));
+        assert!(matches!(scan("image/png", &[b"GIF8", b"9a not a png at all"]), Err(ValidationError::SuspiciousContent)));
+        assert!(matches!(scan("text/plain", &[]), Err(ValidationError::EmptyFile)));
+
+        let mut scanner = ContentScanner::new("text/plain");
+        let chunk = vec![b'a'; 6 * 1024 * 1024];
+        assert!(scanner.push(&validator, &chunk).is_ok());
+        assert!(matches!(scanner.push(&validator, &chunk), Err(ValidationError::FileTooLarge { .. })));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,284 @@
+//! GraphQL API over items, files and search, built with the `graphql` feature
+//!
+//! `POST /api/graphql` runs queries and mutations with the same services,
+//! validation, cache invalidation and events as the REST item endpoints. The
+//! caller is whoever `optional_jwt_auth_middleware` identified, and every
+//! query is bounded by the depth and complexity limits in
+//! [`GraphqlConfig`]. Outside production, `GET /api/graphql` serves GraphiQL.
+
+pub mod schema;
+pub mod types;
+
+use async_graphql::http::GraphiQLSource;
+use async_graphql::{EmptySubscription, ErrorExtensions, Schema};
+use axum::{
+    extract::{ConnectInfo, State},
+    http::HeaderMap,
+    response::Html,
+    routing::post,
+    Extension, Json, Router,
+};
+use std::net::SocketAddr;
+
+use crate::config::GraphqlConfig;
+use crate::error::AppError;
+use crate::middleware::auth::AuthUser;
+use crate::validation::middleware::extract_validation_context;
+use crate::AppState;
+
+pub use schema::{MutationRoot, QueryRoot};
+
+pub const GRAPHQL_PATH: &str = "/api/graphql";
+
+pub type AppSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+pub fn build_schema(config: &GraphqlConfig) -> AppSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .limit_depth(config.max_depth)
+        .limit_complexity(config.max_complexity)
+        .finish()
+}
+
+/// The GraphQL endpoint, plus the playground unless `RUST_ENV` is
+/// `production`.
+pub fn create_graphql_routes(config: &GraphqlConfig) -> Router<AppState> {
+    let mut route = post(handle_graphql);
+    if playground_enabled() {
+        route = route.get(handle_playground);
+    }
+
+    Router::new()
+        .route(GRAPHQL_PATH, route)
+        .layer(Extension(build_schema(config)))
+}
+
+fn playground_enabled() -> bool {
+    std::env::var("RUST_ENV").map_or(true, |env| env != "production")
+}
+
+async fn handle_graphql(
+    State(state): State<AppState>,
+    Extension(schema): Extension<AppSchema>,
+    auth_user: Option<Extension<AuthUser>>,
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    Json(request): Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    let addr = connect_info.map(|ci| ci.0).unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 8080)));
+    let context = extract_validation_context(&headers, &addr, None, None)
+        .with_validation_config(state.validation_config.clone());
+
+    let mut request = request.data(state).data(context);
+    if let Some(Extension(user)) = auth_user {
+        request = request.data(user);
+    }
+
+    Json(schema.execute(request).await)
+}
+
+async fn handle_playground() -> Html<String> {
+    Html(GraphiQLSource::build().endpoint(GRAPHQL_PATH).finish())
+}
+
+fn app_state<'a>(ctx: &async_graphql::Context<'a>) -> &'a AppState {
+    ctx.data_unchecked::<AppState>()
+}
+
+/// `error` as a GraphQL error whose `code` extension mirrors the status the
+/// REST API would answer with. Server-side failures are logged and reported
+/// without detail.
+fn graphql_error(error: AppError) -> async_graphql::Error {
+    let (code, message) = match &error {
+        AppError::BadRequest(_) | AppError::JsonError(_) | AppError::Validation(_) => {
+            ("BAD_REQUEST", error.to_string())
+        }
+        AppError::NotFound(_) => ("NOT_FOUND", error.to_string()),
+        AppError::Conflict(_) | AppError::VersionConflict(_) | AppError::NameConflict(_) => {
+            ("CONFLICT", error.to_string())
+        }
+        AppError::PreconditionRequired(_) => ("PRECONDITION_REQUIRED", error.to_string()),
+        AppError::Unauthorized | AppError::Authentication(_) => ("UNAUTHENTICATED", error.to_string()),
+        AppError::Authorization(_) => ("FORBIDDEN", error.to_string()),
+        AppError::PayloadTooLarge(_) => ("PAYLOAD_TOO_LARGE", error.to_string()),
+        AppError::NotImplemented(_) => ("NOT_IMPLEMENTED", error.to_string()),
+        AppError::ServiceUnavailable(_) => ("SERVICE_UNAVAILABLE", error.to_string()),
+        _ => {
+            tracing::error!("GraphQL resolver failed: {}", error);
+            ("INTERNAL_SERVER_ERROR", "Internal server error".to_string())
+        }
+    };
+
+    let fields = match &error {
+        AppError::Validation(validation) => serde_json::to_value(validation.field_errors())
+            .ok()
+            .and_then(|fields| async_graphql::Value::from_json(fields).ok()),
+        _ => None,
+    };
+
+    async_graphql::Error::new(message).extend_with(|_, extensions| {
+        extensions.set("code", code);
+        if let Some(fields) = &fields {
+            extensions.set("fields", fields.clone());
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::models::UserRole;
+    use crate::test_support::{test_app, TestApp};
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{Request, StatusCode},
+        Router,
+    };
+    use serde_json::{json, Value};
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    fn router(app: &TestApp, configure: impl FnOnce(&mut crate::AppConfig)) -> Router {
+        let mut config = crate::AppConfig::default();
+        config.rate_limit.enable = false;
+        configure(&mut config);
+        crate::create_app_with_config(app.state.clone(), config)
+    }
+
+    async fn execute(router: &Router, token: Option<&str>, query: &str, variables: Value) -> Value {
+        let mut builder = Request::post("/api/graphql")
+            .header("user-agent", "graphql-tests")
+            .header("content-type", "application/json");
+        if let Some(token) = token {
+            builder = builder.header("authorization", format!("Bearer {}", token));
+        }
+        let mut request = builder
+            .body(Body::from(json!({ "query": query, "variables": variables }).to_string()))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_item_mutations_and_queries() {
+        let app = test_app().await;
+        let router = router(&app, |_| {});
+
+        let created = execute(
+            &router,
+            None,
+            "mutation($input: ItemInput!) { createItem(input: $input) { id name tags files { id } } }",
+            json!({ "input": { "name": "Graph item", "tags": ["graph"], "metadata": { "k": 1 } } }),
+        )
+        .await;
+        assert!(created.get("errors").is_none(), "{}", created);
+        let id = created["data"]["createItem"]["id"].as_str().unwrap().to_string();
+        assert_eq!(created["data"]["createItem"]["files"], json!([]));
+
+        let fetched = execute(
+            &router,
+            None,
+            "query($id: ID!) { item(id: $id) { name metadata } missing: item(id: \"999999\") { name } }",
+            json!({ "id": id }),
+        )
+        .await;
+        assert_eq!(fetched["data"]["item"], json!({ "name": "Graph item", "metadata": { "k": 1 } }));
+        assert_eq!(fetched["data"]["missing"], Value::Null);
+
+        let filtered = execute(
+            &router,
+            None,
+            "{ items(filter: { tags: [\"graph\"] }, pagination: { limit: 10 }) { name } }",
+            json!({}),
+        )
+        .await;
+        assert_eq!(filtered["data"]["items"], json!([{ "name": "Graph item" }]));
+
+        let updated = execute(
+            &router,
+            None,
+            "mutation($id: ID!) { updateItem(id: $id, input: { name: \"Renamed\" }) { name tags } }",
+            json!({ "id": id }),
+        )
+        .await;
+        assert_eq!(updated["data"]["updateItem"], json!({ "name": "Renamed", "tags": [] }));
+
+        let deleted = execute(&router, None, "mutation($id: ID!) { deleteItem(id: $id) }", json!({ "id": id })).await;
+        assert_eq!(deleted["data"]["deleteItem"], json!(true));
+        let deleted = execute(&router, None, "mutation($id: ID!) { deleteItem(id: $id) }", json!({ "id": id })).await;
+        assert_eq!(deleted["errors"][0]["extensions"]["code"], "NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn test_mutations_are_validated() {
+        let app = test_app().await;
+        let router = router(&app, |_| {});
+
+        let response = execute(
+            &router,
+            None,
+            "mutation { createItem(input: { name: \"\", tags: [\"\"] }) { id } }",
+            json!({}),
+        )
+        .await;
+        let error = &response["errors"][0];
+        assert_eq!(error["extensions"]["code"], "BAD_REQUEST");
+        let fields: Vec<&str> = error["extensions"]["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field["field"].as_str().unwrap())
+            .collect();
+        assert!(fields.contains(&"name"));
+        assert!(fields.contains(&"tags[0]"));
+
+        let response = execute(
+            &router,
+            None,
+            "mutation($input: ItemInput!) { createItem(input: $input) { id } }",
+            json!({ "input": { "name": "Big", "metadata": { "blob": "x".repeat(1024 * 1024) } } }),
+        )
+        .await;
+        assert_eq!(response["errors"][0]["extensions"]["code"], "PAYLOAD_TOO_LARGE");
+    }
+
+    #[tokio::test]
+    async fn test_depth_and_complexity_limits() {
+        let app = test_app().await;
+        let router = router(&app, |config| {
+            config.graphql.max_depth = 2;
+            config.graphql.max_complexity = 50;
+        });
+
+        let response = execute(&router, None, "{ items(pagination: { limit: 1 }) { name } }", json!({})).await;
+        assert!(response.get("errors").is_none(), "{}", response);
+
+        let response = execute(&router, None, "{ items(pagination: { limit: 1 }) { files { id } } }", json!({})).await;
+        assert!(response["errors"][0]["message"].as_str().unwrap().contains("too deep"));
+
+        let response = execute(&router, None, "{ items(pagination: { limit: 100 }) { name } }", json!({})).await;
+        assert!(response["errors"][0]["message"].as_str().unwrap().contains("complex"));
+    }
+
+    #[tokio::test]
+    async fn test_viewer_comes_from_bearer_token() {
+        let app = test_app().await;
+        let router = router(&app, |_| {});
+
+        let anonymous = execute(&router, None, "{ viewer { username } }", json!({})).await;
+        assert_eq!(anonymous["data"]["viewer"], Value::Null);
+
+        let token = app.login_as("grapher", UserRole::User).await;
+
+        let viewer = execute(&router, Some(&token), "{ viewer { username role namespace } }", json!({})).await;
+        assert_eq!(
+            viewer["data"]["viewer"],
+            json!({ "username": "grapher", "role": "user", "namespace": "default" })
+        );
+    }
+}
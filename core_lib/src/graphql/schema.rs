@@ -0,0 +1,217 @@
+use async_graphql::{Context, Object, ID};
+
+use crate::error::{AppError, Result};
+use crate::events::DomainEvent;
+use crate::middleware::auth::AuthUser;
+use crate::models::items::CreateItemRequest;
+use crate::search::SearchQuery;
+use crate::validation::{ContextValidatable, Sanitizable, ValidationContext};
+use crate::validation::validators::ItemValidator;
+use crate::AppState;
+
+use super::types::{
+    page_size, ItemFilter, ItemInput, ItemObject, Pagination, SearchHit, SearchInput, SearchResults, Viewer,
+};
+use super::{app_state, graphql_error};
+
+fn parse_item_id(id: &ID) -> Result<u64> {
+    id.parse::<u64>()
+        .ok()
+        .filter(|id| *id > 0)
+        .ok_or_else(|| AppError::BadRequest("Invalid item ID".to_string()))
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// The item with this id, or null when there is none.
+    async fn item(&self, ctx: &Context<'_>, id: ID) -> async_graphql::Result<Option<ItemObject>> {
+        let id = parse_item_id(&id).map_err(graphql_error)?;
+        match app_state(ctx).item_service.get_item(id).await {
+            Ok(item) => Ok(Some(ItemObject(item))),
+            Err(AppError::NotFound(_)) => Ok(None),
+            Err(e) => Err(graphql_error(e)),
+        }
+    }
+
+    /// Items, newest first.
+    #[graphql(complexity = "page_size(&pagination) * child_complexity")]
+    async fn items(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<ItemFilter>,
+        pagination: Option<Pagination>,
+    ) -> async_graphql::Result<Vec<ItemObject>> {
+        let items = list_items(app_state(ctx), filter.unwrap_or_default(), pagination.unwrap_or_default())
+            .await
+            .map_err(graphql_error)?;
+        Ok(items.into_iter().map(ItemObject).collect())
+    }
+
+    /// Full-text search over names, descriptions and tags.
+    #[graphql(complexity = "page_size(&pagination) * child_complexity")]
+    async fn search(
+        &self,
+        ctx: &Context<'_>,
+        query: SearchInput,
+        pagination: Option<Pagination>,
+    ) -> async_graphql::Result<SearchResults> {
+        let context = ctx.data_unchecked::<ValidationContext>();
+        query
+            .validate_with_context(context)
+            .ensure_valid("Search query validation failed")
+            .map_err(|e| graphql_error(e.into()))?;
+
+        search_items(app_state(ctx), query, pagination.unwrap_or_default())
+            .await
+            .map_err(graphql_error)
+    }
+
+    /// The authenticated caller, or null for anonymous requests.
+    async fn viewer(&self, ctx: &Context<'_>) -> Option<Viewer> {
+        ctx.data_opt::<AuthUser>().map(Viewer::from)
+    }
+}
+
+async fn list_items(state: &AppState, filter: ItemFilter, pagination: Pagination) -> Result<Vec<crate::store::Item>> {
+    if filter.is_empty() {
+        return state
+            .item_service
+            .get_items(Some(pagination.limit()), Some(pagination.offset()))
+            .await;
+    }
+
+    let Some(search_engine) = state.search_engine() else {
+        // Without a search index the filter applies to one page, as the
+        // REST search does in the same situation.
+        let items = state
+            .item_service
+            .get_items(Some(pagination.limit()), Some(pagination.offset()))
+            .await?;
+        return Ok(items.into_iter().filter(|item| filter.matches(item)).collect());
+    };
+
+    let mut query = SearchQuery::new().with_pagination(pagination.offset() as u64, pagination.limit() as u64);
+    if let Some(tags) = filter.tags.filter(|tags| !tags.is_empty()) {
+        query = query.with_tags(tags);
+    }
+    if filter.created_after.is_some() || filter.created_before.is_some() {
+        query = query.with_created_date_range(filter.created_after, filter.created_before);
+    }
+
+    let result = search_engine.search(&query).await?;
+    Ok(result.items.into_iter().map(|hit| hit.item).collect())
+}
+
+async fn search_items(state: &AppState, input: SearchInput, pagination: Pagination) -> Result<SearchResults> {
+    let Some(search_engine) = state.search_engine() else {
+        return Err(AppError::NotFound("Search is not available".to_string()));
+    };
+
+    let mut query = SearchQuery::new().with_pagination(pagination.offset() as u64, pagination.limit() as u64);
+    if let Some(text) = input.text.filter(|text| !text.trim().is_empty()) {
+        query = query.with_text(text);
+    }
+    if let Some(tags) = input.tags.filter(|tags| !tags.is_empty()) {
+        query = query.with_tags(tags);
+    }
+    if let Some(fuzzy) = input.fuzzy {
+        query = query.with_fuzzy(fuzzy);
+    }
+    if let Some(min_relevance) = input.min_relevance {
+        query = query.with_min_relevance(min_relevance);
+    }
+    if let Some(sort_by) = input.sort_by {
+        let order = input.order.map_or(crate::search::SortOrder::Desc, Into::into);
+        query = query.with_sort(sort_by.into(), order);
+    }
+
+    let result = search_engine.search(&query).await?;
+    Ok(SearchResults {
+        total_count: result.total_count,
+        has_more: result.has_more,
+        hits: result.items.into_iter().map(SearchHit::from).collect(),
+    })
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    async fn create_item(&self, ctx: &Context<'_>, input: ItemInput) -> async_graphql::Result<ItemObject> {
+        let state = app_state(ctx);
+        let request = validated(ctx, input).map_err(graphql_error)?;
+
+        let item = state
+            .item_service
+            .create_item(request.name, request.description, request.tags.unwrap_or_default(), request.metadata)
+            .await
+            .map_err(graphql_error)?;
+
+        state.events.publish(DomainEvent::ItemCreated(item.clone())).await;
+        Ok(ItemObject(item))
+    }
+
+    /// Replaces every field of the item, like `PUT /api/items/{id}`.
+    async fn update_item(&self, ctx: &Context<'_>, id: ID, input: ItemInput) -> async_graphql::Result<ItemObject> {
+        let state = app_state(ctx);
+        let id = parse_item_id(&id).map_err(graphql_error)?;
+        let request = validated(ctx, input).map_err(graphql_error)?;
+
+        let item = state
+            .item_service
+            .update_item(
+                id,
+                request.name,
+                request.description,
+                request.tags.unwrap_or_default(),
+                request.metadata,
+                request.version,
+            )
+            .await
+            .map_err(graphql_error)?;
+
+        state.events.publish(DomainEvent::ItemUpdated(item.clone())).await;
+        Ok(ItemObject(item))
+    }
+
+    /// Deletes the item and returns true; fails when there is none.
+    async fn delete_item(&self, ctx: &Context<'_>, id: ID) -> async_graphql::Result<bool> {
+        let state = app_state(ctx);
+        let id = parse_item_id(&id).map_err(graphql_error)?;
+
+        // Subscribers are sent the item as it was, so it is read before deletion.
+        let deleted_item = state.item_service.get_item(id).await.ok();
+
+        state.item_service.delete_item(id).await.map_err(graphql_error)?;
+
+        state.events.publish(DomainEvent::ItemDeleted { id, item: deleted_item }).await;
+        Ok(true)
+    }
+}
+
+/// `input` sanitized as the REST handlers do, then checked by
+/// [`ItemValidator`].
+fn validated(ctx: &Context<'_>, input: ItemInput) -> Result<CreateItemRequest> {
+    let context = ctx.data_unchecked::<ValidationContext>();
+    let mut request = CreateItemRequest {
+        name: input.name,
+        description: input.description,
+        tags: input.tags,
+        metadata: input.metadata.map(|metadata| metadata.0),
+        version: input.version,
+    };
+    request.sanitize_with_context(context);
+
+    ItemValidator::new(
+        request.name.clone(),
+        request.description.clone(),
+        request.tags.clone(),
+        request.metadata.clone(),
+    )
+    .validate_with_context(context)
+    .ensure_valid("Validation failed")?;
+
+    Ok(request)
+}
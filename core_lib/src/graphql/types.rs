@@ -0,0 +1,283 @@
+use async_graphql::{Enum, InputObject, Json, Object, SimpleObject, ID};
+use chrono::{DateTime, Utc};
+
+use crate::files::FileMetadata;
+use crate::middleware::auth::AuthUser;
+use crate::search::{SearchResultItem, SortField, SortOrder};
+use crate::store::Item;
+use crate::validation::{rules, ContextValidatable, ValidationContext, ValidationResult};
+
+use super::{app_state, graphql_error};
+
+/// Page size when a query does not give one.
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+/// Largest page a query may request.
+pub const MAX_PAGE_SIZE: usize = 100;
+/// Files of an item are not paginated, so they count as this many rows
+/// towards the complexity limit.
+pub const FILES_COMPLEXITY_FACTOR: usize = 10;
+
+const MAX_SEARCH_TEXT_LEN: usize = 500;
+const MAX_SEARCH_TAGS: usize = 20;
+
+pub struct ItemObject(pub Item);
+
+#[Object(name = "Item")]
+impl ItemObject {
+    async fn id(&self) -> ID {
+        ID(self.0.id.to_string())
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn description(&self) -> Option<&str> {
+        self.0.description.as_deref()
+    }
+
+    async fn tags(&self) -> &[String] {
+        &self.0.tags
+    }
+
+    async fn metadata(&self) -> Option<Json<serde_json::Value>> {
+        self.0.metadata.as_ref().map(|metadata| {
+            let mut metadata = metadata.to_value();
+            crate::item_secrets::redact_metadata(&mut metadata);
+            Json(metadata)
+        })
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    async fn updated_at(&self) -> DateTime<Utc> {
+        self.0.updated_at
+    }
+
+    /// Incremented by every update; pass it back to `updateItem` to have
+    /// the update refused if someone else changed the item first.
+    async fn version(&self) -> u64 {
+        self.0.version
+    }
+
+    /// Files attached to the item; empty when file storage is disabled.
+    #[graphql(complexity = "FILES_COMPLEXITY_FACTOR * child_complexity")]
+    async fn files(&self, ctx: &async_graphql::Context<'_>) -> async_graphql::Result<Vec<FileObject>> {
+        let Some(file_manager) = &app_state(ctx).file_manager else {
+            return Ok(Vec::new());
+        };
+        let files = file_manager.get_files_by_item(self.0.id).await.map_err(graphql_error)?;
+        Ok(files.into_iter().map(FileObject).collect())
+    }
+}
+
+pub struct FileObject(pub FileMetadata);
+
+#[Object(name = "File")]
+impl FileObject {
+    async fn id(&self) -> ID {
+        ID(self.0.id.to_string())
+    }
+
+    async fn filename(&self) -> &str {
+        &self.0.filename
+    }
+
+    async fn original_filename(&self) -> &str {
+        &self.0.original_filename
+    }
+
+    async fn content_type(&self) -> &str {
+        &self.0.content_type
+    }
+
+    async fn size(&self) -> u64 {
+        self.0.size
+    }
+
+    async fn uploaded_by(&self) -> u64 {
+        self.0.uploaded_by
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    async fn download_url(&self) -> String {
+        format!("/api/files/{}/download", self.0.id)
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct SearchHit {
+    pub item: ItemObject,
+    pub relevance_score: Option<f64>,
+    pub matched_fields: Vec<String>,
+}
+
+impl From<SearchResultItem> for SearchHit {
+    fn from(result: SearchResultItem) -> Self {
+        Self {
+            item: ItemObject(result.item),
+            relevance_score: result.relevance_score,
+            matched_fields: result.matched_fields,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct SearchResults {
+    pub hits: Vec<SearchHit>,
+    pub total_count: u64,
+    pub has_more: bool,
+}
+
+/// The caller, as identified by their bearer token.
+#[derive(SimpleObject)]
+pub struct Viewer {
+    pub user_id: i64,
+    pub username: String,
+    pub role: String,
+    pub namespace: String,
+}
+
+impl From<&AuthUser> for Viewer {
+    fn from(user: &AuthUser) -> Self {
+        Self {
+            user_id: user.user_id,
+            username: user.username.clone(),
+            role: user.role.to_string(),
+            namespace: user.namespace.clone(),
+        }
+    }
+}
+
+#[derive(InputObject, Default)]
+pub struct Pagination {
+    pub offset: Option<usize>,
+    /// At most 100; 50 when omitted.
+    pub limit: Option<usize>,
+}
+
+impl Pagination {
+    pub fn offset(&self) -> usize {
+        self.offset.unwrap_or(0)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+/// Rows the complexity of a list field is multiplied by.
+pub fn page_size(pagination: &Option<Pagination>) -> usize {
+    pagination.as_ref().map_or(DEFAULT_PAGE_SIZE, Pagination::limit)
+}
+
+#[derive(InputObject, Default)]
+pub struct ItemFilter {
+    /// Items carrying any of these tags.
+    pub tags: Option<Vec<String>>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+impl ItemFilter {
+    pub fn is_empty(&self) -> bool {
+        self.tags.as_ref().is_none_or(Vec::is_empty) && self.created_after.is_none() && self.created_before.is_none()
+    }
+
+    pub fn matches(&self, item: &Item) -> bool {
+        let tags_match = self
+            .tags
+            .as_ref()
+            .filter(|tags| !tags.is_empty())
+            .is_none_or(|tags| item.tags.iter().any(|tag| tags.contains(tag)));
+        tags_match
+            && self.created_after.is_none_or(|after| item.created_at >= after)
+            && self.created_before.is_none_or(|before| item.created_at <= before)
+    }
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum SearchSort {
+    Relevance,
+    Name,
+    CreatedAt,
+    UpdatedAt,
+}
+
+impl From<SearchSort> for SortField {
+    fn from(sort: SearchSort) -> Self {
+        match sort {
+            SearchSort::Relevance => SortField::Relevance,
+            SearchSort::Name => SortField::Name,
+            SearchSort::CreatedAt => SortField::CreatedAt,
+            SearchSort::UpdatedAt => SortField::UpdatedAt,
+        }
+    }
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+impl From<SortDirection> for SortOrder {
+    fn from(direction: SortDirection) -> Self {
+        match direction {
+            SortDirection::Asc => SortOrder::Asc,
+            SortDirection::Desc => SortOrder::Desc,
+        }
+    }
+}
+
+#[derive(InputObject, Default)]
+pub struct SearchInput {
+    pub text: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub fuzzy: Option<bool>,
+    pub min_relevance: Option<f64>,
+    pub sort_by: Option<SearchSort>,
+    pub order: Option<SortDirection>,
+}
+
+impl ContextValidatable for SearchInput {
+    fn validate_with_context(&self, _context: &ValidationContext) -> ValidationResult {
+        let mut result = ValidationResult::success();
+
+        if let Some(text) = &self.text {
+            if text.len() > MAX_SEARCH_TEXT_LEN {
+                result.add_error("text", "Search query is too long");
+            }
+            if rules::validate_no_sql_injection(text).is_err() {
+                result.add_error("text", "Search query contains invalid characters");
+            }
+        }
+
+        if let Some(tags) = &self.tags {
+            if tags.len() > MAX_SEARCH_TAGS {
+                result.add_error("tags", "Too many tags in search");
+            }
+            if tags.iter().any(|tag| rules::validate_no_sql_injection(tag.trim()).is_err()) {
+                result.add_error("tags", "Tag contains invalid characters");
+            }
+        }
+
+        result
+    }
+}
+
+/// Fields of an item to create, or to replace on update.
+#[derive(InputObject)]
+pub struct ItemInput {
+    pub name: String,
+    pub description: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub metadata: Option<Json<serde_json::Value>>,
+    /// Version the update was made against; ignored on create.
+    pub version: Option<u64>,
+}
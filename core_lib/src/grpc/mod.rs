@@ -0,0 +1,358 @@
+//! gRPC item API, built with the `grpc` feature
+//!
+//! [`GrpcServer`] serves `items.v1.ItemService` (see `proto/items.proto`)
+//! and the standard `grpc.health.v1.Health` service on `server.grpc_port`.
+//! Item calls go through the same services, validation, events and
+//! namespaces as the HTTP item endpoints, and each one is counted by the
+//! [`MetricsSink`](crate::MetricsSink) under its method path.
+
+pub mod service;
+
+pub mod proto {
+    tonic::include_proto!("items.v1");
+}
+
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::server::NamedService;
+use tonic::Status;
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tracing::{info, warn};
+
+use crate::config::ServerConfig;
+use crate::error::{AppError, Result};
+use crate::health::{HealthChecker, HealthStatus};
+use crate::AppState;
+
+pub use service::ItemGrpcService;
+
+use proto::item_service_server::ItemServiceServer;
+
+/// How often the health service picks up the status of the health checks.
+const HEALTH_SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The gRPC listener plus the settings from [`ServerConfig`] it shares with
+/// the HTTP server.
+pub struct GrpcServer {
+    listener: TcpListener,
+    shutdown_timeout: Duration,
+}
+
+impl GrpcServer {
+    /// Binds `host:grpc_port`.
+    pub async fn bind(config: &ServerConfig) -> Result<Self> {
+        let port = config
+            .grpc_port
+            .ok_or_else(|| AppError::Configuration("No gRPC port configured".to_string()))?;
+        let host: IpAddr = config
+            .host
+            .parse()
+            .map_err(|_| AppError::Configuration(format!("Invalid server host: {}", config.host)))?;
+
+        let listener = TcpListener::bind(SocketAddr::new(host, port)).await?;
+        info!("gRPC listening on {}", listener.local_addr()?);
+
+        Ok(Self {
+            listener,
+            shutdown_timeout: Duration::from_secs(config.shutdown_timeout_seconds),
+        })
+    }
+
+    /// The bound address, with the actual port when bound to port 0.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serves until `signal` completes. From then on health checks report
+    /// `NOT_SERVING`, `Watch` streams end, and open calls get up to
+    /// `shutdown_timeout_seconds` to finish.
+    pub async fn serve<F>(self, state: AppState, signal: F) -> Result<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (stop_tx, mut stop_rx) = watch::channel(false);
+        tokio::spawn(async move {
+            signal.await;
+            let _ = stop_tx.send(true);
+        });
+
+        let (mut reporter, health_service) = tonic_health::server::health_reporter();
+        reporter.set_serving::<ItemServiceServer<ItemGrpcService>>().await;
+        let health_sync = tokio::spawn(sync_health(reporter.clone(), state.health_checker.clone()));
+
+        let items = ItemGrpcService::new(state);
+        let draining = items.clone();
+        let mut stopped = stop_rx.clone();
+        let shutdown = async move {
+            let _ = stopped.wait_for(|stop| *stop).await;
+            health_sync.abort();
+            for service in ["", ItemServiceServer::<ItemGrpcService>::NAME] {
+                reporter.set_service_status(service, ServingStatus::NotServing).await;
+            }
+            draining.stop_watches();
+        };
+
+        let serving = tonic::transport::Server::builder()
+            .add_service(health_service)
+            .add_service(ItemServiceServer::new(items))
+            .serve_with_incoming_shutdown(TcpListenerStream::new(self.listener), shutdown);
+        tokio::pin!(serving);
+
+        tokio::select! {
+            result = &mut serving => return result.map_err(|e| AppError::Other(e.into())),
+            _ = stop_rx.wait_for(|stop| *stop) => {}
+        }
+
+        match tokio::time::timeout(self.shutdown_timeout, serving).await {
+            Ok(result) => result.map_err(|e| AppError::Other(e.into())),
+            Err(_) => {
+                warn!("gRPC calls still open after {:?}, closing them", self.shutdown_timeout);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Reports the server and the item service as `NOT_SERVING` while any
+/// component is unhealthy. Without health checks both stay `SERVING`.
+async fn sync_health(mut reporter: HealthReporter, health_checker: Option<Arc<HealthChecker>>) {
+    let Some(health_checker) = health_checker else {
+        return;
+    };
+
+    let mut ticker = tokio::time::interval(HEALTH_SYNC_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let healthy = health_checker
+            .component_states()
+            .values()
+            .all(|state| state.status != HealthStatus::Unhealthy);
+        let status = if healthy { ServingStatus::Serving } else { ServingStatus::NotServing };
+        for service in ["", ItemServiceServer::<ItemGrpcService>::NAME] {
+            reporter.set_service_status(service, status).await;
+        }
+    }
+}
+
+/// `error` as a gRPC status whose code matches the status the HTTP API
+/// would answer with. Server-side failures are logged and reported without
+/// detail.
+fn status(error: AppError) -> Status {
+    match &error {
+        AppError::BadRequest(_) | AppError::JsonError(_) | AppError::Validation(_) => {
+            Status::invalid_argument(error.to_string())
+        }
+        AppError::NotFound(_) => Status::not_found(error.to_string()),
+        AppError::Gone(_) => Status::out_of_range(error.to_string()),
+        AppError::Conflict(_) | AppError::NameConflict(_) => Status::already_exists(error.to_string()),
+        AppError::VersionConflict(_) => Status::aborted(error.to_string()),
+        AppError::PreconditionRequired(_) => Status::failed_precondition(error.to_string()),
+        AppError::Unauthorized | AppError::Authentication(_) => Status::unauthenticated(error.to_string()),
+        AppError::Authorization(_) => Status::permission_denied(error.to_string()),
+        AppError::RateLimit(_) | AppError::InsufficientStorage(_) | AppError::PayloadTooLarge(_) => {
+            Status::resource_exhausted(error.to_string())
+        }
+        AppError::NotImplemented(_) => Status::unimplemented(error.to_string()),
+        AppError::ServiceUnavailable(_) => Status::unavailable(error.to_string()),
+        _ => {
+            tracing::error!("gRPC call failed: {}", error);
+            Status::internal("Internal server error")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::proto::item_event::Event;
+    use super::proto::item_service_client::ItemServiceClient;
+    use super::proto::{CreateItemRequest, DeleteItemRequest, GetItemRequest, ListItemsRequest, UpdateItemRequest, WatchRequest};
+    use super::*;
+    use crate::auth::models::UserRole;
+    use crate::tenancy;
+    use crate::test_support::{test_app, TestApp};
+    use tokio::sync::oneshot;
+    use tonic::transport::Channel;
+    use tonic::Code;
+    use tonic_health::pb::{health_client::HealthClient, health_check_response, HealthCheckRequest};
+
+    async fn start(app: &TestApp) -> (Channel, oneshot::Sender<()>, tokio::task::JoinHandle<Result<()>>) {
+        let config = ServerConfig {
+            grpc_port: Some(0),
+            shutdown_timeout_seconds: 1,
+            ..ServerConfig::default()
+        };
+        let server = GrpcServer::bind(&config).await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let serving = tokio::spawn(server.serve(app.state.clone(), async move {
+            let _ = stopped.await;
+        }));
+
+        let channel = Channel::from_shared(format!("http://{}", addr)).unwrap().connect().await.unwrap();
+        (channel, stop, serving)
+    }
+
+    fn create(name: &str) -> CreateItemRequest {
+        CreateItemRequest {
+            name: name.to_string(),
+            description: None,
+            tags: vec!["grpc".to_string()],
+            metadata_json: Some(r#"{"k":1}"#.to_string()),
+        }
+    }
+
+    fn with_token<T>(message: T, token: &str) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        request
+            .metadata_mut()
+            .insert("authorization", format!("Bearer {}", token).parse().unwrap());
+        request
+    }
+
+    #[tokio::test]
+    async fn test_item_calls_are_served_and_watched() {
+        let app = test_app().await;
+        let (channel, stop, serving) = start(&app).await;
+        let mut client = ItemServiceClient::new(channel);
+        let mut events = client.watch(WatchRequest {}).await.unwrap().into_inner();
+
+        let created = client.create_item(create("Remote item")).await.unwrap().into_inner();
+        assert_eq!(created.tags, vec!["grpc"]);
+        assert_eq!(created.metadata_json.as_deref(), Some(r#"{"k":1}"#));
+
+        let fetched = client.get_item(GetItemRequest { id: created.id }).await.unwrap().into_inner();
+        assert_eq!(fetched, created);
+
+        let listed = client
+            .list_items(ListItemsRequest { offset: 0, limit: 1 })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(listed.items, vec![created.clone()]);
+
+        let updated = client
+            .update_item(UpdateItemRequest {
+                id: created.id,
+                name: "Renamed".to_string(),
+                description: Some("Now described".to_string()),
+                tags: Vec::new(),
+                metadata_json: None,
+                version: Some(created.version),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((updated.name.as_str(), updated.tags.len()), ("Renamed", 0));
+        assert_eq!(updated.version, created.version + 1);
+
+        let stale = client
+            .update_item(UpdateItemRequest {
+                id: created.id,
+                name: "Stale".to_string(),
+                description: None,
+                tags: Vec::new(),
+                metadata_json: None,
+                version: Some(created.version),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(stale.code(), Code::Aborted);
+
+        client.delete_item(DeleteItemRequest { id: created.id }).await.unwrap();
+        let missing = client.get_item(GetItemRequest { id: created.id }).await.unwrap_err();
+        assert_eq!(missing.code(), Code::NotFound);
+
+        let invalid = client.create_item(create("")).await.unwrap_err();
+        assert_eq!(invalid.code(), Code::InvalidArgument);
+        let mut bad_metadata = create("Bad metadata");
+        bad_metadata.metadata_json = Some("{".to_string());
+        assert_eq!(client.create_item(bad_metadata).await.unwrap_err().code(), Code::InvalidArgument);
+        let mut big_metadata = create("Big metadata");
+        big_metadata.metadata_json = Some(serde_json::json!({ "blob": "x".repeat(1024 * 1024) }).to_string());
+        assert_eq!(client.create_item(big_metadata).await.unwrap_err().code(), Code::ResourceExhausted);
+
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(events.message().await.unwrap().unwrap().event.unwrap());
+        }
+        assert!(matches!(&received[0], Event::Created(item) if item.id == created.id));
+        assert!(matches!(&received[1], Event::Updated(item) if item.name == "Renamed"));
+        assert_eq!(received[2], Event::Deleted(created.id));
+
+        let methods = app.state.metrics.in_memory().unwrap().rpc_methods();
+        let create_calls = methods
+            .iter()
+            .find(|metric| metric.method == "/items.v1.ItemService/CreateItem")
+            .unwrap();
+        assert_eq!((create_calls.calls, create_calls.failures), (4, 3));
+
+        stop.send(()).unwrap();
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_calls_run_as_the_token_holder() {
+        let app = test_app().await;
+        let (channel, stop, serving) = start(&app).await;
+        let mut client = ItemServiceClient::new(channel);
+
+        let tenant = tenancy::scope("acme".to_string(), app.login_as("acmeuser", UserRole::User)).await;
+        let created = client
+            .create_item(with_token(create("Acme item"), &tenant))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let names = |items: Vec<proto::Item>| items.into_iter().map(|item| item.name).collect::<Vec<_>>();
+        let request = ListItemsRequest { offset: 0, limit: 0 };
+        let acme = client.list_items(with_token(request.clone(), &tenant)).await.unwrap().into_inner();
+        assert_eq!(names(acme.items), vec!["Acme item"]);
+        let anonymous = client.list_items(request.clone()).await.unwrap().into_inner();
+        assert!(!names(anonymous.items).contains(&"Acme item".to_string()));
+        let fetched = client.get_item(GetItemRequest { id: created.id }).await.unwrap_err();
+        assert_eq!(fetched.code(), Code::NotFound);
+
+        let invalid = client.list_items(with_token(request.clone(), "not-a-token")).await.unwrap_err();
+        assert_eq!(invalid.code(), Code::Unauthenticated);
+
+        let mut override_request = with_token(request, &tenant);
+        override_request.metadata_mut().insert("x-namespace", "other".parse().unwrap());
+        let refused = client.list_items(override_request).await.unwrap_err();
+        assert_eq!(refused.code(), Code::PermissionDenied);
+
+        stop.send(()).unwrap();
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_health_is_served_and_shutdown_ends_watches() {
+        let app = test_app().await;
+        let (channel, stop, serving) = start(&app).await;
+        let mut health = HealthClient::new(channel.clone());
+
+        for service in ["", "items.v1.ItemService"] {
+            let response = health
+                .check(HealthCheckRequest { service: service.to_string() })
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(response.status(), health_check_response::ServingStatus::Serving);
+        }
+
+        let mut events = ItemServiceClient::new(channel).watch(WatchRequest {}).await.unwrap().into_inner();
+        stop.send(()).unwrap();
+
+        assert_eq!(events.message().await.unwrap(), None);
+        tokio::time::timeout(Duration::from_secs(5), serving)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,345 @@
+use futures_util::{Stream, StreamExt};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::watch;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tonic::{Request, Response, Status};
+
+use crate::error::{AppError, Result};
+use crate::events::DomainEvent;
+use crate::middleware::auth::{authenticate_token, extract_token_from_header, AuthUser};
+use crate::middleware::namespace::resolve_namespace;
+use crate::models::items::CreateItemRequest as ItemRequest;
+use crate::store::Item;
+use crate::tenancy::{self, NAMESPACE_HEADER};
+use crate::validation::middleware::extract_validation_context;
+use crate::validation::{ContextValidatable, Sanitizable, ValidationContext};
+use crate::websocket::WebSocketMessage;
+use crate::AppState;
+
+use super::proto::item_service_server::ItemService;
+use super::proto::{self, item_event::Event};
+use super::status;
+
+/// Page size when `ListItems` does not give one.
+pub const DEFAULT_PAGE_SIZE: u32 = 50;
+/// Largest page `ListItems` returns.
+pub const MAX_PAGE_SIZE: u32 = 100;
+
+const GET_ITEM: &str = "/items.v1.ItemService/GetItem";
+const LIST_ITEMS: &str = "/items.v1.ItemService/ListItems";
+const CREATE_ITEM: &str = "/items.v1.ItemService/CreateItem";
+const UPDATE_ITEM: &str = "/items.v1.ItemService/UpdateItem";
+const DELETE_ITEM: &str = "/items.v1.ItemService/DeleteItem";
+const WATCH: &str = "/items.v1.ItemService/Watch";
+
+/// Peer address used for validation when the transport does not report one.
+const UNKNOWN_PEER: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED), 0);
+
+type EventStream = Pin<Box<dyn Stream<Item = std::result::Result<proto::ItemEvent, Status>> + Send>>;
+
+/// `items.v1.ItemService` over the [`AppState`] the HTTP API uses.
+#[derive(Clone)]
+pub struct ItemGrpcService {
+    state: AppState,
+    stopping: Arc<watch::Sender<bool>>,
+}
+
+/// What a handler needs besides its request message.
+struct Call {
+    state: AppState,
+    validation: ValidationContext,
+}
+
+impl ItemGrpcService {
+    pub fn new(state: AppState) -> Self {
+        Self {
+            state,
+            stopping: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    /// Ends every open `Watch` stream, and any opened later, so connections
+    /// can drain during shutdown.
+    pub fn stop_watches(&self) {
+        self.stopping.send_replace(true);
+    }
+
+    /// Runs `handler` as the caller, inside their namespace, and counts the
+    /// call under `method`.
+    async fn call<Req, Res, F, Fut>(
+        &self,
+        method: &'static str,
+        request: Request<Req>,
+        handler: F,
+    ) -> std::result::Result<Response<Res>, Status>
+    where
+        F: FnOnce(Call, Req) -> Fut,
+        Fut: Future<Output = Result<Res>>,
+    {
+        let started = Instant::now();
+        let result = self.dispatch(method, request, handler).await.map_err(status);
+        self.state.metrics.record_rpc(method, result.is_ok(), started.elapsed());
+        result.map(Response::new)
+    }
+
+    async fn dispatch<Req, Res, F, Fut>(&self, method: &'static str, request: Request<Req>, handler: F) -> Result<Res>
+    where
+        F: FnOnce(Call, Req) -> Fut,
+        Fut: Future<Output = Result<Res>>,
+    {
+        let peer = request.remote_addr().unwrap_or(UNKNOWN_PEER);
+        let (metadata, _, message) = request.into_parts();
+        let headers = metadata.into_headers();
+
+        let auth_user = self.authenticate(&headers).await?;
+        let requested = headers
+            .get(NAMESPACE_HEADER)
+            .map(|value| {
+                value
+                    .to_str()
+                    .map_err(|_| AppError::BadRequest("Invalid x-namespace metadata".to_string()))
+            })
+            .transpose()?;
+        let namespace = resolve_namespace(&self.state, auth_user.as_ref(), requested, "POST", method)?;
+
+        let call = Call {
+            state: self.state.clone(),
+            validation: extract_validation_context(&headers, &peer, None, None)
+                .with_validation_config(self.state.validation_config.clone()),
+        };
+        tenancy::scope(namespace, handler(call, message)).await
+    }
+
+    /// The caller named by an `authorization` entry, or `None` for calls
+    /// without one. Unlike the HTTP API, an invalid token fails the call
+    /// rather than making it anonymous.
+    async fn authenticate(&self, headers: &http::HeaderMap) -> Result<Option<AuthUser>> {
+        if !headers.contains_key(http::header::AUTHORIZATION) {
+            return Ok(None);
+        }
+        let auth_service = self
+            .state
+            .auth_service
+            .as_ref()
+            .ok_or_else(|| AppError::Authentication("Authentication is not available".to_string()))?;
+
+        let token = extract_token_from_header(headers)?;
+        authenticate_token(auth_service, &token).await.map(Some)
+    }
+}
+
+#[tonic::async_trait]
+impl ItemService for ItemGrpcService {
+    async fn get_item(
+        &self,
+        request: Request<proto::GetItemRequest>,
+    ) -> std::result::Result<Response<proto::Item>, Status> {
+        self.call(GET_ITEM, request, |call, request| async move {
+            let item = call.state.item_service.get_item(item_id(request.id)?).await?;
+            Ok(item.into())
+        })
+        .await
+    }
+
+    async fn list_items(
+        &self,
+        request: Request<proto::ListItemsRequest>,
+    ) -> std::result::Result<Response<proto::ListItemsResponse>, Status> {
+        self.call(LIST_ITEMS, request, |call, request| async move {
+            let limit = match request.limit {
+                0 => DEFAULT_PAGE_SIZE,
+                limit => limit.min(MAX_PAGE_SIZE),
+            };
+            let items = call
+                .state
+                .item_service
+                .get_items(Some(limit as usize), Some(request.offset as usize))
+                .await?;
+            Ok(proto::ListItemsResponse {
+                items: items.into_iter().map(Into::into).collect(),
+            })
+        })
+        .await
+    }
+
+    async fn create_item(
+        &self,
+        request: Request<proto::CreateItemRequest>,
+    ) -> std::result::Result<Response<proto::Item>, Status> {
+        self.call(CREATE_ITEM, request, |call, request| async move {
+            let request = validated(
+                &call.validation,
+                request.name,
+                request.description,
+                request.tags,
+                request.metadata_json,
+            )?;
+
+            let item = call
+                .state
+                .item_service
+                .create_item(request.name, request.description, request.tags.unwrap_or_default(), request.metadata)
+                .await?;
+
+            call.state.events.publish(DomainEvent::ItemCreated(item.clone())).await;
+            Ok(item.into())
+        })
+        .await
+    }
+
+    async fn update_item(
+        &self,
+        request: Request<proto::UpdateItemRequest>,
+    ) -> std::result::Result<Response<proto::Item>, Status> {
+        self.call(UPDATE_ITEM, request, |call, request| async move {
+            let id = item_id(request.id)?;
+            let expected_version = request.version;
+            let request = validated(
+                &call.validation,
+                request.name,
+                request.description,
+                request.tags,
+                request.metadata_json,
+            )?;
+
+            let item = call
+                .state
+                .item_service
+                .update_item(
+                    id,
+                    request.name,
+                    request.description,
+                    request.tags.unwrap_or_default(),
+                    request.metadata,
+                    expected_version,
+                )
+                .await?;
+
+            call.state.events.publish(DomainEvent::ItemUpdated(item.clone())).await;
+            Ok(item.into())
+        })
+        .await
+    }
+
+    async fn delete_item(
+        &self,
+        request: Request<proto::DeleteItemRequest>,
+    ) -> std::result::Result<Response<proto::DeleteItemResponse>, Status> {
+        self.call(DELETE_ITEM, request, |call, request| async move {
+            let id = item_id(request.id)?;
+
+            // Subscribers are sent the item as it was, so it is read before deletion.
+            let deleted_item = call.state.item_service.get_item(id).await.ok();
+
+            call.state.item_service.delete_item(id).await?;
+
+            call.state.events.publish(DomainEvent::ItemDeleted { id, item: deleted_item }).await;
+            Ok(proto::DeleteItemResponse {})
+        })
+        .await
+    }
+
+    type WatchStream = EventStream;
+
+    async fn watch(
+        &self,
+        request: Request<proto::WatchRequest>,
+    ) -> std::result::Result<Response<Self::WatchStream>, Status> {
+        let mut stopping = self.stopping.subscribe();
+        self.call(WATCH, request, |call, _| async move {
+            let ws_manager = call
+                .state
+                .websocket_manager
+                .as_ref()
+                .ok_or_else(|| AppError::NotFound("Item events are not available".to_string()))?;
+
+            let events = BroadcastStream::new(ws_manager.subscribe())
+                .filter_map(|message| std::future::ready(item_event(message)))
+                .take_until(async move {
+                    let _ = stopping.wait_for(|stopping| *stopping).await;
+                });
+            Ok(Box::pin(events) as EventStream)
+        })
+        .await
+    }
+}
+
+fn item_id(id: u64) -> Result<u64> {
+    match id {
+        0 => Err(AppError::BadRequest("Invalid item ID".to_string())),
+        id => Ok(id),
+    }
+}
+
+/// The item fields of a create or update, sanitized and validated as the
+/// HTTP handlers do.
+fn validated(
+    context: &ValidationContext,
+    name: String,
+    description: Option<String>,
+    tags: Vec<String>,
+    metadata_json: Option<String>,
+) -> Result<ItemRequest> {
+    let metadata = metadata_json
+        .map(|json| serde_json::from_str(&json))
+        .transpose()
+        .map_err(|_| AppError::BadRequest("metadata_json is not valid JSON".to_string()))?;
+
+    let mut request = ItemRequest {
+        name,
+        description,
+        tags: Some(tags).filter(|tags| !tags.is_empty()),
+        metadata,
+        version: None,
+    };
+    request.sanitize_with_context(context);
+    request
+        .validate_with_context(context)
+        .ensure_valid("Validation failed")?;
+
+    Ok(request)
+}
+
+/// The `ItemEvent` for a broadcast, `None` for broadcasts about anything
+/// other than items. A watcher that falls too far behind is told how many
+/// events it missed and the stream ends.
+fn item_event(
+    message: std::result::Result<WebSocketMessage, BroadcastStreamRecvError>,
+) -> Option<std::result::Result<proto::ItemEvent, Status>> {
+    let event = match message {
+        Ok(WebSocketMessage::ItemCreated(item)) => Event::Created(item.into()),
+        Ok(WebSocketMessage::ItemUpdated(item)) => Event::Updated(item.into()),
+        Ok(WebSocketMessage::ItemDeleted { id }) => Event::Deleted(id),
+        Ok(_) => return None,
+        Err(BroadcastStreamRecvError::Lagged(missed)) => {
+            return Some(Err(Status::data_loss(format!(
+                "Watcher fell behind and missed {} events",
+                missed
+            ))))
+        }
+    };
+    Some(Ok(proto::ItemEvent { event: Some(event) }))
+}
+
+impl From<Item> for proto::Item {
+    fn from(item: Item) -> Self {
+        Self {
+            id: item.id,
+            name: item.name,
+            description: item.description,
+            tags: item.tags,
+            metadata_json: item.metadata.map(|metadata| {
+                let mut metadata = metadata.to_value();
+                crate::item_secrets::redact_metadata(&mut metadata);
+                metadata.to_string()
+            }),
+            created_at: item.created_at.to_rfc3339(),
+            updated_at: item.updated_at.to_rfc3339(),
+            version: item.version,
+        }
+    }
+}
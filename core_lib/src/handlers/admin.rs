@@ -0,0 +1,863 @@
+use crate::{
+    audit::AuditEvent,
+    auth::models::{CreateUserRequest, UserRole},
+    capture::{CaptureRecorder, CaptureRequest},
+    database::{SortSpec, USER_SORT},
+    error::{AppError, Result},
+    features::Subsystem,
+    jobs::{JobPriority, JobRequest, JobType},
+    middleware::auth::AuthUser,
+    models::request::ApiResponse,
+    seed::{Fixtures, SeedOptions},
+    snapshot::{ExportOptions, OnConflict, SnapshotService, UserImportOptions},
+    websocket::WebSocketMessage,
+    AppState,
+};
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::Deserialize;
+use std::net::IpAddr;
+use tracing::info;
+
+/// Exempt from the JSON body limit; see [`import_snapshot`].
+pub const SNAPSHOT_IMPORT_PATH: &str = "/api/admin/import";
+/// Exempt from the JSON body limit; see [`import_users`].
+pub const USER_IMPORT_PATH: &str = "/api/admin/users/import";
+/// Carries the passphrase of a user bundle, so it stays out of URLs and
+/// captured bodies.
+pub const BUNDLE_PASSPHRASE_HEADER: &str = "x-bundle-passphrase";
+
+/// Every route with the guard protecting it, for checking that nothing is
+/// left open by mistake.
+pub async fn list_routes() -> Result<impl IntoResponse> {
+    info!("GET /api/admin/routes");
+
+    let routes = crate::handlers::routes::route_inventory();
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "routes": routes,
+        "total": routes.len(),
+    }))))
+}
+
+/// Whether each optional subsystem is switched on.
+pub async fn list_features(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    info!("GET /api/admin/features");
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "features": state.features.states(),
+    }))))
+}
+
+/// How each subsystem came up when the server started, including any that
+/// fell back or failed.
+pub async fn get_startup_report(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    info!("GET /api/admin/startup-report");
+
+    let report = state
+        .startup_report
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound("No startup report was recorded".to_string()))?;
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "report": report,
+        "degraded": report.is_degraded(),
+    }))))
+}
+
+/// The subscribers to domain events, with how many events each handled and
+/// failed to handle since startup.
+pub async fn list_event_subscribers(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    info!("GET /api/admin/events");
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "subscribers": state.events.stats(),
+    }))))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetFeatureRequest {
+    pub enabled: bool,
+}
+
+/// Switches a subsystem on or off. The change is saved, so it outlasts a
+/// restart, and cached responses are dropped since they may have been built
+/// with the subsystem in its old state.
+pub async fn set_feature(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Path(name): Path<String>,
+    Json(request): Json<SetFeatureRequest>,
+) -> Result<impl IntoResponse> {
+    info!("POST /api/admin/features/{}", name);
+
+    let subsystem: Subsystem = name.parse()?;
+    let was_enabled = state.features.set(subsystem, request.enabled, &admin.username).await?;
+
+    if was_enabled != request.enabled {
+        if let Some(cache_manager) = &state.cache_manager {
+            cache_manager.clear();
+        }
+    }
+
+    state.audit_log.record(
+        AuditEvent::new("features.switched")
+            .with_actor(admin.username.clone())
+            .with_target(subsystem.name())
+            .with_details(serde_json::json!({
+                "enabled": request.enabled,
+                "was_enabled": was_enabled,
+            })),
+    );
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "feature": subsystem,
+        "enabled": request.enabled,
+        "was_enabled": was_enabled,
+        "features": state.features.states(),
+    }))))
+}
+
+pub async fn list_security_blocks(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse> {
+    info!("GET /api/admin/security/blocks");
+
+    let blocks = state.anomaly_tracker.blocks();
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "blocks": blocks,
+        "total": blocks.len(),
+        "threshold": state.anomaly_tracker.config().anomaly_threshold,
+        "window_seconds": state.anomaly_tracker.config().anomaly_window_seconds,
+        "block_duration_seconds": state.anomaly_tracker.config().block_duration_seconds,
+    }))))
+}
+
+pub async fn unblock_client(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Path(ip): Path<String>,
+) -> Result<impl IntoResponse> {
+    info!("DELETE /api/admin/security/blocks/{}", ip);
+
+    let ip: IpAddr = ip
+        .parse()
+        .map_err(|_| AppError::BadRequest(format!("Invalid IP address: {}", ip)))?;
+
+    let entry = state
+        .anomaly_tracker
+        .unblock(&ip)
+        .ok_or_else(|| AppError::NotFound(format!("No active block for {}", ip)))?;
+
+    state.metrics.record_security_event("client_unblocked");
+    state.audit_log.record(
+        AuditEvent::new("security.client_unblocked")
+            .with_actor(admin.username.clone())
+            .with_target(ip.to_string())
+            .with_details(serde_json::json!({
+                "score": entry.score,
+                "blocked_at": entry.blocked_at,
+            })),
+    );
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "message": format!("Client {} unblocked", ip),
+        "unblocked": entry,
+    }))))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminCreateUserRequest {
+    pub username: String,
+    pub email: String,
+    pub password: String,
+    pub role: Option<UserRole>,
+    /// Whether the address is taken as verified. Defaults to true; with
+    /// false the account goes through verification like a registration.
+    pub email_verified: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserListQuery {
+    /// Only users whose username contains this, ignoring case.
+    #[serde(default)]
+    pub username: String,
+    /// Fields to sort by, such as `-last_login,username`.
+    pub sort: Option<SortSpec>,
+    pub sort_by: Option<String>,
+    pub sort_order: Option<String>,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+}
+
+/// Lists accounts, optionally narrowed by username.
+pub async fn list_users(
+    State(state): State<AppState>,
+    Query(query): Query<UserListQuery>,
+) -> Result<impl IntoResponse> {
+    info!("GET /api/admin/users");
+
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("Authentication is not enabled".to_string()))?;
+
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let offset = query.offset.unwrap_or(0);
+    let sort = USER_SORT.from_query(query.sort, query.sort_by.as_deref(), query.sort_order.as_deref())?;
+    let (users, total) = auth_service.search_users(&query.username, sort.as_ref(), limit, offset).await?;
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "users": users,
+        "total": total,
+        "limit": limit,
+        "offset": offset,
+    }))))
+}
+
+/// Creates an account on someone's behalf, with any role.
+pub async fn create_user(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Json(request): Json<AdminCreateUserRequest>,
+) -> Result<impl IntoResponse> {
+    info!("POST /api/admin/users");
+
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("Authentication is not enabled".to_string()))?;
+
+    let create_request = CreateUserRequest {
+        username: request.username,
+        email: request.email,
+        password: request.password,
+        role: request.role,
+    };
+    let user = if request.email_verified.unwrap_or(true) {
+        auth_service.register_verified_user(create_request).await?
+    } else {
+        auth_service.register_user(create_request).await?
+    };
+
+    state.audit_log.record(
+        AuditEvent::new("users.created")
+            .with_actor(admin.username.clone())
+            .with_target(user.id.to_string())
+            .with_details(serde_json::json!({
+                "username": user.username,
+                "role": user.role,
+                "email_verified": user.email_verified,
+            })),
+    );
+
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(user))))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateUserAccessRequest {
+    pub role: Option<UserRole>,
+    pub is_active: Option<bool>,
+}
+
+/// Changes a user's role or active status. Every role transition is
+/// audited and announced to connected admins.
+pub async fn update_user_access(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Path(user_id): Path<i64>,
+    Json(request): Json<UpdateUserAccessRequest>,
+) -> Result<impl IntoResponse> {
+    info!("PATCH /api/admin/users/{}", user_id);
+
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("Authentication is not enabled".to_string()))?;
+
+    let (before, after) = auth_service
+        .change_user_access(user_id, request.role, request.is_active)
+        .await?;
+
+    if before.role != after.role {
+        state.audit_log.record(
+            AuditEvent::new("users.role_changed")
+                .with_actor(admin.username.clone())
+                .with_target(user_id.to_string())
+                .with_details(serde_json::json!({
+                    "username": after.username,
+                    "old_role": before.role,
+                    "new_role": after.role,
+                })),
+        );
+        if let Some(websocket) = &state.websocket_manager {
+            websocket
+                .send_to_admins(WebSocketMessage::RoleChanged {
+                    user_id: user_id as u64,
+                    username: after.username.clone(),
+                    old_role: before.role.to_string(),
+                    new_role: after.role.to_string(),
+                    changed_by: admin.username.clone(),
+                })
+                .await;
+        }
+    }
+    if before.is_active != after.is_active {
+        let action = if after.is_active { "users.activated" } else { "users.deactivated" };
+        state.audit_log.record(
+            AuditEvent::new(action)
+                .with_actor(admin.username.clone())
+                .with_target(user_id.to_string())
+                .with_details(serde_json::json!({ "username": after.username })),
+        );
+    }
+
+    Ok(Json(ApiResponse::success(after)))
+}
+
+pub async fn delete_user(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Path(user_id): Path<i64>,
+) -> Result<impl IntoResponse> {
+    info!("DELETE /api/admin/users/{}", user_id);
+
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("Authentication is not enabled".to_string()))?;
+
+    let user = auth_service.delete_user(user_id).await?;
+    state.audit_log.record(
+        AuditEvent::new("users.deleted")
+            .with_actor(admin.username.clone())
+            .with_target(user_id.to_string())
+            .with_details(serde_json::json!({ "username": user.username, "role": user.role })),
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub include_password_hashes: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+fn snapshot_service(state: &AppState) -> Result<&SnapshotService> {
+    state
+        .snapshots
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("Snapshots require a database".to_string()))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeedRequest {
+    /// Loaded instead of the built-in fixtures.
+    #[serde(default)]
+    pub fixtures: Option<Fixtures>,
+    #[serde(default)]
+    pub idempotent: bool,
+}
+
+/// Loads the built-in fixtures, or those in the body, refusing in
+/// production. Wiping is only offered by `server seed --wipe`.
+pub async fn seed(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    body: Bytes,
+) -> Result<impl IntoResponse> {
+    info!("POST /api/admin/seed by {}", admin.username);
+
+    let request: SeedRequest = if body.is_empty() {
+        SeedRequest::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|e| AppError::BadRequest(format!("Invalid seed request: {}", e)))?
+    };
+    let seeder = state
+        .seeder()
+        .ok_or_else(|| AppError::BadRequest("Seeding requires the database".to_string()))?;
+
+    let fixtures = request.fixtures.unwrap_or_else(Fixtures::builtin);
+    let options = SeedOptions {
+        idempotent: request.idempotent,
+        wipe: false,
+    };
+    let report = seeder.seed(&fixtures, options, &admin.username).await?;
+
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(report))))
+}
+
+/// Downloads the whole instance as a tar archive. Password hashes are left
+/// out unless `include_password_hashes=true`.
+pub async fn export_snapshot(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Query(query): Query<ExportQuery>,
+) -> Result<impl IntoResponse> {
+    info!("POST /api/admin/export");
+
+    let snapshots = snapshot_service(&state)?;
+    let (manifest, archive) = snapshots
+        .export(ExportOptions {
+            include_password_hashes: query.include_password_hashes,
+        })
+        .await?;
+
+    state.audit_log.record(
+        AuditEvent::new("admin.snapshot_exported")
+            .with_actor(admin.username.clone())
+            .with_details(serde_json::json!({
+                "schema_version": manifest.schema_version,
+                "includes_password_hashes": manifest.includes_password_hashes,
+                "counts": manifest.counts,
+                "bytes": archive.len(),
+            })),
+    );
+
+    let filename = format!("snapshot-{}.tar", manifest.created_at.format("%Y%m%dT%H%M%SZ"));
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        archive,
+    ))
+}
+
+/// Checks an archive against this instance and, unless `dry_run=true`,
+/// queues a `SnapshotImport` job for it. The body is the raw archive, up to
+/// `snapshots.max_archive_size_mb`. An archive with conflicts or errors is
+/// refused with 409 and the report.
+pub async fn import_snapshot(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Query(query): Query<ImportQuery>,
+    body: Body,
+) -> Result<axum::response::Response> {
+    info!("POST /api/admin/import (dry_run: {})", query.dry_run);
+
+    let snapshots = snapshot_service(&state)?;
+    let limit = snapshots.max_archive_bytes();
+    let archive = match axum::body::to_bytes(body, limit).await {
+        Ok(archive) => archive,
+        Err(_) => {
+            return Ok((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(serde_json::json!({
+                    "error": format!("Snapshot archive too large. Maximum size is {} bytes", limit),
+                    "status": 413,
+                })),
+            )
+                .into_response())
+        }
+    };
+
+    let report = snapshots.inspect(&archive).await?;
+    let audit = |outcome: &str, job_id: Option<uuid::Uuid>| {
+        state.audit_log.record(
+            AuditEvent::new("admin.snapshot_import")
+                .with_actor(admin.username.clone())
+                .with_details(serde_json::json!({
+                    "outcome": outcome,
+                    "job_id": job_id,
+                    "schema_version": report.manifest.as_ref().map(|m| m.schema_version),
+                    "counts": report.counts,
+                    "conflicts": report.conflicts.len(),
+                    "errors": report.errors.len(),
+                })),
+        )
+    };
+
+    if query.dry_run {
+        audit("dry_run", None);
+        return Ok(Json(ApiResponse::success(report)).into_response());
+    }
+
+    if !report.importable {
+        audit("rejected", None);
+        return Ok((
+            StatusCode::CONFLICT,
+            Json(ApiResponse {
+                success: false,
+                data: Some(report),
+                message: Some("Snapshot cannot be imported; see errors and conflicts".to_string()),
+            }),
+        )
+            .into_response());
+    }
+
+    let job_queue = state
+        .job_queue
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("Snapshot import requires the job queue".to_string()))?;
+
+    let archive_id = snapshots.stage(&archive).await?;
+    let submitted = job_queue
+        .submit_job(JobRequest {
+            job_type: JobType::SnapshotImport,
+            payload: serde_json::json!({ "archive_id": archive_id }),
+            priority: Some(JobPriority::High),
+            max_retries: Some(0),
+        })
+        .await;
+    let job_id = match submitted {
+        Ok(job_id) => job_id,
+        Err(e) => {
+            snapshots.discard(archive_id).await;
+            return Err(e);
+        }
+    };
+    audit("queued", Some(job_id));
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ApiResponse::success(serde_json::json!({
+            "job_id": job_id,
+            "report": report,
+        }))),
+    )
+        .into_response())
+}
+
+fn bundle_passphrase(headers: &HeaderMap) -> Result<&str> {
+    headers
+        .get(BUNDLE_PASSPHRASE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|passphrase| !passphrase.is_empty())
+        .ok_or_else(|| AppError::BadRequest(format!("The bundle passphrase goes in {}", BUNDLE_PASSPHRASE_HEADER)))
+}
+
+/// Every user account with its password hash, encrypted and signed under
+/// the passphrase in `X-Bundle-Passphrase`, for moving users to another
+/// instance with `POST /api/admin/users/import`.
+pub async fn export_users(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    info!("POST /api/admin/users/export");
+
+    let snapshots = snapshot_service(&state)?;
+    let (bundle_header, bundle) = snapshots.export_users(bundle_passphrase(&headers)?).await?;
+
+    state.audit_log.record(
+        AuditEvent::new("admin.users_exported")
+            .with_actor(admin.username.clone())
+            .with_details(serde_json::json!({
+                "users": bundle_header.users,
+                "bytes": bundle.len(),
+            })),
+    );
+
+    let filename = format!("users-{}.ndjson", bundle_header.created_at.format("%Y%m%dT%H%M%SZ"));
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        bundle,
+    ))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct UserImportQuery {
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+    #[serde(default)]
+    pub allow_admins: bool,
+}
+
+/// Adds the accounts in a bundle from [`export_users`], password hashes
+/// unchanged, after checking its signature. The body is the raw bundle, up
+/// to `snapshots.max_archive_size_mb`. Accounts whose username or email is
+/// taken are skipped with `on_conflict=skip`; otherwise they fail the
+/// import with 409. Admin accounts need `allow_admins=true`.
+pub async fn import_users(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Query(query): Query<UserImportQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<axum::response::Response> {
+    info!("POST /api/admin/users/import (on_conflict: {:?}, allow_admins: {})", query.on_conflict, query.allow_admins);
+
+    let snapshots = snapshot_service(&state)?;
+    let passphrase = bundle_passphrase(&headers)?;
+    let limit = snapshots.max_archive_bytes();
+    let bundle = match axum::body::to_bytes(body, limit).await {
+        Ok(bundle) => bundle,
+        Err(_) => {
+            return Ok((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(serde_json::json!({
+                    "error": format!("User bundle too large. Maximum size is {} bytes", limit),
+                    "status": 413,
+                })),
+            )
+                .into_response())
+        }
+    };
+
+    let options = UserImportOptions {
+        on_conflict: query.on_conflict,
+        allow_admins: query.allow_admins,
+    };
+    let imported = snapshots.import_users(&bundle, passphrase, &options).await;
+    state.audit_log.record(
+        AuditEvent::new("admin.users_imported")
+            .with_actor(admin.username.clone())
+            .with_details(match &imported {
+                Ok(report) => serde_json::json!({
+                    "outcome": "imported",
+                    "imported": report.imported,
+                    "skipped": report.skipped,
+                    "allow_admins": query.allow_admins,
+                }),
+                Err(e) => serde_json::json!({
+                    "outcome": "rejected",
+                    "error": e.to_string(),
+                    "allow_admins": query.allow_admins,
+                }),
+            }),
+    );
+
+    Ok(Json(ApiResponse::success(imported?)).into_response())
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct StartCaptureRequest {
+    pub duration_seconds: Option<u64>,
+    pub path_prefix: Option<String>,
+    pub user: Option<String>,
+}
+
+fn capture_recorder(state: &AppState) -> Result<&CaptureRecorder> {
+    state
+        .capture
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("Request capture is not enabled".to_string()))
+}
+
+/// Starts recording requests, optionally only those under `path_prefix` or
+/// by `user`, for `duration_seconds` or `capture.default_duration_seconds`.
+/// What an earlier capture recorded is discarded.
+pub async fn start_capture(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Json(request): Json<StartCaptureRequest>,
+) -> Result<impl IntoResponse> {
+    info!("POST /api/admin/captures");
+
+    let session = capture_recorder(&state)?.start(
+        &admin.username,
+        CaptureRequest {
+            duration_seconds: request.duration_seconds,
+            path_prefix: request.path_prefix,
+            user: request.user,
+        },
+    )?;
+
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(session))))
+}
+
+/// Stops the running capture, keeping what it recorded for download.
+pub async fn stop_capture(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+) -> Result<impl IntoResponse> {
+    info!("DELETE /api/admin/captures");
+
+    let recorder = capture_recorder(&state)?;
+    let session = recorder
+        .stop(&admin.username)
+        .ok_or_else(|| AppError::NotFound("No capture is running".to_string()))?;
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "stopped": session,
+        "recorded": recorder.len(),
+    }))))
+}
+
+/// The exchanges recorded by the latest capture, as a HAR log.
+pub async fn get_captures(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    info!("GET /api/admin/captures");
+
+    let har = capture_recorder(&state)?.to_har(&state.app_name, &state.version);
+    Ok((
+        [(header::CONTENT_DISPOSITION, "attachment; filename=\"capture.har\"")],
+        Json(har),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::models::UserRole;
+    use crate::test_support::{test_app, TestApp};
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{Request, StatusCode},
+        Router,
+    };
+    use serde_json::Value;
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    fn router(app: &TestApp) -> Router {
+        let mut config = crate::AppConfig::default();
+        config.rate_limit.enable = false;
+        crate::create_app_with_config(app.state.clone(), config)
+    }
+
+    async fn send(router: &Router, uri: &str, token: &str, body: Vec<u8>) -> (StatusCode, Vec<u8>) {
+        let mut request = Request::post(uri)
+            .header("user-agent", "admin-tests")
+            .header("authorization", format!("Bearer {}", token))
+            .header("content-type", "application/x-tar")
+            .body(Body::from(body))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, body.to_vec())
+    }
+
+    async fn get(router: &Router, uri: &str, token: &str) -> (StatusCode, Value) {
+        let mut request = Request::get(uri)
+            .header("user-agent", "admin-tests")
+            .header("authorization", format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, json(&body))
+    }
+
+    fn json(body: &[u8]) -> Value {
+        serde_json::from_slice(body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_endpoints_are_admin_only() {
+        let app = test_app().await;
+        let router = router(&app);
+        let user = app.login_as("operator", UserRole::User).await;
+
+        let (status, _) = send(&router, "/api/admin/export", &user, Vec::new()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(&router, "/api/admin/import?dry_run=true", &user, Vec::new()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_user_listing_sort() {
+        let app = test_app().await;
+        let router = router(&app);
+        let admin = app.login_as("root", UserRole::Admin).await;
+        app.login_as("alice", UserRole::User).await;
+        app.login_as("bob", UserRole::User).await;
+
+        let names = |body: &Value| -> Vec<String> {
+            body["data"]["users"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|user| user["username"].as_str().unwrap().to_string())
+                .collect()
+        };
+        let (status, body) = get(&router, "/api/admin/users?username=&sort=-username", &admin).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(names(&body), ["root", "bob", "alice"]);
+        let (_, body) = get(&router, "/api/admin/users?username=&sort_by=username&sort_order=asc", &admin).await;
+        assert_eq!(names(&body), ["alice", "bob", "root"]);
+
+        let (status, body) = get(&router, "/api/admin/users?username=&sort_by=username;DROP%20TABLE%20users", &admin).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.to_string().contains("sortable fields are: id, username"), "{}", body);
+        let (status, _) = get(&router, "/api/admin/users?username=&sort=password_hash", &admin).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users").fetch_one(&app.pool).await.unwrap();
+        assert_eq!(users, 3);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_is_imported_by_a_job() {
+        let source = test_app().await;
+        let admin = source.login_as("root", UserRole::Admin).await;
+        let (status, archive) = send(&router(&source), "/api/admin/export", &admin, Vec::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        let exported = source.state.audit_log.recent(Some("admin.snapshot_exported"), 10);
+        assert_eq!(exported.len(), 1);
+        assert_eq!(exported[0].actor.as_deref(), Some("root"));
+
+        let target = test_app().await;
+        let admin = target.login_as("root", UserRole::Admin).await;
+        let router = router(&target);
+
+        let (status, body) = send(&router, "/api/admin/import", &admin, archive.clone()).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(json(&body)["data"]["conflicts"].as_array().unwrap().len(), 2);
+
+        sqlx::query("DELETE FROM items").execute(&target.pool).await.unwrap();
+        let (status, body) = send(&router, "/api/admin/import?dry_run=true", &admin, archive.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json(&body)["data"]["importable"], true);
+
+        let (status, body) = send(&router, "/api/admin/import", &admin, archive).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let job_id = json(&body)["data"]["job_id"].as_str().unwrap().parse().unwrap();
+
+        let job_queue = target.state.job_queue.as_ref().unwrap();
+        let mut job = None;
+        for _ in 0..100 {
+            job = job_queue.get_job_status(job_id).await.unwrap();
+            if job.as_ref().is_some_and(|job| {
+                matches!(job.status, crate::jobs::JobStatus::Completed | crate::jobs::JobStatus::Failed)
+            }) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        let job = job.unwrap();
+        assert_eq!(job.status, crate::jobs::JobStatus::Completed, "{:?}", job.error_message);
+        assert_eq!(job.result.unwrap()["counts"]["items"], 2);
+
+        let items: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM items")
+            .fetch_one(&target.pool)
+            .await
+            .unwrap();
+        assert_eq!(items, 2);
+        let outcomes: Vec<Value> = target
+            .state
+            .audit_log
+            .recent(Some("admin.snapshot_import"), 10)
+            .into_iter()
+            .map(|event| event.details["outcome"].clone())
+            .collect();
+        assert_eq!(outcomes.len(), 3);
+    }
+}
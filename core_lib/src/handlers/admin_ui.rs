@@ -0,0 +1,279 @@
+//! Admin page for browsing and fixing data without curl
+//!
+//! `GET /admin` renders a page for admins with the items (and the trash),
+//! jobs, users and cache. Sections for subsystems this server runs without
+//! are left out. The page's script calls the JSON API on the strength of the
+//! session cookie set by `POST /admin/session`, with the CSRF token from
+//! `GET /admin/csrf-token` on every call that changes something; see
+//! [`csrf`](crate::middleware::csrf). Scripts and styles are served from
+//! `/admin/assets`, so the page's policy allows nothing inline.
+
+use axum::{
+    extract::{Path, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::auth::models::{LoginRequest, SessionClient, UserRole};
+use crate::config::AppConfig;
+use crate::error::{AppError, Result};
+use crate::middleware::auth::{require_admin, AuthUser};
+use crate::middleware::csrf;
+use crate::middleware::rate_limit::{rate_limit_middleware, RateLimiter};
+use crate::models::request::ApiResponse;
+use crate::AppState;
+
+pub const ADMIN_PAGE_PATH: &str = "/admin";
+pub const ADMIN_LOGIN_PATH: &str = "/admin/login";
+
+/// Policy of the admin pages: nothing inline, nothing from other origins.
+const PAGE_CSP: &str = "default-src 'self'; script-src 'self'; style-src 'self'; img-src 'self' data:; \
+    connect-src 'self'; frame-ancestors 'none'; base-uri 'none'; form-action 'self'";
+
+const ADMIN_JS: &str = include_str!("../../assets/admin/admin.js");
+const LOGIN_JS: &str = include_str!("../../assets/admin/login.js");
+const ADMIN_CSS: &str = include_str!("../../assets/admin/admin.css");
+
+/// How the admin cookies are set.
+#[derive(Debug, Clone, Copy)]
+struct CookiePolicy {
+    /// Only send the cookies over HTTPS, for servers with TLS.
+    secure: bool,
+}
+
+pub fn create_admin_ui_routes(config: &AppConfig) -> Router<AppState> {
+    let policy = CookiePolicy {
+        secure: config.server.tls.is_some(),
+    };
+    let login_limiter = RateLimiter::per_address(config.auth.login_requests_per_minute);
+
+    Router::new()
+        .route(ADMIN_PAGE_PATH, get(admin_page).route_layer(middleware::from_fn(require_admin_page)))
+        .route(ADMIN_LOGIN_PATH, get(login_page))
+        .route(
+            "/admin/session",
+            post(sign_in)
+                .route_layer(middleware::from_fn_with_state(login_limiter, rate_limit_middleware))
+                .delete(sign_out),
+        )
+        .route("/admin/csrf-token", get(csrf_token))
+        .route("/admin/assets/:name", get(asset))
+        .layer(Extension(policy))
+}
+
+/// [`require_admin`], sending visitors who haven't signed in to the sign-in
+/// page instead of answering 401.
+async fn require_admin_page(request: Request, next: Next) -> Response {
+    if request.extensions().get::<AuthUser>().is_none() {
+        return Redirect::to(ADMIN_LOGIN_PATH).into_response();
+    }
+    require_admin(request, next).await.unwrap_or_else(IntoResponse::into_response)
+}
+
+fn page(html: String) -> Response {
+    (
+        [
+            (header::CONTENT_SECURITY_POLICY, PAGE_CSP),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        Html(html),
+    )
+        .into_response()
+}
+
+async fn admin_page(State(state): State<AppState>, Extension(admin): Extension<AuthUser>) -> Response {
+    info!("GET /admin by {}", admin.username);
+
+    let mut sections = vec![ITEMS_SECTION.to_string(), TRASH_SECTION.to_string()];
+    sections.push(match state.job_queue {
+        Some(_) => JOBS_SECTION.to_string(),
+        None => disabled_section("jobs", "Jobs", "The job queue is disabled on this server."),
+    });
+    sections.push(USERS_SECTION.to_string());
+    sections.push(match state.cache_manager {
+        Some(_) => CACHE_SECTION.to_string(),
+        None => disabled_section("cache", "Cache", "The response cache is disabled on this server."),
+    });
+
+    page(format!(
+        include_str!("../../assets/admin/admin.html"),
+        app_name = escape_html(&state.app_name),
+        username = escape_html(&admin.username),
+        sections = sections.join("\n"),
+    ))
+}
+
+async fn login_page(State(state): State<AppState>) -> Response {
+    page(format!(
+        include_str!("../../assets/admin/login.html"),
+        app_name = escape_html(&state.app_name),
+    ))
+}
+
+fn disabled_section(id: &str, title: &str, reason: &str) -> String {
+    format!(
+        r#"<section id="{}" class="panel disabled"><h2>{}</h2><p class="muted">{}</p></section>"#,
+        id, title, reason
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminSignInRequest {
+    pub username: String,
+    pub password: String,
+}
+
+/// Signs an admin in, keeping the access token in the session cookie.
+/// Other users are refused, and the session their sign-in opened is closed.
+async fn sign_in(
+    State(state): State<AppState>,
+    Extension(policy): Extension<CookiePolicy>,
+    headers: axum::http::HeaderMap,
+    connect_info: Option<axum::extract::ConnectInfo<std::net::SocketAddr>>,
+    Json(request): Json<AdminSignInRequest>,
+) -> Result<Response> {
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("Authentication is not enabled".to_string()))?;
+
+    let forwarded_for = headers.get("x-forwarded-for").and_then(|h| h.to_str().ok());
+    let client = SessionClient {
+        user_agent: headers
+            .get(header::USER_AGENT)
+            .and_then(|h| h.to_str().ok())
+            .map(|s| s.to_string()),
+        ip_address: connect_info.map(|ci| state.anomaly_tracker.client_ip(ci.0.ip(), forwarded_for).to_string()),
+    };
+    let login = auth_service
+        .login_with_client(
+            LoginRequest {
+                username: request.username,
+                password: request.password,
+            },
+            &client,
+        )
+        .await?;
+
+    if login.user.role != UserRole::Admin {
+        if let Ok(claims) = auth_service.validate_token(&login.access_token).await {
+            if let Some(session_id) = claims.sid {
+                auth_service.revoke_session(login.user.id, &session_id).await?;
+            }
+        }
+        return Err(AppError::Authorization("Admin access required".to_string()));
+    }
+
+    info!("Admin page sign-in by {}", login.user.username);
+    let cookie = csrf::set_cookie(csrf::SESSION_COOKIE, &login.access_token, login.expires_in, true, policy.secure);
+    Ok((
+        [(header::SET_COOKIE, header_value(&cookie)?), (header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+        Json(ApiResponse::success(serde_json::json!({
+            "username": login.user.username,
+            "expires_in": login.expires_in,
+        }))),
+    )
+        .into_response())
+}
+
+/// Ends the cookie session, revoking it when the cookie still identified
+/// one.
+async fn sign_out(
+    State(state): State<AppState>,
+    Extension(policy): Extension<CookiePolicy>,
+    user: Option<Extension<AuthUser>>,
+) -> Result<Response> {
+    if let (Some(Extension(user)), Some(auth_service)) = (user, state.auth_service.as_ref()) {
+        if let Some(session_id) = &user.session_id {
+            auth_service.revoke_session(user.user_id, session_id).await?;
+        }
+    }
+
+    let cookie = csrf::set_cookie(csrf::SESSION_COOKIE, "", 0, true, policy.secure);
+    Ok(([(header::SET_COOKIE, header_value(&cookie)?)], StatusCode::NO_CONTENT).into_response())
+}
+
+/// Issues a CSRF token, in the body for the page's script and in a cookie
+/// for the server to compare it with.
+async fn csrf_token(Extension(policy): Extension<CookiePolicy>) -> Result<Response> {
+    let token = csrf::new_token();
+    let cookie = csrf::set_cookie(csrf::CSRF_COOKIE, &token, 24 * 60 * 60, false, policy.secure);
+    Ok((
+        [(header::SET_COOKIE, header_value(&cookie)?), (header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+        Json(ApiResponse::success(serde_json::json!({
+            "token": token,
+            "header": csrf::CSRF_HEADER,
+        }))),
+    )
+        .into_response())
+}
+
+async fn asset(Path(name): Path<String>) -> Result<Response> {
+    let (content_type, body) = match name.as_str() {
+        "admin.js" => ("text/javascript; charset=utf-8", ADMIN_JS),
+        "login.js" => ("text/javascript; charset=utf-8", LOGIN_JS),
+        "admin.css" => ("text/css; charset=utf-8", ADMIN_CSS),
+        _ => return Err(AppError::NotFound(format!("No admin asset named {}", name))),
+    };
+    Ok(([(header::CONTENT_TYPE, content_type), (header::CACHE_CONTROL, "no-cache")], body).into_response())
+}
+
+fn header_value(value: &str) -> Result<HeaderValue> {
+    HeaderValue::from_str(value).map_err(|_| AppError::InternalServerError)
+}
+
+const ITEMS_SECTION: &str = r#"<section id="items" class="panel">
+  <h2>Items</h2>
+  <table><thead><tr><th>ID</th><th>Name</th><th>Tags</th><th>Updated</th><th></th></tr></thead><tbody></tbody></table>
+  <div class="pager"><button type="button" data-page="prev">Previous</button><span class="page-label"></span><button type="button" data-page="next">Next</button></div>
+</section>"#;
+
+const TRASH_SECTION: &str = r#"<section id="trash" class="panel">
+  <h2>Trash</h2>
+  <table><thead><tr><th>ID</th><th>Name</th><th>Deleted</th><th></th></tr></thead><tbody></tbody></table>
+  <div class="pager"><button type="button" data-page="prev">Previous</button><span class="page-label"></span><button type="button" data-page="next">Next</button></div>
+</section>"#;
+
+const JOBS_SECTION: &str = r#"<section id="jobs" class="panel">
+  <h2>Jobs</h2>
+  <p class="stats"></p>
+  <div class="controls"><button type="button" data-action="cleanup-jobs">Clean up old jobs</button></div>
+  <table><thead><tr><th>ID</th><th>Type</th><th>Status</th><th>Created</th><th>Error</th><th></th></tr></thead><tbody></tbody></table>
+  <div class="pager"><button type="button" data-page="prev">Previous</button><span class="page-label"></span><button type="button" data-page="next">Next</button></div>
+</section>"#;
+
+const USERS_SECTION: &str = r#"<section id="users" class="panel">
+  <h2>Users</h2>
+  <p class="muted">Changing an account needs a sign-in within the last five minutes.</p>
+  <table><thead><tr><th>ID</th><th>Username</th><th>Role</th><th>Active</th><th></th></tr></thead><tbody></tbody></table>
+  <div class="pager"><button type="button" data-page="prev">Previous</button><span class="page-label"></span><button type="button" data-page="next">Next</button></div>
+</section>"#;
+
+const CACHE_SECTION: &str = r#"<section id="cache" class="panel">
+  <h2>Cache</h2>
+  <p class="stats"></p>
+  <div class="controls"><button type="button" data-action="clear-cache">Clear cache</button></div>
+</section>"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(escape_html(r#"<b>"Tom" & 'Jerry'</b>"#), "&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;");
+    }
+}
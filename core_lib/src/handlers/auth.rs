@@ -1,16 +1,24 @@
 use crate::auth::{
-    models::{CreateUserRequest, LoginRequest, LoginResponse, RefreshTokenResponse, UserResponse}
+    models::{
+        ChangePasswordRequest, ConfirmEmailRequest, CreateUserRequest, LoginRequest, LoginResponse, ReauthenticateRequest,
+        RefreshTokenResponse, ResendVerificationRequest, SessionClient, SessionResponse, UpdateProfileRequest, UserResponse, VerifyEmailRequest,
+    }
 };
+use crate::config::AuthConfig;
 use crate::error::AppError;
+use crate::list_preferences::ListPreferences;
+use crate::middleware::auth::{jwt_auth_middleware, require_self_or_admin, AuthUser};
+use crate::middleware::rate_limit::{rate_limit_middleware, RateLimiter};
 use crate::models::auth::{RegisterRequest, LoginRequest as ValidatedLoginRequest, RefreshTokenRequest as ValidatedRefreshTokenRequest};
 use crate::validation::{ContextValidatable, middleware::extract_validation_context};
 use crate::AppState;
 use axum::{
     extract::{Path, State},
     http::StatusCode,
+    middleware,
     response::Json,
-    routing::{get, post},
-    Router,
+    routing::{delete, get, post},
+    Extension, Router,
 };
 use serde::{Deserialize, Serialize};
 
@@ -37,12 +45,7 @@ pub async fn register_user(
     let context = extract_validation_context(&headers, &addr, None, None);
     
     let validation_result = request.validate_with_context(&context);
-    if !validation_result.is_valid {
-        return Err(AppError::Validation(format!(
-            "Registration validation failed: {}",
-            serde_json::to_string(&validation_result.errors).unwrap_or_default()
-        )));
-    }
+    validation_result.ensure_valid("Registration validation failed")?;
     
     let auth_service = state
         .auth_service
@@ -67,18 +70,13 @@ pub async fn login_user(
     Json(request): Json<ValidatedLoginRequest>,
 ) -> Result<Json<LoginResponse>, AppError> {
 
-    let addr = connect_info.map(|ci| ci.0).unwrap_or_else(|| {
+    let addr = connect_info.as_ref().map(|ci| ci.0).unwrap_or_else(|| {
         std::net::SocketAddr::from(([127, 0, 0, 1], 8080))
     });
     let context = extract_validation_context(&headers, &addr, None, None);
     
     let validation_result = request.validate_with_context(&context);
-    if !validation_result.is_valid {
-        return Err(AppError::Validation(format!(
-            "Login validation failed: {}",
-            serde_json::to_string(&validation_result.errors).unwrap_or_default()
-        )));
-    }
+    validation_result.ensure_valid("Login validation failed")?;
     
     let auth_service = state
         .auth_service
@@ -89,8 +87,18 @@ pub async fn login_user(
         username: request.username_or_email,
         password: request.password,
     };
+
+    let forwarded_for = headers.get("x-forwarded-for").and_then(|h| h.to_str().ok());
+    let client = SessionClient {
+        user_agent: headers
+            .get("user-agent")
+            .and_then(|h| h.to_str().ok())
+            .map(|s| s.to_string()),
+        ip_address: connect_info
+            .map(|ci| state.anomaly_tracker.client_ip(ci.0.ip(), forwarded_for).to_string()),
+    };
     
-    let login_response = auth_service.login(login_req).await?;
+    let login_response = auth_service.login_with_client(login_req, &client).await?;
     Ok(Json(login_response))
 }
 
@@ -107,12 +115,7 @@ pub async fn refresh_token(
     let context = extract_validation_context(&headers, &addr, None, None);
     
     let validation_result = request.validate_with_context(&context);
-    if !validation_result.is_valid {
-        return Err(AppError::Validation(format!(
-            "Refresh token validation failed: {}",
-            serde_json::to_string(&validation_result.errors).unwrap_or_default()
-        )));
-    }
+    validation_result.ensure_valid("Refresh token validation failed")?;
     
     let auth_service = state
         .auth_service
@@ -137,8 +140,6 @@ pub async fn get_current_user(
     use crate::middleware::auth::extract_token_from_header;
     let token = extract_token_from_header(&headers)?;
     
-    tracing::debug!("Validating token: {}", &token[..std::cmp::min(20, token.len())]);
-    
     let claims = auth_service.jwt_service().validate_access_token(&token)
         .map_err(|e| {
             tracing::debug!("Token validation failed: {:?}", e);
@@ -158,12 +159,212 @@ pub async fn get_current_user(
     Ok(Json(user))
 }
 
+pub async fn update_current_user(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Json(request): Json<UpdateProfileRequest>,
+) -> Result<Json<UserResponse>, AppError> {
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::InternalServerError)?;
+
+    if let Some(Some(file_id)) = request.avatar_file_id {
+        check_avatar(&state, auth_user.user_id, file_id).await?;
+    }
+
+    let user = auth_service.update_profile(auth_user.user_id, request).await?;
+    Ok(Json(user))
+}
+
+pub async fn get_list_preferences(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+) -> Result<Json<ListPreferences>, AppError> {
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::InternalServerError)?;
+
+    Ok(Json(auth_service.list_preferences(auth_user.user_id).await?))
+}
+
+/// Replaces the signed-in user's list defaults, refusing page sizes and
+/// orderings the list queries would refuse.
+pub async fn update_list_preferences(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Json(preferences): Json<ListPreferences>,
+) -> Result<Json<ListPreferences>, AppError> {
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::InternalServerError)?;
+
+    preferences.validate(&state.pagination_config)?;
+    let preferences = auth_service.set_list_preferences(auth_user.user_id, preferences).await?;
+    Ok(Json(preferences))
+}
+
+/// An avatar must be an image the user uploaded themselves. Files that
+/// exist but belong to someone else are refused the same way as missing
+/// ones.
+async fn check_avatar(state: &AppState, user_id: i64, file_id: uuid::Uuid) -> Result<(), AppError> {
+    let file_manager = state
+        .file_manager
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("File storage is not enabled".to_string()))?;
+
+    match file_manager.get_file_metadata(file_id).await? {
+        Some(file) if file.uploaded_by == user_id as u64 => {
+            if file.content_type.starts_with("image/") {
+                Ok(())
+            } else {
+                Err(AppError::BadRequest("Avatar must be an image file".to_string()))
+            }
+        }
+        _ => Err(AppError::BadRequest(format!("File {} not found", file_id))),
+    }
+}
+
+pub async fn confirm_email_change(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Json(request): Json<ConfirmEmailRequest>,
+) -> Result<Json<UserResponse>, AppError> {
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::InternalServerError)?;
+
+    let user = auth_service.confirm_email_change(auth_user.user_id, &request.token).await?;
+    Ok(Json(user))
+}
+
+pub async fn verify_email(
+    State(state): State<AppState>,
+    Json(request): Json<VerifyEmailRequest>,
+) -> Result<Json<MessageResponse>, AppError> {
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::InternalServerError)?;
+
+    auth_service.verify_email(&request.token).await?;
+    Ok(Json(MessageResponse {
+        message: "Email address verified. Refresh your access token to drop the restrictions on unverified accounts."
+            .to_string(),
+    }))
+}
+
+/// Always 202 for a well-formed request, whether or not the address has an
+/// unverified account.
+pub async fn resend_verification_email(
+    State(state): State<AppState>,
+    Json(request): Json<ResendVerificationRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), AppError> {
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::InternalServerError)?;
+
+    auth_service.resend_verification(&request.email).await?;
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(MessageResponse {
+            message: "If that address belongs to an unverified account, a new verification email is on its way".to_string(),
+        }),
+    ))
+}
+
 pub async fn logout_user() -> Result<Json<MessageResponse>, AppError> {
     Ok(Json(MessageResponse {
         message: "Successfully logged out".to_string(),
     }))
 }
 
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+) -> Result<Json<Vec<SessionResponse>>, AppError> {
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::InternalServerError)?;
+
+    let sessions = auth_service
+        .list_sessions(auth_user.user_id, auth_user.session_id.as_deref())
+        .await?;
+    Ok(Json(sessions))
+}
+
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(session_id): Path<String>,
+) -> Result<Json<MessageResponse>, AppError> {
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::InternalServerError)?;
+
+    auth_service.revoke_session(auth_user.user_id, &session_id).await?;
+    Ok(Json(MessageResponse {
+        message: "Session revoked".to_string(),
+    }))
+}
+
+pub async fn revoke_other_sessions(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+) -> Result<Json<MessageResponse>, AppError> {
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::InternalServerError)?;
+
+    let revoked = auth_service
+        .revoke_other_sessions(auth_user.user_id, auth_user.session_id.as_deref())
+        .await?;
+    Ok(Json(MessageResponse {
+        message: format!("Revoked {} other session(s)", revoked),
+    }))
+}
+
+pub async fn change_password(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Json(request): Json<ChangePasswordRequest>,
+) -> Result<Json<MessageResponse>, AppError> {
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::InternalServerError)?;
+
+    auth_service.change_password(auth_user.user_id, request).await?;
+    Ok(Json(MessageResponse {
+        message: "Password changed".to_string(),
+    }))
+}
+
+/// Swaps the caller's access token for one that counts as a fresh sign-in,
+/// given their password.
+pub async fn reauthenticate(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Json(request): Json<ReauthenticateRequest>,
+) -> Result<Json<RefreshTokenResponse>, AppError> {
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::InternalServerError)?;
+
+    let response = auth_service
+        .reauthenticate(auth_user.user_id, auth_user.session_id.as_deref(), &request.password)
+        .await?;
+    Ok(Json(response))
+}
+
 pub async fn get_user_by_id(
     State(state): State<AppState>,
     Path(user_id): Path<i64>,
@@ -187,18 +388,54 @@ pub fn create_auth_routes() -> Router<AppState> {
         .route("/login", post(login_user))
         .route("/refresh", post(refresh_token))
         .route("/logout", post(logout_user))
-        .route("/me", get(get_current_user))
+        .route("/verify-email", post(verify_email))
+        .route("/verify-email/resend", post(resend_verification_email))
+        .route("/me", get(get_current_user).patch(update_current_user))
+        .route("/me/email/confirm", post(confirm_email_change))
+        .route("/me/preferences", get(get_list_preferences).put(update_list_preferences))
+        .route("/sessions", get(list_sessions).delete(revoke_other_sessions))
+        .route("/sessions/:id", delete(revoke_session))
+        .route("/password", post(change_password))
+        .route("/reauthenticate", post(reauthenticate))
         .route("/users/:id", get(get_user_by_id))
 }
 
-pub fn create_auth_routes_with_middleware() -> Router<AppState> {
+/// Auth routes with the protected ones behind [`jwt_auth_middleware`]. The
+/// middleware validates tokens against `state`, so this must be the same
+/// state the router is served with. Sign-in and refresh are also limited
+/// per address as `config` says, apart from the global rate limit.
+pub fn create_auth_routes_with_middleware(state: AppState, config: &AuthConfig) -> Router<AppState> {
+    let protected_routes = Router::new()
+        .route("/me", get(get_current_user).patch(update_current_user))
+        .route("/me/email/confirm", post(confirm_email_change))
+        .route("/me/preferences", get(get_list_preferences).put(update_list_preferences))
+        .route("/sessions", get(list_sessions).delete(revoke_other_sessions))
+        .route("/sessions/:id", delete(revoke_session))
+        .route("/password", post(change_password))
+        .route("/reauthenticate", post(reauthenticate))
+        .route(
+            "/users/:id",
+            get(get_user_by_id).route_layer(middleware::from_fn(require_self_or_admin)),
+        )
+        .route_layer(middleware::from_fn_with_state(state, jwt_auth_middleware));
+
+    let login_limiter = RateLimiter::per_address(config.login_requests_per_minute);
+    let refresh_limiter = RateLimiter::per_address(config.refresh_requests_per_minute);
+
     Router::new()
         .route("/register", post(register_user))
-        .route("/login", post(login_user))
-        .route("/refresh", post(refresh_token))
+        .route(
+            "/login",
+            post(login_user).route_layer(middleware::from_fn_with_state(login_limiter, rate_limit_middleware)),
+        )
+        .route(
+            "/refresh",
+            post(refresh_token).route_layer(middleware::from_fn_with_state(refresh_limiter, rate_limit_middleware)),
+        )
         .route("/logout", post(logout_user))
-        .route("/me", get(get_current_user))
-        .route("/users/:id", get(get_user_by_id))
+        .route("/verify-email", post(verify_email))
+        .route("/verify-email/resend", post(resend_verification_email))
+        .merge(protected_routes)
 }
 
 #[cfg(test)]
@@ -346,4 +583,66 @@ mod tests {
         let response = app.oneshot(request).await.unwrap();
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_session_endpoints() {
+        let app_state = setup_test_app_state().await;
+        let auth_service = app_state.auth_service.clone().unwrap();
+        let app = create_auth_routes_with_middleware(app_state.clone(), &AuthConfig::default()).with_state(app_state);
+
+        auth_service
+            .register_user(CreateUserRequest {
+                username: "testuser".to_string(),
+                email: "test@example.com".to_string(),
+                password: "StrongPass123!".to_string(),
+                role: None,
+            })
+            .await
+            .unwrap();
+        let login = || LoginRequest {
+            username: "testuser".to_string(),
+            password: "StrongPass123!".to_string(),
+        };
+        let current = auth_service.login(login()).await.unwrap();
+        let other = auth_service.login(login()).await.unwrap();
+
+        let authorized = |method: Method, uri: &str, token: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(authorized(Method::GET, "/sessions", &current.access_token))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let sessions: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(sessions.as_array().unwrap().len(), 2);
+
+        let response = app
+            .clone()
+            .oneshot(authorized(Method::DELETE, "/sessions", &current.access_token))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .clone()
+            .oneshot(authorized(Method::GET, "/sessions", &other.access_token))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .oneshot(authorized(Method::DELETE, "/sessions/unknown", &current.access_token))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
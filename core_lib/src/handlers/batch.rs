@@ -0,0 +1,549 @@
+//! `POST /api/batch`: several item, file and job requests in one round trip
+//!
+//! Every sub-request is dispatched through the finished application router,
+//! so it is authenticated, validated, namespaced and rate limited exactly as
+//! if it had been sent on its own. Sub-requests inherit the caller's
+//! credentials and address, may set only a few headers of their own, and are
+//! answered in the order they were given.
+//!
+//! With `?atomic=true` the sub-requests are sent one after another inside a
+//! single [transaction](crate::transaction). The first one to fail ends the
+//! batch: everything the earlier ones wrote is undone, and the batch answers
+//! with that sub-request's status and the responses up to it.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::post,
+    Extension, Json, Router,
+};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tower::ServiceExt;
+
+use crate::config::BatchConfig;
+use crate::error::{AppError, Result};
+use crate::middleware::timeout::timeout_response;
+use crate::AppState;
+
+pub const BATCH_PATH: &str = "/api/batch";
+
+const ITEM_PATHS: [&str; 3] = ["/api/items", "/api/v1/items", "/api/v2/items"];
+const READ_ONLY_PATHS: [&str; 2] = ["/api/files", "/api/jobs"];
+
+/// Headers a sub-request may set itself.
+const SUB_REQUEST_HEADERS: [HeaderName; 6] = [
+    header::ACCEPT,
+    header::CONTENT_TYPE,
+    header::IF_MATCH,
+    header::IF_NONE_MATCH,
+    HeaderName::from_static("prefer"),
+    HeaderName::from_static("x-namespace"),
+];
+
+/// Headers every sub-request takes from the batch request, so it runs as
+/// the same caller.
+const INHERITED_HEADERS: [HeaderName; 5] = [
+    header::AUTHORIZATION,
+    header::USER_AGENT,
+    HeaderName::from_static("x-api-key"),
+    HeaderName::from_static("x-forwarded-for"),
+    HeaderName::from_static("x-real-ip"),
+];
+
+/// Largest sub-response body copied into the batch response.
+const MAX_SUB_RESPONSE_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    pub method: String,
+    pub path: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub body: Option<Value>,
+}
+
+/// One sub-request's answer. JSON bodies are embedded as JSON and other text
+/// as a string; binary bodies are left out.
+#[derive(Debug, Serialize)]
+pub struct BatchResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub body: Value,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct BatchOptions {
+    #[serde(default)]
+    pub atomic: bool,
+}
+
+/// Marks requests issued by a batch, so a batch cannot start another.
+#[derive(Debug, Clone, Copy)]
+struct BatchSubRequest;
+
+/// The batch settings plus the router sub-requests are sent through. The
+/// router is only complete once every layer is on, so it is bound after
+/// [`create_app_with_config`](crate::create_app_with_config) has built it.
+#[derive(Clone)]
+pub struct BatchDispatcher {
+    config: BatchConfig,
+    router: Arc<OnceLock<Router>>,
+}
+
+impl BatchDispatcher {
+    pub fn new(config: BatchConfig) -> Self {
+        Self {
+            config,
+            router: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// Sets the router sub-requests go through. Later calls are ignored.
+    pub fn bind(&self, router: Router) {
+        let _ = self.router.set(router);
+    }
+}
+
+pub fn create_batch_routes(dispatcher: BatchDispatcher) -> Router<AppState> {
+    Router::new()
+        .route(BATCH_PATH, post(handle_batch))
+        .layer(Extension(dispatcher))
+}
+
+async fn handle_batch(
+    State(state): State<AppState>,
+    Extension(dispatcher): Extension<BatchDispatcher>,
+    nested: Option<Extension<BatchSubRequest>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    Query(options): Query<BatchOptions>,
+    headers: HeaderMap,
+    Json(requests): Json<Vec<BatchRequest>>,
+) -> Result<Response> {
+    if nested.is_some() {
+        return Err(AppError::BadRequest("Batch requests cannot be nested".to_string()));
+    }
+
+    let config = &dispatcher.config;
+    if requests.is_empty() {
+        return Err(AppError::BadRequest("A batch needs at least one request".to_string()));
+    }
+    if requests.len() > config.max_requests {
+        return Err(AppError::BadRequest(format!(
+            "A batch may contain at most {} requests",
+            config.max_requests
+        )));
+    }
+
+    let router = dispatcher
+        .router
+        .get()
+        .cloned()
+        .ok_or_else(|| AppError::Configuration("Batch router has not been bound".to_string()))?;
+
+    // Every sub-request is checked before any is sent, so a rejected batch
+    // has no side effects.
+    let sub_requests = requests
+        .into_iter()
+        .enumerate()
+        .map(|(index, request)| {
+            sub_request(request, config.allow_mutations, &headers, connect_info.as_ref())
+                .map_err(|reason| AppError::BadRequest(format!("Request {}: {}", index, reason)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let budget = Duration::from_secs(config.timeout_seconds);
+    if options.atomic {
+        return match tokio::time::timeout(budget, dispatch_atomic(&state, router, sub_requests)).await {
+            Ok(response) => response,
+            Err(_) => {
+                tracing::warn!("Atomic batch did not complete within {:?}; nothing was kept", budget);
+                Ok(timeout_response(BATCH_PATH, budget))
+            }
+        };
+    }
+
+    let responses = stream::iter(sub_requests)
+        .map(|request| dispatch(router.clone(), request))
+        .buffered(config.max_parallelism)
+        .collect::<Vec<_>>();
+
+    match tokio::time::timeout(budget, responses).await {
+        Ok(responses) => Ok(Json(responses).into_response()),
+        Err(_) => {
+            tracing::warn!("Batch did not complete within {:?}", budget);
+            Ok(timeout_response(BATCH_PATH, budget))
+        }
+    }
+}
+
+/// Sends `sub_requests` in order inside one transaction, stopping at the
+/// first that fails and undoing the writes of those before it.
+async fn dispatch_atomic(state: &AppState, router: Router, sub_requests: Vec<Request<Body>>) -> Result<Response> {
+    let mut responses = Vec::with_capacity(sub_requests.len());
+    let outcome = state
+        .item_service
+        .transaction(async {
+            for request in sub_requests {
+                let response = dispatch(router.clone(), request).await;
+                let failed = response.status >= 400;
+                responses.push(response);
+                if failed {
+                    return Err(AppError::BadRequest(format!("Request {} failed", responses.len() - 1)));
+                }
+            }
+            Ok(())
+        })
+        .await;
+
+    match (outcome, responses.last()) {
+        (Ok(()), _) => Ok(Json(responses).into_response()),
+        (Err(_), Some(last)) if last.status >= 400 => {
+            let status = StatusCode::from_u16(last.status).unwrap_or(StatusCode::BAD_REQUEST);
+            Ok((status, Json(responses)).into_response())
+        }
+        (Err(e), _) => Err(e),
+    }
+}
+
+/// Builds the request for one batch entry, or says why it is refused.
+fn sub_request(
+    request: BatchRequest,
+    allow_mutations: bool,
+    batch_headers: &HeaderMap,
+    connect_info: Option<&ConnectInfo<SocketAddr>>,
+) -> std::result::Result<Request<Body>, String> {
+    let method = Method::from_bytes(request.method.to_ascii_uppercase().as_bytes())
+        .map_err(|_| format!("invalid method '{}'", request.method))?;
+    let uri: Uri = request
+        .path
+        .parse()
+        .ok()
+        .filter(|uri: &Uri| uri.scheme().is_none() && uri.path().starts_with('/'))
+        .ok_or_else(|| format!("invalid path '{}'", request.path))?;
+
+    if uri.path() == BATCH_PATH {
+        return Err("batch requests cannot be nested".to_string());
+    }
+    if !is_allowed(&method, uri.path(), allow_mutations) {
+        return Err(format!("{} {} is not allowed in a batch", method, uri.path()));
+    }
+
+    let mut builder = Request::builder().method(method).uri(uri);
+    for name in &INHERITED_HEADERS {
+        for value in batch_headers.get_all(name) {
+            builder = builder.header(name, value);
+        }
+    }
+    for (name, value) in &request.headers {
+        let name = HeaderName::try_from(name.as_str())
+            .ok()
+            .filter(|name| SUB_REQUEST_HEADERS.contains(name))
+            .ok_or_else(|| format!("header '{}' cannot be set in a batch", name))?;
+        let value = HeaderValue::try_from(value.as_str())
+            .map_err(|_| format!("invalid value for header '{}'", name))?;
+        builder = builder.header(name, value);
+    }
+
+    let body = match request.body {
+        Some(body) => {
+            let bytes = serde_json::to_vec(&body).map_err(|e| e.to_string())?;
+            if !request.headers.keys().any(|name| name.eq_ignore_ascii_case("content-type")) {
+                builder = builder.header(header::CONTENT_TYPE, "application/json");
+            }
+            builder = builder.header(header::CONTENT_LENGTH, bytes.len());
+            Body::from(bytes)
+        }
+        None => Body::empty(),
+    };
+
+    let mut sub_request = builder.body(body).map_err(|e| e.to_string())?;
+    sub_request.extensions_mut().insert(BatchSubRequest);
+    if let Some(connect_info) = connect_info {
+        sub_request.extensions_mut().insert(*connect_info);
+    }
+    Ok(sub_request)
+}
+
+/// Item, file and job reads, plus item writes when `allow_mutations` is set.
+fn is_allowed(method: &Method, path: &str, allow_mutations: bool) -> bool {
+    let under = |prefixes: &[&str]| {
+        prefixes.iter().any(|prefix| {
+            path.strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    };
+
+    match *method {
+        Method::GET => under(&ITEM_PATHS) || under(&READ_ONLY_PATHS),
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE => allow_mutations && under(&ITEM_PATHS),
+        _ => false,
+    }
+}
+
+async fn dispatch(router: Router, request: Request<Body>) -> BatchResponse {
+    let response = match router.oneshot(request).await {
+        Ok(response) => response,
+        Err(infallible) => match infallible {},
+    };
+
+    let status = response.status().as_u16();
+    let headers = response
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+
+    match axum::body::to_bytes(response.into_body(), MAX_SUB_RESPONSE_BYTES).await {
+        Ok(bytes) => BatchResponse {
+            status,
+            headers,
+            body: body_value(bytes),
+        },
+        Err(e) => {
+            tracing::warn!("Dropping batch sub-response body: {}", e);
+            BatchResponse {
+                status: 502,
+                headers: BTreeMap::new(),
+                body: serde_json::json!({
+                    "error": "Response body could not be included in the batch",
+                    "status": 502,
+                }),
+            }
+        }
+    }
+}
+
+fn body_value(bytes: Bytes) -> Value {
+    if bytes.is_empty() {
+        return Value::Null;
+    }
+    serde_json::from_slice(&bytes)
+        .ok()
+        .or_else(|| String::from_utf8(bytes.to_vec()).ok().map(Value::String))
+        .unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::models::UserRole;
+    use crate::test_support::{test_app, TestApp};
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{Request, StatusCode},
+        Router,
+    };
+    use serde_json::{json, Value};
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    fn router(app: &TestApp, configure: impl FnOnce(&mut crate::AppConfig)) -> Router {
+        let mut config = crate::AppConfig::default();
+        config.rate_limit.enable = false;
+        configure(&mut config);
+        crate::create_app_with_config(app.state.clone(), config)
+    }
+
+    async fn batch(router: &Router, token: Option<&str>, requests: Value) -> (StatusCode, Value) {
+        send(router, "/api/batch", token, requests).await
+    }
+
+    async fn batch_at(router: &Router, uri: &str, requests: Value) -> (StatusCode, Value) {
+        send(router, uri, None, requests).await
+    }
+
+    async fn send(router: &Router, uri: &str, token: Option<&str>, requests: Value) -> (StatusCode, Value) {
+        let mut builder = Request::post(uri)
+            .header("user-agent", "batch-tests")
+            .header("content-type", "application/json");
+        if let Some(token) = token {
+            builder = builder.header("authorization", format!("Bearer {}", token));
+        }
+        let mut request = builder.body(Body::from(requests.to_string())).unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn statuses(responses: &Value) -> Vec<u64> {
+        responses
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|response| response["status"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_sub_requests_are_answered_in_order() {
+        let app = test_app().await;
+        let router = router(&app, |_| {});
+
+        let (status, responses) = batch(
+            &router,
+            None,
+            json!([
+                { "method": "GET", "path": "/api/items/1" },
+                { "method": "get", "path": "/api/items/999999" },
+                { "method": "GET", "path": "/api/v1/items?limit=1" },
+                { "method": "GET", "path": "/api/jobs", "headers": { "accept": "application/json" } },
+            ]),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(statuses(&responses), vec![200, 404, 200, 200]);
+        assert_eq!(responses[0]["body"]["data"]["name"], "Sample Item 1");
+        assert!(responses[0]["headers"]["content-type"]
+            .as_str()
+            .unwrap()
+            .starts_with("application/json"));
+    }
+
+    #[tokio::test]
+    async fn test_disallowed_and_nested_requests_reject_the_batch() {
+        let app = test_app().await;
+        let router = router(&app, |_| {});
+
+        let cases = [
+            json!([{ "method": "GET", "path": "/api/batch" }]),
+            json!([{ "method": "POST", "path": "/api/batch", "body": [] }]),
+            json!([{ "method": "GET", "path": "/api/admin/users" }]),
+            json!([{ "method": "GET", "path": "/api/itemsx" }]),
+            json!([{ "method": "GET", "path": "http://example.com/api/items" }]),
+            json!([{ "method": "POST", "path": "/api/items", "body": { "name": "Not allowed" } }]),
+            json!([{ "method": "GET", "path": "/api/items", "headers": { "authorization": "Bearer x" } }]),
+            json!([]),
+        ];
+        for requests in cases {
+            let (status, body) = batch(&router, None, requests.clone()).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{} -> {}", requests, body);
+        }
+
+        let (_, body) = batch(&router, None, json!([{ "method": "GET", "path": "/api/batch" }])).await;
+        assert!(body["error"].as_str().unwrap().contains("nested"));
+
+        let items = app.state.item_service.get_items(None, None).await.unwrap();
+        assert!(items.iter().all(|item| item.name != "Not allowed"));
+    }
+
+    #[tokio::test]
+    async fn test_batch_size_is_capped() {
+        let app = test_app().await;
+        let router = router(&app, |config| config.batch.max_requests = 2);
+
+        let request = json!({ "method": "GET", "path": "/api/items/1" });
+        let (status, _) = batch(&router, None, json!([request, request])).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = batch(&router, None, json!([request, request, request])).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains("at most 2"));
+    }
+
+    #[tokio::test]
+    async fn test_mutations_run_as_the_caller_when_enabled() {
+        let app = test_app().await;
+        let router = router(&app, |config| config.batch.allow_mutations = true);
+
+        let token = app.login_as("batcher", UserRole::User).await;
+
+        let (status, responses) = batch(
+            &router,
+            Some(&token),
+            json!([
+                { "method": "POST", "path": "/api/items", "body": { "name": "Batched", "tags": ["batch"] } },
+                { "method": "POST", "path": "/api/items", "body": { "name": "" } },
+                { "method": "DELETE", "path": "/api/items/999999" },
+            ]),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(statuses(&responses), vec![201, 400, 404]);
+        let created = app
+            .state
+            .item_service
+            .get_item(responses[0]["body"]["data"]["id"].as_u64().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(created.name, "Batched");
+    }
+
+    #[tokio::test]
+    async fn test_atomic_batch_keeps_nothing_when_a_request_fails() {
+        let app = test_app().await;
+        let router = router(&app, |config| config.batch.allow_mutations = true);
+        let before = app.state.item_service.get_items(None, None).await.unwrap();
+        let first = app.state.item_service.get_item(1).await.unwrap();
+
+        let (status, responses) = batch_at(
+            &router,
+            "/api/batch?atomic=true",
+            json!([
+                { "method": "POST", "path": "/api/items", "body": { "name": "First of three" } },
+                { "method": "PATCH", "path": "/api/items/1", "body": { "description": "Changed" } },
+                { "method": "DELETE", "path": "/api/items/999999" },
+                { "method": "POST", "path": "/api/items", "body": { "name": "Never sent" } },
+            ]),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(statuses(&responses), vec![201, 200, 404]);
+        let after = app.state.item_service.get_items(None, None).await.unwrap();
+        assert_eq!(after.len(), before.len());
+        let unchanged = app.state.item_service.get_item(1).await.unwrap();
+        assert_eq!((unchanged.description, unchanged.version), (first.description, first.version));
+
+        let (status, responses) = batch_at(
+            &router,
+            "/api/batch?atomic=true",
+            json!([
+                { "method": "POST", "path": "/api/items", "body": { "name": "Kept" } },
+                { "method": "PATCH", "path": "/api/items/1", "body": { "description": "Changed" } },
+            ]),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(statuses(&responses), vec![201, 200]);
+        let kept = responses[0]["body"]["data"]["id"].as_u64().unwrap();
+        assert_eq!(app.state.item_service.get_item(kept).await.unwrap().name, "Kept");
+        assert_eq!(app.state.item_service.get_item(1).await.unwrap().description.as_deref(), Some("Changed"));
+    }
+
+    #[tokio::test]
+    async fn test_each_sub_request_counts_against_the_rate_limit() {
+        let app = test_app().await;
+        let mut limits = app.state.rate_limiter.config();
+        limits.enable = true;
+        limits.requests_per_minute = 3;
+        limits.exempt_cidrs = Vec::new();
+        app.state.rate_limiter.update_config(limits);
+        let router = router(&app, |config| config.rate_limit.enable = true);
+
+        let request = json!({ "method": "GET", "path": "/api/items/1" });
+        let (status, responses) = batch(&router, None, json!([request, request])).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(statuses(&responses), vec![200, 200]);
+
+        let (status, responses) = batch(&router, None, json!([request, request])).await;
+        assert_eq!(status, StatusCode::OK);
+        let mut statuses = statuses(&responses);
+        statuses.sort();
+        assert_eq!(statuses, vec![200, 429]);
+    }
+}
@@ -1,11 +1,25 @@
 use axum::{
-    extract::State,
+    extract::{Query, State},
     response::Json,
+    Extension,
 };
+use serde::Deserialize;
 use serde_json::{json, Value};
 use tracing::debug;
 
-use crate::{AppState, Result, AppError};
+use crate::{
+    audit::AuditEvent,
+    middleware::auth::AuthUser,
+    validation::ValidationError,
+    AppState, Result, AppError,
+};
+
+/// Longest pattern accepted by `POST /api/cache/invalidate`.
+const MAX_PATTERN_LENGTH: usize = 256;
+
+/// Characters that would make a pattern look like a glob or regular
+/// expression, which it is not: entries are matched by plain substring.
+const PATTERN_METACHARACTERS: &[char] = &['*', '(', ')', '[', ']', '{', '}', '|', '\\', '^', '$'];
 
 pub async fn get_cache_stats(
     State(state): State<AppState>,
@@ -39,12 +53,24 @@ pub async fn get_cache_stats(
             "enabled": true,
             "type": "LRU",
             "ttl_seconds": 3600
-        }
+        },
+        "last_clear": cache_manager.last_clear()
     })))
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct ClearCacheQuery {
+    /// Clears even within `cache.clear_cooldown_seconds` of the last clear.
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Clears the whole cache, at most once per `cache.clear_cooldown_seconds`
+/// unless `force=true`. Every call is audited, refused ones included.
 pub async fn clear_cache(
     State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<ClearCacheQuery>,
 ) -> Result<Json<Value>> {
     debug!("Clearing cache");
 
@@ -55,17 +81,61 @@ pub async fn clear_cache(
         }
     };
 
-    let old_size = cache_manager.len();
-    cache_manager.clear();
+    let cleared = cache_manager.clear_by(&user.username, query.force);
+    state.audit_log.record(
+        AuditEvent::new("cache.clear")
+            .with_actor(user.username.clone())
+            .with_details(match &cleared {
+                Ok(entries_removed) => json!({
+                    "outcome": "cleared",
+                    "force": query.force,
+                    "entries_removed": entries_removed,
+                }),
+                Err(remaining) => json!({
+                    "outcome": "cooldown",
+                    "retry_after_seconds": remaining.as_secs_f64().ceil() as u64,
+                }),
+            }),
+    );
+
+    let entries_removed = cleared.map_err(|remaining| {
+        AppError::RateLimit(format!(
+            "The cache was cleared recently; try again in {} seconds or pass force=true",
+            remaining.as_secs_f64().ceil() as u64
+        ))
+    })?;
     
     Ok(Json(json!({
         "message": "Cache cleared successfully",
-        "entries_removed": old_size
+        "entries_removed": entries_removed
     })))
 }
 
+/// Refuses patterns that are empty, overlong or written as a glob or
+/// regular expression.
+fn validate_pattern(pattern: &str) -> std::result::Result<(), ValidationError> {
+    let invalid = |message: &str| Err(ValidationError::field("pattern", "invalid", message));
+
+    if pattern.trim().is_empty() {
+        return invalid("Pattern cannot be empty; use /api/cache/clear to remove every entry");
+    }
+    if pattern.chars().count() > MAX_PATTERN_LENGTH {
+        return invalid(&format!("Pattern cannot be longer than {} characters", MAX_PATTERN_LENGTH));
+    }
+    if pattern.chars().any(char::is_control) {
+        return invalid("Pattern cannot contain control characters");
+    }
+    if pattern.contains(PATTERN_METACHARACTERS) {
+        return invalid("Patterns are matched as plain substrings of cache keys; wildcards and regular expressions are not supported");
+    }
+    Ok(())
+}
+
+/// Removes every entry whose key contains `pattern`. Every call is audited
+/// with the pattern given, rejected ones included.
 pub async fn invalidate_cache_pattern(
     State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
     Json(payload): Json<Value>,
 ) -> Result<Json<Value>> {
     debug!("Invalidating cache pattern");
@@ -82,10 +152,22 @@ pub async fn invalidate_cache_pattern(
         .and_then(|p| p.as_str())
         .ok_or_else(|| AppError::BadRequest("Missing 'pattern' field".to_string()))?;
 
+    let audit = AuditEvent::new("cache.invalidate")
+        .with_actor(user.username.clone())
+        .with_target(pattern);
+    if let Err(error) = validate_pattern(pattern) {
+        state.audit_log.record(audit.with_details(json!({ "outcome": "rejected" })));
+        return Err(error.into());
+    }
+
     let old_size = cache_manager.len();
     cache_manager.invalidate_pattern(pattern);
     let new_size = cache_manager.len();
     let removed_count = old_size.saturating_sub(new_size);
+    state.audit_log.record(audit.with_details(json!({
+        "outcome": "invalidated",
+        "entries_removed": removed_count,
+    })));
     
     Ok(Json(json!({
         "message": "Cache pattern invalidated successfully",
@@ -136,7 +218,11 @@ pub async fn get_cache_health(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{cache::CacheManager, config::CacheConfig};
+    use crate::{auth::models::UserRole, cache::CacheManager, config::CacheConfig};
+
+    fn admin() -> AuthUser {
+        AuthUser::new(1, "admin".to_string(), UserRole::Admin)
+    }
 
     fn create_test_state_with_cache() -> AppState {
         let cache_config = CacheConfig::default();
@@ -180,13 +266,86 @@ mod tests {
             cache.set("test_key", &"test_value").unwrap();
         }
         
-        let result = clear_cache(State(state)).await;
+        let result = clear_cache(State(state.clone()), Extension(admin()), Query(ClearCacheQuery::default())).await;
         assert!(result.is_ok());
         
         let response = result.unwrap();
         let json_value = response.0;
         
         assert_eq!(json_value.get("message").unwrap().as_str().unwrap(), "Cache cleared successfully");
+
+        let stats = get_cache_stats(State(state)).await.unwrap().0;
+        assert_eq!(stats["last_clear"]["actor"], "admin");
+        assert!(stats["last_clear"]["at"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_clear_cache_cooldown() {
+        let state = create_test_state_with_cache();
+        let clear = |force| clear_cache(State(state.clone()), Extension(admin()), Query(ClearCacheQuery { force }));
+
+        assert!(clear(false).await.is_ok());
+        assert!(matches!(clear(false).await, Err(AppError::RateLimit(_))));
+        assert!(clear(true).await.is_ok());
+
+        let outcomes: Vec<Value> = state
+            .audit_log
+            .recent(Some("cache.clear"), 10)
+            .into_iter()
+            .map(|event| event.details["outcome"].clone())
+            .collect();
+        assert_eq!(outcomes.len(), 3);
+        assert!(outcomes.contains(&json!("cooldown")));
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_cache_pattern() {
+        let state = create_test_state_with_cache();
+        let cache = state.cache_manager.clone().unwrap();
+        cache.set("items:1", &"one").unwrap();
+        cache.set("users:1", &"root").unwrap();
+        let invalidate = |pattern: &str| {
+            invalidate_cache_pattern(State(state.clone()), Extension(admin()), Json(json!({ "pattern": pattern })))
+        };
+
+        let response = invalidate("items:").await.unwrap().0;
+        assert_eq!(response["entries_removed"], 1);
+        assert_eq!(cache.len(), 1);
+
+        for pattern in ["", "  ", "(a+)+$", "items:*", "a\nb\u{0}", &"x".repeat(MAX_PATTERN_LENGTH + 1)] {
+            assert!(matches!(invalidate(pattern).await, Err(AppError::Validation(_))), "{:?}", pattern);
+        }
+        assert_eq!(cache.len(), 1);
+
+        let events = state.audit_log.recent(Some("cache.invalidate"), 10);
+        assert_eq!(events.len(), 7);
+        assert!(events.iter().any(|event| event.target.as_deref() == Some("items:*")));
+    }
+
+    #[tokio::test]
+    async fn test_cache_mutations_are_admin_only() {
+        use axum::{body::Body, http::{Request, StatusCode}};
+        use tower::ServiceExt;
+
+        let state = create_test_state_with_cache();
+        let router = crate::handlers::routes::create_routes().with_state(state);
+        let send = |uri: &str, user: Option<AuthUser>| {
+            let mut request = Request::post(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"pattern": "items:"}"#))
+                .unwrap();
+            if let Some(user) = user {
+                request.extensions_mut().insert(user);
+            }
+            router.clone().oneshot(request)
+        };
+
+        for uri in ["/api/cache/clear", "/api/cache/invalidate"] {
+            assert_eq!(send(uri, None).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+            let user = AuthUser::new(2, "user".to_string(), UserRole::User);
+            assert_eq!(send(uri, Some(user)).await.unwrap().status(), StatusCode::FORBIDDEN);
+            assert_eq!(send(uri, Some(admin())).await.unwrap().status(), StatusCode::OK);
+        }
     }
 
     #[tokio::test]
@@ -0,0 +1,133 @@
+use crate::{
+    audit::AuditEvent,
+    auth::models::UserRole,
+    comments::{CommentListQuery, CommentRequest, CommentService},
+    error::{AppError, Result},
+    extractors::UnicodeJson,
+    middleware::auth::AuthUser,
+    models::request::ApiResponse,
+    validation::{middleware::extract_validation_context, ContextValidatable, Sanitizable},
+    websocket::WebSocketMessage,
+    AppState,
+};
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use std::net::SocketAddr;
+use tracing::info;
+
+fn comment_service(state: &AppState) -> Result<&CommentService> {
+    state
+        .comments
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound("Comments are not enabled".to_string()))
+}
+
+/// The signed-in user, who must be allowed to write.
+fn commenter(auth_user: Option<Extension<AuthUser>>) -> Result<AuthUser> {
+    let Extension(user) =
+        auth_user.ok_or_else(|| AppError::Authentication("Sign in to comment on items".to_string()))?;
+    if !user.has_role(&UserRole::User) {
+        return Err(AppError::Authorization("Read-only users cannot comment".to_string()));
+    }
+    Ok(user)
+}
+
+/// Normalizes and validates a comment body under the configured field
+/// policies for `comment.body`.
+fn validated_body(
+    state: &AppState,
+    headers: &HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    mut request: CommentRequest,
+) -> Result<String> {
+    let addr = connect_info
+        .map(|ci| ci.0)
+        .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 8080)));
+    let context = extract_validation_context(headers, &addr, None, None)
+        .with_validation_config(state.validation_config.clone());
+
+    request.sanitize_with_context(&context);
+    request.validate_with_context(&context).ensure_valid("Validation failed")?;
+    Ok(request.body)
+}
+
+pub async fn list_comments(
+    State(state): State<AppState>,
+    Path(item_id): Path<u64>,
+    Query(query): Query<CommentListQuery>,
+) -> Result<impl IntoResponse> {
+    info!("GET /api/items/{}/comments", item_id);
+
+    let page = comment_service(&state)?.list(item_id, &query).await?;
+    Ok(Json(ApiResponse::success(page)))
+}
+
+pub async fn create_comment(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    auth_user: Option<Extension<AuthUser>>,
+    Path(item_id): Path<u64>,
+    UnicodeJson(request): UnicodeJson<CommentRequest>,
+) -> Result<impl IntoResponse> {
+    info!("POST /api/items/{}/comments", item_id);
+
+    let user = commenter(auth_user)?;
+    let body = validated_body(&state, &headers, connect_info, request)?;
+    let comment = comment_service(&state)?.add(item_id, &user, body).await?;
+
+    if let Some(ws_manager) = &state.websocket_manager {
+        ws_manager
+            .publish(WebSocketMessage::ItemCommentAdded(comment.clone()))
+            .await;
+    }
+
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(comment))))
+}
+
+pub async fn update_comment(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    auth_user: Option<Extension<AuthUser>>,
+    Path((item_id, id)): Path<(u64, i64)>,
+    UnicodeJson(request): UnicodeJson<CommentRequest>,
+) -> Result<impl IntoResponse> {
+    info!("PATCH /api/items/{}/comments/{}", item_id, id);
+
+    let user = commenter(auth_user)?;
+    let body = validated_body(&state, &headers, connect_info, request)?;
+    let comment = comment_service(&state)?.edit(item_id, id, &user, body).await?;
+
+    Ok(Json(ApiResponse::success(comment)))
+}
+
+/// Admins removing someone else's comment are recorded in the audit log.
+pub async fn delete_comment(
+    State(state): State<AppState>,
+    auth_user: Option<Extension<AuthUser>>,
+    Path((item_id, id)): Path<(u64, i64)>,
+) -> Result<impl IntoResponse> {
+    info!("DELETE /api/items/{}/comments/{}", item_id, id);
+
+    let user = commenter(auth_user)?;
+    let comment = comment_service(&state)?.delete(item_id, id, &user).await?;
+
+    if comment.author_id != Some(user.user_id) {
+        state.audit_log.record(
+            AuditEvent::new("item.comment_removed")
+                .with_actor(user.username.clone())
+                .with_target(format!("{}/{}", item_id, id))
+                .with_details(serde_json::json!({
+                    "author": comment.author,
+                    "author_id": comment.author_id,
+                })),
+        );
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
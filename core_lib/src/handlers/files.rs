@@ -1,6 +1,6 @@
 use axum::{
-    extract::{Extension, Multipart, Path, Query, State},
-    http::{header, HeaderMap, StatusCode},
+    extract::{Extension, Multipart, OriginalUri, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -8,10 +8,15 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
+    audit::AuditEvent,
     error::{AppError, Result},
-    files::{FileUpload, FileListQuery, FileMetadata},
+    events::DomainEvent,
+    files::{assets, content_index, ContentIndexStatus, FileListQuery, FileMetadata, PublicAsset, ReconcileReport},
+    handlers::pagination::PageLinks,
+    jobs::{JobRequest, JobType},
+    list_preferences::ListDefaults,
     middleware::auth::AuthUser,
-    models::files::{FileUploadRequest},
+    models::{files::FileUploadRequest, request::ApiResponse},
     validation::{ContextValidatable, middleware::extract_validation_context, SecurityValidator},
     AppState,
 };
@@ -30,6 +35,10 @@ pub struct FileUploadResponse {
     pub size: u64,
     pub created_at: String,
     pub item_id: Option<u64>,
+    pub content_index: Option<ContentIndexStatus>,
+    /// The passage matching a content search.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
 }
 
 impl From<FileMetadata> for FileUploadResponse {
@@ -42,6 +51,8 @@ impl From<FileMetadata> for FileUploadResponse {
             size: metadata.size,
             created_at: metadata.created_at.to_rfc3339(),
             item_id: metadata.item_id,
+            content_index: metadata.content_index,
+            snippet: None,
         }
     }
 }
@@ -54,22 +65,99 @@ pub struct FileListResponse {
     pub offset: Option<u64>,
 }
 
+/// Header carrying the id that the upload's WebSocket events are sent under.
+/// A client may choose the id itself by sending it with the request.
+pub const UPLOAD_ID_HEADER: &str = "x-upload-id";
+
 pub async fn upload_file(
     State(state): State<AppState>,
     headers: HeaderMap,
     connect_info: Option<axum::extract::ConnectInfo<std::net::SocketAddr>>,
     auth_user: Option<Extension<AuthUser>>,
     Query(query): Query<FileUploadQuery>,
+    multipart: Multipart,
+) -> Response {
+    let upload_id = match headers.get(UPLOAD_ID_HEADER) {
+        Some(value) => match value.to_str().ok().and_then(|value| Uuid::parse_str(value).ok()) {
+            Some(upload_id) => upload_id,
+            None => {
+                return AppError::BadRequest(format!("{} must be a UUID", UPLOAD_ID_HEADER)).into_response();
+            }
+        },
+        None => Uuid::new_v4(),
+    };
+    let user_id = auth_user.as_ref().map(|Extension(user)| user.user_id as u64);
+    let mut progress = UploadProgress::new(&state, upload_id, user_id, &headers);
+
+    let result = receive_upload(&state, &headers, connect_info, user_id, query, multipart, &mut progress).await;
+    progress.finish(&result).await;
+
+    let mut response = match result {
+        Ok(mut metadata) => {
+            queue_text_extraction(&state, &mut metadata).await;
+            state.events.publish(DomainEvent::FileUploaded(metadata.clone())).await;
+            Json(FileUploadResponse::from(metadata)).into_response()
+        }
+        Err(e) => e.into_response(),
+    };
+    response.headers_mut().insert(UPLOAD_ID_HEADER, upload_id.to_string().parse().unwrap());
+    response
+}
+
+/// Queues a `FileTextExtraction` job for an upload with extractable text.
+/// The upload stands whether or not the job can be queued.
+async fn queue_text_extraction(state: &AppState, metadata: &mut FileMetadata) {
+    let (Some(job_queue), Some(file_manager)) = (&state.job_queue, &state.file_manager) else {
+        return;
+    };
+    if !content_index::is_indexable(&metadata.content_type) {
+        return;
+    }
+
+    if let Err(e) = file_manager.repository().set_content_pending(metadata.id).await {
+        tracing::warn!("Failed to mark file {} for text extraction: {}", metadata.id, e);
+        return;
+    }
+    let request = JobRequest {
+        job_type: JobType::FileTextExtraction,
+        payload: serde_json::json!({ "file_id": metadata.id }),
+        priority: None,
+        max_retries: Some(0),
+    };
+    match job_queue.submit_job(request).await {
+        Ok(_) => metadata.content_index = Some(ContentIndexStatus::Pending),
+        Err(e) => {
+            tracing::warn!("Failed to queue text extraction for file {}: {}", metadata.id, e);
+            let _ = file_manager
+                .repository()
+                .clear_content(metadata.id, Some(ContentIndexStatus::NotIndexed))
+                .await;
+            metadata.content_index = Some(ContentIndexStatus::NotIndexed);
+        }
+    }
+}
+
+/// Streams the `file` field to storage, checking it as it arrives.
+async fn receive_upload(
+    state: &AppState,
+    headers: &HeaderMap,
+    connect_info: Option<axum::extract::ConnectInfo<std::net::SocketAddr>>,
+    user_id: Option<u64>,
+    query: FileUploadQuery,
     mut multipart: Multipart,
-) -> Result<Json<FileUploadResponse>> {
+    progress: &mut UploadProgress,
+) -> Result<FileMetadata> {
     let file_manager = state
         .file_manager
         .as_ref()
         .ok_or_else(|| AppError::InternalServerError)?;
 
-    let mut file_upload: Option<FileUpload> = None;
+    // Turn away uploads that cannot fit before reading their body.
+    if let Some(size) = progress.total {
+        file_manager.ensure_space(size)?;
+    }
 
-    while let Some(field) = multipart.next_field().await.map_err(|e| {
+    while let Some(mut field) = multipart.next_field().await.map_err(|e| {
         AppError::BadRequest(format!("Failed to read multipart field: {}", e))
     })? {
         let name = field.name().unwrap_or("").to_string();
@@ -85,36 +173,37 @@ pub async fn upload_file(
                 .unwrap_or("application/octet-stream")
                 .to_string();
 
-            let data = field.bytes().await.map_err(|e| {
+            let mut incoming = file_manager
+                .begin_upload(&filename, &content_type, user_id.unwrap_or(0), query.item_id)
+                .await?;
+            while let Some(chunk) = field.chunk().await.map_err(|e| {
                 AppError::BadRequest(format!("Failed to read file data: {}", e))
-            })?;
+            })? {
+                incoming.write(&chunk).await?;
+                progress.report(incoming.size()).await;
+            }
 
             let file_request = FileUploadRequest {
                 filename: filename.clone(),
                 content_type: content_type.clone(),
-                size: data.len() as u64,
+                size: incoming.size(),
                 description: None,
                 tags: None,
             };
 
-            let user_id = auth_user.as_ref().map(|Extension(user)| user.user_id as u64);
             let addr = connect_info.map(|ci| ci.0).unwrap_or_else(|| {
                 std::net::SocketAddr::from(([127, 0, 0, 1], 8080))
             });
-            let context = extract_validation_context(&headers, &addr, user_id, None);
+            let context = extract_validation_context(headers, &addr, user_id, None);
             
             let validation_result = file_request.validate_with_context(&context);
-            if !validation_result.is_valid {
-                return Err(AppError::FileValidation(format!(
-                    "File validation failed: {}",
-                    serde_json::to_string(&validation_result.errors).unwrap_or_default()
-                )));
-            }
+            validation_result.ensure_valid("File validation failed")?;
 
+            // Scripts in the content were already looked for chunk by chunk.
             let security_result = SecurityValidator::validate_file_upload_security(
                 &filename,
                 &content_type,
-                &data,
+                incoming.head(),
             );
             
             if !security_result.is_valid {
@@ -124,79 +213,89 @@ pub async fn upload_file(
                 )));
             }
 
-            let uploaded_by = match auth_user.as_ref() {
-                Some(Extension(user)) => user.user_id as u64,
-                None => 0,
-            };
+            return file_manager.finish_upload(incoming).await;
+        }
+    }
 
-            file_upload = Some(FileUpload {
-                original_filename: filename,
-                content_type,
-                data: data.to_vec(),
-                uploaded_by,
-                item_id: query.item_id,
-            });
-            break;
+    Err(AppError::BadRequest("No file found in request".to_string()))
+}
+
+/// Tells the uploading user's WebSocket connections how an upload is going,
+/// at most once per `upload_progress_interval_ms`.
+struct UploadProgress {
+    manager: Option<crate::websocket::WebSocketManager>,
+    upload_id: Uuid,
+    user_id: Option<u64>,
+    total: Option<u64>,
+    interval: std::time::Duration,
+    last_sent: Option<std::time::Instant>,
+}
+
+impl UploadProgress {
+    fn new(state: &AppState, upload_id: Uuid, user_id: Option<u64>, headers: &HeaderMap) -> Self {
+        let manager = state.websocket_manager.clone();
+        let interval = manager
+            .as_ref()
+            .map(|manager| manager.config().upload_progress_interval_ms)
+            .unwrap_or_default();
+        Self {
+            manager,
+            upload_id,
+            user_id,
+            total: headers
+                .get(header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok()),
+            interval: std::time::Duration::from_millis(interval),
+            last_sent: None,
         }
     }
 
-    let upload = file_upload.ok_or_else(|| {
-        AppError::BadRequest("No file found in request".to_string())
-    })?;
+    async fn send(&self, message: crate::websocket::WebSocketMessage) {
+        if let (Some(manager), Some(user_id)) = (&self.manager, self.user_id) {
+            manager.send_to_user(user_id, message).await;
+        }
+    }
 
-    let metadata = file_manager.store_file(upload).await?;
-    
-    if let Some(ws_manager) = &state.websocket_manager {
-        let message = serde_json::json!({
-            "type": "file_uploaded",
-            "file": {
-                "id": metadata.id,
-                "filename": metadata.original_filename,
-                "size": metadata.size,
-                "item_id": metadata.item_id
-            }
-        });
-        let event = crate::websocket::WebSocketEvent::Custom(message);
-        ws_manager.broadcast(event).await;
+    async fn report(&mut self, bytes_received: u64) {
+        if self.manager.is_none() || self.user_id.is_none() {
+            return;
+        }
+        let now = std::time::Instant::now();
+        if self.last_sent.is_some_and(|sent| now.duration_since(sent) < self.interval) {
+            return;
+        }
+        self.last_sent = Some(now);
+        self.send(crate::websocket::WebSocketMessage::UploadProgress {
+            upload_id: self.upload_id,
+            bytes_received,
+            total: self.total,
+        })
+        .await;
     }
 
-    Ok(Json(metadata.into()))
+    async fn finish(&self, result: &Result<FileMetadata>) {
+        let message = match result {
+            Ok(metadata) => crate::websocket::WebSocketMessage::UploadCompleted {
+                upload_id: self.upload_id,
+                file_id: metadata.id,
+            },
+            Err(e) => crate::websocket::WebSocketMessage::UploadFailed {
+                upload_id: self.upload_id,
+                error: e.to_string(),
+            },
+        };
+        self.send(message).await;
+    }
 }
 
 pub async fn serve_file(
     State(state): State<AppState>,
     Path(file_id): Path<Uuid>,
+    method: Method,
+    request_headers: HeaderMap,
 ) -> Result<Response> {
-    let file_manager = state
-        .file_manager
-        .as_ref()
-        .ok_or_else(|| AppError::InternalServerError)?;
-
-    let (metadata, data) = file_manager
-        .get_file_data(file_id)
-        .await?
-        .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
-
-    let mut headers = HeaderMap::new();
-    
-    headers.insert(
-        header::CONTENT_TYPE,
-        metadata.content_type.parse().unwrap_or_else(|_| {
-            "application/octet-stream".parse().unwrap()
-        }),
-    );
-    
-    headers.insert(
-        header::CONTENT_LENGTH,
-        data.len().to_string().parse().unwrap(),
-    );
-    
-    headers.insert(
-        header::CACHE_CONTROL,
-        "public, max-age=3600".parse().unwrap(),
-    );
-
-    Ok((StatusCode::OK, headers, data).into_response())
+    respond_with_file(&state, file_id, &method, &request_headers, false).await
 }
 
 pub async fn get_file_info(
@@ -219,63 +318,175 @@ pub async fn get_file_info(
 pub async fn download_file(
     State(state): State<AppState>,
     Path(file_id): Path<Uuid>,
+    method: Method,
+    request_headers: HeaderMap,
+) -> Result<Response> {
+    respond_with_file(&state, file_id, &method, &request_headers, true).await
+}
+
+/// Answers a GET or HEAD for a stored file. HEAD and requests whose
+/// `If-None-Match` or `If-Modified-Since` still match are answered from the
+/// metadata alone, without reading the file.
+async fn respond_with_file(
+    state: &AppState,
+    file_id: Uuid,
+    method: &Method,
+    request_headers: &HeaderMap,
+    attachment: bool,
 ) -> Result<Response> {
     let file_manager = state
         .file_manager
         .as_ref()
         .ok_or_else(|| AppError::InternalServerError)?;
 
-    let (metadata, data) = file_manager
-        .get_file_data(file_id)
+    let metadata = file_manager
+        .get_file_metadata(file_id)
         .await?
         .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+    if let Some(missing_at) = metadata.missing_at {
+        return Err(AppError::Gone(format!(
+            "File {} has been missing from storage since {}",
+            file_id,
+            missing_at.to_rfc3339()
+        )));
+    }
 
+    let etag = file_etag(&metadata);
     let mut headers = HeaderMap::new();
-    
+    headers.insert(header::ETAG, etag.parse().unwrap());
     headers.insert(
-        header::CONTENT_TYPE,
-        metadata.content_type.parse().unwrap_or_else(|_| {
-            "application/octet-stream".parse().unwrap()
-        }),
+        header::LAST_MODIFIED,
+        metadata.created_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string().parse().unwrap(),
     );
-    
     headers.insert(
-        header::CONTENT_LENGTH,
-        data.len().to_string().parse().unwrap(),
-    );
-    
-    let disposition = format!(
-        "attachment; filename=\"{}\"",
-        metadata.original_filename.replace('"', "\\\"")
+        header::CACHE_CONTROL,
+        HeaderValue::from_str(file_manager.cache_control(&metadata.content_type))
+            .unwrap_or_else(|_| HeaderValue::from_static("no-store")),
     );
+
+    if is_not_modified(request_headers, &etag, &metadata) {
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+
     headers.insert(
-        header::CONTENT_DISPOSITION,
-        disposition.parse().unwrap(),
+        header::CONTENT_TYPE,
+        metadata.content_type.parse().unwrap_or_else(|_| {
+            "application/octet-stream".parse().unwrap()
+        }),
     );
+    headers.insert(header::CONTENT_LENGTH, metadata.size.into());
+    if attachment {
+        let disposition = format!(
+            "attachment; filename=\"{}\"",
+            metadata.original_filename.replace('"', "\\\"")
+        );
+        headers.insert(
+            header::CONTENT_DISPOSITION,
+            disposition.parse().unwrap(),
+        );
+    }
+
+    if method == Method::HEAD {
+        return Ok((StatusCode::OK, headers).into_response());
+    }
+
+    let (_, data) = file_manager
+        .get_file_data(file_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+    headers.insert(header::CONTENT_LENGTH, data.len().into());
 
     Ok((StatusCode::OK, headers, data).into_response())
 }
 
+/// A strong validator from the content hash. Files stored before hashes
+/// were recorded get a weak one from their id, size and upload time.
+fn file_etag(metadata: &FileMetadata) -> String {
+    match &metadata.sha256 {
+        Some(sha256) => format!("\"{}\"", sha256),
+        None => format!(
+            "W/\"{}-{}-{}\"",
+            metadata.id.simple(),
+            metadata.size,
+            metadata.created_at.timestamp()
+        ),
+    }
+}
+
+/// `If-None-Match` wins over `If-Modified-Since` when both are sent, as
+/// RFC 9110 requires.
+fn is_not_modified(request_headers: &HeaderMap, etag: &str, metadata: &FileMetadata) -> bool {
+    if let Some(if_none_match) = request_headers.get(header::IF_NONE_MATCH) {
+        let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+        return if_none_match
+            .to_str()
+            .map(|value| value.split(',').any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag)))
+            .unwrap_or(false);
+    }
+
+    request_headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| chrono::DateTime::parse_from_rfc2822(value).ok())
+        .is_some_and(|since| metadata.created_at.timestamp() <= since.timestamp())
+}
+
+/// Lists files. With `search_content=true` only files whose extracted text
+/// matches `q` are listed, best match first, each with the passage that
+/// matched.
 pub async fn list_files(
     State(state): State<AppState>,
-    Query(query): Query<FileListQuery>,
-) -> Result<Json<FileListResponse>> {
+    defaults: Option<Extension<ListDefaults>>,
+    OriginalUri(uri): OriginalUri,
+    Query(mut query): Query<FileListQuery>,
+) -> Result<(HeaderMap, Json<FileListResponse>)> {
     let file_manager = state
         .file_manager
         .as_ref()
         .ok_or_else(|| AppError::InternalServerError)?;
 
-    let files = file_manager.list_files(query.clone()).await?;
+    query.content = match (query.search_content, query.q.as_deref()) {
+        (true, Some(q)) => {
+            let expr = crate::search::QueryExpr::parse(q).map_err(crate::validation::ValidationError::from)?;
+            Some(expr.content_fts().ok_or_else(|| {
+                AppError::BadRequest(
+                    "q must have a term to look for and only unscoped or content: terms".to_string(),
+                )
+            })?)
+        }
+        (true, None) => return Err(AppError::BadRequest("search_content=true requires q".to_string())),
+        (false, Some(_)) => {
+            return Err(AppError::BadRequest(
+                "q is only used with search_content=true; use filename to match names".to_string(),
+            ))
+        }
+        (false, None) => None,
+    };
+
+    if let Some(sort) = &query.sort {
+        crate::files::repository::FILE_SORT.check(sort)?;
+    }
+    // A default ordering would override the best matches first.
+    let defaults = defaults.map(|Extension(defaults)| defaults).unwrap_or_default();
+    if query.content.is_none() {
+        query.sort = defaults.sort(query.sort.take());
+    }
+    query.limit = Some(state.pagination_config.page_size("limit", defaults.page_size(query.limit))?);
+    let files = file_manager.list_files_with_snippets(query.clone()).await?;
     let total = file_manager.count_files(query.clone()).await?;
 
     let response = FileListResponse {
-        files: files.into_iter().map(|f| f.into()).collect(),
+        files: files
+            .into_iter()
+            .map(|(metadata, snippet)| FileUploadResponse { snippet, ..metadata.into() })
+            .collect(),
         total,
         limit: query.limit,
         offset: query.offset,
     };
+    let links = PageLinks::offset(query.offset.unwrap_or(0), query.limit.unwrap_or_default()).with_total(total);
 
-    Ok(Json(response))
+    Ok((links.headers(&uri), Json(response)))
 }
 
 pub async fn delete_file(
@@ -389,4 +600,183 @@ pub async fn get_item_files(
     let files = file_manager.get_files_by_item(item_id).await?;
 
     Ok(Json(files.into_iter().map(|f| f.into()).collect()))
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublicAssetResponse {
+    pub file_id: Uuid,
+    pub sha256: String,
+    pub content_type: String,
+    pub url: String,
+    pub published_at: String,
+    pub unpublished_at: Option<String>,
+}
+
+impl From<PublicAsset> for PublicAssetResponse {
+    fn from(asset: PublicAsset) -> Self {
+        Self {
+            url: asset.url(),
+            file_id: asset.file_id,
+            sha256: asset.sha256,
+            content_type: asset.content_type,
+            published_at: asset.published_at.to_rfc3339(),
+            unpublished_at: asset.unpublished_at.map(|at| at.to_rfc3339()),
+        }
+    }
+}
+
+/// Publishes a file at the public URL of its contents. Only its uploader or
+/// an admin may.
+pub async fn publish_file(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(file_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<PublicAssetResponse>>> {
+    let file_manager = owned_file(&state, &user, file_id, "publish").await?;
+    let asset = file_manager.publish(file_id, user.user_id as u64).await?;
+
+    state.audit_log.record(
+        AuditEvent::new("files.published")
+            .with_actor(user.username)
+            .with_target(file_id.to_string())
+            .with_details(serde_json::json!({ "sha256": asset.sha256, "url": asset.url() })),
+    );
+    Ok(Json(ApiResponse::success(asset.into())))
+}
+
+/// Withdraws a file's publication; its URL then answers 410. Only its
+/// uploader or an admin may.
+pub async fn unpublish_file(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(file_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<PublicAssetResponse>>> {
+    let file_manager = owned_file(&state, &user, file_id, "unpublish").await?;
+    let asset = file_manager.unpublish(file_id).await?;
+
+    state.audit_log.record(
+        AuditEvent::new("files.unpublished")
+            .with_actor(user.username)
+            .with_target(file_id.to_string())
+            .with_details(serde_json::json!({ "sha256": asset.sha256, "url": asset.url() })),
+    );
+    Ok(Json(ApiResponse::success(asset.into())))
+}
+
+/// The file manager, once `user` is known to be allowed to `action` file
+/// `file_id`.
+async fn owned_file<'a>(
+    state: &'a AppState,
+    user: &AuthUser,
+    file_id: Uuid,
+    action: &str,
+) -> Result<&'a crate::files::FileManager> {
+    let file_manager = state
+        .file_manager
+        .as_ref()
+        .ok_or_else(|| AppError::InternalServerError)?;
+
+    let metadata = file_manager
+        .get_file_metadata(file_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+    if metadata.uploaded_by != user.user_id as u64 && user.role != crate::auth::models::UserRole::Admin {
+        return Err(AppError::Authorization(format!(
+            "You don't have permission to {} this file",
+            action
+        )));
+    }
+    Ok(file_manager)
+}
+
+/// Serves a published file by its content hash, to anyone. The contents
+/// behind a URL never change, so it may be cached as immutable.
+pub async fn serve_public_asset(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    method: Method,
+    request_headers: HeaderMap,
+) -> Result<Response> {
+    let file_manager = state
+        .file_manager
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound("Asset not found".to_string()))?;
+    let (asset, data) = file_manager.public_asset(&name).await?;
+
+    let etag = format!("\"{}\"", asset.sha256);
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ETAG, etag.parse().unwrap());
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(assets::PUBLIC_ASSET_CACHE_CONTROL));
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    let not_modified = request_headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == "*" || tag.trim().trim_start_matches("W/") == etag));
+    if not_modified {
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+
+    headers.insert(
+        header::CONTENT_TYPE,
+        asset.content_type.parse().unwrap_or_else(|_| "application/octet-stream".parse().unwrap()),
+    );
+    headers.insert(header::CONTENT_LENGTH, data.len().into());
+    if method == Method::HEAD {
+        return Ok((StatusCode::OK, headers).into_response());
+    }
+    Ok((StatusCode::OK, headers, data).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReconcileQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Reconciles stored files with their rows now instead of waiting for the
+/// next scheduled run. With `dry_run=true` nothing is deleted or marked and
+/// the counts are what a run would repair.
+pub async fn reconcile_files(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Query(query): Query<ReconcileQuery>,
+) -> Result<Json<ApiResponse<ReconcileReport>>> {
+    tracing::info!("POST /api/admin/files/reconcile (dry_run: {})", query.dry_run);
+
+    let reconciler = state
+        .file_reconciler()
+        .ok_or_else(|| AppError::NotFound("File storage is not enabled".to_string()))?;
+    let report = reconciler.reconcile(query.dry_run, &admin.username).await?;
+    Ok(Json(ApiResponse::success(report)))
+}
+
+/// Queues a `FileReindex` job that extracts the text of every file again.
+/// The counts are the job's result.
+pub async fn reindex_files(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+) -> Result<impl IntoResponse> {
+    tracing::info!("POST /api/admin/files/reindex by {}", admin.username);
+
+    if state.file_manager.is_none() {
+        return Err(AppError::NotFound("File storage is not enabled".to_string()));
+    }
+    let job_queue = state
+        .job_queue
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("File reindexing requires the job queue".to_string()))?;
+
+    let job_id = job_queue
+        .submit_job(JobRequest {
+            job_type: JobType::FileReindex,
+            payload: serde_json::json!({ "requested_by": admin.username }),
+            priority: None,
+            max_retries: Some(0),
+        })
+        .await?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ApiResponse::success(serde_json::json!({ "job_id": job_id }))),
+    ))
+}
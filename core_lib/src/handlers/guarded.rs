@@ -0,0 +1,111 @@
+//! Routes registered together with the guard that protects them
+
+use axum::{http::Method, middleware::from_fn, routing::MethodRouter, Router};
+use serde::Serialize;
+
+use crate::auth::models::UserRole;
+use crate::middleware::auth::{require_admin, require_authenticated, require_role};
+use crate::AppState;
+
+/// Who may call a route. The guard is enforced by a layer on the route, so
+/// the inventory can't drift from what the server does; handlers are still
+/// free to refuse callers for their own reasons, such as file ownership.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Guard {
+    /// Anyone, signed in or not.
+    Public,
+    /// Any signed-in user.
+    Authenticated,
+    Admin,
+    /// Signed-in users with at least this role.
+    Permission(UserRole),
+}
+
+impl Guard {
+    /// Whether a caller with `role`, or an anonymous one, gets past the
+    /// guard.
+    pub fn admits(&self, role: Option<&UserRole>) -> bool {
+        match (self, role) {
+            (Guard::Public, _) => true,
+            (_, None) => false,
+            (Guard::Authenticated, Some(_)) => true,
+            (Guard::Admin, Some(role)) => *role == UserRole::Admin,
+            (Guard::Permission(required), Some(role)) => role.grants(required),
+        }
+    }
+
+    fn protect(&self, route: MethodRouter<AppState>) -> MethodRouter<AppState> {
+        match self {
+            Guard::Public => route,
+            Guard::Authenticated => route.route_layer(from_fn(require_authenticated)),
+            Guard::Admin => route.route_layer(from_fn(require_admin)),
+            Guard::Permission(role) => route.route_layer(from_fn(require_role(role.clone()))),
+        }
+    }
+}
+
+/// One method on one path, as listed by `GET /api/admin/routes`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RouteInfo {
+    pub method: String,
+    /// The route template, path parameters included, e.g. `/api/items/:id`.
+    pub path: String,
+    pub guard: Guard,
+}
+
+/// A [`Router`] that takes routes only with a guard, and keeps the list of
+/// them. A route can't be added without saying who may call it.
+#[derive(Default)]
+pub struct GuardedRouter {
+    router: Router<AppState>,
+    routes: Vec<RouteInfo>,
+}
+
+impl GuardedRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves `method` on `path` with `route`, behind `guard`. `route` should
+    /// handle `method` alone; register each method of a path separately.
+    pub fn route(mut self, method: Method, path: &str, guard: Guard, route: MethodRouter<AppState>) -> Self {
+        self.router = self.router.route(path, guard.protect(route));
+        self.routes.push(RouteInfo {
+            method: method.to_string(),
+            path: path.to_string(),
+            guard,
+        });
+        self
+    }
+
+    pub fn nest(mut self, prefix: &str, nested: GuardedRouter) -> Self {
+        self.router = self.router.nest(prefix, nested.router);
+        self.routes.extend(nested.routes.into_iter().map(|route| RouteInfo {
+            path: match route.path.as_str() {
+                "/" => prefix.to_string(),
+                path => format!("{}{}", prefix, path),
+            },
+            ..route
+        }));
+        self
+    }
+
+    pub fn merge(mut self, other: GuardedRouter) -> Self {
+        self.router = self.router.merge(other.router);
+        self.routes.extend(other.routes);
+        self
+    }
+
+    pub fn routes(&self) -> &[RouteInfo] {
+        &self.routes
+    }
+
+    pub fn into_router(self) -> Router<AppState> {
+        self.router
+    }
+
+    pub fn into_routes(self) -> Vec<RouteInfo> {
+        self.routes
+    }
+}
@@ -17,7 +17,7 @@ pub async fn handle_health(State(state): State<AppState>) -> impl IntoResponse {
     info!("GET /health - Running comprehensive health checks");
     
     if let Some(health_checker) = &state.health_checker {
-        let system_health = health_checker.check_all().await;
+        let system_health = health_checker.check_all_cached().await;
         
         let status_code = match system_health.overall_status {
             crate::health::HealthStatus::Healthy => StatusCode::OK,
@@ -45,7 +45,7 @@ pub async fn handle_basic_health(State(state): State<AppState>) -> impl IntoResp
         "timestamp": chrono::Utc::now().timestamp(),
         "store_stats": stats,
         "using_database": state.item_service.is_using_database(),
-        "version": state.version,
+        "version": &*state.version,
         "uptime_seconds": 0
     });
 
@@ -96,13 +96,27 @@ pub async fn handle_readiness(State(state): State<AppState>) -> impl IntoRespons
     if let Some(db_manager) = &state.db_manager {
         if db_manager.health_check().await.is_err() {
             ready = false;
-            issues.push("database_unavailable");
+            issues.push("database_unavailable".to_string());
         }
     }
     
     if state.item_service.get_stats().await.is_err() {
         ready = false;
-        issues.push("item_service_unavailable");
+        issues.push("item_service_unavailable".to_string());
+    }
+
+    if let Some(health_checker) = &state.health_checker {
+        let mut unhealthy: Vec<String> = health_checker
+            .component_states()
+            .into_iter()
+            .filter(|(_, component)| component.status == crate::health::HealthStatus::Unhealthy)
+            .map(|(name, _)| format!("{}_unhealthy", name))
+            .collect();
+        unhealthy.sort();
+        if !unhealthy.is_empty() {
+            ready = false;
+            issues.extend(unhealthy);
+        }
     }
     
     if ready {
@@ -0,0 +1,56 @@
+use crate::{
+    error::{AppError, Result},
+    jobs::{JobRequest, JobType},
+    middleware::auth::AuthUser,
+    models::request::ApiResponse,
+    AppState,
+};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::Deserialize;
+use tracing::info;
+
+#[derive(Debug, Deserialize)]
+pub struct IntegrityQuery {
+    #[serde(default)]
+    pub repair: bool,
+}
+
+/// Checks the references between tables, repairing broken ones with
+/// `repair=true`. With the job queue the check runs as an `IntegrityCheck`
+/// job whose result is the report; without it the report is returned.
+pub async fn check_integrity(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Query(query): Query<IntegrityQuery>,
+) -> Result<Response> {
+    info!("POST /api/admin/integrity/check (repair: {}) by {}", query.repair, admin.username);
+
+    let checker = state
+        .integrity_checker()
+        .ok_or_else(|| AppError::NotFound("Integrity checks need the database".to_string()))?;
+
+    let Some(job_queue) = &state.job_queue else {
+        let report = checker.check(query.repair, None, &admin.username).await?;
+        return Ok(Json(ApiResponse::success(report)).into_response());
+    };
+
+    let job_id = job_queue
+        .submit_job(JobRequest {
+            job_type: JobType::IntegrityCheck,
+            payload: serde_json::json!({ "requested_by": admin.username, "repair": query.repair }),
+            priority: None,
+            max_retries: Some(0),
+        })
+        .await?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ApiResponse::success(serde_json::json!({ "job_id": job_id }))),
+    )
+        .into_response())
+}
@@ -0,0 +1,138 @@
+//! Token checks for sibling services, so they don't need our signing secret
+//!
+//! `POST /auth/introspect` answers RFC 7662 introspection requests from the
+//! services listed in `auth.introspection_clients`. `GET /auth/validate`
+//! answers gateway subrequests (nginx `auth_request`) with 200 or 401 and
+//! the caller's identity in response headers. Each endpoint has its own rate
+//! limiter, separate from the global one. Tokens are never logged.
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Form, Json, Router,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::auth::models::{IntrospectionRequest, IntrospectionResponse};
+use crate::config::{ApiKeyTier, AuthConfig, RateLimitConfig};
+use crate::error::AppError;
+use crate::middleware::auth::extract_token_from_header;
+use crate::middleware::rate_limit::{rate_limit_middleware, RateLimiter, DEFAULT_TIER};
+use crate::AppState;
+
+pub const INTROSPECT_PATH: &str = "/auth/introspect";
+pub const VALIDATE_PATH: &str = "/auth/validate";
+
+const USER_ID_HEADER: HeaderName = HeaderName::from_static("x-user-id");
+const USER_ROLE_HEADER: HeaderName = HeaderName::from_static("x-user-role");
+
+/// The introspection client a request authenticated as.
+#[derive(Debug, Clone)]
+pub struct ServiceClient(pub String);
+
+/// Introspection clients by key.
+#[derive(Clone)]
+struct ServiceCredentials(Arc<HashMap<String, String>>);
+
+pub fn create_token_check_routes(config: &AuthConfig) -> Router<AppState> {
+    let credentials = ServiceCredentials(Arc::new(
+        config
+            .introspection_clients
+            .iter()
+            .map(|client| (client.key.clone(), client.name.clone()))
+            .collect(),
+    ));
+
+    // Each client is counted in its own bucket, named after it; anything
+    // else by address.
+    let introspect_limiter = RateLimiter::new(RateLimitConfig {
+        requests_per_minute: config.introspect_requests_per_minute,
+        enable_user_based_limits: false,
+        exempt_paths: Vec::new(),
+        api_keys: config
+            .introspection_clients
+            .iter()
+            .map(|client| ApiKeyTier {
+                name: client.name.clone(),
+                key: client.key.clone(),
+                tier: DEFAULT_TIER.to_string(),
+            })
+            .collect(),
+        ..RateLimitConfig::default()
+    });
+    let validate_limiter = RateLimiter::per_address(config.validate_requests_per_minute);
+
+    Router::new()
+        .route(
+            INTROSPECT_PATH,
+            post(introspect_token)
+                .route_layer(middleware::from_fn_with_state(credentials, require_service_credential))
+                .route_layer(middleware::from_fn_with_state(introspect_limiter, rate_limit_middleware)),
+        )
+        .route(
+            VALIDATE_PATH,
+            get(validate_token).route_layer(middleware::from_fn_with_state(validate_limiter, rate_limit_middleware)),
+        )
+}
+
+async fn require_service_credential(
+    State(credentials): State<ServiceCredentials>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let name = request
+        .headers()
+        .get("x-api-key")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|key| credentials.0.get(key))
+        .cloned()
+        .ok_or_else(|| AppError::Authentication("A valid introspection client key is required".to_string()))?;
+
+    request.extensions_mut().insert(ServiceClient(name));
+    Ok(next.run(request).await)
+}
+
+async fn introspect_token(
+    State(state): State<AppState>,
+    Extension(client): Extension<ServiceClient>,
+    Form(request): Form<IntrospectionRequest>,
+) -> Result<Json<IntrospectionResponse>, AppError> {
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::InternalServerError)?;
+
+    let response = match auth_service.introspect(&request.token).await? {
+        Some(claims) => IntrospectionResponse::active(&claims),
+        None => IntrospectionResponse::inactive(),
+    };
+    tracing::debug!("Token introspected by {}: active={}", client.0, response.active);
+    Ok(Json(response))
+}
+
+async fn validate_token(State(state): State<AppState>, headers: HeaderMap) -> Result<Response, AppError> {
+    let auth_service = state
+        .auth_service
+        .as_ref()
+        .ok_or_else(|| AppError::InternalServerError)?;
+
+    let token = extract_token_from_header(&headers)?;
+    let claims = auth_service.validate_token(&token).await.map_err(|e| match e {
+        AppError::Authentication(_) => AppError::Authentication("Invalid or expired token".to_string()),
+        e => e,
+    })?;
+
+    let mut response = StatusCode::OK.into_response();
+    let headers = response.headers_mut();
+    if let Ok(user_id) = HeaderValue::from_str(&claims.sub) {
+        headers.insert(USER_ID_HEADER, user_id);
+    }
+    if let Ok(role) = HeaderValue::from_str(&claims.role) {
+        headers.insert(USER_ROLE_HEADER, role);
+    }
+    Ok(response)
+}
@@ -0,0 +1,42 @@
+use crate::{
+    error::{AppError, Result},
+    jobs::{JobRequest, JobType},
+    middleware::auth::AuthUser,
+    models::request::ApiResponse,
+    AppState,
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use tracing::info;
+
+/// Queues a `NameValidation` job that lists the stored items sharing a
+/// name under `items.unique_names`. The report is the job's result.
+pub async fn validate_names(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+) -> Result<impl IntoResponse> {
+    info!("POST /api/admin/items/names/validate by {}", admin.username);
+
+    let job_queue = state
+        .job_queue
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("Item name validation requires the job queue".to_string()))?;
+
+    let job_id = job_queue
+        .submit_job(JobRequest {
+            job_type: JobType::NameValidation,
+            payload: serde_json::json!({ "requested_by": admin.username }),
+            priority: None,
+            max_retries: Some(0),
+        })
+        .await?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ApiResponse::success(serde_json::json!({ "job_id": job_id }))),
+    ))
+}
@@ -0,0 +1,51 @@
+use crate::{
+    config::ItemSchemaConfig,
+    error::{AppError, Result},
+    jobs::{JobRequest, JobType},
+    middleware::auth::AuthUser,
+    models::request::ApiResponse,
+    AppState,
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use tracing::info;
+
+/// The metadata schema items are currently written under, for clients that
+/// build their item forms from it.
+pub async fn get_item_schema(State(state): State<AppState>) -> Json<ApiResponse<ItemSchemaConfig>> {
+    info!("GET /api/items/schema");
+
+    Json(ApiResponse::success(state.item_schema.current().as_ref().clone()))
+}
+
+/// Queues a `SchemaValidation` job that lists the stored items not matching
+/// the current schema. The report is the job's result.
+pub async fn validate_items(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+) -> Result<impl IntoResponse> {
+    info!("POST /api/admin/items/schema/validate by {}", admin.username);
+
+    let job_queue = state
+        .job_queue
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("Item schema validation requires the job queue".to_string()))?;
+
+    let job_id = job_queue
+        .submit_job(JobRequest {
+            job_type: JobType::SchemaValidation,
+            payload: serde_json::json!({ "requested_by": admin.username }),
+            priority: None,
+            max_retries: Some(0),
+        })
+        .await?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ApiResponse::success(serde_json::json!({ "job_id": job_id }))),
+    ))
+}
@@ -0,0 +1,47 @@
+use crate::{
+    error::{AppError, Result},
+    jobs::{JobRequest, JobType},
+    middleware::auth::AuthUser,
+    models::request::ApiResponse,
+    AppState,
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use tracing::info;
+
+/// Queues a `SecretRekey` job that seals every item secret under the
+/// current key, for after the key has been rotated. The report is the
+/// job's result.
+pub async fn rekey_items(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+) -> Result<impl IntoResponse> {
+    info!("POST /api/admin/items/secrets/rekey by {}", admin.username);
+
+    let key_id = state
+        .item_secrets
+        .current_key_id()
+        .ok_or_else(|| AppError::BadRequest("Item secrets are not encrypted on this server".to_string()))?;
+    let job_queue = state
+        .job_queue
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("Item secret rekeying requires the job queue".to_string()))?;
+
+    let job_id = job_queue
+        .submit_job(JobRequest {
+            job_type: JobType::SecretRekey,
+            payload: serde_json::json!({ "requested_by": admin.username, "key_id": key_id }),
+            priority: None,
+            max_retries: Some(0),
+        })
+        .await?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ApiResponse::success(serde_json::json!({ "job_id": job_id, "key_id": key_id }))),
+    ))
+}
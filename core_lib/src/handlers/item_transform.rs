@@ -0,0 +1,71 @@
+use crate::{
+    error::{AppError, Result},
+    item_transform::{MetadataFilter, MetadataOperation, MetadataTransform, DEFAULT_BATCH_SIZE},
+    jobs::{JobRequest, JobType},
+    middleware::auth::AuthUser,
+    models::request::ApiResponse,
+    AppState,
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::Deserialize;
+use tracing::info;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetadataTransformRequest {
+    pub operations: Vec<MetadataOperation>,
+    #[serde(default)]
+    pub filter: MetadataFilter,
+    pub batch_size: Option<usize>,
+    /// Report what would change, with a sample of before and after
+    /// metadata, without writing anything.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Queues a `MetadataTransform` job that applies the requested operations
+/// to the metadata of every matching item in the caller's namespace. The
+/// report is the job's result.
+pub async fn transform_metadata(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Json(request): Json<MetadataTransformRequest>,
+) -> Result<impl IntoResponse> {
+    info!("POST /api/admin/items/metadata/transform by {} (dry run: {})", admin.username, request.dry_run);
+
+    let transform = MetadataTransform {
+        operations: request.operations,
+        filter: request.filter,
+        batch_size: request.batch_size.unwrap_or(DEFAULT_BATCH_SIZE),
+    };
+    transform.validate()?;
+
+    let job_queue = state
+        .job_queue
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("Item metadata transforms require the job queue".to_string()))?;
+
+    let job_id = job_queue
+        .submit_job(JobRequest {
+            job_type: JobType::MetadataTransform,
+            payload: serde_json::json!({
+                "transform": transform,
+                "dry_run": request.dry_run,
+                "namespace": crate::tenancy::current_or_default(),
+                "requested_by": admin.username,
+            }),
+            priority: None,
+            max_retries: Some(0),
+        })
+        .await?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ApiResponse::success(serde_json::json!({ "job_id": job_id, "dry_run": request.dry_run }))),
+    ))
+}
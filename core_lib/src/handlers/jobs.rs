@@ -1,25 +1,40 @@
 use crate::{
+    database::SortSpec,
     error::{AppError, Result},
-    jobs::{JobRequest, JobListParams},
+    handlers::pagination::PageLinks,
+    jobs::{repository::JOB_SORT, JobRequest, JobListParams, JobStatus},
+    list_preferences::ListDefaults,
     models::request::ApiResponse,
     AppState,
 };
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    response::IntoResponse,
+    body::{Body, Bytes},
+    extract::{Extension, OriginalUri, Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
+use futures_util::stream;
 use serde::Deserialize;
+use tokio::io::AsyncReadExt;
 use tracing::info;
 use uuid::Uuid;
 
+/// Size of the chunks a stored job result is streamed in.
+const RESULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Results are served once and can be large, so the response cache keeps
+/// none of them.
+const RESULT_CACHE_CONTROL: &str = "no-store";
+
 #[derive(Debug, Deserialize)]
 pub struct JobQueryParams {
     pub status: Option<String>,
     pub job_type: Option<String>,
     pub limit: Option<u32>,
     pub offset: Option<u32>,
+    /// Fields to sort by, such as `-priority,created_at`.
+    pub sort: Option<SortSpec>,
     pub sort_by: Option<String>,
     pub sort_order: Option<String>,
 }
@@ -35,6 +50,66 @@ pub async fn submit_job(
         .as_ref()
         .ok_or_else(|| AppError::Job("Job queue not available".to_string()))?;
 
+    if request.job_type == crate::jobs::JobType::SnapshotImport {
+        return Err(AppError::BadRequest(
+            "Snapshot imports are started through POST /api/admin/import".to_string(),
+        ));
+    }
+
+    if request.job_type == crate::jobs::JobType::TrashPurge {
+        return Err(AppError::BadRequest(
+            "Trash purges are started through POST /api/items/trash/purge".to_string(),
+        ));
+    }
+
+    if request.job_type == crate::jobs::JobType::FileReconciliation {
+        return Err(AppError::BadRequest(
+            "File reconciliations are started through POST /api/admin/files/reconcile".to_string(),
+        ));
+    }
+
+    if request.job_type == crate::jobs::JobType::FileTextExtraction {
+        return Err(AppError::BadRequest(
+            "File text extraction is queued when a file is uploaded".to_string(),
+        ));
+    }
+
+    if request.job_type == crate::jobs::JobType::FileReindex {
+        return Err(AppError::BadRequest(
+            "File reindexing is started through POST /api/admin/files/reindex".to_string(),
+        ));
+    }
+
+    if request.job_type == crate::jobs::JobType::SchemaValidation {
+        return Err(AppError::BadRequest(
+            "Item schema validations are started through POST /api/admin/items/schema/validate".to_string(),
+        ));
+    }
+
+    if request.job_type == crate::jobs::JobType::NameValidation {
+        return Err(AppError::BadRequest(
+            "Item name validations are started through POST /api/admin/items/names/validate".to_string(),
+        ));
+    }
+
+    if request.job_type == crate::jobs::JobType::IntegrityCheck {
+        return Err(AppError::BadRequest(
+            "Integrity checks are started through POST /api/admin/integrity/check".to_string(),
+        ));
+    }
+
+    if request.job_type == crate::jobs::JobType::SecretRekey {
+        return Err(AppError::BadRequest(
+            "Item secret rekeying is started through POST /api/admin/items/secrets/rekey".to_string(),
+        ));
+    }
+
+    if request.job_type == crate::jobs::JobType::MetadataTransform {
+        return Err(AppError::BadRequest(
+            "Item metadata transforms are started through POST /api/admin/items/metadata/transform".to_string(),
+        ));
+    }
+
     let job_id = job_queue.submit_job(request).await?;
 
     Ok((
@@ -85,8 +160,99 @@ pub async fn get_job(
     Ok(Json(ApiResponse::success(job)))
 }
 
+/// Returns a completed job's result: the JSON kept on the job, or the file
+/// it was stored in when it was too large for that.
+pub async fn get_job_result(
+    State(state): State<AppState>,
+    Path(job_id): Path<Uuid>,
+) -> Result<Response> {
+    info!("GET /api/jobs/{}/result", job_id);
+
+    let job_queue = state
+        .job_queue
+        .as_ref()
+        .ok_or_else(|| AppError::Job("Job queue not available".to_string()))?;
+
+    let job = job_queue
+        .get_job_status(job_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Job not found".to_string()))?;
+
+    if job.status != JobStatus::Completed {
+        return Err(AppError::BadRequest(
+            "Job has no result until it completes".to_string(),
+        ));
+    }
+
+    let Some(result_file) = &job.result_file else {
+        return match job.result {
+            Some(result) => Ok(([(header::CACHE_CONTROL, RESULT_CACHE_CONTROL)], Json(result)).into_response()),
+            None => Err(AppError::NotFound("Job completed without a result".to_string())),
+        };
+    };
+
+    let path = job_queue
+        .result_path(&job)
+        .ok_or_else(|| AppError::Job("Job result storage not available".to_string()))?;
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(AppError::Gone("Job result is no longer stored".to_string()));
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    // Ends after the first read error, which the client sees as a cut-off body.
+    let chunks = stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut chunk = vec![0; RESULT_CHUNK_SIZE];
+        match file.read(&mut chunk).await {
+            Ok(0) => None,
+            Ok(read) => {
+                chunk.truncate(read);
+                Some((Ok(Bytes::from(chunk)), Some(file)))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, result_file.content_type.clone()),
+            (header::CONTENT_LENGTH, result_file.size.to_string()),
+            (header::CACHE_CONTROL, RESULT_CACHE_CONTROL.to_string()),
+        ],
+        Body::from_stream(chunks),
+    )
+        .into_response())
+}
+
+/// Lists a job's attempts, each with how long it waited in the queue, how
+/// long it ran and, for jobs that report them, the phases it spent that time
+/// in.
+pub async fn get_job_attempts(
+    State(state): State<AppState>,
+    Path(job_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    info!("GET /api/jobs/{}/attempts", job_id);
+
+    let job_queue = state
+        .job_queue
+        .as_ref()
+        .ok_or_else(|| AppError::Job("Job queue not available".to_string()))?;
+
+    if job_queue.get_job_status(job_id).await?.is_none() {
+        return Err(AppError::NotFound("Job not found".to_string()));
+    }
+    let attempts = job_queue.list_attempts(job_id).await?;
+
+    Ok(Json(ApiResponse::success(attempts)))
+}
+
 pub async fn list_jobs(
     State(state): State<AppState>,
+    defaults: Option<Extension<ListDefaults>>,
+    OriginalUri(uri): OriginalUri,
     Query(params): Query<JobQueryParams>,
 ) -> Result<impl IntoResponse> {
     info!("GET /api/jobs - params: {:?}", params);
@@ -108,18 +274,19 @@ pub async fn list_jobs(
         None
     };
 
+    let defaults = defaults.map(|Extension(defaults)| defaults).unwrap_or_default();
     let list_params = JobListParams {
         status,
         job_type,
-        limit: params.limit,
+        limit: Some(state.pagination_config.page_size("limit", defaults.page_size(params.limit.map(u64::from)))? as u32),
         offset: params.offset,
-        sort_by: params.sort_by,
-        sort_order: params.sort_order,
+        sort: defaults.sort(JOB_SORT.from_query(params.sort, params.sort_by.as_deref(), params.sort_order.as_deref())?),
     };
 
     let job_list = job_queue.list_jobs(list_params).await?;
+    let links = PageLinks::offset(job_list.offset as u64, job_list.limit as u64).with_total(job_list.total);
 
-    Ok(Json(ApiResponse::success(job_list)))
+    Ok((links.headers(&uri), Json(ApiResponse::success(job_list))))
 }
 
 pub async fn cancel_job(
@@ -288,8 +455,20 @@ fn parse_job_type(type_str: &str) -> Result<crate::jobs::JobType> {
         "file_processing" | "fileprocessing" => Ok(crate::jobs::JobType::FileProcessing),
         "email_notification" | "emailnotification" => Ok(crate::jobs::JobType::EmailNotification),
         "report_generation" | "reportgeneration" => Ok(crate::jobs::JobType::ReportGeneration),
+        "notification" => Ok(crate::jobs::JobType::Notification),
+        "webhook_delivery" | "webhookdelivery" => Ok(crate::jobs::JobType::WebhookDelivery),
+        "snapshot_import" | "snapshotimport" => Ok(crate::jobs::JobType::SnapshotImport),
+        "trash_purge" | "trashpurge" => Ok(crate::jobs::JobType::TrashPurge),
+        "file_reconciliation" | "filereconciliation" => Ok(crate::jobs::JobType::FileReconciliation),
+        "schema_validation" | "schemavalidation" => Ok(crate::jobs::JobType::SchemaValidation),
+        "file_text_extraction" | "filetextextraction" => Ok(crate::jobs::JobType::FileTextExtraction),
+        "file_reindex" | "filereindex" => Ok(crate::jobs::JobType::FileReindex),
+        "secret_rekey" | "secretrekey" => Ok(crate::jobs::JobType::SecretRekey),
+        "metadata_transform" | "metadatatransform" => Ok(crate::jobs::JobType::MetadataTransform),
+        "name_validation" | "namevalidation" => Ok(crate::jobs::JobType::NameValidation),
+        "integrity_check" | "integritycheck" => Ok(crate::jobs::JobType::IntegrityCheck),
         _ => Err(AppError::BadRequest(format!(
-            "Invalid job type: {}. Valid values: bulk_import, bulk_export, data_migration, file_processing, email_notification, report_generation",
+            "Invalid job type: {}. Valid values: bulk_import, bulk_export, data_migration, file_processing, email_notification, report_generation, notification, webhook_delivery, snapshot_import, trash_purge, file_reconciliation, schema_validation, file_text_extraction, file_reindex, secret_rekey, metadata_transform, name_validation, integrity_check",
             type_str
         ))),
     }
@@ -327,6 +506,14 @@ mod tests {
         let _response = submit_job(State(state), Json(request)).await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_attempts_of_unknown_job_are_not_found() {
+        let state = create_test_app_state().await;
+
+        let result = get_job_attempts(State(state), Path(Uuid::new_v4())).await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+    }
+
     #[tokio::test]
     async fn test_parse_job_status() {
         assert!(matches!(
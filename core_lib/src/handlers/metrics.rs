@@ -1,27 +1,44 @@
 //! Enhanced metrics and monitoring handlers
 
 use crate::{
-    error::Result,
+    error::{AppError, Result},
+    health::{HealthHistoryQuery, HealthStatus},
+    metrics::{MetricsCollector, MetricsSnapshot, RouteDatabaseMetric},
     models::request::ApiResponse,
+    monitoring::{response_times::ResponseTimePoint, system::PerformanceMetrics},
+    supervisor::TaskState,
     AppState,
 };
 
 use axum::{
-    extract::State,
+    extract::{Query, State},
     response::IntoResponse,
     Json,
 };
+use serde::{Deserialize, Serialize};
 use tracing::info;
 
+/// The collector the metrics reports are read from. Without the `memory`
+/// backend metrics are only sent elsewhere, so there is nothing to report.
+fn in_memory_metrics(state: &AppState) -> Result<&MetricsCollector> {
+    state.metrics.in_memory().ok_or_else(|| {
+        AppError::NotImplemented(format!(
+            "Metrics are sent to the {} backend and not kept by this server; add \"memory\" to metrics.backends to serve them here",
+            state.metrics.name()
+        ))
+    })
+}
+
 pub async fn handle_enhanced_metrics(State(state): State<AppState>) -> Result<impl IntoResponse> {
     info!("GET /api/metrics - Enhanced metrics with system monitoring");
     
+    let metrics = in_memory_metrics(&state)?;
     let item_count = match state.item_service.get_stats().await {
         Ok(stats) => stats.get("total_items").and_then(|v| v.as_u64()).unwrap_or(0) as usize,
         Err(_) => 0,
     };
     
-    let mut metrics_snapshot = state.metrics.get_snapshot(item_count);
+    let mut metrics_snapshot = metrics.get_snapshot(item_count);
     
     if let Some(system_monitor) = &state.system_monitor {
         let system_metrics = system_monitor.collect_metrics();
@@ -30,6 +47,10 @@ pub async fn handle_enhanced_metrics(State(state): State<AppState>) -> Result<im
         metrics_snapshot.system_metrics = Some(system_metrics);
         metrics_snapshot.performance_metrics = Some(performance_metrics);
     }
+
+    if let Some(ws_manager) = &state.websocket_manager {
+        metrics_snapshot.websocket = Some(ws_manager.stats().await);
+    }
     
     Ok(Json(ApiResponse::success(metrics_snapshot)))
 }
@@ -45,56 +66,467 @@ pub async fn handle_system_metrics(State(state): State<AppState>) -> Result<impl
     }
 }
 
-pub async fn handle_performance_metrics(State(state): State<AppState>) -> Result<impl IntoResponse> {
+#[derive(Debug, Deserialize)]
+pub struct PerformanceQuery {
+    pub window: Option<String>,
+    pub resolution: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PerformanceMetricsResponse {
+    #[serde(flatten)]
+    pub performance: Option<PerformanceMetrics>,
+    pub window_minutes: u64,
+    pub resolution_minutes: u64,
+    pub response_times: Vec<ResponseTimePoint>,
+    /// Queries and database time per route since startup.
+    pub database: Vec<RouteDatabaseMetric>,
+}
+
+/// Parses a whole number of minutes written as `15m`, `2h` or `90`.
+fn parse_minutes(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (number, scale) = if let Some(minutes) = value.strip_suffix('m') {
+        (minutes, 1)
+    } else if let Some(hours) = value.strip_suffix('h') {
+        (hours, 60)
+    } else {
+        (value, 1)
+    };
+
+    number.parse::<u64>().ok().and_then(|n| n.checked_mul(scale)).filter(|minutes| *minutes > 0)
+}
+
+pub async fn handle_performance_metrics(
+    State(state): State<AppState>,
+    Query(query): Query<PerformanceQuery>,
+) -> Result<impl IntoResponse> {
     info!("GET /api/performance/metrics - Performance metrics");
-    
-    if let Some(system_monitor) = &state.system_monitor {
+
+    let metrics = in_memory_metrics(&state)?;
+    let retained = metrics.response_time_window_minutes();
+    let minutes = |value: Option<&str>, default: u64, name: &str| match value {
+        None => Ok(default),
+        Some(value) => parse_minutes(value).ok_or_else(|| {
+            AppError::BadRequest(format!("Invalid {} '{}': use minutes or hours, e.g. 15m or 1h", name, value))
+        }),
+    };
+    let window_minutes = minutes(query.window.as_deref(), 15.min(retained), "window")?;
+    let resolution_minutes = minutes(query.resolution.as_deref(), 1, "resolution")?;
+
+    if window_minutes > retained {
+        return Err(AppError::BadRequest(format!(
+            "Window exceeds the {} minutes of retained history",
+            retained
+        )));
+    }
+    if resolution_minutes > window_minutes {
+        return Err(AppError::BadRequest("Resolution cannot exceed the window".to_string()));
+    }
+
+    let performance = if let Some(system_monitor) = &state.system_monitor {
         let item_count = match state.item_service.get_stats().await {
             Ok(stats) => stats.get("total_items").and_then(|v| v.as_u64()).unwrap_or(0) as usize,
             Err(_) => 0,
         };
-        
-        let app_metrics = state.metrics.get_snapshot(item_count);
-        let performance_metrics = system_monitor.get_performance_metrics(&app_metrics);
-        
-        Ok(Json(ApiResponse::success(performance_metrics)))
+
+        let app_metrics = metrics.get_snapshot(item_count);
+        Some(system_monitor.get_performance_metrics(&app_metrics))
     } else {
-        Ok(Json(ApiResponse::error("System monitoring not enabled".to_string())))
+        None
+    };
+
+    Ok(Json(ApiResponse::success(PerformanceMetricsResponse {
+        performance,
+        window_minutes,
+        resolution_minutes,
+        response_times: metrics.response_time_series(window_minutes, resolution_minutes),
+        database: metrics.database_usage_by_route(),
+    })))
+}
+
+/// Error rate (percent) above which the alerts endpoint raises an insight.
+const INSIGHT_ERROR_RATE_PERCENT: f64 = 5.0;
+/// Average response time (ms) above which an insight is raised.
+const INSIGHT_RESPONSE_TIME_MS: f64 = 1000.0;
+/// Request rate above which traffic is reported as high.
+const INSIGHT_REQUESTS_PER_SECOND: f64 = 10.0;
+
+/// A threshold-based observation about recent traffic.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Insight {
+    /// `info`, `warning` or `critical`.
+    pub severity: &'static str,
+    pub title: String,
+    pub message: String,
+}
+
+fn traffic_insights(metrics: &MetricsSnapshot) -> Vec<Insight> {
+    let mut insights = Vec::new();
+
+    if metrics.error_rate > INSIGHT_ERROR_RATE_PERCENT {
+        insights.push(Insight {
+            severity: "warning",
+            title: "High Error Rate Detected".to_string(),
+            message: format!("Error rate is {:.1}% across {} requests.", metrics.error_rate, metrics.total_requests),
+        });
     }
+
+    if metrics.average_response_time_ms > INSIGHT_RESPONSE_TIME_MS {
+        insights.push(Insight {
+            severity: "warning",
+            title: "Slow Responses".to_string(),
+            message: format!(
+                "Average response time is {:.0}ms (p99 {:.0}ms).",
+                metrics.average_response_time_ms, metrics.response_time_percentiles.p99_ms
+            ),
+        });
+    }
+
+    let shed: u64 = metrics.shed_requests.values().sum();
+    if shed > 0 {
+        insights.push(Insight {
+            severity: "critical",
+            title: "Requests Shed Under Load".to_string(),
+            message: format!("{} requests were rejected with 503 since startup.", shed),
+        });
+    }
+
+    if metrics.requests_per_second > INSIGHT_REQUESTS_PER_SECOND {
+        insights.push(Insight {
+            severity: "info",
+            title: "High Traffic Volume".to_string(),
+            message: format!("Handling {:.1} requests per second on average.", metrics.requests_per_second),
+        });
+    }
+
+    insights
 }
 
 pub async fn handle_resource_alerts(State(state): State<AppState>) -> Result<impl IntoResponse> {
     info!("GET /api/system/alerts - Resource usage alerts");
     
-    if let Some(system_monitor) = &state.system_monitor {
-        let system_metrics = system_monitor.collect_metrics();
-        let alerts = system_monitor.check_resource_alerts(&system_metrics);
-        
-        Ok(Json(ApiResponse::success(serde_json::json!({
-            "timestamp": chrono::Utc::now(),
-            "alerts": alerts,
-            "alert_count": alerts.len(),
-            "has_critical_alerts": alerts.iter().any(|alert| alert.contains("Critical") || alert.contains("High")),
-            "system_status": if alerts.is_empty() { "healthy" } else if alerts.iter().any(|alert| alert.contains("Critical")) { "critical" } else { "warning" }
-        }))))
-    } else {
-        Ok(Json(ApiResponse::error("System monitoring not enabled".to_string())))
-    }
+    let alerts = match &state.system_monitor {
+        Some(system_monitor) => system_monitor.check_resource_alerts(&system_monitor.collect_metrics()),
+        None => Vec::new(),
+    };
+    let insights = state.metrics.snapshot().map(|metrics| traffic_insights(&metrics)).unwrap_or_default();
+    
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "timestamp": chrono::Utc::now(),
+        "system_monitoring": state.system_monitor.is_some(),
+        "alerts": alerts,
+        "alert_count": alerts.len(),
+        "has_critical_alerts": alerts.iter().any(|alert| alert.contains("Critical") || alert.contains("High")),
+        "system_status": if alerts.is_empty() { "healthy" } else if alerts.iter().any(|alert| alert.contains("Critical")) { "critical" } else { "warning" },
+        "insights": insights
+    }))))
+}
+
+pub async fn handle_background_tasks(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    info!("GET /api/system/tasks - Supervised background tasks");
+
+    let tasks = state.supervisor.statuses();
+    let dead_count = tasks.iter().filter(|task| task.state == TaskState::Dead).count();
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "timestamp": chrono::Utc::now(),
+        "tasks": tasks,
+        "task_count": tasks.len(),
+        "dead_count": dead_count
+    }))))
+}
+
+pub async fn handle_traffic_heatmap(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    info!("GET /api/metrics/heatmap - Hourly request volume");
+
+    let hours = in_memory_metrics(&state)?.hourly_traffic();
+    let peak_requests = hours.iter().map(|hour| hour.requests).max().unwrap_or(0);
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "timestamp": chrono::Utc::now(),
+        "hours": hours,
+        "peak_requests": peak_requests
+    }))))
 }
 
-pub async fn handle_health_history(State(state): State<AppState>) -> Result<impl IntoResponse> {
+/// One component of the running server as drawn on the system map.
+#[derive(Debug, Clone, Serialize)]
+pub struct TopologyNode {
+    pub id: &'static str,
+    pub name: &'static str,
+    /// Health check reporting on this component, if any.
+    pub health_check: Option<&'static str>,
+    /// Last status reported by the health checker; `None` until it has run.
+    pub status: Option<HealthStatus>,
+    pub status_since: Option<chrono::DateTime<chrono::Utc>>,
+    pub depends_on: Vec<&'static str>,
+}
+
+/// Components configured in `state`, with their last known health.
+fn system_topology(state: &AppState) -> Vec<TopologyNode> {
+    let on_database = || if state.db_manager.is_some() { vec!["database"] } else { Vec::new() };
+    let components = [
+        ("api", "HTTP API", Some("memory_store"), true, on_database()),
+        ("database", "Database", Some("database"), state.db_manager.is_some(), Vec::new()),
+        ("cache", "Cache", Some("cache_manager"), state.cache_manager.is_some(), Vec::new()),
+        ("auth", "Authentication", Some("auth_service"), state.auth_service.is_some(), on_database()),
+        ("files", "File Storage", Some("filesystem"), state.file_manager.is_some(), on_database()),
+        ("jobs", "Job Queue", Some("job_queue"), state.job_queue.is_some(), on_database()),
+        ("websocket", "WebSocket", Some("websocket_manager"), state.websocket_manager.is_some(), Vec::new()),
+        ("search", "Search", None, state.search_engine.is_some(), on_database()),
+    ];
+
+    let states = state
+        .health_checker
+        .as_ref()
+        .map(|checker| checker.component_states())
+        .unwrap_or_default();
+
+    components
+        .into_iter()
+        .filter(|(_, _, _, present, _)| *present)
+        .map(|(id, name, health_check, _, depends_on)| {
+            let component = health_check.and_then(|check| states.get(check));
+            TopologyNode {
+                id,
+                name,
+                health_check,
+                status: component.map(|component| component.status.clone()),
+                status_since: component.map(|component| component.since),
+                depends_on,
+            }
+        })
+        .collect()
+}
+
+pub async fn handle_system_topology(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    info!("GET /api/system/topology - Configured components");
+
+    let nodes = system_topology(&state);
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "timestamp": chrono::Utc::now(),
+        "nodes": nodes,
+        "count": nodes.len()
+    }))))
+}
+
+pub async fn handle_health_history(
+    State(state): State<AppState>,
+    Query(query): Query<HealthHistoryQuery>,
+) -> Result<impl IntoResponse> {
     info!("GET /api/health/history - Health status change history");
-    
-    let item_count = match state.item_service.get_stats().await {
-        Ok(stats) => stats.get("total_items").and_then(|v| v.as_u64()).unwrap_or(0) as usize,
-        Err(_) => 0,
-    };
-    
-    let metrics_snapshot = state.metrics.get_snapshot(item_count);
-    
+
+    let health_checker = state
+        .health_checker
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("Health checker not configured".to_string()))?;
+
+    if let (Some(since), Some(until)) = (query.since, query.until) {
+        if since > until {
+            return Err(AppError::BadRequest("'since' must not be after 'until'".to_string()));
+        }
+    }
+
+    let transitions = health_checker.history(&query).await;
+
     Ok(Json(ApiResponse::success(serde_json::json!({
         "timestamp": chrono::Utc::now(),
-        "health_status_changes": metrics_snapshot.health_status_changes,
-        "change_count": metrics_snapshot.health_status_changes.len()
+        "components": health_checker.component_states(),
+        "transitions": transitions,
+        "count": transitions.len()
     }))))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::HealthStatus;
+
+    #[test]
+    fn test_parse_minutes() {
+        assert_eq!(parse_minutes("15m"), Some(15));
+        assert_eq!(parse_minutes("2h"), Some(120));
+        assert_eq!(parse_minutes("90"), Some(90));
+        assert_eq!(parse_minutes("0m"), None);
+        assert_eq!(parse_minutes("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn test_performance_metrics_window_and_resolution() {
+        let state = AppState::default();
+        let query = |window: &str, resolution: &str| {
+            Query(PerformanceQuery {
+                window: Some(window.to_string()),
+                resolution: Some(resolution.to_string()),
+            })
+        };
+
+        let response = handle_performance_metrics(State(state.clone()), query("15m", "5m"))
+            .await
+            .unwrap()
+            .into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["data"]["window_minutes"], 15);
+        assert_eq!(json["data"]["response_times"].as_array().unwrap().len(), 3);
+
+        for (window, resolution) in [("1d", "1m"), ("2h", "1m"), ("5m", "10m")] {
+            let result = handle_performance_metrics(State(state.clone()), query(window, resolution)).await;
+            assert!(matches!(result, Err(AppError::BadRequest(_))), "{} / {}", window, resolution);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_health_history_records_debounced_transitions() {
+        use crate::health::{checks::DependencyHealthCheck, HealthChecker};
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let failing = Arc::new(AtomicBool::new(false));
+        let flag = failing.clone();
+        let checker = HealthChecker::new("1.0.0".to_string())
+            .with_failure_threshold(5)
+            .with_component_failure_threshold("database", 2)
+            .add_check(DependencyHealthCheck::new("database".to_string(), move || {
+                if flag.load(Ordering::SeqCst) {
+                    Err(AppError::Database("database is locked".to_string()))
+                } else {
+                    Ok("Database connection successful".to_string())
+                }
+            }));
+        let mut state = AppState::default();
+        state.health_checker = Some(Arc::new(checker));
+        let checker = state.health_checker.clone().unwrap();
+
+        checker.check_all().await;
+        failing.store(true, Ordering::SeqCst);
+        assert_eq!(checker.check_all().await.overall_status, HealthStatus::Degraded);
+        assert_eq!(checker.check_all().await.overall_status, HealthStatus::Unhealthy);
+        failing.store(false, Ordering::SeqCst);
+        checker.check_all().await;
+
+        let history = |query: HealthHistoryQuery| {
+            let state = state.clone();
+            async move {
+                let response = handle_health_history(State(state), Query(query)).await.unwrap().into_response();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"].clone()
+            }
+        };
+
+        let all = history(HealthHistoryQuery { component: Some("database".to_string()), ..Default::default() }).await;
+        let steps: Vec<(&str, &str)> = all["transitions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| (t["from"].as_str().unwrap(), t["to"].as_str().unwrap()))
+            .collect();
+        assert_eq!(steps, vec![("Unhealthy", "Healthy"), ("Degraded", "Unhealthy"), ("Healthy", "Degraded")]);
+        assert_eq!(all["transitions"][1]["error"], "Database error: database is locked");
+        assert_eq!(all["components"]["database"]["status"], "Healthy");
+
+        let none = history(HealthHistoryQuery { component: Some("filesystem".to_string()), ..Default::default() }).await;
+        assert_eq!(none["count"], 0);
+
+        let later = history(HealthHistoryQuery { since: Some(chrono::Utc::now()), ..Default::default() }).await;
+        assert_eq!(later["count"], 0);
+
+        let inverted = HealthHistoryQuery {
+            since: Some(chrono::Utc::now()),
+            until: Some(chrono::Utc::now() - chrono::Duration::hours(1)),
+            ..Default::default()
+        };
+        assert!(matches!(handle_health_history(State(state), Query(inverted)).await, Err(AppError::BadRequest(_))));
+    }
+
+    async fn response_data(response: impl IntoResponse) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_response().into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"].clone()
+    }
+
+    #[tokio::test]
+    async fn test_topology_lists_configured_components() {
+        use crate::health::{checks::DependencyHealthCheck, HealthChecker};
+        use std::sync::Arc;
+
+        let state = AppState::default();
+        let ids = |topology: &[TopologyNode]| topology.iter().map(|node| node.id).collect::<Vec<_>>();
+        assert!(!ids(&system_topology(&state)).contains(&"jobs"));
+
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let checker = HealthChecker::new("1.0.0".to_string()).add_check(DependencyHealthCheck::new(
+            "job_queue".to_string(),
+            || Ok("Job queue is configured and ready".to_string()),
+        ));
+        checker.check_all().await;
+        let mut state = state.with_job_queue(crate::jobs::JobQueue::new(crate::jobs::JobRepository::new(pool)));
+        state.health_checker = Some(Arc::new(checker));
+
+        let topology = system_topology(&state);
+        let jobs = topology.iter().find(|node| node.id == "jobs").unwrap();
+        assert_eq!(jobs.status, Some(HealthStatus::Healthy));
+        assert!(jobs.depends_on.is_empty());
+        let api = topology.iter().find(|node| node.id == "api").unwrap();
+        assert_eq!(api.status, None);
+
+        let data = response_data(handle_system_topology(State(state)).await.unwrap()).await;
+        assert_eq!(data["count"], topology.len());
+    }
+
+    #[tokio::test]
+    async fn test_heatmap_and_insights_reflect_traffic() {
+        let state = AppState::default();
+        for status in [200, 200, 500, 503] {
+            state.metrics.record_response("/api/items", std::time::Duration::from_millis(1500), status);
+        }
+
+        let heatmap = response_data(handle_traffic_heatmap(State(state.clone())).await.unwrap()).await;
+        let hours = heatmap["hours"].as_array().unwrap();
+        assert_eq!(hours.len(), crate::monitoring::traffic::TRAFFIC_HOURS);
+        assert_eq!(hours.last().unwrap()["requests"], 4);
+        assert_eq!(hours.last().unwrap()["errors"], 2);
+        assert_eq!(heatmap["peak_requests"], 4);
+
+        let metrics = state.metrics.in_memory().unwrap();
+        metrics.total_requests.fetch_add(4, std::sync::atomic::Ordering::Relaxed);
+        let alerts = response_data(handle_resource_alerts(State(state)).await.unwrap()).await;
+        assert_eq!(alerts["system_monitoring"], false);
+        let titles: Vec<&str> = alerts["insights"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|insight| insight["title"].as_str().unwrap())
+            .collect();
+        assert_eq!(titles, vec!["High Error Rate Detected", "Slow Responses"]);
+    }
+
+    #[tokio::test]
+    async fn test_reports_need_the_memory_backend() {
+        use crate::config::{MetricsBackend, MetricsConfig};
+        use axum::http::StatusCode;
+
+        let config = MetricsConfig {
+            backends: vec![MetricsBackend::Statsd],
+            ..MetricsConfig::default()
+        };
+        let state = AppState::default().with_metrics(crate::metrics::sink_from_config(&config).unwrap());
+
+        let response = handle_enhanced_metrics(State(state.clone())).await.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["error"].as_str().unwrap().contains("metrics.backends"), "{}", body);
+
+        let response = handle_traffic_heatmap(State(state.clone())).await.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+
+        let alerts = response_data(handle_resource_alerts(State(state)).await.unwrap()).await;
+        assert_eq!(alerts["insights"], serde_json::json!([]));
+    }
+}
@@ -1,7 +1,23 @@
+pub mod admin;
+pub mod admin_ui;
 pub mod auth;
+pub mod batch;
 pub mod cache;
+pub mod comments;
 pub mod files;
+pub mod guarded;
 pub mod health;
+pub mod integrity;
+pub mod introspect;
+pub mod item_names;
+pub mod item_schema;
+pub mod item_secrets;
+pub mod item_transform;
 pub mod jobs;
 pub mod metrics;
-pub mod routes;
\ No newline at end of file
+pub mod pagination;
+pub mod routes;
+pub mod tags;
+pub mod trash;
+pub mod webhooks;
+pub mod websocket;
\ No newline at end of file
@@ -0,0 +1,264 @@
+//! `Link` and page headers for paginated listings
+//!
+//! Listings answer with RFC 8288 `Link` headers to the `first`, `prev`,
+//! `next` and (when the total is known) `last` pages, along with `X-Page`,
+//! `X-Per-Page` and `X-Total-Count`. A link repeats the request's query
+//! string exactly as it was sent, with only the paging parameters replaced,
+//! so filters come back without being decoded and encoded again.
+
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Uri};
+
+pub const X_PAGE: HeaderName = HeaderName::from_static("x-page");
+pub const X_PER_PAGE: HeaderName = HeaderName::from_static("x-per-page");
+pub const X_TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+
+/// Paging parameters of a linked page; `None` drops the parameter.
+type PageParams = Vec<(&'static str, Option<String>)>;
+
+/// Where a page starts, in the listing's own terms.
+#[derive(Debug, Clone, PartialEq)]
+enum Position {
+    /// `page`, counted from 1, of `page_size` entries.
+    Numbered { page: u64 },
+    /// `offset` and `limit`.
+    Offset { offset: u64 },
+    /// Cursor parameter `param`, with the value that continues after this
+    /// page when there is more. Cursors only lead forward.
+    Cursor { param: &'static str, next: Option<String> },
+}
+
+/// The page a listing answered with, from which its headers are built.
+#[derive(Debug, Clone)]
+pub struct PageLinks {
+    position: Position,
+    per_page: u64,
+    total: Option<u64>,
+    has_more: bool,
+}
+
+impl PageLinks {
+    /// Page `page` of `page_size` entries, as `/api/items` pages.
+    pub fn numbered(page: u64, page_size: u64) -> Self {
+        Self::new(Position::Numbered { page: page.max(1) }, page_size)
+    }
+
+    /// `limit` entries from `offset`.
+    pub fn offset(offset: u64, limit: u64) -> Self {
+        Self::new(Position::Offset { offset }, limit)
+    }
+
+    /// Up to `limit` entries from a cursor; `next` is the value of `param`
+    /// for the following page, if there is one.
+    pub fn cursor(param: &'static str, next: Option<String>, limit: u64) -> Self {
+        let has_more = next.is_some();
+        Self {
+            has_more,
+            ..Self::new(Position::Cursor { param, next }, limit)
+        }
+    }
+
+    fn new(position: Position, per_page: u64) -> Self {
+        Self {
+            position,
+            per_page: per_page.max(1),
+            total: None,
+            has_more: false,
+        }
+    }
+
+    /// Entries in the whole listing, which also settles whether a next page
+    /// exists.
+    pub fn with_total(mut self, total: u64) -> Self {
+        self.total = Some(total);
+        self
+    }
+
+    /// Whether entries follow this page, for listings without a total.
+    pub fn with_more(mut self, has_more: bool) -> Self {
+        self.has_more = has_more;
+        self
+    }
+
+    /// The headers for this page of the listing requested at `uri`, which
+    /// must be the original URI rather than one a nested router stripped.
+    pub fn headers(&self, uri: &Uri) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let links = self
+            .links()
+            .into_iter()
+            .map(|(rel, params)| format!("<{}>; rel=\"{}\"", link(uri, &params), rel))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if let Ok(value) = HeaderValue::from_str(&links) {
+            headers.insert(header::LINK, value);
+        }
+
+        if let Some(page) = self.page_number() {
+            headers.insert(X_PAGE, HeaderValue::from(page));
+        }
+        headers.insert(X_PER_PAGE, HeaderValue::from(self.per_page));
+        if let Some(total) = self.total {
+            headers.insert(X_TOTAL_COUNT, HeaderValue::from(total));
+        }
+        headers
+    }
+
+    /// 1-based number of this page; offsets are counted in whole pages.
+    fn page_number(&self) -> Option<u64> {
+        match &self.position {
+            Position::Numbered { page } => Some(*page),
+            Position::Offset { offset } => Some(offset / self.per_page + 1),
+            Position::Cursor { .. } => None,
+        }
+    }
+
+    fn has_next(&self, start: u64) -> bool {
+        match self.total {
+            Some(total) => start + self.per_page < total,
+            None => self.has_more,
+        }
+    }
+
+    /// Start of the last page, when the total is known.
+    fn last_start(&self) -> Option<u64> {
+        self.total
+            .map(|total| total.saturating_sub(1) / self.per_page * self.per_page)
+    }
+
+    /// Each relation with the paging parameters of its page.
+    fn links(&self) -> Vec<(&'static str, PageParams)> {
+        let per_page = self.per_page;
+        let mut links = Vec::new();
+        match &self.position {
+            Position::Numbered { page } => {
+                let at = |page: u64| vec![("page", Some(page.to_string())), ("page_size", Some(per_page.to_string()))];
+                links.push(("first", at(1)));
+                if *page > 1 {
+                    links.push(("prev", at(page - 1)));
+                }
+                if self.has_next((page - 1) * per_page) {
+                    links.push(("next", at(page + 1)));
+                }
+                if let Some(last) = self.last_start() {
+                    links.push(("last", at(last / per_page + 1)));
+                }
+            }
+            Position::Offset { offset } => {
+                let at = |offset: u64| vec![("offset", Some(offset.to_string())), ("limit", Some(per_page.to_string()))];
+                links.push(("first", at(0)));
+                if *offset > 0 {
+                    links.push(("prev", at(offset.saturating_sub(per_page))));
+                }
+                if self.has_next(*offset) {
+                    links.push(("next", at(offset + per_page)));
+                }
+                if let Some(last) = self.last_start() {
+                    links.push(("last", at(last)));
+                }
+            }
+            Position::Cursor { param, next } => {
+                let limit = ("limit", Some(per_page.to_string()));
+                links.push(("first", vec![(*param, None), limit.clone()]));
+                if let Some(next) = next {
+                    links.push(("next", vec![(*param, Some(encode(next))), limit]));
+                }
+            }
+        }
+        links
+    }
+}
+
+/// `uri` with each of `params` set to its value, or removed when it has
+/// none. A parameter keeps its place in the query; new ones go at the end.
+/// Everything else is copied as sent.
+fn link(uri: &Uri, params: &[(&str, Option<String>)]) -> String {
+    let mut pairs = Vec::new();
+    let mut placed = vec![false; params.len()];
+    for pair in uri.query().unwrap_or_default().split('&').filter(|pair| !pair.is_empty()) {
+        let key = pair.split_once('=').map_or(pair, |(key, _)| key);
+        match params.iter().position(|(name, _)| *name == key) {
+            Some(index) if !placed[index] => {
+                placed[index] = true;
+                if let Some(value) = &params[index].1 {
+                    pairs.push(format!("{}={}", key, value));
+                }
+            }
+            Some(_) => {}
+            None => pairs.push(pair.to_string()),
+        }
+    }
+    for ((name, value), placed) in params.iter().zip(placed) {
+        if let (Some(value), false) = (value, placed) {
+            pairs.push(format!("{}={}", name, value));
+        }
+    }
+
+    if pairs.is_empty() {
+        uri.path().to_string()
+    } else {
+        format!("{}?{}", uri.path(), pairs.join("&"))
+    }
+}
+
+/// Percent-encodes a cursor value for a query string.
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link_header(page: &PageLinks, uri: &str) -> String {
+        let headers = page.headers(&uri.parse().unwrap());
+        headers[header::LINK].to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_links_keep_other_parameters_verbatim() {
+        let uri = "/api/items/search?q=lamp&tags=outdoor%20gear,caf%C3%A9&offset=20&limit=10&sort_by=name";
+        let links = link_header(&PageLinks::offset(20, 10).with_total(45), uri);
+        assert_eq!(
+            links,
+            "</api/items/search?q=lamp&tags=outdoor%20gear,caf%C3%A9&offset=0&limit=10&sort_by=name>; rel=\"first\", \
+             </api/items/search?q=lamp&tags=outdoor%20gear,caf%C3%A9&offset=10&limit=10&sort_by=name>; rel=\"prev\", \
+             </api/items/search?q=lamp&tags=outdoor%20gear,caf%C3%A9&offset=30&limit=10&sort_by=name>; rel=\"next\", \
+             </api/items/search?q=lamp&tags=outdoor%20gear,caf%C3%A9&offset=40&limit=10&sort_by=name>; rel=\"last\""
+        );
+    }
+
+    #[test]
+    fn test_numbered_pages_without_total() {
+        let page = PageLinks::numbered(1, 25).with_more(true);
+        let headers = page.headers(&"/api/items?tags=a+b".parse().unwrap());
+        assert_eq!(
+            headers[header::LINK],
+            "</api/items?tags=a+b&page=1&page_size=25>; rel=\"first\", </api/items?tags=a+b&page=2&page_size=25>; rel=\"next\""
+        );
+        assert_eq!(headers[X_PAGE], "1");
+        assert_eq!(headers[X_PER_PAGE], "25");
+        assert!(!headers.contains_key(X_TOTAL_COUNT));
+
+        let last = PageLinks::numbered(3, 25).with_total(75);
+        let headers = last.headers(&"/api/items?page=3&page_size=25".parse().unwrap());
+        assert!(!headers[header::LINK].to_str().unwrap().contains("rel=\"next\""));
+        assert!(headers[header::LINK].to_str().unwrap().ends_with("</api/items?page=3&page_size=25>; rel=\"last\""));
+        assert_eq!(headers[X_TOTAL_COUNT], "75");
+    }
+
+    #[test]
+    fn test_cursor_links() {
+        let page = PageLinks::cursor("since", Some("42".to_string()), 100);
+        assert_eq!(
+            link_header(&page, "/api/items/changes?since=7"),
+            "</api/items/changes?limit=100>; rel=\"first\", </api/items/changes?since=42&limit=100>; rel=\"next\""
+        );
+        assert_eq!(encode("a b/é"), "a%20b%2F%C3%A9");
+    }
+}
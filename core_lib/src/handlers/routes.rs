@@ -1,85 +1,145 @@
 //! HTTP route handlers for all standard methods
 
 use crate::{
+    changes::{ChangePage, ChangesQuery, DEFAULT_CHANGES_LIMIT, MAX_CHANGES_LIMIT},
     error::{AppError, Result},
-    handlers::files,
+    events::DomainEvent,
+    extractors::{query::unknown_params_header, QueryParams, StrictQuery},
+    handlers::{
+        files,
+        guarded::{Guard, GuardedRouter, RouteInfo},
+        pagination::PageLinks,
+    },
+    features::Subsystem,
+    item_secrets,
+    jobs::{JobRequest, JobType},
+    list_preferences::ListDefaults,
+    middleware::auth::AuthUser,
+    middleware::envelope::prefers_representation,
+    middleware::provenance::{self, CacheStatus},
     models::{
         request::{ApiResponse, FormPayload},
-        items::{CreateItemRequest, ItemListQuery, ItemExportQuery},
+        items::{CreateItemRequest, ItemListQuery, ItemExportQuery, ItemStats, ItemStatsQuery},
     },
-    validation::{ValidationContext, ContextValidatable, middleware::extract_validation_context},
+    validation::{ValidationContext, ValidationError, ContextValidatable, Sanitizable, middleware::extract_validation_context},
+    search::{DuplicateCandidate, ExportFormat, QueryExpr, SearchEntity, SimilarityQuery, SuggestQuery},
+    store::Item,
     AppState,
 };
 use axum::{
-    extract::{Form, Path, Query, State, Request, FromRequest},
-    http::{HeaderMap, StatusCode},
-    response::{IntoResponse, Html},
+    extract::{Form, OriginalUri, Path, Query, State, Request, FromRequest},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Html, Response},
     routing::get,
     Json, Router,
     body::Body,
 };
-use serde::{Deserialize};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use tracing::info;
 
 pub fn create_routes() -> Router<AppState> {
-    Router::new()
-        .route("/", get(handle_root))
-        .route("/health", get(crate::handlers::health::handle_health))
-        .route("/health/:component", get(crate::handlers::health::handle_component_health))
-        .route("/ready", get(crate::handlers::health::handle_readiness))
-        .route("/live", get(crate::handlers::health::handle_liveness))
-        .route("/dashboard", get(handle_dashboard))
-        .route("/test", get(handle_test_page))
-        .route("/websocket-test", get(handle_websocket_test))
-        .route("/api/stats", get(handle_stats))
-        .route("/api/metrics", get(crate::handlers::metrics::handle_enhanced_metrics))
-        .route("/api/system/metrics", get(crate::handlers::metrics::handle_system_metrics))
-        .route("/api/performance/metrics", get(crate::handlers::metrics::handle_performance_metrics))
-        .route("/api/system/alerts", get(crate::handlers::metrics::handle_resource_alerts))
-        .route("/api/health/history", get(crate::handlers::metrics::handle_health_history))
-        .route("/api/items", get(handle_get_items).post(handle_post_item))
-        .route("/api/items/search", get(handle_search_items))
-        .route("/api/items/export", get(handle_export_items))
-        .route(
-            "/api/items/:id",
-            get(handle_get_item)
-                .put(handle_put_item)
-                .delete(handle_delete_item)
-                .patch(handle_patch_item),
-        )
+    guarded_routes().into_router()
+}
+
+/// Every route [`create_routes`] serves, with the guard it is registered
+/// under.
+pub fn route_inventory() -> Vec<RouteInfo> {
+    guarded_routes().into_routes()
+}
+
+fn guarded_routes() -> GuardedRouter {
+    use axum::routing::{delete, head, options, patch, post, put};
+    use Guard::Public;
+
+    GuardedRouter::new()
+        .route(Method::GET, "/", Public, get(handle_root))
+        .route(Method::GET, "/health", Public, get(crate::handlers::health::handle_health))
+        .route(Method::GET, "/health/:component", Public, get(crate::handlers::health::handle_component_health))
+        .route(Method::GET, "/ready", Public, get(crate::handlers::health::handle_readiness))
+        .route(Method::GET, "/live", Public, get(crate::handlers::health::handle_liveness))
+        .route(Method::GET, "/dashboard", Public, get(handle_dashboard))
+        .route(Method::GET, "/test", Public, get(handle_test_page))
+        .route(Method::GET, "/websocket-test", Public, get(handle_websocket_test))
+        .route(Method::GET, "/api/stats", Public, get(handle_stats))
+        .route(Method::GET, "/api/metrics", Public, get(crate::handlers::metrics::handle_enhanced_metrics))
+        .route(Method::GET, "/api/system/metrics", Public, get(crate::handlers::metrics::handle_system_metrics))
+        .route(Method::GET, "/api/performance/metrics", Public, get(crate::handlers::metrics::handle_performance_metrics))
+        .route(Method::GET, "/api/system/alerts", Public, get(crate::handlers::metrics::handle_resource_alerts))
+        .route(Method::GET, "/api/system/tasks", Public, get(crate::handlers::metrics::handle_background_tasks))
+        .route(Method::GET, "/api/system/topology", Public, get(crate::handlers::metrics::handle_system_topology))
+        .route(Method::GET, "/api/metrics/heatmap", Public, get(crate::handlers::metrics::handle_traffic_heatmap))
+        .route(Method::GET, "/api/health/history", Public, get(crate::handlers::metrics::handle_health_history))
+        .route(Method::GET, "/api/search", Public, strict_query(get(handle_unified_search)))
+        .route(Method::GET, "/api/items", Public, strict_query(get(handle_get_items)))
+        .route(Method::POST, "/api/items", Public, post(handle_post_item))
+        .route(Method::GET, "/api/items/search", Public, strict_query(get(handle_search_items)))
+        .route(Method::GET, "/api/items/suggest", Public, get(handle_suggest))
+        .route(Method::POST, "/api/items/search/export", Public, strict_query(post(handle_search_export)))
+        .route(Method::GET, "/api/items/export", Public, strict_query(get(handle_export_items)))
+        .route(Method::GET, "/api/items/changes", Public, get(handle_item_changes))
+        .route(Method::GET, "/api/items/schema", Public, get(crate::handlers::item_schema::get_item_schema))
+        .route(Method::POST, "/api/items/check-duplicate", Public, post(handle_check_duplicate))
+        .route(Method::GET, "/api/items/:id/similar", Public, get(handle_similar_items))
+        .route(Method::GET, "/api/items/:id", Public, get(handle_get_item))
+        .route(Method::PUT, "/api/items/:id", Public, put(handle_put_item))
+        .route(Method::DELETE, "/api/items/:id", Public, delete(handle_delete_item))
+        .route(Method::PATCH, "/api/items/:id", Public, patch(handle_patch_item))
 
         // API v1
-        .route("/api/v1/items", get(handle_get_items).post(handle_post_item))
-        .route("/api/v1/items/search", get(handle_search_items))
-        .route("/api/v1/items/export", get(handle_export_items))
-        .route(
-            "/api/v1/items/:id",
-            get(handle_get_item)
-                .put(handle_put_item)
-                .delete(handle_delete_item)
-                .patch(handle_patch_item),
-        )
+        .route(Method::GET, "/api/v1/items", Public, strict_query(get(handle_get_items)))
+        .route(Method::POST, "/api/v1/items", Public, post(handle_post_item))
+        .route(Method::GET, "/api/v1/items/search", Public, strict_query(get(handle_search_items)))
+        .route(Method::GET, "/api/v1/items/suggest", Public, get(handle_suggest))
+        .route(Method::POST, "/api/v1/items/search/export", Public, strict_query(post(handle_search_export)))
+        .route(Method::GET, "/api/v1/items/export", Public, strict_query(get(handle_export_items)))
+        .route(Method::GET, "/api/v1/items/changes", Public, get(handle_item_changes))
+        .route(Method::GET, "/api/v1/items/schema", Public, get(crate::handlers::item_schema::get_item_schema))
+        .route(Method::POST, "/api/v1/items/check-duplicate", Public, post(handle_check_duplicate))
+        .route(Method::GET, "/api/v1/items/:id/similar", Public, get(handle_similar_items))
+        .route(Method::GET, "/api/v1/items/:id", Public, get(handle_get_item))
+        .route(Method::PUT, "/api/v1/items/:id", Public, put(handle_put_item))
+        .route(Method::DELETE, "/api/v1/items/:id", Public, delete(handle_delete_item))
+        .route(Method::PATCH, "/api/v1/items/:id", Public, patch(handle_patch_item))
 
         // API v2
-        .route("/api/v2/items", get(handle_get_items_v2).post(handle_post_item_v2))
-        .route("/api/v2/items/search", get(handle_search_items))
-        .route("/api/v2/items/export", get(handle_export_items))
-        .route(
-            "/api/v2/items/:id",
-            get(handle_get_item_v2)
-                .put(handle_put_item_v2)
-                .delete(handle_delete_item)
-                .patch(handle_patch_item),
-        )
-        .route("/api/form", axum::routing::post(handle_form_submit))
-        .route("/api/head", axum::routing::head(handle_head))
-        .route("/api/options", axum::routing::options(handle_options))
-        .route("/ws", axum::routing::get(crate::websocket::websocket_handler))
-        .nest("/auth", crate::handlers::auth::create_auth_routes_with_middleware())
+        .route(Method::GET, "/api/v2/items", Public, strict_query(get(handle_get_items_v2)))
+        .route(Method::POST, "/api/v2/items", Public, post(handle_post_item_v2))
+        .route(Method::GET, "/api/v2/items/search", Public, strict_query(get(handle_search_items)))
+        .route(Method::GET, "/api/v2/items/suggest", Public, get(handle_suggest))
+        .route(Method::POST, "/api/v2/items/search/export", Public, strict_query(post(handle_search_export)))
+        .route(Method::GET, "/api/v2/items/export", Public, strict_query(get(handle_export_items)))
+        .route(Method::GET, "/api/v2/items/changes", Public, get(handle_item_changes))
+        .route(Method::GET, "/api/v2/items/schema", Public, get(crate::handlers::item_schema::get_item_schema))
+        .route(Method::POST, "/api/v2/items/check-duplicate", Public, post(handle_check_duplicate))
+        .route(Method::GET, "/api/v2/items/:id/similar", Public, get(handle_similar_items))
+        .route(Method::GET, "/api/v2/items/:id", Public, get(handle_get_item_v2))
+        .route(Method::PUT, "/api/v2/items/:id", Public, put(handle_put_item_v2))
+        .route(Method::DELETE, "/api/v2/items/:id", Public, delete(handle_delete_item))
+        .route(Method::PATCH, "/api/v2/items/:id", Public, patch(handle_patch_item))
+        .route(Method::POST, "/api/form", Public, post(handle_form_submit))
+        .route(Method::HEAD, "/api/head", Public, head(handle_head))
+        .route(Method::OPTIONS, "/api/options", Public, options(handle_options))
+        .route(Method::GET, "/ws", Public, get(crate::websocket::websocket_handler))
+        .route(Method::GET, "/api/events", Public, get(crate::websocket::sse_handler))
+        .route(Method::GET, "/public/assets/:asset", Public, get(files::serve_public_asset))
         .nest("/api/files", create_file_routes())
         .nest("/api/jobs", create_job_routes())
         .nest("/api/cache", create_cache_routes())
+        .nest("/api/admin", create_admin_routes())
+        .nest("/api/webhooks", create_webhook_routes())
+        .nest("/api/websocket", create_websocket_routes())
+        .nest("/api/tags", create_tag_routes())
+        .nest("/api/items/trash", create_trash_routes())
+        .nest("/api/items/:id/comments", create_comment_routes())
+        .nest("/api/v1/items/:id/comments", create_comment_routes())
+        .nest("/api/v2/items/:id/comments", create_comment_routes())
+}
+
+/// `route`, for handlers taking a [`StrictQuery`], with the unknown query
+/// parameters it lets through named in `X-Unknown-Params`.
+fn strict_query(route: axum::routing::MethodRouter<AppState>) -> axum::routing::MethodRouter<AppState> {
+    route.layer(axum::middleware::from_fn(unknown_params_header))
 }
 
 async fn handle_root(State(state): State<AppState>) -> impl IntoResponse {
@@ -88,7 +148,22 @@ async fn handle_root(State(state): State<AppState>) -> impl IntoResponse {
         "stats": "/api/stats",
         "items": "/api/items",
         "search": "/api/items/search",
+        "search_all": "/api/search",
+        "search_export": "/api/items/search/export",
+        "suggest": "/api/items/suggest",
+        "changes": "/api/items/changes",
+        "schema": "/api/items/schema",
         "item": "/api/items/{id}",
+        "similar": "/api/items/{id}/similar",
+        "check_duplicate": "/api/items/check-duplicate",
+        "trash": "/api/items/trash",
+        "trash_restore": "/api/items/trash/{id}/restore",
+        "trash_purge": "/api/items/trash/purge",
+        "tags": {
+            "list": "/api/tags",
+            "rename": "/api/tags/rename",
+            "merge": "/api/tags/merge"
+        },
         "form": "/api/form"
     });
 
@@ -101,12 +176,19 @@ async fn handle_root(State(state): State<AppState>) -> impl IntoResponse {
             "delete": "/api/files/{id}",
             "list": "/api/files",
             "associate": "/api/files/{id}/associate",
-            "item_files": "/api/files/item/{id}"
+            "item_files": "/api/files/item/{id}",
+            "reconcile": "/api/admin/files/reconcile",
+            "search_content": "/api/files?search_content=true&q={query}",
+            "reindex": "/api/admin/files/reindex",
+            "publish": "/api/files/{id}/publish",
+            "public_asset": "/public/assets/{sha256}.{ext}"
         });
     }
 
-    if state.websocket_manager.is_some() {
+    let websocket_enabled = state.websocket_manager.is_some() && state.features.is_enabled(Subsystem::Websockets);
+    if websocket_enabled {
         endpoints["websocket"] = serde_json::Value::String("/ws".to_string());
+        endpoints["events"] = serde_json::Value::String("/api/events?topic={topic}".to_string());
     }
 
     if state.auth_service.is_some() {
@@ -116,11 +198,34 @@ async fn handle_root(State(state): State<AppState>) -> impl IntoResponse {
             "refresh": "/auth/refresh",
             "logout": "/auth/logout",
             "me": "/auth/me",
+            "confirm_email": "/auth/me/email/confirm",
+            "preferences": "/auth/me/preferences",
+            "verify_email": "/auth/verify-email",
+            "resend_verification": "/auth/verify-email/resend",
+            "sessions": "/auth/sessions",
+            "password": "/auth/password",
+            "reauthenticate": "/auth/reauthenticate",
+            "introspect": "/auth/introspect",
+            "validate": "/auth/validate",
             "users": "/auth/users/{id}"
         });
+        endpoints["admin_page"] = serde_json::json!({
+            "page": "/admin",
+            "sign_in": "/admin/session",
+            "csrf_token": "/admin/csrf-token"
+        });
     }
 
-    if state.job_queue.is_some() {
+    if state.comments.is_some() {
+        endpoints["comments"] = serde_json::json!({
+            "list": "/api/items/{id}/comments",
+            "create": "/api/items/{id}/comments",
+            "update": "/api/items/{id}/comments/{comment_id}",
+            "delete": "/api/items/{id}/comments/{comment_id}"
+        });
+    }
+
+    if state.job_queue.is_some() && state.features.is_enabled(Subsystem::Jobs) {
         endpoints["jobs"] = serde_json::json!({
             "submit": "/api/jobs",
             "list": "/api/jobs",
@@ -130,8 +235,36 @@ async fn handle_root(State(state): State<AppState>) -> impl IntoResponse {
             "bulk_export": "/api/jobs/bulk-export",
             "get": "/api/jobs/{id}",
             "status": "/api/jobs/{id}/status",
+            "result": "/api/jobs/{id}/result",
+            "attempts": "/api/jobs/{id}/attempts",
             "cancel": "/api/jobs/{id}/cancel",
-            "retry": "/api/jobs/{id}/retry"
+            "retry": "/api/jobs/{id}/retry",
+            "validate_item_schema": "/api/admin/items/schema/validate",
+            "validate_item_names": "/api/admin/items/names/validate",
+            "rekey_item_secrets": "/api/admin/items/secrets/rekey",
+            "transform_item_metadata": "/api/admin/items/metadata/transform"
+        });
+    }
+
+    if state.webhooks.is_some() {
+        endpoints["webhooks"] = serde_json::json!({
+            "create": "/api/webhooks",
+            "list": "/api/webhooks",
+            "get": "/api/webhooks/{id}",
+            "delete": "/api/webhooks/{id}",
+            "deliveries": "/api/webhooks/{id}/deliveries"
+        });
+    }
+
+    if state.db_manager.is_some() {
+        endpoints["integrity"] = serde_json::json!({
+            "check": "/api/admin/integrity/check"
+        });
+    }
+
+    if state.db_manager.is_some() && crate::seed::seeding_allowed() {
+        endpoints["seed"] = serde_json::json!({
+            "load": "/api/admin/seed"
         });
     }
 
@@ -145,18 +278,79 @@ async fn handle_root(State(state): State<AppState>) -> impl IntoResponse {
     }
 
     Json(ApiResponse::success(serde_json::json!({
-        "app": state.app_name,
-        "version": state.version,
+        "app": &*state.app_name,
+        "version": &*state.version,
         "message": "Welcome to the Rust HTTP Server",
         "authentication_enabled": state.auth_service.is_some(),
-        "websocket_enabled": state.websocket_manager.is_some(),
+        "websocket_enabled": websocket_enabled,
+        "features": state.features.states(),
         "endpoints": endpoints
     })))
 }
 
-async fn handle_stats(State(state): State<AppState>) -> Result<impl IntoResponse> {
-    let stats = state.item_service.get_stats().await?;
-    Ok(Json(ApiResponse::success(stats)))
+/// How long computed item statistics are reused. Item mutations drop them
+/// sooner through the `items` cache tag.
+const ITEM_STATS_TTL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// [`ItemStats`] with when they were computed and whether this response
+/// reused them, so dashboards can show how old the figures are.
+#[derive(Serialize)]
+struct StatsResponse {
+    #[serde(flatten)]
+    stats: ItemStats,
+    computed_at: chrono::DateTime<chrono::Utc>,
+    /// `hit` or `miss`.
+    cache: &'static str,
+}
+
+/// Item totals with breakdowns by tag, creator, creation day, metadata and
+/// description; `?breakdown=tags,daily` limits which sections are computed
+/// and `?top=` the length of the tag and creator rankings. The response
+/// shape is [`StatsResponse`].
+///
+/// Computed statistics are kept in the cache manager for
+/// [`ITEM_STATS_TTL`], and concurrent requests that find them missing share
+/// one computation. Administrators can send `?refresh=true` to recompute.
+async fn handle_stats(
+    State(state): State<AppState>,
+    auth_user: Option<axum::Extension<AuthUser>>,
+    Query(query): Query<ItemStatsQuery>,
+) -> Result<Response> {
+    let breakdowns = query.breakdowns().map_err(AppError::BadRequest)?;
+    let top = query.top();
+    let refresh = query.refresh.unwrap_or(false);
+    if refresh && !auth_user.is_some_and(|user| user.is_admin()) {
+        return Err(AppError::Authorization("Only administrators can refresh statistics".to_string()));
+    }
+
+    let compute = || state.item_service.get_detailed_stats(&breakdowns, top);
+    let (stats, hit) = match &state.cache_manager {
+        Some(cache_manager) => {
+            let key = cache_manager.generate_key("item_stats", &[&breakdowns.key(), &top.to_string()]);
+            let key = crate::tenancy::scoped_cache_key(key);
+            cache_manager
+                .get_or_compute(&key, Some(ITEM_STATS_TTL), &["items".to_string()], refresh, compute)
+                .await?
+        }
+        None => (compute().await?, false),
+    };
+
+    let response = StatsResponse {
+        computed_at: stats.generated_at,
+        stats,
+        cache: if hit { "hit" } else { "miss" },
+    };
+    // The statistics cache above replaces the response cache here, which
+    // would repeat a stale `cache` indicator, and `X-Cache` reports it.
+    let cache_status = if hit { CacheStatus::Hit } else { CacheStatus::Miss };
+    Ok((
+        [
+            (header::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+            (provenance::CACHE, cache_status.header_value()),
+        ],
+        Json(ApiResponse::success(response)),
+    )
+        .into_response())
 }
 
 // Using ItemListQuery from models instead and Using a custom SearchQuery for backward compatibility with existing search functionality
@@ -177,6 +371,8 @@ struct SearchQuery {
     offset: Option<u64>,
 }
 
+impl QueryParams for SearchQuery {}
+
 impl ContextValidatable for SearchQuery {
     fn validate_with_context(&self, _context: &ValidationContext) -> crate::validation::ValidationResult {
         let mut result = crate::validation::ValidationResult::success();
@@ -215,227 +411,148 @@ impl ContextValidatable for SearchQuery {
             }
         }
         
-        if let Some(limit) = self.limit {
-            if limit > 1000 {
-                result.add_error("limit", "Limit cannot exceed 1000");
-            }
-        }
-        
         result
     }
 }
 
-async fn handle_search_items(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    connect_info: Option<axum::extract::ConnectInfo<std::net::SocketAddr>>,
-    Query(params): Query<SearchQuery>
-) -> Result<impl IntoResponse> {
-    info!("GET /api/items/search - query: {:?}", params);
+impl SearchQuery {
+    /// The engine query for these parameters, without pagination.
+    fn to_search_query(&self) -> Result<crate::search::SearchQuery> {
+        let mut search_query = crate::search::SearchQuery::new();
     
-    let addr = connect_info.map(|ci| ci.0).unwrap_or_else(|| {
-        std::net::SocketAddr::from(([127, 0, 0, 1], 8080))
-    });
-    let context = extract_validation_context(&headers, &addr, None, None);
-    
-    let validation_result = params.validate_with_context(&context);
-    if !validation_result.is_valid {
-        return Err(AppError::Validation(format!(
-            "Search query validation failed: {}",
-            serde_json::to_string(&validation_result.errors).unwrap_or_default()
-        )));
-    }
+        if let Some(ref text) = self.q {
+            if !text.trim().is_empty() {
+                search_query = search_query.with_text(text.clone());
+            }
+        }
     
-    if state.search_engine.is_none() {
-        let limit = params.limit.unwrap_or(50).min(100) as usize;
-        let offset = params.offset.unwrap_or(0) as usize;
-        
-        let items = state.item_service.get_items(Some(limit), Some(offset)).await?;
-        
-        let filtered_items = if let Some(ref tags_str) = params.tags {
-            let search_tags: Vec<String> = tags_str
+        if let Some(ref tags_str) = self.tags {
+            let tags: Vec<String> = tags_str
                 .split(',')
                 .map(|s| s.trim().to_string())
                 .filter(|s| !s.is_empty())
                 .collect();
-            
-            if !search_tags.is_empty() {
-                items.into_iter()
-                    .filter(|item| {
-                        item.tags.iter().any(|tag| search_tags.contains(tag))
-                    })
-                    .collect()
-            } else {
-                items
+            if !tags.is_empty() {
+                search_query = search_query.with_tags(tags);
             }
-        } else {
-            items
-        };
-        
-        return Ok(Json(ApiResponse::success(serde_json::json!({
-            "items": filtered_items.iter().map(|item| serde_json::json!({
-                "item": item,
-                "matched_fields": ["name", "description", "tags"],
-                "relevance_score": 1.0
-            })).collect::<Vec<_>>(),
-            "total_count": filtered_items.len(),
-            "offset": offset,
-            "limit": limit,
-            "has_more": false,
-            "query": {
-                "text": params.q,
-                "tags": params.tags,
-                "sort_by": params.sort_by,
-                "sort_order": params.sort_order,
-                "fuzzy": params.fuzzy.unwrap_or(false)
-            }
-        }))));
-    }
-    
-    let search_engine = state.search_engine.as_ref().unwrap();
-    let mut search_query = crate::search::SearchQuery::new();
-    
-    if let Some(ref text) = params.q {
-        if !text.trim().is_empty() {
-            search_query = search_query.with_text(text.clone());
         }
-    }
-    
-    if let Some(ref tags_str) = params.tags {
-        let tags: Vec<String> = tags_str
-            .split(',')
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty())
-            .collect();
-        if !tags.is_empty() {
-            search_query = search_query.with_tags(tags);
-        }
-    }
     
-    if let Some(created_after) = params.created_after {
-        let start_date = chrono::DateTime::parse_from_rfc3339(&created_after)
-            .map_err(|_| AppError::BadRequest("Invalid created_after date format".to_string()))?
-            .with_timezone(&chrono::Utc);
+        if let Some(created_after) = self.created_after.as_deref() {
+            let start_date = chrono::DateTime::parse_from_rfc3339(created_after)
+                .map_err(|_| AppError::BadRequest("Invalid created_after date format".to_string()))?
+                .with_timezone(&chrono::Utc);
         
-        let end_date = if let Some(created_before) = params.created_before {
-            Some(chrono::DateTime::parse_from_rfc3339(&created_before)
-                .map_err(|_| AppError::BadRequest("Invalid created_before date format".to_string()))?
-                .with_timezone(&chrono::Utc))
-        } else {
-            None
-        };
+            let end_date = if let Some(created_before) = self.created_before.as_deref() {
+                Some(chrono::DateTime::parse_from_rfc3339(created_before)
+                    .map_err(|_| AppError::BadRequest("Invalid created_before date format".to_string()))?
+                    .with_timezone(&chrono::Utc))
+            } else {
+                None
+            };
         
-        search_query = search_query.with_created_date_range(Some(start_date), end_date);
-    } else if let Some(created_before) = params.created_before {
-        let end_date = chrono::DateTime::parse_from_rfc3339(&created_before)
-            .map_err(|_| AppError::BadRequest("Invalid created_before date format".to_string()))?
-            .with_timezone(&chrono::Utc);
-        search_query = search_query.with_created_date_range(None, Some(end_date));
-    }
+            search_query = search_query.with_created_date_range(Some(start_date), end_date);
+        } else if let Some(created_before) = self.created_before.as_deref() {
+            let end_date = chrono::DateTime::parse_from_rfc3339(created_before)
+                .map_err(|_| AppError::BadRequest("Invalid created_before date format".to_string()))?
+                .with_timezone(&chrono::Utc);
+            search_query = search_query.with_created_date_range(None, Some(end_date));
+        }
     
-    if let Some(updated_after) = params.updated_after {
-        let start_date = chrono::DateTime::parse_from_rfc3339(&updated_after)
-            .map_err(|_| AppError::BadRequest("Invalid updated_after date format".to_string()))?
-            .with_timezone(&chrono::Utc);
+        if let Some(updated_after) = self.updated_after.as_deref() {
+            let start_date = chrono::DateTime::parse_from_rfc3339(updated_after)
+                .map_err(|_| AppError::BadRequest("Invalid updated_after date format".to_string()))?
+                .with_timezone(&chrono::Utc);
+        
+            let end_date = if let Some(updated_before) = self.updated_before.as_deref() {
+                Some(chrono::DateTime::parse_from_rfc3339(updated_before)
+                    .map_err(|_| AppError::BadRequest("Invalid updated_before date format".to_string()))?
+                    .with_timezone(&chrono::Utc))
+            } else {
+                None
+            };
         
-        let end_date = if let Some(updated_before) = params.updated_before {
-            Some(chrono::DateTime::parse_from_rfc3339(&updated_before)
+            search_query = search_query.with_updated_date_range(Some(start_date), end_date);
+        } else if let Some(updated_before) = self.updated_before.as_deref() {
+            let end_date = chrono::DateTime::parse_from_rfc3339(updated_before)
                 .map_err(|_| AppError::BadRequest("Invalid updated_before date format".to_string()))?
-                .with_timezone(&chrono::Utc))
-        } else {
-            None
+                .with_timezone(&chrono::Utc);
+            search_query = search_query.with_updated_date_range(None, Some(end_date));
+        }
+    
+        let sort_field = match self.sort_by.as_deref() {
+            Some("name") => crate::search::SortField::Name,
+            Some("created_at") => crate::search::SortField::CreatedAt,
+            Some("updated_at") => crate::search::SortField::UpdatedAt,
+            Some("relevance") => crate::search::SortField::Relevance,
+            _ => crate::search::SortField::CreatedAt,
         };
-        
-        search_query = search_query.with_updated_date_range(Some(start_date), end_date);
-    } else if let Some(updated_before) = params.updated_before {
-        let end_date = chrono::DateTime::parse_from_rfc3339(&updated_before)
-            .map_err(|_| AppError::BadRequest("Invalid updated_before date format".to_string()))?
-            .with_timezone(&chrono::Utc);
-        search_query = search_query.with_updated_date_range(None, Some(end_date));
-    }
     
-    let sort_field = match params.sort_by.as_deref() {
-        Some("name") => crate::search::SortField::Name,
-        Some("created_at") => crate::search::SortField::CreatedAt,
-        Some("updated_at") => crate::search::SortField::UpdatedAt,
-        Some("relevance") => crate::search::SortField::Relevance,
-        _ => crate::search::SortField::CreatedAt,
-    };
+        let sort_order = match self.sort_order.as_deref() {
+            Some("asc") => crate::search::SortOrder::Asc,
+            Some("desc") => crate::search::SortOrder::Desc,
+            _ => crate::search::SortOrder::Desc,
+        };
     
-    let sort_order = match params.sort_order.as_deref() {
-        Some("asc") => crate::search::SortOrder::Asc,
-        Some("desc") => crate::search::SortOrder::Desc,
-        _ => crate::search::SortOrder::Desc,
-    };
+        search_query = search_query.with_sort(sort_field, sort_order);
     
-    search_query = search_query.with_sort(sort_field, sort_order);
+        if let Some(fuzzy) = self.fuzzy {
+            search_query = search_query.with_fuzzy(fuzzy);
+        }
     
-    if let Some(fuzzy) = params.fuzzy {
-        search_query = search_query.with_fuzzy(fuzzy);
-    }
+        if let Some(created_by) = self.created_by {
+            search_query = search_query.with_created_by(created_by);
+        }
     
-    if let Some(created_by) = params.created_by {
-        search_query = search_query.with_created_by(created_by);
+        if let Some(min_relevance) = self.min_relevance {
+            search_query = search_query.with_min_relevance(min_relevance);
+        }
+        
+        Ok(search_query)
     }
+}
+
+async fn handle_search_items(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    connect_info: Option<axum::extract::ConnectInfo<std::net::SocketAddr>>,
+    auth_user: Option<axum::Extension<AuthUser>>,
+    OriginalUri(uri): OriginalUri,
+    StrictQuery(params): StrictQuery<SearchQuery>
+) -> Result<Response> {
+    info!("GET /api/items/search - query: {:?}", params);
     
-    if let Some(min_relevance) = params.min_relevance {
-        search_query = search_query.with_min_relevance(min_relevance);
-    }
+    let addr = connect_info.map(|ci| ci.0).unwrap_or_else(|| {
+        std::net::SocketAddr::from(([127, 0, 0, 1], 8080))
+    });
+    let context = extract_validation_context(&headers, &addr, None, None);
+    
+    let validation_result = params.validate_with_context(&context);
+    validation_result.ensure_valid("Search query validation failed")?;
+    
+    let expr = match params.q.as_deref().map(str::trim).filter(|text| !text.is_empty()) {
+        Some(text) => Some(QueryExpr::parse(text).map_err(ValidationError::from)?),
+        None => None,
+    };
+
+    let user = auth_user.as_ref().map(|axum::Extension(user)| user);
+    let Some(search_engine) = state.search_engine() else {
+        return in_memory_search(&state, &params, expr.as_ref(), &uri, user).await;
+    };
+    let mut search_query = params.to_search_query()?;
     
-    let limit = params.limit.unwrap_or(50).min(100);
+    let limit = state.pagination_config.page_size("limit", params.limit)?;
     let offset = params.offset.unwrap_or(0);
     search_query = search_query.with_pagination(offset, limit);
     
-    let search_result = match search_engine.search(&search_query).await {
+    let mut search_result = match search_engine.search(&search_query).await {
         Ok(result) => result,
-        Err(_) => {
-            let limit = params.limit.unwrap_or(50).min(100) as usize;
-            let offset = params.offset.unwrap_or(0) as usize;
-            
-            let items = state.item_service.get_items(Some(limit), Some(offset)).await?;
-            
-            let filtered_items = if let Some(ref tags_str) = params.tags {
-                let search_tags: Vec<String> = tags_str
-                    .split(',')
-                    .map(|s| s.trim().to_string())
-                    .filter(|s| !s.is_empty())
-                    .collect();
-                
-                if !search_tags.is_empty() {
-                    items.into_iter()
-                        .filter(|item| {
-                            item.tags.iter().any(|tag| search_tags.contains(tag))
-                        })
-                        .collect()
-                } else {
-                    items
-                }
-            } else {
-                items
-            };
-            
-            return Ok(Json(ApiResponse::success(serde_json::json!({
-                "items": filtered_items.iter().map(|item| serde_json::json!({
-                    "item": item,
-                    "matched_fields": ["name", "description", "tags"],
-                    "relevance_score": 1.0
-                })).collect::<Vec<_>>(),
-                "total_count": filtered_items.len(),
-                "offset": offset,
-                "limit": limit,
-                "has_more": false,
-                "query": {
-                    "text": params.q,
-                    "tags": params.tags,
-                    "sort_by": params.sort_by,
-                    "sort_order": params.sort_order,
-                    "fuzzy": params.fuzzy.unwrap_or(false)
-                }
-            }))));
-        }
+        Err(error @ AppError::Validation(_)) => return Err(error),
+        Err(_) => return in_memory_search(&state, &params, expr.as_ref(), &uri, user).await,
     };
+    state.item_secrets.present_all(search_result.items.iter_mut().map(|hit| &mut hit.item), user)?;
     
-    Ok(Json(ApiResponse::success(serde_json::json!({
+    let links = PageLinks::offset(search_result.offset, search_result.limit).with_total(search_result.total_count);
+    Ok((links.headers(&uri), Json(ApiResponse::success(serde_json::json!({
         "items": search_result.items,
         "total_count": search_result.total_count,
         "offset": search_result.offset,
@@ -448,14 +565,203 @@ async fn handle_search_items(
             "sort_order": params.sort_order,
             "fuzzy": params.fuzzy.unwrap_or(false)
         }
-    }))))
+    })))).into_response())
+}
+
+/// Query string of `POST /api/items/search/export` besides the search
+/// parameters.
+#[derive(Debug, Deserialize)]
+struct SearchExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+impl QueryParams for SearchExportQuery {}
+
+/// Exports every item matching a search, ignoring `limit` and `offset`.
+/// Up to `search_export.direct_limit` items are returned in the response;
+/// more are written to a file by a `BulkExport` job, answered with 202 and
+/// the job id, for signed-in users only. The finished job's result names
+/// the file.
+async fn handle_search_export(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    connect_info: Option<axum::extract::ConnectInfo<std::net::SocketAddr>>,
+    auth_user: Option<axum::Extension<AuthUser>>,
+    StrictQuery((params, export)): StrictQuery<(SearchQuery, SearchExportQuery)>,
+) -> Result<Response> {
+    info!("POST /api/items/search/export - format: {:?}, query: {:?}", export.format, params);
+
+    let addr = connect_info.map(|ci| ci.0).unwrap_or_else(|| {
+        std::net::SocketAddr::from(([127, 0, 0, 1], 8080))
+    });
+    let context = extract_validation_context(&headers, &addr, None, None);
+    params.validate_with_context(&context).ensure_valid("Search query validation failed")?;
+
+    export.format.ensure_supported()?;
+
+    let search_query = params.to_search_query()?;
+    let parameters = serde_json::json!({ "format": export.format, "search": search_query });
+    let exporter = state.search_exporter();
+    if let Some(items) = exporter.collect_direct(&search_query).await? {
+        return export_response(export.format, "search_export", &items, true, &parameters);
+    }
+
+    let Some(job_queue) = &state.job_queue else {
+        let items = exporter.collect(&search_query).await?;
+        return export_response(export.format, "search_export", &items, true, &parameters);
+    };
+
+    // The exported file belongs to whoever asked for it.
+    let Some(axum::Extension(user)) = auth_user else {
+        return Err(AppError::Unauthorized);
+    };
+    let job_id = job_queue
+        .submit_job(JobRequest {
+            job_type: JobType::BulkExport,
+            payload: serde_json::json!({
+                "search": search_query,
+                "format": export.format,
+                "namespace": crate::tenancy::current_or_default(),
+                "requested_by": user.user_id,
+            }),
+            priority: None,
+            max_retries: None,
+        })
+        .await?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ApiResponse::success(serde_json::json!({
+            "job_id": job_id,
+            "status": "pending",
+            "status_url": format!("/api/jobs/{}", job_id),
+        }))),
+    ).into_response())
+}
+
+/// Query string of `GET /api/search` besides the search parameters.
+#[derive(Debug, Deserialize)]
+struct UnifiedSearchQuery {
+    /// Comma-separated entities to search; items when missing.
+    entities: Option<String>,
+}
+
+impl QueryParams for UnifiedSearchQuery {}
+
+/// Searches items, files and users at once. `q` is a query-language
+/// expression for items and a name fragment for files and users.
+async fn handle_unified_search(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    connect_info: Option<axum::extract::ConnectInfo<std::net::SocketAddr>>,
+    auth_user: Option<axum::Extension<AuthUser>>,
+    OriginalUri(uri): OriginalUri,
+    StrictQuery((params, unified)): StrictQuery<(SearchQuery, UnifiedSearchQuery)>,
+) -> Result<impl IntoResponse> {
+    info!("GET /api/search - entities: {:?}, query: {:?}", unified.entities, params);
+
+    let addr = connect_info.map(|ci| ci.0).unwrap_or_else(|| {
+        std::net::SocketAddr::from(([127, 0, 0, 1], 8080))
+    });
+    let context = extract_validation_context(&headers, &addr, None, None);
+    params.validate_with_context(&context).ensure_valid("Search query validation failed")?;
+
+    let entities = unified
+        .entities
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .filter(|entity| !entity.trim().is_empty())
+        .map(str::parse::<SearchEntity>)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|message| ValidationError::field("entities", "invalid", message))?;
+    let search_query = params
+        .to_search_query()?
+        .with_entities(entities)
+        .with_pagination(params.offset.unwrap_or(0), state.pagination_config.page_size("limit", params.limit)?);
+
+    let caller = auth_user.as_ref().map(|axum::Extension(user)| user);
+    let mut result = state.unified_search().search(&search_query, caller).await?;
+    let items = result.results.iter_mut().filter_map(|hit| match hit {
+        crate::search::SearchHit::Item(hit) => Some(&mut hit.item),
+        _ => None,
+    });
+    state.item_secrets.present_all(items, caller)?;
+    let links = PageLinks::offset(result.offset, result.limit).with_total(result.total_count);
+    Ok((links.headers(&uri), Json(ApiResponse::success(serde_json::to_value(result)?))))
+}
+
+/// Search over a page of items from the item service, for when the search
+/// engine is missing or failing. `expr` is evaluated against each item.
+async fn in_memory_search(
+    state: &AppState,
+    params: &SearchQuery,
+    expr: Option<&QueryExpr>,
+    uri: &axum::http::Uri,
+    user: Option<&AuthUser>,
+) -> Result<Response> {
+    let limit = state.pagination_config.page_size("limit", params.limit)? as usize;
+    let offset = params.offset.unwrap_or(0) as usize;
+    
+    let search_tags: Vec<String> = params
+        .tags
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+    // With tags, every tagged item is a candidate and is paged after
+    // filtering; the tag index keeps that from scanning the whole store.
+    let items = if search_tags.is_empty() {
+        state.item_service.get_items(Some(limit), Some(offset)).await?
+    } else {
+        state.item_service.get_items_by_tags(&search_tags, None, None).await?
+    };
+    let filtered_items: Vec<_> = items
+        .into_iter()
+        .filter(|item| expr.is_none_or(|expr| expr.matches(item)))
+        .collect();
+    let mut filtered_items: Vec<_> = if search_tags.is_empty() {
+        filtered_items
+    } else {
+        filtered_items.into_iter().skip(offset).take(limit).collect()
+    };
+    state.item_secrets.present_all(&mut filtered_items, user)?;
+    
+    let links = PageLinks::offset(offset as u64, limit as u64);
+    Ok((links.headers(uri), Json(ApiResponse::success(serde_json::json!({
+        "items": filtered_items.iter().map(|item| serde_json::json!({
+            "item": item,
+            "matched_fields": match expr {
+                Some(expr) => expr.matched_fields(item),
+                None => vec!["name".to_string(), "description".to_string(), "tags".to_string()],
+            },
+            "relevance_score": 1.0
+        })).collect::<Vec<_>>(),
+        "total_count": filtered_items.len(),
+        "offset": offset,
+        "limit": limit,
+        "has_more": false,
+        "query": {
+            "text": params.q,
+            "tags": params.tags,
+            "sort_by": params.sort_by,
+            "sort_order": params.sort_order,
+            "fuzzy": params.fuzzy.unwrap_or(false)
+        }
+    })))).into_response())
 }
 
 async fn handle_get_items(
     State(state): State<AppState>,
     headers: HeaderMap,
     connect_info: Option<axum::extract::ConnectInfo<std::net::SocketAddr>>,
-    Query(params): Query<ItemListQuery>
+    auth_user: Option<axum::Extension<AuthUser>>,
+    defaults: Option<axum::Extension<ListDefaults>>,
+    OriginalUri(uri): OriginalUri,
+    StrictQuery(mut params): StrictQuery<ItemListQuery>
 ) -> Result<impl IntoResponse> {
     info!("GET /api/items - page_size: {:?}, page: {:?}", params.page_size, params.page);
     
@@ -465,38 +771,81 @@ async fn handle_get_items(
     let context = extract_validation_context(&headers, &addr, None, None);
     
     let validation_result = params.validate_with_context(&context);
-    if !validation_result.is_valid {
-        return Err(AppError::Validation(format!(
-            "Query validation failed: {}",
-            serde_json::to_string(&validation_result.errors).unwrap_or_default()
-        )));
-    }
+    validation_result.ensure_valid("Query validation failed")?;
+
+    let defaults = defaults.map(|axum::Extension(defaults)| defaults).unwrap_or_default();
+    params.include = defaults.include(params.include.take());
     
-    let page_size = params.page_size.unwrap_or(50) as usize;
+    let page_size = state.pagination_config.page_size("page_size", defaults.page_size(params.page_size.map(u64::from)))? as usize;
     let page = params.page.unwrap_or(1);
     let offset = ((page - 1) * page_size as u32) as usize;
     
     tracing::debug!("Pagination: page={}, page_size={}, offset={}", page, page_size, offset);
     
-    let items = state.item_service.get_items(Some(page_size), Some(offset)).await
+    let sort = defaults.sort(params.sort_spec()?);
+    
+    // One more than the page, to tell whether another follows.
+    let mut items = state.item_service.list_items(Some(page_size + 1), Some(offset), sort.as_ref()).await
         .map_err(|e| {
             tracing::error!("Failed to get items: page={}, page_size={}, offset={}, error={:?}", page, page_size, offset, e);
             e
         })?;
+    let links = PageLinks::numbered(page as u64, page_size as u64).with_more(items.len() > page_size);
+    items.truncate(page_size);
+    state.item_secrets.present_all(&mut items, auth_user.as_ref().map(|axum::Extension(user)| user))?;
     
-    Ok(Json(ApiResponse::success(serde_json::json!({
-        "items": items,
+    let entries = item_list_entries(&state, &items, &params).await?;
+
+    let mut body = serde_json::json!({
+        "items": entries,
         "count": items.len(),
         "page_size": page_size,
         "page": page,
         "offset": offset,
-        "source": if state.item_service.is_using_database() { "database" } else { "memory" }
-    }))))
+    });
+    with_legacy_source(&state, &mut body);
+    Ok((links.headers(&uri), Json(ApiResponse::success(body))))
+}
+
+/// Adds the deprecated `source` field, while
+/// [`ItemConfig::source_in_body`](crate::config::ItemConfig::source_in_body)
+/// keeps it. The `X-Data-Source` header says the same on every response.
+fn with_legacy_source(state: &AppState, body: &mut serde_json::Value) {
+    if state.item_service.source_in_body() {
+        body["source"] = serde_json::Value::from(state.item_service.data_source());
+    }
+}
+
+/// `items` as listed, each with its `comment_count` when the query has
+/// `include=comment_counts`. The counts come from one grouped query for the
+/// whole page.
+async fn item_list_entries(state: &AppState, items: &[Item], params: &ItemListQuery) -> Result<serde_json::Value> {
+    if !params.includes(ItemListQuery::COMMENT_COUNTS) {
+        return Ok(serde_json::to_value(items)?);
+    }
+
+    let comments = state
+        .comments
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("Comment counts are only available with a database".to_string()))?;
+    let ids: Vec<u64> = items.iter().map(|item| item.id).collect();
+    let counts = comments.counts(&ids).await?;
+
+    items
+        .iter()
+        .map(|item| {
+            let mut entry = serde_json::to_value(item)?;
+            entry["comment_count"] = counts.get(&item.id).copied().unwrap_or(0).into();
+            Ok(entry)
+        })
+        .collect::<Result<Vec<_>>>()
+        .map(serde_json::Value::Array)
 }
 
 async fn handle_get_item(
     State(state): State<AppState>,
-    Path(id): Path<u64>
+    Path(id): Path<u64>,
+    auth_user: Option<axum::Extension<AuthUser>>,
 ) -> Result<impl IntoResponse> {
     info!("GET /api/items/{}", id);
     
@@ -504,50 +853,73 @@ async fn handle_get_item(
         return Err(AppError::BadRequest("Invalid item ID".to_string()));
     }
 
-    let item = state.item_service.get_item(id).await?;
+    let mut item = state.item_service.get_item(id).await?;
+    state.item_secrets.present(&mut item, auth_user.as_ref().map(|axum::Extension(user)| user))?;
     Ok(Json(ApiResponse::success(item)))
 }
 
+#[derive(Debug, Default, Deserialize)]
+struct CreateItemQuery {
+    /// Refuse with 409 and the likely duplicates instead of creating.
+    #[serde(default)]
+    reject_duplicates: bool,
+}
+
 async fn handle_post_item(
     State(state): State<AppState>,
     headers: HeaderMap,
     connect_info: Option<axum::extract::ConnectInfo<std::net::SocketAddr>>,
+    auth_user: Option<axum::Extension<AuthUser>>,
+    Query(query): Query<CreateItemQuery>,
     payload: crate::extractors::UnicodeJson<CreateItemRequest>
-) -> Result<impl IntoResponse> {
-    let crate::extractors::UnicodeJson(payload) = payload;
+) -> Result<Response> {
+    let crate::extractors::UnicodeJson(mut payload) = payload;
     info!("POST /api/items - name: {}", payload.name);
     
     let addr = connect_info.map(|ci| ci.0).unwrap_or_else(|| {
         std::net::SocketAddr::from(([127, 0, 0, 1], 8080))
     });
-    let context = extract_validation_context(&headers, &addr, None, None);
+    let context = extract_validation_context(&headers, &addr, None, None)
+        .with_validation_config(state.validation_config.clone());
     
+    payload.sanitize_with_context(&context);
     let validation_result = payload.validate_with_context(&context);
-    if !validation_result.is_valid {
-        return Err(AppError::Validation(format!(
-            "Validation failed: {}",
-            serde_json::to_string(&validation_result.errors).unwrap_or_default()
-        )));
+    validation_result.ensure_valid("Validation failed")?;
+
+    if query.reject_duplicates {
+        let candidate = DuplicateCandidate {
+            name: payload.name.clone(),
+            description: payload.description.clone(),
+            tags: payload.tags.clone().unwrap_or_default(),
+        };
+        let candidates = state
+            .duplicate_detector()
+            .find(&candidate, None, &SimilarityQuery::default())
+            .await?;
+        if !candidates.is_empty() {
+            return Ok((
+                StatusCode::CONFLICT,
+                Json(ApiResponse {
+                    success: false,
+                    data: Some(candidates),
+                    message: Some("Item looks like a duplicate of existing items".to_string()),
+                }),
+            )
+                .into_response());
+        }
     }
 
-    let item = state.item_service.create_item(
+    let mut item = state.item_service.create_item(
         payload.name,
         payload.description,
         payload.tags.unwrap_or_default(),
         payload.metadata
     ).await?;
 
-    if let Some(cache_manager) = &state.cache_manager {
-        cache_manager.invalidate_items_cache();
-        cache_manager.invalidate_search_cache();
-    }
-
-    if let Some(ws_manager) = &state.websocket_manager {
-        let event = crate::websocket::WebSocketEvent::ItemCreated(item.clone());
-        ws_manager.broadcast(event).await;
-    }
+    state.events.publish(DomainEvent::ItemCreated(item.clone())).await;
+    state.item_secrets.present(&mut item, auth_user.as_ref().map(|axum::Extension(user)| user))?;
 
-    Ok((StatusCode::CREATED, Json(ApiResponse::success(item))))
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(item))).into_response())
 }
 
 async fn handle_put_item(
@@ -555,9 +927,10 @@ async fn handle_put_item(
     Path(id): Path<u64>,
     headers: HeaderMap,
     connect_info: Option<axum::extract::ConnectInfo<std::net::SocketAddr>>,
+    auth_user: Option<axum::Extension<AuthUser>>,
     payload: crate::extractors::UnicodeJson<CreateItemRequest>,
 ) -> Result<impl IntoResponse> {
-    let crate::extractors::UnicodeJson(payload) = payload;
+    let crate::extractors::UnicodeJson(mut payload) = payload;
     info!("PUT /api/items/{} - name: {}", id, payload.name);
     
     if id == 0 {
@@ -567,97 +940,116 @@ async fn handle_put_item(
     let addr = connect_info.map(|ci| ci.0).unwrap_or_else(|| {
         std::net::SocketAddr::from(([127, 0, 0, 1], 8080))
     });
-    let context = extract_validation_context(&headers, &addr, None, None);
+    let context = extract_validation_context(&headers, &addr, None, None)
+        .with_validation_config(state.validation_config.clone());
     
+    payload.sanitize_with_context(&context);
     let validation_result = payload.validate_with_context(&context);
-    if !validation_result.is_valid {
-        return Err(AppError::Validation(format!(
-            "Validation failed: {}",
-            serde_json::to_string(&validation_result.errors).unwrap_or_default()
-        )));
-    }
+    validation_result.ensure_valid("Validation failed")?;
 
-    let item = state.item_service.update_item(
+    let expected_version = expected_version(&headers, payload.version)?;
+    let mut item = state.item_service.update_item(
         id,
         payload.name,
         payload.description,
         payload.tags.unwrap_or_default(),
-        payload.metadata
+        payload.metadata,
+        expected_version,
     ).await?;
 
-    if let Some(cache_manager) = &state.cache_manager {
-        cache_manager.invalidate_item_cache(id);
-        cache_manager.invalidate_items_cache();
-        cache_manager.invalidate_search_cache();
-    }
+    state.events.publish(DomainEvent::ItemUpdated(item.clone())).await;
+    state.item_secrets.present(&mut item, auth_user.as_ref().map(|axum::Extension(user)| user))?;
 
-    if let Some(ws_manager) = &state.websocket_manager {
-        let event = crate::websocket::WebSocketEvent::ItemUpdated(item.clone());
-        ws_manager.broadcast(event).await;
+    Ok(Json(ApiResponse::success(item)))
+}
+
+/// Version an update was made against. `If-Match: "<version>"`, weak or
+/// strong, takes precedence over a `version` in the body; `If-Match: *`
+/// matches any version, as it would without the header.
+fn expected_version(headers: &HeaderMap, body_version: Option<u64>) -> Result<Option<u64>> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Ok(body_version);
+    };
+
+    let value = value.to_str().unwrap_or_default().trim();
+    if value == "*" {
+        return Ok(body_version);
     }
 
-    Ok(Json(ApiResponse::success(item)))
+    value
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .parse::<u64>()
+        .map(Some)
+        .map_err(|_| AppError::BadRequest("If-Match must be an item version such as \"3\"".to_string()))
 }
 
+/// Deletes an item. Responds with an empty 204 by default, or 200 with the
+/// deleted id when the client prefers a representation.
 async fn handle_delete_item(
     State(state): State<AppState>,
-    Path(id): Path<u64>
-) -> Result<impl IntoResponse> {
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> Result<Response> {
     info!("DELETE /api/items/{}", id);
     
     if id == 0 {
         return Err(AppError::BadRequest("Invalid item ID".to_string()));
     }
 
+    // Subscribers are sent the item as it was, so it is read before deletion.
+    let deleted_item = state.item_service.get_item(id).await.ok();
+
     state.item_service.delete_item(id).await?;
     
-    if let Some(cache_manager) = &state.cache_manager {
-        cache_manager.invalidate_item_cache(id);
-        cache_manager.invalidate_items_cache();
-        cache_manager.invalidate_search_cache();
-    }
+    state.events.publish(DomainEvent::ItemDeleted { id, item: deleted_item }).await;
     
-    if let Some(ws_manager) = &state.websocket_manager {
-        let event = crate::websocket::WebSocketEvent::ItemDeleted(id);
-        ws_manager.broadcast(event).await;
+    if !prefers_representation(&headers) {
+        return Ok(StatusCode::NO_CONTENT.into_response());
     }
-    
+
     Ok((
-        StatusCode::NO_CONTENT,
+        StatusCode::OK,
+        [("Preference-Applied", "return=representation")],
         Json(ApiResponse::success(serde_json::json!({
             "message": "Item deleted successfully",
             "deleted_id": id
         }))),
-    ))
+    )
+        .into_response())
 }
 
 async fn handle_patch_item(
     State(state): State<AppState>,
     Path(id): Path<u64>,
-    Json(patch): Json<HashMap<String, serde_json::Value>>,
+    headers: HeaderMap,
+    auth_user: Option<axum::Extension<AuthUser>>,
+    Json(mut patch): Json<HashMap<String, serde_json::Value>>,
 ) -> Result<impl IntoResponse> {
-    info!("PATCH /api/items/{} - updates: {:?}", id, patch);
+    info!("PATCH /api/items/{} - fields: {:?}", id, patch.keys().collect::<Vec<_>>());
     
     if id == 0 {
         return Err(AppError::BadRequest("Invalid item ID".to_string()));
     }
+
+    let body_version = match patch.remove("version") {
+        None | Some(serde_json::Value::Null) => None,
+        Some(version) => Some(
+            version
+                .as_u64()
+                .ok_or_else(|| AppError::BadRequest("version must be a non-negative integer".to_string()))?,
+        ),
+    };
+    let expected_version = expected_version(&headers, body_version)?;
     
     if patch.is_empty() {
         return Err(AppError::BadRequest("No updates provided".to_string()));
     }
 
-    let item = state.item_service.patch_item(id, patch).await?;
-    
-    if let Some(cache_manager) = &state.cache_manager {
-        cache_manager.invalidate_item_cache(id);
-        cache_manager.invalidate_items_cache();
-        cache_manager.invalidate_search_cache();
-    }
+    let mut item = state.item_service.patch_item(id, patch, expected_version).await?;
     
-    if let Some(ws_manager) = &state.websocket_manager {
-        let event = crate::websocket::WebSocketEvent::ItemUpdated(item.clone());
-        ws_manager.broadcast(event).await;
-    }
+    state.events.publish(DomainEvent::ItemUpdated(item.clone())).await;
+    state.item_secrets.present(&mut item, auth_user.as_ref().map(|axum::Extension(user)| user))?;
     
     Ok(Json(ApiResponse::success(item)))
 }
@@ -699,12 +1091,7 @@ async fn handle_form_submit(
     let context = extract_validation_context(&headers, &addr, None, None);
     
     let validation_result = form.validate_with_context(&context);
-    if !validation_result.is_valid {
-        return Err(AppError::Validation(format!(
-            "Form validation failed: {}",
-            serde_json::to_string(&validation_result.errors).unwrap_or_default()
-        )));
-    }
+    validation_result.ensure_valid("Form validation failed")?;
     
     let item_name = format!("Form submission from {}", form.name);
     let metadata = serde_json::json!({
@@ -714,22 +1101,19 @@ async fn handle_form_submit(
         "submitted_at": chrono::Utc::now().to_rfc3339()
     });
     
-    let item = state.item_service.create_item(
-        item_name,
-        Some(format!("Submitted by {} ({})", form.name, form.email)),
-        vec!["form-submission".to_string()],
-        Some(metadata)
-    ).await?;
-
-    if let Some(cache_manager) = &state.cache_manager {
-        cache_manager.invalidate_items_cache();
-        cache_manager.invalidate_search_cache();
-    }
-
-    if let Some(ws_manager) = &state.websocket_manager {
-        let event = crate::websocket::WebSocketEvent::ItemCreated(item.clone());
-        ws_manager.broadcast(event).await;
-    }
+    // Everything the submission writes is kept or undone together, and
+    // only published once kept.
+    let item = state.item_service.transaction(async {
+        let item = state.item_service.create_item(
+            item_name,
+            Some(format!("Submitted by {} ({})", form.name, form.email)),
+            vec!["form-submission".to_string()],
+            Some(metadata)
+        ).await?;
+
+        state.events.publish(DomainEvent::ItemCreated(item.clone())).await;
+        Ok(item)
+    }).await?;
 
     Ok(Json(ApiResponse::success(serde_json::json!({
         "message": "Form submitted successfully",
@@ -747,9 +1131,6 @@ async fn handle_head(State(state): State<AppState>) -> impl IntoResponse {
     headers.insert("X-Custom-Header", "HEAD-Response".parse().unwrap());
     headers.insert("X-Total-Items", item_count.to_string().parse().unwrap());
     headers.insert("X-Api-Version", state.version.parse().unwrap());
-    headers.insert("X-Data-Source", 
-        if state.item_service.is_using_database() { "database" } else { "memory" }
-            .parse().unwrap());
     
     (StatusCode::OK, headers)
 }
@@ -1511,15 +1892,10 @@ async fn handle_websocket_test() -> impl IntoResponse {
                 const wsUrl = this.elements.wsUrl.value;
                 const token = this.elements.authToken.value;
                 
-                let fullUrl = wsUrl;
-                if (token) {
-                    fullUrl += `?token=${encodeURIComponent(token)}`;
-                }
-                
-                this.addMessage(`Connecting to ${fullUrl}...`, 'system');
+                this.addMessage(`Connecting to ${wsUrl}...`, 'system');
                 
                 try {
-                    this.ws = new WebSocket(fullUrl);
+                    this.ws = token ? new WebSocket(wsUrl, ['bearer', token]) : new WebSocket(wsUrl);
                     
                     this.ws.onopen = (event) => {
                     this.connectTime = Date.now();
@@ -1599,6 +1975,18 @@ async fn handle_websocket_test() -> impl IntoResponse {
                     messageContent = `🗑️ Item Deleted: ID ${data.data?.id}`;
                     break;
                     
+                    case 'ItemsCreated':
+                    messageContent = `📦 ${data.data?.count} Items Created`;
+                    break;
+                    
+                    case 'ItemsUpdated':
+                    messageContent = `📝 ${data.data?.count} Items Updated`;
+                    break;
+                    
+                    case 'ItemsDeleted':
+                    messageContent = `🗑️ ${data.data?.count} Items Deleted`;
+                    break;
+                    
                     case 'MetricsUpdate':
                     messageContent = `📊 Metrics Update: ${data.data?.total_requests} total requests`;
                     break;
@@ -2541,7 +2929,7 @@ async fn handle_dashboard() -> impl IntoResponse {
             
             <div class="analytics-panel">
                 <h3 style="margin-bottom: 20px; color: var(--text-primary);">
-                    <i class="fas fa-brain"></i> AI Insights & Predictions
+                    <i class="fas fa-lightbulb"></i> Insights
                 </h3>
                 <div id="insightsContainer">
                 </div>
@@ -2557,11 +2945,11 @@ async fn handle_dashboard() -> impl IntoResponse {
                 <div class="performance-heatmap" id="performanceHeatmap">
                 </div>
                 <div style="display: flex; justify-content: space-between; margin-top: 12px; font-size: 0.75rem; color: var(--text-secondary);">
-                    <span>00:00</span>
-                    <span>06:00</span>
-                    <span>12:00</span>
-                    <span>18:00</span>
-                    <span>24:00</span>
+                    <span>24h ago</span>
+                    <span>18h ago</span>
+                    <span>12h ago</span>
+                    <span>6h ago</span>
+                    <span>Now</span>
                 </div>
             </div>
             
@@ -2777,24 +3165,49 @@ async fn handle_dashboard() -> impl IntoResponse {
             });
         }
         
-        function initSystemMap() {
+        const systemMapIcons = {
+            api: '🌐',
+            database: '🗄️',
+            cache: '⚡',
+            auth: '🔐',
+            files: '📁',
+            jobs: '⚙️',
+            websocket: '🔌',
+            search: '🔍'
+        };
+        
+        function initSystemMap(nodes = []) {
             const mapContainer = document.querySelector('#systemMap > div');
-            const nodes = [
-                { id: 'api', label: '🌐', x: 50, y: 50, status: 'healthy' },
-                { id: 'db', label: '🗄️', x: 200, y: 50, status: 'healthy' },
-                { id: 'cache', label: '⚡', x: 350, y: 50, status: 'healthy' },
-                { id: 'auth', label: '🔐', x: 125, y: 150, status: 'healthy' },
-                { id: 'files', label: '📁', x: 275, y: 150, status: 'healthy' }
-            ];
+            const statusOf = node => {
+                return { Healthy: 'healthy', Degraded: 'warning', Unhealthy: 'error' }[node.status] || 'unknown';
+            };
+            const perRow = 4;
             
-            mapContainer.innerHTML = nodes.map(node => `
+            mapContainer.innerHTML = nodes.map((node, index) => {
+                const status = statusOf(node);
+                const x = 50 + (index % perRow) * 150;
+                const y = 30 + Math.floor(index / perRow) * 100;
+                const dependsOn = node.depends_on.length ? ` (depends on ${node.depends_on.join(', ')})` : '';
+                return `
                 <div class="system-node" 
-                     style="left: ${node.x}px; top: ${node.y}px; background: ${getNodeColor(node.status)};"
-                     title="${node.id.toUpperCase()} - ${node.status}"
+                     style="left: ${x}px; top: ${y}px; background: ${getNodeColor(status)};"
+                     title="${node.name} - ${status}${dependsOn}"
                      data-node="${node.id}">
-                    ${node.label}
+                    ${systemMapIcons[node.id] || '❔'}
                 </div>
-            `).join('');
+            `;
+            }).join('');
+        }
+        
+        async function updateSystemMap() {
+            try {
+                const response = await fetch('/api/system/topology');
+                if (!response.ok) return;
+                const result = await response.json();
+                initSystemMap(result.data.nodes);
+            } catch (error) {
+                console.warn('Failed to load system topology:', error);
+            }
         }
         
         function getNodeColor(status) {
@@ -2806,25 +3219,32 @@ async fn handle_dashboard() -> impl IntoResponse {
             }
         }
         
-        function initPerformanceHeatmap() {
-            const heatmapContainer = document.getElementById('performanceHeatmap');
-            const hours = 24;
-            const cells = [];
-            
-            for (let i = 0; i < hours; i++) {
-                const intensity = Math.random();
-                const color = getHeatmapColor(intensity);
-                cells.push(`
+        async function updatePerformanceHeatmap() {
+            try {
+                const response = await fetch('/api/metrics/heatmap');
+                if (!response.ok) return;
+                const result = await response.json();
+                const { hours, peak_requests: peak } = result.data;
+                const heatmapContainer = document.getElementById('performanceHeatmap');
+                
+                heatmapContainer.innerHTML = hours.map(hour => {
+                    const intensity = peak > 0 ? hour.requests / peak : 0;
+                    const errorRate = hour.requests > 0 ? hour.errors / hour.requests : 0;
+                    // Hours with many server errors are shown hot whatever their volume.
+                    const color = getHeatmapColor(errorRate > 0.05 ? 1 : intensity);
+                    const label = new Date(hour.hour).toLocaleTimeString([], { hour: '2-digit', minute: '2-digit' });
+                    return `
                     <div class="heatmap-cell" 
                          style="background: ${color};" 
-                         title="Hour ${i}:00 - ${(intensity * 100).toFixed(1)}% load"
-                         data-hour="${i}"
+                         title="${label} - ${hour.requests.toLocaleString()} requests, ${hour.errors} errors, ${hour.average_ms.toFixed(0)}ms avg"
+                         data-hour="${hour.hour}"
                          data-intensity="${intensity}">
                     </div>
-                `);
+                `;
+                }).join('');
+            } catch (error) {
+                console.warn('Failed to load traffic heatmap:', error);
             }
-            
-            heatmapContainer.innerHTML = cells.join('');
         }
         
         function getHeatmapColor(intensity) {
@@ -2839,40 +3259,27 @@ async fn handle_dashboard() -> impl IntoResponse {
             return colors[2];
         }
         
-        function generateInsights(metrics) {
-            const insights = [];
-            
-            if (metrics.error_rate > 5) {
-                insights.push({
-                    type: 'warning',
-                    title: 'High Error Rate Detected',
-                    message: `Error rate is ${metrics.error_rate.toFixed(1)}%. Consider investigating recent deployments.`
-                });
-            }
-            
-            if (metrics.average_response_time_ms > 1000) {
-                insights.push({
-                    type: 'performance',
-                    title: 'Response Time Alert',
-                    message: `Average response time is ${metrics.average_response_time_ms.toFixed(0)}ms. Database optimization recommended.`
-                });
-            }
-            
-            if (metrics.requests_per_second > 10) {
-                insights.push({
-                    type: 'success',
-                    title: 'High Traffic Volume',
-                    message: `Handling ${metrics.requests_per_second.toFixed(1)} req/s. System performing well under load.`
-                });
+        async function updateInsights() {
+            try {
+                const response = await fetch('/api/system/alerts');
+                if (!response.ok) return;
+                const result = await response.json();
+                const insights = result.data.insights.concat(result.data.alerts.map(alert => ({
+                    severity: 'warning',
+                    title: 'Resource Alert',
+                    message: alert
+                })));
+                
+                const container = document.getElementById('insightsContainer');
+                container.innerHTML = insights.map(insight => `
+                    <div class="insight-card">
+                        <h4 style="margin: 0 0 8px 0; color: var(--text-primary);">${insight.title}</h4>
+                        <p style="margin: 0; color: var(--text-secondary); font-size: 0.875rem;">${insight.message}</p>
+                    </div>
+                `).join('') || '<p style="color: var(--text-secondary); font-style: italic;">All systems operating normally. No insights to display.</p>';
+            } catch (error) {
+                console.warn('Failed to load insights:', error);
             }
-            
-            const container = document.getElementById('insightsContainer');
-            container.innerHTML = insights.map(insight => `
-                <div class="insight-card">
-                    <h4 style="margin: 0 0 8px 0; color: var(--text-primary);">${insight.title}</h4>
-                    <p style="margin: 0; color: var(--text-secondary); font-size: 0.875rem;">${insight.message}</p>
-                </div>
-            `).join('') || '<p style="color: var(--text-secondary); font-style: italic;">All systems operating normally. No insights to display.</p>';
         }
         
         function animateRequestFlow() {
@@ -2930,8 +3337,6 @@ async fn handle_dashboard() -> impl IntoResponse {
                 window.currentMetrics = metrics;
                 window.prevMetrics = metrics;
                 
-                generateInsights(metrics);
-                
                 if (metrics.error_rate > 5 && (!prevMetrics.error_rate || prevMetrics.error_rate <= 5)) {
                     addAlert('error', 'High Error Rate', `Error rate spiked to ${metrics.error_rate.toFixed(1)}%`);
                 }
@@ -3008,12 +3413,10 @@ async fn handle_dashboard() -> impl IntoResponse {
                     </div>
                 `;
                 
-                const timeLabels = metrics.last_hour_response_times
-                    .slice(-20)
-                    .map(rt => new Date(rt.timestamp).toLocaleTimeString());
-                const timeData = metrics.last_hour_response_times
-                    .slice(-20)
-                    .map(rt => rt.duration_ms);
+                const timeLabels = metrics.response_time_series
+                    .map(point => new Date(point.timestamp).toLocaleTimeString());
+                const timeData = metrics.response_time_series
+                    .map(point => point.average_ms);
                 
                 responseTimeChart.data.labels = timeLabels;
                 responseTimeChart.data.datasets[0].data = timeData;
@@ -3172,7 +3575,12 @@ async fn handle_dashboard() -> impl IntoResponse {
         
         initTheme();
         initSystemMap();
-        initPerformanceHeatmap();
+        updateSystemMap();
+        setInterval(updateSystemMap, 15000);
+        updatePerformanceHeatmap();
+        setInterval(updatePerformanceHeatmap, 60000);
+        updateInsights();
+        setInterval(updateInsights, 15000);
         updateDashboard();
         
         setInterval(animateRequestFlow, 1000);
@@ -3216,7 +3624,7 @@ async fn handle_export_items(
     State(state): State<AppState>,
     headers: HeaderMap,
     connect_info: Option<axum::extract::ConnectInfo<std::net::SocketAddr>>,
-    Query(params): Query<ItemExportQuery>,
+    StrictQuery(params): StrictQuery<ItemExportQuery>,
 ) -> Result<impl IntoResponse> {
 
     let addr = connect_info.map(|ci| ci.0).unwrap_or_else(|| {
@@ -3225,134 +3633,276 @@ async fn handle_export_items(
     let context = extract_validation_context(&headers, &addr, None, None);
     
     let validation_result = params.validate_with_context(&context);
-    if !validation_result.is_valid {
-        return Err(AppError::Validation(format!(
-            "Export query validation failed: {}",
-            serde_json::to_string(&validation_result.errors).unwrap_or_default()
-        )));
-    }
+    validation_result.ensure_valid("Export query validation failed")?;
     
-    let format = params.format.as_deref().unwrap_or("json");
-    let items = state.item_service.get_items(None, None).await?;
-    
-    match format {
-        "csv" => {
-            let mut csv = String::from("id,name,description,tags,created_at,updated_at\n");
-            for item in items {
-                csv.push_str(&format!(
-                    "{},{},\"{}\",\"{}\",{},{}\n",
-                    item.id,
-                    item.name,
-                    item.description.unwrap_or_default().replace("\"", "\"\""),
-                    item.tags.join(";"),
-                    item.created_at.to_rfc3339(),
-                    item.updated_at.to_rfc3339()
-                ));
-            }
-            Ok((
-                StatusCode::OK,
-                [
-                    ("Content-Type", "text/csv"),
-                    ("Content-Disposition", "attachment; filename=\"items_export.csv\"")
-                ],
-                csv,
-            ).into_response())
-        },
-        "yaml" => {
-            let yaml = serde_yaml::to_string(&items)
-                .map_err(|e| AppError::Other(anyhow::anyhow!("Failed to serialize to YAML: {}", e)))?;
-            Ok((
-                StatusCode::OK,
-                [
-                    ("Content-Type", "text/yaml"),
-                    ("Content-Disposition", "attachment; filename=\"items_export.yaml\"")
-                ],
-                yaml,
-            ).into_response())
-        },
-        _ => {
-            let json = serde_json::to_string_pretty(&items)?;
-            Ok((
-                StatusCode::OK,
-                [
-                    ("Content-Type", "application/json"),
-                    ("Content-Disposition", "attachment; filename=\"items_export.json\"")
-                ],
-                json,
-            ).into_response())
+    let format = ExportFormat::parse(params.format.as_deref().unwrap_or("json"));
+    format.ensure_supported()?;
+    // One more than the cap, to tell whether the export would be cut short.
+    let cap = state.pagination_config.max_export_items as usize;
+    let mut items = state.item_service.get_items(Some(cap + 1), None).await?;
+    if items.len() > cap {
+        if !state.pagination_config.clamp_oversized {
+            return Err(AppError::BadRequest(format!(
+                "Exports are limited to {} items; use POST /api/items/search/export for more",
+                cap
+            )));
         }
+        items.truncate(cap);
     }
+    let parameters = serde_json::json!({ "format": format, "max_items": cap });
+    export_response(format, "items_export", &items, params.safe_csv.unwrap_or(true), &parameters)
 }
 
-fn create_auth_routes_with_middleware() -> Router<AppState> {
-    use crate::handlers::auth::{
-        register_user, login_user, refresh_token, logout_user, 
-        get_current_user, get_user_by_id
-    };
-    use axum::routing::{get, post};
-    use axum::middleware;
-
-    let protected_routes = Router::new()
-        .route("/me", get(get_current_user))
-        .route("/users/:id", get(get_user_by_id))
-        .layer(middleware::from_fn_with_state(
-            crate::AppState::default(),
-            crate::middleware::auth::jwt_auth_middleware,
-        ));
-
-    Router::new()
-        .route("/register", post(register_user))
-        .route("/login", post(login_user))
-        .route("/refresh", post(refresh_token))
-        .route("/logout", post(logout_user))
-        .merge(protected_routes)
+/// `items` rendered as `format`, as an attachment named `name`. Workbooks
+/// list `parameters` on a sheet of their own.
+fn export_response(
+    format: ExportFormat,
+    name: &str,
+    items: &[Item],
+    safe_csv: bool,
+    parameters: &serde_json::Value,
+) -> Result<Response> {
+    let disposition = format!("attachment; filename=\"{}.{}\"", name, format.extension());
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        format.render_bytes(items, safe_csv, parameters)?,
+    ).into_response())
 }
 
-fn create_file_routes() -> Router<AppState> {
-    use axum::routing::{delete, get, post};
+/// Item changes after `since`, oldest first. Answers 410 when some of them
+/// have already been dropped from the change log.
+async fn handle_item_changes(
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+    Query(params): Query<ChangesQuery>,
+) -> Result<(HeaderMap, Json<ChangePage>)> {
+    let limit = params.limit.unwrap_or(DEFAULT_CHANGES_LIMIT).clamp(1, MAX_CHANGES_LIMIT);
+    let mut page = state.item_service.changes_since(params.since.unwrap_or(0), limit).await?;
+    page.changes.iter_mut().filter_map(|change| change.item.as_mut()).for_each(item_secrets::redact);
+    let links = PageLinks::cursor("since", page.has_more.then(|| page.next_since.to_string()), limit as u64);
+    Ok((links.headers(&uri), Json(page)))
+}
+
+/// Typeahead suggestions for item names or tags starting with `q`.
+async fn handle_suggest(
+    State(state): State<AppState>,
+    Query(query): Query<SuggestQuery>,
+) -> Result<impl IntoResponse> {
+    info!("GET /api/items/suggest - field: {:?}, q: {}", query.field, query.q);
+
+    let suggestions = state.suggester().suggest(&query).await?;
 
-    Router::new()
-        .route("/upload", post(files::upload_file))
-        .route("/:id/serve", get(files::serve_file))
-        .route("/:id/info", get(files::get_file_info))
-        .route("/:id/download", get(files::download_file))
-        .route("/:id", delete(files::delete_file))
-        .route("/:id/associate", post(files::associate_file_with_item))
-        .route("/", get(files::list_files))
-        .route("/item/:id", get(files::get_item_files))
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "field": query.field,
+        "query": query.q,
+        "suggestions": suggestions,
+    }))))
 }
 
-fn create_job_routes() -> Router<AppState> {
-    use crate::handlers::jobs;
+/// Items resembling item `id`, most similar first.
+async fn handle_similar_items(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Query(query): Query<SimilarityQuery>,
+) -> Result<impl IntoResponse> {
+    info!("GET /api/items/{}/similar", id);
+
+    if id == 0 {
+        return Err(AppError::BadRequest("Invalid item ID".to_string()));
+    }
+
+    let item = state.item_service.get_item(id).await?;
+    let candidates = state
+        .duplicate_detector()
+        .find(&DuplicateCandidate::from(&item), Some(id), &query)
+        .await?;
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "item_id": id,
+        "total": candidates.len(),
+        "candidates": candidates,
+    }))))
+}
+
+/// Existing items that an item with the posted name, description and tags
+/// would duplicate.
+async fn handle_check_duplicate(
+    State(state): State<AppState>,
+    Query(query): Query<SimilarityQuery>,
+    payload: crate::extractors::UnicodeJson<DuplicateCandidate>,
+) -> Result<impl IntoResponse> {
+    let crate::extractors::UnicodeJson(candidate) = payload;
+    info!("POST /api/items/check-duplicate - name: {}", candidate.name);
+
+    let candidates = state.duplicate_detector().find(&candidate, None, &query).await?;
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "duplicate": !candidates.is_empty(),
+        "total": candidates.len(),
+        "candidates": candidates,
+    }))))
+}
+
+fn create_file_routes() -> GuardedRouter {
     use axum::routing::{delete, get, post};
+    use Guard::{Authenticated, Public};
+
+    GuardedRouter::new()
+        .route(Method::POST, "/upload", Public, post(files::upload_file))
+        .route(Method::GET, "/:id/serve", Public, get(files::serve_file))
+        .route(Method::GET, "/:id/info", Public, get(files::get_file_info))
+        .route(Method::GET, "/:id/download", Public, get(files::download_file))
+        .route(Method::DELETE, "/:id", Public, delete(files::delete_file))
+        .route(Method::POST, "/:id/associate", Public, post(files::associate_file_with_item))
+        .route(Method::POST, "/:id/publish", Authenticated, post(files::publish_file))
+        .route(Method::DELETE, "/:id/publish", Authenticated, delete(files::unpublish_file))
+        .route(Method::GET, "/", Public, get(files::list_files))
+        .route(Method::GET, "/item/:id", Public, get(files::get_item_files))
+}
 
-    Router::new()
-        .route("/", post(jobs::submit_job).get(jobs::list_jobs))
-        .route("/stats", get(jobs::get_queue_stats))
-        .route("/cleanup", post(jobs::cleanup_jobs))
-        .route("/bulk-import", post(jobs::submit_bulk_import))
-        .route("/bulk-export", post(jobs::submit_bulk_export))
-        .route("/:id", get(jobs::get_job))
-        .route("/:id/status", get(jobs::get_job_status))
-        .route("/:id/cancel", delete(jobs::cancel_job))
-        .route("/:id/retry", post(jobs::retry_job))
+fn create_job_routes() -> GuardedRouter {
+    use crate::handlers::jobs;
+    use axum::routing::{delete, get, post};
+    use Guard::Public;
+
+    GuardedRouter::new()
+        .route(Method::POST, "/", Public, post(jobs::submit_job))
+        .route(Method::GET, "/", Public, get(jobs::list_jobs))
+        .route(Method::GET, "/stats", Public, get(jobs::get_queue_stats))
+        .route(Method::POST, "/cleanup", Public, post(jobs::cleanup_jobs))
+        .route(Method::POST, "/bulk-import", Public, post(jobs::submit_bulk_import))
+        .route(Method::POST, "/bulk-export", Public, post(jobs::submit_bulk_export))
+        .route(Method::GET, "/:id", Public, get(jobs::get_job))
+        .route(Method::GET, "/:id/status", Public, get(jobs::get_job_status))
+        .route(Method::GET, "/:id/result", Public, get(jobs::get_job_result))
+        .route(Method::GET, "/:id/attempts", Public, get(jobs::get_job_attempts))
+        .route(Method::DELETE, "/:id/cancel", Public, delete(jobs::cancel_job))
+        .route(Method::POST, "/:id/retry", Public, post(jobs::retry_job))
 }
 
-fn create_cache_routes() -> Router<AppState> {
+fn create_cache_routes() -> GuardedRouter {
     use crate::handlers::cache;
-    
-    Router::new()
-        .route("/stats", get(cache::get_cache_stats))
-        .route("/health", get(cache::get_cache_health))
-        .route("/clear", axum::routing::post(cache::clear_cache))
-        .route("/invalidate", axum::routing::post(cache::invalidate_cache_pattern))
+    use axum::routing::post;
+
+    GuardedRouter::new()
+        .route(Method::GET, "/stats", Guard::Public, get(cache::get_cache_stats))
+        .route(Method::GET, "/health", Guard::Public, get(cache::get_cache_health))
+        .route(Method::POST, "/clear", Guard::Admin, post(cache::clear_cache))
+        .route(Method::POST, "/invalidate", Guard::Admin, post(cache::invalidate_cache_pattern))
+}
+
+fn create_webhook_routes() -> GuardedRouter {
+    use crate::handlers::webhooks;
+    use axum::routing::{delete, post};
+    use Guard::Admin;
+
+    GuardedRouter::new()
+        .route(Method::POST, "/", Admin, post(webhooks::create_webhook))
+        .route(Method::GET, "/", Admin, get(webhooks::list_webhooks))
+        .route(Method::GET, "/:id", Admin, get(webhooks::get_webhook))
+        .route(Method::DELETE, "/:id", Admin, delete(webhooks::delete_webhook))
+        .route(Method::GET, "/:id/deliveries", Admin, get(webhooks::list_deliveries))
+}
+
+fn create_websocket_routes() -> GuardedRouter {
+    use crate::handlers::websocket;
+    use axum::routing::delete;
+    use Guard::Admin;
+
+    GuardedRouter::new()
+        .route(Method::GET, "/connections", Admin, get(websocket::list_connections))
+        .route(Method::DELETE, "/connections/:id", Admin, delete(websocket::disconnect_connection))
+}
+
+/// Listing is open like the item routes; renames and merges rewrite every
+/// item and are limited to admins.
+fn create_tag_routes() -> GuardedRouter {
+    use crate::handlers::tags;
+    use axum::routing::post;
+
+    GuardedRouter::new()
+        .route(Method::GET, "/", Guard::Public, get(tags::list_tags))
+        .route(Method::POST, "/rename", Guard::Admin, post(tags::rename_tag))
+        .route(Method::POST, "/merge", Guard::Admin, post(tags::merge_tags))
+}
+
+/// Reading is open like the item routes; writing needs a signed-in user,
+/// and changing a comment its author or an admin.
+fn create_comment_routes() -> GuardedRouter {
+    use crate::handlers::comments;
+    use axum::routing::{delete, patch, post};
+
+    GuardedRouter::new()
+        .route(Method::GET, "/", Guard::Public, get(comments::list_comments))
+        .route(Method::POST, "/", Guard::Authenticated, post(comments::create_comment))
+        .route(Method::PATCH, "/:cid", Guard::Authenticated, patch(comments::update_comment))
+        .route(Method::DELETE, "/:cid", Guard::Authenticated, delete(comments::delete_comment))
+}
+
+fn create_trash_routes() -> GuardedRouter {
+    use crate::handlers::trash;
+    use axum::routing::post;
+    use Guard::Admin;
+
+    GuardedRouter::new()
+        .route(Method::GET, "/", Admin, get(trash::list_trash))
+        .route(Method::POST, "/:id/restore", Admin, post(trash::restore_item))
+        .route(Method::POST, "/purge", Admin, post(trash::purge_trash))
+}
+
+/// How recently an admin must have entered their password to change
+/// another account's role or status, or delete it.
+const USER_ADMIN_AUTH_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(300);
+
+fn create_admin_routes() -> GuardedRouter {
+    use crate::handlers::admin;
+    use crate::middleware::auth::require_recent_auth;
+    use axum::routing::{delete, patch, post};
+    use Guard::Admin;
+
+    let recent_auth = || axum::middleware::from_fn(require_recent_auth(USER_ADMIN_AUTH_MAX_AGE));
+
+    GuardedRouter::new()
+        .route(Method::GET, "/routes", Admin, get(admin::list_routes))
+        .route(Method::GET, "/features", Admin, get(admin::list_features))
+        .route(Method::POST, "/features/:name", Admin, post(admin::set_feature))
+        .route(Method::GET, "/startup-report", Admin, get(admin::get_startup_report))
+        .route(Method::GET, "/events", Admin, get(admin::list_event_subscribers))
+        .route(Method::GET, "/security/blocks", Admin, get(admin::list_security_blocks))
+        .route(Method::DELETE, "/security/blocks/:ip", Admin, delete(admin::unblock_client))
+        .route(Method::GET, "/users", Admin, get(admin::list_users))
+        .route(Method::POST, "/users/export", Admin, post(admin::export_users))
+        .route(Method::POST, "/users/import", Admin, post(admin::import_users))
+        .route(Method::POST, "/users", Admin, post(admin::create_user))
+        .route(Method::PATCH, "/users/:id", Admin, patch(admin::update_user_access).route_layer(recent_auth()))
+        .route(Method::DELETE, "/users/:id", Admin, delete(admin::delete_user).route_layer(recent_auth()))
+        .route(Method::POST, "/export", Admin, post(admin::export_snapshot))
+        .route(Method::POST, "/import", Admin, post(admin::import_snapshot))
+        .route(Method::POST, "/seed", Admin, post(admin::seed))
+        .route(Method::POST, "/files/reconcile", Admin, post(files::reconcile_files))
+        .route(Method::POST, "/files/reindex", Admin, post(files::reindex_files))
+        .route(Method::POST, "/items/schema/validate", Admin, post(crate::handlers::item_schema::validate_items))
+        .route(Method::POST, "/items/names/validate", Admin, post(crate::handlers::item_names::validate_names))
+        .route(Method::POST, "/integrity/check", Admin, post(crate::handlers::integrity::check_integrity))
+        .route(Method::POST, "/items/secrets/rekey", Admin, post(crate::handlers::item_secrets::rekey_items))
+        .route(Method::POST, "/items/metadata/transform", Admin, post(crate::handlers::item_transform::transform_metadata))
+        .route(Method::GET, "/captures", Admin, get(admin::get_captures))
+        .route(Method::POST, "/captures", Admin, post(admin::start_capture))
+        .route(Method::DELETE, "/captures", Admin, delete(admin::stop_capture))
 }
 
 async fn handle_get_items_v2(
     State(state): State<AppState>,
     headers: HeaderMap,
     connect_info: Option<axum::extract::ConnectInfo<std::net::SocketAddr>>,
-    Query(mut params): Query<ItemListQuery>
+    auth_user: Option<axum::Extension<AuthUser>>,
+    defaults: Option<axum::Extension<ListDefaults>>,
+    OriginalUri(uri): OriginalUri,
+    StrictQuery(mut params): StrictQuery<ItemListQuery>
 ) -> Result<impl IntoResponse> {
     info!("GET /api/v2/items - enhanced version");
     
@@ -3362,35 +3912,40 @@ async fn handle_get_items_v2(
     let context = extract_validation_context(&headers, &addr, None, None);
     
     let validation_result = params.validate_with_context(&context);
-    if !validation_result.is_valid {
-        return Err(AppError::Validation(format!(
-            "Query validation failed: {}",
-            serde_json::to_string(&validation_result.errors).unwrap_or_default()
-        )));
-    }
+    validation_result.ensure_valid("Query validation failed")?;
+
+    let defaults = defaults.map(|axum::Extension(defaults)| defaults).unwrap_or_default();
+    params.include = defaults.include(params.include.take());
     
-    let page_size = params.page_size.unwrap_or(50) as usize;
+    let page_size = state.pagination_config.page_size("page_size", defaults.page_size(params.page_size.map(u64::from)))? as usize;
     let page = params.page.unwrap_or(1);
     let offset = ((page - 1) * page_size as u32) as usize;
     
-    let items = state.item_service.get_items(Some(page_size), Some(offset)).await?;
+    let sort = defaults.sort(params.sort_spec()?);
+    let mut items = state.item_service.list_items(Some(page_size + 1), Some(offset), sort.as_ref()).await?;
+    let links = PageLinks::numbered(page as u64, page_size as u64).with_more(items.len() > page_size);
+    items.truncate(page_size);
+    state.item_secrets.present_all(&mut items, auth_user.as_ref().map(|axum::Extension(user)| user))?;
+    let entries = item_list_entries(&state, &items, &params).await?;
     
-    Ok(Json(ApiResponse::success(serde_json::json!({
-        "items": items,
+    let mut body = serde_json::json!({
+        "items": entries,
         "count": items.len(),
         "page_size": page_size,
         "page": page,
         "offset": offset,
         "api_version": "2.0",
         "enhanced_features": true,
-        "source": if state.item_service.is_using_database() { "database" } else { "memory" },
         "include_files": params.include_files.unwrap_or(false)
-    }))))
+    });
+    with_legacy_source(&state, &mut body);
+    Ok((links.headers(&uri), Json(ApiResponse::success(body))))
 }
 
 async fn handle_get_item_v2(
     State(state): State<AppState>,
-    Path(id): Path<u64>
+    Path(id): Path<u64>,
+    auth_user: Option<axum::Extension<AuthUser>>,
 ) -> Result<impl IntoResponse> {
     info!("GET /api/v2/items/{} - enhanced version", id);
     
@@ -3398,56 +3953,49 @@ async fn handle_get_item_v2(
         return Err(AppError::BadRequest("Invalid item ID".to_string()));
     }
 
-    let item = state.item_service.get_item(id).await?;
+    let mut item = state.item_service.get_item(id).await?;
+    state.item_secrets.present(&mut item, auth_user.as_ref().map(|axum::Extension(user)| user))?;
     
-    Ok(Json(ApiResponse::success(serde_json::json!({
+    let mut body = serde_json::json!({
         "item": item,
         "api_version": "2.0",
         "enhanced_features": true,
         "metadata": {
             "retrieved_at": chrono::Utc::now().to_rfc3339(),
-            "source": if state.item_service.is_using_database() { "database" } else { "memory" }
         }
-    }))))
+    });
+    with_legacy_source(&state, &mut body["metadata"]);
+    Ok(Json(ApiResponse::success(body)))
 }
 
 async fn handle_post_item_v2(
     State(state): State<AppState>,
     headers: HeaderMap,
     connect_info: Option<axum::extract::ConnectInfo<std::net::SocketAddr>>,
-    Json(payload): Json<CreateItemRequest>
+    auth_user: Option<axum::Extension<AuthUser>>,
+    Json(mut payload): Json<CreateItemRequest>
 ) -> Result<impl IntoResponse> {
     info!("POST /api/v2/items - enhanced version - name: {}", payload.name);
     
     let addr = connect_info.map(|ci| ci.0).unwrap_or_else(|| {
         std::net::SocketAddr::from(([127, 0, 0, 1], 8080))
     });
-    let context = extract_validation_context(&headers, &addr, None, None);
+    let context = extract_validation_context(&headers, &addr, None, None)
+        .with_validation_config(state.validation_config.clone());
     
+    payload.sanitize_with_context(&context);
     let validation_result = payload.validate_with_context(&context);
-    if !validation_result.is_valid {
-        return Err(AppError::Validation(format!(
-            "Validation failed: {}",
-            serde_json::to_string(&validation_result.errors).unwrap_or_default()
-        )));
-    }
+    validation_result.ensure_valid("Validation failed")?;
 
-    let item = state.item_service.create_item(
+    let mut item = state.item_service.create_item(
         payload.name,
         payload.description,
         payload.tags.unwrap_or_default(),
         payload.metadata
     ).await?;
 
-    if let Some(cache_manager) = &state.cache_manager {
-        cache_manager.invalidate_items_cache();
-        cache_manager.invalidate_search_cache();
-    }
-
-    if let Some(ws_manager) = &state.websocket_manager {
-        let event = crate::websocket::WebSocketEvent::ItemCreated(item.clone());
-        ws_manager.broadcast(event).await;
-    }
+    state.events.publish(DomainEvent::ItemCreated(item.clone())).await;
+    state.item_secrets.present(&mut item, auth_user.as_ref().map(|axum::Extension(user)| user))?;
 
     Ok((StatusCode::CREATED, Json(ApiResponse::success(serde_json::json!({
         "item": item,
@@ -3462,7 +4010,8 @@ async fn handle_put_item_v2(
     Path(id): Path<u64>,
     headers: HeaderMap,
     connect_info: Option<axum::extract::ConnectInfo<std::net::SocketAddr>>,
-    Json(payload): Json<CreateItemRequest>,
+    auth_user: Option<axum::Extension<AuthUser>>,
+    Json(mut payload): Json<CreateItemRequest>,
 ) -> Result<impl IntoResponse> {
     info!("PUT /api/v2/items/{} - enhanced version - name: {}", id, payload.name);
     
@@ -3473,34 +4022,25 @@ async fn handle_put_item_v2(
     let addr = connect_info.map(|ci| ci.0).unwrap_or_else(|| {
         std::net::SocketAddr::from(([127, 0, 0, 1], 8080))
     });
-    let context = extract_validation_context(&headers, &addr, None, None);
+    let context = extract_validation_context(&headers, &addr, None, None)
+        .with_validation_config(state.validation_config.clone());
     
+    payload.sanitize_with_context(&context);
     let validation_result = payload.validate_with_context(&context);
-    if !validation_result.is_valid {
-        return Err(AppError::Validation(format!(
-            "Validation failed: {}",
-            serde_json::to_string(&validation_result.errors).unwrap_or_default()
-        )));
-    }
+    validation_result.ensure_valid("Validation failed")?;
 
-    let item = state.item_service.update_item(
+    let expected_version = expected_version(&headers, payload.version)?;
+    let mut item = state.item_service.update_item(
         id,
         payload.name,
         payload.description,
         payload.tags.unwrap_or_default(),
-        payload.metadata
+        payload.metadata,
+        expected_version,
     ).await?;
 
-    if let Some(cache_manager) = &state.cache_manager {
-        cache_manager.invalidate_item_cache(id);
-        cache_manager.invalidate_items_cache();
-        cache_manager.invalidate_search_cache();
-    }
-
-    if let Some(ws_manager) = &state.websocket_manager {
-        let event = crate::websocket::WebSocketEvent::ItemUpdated(item.clone());
-        ws_manager.broadcast(event).await;
-    }
+    state.events.publish(DomainEvent::ItemUpdated(item.clone())).await;
+    state.item_secrets.present(&mut item, auth_user.as_ref().map(|axum::Extension(user)| user))?;
 
     Ok(Json(ApiResponse::success(serde_json::json!({
         "item": item,
@@ -3508,4 +4048,513 @@ async fn handle_put_item_v2(
         "enhanced_features": true,
         "updated_at": chrono::Utc::now().to_rfc3339()
     }))))
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::CacheManager;
+    use axum::extract::ConnectInfo;
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    async fn app_with_items(count: usize) -> (Router, Vec<u64>) {
+        let state = AppState::default().with_cache_manager(CacheManager::default());
+        let mut ids = Vec::new();
+        for i in 0..count {
+            let item = state
+                .item_service
+                .create_item(format!("Delete me {}", i), None, Vec::new(), None)
+                .await
+                .unwrap();
+            ids.push(item.id);
+        }
+        let mut config = crate::AppConfig::default();
+        config.rate_limit.enable = false;
+        (crate::create_app_with_config(state, config), ids)
+    }
+
+    fn delete(uri: &str, prefer: Option<&str>) -> Request {
+        let mut builder = Request::delete(uri).header("user-agent", "routes-tests");
+        if let Some(prefer) = prefer {
+            builder = builder.header("prefer", prefer);
+        }
+        let mut request = builder.body(Body::empty()).unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+        request
+    }
+
+    #[test]
+    fn test_prefers_representation() {
+        let mut headers = HeaderMap::new();
+        assert!(!prefers_representation(&headers));
+
+        headers.insert("prefer", "respond-async, return=representation".parse().unwrap());
+        assert!(prefers_representation(&headers));
+
+        headers.insert("prefer", "return=minimal".parse().unwrap());
+        assert!(!prefers_representation(&headers));
+    }
+
+    #[tokio::test]
+    async fn test_delete_item_contract() {
+        for prefix in ["/api/items", "/api/v1/items", "/api/v2/items"] {
+            let (app, ids) = app_with_items(2).await;
+
+            let response = app.clone().oneshot(delete(&format!("{}/{}", prefix, ids[0]), None)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NO_CONTENT, "{}", prefix);
+            assert!(response.headers().get("content-type").is_none(), "{}", prefix);
+            assert_eq!(response.headers()["x-cache"], "BYPASS", "{}", prefix);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert!(body.is_empty(), "{}", prefix);
+
+            let uri = format!("{}/{}", prefix, ids[1]);
+            let response = app.clone().oneshot(delete(&uri, Some("return=representation"))).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", prefix);
+            assert_eq!(response.headers()["preference-applied"], "return=representation");
+            assert_eq!(response.headers()["content-type"], "application/json");
+            assert_eq!(response.headers()["x-cache"], "BYPASS", "{}", prefix);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(json["data"]["deleted_id"], ids[1]);
+
+            let response = app.oneshot(delete(&uri, Some("return=representation"))).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", prefix);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_item_changes_contract() {
+        let retention = crate::config::ChangeFeedConfig {
+            retention_hours: 24,
+            max_entries: 3,
+        };
+        let state = AppState::default()
+            .with_change_feed(&retention)
+            .with_cache_manager(CacheManager::default());
+        for name in ["First", "Second"] {
+            state.item_service.create_item(name.to_string(), None, Vec::new(), None).await.unwrap();
+        }
+        let mut config = crate::AppConfig::default();
+        config.rate_limit.enable = false;
+        let app = crate::create_app_with_config(state, config);
+
+        let get = |uri: &str| {
+            let mut request = Request::get(uri).header("user-agent", "routes-tests").body(Body::empty()).unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+            request
+        };
+        let read = |response: Response| async move {
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, serde_json::from_slice::<serde_json::Value>(&body).unwrap())
+        };
+
+        let (status, page) = read(app.clone().oneshot(get("/api/items/changes?since=0&limit=1")).await.unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(page["changes"][0]["seq"], 1);
+        assert_eq!(page["changes"][0]["op"], "created");
+        assert_eq!(page["changes"][0]["item"]["name"], "First");
+        assert_eq!(page["next_since"], 1);
+        assert_eq!(page["has_more"], true);
+
+        let response = app.clone().oneshot(delete("/api/items/1", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let (status, page) = read(app.clone().oneshot(get("/api/v2/items/changes?since=1")).await.unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(page["changes"].as_array().unwrap().len(), 2);
+        assert_eq!(page["changes"][1]["op"], "deleted");
+        assert_eq!(page["changes"][1]["item_id"], 1);
+        assert!(page["changes"][1]["item"].is_null());
+        assert_eq!(page["next_since"], 3);
+        assert_eq!(page["has_more"], false);
+
+        let response = app.clone().oneshot(delete("/api/items/2", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let (status, body) = read(app.oneshot(get("/api/items/changes?since=0")).await.unwrap()).await;
+        assert_eq!(status, StatusCode::GONE);
+        assert!(body["error"].as_str().unwrap().contains("continue from sequence 4"));
+    }
+
+    #[tokio::test]
+    async fn test_item_version_contract() {
+        let (app, ids) = app_with_items(1).await;
+        let uri = format!("/api/items/{}", ids[0]);
+
+        let send = |method: &str, uri: &str, if_match: Option<&str>, body: serde_json::Value| {
+            let mut builder = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("user-agent", "routes-tests")
+                .header("content-type", "application/json");
+            if let Some(if_match) = if_match {
+                builder = builder.header("if-match", if_match);
+            }
+            let mut request = builder.body(Body::from(body.to_string())).unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, serde_json::from_slice::<serde_json::Value>(&body).unwrap())
+            }
+        };
+
+        let (status, body) = send("PUT", &uri, Some("\"1\""), serde_json::json!({"name": "First editor"})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["version"], 2);
+
+        let (status, body) = send("PUT", &uri, None, serde_json::json!({"name": "Second editor", "version": 1})).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["current_version"], 2);
+        assert_eq!(body["changed_fields"], serde_json::json!(["name"]));
+        assert_eq!(body["current"]["name"], "First editor");
+
+        let (status, body) = send("PATCH", &uri, Some("W/\"2\""), serde_json::json!({"tags": ["merged"]})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["version"], 3);
+        assert_eq!(body["data"]["name"], "First editor");
+
+        let (status, _) = send("PATCH", &uri, None, serde_json::json!({"tags": [], "version": 2})).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (status, _) = send("PATCH", &uri, Some("not-a-version"), serde_json::json!({"tags": []})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = send("PUT", &uri, Some("*"), serde_json::json!({"name": "Anyone"})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["version"], 4);
+
+        let state = AppState::default().with_item_config(&crate::config::ItemConfig { require_version: true, ..Default::default() });
+        let mut config = crate::AppConfig::default();
+        config.rate_limit.enable = false;
+        let strict = crate::create_app_with_config(state, config);
+        let mut request = Request::put("/api/items/1")
+            .header("user-agent", "routes-tests")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"name": "Unversioned"}"#))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+        let response = strict.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_REQUIRED);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_detection() {
+        let app = crate::test_support::test_app().await;
+        app.state
+            .item_service
+            .create_item("Wireless Mouse".to_string(), None, vec!["peripheral".to_string()], None)
+            .await
+            .unwrap();
+        let mut config = crate::AppConfig::default();
+        config.rate_limit.enable = false;
+        let router = crate::create_app_with_config(app.state.clone(), config);
+
+        let send = |method: &str, uri: &str, body: Option<serde_json::Value>| {
+            let mut request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("user-agent", "routes-tests")
+                .header("content-type", "application/json")
+                .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+            let router = router.clone();
+            async move {
+                let response = router.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, serde_json::from_slice::<serde_json::Value>(&body).unwrap())
+            }
+        };
+
+        let (status, body) = send("GET", "/api/items/1/similar", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let candidates = body["data"]["candidates"].as_array().unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0]["item"]["id"], 2);
+        assert_eq!(candidates[0]["matched_fields"], serde_json::json!(["name", "tags"]));
+
+        let candidate = serde_json::json!({"name": "wireless  mouse", "tags": ["Peripheral"]});
+        let (status, body) = send("POST", "/api/v1/items/check-duplicate", Some(candidate)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["duplicate"], true);
+        assert_eq!(body["data"]["candidates"][0]["item"]["name"], "Wireless Mouse");
+        assert_eq!(body["data"]["candidates"][0]["similarity"], 1.0);
+
+        let (status, _) = send(
+            "POST",
+            "/api/items/check-duplicate?min_similarity=1.5",
+            Some(serde_json::json!({"name": "Wireless Mouse"})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = send(
+            "POST",
+            "/api/items?reject_duplicates=true",
+            Some(serde_json::json!({"name": "Wireless Mouse", "tags": ["peripheral"]})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["data"][0]["item"]["id"], 3);
+
+        let (status, _) = send(
+            "POST",
+            "/api/items?reject_duplicates=true",
+            Some(serde_json::json!({"name": "Standing Desk"})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_raw_response_mode() {
+        let app = crate::test_support::test_app().await;
+        app.state
+            .job_queue
+            .as_ref()
+            .unwrap()
+            .submit_job(crate::jobs::JobRequest {
+                job_type: crate::jobs::JobType::ReportGeneration,
+                payload: serde_json::json!({}),
+                priority: None,
+                max_retries: None,
+            })
+            .await
+            .unwrap();
+        let mut config = crate::AppConfig::default();
+        config.rate_limit.enable = false;
+        let router = crate::create_app_with_config(app.state.with_cache_manager(CacheManager::default()), config);
+
+        let get = |uri: &str, prefer: Option<&str>| {
+            let mut builder = Request::get(uri).header("user-agent", "routes-tests");
+            if let Some(prefer) = prefer {
+                builder = builder.header("prefer", prefer);
+            }
+            let mut request = builder.body(Body::empty()).unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+            let router = router.clone();
+            async move {
+                let response = router.oneshot(request).await.unwrap();
+                let (parts, body) = response.into_parts();
+                let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+                (parts, serde_json::from_slice::<serde_json::Value>(&body).unwrap())
+            }
+        };
+
+        // Enveloped by default; the raw request for the same URL is a
+        // separate cache entry, and the enveloped one is still served after.
+        for _ in 0..2 {
+            let (parts, enveloped) = get("/api/items?page_size=1", None).await;
+            assert_eq!(parts.status, StatusCode::OK);
+            assert_eq!(enveloped["success"], true);
+
+            let (parts, body) = get("/api/items?page_size=1", Some("return=representation")).await;
+            assert_eq!(parts.status, StatusCode::OK);
+            assert_eq!(body, enveloped["data"]["items"]);
+            assert_eq!(parts.headers["x-page"], "1");
+            assert_eq!(parts.headers["x-page-size"], "1");
+            assert_eq!(parts.headers["x-result-count"], "1");
+            assert_eq!(parts.headers["preference-applied"], "return=representation");
+        }
+
+        let (_, body) = get("/api/v1/items/2", None).await;
+        assert_eq!(body["data"]["id"], 2);
+        let (_, body) = get("/api/v1/items/2?envelope=false", None).await;
+        assert_eq!(body["id"], 2);
+        assert!(body.get("success").is_none());
+
+        let (_, body) = get("/api/v2/items/2", None).await;
+        assert_eq!(body["data"]["item"]["id"], 2);
+        let (parts, body) = get("/api/v2/items/2?envelope=false", None).await;
+        assert_eq!(body["id"], 2);
+        assert!(parts.headers.get("preference-applied").is_none());
+
+        let (_, body) = get("/api/jobs", None).await;
+        assert_eq!(body["data"]["jobs"].as_array().unwrap().len(), 1);
+        let (parts, body) = get("/api/jobs?envelope=false", None).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(parts.headers["x-total-count"], "1");
+        assert_eq!(parts.headers["x-offset"], "0");
+
+        let (parts, body) = get("/api/files?envelope=false", None).await;
+        assert_eq!(parts.status, StatusCode::OK);
+        assert!(body.as_array().unwrap().is_empty());
+        assert_eq!(parts.headers["x-total-count"], "0");
+
+        let (parts, body) = get("/api/items/999", None).await;
+        assert_eq!(parts.status, StatusCode::NOT_FOUND);
+        assert_eq!(parts.headers["content-type"], "application/json");
+        assert!(body["error"].is_string());
+
+        let (parts, body) = get("/api/items/999?envelope=false", None).await;
+        assert_eq!(parts.status, StatusCode::NOT_FOUND);
+        assert_eq!(parts.headers["content-type"], "application/problem+json");
+        assert_eq!(body["status"], 404);
+        assert_eq!(body["title"], "Not Found");
+        assert_eq!(body["instance"], "/api/items/999");
+        assert!(body["detail"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_item_secrets() {
+        let storage = tempfile::TempDir::new().unwrap();
+        let mut config = crate::test_support::test_config(storage.path());
+        config.item_secrets.enabled = true;
+        config.item_secrets.key = Some("11".repeat(32));
+        let app = crate::test_support::test_app_with_config(config, storage).await;
+        let mut config = crate::AppConfig::default();
+        config.rate_limit.enable = false;
+        let router = crate::create_app_with_config(app.state.clone(), config);
+
+        let admin = AuthUser::new(1, "admin".to_string(), crate::auth::models::UserRole::Admin);
+        let user = AuthUser::new(2, "user".to_string(), crate::auth::models::UserRole::User);
+        let send = |method: &str, uri: &str, user: Option<&AuthUser>, body: Option<serde_json::Value>| {
+            let mut request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("user-agent", "routes-tests")
+                .header("content-type", "application/json")
+                .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+            // Authenticated requests skip the response cache, as they would
+            // with a real token.
+            if let Some(user) = user {
+                request.headers_mut().insert("authorization", "Bearer test".parse().unwrap());
+                request.extensions_mut().insert(user.clone());
+            }
+            let router = router.clone();
+            async move {
+                let response = router.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, serde_json::from_slice::<serde_json::Value>(&body).unwrap())
+            }
+        };
+
+        let item = serde_json::json!({"name": "Vault", "metadata": {"secret": "s3cret", "color": "red"}});
+        let (status, body) = send("POST", "/api/v1/items", Some(&admin), Some(item)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["data"]["metadata"]["secret"], "s3cret");
+        let id = body["data"]["id"].as_u64().unwrap();
+        let uri = format!("/api/v1/items/{}", id);
+
+        let stored = app.state.item_service.get_item(id).await.unwrap();
+        let sealed = &stored.metadata.as_ref().unwrap().to_value()["secret"];
+        assert!(sealed.as_str().unwrap().starts_with("enc:v1:"));
+
+        for reader in [None, Some(&user)] {
+            let (_, body) = send("GET", &uri, reader, None).await;
+            assert_eq!(body["data"]["metadata"]["secret"], "***");
+            assert_eq!(body["data"]["metadata"]["color"], "red");
+        }
+        let (_, body) = send("GET", &uri, Some(&admin), None).await;
+        assert_eq!(body["data"]["metadata"]["secret"], "s3cret");
+
+        // Sending the redacted value back keeps the stored secret.
+        let update = serde_json::json!({"name": "Vault", "metadata": {"secret": "***", "color": "blue"}});
+        let (status, body) = send("PUT", &uri, Some(&user), Some(update)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["metadata"]["secret"], "***");
+        let (_, body) = send("GET", &uri, Some(&admin), None).await;
+        assert_eq!(body["data"]["metadata"]["secret"], "s3cret");
+        assert_eq!(body["data"]["metadata"]["color"], "blue");
+
+        let (status, _) = send("GET", "/api/items/search?q=metadata.secret:s3cret", Some(&admin), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = send("POST", "/api/admin/items/secrets/rekey", Some(&admin), None).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let old_key = crate::item_secrets::sealed_key_id(sealed).unwrap();
+        assert_eq!(body["data"]["key_id"], old_key);
+        let (status, _) = send("POST", "/api/admin/items/secrets/rekey", Some(&user), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // After a rotation the rekeyer moves the secret to the new key.
+        let mut rotated = crate::config::ItemSecretsConfig::default();
+        rotated.enabled = true;
+        rotated.key = Some("22".repeat(32));
+        rotated.previous_keys = vec!["11".repeat(32)];
+        let secrets = crate::item_secrets::ItemSecrets::from_config(&rotated).unwrap();
+        let state = app.state.clone().with_item_secrets(secrets.clone());
+        let report = state.secret_rekeyer().rekey("admin").await.unwrap();
+        assert_eq!((report.checked, report.resealed, report.unreadable), (1, 1, 0));
+
+        let mut stored = state.item_service.get_item(id).await.unwrap();
+        let secret = &stored.metadata.as_ref().unwrap().to_value()["secret"];
+        assert_eq!(crate::item_secrets::sealed_key_id(secret), secrets.current_key_id());
+        assert_eq!(stored.version, 2);
+        secrets.present(&mut stored, Some(&admin)).unwrap();
+        assert_eq!(stored.metadata.unwrap().to_value()["secret"], "s3cret");
+    }
+
+    #[tokio::test]
+    async fn test_oversized_metadata_is_refused_on_every_route() {
+        let app = crate::test_support::test_app().await;
+        let mut config = crate::AppConfig::default();
+        config.rate_limit.enable = false;
+        let router = crate::create_app_with_config(app.state.clone(), config);
+        let user = AuthUser::new(1, "admin".to_string(), crate::auth::models::UserRole::Admin);
+        let item = app
+            .state
+            .item_service
+            .create_item("Small".to_string(), None, Vec::new(), None)
+            .await
+            .unwrap();
+
+        // Just under the request body limit, so the metadata limit refuses it.
+        let metadata = serde_json::json!({ "blob": "x".repeat(1_000_000) });
+        let full = serde_json::json!({ "name": "Big", "metadata": metadata });
+        let partial = serde_json::json!({ "metadata": metadata });
+        let writes = [
+            ("POST", "/api/items".to_string(), &full),
+            ("POST", "/api/v1/items".to_string(), &full),
+            ("POST", "/api/v2/items".to_string(), &full),
+            ("PUT", format!("/api/v1/items/{}", item.id), &full),
+            ("PUT", format!("/api/v2/items/{}", item.id), &full),
+            ("PATCH", format!("/api/v1/items/{}", item.id), &partial),
+        ];
+        for (method, uri, body) in writes {
+            let mut request = Request::builder()
+                .method(method)
+                .uri(&uri)
+                .header("user-agent", "routes-tests")
+                .header("content-type", "application/json")
+                .header("authorization", "Bearer test")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+            request.extensions_mut().insert(user.clone());
+
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE, "{} {}", method, uri);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert!(body["error"].as_str().unwrap().starts_with("Metadata is larger than"), "{} {}: {}", method, uri, body);
+        }
+
+        let stored = app.state.item_service.get_item(item.id).await.unwrap();
+        assert_eq!(stored.metadata, item.metadata);
+        assert_eq!(stored.version, item.version);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{
+    audit::AuditEvent,
+    error::Result,
+    events::DomainEvent,
+    middleware::auth::AuthUser,
+    models::{
+        items::{TagMergeRequest, TagRenameRequest, TagRewrite, TagRewriteResult},
+        request::ApiResponse,
+    },
+    AppState,
+};
+use axum::{extract::State, response::IntoResponse, Extension, Json};
+use tracing::info;
+
+pub async fn list_tags(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    info!("GET /api/tags");
+
+    let tags = state.item_service.tag_counts().await?;
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "total": tags.len(),
+        "tags": tags,
+    }))))
+}
+
+pub async fn rename_tag(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Json(request): Json<TagRenameRequest>,
+) -> Result<impl IntoResponse> {
+    info!("POST /api/tags/rename - {} -> {}", request.from, request.to);
+
+    let rewrite = TagRewrite::new(vec![request.from], request.to, request.case_insensitive)?;
+    rewrite_tags(&state, &admin, "tags.renamed", &rewrite, request.dry_run).await
+}
+
+pub async fn merge_tags(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Json(request): Json<TagMergeRequest>,
+) -> Result<impl IntoResponse> {
+    info!("POST /api/tags/merge - {:?} -> {}", request.sources, request.target);
+
+    let rewrite = TagRewrite::new(request.sources, request.target, request.case_insensitive)?;
+    rewrite_tags(&state, &admin, "tags.merged", &rewrite, request.dry_run).await
+}
+
+/// Applies `rewrite` and, unless it is a dry run, publishes each changed
+/// item once, however many of its tags were rewritten.
+async fn rewrite_tags(
+    state: &AppState,
+    admin: &AuthUser,
+    action: &str,
+    rewrite: &TagRewrite,
+    dry_run: bool,
+) -> Result<Json<ApiResponse<TagRewriteResult>>> {
+    let items = state.item_service.rewrite_tags(rewrite, dry_run).await?;
+
+    if !dry_run {
+        state.events.publish_all(items.iter().cloned().map(DomainEvent::ItemUpdated).collect()).await;
+        state.audit_log.record(
+            AuditEvent::new(action)
+                .with_actor(admin.username.clone())
+                .with_target(rewrite.target())
+                .with_details(serde_json::json!({
+                    "sources": rewrite.sources(),
+                    "case_insensitive": rewrite.case_insensitive(),
+                    "affected_items": items.len(),
+                })),
+        );
+    }
+
+    Ok(Json(ApiResponse::success(TagRewriteResult {
+        dry_run,
+        affected_items: items.len() as u64,
+        item_ids: items.iter().map(|item| item.id).collect(),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::models::UserRole;
+    use crate::test_support::{test_app, TestApp};
+    use crate::websocket::WebSocketMessage;
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{Request, StatusCode},
+        Router,
+    };
+    use serde_json::{json, Value};
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    fn router(app: &TestApp) -> Router {
+        let mut config = crate::AppConfig::default();
+        config.rate_limit.enable = false;
+        crate::create_app_with_config(app.state.clone(), config)
+    }
+
+    async fn send(router: &Router, method: &str, uri: &str, token: Option<&str>, body: Option<Value>) -> (StatusCode, Value) {
+        let mut builder = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("user-agent", "tag-tests");
+        if let Some(token) = token {
+            builder = builder.header("authorization", format!("Bearer {}", token));
+        }
+        let mut request = match body {
+            Some(body) => builder
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+            None => builder.body(Body::empty()).unwrap(),
+        };
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_tags_are_listed_with_counts() {
+        let app = test_app().await;
+        let router = router(&app);
+
+        let (status, body) = send(&router, "GET", "/api/tags", None, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["total"], 2);
+        assert_eq!(
+            body["data"]["tags"],
+            json!([{ "tag": "demo", "count": 2 }, { "tag": "sample", "count": 1 }])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_merge_rewrites_items_and_announces_each_once() {
+        let app = test_app().await;
+        let router = router(&app);
+        let admin = app.login_as("curator", UserRole::Admin).await;
+        let user = app.login_as("tagger", UserRole::User).await;
+        let merge = json!({ "sources": ["demo", "sample"], "target": "showcase" });
+
+        let (status, _) = send(&router, "POST", "/api/tags/merge", Some(&user), Some(merge.clone())).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let mut dry_run = merge.clone();
+        dry_run["dry_run"] = json!(true);
+        let (status, body) = send(&router, "POST", "/api/tags/merge", Some(&admin), Some(dry_run)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["affected_items"], 2);
+        assert_eq!(app.state.item_service.get_item(1).await.unwrap().tags, vec!["sample", "demo"]);
+
+        let mut events = app.state.websocket_manager.as_ref().unwrap().subscribe();
+        let (status, body) = send(&router, "POST", "/api/tags/merge", Some(&admin), Some(merge)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"], json!({ "dry_run": false, "affected_items": 2, "item_ids": [1, 2] }));
+
+        let mut updated = Vec::new();
+        while let Ok(message) = events.try_recv() {
+            if let WebSocketMessage::ItemUpdated(item) = message {
+                assert_eq!(item.tags, vec!["showcase"]);
+                updated.push(item.id);
+            }
+        }
+        assert_eq!(updated, vec![1, 2]);
+        assert_eq!(app.state.audit_log.recent(Some("tags.merged"), 10).len(), 1);
+
+        let (_, body) = send(&router, "GET", "/api/tags", None, None).await;
+        assert_eq!(body["data"]["tags"], json!([{ "tag": "showcase", "count": 2 }]));
+
+        let rename = json!({ "from": "SHOWCASE", "to": "featured", "case_insensitive": true });
+        let (status, body) = send(&router, "POST", "/api/tags/rename", Some(&admin), Some(rename)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["affected_items"], 2);
+
+        let invalid = json!({ "from": "featured", "to": "featured" });
+        let (status, _) = send(&router, "POST", "/api/tags/rename", Some(&admin), Some(invalid)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::{
+    audit::AuditEvent,
+    error::{AppError, Result},
+    events::DomainEvent,
+    middleware::auth::AuthUser,
+    models::request::ApiResponse,
+    trash::PurgeReport,
+    AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    Extension, Json,
+};
+use serde::Deserialize;
+use tracing::info;
+
+/// Most trashed items listed at once.
+const MAX_TRASH_PAGE: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct TrashListQuery {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+/// Items waiting in the trash, most recently deleted first, each with its
+/// `deleted_at`.
+pub async fn list_trash(
+    State(state): State<AppState>,
+    user: Option<Extension<AuthUser>>,
+    Query(query): Query<TrashListQuery>,
+) -> Result<Json<ApiResponse<serde_json::Value>>> {
+    info!("GET /api/items/trash");
+
+    let limit = query.limit.unwrap_or(20).clamp(1, MAX_TRASH_PAGE);
+    let offset = query.offset.unwrap_or(0);
+    // One extra row tells whether there is another page.
+    let mut trashed = state.item_service.trashed_items(limit + 1, offset).await?;
+    let has_more = trashed.len() > limit;
+    trashed.truncate(limit);
+
+    let user = user.as_ref().map(|Extension(user)| user);
+    let items: Vec<serde_json::Value> = trashed
+        .into_iter()
+        .map(|(mut item, deleted_at)| {
+            state.item_secrets.present(&mut item, user)?;
+            let mut value = serde_json::to_value(item)?;
+            value["deleted_at"] = serde_json::json!(deleted_at);
+            Ok(value)
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "items": items,
+        "limit": limit,
+        "offset": offset,
+        "has_more": has_more,
+    }))))
+}
+
+/// Takes an item out of the trash, publishing it as created again.
+pub async fn restore_item(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Path(id): Path<u64>,
+) -> Result<Json<ApiResponse<crate::store::Item>>> {
+    info!("POST /api/items/trash/{}/restore", id);
+
+    if id == 0 {
+        return Err(AppError::BadRequest("Invalid item ID".to_string()));
+    }
+    let mut item = state.item_service.restore_item(id).await?;
+    state.events.publish(DomainEvent::ItemCreated(item.clone())).await;
+    state.item_secrets.present(&mut item, Some(&admin))?;
+    state.audit_log.record(
+        AuditEvent::new("items.restored")
+            .with_actor(admin.username)
+            .with_target(id.to_string()),
+    );
+
+    Ok(Json(ApiResponse::success(item)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PurgeQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Purges items past trash retention now instead of waiting for the next
+/// scheduled run. With `dry_run=true` nothing is removed and the counts are
+/// what a purge would remove.
+pub async fn purge_trash(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Query(query): Query<PurgeQuery>,
+) -> Result<Json<ApiResponse<PurgeReport>>> {
+    info!("POST /api/items/trash/purge (dry_run: {})", query.dry_run);
+
+    let report = state.trash_purger().purge(query.dry_run, &admin.username).await?;
+    Ok(Json(ApiResponse::success(report)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::models::UserRole;
+    use crate::jobs::{JobRequest, JobStatus, JobType};
+    use crate::test_support::{test_app_with_config, test_config, TestApp};
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{Request, StatusCode},
+        Router,
+    };
+    use serde_json::Value;
+    use std::net::SocketAddr;
+    use tempfile::TempDir;
+    use tower::ServiceExt;
+
+    /// Items are purged as soon as they are deleted, one per batch.
+    async fn app() -> TestApp {
+        let storage = TempDir::new().unwrap();
+        let mut config = test_config(storage.path());
+        config.trash.retention_days = 0;
+        config.trash.batch_size = 1;
+        test_app_with_config(config, storage).await
+    }
+
+    fn router(app: &TestApp) -> Router {
+        let mut config = crate::AppConfig::default();
+        config.rate_limit.enable = false;
+        crate::create_app_with_config(app.state.clone(), config)
+    }
+
+    async fn send(router: &Router, method: &str, uri: &str, token: &str) -> (StatusCode, Value) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("user-agent", "trash-tests")
+            .header("authorization", format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    async fn count(app: &TestApp, sql: &str) -> i64 {
+        sqlx::query_scalar(sql).fetch_one(&app.pool).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_purge_removes_trashed_items_in_batches() {
+        let app = app().await;
+        let router = router(&app);
+        let admin = app.login_as("janitor", UserRole::Admin).await;
+        let user = app.login_as("visitor", UserRole::User).await;
+
+        sqlx::query(
+            "INSERT INTO files (id, filename, original_filename, content_type, size, path, uploaded_by, item_id)
+             VALUES ('f1', 'f1.txt', 'f1.txt', 'text/plain', 1, 'f1.txt', 1, 1)",
+        )
+        .execute(&app.pool)
+        .await
+        .unwrap();
+        app.state.item_service.delete_item(1).await.unwrap();
+        app.state.item_service.delete_item(2).await.unwrap();
+
+        let (status, _) = send(&router, "GET", "/api/items/1", &user).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(count(&app, "SELECT COUNT(*) FROM items").await, 2);
+        let changes = count(&app, "SELECT COUNT(*) FROM item_changes").await;
+        assert!(changes >= 2);
+
+        let (status, _) = send(&router, "POST", "/api/items/trash/purge", &user).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = send(&router, "POST", "/api/items/trash/purge?dry_run=true", &admin).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["dry_run"], true);
+        assert_eq!(body["data"]["items"], 2);
+        assert_eq!(body["data"]["files_detached"], 1);
+        assert_eq!(body["data"]["changes_removed"], changes);
+        assert_eq!(body["data"]["batches"], 2);
+        assert_eq!(count(&app, "SELECT COUNT(*) FROM items").await, 2);
+
+        let (status, body) = send(&router, "POST", "/api/items/trash/purge", &admin).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["items"], 2);
+        assert_eq!(body["data"]["files_detached"], 1);
+        assert_eq!(body["data"]["changes_removed"], changes);
+        assert_eq!(body["data"]["batches"], 2);
+
+        assert_eq!(count(&app, "SELECT COUNT(*) FROM items").await, 0);
+        assert_eq!(count(&app, "SELECT COUNT(*) FROM items_fts").await, 0);
+        assert_eq!(count(&app, "SELECT COUNT(*) FROM item_changes").await, 0);
+        assert_eq!(count(&app, "SELECT COUNT(*) FROM files WHERE item_id IS NULL").await, 1);
+
+        let audited = app.state.audit_log.recent(Some("items.trash_purged"), 10);
+        assert_eq!(audited.len(), 2);
+        assert_eq!(audited[0].actor.as_deref(), Some("janitor"));
+        assert_eq!(audited[0].details["items"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_trash_purge_job_reports_counts() {
+        let app = app().await;
+        app.state.item_service.delete_item(2).await.unwrap();
+
+        let job_queue = app.state.job_queue.as_ref().unwrap();
+        let job_id = job_queue
+            .submit_job(JobRequest {
+                job_type: JobType::TrashPurge,
+                payload: serde_json::json!({}),
+                priority: None,
+                max_retries: Some(0),
+            })
+            .await
+            .unwrap();
+
+        let mut job = None;
+        for _ in 0..100 {
+            job = job_queue.get_job_status(job_id).await.unwrap();
+            if job.as_ref().is_some_and(|job| matches!(job.status, JobStatus::Completed | JobStatus::Failed)) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        let job = job.unwrap();
+        assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error_message);
+        assert_eq!(job.result.unwrap()["items"], 1);
+        assert!(app.state.item_service.get_item(1).await.is_ok());
+
+        let audited = app.state.audit_log.recent(Some("items.trash_purged"), 10);
+        assert_eq!(audited[0].actor, Some(format!("job:{}", job_id)));
+    }
+}
@@ -0,0 +1,101 @@
+use crate::{
+    audit::AuditEvent,
+    error::{AppError, Result},
+    middleware::auth::AuthUser,
+    models::request::ApiResponse,
+    webhooks::{CreateWebhookRequest, WebhookDeliveryQuery, WebhookService},
+    AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use tracing::info;
+
+fn webhook_service(state: &AppState) -> Result<&WebhookService> {
+    state
+        .webhooks
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound("Webhooks are not enabled".to_string()))
+}
+
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Json(request): Json<CreateWebhookRequest>,
+) -> Result<impl IntoResponse> {
+    info!("POST /api/webhooks");
+
+    let webhook = webhook_service(&state)?
+        .create(request, Some(admin.user_id))
+        .await?;
+
+    state.audit_log.record(
+        AuditEvent::new("webhook.created")
+            .with_actor(admin.username.clone())
+            .with_target(webhook.id.to_string())
+            .with_details(serde_json::json!({
+                "url": webhook.url,
+                "events": webhook.events,
+                "tags": webhook.tags,
+            })),
+    );
+
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(webhook))))
+}
+
+pub async fn list_webhooks(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    info!("GET /api/webhooks");
+
+    let webhooks = webhook_service(&state)?.list().await?;
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "total": webhooks.len(),
+        "webhooks": webhooks,
+    }))))
+}
+
+pub async fn get_webhook(State(state): State<AppState>, Path(id): Path<i64>) -> Result<impl IntoResponse> {
+    info!("GET /api/webhooks/{}", id);
+
+    let webhook = webhook_service(&state)?.get(id).await?;
+    Ok(Json(ApiResponse::success(webhook)))
+}
+
+pub async fn delete_webhook(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse> {
+    info!("DELETE /api/webhooks/{}", id);
+
+    webhook_service(&state)?.delete(id).await?;
+
+    state.audit_log.record(
+        AuditEvent::new("webhook.deleted")
+            .with_actor(admin.username.clone())
+            .with_target(id.to_string()),
+    );
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "message": format!("Webhook {} deleted", id),
+    }))))
+}
+
+pub async fn list_deliveries(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Query(query): Query<WebhookDeliveryQuery>,
+) -> Result<impl IntoResponse> {
+    info!("GET /api/webhooks/{}/deliveries", id);
+
+    let deliveries = webhook_service(&state)?.deliveries(id, query.limit()).await?;
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "webhook_id": id,
+        "total": deliveries.len(),
+        "deliveries": deliveries,
+    }))))
+}
@@ -0,0 +1,111 @@
+use crate::{
+    audit::AuditEvent,
+    error::{AppError, Result},
+    middleware::auth::AuthUser,
+    models::request::ApiResponse,
+    websocket::WebSocketManager,
+    AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::Deserialize;
+use tracing::info;
+use uuid::Uuid;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ConnectionListQuery {
+    pub page: Option<u32>,
+    pub page_size: Option<u32>,
+}
+
+impl ConnectionListQuery {
+    pub const DEFAULT_PAGE_SIZE: u32 = 50;
+    pub const MAX_PAGE_SIZE: u32 = 500;
+
+    pub fn page(&self) -> u32 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    pub fn page_size(&self) -> u32 {
+        self.page_size.unwrap_or(Self::DEFAULT_PAGE_SIZE).clamp(1, Self::MAX_PAGE_SIZE)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DisconnectQuery {
+    pub reason: Option<String>,
+}
+
+fn websocket_manager(state: &AppState) -> Result<&WebSocketManager> {
+    state
+        .websocket_manager
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound("WebSocket support is not enabled".to_string()))
+}
+
+/// Open WebSocket connections, oldest first.
+pub async fn list_connections(
+    State(state): State<AppState>,
+    Query(query): Query<ConnectionListQuery>,
+) -> Result<impl IntoResponse> {
+    info!("GET /api/websocket/connections");
+
+    let connections = websocket_manager(&state)?.connections().await;
+    let (page, page_size) = (query.page(), query.page_size());
+    let total = connections.len();
+    let connections: Vec<_> = connections
+        .into_iter()
+        .skip((page as usize - 1) * page_size as usize)
+        .take(page_size as usize)
+        .collect();
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "connections": connections,
+        "total": total,
+        "page": page,
+        "page_size": page_size,
+    }))))
+}
+
+/// Closes a connection; the client sees `reason` in the close frame.
+pub async fn disconnect_connection(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthUser>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<DisconnectQuery>,
+) -> Result<impl IntoResponse> {
+    info!("DELETE /api/websocket/connections/{}", id);
+
+    let reason = query
+        .reason
+        .filter(|reason| !reason.trim().is_empty())
+        .unwrap_or_else(|| "Disconnected by an administrator".to_string());
+    // Close frame reasons are limited to 123 bytes.
+    if reason.len() > 123 {
+        return Err(AppError::BadRequest("Reason must be at most 123 bytes".to_string()));
+    }
+
+    let connection = websocket_manager(&state)?
+        .disconnect(&id, &reason)
+        .await
+        .ok_or_else(|| AppError::NotFound(format!("No open WebSocket connection {}", id)))?;
+
+    state.audit_log.record(
+        AuditEvent::new("websocket.disconnected")
+            .with_actor(admin.username.clone())
+            .with_target(id.to_string())
+            .with_details(serde_json::json!({
+                "reason": reason,
+                "user_id": connection.user_id,
+                "remote_ip": connection.remote_ip,
+            })),
+    );
+
+    Ok(Json(ApiResponse::success(serde_json::json!({
+        "message": format!("WebSocket connection {} disconnected", id),
+        "connection": connection,
+    }))))
+}
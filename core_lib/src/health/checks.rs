@@ -1,11 +1,24 @@
 //! Comprehensive health check system for monitoring server components
 
+use super::history::{HealthHistory, HealthHistoryQuery, HealthHistoryStore, HealthTransition};
+use crate::config::HealthConfig;
+use crate::features::FeatureSwitches;
+use crate::files::LastReconciliation;
+use crate::integrity::LastIntegrityCheck;
+use crate::metrics::MetricsSink;
+use crate::monitoring::SystemMonitor;
+use crate::supervisor::{Supervisor, TaskState};
 use crate::{AppState, Result};
+use chrono::{DateTime, Utc};
+use futures_util::future::join_all;
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::Path;
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::fs;
+use tokio::task::JoinHandle;
 use tracing::{info, warn, error};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -25,6 +38,19 @@ impl std::fmt::Display for HealthStatus {
     }
 }
 
+impl std::str::FromStr for HealthStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "healthy" => Ok(HealthStatus::Healthy),
+            "degraded" => Ok(HealthStatus::Degraded),
+            "unhealthy" => Ok(HealthStatus::Unhealthy),
+            other => Err(format!("Unknown health status '{}'", other)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ComponentHealth {
     pub status: HealthStatus,
@@ -75,6 +101,10 @@ impl ComponentHealth {
 pub struct SystemHealth {
     pub overall_status: HealthStatus,
     pub components: HashMap<String, ComponentHealth>,
+    /// How long each check took as timed by the checker, including any
+    /// that timed out.
+    #[serde(default)]
+    pub check_durations_ms: HashMap<String, u64>,
     pub timestamp: chrono::DateTime<chrono::Utc>,
     pub uptime_seconds: u64,
     pub version: String,
@@ -85,6 +115,7 @@ impl SystemHealth {
         Self {
             overall_status: HealthStatus::Healthy,
             components: HashMap::new(),
+            check_durations_ms: HashMap::new(),
             timestamp: chrono::Utc::now(),
             uptime_seconds,
             version,
@@ -277,6 +308,73 @@ impl HealthCheck for FilesystemHealthCheck {
     }
 }
 
+/// Probes the upload directory with a small write and delete and watches
+/// the free space on its volume.
+pub struct FileStorageHealthCheck {
+    upload_dir: PathBuf,
+    min_free_space: u64,
+    reconciliation: Option<LastReconciliation>,
+}
+
+impl FileStorageHealthCheck {
+    pub fn new(upload_dir: PathBuf, min_free_space: u64) -> Self {
+        Self { upload_dir, min_free_space, reconciliation: None }
+    }
+
+    /// Reports the latest reconciliation of the upload directory in the
+    /// details.
+    pub fn with_reconciliation(mut self, reconciliation: LastReconciliation) -> Self {
+        self.reconciliation = Some(reconciliation);
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl HealthCheck for FileStorageHealthCheck {
+    async fn check(&self) -> ComponentHealth {
+        let start = Instant::now();
+        let probe = self.upload_dir.join(format!(".health_probe_{}", uuid::Uuid::new_v4()));
+        let probed = match fs::write(&probe, b"probe").await {
+            Ok(()) => fs::remove_file(&probe).await,
+            Err(e) => Err(e),
+        };
+        let disk = SystemMonitor::disk_usage_for(&self.upload_dir);
+        let response_time = start.elapsed().as_millis() as u64;
+
+        let details = serde_json::json!({
+            "upload_dir": self.upload_dir.display().to_string(),
+            "writable": probed.is_ok(),
+            "available_bytes": disk.as_ref().map(|disk| disk.available_bytes),
+            "total_bytes": disk.as_ref().map(|disk| disk.total_bytes),
+            "mount_point": disk.as_ref().map(|disk| disk.mount_point.clone()),
+            "min_free_bytes": self.min_free_space,
+            "last_reconciliation": self.reconciliation.as_ref().and_then(LastReconciliation::get),
+        });
+
+        if let Err(e) = probed {
+            return ComponentHealth::unhealthy(
+                format!("Cannot write to upload directory {}: {}", self.upload_dir.display(), e),
+                response_time,
+            )
+            .with_details(details);
+        }
+
+        match disk {
+            Some(disk) if disk.available_bytes < self.min_free_space => ComponentHealth::degraded(
+                format!("Only {} MB free for uploads", disk.available_bytes / (1024 * 1024)),
+                response_time,
+            )
+            .with_details(details),
+            _ => ComponentHealth::healthy("Upload directory writable".to_string(), response_time)
+                .with_details(details),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "files"
+    }
+}
+
 pub struct DependencyHealthCheck {
     name: String,
     check_fn: Box<dyn Fn() -> Result<String> + Send + Sync>,
@@ -322,18 +420,169 @@ impl HealthCheck for DependencyHealthCheck {
     }
 }
 
+/// Background tasks run by the [`Supervisor`]: Degraded once any has used up
+/// its restart budget.
+pub struct BackgroundTasksHealthCheck {
+    supervisor: Supervisor,
+}
+
+impl BackgroundTasksHealthCheck {
+    pub fn new(supervisor: Supervisor) -> Self {
+        Self { supervisor }
+    }
+}
+
+#[async_trait::async_trait]
+impl HealthCheck for BackgroundTasksHealthCheck {
+    async fn check(&self) -> ComponentHealth {
+        let start = Instant::now();
+        let tasks = self.supervisor.statuses();
+        let dead: Vec<&str> = tasks
+            .iter()
+            .filter(|task| task.state == TaskState::Dead)
+            .map(|task| task.name.as_str())
+            .collect();
+        let details = serde_json::json!({ "tasks": tasks });
+        let response_time = start.elapsed().as_millis() as u64;
+
+        if dead.is_empty() {
+            ComponentHealth::healthy(format!("{} background tasks supervised", tasks.len()), response_time)
+                .with_details(details)
+        } else {
+            ComponentHealth::degraded(
+                format!("Background tasks stopped after too many restarts: {}", dead.join(", ")),
+                response_time,
+            )
+            .with_details(details)
+        }
+    }
+
+    fn name(&self) -> &str {
+        "background_tasks"
+    }
+}
+
+/// Optional subsystems: Degraded while any is switched off.
+pub struct FeatureSwitchesHealthCheck {
+    features: FeatureSwitches,
+}
+
+impl FeatureSwitchesHealthCheck {
+    pub fn new(features: FeatureSwitches) -> Self {
+        Self { features }
+    }
+}
+
+#[async_trait::async_trait]
+impl HealthCheck for FeatureSwitchesHealthCheck {
+    async fn check(&self) -> ComponentHealth {
+        let start = Instant::now();
+        let disabled: Vec<&str> = self.features.disabled().iter().map(|subsystem| subsystem.name()).collect();
+        let details = serde_json::json!({ "features": self.features.states() });
+        let response_time = start.elapsed().as_millis() as u64;
+
+        if disabled.is_empty() {
+            ComponentHealth::healthy("All optional subsystems are switched on".to_string(), response_time)
+                .with_details(details)
+        } else {
+            ComponentHealth::degraded(format!("Switched off: {}", disabled.join(", ")), response_time)
+                .with_details(details)
+        }
+    }
+
+    fn name(&self) -> &str {
+        "features"
+    }
+}
+
+/// The latest integrity check of the references between tables. Only
+/// informational: broken references show in the message and details, but
+/// the component stays Healthy.
+pub struct IntegrityHealthCheck {
+    last: LastIntegrityCheck,
+}
+
+impl IntegrityHealthCheck {
+    pub fn new(last: LastIntegrityCheck) -> Self {
+        Self { last }
+    }
+}
+
+#[async_trait::async_trait]
+impl HealthCheck for IntegrityHealthCheck {
+    async fn check(&self) -> ComponentHealth {
+        let start = Instant::now();
+        let report = self.last.get();
+        let message = match &report {
+            None => "No integrity check has run".to_string(),
+            Some(report) if report.broken == 0 => "No broken references found by the last integrity check".to_string(),
+            Some(report) => format!(
+                "The last integrity check found {} broken references and repaired {}",
+                report.broken, report.repaired
+            ),
+        };
+        let response_time = start.elapsed().as_millis() as u64;
+
+        ComponentHealth::healthy(message, response_time)
+            .with_details(serde_json::json!({ "last_check": report }))
+    }
+
+    fn name(&self) -> &str {
+        "integrity"
+    }
+}
+
+/// Status a component is currently reported with, and since when.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComponentState {
+    pub status: HealthStatus,
+    pub since: DateTime<Utc>,
+    pub consecutive_failures: u32,
+}
+
+/// Runs the health checks concurrently, each within its timeout and all
+/// within an overall budget, and keeps the last full result for a short
+/// while to answer frequent probes.
 pub struct HealthChecker {
     checks: Vec<Box<dyn HealthCheck + Send + Sync>>,
+    check_timeout: Duration,
+    component_check_timeouts: HashMap<String, Duration>,
+    overall_timeout: Duration,
+    cache_ttl: Duration,
+    last_result: Mutex<Option<(Instant, SystemHealth)>>,
+    /// Held while a run started by [`check_all_cached`](Self::check_all_cached)
+    /// is in progress, so concurrent probes wait for it instead of starting
+    /// their own.
+    refreshing: tokio::sync::Mutex<()>,
     start_time: Instant,
+    started_at: DateTime<Utc>,
     version: String,
+    failure_threshold: u32,
+    component_failure_thresholds: HashMap<String, u32>,
+    states: Mutex<HashMap<String, ComponentState>>,
+    history: HealthHistory,
+    metrics: Option<Arc<dyn MetricsSink>>,
 }
 
 impl HealthChecker {
     pub fn new(version: String) -> Self {
+        let config = HealthConfig::default();
         Self {
             checks: Vec::new(),
+            check_timeout: Duration::from_millis(config.check_timeout_ms),
+            component_check_timeouts: HashMap::new(),
+            overall_timeout: Duration::from_millis(config.overall_timeout_ms),
+            cache_ttl: Duration::from_millis(config.cache_ttl_ms),
+            last_result: Mutex::new(None),
+            refreshing: tokio::sync::Mutex::new(()),
             start_time: Instant::now(),
+            started_at: Utc::now(),
             version,
+            failure_threshold: 1,
+            component_failure_thresholds: HashMap::new(),
+            states: Mutex::new(HashMap::new()),
+            history: HealthHistory::new(config.history_capacity),
+            metrics: None,
         }
     }
 
@@ -342,18 +591,249 @@ impl HealthChecker {
         self
     }
 
+    /// Consecutive failed checks before a component is reported unhealthy.
+    /// Until then a failing component is reported as degraded.
+    pub fn with_failure_threshold(mut self, threshold: u32) -> Self {
+        self.failure_threshold = threshold.max(1);
+        self
+    }
+
+    pub fn with_component_failure_threshold(mut self, component: &str, threshold: u32) -> Self {
+        self.component_failure_thresholds.insert(component.to_string(), threshold.max(1));
+        self
+    }
+
+    /// How long a check may run before its component is reported unhealthy
+    /// with a timeout.
+    pub fn with_check_timeout(mut self, timeout: Duration) -> Self {
+        self.check_timeout = timeout;
+        self
+    }
+
+    pub fn with_component_check_timeout(mut self, component: &str, timeout: Duration) -> Self {
+        self.component_check_timeouts.insert(component.to_string(), timeout);
+        self
+    }
+
+    /// Bound on a whole run of the checks. No check is given longer.
+    pub fn with_overall_timeout(mut self, timeout: Duration) -> Self {
+        self.overall_timeout = timeout;
+        self
+    }
+
+    /// How long [`check_all_cached`](Self::check_all_cached) answers with
+    /// the last full result.
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    pub fn with_config(mut self, config: &HealthConfig) -> Self {
+        self.history = HealthHistory::new(config.history_capacity);
+        self = self
+            .with_failure_threshold(config.failure_threshold)
+            .with_check_timeout(Duration::from_millis(config.check_timeout_ms))
+            .with_overall_timeout(Duration::from_millis(config.overall_timeout_ms))
+            .with_cache_ttl(Duration::from_millis(config.cache_ttl_ms));
+        for (component, threshold) in &config.component_failure_thresholds {
+            self = self.with_component_failure_threshold(component, *threshold);
+        }
+        for (component, timeout) in &config.component_check_timeouts_ms {
+            self = self.with_component_check_timeout(component, Duration::from_millis(*timeout));
+        }
+        self
+    }
+
+    pub fn with_history_store(mut self, store: HealthHistoryStore) -> Self {
+        self.history = self.history.with_store(store);
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<dyn MetricsSink>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    fn failure_threshold_for(&self, component: &str) -> u32 {
+        self.component_failure_thresholds
+            .get(component)
+            .copied()
+            .unwrap_or(self.failure_threshold)
+    }
+
+    fn check_timeout_for(&self, component: &str) -> Duration {
+        self.component_check_timeouts
+            .get(component)
+            .copied()
+            .unwrap_or(self.check_timeout)
+            .min(self.overall_timeout)
+    }
+
+    /// Runs one check within its timeout. A check that overruns is reported
+    /// unhealthy with a `timeout` cause. Returns the result with the time
+    /// the checker waited for it.
+    async fn run_check(&self, check: &(dyn HealthCheck + Send + Sync)) -> (ComponentHealth, Duration) {
+        let timeout = self.check_timeout_for(check.name());
+        let start = Instant::now();
+        let health = match tokio::time::timeout(timeout, check.check()).await {
+            Ok(health) => health,
+            Err(_) => ComponentHealth::unhealthy(
+                format!("Health check timed out after {} ms", timeout.as_millis()),
+                start.elapsed().as_millis() as u64,
+            )
+            .with_details(serde_json::json!({
+                "cause": "timeout",
+                "timeout_ms": timeout.as_millis() as u64
+            })),
+        };
+        (health, start.elapsed())
+    }
+
+    /// Applies failure debouncing to a raw check result and records a
+    /// transition if the reported status changes. Components start out
+    /// healthy when the checker is created.
+    async fn evaluate(&self, component: &str, health: ComponentHealth) -> ComponentHealth {
+        let (health, transition) = self.debounce(component, health);
+        if let Some(transition) = transition {
+            self.record(transition).await;
+        }
+        health
+    }
+
+    /// The status to report for a raw check result, and the transition to
+    /// record if it differs from the one reported before.
+    fn debounce(&self, component: &str, mut health: ComponentHealth) -> (ComponentHealth, Option<HealthTransition>) {
+        let threshold = self.failure_threshold_for(component);
+
+        let transition = {
+            let mut states = self.states.lock();
+            let state = states.entry(component.to_string()).or_insert_with(|| ComponentState {
+                status: HealthStatus::Healthy,
+                since: self.started_at,
+                consecutive_failures: 0,
+            });
+
+            if health.status == HealthStatus::Unhealthy {
+                state.consecutive_failures += 1;
+                if state.consecutive_failures < threshold {
+                    health.status = HealthStatus::Degraded;
+                    health.message = format!(
+                        "{} (failure {} of {} before unhealthy)",
+                        health.message, state.consecutive_failures, threshold
+                    );
+                }
+            } else {
+                state.consecutive_failures = 0;
+            }
+
+            if health.status == state.status {
+                None
+            } else {
+                let error = (health.status != HealthStatus::Healthy).then(|| {
+                    health
+                        .details
+                        .as_ref()
+                        .and_then(|details| details.get("error"))
+                        .and_then(|error| error.as_str())
+                        .map(str::to_string)
+                        .unwrap_or_else(|| health.message.clone())
+                });
+                let transition = HealthTransition {
+                    timestamp: health.last_checked,
+                    component: component.to_string(),
+                    from: state.status.clone(),
+                    to: health.status.clone(),
+                    message: health.message.clone(),
+                    error,
+                    previous_state_duration_ms: (health.last_checked - state.since).num_milliseconds().max(0) as u64,
+                };
+                state.status = health.status.clone();
+                state.since = health.last_checked;
+                Some(transition)
+            }
+        };
+
+        (health, transition)
+    }
+
+    async fn record(&self, transition: HealthTransition) {
+        warn!(
+            "Health of '{}' changed from {} to {}: {}",
+            transition.component, transition.from, transition.to, transition.message
+        );
+        if let Some(metrics) = &self.metrics {
+            metrics.record_health_status_change(
+                transition.component.clone(),
+                transition.from.to_string(),
+                transition.to.to_string(),
+                transition.message.clone(),
+            );
+        }
+        self.history.record(transition).await;
+    }
+
+    /// Currently reported status of every component that has been checked.
+    pub fn component_states(&self) -> HashMap<String, ComponentState> {
+        self.states.lock().clone()
+    }
+
+    /// Recorded transitions matching `query`, newest first.
+    pub async fn history(&self, query: &HealthHistoryQuery) -> Vec<HealthTransition> {
+        self.history.query(query).await
+    }
+
+    /// Runs every check each `interval` so transitions are recorded even
+    /// when nobody is polling /health.
+    pub fn spawn_monitor(self: Arc<Self>, supervisor: &Supervisor, interval: Duration) -> JoinHandle<()> {
+        supervisor.spawn("health_monitor", move || {
+            let checker = self.clone();
+            async move {
+                let mut ticker = tokio::time::interval(interval);
+                loop {
+                    ticker.tick().await;
+                    checker.check_all().await;
+                }
+            }
+        })
+    }
+
+    /// The last full result if it is younger than the cache TTL, otherwise
+    /// a fresh one. Concurrent callers share a single run.
+    pub async fn check_all_cached(&self) -> SystemHealth {
+        if let Some(health) = self.fresh_result() {
+            return health;
+        }
+        let _refreshing = self.refreshing.lock().await;
+        if let Some(health) = self.fresh_result() {
+            return health;
+        }
+        self.check_all().await
+    }
+
+    fn fresh_result(&self) -> Option<SystemHealth> {
+        self.last_result
+            .lock()
+            .as_ref()
+            .filter(|(checked_at, _)| checked_at.elapsed() < self.cache_ttl)
+            .map(|(_, health)| health.clone())
+    }
+
+    /// Runs every check concurrently and answers within the overall
+    /// timeout.
     pub async fn check_all(&self) -> SystemHealth {
+        let started = Instant::now();
         let uptime_seconds = self.start_time.elapsed().as_secs();
         let mut system_health = SystemHealth::new(self.version.clone(), uptime_seconds);
 
         info!("Running comprehensive health checks for {} components", self.checks.len());
 
-        for check in &self.checks {
+        let results = join_all(self.checks.iter().map(|check| self.run_check(check.as_ref()))).await;
+
+        let mut transitions = Vec::new();
+        for (check, (health, check_duration)) in self.checks.iter().zip(results) {
             let component_name = check.name().to_string();
-            let start = Instant::now();
-            
-            let health = check.check().await;
-            let check_duration = start.elapsed();
+            let (health, transition) = self.debounce(&component_name, health);
+            transitions.extend(transition);
             
             match health.status {
                 HealthStatus::Healthy => {
@@ -367,17 +847,30 @@ impl HealthChecker {
                 }
             }
             
+            system_health
+                .check_durations_ms
+                .insert(component_name.clone(), check_duration.as_millis() as u64);
             system_health.add_component(component_name, health);
         }
 
+        // Persisting a transition can wait on the very database that just
+        // failed its check; the transitions are kept in memory regardless.
+        let remaining = self.overall_timeout.saturating_sub(started.elapsed());
+        let recorded = join_all(transitions.into_iter().map(|transition| self.record(transition)));
+        if tokio::time::timeout(remaining, recorded).await.is_err() {
+            warn!("Recording health transitions overran the health check budget");
+        }
+
         info!("Health check completed - Overall status: {}", system_health.overall_status);
+        *self.last_result.lock() = Some((Instant::now(), system_health.clone()));
         system_health
     }
 
     pub async fn check_component(&self, component_name: &str) -> Option<ComponentHealth> {
         for check in &self.checks {
             if check.name() == component_name {
-                return Some(check.check().await);
+                let (health, _) = self.run_check(check.as_ref()).await;
+                return Some(self.evaluate(component_name, health).await);
             }
         }
         None
@@ -386,17 +879,24 @@ impl HealthChecker {
 
 impl HealthChecker {
     pub fn from_app_state(state: &AppState) -> Self {
-        let mut checker = HealthChecker::new(state.version.clone());
+        let mut checker = HealthChecker::new(state.version.to_string()).with_metrics(state.metrics.clone());
 
         if let Some(db_manager) = &state.db_manager {
             checker = checker.add_check(DatabaseHealthCheck::new(db_manager.pool().clone()));
+            checker = checker.add_check(IntegrityHealthCheck::new(state.last_integrity_check.clone()));
         }
 
         let mut fs_paths = vec!["./".to_string()];
         
-        if let Some(_file_manager) = &state.file_manager {
-            fs_paths.push("./uploads".to_string());
+        if let Some(file_manager) = &state.file_manager {
             fs_paths.push("./temp".to_string());
+            checker = checker.add_check(
+                FileStorageHealthCheck::new(
+                    file_manager.storage_path().to_path_buf(),
+                    file_manager.min_free_space(),
+                )
+                .with_reconciliation(file_manager.last_reconciliation().clone()),
+            );
         }
         
         checker = checker.add_check(FilesystemHealthCheck::new(fs_paths));
@@ -447,6 +947,9 @@ impl HealthChecker {
             ));
         }
 
+        checker = checker.add_check(BackgroundTasksHealthCheck::new(state.supervisor.clone()));
+        checker = checker.add_check(FeatureSwitchesHealthCheck::new(state.features.clone()));
+
         checker
     }
 }
\ No newline at end of file
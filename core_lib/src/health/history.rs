@@ -0,0 +1,256 @@
+//! Log of component health transitions
+
+use super::checks::HealthStatus;
+use crate::error::{AppError, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, Row, SqlitePool};
+use std::collections::VecDeque;
+
+pub const DEFAULT_HISTORY_LIMIT: usize = 100;
+pub const MAX_HISTORY_LIMIT: usize = 1000;
+
+/// A component moving from one reported status to another.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthTransition {
+    pub timestamp: DateTime<Utc>,
+    pub component: String,
+    pub from: HealthStatus,
+    pub to: HealthStatus,
+    /// Message of the check that caused the transition.
+    pub message: String,
+    /// Error reported by the failing check, if any.
+    pub error: Option<String>,
+    /// How long the component had been in `from`.
+    pub previous_state_duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HealthHistoryQuery {
+    pub component: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+impl HealthHistoryQuery {
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_HISTORY_LIMIT).min(MAX_HISTORY_LIMIT)
+    }
+
+    fn matches(&self, transition: &HealthTransition) -> bool {
+        self.component.as_ref().is_none_or(|component| &transition.component == component)
+            && self.since.is_none_or(|since| transition.timestamp >= since)
+            && self.until.is_none_or(|until| transition.timestamp <= until)
+    }
+}
+
+fn timestamp_text(timestamp: DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/// Persists transitions in the `health_transitions` table so the history
+/// survives restarts.
+#[derive(Clone)]
+pub struct HealthHistoryStore {
+    pool: SqlitePool,
+}
+
+impl HealthHistoryStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn insert(&self, transition: &HealthTransition) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO health_transitions
+                (component, from_status, to_status, message, error, previous_state_duration_ms, occurred_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&transition.component)
+        .bind(transition.from.to_string())
+        .bind(transition.to.to_string())
+        .bind(&transition.message)
+        .bind(&transition.error)
+        .bind(transition.previous_state_duration_ms as i64)
+        .bind(timestamp_text(transition.timestamp))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Matching transitions, newest first.
+    pub async fn query(&self, query: &HealthHistoryQuery) -> Result<Vec<HealthTransition>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT component, from_status, to_status, message, error, previous_state_duration_ms, occurred_at
+            FROM health_transitions
+            WHERE (?1 IS NULL OR component = ?1)
+              AND (?2 IS NULL OR occurred_at >= ?2)
+              AND (?3 IS NULL OR occurred_at <= ?3)
+            ORDER BY occurred_at DESC, id DESC
+            LIMIT ?4
+            "#,
+        )
+        .bind(&query.component)
+        .bind(query.since.map(timestamp_text))
+        .bind(query.until.map(timestamp_text))
+        .bind(query.limit() as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(row_to_transition).collect()
+    }
+}
+
+fn row_to_transition(row: &SqliteRow) -> Result<HealthTransition> {
+    let status = |column: &str| -> Result<HealthStatus> {
+        let value: String = row.try_get(column)?;
+        value.parse().map_err(AppError::Database)
+    };
+    let occurred_at: String = row.try_get("occurred_at")?;
+
+    Ok(HealthTransition {
+        timestamp: DateTime::parse_from_rfc3339(&occurred_at)
+            .map_err(|e| AppError::Database(format!("Invalid transition timestamp: {}", e)))?
+            .with_timezone(&Utc),
+        component: row.try_get("component")?,
+        from: status("from_status")?,
+        to: status("to_status")?,
+        message: row.try_get("message")?,
+        error: row.try_get("error")?,
+        previous_state_duration_ms: row.try_get::<i64, _>("previous_state_duration_ms")?.max(0) as u64,
+    })
+}
+
+/// The most recent transitions in memory, optionally mirrored to a
+/// [`HealthHistoryStore`]. Queries prefer the store, which reaches further
+/// back, and fall back to memory if it fails.
+pub struct HealthHistory {
+    recent: Mutex<VecDeque<HealthTransition>>,
+    capacity: usize,
+    store: Option<HealthHistoryStore>,
+}
+
+impl HealthHistory {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            recent: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            store: None,
+        }
+    }
+
+    pub fn with_store(mut self, store: HealthHistoryStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    pub async fn record(&self, transition: HealthTransition) {
+        {
+            let mut recent = self.recent.lock();
+            if recent.len() == self.capacity {
+                recent.pop_front();
+            }
+            recent.push_back(transition.clone());
+        }
+
+        if let Some(store) = &self.store {
+            if let Err(e) = store.insert(&transition).await {
+                tracing::warn!("Failed to persist health transition for '{}': {}", transition.component, e);
+            }
+        }
+    }
+
+    /// Matching transitions, newest first.
+    pub async fn query(&self, query: &HealthHistoryQuery) -> Vec<HealthTransition> {
+        if let Some(store) = &self.store {
+            match store.query(query).await {
+                Ok(transitions) => return transitions,
+                Err(e) => tracing::warn!("Failed to read persisted health history, using memory: {}", e),
+            }
+        }
+
+        self.recent
+            .lock()
+            .iter()
+            .rev()
+            .filter(|transition| query.matches(transition))
+            .take(query.limit())
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn transition(component: &str, minute: u32, to: HealthStatus) -> HealthTransition {
+        HealthTransition {
+            timestamp: Utc.with_ymd_and_hms(2024, 3, 1, 2, minute, 0).unwrap(),
+            component: component.to_string(),
+            from: HealthStatus::Healthy,
+            to,
+            message: "Database connection failed".to_string(),
+            error: Some("database is locked".to_string()),
+            previous_state_duration_ms: 60_000,
+        }
+    }
+
+    async fn record_sample(history: &HealthHistory) {
+        history.record(transition("database", 1, HealthStatus::Degraded)).await;
+        history.record(transition("filesystem", 2, HealthStatus::Degraded)).await;
+        history.record(transition("database", 3, HealthStatus::Unhealthy)).await;
+        history.record(transition("database", 4, HealthStatus::Healthy)).await;
+    }
+
+    fn database_since_minute_2() -> HealthHistoryQuery {
+        HealthHistoryQuery {
+            component: Some("database".to_string()),
+            since: Some(Utc.with_ymd_and_hms(2024, 3, 1, 2, 2, 0).unwrap()),
+            until: None,
+            limit: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_history_is_bounded_and_filtered() {
+        let history = HealthHistory::new(3);
+        record_sample(&history).await;
+
+        let all = history.query(&HealthHistoryQuery::default()).await;
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].to, HealthStatus::Healthy);
+
+        let database = history.query(&database_since_minute_2()).await;
+        assert_eq!(database.iter().map(|t| t.to.clone()).collect::<Vec<_>>(), vec![HealthStatus::Healthy, HealthStatus::Unhealthy]);
+    }
+
+    #[tokio::test]
+    async fn test_persisted_history_round_trips() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::database::run_migrations(pool.clone()).await.unwrap();
+
+        let history = HealthHistory::new(1).with_store(HealthHistoryStore::new(pool));
+        record_sample(&history).await;
+
+        let database = history.query(&database_since_minute_2()).await;
+        assert_eq!(database.len(), 2);
+        assert_eq!(database[1], transition("database", 3, HealthStatus::Unhealthy));
+
+        let limited = history.query(&HealthHistoryQuery { limit: Some(1), ..Default::default() }).await;
+        assert_eq!(limited.len(), 1);
+        assert_eq!(limited[0].component, "database");
+    }
+}
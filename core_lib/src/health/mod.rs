@@ -1,6 +1,8 @@
 pub mod checks;
+pub mod history;
 
 #[cfg(test)]
 mod tests;
 
-pub use checks::{HealthChecker, HealthStatus, HealthCheck, ComponentHealth, SystemHealth};
\ No newline at end of file
+pub use checks::{HealthChecker, HealthStatus, HealthCheck, ComponentHealth, BackgroundTasksHealthCheck, ComponentState, FileStorageHealthCheck, IntegrityHealthCheck, SystemHealth};
+pub use history::{HealthHistory, HealthHistoryQuery, HealthHistoryStore, HealthTransition};
\ No newline at end of file
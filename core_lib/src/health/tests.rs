@@ -2,7 +2,7 @@
 mod tests {
     use crate::health::checks::{
         HealthChecker, HealthStatus, ComponentHealth, SystemHealth,
-        DatabaseHealthCheck, FilesystemHealthCheck, DependencyHealthCheck, HealthCheck,
+        DatabaseHealthCheck, FilesystemHealthCheck, DependencyHealthCheck, FileStorageHealthCheck, HealthCheck,
     };
     use sqlx::SqlitePool;
     use tempfile::TempDir;
@@ -185,6 +185,28 @@ mod tests {
         assert!(result.message.contains("Some filesystem issues"));
     }
 
+    #[tokio::test]
+    async fn test_file_storage_health_check() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let healthy = FileStorageHealthCheck::new(temp_dir.path().to_path_buf(), 0).check().await;
+        assert_eq!(healthy.status, HealthStatus::Healthy);
+        let details = healthy.details.unwrap();
+        assert_eq!(details["writable"], true);
+        assert!(details["available_bytes"].as_u64().unwrap() > 0);
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+
+        let low = FileStorageHealthCheck::new(temp_dir.path().to_path_buf(), u64::MAX).check().await;
+        assert_eq!(low.status, HealthStatus::Degraded);
+        assert!(low.message.contains("free for uploads"));
+
+        let missing = FileStorageHealthCheck::new(temp_dir.path().join("missing"), 0);
+        let result = missing.check().await;
+        assert_eq!(result.status, HealthStatus::Unhealthy);
+        assert_eq!(result.details.unwrap()["writable"], false);
+        assert_eq!(missing.name(), "files");
+    }
+
     #[tokio::test]
     async fn test_dependency_health_check_success() {
         let health_check = DependencyHealthCheck::new(
@@ -290,6 +312,101 @@ mod tests {
         assert!(nonexistent_result.is_none());
     }
 
+    /// Sleeps for `delay` and counts its runs.
+    struct SlowCheck {
+        name: &'static str,
+        delay: std::time::Duration,
+        runs: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl HealthCheck for SlowCheck {
+        async fn check(&self) -> ComponentHealth {
+            self.runs.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            ComponentHealth::healthy("Done".to_string(), self.delay.as_millis() as u64)
+        }
+
+        fn name(&self) -> &str {
+            self.name
+        }
+    }
+
+    fn slow_check(name: &'static str, delay_ms: u64) -> SlowCheck {
+        SlowCheck {
+            name,
+            delay: std::time::Duration::from_millis(delay_ms),
+            runs: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_health_checks_run_concurrently_within_timeouts() {
+        use std::time::{Duration, Instant};
+
+        let checker = HealthChecker::new("1.0.0".to_string())
+            .with_check_timeout(Duration::from_millis(300))
+            .with_component_check_timeout("quick", Duration::from_millis(50))
+            .with_overall_timeout(Duration::from_millis(500))
+            .add_check(slow_check("hung", 60_000))
+            .add_check(slow_check("quick", 1_000))
+            .add_check(slow_check("first", 100))
+            .add_check(slow_check("second", 100));
+
+        let start = Instant::now();
+        let health = checker.check_all().await;
+        assert!(start.elapsed() < Duration::from_millis(450), "{:?}", start.elapsed());
+
+        assert_eq!(health.overall_status, HealthStatus::Unhealthy);
+        for (name, timeout_ms) in [("hung", 300), ("quick", 50)] {
+            let component = &health.components[name];
+            assert_eq!(component.status, HealthStatus::Unhealthy);
+            let details = component.details.as_ref().unwrap();
+            assert_eq!(details["cause"], "timeout");
+            assert_eq!(details["timeout_ms"], timeout_ms);
+        }
+        assert_eq!(health.components["first"].status, HealthStatus::Healthy);
+        assert_eq!(health.components["second"].status, HealthStatus::Healthy);
+        assert!(health.check_durations_ms["hung"] >= 300);
+        assert!(health.check_durations_ms["first"] >= 100);
+
+        let quick = checker.check_component("quick").await.unwrap();
+        assert_eq!(quick.details.unwrap()["cause"], "timeout");
+    }
+
+    #[tokio::test]
+    async fn test_health_results_are_cached() {
+        use std::time::Duration;
+
+        let check = slow_check("probe", 20);
+        let runs = check.runs.clone();
+        let checker = std::sync::Arc::new(
+            HealthChecker::new("1.0.0".to_string())
+                .with_cache_ttl(Duration::from_millis(200))
+                .add_check(check),
+        );
+
+        let probes: Vec<_> = (0..10)
+            .map(|_| {
+                let checker = checker.clone();
+                tokio::spawn(async move { checker.check_all_cached().await })
+            })
+            .collect();
+        for probe in probes {
+            assert_eq!(probe.await.unwrap().overall_status, HealthStatus::Healthy);
+        }
+        assert_eq!(runs.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        checker.check_all_cached().await;
+        assert_eq!(runs.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // The background monitor's runs refresh the cache too.
+        checker.check_all().await;
+        checker.check_all_cached().await;
+        assert_eq!(runs.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
     #[test]
     fn test_health_status_equality() {
         assert_eq!(HealthStatus::Healthy, HealthStatus::Healthy);
@@ -0,0 +1,55 @@
+//! Injectable generation of the random ids given to jobs and stored files
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub trait IdGenerator: Send + Sync + std::fmt::Debug {
+    fn uuid(&self) -> Uuid;
+}
+
+pub type SharedIdGenerator = Arc<dyn IdGenerator>;
+
+/// Random version 4 UUIDs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomIds;
+
+impl RandomIds {
+    pub fn shared() -> SharedIdGenerator {
+        Arc::new(RandomIds)
+    }
+}
+
+impl IdGenerator for RandomIds {
+    fn uuid(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+}
+
+/// UUIDs counting up from 1, so tests can predict them. Clones share the
+/// counter.
+#[derive(Debug, Clone, Default)]
+pub struct SequentialIds {
+    issued: Arc<AtomicU64>,
+}
+
+impl SequentialIds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn shared(&self) -> SharedIdGenerator {
+        Arc::new(self.clone())
+    }
+
+    /// The id returned by the `n`th call, counting from 1.
+    pub fn nth(n: u64) -> Uuid {
+        Uuid::from_u128(n as u128)
+    }
+}
+
+impl IdGenerator for SequentialIds {
+    fn uuid(&self) -> Uuid {
+        Self::nth(self.issued.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+}
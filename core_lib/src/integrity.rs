@@ -0,0 +1,530 @@
+//! Checks of the references between tables
+//!
+//! Not every reference is a declared foreign key (a job's requester, a
+//! job's result file, an avatar), and declared ones are only enforced on
+//! connections that switch enforcement on, so a crash or a manual edit can
+//! leave rows pointing at rows that are gone: files at deleted items, jobs
+//! at deleted users. Requests following such a reference then fail.
+//!
+//! [`IntegrityChecker`] walks every reference in [`REFERENCES`] whose
+//! tables exist, `integrity.batch_size` rows per query, and reports the
+//! rows whose reference is broken with counts and sample ids. Asked to
+//! repair, it clears the reference or deletes the row, as the reference's
+//! [`Repair`] says, and records the repairs in the audit log. A new table
+//! is covered by adding its references to the list.
+//!
+//! With `integrity.startup` set to `warn` or `repair`, the check runs when
+//! the server starts, for at most `startup_budget_ms`. When that leaves
+//! references unchecked, an `IntegrityCheck` job checks everything again
+//! in the background. Admins can run the check at any time through
+//! `POST /api/admin/integrity/check`. The latest report is shown by the
+//! `integrity` health component, which is informational and never changes
+//! the overall status.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqlitePool};
+use tracing::{info, warn};
+
+use crate::audit::{AuditEvent, AuditLog};
+use crate::config::IntegrityConfig;
+use crate::database::InstrumentedPool;
+use crate::error::Result;
+
+/// Most row ids listed per reference in a report.
+const MAX_SAMPLES: usize = 20;
+
+/// What a repair does to a row whose reference is broken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Repair {
+    /// Sets the referencing column to `NULL`, keeping the row.
+    Clear,
+    /// Deletes the row.
+    Delete,
+    /// Nothing; the row is only reported.
+    None,
+}
+
+/// A column of `table` holding the key of a row in `parent`.
+#[derive(Debug, Clone, Copy)]
+pub struct Reference {
+    pub table: &'static str,
+    pub column: &'static str,
+    /// SQL for the referenced key, over the row as `child`, when it is not
+    /// the column itself. `NULL` references nothing.
+    pub value: Option<&'static str>,
+    /// SQL naming the row in reports.
+    pub row_id: &'static str,
+    pub parent: &'static str,
+    pub parent_key: &'static str,
+    pub repair: Repair,
+}
+
+const fn reference(
+    table: &'static str,
+    column: &'static str,
+    parent: &'static str,
+    parent_key: &'static str,
+    repair: Repair,
+) -> Reference {
+    Reference {
+        table,
+        column,
+        value: None,
+        row_id: "child.id",
+        parent,
+        parent_key,
+        repair,
+    }
+}
+
+/// Every reference checked, parents before children, so that rows deleted
+/// by one repair are seen by the references to them.
+pub const REFERENCES: &[Reference] = &[
+    reference("items", "created_by", "users", "id", Repair::Clear),
+    reference("users", "avatar_file_id", "files", "id", Repair::Clear),
+    reference("files", "item_id", "items", "id", Repair::Clear),
+    // Files must have an uploader, and deleting the row would orphan the
+    // stored file, so these are left for an admin.
+    reference("files", "uploaded_by", "users", "id", Repair::None),
+    Reference {
+        row_id: "child.file_id",
+        ..reference("public_assets", "file_id", "files", "id", Repair::Delete)
+    },
+    // Search exports record the id of the user the exported file is for.
+    Reference {
+        value: Some(
+            "CASE WHEN json_valid(child.payload) THEN \
+             CASE json_type(child.payload, '$.requested_by') WHEN 'integer' \
+             THEN json_extract(child.payload, '$.requested_by') END END",
+        ),
+        ..reference("jobs", "requested_by", "users", "id", Repair::Delete)
+    },
+    reference("jobs", "result_file_id", "files", "id", Repair::Clear),
+    Reference {
+        row_id: "child.job_id || '#' || child.attempt",
+        ..reference("job_attempts", "job_id", "jobs", "id", Repair::Delete)
+    },
+    reference("item_comments", "item_id", "items", "id", Repair::Delete),
+    reference("item_comments", "author_id", "users", "id", Repair::Clear),
+    reference("user_sessions", "user_id", "users", "id", Repair::Delete),
+    reference("refresh_tokens", "session_id", "user_sessions", "id", Repair::Delete),
+    reference("webhooks", "created_by", "users", "id", Repair::Clear),
+    reference("webhook_deliveries", "webhook_id", "webhooks", "id", Repair::Delete),
+];
+
+impl Reference {
+    /// `table.column`, as reports name it.
+    pub fn name(&self) -> String {
+        format!("{}.{}", self.table, self.column)
+    }
+
+    fn value(&self) -> String {
+        match self.value {
+            Some(value) => format!("({})", value),
+            None => format!("child.{}", self.column),
+        }
+    }
+
+    /// SQL true for a `child` row whose reference is broken.
+    fn broken(&self) -> String {
+        let value = self.value();
+        format!(
+            "{value} IS NOT NULL AND NOT EXISTS (SELECT 1 FROM {parent} AS parent WHERE parent.{key} = {value})",
+            value = value,
+            parent = self.parent,
+            key = self.parent_key
+        )
+    }
+
+    /// Reads up to `?2` rows after rowid `?1`, with whether each is broken.
+    fn scan_query(&self) -> String {
+        format!(
+            "SELECT child.rowid AS row, CAST({} AS TEXT) AS row_id, {} AS broken \
+             FROM {} AS child WHERE child.rowid > ?1 ORDER BY child.rowid LIMIT ?2",
+            self.row_id,
+            self.broken(),
+            self.table
+        )
+    }
+
+    /// Repairs the rows among `rows` rowids that are still broken.
+    fn repair_query(&self, rows: usize) -> Option<String> {
+        let placeholders = vec!["?"; rows].join(", ");
+        let statement = match self.repair {
+            Repair::Clear => format!("UPDATE {} AS child SET {} = NULL", self.table, self.column),
+            Repair::Delete => format!("DELETE FROM {} AS child", self.table),
+            Repair::None => return None,
+        };
+        Some(format!(
+            "{} WHERE child.rowid IN ({}) AND {}",
+            statement,
+            placeholders,
+            self.broken()
+        ))
+    }
+}
+
+/// What the check found for one reference.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReferenceReport {
+    /// `table.column`.
+    pub reference: String,
+    /// `table.column` it points at.
+    pub target: String,
+    pub repair: Repair,
+    pub rows_scanned: u64,
+    /// Rows whose reference is broken.
+    pub broken: u64,
+    pub repaired: u64,
+    /// Whether every row was checked.
+    pub complete: bool,
+    pub sample_ids: Vec<String>,
+}
+
+/// What a check found and repaired.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntegrityReport {
+    pub repair: bool,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// Whether every reference was checked before the time ran out.
+    pub complete: bool,
+    pub rows_scanned: u64,
+    pub broken: u64,
+    pub repaired: u64,
+    /// In the order of [`REFERENCES`]; references whose tables do not exist
+    /// are left out, as are those the time ran out before.
+    pub references: Vec<ReferenceReport>,
+}
+
+impl IntegrityReport {
+    fn new(repair: bool) -> Self {
+        let now = Utc::now();
+        Self {
+            repair,
+            started_at: now,
+            finished_at: now,
+            complete: true,
+            rows_scanned: 0,
+            broken: 0,
+            repaired: 0,
+            references: Vec::new(),
+        }
+    }
+}
+
+/// The report of the most recent check, shared with the integrity health
+/// check.
+#[derive(Debug, Clone, Default)]
+pub struct LastIntegrityCheck(Arc<RwLock<Option<IntegrityReport>>>);
+
+impl LastIntegrityCheck {
+    pub fn get(&self) -> Option<IntegrityReport> {
+        self.0.read().clone()
+    }
+
+    fn set(&self, report: IntegrityReport) {
+        *self.0.write() = Some(report);
+    }
+}
+
+/// Checks, and optionally repairs, the references between tables.
+#[derive(Clone)]
+pub struct IntegrityChecker {
+    pool: InstrumentedPool,
+    audit_log: AuditLog,
+    config: IntegrityConfig,
+    last: LastIntegrityCheck,
+}
+
+impl IntegrityChecker {
+    pub fn new(pool: SqlitePool, audit_log: AuditLog, config: IntegrityConfig, last: LastIntegrityCheck) -> Self {
+        Self {
+            pool: pool.into(),
+            audit_log,
+            config,
+            last,
+        }
+    }
+
+    /// Checks every reference on behalf of `actor`, repairing what it finds
+    /// when `repair` is set. With a `budget`, references are left unchecked
+    /// once it is used up, and the report says it is incomplete.
+    pub async fn check(&self, repair: bool, budget: Option<Duration>, actor: &str) -> Result<IntegrityReport> {
+        let deadline = budget.map(|budget| Instant::now() + budget);
+        let tables = self.tables().await?;
+        let mut report = IntegrityReport::new(repair);
+
+        for reference in REFERENCES {
+            if !tables.contains(reference.table) || !tables.contains(reference.parent) {
+                continue;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                report.complete = false;
+                break;
+            }
+            let checked = self.check_reference(reference, repair, deadline).await?;
+            report.rows_scanned += checked.rows_scanned;
+            report.broken += checked.broken;
+            report.repaired += checked.repaired;
+            report.complete &= checked.complete;
+            report.references.push(checked);
+        }
+        report.finished_at = Utc::now();
+
+        let message = format!(
+            "Integrity check by {} (repair: {}, complete: {}): {} of {} rows have broken references, {} repaired",
+            actor, repair, report.complete, report.broken, report.rows_scanned, report.repaired
+        );
+        if report.broken > 0 {
+            warn!("{}", message);
+            for checked in report.references.iter().filter(|checked| checked.broken > 0) {
+                warn!(
+                    "  {} -> {}: {} broken, e.g. {}",
+                    checked.reference,
+                    checked.target,
+                    checked.broken,
+                    checked.sample_ids.join(", ")
+                );
+            }
+        } else {
+            info!("{}", message);
+        }
+        if report.repaired > 0 {
+            self.audit_log.record(
+                AuditEvent::new("integrity.repaired")
+                    .with_actor(actor)
+                    .with_details(serde_json::to_value(&report)?),
+            );
+        }
+        self.last.set(report.clone());
+
+        Ok(report)
+    }
+
+    async fn tables(&self) -> Result<HashSet<String>> {
+        let tables = sqlx::query_scalar::<_, String>("SELECT name FROM sqlite_master WHERE type = 'table'")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(tables.into_iter().collect())
+    }
+
+    /// Pages through `reference.table` by rowid, repairing each batch
+    /// before reading the next.
+    async fn check_reference(
+        &self,
+        reference: &Reference,
+        repair: bool,
+        deadline: Option<Instant>,
+    ) -> Result<ReferenceReport> {
+        let batch_size = self.config.batch_size.max(1);
+        let scan = reference.scan_query();
+        let mut checked = ReferenceReport {
+            reference: reference.name(),
+            target: format!("{}.{}", reference.parent, reference.parent_key),
+            repair: reference.repair,
+            rows_scanned: 0,
+            broken: 0,
+            repaired: 0,
+            complete: true,
+            sample_ids: Vec::new(),
+        };
+        let mut after = 0i64;
+
+        loop {
+            let rows = sqlx::query(&scan)
+                .bind(after)
+                .bind(batch_size as i64)
+                .fetch_all(&self.pool)
+                .await?;
+            let Some(last) = rows.last() else {
+                return Ok(checked);
+            };
+            after = last.get("row");
+
+            let mut broken = Vec::new();
+            for row in &rows {
+                checked.rows_scanned += 1;
+                if !row.get::<bool, _>("broken") {
+                    continue;
+                }
+                checked.broken += 1;
+                if checked.sample_ids.len() < MAX_SAMPLES {
+                    checked.sample_ids.push(row.get::<Option<String>, _>("row_id").unwrap_or_default());
+                }
+                broken.push(row.get::<i64, _>("row"));
+            }
+            if repair && !broken.is_empty() {
+                checked.repaired += self.repair(reference, &broken).await?;
+            }
+
+            if rows.len() < batch_size {
+                return Ok(checked);
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                checked.complete = false;
+                return Ok(checked);
+            }
+        }
+    }
+
+    async fn repair(&self, reference: &Reference, rows: &[i64]) -> Result<u64> {
+        let Some(sql) = reference.repair_query(rows.len()) else {
+            return Ok(0);
+        };
+        let mut query = sqlx::query(&sql);
+        for row in rows {
+            query = query.bind(row);
+        }
+        Ok(query.execute(&self.pool).await?.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::run_migrations;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        run_migrations(pool.clone()).await.unwrap();
+        // Stands in for a crash: rows are written without their parents.
+        sqlx::query("PRAGMA foreign_keys = OFF").execute(&pool).await.unwrap();
+        pool
+    }
+
+    async fn execute(pool: &SqlitePool, sql: &str) {
+        sqlx::query(sql).execute(pool).await.unwrap();
+    }
+
+    async fn seed_broken_references(pool: &SqlitePool) {
+        let now = Utc::now().to_rfc3339();
+        execute(pool, "INSERT INTO users (id, username, email, password_hash) VALUES (1, 'alice', 'alice@example.com', 'x')").await;
+        execute(pool, "INSERT INTO items (id, name) VALUES (1, 'Lamp')").await;
+        for (id, uploaded_by, item_id) in [("f1", 1, "1"), ("f2", 1, "42"), ("f3", 7, "NULL")] {
+            execute(
+                pool,
+                &format!(
+                    "INSERT INTO files (id, filename, original_filename, content_type, size, path, uploaded_by, item_id) \
+                     VALUES ('{0}', '{0}', '{0}.txt', 'text/plain', 1, '/tmp/{0}', {1}, {2})",
+                    id, uploaded_by, item_id
+                ),
+            )
+            .await;
+        }
+        for (id, requested_by) in [("j1", "1"), ("j2", "9"), ("j3", "\"admin\"")] {
+            execute(
+                pool,
+                &format!(
+                    "INSERT INTO jobs (id, job_type, status, payload, created_at) \
+                     VALUES ('{}', 'bulk_export', 'completed', '{{\"requested_by\": {}}}', '{}')",
+                    id, requested_by, now
+                ),
+            )
+            .await;
+        }
+        execute(
+            pool,
+            &format!(
+                "INSERT INTO item_comments (id, item_id, author_id, author, body, created_at) \
+                 VALUES (1, 1, 5, 'bob', 'Nice', '{0}'), (2, 42, 1, 'alice', 'Gone', '{0}')",
+                now
+            ),
+        )
+        .await;
+    }
+
+    fn checker(pool: &SqlitePool, audit_log: &AuditLog, batch_size: usize) -> IntegrityChecker {
+        let config = IntegrityConfig {
+            batch_size,
+            ..IntegrityConfig::default()
+        };
+        IntegrityChecker::new(pool.clone(), audit_log.clone(), config, LastIntegrityCheck::default())
+    }
+
+    fn broken<'a>(report: &'a IntegrityReport, reference: &str) -> &'a ReferenceReport {
+        report.references.iter().find(|checked| checked.reference == reference).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_check_reports_broken_references_in_batches() {
+        let pool = pool().await;
+        seed_broken_references(&pool).await;
+        let audit_log = AuditLog::new();
+
+        let report = checker(&pool, &audit_log, 2).check(false, None, "test").await.unwrap();
+        assert!(report.complete);
+        assert_eq!(report.repaired, 0);
+        assert_eq!(broken(&report, "files.item_id").rows_scanned, 3);
+        assert_eq!(broken(&report, "files.item_id").sample_ids, vec!["f2"]);
+        assert_eq!(broken(&report, "files.uploaded_by").sample_ids, vec!["f3"]);
+        assert_eq!(broken(&report, "jobs.requested_by").sample_ids, vec!["j2"]);
+        assert_eq!(broken(&report, "item_comments.item_id").sample_ids, vec!["2"]);
+        assert_eq!(broken(&report, "item_comments.author_id").sample_ids, vec!["1"]);
+        assert_eq!(report.broken, 5);
+        assert!(audit_log.recent(Some("integrity.repaired"), 10).is_empty());
+
+        let item_id: Option<i64> = sqlx::query_scalar("SELECT item_id FROM files WHERE id = 'f2'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(item_id, Some(42));
+    }
+
+    #[tokio::test]
+    async fn test_repair_follows_each_references_policy() {
+        let pool = pool().await;
+        seed_broken_references(&pool).await;
+        let audit_log = AuditLog::new();
+        let checker = checker(&pool, &audit_log, 1000);
+
+        let report = checker.check(true, None, "test").await.unwrap();
+        assert_eq!(report.broken, 5);
+        // The file without an uploader is only reported.
+        assert_eq!(report.repaired, 4);
+        assert_eq!(audit_log.recent(Some("integrity.repaired"), 10).len(), 1);
+
+        let item_id: Option<i64> = sqlx::query_scalar("SELECT item_id FROM files WHERE id = 'f2'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(item_id, None);
+        let jobs: Vec<String> = sqlx::query_scalar("SELECT id FROM jobs ORDER BY id").fetch_all(&pool).await.unwrap();
+        assert_eq!(jobs, vec!["j1", "j3"]);
+        let comments: Vec<(i64, Option<i64>)> = sqlx::query_as("SELECT id, author_id FROM item_comments")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(comments, vec![(1, None)]);
+
+        let again = checker.check(true, None, "test").await.unwrap();
+        assert_eq!((again.broken, again.repaired), (1, 0));
+        assert_eq!(checker.last.get().unwrap().broken, 1);
+    }
+
+    #[tokio::test]
+    async fn test_spent_budget_leaves_the_check_incomplete() {
+        let pool = pool().await;
+        seed_broken_references(&pool).await;
+
+        let report = checker(&pool, &AuditLog::new(), 1)
+            .check(true, Some(Duration::ZERO), "startup")
+            .await
+            .unwrap();
+        assert!(!report.complete);
+        assert!(report.references.is_empty());
+        assert_eq!(report.rows_scanned, 0);
+    }
+}
@@ -0,0 +1,147 @@
+//! Limits on the size of item metadata
+//!
+//! The item service checks every metadata value it is asked to write, and
+//! snapshot imports check every item they bring in, so whichever API an
+//! item arrives through it is stored only within `items.max_metadata_bytes`
+//! serialized, `items.max_metadata_depth` levels of nesting and
+//! `items.max_metadata_keys` object keys in all. Metadata over a limit is
+//! refused with [`AppError::PayloadTooLarge`]. Depth and keys are counted
+//! without recursion, before the value is serialized or walked by anything
+//! that recurses, so deeply nested metadata can't exhaust the stack.
+
+use std::io;
+
+use serde_json::Value;
+
+use crate::config::ItemConfig;
+use crate::error::{AppError, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataLimits {
+    /// Most bytes metadata may take serialized as compact JSON.
+    pub max_bytes: usize,
+    /// Most arrays and objects metadata may nest; `{"a": {}}` is two deep.
+    pub max_depth: usize,
+    /// Most keys across all the objects in metadata.
+    pub max_keys: usize,
+}
+
+impl Default for MetadataLimits {
+    fn default() -> Self {
+        Self::from_config(&ItemConfig::default())
+    }
+}
+
+impl MetadataLimits {
+    pub fn from_config(config: &ItemConfig) -> Self {
+        Self {
+            max_bytes: config.max_metadata_bytes,
+            max_depth: config.max_metadata_depth,
+            max_keys: config.max_metadata_keys,
+        }
+    }
+
+    /// Refuses `metadata` over any of the limits.
+    pub fn check(&self, metadata: &Value) -> Result<()> {
+        self.check_shape(metadata)?;
+
+        let mut counter = ByteCounter { bytes: 0, limit: self.max_bytes };
+        if serde_json::to_writer(&mut counter, metadata).is_err() {
+            return Err(AppError::PayloadTooLarge(format!(
+                "Metadata is larger than {} bytes",
+                self.max_bytes
+            )));
+        }
+        Ok(())
+    }
+
+    fn check_shape(&self, metadata: &Value) -> Result<()> {
+        let mut keys = 0;
+        let mut pending = vec![(metadata, 0)];
+        while let Some((value, depth)) = pending.pop() {
+            let children: Box<dyn Iterator<Item = &Value>> = match value {
+                Value::Array(values) => Box::new(values.iter()),
+                Value::Object(map) => {
+                    keys += map.len();
+                    Box::new(map.values())
+                }
+                _ => continue,
+            };
+
+            let depth = depth + 1;
+            if depth > self.max_depth {
+                return Err(AppError::PayloadTooLarge(format!(
+                    "Metadata is nested more than {} levels deep",
+                    self.max_depth
+                )));
+            }
+            if keys > self.max_keys {
+                return Err(AppError::PayloadTooLarge(format!(
+                    "Metadata has more than {} keys",
+                    self.max_keys
+                )));
+            }
+            pending.extend(children.map(|child| (child, depth)));
+        }
+        Ok(())
+    }
+}
+
+/// Counts what is written to it, failing once past `limit` so that
+/// serializing oversized metadata stops early.
+struct ByteCounter {
+    bytes: usize,
+    limit: usize,
+}
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes += buf.len();
+        if self.bytes > self.limit {
+            return Err(io::Error::other("metadata size limit exceeded"));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const LIMITS: MetadataLimits = MetadataLimits { max_bytes: 64, max_depth: 3, max_keys: 4 };
+
+    fn too_large(metadata: &Value) -> bool {
+        matches!(LIMITS.check(metadata), Err(AppError::PayloadTooLarge(_)))
+    }
+
+    #[test]
+    fn test_metadata_within_limits() {
+        assert!(LIMITS.check(&json!({"a": {"b": [1, 2]}, "c": "d"})).is_ok());
+        assert!(LIMITS.check(&json!("scalar")).is_ok());
+        assert!(LIMITS.check(&json!({"text": "x".repeat(50)})).is_ok());
+    }
+
+    #[test]
+    fn test_metadata_over_limits() {
+        assert!(too_large(&json!({"text": "x".repeat(64)})));
+        assert!(too_large(&json!({"a": {"b": {"c": {}}}})));
+        assert!(too_large(&json!([[[[]]]])));
+        assert!(too_large(&json!({"a": 1, "b": 2, "c": {"d": 3, "e": 4}})));
+
+        // Far deeper than serde_json would parse, built by hand.
+        let mut deep = json!(null);
+        for _ in 0..100_000 {
+            deep = Value::Array(vec![deep]);
+        }
+        assert!(too_large(&deep));
+        // Dropping a value this deep recurses, so unwind it in a loop.
+        while let Value::Array(mut values) = deep {
+            deep = values.pop().unwrap_or_default();
+        }
+    }
+}
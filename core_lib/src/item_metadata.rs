@@ -0,0 +1,155 @@
+//! Item metadata carried as serialized JSON
+//!
+//! Item metadata can be large, and most requests only pass it along: it is
+//! read from the database and written into a response, a WebSocket event or
+//! a webhook. [`Metadata`] therefore holds the JSON text itself, shared
+//! between clones, and writes it out verbatim when serialized with
+//! `serde_json`. A [`Value`] is only built when something has to look
+//! inside, such as a patch, the schema check or a metadata filter.
+//!
+//! The text is always in the form `serde_json` writes a [`Value`] in:
+//! compact, with object keys sorted. Metadata from requests is written out
+//! in that form once, which is also how it is stored, so the JSON the API
+//! returns is the same whether or not it was parsed on the way.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::value::RawValue;
+use serde_json::Value;
+use std::fmt;
+use std::sync::Arc;
+
+/// Item metadata as canonical JSON text.
+#[derive(Clone)]
+pub struct Metadata(Arc<RawValue>);
+
+impl Metadata {
+    /// Metadata holding `value`.
+    pub fn from_value(value: &Value) -> Self {
+        let raw = serde_json::value::to_raw_value(value).expect("a JSON value always serializes");
+        Self(Arc::from(raw))
+    }
+
+    /// Metadata as stored by the item repository, which wrote it with
+    /// [`as_str`](Self::as_str). The text is checked to be JSON but not
+    /// parsed into a value.
+    pub fn from_stored(text: String) -> serde_json::Result<Self> {
+        Ok(Self(Arc::from(RawValue::from_string(text)?)))
+    }
+
+    /// The JSON text.
+    pub fn as_str(&self) -> &str {
+        self.0.get()
+    }
+
+    /// Parses the metadata.
+    pub fn to_value(&self) -> Value {
+        serde_json::from_str(self.as_str()).expect("metadata holds valid JSON")
+    }
+
+    /// Whether the metadata may hold `key` at its top level. A quick check
+    /// of the text that can only be wrong by answering yes, so that the
+    /// metadata is only parsed when it might. The key is looked for as
+    /// `serde_json` writes it, escapes included, since the text is canonical.
+    pub fn may_contain_key(&self, key: &str) -> bool {
+        let needle = serde_json::to_string(key).expect("a string always serializes");
+        self.as_str().starts_with('{') && self.as_str().contains(&needle)
+    }
+
+    /// The value under `key` at the top level, parsing the metadata only
+    /// when it may hold it.
+    pub fn get(&self, key: &str) -> Option<Value> {
+        if !self.may_contain_key(key) {
+            return None;
+        }
+        match self.to_value() {
+            Value::Object(mut object) => object.remove(key),
+            _ => None,
+        }
+    }
+}
+
+impl From<Value> for Metadata {
+    fn from(value: Value) -> Self {
+        Self::from_value(&value)
+    }
+}
+
+impl From<&Value> for Metadata {
+    fn from(value: &Value) -> Self {
+        Self::from_value(value)
+    }
+}
+
+impl PartialEq for Metadata {
+    fn eq(&self, other: &Self) -> bool {
+        // Both are canonical, so equal values have equal text.
+        self.as_str() == other.as_str()
+    }
+}
+
+impl PartialEq<Value> for Metadata {
+    fn eq(&self, other: &Value) -> bool {
+        self.to_value() == *other
+    }
+}
+
+impl fmt::Debug for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Metadata {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Metadata {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Parsed rather than kept as sent, so that the text is canonical.
+        Value::deserialize(deserializer).map(|value| Self::from_value(&value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_serializes_as_the_value_would() {
+        let value = json!({"zeta": [1, 2.5, null], "alpha": {"b": "x y", "a": true}, "n": 1e3});
+        let metadata = Metadata::from_value(&value);
+        assert_eq!(serde_json::to_string(&metadata).unwrap(), serde_json::to_string(&value).unwrap());
+        assert_eq!(serde_json::to_value(&metadata).unwrap(), value);
+        assert_eq!(metadata.to_value(), value);
+
+        let stored = Metadata::from_stored(metadata.as_str().to_string()).unwrap();
+        assert_eq!(stored, metadata);
+        assert!(Metadata::from_stored("{not json".to_string()).is_err());
+
+        // Sent in another order or spacing, it is still written canonically.
+        let sent: Metadata = serde_json::from_str(r#"{ "n": 1000.0, "alpha": {"a": true, "b": "x y"}, "zeta": [1, 2.5, null] }"#).unwrap();
+        assert_eq!(sent, metadata);
+    }
+
+    #[test]
+    fn test_key_lookup_only_parses_when_the_key_may_be_there() {
+        let metadata = Metadata::from_value(&json!({"secret": {"k": 1}, "note": "a \"secret\" mention"}));
+        assert_eq!(metadata.get("secret"), Some(json!({"k": 1})));
+        assert_eq!(metadata.get("missing"), None);
+
+        let nested = Metadata::from_value(&json!({"outer": {"secret": 1}}));
+        assert!(nested.may_contain_key("secret"));
+        assert_eq!(nested.get("secret"), None);
+        assert!(!Metadata::from_value(&json!(["secret"])).may_contain_key("secret"));
+
+        // Keys JSON has to escape are found as written.
+        for key in ["say \"hi\"", "C:\\temp", "line\nbreak", "tab\tand\u{1}"] {
+            let escaped = Metadata::from_value(&json!({ key: 1 }));
+            assert!(escaped.may_contain_key(key), "{:?}", key);
+            assert_eq!(escaped.get(key), Some(json!(1)));
+        }
+    }
+}
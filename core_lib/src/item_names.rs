@@ -0,0 +1,290 @@
+//! Optional uniqueness of item names
+//!
+//! With `items.unique_names` set to `global` or `namespace`, no two items
+//! outside the trash may share a name, across all namespaces or within
+//! each. While the rule is on, names are stored with surrounding whitespace
+//! trimmed and compared as stored, except that ASCII letters match either
+//! case unless `items.unique_names_case_sensitive` is set. Other letters
+//! only ever match themselves: this is SQLite's `NOCASE` collation, which
+//! the memory store follows so that both backends agree.
+//!
+//! The item service checks the rule on every create, update and patch,
+//! whichever API or form the write came from, and answers 409 naming the
+//! item that already has the name. Snapshot imports report such items as
+//! conflicts. The database also holds a unique index for the rule, created
+//! at startup, so that two concurrent writes cannot both pass the check;
+//! the memory store checks under its write lock. When stored items already
+//! break the rule the index cannot be built. The server then starts without
+//! it and queues a `NameValidation` job listing the duplicates, which can
+//! be run again through `POST /api/admin/items/names/validate`.
+
+use crate::audit::{AuditEvent, AuditLog};
+use crate::config::{ItemConfig, NameScope};
+use crate::error::{AppError, Result};
+use crate::services::ItemService;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+/// Indexes the rules can create, dropped when another rule is in force.
+const NAME_INDEXES: [&str; 4] = [
+    "idx_items_unique_name_global_nocase",
+    "idx_items_unique_name_global_binary",
+    "idx_items_unique_name_namespace_nocase",
+    "idx_items_unique_name_namespace_binary",
+];
+
+/// A write refused because another item already has the name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NameConflict {
+    pub name: String,
+    /// The item that has it.
+    pub item_id: u64,
+}
+
+impl NameConflict {
+    pub fn new(name: impl Into<String>, item_id: u64) -> Self {
+        Self { name: name.into(), item_id }
+    }
+}
+
+impl From<NameConflict> for AppError {
+    fn from(conflict: NameConflict) -> Self {
+        AppError::NameConflict(Box::new(conflict))
+    }
+}
+
+/// Which item names clash, as configured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NameRule {
+    pub scope: NameScope,
+    pub case_sensitive: bool,
+}
+
+impl NameRule {
+    pub fn from_config(config: &ItemConfig) -> Self {
+        Self {
+            scope: config.unique_names,
+            case_sensitive: config.unique_names_case_sensitive,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.scope != NameScope::Off
+    }
+
+    /// `name` as it is stored: trimmed while the rule is on.
+    pub fn normalize(&self, name: String) -> String {
+        match self.is_enabled() {
+            true if name.trim().len() != name.len() => name.trim().to_string(),
+            _ => name,
+        }
+    }
+
+    /// Whether `a` and `b` are the same name under this rule. Scopes are
+    /// left to the caller.
+    pub fn matches(&self, a: &str, b: &str) -> bool {
+        match self.case_sensitive {
+            true => a == b,
+            false => a.eq_ignore_ascii_case(b),
+        }
+    }
+
+    /// What [`matches`](Self::matches) compares of `name`, for grouping.
+    pub fn key(&self, name: &str) -> String {
+        match self.case_sensitive {
+            true => name.to_string(),
+            false => name.to_ascii_lowercase(),
+        }
+    }
+
+    fn collation(&self) -> &'static str {
+        match self.case_sensitive {
+            true => "BINARY",
+            false => "NOCASE",
+        }
+    }
+
+    /// The name of the index backing this rule, if it has one.
+    pub(crate) fn index_name(&self) -> Option<&'static str> {
+        let index = match (self.scope, self.case_sensitive) {
+            (NameScope::Off, _) => return None,
+            (NameScope::Global, false) => NAME_INDEXES[0],
+            (NameScope::Global, true) => NAME_INDEXES[1],
+            (NameScope::Namespace, false) => NAME_INDEXES[2],
+            (NameScope::Namespace, true) => NAME_INDEXES[3],
+        };
+        Some(index)
+    }
+
+    /// Statements dropping the indexes of every other rule.
+    pub(crate) fn drop_other_indexes(&self) -> Vec<String> {
+        NAME_INDEXES
+            .iter()
+            .filter(|index| Some(**index) != self.index_name())
+            .map(|index| format!("DROP INDEX IF EXISTS {}", index))
+            .collect()
+    }
+
+    /// The statement creating this rule's index.
+    pub(crate) fn create_index(&self) -> Option<String> {
+        let index = self.index_name()?;
+        let namespace = match self.scope {
+            NameScope::Namespace => "namespace, ",
+            _ => "",
+        };
+        Some(format!(
+            "CREATE UNIQUE INDEX IF NOT EXISTS {} ON items ({}name COLLATE {}) WHERE deleted_at IS NULL",
+            index,
+            namespace,
+            self.collation()
+        ))
+    }
+
+    /// Finds the lowest id of a live item other than `?2` named `?1`. With
+    /// a namespace scope, the namespace is that of item `?2`, or `?3` for a
+    /// new item.
+    pub(crate) fn lookup_query(&self) -> String {
+        let namespace = match self.scope {
+            NameScope::Namespace => "AND namespace = COALESCE((SELECT namespace FROM items WHERE id = ?2), ?3)",
+            _ => "",
+        };
+        format!(
+            "SELECT id FROM items WHERE deleted_at IS NULL AND name = ?1 COLLATE {} AND id IS NOT ?2 {} ORDER BY id LIMIT 1",
+            self.collation(),
+            namespace
+        )
+    }
+
+    /// Groups the live items sharing a name, as `namespace`, `name`, `ids`
+    /// rows; `namespace` is `NULL` for a global scope.
+    pub(crate) fn duplicates_query(&self) -> String {
+        let (namespace, group) = match self.scope {
+            NameScope::Namespace => ("namespace", "namespace, "),
+            _ => ("NULL", ""),
+        };
+        format!(
+            r#"
+            SELECT {} AS namespace, MIN(name) AS name, group_concat(id) AS ids
+            FROM items
+            WHERE deleted_at IS NULL
+            GROUP BY {}name COLLATE {}
+            HAVING COUNT(*) > 1
+            ORDER BY MIN(id)
+            "#,
+            namespace,
+            group,
+            self.collation()
+        )
+    }
+}
+
+/// Items sharing one name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DuplicateName {
+    pub name: String,
+    /// Where they clash, for a namespace scope.
+    pub namespace: Option<String>,
+    /// In id order.
+    pub item_ids: Vec<u64>,
+}
+
+/// The outcome of checking stored items against the rule.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NameReport {
+    /// Names used by more than one item.
+    pub duplicate_names: u64,
+    /// Items whose name another item also has.
+    pub duplicate_items: u64,
+    /// The first [`NameChecker::MAX_REPORTED`] duplicated names.
+    pub names: Vec<DuplicateName>,
+}
+
+/// Lists the stored items breaking the name rule, recording every run in
+/// the audit log. Nothing is changed.
+#[derive(Clone)]
+pub struct NameChecker {
+    items: ItemService,
+    audit_log: AuditLog,
+}
+
+impl NameChecker {
+    pub const MAX_REPORTED: usize = 1000;
+
+    pub fn new(items: ItemService, audit_log: AuditLog) -> Self {
+        Self { items, audit_log }
+    }
+
+    /// Checks every item, in every namespace, on behalf of `actor`.
+    pub async fn check(&self, actor: &str) -> Result<NameReport> {
+        let duplicates = self.items.duplicate_names().await?;
+        let report = NameReport {
+            duplicate_names: duplicates.len() as u64,
+            duplicate_items: duplicates.iter().map(|duplicate| duplicate.item_ids.len() as u64).sum(),
+            names: duplicates.into_iter().take(Self::MAX_REPORTED).collect(),
+        };
+
+        info!(
+            "Item name check by {}: {} names are used by {} items",
+            actor, report.duplicate_names, report.duplicate_items
+        );
+        self.audit_log.record(
+            AuditEvent::new("items.names_checked")
+                .with_actor(actor)
+                .with_details(serde_json::json!({
+                    "duplicate_names": report.duplicate_names,
+                    "duplicate_items": report.duplicate_items,
+                })),
+        );
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(scope: NameScope, case_sensitive: bool) -> NameRule {
+        NameRule { scope, case_sensitive }
+    }
+
+    #[test]
+    fn test_trimming_and_case_rules() {
+        let off = NameRule::default();
+        assert_eq!(off.normalize(" Lamp ".to_string()), " Lamp ");
+        assert_eq!(off.create_index(), None);
+
+        let folded = rule(NameScope::Global, false);
+        assert_eq!(folded.normalize(" Lamp\t".to_string()), "Lamp");
+        assert!(folded.matches("Lamp", "LAMP"));
+        assert!(!folded.matches("Lamp", "Lamp "));
+        // Only ASCII letters are folded, as by SQLite's NOCASE.
+        assert!(!folded.matches("Ärger", "ärger"));
+        assert_eq!(folded.key("LAMP"), folded.key("lamp"));
+
+        let exact = rule(NameScope::Namespace, true);
+        assert!(!exact.matches("Lamp", "lamp"));
+        assert!(exact.matches("Lamp", "Lamp"));
+    }
+
+    #[test]
+    fn test_each_rule_has_its_own_index() {
+        let rules = [
+            rule(NameScope::Global, false),
+            rule(NameScope::Global, true),
+            rule(NameScope::Namespace, false),
+            rule(NameScope::Namespace, true),
+        ];
+        let names: std::collections::HashSet<_> = rules.iter().filter_map(NameRule::index_name).collect();
+        assert_eq!(names.len(), rules.len());
+        for rule in rules {
+            let drops = rule.drop_other_indexes();
+            assert_eq!(drops.len(), NAME_INDEXES.len() - 1);
+            assert!(!drops.iter().any(|drop| drop.ends_with(rule.index_name().unwrap())));
+        }
+        assert!(rule(NameScope::Namespace, false)
+            .create_index()
+            .unwrap()
+            .contains("(namespace, name COLLATE NOCASE) WHERE deleted_at IS NULL"));
+    }
+}
@@ -0,0 +1,126 @@
+//! Required item metadata
+//!
+//! [`ItemSchemaConfig`] names the metadata keys a deployment requires. The
+//! item service checks it on every create, update and metadata patch;
+//! reads never are, so a reloaded schema applies only to new writes. Items
+//! written before a change are found by a `SchemaValidation` job, started
+//! through `POST /api/admin/items/schema/validate`.
+
+use crate::audit::{AuditEvent, AuditLog};
+use crate::config::ItemSchemaConfig;
+use crate::error::Result;
+use crate::item_metadata::Metadata;
+use crate::services::ItemService;
+use crate::validation::{ItemValidator, ValidationResult};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tracing::info;
+
+/// The schema in force, shared by every clone and replaced in place when
+/// the configuration is reloaded.
+#[derive(Clone, Default)]
+pub struct ItemSchema {
+    config: Arc<RwLock<Arc<ItemSchemaConfig>>>,
+}
+
+impl ItemSchema {
+    pub fn new(config: ItemSchemaConfig) -> Self {
+        Self { config: Arc::new(RwLock::new(Arc::new(config))) }
+    }
+
+    pub fn current(&self) -> Arc<ItemSchemaConfig> {
+        self.config.read().clone()
+    }
+
+    /// Applies `config` to writes from now on.
+    pub fn update_config(&self, config: ItemSchemaConfig) {
+        *self.config.write() = Arc::new(config);
+    }
+
+    pub fn check(&self, metadata: Option<&serde_json::Value>) -> ValidationResult {
+        ItemValidator::validate_metadata_schema(&self.current(), metadata)
+    }
+}
+
+/// An item whose metadata does not satisfy the current schema.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NonconformingItem {
+    pub id: u64,
+    pub name: String,
+    /// Messages per `metadata.<key>`.
+    pub errors: BTreeMap<String, Vec<String>>,
+}
+
+/// The outcome of checking every item against the schema.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SchemaReport {
+    pub checked: u64,
+    pub nonconforming: u64,
+    /// The first [`SchemaChecker::MAX_REPORTED`] nonconforming items.
+    pub items: Vec<NonconformingItem>,
+}
+
+/// Checks stored items against the schema, recording every run in the
+/// audit log. Nothing is changed.
+#[derive(Clone)]
+pub struct SchemaChecker {
+    items: ItemService,
+    schema: ItemSchema,
+    audit_log: AuditLog,
+}
+
+impl SchemaChecker {
+    pub const MAX_REPORTED: usize = 1000;
+    const PAGE_SIZE: usize = 500;
+
+    pub fn new(items: ItemService, schema: ItemSchema, audit_log: AuditLog) -> Self {
+        Self { items, schema, audit_log }
+    }
+
+    /// Checks every item on behalf of `actor`.
+    pub async fn check(&self, actor: &str) -> Result<SchemaReport> {
+        let schema = self.schema.current();
+        let mut report = SchemaReport::default();
+        let mut offset = 0;
+
+        loop {
+            let page = self.items.get_items(Some(Self::PAGE_SIZE), Some(offset)).await?;
+            for item in &page {
+                report.checked += 1;
+                let result = ItemValidator::validate_metadata_schema(&schema, item.metadata.as_ref().map(Metadata::to_value).as_ref());
+                if result.is_valid {
+                    continue;
+                }
+                report.nonconforming += 1;
+                if report.items.len() < Self::MAX_REPORTED {
+                    report.items.push(NonconformingItem {
+                        id: item.id,
+                        name: item.name.clone(),
+                        errors: result.errors.into_iter().collect(),
+                    });
+                }
+            }
+            if page.len() < Self::PAGE_SIZE {
+                break;
+            }
+            offset += page.len();
+        }
+
+        info!(
+            "Item schema check by {}: {} of {} items do not conform",
+            actor, report.nonconforming, report.checked
+        );
+        self.audit_log.record(
+            AuditEvent::new("items.schema_checked")
+                .with_actor(actor)
+                .with_details(serde_json::json!({
+                    "checked": report.checked,
+                    "nonconforming": report.nonconforming,
+                })),
+        );
+
+        Ok(report)
+    }
+}
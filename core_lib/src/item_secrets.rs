@@ -0,0 +1,531 @@
+//! Secrets kept in item metadata
+//!
+//! Whatever an item keeps under `metadata.secret` is only returned to users
+//! holding [`READ_SECRETS`]; everyone else, and every export, audit entry,
+//! capture, WebSocket event and webhook, gets [`REDACTED`] in its place. A
+//! write that sends [`REDACTED`] back keeps the stored secret. With
+//! [`ItemSecretsConfig::enabled`], the item service also seals secrets with
+//! AES-256-GCM before they reach the database or the memory store, as
+//! `enc:v1:<key id>:<hex>`. A secret sealed under a key that is no longer
+//! configured is reported as an error naming the key, never returned as
+//! ciphertext. After the key is rotated, a `SecretRekey` job, started
+//! through `POST /api/admin/items/secrets/rekey`, seals every secret under
+//! the new one.
+
+use crate::audit::{AuditEvent, AuditLog};
+use crate::auth::models::UserRole;
+use crate::config::ItemSecretsConfig;
+use crate::error::{AppError, Result};
+use crate::item_metadata::Metadata;
+use crate::middleware::auth::AuthUser;
+use crate::services::ItemService;
+use crate::store::Item;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::Arc;
+use tracing::info;
+
+/// The metadata key holding an item's secret.
+pub const SECRET_FIELD: &str = "secret";
+/// Shown instead of a secret to those who may not read it.
+pub const REDACTED: &str = "***";
+/// The permission to read secrets, held by the configured read roles.
+pub const READ_SECRETS: &str = "items:read_secrets";
+
+const SEALED_PREFIX: &str = "enc:v1:";
+
+struct SecretKey {
+    /// The first four bytes of the key's SHA-256, in hex, recorded with
+    /// everything sealed under it.
+    id: String,
+    key: LessSafeKey,
+}
+
+impl SecretKey {
+    fn from_hex(hex_key: &str, source: &str) -> Result<Self> {
+        let invalid = || AppError::Configuration(format!("Item secret key {} is not 64 hex characters", source));
+        let bytes = hex::decode(hex_key.trim()).map_err(|_| invalid())?;
+        let key = UnboundKey::new(&AES_256_GCM, &bytes).map_err(|_| invalid())?;
+
+        Ok(Self {
+            id: hex::encode(&Sha256::digest(&bytes)[..4]),
+            key: LessSafeKey::new(key),
+        })
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            AppError::Configuration(format!("Cannot read item secret key file {}: {}", path.display(), e))
+        })?;
+        Self::from_hex(&contents, &path.display().to_string())
+    }
+
+    fn seal(&self, secret: &Value) -> Result<Value> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| AppError::InternalServerError)?;
+
+        let mut sealed = serde_json::to_vec(secret)?;
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut sealed)
+            .map_err(|_| AppError::InternalServerError)?;
+
+        let mut bytes = nonce.to_vec();
+        bytes.extend_from_slice(&sealed);
+        Ok(Value::String(format!("{}{}:{}", SEALED_PREFIX, self.id, hex::encode(bytes))))
+    }
+
+    fn open(&self, item_id: u64, sealed: &str) -> Result<Value> {
+        let damaged = || {
+            AppError::SecretUnavailable(format!(
+                "The secret of item {} cannot be decrypted with key {}: the stored value is damaged",
+                item_id, self.id
+            ))
+        };
+        let mut bytes = hex::decode(sealed).map_err(|_| damaged())?;
+        if bytes.len() < NONCE_LEN {
+            return Err(damaged());
+        }
+
+        let mut ciphertext = bytes.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&bytes).map_err(|_| damaged())?;
+        let plaintext = self
+            .key
+            .open_in_place(nonce, Aad::empty(), &mut ciphertext)
+            .map_err(|_| damaged())?;
+        serde_json::from_slice(plaintext).map_err(|_| damaged())
+    }
+}
+
+#[derive(Default)]
+struct Keyring {
+    /// What secrets are sealed under; none when encryption is off.
+    current: Option<SecretKey>,
+    /// Retired keys that can still open what they sealed.
+    previous: Vec<SecretKey>,
+}
+
+impl Keyring {
+    fn get(&self, id: &str) -> Option<&SecretKey> {
+        self.current.iter().chain(&self.previous).find(|key| key.id == id)
+    }
+}
+
+/// Who may read item secrets and the keys they are sealed under. Clones
+/// share the keys.
+#[derive(Clone)]
+pub struct ItemSecrets {
+    keys: Arc<Keyring>,
+    read_roles: Arc<Vec<UserRole>>,
+}
+
+impl Default for ItemSecrets {
+    fn default() -> Self {
+        Self {
+            keys: Arc::new(Keyring::default()),
+            read_roles: Arc::new(vec![UserRole::Admin]),
+        }
+    }
+}
+
+impl ItemSecrets {
+    /// Loads the keys `config` names, failing if any of them is missing or
+    /// malformed.
+    pub fn from_config(config: &ItemSecretsConfig) -> Result<Self> {
+        let read_roles = config
+            .read_roles
+            .iter()
+            .map(|role| role.parse().map_err(AppError::Configuration))
+            .collect::<Result<Vec<UserRole>>>()?;
+
+        let mut keys = Keyring::default();
+        if config.enabled {
+            keys.current = Some(match (&config.key, &config.key_file) {
+                (Some(key), _) => SecretKey::from_hex(key, "item_secrets.key")?,
+                (None, Some(path)) => SecretKey::from_file(path)?,
+                (None, None) => {
+                    return Err(AppError::Configuration(
+                        "Item secrets are enabled but no key is configured".to_string(),
+                    ))
+                }
+            });
+            for (index, key) in config.previous_keys.iter().enumerate() {
+                keys.previous
+                    .push(SecretKey::from_hex(key, &format!("item_secrets.previous_keys[{}]", index))?);
+            }
+            for path in &config.previous_key_files {
+                keys.previous.push(SecretKey::from_file(path)?);
+            }
+        }
+
+        Ok(Self {
+            keys: Arc::new(keys),
+            read_roles: Arc::new(read_roles),
+        })
+    }
+
+    /// Whether secrets are sealed before they are stored.
+    pub fn is_enabled(&self) -> bool {
+        self.keys.current.is_some()
+    }
+
+    /// Id of the key new secrets are sealed under.
+    pub fn current_key_id(&self) -> Option<&str> {
+        self.keys.current.as_ref().map(|key| key.id.as_str())
+    }
+
+    /// Whether `user` holds [`READ_SECRETS`].
+    pub fn can_read(&self, user: Option<&AuthUser>) -> bool {
+        user.is_some_and(|user| self.read_roles.contains(&user.role))
+    }
+
+    /// Prepares `metadata` to be written over `stored`: a [`REDACTED`]
+    /// secret is replaced by the stored one, and the secret is sealed when
+    /// encryption is on. Secrets that arrive looking sealed are sealed like
+    /// any other value.
+    pub fn seal(&self, metadata: &mut Value, stored: Option<&Value>) -> Result<()> {
+        let Some(secret) = metadata.get_mut(SECRET_FIELD).filter(|secret| !secret.is_null()) else {
+            return Ok(());
+        };
+
+        if secret == REDACTED {
+            *secret = stored
+                .and_then(|stored| stored.get(SECRET_FIELD))
+                .filter(|stored| !stored.is_null())
+                .cloned()
+                .ok_or_else(|| {
+                    AppError::BadRequest(format!(
+                        "metadata.{} is \"{}\", which keeps the stored secret, but the item has none",
+                        SECRET_FIELD, REDACTED
+                    ))
+                })?;
+            if sealed_key_id(secret).is_some() {
+                return Ok(());
+            }
+        }
+
+        if let Some(key) = &self.keys.current {
+            *secret = key.seal(secret)?;
+        }
+        Ok(())
+    }
+
+    /// The secret as it was written, unsealing it if it was sealed.
+    pub fn open(&self, item_id: u64, secret: &Value) -> Result<Value> {
+        let Some((key_id, sealed)) = split_sealed(secret) else {
+            return Ok(secret.clone());
+        };
+
+        let key = self.keys.get(key_id).ok_or_else(|| {
+            AppError::SecretUnavailable(format!(
+                "The secret of item {} was sealed under key {}, which is not configured; \
+                 set it as item_secrets.key or add it to item_secrets.previous_keys",
+                item_id, key_id
+            ))
+        })?;
+        key.open(item_id, sealed)
+    }
+
+    /// Prepares `item` for `user`: the secret is unsealed for holders of
+    /// [`READ_SECRETS`] and redacted for everyone else.
+    pub fn present(&self, item: &mut Item, user: Option<&AuthUser>) -> Result<()> {
+        if !self.can_read(user) {
+            redact(item);
+            return Ok(());
+        }
+
+        let id = item.id;
+        let Some(metadata) = item.metadata.as_mut().filter(|m| m.may_contain_key(SECRET_FIELD)) else {
+            return Ok(());
+        };
+        let mut value = metadata.to_value();
+        if let Some(secret) = value.get_mut(SECRET_FIELD) {
+            *secret = self.open(id, secret)?;
+            *metadata = Metadata::from_value(&value);
+        }
+        Ok(())
+    }
+
+    /// [`present`](Self::present) for each of `items`.
+    pub fn present_all<'a>(&self, items: impl IntoIterator<Item = &'a mut Item>, user: Option<&AuthUser>) -> Result<()> {
+        items.into_iter().try_for_each(|item| self.present(item, user))
+    }
+}
+
+/// Whether `metadata` asks for the stored secret to be kept.
+pub fn keeps_stored_secret(metadata: &Value) -> bool {
+    metadata.get(SECRET_FIELD).is_some_and(|secret| secret == REDACTED)
+}
+
+/// Replaces the item's secret, if it has one, with [`REDACTED`].
+pub fn redact(item: &mut Item) {
+    if let Some(metadata) = item.metadata.as_mut().filter(|m| m.may_contain_key(SECRET_FIELD)) {
+        let mut value = metadata.to_value();
+        redact_metadata(&mut value);
+        *metadata = Metadata::from_value(&value);
+    }
+}
+
+/// A copy of `item` with its secret redacted, for events and exports.
+pub fn redacted(item: &Item) -> Item {
+    let mut item = item.clone();
+    redact(&mut item);
+    item
+}
+
+/// Replaces the secret in item metadata with [`REDACTED`].
+pub fn redact_metadata(metadata: &mut Value) {
+    if let Some(secret) = metadata.get_mut(SECRET_FIELD).filter(|secret| !secret.is_null()) {
+        *secret = Value::String(REDACTED.to_string());
+    }
+}
+
+/// Redacts the secret of every item in a JSON document, wherever it is
+/// nested, taking any object with a `metadata` object for an item. Returns
+/// whether anything was redacted.
+pub fn redact_json(value: &mut Value) -> bool {
+    match value {
+        Value::Object(object) => {
+            let mut redacted = false;
+            if let Some(metadata) = object.get_mut("metadata").filter(|m| m.is_object()) {
+                redacted = metadata.get(SECRET_FIELD).is_some_and(|s| !s.is_null() && s != REDACTED);
+                redact_metadata(metadata);
+            }
+            for child in object.values_mut() {
+                redacted |= redact_json(child);
+            }
+            redacted
+        }
+        Value::Array(values) => {
+            let mut redacted = false;
+            for child in values {
+                redacted |= redact_json(child);
+            }
+            redacted
+        }
+        _ => false,
+    }
+}
+
+/// Id of the key `secret` was sealed under, if it is sealed.
+pub fn sealed_key_id(secret: &Value) -> Option<&str> {
+    split_sealed(secret).map(|(key_id, _)| key_id)
+}
+
+fn split_sealed(secret: &Value) -> Option<(&str, &str)> {
+    secret.as_str()?.strip_prefix(SEALED_PREFIX)?.split_once(':')
+}
+
+/// An item whose secret could not be unsealed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnreadableSecret {
+    pub id: u64,
+    pub error: String,
+}
+
+/// The outcome of sealing every secret under the current key.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RekeyReport {
+    pub key_id: String,
+    /// Items holding a secret.
+    pub checked: u64,
+    pub resealed: u64,
+    /// Items changed while they were being resealed. Their writes sealed
+    /// them under the current key already, unless they kept the stored
+    /// secret; run the job again to be sure.
+    pub skipped: u64,
+    pub unreadable: u64,
+    /// The first [`SecretRekeyer::MAX_REPORTED`] unreadable secrets.
+    pub items: Vec<UnreadableSecret>,
+}
+
+/// Seals the secret of every item, in every namespace and the trash, under
+/// the current key, recording every run in the audit log. Secrets stored in
+/// plaintext before encryption was enabled are sealed too.
+#[derive(Clone)]
+pub struct SecretRekeyer {
+    items: ItemService,
+    secrets: ItemSecrets,
+    audit_log: AuditLog,
+}
+
+impl SecretRekeyer {
+    pub const MAX_REPORTED: usize = 1000;
+    const PAGE_SIZE: usize = 500;
+
+    pub fn new(items: ItemService, secrets: ItemSecrets, audit_log: AuditLog) -> Self {
+        Self { items, secrets, audit_log }
+    }
+
+    /// Reseals every secret not sealed under the current key on behalf of
+    /// `actor`.
+    pub async fn rekey(&self, actor: &str) -> Result<RekeyReport> {
+        let key = self.secrets.keys.current.as_ref().ok_or_else(|| {
+            AppError::BadRequest("Item secrets are not encrypted, so there is no key to seal them under".to_string())
+        })?;
+        let mut report = RekeyReport {
+            key_id: key.id.clone(),
+            ..RekeyReport::default()
+        };
+        let mut after_id = 0;
+
+        loop {
+            let page = self.items.items_with_secrets(after_id, Self::PAGE_SIZE).await?;
+            for item in &page {
+                report.checked += 1;
+                let Some(mut metadata) = item.metadata.as_ref().map(Metadata::to_value) else {
+                    continue;
+                };
+                let Some(secret) = metadata.get_mut(SECRET_FIELD) else {
+                    continue;
+                };
+                if sealed_key_id(secret) == Some(key.id.as_str()) {
+                    continue;
+                }
+
+                match self.secrets.open(item.id, secret) {
+                    Ok(plaintext) => *secret = key.seal(&plaintext)?,
+                    Err(e) => {
+                        report.unreadable += 1;
+                        if report.items.len() < Self::MAX_REPORTED {
+                            report.items.push(UnreadableSecret {
+                                id: item.id,
+                                error: e.to_string(),
+                            });
+                        }
+                        continue;
+                    }
+                }
+                if self.items.replace_metadata(item.id, item.version, metadata).await? {
+                    report.resealed += 1;
+                } else {
+                    report.skipped += 1;
+                }
+            }
+            match page.last() {
+                Some(last) if page.len() == Self::PAGE_SIZE => after_id = last.id,
+                _ => break,
+            }
+        }
+
+        info!(
+            "Item secrets rekeyed to key {} by {}: {} of {} resealed, {} unreadable",
+            report.key_id, actor, report.resealed, report.checked, report.unreadable
+        );
+        self.audit_log.record(
+            AuditEvent::new("items.secrets_rekeyed")
+                .with_actor(actor)
+                .with_details(serde_json::json!({
+                    "key_id": report.key_id,
+                    "checked": report.checked,
+                    "resealed": report.resealed,
+                    "skipped": report.skipped,
+                    "unreadable": report.unreadable,
+                })),
+        );
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const OLD_KEY: &str = "1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100";
+
+    fn secrets(key: &str, previous: &[&str]) -> ItemSecrets {
+        ItemSecrets::from_config(&ItemSecretsConfig {
+            enabled: true,
+            key: Some(key.to_string()),
+            previous_keys: previous.iter().map(|key| key.to_string()).collect(),
+            ..ItemSecretsConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let secrets = secrets(KEY, &[]);
+        let mut metadata = json!({"owner": "ops", "secret": {"api_key": "sk-live-123"}});
+        secrets.seal(&mut metadata, None).unwrap();
+
+        let sealed = metadata["secret"].as_str().unwrap();
+        assert!(sealed.starts_with("enc:v1:"));
+        assert!(!sealed.contains("sk-live-123"));
+        assert_eq!(sealed_key_id(&metadata["secret"]), secrets.current_key_id());
+        assert_eq!(metadata["owner"], "ops");
+        assert_eq!(secrets.open(1, &metadata["secret"]).unwrap(), json!({"api_key": "sk-live-123"}));
+    }
+
+    #[test]
+    fn test_redacted_secret_keeps_the_stored_one() {
+        let secrets = secrets(KEY, &[]);
+        let mut stored = json!({"secret": "hunter2"});
+        secrets.seal(&mut stored, None).unwrap();
+
+        let mut metadata = json!({"secret": REDACTED, "note": "changed"});
+        secrets.seal(&mut metadata, Some(&stored)).unwrap();
+        assert_eq!(metadata["secret"], stored["secret"]);
+
+        let mut without_stored = json!({"secret": REDACTED});
+        assert!(matches!(secrets.seal(&mut without_stored, None), Err(AppError::BadRequest(_))));
+    }
+
+    #[test]
+    fn test_unknown_key_is_an_error() {
+        let mut metadata = json!({"secret": "hunter2"});
+        secrets(OLD_KEY, &[]).seal(&mut metadata, None).unwrap();
+
+        let error = secrets(KEY, &[]).open(7, &metadata["secret"]).unwrap_err();
+        assert!(matches!(&error, AppError::SecretUnavailable(message) if message.contains("item 7")));
+        assert!(ItemSecrets::default().open(7, &metadata["secret"]).is_err());
+
+        let rotated = secrets(KEY, &[OLD_KEY]);
+        assert_eq!(rotated.open(7, &metadata["secret"]).unwrap(), json!("hunter2"));
+    }
+
+    #[test]
+    fn test_tampered_secret_is_an_error() {
+        let secrets = secrets(KEY, &[]);
+        let mut metadata = json!({"secret": "hunter2"});
+        secrets.seal(&mut metadata, None).unwrap();
+
+        let mut tampered = metadata["secret"].as_str().unwrap().to_string();
+        let last = tampered.pop().unwrap();
+        tampered.push(if last == '0' { '1' } else { '0' });
+        assert!(matches!(secrets.open(1, &json!(tampered)), Err(AppError::SecretUnavailable(_))));
+    }
+
+    #[test]
+    fn test_redact_json() {
+        let mut body = json!({
+            "success": true,
+            "data": {"items": [
+                {"id": 1, "metadata": {"secret": "enc:v1:abcd:00", "owner": "ops"}},
+                {"id": 2, "metadata": {"owner": "sales"}},
+            ]},
+        });
+        assert!(redact_json(&mut body));
+        assert_eq!(body["data"]["items"][0]["metadata"], json!({"secret": "***", "owner": "ops"}));
+        assert_eq!(body["data"]["items"][1]["metadata"], json!({"owner": "sales"}));
+        assert!(!redact_json(&mut body));
+    }
+
+    #[test]
+    fn test_invalid_key_is_refused() {
+        let config = ItemSecretsConfig {
+            enabled: true,
+            key: Some("abc".to_string()),
+            ..ItemSecretsConfig::default()
+        };
+        assert!(matches!(ItemSecrets::from_config(&config), Err(AppError::Configuration(_))));
+    }
+}
@@ -0,0 +1,548 @@
+//! Bulk changes to item metadata conventions
+//!
+//! A [`MetadataTransform`] is a list of declarative operations, applied in
+//! order to the top-level keys of every item matching an optional filter:
+//!
+//! - `rename_key`: moves `from` to `to`. An item already holding a
+//!   different value under `to` is reported as an error and left alone.
+//! - `delete_key`: removes `key`.
+//! - `set_default`: sets `key` to `value` where it is missing or null.
+//! - `map_values`: replaces string values of `key` found in `values`.
+//!
+//! A `MetadataTransform` job, started through
+//! `POST /api/admin/items/metadata/transform`, applies it in batches of
+//! `batch_size` items, each read and written in one transaction, so a write
+//! made while the job runs is never lost. Transformed items are updated as
+//! any other write: their version and update time move on, the change feed
+//! records them and cached responses are dropped. They are published as
+//! `ItemUpdated` events, which the WebSocket manager merges into
+//! `ItemsUpdated` summaries. Items whose new metadata would break the schema
+//! or the metadata limits are reported and left alone. A dry run writes
+//! nothing and returns a sample of items before and after.
+
+use crate::audit::{AuditEvent, AuditLog};
+use crate::error::Result;
+use crate::events::{DomainEvent, EventBus};
+use crate::item_metadata::Metadata;
+use crate::item_secrets::{self, SECRET_FIELD};
+use crate::services::ItemService;
+use crate::store::Item;
+use crate::validation::ValidationError;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::sync::watch;
+use tracing::info;
+
+/// Most operations one transform may hold.
+pub const MAX_OPERATIONS: usize = 20;
+const MAX_KEY_LENGTH: usize = 128;
+pub const DEFAULT_BATCH_SIZE: usize = 500;
+const MAX_BATCH_SIZE: usize = 5000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MetadataOperation {
+    RenameKey { from: String, to: String },
+    DeleteKey { key: String },
+    SetDefault { key: String, value: Value },
+    MapValues { key: String, values: Map<String, Value> },
+}
+
+impl MetadataOperation {
+    fn keys(&self) -> Vec<&str> {
+        match self {
+            MetadataOperation::RenameKey { from, to } => vec![from, to],
+            MetadataOperation::DeleteKey { key }
+            | MetadataOperation::SetDefault { key, .. }
+            | MetadataOperation::MapValues { key, .. } => vec![key],
+        }
+    }
+
+    /// Applies the operation to `object`, returning whether it changed.
+    fn apply(&self, object: &mut Map<String, Value>) -> std::result::Result<bool, String> {
+        match self {
+            MetadataOperation::RenameKey { from, to } => {
+                let Some(value) = object.get(from) else {
+                    return Ok(false);
+                };
+                match object.get(to) {
+                    Some(existing) if existing != value => {
+                        return Err(format!("Cannot rename {} to {}: both are set", from, to))
+                    }
+                    _ => {}
+                }
+                let value = object.remove(from).unwrap_or(Value::Null);
+                object.insert(to.clone(), value);
+                Ok(true)
+            }
+            MetadataOperation::DeleteKey { key } => Ok(object.remove(key).is_some()),
+            MetadataOperation::SetDefault { key, value } => match object.get(key) {
+                Some(existing) if !existing.is_null() => Ok(false),
+                _ => {
+                    object.insert(key.clone(), value.clone());
+                    Ok(true)
+                }
+            },
+            MetadataOperation::MapValues { key, values } => {
+                let mapped = object
+                    .get(key)
+                    .and_then(|value| value.as_str())
+                    .and_then(|value| values.get(value))
+                    .filter(|mapped| object.get(key) != Some(mapped))
+                    .cloned();
+                match mapped {
+                    Some(mapped) => {
+                        object.insert(key.clone(), mapped);
+                        Ok(true)
+                    }
+                    None => Ok(false),
+                }
+            }
+        }
+    }
+}
+
+/// Which items a transform applies to; every item when empty.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetadataFilter {
+    /// Items carrying any of these tags.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Items whose metadata holds each of these values.
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+}
+
+impl MetadataFilter {
+    pub fn matches(&self, item: &Item) -> bool {
+        let tagged = self.tags.is_empty() || item.tags.iter().any(|tag| self.tags.contains(tag));
+        tagged
+            && self.metadata.iter().all(|(key, expected)| {
+                item.metadata.as_ref().and_then(|metadata| metadata.get(key)).as_ref() == Some(expected)
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetadataTransform {
+    pub operations: Vec<MetadataOperation>,
+    #[serde(default)]
+    pub filter: MetadataFilter,
+    /// Items read and written per transaction.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+}
+
+fn default_batch_size() -> usize {
+    DEFAULT_BATCH_SIZE
+}
+
+/// What a transform does to one item.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataOutcome {
+    /// The item does not match the filter.
+    Skipped,
+    Unchanged,
+    Changed(Value),
+    Failed(String),
+}
+
+/// Items read in one batch and what was done to them.
+#[derive(Debug, Clone, Default)]
+pub struct MetadataBatch {
+    pub scanned: usize,
+    /// Id of the last item read; the next batch starts after it.
+    pub last_id: Option<u64>,
+    pub unchanged: usize,
+    /// Items as they were and as they are now, or would be on a dry run.
+    pub changed: Vec<(Item, Item)>,
+    pub failed: Vec<(u64, String)>,
+}
+
+impl MetadataTransform {
+    /// Checks the operations and batch size.
+    pub fn validate(&self) -> std::result::Result<(), ValidationError> {
+        if self.operations.is_empty() || self.operations.len() > MAX_OPERATIONS {
+            return Err(ValidationError::field(
+                "operations",
+                "length",
+                format!("Between 1 and {} operations are required", MAX_OPERATIONS),
+            ));
+        }
+        for operation in &self.operations {
+            for key in operation.keys() {
+                if key.trim().is_empty() || key.chars().count() > MAX_KEY_LENGTH {
+                    return Err(ValidationError::field(
+                        "operations",
+                        "key",
+                        format!("Metadata keys must be 1 to {} characters", MAX_KEY_LENGTH),
+                    ));
+                }
+                if key == SECRET_FIELD {
+                    return Err(ValidationError::field(
+                        "operations",
+                        "secret",
+                        format!("The {} key cannot be transformed", SECRET_FIELD),
+                    ));
+                }
+            }
+            if let MetadataOperation::RenameKey { from, to } = operation {
+                if from == to {
+                    return Err(ValidationError::field("operations", "unchanged", "A key cannot be renamed to itself"));
+                }
+            }
+        }
+        if self.batch_size == 0 || self.batch_size > MAX_BATCH_SIZE {
+            return Err(ValidationError::field(
+                "batch_size",
+                "range",
+                format!("Batch size must be between 1 and {}", MAX_BATCH_SIZE),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The metadata `item` would have after the transform. Missing metadata
+    /// counts as an empty object, and stays missing if nothing is added.
+    pub fn apply(&self, item: &Item) -> MetadataOutcome {
+        if !self.filter.matches(item) {
+            return MetadataOutcome::Skipped;
+        }
+        let mut object = match item.metadata.as_ref().map(Metadata::to_value) {
+            None | Some(Value::Null) => Map::new(),
+            Some(Value::Object(object)) => object,
+            Some(_) => return MetadataOutcome::Failed("Metadata is not an object".to_string()),
+        };
+
+        let mut changed = false;
+        for operation in &self.operations {
+            match operation.apply(&mut object) {
+                Ok(applied) => changed |= applied,
+                Err(e) => return MetadataOutcome::Failed(e),
+            }
+        }
+        if changed {
+            MetadataOutcome::Changed(Value::Object(object))
+        } else {
+            MetadataOutcome::Unchanged
+        }
+    }
+}
+
+/// How far a transform has got, reported while it runs.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransformProgress {
+    pub batches: u64,
+    pub scanned: u64,
+    pub changed: u64,
+    pub failed: u64,
+}
+
+/// An item the transform could not be applied to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransformError {
+    pub id: u64,
+    pub error: String,
+}
+
+/// An item's metadata before and after, as shown by a dry run. Secrets are
+/// redacted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransformSample {
+    pub id: u64,
+    pub name: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransformReport {
+    pub dry_run: bool,
+    pub batches: u64,
+    pub scanned: u64,
+    /// Items matching the filter.
+    pub matched: u64,
+    /// Items transformed, or that would be on a dry run.
+    pub changed: u64,
+    pub unchanged: u64,
+    pub failed: u64,
+    /// The first [`MetadataTransformer::MAX_REPORTED`] failures.
+    pub errors: Vec<TransformError>,
+    /// On a dry run, the first [`MetadataTransformer::SAMPLE_SIZE`] items
+    /// that would change.
+    pub samples: Vec<TransformSample>,
+}
+
+/// Runs a [`MetadataTransform`] over every item, recording every run in the
+/// audit log.
+#[derive(Clone)]
+pub struct MetadataTransformer {
+    items: ItemService,
+    audit_log: AuditLog,
+    events: EventBus,
+}
+
+impl MetadataTransformer {
+    pub const MAX_REPORTED: usize = 1000;
+    pub const SAMPLE_SIZE: usize = 20;
+
+    pub fn new(items: ItemService, audit_log: AuditLog) -> Self {
+        Self {
+            items,
+            audit_log,
+            events: EventBus::new(),
+        }
+    }
+
+    /// Bus that transformed items are published on.
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Applies `transform` to every item on behalf of `actor`, or only
+    /// reports what it would do on a dry run. Progress is sent to
+    /// `progress` after each batch.
+    pub async fn run(
+        &self,
+        transform: &MetadataTransform,
+        dry_run: bool,
+        actor: &str,
+        progress: &watch::Sender<TransformProgress>,
+    ) -> Result<TransformReport> {
+        transform.validate()?;
+        let mut report = TransformReport {
+            dry_run,
+            ..TransformReport::default()
+        };
+        let mut after_id = 0;
+
+        loop {
+            let batch = self.items.transform_metadata(transform, after_id, dry_run).await?;
+            report.batches += 1;
+            report.scanned += batch.scanned as u64;
+            report.unchanged += batch.unchanged as u64;
+            report.changed += batch.changed.len() as u64;
+            report.failed += batch.failed.len() as u64;
+            report.matched += (batch.unchanged + batch.changed.len() + batch.failed.len()) as u64;
+            for (id, error) in &batch.failed {
+                if report.errors.len() < Self::MAX_REPORTED {
+                    report.errors.push(TransformError { id: *id, error: error.clone() });
+                }
+            }
+
+            if dry_run {
+                for (before, after) in &batch.changed {
+                    if report.samples.len() < Self::SAMPLE_SIZE {
+                        report.samples.push(TransformSample {
+                            id: before.id,
+                            name: before.name.clone(),
+                            before: item_secrets::redacted(before).metadata.as_ref().map(Metadata::to_value),
+                            after: item_secrets::redacted(after).metadata.as_ref().map(Metadata::to_value),
+                        });
+                    }
+                }
+            } else {
+                self.announce(&batch.changed).await;
+            }
+
+            let _ = progress.send(TransformProgress {
+                batches: report.batches,
+                scanned: report.scanned,
+                changed: report.changed,
+                failed: report.failed,
+            });
+            match batch.last_id {
+                Some(last_id) if batch.scanned == transform.batch_size => after_id = last_id,
+                _ => break,
+            }
+        }
+
+        info!(
+            "Item metadata transform by {}{}: {} of {} items changed, {} failed",
+            actor,
+            if dry_run { " (dry run)" } else { "" },
+            report.changed,
+            report.matched,
+            report.failed
+        );
+        if !dry_run {
+            self.audit_log.record(
+                AuditEvent::new("items.metadata_transformed")
+                    .with_actor(actor)
+                    .with_details(serde_json::json!({
+                        "operations": transform.operations,
+                        "filter": transform.filter,
+                        "matched": report.matched,
+                        "changed": report.changed,
+                        "failed": report.failed,
+                    })),
+            );
+        }
+
+        Ok(report)
+    }
+
+    async fn announce(&self, changed: &[(Item, Item)]) {
+        let events = changed.iter().map(|(_, item)| DomainEvent::ItemUpdated(item.clone())).collect();
+        self.events.publish_all(events).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::ChangeOp;
+    use crate::test_support::{test_app, TestApp};
+    use serde_json::json;
+
+    fn transform(operations: Value) -> MetadataTransform {
+        serde_json::from_value(json!({ "operations": operations })).unwrap()
+    }
+
+    fn item(tags: &[&str], metadata: Value) -> Item {
+        let now = chrono::Utc::now();
+        Item {
+            id: 1,
+            name: "widget".to_string(),
+            description: None,
+            created_at: now,
+            updated_at: now,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            metadata: Some(metadata.into()),
+            version: 1,
+        }
+    }
+
+    async fn create(app: &TestApp, name: &str, tags: &[&str], metadata: Value) -> Item {
+        app.state
+            .item_service
+            .create_item(name.to_string(), None, tags.iter().map(|t| t.to_string()).collect(), Some(metadata))
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_operations_apply_in_order() {
+        let transform = transform(json!([
+            {"op": "rename_key", "from": "colour", "to": "color"},
+            {"op": "map_values", "key": "color", "values": {"grey": "gray"}},
+            {"op": "set_default", "key": "status", "value": "active"},
+            {"op": "delete_key", "key": "legacy"},
+        ]));
+
+        let outcome = transform.apply(&item(&[], json!({"colour": "grey", "legacy": true, "status": null})));
+        assert_eq!(outcome, MetadataOutcome::Changed(json!({"color": "gray", "status": "active"})));
+        assert_eq!(transform.apply(&item(&[], json!({"color": "gray", "status": "active"}))), MetadataOutcome::Unchanged);
+    }
+
+    #[test]
+    fn test_rename_onto_a_different_value_fails() {
+        let transform = transform(json!([{"op": "rename_key", "from": "colour", "to": "color"}]));
+
+        assert_eq!(
+            transform.apply(&item(&[], json!({"colour": "red", "color": "blue"}))),
+            MetadataOutcome::Failed("Cannot rename colour to color: both are set".to_string())
+        );
+        assert_eq!(
+            transform.apply(&item(&[], json!({"colour": "red", "color": "red"}))),
+            MetadataOutcome::Changed(json!({"color": "red"}))
+        );
+        assert_eq!(
+            transform.apply(&item(&[], json!(["red"]))),
+            MetadataOutcome::Failed("Metadata is not an object".to_string())
+        );
+    }
+
+    #[test]
+    fn test_filter_selects_items() {
+        let mut transform = transform(json!([{"op": "delete_key", "key": "legacy"}]));
+        transform.filter = serde_json::from_value(json!({"tags": ["a", "b"], "metadata": {"kind": "tool"}})).unwrap();
+
+        assert_eq!(transform.apply(&item(&["c"], json!({"kind": "tool", "legacy": 1}))), MetadataOutcome::Skipped);
+        assert_eq!(transform.apply(&item(&["b"], json!({"kind": "part", "legacy": 1}))), MetadataOutcome::Skipped);
+        assert_eq!(
+            transform.apply(&item(&["b"], json!({"kind": "tool", "legacy": 1}))),
+            MetadataOutcome::Changed(json!({"kind": "tool"}))
+        );
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(transform(json!([])).validate().is_err());
+        assert!(transform(json!([{"op": "delete_key", "key": ""}])).validate().is_err());
+        assert!(transform(json!([{"op": "delete_key", "key": SECRET_FIELD}])).validate().is_err());
+        assert!(transform(json!([{"op": "rename_key", "from": "a", "to": "a"}])).validate().is_err());
+        assert!(serde_json::from_value::<MetadataTransform>(json!({"operations": [{"op": "upcase", "key": "a"}]})).is_err());
+
+        let mut large = transform(json!([{"op": "delete_key", "key": "a"}]));
+        assert!(large.validate().is_ok());
+        large.batch_size = MAX_BATCH_SIZE + 1;
+        assert!(large.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_reports_samples_without_writing() {
+        let app = test_app().await;
+        let item = create(&app, "widget", &[], json!({"colour": "red"})).await;
+        let transform = transform(json!([{"op": "rename_key", "from": "colour", "to": "color"}]));
+        let (progress, _) = watch::channel(TransformProgress::default());
+
+        let report = app.state.metadata_transformer().run(&transform, true, "admin", &progress).await.unwrap();
+
+        assert!(report.dry_run);
+        assert_eq!(report.changed, 1);
+        assert_eq!(report.samples.len(), 1);
+        assert_eq!(report.samples[0].before, Some(json!({"colour": "red"})));
+        assert_eq!(report.samples[0].after, Some(json!({"color": "red"})));
+        let stored = app.state.item_service.get_item(item.id).await.unwrap();
+        assert_eq!(stored.metadata.unwrap(), json!({"colour": "red"}));
+        assert_eq!(stored.version, item.version);
+    }
+
+    #[tokio::test]
+    async fn test_run_updates_items_in_batches() {
+        let app = test_app().await;
+        let existing = app.state.item_service.get_items(None, None).await.unwrap().len() as u64;
+        let mut items = Vec::new();
+        for i in 0..5 {
+            items.push(create(&app, &format!("widget {}", i), &["tool"], json!({"colour": "red"})).await);
+        }
+        let clash = create(&app, "clash", &["tool"], json!({"colour": "red", "color": "blue"})).await;
+        let other = create(&app, "other", &["part"], json!({"colour": "red"})).await;
+        let since = app.state.item_service.changes_since(0, 1000).await.unwrap().next_since;
+
+        let mut transform = transform(json!([{"op": "rename_key", "from": "colour", "to": "color"}]));
+        transform.filter.tags = vec!["tool".to_string()];
+        transform.batch_size = 2;
+        let (progress, updates) = watch::channel(TransformProgress::default());
+
+        let report = app.state.metadata_transformer().run(&transform, false, "admin", &progress).await.unwrap();
+
+        assert_eq!(report.scanned, existing + 7);
+        assert_eq!(report.batches, (existing + 7) / 2 + 1);
+        assert_eq!(report.matched, 6);
+        assert_eq!(report.changed, 5);
+        assert_eq!(report.failed, 1);
+        assert_eq!(report.errors, vec![TransformError {
+            id: clash.id,
+            error: "Cannot rename colour to color: both are set".to_string(),
+        }]);
+        assert!(report.samples.is_empty());
+        assert_eq!(updates.borrow().changed, 5);
+
+        for item in &items {
+            let stored = app.state.item_service.get_item(item.id).await.unwrap();
+            assert_eq!(stored.metadata.unwrap(), json!({"color": "red"}));
+            assert_eq!(stored.version, item.version + 1);
+        }
+        let untouched = app.state.item_service.get_item(other.id).await.unwrap();
+        assert_eq!(untouched.metadata.unwrap(), json!({"colour": "red"}));
+
+        let changes = app.state.item_service.changes_since(since, 1000).await.unwrap().changes;
+        assert_eq!(changes.len(), 5);
+        assert!(changes.iter().all(|change| change.op == ChangeOp::Updated));
+    }
+}
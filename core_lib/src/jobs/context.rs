@@ -0,0 +1,46 @@
+//! What a job's handler can report about the attempt it is running
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+/// Handed to a job's handler for the length of one attempt. Handlers opt
+/// into a breakdown of where the attempt's time went by timing their phases
+/// here, such as reading input or writing to the database; the totals are
+/// kept with the attempt.
+#[derive(Debug, Clone, Default)]
+pub struct JobContext {
+    phases: Arc<Mutex<BTreeMap<String, Duration>>>,
+}
+
+impl JobContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `work`, adding the time it takes to `phase`.
+    pub async fn phase<F: Future>(&self, phase: &str, work: F) -> F::Output {
+        let started = Instant::now();
+        let output = work.await;
+        self.record(phase, started.elapsed());
+        output
+    }
+
+    /// Adds `elapsed` to the time spent in `phase`. A phase entered several
+    /// times is reported once, with the total.
+    pub fn record(&self, phase: &str, elapsed: Duration) {
+        *self.phases.lock().entry(phase.to_string()).or_default() += elapsed;
+    }
+
+    /// Milliseconds spent in each phase so far.
+    pub fn phases_ms(&self) -> BTreeMap<String, f64> {
+        self.phases
+            .lock()
+            .iter()
+            .map(|(phase, elapsed)| (phase.clone(), elapsed.as_secs_f64() * 1000.0))
+            .collect()
+    }
+}
@@ -1,3 +1,4 @@
+pub mod context;
 pub mod models;
 pub mod queue;
 pub mod repository;
@@ -6,7 +7,8 @@ pub mod worker;
 #[cfg(test)]
 mod integration_test;
 
+pub use context::JobContext;
 pub use models::*;
 pub use queue::JobQueue;
 pub use repository::{JobRepository, JobRepositoryTrait};
-pub use worker::{JobWorker, WorkerPool};
\ No newline at end of file
+pub use worker::{JobWorker, WorkerPool, WorkerServices};
\ No newline at end of file
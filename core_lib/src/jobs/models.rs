@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -8,9 +10,19 @@ pub struct Job {
     pub job_type: JobType,
     pub status: JobStatus,
     pub payload: serde_json::Value,
+    /// The result, unless it was too large to keep inline.
     pub result: Option<serde_json::Value>,
+    /// Bytes of the result as JSON, inline or not.
+    #[sqlx(skip)]
+    pub result_size: Option<u64>,
+    /// Where a result too large to keep inline was stored.
+    #[sqlx(skip)]
+    pub result_file: Option<JobResultFile>,
     pub error_message: Option<String>,
     pub created_at: DateTime<Utc>,
+    /// When the job last joined the queue: on submission, and again each
+    /// time it is retried.
+    pub queued_at: DateTime<Utc>,
     pub started_at: Option<DateTime<Utc>>,
     pub completed_at: Option<DateTime<Utc>>,
     pub retry_count: i32,
@@ -18,6 +30,15 @@ pub struct Job {
     pub priority: JobPriority,
 }
 
+/// A job result stored in file storage instead of the jobs table, served
+/// by `GET /api/jobs/:id/result`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobResultFile {
+    pub file_id: Uuid,
+    pub size: u64,
+    pub content_type: String,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "job_type", rename_all = "snake_case")]
 pub enum JobType {
@@ -27,6 +48,26 @@ pub enum JobType {
     FileProcessing,
     EmailNotification,
     ReportGeneration,
+    Notification,
+    WebhookDelivery,
+    SnapshotImport,
+    TrashPurge,
+    FileReconciliation,
+    SchemaValidation,
+    FileTextExtraction,
+    FileReindex,
+    SecretRekey,
+    MetadataTransform,
+    NameValidation,
+    IntegrityCheck,
+}
+
+impl JobType {
+    /// Job types the worker re-queues with backoff after a failure, instead
+    /// of waiting for a manual retry.
+    pub fn retries_automatically(&self) -> bool {
+        matches!(self, JobType::Notification | JobType::WebhookDelivery)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::Type)]
@@ -72,6 +113,10 @@ pub struct JobResponse {
     pub started_at: Option<DateTime<Utc>>,
     pub completed_at: Option<DateTime<Utc>>,
     pub result: Option<serde_json::Value>,
+    #[serde(default)]
+    pub result_size: Option<u64>,
+    #[serde(default)]
+    pub result_file: Option<JobResultFile>,
     pub error_message: Option<String>,
     pub retry_count: i32,
     pub max_retries: i32,
@@ -88,6 +133,43 @@ impl From<Job> for JobResponse {
             started_at: job.started_at,
             completed_at: job.completed_at,
             result: job.result,
+            result_size: job.result_size,
+            result_file: job.result_file,
+            error_message: job.error_message,
+            retry_count: job.retry_count,
+            max_retries: job.max_retries,
+            priority: job.priority,
+        }
+    }
+}
+
+/// A job in a listing: everything but its result, of which only the size
+/// is given.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobSummary {
+    pub id: Uuid,
+    pub job_type: JobType,
+    pub status: JobStatus,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub result_size: Option<u64>,
+    pub error_message: Option<String>,
+    pub retry_count: i32,
+    pub max_retries: i32,
+    pub priority: JobPriority,
+}
+
+impl From<Job> for JobSummary {
+    fn from(job: Job) -> Self {
+        Self {
+            id: job.id,
+            job_type: job.job_type,
+            status: job.status,
+            created_at: job.created_at,
+            started_at: job.started_at,
+            completed_at: job.completed_at,
+            result_size: job.result_size,
             error_message: job.error_message,
             retry_count: job.retry_count,
             max_retries: job.max_retries,
@@ -102,8 +184,8 @@ pub struct JobListParams {
     pub job_type: Option<JobType>,
     pub limit: Option<u32>,
     pub offset: Option<u32>,
-    pub sort_by: Option<String>,
-    pub sort_order: Option<String>,
+    /// Newest first when `None`.
+    pub sort: Option<crate::database::SortSpec>,
 }
 
 impl Default for JobListParams {
@@ -111,17 +193,94 @@ impl Default for JobListParams {
         Self {
             status: None,
             job_type: None,
-            limit: Some(50),
+            limit: Some(crate::config::DEFAULT_PAGE_SIZE as u32),
             offset: Some(0),
-            sort_by: Some("created_at".to_string()),
-            sort_order: Some("desc".to_string()),
+            sort: None,
+        }
+    }
+}
+
+/// One run of a job, served by `GET /api/jobs/:id/attempts`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobAttempt {
+    pub job_id: Uuid,
+    /// 1 for the first run, counting automatic and manual retries alike.
+    pub attempt: i32,
+    pub queued_at: DateTime<Utc>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub outcome: AttemptOutcome,
+    pub error: Option<String>,
+    /// Time between joining the queue and a worker picking the job up.
+    pub queue_wait_ms: u64,
+    /// Time from start to finish, once finished.
+    pub duration_ms: Option<u64>,
+    /// Time the job's handler reported spending in each of its phases.
+    pub phases_ms: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AttemptOutcome {
+    Running,
+    Completed,
+    Failed,
+}
+
+impl JobAttempt {
+    /// The attempt of `job` that a worker started at `started_at`.
+    pub fn start(job: &Job, started_at: DateTime<Utc>) -> Self {
+        Self {
+            job_id: job.id,
+            attempt: job.retry_count + 1,
+            queued_at: job.queued_at,
+            started_at,
+            finished_at: None,
+            outcome: AttemptOutcome::Running,
+            error: None,
+            queue_wait_ms: millis_between(job.queued_at, started_at),
+            duration_ms: None,
+            phases_ms: BTreeMap::new(),
         }
     }
+
+    /// Records the end of the attempt at `finished_at`, failed when there
+    /// is an `error`.
+    pub fn finish(&mut self, finished_at: DateTime<Utc>, error: Option<String>, phases_ms: BTreeMap<String, f64>) {
+        self.finished_at = Some(finished_at);
+        self.outcome = if error.is_some() {
+            AttemptOutcome::Failed
+        } else {
+            AttemptOutcome::Completed
+        };
+        self.error = error;
+        self.duration_ms = Some(millis_between(self.started_at, finished_at));
+        self.phases_ms = phases_ms;
+    }
+}
+
+/// How long a finished attempt of a job of type `job_type` waited and ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttemptTiming {
+    pub job_type: JobType,
+    pub queue_wait_ms: u64,
+    pub duration_ms: Option<u64>,
+}
+
+fn millis_between(from: DateTime<Utc>, to: DateTime<Utc>) -> u64 {
+    (to - from).num_milliseconds().max(0) as u64
+}
+
+/// What removing expired jobs deleted.
+#[derive(Debug, Clone, Default)]
+pub struct JobCleanup {
+    pub deleted: u64,
+    /// Stored results of the deleted jobs, still to be removed.
+    pub result_files: Vec<Uuid>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JobListResponse {
-    pub jobs: Vec<JobResponse>,
+    pub jobs: Vec<JobSummary>,
     pub total: u64,
     pub limit: u32,
     pub offset: u32,
@@ -129,15 +288,21 @@ pub struct JobListResponse {
 
 impl Job {
     pub fn new(request: JobRequest) -> Self {
-        let now = Utc::now();
+        Self::new_at(request, Uuid::new_v4(), Utc::now())
+    }
+
+    pub fn new_at(request: JobRequest, id: Uuid, now: DateTime<Utc>) -> Self {
         Self {
-            id: Uuid::new_v4(),
+            id,
             job_type: request.job_type,
             status: JobStatus::Pending,
             payload: request.payload,
             result: None,
+            result_size: None,
+            result_file: None,
             error_message: None,
             created_at: now,
+            queued_at: now,
             started_at: None,
             completed_at: None,
             retry_count: 0,
@@ -154,7 +319,21 @@ impl Job {
     pub fn complete(&mut self, result: Option<serde_json::Value>) {
         self.status = JobStatus::Completed;
         self.completed_at = Some(Utc::now());
+        self.result_size = result
+            .as_ref()
+            .and_then(|result| serde_json::to_vec(result).ok())
+            .map(|json| json.len() as u64);
         self.result = result;
+        self.result_file = None;
+    }
+
+    /// Completes the job with a result that was stored as `file`.
+    pub fn complete_with_file(&mut self, file: JobResultFile) {
+        self.status = JobStatus::Completed;
+        self.completed_at = Some(Utc::now());
+        self.result = None;
+        self.result_size = Some(file.size);
+        self.result_file = Some(file);
     }
 
     pub fn fail(&mut self, error: String) {
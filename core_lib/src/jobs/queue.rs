@@ -1,12 +1,31 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{mpsc, RwLock};
 use tracing::{info, error, warn};
 use uuid::Uuid;
 
+use crate::clock::{SharedClock, SystemClock};
 use crate::error::{AppError, Result};
-use super::models::{Job, JobRequest, JobStatus};
+use crate::features::FeatureSwitch;
+use crate::events::EventBus;
+use crate::ids::{RandomIds, SharedIdGenerator};
+use super::models::{Job, JobAttempt, JobRequest, JobStatus};
 use super::repository::{JobRepository, JobRepositoryTrait};
-use super::worker::WorkerPool;
+use super::worker::{WorkerPool, WorkerServices};
+use crate::notifications::Notifier;
+use crate::search::SearchExporter;
+use crate::snapshot::SnapshotService;
+use crate::supervisor::Supervisor;
+use crate::integrity::IntegrityChecker;
+use crate::item_names::NameChecker;
+use crate::item_schema::SchemaChecker;
+use crate::item_secrets::SecretRekeyer;
+use crate::item_transform::MetadataTransformer;
+use crate::monitoring::response_times::ResponseTimePercentiles;
+use crate::trash::TrashPurger;
+use crate::files::{ContentIndexer, FileManager, FileReconciler};
+use crate::webhooks::WebhookDeliverer;
 
 #[derive(Clone)]
 pub struct JobQueue {
@@ -14,6 +33,25 @@ pub struct JobQueue {
     repository: Arc<dyn JobRepositoryTrait>,
     worker_pool: Arc<RwLock<Option<WorkerPool>>>,
     websocket_manager: Option<Arc<crate::websocket::WebSocketManager>>,
+    notifier: Option<Arc<dyn Notifier>>,
+    webhooks: Option<Arc<WebhookDeliverer>>,
+    snapshots: Option<Arc<SnapshotService>>,
+    trash: Option<Arc<TrashPurger>>,
+    reconciler: Option<Arc<FileReconciler>>,
+    schema_checker: Option<Arc<SchemaChecker>>,
+    name_checker: Option<Arc<NameChecker>>,
+    integrity_checker: Option<Arc<IntegrityChecker>>,
+    events: Option<EventBus>,
+    rekeyer: Option<Arc<SecretRekeyer>>,
+    metadata_transformer: Option<Arc<MetadataTransformer>>,
+    content_indexer: Option<Arc<ContentIndexer>>,
+    exports: Option<Arc<SearchExporter>>,
+    results: Option<FileManager>,
+    max_inline_result: usize,
+    retry_delay: Duration,
+    clock: SharedClock,
+    ids: SharedIdGenerator,
+    switch: FeatureSwitch,
 }
 
 impl JobQueue {
@@ -33,6 +71,25 @@ impl JobQueue {
             repository: repository.clone(),
             worker_pool: Arc::new(RwLock::new(None)),
             websocket_manager,
+            notifier: None,
+            webhooks: None,
+            snapshots: None,
+            trash: None,
+            reconciler: None,
+            schema_checker: None,
+            name_checker: None,
+            integrity_checker: None,
+            events: None,
+            rekeyer: None,
+            metadata_transformer: None,
+            content_indexer: None,
+            exports: None,
+            results: None,
+            max_inline_result: WorkerServices::default().max_inline_result,
+            retry_delay: WorkerServices::default().retry_delay,
+            clock: SystemClock::shared(),
+            ids: RandomIds::shared(),
+            switch: FeatureSwitch::default(),
         };
 
         let queue_clone = queue.clone();
@@ -43,11 +100,165 @@ impl JobQueue {
         queue
     }
 
+    /// Notifier used by `Notification` jobs. Must be set before
+    /// [`start_workers`](Self::start_workers).
+    pub fn with_notifier(mut self, notifier: Arc<dyn Notifier>) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    /// Deliverer used by `WebhookDelivery` jobs. Must be set before
+    /// [`start_workers`](Self::start_workers).
+    pub fn with_webhooks(mut self, webhooks: Arc<WebhookDeliverer>) -> Self {
+        self.webhooks = Some(webhooks);
+        self
+    }
+
+    /// Service used by `SnapshotImport` jobs. Must be set before
+    /// [`start_workers`](Self::start_workers).
+    pub fn with_snapshots(mut self, snapshots: Arc<SnapshotService>) -> Self {
+        self.snapshots = Some(snapshots);
+        self
+    }
+
+    /// Purger used by `TrashPurge` jobs. Must be set before
+    /// [`start_workers`](Self::start_workers).
+    pub fn with_trash(mut self, trash: Arc<TrashPurger>) -> Self {
+        self.trash = Some(trash);
+        self
+    }
+
+    /// Reconciler used by `FileReconciliation` jobs. Must be set before
+    /// [`start_workers`](Self::start_workers).
+    pub fn with_file_reconciler(mut self, reconciler: Arc<FileReconciler>) -> Self {
+        self.reconciler = Some(reconciler);
+        self
+    }
+
+    /// Checker used by `SchemaValidation` jobs. Must be set before
+    /// [`start_workers`](Self::start_workers).
+    pub fn with_schema_checker(mut self, schema_checker: Arc<SchemaChecker>) -> Self {
+        self.schema_checker = Some(schema_checker);
+        self
+    }
+
+    /// Rekeyer used by `SecretRekey` jobs. Must be set before
+    /// [`start_workers`](Self::start_workers).
+    pub fn with_secret_rekeyer(mut self, rekeyer: Arc<SecretRekeyer>) -> Self {
+        self.rekeyer = Some(rekeyer);
+        self
+    }
+
+    /// Checker used by `NameValidation` jobs. Must be set before
+    /// [`start_workers`](Self::start_workers).
+    pub fn with_name_checker(mut self, name_checker: Arc<NameChecker>) -> Self {
+        self.name_checker = Some(name_checker);
+        self
+    }
+
+    /// Checker used by `IntegrityCheck` jobs. Must be set before
+    /// [`start_workers`](Self::start_workers).
+    pub fn with_integrity_checker(mut self, integrity_checker: Arc<IntegrityChecker>) -> Self {
+        self.integrity_checker = Some(integrity_checker);
+        self
+    }
+
+    /// Bus that completed jobs are published on. Must be set before
+    /// [`start_workers`](Self::start_workers).
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Transformer used by `MetadataTransform` jobs. Must be set before
+    /// [`start_workers`](Self::start_workers).
+    pub fn with_metadata_transformer(mut self, metadata_transformer: Arc<MetadataTransformer>) -> Self {
+        self.metadata_transformer = Some(metadata_transformer);
+        self
+    }
+
+    /// Indexer used by `FileTextExtraction` and `FileReindex` jobs. Must be
+    /// set before [`start_workers`](Self::start_workers).
+    pub fn with_content_indexer(mut self, content_indexer: Arc<ContentIndexer>) -> Self {
+        self.content_indexer = Some(content_indexer);
+        self
+    }
+
+    /// Exporter used by `BulkExport` jobs for search results. Must be set
+    /// before [`start_workers`](Self::start_workers).
+    pub fn with_exports(mut self, exports: Arc<SearchExporter>) -> Self {
+        self.exports = Some(exports);
+        self
+    }
+
+    /// Storage for job results larger than `max_inline` bytes, which are
+    /// then kept out of the jobs table. Must be set before
+    /// [`start_workers`](Self::start_workers).
+    pub fn with_result_store(mut self, results: FileManager, max_inline: usize) -> Self {
+        self.results = Some(results);
+        self.max_inline_result = max_inline;
+        self
+    }
+
+    /// Base backoff for automatically retried job types.
+    pub fn with_retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Clock for submission times and retry backoff. Must be set before
+    /// [`start_workers`](Self::start_workers).
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_id_generator(mut self, ids: SharedIdGenerator) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// While `switch` is off, submissions and retries are refused and the
+    /// workers take no new jobs. Must be set before
+    /// [`start_workers`](Self::start_workers).
+    pub fn with_switch(mut self, switch: FeatureSwitch) -> Self {
+        self.switch = switch;
+        self
+    }
+
+    fn ensure_enabled(&self) -> Result<()> {
+        if !self.switch.is_enabled() {
+            return Err(AppError::ServiceUnavailable("Jobs are switched off".to_string()));
+        }
+        Ok(())
+    }
+
     pub async fn start_workers(&self, worker_count: usize) -> Result<()> {
-        let worker_pool = WorkerPool::new_with_websocket(
-            worker_count, 
+        let services = WorkerServices {
+            websocket_manager: self.websocket_manager.clone(),
+            notifier: self.notifier.clone(),
+            webhooks: self.webhooks.clone(),
+            snapshots: self.snapshots.clone(),
+            trash: self.trash.clone(),
+            reconciler: self.reconciler.clone(),
+            schema_checker: self.schema_checker.clone(),
+            name_checker: self.name_checker.clone(),
+            integrity_checker: self.integrity_checker.clone(),
+            events: self.events.clone(),
+            rekeyer: self.rekeyer.clone(),
+            metadata_transformer: self.metadata_transformer.clone(),
+            content_indexer: self.content_indexer.clone(),
+            exports: self.exports.clone(),
+            results: self.results.clone(),
+            max_inline_result: self.max_inline_result,
+            retry_delay: self.retry_delay,
+            clock: self.clock.clone(),
+            switch: self.switch.clone(),
+        };
+        let worker_pool = WorkerPool::new_with_services(
+            worker_count,
             self.repository.clone(),
-            self.websocket_manager.clone()
+            services,
         ).await?;
         
         self.process_pending_jobs().await?;
@@ -59,7 +270,8 @@ impl JobQueue {
     }
 
     pub async fn submit_job(&self, request: JobRequest) -> Result<Uuid> {
-        let mut job = Job::new(request);
+        self.ensure_enabled()?;
+        let mut job = Job::new_at(request, self.ids.uuid(), self.clock.now());
         
         job = self.repository.create(&job).await?;
         
@@ -70,6 +282,33 @@ impl JobQueue {
         Ok(job.id)
     }
 
+    /// Submits a copy of `request` every `every`, starting one interval from
+    /// now, for as long as the returned task runs. The task is supervised as
+    /// `name`.
+    pub fn spawn_recurring(
+        &self,
+        supervisor: &Supervisor,
+        name: &str,
+        request: JobRequest,
+        every: Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        let queue = self.clone();
+        supervisor.spawn(name, move || {
+            let queue = queue.clone();
+            let request = request.clone();
+            async move {
+                let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + every, every);
+                interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = queue.submit_job(request.clone()).await {
+                        warn!("Failed to submit recurring {:?} job: {}", request.job_type, e);
+                    }
+                }
+            }
+        })
+    }
+
     pub async fn get_job_status(&self, job_id: Uuid) -> Result<Option<Job>> {
         self.repository.get_by_id(job_id).await
     }
@@ -95,9 +334,11 @@ impl JobQueue {
     }
 
     pub async fn retry_job(&self, job_id: Uuid) -> Result<bool> {
+        self.ensure_enabled()?;
         if let Some(mut job) = self.repository.get_by_id(job_id).await? {
             if job.can_retry() {
                 job.retry();
+                job.queued_at = self.clock.now();
                 job = self.repository.update(&job).await?;
                 
                 if let Some(ws_manager) = &self.websocket_manager {
@@ -129,19 +370,59 @@ impl JobQueue {
             0
         };
 
+        let mut samples: BTreeMap<String, (Vec<u64>, Vec<u64>)> = BTreeMap::new();
+        for timing in self.repository.attempt_timings().await? {
+            let job_type = serde_json::to_string(&timing.job_type)?;
+            let (queue_waits, durations) = samples.entry(job_type.trim_matches('"').to_string()).or_default();
+            queue_waits.push(timing.queue_wait_ms);
+            durations.extend(timing.duration_ms);
+        }
+        let timings = samples
+            .into_iter()
+            .map(|(job_type, (queue_waits, durations))| {
+                let timings = JobTimings {
+                    attempts: queue_waits.len() as u64,
+                    queue_wait: percentiles(queue_waits),
+                    duration: percentiles(durations),
+                };
+                (job_type, timings)
+            })
+            .collect();
+
         Ok(QueueStats {
             pending_jobs: pending_jobs.len() as u64,
             running_jobs: running_jobs.len() as u64,
             completed_jobs: completed_jobs.len() as u64,
             failed_jobs: failed_jobs.len() as u64,
             active_workers: worker_count,
+            timings,
         })
     }
 
+    /// The attempts made at job `job_id` so far, first attempt first.
+    pub async fn list_attempts(&self, job_id: Uuid) -> Result<Vec<JobAttempt>> {
+        self.repository.list_attempts(job_id).await
+    }
+
+    /// Deletes jobs completed more than `days` ago along with their stored
+    /// results.
     pub async fn cleanup_old_jobs(&self, days: u32) -> Result<u64> {
-        let deleted_count = self.repository.cleanup_old_jobs(days).await?;
-        info!("Cleaned up {} old jobs", deleted_count);
-        Ok(deleted_count)
+        let cleanup = self.repository.cleanup_old_jobs(days).await?;
+        if let Some(results) = &self.results {
+            for file_id in &cleanup.result_files {
+                if let Err(e) = results.delete_job_result(*file_id).await {
+                    warn!("Failed to delete stored job result {}: {}", file_id, e);
+                }
+            }
+        }
+        info!("Cleaned up {} old jobs", cleanup.deleted);
+        Ok(cleanup.deleted)
+    }
+
+    /// Where `job`'s result was stored, if it was too large to keep inline.
+    pub fn result_path(&self, job: &Job) -> Option<std::path::PathBuf> {
+        let file = job.result_file.as_ref()?;
+        Some(self.results.as_ref()?.job_result_path(file.file_id))
     }
 
     pub async fn list_jobs(&self, params: crate::jobs::JobListParams) -> Result<crate::jobs::JobListResponse> {
@@ -196,6 +477,34 @@ pub struct QueueStats {
     pub completed_jobs: u64,
     pub failed_jobs: u64,
     pub active_workers: usize,
+    /// By job type, over the finished attempts still kept; attempts go
+    /// when their job is cleaned up.
+    pub timings: BTreeMap<String, JobTimings>,
+}
+
+/// How long one job type's attempts waited in the queue and then ran.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct JobTimings {
+    pub attempts: u64,
+    pub queue_wait: ResponseTimePercentiles,
+    pub duration: ResponseTimePercentiles,
+}
+
+/// Nearest-rank percentiles of `values`, in milliseconds.
+fn percentiles(mut values: Vec<u64>) -> ResponseTimePercentiles {
+    values.sort_unstable();
+    let at = |percentile: f64| {
+        if values.is_empty() {
+            return 0.0;
+        }
+        let rank = ((percentile / 100.0) * values.len() as f64).ceil().max(1.0) as usize;
+        values[rank.min(values.len()) - 1] as f64
+    };
+    ResponseTimePercentiles {
+        p50_ms: at(50.0),
+        p95_ms: at(95.0),
+        p99_ms: at(99.0),
+    }
 }
 
 #[cfg(test)]
@@ -264,4 +573,83 @@ mod tests {
         let job = queue.get_job_status(job_id).await.unwrap().unwrap();
         assert_eq!(job.status, JobStatus::Cancelled);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_percentiles_use_nearest_rank() {
+        let timings = percentiles((1..=100).rev().collect());
+        assert_eq!((timings.p50_ms, timings.p95_ms, timings.p99_ms), (50.0, 95.0, 99.0));
+
+        let timings = percentiles(vec![7]);
+        assert_eq!((timings.p50_ms, timings.p99_ms), (7.0, 7.0));
+        assert_eq!(percentiles(Vec::new()).p95_ms, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_stats_break_down_attempt_timings_by_job_type() {
+        let repo = create_test_repository().await;
+        let queue = JobQueue::new(repo.clone());
+
+        let request = JobRequest {
+            job_type: JobType::BulkImport,
+            payload: json!({"data": []}),
+            priority: None,
+            max_retries: None,
+        };
+        let job_id = queue.submit_job(request).await.unwrap();
+        let job = queue.get_job_status(job_id).await.unwrap().unwrap();
+        let started_at = job.queued_at + chrono::Duration::milliseconds(250);
+        let mut attempt = JobAttempt::start(&job, started_at);
+        attempt.finish(started_at + chrono::Duration::seconds(2), None, BTreeMap::new());
+        repo.save_attempt(&attempt).await.unwrap();
+
+        let stats = queue.get_queue_stats().await.unwrap();
+        let timings = &stats.timings["BulkImport"];
+        assert_eq!(timings.attempts, 1);
+        assert_eq!(timings.queue_wait.p50_ms, 250.0);
+        assert_eq!(timings.duration.p99_ms, 2000.0);
+        assert_eq!(queue.list_attempts(job_id).await.unwrap(), vec![attempt]);
+    }
+
+    #[tokio::test]
+    async fn test_workers_hold_jobs_while_switched_off() {
+        // The worker must see the jobs table the test created, which each
+        // connection to an in-memory database would not.
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let repo = JobRepository::new(pool);
+        repo.create_table().await.unwrap();
+        let switch = FeatureSwitch::new(true);
+        let queue = JobQueue::new(repo).with_switch(switch.clone());
+        queue.start_workers(1).await.unwrap();
+        let request = JobRequest {
+            job_type: JobType::BulkImport,
+            payload: json!({"data": []}),
+            priority: None,
+            max_retries: None,
+        };
+
+        switch.set(false);
+        assert!(matches!(queue.submit_job(request.clone()).await, Err(AppError::ServiceUnavailable(_))));
+        // A job queued just before the switch went off still reaches a
+        // worker, which holds it.
+        let job = queue.repository.create(&Job::new(request)).await.unwrap();
+        let job_id = job.id;
+        queue.sender.send(job).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert_eq!(queue.get_job_status(job_id).await.unwrap().unwrap().status, JobStatus::Pending);
+
+        switch.set(true);
+        let mut status = JobStatus::Pending;
+        for _ in 0..250 {
+            status = queue.get_job_status(job_id).await.unwrap().unwrap().status;
+            if status == JobStatus::Completed {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(status, JobStatus::Completed);
+    }
+}
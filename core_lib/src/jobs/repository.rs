@@ -3,8 +3,34 @@ use chrono::Utc;
 use sqlx::{SqlitePool, Row};
 use uuid::Uuid;
 
+use crate::database::{InstrumentedPool, SortFields, SortOrder};
 use crate::error::{AppError, Result};
-use super::models::{Job, JobStatus, JobType, JobPriority, JobListParams, JobListResponse, JobResponse};
+use super::models::{
+    AttemptOutcome, AttemptTiming, Job, JobAttempt, JobCleanup, JobResultFile, JobStatus, JobType, JobPriority,
+    JobListParams, JobListResponse, JobSummary,
+};
+
+/// Columns read for listings, which leave out the payload and result.
+const SUMMARY_COLUMNS: &str = "id, job_type, status, 'null' AS payload, NULL AS result, result_size, \
+    result_file_id, result_content_type, error_message, created_at, queued_at, started_at, completed_at, \
+    retry_count, max_retries, priority";
+
+/// What job listings can be sorted on. Priorities sort by rank rather than
+/// by name.
+pub const JOB_SORT: SortFields = SortFields {
+    fields: &[
+        ("id", "id"),
+        ("job_type", "job_type"),
+        ("status", "status"),
+        ("priority", "CASE priority WHEN 'Critical' THEN 3 WHEN 'High' THEN 2 WHEN 'Normal' THEN 1 ELSE 0 END"),
+        ("created_at", "created_at"),
+        ("started_at", "started_at"),
+        ("completed_at", "completed_at"),
+        ("retry_count", "retry_count"),
+    ],
+    default: ("created_at", SortOrder::Desc),
+    tiebreak: "id",
+};
 
 #[async_trait]
 pub trait JobRepositoryTrait: Send + Sync {
@@ -15,17 +41,24 @@ pub trait JobRepositoryTrait: Send + Sync {
     async fn list(&self, params: JobListParams) -> Result<JobListResponse>;
     async fn get_pending_jobs(&self, limit: u32) -> Result<Vec<Job>>;
     async fn get_jobs_by_status(&self, status: JobStatus) -> Result<Vec<Job>>;
-    async fn cleanup_old_jobs(&self, days: u32) -> Result<u64>;
+    /// Deletes jobs completed more than `days` ago.
+    async fn cleanup_old_jobs(&self, days: u32) -> Result<JobCleanup>;
+    /// Records `attempt`, replacing what was recorded for it before.
+    async fn save_attempt(&self, attempt: &JobAttempt) -> Result<()>;
+    /// The attempts of job `job_id`, first attempt first.
+    async fn list_attempts(&self, job_id: Uuid) -> Result<Vec<JobAttempt>>;
+    /// Queue wait and duration of every finished attempt still kept.
+    async fn attempt_timings(&self) -> Result<Vec<AttemptTiming>>;
 }
 
 #[derive(Clone)]
 pub struct JobRepository {
-    pool: SqlitePool,
+    pool: InstrumentedPool,
 }
 
 impl JobRepository {
     pub fn new(pool: SqlitePool) -> Self {
-        Self { pool }
+        Self { pool: pool.into() }
     }
 
     pub async fn create_table(&self) -> Result<()> {
@@ -37,13 +70,18 @@ impl JobRepository {
                 status TEXT NOT NULL,
                 payload TEXT NOT NULL,
                 result TEXT,
+                result_size INTEGER,
+                result_file_id TEXT,
+                result_content_type TEXT,
                 error_message TEXT,
                 created_at TEXT NOT NULL,
+                queued_at TEXT,
                 started_at TEXT,
                 completed_at TEXT,
                 retry_count INTEGER NOT NULL DEFAULT 0,
                 max_retries INTEGER NOT NULL DEFAULT 3,
-                priority TEXT NOT NULL DEFAULT 'normal'
+                priority TEXT NOT NULL DEFAULT 'normal',
+                namespace TEXT NOT NULL DEFAULT 'default'
             )
             "#,
         )
@@ -66,6 +104,34 @@ impl JobRepository {
             .execute(&self.pool)
             .await?;
 
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS job_attempts (
+                job_id TEXT NOT NULL,
+                attempt INTEGER NOT NULL,
+                queued_at TEXT NOT NULL,
+                started_at TEXT NOT NULL,
+                finished_at TEXT,
+                outcome TEXT NOT NULL,
+                error TEXT,
+                queue_wait_ms INTEGER NOT NULL,
+                duration_ms INTEGER,
+                phases TEXT NOT NULL DEFAULT '{}',
+                PRIMARY KEY (job_id, attempt)
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Removes the attempts of jobs that no longer exist.
+    async fn delete_orphaned_attempts(&self) -> Result<()> {
+        sqlx::query("DELETE FROM job_attempts WHERE job_id NOT IN (SELECT id FROM jobs)")
+            .execute(&self.pool)
+            .await?;
         Ok(())
     }
 }
@@ -82,9 +148,10 @@ impl JobRepositoryTrait for JobRepository {
         sqlx::query(
             r#"
             INSERT INTO jobs (
-                id, job_type, status, payload, result, error_message,
-                created_at, started_at, completed_at, retry_count, max_retries, priority
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                id, job_type, status, payload, result, result_size, result_file_id, result_content_type,
+                error_message, created_at, queued_at, started_at, completed_at, retry_count, max_retries, priority,
+                namespace
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#,
         )
         .bind(job.id.to_string())
@@ -92,13 +159,18 @@ impl JobRepositoryTrait for JobRepository {
         .bind(status_str.trim_matches('"'))
         .bind(payload_str)
         .bind(result_str)
+        .bind(job.result_size.map(|size| size as i64))
+        .bind(job.result_file.as_ref().map(|file| file.file_id.to_string()))
+        .bind(job.result_file.as_ref().map(|file| file.content_type.clone()))
         .bind(&job.error_message)
         .bind(job.created_at.to_rfc3339())
+        .bind(job.queued_at.to_rfc3339())
         .bind(job.started_at.map(|dt| dt.to_rfc3339()))
         .bind(job.completed_at.map(|dt| dt.to_rfc3339()))
         .bind(job.retry_count)
         .bind(job.max_retries)
         .bind(priority_str.trim_matches('"'))
+        .bind(crate::tenancy::current_or_default())
         .execute(&self.pool)
         .await?;
 
@@ -106,8 +178,9 @@ impl JobRepositoryTrait for JobRepository {
     }
 
     async fn get_by_id(&self, id: Uuid) -> Result<Option<Job>> {
-        let row = sqlx::query("SELECT * FROM jobs WHERE id = ?")
+        let row = sqlx::query("SELECT * FROM jobs WHERE id = ? AND namespace = COALESCE(?, namespace)")
             .bind(id.to_string())
+            .bind(crate::tenancy::current())
             .fetch_optional(&self.pool)
             .await?;
 
@@ -129,22 +202,28 @@ impl JobRepositoryTrait for JobRepository {
         sqlx::query(
             r#"
             UPDATE jobs SET
-                job_type = ?, status = ?, payload = ?, result = ?, error_message = ?,
-                started_at = ?, completed_at = ?, retry_count = ?, max_retries = ?, priority = ?
-            WHERE id = ?
+                job_type = ?, status = ?, payload = ?, result = ?, result_size = ?, result_file_id = ?,
+                result_content_type = ?, error_message = ?, queued_at = ?, started_at = ?, completed_at = ?,
+                retry_count = ?, max_retries = ?, priority = ?
+            WHERE id = ? AND namespace = COALESCE(?, namespace)
             "#,
         )
         .bind(job_type_str.trim_matches('"'))
         .bind(status_str.trim_matches('"'))
         .bind(payload_str)
         .bind(result_str)
+        .bind(job.result_size.map(|size| size as i64))
+        .bind(job.result_file.as_ref().map(|file| file.file_id.to_string()))
+        .bind(job.result_file.as_ref().map(|file| file.content_type.clone()))
         .bind(&job.error_message)
+        .bind(job.queued_at.to_rfc3339())
         .bind(job.started_at.map(|dt| dt.to_rfc3339()))
         .bind(job.completed_at.map(|dt| dt.to_rfc3339()))
         .bind(job.retry_count)
         .bind(job.max_retries)
         .bind(priority_str.trim_matches('"'))
         .bind(job.id.to_string())
+        .bind(crate::tenancy::current())
         .execute(&self.pool)
         .await?;
 
@@ -152,18 +231,21 @@ impl JobRepositoryTrait for JobRepository {
     }
 
     async fn delete(&self, id: Uuid) -> Result<()> {
-        sqlx::query("DELETE FROM jobs WHERE id = ?")
+        sqlx::query("DELETE FROM jobs WHERE id = ? AND namespace = COALESCE(?, namespace)")
             .bind(id.to_string())
+            .bind(crate::tenancy::current())
             .execute(&self.pool)
             .await?;
+        self.delete_orphaned_attempts().await?;
 
         Ok(())
     }
 
     async fn list(&self, params: JobListParams) -> Result<JobListResponse> {
-        let mut query = "SELECT * FROM jobs WHERE 1=1".to_string();
-        let mut count_query = "SELECT COUNT(*) FROM jobs WHERE 1=1".to_string();
+        let mut query = format!("SELECT {} FROM jobs WHERE namespace = COALESCE(?, namespace)", SUMMARY_COLUMNS);
+        let mut count_query = "SELECT COUNT(*) FROM jobs WHERE namespace = COALESCE(?, namespace)".to_string();
         let mut bind_values = Vec::new();
+        let namespace = crate::tenancy::current();
 
         if let Some(status) = &params.status {
             let status_str = serde_json::to_string(status)?;
@@ -179,15 +261,13 @@ impl JobRepositoryTrait for JobRepository {
             bind_values.push(type_str.trim_matches('"').to_string());
         }
 
-        let sort_by = params.sort_by.as_deref().unwrap_or("created_at");
-        let sort_order = params.sort_order.as_deref().unwrap_or("desc");
-        query.push_str(&format!(" ORDER BY {} {}", sort_by, sort_order));
+        query.push_str(&format!(" ORDER BY {}", JOB_SORT.order_by(params.sort.as_ref())?));
 
-        let limit = params.limit.unwrap_or(50);
+        let limit = params.limit.unwrap_or(crate::config::DEFAULT_PAGE_SIZE as u32);
         let offset = params.offset.unwrap_or(0);
         query.push_str(&format!(" LIMIT {} OFFSET {}", limit, offset));
 
-        let mut count_query_builder = sqlx::query(&count_query);
+        let mut count_query_builder = sqlx::query(&count_query).bind(&namespace);
         for value in &bind_values {
             count_query_builder = count_query_builder.bind(value);
         }
@@ -197,7 +277,7 @@ impl JobRepositoryTrait for JobRepository {
             .await?
             .get(0);
 
-        let mut query_builder = sqlx::query(&query);
+        let mut query_builder = sqlx::query(&query).bind(&namespace);
         for value in &bind_values {
             query_builder = query_builder.bind(value);
         }
@@ -208,7 +288,7 @@ impl JobRepositoryTrait for JobRepository {
         let jobs = jobs?;
 
         Ok(JobListResponse {
-            jobs: jobs.into_iter().map(JobResponse::from).collect(),
+            jobs: jobs.into_iter().map(JobSummary::from).collect(),
             total: total as u64,
             limit,
             offset,
@@ -217,7 +297,7 @@ impl JobRepositoryTrait for JobRepository {
 
     async fn get_pending_jobs(&self, limit: u32) -> Result<Vec<Job>> {
         let rows = sqlx::query(
-            "SELECT * FROM jobs WHERE status IN ('pending', 'retrying') ORDER BY priority DESC, created_at ASC LIMIT ?"
+            "SELECT * FROM jobs WHERE status IN ('pending', 'retrying') ORDER BY priority DESC, created_at ASC, id ASC LIMIT ?"
         )
         .bind(limit as i64)
         .fetch_all(&self.pool)
@@ -229,8 +309,9 @@ impl JobRepositoryTrait for JobRepository {
 
     async fn get_jobs_by_status(&self, status: JobStatus) -> Result<Vec<Job>> {
         let status_str = serde_json::to_string(&status)?;
-        let rows = sqlx::query("SELECT * FROM jobs WHERE status = ?")
+        let rows = sqlx::query("SELECT * FROM jobs WHERE status = ? AND namespace = COALESCE(?, namespace)")
             .bind(status_str.trim_matches('"'))
+            .bind(crate::tenancy::current())
             .fetch_all(&self.pool)
             .await?;
 
@@ -238,16 +319,92 @@ impl JobRepositoryTrait for JobRepository {
         jobs
     }
 
-    async fn cleanup_old_jobs(&self, days: u32) -> Result<u64> {
+    async fn cleanup_old_jobs(&self, days: u32) -> Result<JobCleanup> {
         let cutoff_date = Utc::now() - chrono::Duration::days(days as i64);
-        let result = sqlx::query(
-            "DELETE FROM jobs WHERE completed_at IS NOT NULL AND completed_at < ?"
+        let rows = sqlx::query(
+            "DELETE FROM jobs WHERE completed_at IS NOT NULL AND completed_at < ? AND namespace = COALESCE(?, namespace) \
+             RETURNING result_file_id"
         )
         .bind(cutoff_date.to_rfc3339())
+        .bind(crate::tenancy::current())
+        .fetch_all(&self.pool)
+        .await?;
+        self.delete_orphaned_attempts().await?;
+
+        let result_files = rows
+            .iter()
+            .filter_map(|row| row.get::<Option<String>, _>("result_file_id"))
+            .filter_map(|id| Uuid::parse_str(&id).ok())
+            .collect();
+        Ok(JobCleanup {
+            deleted: rows.len() as u64,
+            result_files,
+        })
+    }
+
+    async fn save_attempt(&self, attempt: &JobAttempt) -> Result<()> {
+        let outcome_str = serde_json::to_string(&attempt.outcome)?;
+        sqlx::query(
+            r#"
+            INSERT INTO job_attempts (
+                job_id, attempt, queued_at, started_at, finished_at, outcome, error, queue_wait_ms, duration_ms, phases
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(job_id, attempt) DO UPDATE SET
+                queued_at = excluded.queued_at, started_at = excluded.started_at, finished_at = excluded.finished_at,
+                outcome = excluded.outcome, error = excluded.error, queue_wait_ms = excluded.queue_wait_ms,
+                duration_ms = excluded.duration_ms, phases = excluded.phases
+            "#,
+        )
+        .bind(attempt.job_id.to_string())
+        .bind(attempt.attempt)
+        .bind(attempt.queued_at.to_rfc3339())
+        .bind(attempt.started_at.to_rfc3339())
+        .bind(attempt.finished_at.map(|dt| dt.to_rfc3339()))
+        .bind(outcome_str.trim_matches('"'))
+        .bind(&attempt.error)
+        .bind(attempt.queue_wait_ms as i64)
+        .bind(attempt.duration_ms.map(|ms| ms as i64))
+        .bind(serde_json::to_string(&attempt.phases_ms)?)
         .execute(&self.pool)
         .await?;
 
-        Ok(result.rows_affected())
+        Ok(())
+    }
+
+    async fn list_attempts(&self, job_id: Uuid) -> Result<Vec<JobAttempt>> {
+        let rows = sqlx::query(
+            "SELECT job_attempts.* FROM job_attempts JOIN jobs ON jobs.id = job_attempts.job_id \
+             WHERE job_attempts.job_id = ? AND jobs.namespace = COALESCE(?, jobs.namespace) \
+             ORDER BY job_attempts.attempt",
+        )
+        .bind(job_id.to_string())
+        .bind(crate::tenancy::current())
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(|row| self.row_to_attempt(row)).collect()
+    }
+
+    async fn attempt_timings(&self) -> Result<Vec<AttemptTiming>> {
+        let rows = sqlx::query(
+            "SELECT jobs.job_type, job_attempts.queue_wait_ms, job_attempts.duration_ms \
+             FROM job_attempts JOIN jobs ON jobs.id = job_attempts.job_id \
+             WHERE job_attempts.finished_at IS NOT NULL AND jobs.namespace = COALESCE(?, jobs.namespace)",
+        )
+        .bind(crate::tenancy::current())
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let job_type_str: String = row.get("job_type");
+                Ok(AttemptTiming {
+                    job_type: serde_json::from_str(&format!("\"{}\"", job_type_str))?,
+                    queue_wait_ms: row.get::<i64, _>("queue_wait_ms").max(0) as u64,
+                    duration_ms: row.get::<Option<i64>, _>("duration_ms").map(|ms| ms.max(0) as u64),
+                })
+            })
+            .collect()
     }
 }
 
@@ -272,12 +429,31 @@ impl JobRepository {
         let result: Option<serde_json::Value> = row.get::<Option<String>, _>("result")
             .map(|s| serde_json::from_str(&s))
             .transpose()?;
+        let result_size = row.get::<Option<i64>, _>("result_size").map(|size| size as u64);
+        let result_file = match row.get::<Option<String>, _>("result_file_id") {
+            Some(file_id) => Some(JobResultFile {
+                file_id: Uuid::parse_str(&file_id)
+                    .map_err(|e| AppError::Database(format!("Invalid result file id: {}", e)))?,
+                size: result_size.unwrap_or_default(),
+                content_type: row
+                    .get::<Option<String>, _>("result_content_type")
+                    .unwrap_or_else(|| "application/json".to_string()),
+            }),
+            None => None,
+        };
 
         let created_at_str: String = row.get("created_at");
         let created_at = chrono::DateTime::parse_from_rfc3339(&created_at_str)
             .map_err(|e| AppError::Database(format!("Invalid datetime: {}", e)))?
             .with_timezone(&Utc);
 
+        let queued_at = row.get::<Option<String>, _>("queued_at")
+            .map(|s| chrono::DateTime::parse_from_rfc3339(&s))
+            .transpose()
+            .map_err(|e| AppError::Database(format!("Invalid queued_at datetime: {}", e)))?
+            .map(|dt| dt.with_timezone(&Utc))
+            .unwrap_or(created_at);
+
         let started_at = row.get::<Option<String>, _>("started_at")
             .map(|s| chrono::DateTime::parse_from_rfc3339(&s))
             .transpose()
@@ -296,8 +472,11 @@ impl JobRepository {
             status,
             payload,
             result,
+            result_size,
+            result_file,
             error_message: row.get("error_message"),
             created_at,
+            queued_at,
             started_at,
             completed_at,
             retry_count: row.get("retry_count"),
@@ -305,4 +484,34 @@ impl JobRepository {
             priority,
         })
     }
+
+    fn row_to_attempt(&self, row: sqlx::sqlite::SqliteRow) -> Result<JobAttempt> {
+        let parse_time = |column: &str, value: String| {
+            chrono::DateTime::parse_from_rfc3339(&value)
+                .map(|dt| dt.with_timezone(&Utc))
+                .map_err(|e| AppError::Database(format!("Invalid {} datetime: {}", column, e)))
+        };
+
+        let job_id_str: String = row.get("job_id");
+        let outcome_str: String = row.get("outcome");
+        let outcome: AttemptOutcome = serde_json::from_str(&format!("\"{}\"", outcome_str))?;
+        let phases_str: String = row.get("phases");
+
+        Ok(JobAttempt {
+            job_id: Uuid::parse_str(&job_id_str)
+                .map_err(|e| AppError::Database(format!("Invalid UUID: {}", e)))?,
+            attempt: row.get("attempt"),
+            queued_at: parse_time("queued_at", row.get("queued_at"))?,
+            started_at: parse_time("started_at", row.get("started_at"))?,
+            finished_at: row
+                .get::<Option<String>, _>("finished_at")
+                .map(|s| parse_time("finished_at", s))
+                .transpose()?,
+            outcome,
+            error: row.get("error"),
+            queue_wait_ms: row.get::<i64, _>("queue_wait_ms").max(0) as u64,
+            duration_ms: row.get::<Option<i64>, _>("duration_ms").map(|ms| ms.max(0) as u64),
+            phases_ms: serde_json::from_str(&phases_str)?,
+        })
+    }
 }
\ No newline at end of file
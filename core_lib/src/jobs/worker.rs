@@ -1,14 +1,88 @@
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{mpsc, Semaphore};
 use tracing::{info, error, warn};
 use uuid::Uuid;
 
+use crate::clock::{SharedClock, SystemClock};
 use crate::error::{AppError, Result};
+use crate::features::FeatureSwitch;
+use crate::events::{DomainEvent, EventBus};
+use crate::notifications::Notifier;
+use crate::search::{ExportFormat, SearchExporter, SearchQuery};
+use crate::snapshot::{ImportProgress, SnapshotService};
+use crate::integrity::IntegrityChecker;
+use crate::item_names::NameChecker;
+use crate::item_schema::SchemaChecker;
+use crate::item_secrets::SecretRekeyer;
+use crate::item_transform::{MetadataTransform, MetadataTransformer, TransformProgress};
+use crate::trash::TrashPurger;
+use crate::files::{ContentIndexer, FileManager, FileReconciler};
+use crate::webhooks::WebhookDeliverer;
 use crate::websocket::{WebSocketManager, WebSocketEvent};
 use crate::jobs::JobResponse;
-use super::models::{Job, JobType};
+use super::context::JobContext;
+use super::models::{Job, JobAttempt, JobResultFile, JobStatus, JobType};
 use super::repository::JobRepositoryTrait;
 
+/// Upper bound on the backoff between automatic retries.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+
+/// Collaborators that job handlers need besides the repository.
+#[derive(Clone)]
+pub struct WorkerServices {
+    pub websocket_manager: Option<Arc<WebSocketManager>>,
+    pub notifier: Option<Arc<dyn Notifier>>,
+    pub webhooks: Option<Arc<WebhookDeliverer>>,
+    pub snapshots: Option<Arc<SnapshotService>>,
+    pub trash: Option<Arc<TrashPurger>>,
+    pub reconciler: Option<Arc<FileReconciler>>,
+    pub schema_checker: Option<Arc<SchemaChecker>>,
+    pub name_checker: Option<Arc<NameChecker>>,
+    pub integrity_checker: Option<Arc<IntegrityChecker>>,
+    pub events: Option<EventBus>,
+    pub rekeyer: Option<Arc<SecretRekeyer>>,
+    pub metadata_transformer: Option<Arc<MetadataTransformer>>,
+    pub content_indexer: Option<Arc<ContentIndexer>>,
+    pub exports: Option<Arc<SearchExporter>>,
+    /// Storage for results larger than `max_inline_result` bytes. Without
+    /// it every result is kept on the job row.
+    pub results: Option<FileManager>,
+    pub max_inline_result: usize,
+    /// Delay before the first automatic retry; doubles on each attempt.
+    pub retry_delay: Duration,
+    /// Clock that retry delays are waited out on.
+    pub clock: SharedClock,
+    /// Workers take no jobs while this is off.
+    pub switch: FeatureSwitch,
+}
+
+impl Default for WorkerServices {
+    fn default() -> Self {
+        Self {
+            websocket_manager: None,
+            notifier: None,
+            webhooks: None,
+            snapshots: None,
+            trash: None,
+            reconciler: None,
+            schema_checker: None,
+            name_checker: None,
+            integrity_checker: None,
+            events: None,
+            rekeyer: None,
+            metadata_transformer: None,
+            content_indexer: None,
+            exports: None,
+            results: None,
+            max_inline_result: 64 * 1024,
+            retry_delay: Duration::from_secs(60),
+            clock: SystemClock::shared(),
+            switch: FeatureSwitch::default(),
+        }
+    }
+}
+
 pub struct WorkerPool {
     job_sender: mpsc::UnboundedSender<Job>,
     worker_count: usize,
@@ -27,6 +101,18 @@ impl WorkerPool {
         worker_count: usize,
         repository: Arc<dyn JobRepositoryTrait>,
         websocket_manager: Option<Arc<WebSocketManager>>,
+    ) -> Result<Self> {
+        let services = WorkerServices {
+            websocket_manager,
+            ..WorkerServices::default()
+        };
+        Self::new_with_services(worker_count, repository, services).await
+    }
+
+    pub async fn new_with_services(
+        worker_count: usize,
+        repository: Arc<dyn JobRepositoryTrait>,
+        services: WorkerServices,
     ) -> Result<Self> {
         let (job_sender, job_receiver) = mpsc::unbounded_channel();
         let semaphore = Arc::new(Semaphore::new(worker_count));
@@ -39,8 +125,25 @@ impl WorkerPool {
                 shared_receiver.clone(),
                 repository.clone(),
                 semaphore.clone(),
-                websocket_manager.clone(),
-            );
+                services.websocket_manager.clone(),
+            )
+            .with_retries(job_sender.downgrade(), services.retry_delay)
+            .with_notifier(services.notifier.clone())
+            .with_webhooks(services.webhooks.clone())
+            .with_snapshots(services.snapshots.clone())
+            .with_trash(services.trash.clone())
+            .with_file_reconciler(services.reconciler.clone())
+            .with_schema_checker(services.schema_checker.clone())
+            .with_name_checker(services.name_checker.clone())
+            .with_integrity_checker(services.integrity_checker.clone())
+            .with_events(services.events.clone())
+            .with_secret_rekeyer(services.rekeyer.clone())
+            .with_metadata_transformer(services.metadata_transformer.clone())
+            .with_content_indexer(services.content_indexer.clone())
+            .with_exports(services.exports.clone())
+            .with_result_store(services.results.clone(), services.max_inline_result)
+            .with_clock(services.clock.clone())
+            .with_switch(services.switch.clone());
             
             tokio::spawn(async move {
                 worker.run().await;
@@ -73,6 +176,25 @@ pub struct JobWorker {
     repository: Arc<dyn JobRepositoryTrait>,
     semaphore: Arc<Semaphore>,
     websocket_manager: Option<Arc<WebSocketManager>>,
+    notifier: Option<Arc<dyn Notifier>>,
+    webhooks: Option<Arc<WebhookDeliverer>>,
+    snapshots: Option<Arc<SnapshotService>>,
+    trash: Option<Arc<TrashPurger>>,
+    reconciler: Option<Arc<FileReconciler>>,
+    schema_checker: Option<Arc<SchemaChecker>>,
+    name_checker: Option<Arc<NameChecker>>,
+    integrity_checker: Option<Arc<IntegrityChecker>>,
+    events: Option<EventBus>,
+    rekeyer: Option<Arc<SecretRekeyer>>,
+    metadata_transformer: Option<Arc<MetadataTransformer>>,
+    content_indexer: Option<Arc<ContentIndexer>>,
+    exports: Option<Arc<SearchExporter>>,
+    results: Option<FileManager>,
+    max_inline_result: usize,
+    retry_sender: Option<mpsc::WeakUnboundedSender<Job>>,
+    retry_delay: Duration,
+    clock: SharedClock,
+    switch: FeatureSwitch,
 }
 
 impl JobWorker {
@@ -89,13 +211,130 @@ impl JobWorker {
             repository,
             semaphore,
             websocket_manager,
+            notifier: None,
+            webhooks: None,
+            snapshots: None,
+            trash: None,
+            reconciler: None,
+            schema_checker: None,
+            name_checker: None,
+            integrity_checker: None,
+            events: None,
+            rekeyer: None,
+            metadata_transformer: None,
+            content_indexer: None,
+            exports: None,
+            results: None,
+            max_inline_result: WorkerServices::default().max_inline_result,
+            retry_sender: None,
+            retry_delay: WorkerServices::default().retry_delay,
+            clock: SystemClock::shared(),
+            switch: FeatureSwitch::default(),
         }
     }
 
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_notifier(mut self, notifier: Option<Arc<dyn Notifier>>) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    /// Pauses the worker while `switch` is off.
+    pub fn with_switch(mut self, switch: FeatureSwitch) -> Self {
+        self.switch = switch;
+        self
+    }
+
+    pub fn with_webhooks(mut self, webhooks: Option<Arc<WebhookDeliverer>>) -> Self {
+        self.webhooks = webhooks;
+        self
+    }
+
+    pub fn with_snapshots(mut self, snapshots: Option<Arc<SnapshotService>>) -> Self {
+        self.snapshots = snapshots;
+        self
+    }
+
+    pub fn with_trash(mut self, trash: Option<Arc<TrashPurger>>) -> Self {
+        self.trash = trash;
+        self
+    }
+
+    pub fn with_file_reconciler(mut self, reconciler: Option<Arc<FileReconciler>>) -> Self {
+        self.reconciler = reconciler;
+        self
+    }
+
+    pub fn with_schema_checker(mut self, schema_checker: Option<Arc<SchemaChecker>>) -> Self {
+        self.schema_checker = schema_checker;
+        self
+    }
+
+    pub fn with_name_checker(mut self, name_checker: Option<Arc<NameChecker>>) -> Self {
+        self.name_checker = name_checker;
+        self
+    }
+
+    pub fn with_integrity_checker(mut self, integrity_checker: Option<Arc<IntegrityChecker>>) -> Self {
+        self.integrity_checker = integrity_checker;
+        self
+    }
+
+    pub fn with_events(mut self, events: Option<EventBus>) -> Self {
+        self.events = events;
+        self
+    }
+
+    pub fn with_secret_rekeyer(mut self, rekeyer: Option<Arc<SecretRekeyer>>) -> Self {
+        self.rekeyer = rekeyer;
+        self
+    }
+
+    pub fn with_metadata_transformer(mut self, metadata_transformer: Option<Arc<MetadataTransformer>>) -> Self {
+        self.metadata_transformer = metadata_transformer;
+        self
+    }
+
+    pub fn with_content_indexer(mut self, content_indexer: Option<Arc<ContentIndexer>>) -> Self {
+        self.content_indexer = content_indexer;
+        self
+    }
+
+    pub fn with_exports(mut self, exports: Option<Arc<SearchExporter>>) -> Self {
+        self.exports = exports;
+        self
+    }
+
+    /// Stores results larger than `max_inline` bytes in `results` rather
+    /// than on the job row.
+    pub fn with_result_store(mut self, results: Option<FileManager>, max_inline: usize) -> Self {
+        self.results = results;
+        self.max_inline_result = max_inline;
+        self
+    }
+
+    /// Lets the worker re-queue failed jobs whose type retries automatically.
+    /// The sender is weak so that workers never keep the pool's channel open.
+    pub fn with_retries(mut self, sender: mpsc::WeakUnboundedSender<Job>, retry_delay: Duration) -> Self {
+        self.retry_sender = Some(sender);
+        self.retry_delay = retry_delay;
+        self
+    }
+
     pub async fn run(self) {
         info!("Worker {} started", self.id);
 
         loop {
+            if !self.switch.is_enabled() {
+                info!("Worker {} paused: jobs are switched off", self.id);
+                self.switch.wait_until_enabled().await;
+                info!("Worker {} resumed", self.id);
+            }
+
             let job = {
                 let mut receiver = self.job_receiver.lock().await;
                 receiver.recv().await
@@ -103,6 +342,10 @@ impl JobWorker {
 
             match job {
                 Some(job) => {
+                    // A job taken as the switch went off is held, still
+                    // pending, until it is back on.
+                    self.switch.wait_until_enabled().await;
+
                     let _permit = match self.semaphore.acquire().await {
                         Ok(permit) => permit,
                         Err(_) => {
@@ -128,20 +371,30 @@ impl JobWorker {
 
         job.start();
         let updated_job = self.repository.update(&job).await?;
+        let mut attempt = JobAttempt::start(&job, self.clock.now());
+        self.record_attempt(&attempt).await;
         
         if let Some(ws_manager) = &self.websocket_manager {
             let event = WebSocketEvent::JobStarted(JobResponse::from(updated_job.clone()));
             ws_manager.broadcast(event).await;
         }
 
-        let result = self.execute_job(&job).await;
+        let context = JobContext::new();
+        let result = match self.execute_job(&job, &context).await {
+            Ok(job_result) => self.complete_job(&mut job, job_result).await,
+            Err(e) => Err(e),
+        };
+        attempt.finish(self.clock.now(), result.as_ref().err().map(|e| e.to_string()), context.phases_ms());
+        self.record_attempt(&attempt).await;
 
         match result {
-            Ok(job_result) => {
-                job.complete(job_result);
+            Ok(()) => {
                 let completed_job = self.repository.update(&job).await?;
                 info!("Worker {} completed job {}", self.id, job.id);
-                
+
+                if let Some(events) = &self.events {
+                    events.publish(DomainEvent::JobCompleted(completed_job.clone())).await;
+                }
                 if let Some(ws_manager) = &self.websocket_manager {
                     let event = WebSocketEvent::JobCompleted(JobResponse::from(completed_job));
                     ws_manager.broadcast(event).await;
@@ -150,10 +403,19 @@ impl JobWorker {
             Err(e) => {
                 let error_msg = e.to_string();
                 job.fail(error_msg);
+                if job.job_type.retries_automatically() && job.can_retry() && self.retry_sender.is_some() {
+                    job.retry();
+                }
                 let failed_job = self.repository.update(&job).await?;
                 error!("Worker {} failed job {}: {}", self.id, job.id, e);
-                
-                if let Some(ws_manager) = &self.websocket_manager {
+
+                if job.status == JobStatus::Retrying {
+                    self.schedule_retry(job);
+                    if let Some(ws_manager) = &self.websocket_manager {
+                        let event = WebSocketEvent::JobRetrying(JobResponse::from(failed_job));
+                        ws_manager.broadcast(event).await;
+                    }
+                } else if let Some(ws_manager) = &self.websocket_manager {
                     let event = WebSocketEvent::JobFailed(JobResponse::from(failed_job));
                     ws_manager.broadcast(event).await;
                 }
@@ -163,18 +425,87 @@ impl JobWorker {
         Ok(())
     }
 
-    async fn execute_job(&self, job: &Job) -> Result<Option<serde_json::Value>> {
+    /// Marks `job` completed with `result`, moving the result to file
+    /// storage when it is too large to keep on the row.
+    async fn complete_job(&self, job: &mut Job, result: Option<serde_json::Value>) -> Result<()> {
+        let (Some(results), Some(value)) = (&self.results, &result) else {
+            job.complete(result);
+            return Ok(());
+        };
+        let json = serde_json::to_vec(value)?;
+        if json.len() <= self.max_inline_result {
+            job.complete(result);
+            return Ok(());
+        }
+
+        let file_id = results.store_job_result(&json).await?;
+        info!("Stored {} byte result of job {} as {}", json.len(), job.id, file_id);
+        job.complete_with_file(JobResultFile {
+            file_id,
+            size: json.len() as u64,
+            content_type: "application/json".to_string(),
+        });
+        Ok(())
+    }
+
+    /// Keeps the record of `attempt`. Failing to is logged rather than
+    /// failing the job.
+    async fn record_attempt(&self, attempt: &JobAttempt) {
+        if let Err(e) = self.repository.save_attempt(attempt).await {
+            warn!("Failed to record attempt {} of job {}: {}", attempt.attempt, attempt.job_id, e);
+        }
+    }
+
+    /// Re-submits `job` after an exponential backoff based on its retry count.
+    /// The job counts as queued again once the backoff is over.
+    fn schedule_retry(&self, mut job: Job) {
+        let Some(sender) = self.retry_sender.clone() else {
+            return;
+        };
+
+        let exponent = (job.retry_count.max(1) - 1).min(16) as u32;
+        let delay = self.retry_delay.saturating_mul(1 << exponent).min(MAX_RETRY_DELAY);
+        info!("Retrying job {} in {:?} (attempt {} of {})", job.id, delay, job.retry_count + 1, job.max_retries);
+
+        let sleep = self.clock.sleep(delay);
+        let clock = self.clock.clone();
+        tokio::spawn(async move {
+            sleep.await;
+            job.queued_at = clock.now();
+            if let Some(sender) = sender.upgrade() {
+                if sender.send(job).is_err() {
+                    warn!("Worker pool closed before a job retry could be queued");
+                }
+            }
+        });
+    }
+
+    /// Runs `job`'s handler. Handlers that break their time down into
+    /// phases report them to `context`.
+    async fn execute_job(&self, job: &Job, context: &JobContext) -> Result<Option<serde_json::Value>> {
         match job.job_type {
-            JobType::BulkImport => self.execute_bulk_import(job).await,
-            JobType::BulkExport => self.execute_bulk_export(job).await,
+            JobType::BulkImport => self.execute_bulk_import(job, context).await,
+            JobType::BulkExport => self.execute_bulk_export(job, context).await,
             JobType::DataMigration => self.execute_data_migration(job).await,
             JobType::FileProcessing => self.execute_file_processing(job).await,
             JobType::EmailNotification => self.execute_email_notification(job).await,
             JobType::ReportGeneration => self.execute_report_generation(job).await,
+            JobType::Notification => self.execute_notification(job).await,
+            JobType::WebhookDelivery => self.execute_webhook_delivery(job).await,
+            JobType::SnapshotImport => self.execute_snapshot_import(job, context).await,
+            JobType::TrashPurge => self.execute_trash_purge(job).await,
+            JobType::FileReconciliation => self.execute_file_reconciliation(job).await,
+            JobType::SchemaValidation => self.execute_schema_validation(job).await,
+            JobType::FileTextExtraction => self.execute_file_text_extraction(job).await,
+            JobType::FileReindex => self.execute_file_reindex(job).await,
+            JobType::SecretRekey => self.execute_secret_rekey(job).await,
+            JobType::MetadataTransform => self.execute_metadata_transform(job).await,
+            JobType::NameValidation => self.execute_name_validation(job).await,
+            JobType::IntegrityCheck => self.execute_integrity_check(job).await,
         }
     }
 
-    async fn execute_bulk_import(&self, job: &Job) -> Result<Option<serde_json::Value>> {
+    async fn execute_bulk_import(&self, job: &Job, context: &JobContext) -> Result<Option<serde_json::Value>> {
         info!("Executing bulk import for job {}", job.id);
         
         let import_data = job.payload.get("data")
@@ -183,7 +514,9 @@ impl JobWorker {
         let items = import_data.as_array()
             .ok_or_else(|| AppError::Job("Import data must be an array".to_string()))?;
 
-        tokio::time::sleep(tokio::time::Duration::from_millis(100 * items.len() as u64)).await;
+        context
+            .phase("import", tokio::time::sleep(tokio::time::Duration::from_millis(100 * items.len() as u64)))
+            .await;
 
         let result = serde_json::json!({
             "imported_count": items.len(),
@@ -194,9 +527,13 @@ impl JobWorker {
         Ok(Some(result))
     }
 
-    async fn execute_bulk_export(&self, job: &Job) -> Result<Option<serde_json::Value>> {
+    async fn execute_bulk_export(&self, job: &Job, context: &JobContext) -> Result<Option<serde_json::Value>> {
         info!("Executing bulk export for job {}", job.id);
         
+        if let Some(search) = job.payload.get("search") {
+            return context.phase("export", self.execute_search_export(job, search)).await;
+        }
+        
         let format = job.payload.get("format")
             .and_then(|f| f.as_str())
             .unwrap_or("json");
@@ -216,6 +553,40 @@ impl JobWorker {
         Ok(Some(result))
     }
 
+    /// Exports the results of a search to a file, in the namespace it was
+    /// requested from.
+    async fn execute_search_export(&self, job: &Job, search: &serde_json::Value) -> Result<Option<serde_json::Value>> {
+        let exports = self.exports.as_ref()
+            .ok_or_else(|| AppError::Job("Search export is not configured".to_string()))?;
+
+        let query: SearchQuery = serde_json::from_value(search.clone())
+            .map_err(|e| AppError::Job(format!("Invalid search in payload: {}", e)))?;
+        let format = job.payload.get("format")
+            .and_then(|f| f.as_str())
+            .map(ExportFormat::parse)
+            .unwrap_or_default();
+        let namespace = job.payload.get("namespace")
+            .and_then(|n| n.as_str())
+            .unwrap_or(crate::tenancy::DEFAULT_NAMESPACE)
+            .to_string();
+        let requested_by = job.payload.get("requested_by")
+            .and_then(|r| r.as_u64())
+            .unwrap_or(0);
+
+        let (file, exported_count) =
+            crate::tenancy::scope(namespace, exports.export_to_file(&query, format, requested_by)).await?;
+
+        Ok(Some(serde_json::json!({
+            "export_format": format,
+            "exported_count": exported_count,
+            "file_id": file.id,
+            "filename": file.original_filename,
+            "size": file.size,
+            "download_url": format!("/api/files/{}/download", file.id),
+            "success": true
+        })))
+    }
+
     async fn execute_data_migration(&self, job: &Job) -> Result<Option<serde_json::Value>> {
         info!("Executing data migration for job {}", job.id);
         
@@ -281,6 +652,223 @@ impl JobWorker {
         Ok(Some(result))
     }
 
+    async fn execute_notification(&self, job: &Job) -> Result<Option<serde_json::Value>> {
+        let notifier = self.notifier.as_ref()
+            .ok_or_else(|| AppError::Job("No notifier configured".to_string()))?;
+
+        let template_id = job.payload.get("template_id")
+            .and_then(|t| t.as_str())
+            .ok_or_else(|| AppError::Job("Missing template_id in payload".to_string()))?;
+
+        let recipient = job.payload.get("recipient")
+            .and_then(|r| r.as_str())
+            .ok_or_else(|| AppError::Job("Missing recipient in payload".to_string()))?;
+
+        let context = job.payload.get("context").cloned().unwrap_or_default();
+
+        notifier.send(template_id, recipient, &context).await?;
+
+        Ok(Some(serde_json::json!({
+            "template_id": template_id,
+            "recipient": recipient,
+            "notifier": notifier.name(),
+            "sent": true
+        })))
+    }
+
+    async fn execute_webhook_delivery(&self, job: &Job) -> Result<Option<serde_json::Value>> {
+        let webhooks = self.webhooks.as_ref()
+            .ok_or_else(|| AppError::Job("Webhook delivery is not configured".to_string()))?;
+
+        let attempt = job.retry_count.max(0) as u32 + 1;
+        webhooks.deliver(&job.payload, attempt).await.map(Some)
+    }
+
+    /// Imports a staged snapshot archive, recording progress in the job's
+    /// result while it runs. The time spent writing each table is reported
+    /// as a phase named after it.
+    async fn execute_snapshot_import(&self, job: &Job, context: &JobContext) -> Result<Option<serde_json::Value>> {
+        let snapshots = self.snapshots.as_ref()
+            .ok_or_else(|| AppError::Job("Snapshot import is not configured".to_string()))?;
+
+        let archive_id = job.payload.get("archive_id")
+            .and_then(|a| a.as_str())
+            .and_then(|a| Uuid::parse_str(a).ok())
+            .ok_or_else(|| AppError::Job("Missing archive_id in payload".to_string()))?;
+
+        let (progress, mut updates) = tokio::sync::watch::channel(ImportProgress::default());
+        let repository = self.repository.clone();
+        let mut running = job.clone();
+        let context = context.clone();
+        let reporter = tokio::spawn(async move {
+            let mut stage: Option<(String, std::time::Instant)> = None;
+            while updates.changed().await.is_ok() {
+                let current = updates.borrow_and_update().clone();
+                if stage.as_ref().map(|(name, _)| name) != Some(&current.stage) {
+                    if let Some((name, since)) = stage.replace((current.stage.clone(), std::time::Instant::now())) {
+                        context.record(&name, since.elapsed());
+                    }
+                }
+                running.result = Some(serde_json::json!({ "progress": current }));
+                if let Err(e) = repository.update(&running).await {
+                    warn!("Failed to record progress of job {}: {}", running.id, e);
+                }
+            }
+            if let Some((name, since)) = stage {
+                context.record(&name, since.elapsed());
+            }
+        });
+
+        let report = snapshots.import_staged(archive_id, &progress).await;
+        drop(progress);
+        let _ = reporter.await;
+
+        Ok(Some(serde_json::to_value(report?)?))
+    }
+
+    /// Purges the trash; the report becomes the job's result.
+    async fn execute_trash_purge(&self, job: &Job) -> Result<Option<serde_json::Value>> {
+        let trash = self.trash.as_ref()
+            .ok_or_else(|| AppError::Job("Trash purge is not configured".to_string()))?;
+
+        let dry_run = job.payload.get("dry_run")
+            .and_then(|d| d.as_bool())
+            .unwrap_or(false);
+
+        let report = trash.purge(dry_run, &format!("job:{}", job.id)).await?;
+        Ok(Some(serde_json::to_value(report)?))
+    }
+
+    /// Reconciles stored files with their rows; the report becomes the
+    /// job's result.
+    async fn execute_file_reconciliation(&self, job: &Job) -> Result<Option<serde_json::Value>> {
+        let reconciler = self.reconciler.as_ref()
+            .ok_or_else(|| AppError::Job("File reconciliation is not configured".to_string()))?;
+
+        let dry_run = job.payload.get("dry_run")
+            .and_then(|d| d.as_bool())
+            .unwrap_or(false);
+
+        let report = reconciler.reconcile(dry_run, &format!("job:{}", job.id)).await?;
+        Ok(Some(serde_json::to_value(report)?))
+    }
+
+    /// Checks stored items against the item schema; the report of those
+    /// that do not conform becomes the job's result.
+    async fn execute_schema_validation(&self, job: &Job) -> Result<Option<serde_json::Value>> {
+        let checker = self.schema_checker.as_ref()
+            .ok_or_else(|| AppError::Job("Item schema validation is not configured".to_string()))?;
+
+        let report = checker.check(&format!("job:{}", job.id)).await?;
+        Ok(Some(serde_json::to_value(report)?))
+    }
+
+    /// Lists the items sharing a name under the name rule; the report
+    /// becomes the job's result.
+    async fn execute_name_validation(&self, job: &Job) -> Result<Option<serde_json::Value>> {
+        let checker = self.name_checker.as_ref()
+            .ok_or_else(|| AppError::Job("Item name validation is not configured".to_string()))?;
+
+        let report = checker.check(&format!("job:{}", job.id)).await?;
+        Ok(Some(serde_json::to_value(report)?))
+    }
+
+    /// Checks the references between tables, repairing them when the
+    /// payload asks to; the report becomes the job's result.
+    async fn execute_integrity_check(&self, job: &Job) -> Result<Option<serde_json::Value>> {
+        let checker = self.integrity_checker.as_ref()
+            .ok_or_else(|| AppError::Job("Integrity checks are not configured".to_string()))?;
+
+        let repair = job.payload.get("repair")
+            .and_then(|r| r.as_bool())
+            .unwrap_or(false);
+        let actor = job.payload.get("requested_by")
+            .and_then(|r| r.as_str())
+            .map(|r| r.to_string())
+            .unwrap_or_else(|| format!("job:{}", job.id));
+
+        let report = checker.check(repair, None, &actor).await?;
+        Ok(Some(serde_json::to_value(report)?))
+    }
+
+    /// Seals every item secret under the current key; the report becomes
+    /// the job's result.
+    async fn execute_secret_rekey(&self, job: &Job) -> Result<Option<serde_json::Value>> {
+        let rekeyer = self.rekeyer.as_ref()
+            .ok_or_else(|| AppError::Job("Item secret rekeying is not configured".to_string()))?;
+
+        let report = rekeyer.rekey(&format!("job:{}", job.id)).await?;
+        Ok(Some(serde_json::to_value(report)?))
+    }
+
+    /// Applies the metadata transform in the payload to the items of the
+    /// job's namespace, recording progress in the job's result while it
+    /// runs. The report becomes the job's result.
+    async fn execute_metadata_transform(&self, job: &Job) -> Result<Option<serde_json::Value>> {
+        let transformer = self.metadata_transformer.as_ref()
+            .ok_or_else(|| AppError::Job("Item metadata transforms are not configured".to_string()))?;
+
+        let transform: MetadataTransform = job.payload.get("transform")
+            .cloned()
+            .ok_or_else(|| AppError::Job("Missing transform in payload".to_string()))
+            .and_then(|t| serde_json::from_value(t).map_err(|e| AppError::Job(format!("Invalid transform in payload: {}", e))))?;
+        let dry_run = job.payload.get("dry_run")
+            .and_then(|d| d.as_bool())
+            .unwrap_or(false);
+        let namespace = job.payload.get("namespace")
+            .and_then(|n| n.as_str())
+            .unwrap_or(crate::tenancy::DEFAULT_NAMESPACE)
+            .to_string();
+        let actor = job.payload.get("requested_by")
+            .and_then(|r| r.as_str())
+            .map(|r| r.to_string())
+            .unwrap_or_else(|| format!("job:{}", job.id));
+
+        let (progress, mut updates) = tokio::sync::watch::channel(TransformProgress::default());
+        let repository = self.repository.clone();
+        let mut running = job.clone();
+        let reporter = tokio::spawn(async move {
+            while updates.changed().await.is_ok() {
+                let current = updates.borrow_and_update().clone();
+                running.result = Some(serde_json::json!({ "progress": current }));
+                if let Err(e) = repository.update(&running).await {
+                    warn!("Failed to record progress of job {}: {}", running.id, e);
+                }
+            }
+        });
+
+        let report = crate::tenancy::scope(namespace, transformer.run(&transform, dry_run, &actor, &progress)).await;
+        drop(progress);
+        let _ = reporter.await;
+
+        Ok(Some(serde_json::to_value(report?)?))
+    }
+
+    /// Indexes the text of the uploaded file named by `file_id`. A failed
+    /// extraction marks the file not indexed without failing the job.
+    async fn execute_file_text_extraction(&self, job: &Job) -> Result<Option<serde_json::Value>> {
+        let indexer = self.content_indexer.as_ref()
+            .ok_or_else(|| AppError::Job("File content indexing is not configured".to_string()))?;
+
+        let file_id = job.payload.get("file_id")
+            .and_then(|id| id.as_str())
+            .and_then(|id| Uuid::parse_str(id).ok())
+            .ok_or_else(|| AppError::Job("Missing file_id".to_string()))?;
+
+        let status = indexer.index_file(file_id).await?;
+        Ok(Some(serde_json::json!({ "file_id": file_id, "content_index": status })))
+    }
+
+    /// Extracts the text of every file again; the counts become the job's
+    /// result.
+    async fn execute_file_reindex(&self, job: &Job) -> Result<Option<serde_json::Value>> {
+        let indexer = self.content_indexer.as_ref()
+            .ok_or_else(|| AppError::Job("File content indexing is not configured".to_string()))?;
+
+        let report = indexer.reindex_all(&format!("job:{}", job.id)).await?;
+        Ok(Some(serde_json::to_value(report)?))
+    }
+
     async fn execute_report_generation(&self, job: &Job) -> Result<Option<serde_json::Value>> {
         info!("Executing report generation for job {}", job.id);
         
@@ -360,5 +948,116 @@ mod tests {
         let result = updated_job.result.unwrap();
         assert_eq!(result["imported_count"], 2);
         assert_eq!(result["success"], true);
+
+        let attempts = repo.list_attempts(job_id).await.unwrap();
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].outcome, AttemptOutcome::Completed);
+        assert!(attempts[0].duration_ms.unwrap() >= 200);
+        assert!(attempts[0].phases_ms["import"] >= 200.0);
+    }
+
+    struct FlakyNotifier {
+        failures_left: std::sync::atomic::AtomicUsize,
+        delivered: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl Notifier for FlakyNotifier {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+
+        async fn send(&self, _template_id: &str, _recipient: &str, _context: &serde_json::Value) -> Result<()> {
+            use std::sync::atomic::Ordering;
+            if self
+                .failures_left
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok()
+            {
+                return Err(crate::error::AppError::Job("smtp down".to_string()));
+            }
+            self.delivered.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_notification_is_retried() {
+        let repo = create_test_repository().await;
+        let notifier = Arc::new(FlakyNotifier {
+            failures_left: 1.into(),
+            delivered: 0.into(),
+        });
+        let services = WorkerServices {
+            notifier: Some(notifier.clone()),
+            retry_delay: Duration::from_millis(50),
+            ..WorkerServices::default()
+        };
+        let pool = WorkerPool::new_with_services(1, repo.clone(), services).await.unwrap();
+
+        let job = Job::new(JobRequest {
+            job_type: JobType::Notification,
+            payload: json!({
+                "template_id": "password_changed",
+                "recipient": "user@example.com",
+                "context": {"username": "user"}
+            }),
+            priority: None,
+            max_retries: Some(2),
+        });
+        let job_id = job.id;
+        repo.create(&job).await.unwrap();
+        pool.submit_job(job).await.unwrap();
+
+        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+        let updated_job = repo.get_by_id(job_id).await.unwrap().unwrap();
+        assert_eq!(updated_job.status, JobStatus::Completed);
+        assert_eq!(updated_job.retry_count, 1);
+        assert_eq!(notifier.delivered.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let attempts = repo.list_attempts(job_id).await.unwrap();
+        let outcomes: Vec<_> = attempts.iter().map(|attempt| (attempt.attempt, attempt.outcome.clone())).collect();
+        assert_eq!(outcomes, vec![(1, AttemptOutcome::Failed), (2, AttemptOutcome::Completed)]);
+        assert_eq!(attempts[0].error.as_deref(), Some("Job processing error: smtp down"));
+        assert!(attempts[1].queued_at >= attempts[0].finished_at.unwrap());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_retry_backoff_waits_on_injected_clock() {
+        let repo = create_test_repository().await;
+        let notifier = Arc::new(FlakyNotifier {
+            failures_left: 2.into(),
+            delivered: 0.into(),
+        });
+        let clock = crate::clock::MockClock::default();
+        let services = WorkerServices {
+            notifier: Some(notifier.clone()),
+            retry_delay: Duration::from_secs(600),
+            clock: clock.shared(),
+            ..WorkerServices::default()
+        };
+        let pool = WorkerPool::new_with_services(1, repo.clone(), services).await.unwrap();
+
+        let job = Job::new(JobRequest {
+            job_type: JobType::Notification,
+            payload: json!({
+                "template_id": "password_changed",
+                "recipient": "user@example.com",
+                "context": {"username": "user"}
+            }),
+            priority: None,
+            max_retries: Some(3),
+        });
+        let job_id = job.id;
+        repo.create(&job).await.unwrap();
+        pool.submit_job(job).await.unwrap();
+
+        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+        let updated_job = repo.get_by_id(job_id).await.unwrap().unwrap();
+        assert_eq!(updated_job.status, JobStatus::Completed);
+        assert_eq!(updated_job.retry_count, 2);
+        assert_eq!(clock.elapsed(), Duration::from_secs(600 + 1200));
+    }
+}
@@ -1,27 +1,65 @@
 //! Core library containing business logic and route handlers for the HTTP server.
 
+pub mod audit;
 pub mod auth;
 pub mod cache;
+pub mod capture;
+pub mod changes;
+pub mod clock;
+pub mod comments;
 pub mod config;
 pub mod database;
 pub mod error;
+pub mod events;
 pub mod extractors;
+pub mod features;
 pub mod files;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod handlers;
 pub mod health;
+pub mod ids;
+pub mod integrity;
+pub mod item_limits;
+pub mod item_metadata;
+pub mod item_names;
+pub mod item_schema;
+pub mod item_secrets;
+pub mod item_transform;
 pub mod jobs;
+pub mod list_preferences;
 pub mod middleware;
 pub mod models;
 pub mod monitoring;
+pub mod net;
+pub mod notifications;
+pub mod outbound;
 pub mod search;
+pub mod seed;
+pub mod server;
 pub mod services;
+pub mod snapshot;
+pub mod startup;
+pub mod state_builder;
 pub mod store;
+pub mod supervisor;
+pub mod tenancy;
+pub mod transaction;
+pub mod trash;
 pub mod metrics;
 pub mod validation;
+pub mod webhooks;
 pub mod websocket;
 
+#[cfg(any(test, feature = "test_support"))]
+pub mod test_support;
+
+pub use audit::{AuditEvent, AuditLog};
 pub use auth::{AuthService, JwtService, UserRepository, UserRepositoryTrait};
 pub use cache::{CacheManager, CacheStats};
+pub use comments::CommentService;
 pub use config::AppConfig;
 pub use database::{DatabaseManager, get_database_pool, run_migrations, ItemRepository, MigrationService};
 pub use files::{FileManager, FileRepository, FileValidator, FileManagerConfig};
@@ -31,15 +69,18 @@ pub use monitoring::{SystemMonitor, SystemMetrics, ResourceUsage, DiskUsage};
 pub use search::{SearchEngine, SearchQuery, SearchResult, SearchFilters, SearchCache};
 pub use services::ItemService;
 pub use error::{AppError, Result};
-pub use handlers::routes::create_routes;
+pub use handlers::routes::{create_routes, route_inventory};
 
-pub use middleware::cors::{cors_layer, cors_layer_permissive, cors_layer_from_config};
+pub use middleware::cors::{cors_layer, cors_layer_permissive, cors_layer_from_config, CorsPolicy};
 pub use middleware::auth::{AuthUser, jwt_auth_middleware, optional_jwt_auth_middleware, require_admin, require_self_or_admin};
 pub use middleware::cache::cache_middleware;
+pub use state_builder::AppStateBuilder;
 pub use store::DataStore;
-pub use metrics::MetricsCollector;
+pub use metrics::{MetricsCollector, MetricsSink};
 pub use middleware::rate_limit::RateLimiter;
-pub use validation::{ValidationResult, ValidationContext, Validatable, ContextValidatable, SecurityValidator};
+pub use validation::{ValidationResult, ValidationError, ValidationContext, Validatable, ContextValidatable, SecurityValidator};
+pub use snapshot::SnapshotService;
+pub use webhooks::WebhookService;
 pub use websocket::{WebSocketManager, websocket_handler};
 
 use axum::{
@@ -57,44 +98,102 @@ use tracing::info;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub app_name: String,
-    pub version: String,
+    // Shared rather than owned: the state is cloned by every middleware layer
+    // on every request.
+    pub app_name: Arc<str>,
+    pub version: Arc<str>,
     pub store: DataStore,
     pub db_manager: Option<DatabaseManager>,
     pub item_service: ItemService,
+    /// Required item metadata, shared with `item_service`.
+    pub item_schema: item_schema::ItemSchema,
+    /// Who may read item secrets and the keys they are sealed under.
+    pub item_secrets: item_secrets::ItemSecrets,
     pub search_engine: Option<SearchEngine>,
-    pub metrics: MetricsCollector,
+    pub metrics: Arc<dyn MetricsSink>,
+    /// The client every outbound HTTP call goes through.
+    pub outbound: outbound::OutboundHttp,
     pub rate_limiter: RateLimiter,
     pub auth_service: Option<AuthService>,
     pub websocket_manager: Option<WebSocketManager>,
     pub file_manager: Option<FileManager>,
     pub job_queue: Option<JobQueue>,
+    pub webhooks: Option<WebhookService>,
+    /// Item comments, present only with a database.
+    pub comments: Option<CommentService>,
+    pub snapshots: Option<SnapshotService>,
     pub cache_manager: Option<CacheManager>,
     pub health_checker: Option<std::sync::Arc<HealthChecker>>,
     pub system_monitor: Option<std::sync::Arc<SystemMonitor>>,
+    pub validation_config: crate::config::ValidationConfig,
+    pub anomaly_tracker: validation::AnomalyTracker,
+    pub audit_log: AuditLog,
+    /// Delivers the events writes publish to the side effects that follow
+    /// them.
+    pub events: events::EventBus,
+    pub trash_config: crate::config::TrashConfig,
+    pub integrity_config: crate::config::IntegrityConfig,
+    /// The latest integrity check, shown by the health checker.
+    pub last_integrity_check: integrity::LastIntegrityCheck,
+    pub duplicate_config: crate::config::DuplicateConfig,
+    pub suggest_config: crate::config::SuggestConfig,
+    pub search_export_config: crate::config::SearchExportConfig,
+    pub pagination_config: crate::config::PaginationConfig,
+    /// Request capture, present only when `capture.enabled` is set.
+    pub capture: Option<capture::CaptureRecorder>,
+    /// Runs the background loops, restarting them when they panic.
+    pub supervisor: supervisor::Supervisor,
+    /// Runtime switches for the job queue, WebSockets and search.
+    pub features: features::FeatureSwitches,
+    /// How each subsystem came up, set once startup is done.
+    pub startup_report: Option<Arc<startup::StartupReport>>,
 }
 
 impl Default for AppState {
     fn default() -> Self {
         let store = DataStore::new();
         let item_service = ItemService::with_memory_store(store.clone());
-        
+        let audit_log = AuditLog::new();
+        let events = events::EventBus::new();
+        events.subscribe(events::AuditTrail::new(audit_log.clone()));
+
         Self {
-            app_name: "Rust HTTP Server".to_string(),
-            version: env!("CARGO_PKG_VERSION").to_string(),
+            app_name: Arc::from("Rust HTTP Server"),
+            version: Arc::from(env!("CARGO_PKG_VERSION")),
             store,
             db_manager: None,
             item_service,
+            item_schema: item_schema::ItemSchema::default(),
+            item_secrets: item_secrets::ItemSecrets::default(),
             search_engine: None,
-            metrics: MetricsCollector::new(),
+            metrics: Arc::new(MetricsCollector::new()),
+            outbound: outbound::OutboundHttp::default(),
             rate_limiter: RateLimiter::new(crate::config::RateLimitConfig::default()),
             auth_service: None,
             websocket_manager: None,
             file_manager: None,
             job_queue: None,
+            webhooks: None,
+            comments: None,
+            snapshots: None,
             cache_manager: None,
             health_checker: None,
             system_monitor: None,
+            validation_config: crate::config::ValidationConfig::default(),
+            anomaly_tracker: validation::AnomalyTracker::default(),
+            audit_log,
+            events,
+            trash_config: crate::config::TrashConfig::default(),
+            integrity_config: crate::config::IntegrityConfig::default(),
+            last_integrity_check: integrity::LastIntegrityCheck::default(),
+            duplicate_config: crate::config::DuplicateConfig::default(),
+            suggest_config: crate::config::SuggestConfig::default(),
+            search_export_config: crate::config::SearchExportConfig::default(),
+            pagination_config: crate::config::PaginationConfig::default(),
+            capture: None,
+            supervisor: supervisor::Supervisor::new(),
+            features: features::FeatureSwitches::default(),
+            startup_report: None,
         }
     }
 }
@@ -105,37 +204,96 @@ impl AppState {
         let item_service = ItemService::with_database(item_repository, store.clone());
         let search_cache = SearchCache::default();
         let search_engine = SearchEngine::new(db_manager.pool().clone()).with_cache(search_cache);
-        
+        let audit_log = AuditLog::new();
+        let events = events::EventBus::new();
+        events.subscribe(events::AuditTrail::new(audit_log.clone()));
+        events.subscribe(events::SearchCacheInvalidation::new(search_engine.clone()));
+
         Self {
-            app_name: "Rust HTTP Server".to_string(),
-            version: env!("CARGO_PKG_VERSION").to_string(),
+            app_name: Arc::from("Rust HTTP Server"),
+            version: Arc::from(env!("CARGO_PKG_VERSION")),
             store,
             db_manager: Some(db_manager),
             item_service,
+            item_schema: item_schema::ItemSchema::default(),
+            item_secrets: item_secrets::ItemSecrets::default(),
             search_engine: Some(search_engine),
-            metrics: MetricsCollector::new(),
+            metrics: Arc::new(MetricsCollector::new()),
+            outbound: outbound::OutboundHttp::default(),
             rate_limiter: RateLimiter::new(crate::config::RateLimitConfig::default()),
             auth_service: None,
             websocket_manager: None,
             file_manager: None,
             job_queue: None,
+            webhooks: None,
+            comments: None,
+            snapshots: None,
             cache_manager: None,
             health_checker: None,
             system_monitor: None,
+            validation_config: crate::config::ValidationConfig::default(),
+            anomaly_tracker: validation::AnomalyTracker::default(),
+            audit_log,
+            events,
+            trash_config: crate::config::TrashConfig::default(),
+            integrity_config: crate::config::IntegrityConfig::default(),
+            last_integrity_check: integrity::LastIntegrityCheck::default(),
+            duplicate_config: crate::config::DuplicateConfig::default(),
+            suggest_config: crate::config::SuggestConfig::default(),
+            search_export_config: crate::config::SearchExportConfig::default(),
+            pagination_config: crate::config::PaginationConfig::default(),
+            capture: None,
+            supervisor: supervisor::Supervisor::new(),
+            features: features::FeatureSwitches::default(),
+            startup_report: None,
         }
     }
 
+    pub fn with_metrics(mut self, metrics: Arc<dyn MetricsSink>) -> Self {
+        self.outbound = self.outbound.with_metrics(metrics.clone());
+        self.metrics = metrics;
+        self
+    }
+
+    /// Replaces the outbound client, recording its calls in this state's
+    /// metrics.
+    pub fn with_outbound(mut self, outbound: outbound::OutboundHttp) -> Self {
+        self.outbound = outbound.with_metrics(self.metrics.clone());
+        self
+    }
+
     pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
         self.rate_limiter = rate_limiter;
         self
     }
 
+    pub fn with_validation_config(mut self, validation_config: crate::config::ValidationConfig) -> Self {
+        self.validation_config = validation_config;
+        self
+    }
+
+    pub fn with_anomaly_tracker(mut self, anomaly_tracker: validation::AnomalyTracker) -> Self {
+        self.anomaly_tracker = anomaly_tracker;
+        self
+    }
+
     pub fn with_auth(mut self, auth_service: AuthService) -> Self {
         self.auth_service = Some(auth_service);
         self
     }
 
+    /// Switches for the optional subsystems. Set before the WebSocket
+    /// manager and job queue, which are handed their switch when added.
+    pub fn with_features(mut self, features: features::FeatureSwitches) -> Self {
+        self.features = features;
+        self
+    }
+
     pub fn with_websocket(mut self, websocket_manager: WebSocketManager) -> Self {
+        let websocket_manager =
+            websocket_manager.with_switch(self.features.switch(features::Subsystem::Websockets).clone());
+        self.supervisor = self.supervisor.with_alerts(websocket_manager.clone());
+        self.events.subscribe(events::WebSocketBroadcast::new(websocket_manager.clone()));
         self.websocket_manager = Some(websocket_manager);
         self
     }
@@ -150,26 +308,320 @@ impl AppState {
         self
     }
 
+    pub fn with_webhooks(mut self, webhooks: WebhookService) -> Self {
+        self.events.subscribe(events::WebhookPublisher::new(webhooks.clone()));
+        self.webhooks = Some(webhooks);
+        self
+    }
+
+    pub fn with_comments(mut self, comments: CommentService) -> Self {
+        self.comments = Some(comments);
+        self
+    }
+
+    pub fn with_snapshots(mut self, snapshots: SnapshotService) -> Self {
+        self.snapshots = Some(snapshots);
+        self
+    }
+
     pub fn with_cache_manager(mut self, cache_manager: CacheManager) -> Self {
+        self.events.subscribe(events::CacheInvalidation::new(cache_manager.clone()));
         self.cache_manager = Some(cache_manager);
         self
     }
 
-    pub fn with_health_checker(mut self) -> Self {
-        let health_checker = HealthChecker::from_app_state(&self);
+    pub fn with_health_checker(self) -> Self {
+        self.with_health_config(&crate::config::HealthConfig::default())
+    }
+
+    /// Change feed retention for the item service. The in-memory change log
+    /// is shared with `store`, so it is configured too.
+    pub fn with_change_feed(mut self, config: &crate::config::ChangeFeedConfig) -> Self {
+        self.item_service = self.item_service.with_change_feed(config);
+        self
+    }
+
+    /// Item write policy for the item service.
+    pub fn with_item_config(mut self, config: &crate::config::ItemConfig) -> Self {
+        self.item_service = self.item_service.with_item_config(config);
+        self
+    }
+
+    /// Metadata schema for item writes. Must be set before the item
+    /// service is handed to other services, which keep their own clone.
+    pub fn with_item_schema(mut self, config: &crate::config::ItemSchemaConfig) -> Self {
+        self.item_schema = item_schema::ItemSchema::new(config.clone());
+        self.item_service = self.item_service.with_item_schema(self.item_schema.clone());
+        self
+    }
+
+    /// Checker reporting stored items that do not match the schema.
+    pub fn schema_checker(&self) -> item_schema::SchemaChecker {
+        item_schema::SchemaChecker::new(self.item_service.clone(), self.item_schema.clone(), self.audit_log.clone())
+    }
+
+    /// Checker reporting stored items that break the item name rule.
+    pub fn name_checker(&self) -> item_names::NameChecker {
+        item_names::NameChecker::new(self.item_service.clone(), self.audit_log.clone())
+    }
+
+    /// Builds the index backing the item name rule. When stored items
+    /// already break the rule, the server runs without it and, given a job
+    /// queue, a `NameValidation` job is queued to list them. Returns
+    /// whether the rule is fully enforced.
+    pub async fn prepare_item_names(&self) -> Result<bool> {
+        if self.item_service.ensure_name_index().await? {
+            return Ok(true);
+        }
+
+        tracing::warn!(
+            "Stored items already break items.unique_names, so it is enforced without a unique index; see the NameValidation job"
+        );
+        if let Some(job_queue) = &self.job_queue {
+            job_queue
+                .submit_job(jobs::JobRequest {
+                    job_type: jobs::JobType::NameValidation,
+                    payload: serde_json::json!({ "requested_by": "startup" }),
+                    priority: None,
+                    max_retries: Some(0),
+                })
+                .await?;
+        }
+        Ok(false)
+    }
+
+    /// Keys and read roles for item secrets, loaded with
+    /// [`ItemSecrets::from_config`](item_secrets::ItemSecrets::from_config).
+    /// Like the schema, must be set before the item service is handed on.
+    pub fn with_item_secrets(mut self, secrets: item_secrets::ItemSecrets) -> Self {
+        self.item_service = self.item_service.with_item_secrets(secrets.clone());
+        self.item_secrets = secrets;
+        self
+    }
+
+    /// Rekeyer sealing every item secret under the current key.
+    pub fn secret_rekeyer(&self) -> item_secrets::SecretRekeyer {
+        item_secrets::SecretRekeyer::new(self.item_service.clone(), self.item_secrets.clone(), self.audit_log.clone())
+    }
+
+    /// Transformer applying bulk metadata changes to this state's items.
+    pub fn metadata_transformer(&self) -> item_transform::MetadataTransformer {
+        item_transform::MetadataTransformer::new(self.item_service.clone(), self.audit_log.clone())
+            .with_events(self.events.clone())
+    }
+
+    /// Retention and batching for purging deleted items.
+    pub fn with_trash_config(mut self, config: &crate::config::TrashConfig) -> Self {
+        self.trash_config = config.clone();
+        self
+    }
+
+    /// Purger for this state's items, auditing into its audit log.
+    pub fn trash_purger(&self) -> trash::TrashPurger {
+        trash::TrashPurger::new(self.item_service.clone(), self.audit_log.clone(), self.trash_config.clone())
+    }
+
+    /// Startup mode, budget and batching of the integrity check.
+    pub fn with_integrity_config(mut self, config: &crate::config::IntegrityConfig) -> Self {
+        self.integrity_config = config.clone();
+        self
+    }
+
+    /// Checker of the references between this state's tables, when there
+    /// is a database.
+    pub fn integrity_checker(&self) -> Option<integrity::IntegrityChecker> {
+        let pool = self.db_manager.as_ref()?.pool().clone();
+        Some(integrity::IntegrityChecker::new(
+            pool,
+            self.audit_log.clone(),
+            self.integrity_config.clone(),
+            self.last_integrity_check.clone(),
+        ))
+    }
+
+    /// Runs the integrity check as `integrity.startup` says, for at most
+    /// `integrity.startup_budget_ms`. When that leaves references unchecked
+    /// and there is a job queue, an `IntegrityCheck` job runs the whole
+    /// check again.
+    pub async fn check_integrity_at_startup(&self) -> Result<Option<integrity::IntegrityReport>> {
+        let repair = match self.integrity_config.startup {
+            crate::config::IntegrityMode::Off => return Ok(None),
+            crate::config::IntegrityMode::Warn => false,
+            crate::config::IntegrityMode::Repair => true,
+        };
+        let Some(checker) = self.integrity_checker() else {
+            return Ok(None);
+        };
+
+        let budget = std::time::Duration::from_millis(self.integrity_config.startup_budget_ms);
+        let report = checker.check(repair, Some(budget), "startup").await?;
+        if !report.complete {
+            tracing::warn!("The integrity check ran out of its startup budget; the rest is left to an IntegrityCheck job");
+            if let Some(job_queue) = &self.job_queue {
+                job_queue
+                    .submit_job(jobs::JobRequest {
+                        job_type: jobs::JobType::IntegrityCheck,
+                        payload: serde_json::json!({ "requested_by": "startup", "repair": repair }),
+                        priority: None,
+                        max_retries: Some(0),
+                    })
+                    .await?;
+            }
+        }
+        Ok(Some(report))
+    }
+
+    /// Reconciler for this state's stored files, when file storage is on.
+    pub fn file_reconciler(&self) -> Option<files::FileReconciler> {
+        self.file_manager
+            .clone()
+            .map(|file_manager| files::FileReconciler::new(file_manager, self.audit_log.clone()))
+    }
+
+    /// Extracts uploaded files' text for content search, when file storage
+    /// is enabled.
+    pub fn content_indexer(&self) -> Option<files::ContentIndexer> {
+        self.file_manager
+            .clone()
+            .map(|file_manager| files::ContentIndexer::new(file_manager, self.audit_log.clone()))
+    }
+
+    /// Loads development fixtures, when there is a database and accounts.
+    pub fn seeder(&self) -> Option<seed::Seeder> {
+        let pool = self.db_manager.as_ref()?.pool().clone();
+        let auth = self.auth_service.clone()?;
+        Some(seed::Seeder::new(
+            self.item_service.clone(),
+            auth,
+            self.file_manager.clone(),
+            pool,
+            self.audit_log.clone(),
+        ))
+    }
+
+    /// Thresholds and caps for near-duplicate detection.
+    pub fn with_duplicate_config(mut self, config: &crate::config::DuplicateConfig) -> Self {
+        self.duplicate_config = config.clone();
+        self
+    }
+
+    pub fn duplicate_detector(&self) -> search::DuplicateDetector {
+        search::DuplicateDetector::new(
+            self.search_engine().cloned(),
+            self.item_service.clone(),
+            self.duplicate_config.clone(),
+        )
+    }
+
+    /// The full-text index, unless there is none or search is switched off,
+    /// in which case searches filter items in memory.
+    pub fn search_engine(&self) -> Option<&SearchEngine> {
+        self.search_engine
+            .as_ref()
+            .filter(|_| self.features.is_enabled(features::Subsystem::Search))
+    }
+
+    /// Minimum prefix, limits and cache sizing for typeahead suggestions.
+    pub fn with_suggest_config(mut self, config: &crate::config::SuggestConfig) -> Self {
+        self.suggest_config = config.clone();
+        self.search_engine = self.search_engine.map(|engine| {
+            engine.with_suggestion_cache(config.cache_size, std::time::Duration::from_secs(config.cache_ttl_seconds))
+        });
+        // The suggestion cache was replaced.
+        self.subscribe_search_engine();
+        self
+    }
+
+    /// Column weights and recency boost for full-text relevance.
+    pub fn with_search_config(mut self, config: &crate::config::SearchConfig) -> Self {
+        self.search_engine = self.search_engine.map(|engine| engine.with_ranking(config));
+        self.subscribe_search_engine();
+        self
+    }
+
+    fn subscribe_search_engine(&self) {
+        if let Some(search_engine) = &self.search_engine {
+            self.events.subscribe(events::SearchCacheInvalidation::new(search_engine.clone()));
+        }
+    }
+
+    pub fn suggester(&self) -> search::Suggester {
+        search::Suggester::new(
+            self.search_engine().cloned(),
+            self.item_service.clone(),
+            self.suggest_config.clone(),
+        )
+    }
+
+    /// When search results are exported directly and how large the pages
+    /// of an export job are.
+    pub fn with_search_export_config(mut self, config: &crate::config::SearchExportConfig) -> Self {
+        self.search_export_config = config.clone();
+        self
+    }
+
+    /// Default and largest page sizes of the lists.
+    pub fn with_pagination_config(mut self, config: &crate::config::PaginationConfig) -> Self {
+        self.pagination_config = config.clone();
+        self
+    }
+
+    pub fn search_exporter(&self) -> search::SearchExporter {
+        search::SearchExporter::new(
+            self.search_engine.clone(),
+            self.item_service.clone(),
+            self.file_manager.clone(),
+            self.search_export_config.clone(),
+        )
+        .with_search_switch(self.features.switch(features::Subsystem::Search).clone())
+    }
+
+    pub fn unified_search(&self) -> search::UnifiedSearch {
+        search::UnifiedSearch::new(
+            self.search_engine().cloned(),
+            self.item_service.clone(),
+            self.file_manager.clone(),
+            self.auth_service.clone(),
+        )
+    }
+
+    /// Whether a GET of `uri` from an anonymous client in the default
+    /// namespace would be answered from the response cache.
+    pub fn is_response_cached(&self, uri: &str) -> bool {
+        let Some(cache_manager) = &self.cache_manager else {
+            return false;
+        };
+        let Ok(request) = Request::get(uri).body(Body::empty()) else {
+            return false;
+        };
+        cache_manager.contains_key(&middleware::cache::response_cache_key(&request))
+    }
+
+    /// Builds the health checker from the configured components, persisting
+    /// its transition log in the database when there is one.
+    pub fn with_health_config(mut self, config: &crate::config::HealthConfig) -> Self {
+        let mut health_checker = HealthChecker::from_app_state(&self).with_config(config);
+        if let (true, Some(db_manager)) = (config.persist_history, &self.db_manager) {
+            health_checker = health_checker.with_history_store(health::HealthHistoryStore::new(db_manager.pool().clone()));
+        }
         self.health_checker = Some(std::sync::Arc::new(health_checker));
         self
     }
 
-    pub fn with_system_monitor(mut self) -> Self {
-        let system_monitor = SystemMonitor::new();
+    pub fn with_system_monitor(mut self, system_monitor: SystemMonitor) -> Self {
         self.system_monitor = Some(std::sync::Arc::new(system_monitor));
         self
     }
 
+    pub fn with_startup_report(mut self, report: startup::StartupReport) -> Self {
+        self.startup_report = Some(Arc::new(report));
+        self
+    }
+
     pub async fn create_job_queue_with_websocket(&self, job_repository: JobRepository) -> Result<JobQueue> {
         let websocket_manager = self.websocket_manager.as_ref().map(|ws| Arc::new(ws.clone()));
-        let job_queue = JobQueue::new_with_websocket(job_repository, websocket_manager);
+        let job_queue = JobQueue::new_with_websocket(job_repository, websocket_manager)
+            .with_switch(self.features.switch(features::Subsystem::Jobs).clone());
         Ok(job_queue)
     }
 
@@ -191,21 +643,56 @@ pub fn create_app(state: AppState) -> Router {
     create_app_with_config(state, AppConfig::default())
 }
 
-pub fn create_app_with_config(state: AppState, config: AppConfig) -> Router {
-    let mut router = Router::new().merge(create_routes());
+pub fn create_app_with_config(mut state: AppState, config: AppConfig) -> Router {
+    if config.capture.enabled {
+        state.capture = Some(capture::CaptureRecorder::new(&config.capture, state.audit_log.clone()));
+    }
+
+    let mut router = Router::new()
+        .merge(create_routes())
+        .nest("/auth", handlers::auth::create_auth_routes_with_middleware(state.clone(), &config.auth));
+    if state.auth_service.is_some() {
+        router = router
+            .merge(handlers::introspect::create_token_check_routes(&config.auth))
+            .merge(handlers::admin_ui::create_admin_ui_routes(&config));
+    }
 
-    router = router.layer(middleware::cors::cors_layer_from_config(&config.cors));
+    #[cfg(feature = "graphql")]
+    if config.graphql.enabled {
+        router = router.merge(graphql::create_graphql_routes(&config.graphql));
+    }
+
+    let batch = config
+        .batch
+        .enabled
+        .then(|| handlers::batch::BatchDispatcher::new(config.batch.clone()));
+    if let Some(batch) = &batch {
+        router = router.merge(handlers::batch::create_batch_routes(batch.clone()));
+    }
+
+    // Inside the cache, so raw and enveloped bodies are cached separately.
+    router = router.layer(axum_middleware::from_fn(middleware::envelope::envelope_middleware));
 
     router = router.layer(axum_middleware::from_fn_with_state(
         state.clone(),
-        middleware::auth::optional_jwt_auth_middleware,
+        middleware::cache::cache_middleware,
     ));
 
+    // Outside the cache, so a response cached for one origin is sent with
+    // the CORS headers of the origin asking for it.
     router = router.layer(axum_middleware::from_fn_with_state(
-        state.clone(),
-        middleware::cache::cache_middleware,
+        middleware::cors::CorsPolicy::from_config(&config.cors),
+        middleware::cors::cors_middleware,
     ));
 
+    if let Some(auth_service) = state.auth_service.clone() {
+        router = router.layer(axum_middleware::from_fn_with_state(
+            auth_service,
+            middleware::list_preferences::list_preferences_middleware,
+        ));
+    }
+
+    // Rate limiting runs after authentication so it can pick the caller's tier.
     if config.rate_limit.enable {
         router = router.layer(axum_middleware::from_fn_with_state(
             state.rate_limiter.clone(),
@@ -213,26 +700,98 @@ pub fn create_app_with_config(state: AppState, config: AppConfig) -> Router {
         ));
     }
 
+    router = router.layer(axum_middleware::from_fn_with_state(
+        middleware::timeout::RequestTimeouts::new(&config.server, &config.timeouts, state.metrics.clone()),
+        middleware::timeout::request_timeout_middleware,
+    ));
+
+    // Inside authentication so that captures can be limited to one user.
+    if let Some(recorder) = state.capture.clone() {
+        router = router.layer(axum_middleware::from_fn_with_state(
+            recorder,
+            middleware::capture::capture_middleware,
+        ));
+    }
+
+    // Everything inside runs in the caller's namespace, which is only known
+    // once the token has been checked.
+    router = router.layer(axum_middleware::from_fn_with_state(
+        state.clone(),
+        middleware::namespace::namespace_middleware,
+    ));
+
+    // Needs the token's claims, so it sits just inside authentication.
+    if let Some(policy) = state.auth_service.as_ref().and_then(|auth| auth.unverified_user_policy()) {
+        if policy == config::UnverifiedUserPolicy::BlockItemWrites {
+            router = router.layer(axum_middleware::from_fn_with_state(
+                policy,
+                middleware::auth::email_verification_middleware,
+            ));
+        }
+    }
+
+    router = router.layer(axum_middleware::from_fn_with_state(
+        state.clone(),
+        middleware::auth::optional_jwt_auth_middleware,
+    ));
+
     router = router.layer(axum_middleware::from_fn_with_state(
         state.clone(),
         metrics_middleware,
     ));
 
-    router = router.layer(axum_middleware::from_fn(validation::middleware::validation_middleware));
+    if config.concurrency.enable {
+        router = router.layer(axum_middleware::from_fn_with_state(
+            middleware::concurrency::ConcurrencyLimiter::new(
+                config.server.max_connections,
+                &config.concurrency,
+                state.metrics.clone(),
+            ),
+            middleware::concurrency::concurrency_limit_middleware,
+        ));
+    }
+
+    // Shed requests are rejected before they are timed, so the fast 503s do
+    // not mask the latency that triggered shedding.
+    router = router.layer(axum_middleware::from_fn_with_state(
+        middleware::load_shed::LoadShedder::new(&config.load_shedding, state.metrics.clone()),
+        middleware::load_shed::load_shedding_middleware,
+    ));
+
+    router = router.layer(axum_middleware::from_fn_with_state(
+        state.clone(),
+        validation::middleware::validation_middleware,
+    ));
+
+    router = router.layer(axum_middleware::from_fn_with_state(
+        state.clone(),
+        validation::middleware::anomaly_blocking_middleware,
+    ));
 
     router = router.layer(axum_middleware::from_fn(
         middleware::request_validation::request_validation_middleware
     ));
 
     router = router.layer(axum_middleware::from_fn(
-        middleware::logging::log_request_with_config(config.logging)
+        middleware::logging::log_request_with_config(
+            config.logging,
+            Some(middleware::provenance::Provenance::new(
+                &config.server.instance_id,
+                state.item_service.data_source(),
+            )),
+        )
     ));
 
     router = router.layer(axum_middleware::from_fn(
         middleware::request_validation::security_headers_middleware
     ));
 
-    router.with_state(state)
+    let router = router.with_state(state);
+    // Batch sub-requests are sent back through every layer above.
+    if let Some(batch) = batch {
+        batch.bind(router.clone());
+    }
+    router
 }
 
 async fn metrics_middleware(
@@ -240,17 +799,25 @@ async fn metrics_middleware(
     request: Request<Body>,
     next: Next,
 ) -> std::result::Result<Response, std::convert::Infallible> {
-    let method = request.method().to_string();
-    let path = request.uri().path().to_string();
+    // Label by route template so that path parameters don't create a new
+    // endpoint entry per id.
+    let matched = request.extensions().get::<axum::extract::MatchedPath>().cloned();
+    let endpoint = matched.as_ref().map_or(metrics::UNMATCHED_ENDPOINT, |matched| matched.as_str());
     let start = std::time::Instant::now();
     
-    state.metrics.record_request(&method, &path);
+    state.metrics.record_request(request.method().as_str(), endpoint);
     
-    let response = next.run(request).await;
+    let queries = Arc::new(database::QueryStats::default());
+    let mut response = database::QueryStats::scope(queries.clone(), next.run(request)).await;
     
     let duration = start.elapsed();
     let status = response.status().as_u16();
-    state.metrics.record_response(&path, duration.as_millis(), status);
+    state.metrics.record_response(endpoint, duration, status);
+
+    // Handed to the access log, which runs outside this middleware.
+    let queries = queries.snapshot();
+    state.metrics.record_database_usage(endpoint, queries, duration);
+    response.extensions_mut().insert(queries);
     
     Ok(response)
 }
@@ -283,17 +850,38 @@ async fn security_headers_middleware(
 }
 
 pub async fn run_server(app: Router, addr: SocketAddr) -> Result<()> {
-    info!("Starting server on {}", addr);
+    let config = crate::config::ServerConfig {
+        host: addr.ip().to_string(),
+        port: addr.port(),
+        ..crate::config::ServerConfig::default()
+    };
+    run_server_with_config(app, &config).await
+}
 
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    
-    let app = app.into_make_service_with_connect_info::<SocketAddr>();
-    
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
-    
-    Ok(())
+/// Serves `app` on every listener in `config` until Ctrl+C or SIGTERM.
+pub async fn run_server_with_config(app: Router, config: &crate::config::ServerConfig) -> Result<()> {
+    info!("Starting server on {}:{}", config.host, config.port);
+
+    server::Server::bind(config).await?.serve(app, shutdown_signal()).await
+}
+
+/// As [`run_server_with_config`], plus the gRPC API on `grpc_port` when
+/// one is configured. Both servers stop on the same signal.
+#[cfg(feature = "grpc")]
+pub async fn run_server_with_grpc(app: Router, state: AppState, config: &crate::config::ServerConfig) -> Result<()> {
+    use futures_util::FutureExt;
+
+    if config.grpc_port.is_none() {
+        return run_server_with_config(app, config).await;
+    }
+
+    info!("Starting server on {}:{}", config.host, config.port);
+    let http = server::Server::bind(config).await?;
+    let grpc = grpc::GrpcServer::bind(config).await?;
+
+    let signal = shutdown_signal().boxed().shared();
+    let (http, grpc) = tokio::join!(http.serve(app, signal.clone()), grpc.serve(state, signal));
+    http.and(grpc)
 }
 
 async fn shutdown_signal() {
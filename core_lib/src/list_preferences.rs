@@ -0,0 +1,205 @@
+//! Per-user defaults for the item, file and job lists
+//!
+//! A signed-in user can keep, for each list, a default page size, ordering
+//! and, where the list has any, extras to include, through `GET` and `PUT
+//! /auth/me/preferences`. They are stored with the user and checked against
+//! the same page size cap and sortable fields as the list queries.
+//!
+//! [`list_preferences_middleware`](crate::middleware::list_preferences::list_preferences_middleware)
+//! looks the caller's defaults up for list requests and leaves the ones for
+//! that list in the request as [`ListDefaults`]. The handler applies them
+//! where the query says nothing, so explicit parameters always win. Only
+//! signed-in requests have defaults, and the response cache never stores them,
+//! so callers with different defaults never share an entry.
+
+use crate::config::PaginationConfig;
+use crate::database::{SortFields, SortSpec, ITEM_SORT};
+use crate::files::repository::FILE_SORT;
+use crate::jobs::repository::JOB_SORT;
+use crate::models::items::ItemListQuery;
+use crate::validation::{ValidationError, ValidationResult};
+use axum::http::Method;
+use serde::{Deserialize, Serialize};
+
+/// A list whose defaults a user can set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListKind {
+    Items,
+    Files,
+    Jobs,
+}
+
+impl ListKind {
+    pub const ALL: [ListKind; 3] = [ListKind::Items, ListKind::Files, ListKind::Jobs];
+
+    /// The list a `method` request to `path` reads, if any.
+    pub fn of(method: &Method, path: &str) -> Option<Self> {
+        if method != Method::GET {
+            return None;
+        }
+        match path.trim_end_matches('/') {
+            "/api/items" | "/api/v1/items" | "/api/v2/items" => Some(ListKind::Items),
+            "/api/files" => Some(ListKind::Files),
+            "/api/jobs" => Some(ListKind::Jobs),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ListKind::Items => "items",
+            ListKind::Files => "files",
+            ListKind::Jobs => "jobs",
+        }
+    }
+
+    fn sort_fields(&self) -> &'static SortFields {
+        match self {
+            ListKind::Items => &ITEM_SORT,
+            ListKind::Files => &FILE_SORT,
+            ListKind::Jobs => &JOB_SORT,
+        }
+    }
+
+    fn includes(&self) -> &'static [&'static str] {
+        match self {
+            ListKind::Items => ItemListQuery::INCLUDES,
+            ListKind::Files | ListKind::Jobs => &[],
+        }
+    }
+}
+
+/// What one list falls back to when the query leaves something out.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListDefaults {
+    pub page_size: Option<u64>,
+    pub sort: Option<SortSpec>,
+    pub include: Vec<String>,
+}
+
+impl ListDefaults {
+    /// `requested`, or the default page size.
+    pub fn page_size(&self, requested: Option<u64>) -> Option<u64> {
+        requested.or(self.page_size)
+    }
+
+    /// `requested`, or the default ordering.
+    pub fn sort(&self, requested: Option<SortSpec>) -> Option<SortSpec> {
+        requested.or_else(|| self.sort.clone())
+    }
+
+    /// `requested`, or the default extras as an `include` parameter.
+    pub fn include(&self, requested: Option<String>) -> Option<String> {
+        requested.or_else(|| (!self.include.is_empty()).then(|| self.include.join(",")))
+    }
+}
+
+/// A user's defaults for each list.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListPreferences {
+    pub items: ListDefaults,
+    pub files: ListDefaults,
+    pub jobs: ListDefaults,
+}
+
+impl ListPreferences {
+    pub fn for_list(&self, kind: ListKind) -> &ListDefaults {
+        match kind {
+            ListKind::Items => &self.items,
+            ListKind::Files => &self.files,
+            ListKind::Jobs => &self.jobs,
+        }
+    }
+
+    /// Refuses page sizes `pagination` would refuse, fields a list can't be
+    /// sorted on and extras it doesn't have, naming each as `<list>.<field>`.
+    pub fn validate(&self, pagination: &PaginationConfig) -> Result<(), ValidationError> {
+        let mut result = ValidationResult::success();
+
+        for kind in ListKind::ALL {
+            let defaults = self.for_list(kind);
+            let field = |name: &str| format!("{}.{}", kind.name(), name);
+
+            if let Some(size) = defaults.page_size {
+                if size == 0 || size > pagination.max_page_size {
+                    result.add_error(
+                        &field("page_size"),
+                        &format!("Page size must be between 1 and {}", pagination.max_page_size),
+                    );
+                }
+            }
+            if let Some(sort) = &defaults.sort {
+                if let Err(e) = kind.sort_fields().check(sort) {
+                    result.add_error(&field("sort"), &e.to_string());
+                }
+            }
+            for name in &defaults.include {
+                if !kind.includes().contains(&name.as_str()) {
+                    let message = match kind.includes() {
+                        [] => format!("The {} list has nothing to include", kind.name()),
+                        includes => format!("Unknown include '{}'; expected one of {}", name, includes.join(", ")),
+                    };
+                    result.add_error(&field("include"), &message);
+                }
+            }
+        }
+
+        result.ensure_valid("Invalid list preferences")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_preferences_are_checked_like_list_queries() {
+        let pagination = PaginationConfig::default();
+        let valid: ListPreferences = serde_json::from_value(json!({
+            "items": {"page_size": 20, "sort": "-updated_at", "include": ["comment_counts"]},
+            "jobs": {"sort": "priority"}
+        }))
+        .unwrap();
+        assert!(valid.validate(&pagination).is_ok());
+        assert_eq!(valid.files, ListDefaults::default());
+
+        let invalid: ListPreferences = serde_json::from_value(json!({
+            "items": {"page_size": pagination.max_page_size + 1, "include": ["everything"]},
+            "files": {"sort": "colour", "include": ["comment_counts"]}
+        }))
+        .unwrap();
+        let fields: Vec<String> = invalid
+            .validate(&pagination)
+            .unwrap_err()
+            .field_errors()
+            .into_iter()
+            .map(|error| error.field)
+            .collect();
+        for field in ["items.page_size", "items.include", "files.sort", "files.include"] {
+            assert!(fields.contains(&field.to_string()), "{} not in {:?}", field, fields);
+        }
+
+        assert!(serde_json::from_value::<ListPreferences>(json!({"items": {"limit": 5}})).is_err());
+    }
+
+    #[test]
+    fn test_explicit_parameters_win_over_defaults() {
+        let defaults = ListDefaults {
+            page_size: Some(20),
+            sort: Some(SortSpec::parse("-name").unwrap()),
+            include: vec!["comment_counts".to_string()],
+        };
+        assert_eq!(defaults.page_size(None), Some(20));
+        assert_eq!(defaults.page_size(Some(5)), Some(5));
+        assert_eq!(defaults.sort(None).unwrap().to_string(), "-name");
+        assert_eq!(defaults.sort(Some(SortSpec::parse("id").unwrap())).unwrap().to_string(), "id");
+        assert_eq!(defaults.include(None).as_deref(), Some("comment_counts"));
+        assert_eq!(defaults.include(Some(String::new())).as_deref(), Some(""));
+
+        assert_eq!(ListKind::of(&Method::GET, "/api/files/"), Some(ListKind::Files));
+        assert_eq!(ListKind::of(&Method::POST, "/api/items"), None);
+    }
+}
@@ -1,34 +1,122 @@
 //! Server metrics collection and reporting
 
+mod sink;
+mod statsd;
+
+pub use sink::{names, sink_from_config, FanoutSink, InFlightGauge, InFlightGuard, MetricsSink};
+pub use statsd::StatsdSink;
+
 use std::sync::{
     atomic::{AtomicU64, Ordering},
     Arc,
 };
 use std::collections::HashMap;
+use std::time::Duration;
 use parking_lot::RwLock;
 use serde::{Serialize, Deserialize};
 use chrono::{DateTime, Utc};
+use crate::config::MetricsConfig;
+use crate::database::QueryStatsSnapshot;
 use crate::monitoring::{SystemMetrics};
+use crate::monitoring::response_times::{ResponseTimeHistory, ResponseTimePercentiles, ResponseTimePoint};
 use crate::monitoring::system::PerformanceMetrics;
+use crate::monitoring::traffic::{HourlyTraffic, TrafficHistory};
+
+pub use crate::monitoring::response_times::ResponseTime;
+use crate::websocket::WebSocketStats;
+
+/// Endpoint label for requests that matched no route, and for routes seen
+/// after [`MAX_ENDPOINT_LABELS`] distinct labels have been recorded.
+pub const UNMATCHED_ENDPOINT: &str = "unmatched";
+
+/// Hard cap on distinct endpoint labels kept in memory.
+pub const MAX_ENDPOINT_LABELS: usize = 256;
+
+/// Endpoints included in a snapshot, busiest first.
+const SNAPSHOT_TOP_ENDPOINTS: usize = 50;
 
+/// Minutes of per-minute response times included in a snapshot.
+const SNAPSHOT_SERIES_MINUTES: u64 = 15;
+
+/// Keeps metrics in memory for /api/metrics, the dashboard and load
+/// shedding's latency checks.
 #[derive(Clone)]
 pub struct MetricsCollector {
     pub total_requests: Arc<AtomicU64>,
     pub successful_requests: Arc<AtomicU64>,
     pub failed_requests: Arc<AtomicU64>,
     pub requests_by_method: Arc<RwLock<HashMap<String, u64>>>,
-    pub requests_by_endpoint: Arc<RwLock<HashMap<String, u64>>>,
-    pub response_times: Arc<RwLock<Vec<ResponseTime>>>,
+    /// Keyed by the shared label also held by each recorded [`ResponseTime`].
+    pub requests_by_endpoint: Arc<RwLock<HashMap<Arc<str>, u64>>>,
+    pub response_times: Arc<RwLock<ResponseTimeHistory>>,
     pub start_time: DateTime<Utc>,
     pub health_status_changes: Arc<RwLock<Vec<HealthStatusChange>>>,
+    /// Counters by name, then label; unlabelled counts are under "".
+    pub counters: Arc<RwLock<HashMap<String, HashMap<String, u64>>>>,
+    pub gauges: Arc<RwLock<HashMap<String, f64>>>,
+    pub database_usage: Arc<RwLock<HashMap<String, RouteDatabaseUsage>>>,
+    pub traffic: Arc<RwLock<TrafficHistory>>,
+    pub rpc_calls: Arc<RwLock<HashMap<String, RpcMethodUsage>>>,
+    pub outbound_calls: Arc<RwLock<HashMap<String, RpcMethodUsage>>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ResponseTime {
-    pub timestamp: DateTime<Utc>,
-    pub duration_ms: u128,
-    pub endpoint: String,
-    pub status: u16,
+/// Running database totals for one route.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RouteDatabaseUsage {
+    pub requests: u64,
+    pub queries: u64,
+    pub db_time_us: u64,
+    pub request_time_us: u64,
+}
+
+/// Per-route database usage as reported in performance metrics.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteDatabaseMetric {
+    pub route: String,
+    pub requests: u64,
+    pub avg_queries_per_request: f64,
+    pub avg_db_time_ms: f64,
+    /// Share of the route's total response time spent in the database.
+    pub db_time_share_percent: f64,
+}
+
+/// Running totals for one gRPC method or outbound destination.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RpcMethodUsage {
+    pub calls: u64,
+    pub failures: u64,
+    pub total_time_us: u64,
+}
+
+/// Per-method gRPC calls as reported in a snapshot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcMethodMetric {
+    /// Full method path, e.g. `/items.v1.ItemService/GetItem`.
+    pub method: String,
+    pub calls: u64,
+    /// Calls that ended with a status other than `OK`.
+    pub failures: u64,
+    pub avg_duration_ms: f64,
+}
+
+/// Outbound calls to one destination as reported in a snapshot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutboundDestinationMetric {
+    /// `host:port`.
+    pub destination: String,
+    pub calls: u64,
+    /// Calls that got no response or a 5xx one.
+    pub failures: u64,
+    pub avg_duration_ms: f64,
+}
+
+/// Requests under the connection limits, as reported in a snapshot.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConcurrencyMetrics {
+    pub in_flight: u64,
+    pub queued: u64,
+    /// Requests turned away, by reason.
+    pub rejected: HashMap<String, u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -42,10 +130,44 @@ pub struct MetricsSnapshot {
     pub uptime_seconds: i64,
     pub requests_per_second: f64,
     pub error_rate: f64,
-    pub last_hour_response_times: Vec<ResponseTime>,
+    /// Per-minute response times for the last few minutes, oldest first.
+    #[serde(default)]
+    pub response_time_series: Vec<ResponseTimePoint>,
+    #[serde(default)]
+    pub response_time_percentiles: ResponseTimePercentiles,
     pub system_metrics: Option<SystemMetrics>,
     pub performance_metrics: Option<PerformanceMetrics>,
     pub health_status_changes: Vec<HealthStatusChange>,
+    #[serde(default)]
+    pub security_events: HashMap<String, u64>,
+    #[serde(default)]
+    pub websocket: Option<WebSocketStats>,
+    #[serde(default)]
+    pub slow_requests: u64,
+    #[serde(default)]
+    pub timed_out_requests: u64,
+    #[serde(default)]
+    pub coalesced_requests: u64,
+    #[serde(default)]
+    pub coalesce_timeouts: u64,
+    #[serde(default)]
+    pub password_rehashes: u64,
+    #[serde(default)]
+    pub in_flight_requests: u64,
+    /// Requests rejected by load shedding, by traffic class.
+    #[serde(default)]
+    pub shed_requests: HashMap<String, u64>,
+    #[serde(default)]
+    pub concurrency: ConcurrencyMetrics,
+    /// gRPC calls by method, busiest first.
+    #[serde(default)]
+    pub rpc_methods: Vec<RpcMethodMetric>,
+    /// Outbound calls by destination, busiest first.
+    #[serde(default)]
+    pub outbound_destinations: Vec<OutboundDestinationMetric>,
+    /// Outbound calls refused before being made, by reason.
+    #[serde(default)]
+    pub outbound_refused: HashMap<String, u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -66,69 +188,152 @@ pub struct HealthStatusChange {
 
 impl MetricsCollector {
     pub fn new() -> Self {
+        Self::with_config(&MetricsConfig::default())
+    }
+
+    pub fn with_config(config: &MetricsConfig) -> Self {
         Self {
             total_requests: Arc::new(AtomicU64::new(0)),
             successful_requests: Arc::new(AtomicU64::new(0)),
             failed_requests: Arc::new(AtomicU64::new(0)),
             requests_by_method: Arc::new(RwLock::new(HashMap::new())),
             requests_by_endpoint: Arc::new(RwLock::new(HashMap::new())),
-            response_times: Arc::new(RwLock::new(Vec::new())),
+            response_times: Arc::new(RwLock::new(ResponseTimeHistory::new(config))),
             start_time: Utc::now(),
             health_status_changes: Arc::new(RwLock::new(Vec::new())),
+            counters: Arc::new(RwLock::new(HashMap::new())),
+            gauges: Arc::new(RwLock::new(HashMap::new())),
+            database_usage: Arc::new(RwLock::new(HashMap::new())),
+            traffic: Arc::new(RwLock::new(TrafficHistory::new())),
+            rpc_calls: Arc::new(RwLock::new(HashMap::new())),
+            outbound_calls: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
-    pub fn record_request(&self, method: &str, endpoint: &str) {
-        self.total_requests.fetch_add(1, Ordering::Relaxed);
-        
-        let mut methods = self.requests_by_method.write();
-        *methods.entry(method.to_string()).or_insert(0) += 1;
-        
-        let mut endpoints = self.requests_by_endpoint.write();
-        *endpoints.entry(endpoint.to_string()).or_insert(0) += 1;
+    /// Response time aggregates for the last `window_minutes` minutes in
+    /// steps of `resolution_minutes`. Windows longer than the retained
+    /// history are clamped to it.
+    pub fn response_time_series(&self, window_minutes: u64, resolution_minutes: u64) -> Vec<ResponseTimePoint> {
+        self.response_times.read().series(Utc::now(), window_minutes, resolution_minutes)
     }
 
-    pub fn record_response(&self, endpoint: &str, duration_ms: u128, status: u16) {
-        if status < 400 {
-            self.successful_requests.fetch_add(1, Ordering::Relaxed);
-        } else {
-            self.failed_requests.fetch_add(1, Ordering::Relaxed);
-        }
+    pub fn response_time_window_minutes(&self) -> u64 {
+        self.response_times.read().window_minutes()
+    }
 
-        let response_time = ResponseTime {
-            timestamp: Utc::now(),
-            duration_ms,
-            endpoint: endpoint.to_string(),
-            status,
-        };
+    /// Requests counted against the route template `endpoint`.
+    pub fn endpoint_requests(&self, endpoint: &str) -> u64 {
+        self.requests_by_endpoint.read().get(endpoint).copied().unwrap_or(0)
+    }
 
-        let mut times = self.response_times.write();
-        times.push(response_time);
-        
-        if times.len() > 1000 {
-            let drain_end = times.len() - 1000;
-            times.drain(0..drain_end);
-        }
+    /// Retained responses from `endpoint`, oldest first.
+    pub fn recent_responses(&self, endpoint: &str) -> Vec<ResponseTime> {
+        self.response_times
+            .read()
+            .recent()
+            .filter(|response| &*response.endpoint == endpoint)
+            .cloned()
+            .collect()
     }
 
-    pub fn record_health_status_change(&self, component: String, old_status: String, new_status: String, message: String) {
-        let change = HealthStatusChange {
-            timestamp: Utc::now(),
-            component,
-            old_status,
-            new_status,
-            message,
-        };
+    /// Requests per hour over the last day, oldest first.
+    pub fn hourly_traffic(&self) -> Vec<HourlyTraffic> {
+        self.traffic.read().hours(Utc::now())
+    }
 
-        let mut changes = self.health_status_changes.write();
-        changes.push(change);
-        
-        if changes.len() > 100 {
-            let excess = changes.len() - 100;
-            changes.drain(0..excess);
+    /// The total of the counter `name` across its labels.
+    pub fn counter(&self, name: &str) -> u64 {
+        self.counters.read().get(name).map_or(0, |labels| labels.values().sum())
+    }
+
+    /// The counter `name` by label.
+    pub fn labelled_counter(&self, name: &str) -> HashMap<String, u64> {
+        self.counters.read().get(name).cloned().unwrap_or_default()
+    }
+
+    pub fn gauge(&self, name: &str) -> f64 {
+        self.gauges.read().get(name).copied().unwrap_or(0.0)
+    }
+
+    pub fn concurrency(&self) -> ConcurrencyMetrics {
+        ConcurrencyMetrics {
+            in_flight: self.gauge(names::LIMITED_IN_FLIGHT) as u64,
+            queued: self.gauge(names::QUEUED_REQUESTS) as u64,
+            rejected: self.labelled_counter(names::CONCURRENCY_REJECTIONS),
         }
     }
 
+    /// Calls to every gRPC method that has been called, busiest first.
+    pub fn rpc_methods(&self) -> Vec<RpcMethodMetric> {
+        let mut methods: Vec<RpcMethodMetric> = self
+            .rpc_calls
+            .read()
+            .iter()
+            .map(|(method, usage)| RpcMethodMetric {
+                method: method.clone(),
+                calls: usage.calls,
+                failures: usage.failures,
+                avg_duration_ms: usage.total_time_us as f64 / usage.calls.max(1) as f64 / 1000.0,
+            })
+            .collect();
+
+        methods.sort_by(|a, b| b.calls.cmp(&a.calls).then_with(|| a.method.cmp(&b.method)));
+        methods
+    }
+
+    /// Calls to every outbound destination that has been called, busiest
+    /// first.
+    pub fn outbound_destinations(&self) -> Vec<OutboundDestinationMetric> {
+        let mut destinations: Vec<OutboundDestinationMetric> = self
+            .outbound_calls
+            .read()
+            .iter()
+            .map(|(destination, usage)| OutboundDestinationMetric {
+                destination: destination.clone(),
+                calls: usage.calls,
+                failures: usage.failures,
+                avg_duration_ms: usage.total_time_us as f64 / usage.calls.max(1) as f64 / 1000.0,
+            })
+            .collect();
+
+        destinations.sort_by(|a, b| b.calls.cmp(&a.calls).then_with(|| a.destination.cmp(&b.destination)));
+        destinations
+    }
+
+    /// Database usage of every route that has served a request, most
+    /// database time first.
+    pub fn database_usage_by_route(&self) -> Vec<RouteDatabaseMetric> {
+        let usage = self.database_usage.read();
+        let mut routes: Vec<(u64, RouteDatabaseMetric)> = usage
+            .iter()
+            .map(|(route, totals)| {
+                let requests = totals.requests.max(1) as f64;
+                let metric = RouteDatabaseMetric {
+                    route: route.clone(),
+                    requests: totals.requests,
+                    avg_queries_per_request: totals.queries as f64 / requests,
+                    avg_db_time_ms: totals.db_time_us as f64 / requests / 1000.0,
+                    db_time_share_percent: if totals.request_time_us > 0 {
+                        (totals.db_time_us as f64 / totals.request_time_us as f64 * 100.0).min(100.0)
+                    } else {
+                        0.0
+                    },
+                };
+                (totals.db_time_us, metric)
+            })
+            .collect();
+
+        routes.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.route.cmp(&b.1.route)));
+        routes.into_iter().map(|(_, metric)| metric).collect()
+    }
+
+    /// Percentile of the individual response times recorded in the last
+    /// `window`, or `None` if fewer than `min_samples` were recorded.
+    pub fn recent_latency_percentile(&self, percentile: f64, window: Duration, min_samples: usize) -> Option<u64> {
+        let since = Utc::now() - chrono::Duration::from_std(window).unwrap_or(chrono::Duration::MAX);
+        self.response_times.read().recent_percentile(since, percentile, min_samples)
+    }
+
     pub fn get_snapshot(&self, _item_count: usize) -> MetricsSnapshot {
         let total = self.total_requests.load(Ordering::Relaxed);
         let successful = self.successful_requests.load(Ordering::Relaxed);
@@ -143,7 +348,7 @@ impl MetricsCollector {
         let mut endpoint_metrics: Vec<EndpointMetric> = endpoints
             .iter()
             .map(|(endpoint, count)| EndpointMetric {
-                endpoint: endpoint.clone(),
+                endpoint: endpoint.to_string(),
                 count: *count,
                 percentage: if total > 0 {
                     (*count as f64 / total as f64) * 100.0
@@ -153,23 +358,22 @@ impl MetricsCollector {
             })
             .collect();
         
-        endpoint_metrics.sort_by(|a, b| b.count.cmp(&a.count));
+        endpoint_metrics.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.endpoint.cmp(&b.endpoint)));
+        endpoint_metrics.truncate(SNAPSHOT_TOP_ENDPOINTS);
         
-        let one_hour_ago = Utc::now() - chrono::Duration::hours(1);
-        let times = self.response_times.read();
-        let recent_times: Vec<ResponseTime> = times
-            .iter()
-            .filter(|t| t.timestamp > one_hour_ago)
-            .cloned()
-            .collect();
-        
-        let avg_response_time = if !recent_times.is_empty() {
-            recent_times.iter().map(|t| t.duration_ms as f64).sum::<f64>() / recent_times.len() as f64
-        } else {
-            0.0
+        let now = Utc::now();
+        let (response_time_series, response_time_percentiles, avg_response_time) = {
+            let history = self.response_times.read();
+            let window = history.window_minutes();
+            (
+                history.series(now, SNAPSHOT_SERIES_MINUTES.min(window), 1),
+                history.percentiles(now, window),
+                history.average_ms(now, window),
+            )
         };
 
         let health_changes = self.health_status_changes.read().clone();
+        let security_events = self.labelled_counter(names::SECURITY_EVENTS);
 
         MetricsSnapshot {
             total_requests: total,
@@ -185,16 +389,312 @@ impl MetricsCollector {
             } else {
                 0.0
             },
-            last_hour_response_times: recent_times,
+            response_time_series,
+            response_time_percentiles,
             system_metrics: None,
             performance_metrics: None,
             health_status_changes: health_changes,
+            security_events,
+            websocket: None,
+            slow_requests: self.counter(names::SLOW_REQUESTS),
+            timed_out_requests: self.counter(names::TIMED_OUT_REQUESTS),
+            coalesced_requests: self.counter(names::COALESCED_REQUESTS),
+            coalesce_timeouts: self.counter(names::COALESCE_TIMEOUTS),
+            in_flight_requests: self.gauge(names::IN_FLIGHT_REQUESTS) as u64,
+            shed_requests: self.labelled_counter(names::SHED_REQUESTS),
+            concurrency: self.concurrency(),
+            rpc_methods: self.rpc_methods(),
+            outbound_destinations: self.outbound_destinations(),
+            outbound_refused: self.labelled_counter(names::OUTBOUND_REFUSED),
+            password_rehashes: self.counter(names::PASSWORD_REHASHES),
+        }
+    }
+}
+
+impl MetricsSink for MetricsCollector {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn record_request(&self, method: &str, endpoint: &str) {
+        self.total_requests.fetch_add(1, Ordering::Relaxed);
+        
+        increment(&mut self.requests_by_method.write(), method);
+        
+        let mut endpoints = self.requests_by_endpoint.write();
+        let endpoint = if endpoints.contains_key(endpoint) || endpoints.len() < MAX_ENDPOINT_LABELS {
+            endpoint
+        } else {
+            UNMATCHED_ENDPOINT
+        };
+        match endpoints.get_mut(endpoint) {
+            Some(count) => *count += 1,
+            None => {
+                endpoints.insert(Arc::from(endpoint), 1);
+            }
+        }
+    }
+
+    fn record_response(&self, endpoint: &str, duration: Duration, status: u16) {
+        if status < 400 {
+            self.successful_requests.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.failed_requests.fetch_add(1, Ordering::Relaxed);
+        }
+
+        // Share the label counted by `record_request`, which caps labels.
+        let endpoint = {
+            let endpoints = self.requests_by_endpoint.read();
+            match endpoints.get_key_value(endpoint) {
+                Some((label, _)) => label.clone(),
+                None if endpoints.len() < MAX_ENDPOINT_LABELS => Arc::from(endpoint),
+                None => Arc::from(UNMATCHED_ENDPOINT),
+            }
+        };
+
+        let timestamp = Utc::now();
+        let duration_ms = duration.as_millis();
+        self.traffic
+            .write()
+            .record(timestamp, u64::try_from(duration_ms).unwrap_or(u64::MAX), status);
+
+        let response_time = ResponseTime {
+            timestamp,
+            duration_ms,
+            endpoint,
+            status,
+        };
+
+        self.response_times.write().record(response_time);
+    }
+
+    /// Labels are capped per counter as endpoint labels are.
+    fn record_counter(&self, name: &str, label: Option<&str>, value: u64) {
+        let mut counters = self.counters.write();
+        let labels = counters.entry(name.to_string()).or_default();
+
+        let label = label.unwrap_or_default();
+        let label = if labels.contains_key(label) || labels.len() < MAX_ENDPOINT_LABELS {
+            label
+        } else {
+            UNMATCHED_ENDPOINT
+        };
+        match labels.get_mut(label) {
+            Some(count) => *count = count.saturating_add(value),
+            None => {
+                labels.insert(label.to_string(), value);
+            }
+        }
+    }
+
+    fn record_gauge(&self, name: &str, value: f64) {
+        let mut gauges = self.gauges.write();
+        match gauges.get_mut(name) {
+            Some(gauge) => *gauge = value,
+            None => {
+                gauges.insert(name.to_string(), value);
+            }
+        }
+    }
+
+    fn snapshot(&self) -> Option<MetricsSnapshot> {
+        Some(self.get_snapshot(0))
+    }
+
+    fn in_memory(&self) -> Option<&MetricsCollector> {
+        Some(self)
+    }
+
+    fn record_health_status_change(&self, component: String, old_status: String, new_status: String, message: String) {
+        let change = HealthStatusChange {
+            timestamp: Utc::now(),
+            component,
+            old_status,
+            new_status,
+            message,
+        };
+
+        let mut changes = self.health_status_changes.write();
+        changes.push(change);
+        
+        if changes.len() > 100 {
+            let excess = changes.len() - 100;
+            changes.drain(0..excess);
         }
     }
+
+    /// Endpoint labels are capped as in [`record_request`](Self::record_request).
+    fn record_database_usage(&self, endpoint: &str, stats: QueryStatsSnapshot, request_time: Duration) {
+        let mut usage = self.database_usage.write();
+        let endpoint = if usage.contains_key(endpoint) || usage.len() < MAX_ENDPOINT_LABELS {
+            endpoint
+        } else {
+            UNMATCHED_ENDPOINT
+        };
+        let route = usage.entry(endpoint.to_string()).or_default();
+        route.requests += 1;
+        route.queries += stats.queries;
+        route.db_time_us = route.db_time_us.saturating_add(sink::micros(stats.db_time));
+        route.request_time_us = route.request_time_us.saturating_add(sink::micros(request_time));
+    }
+
+    /// Method labels are capped as endpoint labels are.
+    fn record_rpc(&self, method: &str, success: bool, duration: Duration) {
+        record_call(&mut self.rpc_calls.write(), method, success, duration);
+    }
+
+    /// Destination labels are capped as endpoint labels are.
+    fn record_outbound(&self, destination: &str, success: bool, duration: Duration) {
+        record_call(&mut self.outbound_calls.write(), destination, success, duration);
+    }
 }
 
 impl Default for MetricsCollector {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+fn record_call(calls: &mut HashMap<String, RpcMethodUsage>, label: &str, success: bool, duration: Duration) {
+    let label = if calls.contains_key(label) || calls.len() < MAX_ENDPOINT_LABELS {
+        label
+    } else {
+        UNMATCHED_ENDPOINT
+    };
+
+    let usage = calls.entry(label.to_string()).or_default();
+    usage.calls += 1;
+    if !success {
+        usage.failures += 1;
+    }
+    usage.total_time_us = usage.total_time_us.saturating_add(sink::micros(duration));
+}
+
+/// Adds one to `key`'s count, allocating the key only the first time it
+/// is seen.
+fn increment(counts: &mut HashMap<String, u64>, key: &str) {
+    match counts.get_mut(key) {
+        Some(count) => *count += 1,
+        None => {
+            counts.insert(key.to_string(), 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_labels_are_capped() {
+        let metrics = MetricsCollector::new();
+        for i in 0..MAX_ENDPOINT_LABELS + 10 {
+            metrics.record_request("GET", &format!("/route/{}", i));
+        }
+
+        let endpoints = metrics.requests_by_endpoint.read();
+        assert_eq!(endpoints.len(), MAX_ENDPOINT_LABELS + 1);
+        assert_eq!(endpoints[UNMATCHED_ENDPOINT], 10);
+        drop(endpoints);
+
+        let snapshot = metrics.get_snapshot(0);
+        assert_eq!(snapshot.requests_by_endpoint.len(), SNAPSHOT_TOP_ENDPOINTS);
+        assert_eq!(snapshot.requests_by_endpoint[0].endpoint, UNMATCHED_ENDPOINT);
+    }
+
+    #[test]
+    fn test_rpc_calls_are_counted_per_method() {
+        let metrics = MetricsCollector::new();
+        metrics.record_rpc("/items.v1.ItemService/GetItem", true, Duration::from_millis(2));
+        metrics.record_rpc("/items.v1.ItemService/GetItem", false, Duration::from_millis(4));
+        metrics.record_rpc("/items.v1.ItemService/ListItems", true, Duration::from_millis(1));
+
+        let methods = metrics.get_snapshot(0).rpc_methods;
+        assert_eq!(methods.len(), 2);
+        assert_eq!(methods[0].method, "/items.v1.ItemService/GetItem");
+        assert_eq!((methods[0].calls, methods[0].failures), (2, 1));
+        assert_eq!(methods[0].avg_duration_ms, 3.0);
+        assert_eq!(methods[1].method, "/items.v1.ItemService/ListItems");
+    }
+
+    #[tokio::test]
+    async fn test_item_ids_share_one_endpoint_label() {
+        use axum::{body::Body, extract::ConnectInfo, http::Request};
+        use std::net::SocketAddr;
+        use tower::ServiceExt;
+
+        let state = crate::AppState::default();
+        let metrics = state.metrics.clone();
+        let mut config = crate::AppConfig::default();
+        config.rate_limit.enable = false;
+        let app = crate::create_app_with_config(state, config);
+
+        let get = |uri: String| {
+            let mut request = Request::get(uri)
+                .header("user-agent", "metrics-tests")
+                .body(Body::empty())
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+            app.clone().oneshot(request)
+        };
+
+        for id in 1..=1000 {
+            get(format!("/api/items/{}", id)).await.unwrap();
+        }
+        get("/no/such/route".to_string()).await.unwrap();
+        get("/another/missing/route".to_string()).await.unwrap();
+
+        let snapshot = metrics.snapshot().unwrap();
+        let endpoints: Vec<(&str, u64)> = snapshot
+            .requests_by_endpoint
+            .iter()
+            .map(|metric| (metric.endpoint.as_str(), metric.count))
+            .collect();
+        assert_eq!(endpoints, vec![("/api/items/:id", 1000), (UNMATCHED_ENDPOINT, 2)]);
+    }
+
+    #[tokio::test]
+    async fn test_database_usage_is_reported_per_route() {
+        use axum::{body::Body, extract::ConnectInfo, http::Request};
+        use std::net::SocketAddr;
+        use tower::ServiceExt;
+
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::database::run_migrations(pool.clone()).await.unwrap();
+        let state = crate::AppState::with_database(
+            crate::DatabaseManager::new(pool.clone()),
+            crate::ItemRepository::new(pool),
+        );
+        let metrics = state.metrics.clone();
+        let mut config = crate::AppConfig::default();
+        config.rate_limit.enable = false;
+        let app = crate::create_app_with_config(state, config);
+
+        for uri in ["/api/items", "/api/items", "/live"] {
+            let mut request = Request::get(uri)
+                .header("user-agent", "metrics-tests")
+                .body(Body::empty())
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+            app.clone().oneshot(request).await.unwrap();
+        }
+
+        let routes = metrics.in_memory().unwrap().database_usage_by_route();
+        let items = routes.iter().find(|route| route.route == "/api/items").unwrap();
+        assert_eq!(items.requests, 2);
+        assert!(items.avg_queries_per_request >= 1.0);
+        assert!(items.db_time_share_percent > 0.0 && items.db_time_share_percent <= 100.0);
+
+        let live = routes.iter().find(|route| route.route == "/live").unwrap();
+        assert_eq!(live.avg_queries_per_request, 0.0);
+        assert_eq!(routes[0].route, "/api/items");
+    }
+}
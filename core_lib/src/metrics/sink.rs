@@ -0,0 +1,361 @@
+//! Destinations for metrics, selected by `metrics.backends`
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use super::{MetricsCollector, MetricsSnapshot, StatsdSink};
+use crate::config::{MetricsBackend, MetricsConfig};
+use crate::database::QueryStatsSnapshot;
+use crate::error::Result;
+
+/// Names of the counters and gauges the server records.
+pub mod names {
+    /// Requests flagged by validation or anomaly detection, by event.
+    pub const SECURITY_EVENTS: &str = "security_events";
+    pub const SLOW_REQUESTS: &str = "slow_requests";
+    pub const TIMED_OUT_REQUESTS: &str = "timed_out_requests";
+    /// GETs answered with a copy of an identical concurrent request's
+    /// response.
+    pub const COALESCED_REQUESTS: &str = "coalesced_requests";
+    /// Coalesced GETs that stopped waiting and ran themselves.
+    pub const COALESCE_TIMEOUTS: &str = "coalesce_timeouts";
+    pub const PASSWORD_REHASHES: &str = "password_rehashes";
+    /// Requests rejected by load shedding, by traffic class.
+    pub const SHED_REQUESTS: &str = "shed_requests";
+    /// Requests turned away by the connection limits, by reason.
+    pub const CONCURRENCY_REJECTIONS: &str = "concurrency_rejections";
+    /// Health status changes, by component.
+    pub const HEALTH_STATUS_CHANGES: &str = "health_status_changes";
+    /// Requests served, and the queries, database time and total time they
+    /// took, by route.
+    pub const DATABASE_REQUESTS: &str = "database.requests";
+    pub const DATABASE_QUERIES: &str = "database.queries";
+    pub const DATABASE_TIME_US: &str = "database.time_us";
+    pub const DATABASE_REQUEST_TIME_US: &str = "database.request_time_us";
+    /// gRPC calls, failed calls and time spent, by method.
+    pub const RPC_CALLS: &str = "rpc.calls";
+    pub const RPC_FAILURES: &str = "rpc.failures";
+    pub const RPC_TIME_US: &str = "rpc.time_us";
+    /// Outbound calls, failed calls and time spent, by destination.
+    pub const OUTBOUND_CALLS: &str = "outbound.calls";
+    pub const OUTBOUND_FAILURES: &str = "outbound.failures";
+    pub const OUTBOUND_TIME_US: &str = "outbound.time_us";
+    /// Outbound calls refused before being made, by reason.
+    pub const OUTBOUND_REFUSED: &str = "outbound.refused";
+    /// Requests counted by load shedding as in flight.
+    pub const IN_FLIGHT_REQUESTS: &str = "in_flight_requests";
+    /// Requests holding a slot under the connection limits.
+    pub const LIMITED_IN_FLIGHT: &str = "concurrency.in_flight";
+    /// Requests waiting for a slot in their client's queue.
+    pub const QUEUED_REQUESTS: &str = "concurrency.queued";
+}
+
+/// Receives every measurement the server makes. Counters are reported as
+/// increments and gauges with their new value.
+///
+/// The provided methods are shorthands for the counters in [`names`]. A sink
+/// that keeps more detail, as [`MetricsCollector`] does for health changes,
+/// database usage and gRPC calls, overrides them.
+pub trait MetricsSink: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Counts a request against `endpoint`, which should be the matched
+    /// route template (e.g. `/api/items/:id`) rather than the raw path.
+    fn record_request(&self, method: &str, endpoint: &str);
+
+    /// Records the response to a request counted against `endpoint`.
+    fn record_response(&self, endpoint: &str, duration: Duration, status: u16);
+
+    /// Adds `value` to the counter `name`, under `label` when given.
+    fn record_counter(&self, name: &str, label: Option<&str>, value: u64);
+
+    fn record_gauge(&self, name: &str, value: f64);
+
+    /// Everything recorded so far, from sinks that keep it.
+    fn snapshot(&self) -> Option<MetricsSnapshot> {
+        None
+    }
+
+    /// The in-memory collector among this sink's destinations, for the
+    /// reports that need its history.
+    fn in_memory(&self) -> Option<&MetricsCollector> {
+        None
+    }
+
+    fn record_security_event(&self, event: &str) {
+        self.record_counter(names::SECURITY_EVENTS, Some(event), 1);
+    }
+
+    fn record_slow_request(&self) {
+        self.record_counter(names::SLOW_REQUESTS, None, 1);
+    }
+
+    fn record_timeout(&self) {
+        self.record_counter(names::TIMED_OUT_REQUESTS, None, 1);
+    }
+
+    fn record_coalesced_request(&self) {
+        self.record_counter(names::COALESCED_REQUESTS, None, 1);
+    }
+
+    fn record_coalesce_timeout(&self) {
+        self.record_counter(names::COALESCE_TIMEOUTS, None, 1);
+    }
+
+    fn record_password_rehash(&self) {
+        self.record_counter(names::PASSWORD_REHASHES, None, 1);
+    }
+
+    fn record_shed_request(&self, class: &str) {
+        self.record_counter(names::SHED_REQUESTS, Some(class), 1);
+    }
+
+    fn record_concurrency_rejection(&self, reason: &str) {
+        self.record_counter(names::CONCURRENCY_REJECTIONS, Some(reason), 1);
+    }
+
+    fn record_health_status_change(&self, component: String, _old_status: String, _new_status: String, _message: String) {
+        self.record_counter(names::HEALTH_STATUS_CHANGES, Some(&component), 1);
+    }
+
+    /// Adds the queries made while serving one request to `endpoint`'s
+    /// totals.
+    fn record_database_usage(&self, endpoint: &str, stats: QueryStatsSnapshot, request_time: Duration) {
+        self.record_counter(names::DATABASE_REQUESTS, Some(endpoint), 1);
+        self.record_counter(names::DATABASE_QUERIES, Some(endpoint), stats.queries);
+        self.record_counter(names::DATABASE_TIME_US, Some(endpoint), micros(stats.db_time));
+        self.record_counter(names::DATABASE_REQUEST_TIME_US, Some(endpoint), micros(request_time));
+    }
+
+    /// Counts one gRPC call to `method`, which took `duration` and failed
+    /// unless `success`.
+    fn record_rpc(&self, method: &str, success: bool, duration: Duration) {
+        self.record_counter(names::RPC_CALLS, Some(method), 1);
+        if !success {
+            self.record_counter(names::RPC_FAILURES, Some(method), 1);
+        }
+        self.record_counter(names::RPC_TIME_US, Some(method), micros(duration));
+    }
+
+    /// Counts one outbound call to `destination` (`host:port`), which took
+    /// `duration` and failed unless `success`.
+    fn record_outbound(&self, destination: &str, success: bool, duration: Duration) {
+        self.record_counter(names::OUTBOUND_CALLS, Some(destination), 1);
+        if !success {
+            self.record_counter(names::OUTBOUND_FAILURES, Some(destination), 1);
+        }
+        self.record_counter(names::OUTBOUND_TIME_US, Some(destination), micros(duration));
+    }
+}
+
+pub(crate) fn micros(duration: Duration) -> u64 {
+    u64::try_from(duration.as_micros()).unwrap_or(u64::MAX)
+}
+
+/// Sends every measurement to each of several sinks.
+pub struct FanoutSink {
+    sinks: Vec<Arc<dyn MetricsSink>>,
+}
+
+impl FanoutSink {
+    pub fn new(sinks: Vec<Arc<dyn MetricsSink>>) -> Self {
+        Self { sinks }
+    }
+}
+
+impl MetricsSink for FanoutSink {
+    fn name(&self) -> &'static str {
+        "fanout"
+    }
+
+    fn record_request(&self, method: &str, endpoint: &str) {
+        for sink in &self.sinks {
+            sink.record_request(method, endpoint);
+        }
+    }
+
+    fn record_response(&self, endpoint: &str, duration: Duration, status: u16) {
+        for sink in &self.sinks {
+            sink.record_response(endpoint, duration, status);
+        }
+    }
+
+    fn record_counter(&self, name: &str, label: Option<&str>, value: u64) {
+        for sink in &self.sinks {
+            sink.record_counter(name, label, value);
+        }
+    }
+
+    fn record_gauge(&self, name: &str, value: f64) {
+        for sink in &self.sinks {
+            sink.record_gauge(name, value);
+        }
+    }
+
+    fn snapshot(&self) -> Option<MetricsSnapshot> {
+        self.sinks.iter().find_map(|sink| sink.snapshot())
+    }
+
+    fn in_memory(&self) -> Option<&MetricsCollector> {
+        self.sinks.iter().find_map(|sink| sink.in_memory())
+    }
+
+    fn record_health_status_change(&self, component: String, old_status: String, new_status: String, message: String) {
+        for sink in &self.sinks {
+            sink.record_health_status_change(component.clone(), old_status.clone(), new_status.clone(), message.clone());
+        }
+    }
+
+    fn record_database_usage(&self, endpoint: &str, stats: QueryStatsSnapshot, request_time: Duration) {
+        for sink in &self.sinks {
+            sink.record_database_usage(endpoint, stats, request_time);
+        }
+    }
+
+    fn record_rpc(&self, method: &str, success: bool, duration: Duration) {
+        for sink in &self.sinks {
+            sink.record_rpc(method, success, duration);
+        }
+    }
+
+    fn record_outbound(&self, destination: &str, success: bool, duration: Duration) {
+        for sink in &self.sinks {
+            sink.record_outbound(destination, success, duration);
+        }
+    }
+}
+
+/// The sink for `metrics.backends`: the backend itself when there is one,
+/// otherwise a [`FanoutSink`] over all of them.
+pub fn sink_from_config(config: &MetricsConfig) -> Result<Arc<dyn MetricsSink>> {
+    let mut backends = Vec::new();
+    for backend in &config.backends {
+        if !backends.contains(backend) {
+            backends.push(*backend);
+        }
+    }
+
+    let mut sinks = backends
+        .into_iter()
+        .map(|backend| -> Result<Arc<dyn MetricsSink>> {
+            Ok(match backend {
+                MetricsBackend::Memory => Arc::new(MetricsCollector::with_config(config)),
+                MetricsBackend::Statsd => Arc::new(StatsdSink::from_config(config)?),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(match sinks.len() {
+        1 => sinks.remove(0),
+        _ => Arc::new(FanoutSink::new(sinks)),
+    })
+}
+
+/// A count of things in progress, reported to a sink as a gauge each time
+/// it changes. Changes are reported in the order they happen, so the sink
+/// always ends up with the current value.
+#[derive(Clone)]
+pub struct InFlightGauge {
+    name: &'static str,
+    value: Arc<Mutex<u64>>,
+    sink: Arc<dyn MetricsSink>,
+}
+
+impl InFlightGauge {
+    pub fn new(name: &'static str, sink: Arc<dyn MetricsSink>) -> Self {
+        Self {
+            name,
+            value: Arc::new(Mutex::new(0)),
+            sink,
+        }
+    }
+
+    pub fn get(&self) -> u64 {
+        *self.value.lock()
+    }
+
+    /// Adds one until the returned guard is dropped.
+    pub fn track(&self) -> InFlightGuard {
+        self.adjust(|value| *value += 1);
+        InFlightGuard { gauge: self.clone() }
+    }
+
+    fn adjust(&self, change: impl FnOnce(&mut u64)) {
+        let mut value = self.value.lock();
+        change(&mut value);
+        self.sink.record_gauge(self.name, *value as f64);
+    }
+}
+
+/// Marks one request as in flight until dropped.
+pub struct InFlightGuard {
+    gauge: InFlightGauge,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.gauge.adjust(|value| *value = value.saturating_sub(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn test_fanout_reaches_every_sink() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let config = MetricsConfig {
+            backends: vec![MetricsBackend::Memory, MetricsBackend::Statsd],
+            statsd_address: receiver.local_addr().unwrap().to_string(),
+            statsd_prefix: "test".to_string(),
+            ..MetricsConfig::default()
+        };
+        let sink = sink_from_config(&config).unwrap();
+        assert_eq!(sink.name(), "fanout");
+
+        sink.record_security_event("blocked_request");
+        let mut buf = [0u8; 512];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"test.security_events.blocked_request:1|c");
+
+        let snapshot = sink.snapshot().unwrap();
+        assert_eq!(snapshot.security_events.get("blocked_request"), Some(&1));
+        assert!(sink.in_memory().is_some());
+    }
+
+    #[test]
+    fn test_single_backend_is_used_directly() {
+        let sink = sink_from_config(&MetricsConfig::default()).unwrap();
+        assert_eq!(sink.name(), "memory");
+        assert!(sink.in_memory().is_some());
+
+        let config = MetricsConfig {
+            backends: vec![MetricsBackend::Statsd],
+            ..MetricsConfig::default()
+        };
+        let sink = sink_from_config(&config).unwrap();
+        assert_eq!(sink.name(), "statsd");
+        assert!(sink.snapshot().is_none() && sink.in_memory().is_none());
+    }
+
+    #[test]
+    fn test_in_flight_gauge_reports_every_change() {
+        let collector = Arc::new(MetricsCollector::new());
+        let gauge = InFlightGauge::new(names::IN_FLIGHT_REQUESTS, collector.clone());
+
+        let first = gauge.track();
+        let second = gauge.track();
+        assert_eq!(gauge.get(), 2);
+        assert_eq!(collector.gauge(names::IN_FLIGHT_REQUESTS), 2.0);
+
+        drop(first);
+        drop(second);
+        assert_eq!(gauge.get(), 0);
+        assert_eq!(collector.get_snapshot(0).in_flight_requests, 0);
+    }
+}
@@ -0,0 +1,155 @@
+//! Metrics sent to a statsd server over UDP
+
+use std::fmt::Display;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use super::MetricsSink;
+use crate::config::MetricsConfig;
+use crate::error::{AppError, Result};
+
+/// Sends each measurement as one statsd line: counters as `name.label:n|c`,
+/// gauges as `name:v|g` and response times as `response_time.endpoint:ms|ms`.
+///
+/// Sending never waits. Like statsd itself this is lossy: a measurement that
+/// cannot be sent at once, or that no server receives, is dropped.
+pub struct StatsdSink {
+    socket: UdpSocket,
+    prefix: String,
+}
+
+impl StatsdSink {
+    /// Sends to `address`, resolved once here, naming every metric
+    /// `prefix.name`.
+    pub fn new(address: &str, prefix: &str) -> Result<Self> {
+        let target = address
+            .to_socket_addrs()
+            .map_err(|e| AppError::Configuration(format!("Invalid statsd address '{}': {}", address, e)))?
+            .next()
+            .ok_or_else(|| AppError::Configuration(format!("Statsd address '{}' did not resolve", address)))?;
+
+        let socket = UdpSocket::bind(if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+        socket.connect(target)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            socket,
+            prefix: sanitize(prefix, true),
+        })
+    }
+
+    pub fn from_config(config: &MetricsConfig) -> Result<Self> {
+        Self::new(&config.statsd_address, &config.statsd_prefix)
+    }
+
+    fn send(&self, name: &str, label: Option<&str>, value: impl Display, kind: &str) {
+        let mut line = self.prefix.clone();
+        for part in [Some(sanitize(name, true)), label.map(|label| sanitize(label, false))]
+            .into_iter()
+            .flatten()
+            .filter(|part| !part.is_empty())
+        {
+            if !line.is_empty() {
+                line.push('.');
+            }
+            line.push_str(&part);
+        }
+        line.push_str(&format!(":{}|{}", value, kind));
+
+        if let Err(e) = self.socket.send(line.as_bytes()) {
+            tracing::trace!("Dropped statsd metric {}: {}", line, e);
+        }
+    }
+}
+
+impl MetricsSink for StatsdSink {
+    fn name(&self) -> &'static str {
+        "statsd"
+    }
+
+    fn record_request(&self, method: &str, _endpoint: &str) {
+        self.send("requests", Some(method), 1, "c");
+    }
+
+    fn record_response(&self, endpoint: &str, duration: Duration, status: u16) {
+        self.send("response_time", Some(endpoint), duration.as_millis(), "ms");
+        self.send("responses", Some(&format!("{}xx", status / 100)), 1, "c");
+    }
+
+    fn record_counter(&self, name: &str, label: Option<&str>, value: u64) {
+        self.send(name, label, value, "c");
+    }
+
+    fn record_gauge(&self, name: &str, value: f64) {
+        self.send(name, None, value, "g");
+    }
+}
+
+/// `value` as a metric name segment: runs of anything but letters, digits,
+/// `-` and `_` (and `.`, when `keep_dots`) become one `_`, and leading and
+/// trailing separators are dropped, so `/api/items/:id` becomes
+/// `api_items_id`.
+fn sanitize(value: &str, keep_dots: bool) -> String {
+    let mut sanitized = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c.is_ascii_alphanumeric() || c == '-' || c == '_' || (keep_dots && c == '.') {
+            sanitized.push(c);
+        } else if !sanitized.ends_with('_') {
+            sanitized.push('_');
+        }
+    }
+    sanitized.trim_matches(|c| c == '_' || c == '.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receive(socket: &UdpSocket, count: usize) -> Vec<String> {
+        let mut buf = [0u8; 512];
+        (0..count)
+            .map(|_| {
+                let len = socket.recv(&mut buf).unwrap();
+                String::from_utf8_lossy(&buf[..len]).into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_measurements_are_sent_as_statsd_lines() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let sink = StatsdSink::new(&receiver.local_addr().unwrap().to_string(), "app.prod").unwrap();
+
+        sink.record_request("GET", "/api/items/:id");
+        sink.record_response("/api/items/:id", Duration::from_millis(42), 404);
+        sink.record_gauge("concurrency.queued", 3.0);
+        sink.record_rpc("/items.v1.ItemService/GetItem", false, Duration::from_micros(1500));
+
+        assert_eq!(
+            receive(&receiver, 7),
+            vec![
+                "app.prod.requests.GET:1|c",
+                "app.prod.response_time.api_items_id:42|ms",
+                "app.prod.responses.4xx:1|c",
+                "app.prod.concurrency.queued:3|g",
+                "app.prod.rpc.calls.items_v1_ItemService_GetItem:1|c",
+                "app.prod.rpc.failures.items_v1_ItemService_GetItem:1|c",
+                "app.prod.rpc.time_us.items_v1_ItemService_GetItem:1500|c",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sending_without_a_server_is_harmless() {
+        let unused = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = unused.local_addr().unwrap().to_string();
+        drop(unused);
+
+        let sink = StatsdSink::new(&address, "").unwrap();
+        for _ in 0..100 {
+            sink.record_security_event("blocked_request");
+        }
+        assert!(StatsdSink::new("not an address", "app").is_err());
+    }
+}
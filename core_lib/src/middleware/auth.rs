@@ -1,9 +1,12 @@
 use crate::auth::models::{UserRole};
+use crate::auth::AuthService;
+use crate::config::UnverifiedUserPolicy;
 use crate::error::AppError;
+use crate::middleware::csrf;
 use crate::AppState;
 use axum::{
     extract::{Request, State},
-    http::{header::AUTHORIZATION, HeaderMap},
+    http::{header::AUTHORIZATION, HeaderMap, Method},
     middleware::Next,
     response::Response,
 };
@@ -13,6 +16,15 @@ pub struct AuthUser {
     pub user_id: i64,
     pub username: String,
     pub role: UserRole,
+    pub session_id: Option<String>,
+    /// Tenant the user belongs to; the default namespace unless their token
+    /// carries a tenant claim.
+    pub namespace: String,
+    /// False while the token carries the `unverified` claim.
+    pub email_verified: bool,
+    /// How long ago the user last entered their password, by the token
+    /// service's clock. `None` for tokens without an `auth_time` claim.
+    pub auth_age: Option<chrono::Duration>,
 }
 
 impl AuthUser {
@@ -21,17 +33,35 @@ impl AuthUser {
             user_id,
             username,
             role,
+            session_id: None,
+            namespace: crate::tenancy::DEFAULT_NAMESPACE.to_string(),
+            email_verified: true,
+            auth_age: None,
         }
     }
 
+    pub fn with_session(mut self, session_id: Option<String>) -> Self {
+        self.session_id = session_id;
+        self
+    }
+
+    pub fn with_namespace(mut self, namespace: Option<String>) -> Self {
+        self.namespace = namespace.unwrap_or_else(|| crate::tenancy::DEFAULT_NAMESPACE.to_string());
+        self
+    }
+
+    pub fn with_email_verified(mut self, email_verified: bool) -> Self {
+        self.email_verified = email_verified;
+        self
+    }
+
+    pub fn with_auth_age(mut self, auth_age: Option<chrono::Duration>) -> Self {
+        self.auth_age = auth_age;
+        self
+    }
+
     pub fn has_role(&self, required_role: &UserRole) -> bool {
-        match (&self.role, required_role) {
-            (UserRole::Admin, _) => true,
-            (UserRole::User, UserRole::User) => true,
-            (UserRole::User, UserRole::ReadOnly) => true,
-            (UserRole::ReadOnly, UserRole::ReadOnly) => true,
-            _ => false,
-        }
+        self.role.grants(required_role)
     }
 
     pub fn is_admin(&self) -> bool {
@@ -49,9 +79,19 @@ pub async fn jwt_auth_middleware(
         .as_ref()
         .ok_or_else(|| AppError::InternalServerError)?;
 
-    let token = extract_token_from_header(request.headers())?;
+    let token = request_token(request.method(), request.headers())?
+        .map_or_else(|| extract_token_from_header(request.headers()), Ok)?;
+    let auth_user = authenticate_token(auth_service, &token).await?;
+    request.extensions_mut().insert(auth_user);
+
+    Ok(next.run(request).await)
+}
 
-    let claims = auth_service.jwt_service().validate_access_token(&token)?;
+/// The user an access token identifies, provided its session is still
+/// active.
+pub async fn authenticate_token(auth_service: &AuthService, token: &str) -> Result<AuthUser, AppError> {
+    let claims = auth_service.jwt_service().validate_access_token(token)?;
+    auth_service.check_session(&claims).await?;
 
     let role: UserRole = claims.role.parse()
         .map_err(|_| AppError::Authentication("Invalid role in token".to_string()))?;
@@ -59,10 +99,14 @@ pub async fn jwt_auth_middleware(
     let user_id: i64 = claims.sub.parse()
         .map_err(|_| AppError::Authentication("Invalid user ID in token".to_string()))?;
 
-    let auth_user = AuthUser::new(user_id, claims.username, role);
-    request.extensions_mut().insert(auth_user);
+    let now = auth_service.jwt_service().clock().now().timestamp();
+    let auth_age = claims.auth_time.map(|time| chrono::Duration::seconds(now - time as i64));
 
-    Ok(next.run(request).await)
+    Ok(AuthUser::new(user_id, claims.username, role)
+        .with_session(claims.sid)
+        .with_namespace(claims.tenant)
+        .with_email_verified(!claims.unverified)
+        .with_auth_age(auth_age))
 }
 
 pub async fn optional_jwt_auth_middleware(
@@ -75,15 +119,9 @@ pub async fn optional_jwt_auth_middleware(
         None => return Ok(next.run(request).await),
     };
 
-    if let Ok(token) = extract_token_from_header(request.headers()) {
-        if let Ok(claims) = auth_service.jwt_service().validate_access_token(&token) {
-            if let (Ok(role), Ok(user_id)) = (
-                claims.role.parse::<UserRole>(),
-                claims.sub.parse::<i64>()
-            ) {
-                let auth_user = AuthUser::new(user_id, claims.username, role);
-                request.extensions_mut().insert(auth_user);
-            }
+    if let Some(token) = request_token(request.method(), request.headers())? {
+        if let Ok(auth_user) = authenticate_token(auth_service, &token).await {
+            request.extensions_mut().insert(auth_user);
         }
     }
 
@@ -111,6 +149,39 @@ pub fn require_role(required_role: UserRole) -> impl Fn(Request, Next) -> std::p
     }
 }
 
+/// Lets a request through only if the user entered their password within
+/// `max_age`, for actions a stolen token alone shouldn't be enough for. A
+/// token that is too old can be exchanged through `POST /auth/reauthenticate`.
+pub fn require_recent_auth(max_age: std::time::Duration) -> impl Fn(Request, Next) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Response, AppError>> + Send>> + Clone {
+    let max_age = chrono::Duration::from_std(max_age).unwrap_or(chrono::Duration::MAX);
+    move |request: Request, next: Next| {
+        Box::pin(async move {
+            let auth_user = request
+                .extensions()
+                .get::<AuthUser>()
+                .ok_or_else(|| AppError::Authentication("Authentication required".to_string()))?;
+
+            if auth_user.auth_age.is_none_or(|age| age > max_age) {
+                return Err(AppError::Authorization(format!(
+                    "This action needs a password entered in the last {} seconds; re-authenticate at /auth/reauthenticate",
+                    max_age.num_seconds()
+                )));
+            }
+
+            Ok(next.run(request).await)
+        })
+    }
+}
+
+/// Lets a request through only from a signed-in user, of any role.
+pub async fn require_authenticated(request: Request, next: Next) -> Result<Response, AppError> {
+    if request.extensions().get::<AuthUser>().is_none() {
+        return Err(AppError::Authentication("Authentication required".to_string()));
+    }
+
+    Ok(next.run(request).await)
+}
+
 pub async fn require_admin(request: Request, next: Next) -> Result<Response, AppError> {
     let auth_user = request
         .extensions()
@@ -155,6 +226,73 @@ pub async fn require_self_or_admin(
     Ok(next.run(request).await)
 }
 
+/// Refuses item writes from signed-in users whose email address isn't
+/// verified, under [`UnverifiedUserPolicy::BlockItemWrites`]. Relies on the
+/// token's `unverified` claim, so it runs inside the optional JWT layer and
+/// costs nothing for anonymous requests or reads.
+pub async fn email_verification_middleware(
+    State(policy): State<UnverifiedUserPolicy>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let unverified = request
+        .extensions()
+        .get::<AuthUser>()
+        .is_some_and(|user| !user.email_verified);
+
+    if unverified
+        && policy == UnverifiedUserPolicy::BlockItemWrites
+        && is_item_write(request.method(), request.uri().path())
+    {
+        return Err(AppError::Authorization(
+            "Verify your email address before creating or changing items. \
+             If you already have, refresh your access token."
+                .to_string(),
+        ));
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Creating, replacing, patching or deleting an item under any API version.
+/// Other POSTs beneath the collection, such as duplicate checks, only read.
+fn is_item_write(method: &Method, path: &str) -> bool {
+    if !matches!(*method, Method::POST | Method::PUT | Method::PATCH | Method::DELETE) {
+        return false;
+    }
+    let rest = path
+        .strip_prefix("/api/v1")
+        .or_else(|| path.strip_prefix("/api/v2"))
+        .or_else(|| path.strip_prefix("/api"))
+        .and_then(|rest| rest.strip_prefix("/items"));
+
+    match rest {
+        Some("") | Some("/") => true,
+        Some(rest) => rest
+            .trim_start_matches('/')
+            .trim_end_matches('/')
+            .parse::<u64>()
+            .is_ok(),
+        None => false,
+    }
+}
+
+/// The access token a request carries: its bearer token or, without an
+/// `Authorization` header, the admin page's session cookie. A request that
+/// changes something on the strength of the cookie must pass the CSRF check.
+pub fn request_token(method: &Method, headers: &HeaderMap) -> Result<Option<String>, AppError> {
+    if headers.contains_key(AUTHORIZATION) {
+        return Ok(extract_token_from_header(headers).ok());
+    }
+    match csrf::session_token(headers) {
+        Some(token) => {
+            csrf::verify(method, headers)?;
+            Ok(Some(token.to_string()))
+        }
+        None => Ok(None),
+    }
+}
+
 pub fn extract_token_from_header(headers: &HeaderMap) -> Result<String, AppError> {
     let auth_header = headers
         .get(AUTHORIZATION)
@@ -224,6 +362,10 @@ mod tests {
             created_at: Utc::now(),
             last_login: None,
             is_active: true,
+            namespace: "default".to_string(),
+            profile: Default::default(),
+            pending_email: None,
+            email_verified: true,
         };
 
         jwt_service.generate_access_token(&user).unwrap()
@@ -371,4 +513,110 @@ mod tests {
         headers.insert(AUTHORIZATION, "Bearer ".parse().unwrap());
         assert!(extract_token_from_header(&headers).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_is_item_write() {
+        assert!(is_item_write(&Method::POST, "/api/items"));
+        assert!(is_item_write(&Method::PUT, "/api/v1/items/7"));
+        assert!(is_item_write(&Method::PATCH, "/api/v2/items/7/"));
+        assert!(is_item_write(&Method::DELETE, "/api/items/7"));
+
+        assert!(!is_item_write(&Method::GET, "/api/items/7"));
+        assert!(!is_item_write(&Method::POST, "/api/items/check-duplicate"));
+        assert!(!is_item_write(&Method::POST, "/api/itemsx"));
+        assert!(!is_item_write(&Method::POST, "/auth/register"));
+    }
+
+    #[tokio::test]
+    async fn test_email_verification_middleware() {
+        let state = setup_test_state().await;
+        let jwt_service = state.auth_service.as_ref().unwrap().jwt_service();
+        let mut user = User {
+            id: 1,
+            username: "newcomer".to_string(),
+            email: "newcomer@example.com".to_string(),
+            password_hash: "hash".to_string(),
+            role: UserRole::User.to_string(),
+            created_at: Utc::now(),
+            last_login: None,
+            is_active: true,
+            namespace: "default".to_string(),
+            profile: Default::default(),
+            pending_email: None,
+            email_verified: false,
+        };
+        let unverified = jwt_service.generate_access_token(&user).unwrap();
+        user.email_verified = true;
+        let verified = jwt_service.generate_access_token(&user).unwrap();
+
+        let app = |policy: UnverifiedUserPolicy| {
+            Router::new()
+                .route("/api/items", get(test_handler).post(test_handler))
+                .route("/api/items/check-duplicate", axum::routing::post(test_handler))
+                .layer(middleware::from_fn_with_state(policy, email_verification_middleware))
+                .layer(middleware::from_fn_with_state(state.clone(), optional_jwt_auth_middleware))
+                .with_state(state.clone())
+        };
+        let send = |policy, method: Method, uri: &str, token: Option<&str>| {
+            let mut request = Request::builder().method(method).uri(uri);
+            if let Some(token) = token {
+                request = request.header("Authorization", format!("Bearer {}", token));
+            }
+            app(policy).oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        let block = UnverifiedUserPolicy::BlockItemWrites;
+        let response = send(block, Method::POST, "/api/items", Some(&unverified)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        for (method, uri, token) in [
+            (Method::GET, "/api/items", Some(unverified.as_str())),
+            (Method::POST, "/api/items/check-duplicate", Some(unverified.as_str())),
+            (Method::POST, "/api/items", Some(verified.as_str())),
+            (Method::POST, "/api/items", None),
+        ] {
+            let response = send(block, method.clone(), uri, token).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{} {}", method, uri);
+        }
+
+        let response = send(UnverifiedUserPolicy::Allow, Method::POST, "/api/items", Some(&unverified))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_require_recent_auth_middleware() {
+        use crate::clock::MockClock;
+        use std::time::Duration;
+
+        let clock = MockClock::default();
+        let pool = SqlitePool::connect(":memory:").await.unwrap();
+        let user_repo = UserRepository::new(pool);
+        user_repo.ensure_tables_exist().await.unwrap();
+        let jwt_service = JwtService::with_secret("7d2c9e4b1a0f8e6d5c4b3a2918f7e6d5c4")
+            .unwrap()
+            .with_clock(clock.shared());
+        let state = AppState::default().with_auth(AuthService::new(user_repo, jwt_service));
+        let token = create_test_token(&state, UserRole::Admin).await;
+
+        let app = Router::new()
+            .route("/sensitive", get(test_handler))
+            .route_layer(middleware::from_fn(require_recent_auth(Duration::from_secs(300))))
+            .layer(middleware::from_fn_with_state(state.clone(), optional_jwt_auth_middleware))
+            .with_state(state.clone());
+        let send = |token: Option<&str>| {
+            let mut request = Request::builder().uri("/sensitive");
+            if let Some(token) = token {
+                request = request.header("Authorization", format!("Bearer {}", token));
+            }
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        assert_eq!(send(Some(&token)).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(send(None).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+        clock.advance(Duration::from_secs(301));
+        assert_eq!(send(Some(&token)).await.unwrap().status(), StatusCode::FORBIDDEN);
+    }
+}
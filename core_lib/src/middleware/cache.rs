@@ -1,14 +1,21 @@
 use axum::{
     body::Body,
     extract::State,
-    http::{HeaderValue, Method, Request},
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
     middleware::Next,
     response::Response,
 };
+use std::sync::Arc;
 use std::time::Duration;
 use tracing::{debug, warn};
 
-use crate::{cache::CacheManager, AppState};
+use crate::{
+    cache::CacheManager,
+    middleware::provenance::{CacheStatus, CACHE},
+    middleware::coalesce::{coalesce_key, is_shareable, Flight, Joined, SharedResponse},
+    middleware::envelope::ResponseMode,
+    AppState,
+};
 
 #[derive(Debug, Clone)]
 pub struct CacheMiddlewareConfig {
@@ -50,89 +57,158 @@ pub async fn cache_middleware(
     };
 
     let config = CacheMiddlewareConfig::default();
-    
-    if !should_cache_request(&request, &config) {
+
+    if is_mutation(request.method()) {
+        let tags = invalidation_tags(request.uri().path());
+        let response = next.run(request).await;
+        if response.status().is_success() {
+            for tag in &tags {
+                cache_manager.invalidate_tag(tag);
+            }
+        }
+        return Ok(response);
+    }
+
+    let cacheable = should_cache_request(&request, &config);
+    let coalescer = cache_manager
+        .request_coalescer()
+        .filter(|_| should_coalesce_request(&request));
+    if !cacheable && coalescer.is_none() {
         return Ok(next.run(request).await);
     }
 
+    let tags = cache_tags(request.uri().path());
+
     let cache_key = generate_cache_key(&request, &config);
     
-    if let Some(cached_response) = get_cached_response(cache_manager, &cache_key).await {
-        debug!("Cache hit for key: {}", cache_key);
-        return Ok(cached_response);
+    if cacheable {
+        if let Some(cached_response) = get_cached_response(cache_manager, &cache_key).await {
+            debug!("Cache hit for key: {}", cache_key);
+            return Ok(cached_response);
+        }
+        debug!("Cache miss for key: {}", cache_key);
+    }
+
+    // Of identical requests arriving together, only the leader runs.
+    let mut leader = None;
+    if let Some(coalescer) = coalescer {
+        match coalescer.join(coalesce_key(&request)) {
+            Flight::Leader(flight) => leader = Some(flight),
+            Flight::Follower(receiver) => match coalescer.wait(receiver).await {
+                Joined::Shared(shared) => {
+                    state.metrics.record_coalesced_request();
+                    let mut response = shared.response();
+                    mark_miss(&mut response, cacheable);
+                    return Ok(response);
+                }
+                Joined::NotShared => {}
+                Joined::TimedOut => {
+                    warn!("Gave up waiting for a coalesced {} {}", request.method(), request.uri());
+                    state.metrics.record_coalesce_timeout();
+                }
+            },
+        }
     }
 
-    debug!("Cache miss for key: {}", cache_key);
     let response = next.run(request).await;
-    
-    if response.status().is_success() {
+    let store = cacheable && should_cache_response(&response);
+    let share = leader.is_some() && is_shareable(&response, config.max_response_size);
+
+    if store || share {
         let (parts, body) = response.into_parts();
         
         match axum::body::to_bytes(body, config.max_response_size).await {
             Ok(body_bytes) => {
-                let cached_response = CachedResponse {
-                    status_code: parts.status.as_u16(),
-                    headers: parts.headers
-                        .iter()
-                        .filter_map(|(name, value)| {
-                            let name_str = name.as_str().to_lowercase();
-                            if should_cache_header(&name_str) {
-                                value.to_str().ok().map(|v| (name.to_string(), v.to_string()))
-                            } else {
-                                None
-                            }
-                        })
-                        .collect(),
-                    body: body_bytes.to_vec(),
-                };
-
-                if let Err(e) = cache_manager.set_with_ttl(&cache_key, &cached_response, Some(config.default_ttl)) {
-                    warn!("Failed to cache response for key {}: {}", cache_key, e);
-                } else {
-                    debug!("Cached response for key: {} ({} bytes)", cache_key, cached_response.body.len());
-                }
+                let kept_headers: Vec<(String, String)> = parts.headers
+                    .iter()
+                    .filter_map(|(name, value)| {
+                        let name_str = name.as_str().to_lowercase();
+                        if should_cache_header(&name_str) {
+                            value.to_str().ok().map(|v| (name.to_string(), v.to_string()))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect();
 
-                let mut response_builder = Response::builder().status(parts.status);
-                
-                for (name, value) in parts.headers.iter() {
-                    response_builder = response_builder.header(name, value);
+                if let Some(leader) = leader {
+                    let mut headers = HeaderMap::new();
+                    for (name, value) in parts.headers.iter().filter(|(name, _)| should_cache_header(name.as_str())) {
+                        headers.append(name.clone(), value.clone());
+                    }
+                    leader.finish(share.then(|| {
+                        Arc::new(SharedResponse::new(parts.status, headers, body_bytes.clone()))
+                    }));
                 }
-                
-                response_builder = response_builder.header("X-Cache", "MISS");
-                
-                Ok(match response_builder.body(Body::from(body_bytes.clone())) {
-                    Ok(response) => response,
-                    Err(_) => {
-                        Response::builder()
-                            .status(parts.status)
-                            .header("X-Cache", "MISS")
-                            .body(Body::from(body_bytes))
-                            .unwrap_or_else(|_| Response::new(Body::empty()))
+
+                if store {
+                    let cached_response = CachedResponse {
+                        status_code: parts.status.as_u16(),
+                        headers: kept_headers,
+                        body: body_bytes.to_vec(),
+                    };
+
+                    if let Err(e) = cache_manager.set_with_tags(&cache_key, &cached_response, Some(config.default_ttl), &tags) {
+                        warn!("Failed to cache response for key {}: {}", cache_key, e);
+                    } else {
+                        debug!("Cached response for key: {} ({} bytes)", cache_key, cached_response.body.len());
                     }
-                })
+                }
+
+                let mut response = Response::from_parts(parts, Body::from(body_bytes));
+                mark_miss(&mut response, cacheable);
+                Ok(response)
             }
             Err(e) => {
                 warn!("Failed to extract response body for caching: {}", e);
                 let mut response = Response::from_parts(parts, Body::empty());
-                response.headers_mut().insert("X-Cache", HeaderValue::from_static("MISS"));
+                mark_miss(&mut response, true);
                 Ok(response)
             }
         }
     } else {
-        let (mut parts, body) = response.into_parts();
-        parts.headers.insert("X-Cache", HeaderValue::from_static("MISS"));
-        Ok(Response::from_parts(parts, body))
+        // Followers run the request themselves.
+        drop(leader);
+        let mut response = response;
+        mark_miss(&mut response, cacheable);
+        Ok(response)
+    }
+}
+
+/// Reports a miss on a response to a request the cache looked up, unless
+/// the handler reported on a cache of its own.
+fn mark_miss(response: &mut Response, cacheable: bool) {
+    if cacheable {
+        response.headers_mut().entry(CACHE).or_insert(CacheStatus::Miss.header_value());
     }
 }
 
+/// Paths whose responses are never cached or shared.
+fn is_uncacheable_path(path: &str) -> bool {
+    // Probes must see the current state, and event streams never end.
+    path.starts_with("/auth/")
+        || path == "/health"
+        || path.starts_with("/health/")
+        || path == "/ready"
+        || path == "/live"
+        || path == "/api/events"
+}
+
+/// Conditional requests are answered by the handler, which can send a 304
+/// without rebuilding the body.
+fn is_conditional(request: &Request<Body>) -> bool {
+    request.headers().contains_key(header::IF_NONE_MATCH)
+        || request.headers().contains_key(header::IF_MODIFIED_SINCE)
+}
+
 fn should_cache_request(request: &Request<Body>, config: &CacheMiddlewareConfig) -> bool {
-    let path = request.uri().path();
-    
-    if path.starts_with("/auth/") {
+    if is_uncacheable_path(request.uri().path()) || is_conditional(request) {
         return false;
     }
     
-    if request.headers().contains_key("authorization") {
+    if request.headers().contains_key("authorization")
+        || crate::middleware::csrf::session_token(request.headers()).is_some()
+    {
         return false;
     }
     
@@ -143,16 +219,107 @@ fn should_cache_request(request: &Request<Body>, config: &CacheMiddlewareConfig)
     }
 }
 
+/// GETs that may share one handler run with identical concurrent ones:
+/// those that could be cached and authenticated ones, which are only
+/// coalesced with requests carrying the same credentials. Upgrades are
+/// left alone, each needing its own connection.
+fn should_coalesce_request(request: &Request<Body>) -> bool {
+    request.method() == Method::GET
+        && !is_uncacheable_path(request.uri().path())
+        && !is_conditional(request)
+        && !request.headers().contains_key(header::UPGRADE)
+}
+
+/// Resources whose cached representations are tracked for invalidation.
+const TRACKED_RESOURCES: [&str; 3] = ["items", "files", "jobs"];
+
+fn is_mutation(method: &Method) -> bool {
+    matches!(*method, Method::POST | Method::PUT | Method::PATCH | Method::DELETE)
+}
+
+/// The tracked resource collection a path belongs to and, for routes about a
+/// single resource, its identifier. Versioned paths map to the same resource,
+/// `/api/stats` and `/api/tags` summarise items, and public assets are files,
+/// dropped when any file is published, unpublished or deleted.
+fn resource_of(path: &str) -> Option<(&str, Option<&str>)> {
+    if path.starts_with(crate::files::assets::PUBLIC_ASSETS_PATH) {
+        return Some(("files", None));
+    }
+    let rest = path.strip_prefix("/api/")?;
+    let rest = rest
+        .strip_prefix("v1/")
+        .or_else(|| rest.strip_prefix("v2/"))
+        .unwrap_or(rest);
+
+    let mut segments = rest.split('/').filter(|segment| !segment.is_empty());
+    let collection = segments.next()?;
+    if collection == "stats" || collection == "tags" {
+        return Some(("items", None));
+    }
+    if !TRACKED_RESOURCES.contains(&collection) {
+        return None;
+    }
+
+    let id = segments
+        .next()
+        .filter(|segment| segment.parse::<u64>().is_ok() || segment.parse::<uuid::Uuid>().is_ok());
+    Some((collection, id))
+}
+
+/// Tags for a cached GET: detail routes depend only on their own resource,
+/// everything else (lists, search, export, stats) on the whole collection.
+fn cache_tags(path: &str) -> Vec<String> {
+    match resource_of(path) {
+        Some((collection, Some(id))) => vec![format!("{}:{}", collection, id)],
+        Some((collection, None)) => vec![collection.to_string()],
+        None => Vec::new(),
+    }
+}
+
+/// Tags to drop after a successful mutation: the collection's derived views
+/// and, when a single resource changed, that resource's detail entries.
+fn invalidation_tags(path: &str) -> Vec<String> {
+    match resource_of(path) {
+        Some((collection, id)) => {
+            let mut tags = vec![collection.to_string()];
+            tags.extend(id.map(|id| format!("{}:{}", collection, id)));
+            tags
+        }
+        None => Vec::new(),
+    }
+}
+
+/// Only full 200 responses are stored; 201/204 and other successes describe
+/// a one-off outcome rather than the resource at this key. Responses marked
+/// `no-store` are never kept.
+fn should_cache_response(response: &Response) -> bool {
+    response.status() == StatusCode::OK
+        && !response
+            .headers()
+            .get(header::CACHE_CONTROL)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.split(',').any(|directive| directive.trim() == "no-store"))
+}
+
+/// The key a cacheable response to `request` is stored under.
+pub(crate) fn response_cache_key(request: &Request<Body>) -> String {
+    generate_cache_key(request, &CacheMiddlewareConfig::default())
+}
+
 fn generate_cache_key(request: &Request<Body>, config: &CacheMiddlewareConfig) -> String {
     let method = request.method().as_str();
     let path = request.uri().path();
     let query = request.uri().query().unwrap_or("");
     
-    if query.is_empty() {
+    let mut key = if query.is_empty() {
         format!("{}:{}:{}", config.key_prefix, method, path)
     } else {
         format!("{}:{}:{}?{}", config.key_prefix, method, path, query)
+    };
+    if ResponseMode::of(request) == ResponseMode::Raw {
+        key.push_str(":raw");
     }
+    crate::tenancy::scoped_cache_key(key)
 }
 
 async fn get_cached_response(
@@ -175,7 +342,7 @@ async fn get_cached_response(
         }
         
         response = response
-            .header("X-Cache", "HIT")
+            .header(CACHE, CacheStatus::Hit.header_value())
             .header("X-Cache-Key", cache_key);
         
         if let Ok(response) = response.body(Body::from(cached.body)) {
@@ -210,27 +377,16 @@ impl CacheManager {
     }
 
     pub fn invalidate_items_cache(&self) {
-        self.invalidate_pattern("http_cache:GET:/api/items");
-        self.invalidate_pattern("http_cache:GET:/api/v1/items");
-        self.invalidate_pattern("http_cache:GET:/api/v2/items");
+        self.invalidate_tag("items");
     }
 
     pub fn invalidate_item_cache(&self, item_id: u64) {
-        let patterns = [
-            format!("http_cache:GET:/api/items/{}", item_id),
-            format!("http_cache:GET:/api/v1/items/{}", item_id),
-            format!("http_cache:GET:/api/v2/items/{}", item_id),
-        ];
-        
-        for pattern in patterns {
-            self.invalidate_pattern(&pattern);
-        }
+        self.invalidate_tag(&format!("items:{}", item_id));
     }
 
+    /// Search results are collection views, tagged with the collection.
     pub fn invalidate_search_cache(&self) {
-        self.invalidate_pattern("http_cache:GET:/api/items/search");
-        self.invalidate_pattern("http_cache:GET:/api/v1/items/search");
-        self.invalidate_pattern("http_cache:GET:/api/v2/items/search");
+        self.invalidate_tag("items");
     }
 }
 
@@ -255,8 +411,47 @@ mod tests {
             .body(Body::empty())
             .unwrap();
         
+        let delete_request = Request::builder()
+            .method(Method::DELETE)
+            .uri("/api/items/1")
+            .body(Body::empty())
+            .unwrap();
+
+        // Signed in, so possibly with list defaults of the caller's own.
+        let authenticated_request = Request::builder()
+            .method(Method::GET)
+            .uri("/api/items")
+            .header(header::AUTHORIZATION, "Bearer token")
+            .body(Body::empty())
+            .unwrap();
+
+        let conditional_request = Request::builder()
+            .method(Method::GET)
+            .uri("/api/files/1/serve")
+            .header(header::IF_NONE_MATCH, "\"abc\"")
+            .body(Body::empty())
+            .unwrap();
+
         assert!(should_cache_request(&get_request, &config));
+        assert!(!should_cache_request(&authenticated_request, &config));
+        assert!(!should_cache_request(&conditional_request, &config));
         assert!(!should_cache_request(&post_request, &config));
+        assert!(!should_cache_request(&delete_request, &config));
+    }
+
+    #[test]
+    fn test_should_cache_response() {
+        let response = |status: StatusCode| Response::builder().status(status).body(Body::empty()).unwrap();
+
+        assert!(should_cache_response(&response(StatusCode::OK)));
+        assert!(!should_cache_response(&response(StatusCode::NO_CONTENT)));
+        assert!(!should_cache_response(&response(StatusCode::CREATED)));
+        assert!(!should_cache_response(&response(StatusCode::NOT_FOUND)));
+        let no_store = Response::builder()
+            .header(header::CACHE_CONTROL, "private, no-store")
+            .body(Body::empty())
+            .unwrap();
+        assert!(!should_cache_response(&no_store));
     }
 
     #[test]
@@ -284,4 +479,226 @@ mod tests {
         assert!(!should_cache_header("set-cookie"));
         assert!(!should_cache_header("authorization"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_resource_tags() {
+        let job_id = "6f1c2d3e-4a5b-4c6d-8e7f-901234567890";
+
+        assert_eq!(cache_tags("/api/items"), vec!["items"]);
+        assert_eq!(cache_tags("/api/v2/items/search"), vec!["items"]);
+        assert_eq!(cache_tags("/api/stats"), vec!["items"]);
+        assert_eq!(cache_tags("/api/tags"), vec!["items"]);
+        assert_eq!(cache_tags("/api/v1/items/42"), vec!["items:42"]);
+        assert_eq!(cache_tags(&format!("/api/jobs/{}/status", job_id)), vec![format!("jobs:{}", job_id)]);
+        assert_eq!(cache_tags("/api/files/item/7"), vec!["files"]);
+        assert_eq!(cache_tags(&format!("/public/assets/{}.png", "a".repeat(64))), vec!["files"]);
+        assert!(cache_tags("/api/metrics").is_empty());
+        assert!(cache_tags("/health").is_empty());
+
+        assert_eq!(invalidation_tags("/api/items"), vec!["items"]);
+        assert_eq!(invalidation_tags("/api/v2/items/42"), vec!["items", "items:42"]);
+        assert_eq!(
+            invalidation_tags(&format!("/api/jobs/{}/cancel", job_id)),
+            vec!["jobs".to_string(), format!("jobs:{}", job_id)]
+        );
+        assert_eq!(invalidation_tags("/api/jobs/bulk-import"), vec!["jobs"]);
+    }
+
+    #[tokio::test]
+    async fn test_mutation_invalidates_cached_lists() {
+        use axum::extract::ConnectInfo;
+        use std::net::SocketAddr;
+        use tower::ServiceExt;
+
+        let state = AppState::default().with_cache_manager(CacheManager::default());
+        let item = state
+            .item_service
+            .create_item("Cached item".to_string(), None, Vec::new(), None)
+            .await
+            .unwrap();
+        let mut config = crate::AppConfig::default();
+        config.rate_limit.enable = false;
+        let app = crate::create_app_with_config(state, config);
+
+        let send = |method: Method, uri: &str| {
+            let mut request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("user-agent", "cache-tests")
+                .body(Body::empty())
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+            app.clone().oneshot(request)
+        };
+        let read = |response: Response| async move {
+            let cache_status = response.headers()["x-cache"].to_str().unwrap().to_string();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (cache_status, String::from_utf8(body.to_vec()).unwrap())
+        };
+        let marker = format!("\"id\":{},", item.id);
+
+        for uri in ["/api/items?page=1", "/api/v1/items?page=1&page_size=5"] {
+            let (status, _) = read(send(Method::GET, uri).await.unwrap()).await;
+            assert_eq!(status, "MISS", "{}", uri);
+            let (status, _) = read(send(Method::GET, uri).await.unwrap()).await;
+            assert_eq!(status, "HIT", "{}", uri);
+        }
+        // Statistics are cached by their handler, which says so in the body
+        // and in `X-Cache`.
+        let (status, _) = read(send(Method::GET, "/api/stats").await.unwrap()).await;
+        assert_eq!(status, "MISS");
+        let (status, stats_before) = read(send(Method::GET, "/api/stats").await.unwrap()).await;
+        assert_eq!(status, "HIT");
+        assert!(stats_before.contains(r#""cache":"hit""#));
+        let (_, list_before) = read(send(Method::GET, "/api/items?page=1").await.unwrap()).await;
+        assert!(list_before.contains(&marker));
+
+        let response = send(Method::DELETE, &format!("/api/v2/items/{}", item.id)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        for uri in ["/api/items?page=1", "/api/v1/items?page=1&page_size=5"] {
+            let (status, body) = read(send(Method::GET, uri).await.unwrap()).await;
+            assert_eq!(status, "MISS", "{}", uri);
+            assert!(!body.contains(&marker), "{}", uri);
+        }
+        let (_, stats_after) = read(send(Method::GET, "/api/stats").await.unwrap()).await;
+        assert!(stats_after.contains(r#""cache":"miss""#));
+        assert_ne!(stats_after, stats_before);
+    }
+
+    #[tokio::test]
+    async fn test_cached_response_carries_the_requesting_origin() {
+        use axum::extract::ConnectInfo;
+        use std::net::SocketAddr;
+        use tower::ServiceExt;
+
+        let state = AppState::default().with_cache_manager(CacheManager::default());
+        let mut config = crate::AppConfig::default();
+        config.rate_limit.enable = false;
+        let app = crate::create_app_with_config(state, config);
+
+        let send = |origin: &'static str| {
+            let mut request = Request::get("/api/items")
+                .header("user-agent", "cache-tests")
+                .header(header::ORIGIN, origin)
+                .body(Body::empty())
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+            app.clone().oneshot(request)
+        };
+
+        let first = send("http://localhost:3000").await.unwrap();
+        assert_eq!(first.headers()["x-cache"], "MISS");
+        assert_eq!(first.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "http://localhost:3000");
+
+        let second = send("http://localhost:5173").await.unwrap();
+        assert_eq!(second.headers()["x-cache"], "HIT");
+        assert_eq!(second.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "http://localhost:5173");
+        assert_eq!(second.headers().get_all(header::VARY).iter().filter(|v| *v == "origin").count(), 1);
+    }
+    #[tokio::test]
+    async fn test_concurrent_identical_gets_are_coalesced() {
+        use axum::{routing::get, Extension, Router};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tower::ServiceExt;
+
+        let state = AppState::default().with_cache_manager(CacheManager::default());
+        state
+            .item_service
+            .create_item("Popular".to_string(), None, Vec::new(), None)
+            .await
+            .unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        async fn popular(
+            State(state): State<AppState>,
+            Extension(calls): Extension<Arc<AtomicUsize>>,
+        ) -> axum::Json<Vec<crate::store::Item>> {
+            calls.fetch_add(1, Ordering::SeqCst);
+            // Long enough for every request to arrive while this one runs.
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            axum::Json(state.item_service.get_items(None, None).await.unwrap())
+        }
+        let app = Router::new()
+            .route("/api/items/popular", get(popular))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), cache_middleware))
+            .layer(Extension(calls.clone()))
+            .with_state(state.clone());
+
+        let send = |uri: &'static str, authorization: Option<&'static str>| {
+            let mut request = Request::get(uri);
+            if let Some(authorization) = authorization {
+                request = request.header("authorization", authorization);
+            }
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let coalesced = response.headers().contains_key("x-coalesced");
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (coalesced, body)
+            }
+        };
+
+        let requests = (0..10).flat_map(|i| {
+            let uri = if i % 2 == 0 { "/api/items/popular?a=1&b=2" } else { "/api/items/popular?b=2&a=1" };
+            [send(uri, None), send(uri, Some("Bearer alice"))]
+        });
+        let responses = futures_util::future::join_all(requests).await;
+
+        // One run for the anonymous callers and one for alice.
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(responses.iter().filter(|(coalesced, _)| *coalesced).count(), 18);
+        assert!(responses.iter().all(|(_, body)| body == &responses[0].1));
+        assert_eq!(state.metrics.snapshot().unwrap().coalesced_requests, 18);
+        assert_eq!(state.cache_manager.as_ref().unwrap().request_coalescer().unwrap().in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_coalesced_requests_carry_their_own_origin() {
+        use crate::middleware::cors::{cors_middleware, CorsPolicy};
+        use axum::{routing::get, Extension, Router};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tower::ServiceExt;
+
+        let state = AppState::default().with_cache_manager(CacheManager::default());
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        async fn slow(Extension(calls): Extension<Arc<AtomicUsize>>) -> &'static str {
+            calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            "slow"
+        }
+        // Layered as in `create_app_with_config`, with CORS outside the cache.
+        let app = Router::new()
+            .route("/api/items/slow", get(slow))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), cache_middleware))
+            .layer(axum::middleware::from_fn_with_state(
+                CorsPolicy::from_config(&crate::config::CorsConfig::default()),
+                cors_middleware,
+            ))
+            .layer(Extension(calls.clone()))
+            .with_state(state);
+
+        let requests = (0..10).map(|i| {
+            let origin = if i % 2 == 0 { "http://localhost:3000" } else { "http://localhost:5173" };
+            let request = Request::get("/api/items/slow")
+                .header(header::ORIGIN, origin)
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move { (origin, app.oneshot(request).await.unwrap()) }
+        });
+        let responses = futures_util::future::join_all(requests).await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(responses.iter().filter(|(_, response)| response.headers().contains_key("x-coalesced")).count(), 9);
+        for (origin, response) in &responses {
+            assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], *origin);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Records exchanges for a running capture; see [`crate::capture`]
+//!
+//! Only installed when `capture.enabled` is set. Bodies are copied as they
+//! stream through rather than buffered, and an exchange is recorded once its
+//! response body has been sent or dropped.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use chrono::Utc;
+use futures_util::StreamExt;
+use parking_lot::Mutex;
+
+use crate::capture::{CaptureRecorder, CapturedBody, CapturedExchange};
+use crate::middleware::auth::AuthUser;
+
+/// Requests whose bodies carry credentials. They are recorded without
+/// either body.
+const BODYLESS_PATHS: &[&str] = &[
+    "/auth/login",
+    "/auth/register",
+    "/auth/refresh",
+    "/auth/password",
+    "/auth/reauthenticate",
+    "/auth/me",
+    "/auth/me/email/confirm",
+    "/auth/verify-email",
+    crate::handlers::introspect::INTROSPECT_PATH,
+    "/api/admin/users",
+];
+
+/// The capture endpoints themselves, left out so that downloading a capture
+/// doesn't record it.
+const CAPTURE_PATH: &str = "/api/admin/captures";
+
+pub async fn capture_middleware(
+    State(recorder): State<CaptureRecorder>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if path.starts_with(CAPTURE_PATH) {
+        return next.run(request).await;
+    }
+    let Some(session) = recorder.active() else {
+        return next.run(request).await;
+    };
+    let user = request.extensions().get::<AuthUser>().map(|user| user.username.clone());
+    if !session.matches(path, user.as_deref()) {
+        return next.run(request).await;
+    }
+
+    let omit_bodies = BODYLESS_PATHS.contains(&path);
+    let new_body = || if omit_bodies { CapturedBody::omitted() } else { CapturedBody::default() };
+    let cap = recorder.config().max_body_bytes;
+    let start = Instant::now();
+    let mut exchange = CapturedExchange {
+        started_at: Utc::now(),
+        duration: Default::default(),
+        method: request.method().to_string(),
+        url: request.uri().to_string(),
+        http_version: format!("{:?}", request.version()),
+        user,
+        request_headers: recorder.redact(request.headers()),
+        request_body: new_body(),
+        status: 0,
+        response_headers: Vec::new(),
+        response_body: new_body(),
+    };
+
+    let request_body = Arc::new(Mutex::new(new_body()));
+    let (parts, body) = request.into_parts();
+    let copy = request_body.clone();
+    let body = body.into_data_stream().map(move |chunk| {
+        if let Ok(bytes) = &chunk {
+            copy.lock().push(bytes, cap);
+        }
+        chunk
+    });
+    let response = next.run(Request::from_parts(parts, Body::from_stream(body))).await;
+
+    exchange.status = response.status().as_u16();
+    exchange.response_headers = recorder.redact(response.headers());
+    let mut pending = PendingExchange {
+        recorder,
+        exchange: Some(exchange),
+        request_body,
+        start,
+        cap,
+    };
+    let (parts, body) = response.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        if let Ok(bytes) = &chunk {
+            pending.push_response(bytes);
+        }
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+/// An exchange whose response is still being sent. It is recorded when
+/// dropped along with the response body.
+struct PendingExchange {
+    recorder: CaptureRecorder,
+    exchange: Option<CapturedExchange>,
+    request_body: Arc<Mutex<CapturedBody>>,
+    start: Instant,
+    cap: usize,
+}
+
+impl PendingExchange {
+    fn push_response(&mut self, chunk: &[u8]) {
+        if let Some(exchange) = self.exchange.as_mut() {
+            exchange.response_body.push(chunk, self.cap);
+        }
+    }
+}
+
+impl Drop for PendingExchange {
+    fn drop(&mut self) {
+        if let Some(mut exchange) = self.exchange.take() {
+            exchange.duration = self.start.elapsed();
+            exchange.request_body = std::mem::take(&mut *self.request_body.lock());
+            self.recorder.record(exchange);
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Single-flight for identical concurrent GETs
+//!
+//! When a burst of identical reads misses the response cache, only the
+//! first request goes to its handler; the others wait for its response and
+//! are sent a copy, marked `X-Coalesced: true`. Requests are identical when
+//! they share the method, path, query (in any parameter order), `Accept`
+//! and `Prefer` headers, namespace and caller, so requests with different
+//! credentials are never coalesced. The `Origin` header is left out, as
+//! CORS headers are added outside the cache to each copy separately. A
+//! waiting request gives up after `cache.coalesce_timeout_seconds` and runs
+//! itself, as it does when the response it waited for is streamed or too
+//! large to copy.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Request, StatusCode},
+    response::Response,
+};
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use tokio::sync::watch;
+
+use crate::middleware::auth::AuthUser;
+use crate::middleware::envelope::ResponseMode;
+
+/// What a leader publishes: a response to copy, or `None` when its
+/// response could not be shared and each follower must run itself.
+type Outcome = Option<Arc<SharedResponse>>;
+
+/// A buffered response handed to every request that joined its flight.
+#[derive(Debug)]
+pub struct SharedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl SharedResponse {
+    pub fn new(status: StatusCode, headers: HeaderMap, body: Bytes) -> Self {
+        Self { status, headers, body }
+    }
+
+    /// A copy of the response for one follower.
+    pub fn response(&self) -> Response {
+        let mut response = Response::new(Body::from(self.body.clone()));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        response.headers_mut().insert("X-Coalesced", HeaderValue::from_static("true"));
+        response
+    }
+}
+
+/// Whether `response` can be buffered and copied: its whole body is known
+/// up front and is no larger than `max_bytes`.
+pub fn is_shareable(response: &Response, max_bytes: usize) -> bool {
+    response
+        .body()
+        .size_hint()
+        .exact()
+        .is_some_and(|size| size <= max_bytes as u64)
+}
+
+/// The requests currently being answered, by coalescing key.
+#[derive(Debug, Clone)]
+pub struct RequestCoalescer {
+    flights: Arc<Mutex<HashMap<String, watch::Receiver<Option<Outcome>>>>>,
+    timeout: Duration,
+}
+
+/// The part a request plays in its flight.
+pub enum Flight {
+    /// Runs the request and publishes its response.
+    Leader(FlightLeader),
+    /// Waits for the leader's response.
+    Follower(watch::Receiver<Option<Outcome>>),
+}
+
+/// How a follower's wait ended.
+pub enum Joined {
+    Shared(Arc<SharedResponse>),
+    /// The leader's response could not be copied, or the leader went away
+    /// without one.
+    NotShared,
+    TimedOut,
+}
+
+impl RequestCoalescer {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            flights: Arc::new(Mutex::new(HashMap::new())),
+            timeout,
+        }
+    }
+
+    /// Joins the flight for `key`, starting it if there is none.
+    pub fn join(&self, key: String) -> Flight {
+        let mut flights = self.flights.lock();
+        if let Some(receiver) = flights.get(&key) {
+            return Flight::Follower(receiver.clone());
+        }
+        let (sender, receiver) = watch::channel(None);
+        flights.insert(key.clone(), receiver);
+        Flight::Leader(FlightLeader {
+            key,
+            flights: Arc::clone(&self.flights),
+            sender,
+            landed: false,
+        })
+    }
+
+    /// Waits up to the configured timeout for the leader of a flight.
+    pub async fn wait(&self, mut receiver: watch::Receiver<Option<Outcome>>) -> Joined {
+        match tokio::time::timeout(self.timeout, receiver.wait_for(Option::is_some)).await {
+            Ok(Ok(outcome)) => match outcome.clone().flatten() {
+                Some(response) => Joined::Shared(response),
+                None => Joined::NotShared,
+            },
+            // The leader was dropped, by a panic or a cancelled request.
+            Ok(Err(_)) => Joined::NotShared,
+            Err(_) => Joined::TimedOut,
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.flights.lock().len()
+    }
+}
+
+/// Held by the request answering a flight. Dropping it without
+/// [`finish`](Self::finish) releases its followers to run themselves.
+pub struct FlightLeader {
+    key: String,
+    flights: Arc<Mutex<HashMap<String, watch::Receiver<Option<Outcome>>>>>,
+    sender: watch::Sender<Option<Outcome>>,
+    landed: bool,
+}
+
+impl FlightLeader {
+    /// Hands `response` to every follower. Requests arriving from now on
+    /// start a new flight.
+    pub fn finish(mut self, response: Outcome) {
+        self.land();
+        let _ = self.sender.send(Some(response));
+    }
+
+    fn land(&mut self) {
+        if !self.landed {
+            self.flights.lock().remove(&self.key);
+            self.landed = true;
+        }
+    }
+}
+
+impl Drop for FlightLeader {
+    fn drop(&mut self) {
+        self.land();
+    }
+}
+
+/// The key `request` is coalesced under: method, path, query parameters
+/// in a stable order, negotiation headers, response mode, namespace and
+/// caller.
+pub fn coalesce_key(request: &Request<Body>) -> String {
+    let mut pairs: Vec<&str> = request
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .collect();
+    pairs.sort_unstable();
+
+    // Handlers that negotiate on these headers answer differently.
+    let negotiated = [header::ACCEPT, HeaderName::from_static("prefer")].map(|name| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    });
+
+    let mut key = format!(
+        "coalesce:{}:{}?{}:{}:{}",
+        request.method(),
+        request.uri().path(),
+        pairs.join("&"),
+        negotiated.join("|"),
+        principal(request)
+    );
+    if ResponseMode::of(request) == ResponseMode::Raw {
+        key.push_str(":raw");
+    }
+    crate::tenancy::scoped_cache_key(key)
+}
+
+/// Who is asking: the authenticated user, if any, and a digest of the
+/// credentials sent, so that no two callers share a response.
+fn principal(request: &Request<Body>) -> String {
+    let mut credentials = Sha256::new();
+    if let Some(authorization) = request.headers().get(header::AUTHORIZATION) {
+        credentials.update(authorization.as_bytes());
+    }
+    credentials.update([0]);
+    if let Some(session) = crate::middleware::csrf::session_token(request.headers()) {
+        credentials.update(session.as_bytes());
+    }
+    let credentials = hex::encode(&credentials.finalize()[..16]);
+
+    match request.extensions().get::<AuthUser>() {
+        Some(user) => format!(
+            "user={}/{}/{}/{}:{}",
+            user.user_id,
+            user.role,
+            user.namespace,
+            user.session_id.as_deref().unwrap_or_default(),
+            credentials
+        ),
+        None => format!("anonymous:{}", credentials),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::models::UserRole;
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn test_coalesce_key() {
+        assert_eq!(coalesce_key(&get("/api/items?b=2&a=1")), coalesce_key(&get("/api/items?a=1&b=2")));
+        assert_ne!(coalesce_key(&get("/api/items?a=1")), coalesce_key(&get("/api/items?a=2")));
+        let mut csv = get("/api/items?a=1");
+        csv.headers_mut().insert("accept", "text/csv".parse().unwrap());
+        assert_ne!(coalesce_key(&csv), coalesce_key(&get("/api/items?a=1")));
+
+        let mut alice = get("/api/items");
+        alice.headers_mut().insert("authorization", "Bearer alice".parse().unwrap());
+        let mut bob = get("/api/items");
+        bob.headers_mut().insert("authorization", "Bearer bob".parse().unwrap());
+        assert_ne!(coalesce_key(&alice), coalesce_key(&bob));
+        assert_ne!(coalesce_key(&alice), coalesce_key(&get("/api/items")));
+
+        let mut admin = get("/api/items");
+        admin.extensions_mut().insert(AuthUser::new(1, "admin".to_string(), UserRole::Admin));
+        let mut user = get("/api/items");
+        user.extensions_mut().insert(AuthUser::new(2, "user".to_string(), UserRole::User));
+        assert_ne!(coalesce_key(&admin), coalesce_key(&user));
+    }
+
+    #[tokio::test]
+    async fn test_followers_share_the_leader_response() {
+        let coalescer = RequestCoalescer::new(Duration::from_secs(5));
+        let Flight::Leader(leader) = coalescer.join("key".to_string()) else {
+            panic!("first request should lead");
+        };
+        let Flight::Follower(follower) = coalescer.join("key".to_string()) else {
+            panic!("second request should follow");
+        };
+
+        let shared = SharedResponse::new(StatusCode::OK, HeaderMap::new(), Bytes::from_static(b"hello"));
+        leader.finish(Some(Arc::new(shared)));
+        assert_eq!(coalescer.in_flight(), 0);
+
+        let Joined::Shared(response) = coalescer.wait(follower).await else {
+            panic!("follower should get the response");
+        };
+        let response = response.response();
+        assert_eq!(response.headers()["x-coalesced"], "true");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_followers_are_released() {
+        let coalescer = RequestCoalescer::new(Duration::from_millis(20));
+
+        // A leader that hangs.
+        let _leader = coalescer.join("hung".to_string());
+        let Flight::Follower(follower) = coalescer.join("hung".to_string()) else {
+            panic!("second request should follow");
+        };
+        assert!(matches!(coalescer.wait(follower).await, Joined::TimedOut));
+
+        // A leader that goes away.
+        let leader = coalescer.join("dropped".to_string());
+        let Flight::Follower(follower) = coalescer.join("dropped".to_string()) else {
+            panic!("second request should follow");
+        };
+        drop(leader);
+        assert!(matches!(coalescer.wait(follower).await, Joined::NotShared));
+        assert!(matches!(coalescer.join("dropped".to_string()), Flight::Leader(_)));
+    }
+}
@@ -0,0 +1,316 @@
+//! Concurrency limits across the server and per client address
+
+use crate::config::ConcurrencyConfig;
+use crate::metrics::{names, InFlightGauge, InFlightGuard, MetricsSink};
+use crate::middleware::load_shed::TrafficClass;
+use crate::middleware::timeout::is_streaming_request;
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::StreamExt;
+use parking_lot::Mutex;
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Why a request was turned away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// `server.max_connections` requests were already in flight.
+    ServerLimit,
+    /// The client's queue was full.
+    QueueFull,
+    /// The request waited in its client's queue longer than allowed.
+    QueueTimeout,
+    /// A WebSocket or event stream was opened while the client was at its
+    /// limit.
+    StreamLimit,
+}
+
+impl Rejection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rejection::ServerLimit => "server_limit",
+            Rejection::QueueFull => "queue_full",
+            Rejection::QueueTimeout => "queue_timeout",
+            Rejection::StreamLimit => "stream_limit",
+        }
+    }
+
+    /// 429 when the client is over its own share, 503 when the server is.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Rejection::QueueFull | Rejection::StreamLimit => StatusCode::TOO_MANY_REQUESTS,
+            Rejection::ServerLimit | Rejection::QueueTimeout => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+/// One client's slots and how many of its requests hold or wait for one.
+struct Client {
+    slots: Arc<Semaphore>,
+    active: usize,
+}
+
+type Clients = Arc<Mutex<HashMap<IpAddr, Client>>>;
+
+/// Counts a request as active for its client until dropped, forgetting the
+/// client once it has nothing active.
+struct Membership {
+    clients: Clients,
+    ip: IpAddr,
+}
+
+impl Drop for Membership {
+    fn drop(&mut self) {
+        let mut clients = self.clients.lock();
+        if let Some(client) = clients.get_mut(&self.ip) {
+            client.active -= 1;
+            if client.active == 0 {
+                clients.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// The slots held by an admitted request, released when dropped.
+pub struct ConcurrencyPermit {
+    _server: OwnedSemaphorePermit,
+    _client: Option<(OwnedSemaphorePermit, Membership)>,
+    _in_flight: InFlightGuard,
+}
+
+/// Keeps a WebSocket or event stream's slots for as long as it is open.
+/// Added to upgrade requests so the handler can move it into the socket.
+#[derive(Clone)]
+pub struct ConnectionSlot {
+    _permit: Arc<ConcurrencyPermit>,
+}
+
+/// Admits requests while fewer than `server.max_connections` are in flight
+/// overall and the client is within its own limit. A client at its limit
+/// queues, first come first served, so one busy client cannot take the
+/// slots others are waiting for.
+#[derive(Clone)]
+pub struct ConcurrencyLimiter {
+    config: Arc<ConcurrencyConfig>,
+    server_slots: Arc<Semaphore>,
+    clients: Clients,
+    in_flight: InFlightGauge,
+    queued: InFlightGauge,
+    metrics: Arc<dyn MetricsSink>,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(max_connections: usize, config: &ConcurrencyConfig, metrics: Arc<dyn MetricsSink>) -> Self {
+        Self {
+            config: Arc::new(config.clone()),
+            server_slots: Arc::new(Semaphore::new(max_connections)),
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            in_flight: InFlightGauge::new(names::LIMITED_IN_FLIGHT, metrics.clone()),
+            queued: InFlightGauge::new(names::QUEUED_REQUESTS, metrics.clone()),
+            metrics,
+        }
+    }
+
+    /// Admits a request from `client`, when known, waiting in its queue if
+    /// it is at its limit. Streams are admitted or rejected at once.
+    pub async fn admit(&self, client: Option<IpAddr>, streaming: bool) -> Result<ConcurrencyPermit, Rejection> {
+        let client = match client {
+            Some(ip) => Some(self.client_slot(ip, streaming).await?),
+            None => None,
+        };
+        let server = self
+            .server_slots
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| Rejection::ServerLimit)?;
+
+        Ok(ConcurrencyPermit {
+            _server: server,
+            _client: client,
+            _in_flight: self.in_flight.track(),
+        })
+    }
+
+    async fn client_slot(&self, ip: IpAddr, streaming: bool) -> Result<(OwnedSemaphorePermit, Membership), Rejection> {
+        let slots = {
+            let mut clients = self.clients.lock();
+            let client = clients.entry(ip).or_insert_with(|| Client {
+                slots: Arc::new(Semaphore::new(self.config.per_client_limit)),
+                active: 0,
+            });
+            let (capacity, rejection) = if streaming {
+                (self.config.per_client_limit, Rejection::StreamLimit)
+            } else {
+                (self.config.per_client_limit + self.config.per_client_queue, Rejection::QueueFull)
+            };
+            if client.active >= capacity {
+                return Err(rejection);
+            }
+            client.active += 1;
+            client.slots.clone()
+        };
+        let membership = Membership {
+            clients: self.clients.clone(),
+            ip,
+        };
+
+        if let Ok(permit) = slots.clone().try_acquire_owned() {
+            return Ok((permit, membership));
+        }
+        if streaming {
+            return Err(Rejection::StreamLimit);
+        }
+
+        let _queued = self.queued.track();
+        let timeout = Duration::from_millis(self.config.queue_timeout_ms);
+        match tokio::time::timeout(timeout, slots.acquire_owned()).await {
+            Ok(Ok(permit)) => Ok((permit, membership)),
+            _ => Err(Rejection::QueueTimeout),
+        }
+    }
+
+    /// Clients with a request in flight or queued.
+    pub fn active_clients(&self) -> usize {
+        self.clients.lock().len()
+    }
+}
+
+fn rejected_response(rejection: Rejection, path: &str, retry_after_seconds: u64) -> Response {
+    let status = rejection.status();
+    let detail = match rejection {
+        Rejection::ServerLimit => "Server is at its connection limit; retry later",
+        Rejection::QueueFull => "Too many concurrent requests from this client",
+        Rejection::QueueTimeout => "Timed out waiting for a free request slot",
+        Rejection::StreamLimit => "Too many concurrent connections from this client",
+    };
+    let body = json!({
+        "type": "about:blank",
+        "title": status.canonical_reason().unwrap_or("Error"),
+        "status": status.as_u16(),
+        "detail": detail,
+        "reason": rejection.as_str(),
+        "instance": path,
+    });
+
+    let mut response = (status, body.to_string()).into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/problem+json"));
+    headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after_seconds));
+    response
+}
+
+/// Holds a slot under [`ConcurrencyLimiter`] for each request, rejecting
+/// with 429 or 503 and `Retry-After` when none can be had. Health checks
+/// and admin endpoints are never limited.
+pub async fn concurrency_limit_middleware(
+    State(limiter): State<ConcurrencyLimiter>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    if TrafficClass::of(request.method(), request.uri().path()) == TrafficClass::Essential {
+        return next.run(request).await;
+    }
+
+    let streaming = is_streaming_request(&request);
+    let client = connect_info.map(|ConnectInfo(addr)| addr.ip());
+    let permit = match limiter.admit(client, streaming).await {
+        Ok(permit) => permit,
+        Err(rejection) => {
+            limiter.metrics.record_concurrency_rejection(rejection.as_str());
+            tracing::debug!(client = ?client, reason = rejection.as_str(), "Request rejected by concurrency limit");
+            return rejected_response(rejection, request.uri().path(), limiter.config.retry_after_seconds);
+        }
+    };
+
+    if !streaming {
+        let response = next.run(request).await;
+        drop(permit);
+        return response;
+    }
+
+    // An upgraded socket outlives its response, so the handler takes the
+    // slot from the request; an event stream keeps it in its body.
+    let slot = ConnectionSlot { _permit: Arc::new(permit) };
+    request.extensions_mut().insert(slot.clone());
+    let (parts, body) = next.run(request).await.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _slot = &slot;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::MetricsCollector;
+
+    fn limiter(max_connections: usize, per_client_limit: usize, per_client_queue: usize) -> ConcurrencyLimiter {
+        let config = ConcurrencyConfig {
+            per_client_limit,
+            per_client_queue,
+            queue_timeout_ms: 200,
+            ..ConcurrencyConfig::default()
+        };
+        ConcurrencyLimiter::new(max_connections, &config, Arc::new(MetricsCollector::new()))
+    }
+
+    fn ip(last: u8) -> Option<IpAddr> {
+        Some(IpAddr::from([10, 0, 0, last]))
+    }
+
+    #[tokio::test]
+    async fn test_client_queue_then_rejects() {
+        let limiter = limiter(100, 2, 1);
+        let first = limiter.admit(ip(1), false).await.unwrap();
+        let _second = limiter.admit(ip(1), false).await.unwrap();
+
+        // Third waits for a slot; a fourth finds the queue full.
+        let queued = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.admit(ip(1), false).await.map(|_| ()) }
+        });
+        while limiter.metrics.in_memory().unwrap().concurrency().queued == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(limiter.admit(ip(1), false).await.err(), Some(Rejection::QueueFull));
+        assert_eq!(limiter.admit(ip(1), true).await.err(), Some(Rejection::StreamLimit));
+
+        // Other clients are unaffected.
+        assert!(limiter.admit(ip(2), false).await.is_ok());
+
+        drop(first);
+        assert!(queued.await.unwrap().is_ok());
+        assert_eq!(limiter.metrics.in_memory().unwrap().concurrency().queued, 0);
+    }
+
+    #[tokio::test]
+    async fn test_queued_request_times_out() {
+        let limiter = limiter(100, 1, 4);
+        let _held = limiter.admit(ip(1), false).await.unwrap();
+        assert_eq!(limiter.admit(ip(1), false).await.err(), Some(Rejection::QueueTimeout));
+    }
+
+    #[tokio::test]
+    async fn test_server_limit_and_cleanup() {
+        let limiter = limiter(2, 4, 4);
+        let first = limiter.admit(ip(1), false).await.unwrap();
+        let _second = limiter.admit(None, false).await.unwrap();
+        assert_eq!(limiter.admit(ip(2), false).await.err(), Some(Rejection::ServerLimit));
+        assert_eq!(limiter.metrics.in_memory().unwrap().concurrency().in_flight, 2);
+
+        drop(first);
+        assert_eq!(limiter.active_clients(), 0);
+        assert!(limiter.admit(ip(2), false).await.is_ok());
+    }
+}
@@ -1,8 +1,275 @@
 //! CORS (Cross-Origin Resource Sharing) middleware configuration
 
-use crate::config::CorsConfig;
+use crate::config::{CorsConfig, CorsRouteConfig};
 use tower_http::cors::{Any, CorsLayer as TowerCorsLayer};
-use axum::http::{HeaderValue, Method, HeaderName};
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Method, HeaderName, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use regex::Regex;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// An allowed origin: `*`, an exact origin, a `*.` subdomain wildcard with an
+/// optional scheme (`https://*.partner.example`), or `regex:<expression>`.
+/// Regular expressions must match the whole origin.
+#[derive(Debug, Clone)]
+pub enum OriginPattern {
+    Any,
+    Exact(String),
+    Subdomain { scheme: Option<String>, suffix: String },
+    Regex(Regex),
+}
+
+impl OriginPattern {
+    pub fn is_any(&self) -> bool {
+        matches!(self, OriginPattern::Any)
+    }
+
+    pub fn matches(&self, origin: &str) -> bool {
+        match self {
+            OriginPattern::Any => true,
+            OriginPattern::Exact(allowed) => allowed.eq_ignore_ascii_case(origin),
+            OriginPattern::Subdomain { scheme, suffix } => {
+                let origin = origin.to_ascii_lowercase();
+                let (origin_scheme, host) = match origin.split_once("://") {
+                    Some(parts) => parts,
+                    None => return false,
+                };
+                if scheme.as_deref().is_some_and(|scheme| scheme != origin_scheme) {
+                    return false;
+                }
+                host.strip_suffix(suffix.as_str())
+                    .is_some_and(|subdomain| !subdomain.is_empty() && !subdomain.contains([':', '/', '@']))
+            }
+            OriginPattern::Regex(regex) => regex.is_match(origin),
+        }
+    }
+}
+
+impl FromStr for OriginPattern {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let pattern = pattern.trim();
+
+        if pattern == "*" {
+            return Ok(OriginPattern::Any);
+        }
+
+        if let Some(expression) = pattern.strip_prefix("regex:") {
+            return Regex::new(&format!("^(?:{})$", expression))
+                .map(OriginPattern::Regex)
+                .map_err(|e| format!("invalid regular expression: {}", e));
+        }
+
+        let (scheme, rest) = match pattern.split_once("://") {
+            Some((scheme, rest)) => (Some(scheme.to_ascii_lowercase()), rest),
+            None => (None, pattern),
+        };
+
+        if let Some(domain) = rest.strip_prefix("*.") {
+            if domain.is_empty() || domain.contains('*') {
+                return Err("wildcard must be followed by a domain".to_string());
+            }
+            return Ok(OriginPattern::Subdomain {
+                scheme,
+                suffix: format!(".{}", domain.to_ascii_lowercase()),
+            });
+        }
+
+        if scheme.is_none() || rest.is_empty() || rest.contains(['*', '/']) {
+            return Err("expected an origin such as https://app.example.com".to_string());
+        }
+
+        Ok(OriginPattern::Exact(pattern.to_string()))
+    }
+}
+
+/// One compiled CORS policy, either the global one or a route override.
+#[derive(Debug)]
+struct CorsRule {
+    origins: Vec<OriginPattern>,
+    allow_methods: Option<HeaderValue>,
+    allow_headers: Option<HeaderValue>,
+    expose_headers: Option<HeaderValue>,
+    allow_credentials: bool,
+    max_age: HeaderValue,
+}
+
+fn join_header(values: &[String]) -> Option<HeaderValue> {
+    if values.is_empty() {
+        return None;
+    }
+    HeaderValue::from_str(&values.join(", ")).ok()
+}
+
+fn parse_origins(origins: &[String]) -> Vec<OriginPattern> {
+    origins
+        .iter()
+        .filter_map(|origin| match origin.parse() {
+            Ok(pattern) => Some(pattern),
+            Err(e) => {
+                tracing::warn!("Ignoring invalid CORS origin '{}': {}", origin, e);
+                None
+            }
+        })
+        .collect()
+}
+
+impl CorsRule {
+    fn global(config: &CorsConfig) -> Self {
+        if config.enable_permissive_mode {
+            return Self {
+                origins: vec![OriginPattern::Any],
+                allow_methods: None,
+                allow_headers: None,
+                expose_headers: Some(HeaderValue::from_static("*")),
+                allow_credentials: false,
+                max_age: HeaderValue::from(config.max_age_seconds),
+            };
+        }
+
+        Self {
+            origins: parse_origins(&config.allowed_origins),
+            allow_methods: join_header(&config.allowed_methods),
+            allow_headers: join_header(&config.allowed_headers),
+            expose_headers: join_header(&config.exposed_headers),
+            allow_credentials: config.allow_credentials,
+            max_age: HeaderValue::from(config.max_age_seconds),
+        }
+    }
+
+    fn route(config: &CorsConfig, route: &CorsRouteConfig) -> Self {
+        let pick = |own: &Option<Vec<String>>, global: &[String]| {
+            join_header(own.as_deref().unwrap_or(global))
+        };
+
+        Self {
+            origins: parse_origins(&route.allowed_origins),
+            allow_methods: pick(&route.allowed_methods, &config.allowed_methods),
+            allow_headers: pick(&route.allowed_headers, &config.allowed_headers),
+            expose_headers: pick(&route.exposed_headers, &config.exposed_headers),
+            allow_credentials: route.allow_credentials.unwrap_or(config.allow_credentials),
+            max_age: HeaderValue::from(route.max_age_seconds.unwrap_or(config.max_age_seconds)),
+        }
+    }
+
+    /// The `Access-Control-Allow-Origin` value for `origin`, if it is allowed.
+    fn allow_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        let origin_str = origin.to_str().ok()?;
+        let pattern = self.origins.iter().find(|pattern| pattern.matches(origin_str))?;
+
+        if pattern.is_any() && !self.allow_credentials {
+            Some(HeaderValue::from_static("*"))
+        } else {
+            Some(origin.clone())
+        }
+    }
+}
+
+/// Per-request CORS evaluation with route-scoped rules. Requests are matched
+/// to the rule with the longest path prefix, falling back to the global
+/// policy from [`CorsConfig`].
+#[derive(Clone)]
+pub struct CorsPolicy {
+    global: Arc<CorsRule>,
+    routes: Arc<Vec<(String, CorsRule)>>,
+}
+
+impl CorsPolicy {
+    pub fn from_config(config: &CorsConfig) -> Self {
+        let mut routes: Vec<(String, CorsRule)> = config
+            .routes
+            .iter()
+            .map(|route| (route.path.trim_end_matches('/').to_string(), CorsRule::route(config, route)))
+            .collect();
+        routes.sort_by_key(|(path, _)| std::cmp::Reverse(path.len()));
+
+        Self {
+            global: Arc::new(CorsRule::global(config)),
+            routes: Arc::new(routes),
+        }
+    }
+
+    fn rule_for(&self, path: &str) -> &CorsRule {
+        self.routes
+            .iter()
+            .find(|(prefix, _)| {
+                path == prefix
+                    || path
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+            .map_or(&self.global, |(_, rule)| rule)
+    }
+}
+
+fn append_vary(headers: &mut HeaderMap, values: &'static str) {
+    headers.append(header::VARY, HeaderValue::from_static(values));
+}
+
+fn is_preflight(request: &Request<Body>) -> bool {
+    request.method() == Method::OPTIONS
+        && request.headers().contains_key(header::ORIGIN)
+        && request.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+/// Answers preflight requests directly and decorates actual cross-origin
+/// responses. Disallowed origins get no CORS headers, which the browser
+/// treats as a denial.
+pub async fn cors_middleware(
+    State(policy): State<CorsPolicy>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let rule = policy.rule_for(request.uri().path());
+    let origin = request.headers().get(header::ORIGIN).cloned();
+    let allowed_origin = origin.as_ref().and_then(|origin| rule.allow_origin(origin));
+
+    if is_preflight(&request) {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let headers = response.headers_mut();
+        append_vary(headers, "origin, access-control-request-method, access-control-request-headers");
+
+        if let Some(allowed_origin) = allowed_origin {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allowed_origin);
+            let requested_method = request.headers().get(header::ACCESS_CONTROL_REQUEST_METHOD).cloned();
+            if let Some(methods) = rule.allow_methods.clone().or(requested_method) {
+                headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, methods);
+            }
+            let requested_headers = request.headers().get(header::ACCESS_CONTROL_REQUEST_HEADERS).cloned();
+            if let Some(allowed_headers) = rule.allow_headers.clone().or(requested_headers) {
+                headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers);
+            }
+            if rule.allow_credentials {
+                headers.insert(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+            }
+            headers.insert(header::ACCESS_CONTROL_MAX_AGE, rule.max_age.clone());
+        }
+
+        return response;
+    }
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    append_vary(headers, "origin");
+
+    if let Some(allowed_origin) = allowed_origin {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allowed_origin);
+        if rule.allow_credentials {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+        }
+        if let Some(exposed) = rule.expose_headers.clone() {
+            headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, exposed);
+        }
+    }
+
+    response
+}
 
 pub fn cors_layer_from_config(config: &CorsConfig) -> TowerCorsLayer {
     if config.enable_permissive_mode {
@@ -118,4 +385,175 @@ pub fn cors_layer_production(allowed_origins: Vec<&str>) -> TowerCorsLayer {
         ])
         .allow_credentials(true)
         .max_age(std::time::Duration::from_secs(3600))
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+    use tower::ServiceExt;
+
+    fn config() -> CorsConfig {
+        CorsConfig {
+            allowed_origins: vec!["https://app.example.com".to_string()],
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+            allowed_headers: vec!["content-type".to_string(), "authorization".to_string()],
+            exposed_headers: vec!["x-request-id".to_string()],
+            allow_credentials: true,
+            max_age_seconds: 600,
+            enable_permissive_mode: false,
+            routes: vec![
+                CorsRouteConfig {
+                    path: "/api/items".to_string(),
+                    allowed_origins: vec![
+                        "https://app.example.com".to_string(),
+                        "https://*.partner.example".to_string(),
+                        r"regex:https://tenant-[0-9]+\.example\.org".to_string(),
+                    ],
+                    allowed_methods: Some(vec!["GET".to_string()]),
+                    allowed_headers: None,
+                    exposed_headers: None,
+                    allow_credentials: Some(false),
+                    max_age_seconds: Some(60),
+                },
+                CorsRouteConfig {
+                    path: "/public".to_string(),
+                    allowed_origins: vec!["*".to_string()],
+                    allowed_methods: None,
+                    allowed_headers: None,
+                    exposed_headers: None,
+                    allow_credentials: Some(false),
+                    max_age_seconds: None,
+                },
+            ],
+        }
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route("/auth/login", get(|| async { "login" }))
+            .route("/api/items", get(|| async { "items" }))
+            .route("/public/info", get(|| async { "info" }))
+            .layer(axum::middleware::from_fn_with_state(
+                CorsPolicy::from_config(&config()),
+                cors_middleware,
+            ))
+    }
+
+    async fn send(method: Method, path: &str, origin: &str) -> Response {
+        let mut request = Request::builder().method(method.clone()).uri(path).header(header::ORIGIN, origin);
+        if method == Method::OPTIONS {
+            request = request
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+                .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type");
+        }
+        app().oneshot(request.body(Body::empty()).unwrap()).await.unwrap()
+    }
+
+    fn allow_origin(response: &Response) -> Option<&str> {
+        response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .map(|value| value.to_str().unwrap())
+    }
+
+    #[test]
+    fn test_origin_patterns() {
+        let exact: OriginPattern = "https://app.example.com".parse().unwrap();
+        assert!(exact.matches("https://app.example.com"));
+        assert!(!exact.matches("https://app.example.com.evil.test"));
+
+        let wildcard: OriginPattern = "https://*.partner.example".parse().unwrap();
+        assert!(wildcard.matches("https://eu.partner.example"));
+        assert!(wildcard.matches("https://a.b.partner.example"));
+        assert!(!wildcard.matches("https://partner.example"));
+        assert!(!wildcard.matches("http://eu.partner.example"));
+        assert!(!wildcard.matches("https://evilpartner.example"));
+
+        let any_scheme: OriginPattern = "*.partner.example".parse().unwrap();
+        assert!(any_scheme.matches("http://eu.partner.example"));
+
+        let regex: OriginPattern = r"regex:https://tenant-[0-9]+\.example\.org".parse().unwrap();
+        assert!(regex.matches("https://tenant-42.example.org"));
+        assert!(!regex.matches("https://tenant-42.example.org.evil.test"));
+
+        assert!("*".parse::<OriginPattern>().unwrap().is_any());
+        assert!("app.example.com".parse::<OriginPattern>().is_err());
+        assert!("https://*.".parse::<OriginPattern>().is_err());
+        assert!("regex:(".parse::<OriginPattern>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_exact_origin_rule() {
+        let preflight = send(Method::OPTIONS, "/auth/login", "https://app.example.com").await;
+        assert_eq!(preflight.status(), StatusCode::NO_CONTENT);
+        assert_eq!(allow_origin(&preflight), Some("https://app.example.com"));
+        assert_eq!(preflight.headers()[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(preflight.headers()[header::ACCESS_CONTROL_MAX_AGE], "600");
+        assert_eq!(preflight.headers()[header::ACCESS_CONTROL_ALLOW_METHODS], "GET, POST");
+
+        let actual = send(Method::GET, "/auth/login", "https://app.example.com").await;
+        assert_eq!(actual.status(), StatusCode::OK);
+        assert_eq!(allow_origin(&actual), Some("https://app.example.com"));
+        assert_eq!(actual.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS], "x-request-id");
+        assert_eq!(actual.headers()[header::VARY], "origin");
+
+        let partner = send(Method::GET, "/auth/login", "https://eu.partner.example").await;
+        assert_eq!(allow_origin(&partner), None);
+        let partner_preflight = send(Method::OPTIONS, "/auth/login", "https://eu.partner.example").await;
+        assert_eq!(allow_origin(&partner_preflight), None);
+    }
+
+    #[tokio::test]
+    async fn test_wildcard_and_regex_route_rule() {
+        for origin in ["https://eu.partner.example", "https://tenant-7.example.org"] {
+            let preflight = send(Method::OPTIONS, "/api/items", origin).await;
+            assert_eq!(allow_origin(&preflight), Some(origin));
+            assert_eq!(preflight.headers()[header::ACCESS_CONTROL_ALLOW_METHODS], "GET");
+            assert_eq!(preflight.headers()[header::ACCESS_CONTROL_MAX_AGE], "60");
+            assert!(preflight.headers().get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+
+            let actual = send(Method::GET, "/api/items", origin).await;
+            assert_eq!(allow_origin(&actual), Some(origin));
+        }
+
+        let outsider = send(Method::GET, "/api/items", "https://evil.test").await;
+        assert_eq!(outsider.status(), StatusCode::OK);
+        assert_eq!(allow_origin(&outsider), None);
+        assert_eq!(outsider.headers()[header::VARY], "origin");
+    }
+
+    #[tokio::test]
+    async fn test_any_origin_route_rule() {
+        let preflight = send(Method::OPTIONS, "/public/info", "https://anyone.test").await;
+        assert_eq!(allow_origin(&preflight), Some("*"));
+
+        let actual = send(Method::GET, "/public/info", "https://anyone.test").await;
+        assert_eq!(allow_origin(&actual), Some("*"));
+    }
+
+    #[test]
+    fn test_credentials_with_wildcard_origin_is_rejected() {
+        let mut config = config();
+        assert!(config.validate().is_ok());
+
+        config.routes[1].allow_credentials = Some(true);
+        assert!(config.validate().is_err());
+
+        let mut config = CorsConfig::default();
+        config.allowed_origins = vec!["*".to_string()];
+        assert!(config.validate().is_err());
+        config.allow_credentials = false;
+        assert!(config.validate().is_ok());
+
+        config.routes.push(CorsRouteConfig {
+            path: "/api".to_string(),
+            allowed_origins: vec!["regex:(".to_string()],
+            allowed_methods: None,
+            allowed_headers: None,
+            exposed_headers: None,
+            allow_credentials: None,
+            max_age_seconds: None,
+        });
+        assert!(config.validate().is_err());
+    }
+}
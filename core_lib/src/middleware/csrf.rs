@@ -0,0 +1,109 @@
+//! Cookie sessions for the admin page, and the CSRF tokens guarding them
+//!
+//! The admin page signs in through `POST /admin/session`, which keeps the
+//! access token in an HttpOnly cookie rather than handing it to scripts.
+//! Browsers attach that cookie to every request to the server, so a request
+//! it authenticates that changes anything must also repeat the token from
+//! `GET /admin/csrf-token` in `X-CSRF-Token`, matching the `csrf_token`
+//! cookie set alongside it. Other origins can make the browser send the
+//! cookies but cannot read them to copy the token. Requests with a bearer
+//! token are not affected.
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::http::{header::COOKIE, HeaderMap, Method};
+
+use crate::error::AppError;
+
+pub const SESSION_COOKIE: &str = "admin_session";
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// The value of cookie `name`, if the request has a non-empty one.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.trim())
+        .filter(|value| !value.is_empty())
+}
+
+/// The access token in the admin session cookie.
+pub fn session_token(headers: &HeaderMap) -> Option<&str> {
+    cookie(headers, SESSION_COOKIE)
+}
+
+/// Checks a cookie-authenticated request: methods that change something
+/// need the CSRF header to match the CSRF cookie.
+pub fn verify(method: &Method, headers: &HeaderMap) -> Result<(), AppError> {
+    if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(());
+    }
+
+    let expected = cookie(headers, CSRF_COOKIE);
+    let given = headers.get(CSRF_HEADER).and_then(|value| value.to_str().ok());
+    match (expected, given) {
+        (Some(expected), Some(given)) if constant_time_eq(expected.as_bytes(), given.as_bytes()) => Ok(()),
+        _ => Err(AppError::Authorization(
+            "Missing or mismatched CSRF token; fetch one from /admin/csrf-token".to_string(),
+        )),
+    }
+}
+
+pub fn new_token() -> String {
+    let mut token = [0u8; 32];
+    OsRng.fill_bytes(&mut token);
+    hex::encode(token)
+}
+
+/// A `Set-Cookie` value for `name`, sent only to this site. `max_age` of
+/// zero removes the cookie.
+pub fn set_cookie(name: &str, value: &str, max_age: i64, http_only: bool, secure: bool) -> String {
+    let mut cookie = format!("{}={}; Path=/; Max-Age={}; SameSite=Strict", name, value, max_age.max(0));
+    if http_only {
+        cookie.push_str("; HttpOnly");
+    }
+    if secure {
+        cookie.push_str("; Secure");
+    }
+    cookie
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_verify_double_submitted_token() {
+        let cookies = "theme=dark; admin_session=abc; csrf_token=t0k3n";
+        assert_eq!(session_token(&headers(&[("cookie", cookies)])), Some("abc"));
+        assert_eq!(session_token(&headers(&[("cookie", "admin_session=")])), None);
+
+        assert!(verify(&Method::GET, &headers(&[("cookie", cookies)])).is_ok());
+        assert!(verify(&Method::POST, &headers(&[("cookie", cookies)])).is_err());
+        assert!(verify(&Method::DELETE, &headers(&[("cookie", cookies), (CSRF_HEADER, "t0k3n")])).is_ok());
+        assert!(verify(&Method::PATCH, &headers(&[("cookie", cookies), (CSRF_HEADER, "t0k3m")])).is_err());
+        assert!(verify(&Method::POST, &headers(&[("cookie", "admin_session=abc"), (CSRF_HEADER, "")])).is_err());
+
+        assert_eq!(
+            set_cookie(SESSION_COOKIE, "abc", 60, true, true),
+            "admin_session=abc; Path=/; Max-Age=60; SameSite=Strict; HttpOnly; Secure"
+        );
+        assert_ne!(new_token(), new_token());
+    }
+}
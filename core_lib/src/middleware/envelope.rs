@@ -0,0 +1,255 @@
+//! Opt-out of the `ApiResponse` envelope for item, file and job reads
+//!
+//! Clients that send `Prefer: return=representation` or `?envelope=false`
+//! get the bare resource. Lists become a top-level array with their paging
+//! fields moved to headers, and errors become `application/problem+json`.
+//! Without either signal responses are left untouched.
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::{json, Map, Value};
+
+/// Collections whose reads can be requested raw, with the field naming a
+/// single resource inside a wrapped detail response.
+const RAW_RESOURCES: [(&str, &str); 3] = [("items", "item"), ("files", "file"), ("jobs", "job")];
+
+/// List fields moved to headers in raw mode.
+const PAGINATION_HEADERS: [(&str, &str); 8] = [
+    ("total", "x-total-count"),
+    ("total_count", "x-total-count"),
+    ("count", "x-result-count"),
+    ("page", "x-page"),
+    ("page_size", "x-page-size"),
+    ("limit", "x-limit"),
+    ("offset", "x-offset"),
+    ("has_more", "x-has-more"),
+];
+
+/// How a response body is shaped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseMode {
+    /// Data wrapped in `ApiResponse`, the default.
+    Envelope,
+    /// The bare resource.
+    Raw,
+}
+
+impl ResponseMode {
+    /// The mode asked for by `request`, whatever its path.
+    pub fn of(request: &Request<Body>) -> Self {
+        let envelope_off = request
+            .uri()
+            .query()
+            .unwrap_or("")
+            .split('&')
+            .any(|pair| pair.eq_ignore_ascii_case("envelope=false"));
+
+        if envelope_off || prefers_representation(request.headers()) {
+            ResponseMode::Raw
+        } else {
+            ResponseMode::Envelope
+        }
+    }
+}
+
+/// True when the client asked for the affected resource in the response
+/// (`Prefer: return=representation`, RFC 7240) instead of an empty body.
+pub(crate) fn prefers_representation(headers: &HeaderMap) -> bool {
+    headers
+        .get_all("prefer")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|preference| preference.trim().eq_ignore_ascii_case("return=representation"))
+}
+
+/// The collection an item, file or job read belongs to.
+fn raw_resource(method: &Method, path: &str) -> Option<(&'static str, &'static str)> {
+    if *method != Method::GET {
+        return None;
+    }
+    let rest = path.strip_prefix("/api/")?;
+    let rest = rest
+        .strip_prefix("v1/")
+        .or_else(|| rest.strip_prefix("v2/"))
+        .unwrap_or(rest);
+    let collection = rest.split('/').next()?;
+    RAW_RESOURCES.into_iter().find(|(name, _)| *name == collection)
+}
+
+/// Strips the envelope from item, file and job reads when the client asked
+/// for raw responses.
+pub async fn envelope_middleware(request: Request<Body>, next: Next) -> Response {
+    let Some((collection, singular)) = raw_resource(request.method(), request.uri().path()) else {
+        return next.run(request).await;
+    };
+    let mode = ResponseMode::of(&request);
+    let prefer = prefers_representation(request.headers());
+    let path = request.uri().path().to_string();
+
+    let mut response = next.run(request).await;
+    // Shared caches must not hand an enveloped body to a raw client.
+    response.headers_mut().append(header::VARY, HeaderValue::from_static("prefer"));
+    if mode == ResponseMode::Envelope || !is_json(response.headers()) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::warn!("Failed to read response body for raw mode: {}", e);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    let Ok(value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+
+    let body = if parts.status.is_success() {
+        let (resource, pagination) = unwrap_resource(value, collection, singular);
+        for (name, value) in pagination {
+            parts.headers.insert(name, value);
+        }
+        if prefer {
+            parts
+                .headers
+                .insert("preference-applied", HeaderValue::from_static("return=representation"));
+        }
+        resource
+    } else {
+        parts.headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        problem_details(parts.status, value, &path)
+    };
+
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(body.to_string()))
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/json"))
+}
+
+/// The resource inside a successful body, with the headers replacing a
+/// list's paging fields.
+fn unwrap_resource(value: Value, collection: &str, singular: &str) -> (Value, Vec<(HeaderName, HeaderValue)>) {
+    let data = match value {
+        Value::Object(mut envelope) if envelope.contains_key("success") && envelope.contains_key("data") => {
+            envelope.remove("data").unwrap_or(Value::Null)
+        }
+        other => other,
+    };
+
+    let Value::Object(mut fields) = data else {
+        return (data, Vec::new());
+    };
+    if fields.get(collection).is_some_and(Value::is_array) {
+        let list = fields.remove(collection).unwrap_or_default();
+        return (list, pagination_headers(&fields));
+    }
+    if fields.get(singular).is_some_and(Value::is_object) {
+        return (fields.remove(singular).unwrap_or_default(), Vec::new());
+    }
+    (Value::Object(fields), Vec::new())
+}
+
+fn pagination_headers(fields: &Map<String, Value>) -> Vec<(HeaderName, HeaderValue)> {
+    PAGINATION_HEADERS
+        .into_iter()
+        .filter_map(|(field, name)| {
+            let value = match fields.get(field)? {
+                Value::Number(number) => number.to_string(),
+                Value::Bool(flag) => flag.to_string(),
+                _ => return None,
+            };
+            Some((HeaderName::from_static(name), HeaderValue::from_str(&value).ok()?))
+        })
+        .collect()
+}
+
+/// RFC 9457 problem details for an error body, keeping any fields beyond
+/// the message and status as extension members.
+fn problem_details(status: StatusCode, value: Value, path: &str) -> Value {
+    let mut problem = Map::new();
+    problem.insert("type".to_string(), json!("about:blank"));
+    problem.insert("title".to_string(), json!(status.canonical_reason().unwrap_or("Error")));
+    problem.insert("status".to_string(), json!(status.as_u16()));
+
+    match value {
+        Value::Object(fields) => {
+            for (name, value) in fields {
+                match name.as_str() {
+                    "error" | "message" => {
+                        problem.entry("detail").or_insert(value);
+                    }
+                    "status" | "success" => {}
+                    "data" if value.is_null() => {}
+                    _ => {
+                        problem.insert(name, value);
+                    }
+                }
+            }
+        }
+        other => {
+            problem.insert("detail".to_string(), other);
+        }
+    }
+    problem.insert("instance".to_string(), json!(path));
+    Value::Object(problem)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_mode() {
+        let request = |uri: &str, prefer: Option<&str>| {
+            let mut builder = Request::builder().uri(uri);
+            if let Some(prefer) = prefer {
+                builder = builder.header("prefer", prefer);
+            }
+            builder.body(Body::empty()).unwrap()
+        };
+
+        assert_eq!(ResponseMode::of(&request("/api/items", None)), ResponseMode::Envelope);
+        assert_eq!(ResponseMode::of(&request("/api/items?envelope=true", None)), ResponseMode::Envelope);
+        assert_eq!(ResponseMode::of(&request("/api/items?page=2&envelope=false", None)), ResponseMode::Raw);
+        assert_eq!(
+            ResponseMode::of(&request("/api/items", Some("respond-async, return=representation"))),
+            ResponseMode::Raw
+        );
+        assert_eq!(ResponseMode::of(&request("/api/items", Some("return=minimal"))), ResponseMode::Envelope);
+    }
+
+    #[test]
+    fn test_raw_resource() {
+        assert_eq!(raw_resource(&Method::GET, "/api/v2/items/3"), Some(("items", "item")));
+        assert_eq!(raw_resource(&Method::GET, "/api/files/item/3"), Some(("files", "file")));
+        assert_eq!(raw_resource(&Method::GET, "/api/jobs"), Some(("jobs", "job")));
+        assert_eq!(raw_resource(&Method::POST, "/api/items"), None);
+        assert_eq!(raw_resource(&Method::GET, "/api/stats"), None);
+    }
+
+    #[test]
+    fn test_problem_details_keeps_extensions() {
+        let body = json!({"error": "Item 1 has changed", "status": 409, "current_version": 3});
+        let problem = problem_details(StatusCode::CONFLICT, body, "/api/items/1");
+
+        assert_eq!(problem["title"], "Conflict");
+        assert_eq!(problem["detail"], "Item 1 has changed");
+        assert_eq!(problem["status"], 409);
+        assert_eq!(problem["current_version"], 3);
+        assert_eq!(problem["instance"], "/api/items/1");
+    }
+}
@@ -35,7 +35,10 @@ pub fn with_cache(method_router: MethodRouter<AppState>) -> MethodRouter<AppStat
 }
 
 pub fn with_validation(method_router: MethodRouter<AppState>) -> MethodRouter<AppState> {
-    method_router.layer(axum_middleware::from_fn(crate::validation::middleware::validation_middleware))
+    method_router.layer(axum_middleware::from_fn_with_state(
+        AppState::default(),
+        crate::validation::middleware::validation_middleware,
+    ))
 }
 
 pub fn protected_routes(_config: &AppConfig) -> Router<AppState> {
@@ -62,7 +65,10 @@ pub fn apply_middleware_stack(
 ) -> Router<AppState> {
     let mut router = router;
 
-    router = router.layer(crate::middleware::cors::cors_layer_from_config(&config.cors));
+    router = router.layer(axum_middleware::from_fn_with_state(
+        crate::middleware::cors::CorsPolicy::from_config(&config.cors),
+        crate::middleware::cors::cors_middleware,
+    ));
 
     router = router.layer(axum_middleware::from_fn_with_state(
         state.clone(),
@@ -82,10 +88,19 @@ pub fn apply_middleware_stack(
         cache_middleware,
     ));
 
-    router = router.layer(axum_middleware::from_fn(crate::validation::middleware::validation_middleware));
+    router = router.layer(axum_middleware::from_fn_with_state(
+        state.clone(),
+        crate::validation::middleware::validation_middleware,
+    ));
 
     router = router.layer(axum_middleware::from_fn(
-        crate::middleware::logging::log_request_with_config(config.logging.clone())
+        crate::middleware::logging::log_request_with_config(
+            config.logging.clone(),
+            Some(crate::middleware::provenance::Provenance::new(
+                &config.server.instance_id,
+                state.item_service.data_source(),
+            )),
+        )
     ));
 
     router
@@ -0,0 +1,30 @@
+use crate::auth::AuthService;
+use crate::list_preferences::ListKind;
+use crate::middleware::auth::AuthUser;
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use tracing::warn;
+
+/// Leaves the signed-in caller's [`ListDefaults`](crate::list_preferences::ListDefaults)
+/// for the list being read in the request. Lists are served without them
+/// when they can't be looked up.
+///
+/// Must run after authentication.
+pub async fn list_preferences_middleware(State(auth_service): State<AuthService>, mut request: Request, next: Next) -> Response {
+    let kind = ListKind::of(request.method(), request.uri().path());
+    let user_id = request.extensions().get::<AuthUser>().map(|user| user.user_id);
+
+    if let (Some(kind), Some(user_id)) = (kind, user_id) {
+        match auth_service.list_preferences(user_id).await {
+            Ok(preferences) => {
+                request.extensions_mut().insert(preferences.for_list(kind).clone());
+            }
+            Err(e) => warn!("Serving {} without the list preferences of user {}: {}", kind.name(), user_id, e),
+        }
+    }
+
+    next.run(request).await
+}
@@ -0,0 +1,353 @@
+//! Load shedding when too many requests are in flight or latency climbs
+
+use crate::config::LoadSheddingConfig;
+use crate::metrics::{names, InFlightGauge, MetricsSink};
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use parking_lot::Mutex;
+use serde_json::json;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Fewer recent responses than this say too little about latency to shed on.
+const MIN_LATENCY_SAMPLES: usize = 20;
+
+/// How long a computed p99 latency is reused before recomputing it.
+const LATENCY_REFRESH: Duration = Duration::from_millis(250);
+
+/// A p99 latency and when it was computed.
+type CachedLatency = Option<(Instant, Option<u64>)>;
+
+/// Traffic classes, in the order they are shed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TrafficClass {
+    /// Reads other than authentication, health checks and admin endpoints.
+    Read,
+    /// Writes and authentication.
+    Write,
+    /// Health checks and admin endpoints, which are never shed.
+    Essential,
+}
+
+impl TrafficClass {
+    pub fn of(method: &Method, path: &str) -> Self {
+        let under = |prefix: &str| {
+            path == prefix
+                || path
+                    .strip_prefix(prefix)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        };
+
+        if ["/health", "/ready", "/live", "/api/health", "/api/admin"]
+            .into_iter()
+            .any(under)
+        {
+            TrafficClass::Essential
+        } else if under("/auth")
+            || !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+        {
+            TrafficClass::Write
+        } else {
+            TrafficClass::Read
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrafficClass::Read => "read",
+            TrafficClass::Write => "write",
+            TrafficClass::Essential => "essential",
+        }
+    }
+}
+
+/// Traffic currently shed: classes below this are rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum ShedLevel {
+    None = 0,
+    Reads = 1,
+    ReadsAndWrites = 2,
+}
+
+impl ShedLevel {
+    fn sheds(&self, class: TrafficClass) -> bool {
+        match self {
+            ShedLevel::None => false,
+            ShedLevel::Reads => class == TrafficClass::Read,
+            ShedLevel::ReadsAndWrites => class != TrafficClass::Essential,
+        }
+    }
+}
+
+/// Decides from the requests in flight and recent p99 latency whether a
+/// request is admitted. Latency is only known with the in-memory metrics
+/// backend; without it only the in-flight limits apply. With shedding
+/// disabled every request is admitted, but in-flight requests are still
+/// counted.
+#[derive(Clone)]
+pub struct LoadShedder {
+    config: Arc<LoadSheddingConfig>,
+    metrics: Arc<dyn MetricsSink>,
+    in_flight: InFlightGauge,
+    level: Arc<AtomicU8>,
+    p99_cache: Arc<Mutex<CachedLatency>>,
+}
+
+impl LoadShedder {
+    pub fn new(config: &LoadSheddingConfig, metrics: Arc<dyn MetricsSink>) -> Self {
+        Self {
+            config: Arc::new(config.clone()),
+            in_flight: InFlightGauge::new(names::IN_FLIGHT_REQUESTS, metrics.clone()),
+            metrics,
+            level: Arc::new(AtomicU8::new(ShedLevel::None as u8)),
+            p99_cache: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn p99_latency_ms(&self) -> Option<u64> {
+        let mut cache = self.p99_cache.lock();
+        match *cache {
+            Some((computed_at, p99)) if computed_at.elapsed() < LATENCY_REFRESH => p99,
+            _ => {
+                let window = Duration::from_secs(self.config.latency_window_seconds);
+                let p99 = self
+                    .metrics
+                    .in_memory()
+                    .and_then(|collector| collector.recent_latency_percentile(99.0, window, MIN_LATENCY_SAMPLES));
+                *cache = Some((Instant::now(), p99));
+                p99
+            }
+        }
+    }
+
+    /// The shedding level warranted by current load and the threshold that
+    /// triggered it.
+    fn assess(&self) -> (ShedLevel, Option<String>) {
+        let config = &self.config;
+        let in_flight = self.in_flight.get();
+        let p99 = self.p99_latency_ms().unwrap_or(0);
+
+        if in_flight >= config.write_in_flight_limit {
+            let reason = format!("{} requests in flight (write limit {})", in_flight, config.write_in_flight_limit);
+            (ShedLevel::ReadsAndWrites, Some(reason))
+        } else if p99 >= config.write_p99_latency_ms {
+            let reason = format!("p99 latency {}ms (write threshold {}ms)", p99, config.write_p99_latency_ms);
+            (ShedLevel::ReadsAndWrites, Some(reason))
+        } else if in_flight >= config.read_in_flight_limit {
+            let reason = format!("{} requests in flight (read limit {})", in_flight, config.read_in_flight_limit);
+            (ShedLevel::Reads, Some(reason))
+        } else if p99 >= config.read_p99_latency_ms {
+            let reason = format!("p99 latency {}ms (read threshold {}ms)", p99, config.read_p99_latency_ms);
+            (ShedLevel::Reads, Some(reason))
+        } else {
+            (ShedLevel::None, None)
+        }
+    }
+
+    /// Whether a request of `class` should be rejected right now. Changes of
+    /// shedding level are logged with the threshold that caused them.
+    pub fn should_shed(&self, class: TrafficClass) -> bool {
+        if !self.config.enable || class == TrafficClass::Essential {
+            return false;
+        }
+
+        let (level, reason) = self.assess();
+        let previous = self.level.swap(level as u8, Ordering::Relaxed);
+        if previous != level as u8 {
+            match (level, &reason) {
+                (ShedLevel::None, _) => tracing::info!("Load shedding stopped"),
+                (ShedLevel::Reads, Some(reason)) => tracing::warn!("Shedding API reads: {}", reason),
+                (_, reason) => tracing::warn!("Shedding reads and writes: {}", reason.as_deref().unwrap_or("overloaded")),
+            }
+        }
+
+        let shed = level.sheds(class);
+        if shed {
+            tracing::debug!(
+                class = class.as_str(),
+                threshold = reason.as_deref().unwrap_or_default(),
+                "Request shed"
+            );
+        }
+        shed
+    }
+}
+
+fn overloaded_response(path: &str, retry_after_seconds: u64) -> Response {
+    let body = json!({
+        "type": "about:blank",
+        "title": "Service Unavailable",
+        "status": 503,
+        "detail": "Server is overloaded; retry later",
+        "instance": path,
+    });
+
+    let mut response = (StatusCode::SERVICE_UNAVAILABLE, body.to_string()).into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/problem+json"));
+    headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after_seconds));
+    response
+}
+
+/// Rejects lower-priority requests with 503 and `Retry-After` while the
+/// server is overloaded, and counts admitted requests as in flight.
+pub async fn load_shedding_middleware(
+    State(shedder): State<LoadShedder>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let class = TrafficClass::of(request.method(), request.uri().path());
+
+    if shedder.should_shed(class) {
+        shedder.metrics.record_shed_request(class.as_str());
+        return overloaded_response(request.uri().path(), shedder.config.retry_after_seconds);
+    }
+
+    let _in_flight = shedder.in_flight.track();
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::MetricsCollector;
+    use axum::{routing::get, Router};
+    use tokio::sync::Semaphore;
+    use tower::ServiceExt;
+
+    const BACKEND_CONCURRENCY: usize = 4;
+    const BACKEND_WORK: Duration = Duration::from_millis(40);
+
+    fn config(enable: bool) -> LoadSheddingConfig {
+        LoadSheddingConfig {
+            enable,
+            read_in_flight_limit: 8,
+            write_in_flight_limit: 16,
+            ..LoadSheddingConfig::default()
+        }
+    }
+
+    /// A backend that serves a few requests at a time, like a saturated
+    /// database pool, so queued requests wait for each other.
+    fn app(shedder: LoadShedder) -> Router {
+        let backend = Arc::new(Semaphore::new(BACKEND_CONCURRENCY));
+        Router::new()
+            .route(
+                "/api/items",
+                get(move || {
+                    let backend = backend.clone();
+                    async move {
+                        let _permit = backend.acquire().await.unwrap();
+                        tokio::time::sleep(BACKEND_WORK).await;
+                        "items"
+                    }
+                }),
+            )
+            .route("/health", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(shedder, load_shedding_middleware))
+    }
+
+    /// Fires `requests` concurrent reads and returns each one's status and
+    /// latency, plus the status of a health check sent mid-burst.
+    async fn burst(app: Router, requests: usize) -> (Vec<(StatusCode, Duration)>, StatusCode) {
+        let tasks: Vec<_> = (0..requests)
+            .map(|_| {
+                let app = app.clone();
+                tokio::spawn(async move {
+                    let start = Instant::now();
+                    let response = app
+                        .oneshot(Request::get("/api/items").body(Body::empty()).unwrap())
+                        .await
+                        .unwrap();
+                    if response.status() == StatusCode::SERVICE_UNAVAILABLE {
+                        assert_eq!(response.headers()[header::RETRY_AFTER], "5");
+                    }
+                    (response.status(), start.elapsed())
+                })
+            })
+            .collect();
+
+        tokio::task::yield_now().await;
+        let health = app
+            .oneshot(Request::get("/health").body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status();
+
+        let mut results = Vec::new();
+        for task in tasks {
+            results.push(task.await.unwrap());
+        }
+        (results, health)
+    }
+
+    #[test]
+    fn test_traffic_classes() {
+        assert_eq!(TrafficClass::of(&Method::GET, "/api/items"), TrafficClass::Read);
+        assert_eq!(TrafficClass::of(&Method::POST, "/api/items"), TrafficClass::Write);
+        assert_eq!(TrafficClass::of(&Method::GET, "/auth/me"), TrafficClass::Write);
+        assert_eq!(TrafficClass::of(&Method::GET, "/health/database"), TrafficClass::Essential);
+        assert_eq!(TrafficClass::of(&Method::GET, "/ready"), TrafficClass::Essential);
+        assert_eq!(TrafficClass::of(&Method::DELETE, "/api/admin/users/3"), TrafficClass::Essential);
+        assert_eq!(TrafficClass::of(&Method::GET, "/api/healthy"), TrafficClass::Read);
+    }
+
+    #[test]
+    fn test_high_p99_sheds_reads_before_writes() {
+        let metrics = Arc::new(MetricsCollector::new());
+        let shedder = LoadShedder::new(&config(true), metrics.clone());
+        assert!(!shedder.should_shed(TrafficClass::Read));
+
+        for _ in 0..MIN_LATENCY_SAMPLES {
+            metrics.record_response("/api/items", Duration::from_millis(3000), 200);
+        }
+        *shedder.p99_cache.lock() = None;
+        assert!(shedder.should_shed(TrafficClass::Read));
+        assert!(!shedder.should_shed(TrafficClass::Write));
+
+        for _ in 0..MIN_LATENCY_SAMPLES {
+            metrics.record_response("/api/items", Duration::from_millis(8000), 200);
+        }
+        *shedder.p99_cache.lock() = None;
+        assert!(shedder.should_shed(TrafficClass::Write));
+        assert!(!shedder.should_shed(TrafficClass::Essential));
+    }
+
+    #[tokio::test]
+    async fn test_admitted_latency_stays_bounded_while_shedding() {
+        const REQUESTS: usize = 100;
+
+        let metrics = Arc::new(MetricsCollector::new());
+        let (results, health) = burst(app(LoadShedder::new(&config(true), metrics.clone())), REQUESTS).await;
+
+        let admitted: Vec<Duration> = results
+            .iter()
+            .filter(|(status, _)| *status == StatusCode::OK)
+            .map(|(_, latency)| *latency)
+            .collect();
+        let shed = results.iter().filter(|(status, _)| *status == StatusCode::SERVICE_UNAVAILABLE).count();
+        assert_eq!(admitted.len() + shed, REQUESTS);
+        assert!(admitted.len() >= 8 && shed > 0, "admitted {}, shed {}", admitted.len(), shed);
+        assert_eq!(health, StatusCode::OK);
+        assert_eq!(metrics.get_snapshot(0).shed_requests.get("read"), Some(&(shed as u64)));
+        assert_eq!(metrics.get_snapshot(0).in_flight_requests, 0);
+
+        // At most 8 requests queue for 4 backend slots: two rounds of work.
+        let bound = BACKEND_WORK * 2 + Duration::from_millis(150);
+        let worst_admitted = admitted.iter().max().copied().unwrap();
+        assert!(worst_admitted < bound, "admitted request took {:?}", worst_admitted);
+
+        // Without shedding every request queues behind the whole burst.
+        let (unshed, _) = burst(app(LoadShedder::new(&config(false), Arc::new(MetricsCollector::new()))), REQUESTS).await;
+        assert!(unshed.iter().all(|(status, _)| *status == StatusCode::OK));
+        let worst_unshed = unshed.iter().map(|(_, latency)| *latency).max().unwrap();
+        assert!(worst_unshed > worst_admitted * 3, "unshed {:?} vs shed {:?}", worst_unshed, worst_admitted);
+    }
+}
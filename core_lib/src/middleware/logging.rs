@@ -1,10 +1,12 @@
 //! Request logging middleware configuration
 
 use crate::config::LoggingConfig;
+use crate::database::QueryStatsSnapshot;
 use crate::middleware::auth::AuthUser;
+use crate::middleware::provenance::Provenance;
 use axum::{
     body::Body,
-    http::Request,
+    http::{HeaderValue, Request},
     middleware::Next,
     response::Response,
 };
@@ -13,67 +15,43 @@ use std::time::Instant;
 use tracing::{info, warn, error, info_span, Instrument};
 use uuid::Uuid;
 
+/// Logs each request and stamps its response with the request id, timing
+/// and, given `provenance`, the provenance headers.
 pub fn log_request_with_config(
     config: LoggingConfig,
+    provenance: Option<Provenance>,
 ) -> impl Fn(Request<Body>, Next) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Response, std::convert::Infallible>> + Send>> + Clone {
+    // Only the switches are needed per request, so the config itself is
+    // not cloned into every request future.
+    let LoggingConfig { include_request_id, include_user_info, include_timing, .. } = config;
+
     move |mut req: Request<Body>, next: Next| {
-        let config = config.clone();
+        let provenance = provenance.clone();
         Box::pin(async move {
-            let method = req.method().clone();
-            let uri = req.uri().clone();
-            let version = req.version();
-            let user_agent = req.headers()
-                .get("user-agent")
-                .and_then(|h| h.to_str().ok())
-                .unwrap_or("unknown")
-                .to_string();
-            
-            let request_id = if config.include_request_id {
-                let id = Uuid::new_v4().to_string();
-                req.headers_mut().insert(
-                    "x-request-id",
-                    id.parse().unwrap(),
-                );
-                Some(id)
-            } else {
-                None
-            };
-            
-            let user_info = if config.include_user_info {
-                req.extensions().get::<AuthUser>().map(|user| {
-                    (user.user_id, user.username.clone(), user.role.clone())
-                })
+            let request_id = include_request_id.then(|| {
+                let id = HeaderValue::from_str(&Uuid::new_v4().to_string()).unwrap();
+                req.headers_mut().insert("x-request-id", id.clone());
+                id
+            });
+
+            let user = if include_user_info {
+                req.extensions().get::<AuthUser>()
             } else {
                 None
             };
-            
-            let mut span_fields = vec![
-                ("method", method.to_string()),
-                ("uri", uri.to_string()),
-                ("version", format!("{:?}", version)),
-                ("user_agent", user_agent.clone()),
-            ];
-            
-            if let Some(ref req_id) = request_id {
-                span_fields.push(("request_id", req_id.clone()));
-            }
-            
-            if let Some((user_id, username, role)) = &user_info {
-                span_fields.push(("user_id", user_id.to_string()));
-                span_fields.push(("username", username.clone()));
-                span_fields.push(("user_role", format!("{:?}", role)));
-            }
-            
             let span = info_span!(
                 "http_request",
-                method = %method,
-                uri = %uri,
-                version = ?version,
-                user_agent = %user_agent,
-                request_id = request_id.as_deref().unwrap_or(""),
-                user_id = user_info.as_ref().map(|(id, _, _)| id.to_string()).unwrap_or_default(),
-                username = user_info.as_ref().map(|(_, name, _)| name.clone()).unwrap_or_default(),
-                user_role = user_info.as_ref().map(|(_, _, role)| format!("{:?}", role)).unwrap_or_default(),
+                method = %req.method(),
+                uri = %req.uri(),
+                version = ?req.version(),
+                user_agent = req.headers()
+                    .get("user-agent")
+                    .and_then(|h| h.to_str().ok())
+                    .unwrap_or("unknown"),
+                request_id = request_id.as_ref().and_then(|id| id.to_str().ok()).unwrap_or(""),
+                user_id = user.map(|user| user.user_id.to_string()).unwrap_or_default(),
+                username = user.map_or("", |user| user.username.as_str()),
+                user_role = user.map(|user| format!("{:?}", user.role)).unwrap_or_default(),
             );
             
             let start = Instant::now();
@@ -84,41 +62,50 @@ pub fn log_request_with_config(
             let status = response.status();
             
             if let Some(req_id) = request_id {
-                response.headers_mut().insert(
-                    "x-request-id",
-                    req_id.parse().unwrap(),
-                );
+                response.headers_mut().insert("x-request-id", req_id);
             }
             
-            if config.include_timing {
+            if include_timing {
                 response.headers_mut().insert(
                     "x-response-time",
                     format!("{}ms", latency.as_millis()).parse().unwrap(),
                 );
             }
+
+            if let Some(provenance) = &provenance {
+                provenance.apply(response.headers_mut(), latency);
+            }
             
+            let queries = response
+                .extensions()
+                .get::<QueryStatsSnapshot>()
+                .copied()
+                .unwrap_or_default();
+            let db_time_ms = queries.db_time.as_secs_f64() * 1000.0;
+
             span.in_scope(|| {
-                let _base_fields = [
-                    ("status", status.as_u16().to_string()),
-                    ("latency_ms", latency.as_millis().to_string()),
-                ];
-                
                 if status.is_success() {
                     info!(
                         status = status.as_u16(),
                         latency_ms = latency.as_millis(),
+                        db_queries = queries.queries,
+                        db_time_ms,
                         "request completed successfully"
                     );
                 } else if status.is_client_error() {
                     warn!(
                         status = status.as_u16(),
                         latency_ms = latency.as_millis(),
+                        db_queries = queries.queries,
+                        db_time_ms,
                         "client error"
                     );
                 } else if status.is_server_error() {
                     error!(
                         status = status.as_u16(),
                         latency_ms = latency.as_millis(),
+                        db_queries = queries.queries,
+                        db_time_ms,
                         "server error"
                     );
                 }
@@ -134,5 +121,5 @@ pub async fn log_request(
     next: Next,
 ) -> Result<Response, std::convert::Infallible> {
     let config = LoggingConfig::default();
-    log_request_with_config(config)(req, next).await
+    log_request_with_config(config, None)(req, next).await
 }
\ No newline at end of file
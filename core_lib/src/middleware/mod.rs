@@ -2,9 +2,20 @@
 
 pub mod auth;
 pub mod cache;
+pub mod capture;
+pub mod coalesce;
+pub mod concurrency;
 pub mod cors;
+pub mod csrf;
+pub mod envelope;
 pub mod integration;
+pub mod list_preferences;
+pub mod load_shed;
 pub mod logging;
+pub mod namespace;
 pub mod optional_auth;
+pub mod provenance;
 pub mod rate_limit;
-pub mod request_validation;
\ No newline at end of file
+pub mod rate_limit_store;
+pub mod request_validation;
+pub mod timeout;
\ No newline at end of file
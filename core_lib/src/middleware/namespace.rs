@@ -0,0 +1,165 @@
+use crate::audit::AuditEvent;
+use crate::error::AppError;
+use crate::middleware::auth::AuthUser;
+use crate::tenancy::{self, DEFAULT_NAMESPACE, NAMESPACE_HEADER};
+use crate::AppState;
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+
+/// Runs the rest of the request inside the caller's namespace: the tenant
+/// in their token, or the default namespace for anonymous callers and
+/// tokens without one. Admins may name another namespace in `X-Namespace`;
+/// each such request is audited. Anyone else naming a namespace other than
+/// their own is refused.
+///
+/// Must run after authentication.
+pub async fn namespace_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let requested = request
+        .headers()
+        .get(NAMESPACE_HEADER)
+        .map(|value| {
+            value
+                .to_str()
+                .map_err(|_| AppError::BadRequest("Invalid X-Namespace header".to_string()))
+        })
+        .transpose()?;
+
+    let namespace = resolve_namespace(
+        &state,
+        request.extensions().get::<AuthUser>(),
+        requested,
+        request.method().as_str(),
+        request.uri().path(),
+    )?;
+
+    Ok(tenancy::scope(namespace, next.run(request)).await)
+}
+
+/// The namespace a call to `method` `path` by `auth_user` runs in, given
+/// the namespace it asked for, if any. Overrides are audited.
+pub fn resolve_namespace(
+    state: &AppState,
+    auth_user: Option<&AuthUser>,
+    requested: Option<&str>,
+    method: &str,
+    path: &str,
+) -> Result<String, AppError> {
+    let own = auth_user.map_or(DEFAULT_NAMESPACE, |user| user.namespace.as_str());
+
+    match requested {
+        None => Ok(own.to_string()),
+        Some(requested) if requested == own => Ok(own.to_string()),
+        Some(requested) => {
+            tenancy::validate_namespace(requested)?;
+            let admin = auth_user
+                .filter(|user| user.is_admin())
+                .ok_or_else(|| AppError::Authorization("Only admins may act in another namespace".to_string()))?;
+
+            state.audit_log.record(
+                AuditEvent::new("tenant.namespace_override")
+                    .with_actor(admin.username.clone())
+                    .with_target(requested)
+                    .with_details(serde_json::json!({
+                        "own_namespace": own,
+                        "method": method,
+                        "path": path,
+                    })),
+            );
+            Ok(requested.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::models::UserRole;
+    use crate::test_support::test_app;
+    use crate::tenancy;
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{Request, StatusCode},
+        Router,
+    };
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    fn request(method: &str, token: &str, namespace: Option<&str>, body: Option<serde_json::Value>) -> Request<Body> {
+        let mut builder = Request::builder()
+            .method(method)
+            .uri("/api/items")
+            .header("user-agent", "namespace-tests")
+            .header("authorization", format!("Bearer {}", token));
+        if let Some(namespace) = namespace {
+            builder = builder.header("x-namespace", namespace);
+        }
+        let mut request = match body {
+            Some(body) => builder
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+            None => builder.body(Body::empty()).unwrap(),
+        };
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+        request
+    }
+
+    async fn item_names(router: &Router, token: &str, namespace: Option<&str>) -> Vec<String> {
+        let response = router.clone().oneshot(request("GET", token, namespace, None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        json["data"]["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["name"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_items_are_isolated_between_namespaces() {
+        let app = test_app().await;
+        let mut config = crate::AppConfig::default();
+        config.rate_limit.enable = false;
+        let router = crate::create_app_with_config(app.state.clone(), config);
+
+        let local = app.login_as("local", UserRole::User).await;
+        let tenant = tenancy::scope("acme".to_string(), app.login_as("acmeuser", UserRole::User)).await;
+        let admin = app.login_as("operator", UserRole::Admin).await;
+
+        for (token, name) in [(&local, "Local item"), (&tenant, "Acme item")] {
+            let create = request("POST", token, None, Some(serde_json::json!({ "name": name })));
+            let response = router.clone().oneshot(create).await.unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+
+        // The default namespace also holds the seeded sample items.
+        let local_names = item_names(&router, &local, None).await;
+        assert!(local_names.contains(&"Local item".to_string()));
+        assert!(!local_names.contains(&"Acme item".to_string()));
+        assert_eq!(item_names(&router, &tenant, None).await, vec!["Acme item"]);
+        assert_eq!(item_names(&router, &tenant, Some("acme")).await, vec!["Acme item"]);
+        assert!(app.state.audit_log.recent(Some("tenant.namespace_override"), 10).is_empty());
+
+        assert_eq!(item_names(&router, &admin, Some("acme")).await, vec!["Acme item"]);
+        let overrides = app.state.audit_log.recent(Some("tenant.namespace_override"), 10);
+        assert_eq!(overrides.len(), 1);
+        assert_eq!(overrides[0].actor.as_deref(), Some("operator"));
+        assert_eq!(overrides[0].target.as_deref(), Some("acme"));
+
+        let response = router.clone().oneshot(request("GET", &local, Some("acme"), None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = router.clone().oneshot(request("GET", &admin, Some("Not Valid"), None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,95 @@
+//! Headers saying where a response came from, for support and debugging
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use std::time::Duration;
+
+pub const DATA_SOURCE: HeaderName = HeaderName::from_static("x-data-source");
+pub const CACHE: HeaderName = HeaderName::from_static("x-cache");
+pub const SERVED_BY: HeaderName = HeaderName::from_static("x-served-by");
+pub const RESPONSE_TIME_MS: HeaderName = HeaderName::from_static("x-response-time-ms");
+
+/// How the response cache dealt with a request, as sent in `X-Cache`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// Served from the cache.
+    Hit,
+    /// Looked up and not found, so the handler produced the response.
+    Miss,
+    /// Served from the cache after it expired. Nothing serves stale
+    /// entries yet; the value is reserved so clients can rely on the set.
+    Stale,
+    /// Never looked up: not cacheable, or there is no cache.
+    Bypass,
+}
+
+impl CacheStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheStatus::Hit => "HIT",
+            CacheStatus::Miss => "MISS",
+            CacheStatus::Stale => "STALE",
+            CacheStatus::Bypass => "BYPASS",
+        }
+    }
+
+    pub fn header_value(&self) -> HeaderValue {
+        HeaderValue::from_static(self.as_str())
+    }
+}
+
+/// What the provenance headers report that is fixed for the server's
+/// lifetime. The request logging middleware adds the headers, as it already
+/// times every request; a layer of their own would cost dozens of
+/// allocations per request (see `tests/allocation_tests.rs`).
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    served_by: HeaderValue,
+    data_source: HeaderValue,
+}
+
+impl Provenance {
+    /// `instance_id` is checked to be a valid header value when the config
+    /// is validated; one that isn't is replaced by `unknown`.
+    pub fn new(instance_id: &str, data_source: &'static str) -> Self {
+        Self {
+            served_by: HeaderValue::from_str(instance_id).unwrap_or_else(|_| HeaderValue::from_static("unknown")),
+            data_source: HeaderValue::from_static(data_source),
+        }
+    }
+
+    /// Adds `X-Data-Source`, `X-Cache`, `X-Served-By` and
+    /// `X-Response-Time-Ms` to a response that took `elapsed`. `X-Cache` is
+    /// set by the cache layer, or by handlers keeping their own cache, and
+    /// is `BYPASS` when neither did.
+    pub fn apply(&self, headers: &mut HeaderMap, elapsed: Duration) {
+        headers.insert(DATA_SOURCE, self.data_source.clone());
+        headers.entry(CACHE).or_insert(CacheStatus::Bypass.header_value());
+        headers.insert(SERVED_BY, self.served_by.clone());
+        headers.insert(RESPONSE_TIME_MS, HeaderValue::from(elapsed.as_millis() as u64));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers_are_added() {
+        let mut headers = HeaderMap::new();
+        Provenance::new("api-1", "memory").apply(&mut headers, Duration::from_millis(12));
+
+        assert_eq!(headers[DATA_SOURCE], "memory");
+        assert_eq!(headers[CACHE], "BYPASS");
+        assert_eq!(headers[SERVED_BY], "api-1");
+        assert_eq!(headers[RESPONSE_TIME_MS], "12");
+    }
+
+    #[test]
+    fn test_cache_status_already_set_is_kept() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CACHE, CacheStatus::Hit.header_value());
+        Provenance::new("api-1", "database").apply(&mut headers, Duration::ZERO);
+
+        assert_eq!(headers.get_all(CACHE).iter().collect::<Vec<_>>(), ["HIT"]);
+    }
+}
@@ -1,12 +1,16 @@
 //! Rate limiting middleware
 
+use crate::clock::{SharedClock, SystemClock};
 use crate::config::RateLimitConfig;
 use crate::middleware::auth::AuthUser;
+use crate::middleware::rate_limit_store::RateLimitStore;
+use crate::net::IpCidr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use std::collections::HashMap;
 use std::net::IpAddr;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 use axum::{
     extract::{ConnectInfo, State},
     http::{Request, StatusCode},
@@ -18,104 +22,352 @@ use axum::{
 use serde_json::json;
 use std::net::SocketAddr;
 
+pub const DEFAULT_TIER: &str = "default";
+pub const USER_TIER: &str = "user";
+pub const ADMIN_TIER: &str = "admin";
+pub const EXEMPT_TIER: &str = "exempt";
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum RateLimitKey {
     Ip(IpAddr),
     User(i64),
+    /// Keyed by the configured key name, never the secret itself.
+    ApiKey(String),
+}
+
+impl RateLimitKey {
+    fn storage_key(&self) -> String {
+        match self {
+            RateLimitKey::Ip(ip) => format!("ip:{}", ip),
+            RateLimitKey::User(user_id) => format!("user:{}", user_id),
+            RateLimitKey::ApiKey(name) => format!("api_key:{}", name),
+        }
+    }
+
+    fn key_type(&self) -> &'static str {
+        match self {
+            RateLimitKey::Ip(_) => "ip",
+            RateLimitKey::User(_) => "user",
+            RateLimitKey::ApiKey(_) => "api_key",
+        }
+    }
+}
+
+/// Who a request is billed to, as decided by [`RateLimiter::resolve`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RateLimitSubject {
+    Exempt,
+    Limited { key: RateLimitKey, tier: String },
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitUsage {
+    pub tier: String,
+    pub limit: usize,
+    pub used: usize,
+    pub remaining: usize,
+}
+
+/// Exemptions and tier rates compiled from [`RateLimitConfig`]. Replaced as a
+/// whole when the configuration is reloaded.
+struct RateLimitPolicy {
+    config: RateLimitConfig,
+    exempt_networks: Vec<IpCidr>,
+    tier_limits: HashMap<String, usize>,
+    role_tiers: HashMap<String, String>,
+    api_keys: HashMap<String, (String, String)>,
+}
+
+impl RateLimitPolicy {
+    fn compile(config: RateLimitConfig) -> Self {
+        let exempt_networks = config
+            .exempt_cidrs
+            .iter()
+            .filter_map(|cidr| match cidr.parse() {
+                Ok(network) => Some(network),
+                Err(e) => {
+                    tracing::warn!("Ignoring invalid rate limit exempt CIDR '{}': {}", cidr, e);
+                    None
+                }
+            })
+            .collect();
+
+        let mut tier_limits = HashMap::from([
+            (DEFAULT_TIER.to_string(), config.requests_per_minute),
+            (USER_TIER.to_string(), config.user_requests_per_minute),
+            (ADMIN_TIER.to_string(), config.admin_requests_per_minute),
+        ]);
+        let mut role_tiers = HashMap::new();
+        for tier in &config.tiers {
+            tier_limits.insert(tier.name.clone(), tier.requests_per_minute);
+            for role in &tier.roles {
+                role_tiers.insert(role.to_lowercase(), tier.name.clone());
+            }
+        }
+
+        let api_keys = config
+            .api_keys
+            .iter()
+            .map(|api_key| (api_key.key.clone(), (api_key.name.clone(), api_key.tier.clone())))
+            .collect();
+
+        Self {
+            config,
+            exempt_networks,
+            tier_limits,
+            role_tiers,
+            api_keys,
+        }
+    }
+
+    fn limit_for_tier(&self, tier: &str) -> usize {
+        self.tier_limits
+            .get(tier)
+            .copied()
+            .unwrap_or(self.config.requests_per_minute)
+    }
+
+    fn is_exempt_path(&self, path: &str) -> bool {
+        self.config.exempt_paths.iter().any(|prefix| {
+            path == prefix
+                || path
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.starts_with('/') || prefix.ends_with('/'))
+        })
+    }
+}
+
+/// Per-key request limiter. Buckets live in process memory unless a shared
+/// [`RateLimitStore`] is attached, in which case every process using the same
+/// store enforces one combined limit. If the shared store errors or is slow,
+/// requests are limited locally until it recovers.
 #[derive(Clone)]
 pub struct RateLimiter {
     requests: Arc<Mutex<HashMap<RateLimitKey, Vec<Instant>>>>,
-    config: RateLimitConfig,
+    policy: Arc<RwLock<Arc<RateLimitPolicy>>>,
     window: Duration,
+    store: Option<Arc<dyn RateLimitStore>>,
+    degraded: Arc<AtomicBool>,
+    clock: SharedClock,
 }
 
 impl RateLimiter {
     pub fn new(config: RateLimitConfig) -> Self {
         Self {
             requests: Arc::new(Mutex::new(HashMap::new())),
-            config,
+            policy: Arc::new(RwLock::new(Arc::new(RateLimitPolicy::compile(config)))),
             window: Duration::from_secs(60),
+            store: None,
+            degraded: Arc::new(AtomicBool::new(false)),
+            clock: SystemClock::shared(),
         }
     }
 
-    pub fn check(&self, key: RateLimitKey) -> Result<(), RateLimitError> {
-        if !self.config.enable {
-            return Ok(());
+    /// A limiter of its own for one endpoint, allowing `requests_per_minute`
+    /// from each client address regardless of who the caller is.
+    pub fn per_address(requests_per_minute: usize) -> Self {
+        Self::new(RateLimitConfig {
+            requests_per_minute,
+            enable_user_based_limits: false,
+            exempt_paths: Vec::new(),
+            ..RateLimitConfig::default()
+        })
+    }
+
+    /// Clock the local windows are measured against. A shared store keeps
+    /// its own time.
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_store(mut self, store: Arc<dyn RateLimitStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    pub fn config(&self) -> RateLimitConfig {
+        self.policy().config.clone()
+    }
+
+    /// Applies new rates, exemptions and tier assignments to every clone of
+    /// this limiter. Existing buckets are kept; the storage backend is fixed
+    /// at startup.
+    pub fn update_config(&self, config: RateLimitConfig) {
+        *self.policy.write() = Arc::new(RateLimitPolicy::compile(config));
+    }
+
+    fn policy(&self) -> Arc<RateLimitPolicy> {
+        self.policy.read().clone()
+    }
+
+    pub fn backend_name(&self) -> &'static str {
+        self.store.as_ref().map_or("memory", |store| store.name())
+    }
+
+    /// True while the shared store is unavailable and limits are enforced
+    /// per process.
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    /// Decides whether a request is exempt and, if not, which bucket and tier
+    /// it counts against. A recognised API key takes precedence over the
+    /// authenticated user, who takes precedence over the client address.
+    pub fn resolve(
+        &self,
+        ip: IpAddr,
+        path: &str,
+        api_key: Option<&str>,
+        auth_user: Option<&AuthUser>,
+    ) -> RateLimitSubject {
+        let policy = self.policy();
+
+        if !policy.config.enable
+            || policy.is_exempt_path(path)
+            || policy.exempt_networks.iter().any(|network| network.contains(&ip))
+        {
+            return RateLimitSubject::Exempt;
+        }
+
+        if let Some((name, tier)) = api_key.and_then(|key| policy.api_keys.get(key)) {
+            return RateLimitSubject::Limited {
+                key: RateLimitKey::ApiKey(name.clone()),
+                tier: tier.clone(),
+            };
+        }
+
+        match auth_user {
+            Some(user) if policy.config.enable_user_based_limits => {
+                let role = user.role.to_string();
+                let tier = policy.role_tiers.get(&role).cloned().unwrap_or_else(|| {
+                    if user.is_admin() { ADMIN_TIER } else { USER_TIER }.to_string()
+                });
+                RateLimitSubject::Limited {
+                    key: RateLimitKey::User(user.user_id),
+                    tier,
+                }
+            }
+            _ => RateLimitSubject::Limited {
+                key: RateLimitKey::Ip(ip),
+                tier: DEFAULT_TIER.to_string(),
+            },
         }
+    }
+
+    /// Counts a request against `key` at the rate of `tier`, using the shared
+    /// store when one is configured and falling back to the local buckets
+    /// otherwise.
+    pub async fn acquire(&self, key: &RateLimitKey, tier: &str) -> Result<RateLimitUsage, RateLimitError> {
+        let policy = self.policy();
+        let limit = policy.limit_for_tier(tier);
+
+        if let Some(store) = &self.store {
+            let timeout = Duration::from_millis(policy.config.shared_store_timeout_ms);
+            let outcome = tokio::time::timeout(timeout, store.hit(&key.storage_key(), limit, self.window)).await;
+
+            match outcome {
+                Ok(Ok(hit)) => {
+                    if self.degraded.swap(false, Ordering::Relaxed) {
+                        tracing::info!("Rate limit store '{}' recovered; resuming shared limits", store.name());
+                    }
+
+                    if !hit.allowed {
+                        tracing::warn!("Rate limit exceeded for {:?} (tier {}): {} >= {}", key, tier, hit.used, limit);
+                        return Err(RateLimitError {
+                            retry_after_seconds: hit.reset_in.as_secs().max(1),
+                            limit,
+                            remaining: 0,
+                            key_type: key.key_type().to_string(),
+                            tier: tier.to_string(),
+                        });
+                    }
 
-        let max_requests = self.get_limit_for_key(&key);
-        let now = Instant::now();
+                    return Ok(RateLimitUsage {
+                        tier: tier.to_string(),
+                        limit,
+                        used: hit.used,
+                        remaining: limit.saturating_sub(hit.used),
+                    });
+                }
+                Ok(Err(e)) => self.mark_degraded(store.name(), &e.to_string()),
+                Err(_) => self.mark_degraded(store.name(), &format!("timed out after {}ms", timeout.as_millis())),
+            }
+        }
+
+        self.check(key, tier, limit)?;
+        let (used, remaining) = self.get_current_usage(key, limit);
+        Ok(RateLimitUsage {
+            tier: tier.to_string(),
+            limit,
+            used,
+            remaining,
+        })
+    }
+
+    fn mark_degraded(&self, store: &str, reason: &str) {
+        if !self.degraded.swap(true, Ordering::Relaxed) {
+            tracing::warn!("Rate limit store '{}' unavailable ({}); falling back to local limits", store, reason);
+        } else {
+            tracing::debug!("Rate limit store '{}' still unavailable: {}", store, reason);
+        }
+    }
+
+    fn check(&self, key: &RateLimitKey, tier: &str, max_requests: usize) -> Result<(), RateLimitError> {
+        let now = self.clock.instant();
         let mut requests = self.requests.lock();
-        
-        let entries = requests.entry(key.clone()).or_insert_with(Vec::new);
-        
+
+        let entries = requests.entry(key.clone()).or_default();
+
         entries.retain(|&instant| now.duration_since(instant) < self.window);
-        
-        tracing::debug!("Rate limit check: key={:?}, current_requests={}, max_requests={}", key, entries.len(), max_requests);
-        
+
+        tracing::debug!("Rate limit check: key={:?}, tier={}, current_requests={}, max_requests={}", key, tier, entries.len(), max_requests);
+
         if entries.len() >= max_requests {
             let oldest = entries.first().copied().unwrap_or(now);
             let reset_in = self.window.saturating_sub(now.duration_since(oldest));
-            
-            tracing::warn!("Rate limit exceeded for {:?}: {} >= {}", key, entries.len(), max_requests);
-            
+
+            tracing::warn!("Rate limit exceeded for {:?} (tier {}): {} >= {}", key, tier, entries.len(), max_requests);
+
             return Err(RateLimitError {
                 retry_after_seconds: reset_in.as_secs(),
                 limit: max_requests,
                 remaining: 0,
-                key_type: self.get_key_type(&key),
+                key_type: key.key_type().to_string(),
+                tier: tier.to_string(),
             });
         }
-        
+
         entries.push(now);
-        
+
         Ok(())
     }
 
-    pub fn get_current_usage(&self, key: &RateLimitKey) -> (usize, usize) {
-        let max_requests = self.get_limit_for_key(key);
-        let now = Instant::now();
+    fn get_current_usage(&self, key: &RateLimitKey, max_requests: usize) -> (usize, usize) {
+        let now = self.clock.instant();
         let mut requests = self.requests.lock();
-        
-        let entries = requests.entry(key.clone()).or_insert_with(Vec::new);
+
+        let entries = requests.entry(key.clone()).or_default();
         entries.retain(|&instant| now.duration_since(instant) < self.window);
-        
+
         let used = entries.len();
         let remaining = max_requests.saturating_sub(used);
-        
-        (used, remaining)
-    }
-
-    fn get_limit_for_key(&self, key: &RateLimitKey) -> usize {
-        match key {
-            RateLimitKey::Ip(_) => self.config.requests_per_minute,
-            RateLimitKey::User(_) => {
-                if self.config.enable_user_based_limits {
-                    self.config.user_requests_per_minute
-                } else {
-                    self.config.requests_per_minute
-                }
-            }
-        }
-    }
 
-    fn get_key_type(&self, key: &RateLimitKey) -> String {
-        match key {
-            RateLimitKey::Ip(_) => "ip".to_string(),
-            RateLimitKey::User(_) => "user".to_string(),
-        }
+        (used, remaining)
     }
 
-    pub fn cleanup_expired(&self) {
-        let now = Instant::now();
-        let mut requests = self.requests.lock();
-        
-        requests.retain(|_, entries| {
+    pub async fn cleanup_expired(&self) {
+        let now = self.clock.instant();
+        self.requests.lock().retain(|_, entries| {
             entries.retain(|&instant| now.duration_since(instant) < self.window);
             !entries.is_empty()
         });
+
+        if let Some(store) = &self.store {
+            if let Err(e) = store.cleanup_expired().await {
+                tracing::warn!("Failed to clean up rate limit store '{}': {}", store.name(), e);
+            }
+        }
     }
 }
 
@@ -125,6 +377,7 @@ pub struct RateLimitError {
     pub limit: usize,
     pub remaining: usize,
     pub key_type: String,
+    pub tier: String,
 }
 
 impl IntoResponse for RateLimitError {
@@ -136,10 +389,11 @@ impl IntoResponse for RateLimitError {
             "limit": self.limit,
             "remaining": self.remaining,
             "limit_type": self.key_type,
+            "tier": self.tier,
         }));
 
         let mut response = (StatusCode::TOO_MANY_REQUESTS, body).into_response();
-        
+
         response.headers_mut().insert(
             "x-ratelimit-limit",
             self.limit.to_string().parse().unwrap(),
@@ -148,11 +402,14 @@ impl IntoResponse for RateLimitError {
             "x-ratelimit-remaining",
             self.remaining.to_string().parse().unwrap(),
         );
+        if let Ok(tier) = self.tier.parse() {
+            response.headers_mut().insert("x-ratelimit-tier", tier);
+        }
         response.headers_mut().insert(
             "retry-after",
             self.retry_after_seconds.to_string().parse().unwrap(),
         );
-        
+
         response
     }
 }
@@ -164,48 +421,233 @@ pub async fn rate_limit_middleware(
     next: Next,
 ) -> Result<Response, RateLimitError> {
     let ip = addr.ip();
-    
+
     tracing::debug!("Rate limit middleware called for IP: {}", ip);
-    
-    let rate_limit_key = if limiter.config.enable_user_based_limits {
-        if let Some(auth_user) = request.extensions().get::<AuthUser>() {
-            RateLimitKey::User(auth_user.user_id)
-        } else {
-            RateLimitKey::Ip(ip)
+
+    // A batch is charged once per sub-request as each comes back through
+    // this middleware, not for the batch itself.
+    if request.uri().path() == crate::handlers::batch::BATCH_PATH {
+        return Ok(next.run(request).await);
+    }
+
+    let subject = limiter.resolve(
+        ip,
+        request.uri().path(),
+        request.headers().get("x-api-key").and_then(|value| value.to_str().ok()),
+        request.extensions().get::<AuthUser>(),
+    );
+
+    let (rate_limit_key, tier) = match subject {
+        RateLimitSubject::Exempt => {
+            let mut response = next.run(request).await;
+            response.headers_mut().insert("x-ratelimit-tier", EXEMPT_TIER.parse().unwrap());
+            return Ok(response);
         }
-    } else {
-        RateLimitKey::Ip(ip)
+        RateLimitSubject::Limited { key, tier } => (key, tier),
     };
-    
-    tracing::debug!("Using rate limit key: {:?}", rate_limit_key);
-    
-    if let Err(rate_limit_error) = limiter.check(rate_limit_key.clone()) {
-        tracing::warn!("Rate limit exceeded, returning 429");
-        return Err(rate_limit_error);
-    }
-    
-    let (used, remaining) = limiter.get_current_usage(&rate_limit_key);
-    let limit = limiter.get_limit_for_key(&rate_limit_key);
-    
+
+    tracing::debug!("Using rate limit key: {:?} (tier {})", rate_limit_key, tier);
+
+    let usage = match limiter.acquire(&rate_limit_key, &tier).await {
+        Ok(usage) => usage,
+        Err(rate_limit_error) => {
+            tracing::warn!("Rate limit exceeded, returning 429");
+            return Err(rate_limit_error);
+        }
+    };
+
     request.headers_mut().insert(
         "x-ratelimit-used",
-        used.to_string().parse().unwrap(),
+        usage.used.to_string().parse().unwrap(),
     );
-    
+
     let mut response = next.run(request).await;
-    
+
     response.headers_mut().insert(
         "x-ratelimit-limit",
-        limit.to_string().parse().unwrap(),
+        usage.limit.to_string().parse().unwrap(),
     );
     response.headers_mut().insert(
         "x-ratelimit-remaining",
-        remaining.to_string().parse().unwrap(),
+        usage.remaining.to_string().parse().unwrap(),
     );
     response.headers_mut().insert(
         "x-ratelimit-type",
-        limiter.get_key_type(&rate_limit_key).parse().unwrap(),
+        rate_limit_key.key_type().parse().unwrap(),
     );
-    
+    if let Ok(tier) = usage.tier.parse() {
+        response.headers_mut().insert("x-ratelimit-tier", tier);
+    }
+
     Ok(response)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::models::UserRole;
+    use crate::config::{ApiKeyTier, RateLimitTier};
+    use crate::error::{AppError, Result as AppResult};
+    use crate::middleware::rate_limit_store::StoreHit;
+
+    struct FailingStore;
+
+    #[async_trait::async_trait]
+    impl RateLimitStore for FailingStore {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        async fn hit(&self, _key: &str, _limit: usize, _window: Duration) -> AppResult<StoreHit> {
+            Err(AppError::Database("connection refused".to_string()))
+        }
+    }
+
+    fn limiter(requests_per_minute: usize) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            requests_per_minute,
+            ..RateLimitConfig::default()
+        })
+    }
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_acquire_limits_locally() {
+        let limiter = limiter(2);
+        let key = RateLimitKey::Ip(ip("203.0.113.7"));
+
+        let usage = limiter.acquire(&key, DEFAULT_TIER).await.unwrap();
+        assert_eq!((usage.limit, usage.used, usage.remaining), (2, 1, 1));
+        limiter.acquire(&key, DEFAULT_TIER).await.unwrap();
+        assert!(limiter.acquire(&key, DEFAULT_TIER).await.is_err());
+        assert_eq!(limiter.backend_name(), "memory");
+    }
+
+    #[tokio::test]
+    async fn test_local_window_follows_injected_clock() {
+        let clock = crate::clock::MockClock::default();
+        let limiter = limiter(1).with_clock(clock.shared());
+        let key = RateLimitKey::User(42);
+
+        limiter.acquire(&key, DEFAULT_TIER).await.unwrap();
+        clock.advance(Duration::from_secs(45));
+        let err = limiter.acquire(&key, DEFAULT_TIER).await.unwrap_err();
+        assert_eq!(err.retry_after_seconds, 15);
+
+        clock.advance(Duration::from_secs(15));
+        assert!(limiter.acquire(&key, DEFAULT_TIER).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_failing_store_degrades_to_local_limits() {
+        let limiter = limiter(2).with_store(Arc::new(FailingStore));
+        let key = RateLimitKey::Ip(ip("203.0.113.7"));
+
+        assert!(limiter.acquire(&key, DEFAULT_TIER).await.is_ok());
+        assert!(limiter.is_degraded());
+        assert!(limiter.acquire(&key, DEFAULT_TIER).await.is_ok());
+        assert!(limiter.acquire(&key, DEFAULT_TIER).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shared_store_is_combined_across_limiters() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let store: Arc<dyn RateLimitStore> = Arc::new(
+            crate::middleware::rate_limit_store::SqliteRateLimitStore::new(pool).await.unwrap(),
+        );
+        let first = limiter(2).with_store(store.clone());
+        let second = limiter(2).with_store(store);
+        let key = RateLimitKey::Ip(ip("198.51.100.4"));
+
+        first.acquire(&key, DEFAULT_TIER).await.unwrap();
+        second.acquire(&key, DEFAULT_TIER).await.unwrap();
+        assert!(first.acquire(&key, DEFAULT_TIER).await.is_err());
+        assert!(second.acquire(&key, DEFAULT_TIER).await.is_err());
+        assert!(!first.is_degraded());
+        assert_eq!(first.backend_name(), "sqlite");
+    }
+
+    #[test]
+    fn test_resolve_exemptions_and_tiers() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            exempt_cidrs: vec!["10.0.0.0/8".to_string()],
+            exempt_paths: vec!["/health".to_string(), "/api/metrics".to_string()],
+            tiers: vec![RateLimitTier {
+                name: "partner".to_string(),
+                requests_per_minute: 5000,
+                roles: vec!["readonly".to_string()],
+            }],
+            api_keys: vec![ApiKeyTier {
+                name: "acme".to_string(),
+                key: "secret-key".to_string(),
+                tier: "partner".to_string(),
+            }],
+            ..RateLimitConfig::default()
+        });
+        let client = ip("203.0.113.7");
+        let user = AuthUser::new(1, "alice".to_string(), UserRole::User);
+        let reader = AuthUser::new(2, "bob".to_string(), UserRole::ReadOnly);
+        let admin = AuthUser::new(3, "root".to_string(), UserRole::Admin);
+
+        assert_eq!(limiter.resolve(ip("10.2.3.4"), "/api/items", None, None), RateLimitSubject::Exempt);
+        assert_eq!(limiter.resolve(client, "/health/ready", None, None), RateLimitSubject::Exempt);
+        assert_eq!(limiter.resolve(client, "/api/metrics", None, None), RateLimitSubject::Exempt);
+        assert!(matches!(
+            limiter.resolve(client, "/healthz", None, None),
+            RateLimitSubject::Limited { .. }
+        ));
+
+        let tier_of = |api_key: Option<&str>, user: Option<&AuthUser>| match limiter.resolve(client, "/api/items", api_key, user) {
+            RateLimitSubject::Limited { key, tier } => (key, tier),
+            RateLimitSubject::Exempt => panic!("unexpected exemption"),
+        };
+
+        assert_eq!(tier_of(None, None), (RateLimitKey::Ip(client), DEFAULT_TIER.to_string()));
+        assert_eq!(tier_of(Some("wrong"), None), (RateLimitKey::Ip(client), DEFAULT_TIER.to_string()));
+        assert_eq!(tier_of(Some("secret-key"), Some(&user)), (RateLimitKey::ApiKey("acme".to_string()), "partner".to_string()));
+        assert_eq!(tier_of(None, Some(&user)), (RateLimitKey::User(1), USER_TIER.to_string()));
+        assert_eq!(tier_of(None, Some(&reader)), (RateLimitKey::User(2), "partner".to_string()));
+        assert_eq!(tier_of(None, Some(&admin)), (RateLimitKey::User(3), ADMIN_TIER.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_update_config_changes_tier_assignment() {
+        let limiter = limiter(60);
+        let client = ip("203.0.113.7");
+        assert!(matches!(
+            limiter.resolve(client, "/api/items", Some("new-key"), None),
+            RateLimitSubject::Limited { key: RateLimitKey::Ip(_), .. }
+        ));
+
+        limiter.update_config(RateLimitConfig {
+            tiers: vec![RateLimitTier {
+                name: "partner".to_string(),
+                requests_per_minute: 1,
+                roles: Vec::new(),
+            }],
+            api_keys: vec![ApiKeyTier {
+                name: "acme".to_string(),
+                key: "new-key".to_string(),
+                tier: "partner".to_string(),
+            }],
+            ..RateLimitConfig::default()
+        });
+
+        let key = RateLimitKey::ApiKey("acme".to_string());
+        assert_eq!(
+            limiter.resolve(client, "/api/items", Some("new-key"), None),
+            RateLimitSubject::Limited { key: key.clone(), tier: "partner".to_string() }
+        );
+        let usage = limiter.acquire(&key, "partner").await.unwrap();
+        assert_eq!((usage.tier.as_str(), usage.limit), ("partner", 1));
+        let error = limiter.acquire(&key, "partner").await.unwrap_err();
+        assert_eq!(error.tier, "partner");
+    }
+}
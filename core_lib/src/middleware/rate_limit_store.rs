@@ -0,0 +1,199 @@
+//! Shared storage backends for rate limit buckets
+
+use crate::error::Result;
+use async_trait::async_trait;
+use sqlx::{sqlite::SqlitePoolOptions, Row, SqlitePool};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Outcome of counting one request against a shared bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreHit {
+    pub allowed: bool,
+    /// Requests counted in the current window, including this one if allowed.
+    pub used: usize,
+    pub reset_in: Duration,
+}
+
+/// Bucket storage shared between server processes. Implementations count
+/// requests in fixed windows and must make the check-and-increment atomic,
+/// since several processes update the same bucket concurrently.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn hit(&self, key: &str, limit: usize, window: Duration) -> Result<StoreHit>;
+
+    /// Removes expired buckets. Backends whose keys expire natively keep the
+    /// default no-op.
+    async fn cleanup_expired(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Start of the fixed window containing `now` and the time left in it.
+fn current_window(now: Duration, window: Duration) -> (u64, Duration) {
+    let window_secs = window.as_secs().max(1);
+    let start = now.as_secs() / window_secs * window_secs;
+    let reset_in = Duration::from_secs(start + window_secs).saturating_sub(now);
+    (start, reset_in)
+}
+
+fn unix_now() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+/// Stores buckets in a SQLite table so that several processes on one host
+/// share their limits and counters survive restarts.
+#[derive(Clone)]
+pub struct SqliteRateLimitStore {
+    pool: SqlitePool,
+}
+
+impl SqliteRateLimitStore {
+    pub async fn connect(database_url: &str) -> Result<Self> {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(4)
+            .acquire_timeout(Duration::from_secs(5))
+            .connect(database_url)
+            .await?;
+
+        sqlx::query("PRAGMA journal_mode = WAL").execute(&pool).await?;
+        sqlx::query("PRAGMA busy_timeout = 1000").execute(&pool).await?;
+
+        Self::new(pool).await
+    }
+
+    pub async fn new(pool: SqlitePool) -> Result<Self> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS rate_limit_buckets (
+                bucket_key TEXT NOT NULL,
+                window_start INTEGER NOT NULL,
+                count INTEGER NOT NULL,
+                expires_at INTEGER NOT NULL,
+                PRIMARY KEY (bucket_key, window_start)
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_rate_limit_buckets_expires_at ON rate_limit_buckets(expires_at)")
+            .execute(&pool)
+            .await?;
+
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for SqliteRateLimitStore {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn hit(&self, key: &str, limit: usize, window: Duration) -> Result<StoreHit> {
+        let (window_start, reset_in) = current_window(unix_now(), window);
+        let expires_at = window_start + window.as_secs().max(1);
+
+        // The conditional upsert increments only while the bucket is under the
+        // limit, so a rejected request neither counts nor races another process.
+        let row = sqlx::query(
+            r#"
+            INSERT INTO rate_limit_buckets (bucket_key, window_start, count, expires_at)
+            VALUES (?, ?, 1, ?)
+            ON CONFLICT (bucket_key, window_start)
+            DO UPDATE SET count = count + 1 WHERE count < ?
+            RETURNING count
+            "#,
+        )
+        .bind(key)
+        .bind(window_start as i64)
+        .bind(expires_at as i64)
+        .bind(limit as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let (allowed, used) = match row {
+            Some(row) => (true, row.try_get::<i64, _>("count")?),
+            None => (false, limit as i64),
+        };
+
+        Ok(StoreHit {
+            allowed: allowed && limit > 0,
+            used: used.max(0) as usize,
+            reset_in,
+        })
+    }
+
+    async fn cleanup_expired(&self) -> Result<()> {
+        let now = unix_now().as_secs() as i64;
+        sqlx::query("DELETE FROM rate_limit_buckets WHERE expires_at <= ?")
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn memory_store() -> SqliteRateLimitStore {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        SqliteRateLimitStore::new(pool).await.unwrap()
+    }
+
+    #[test]
+    fn test_current_window() {
+        let window = Duration::from_secs(60);
+        let (start, reset_in) = current_window(Duration::from_secs(125), window);
+        assert_eq!(start, 120);
+        assert_eq!(reset_in, Duration::from_secs(55));
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store_enforces_limit() {
+        let store = memory_store().await;
+        let window = Duration::from_secs(60);
+
+        let first = store.hit("ip:203.0.113.7", 2, window).await.unwrap();
+        assert!(first.allowed);
+        assert_eq!(first.used, 1);
+
+        let second = store.hit("ip:203.0.113.7", 2, window).await.unwrap();
+        assert!(second.allowed);
+        assert_eq!(second.used, 2);
+
+        let third = store.hit("ip:203.0.113.7", 2, window).await.unwrap();
+        assert!(!third.allowed);
+        assert_eq!(third.used, 2);
+
+        let other = store.hit("user:1", 2, window).await.unwrap();
+        assert!(other.allowed);
+        assert_eq!(other.used, 1);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store_cleanup_removes_expired() {
+        let store = memory_store().await;
+        sqlx::query("INSERT INTO rate_limit_buckets VALUES ('ip:old', 0, 5, 60)")
+            .execute(&store.pool)
+            .await
+            .unwrap();
+        store.hit("ip:new", 10, Duration::from_secs(60)).await.unwrap();
+
+        store.cleanup_expired().await.unwrap();
+
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM rate_limit_buckets")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 1);
+    }
+}
@@ -1,4 +1,7 @@
-//! Request validation middleware for content type and size limits
+//! Request validation middleware for body size limits
+//!
+//! Media types are checked by
+//! [`validation_middleware`](crate::validation::middleware::validation_middleware).
 
 use axum::{
     body::Body,
@@ -19,40 +22,12 @@ pub async fn request_validation_middleware(
 ) -> Result<Response, Infallible> {
     let (parts, body) = request.into_parts();
     
-    if matches!(parts.method.as_str(), "POST" | "PUT" | "PATCH") {
-        let path = parts.uri.path();
-        let has_body = parts.headers.get("content-length")
-            .and_then(|cl| cl.to_str().ok())
-            .and_then(|cl| cl.parse::<usize>().ok())
-            .map(|len| len > 0)
-            .unwrap_or(false);
-        
-        let skip_validation = path.ends_with("/cleanup") || path.ends_with("/logout") || path.ends_with("/retry") || path.ends_with("/clear") || (!has_body && !path.contains("/items") && !path.contains("/register") && !path.contains("/login"));
-        
-        if !skip_validation {
-            if let Some(content_type) = parts.headers.get("content-type") {
-                let content_type_str = content_type.to_str().unwrap_or("");
-                
-                if !content_type_str.starts_with("application/json") 
-                    && !content_type_str.starts_with("application/x-www-form-urlencoded")
-                    && !content_type_str.starts_with("multipart/form-data") {
-                    
-                    let error_response = Json(json!({
-                        "error": "Unsupported content type. Expected application/json, application/x-www-form-urlencoded, or multipart/form-data",
-                        "status": 415
-                    }));
-                    
-                    return Ok((StatusCode::UNSUPPORTED_MEDIA_TYPE, error_response).into_response());
-                }
-            } else if has_body {
-                let error_response = Json(json!({
-                    "error": "Missing Content-Type header. Expected application/json, application/x-www-form-urlencoded, or multipart/form-data",
-                    "status": 415
-                }));
-                
-                return Ok((StatusCode::UNSUPPORTED_MEDIA_TYPE, error_response).into_response());
-            }
-        }
+    // Snapshot archives and user bundles go far beyond the JSON body limit;
+    // their import handlers enforce `snapshots.max_archive_size_mb` themselves.
+    let path = parts.uri.path();
+    if path == crate::handlers::admin::SNAPSHOT_IMPORT_PATH || path == crate::handlers::admin::USER_IMPORT_PATH {
+        let request = Request::from_parts(parts, body);
+        return Ok(next.run(request).await);
     }
     
     if let Some(content_length) = parts.headers.get("content-length") {
@@ -86,7 +61,11 @@ pub async fn security_headers_middleware(
     headers.insert("X-Frame-Options", "DENY".parse().unwrap());
     headers.insert("X-XSS-Protection", "1; mode=block".parse().unwrap());
     headers.insert("Referrer-Policy", "strict-origin-when-cross-origin".parse().unwrap());
-    headers.insert("Content-Security-Policy", "default-src 'self'; script-src 'self' 'unsafe-inline' https://cdn.jsdelivr.net; style-src 'self' 'unsafe-inline'".parse().unwrap());
+    // Pages that need no inline code, like the admin page, set a stricter
+    // policy of their own.
+    if !headers.contains_key("Content-Security-Policy") {
+        headers.insert("Content-Security-Policy", "default-src 'self'; script-src 'self' 'unsafe-inline' https://cdn.jsdelivr.net; style-src 'self' 'unsafe-inline'".parse().unwrap());
+    }
     
     headers.insert("X-API-Version", "1.0".parse().unwrap());
     headers.insert("API-Version", "1.0".parse().unwrap());
@@ -0,0 +1,288 @@
+//! Request timeouts and slow-request logging
+
+use crate::config::{ServerConfig, TimeoutConfig};
+use crate::metrics::MetricsSink;
+use crate::middleware::auth::AuthUser;
+use axum::{
+    body::{Body, HttpBody},
+    extract::{MatchedPath, State},
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::stream::{self, StreamExt};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Handler budgets resolved from [`ServerConfig`] and [`TimeoutConfig`].
+/// A budget of zero disables the timeout for that route.
+#[derive(Clone)]
+pub struct RequestTimeouts {
+    default_timeout: Duration,
+    routes: Arc<Vec<(String, Duration)>>,
+    slow_threshold: Duration,
+    stream_idle_timeout: Duration,
+    metrics: Arc<dyn MetricsSink>,
+}
+
+impl RequestTimeouts {
+    pub fn new(server: &ServerConfig, config: &TimeoutConfig, metrics: Arc<dyn MetricsSink>) -> Self {
+        let mut routes: Vec<(String, Duration)> = config
+            .routes
+            .iter()
+            .map(|route| (route.path.clone(), Duration::from_secs(route.timeout_seconds)))
+            .collect();
+        routes.sort_by_key(|(path, _)| std::cmp::Reverse(path.len()));
+
+        Self {
+            default_timeout: Duration::from_secs(server.request_timeout_seconds),
+            routes: Arc::new(routes),
+            slow_threshold: Duration::from_millis(config.slow_request_threshold_ms),
+            stream_idle_timeout: Duration::from_secs(config.stream_idle_timeout_seconds),
+            metrics,
+        }
+    }
+
+    pub fn timeout_for(&self, path: &str) -> Duration {
+        self.routes
+            .iter()
+            .find(|(prefix, _)| {
+                path == prefix
+                    || path
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+            .map_or(self.default_timeout, |(_, timeout)| *timeout)
+    }
+}
+
+/// WebSocket upgrades and server-sent event subscriptions live far longer
+/// than any handler budget.
+pub(crate) fn is_streaming_request(request: &Request<Body>) -> bool {
+    let is_upgrade = request.headers().contains_key(header::UPGRADE);
+    let wants_event_stream = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/event-stream"));
+
+    is_upgrade || wants_event_stream
+}
+
+pub(crate) fn timeout_response(path: &str, timeout: Duration) -> Response {
+    let body = json!({
+        "type": "about:blank",
+        "title": "Gateway Timeout",
+        "status": 504,
+        "detail": format!("Request did not complete within {} seconds", timeout.as_secs()),
+        "instance": path,
+    });
+
+    let mut response = (StatusCode::GATEWAY_TIMEOUT, body.to_string()).into_response();
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/problem+json"),
+    );
+    response
+}
+
+/// Ends a streaming body that goes `idle` without producing a frame.
+fn with_idle_timeout(body: Body, idle: Duration) -> Body {
+    let frames = body.into_data_stream();
+    let guarded = stream::unfold(Some(frames), move |frames| async move {
+        let mut frames = frames?;
+        match tokio::time::timeout(idle, frames.next()).await {
+            Ok(Some(chunk)) => Some((chunk, Some(frames))),
+            Ok(None) => None,
+            Err(_) => {
+                tracing::warn!("Closing streaming response idle for more than {:?}", idle);
+                Some((
+                    Err(axum::Error::new(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "stream idle timeout",
+                    ))),
+                    None,
+                ))
+            }
+        }
+    });
+
+    Body::from_stream(guarded)
+}
+
+/// Aborts handlers that exceed their route's budget with a 504 problem
+/// response, logs requests slower than the configured threshold, and applies
+/// an idle timeout to streaming responses instead of a total one.
+pub async fn request_timeout_middleware(
+    State(timeouts): State<RequestTimeouts>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched| matched.as_str().to_string())
+        .unwrap_or_else(|| path.clone());
+    let user = request
+        .extensions()
+        .get::<AuthUser>()
+        .map(|user| user.username.clone());
+
+    let streaming = is_streaming_request(&request);
+    let timeout = if streaming { Duration::ZERO } else { timeouts.timeout_for(&path) };
+    let start = Instant::now();
+
+    let response = if timeout.is_zero() {
+        next.run(request).await
+    } else {
+        match tokio::time::timeout(timeout, next.run(request)).await {
+            Ok(response) => response,
+            Err(_) => {
+                timeouts.metrics.record_timeout();
+                tracing::warn!(
+                    method = %method,
+                    route = %route,
+                    user = user.as_deref().unwrap_or("anonymous"),
+                    timeout_seconds = timeout.as_secs(),
+                    "Request timed out"
+                );
+                return timeout_response(&path, timeout);
+            }
+        }
+    };
+
+    let elapsed = start.elapsed();
+    if !timeouts.slow_threshold.is_zero() && elapsed >= timeouts.slow_threshold {
+        timeouts.metrics.record_slow_request();
+        tracing::warn!(
+            method = %method,
+            route = %route,
+            user = user.as_deref().unwrap_or("anonymous"),
+            status = response.status().as_u16(),
+            duration_ms = elapsed.as_millis() as u64,
+            "Slow request"
+        );
+    }
+
+    let is_streaming_body = response.body().size_hint().exact().is_none();
+    if is_streaming_body && !timeouts.stream_idle_timeout.is_zero() {
+        let (parts, body) = response.into_parts();
+        return Response::from_parts(parts, with_idle_timeout(body, timeouts.stream_idle_timeout));
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RouteTimeout;
+    use crate::metrics::MetricsCollector;
+    use axum::{routing::get, Router};
+    use tower::ServiceExt;
+
+    fn timeouts(default_seconds: u64, routes: Vec<RouteTimeout>) -> RequestTimeouts {
+        let server = ServerConfig {
+            request_timeout_seconds: default_seconds,
+            ..ServerConfig::default()
+        };
+        let config = TimeoutConfig {
+            slow_request_threshold_ms: 50,
+            stream_idle_timeout_seconds: 1,
+            routes,
+        };
+        RequestTimeouts::new(&server, &config, Arc::new(MetricsCollector::new()))
+    }
+
+    fn app(timeouts: RequestTimeouts) -> Router {
+        Router::new()
+            .route("/fast", get(|| async { "ok" }))
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_millis(80)).await;
+                    "done"
+                }),
+            )
+            .route(
+                "/stuck",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(30)).await;
+                    "never"
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(timeouts, request_timeout_middleware))
+    }
+
+    #[test]
+    fn test_timeout_for_uses_longest_prefix() {
+        let timeouts = timeouts(
+            30,
+            vec![
+                RouteTimeout { path: "/api".to_string(), timeout_seconds: 10 },
+                RouteTimeout { path: "/api/items/export".to_string(), timeout_seconds: 120 },
+            ],
+        );
+
+        assert_eq!(timeouts.timeout_for("/api/items/export"), Duration::from_secs(120));
+        assert_eq!(timeouts.timeout_for("/api/items"), Duration::from_secs(10));
+        assert_eq!(timeouts.timeout_for("/apiary"), Duration::from_secs(30));
+        assert_eq!(timeouts.timeout_for("/health"), Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn test_stuck_handler_returns_problem_json() {
+        let timeouts = timeouts(30, vec![RouteTimeout { path: "/stuck".to_string(), timeout_seconds: 1 }]);
+        let metrics = timeouts.metrics.clone();
+
+        let response = app(timeouts)
+            .oneshot(Request::get("/stuck").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/problem+json");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["status"], 504);
+        assert_eq!(problem["instance"], "/stuck");
+        assert_eq!(metrics.snapshot().unwrap().timed_out_requests, 1);
+    }
+
+    #[tokio::test]
+    async fn test_slow_requests_are_counted() {
+        let timeouts = timeouts(30, Vec::new());
+        let metrics = timeouts.metrics.clone();
+        let app = app(timeouts);
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/fast").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(metrics.snapshot().unwrap().slow_requests, 0);
+
+        let response = app
+            .oneshot(Request::get("/slow").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(metrics.snapshot().unwrap().slow_requests, 1);
+    }
+
+    #[tokio::test]
+    async fn test_streaming_body_gets_idle_timeout() {
+        let stalled = stream::once(async { Ok::<_, std::io::Error>("first") })
+            .chain(stream::pending());
+        let body = with_idle_timeout(Body::from_stream(stalled), Duration::from_millis(50));
+
+        let mut frames = body.into_data_stream();
+        assert_eq!(frames.next().await.unwrap().unwrap(), "first");
+        assert!(frames.next().await.unwrap().is_err());
+        assert!(frames.next().await.is_none());
+    }
+}
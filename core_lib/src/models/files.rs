@@ -44,6 +44,10 @@ impl ContextValidatable for FileUploadRequest {
             result.add_error("filename", "Filename contains null bytes");
         }
 
+        if crate::validation::unicode::contains_bidi_control(&self.filename) {
+            result.add_error("filename", "Filename contains bidirectional control characters");
+        }
+
         if !self.is_allowed_content_type() {
             result.add_error("content_type", "Content type not allowed");
         }
@@ -62,7 +66,7 @@ impl ContextValidatable for FileUploadRequest {
                 if tag.is_empty() {
                     result.add_error(&format!("tags[{}]", i), "Tag cannot be empty");
                 }
-                if tag.len() > 50 {
+                if crate::validation::unicode::text_length(tag) > 50 {
                     result.add_error(&format!("tags[{}]", i), "Tag is too long");
                 }
                 if let Err(_) = crate::validation::rules::validate_no_xss(tag) {
@@ -194,7 +198,7 @@ impl ContextValidatable for FileUpdateRequest {
                 if tag.is_empty() {
                     result.add_error(&format!("tags[{}]", i), "Tag cannot be empty");
                 }
-                if tag.len() > 50 {
+                if crate::validation::unicode::text_length(tag) > 50 {
                     result.add_error(&format!("tags[{}]", i), "Tag is too long");
                 }
                 if let Err(_) = crate::validation::rules::validate_no_xss(tag) {
@@ -1,6 +1,8 @@
 //! Item-related models with validation
 
-use crate::validation::{ValidationResult, ValidationContext, ContextValidatable, Validatable};
+use crate::database::{SortSpec, ITEM_SORT};
+use crate::extractors::QueryParams;
+use crate::validation::{ValidationResult, ValidationContext, ContextValidatable, Validatable, Sanitizable, SecurityValidator, ValidationError, unicode};
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 use chrono::{DateTime, Utc};
@@ -18,70 +20,72 @@ pub struct CreateItemRequest {
     pub tags: Option<Vec<String>>,
 
     pub metadata: Option<serde_json::Value>,
+
+    /// Version of the item the client last read. A `PUT` carrying it is
+    /// rejected when the item has changed since; ignored on create.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
 }
 
 impl ContextValidatable for CreateItemRequest {
-    fn validate_with_context(&self, _context: &ValidationContext) -> ValidationResult {
+    fn validate_with_context(&self, context: &ValidationContext) -> ValidationResult {
         let mut result = self.validate_comprehensive();
         
         if let Some(tags) = &self.tags {
             for (i, tag) in tags.iter().enumerate() {
+                let key = format!("tags[{}]", i);
+
                 if tag.is_empty() {
-                    result.add_error(&format!("tags[{}]", i), "Tag cannot be empty");
+                    result.add_error(&key, "Tag cannot be empty");
                 }
 
-                if tag.len() > 50 {
-                    result.add_error(&format!("tags[{}]", i), "Tag must not exceed 50 characters");
+                if unicode::text_length(tag) > 50 {
+                    result.add_error(&key, "Tag must not exceed 50 characters");
                 }
 
-                if let Err(_) = crate::validation::rules::validate_no_sql_injection(tag) {
-                    result.add_error(&format!("tags[{}]", i), "Tag contains invalid characters");
-                }
-
-                if let Err(_) = crate::validation::rules::validate_no_xss(tag) {
-                    result.add_error(&format!("tags[{}]", i), "Tag contains invalid characters");
-                }
+                SecurityValidator::validate_field(&mut result, context, "item.tags", &key, tag, "Tag contains invalid characters");
             }
         }
 
         if let Some(metadata) = &self.metadata {
-            match serde_json::to_string(metadata) {
-                Ok(serialized) => {
-                    if serialized.len() > 10000 {
-                        result.add_error("metadata", "Metadata is too large (max 10KB)");
-                    }
-                }
-                Err(_) => {
-                    result.add_error("metadata", "Invalid metadata format");
-                }
-            }
+            SecurityValidator::validate_json_field(&mut result, context, "item.metadata", "metadata", metadata, "Metadata contains potentially dangerous content");
+        }
 
-            let metadata_str = metadata.to_string();
-            if let Err(_) = crate::validation::rules::validate_no_sql_injection(&metadata_str) {
-                result.add_error("metadata", "Metadata contains potentially dangerous content");
-            }
-            if let Err(_) = crate::validation::rules::validate_no_xss(&metadata_str) {
-                result.add_error("metadata", "Metadata contains potentially dangerous content");
-            }
+        SecurityValidator::validate_field(&mut result, context, "item.name", "name", &self.name, "Name contains invalid characters");
+
+        if let Some(desc) = &self.description {
+            SecurityValidator::validate_field(&mut result, context, "item.description", "description", desc, "Description contains invalid characters");
         }
+        
+        result
+    }
+}
 
-        if let Err(_) = crate::validation::rules::validate_no_sql_injection(&self.name) {
-            result.add_error("name", "Name contains invalid characters");
+impl Sanitizable for CreateItemRequest {
+    fn sanitize_with_context(&mut self, context: &ValidationContext) {
+        self.name = unicode::normalize_line(&self.name);
+        self.description = self.description.as_deref().map(unicode::normalize_multiline);
+        if let Some(tags) = &mut self.tags {
+            for tag in tags.iter_mut() {
+                *tag = unicode::normalize_line(tag);
+            }
         }
-        if let Err(_) = crate::validation::rules::validate_no_xss(&self.name) {
-            result.add_error("name", "Name contains invalid characters");
+
+        SecurityValidator::sanitize_field(context, "item.name", &mut self.name);
+
+        if let Some(desc) = &mut self.description {
+            SecurityValidator::sanitize_field(context, "item.description", desc);
         }
 
-        if let Some(desc) = &self.description {
-            if let Err(_) = crate::validation::rules::validate_no_sql_injection(desc) {
-                result.add_error("description", "Description contains invalid characters");
-            }
-            if let Err(_) = crate::validation::rules::validate_no_xss(desc) {
-                result.add_error("description", "Description contains invalid characters");
+        if let Some(tags) = &mut self.tags {
+            for tag in tags.iter_mut() {
+                SecurityValidator::sanitize_field(context, "item.tags", tag);
             }
         }
-        
-        result
+
+        if let Some(metadata) = &mut self.metadata {
+            SecurityValidator::sanitize_json_field(context, "item.metadata", metadata);
+        }
     }
 }
 
@@ -100,66 +104,65 @@ pub struct UpdateItemRequest {
 }
 
 impl ContextValidatable for UpdateItemRequest {
-    fn validate_with_context(&self, _context: &ValidationContext) -> ValidationResult {
+    fn validate_with_context(&self, context: &ValidationContext) -> ValidationResult {
         let mut result = self.validate_comprehensive();
         
         if let Some(name) = &self.name {
-            if let Err(_) = crate::validation::rules::validate_no_sql_injection(name) {
-                result.add_error("name", "Name contains invalid characters");
-            }
-            if let Err(_) = crate::validation::rules::validate_no_xss(name) {
-                result.add_error("name", "Name contains invalid characters");
-            }
+            SecurityValidator::validate_field(&mut result, context, "item.name", "name", name, "Name contains invalid characters");
         }
 
         if let Some(desc) = &self.description {
-            if let Err(_) = crate::validation::rules::validate_no_sql_injection(desc) {
-                result.add_error("description", "Description contains invalid characters");
-            }
-            if let Err(_) = crate::validation::rules::validate_no_xss(desc) {
-                result.add_error("description", "Description contains invalid characters");
-            }
+            SecurityValidator::validate_field(&mut result, context, "item.description", "description", desc, "Description contains invalid characters");
         }
 
         if let Some(tags) = &self.tags {
             for (i, tag) in tags.iter().enumerate() {
+                let key = format!("tags[{}]", i);
                 if tag.is_empty() {
-                    result.add_error(&format!("tags[{}]", i), "Tag cannot be empty");
+                    result.add_error(&key, "Tag cannot be empty");
                 }
-                if tag.len() > 50 {
-                    result.add_error(&format!("tags[{}]", i), "Tag must not exceed 50 characters");
-                }
-                if let Err(_) = crate::validation::rules::validate_no_sql_injection(tag) {
-                    result.add_error(&format!("tags[{}]", i), "Tag contains invalid characters");
-                }
-                if let Err(_) = crate::validation::rules::validate_no_xss(tag) {
-                    result.add_error(&format!("tags[{}]", i), "Tag contains invalid characters");
+                if unicode::text_length(tag) > 50 {
+                    result.add_error(&key, "Tag must not exceed 50 characters");
                 }
+                SecurityValidator::validate_field(&mut result, context, "item.tags", &key, tag, "Tag contains invalid characters");
             }
         }
 
         if let Some(metadata) = &self.metadata {
-            match serde_json::to_string(metadata) {
-                Ok(serialized) => {
-                    if serialized.len() > 10000 {
-                        result.add_error("metadata", "Metadata is too large (max 10KB)");
-                    }
-                }
-                Err(_) => {
-                    result.add_error("metadata", "Invalid metadata format");
-                }
-            }
+            SecurityValidator::validate_json_field(&mut result, context, "item.metadata", "metadata", metadata, "Metadata contains potentially dangerous content");
+        }
+        
+        result
+    }
+}
 
-            let metadata_str = metadata.to_string();
-            if let Err(_) = crate::validation::rules::validate_no_sql_injection(&metadata_str) {
-                result.add_error("metadata", "Metadata contains potentially dangerous content");
+impl Sanitizable for UpdateItemRequest {
+    fn sanitize_with_context(&mut self, context: &ValidationContext) {
+        self.name = self.name.as_deref().map(unicode::normalize_line);
+        self.description = self.description.as_deref().map(unicode::normalize_multiline);
+        if let Some(tags) = &mut self.tags {
+            for tag in tags.iter_mut() {
+                *tag = unicode::normalize_line(tag);
             }
-            if let Err(_) = crate::validation::rules::validate_no_xss(&metadata_str) {
-                result.add_error("metadata", "Metadata contains potentially dangerous content");
+        }
+
+        if let Some(name) = &mut self.name {
+            SecurityValidator::sanitize_field(context, "item.name", name);
+        }
+
+        if let Some(desc) = &mut self.description {
+            SecurityValidator::sanitize_field(context, "item.description", desc);
+        }
+
+        if let Some(tags) = &mut self.tags {
+            for tag in tags.iter_mut() {
+                SecurityValidator::sanitize_field(context, "item.tags", tag);
             }
         }
-        
-        result
+
+        if let Some(metadata) = &mut self.metadata {
+            SecurityValidator::sanitize_json_field(context, "item.metadata", metadata);
+        }
     }
 }
 
@@ -178,12 +181,16 @@ pub struct ItemResponse {
 
 #[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct ItemListQuery {
-    #[validate(range(min = 1, max = 1000, message = "Page size must be between 1 and 1000"))]
+    /// Capped by `pagination.max_page_size` where the list is served.
+    #[validate(range(min = 1, message = "Page size must be at least 1"))]
     pub page_size: Option<u32>,
 
     #[validate(range(min = 1, message = "Page number must be at least 1"))]
     pub page: Option<u32>,
 
+    /// Fields to sort by, such as `-updated_at,name`.
+    pub sort: Option<SortSpec>,
+
     #[validate(length(max = 100, message = "Sort field name is too long"))]
     pub sort_by: Option<String>,
 
@@ -195,8 +202,32 @@ pub struct ItemListQuery {
     #[validate(length(max = 500, message = "Search query is too long"))]
     pub search: Option<String>,
     pub include_files: Option<bool>,
+
+    /// Comma-separated extras to embed in each listed item, from
+    /// [`ItemListQuery::INCLUDES`].
+    #[validate(length(max = 200, message = "Include list is too long"))]
+    pub include: Option<String>,
+}
+
+impl ItemListQuery {
+    /// Adds `comment_count` to every listed item.
+    pub const COMMENT_COUNTS: &'static str = "comment_counts";
+    pub const INCLUDES: &'static [&'static str] = &[Self::COMMENT_COUNTS];
+
+    pub fn includes(&self, extra: &str) -> bool {
+        self.include
+            .as_deref()
+            .is_some_and(|include| include.split(',').any(|name| name.trim() == extra))
+    }
+
+    /// The ordering asked for by `sort`, or by `sort_by` and `sort_order`.
+    pub fn sort_spec(&self) -> Result<Option<SortSpec>, ValidationError> {
+        ITEM_SORT.from_query(self.sort.clone(), self.sort_by.as_deref(), self.sort_order.as_deref())
+    }
 }
 
+impl QueryParams for ItemListQuery {}
+
 impl ContextValidatable for ItemListQuery {
     fn validate_with_context(&self, _context: &ValidationContext) -> ValidationResult {
         let mut result = self.validate_comprehensive();
@@ -212,7 +243,7 @@ impl ContextValidatable for ItemListQuery {
                 if tag.is_empty() {
                     result.add_error(&format!("tags[{}]", i), "Tag cannot be empty");
                 }
-                if tag.len() > 50 {
+                if unicode::text_length(tag) > 50 {
                     result.add_error(&format!("tags[{}]", i), "Tag is too long");
                 }
                 if let Err(_) = crate::validation::rules::validate_no_sql_injection(tag) {
@@ -226,6 +257,17 @@ impl ContextValidatable for ItemListQuery {
                 result.add_error("search", "Search query contains invalid characters");
             }
         }
+
+        if let Some(include) = &self.include {
+            for name in include.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                if !Self::INCLUDES.contains(&name) {
+                    result.add_error(
+                        "include",
+                        &format!("Unknown include '{}'; expected one of {}", name, Self::INCLUDES.join(", ")),
+                    );
+                }
+            }
+        }
         
         result
     }
@@ -241,16 +283,22 @@ pub struct ItemExportQuery {
 
     #[validate(length(max = 500, message = "Search query is too long"))]
     pub search: Option<String>,
+
+    /// Prefix CSV cells that start like a formula with `'` so spreadsheets
+    /// show them as text. On unless set to `false`.
+    pub safe_csv: Option<bool>,
 }
 
+impl QueryParams for ItemExportQuery {}
+
 impl ContextValidatable for ItemExportQuery {
     fn validate_with_context(&self, _context: &ValidationContext) -> ValidationResult {
         let mut result = self.validate_comprehensive();
         
         if let Some(format) = &self.format {
-            let allowed_formats = ["json", "csv", "yaml"];
+            let allowed_formats = ["json", "csv", "yaml", "xlsx"];
             if !allowed_formats.contains(&format.to_lowercase().as_str()) {
-                result.add_error("format", "Format must be one of: json, csv, yaml");
+                result.add_error("format", "Format must be one of: json, csv, yaml, xlsx");
             }
         }
 
@@ -273,4 +321,462 @@ impl ContextValidatable for ItemExportQuery {
         
         result
     }
-}
\ No newline at end of file
+}
+/// Optional sections of the item statistics, selected with `?breakdown=`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatsBreakdowns {
+    pub tags: bool,
+    pub creators: bool,
+    pub daily: bool,
+    pub metadata: bool,
+    pub descriptions: bool,
+}
+
+impl StatsBreakdowns {
+    pub const NAMES: [&'static str; 5] = ["tags", "creators", "daily", "metadata", "descriptions"];
+
+    pub fn all() -> Self {
+        Self { tags: true, creators: true, daily: true, metadata: true, descriptions: true }
+    }
+
+    pub fn none() -> Self {
+        Self { tags: false, creators: false, daily: false, metadata: false, descriptions: false }
+    }
+
+    /// Parses a comma-separated list of section names.
+    pub fn parse(list: &str) -> Result<Self, String> {
+        let mut breakdowns = Self::none();
+        for name in list.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match name {
+                "tags" => breakdowns.tags = true,
+                "creators" => breakdowns.creators = true,
+                "daily" => breakdowns.daily = true,
+                "metadata" => breakdowns.metadata = true,
+                "descriptions" => breakdowns.descriptions = true,
+                other => {
+                    return Err(format!(
+                        "Unknown breakdown '{}'; expected one of: {}",
+                        other,
+                        Self::NAMES.join(", ")
+                    ))
+                }
+            }
+        }
+        Ok(breakdowns)
+    }
+
+    /// Canonical comma-separated form, used in cache keys.
+    pub fn key(&self) -> String {
+        let selected = [self.tags, self.creators, self.daily, self.metadata, self.descriptions];
+        Self::NAMES
+            .iter()
+            .zip(selected)
+            .filter(|(_, selected)| *selected)
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ItemStatsQuery {
+    /// Comma-separated sections to compute; all of them when absent.
+    pub breakdown: Option<String>,
+    /// Length of the tag and creator rankings.
+    pub top: Option<usize>,
+    /// Recompute instead of serving cached statistics; administrators only.
+    pub refresh: Option<bool>,
+}
+
+pub const DEFAULT_STATS_TOP: usize = 10;
+pub const MAX_STATS_TOP: usize = 100;
+/// Days covered by [`ItemStats::created_per_day`], ending today.
+pub const STATS_DAILY_DAYS: i64 = 30;
+
+impl ItemStatsQuery {
+    pub fn breakdowns(&self) -> Result<StatsBreakdowns, String> {
+        self.breakdown.as_deref().map_or(Ok(StatsBreakdowns::all()), StatsBreakdowns::parse)
+    }
+
+    pub fn top(&self) -> usize {
+        self.top.unwrap_or(DEFAULT_STATS_TOP).clamp(1, MAX_STATS_TOP)
+    }
+}
+
+/// Item statistics served by `/api/stats`. Every key is always present;
+/// sections that were not requested are `null`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ItemStats {
+    pub total_items: u64,
+    /// `database` or `memory`.
+    pub source: String,
+    pub generated_at: DateTime<Utc>,
+    pub tags: Option<TagStats>,
+    /// Items per creator, most first. Items without a creator are counted
+    /// under a `null` `created_by`.
+    pub creators: Option<Vec<CreatorCount>>,
+    /// Items created on each of the last [`STATS_DAILY_DAYS`] UTC days,
+    /// oldest first, including days with none.
+    pub created_per_day: Option<Vec<DailyItemCount>>,
+    pub metadata: Option<MetadataStats>,
+    pub descriptions: Option<DescriptionStats>,
+}
+
+impl ItemStats {
+    pub fn new(total_items: u64, source: &str) -> Self {
+        Self {
+            total_items,
+            source: source.to_string(),
+            generated_at: Utc::now(),
+            tags: None,
+            creators: None,
+            created_per_day: None,
+            metadata: None,
+            descriptions: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TagStats {
+    pub unique_tags: u64,
+    /// Most used tags, most first.
+    pub top: Vec<TagCount>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreatorCount {
+    pub created_by: Option<i64>,
+    pub username: Option<String>,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DailyItemCount {
+    pub date: chrono::NaiveDate,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetadataStats {
+    /// Items with metadata other than an empty object.
+    pub items_with_metadata: u64,
+    /// Mean size of that metadata serialized as JSON.
+    pub average_size_bytes: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DescriptionStats {
+    pub with_description: u64,
+    pub without_description: u64,
+}
+
+/// The last [`STATS_DAILY_DAYS`] days ending `today`, oldest first, with
+/// counts taken from `counts` and zero elsewhere.
+pub fn daily_counts(today: chrono::NaiveDate, counts: &std::collections::HashMap<chrono::NaiveDate, u64>) -> Vec<DailyItemCount> {
+    (0..STATS_DAILY_DAYS)
+        .rev()
+        .map(|days_ago| {
+            let date = today - chrono::Duration::days(days_ago);
+            DailyItemCount { date, count: counts.get(&date).copied().unwrap_or(0) }
+        })
+        .collect()
+}
+
+/// Longest tag, in characters.
+pub const MAX_TAG_LENGTH: usize = 50;
+/// Most tags one merge may fold into its target.
+pub const MAX_MERGE_SOURCES: usize = 50;
+
+/// Body of `POST /api/tags/rename`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TagRenameRequest {
+    pub from: String,
+    pub to: String,
+    /// Also rename tags that differ from `from` only in ASCII case.
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// Count the items that would change without changing them.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Body of `POST /api/tags/merge`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TagMergeRequest {
+    pub sources: Vec<String>,
+    pub target: String,
+    /// Also merge tags that differ from a source only in ASCII case.
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// Count the items that would change without changing them.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Replacement of one or more tags by another, as applied to every item by
+/// a rename or merge. Matching ignores ASCII case only, which is what
+/// SQLite's `lower()` folds, so both stores agree on what matches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagRewrite {
+    sources: Vec<String>,
+    target: String,
+    case_insensitive: bool,
+}
+
+impl TagRewrite {
+    /// Normalizes the tags as item tags are normalized and checks them.
+    pub fn new(sources: Vec<String>, target: String, case_insensitive: bool) -> Result<Self, ValidationError> {
+        if sources.is_empty() || sources.len() > MAX_MERGE_SOURCES {
+            return Err(ValidationError::field(
+                "sources",
+                "length",
+                format!("Between 1 and {} source tags are required", MAX_MERGE_SOURCES),
+            ));
+        }
+
+        let check = |field: &str, tag: &str| -> Result<String, ValidationError> {
+            let tag = unicode::normalize_line(tag);
+            if tag.trim().is_empty() {
+                return Err(ValidationError::field(field, "required", "Tag cannot be empty"));
+            }
+            if unicode::text_length(&tag) > MAX_TAG_LENGTH {
+                return Err(ValidationError::field(
+                    field,
+                    "length",
+                    format!("Tag too long (maximum {} characters)", MAX_TAG_LENGTH),
+                ));
+            }
+            Ok(tag)
+        };
+
+        let target = check("target", &target)?;
+        let mut normalized: Vec<String> = Vec::with_capacity(sources.len());
+        for source in &sources {
+            let source = check("sources", source)?;
+            if !normalized.contains(&source) {
+                normalized.push(source);
+            }
+        }
+        if !case_insensitive && normalized.iter().all(|source| *source == target) {
+            return Err(ValidationError::field(
+                "target",
+                "unchanged",
+                "Target must differ from the source tags",
+            ));
+        }
+
+        Ok(Self { sources: normalized, target, case_insensitive })
+    }
+
+    pub fn sources(&self) -> &[String] {
+        &self.sources
+    }
+
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    pub fn case_insensitive(&self) -> bool {
+        self.case_insensitive
+    }
+
+    pub fn matches(&self, tag: &str) -> bool {
+        self.sources.iter().any(|source| {
+            if self.case_insensitive {
+                source.eq_ignore_ascii_case(tag)
+            } else {
+                source == tag
+            }
+        })
+    }
+
+    /// `tags` with every matching tag replaced by the target, which is kept
+    /// once at its first position. `None` when nothing would change.
+    pub fn apply(&self, tags: &[String]) -> Option<Vec<String>> {
+        let mut rewritten: Vec<String> = Vec::with_capacity(tags.len());
+        let mut has_target = false;
+        for tag in tags {
+            if self.matches(tag) || *tag == self.target {
+                if !has_target {
+                    rewritten.push(self.target.clone());
+                    has_target = true;
+                }
+            } else {
+                rewritten.push(tag.clone());
+            }
+        }
+        (rewritten != tags).then_some(rewritten)
+    }
+}
+
+/// Outcome of a tag rename or merge.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TagRewriteResult {
+    pub dry_run: bool,
+    /// Items whose tags changed, or would change on a dry run.
+    pub affected_items: u64,
+    pub item_ids: Vec<u64>,
+}
+
+/// Why an update was refused: the client edited `expected_version` but the
+/// item has moved on. `changed_fields` names the fields where the client's
+/// write and the current item differ, as a hint for merging.
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionConflict {
+    pub item_id: u64,
+    pub expected_version: u64,
+    pub current_version: u64,
+    pub changed_fields: Vec<&'static str>,
+    pub current: crate::store::Item,
+}
+
+impl VersionConflict {
+    /// Conflict between `current` and `proposed`, the item as the client's
+    /// write would have left it.
+    pub fn new(expected_version: u64, current: crate::store::Item, proposed: &crate::store::Item) -> Self {
+        let mut changed_fields = Vec::new();
+        if current.name != proposed.name {
+            changed_fields.push("name");
+        }
+        if current.description != proposed.description {
+            changed_fields.push("description");
+        }
+        if current.tags != proposed.tags {
+            changed_fields.push("tags");
+        }
+        if current.metadata != proposed.metadata {
+            changed_fields.push("metadata");
+        }
+
+        Self {
+            item_id: current.id,
+            expected_version,
+            current_version: current.version,
+            changed_fields,
+            current,
+        }
+    }
+
+    /// Fails when the client expected a version other than `current`'s.
+    /// Writes without an expected version always pass.
+    pub fn check(expected_version: Option<u64>, current: &crate::store::Item, proposed: &crate::store::Item) -> crate::error::Result<()> {
+        match expected_version {
+            Some(expected) if expected != current.version => Err(crate::error::AppError::VersionConflict(Box::new(
+                Self::new(expected, current.clone(), proposed),
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FieldPolicy, ValidationConfig};
+
+    fn sanitize_context() -> ValidationContext {
+        let mut config = ValidationConfig::default();
+        config.field_policies.insert("item.description".to_string(), FieldPolicy::Sanitize);
+        config.field_policies.insert("item.metadata".to_string(), FieldPolicy::Sanitize);
+        ValidationContext::default().with_validation_config(config)
+    }
+
+    fn code_snippet_request() -> CreateItemRequest {
+        CreateItemRequest {
+            name: "Snippet".to_string(),
+            description: Some("```html\n<script>alert('hi')</script>\n```".to_string()),
+            tags: None,
+            metadata: Some(serde_json::json!({"example": "```\n<script>select 1</script>\n```"})),
+            version: None,
+        }
+    }
+
+    #[test]
+    fn test_default_policy_rejects_script_in_description() {
+        let request = code_snippet_request();
+        let result = request.validate_with_context(&ValidationContext::default());
+
+        assert!(!result.is_valid);
+        assert!(result.errors.contains_key("description"));
+        assert!(result.errors.contains_key("metadata"));
+    }
+
+    #[test]
+    fn test_sanitize_policy_keeps_code_block_as_escaped_text() {
+        let context = sanitize_context();
+        let mut request = code_snippet_request();
+
+        request.sanitize_with_context(&context);
+        let result = request.validate_with_context(&context);
+
+        assert!(result.is_valid, "unexpected errors: {:?}", result.errors);
+        let description = request.description.unwrap();
+        assert!(description.starts_with("```html\n&lt;script&gt;"));
+        assert!(!description.contains("<script>"));
+        assert_eq!(
+            request.metadata.unwrap()["example"],
+            "```\n&lt;script&gt;select 1&lt;&#x2F;script&gt;\n```"
+        );
+    }
+
+    #[test]
+    fn test_sanitize_policy_leaves_strict_fields_untouched() {
+        let context = sanitize_context();
+        let mut request = code_snippet_request();
+        request.name = "<script>".to_string();
+
+        request.sanitize_with_context(&context);
+        let result = request.validate_with_context(&context);
+
+        assert_eq!(request.name, "<script>");
+        assert!(result.errors.contains_key("name"));
+    }
+
+    #[test]
+    fn test_off_policy_skips_security_rules() {
+        let mut config = ValidationConfig::default();
+        config.default_policy = FieldPolicy::Off;
+        let context = ValidationContext::default().with_validation_config(config);
+
+        let result = code_snippet_request().validate_with_context(&context);
+        assert!(result.is_valid);
+    }
+
+    fn tags(list: &[&str]) -> Vec<String> {
+        list.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    #[test]
+    fn test_tag_rewrite_merges_into_one_target() {
+        let rewrite = TagRewrite::new(tags(&["proirity", "prio"]), "priority".to_string(), false).unwrap();
+
+        assert_eq!(rewrite.apply(&tags(&["prio", "ops", "proirity"])), Some(tags(&["priority", "ops"])));
+        assert_eq!(rewrite.apply(&tags(&["ops", "priority", "prio"])), Some(tags(&["ops", "priority"])));
+        assert_eq!(rewrite.apply(&tags(&["ops", "Prio"])), None);
+        assert_eq!(rewrite.apply(&tags(&["priority", "ops", "ops"])), None);
+    }
+
+    #[test]
+    fn test_tag_rewrite_case_insensitive() {
+        let rewrite = TagRewrite::new(tags(&["priority"]), "priority".to_string(), true).unwrap();
+
+        assert_eq!(rewrite.apply(&tags(&["PRIORITY", "ops"])), Some(tags(&["priority", "ops"])));
+        assert_eq!(rewrite.apply(&tags(&["priority"])), None);
+        assert!(TagRewrite::new(tags(&["priority"]), "priority".to_string(), false).is_err());
+    }
+
+    #[test]
+    fn test_tag_rewrite_rejects_invalid_tags() {
+        assert!(TagRewrite::new(Vec::new(), "target".to_string(), false).is_err());
+        assert!(TagRewrite::new(tags(&["  "]), "target".to_string(), false).is_err());
+        assert!(TagRewrite::new(tags(&["source"]), "t".repeat(MAX_TAG_LENGTH + 1), false).is_err());
+        assert!(TagRewrite::new(vec!["s".to_string(); MAX_MERGE_SOURCES + 1], "target".to_string(), false).is_err());
+    }
+}
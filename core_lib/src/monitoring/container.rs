@@ -0,0 +1,258 @@
+//! Container limits from cgroups and per-process counters from /proc
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const PROC_SELF_CGROUP: &str = "/proc/self/cgroup";
+
+/// cgroup v1 reports "no limit" as a page-aligned `i64::MAX`; anything this
+/// large is treated as unlimited.
+const UNLIMITED_THRESHOLD: u64 = 1 << 62;
+
+/// Limits of the cgroup this process runs in, as exposed in metrics.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContainerLimits {
+    pub cgroup_version: u8,
+    pub memory_limit_bytes: Option<u64>,
+    pub cpu_limit_cores: Option<f64>,
+}
+
+/// One reading of the process's cgroup.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CgroupStats {
+    pub version: u8,
+    pub memory_limit_bytes: Option<u64>,
+    /// Memory charged to the cgroup minus reclaimable page cache, which is
+    /// what the OOM killer compares against the limit.
+    pub memory_used_bytes: Option<u64>,
+    pub cpu_limit_cores: Option<f64>,
+    /// Cumulative CPU time consumed by the cgroup.
+    pub cpu_usage_usec: Option<u64>,
+}
+
+impl CgroupStats {
+    pub fn limits(&self) -> ContainerLimits {
+        ContainerLimits {
+            cgroup_version: self.version,
+            memory_limit_bytes: self.memory_limit_bytes,
+            cpu_limit_cores: self.cpu_limit_cores,
+        }
+    }
+}
+
+/// Reads cgroup v1 or v2 controller files for the current process.
+#[derive(Debug, Clone)]
+pub struct CgroupReader {
+    root: PathBuf,
+    /// Contents of /proc/self/cgroup, used to find this process's cgroup
+    /// below `root` when no cgroup namespace hides the hierarchy.
+    membership: String,
+}
+
+impl Default for CgroupReader {
+    fn default() -> Self {
+        Self::new(CGROUP_ROOT, std::fs::read_to_string(PROC_SELF_CGROUP).unwrap_or_default())
+    }
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+fn read_u64(path: &Path) -> Option<u64> {
+    read_trimmed(path)?.parse().ok()
+}
+
+fn stat_value(path: &Path, key: &str) -> Option<u64> {
+    let stat = std::fs::read_to_string(path).ok()?;
+    stat.lines().find_map(|line| {
+        let (name, value) = line.split_once(' ')?;
+        (name == key).then(|| value.trim().parse().ok()).flatten()
+    })
+}
+
+fn limit(value: u64) -> Option<u64> {
+    (value < UNLIMITED_THRESHOLD).then_some(value)
+}
+
+impl CgroupReader {
+    pub fn new(root: impl Into<PathBuf>, membership: impl Into<String>) -> Self {
+        Self {
+            root: root.into(),
+            membership: membership.into(),
+        }
+    }
+
+    /// Path of this process's cgroup for `controller` (`""` for the v2
+    /// unified hierarchy) as listed in /proc/self/cgroup.
+    fn membership_path(&self, controller: &str) -> Option<&str> {
+        self.membership.lines().find_map(|line| {
+            let mut fields = line.splitn(3, ':');
+            let (_, controllers, path) = (fields.next()?, fields.next()?, fields.next()?);
+            let matches = if controller.is_empty() {
+                controllers.is_empty()
+            } else {
+                controllers.split(',').any(|name| name == controller)
+            };
+            matches.then_some(path)
+        })
+    }
+
+    /// Directory holding the controller files: the process's own cgroup if
+    /// it is visible, otherwise the mount root (as inside a cgroup namespace).
+    fn controller_dir(&self, mount: &Path, controller: &str, probe: &str) -> Option<PathBuf> {
+        let nested = self
+            .membership_path(controller)
+            .map(|path| mount.join(path.trim_start_matches('/')))
+            .filter(|dir| dir.join(probe).exists());
+
+        nested.or_else(|| mount.join(probe).exists().then(|| mount.to_path_buf()))
+    }
+
+    pub fn read(&self) -> Option<CgroupStats> {
+        if self.root.join("cgroup.controllers").exists() {
+            Some(self.read_v2())
+        } else if self.root.join("memory").is_dir() || self.root.join("cpu").is_dir() {
+            Some(self.read_v1())
+        } else {
+            None
+        }
+    }
+
+    fn read_v2(&self) -> CgroupStats {
+        let mut stats = CgroupStats {
+            version: 2,
+            ..CgroupStats::default()
+        };
+
+        if let Some(dir) = self.controller_dir(&self.root, "", "memory.max") {
+            stats.memory_limit_bytes = read_trimmed(&dir.join("memory.max"))
+                .and_then(|max| max.parse().ok())
+                .and_then(limit);
+            stats.memory_used_bytes = read_u64(&dir.join("memory.current")).map(|current| {
+                current.saturating_sub(stat_value(&dir.join("memory.stat"), "inactive_file").unwrap_or(0))
+            });
+        }
+
+        if let Some(dir) = self.controller_dir(&self.root, "", "cpu.stat") {
+            stats.cpu_usage_usec = stat_value(&dir.join("cpu.stat"), "usage_usec");
+            stats.cpu_limit_cores = read_trimmed(&dir.join("cpu.max")).and_then(|max| {
+                let (quota, period) = max.split_once(' ')?;
+                let (quota, period): (f64, f64) = (quota.parse().ok()?, period.parse().ok()?);
+                (period > 0.0).then(|| quota / period)
+            });
+        }
+
+        stats
+    }
+
+    fn read_v1(&self) -> CgroupStats {
+        let mut stats = CgroupStats {
+            version: 1,
+            ..CgroupStats::default()
+        };
+
+        let memory = self.root.join("memory");
+        if let Some(dir) = self.controller_dir(&memory, "memory", "memory.usage_in_bytes") {
+            stats.memory_limit_bytes = read_u64(&dir.join("memory.limit_in_bytes")).and_then(limit);
+            stats.memory_used_bytes = read_u64(&dir.join("memory.usage_in_bytes")).map(|usage| {
+                usage.saturating_sub(stat_value(&dir.join("memory.stat"), "total_inactive_file").unwrap_or(0))
+            });
+        }
+
+        let cpu = self.root.join("cpu");
+        if let Some(dir) = self.controller_dir(&cpu, "cpu", "cpu.cfs_quota_us") {
+            let quota = read_trimmed(&dir.join("cpu.cfs_quota_us")).and_then(|q| q.parse::<i64>().ok());
+            let period = read_u64(&dir.join("cpu.cfs_period_us"));
+            stats.cpu_limit_cores = match (quota, period) {
+                (Some(quota), Some(period)) if quota > 0 && period > 0 => Some(quota as f64 / period as f64),
+                _ => None,
+            };
+        }
+
+        let cpuacct = self.root.join("cpuacct");
+        if let Some(dir) = self.controller_dir(&cpuacct, "cpuacct", "cpuacct.usage") {
+            stats.cpu_usage_usec = read_u64(&dir.join("cpuacct.usage")).map(|nanos| nanos / 1000);
+        }
+
+        stats
+    }
+}
+
+/// Number of file descriptors this process has open, where /proc exposes it.
+pub fn open_file_descriptors() -> Option<usize> {
+    std::fs::read_dir("/proc/self/fd").ok().map(|entries| entries.count())
+}
+
+/// Number of OS threads in this process, where /proc exposes it.
+pub fn thread_count() -> Option<usize> {
+    std::fs::read_dir("/proc/self/task").ok().map(|entries| entries.count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, contents: &str) {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join(name), contents).unwrap();
+    }
+
+    #[test]
+    fn test_reads_cgroup_v2_limits() {
+        let root = tempfile::tempdir().unwrap();
+        let own = root.path().join("system.slice/app.service");
+        write(root.path(), "cgroup.controllers", "cpu memory");
+        write(root.path(), "memory.max", "max");
+        write(&own, "memory.max", "536870912");
+        write(&own, "memory.current", "300000000");
+        write(&own, "memory.stat", "anon 200000000\ninactive_file 100000000\n");
+        write(&own, "cpu.max", "150000 100000");
+        write(&own, "cpu.stat", "usage_usec 4200\nuser_usec 4000\n");
+
+        let stats = CgroupReader::new(root.path(), "0::/system.slice/app.service\n").read().unwrap();
+        assert_eq!(stats.version, 2);
+        assert_eq!(stats.memory_limit_bytes, Some(536870912));
+        assert_eq!(stats.memory_used_bytes, Some(200000000));
+        assert_eq!(stats.cpu_limit_cores, Some(1.5));
+        assert_eq!(stats.cpu_usage_usec, Some(4200));
+
+        // Inside a cgroup namespace the process's path is not below the mount.
+        write(root.path(), "cpu.max", "max 100000");
+        write(root.path(), "cpu.stat", "usage_usec 10\n");
+        let stats = CgroupReader::new(root.path(), "0::/\n").read().unwrap();
+        assert_eq!(stats.memory_limit_bytes, None);
+        assert_eq!(stats.cpu_limit_cores, None);
+    }
+
+    #[test]
+    fn test_reads_cgroup_v1_limits() {
+        let root = tempfile::tempdir().unwrap();
+        let memory = root.path().join("memory/docker/abc");
+        write(&memory, "memory.limit_in_bytes", "1073741824");
+        write(&memory, "memory.usage_in_bytes", "600000000");
+        write(&memory, "memory.stat", "cache 5\ntotal_inactive_file 100000000\n");
+        write(&root.path().join("cpu"), "cpu.cfs_quota_us", "-1");
+        write(&root.path().join("cpu"), "cpu.cfs_period_us", "100000");
+        write(&root.path().join("cpuacct"), "cpuacct.usage", "5000000");
+
+        let membership = "4:memory:/docker/abc\n2:cpu,cpuacct:/\n";
+        let stats = CgroupReader::new(root.path(), membership).read().unwrap();
+        assert_eq!(stats.version, 1);
+        assert_eq!(stats.memory_limit_bytes, Some(1073741824));
+        assert_eq!(stats.memory_used_bytes, Some(500000000));
+        assert_eq!(stats.cpu_limit_cores, None);
+        assert_eq!(stats.cpu_usage_usec, Some(5000));
+
+        write(&memory, "memory.limit_in_bytes", "9223372036854771712");
+        let stats = CgroupReader::new(root.path(), membership).read().unwrap();
+        assert_eq!(stats.memory_limit_bytes, None);
+    }
+
+    #[test]
+    fn test_no_cgroup_filesystem() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(CgroupReader::new(root.path(), "").read().is_none());
+    }
+}
@@ -1,3 +1,6 @@
+pub mod container;
+pub mod response_times;
 pub mod system;
+pub mod traffic;
 
 pub use system::{SystemMonitor, SystemMetrics, ResourceUsage, DiskUsage};
\ No newline at end of file
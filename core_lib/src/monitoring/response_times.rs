@@ -0,0 +1,338 @@
+//! Bounded response time history with per-minute aggregates
+
+use crate::config::MetricsConfig;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Upper bounds (ms) of the latency histogram kept per minute. Percentiles
+/// are estimated as the bound of the bucket holding the requested rank, so
+/// they never exceed the bucket's observed maximum.
+const LATENCY_BOUNDS_MS: [u64; 14] = [1, 2, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000, u64::MAX];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseTime {
+    pub timestamp: DateTime<Utc>,
+    pub duration_ms: u128,
+    pub endpoint: Arc<str>,
+    pub status: u16,
+}
+
+/// Aggregated response times for one interval of a series.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResponseTimePoint {
+    pub timestamp: DateTime<Utc>,
+    pub count: u64,
+    pub average_ms: f64,
+    pub max_ms: u64,
+    pub p95_ms: u64,
+    pub errors: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResponseTimePercentiles {
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Aggregate {
+    count: u64,
+    sum_ms: u64,
+    max_ms: u64,
+    errors: u64,
+    histogram: [u64; LATENCY_BOUNDS_MS.len()],
+}
+
+impl Aggregate {
+    fn record(&mut self, duration_ms: u64, status: u16) {
+        self.count += 1;
+        self.sum_ms = self.sum_ms.saturating_add(duration_ms);
+        self.max_ms = self.max_ms.max(duration_ms);
+        if status >= 500 {
+            self.errors += 1;
+        }
+        let slot = LATENCY_BOUNDS_MS.iter().position(|bound| duration_ms <= *bound).unwrap_or(LATENCY_BOUNDS_MS.len() - 1);
+        self.histogram[slot] += 1;
+    }
+
+    fn merge(&mut self, other: &Aggregate) {
+        self.count += other.count;
+        self.sum_ms = self.sum_ms.saturating_add(other.sum_ms);
+        self.max_ms = self.max_ms.max(other.max_ms);
+        self.errors += other.errors;
+        for (total, count) in self.histogram.iter_mut().zip(other.histogram.iter()) {
+            *total += count;
+        }
+    }
+
+    fn percentile(&self, percentile: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+
+        let rank = ((percentile / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (slot, count) in self.histogram.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return LATENCY_BOUNDS_MS[slot].min(self.max_ms);
+            }
+        }
+        self.max_ms
+    }
+
+    fn average_ms(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum_ms as f64 / self.count as f64
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MinuteBucket {
+    minute: i64,
+    aggregate: Aggregate,
+}
+
+/// The last `capacity` response times plus per-minute aggregates for the
+/// last `window_minutes` minutes. Both are fixed-size, so memory stays
+/// bounded however many requests are recorded.
+#[derive(Debug, Clone)]
+pub struct ResponseTimeHistory {
+    recent: VecDeque<ResponseTime>,
+    capacity: usize,
+    buckets: VecDeque<MinuteBucket>,
+    window_minutes: i64,
+}
+
+impl Default for ResponseTimeHistory {
+    fn default() -> Self {
+        Self::new(&MetricsConfig::default())
+    }
+}
+
+fn unix_minute(timestamp: DateTime<Utc>) -> i64 {
+    timestamp.timestamp().div_euclid(60)
+}
+
+impl ResponseTimeHistory {
+    pub fn new(config: &MetricsConfig) -> Self {
+        let capacity = config.response_time_capacity.max(1);
+        let window_minutes = config.history_window_minutes.max(1) as i64;
+
+        Self {
+            recent: VecDeque::with_capacity(capacity),
+            capacity,
+            buckets: VecDeque::with_capacity(window_minutes as usize),
+            window_minutes,
+        }
+    }
+
+    /// Longest window that [`series`](Self::series) can answer.
+    pub fn window_minutes(&self) -> u64 {
+        self.window_minutes as u64
+    }
+
+    pub fn record(&mut self, response_time: ResponseTime) {
+        let minute = unix_minute(response_time.timestamp);
+        let duration_ms = u64::try_from(response_time.duration_ms).unwrap_or(u64::MAX);
+
+        match self.buckets.back_mut() {
+            Some(bucket) if bucket.minute == minute => bucket.aggregate.record(duration_ms, response_time.status),
+            Some(bucket) if bucket.minute > minute => {
+                // Clock went backwards; fold into the newest bucket rather
+                // than reordering history.
+                bucket.aggregate.record(duration_ms, response_time.status)
+            }
+            _ => {
+                let mut aggregate = Aggregate::default();
+                aggregate.record(duration_ms, response_time.status);
+                self.buckets.push_back(MinuteBucket { minute, aggregate });
+            }
+        }
+        self.expire(minute);
+
+        if self.recent.len() == self.capacity {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(response_time);
+    }
+
+    fn expire(&mut self, current_minute: i64) {
+        while self
+            .buckets
+            .front()
+            .is_some_and(|bucket| bucket.minute <= current_minute - self.window_minutes)
+        {
+            self.buckets.pop_front();
+        }
+    }
+
+    /// Percentile of the individual response times recorded at or after
+    /// `since`, or `None` if fewer than `min_samples` of them are retained.
+    pub fn recent_percentile(&self, since: DateTime<Utc>, percentile: f64, min_samples: usize) -> Option<u64> {
+        let mut durations: Vec<u128> = self
+            .recent
+            .iter()
+            .rev()
+            .take_while(|response| response.timestamp >= since)
+            .map(|response| response.duration_ms)
+            .collect();
+        if durations.is_empty() || durations.len() < min_samples {
+            return None;
+        }
+
+        durations.sort_unstable();
+        let rank = ((percentile / 100.0) * durations.len() as f64).ceil().max(1.0) as usize;
+        let duration = durations[rank.min(durations.len()) - 1];
+        Some(u64::try_from(duration).unwrap_or(u64::MAX))
+    }
+
+    /// The most recent individual response times, oldest first.
+    pub fn recent(&self) -> impl Iterator<Item = &ResponseTime> {
+        self.recent.iter()
+    }
+
+    fn aggregate_since(&self, start_minute: i64) -> Aggregate {
+        let mut total = Aggregate::default();
+        for bucket in self.buckets.iter().filter(|bucket| bucket.minute >= start_minute) {
+            total.merge(&bucket.aggregate);
+        }
+        total
+    }
+
+    /// Average response time over the last `window_minutes` minutes.
+    pub fn average_ms(&self, now: DateTime<Utc>, window_minutes: u64) -> f64 {
+        self.aggregate_since(unix_minute(now) - window_minutes as i64 + 1).average_ms()
+    }
+
+    /// Estimated percentiles over the last `window_minutes` minutes.
+    pub fn percentiles(&self, now: DateTime<Utc>, window_minutes: u64) -> ResponseTimePercentiles {
+        let aggregate = self.aggregate_since(unix_minute(now) - window_minutes as i64 + 1);
+        ResponseTimePercentiles {
+            p50_ms: aggregate.percentile(50.0) as f64,
+            p95_ms: aggregate.percentile(95.0) as f64,
+            p99_ms: aggregate.percentile(99.0) as f64,
+        }
+    }
+
+    /// Aggregates for the last `window_minutes` minutes in steps of
+    /// `resolution_minutes`, oldest first. Intervals without requests are
+    /// included with a zero count so the series has a fixed length.
+    pub fn series(&self, now: DateTime<Utc>, window_minutes: u64, resolution_minutes: u64) -> Vec<ResponseTimePoint> {
+        let window = window_minutes.clamp(1, self.window_minutes as u64) as i64;
+        let resolution = resolution_minutes.clamp(1, window as u64) as i64;
+        let current = unix_minute(now);
+        let start = current - window + 1;
+
+        let mut points: Vec<(i64, Aggregate)> = (0..(window + resolution - 1) / resolution)
+            .map(|step| (start + step * resolution, Aggregate::default()))
+            .collect();
+
+        for bucket in self.buckets.iter().filter(|bucket| bucket.minute >= start && bucket.minute <= current) {
+            let step = ((bucket.minute - start) / resolution) as usize;
+            points[step].1.merge(&bucket.aggregate);
+        }
+
+        points
+            .into_iter()
+            .map(|(minute, aggregate)| ResponseTimePoint {
+                timestamp: Utc.timestamp_opt(minute * 60, 0).single().unwrap_or(now),
+                count: aggregate.count,
+                average_ms: aggregate.average_ms(),
+                max_ms: aggregate.max_ms,
+                p95_ms: aggregate.percentile(95.0),
+                errors: aggregate.errors,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(timestamp: DateTime<Utc>, duration_ms: u128, status: u16) -> ResponseTime {
+        ResponseTime {
+            timestamp,
+            duration_ms,
+            endpoint: Arc::from("/api/items/:id"),
+            status,
+        }
+    }
+
+    fn history(capacity: usize, window_minutes: u64) -> ResponseTimeHistory {
+        ResponseTimeHistory::new(&MetricsConfig {
+            response_time_capacity: capacity,
+            history_window_minutes: window_minutes,
+            ..MetricsConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_memory_is_bounded() {
+        let mut history = history(10, 5);
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+
+        for i in 0..100_000i64 {
+            let timestamp = start + chrono::Duration::milliseconds(i * 10);
+            history.record(response(timestamp, (i % 300) as u128, 200));
+        }
+
+        assert_eq!(history.recent.len(), 10);
+        assert!(history.buckets.len() <= 5);
+    }
+
+    #[test]
+    fn test_series_aggregates_per_interval() {
+        let mut history = history(100, 15);
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 12, 14, 30).unwrap();
+
+        for minute in 0..15 {
+            let at = Utc.with_ymd_and_hms(2024, 1, 1, 12, minute, 10).unwrap();
+            history.record(response(at, 10, 200));
+            history.record(response(at, 30, if minute == 14 { 503 } else { 200 }));
+        }
+
+        let series = history.series(now, 15, 5);
+        assert_eq!(series.len(), 3);
+        assert_eq!(series[0].timestamp, Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap());
+        assert!(series.iter().all(|point| point.count == 10));
+        assert_eq!(series[2].average_ms, 20.0);
+        assert_eq!(series[2].max_ms, 30);
+        assert_eq!(series[2].p95_ms, 30);
+        assert_eq!(series[2].errors, 1);
+
+        let minutes = history.series(now, 3, 1);
+        assert_eq!(minutes.len(), 3);
+        assert_eq!(minutes[2].timestamp, Utc.with_ymd_and_hms(2024, 1, 1, 12, 14, 0).unwrap());
+
+        let percentiles = history.percentiles(now, 15);
+        assert_eq!(percentiles.p50_ms, 10.0);
+        assert_eq!(percentiles.p99_ms, 30.0);
+    }
+
+    #[test]
+    fn test_series_skips_expired_minutes() {
+        let mut history = history(100, 5);
+        let old = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 12, 10, 0).unwrap();
+
+        history.record(response(old, 500, 200));
+        history.record(response(now, 20, 200));
+
+        assert_eq!(history.recent_percentile(now, 99.0, 1), Some(20));
+        assert_eq!(history.recent_percentile(old, 99.0, 1), Some(500));
+        assert_eq!(history.recent_percentile(old, 99.0, 3), None);
+
+        let series = history.series(now, 60, 1);
+        assert_eq!(series.len(), 5);
+        assert_eq!(series.iter().map(|point| point.count).sum::<u64>(), 1);
+        assert_eq!(history.average_ms(now, 5), 20.0);
+    }
+}
@@ -1,17 +1,25 @@
 //! System resource monitoring and metrics collection
 
+use super::container::{self, CgroupReader, CgroupStats, ContainerLimits};
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use sysinfo::{System, Networks, Pid};
 use tracing::debug;
 
+/// CPU and memory figures are relative to the container's cgroup limits when
+/// the process runs under one, and to the host otherwise.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResourceUsage {
     pub cpu_usage_percent: f32,
     pub memory_used_bytes: u64,
     pub memory_total_bytes: u64,
     pub memory_usage_percent: f64,
+    pub host_cpu_usage_percent: f32,
+    pub host_memory_used_bytes: u64,
+    pub host_memory_total_bytes: u64,
+    pub container: Option<ContainerLimits>,
     pub swap_used_bytes: u64,
     pub swap_total_bytes: u64,
     pub swap_usage_percent: f64,
@@ -29,6 +37,8 @@ pub struct DiskUsage {
     pub used_bytes: u64,
     pub usage_percent: f64,
     pub file_system: String,
+    /// Configured storage paths (database, uploads) that live on this filesystem.
+    pub paths: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -47,12 +57,22 @@ pub struct ProcessInfo {
     pub pid: u32,
     pub name: String,
     pub cpu_usage: f32,
+    /// Resident set size.
     pub memory_bytes: u64,
     pub virtual_memory_bytes: u64,
+    pub open_file_descriptors: Option<usize>,
+    pub thread_count: Option<usize>,
     pub status: String,
     pub start_time: u64,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuntimeStats {
+    pub worker_threads: usize,
+    pub alive_tasks: usize,
+    pub global_queue_depth: usize,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SystemMetrics {
     pub timestamp: chrono::DateTime<chrono::Utc>,
@@ -60,6 +80,7 @@ pub struct SystemMetrics {
     pub disk_usage: Vec<DiskUsage>,
     pub network_stats: Vec<NetworkStats>,
     pub current_process: Option<ProcessInfo>,
+    pub runtime: Option<RuntimeStats>,
     pub system_info: SystemInfo,
 }
 
@@ -92,6 +113,9 @@ pub struct SystemMonitor {
     current_pid: Option<u32>,
     metrics_history: Arc<Mutex<Vec<SystemMetrics>>>,
     max_history_size: usize,
+    cgroup: CgroupReader,
+    last_cgroup_cpu: Mutex<Option<(Instant, u64)>>,
+    storage_paths: Vec<PathBuf>,
 }
 
 impl SystemMonitor {
@@ -107,7 +131,24 @@ impl SystemMonitor {
             current_pid: Some(current_pid),
             metrics_history: Arc::new(Mutex::new(Vec::new())),
             max_history_size: 100,
+            cgroup: CgroupReader::default(),
+            last_cgroup_cpu: Mutex::new(None),
+            storage_paths: vec![PathBuf::from(".")],
+        }
+    }
+
+    /// Report disk usage for the filesystems holding these paths instead of
+    /// the working directory.
+    pub fn with_storage_paths(mut self, paths: Vec<PathBuf>) -> Self {
+        if !paths.is_empty() {
+            self.storage_paths = paths;
         }
+        self
+    }
+
+    pub fn with_cgroup_reader(mut self, cgroup: CgroupReader) -> Self {
+        self.cgroup = cgroup;
+        self
     }
 
     pub fn collect_metrics(&self) -> SystemMetrics {
@@ -117,14 +158,14 @@ impl SystemMonitor {
         
         let timestamp = chrono::Utc::now();
         
-        let resource_usage = self.collect_resource_usage(&system);
+        let current_process = self.collect_current_process_info(&system);
+        
+        let resource_usage = self.collect_resource_usage(&system, current_process.as_ref());
         
         let disk_usage = self.collect_disk_usage(&system);
         
         let network_stats = self.collect_network_stats(&system);
         
-        let current_process = self.collect_current_process_info(&system);
-        
         let system_info = self.collect_system_info(&system);
         
         let metrics = SystemMetrics {
@@ -133,6 +174,7 @@ impl SystemMonitor {
             disk_usage,
             network_stats,
             current_process,
+            runtime: collect_runtime_stats(),
             system_info,
         };
         
@@ -143,16 +185,29 @@ impl SystemMonitor {
         metrics
     }
 
-    fn collect_resource_usage(&self, system: &System) -> ResourceUsage {
-        let cpu_usage = system.global_cpu_info().cpu_usage();
-        
-        let memory_total = system.total_memory();
-        let memory_used = system.used_memory();
+    fn collect_resource_usage(&self, system: &System, process: Option<&ProcessInfo>) -> ResourceUsage {
+        let host_cpu_usage = system.global_cpu_info().cpu_usage();
+        let host_memory_total = system.total_memory();
+        let host_memory_used = system.used_memory();
+
+        let cgroup = self.cgroup.read();
+        let (memory_used, memory_total) = match &cgroup {
+            Some(CgroupStats { memory_limit_bytes: Some(limit), memory_used_bytes: Some(used), .. })
+                if *limit < host_memory_total || host_memory_total == 0 => (*used, *limit),
+            _ => (host_memory_used, host_memory_total),
+        };
         let memory_usage_percent = if memory_total > 0 {
             (memory_used as f64 / memory_total as f64) * 100.0
         } else {
             0.0
         };
+
+        let cpu_usage = match &cgroup {
+            Some(stats @ CgroupStats { cpu_limit_cores: Some(cores), .. }) if *cores > 0.0 => {
+                self.container_cpu_percent(stats, *cores, process)
+            }
+            _ => host_cpu_usage,
+        };
         
         let swap_total = system.total_swap();
         let swap_used = system.used_swap();
@@ -177,6 +232,10 @@ impl SystemMonitor {
             memory_used_bytes: memory_used,
             memory_total_bytes: memory_total,
             memory_usage_percent,
+            host_cpu_usage_percent: host_cpu_usage,
+            host_memory_used_bytes: host_memory_used,
+            host_memory_total_bytes: host_memory_total,
+            container: cgroup.as_ref().map(CgroupStats::limits),
             swap_used_bytes: swap_used,
             swap_total_bytes: swap_total,
             swap_usage_percent,
@@ -186,28 +245,53 @@ impl SystemMonitor {
         }
     }
 
+    /// CPU used by the whole cgroup since the previous sample, as a share of
+    /// its quota. The first sample has no baseline, so it falls back to this
+    /// process's own usage.
+    fn container_cpu_percent(&self, stats: &CgroupStats, cores: f64, process: Option<&ProcessInfo>) -> f32 {
+        let now = Instant::now();
+        let previous = match stats.cpu_usage_usec {
+            Some(usage) => self.last_cgroup_cpu.lock().unwrap().replace((now, usage)),
+            None => None,
+        };
+
+        let percent = match (previous, stats.cpu_usage_usec) {
+            (Some((at, before)), Some(usage)) if now > at => {
+                let elapsed_usec = now.duration_since(at).as_micros() as f64;
+                usage.saturating_sub(before) as f64 / (elapsed_usec * cores) * 100.0
+            }
+            _ => process.map_or(0.0, |process| process.cpu_usage as f64 / cores),
+        };
+
+        percent.clamp(0.0, 100.0) as f32
+    }
+
     fn collect_disk_usage(&self, _system: &System) -> Vec<DiskUsage> {
         let disks = sysinfo::Disks::new_with_refreshed_list();
-        disks.iter().map(|disk| {
-            let total_bytes = disk.total_space();
-            let available_bytes = disk.available_space();
-            let used_bytes = total_bytes - available_bytes;
-            let usage_percent = if total_bytes > 0 {
-                (used_bytes as f64 / total_bytes as f64) * 100.0
-            } else {
-                0.0
-            };
-            
-            DiskUsage {
-                name: disk.name().to_string_lossy().to_string(),
-                mount_point: disk.mount_point().to_string_lossy().to_string(),
-                total_bytes,
-                available_bytes,
-                used_bytes,
-                usage_percent,
-                file_system: disk.file_system().to_string_lossy().to_string(),
+        let mount_points: Vec<PathBuf> = disks.iter().map(|disk| disk.mount_point().to_path_buf()).collect();
+
+        let mut paths_by_disk: Vec<Vec<String>> = vec![Vec::new(); mount_points.len()];
+        for path in &self.storage_paths {
+            if let Some(index) = filesystem_containing(path, &mount_points) {
+                paths_by_disk[index].push(path.display().to_string());
             }
-        }).collect()
+        }
+
+        disks
+            .iter()
+            .zip(paths_by_disk)
+            .filter(|(_, paths)| !paths.is_empty())
+            .map(|(disk, paths)| disk_usage(disk, paths))
+            .collect()
+    }
+
+    /// Usage of the filesystem holding `path`, or `None` when it cannot be
+    /// told which one that is.
+    pub fn disk_usage_for(path: &Path) -> Option<DiskUsage> {
+        let disks = sysinfo::Disks::new_with_refreshed_list();
+        let mount_points: Vec<PathBuf> = disks.iter().map(|disk| disk.mount_point().to_path_buf()).collect();
+        let index = filesystem_containing(path, &mount_points)?;
+        Some(disk_usage(&disks[index], vec![path.display().to_string()]))
     }
 
     fn collect_network_stats(&self, _system: &System) -> Vec<NetworkStats> {
@@ -234,6 +318,8 @@ impl SystemMonitor {
                     cpu_usage: process.cpu_usage(),
                     memory_bytes: process.memory(),
                     virtual_memory_bytes: process.virtual_memory(),
+                    open_file_descriptors: container::open_file_descriptors(),
+                    thread_count: container::thread_count(),
                     status: format!("{:?}", process.status()),
                     start_time: process.start_time(),
                 });
@@ -283,15 +369,8 @@ impl SystemMonitor {
     pub fn get_performance_metrics(&self, app_metrics: &crate::metrics::MetricsSnapshot) -> PerformanceMetrics {
         let history = self.get_metrics_history();
         
-        let mut response_times: Vec<f64> = app_metrics.last_hour_response_times
-            .iter()
-            .map(|rt| rt.duration_ms as f64)
-            .collect();
-        response_times.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        
-        let p50 = percentile(&response_times, 50.0);
-        let p95 = percentile(&response_times, 95.0);
-        let p99 = percentile(&response_times, 99.0);
+        let percentiles = app_metrics.response_time_percentiles;
+        let (p50, p95, p99) = (percentiles.p50_ms, percentiles.p95_ms, percentiles.p99_ms);
         
         let recent_count = 10.min(history.len());
         let recent_metrics = if history.len() >= recent_count {
@@ -346,7 +425,11 @@ impl SystemMonitor {
         }
         
         if let Some(process) = &metrics.current_process {
-            if process.memory_bytes > 1024 * 1024 * 1024 { // 1GB
+            // Inside a memory-limited container the process is killed at the
+            // limit, so warn well before it; otherwise keep a fixed 1GB ceiling.
+            let container_limit = metrics.resource_usage.container.as_ref().and_then(|c| c.memory_limit_bytes);
+            let threshold = container_limit.map_or(1024 * 1024 * 1024, |limit| limit / 10 * 8);
+            if process.memory_bytes > threshold {
                 alerts.push(format!("High process memory usage: {:.1} MB", 
                     process.memory_bytes as f64 / (1024.0 * 1024.0)));
             }
@@ -356,34 +439,58 @@ impl SystemMonitor {
     }
 }
 
-impl Default for SystemMonitor {
-    fn default() -> Self {
-        Self::new()
+fn disk_usage(disk: &sysinfo::Disk, paths: Vec<String>) -> DiskUsage {
+    let total_bytes = disk.total_space();
+    let available_bytes = disk.available_space();
+    let used_bytes = total_bytes - available_bytes;
+    let usage_percent = if total_bytes > 0 {
+        (used_bytes as f64 / total_bytes as f64) * 100.0
+    } else {
+        0.0
+    };
+
+    DiskUsage {
+        name: disk.name().to_string_lossy().to_string(),
+        mount_point: disk.mount_point().to_string_lossy().to_string(),
+        total_bytes,
+        available_bytes,
+        used_bytes,
+        usage_percent,
+        file_system: disk.file_system().to_string_lossy().to_string(),
+        paths,
     }
 }
 
-fn percentile(sorted_values: &[f64], percentile: f64) -> f64 {
-    if sorted_values.is_empty() {
-        return 0.0;
-    }
-    
-    if percentile <= 0.0 {
-        return sorted_values[0];
-    }
-    
-    if percentile >= 100.0 {
-        return sorted_values[sorted_values.len() - 1];
-    }
-    
-    let index = (percentile / 100.0) * (sorted_values.len() - 1) as f64;
-    let lower_index = index.floor() as usize;
-    let upper_index = index.ceil() as usize;
-    
-    if lower_index == upper_index {
-        sorted_values[lower_index]
-    } else {
-        let weight = index - lower_index as f64;
-        sorted_values[lower_index] * (1.0 - weight) + sorted_values[upper_index] * weight
+/// Index of the mount point in `mount_points` that holds `path`: the longest
+/// one that is a prefix of the path's nearest existing ancestor.
+fn filesystem_containing(path: &Path, mount_points: &[PathBuf]) -> Option<usize> {
+    let resolved = path
+        .ancestors()
+        .find_map(|ancestor| {
+            let ancestor = if ancestor.as_os_str().is_empty() { Path::new(".") } else { ancestor };
+            ancestor.canonicalize().ok()
+        })?;
+
+    mount_points
+        .iter()
+        .enumerate()
+        .filter(|(_, mount)| resolved.starts_with(mount))
+        .max_by_key(|(_, mount)| mount.components().count())
+        .map(|(index, _)| index)
+}
+
+fn collect_runtime_stats() -> Option<RuntimeStats> {
+    let metrics = tokio::runtime::Handle::try_current().ok()?.metrics();
+    Some(RuntimeStats {
+        worker_threads: metrics.num_workers(),
+        alive_tasks: metrics.num_alive_tasks(),
+        global_queue_depth: metrics.global_queue_depth(),
+    })
+}
+
+impl Default for SystemMonitor {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -407,14 +514,6 @@ mod tests {
         assert!(!metrics.disk_usage.is_empty());
     }
 
-    #[test]
-    fn test_percentile_calculation() {
-        let values = vec![1.0, 2.0, 3.0, 4.0, 5.0];
-        assert_eq!(percentile(&values, 50.0), 3.0);
-        assert_eq!(percentile(&values, 0.0), 1.0);
-        assert_eq!(percentile(&values, 100.0), 5.0);
-    }
-
     #[test]
     fn test_resource_alerts() {
         let monitor = SystemMonitor::new();
@@ -424,4 +523,70 @@ mod tests {
         let alerts = monitor.check_resource_alerts(&metrics);
         assert!(alerts.iter().any(|alert| alert.contains("High CPU usage")));
     }
+
+    fn cgroup_v2(memory_max: &str, memory_current: &str) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        for (name, contents) in [
+            ("cgroup.controllers", "cpu memory"),
+            ("memory.max", memory_max),
+            ("memory.current", memory_current),
+            ("cpu.max", "50000 100000"),
+            ("cpu.stat", "usage_usec 1000"),
+        ] {
+            std::fs::write(root.path().join(name), contents).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn test_memory_is_relative_to_container_limit() {
+        let root = cgroup_v2("268435456", "201326592");
+        let monitor = SystemMonitor::new().with_cgroup_reader(CgroupReader::new(root.path(), "0::/\n"));
+
+        let metrics = monitor.collect_metrics();
+        let usage = &metrics.resource_usage;
+        assert_eq!(usage.memory_total_bytes, 268435456);
+        assert_eq!(usage.memory_used_bytes, 201326592);
+        assert_eq!(usage.memory_usage_percent, 75.0);
+        assert!(usage.host_memory_total_bytes > 0);
+        assert_eq!(usage.container.as_ref().unwrap().cpu_limit_cores, Some(0.5));
+
+        let mut limited = metrics.clone();
+        limited.resource_usage.memory_usage_percent = 92.0;
+        limited.current_process.as_mut().unwrap().memory_bytes = 230_000_000;
+        let alerts = monitor.check_resource_alerts(&limited);
+        assert!(alerts.iter().any(|alert| alert.contains("High memory usage")));
+        assert!(alerts.iter().any(|alert| alert.contains("High process memory usage")));
+    }
+
+    #[test]
+    fn test_disk_usage_reports_storage_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = dir.path().join("data/app.db");
+        let monitor = SystemMonitor::new().with_storage_paths(vec![database.clone(), dir.path().to_path_buf()]);
+
+        let disks = monitor.collect_metrics().disk_usage;
+        assert_eq!(disks.len(), 1);
+        assert_eq!(disks[0].paths, vec![database.display().to_string(), dir.path().display().to_string()]);
+
+        let resolved = dir.path().canonicalize().unwrap();
+        let mounts = vec![PathBuf::from("/"), resolved.join("data"), resolved.clone()];
+        assert_eq!(filesystem_containing(&database, &mounts), Some(2));
+        std::fs::create_dir(resolved.join("data")).unwrap();
+        assert_eq!(filesystem_containing(&database, &mounts), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_process_and_runtime_stats() {
+        let metrics = SystemMonitor::new().collect_metrics();
+        let runtime = metrics.runtime.expect("collected inside a runtime");
+        assert!(runtime.worker_threads >= 1);
+
+        let process = metrics.current_process.unwrap();
+        assert!(process.memory_bytes > 0);
+        if cfg!(target_os = "linux") {
+            assert!(process.open_file_descriptors.unwrap() > 0);
+            assert!(process.thread_count.unwrap() >= 1);
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,327 @@
+//! Hourly request totals behind the dashboard heatmap
+
+use crate::error::{AppError, Result};
+use crate::metrics::MetricsCollector;
+use crate::supervisor::Supervisor;
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqlitePool};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Hours of totals kept in memory and shown by the heatmap.
+pub const TRAFFIC_HOURS: usize = 24;
+
+/// Persisted hours older than this are pruned.
+const RETENTION_DAYS: i64 = 7;
+
+/// Requests served during one clock hour.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HourlyTraffic {
+    /// Start of the hour.
+    pub hour: DateTime<Utc>,
+    pub requests: u64,
+    /// Responses with a 5xx status.
+    pub errors: u64,
+    pub average_ms: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct HourBucket {
+    hour: i64,
+    requests: u64,
+    errors: u64,
+    total_duration_ms: u64,
+}
+
+impl HourBucket {
+    fn traffic(&self) -> HourlyTraffic {
+        HourlyTraffic {
+            hour: hour_start(self.hour),
+            requests: self.requests,
+            errors: self.errors,
+            average_ms: if self.requests == 0 {
+                0.0
+            } else {
+                self.total_duration_ms as f64 / self.requests as f64
+            },
+        }
+    }
+}
+
+fn unix_hour(timestamp: DateTime<Utc>) -> i64 {
+    timestamp.timestamp().div_euclid(3600)
+}
+
+fn hour_start(hour: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(hour * 3600, 0).single().unwrap_or_default()
+}
+
+/// Per-hour totals for the last [`TRAFFIC_HOURS`] hours.
+#[derive(Debug, Clone, Default)]
+pub struct TrafficHistory {
+    buckets: VecDeque<HourBucket>,
+}
+
+impl TrafficHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn bucket_mut(&mut self, hour: i64) -> &mut HourBucket {
+        let position = self.buckets.iter().rposition(|bucket| bucket.hour <= hour);
+        let index = match position {
+            Some(index) if self.buckets[index].hour == hour => index,
+            _ => {
+                let index = position.map_or(0, |index| index + 1);
+                self.buckets.insert(index, HourBucket { hour, ..HourBucket::default() });
+                index
+            }
+        };
+        &mut self.buckets[index]
+    }
+
+    fn expire(&mut self) {
+        let Some(newest) = self.buckets.back().map(|bucket| bucket.hour) else {
+            return;
+        };
+        while self
+            .buckets
+            .front()
+            .is_some_and(|bucket| bucket.hour <= newest - TRAFFIC_HOURS as i64)
+        {
+            self.buckets.pop_front();
+        }
+    }
+
+    pub fn record(&mut self, timestamp: DateTime<Utc>, duration_ms: u64, status: u16) {
+        let bucket = self.bucket_mut(unix_hour(timestamp));
+        bucket.requests += 1;
+        bucket.total_duration_ms = bucket.total_duration_ms.saturating_add(duration_ms);
+        if status >= 500 {
+            bucket.errors += 1;
+        }
+        self.expire();
+    }
+
+    /// Adds totals read back from a [`TrafficStore`] to the hours already
+    /// recorded since startup.
+    pub fn restore(&mut self, hours: &[StoredHour]) {
+        for stored in hours {
+            let bucket = self.bucket_mut(unix_hour(stored.hour));
+            bucket.requests += stored.requests;
+            bucket.errors += stored.errors;
+            bucket.total_duration_ms = bucket.total_duration_ms.saturating_add(stored.total_duration_ms);
+        }
+        self.expire();
+    }
+
+    /// One entry per hour for the [`TRAFFIC_HOURS`] hours ending with the
+    /// current one, oldest first; hours without requests are zero.
+    pub fn hours(&self, now: DateTime<Utc>) -> Vec<HourlyTraffic> {
+        let current = unix_hour(now);
+        (current - TRAFFIC_HOURS as i64 + 1..=current)
+            .map(|hour| {
+                self.buckets
+                    .iter()
+                    .find(|bucket| bucket.hour == hour)
+                    .copied()
+                    .unwrap_or(HourBucket { hour, ..HourBucket::default() })
+                    .traffic()
+            })
+            .collect()
+    }
+
+    /// Raw totals of the hours starting at or after `since`, for persisting.
+    pub fn stored_since(&self, since: DateTime<Utc>) -> Vec<StoredHour> {
+        let since = unix_hour(since);
+        self.buckets
+            .iter()
+            .filter(|bucket| bucket.hour >= since)
+            .map(|bucket| StoredHour {
+                hour: hour_start(bucket.hour),
+                requests: bucket.requests,
+                errors: bucket.errors,
+                total_duration_ms: bucket.total_duration_ms,
+            })
+            .collect()
+    }
+}
+
+/// Totals of one hour as kept in the `traffic_hourly` table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StoredHour {
+    pub hour: DateTime<Utc>,
+    pub requests: u64,
+    pub errors: u64,
+    pub total_duration_ms: u64,
+}
+
+fn timestamp_text(timestamp: DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Persists hourly totals so the heatmap survives restarts.
+#[derive(Clone)]
+pub struct TrafficStore {
+    pool: SqlitePool,
+}
+
+impl TrafficStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// Writes the given totals, replacing whatever was stored for each hour,
+    /// and prunes hours past retention.
+    pub async fn save(&self, hours: &[StoredHour]) -> Result<()> {
+        let now = timestamp_text(Utc::now());
+        for stored in hours {
+            sqlx::query(
+                r#"
+                INSERT INTO traffic_hourly (hour_start, requests, errors, total_duration_ms, updated_at)
+                VALUES (?, ?, ?, ?, ?)
+                ON CONFLICT(hour_start) DO UPDATE SET
+                    requests = excluded.requests,
+                    errors = excluded.errors,
+                    total_duration_ms = excluded.total_duration_ms,
+                    updated_at = excluded.updated_at
+                "#,
+            )
+            .bind(timestamp_text(stored.hour))
+            .bind(stored.requests as i64)
+            .bind(stored.errors as i64)
+            .bind(stored.total_duration_ms as i64)
+            .bind(&now)
+            .execute(&self.pool)
+            .await?;
+        }
+
+        sqlx::query("DELETE FROM traffic_hourly WHERE hour_start < ?")
+            .bind(timestamp_text(Utc::now() - chrono::Duration::days(RETENTION_DAYS)))
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Stored totals of the hour containing `since` and every later hour.
+    pub async fn load_since(&self, since: DateTime<Utc>) -> Result<Vec<StoredHour>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT hour_start, requests, errors, total_duration_ms
+            FROM traffic_hourly
+            WHERE hour_start >= ?
+            ORDER BY hour_start
+            "#,
+        )
+        .bind(timestamp_text(hour_start(unix_hour(since))))
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                let hour: String = row.try_get("hour_start")?;
+                Ok(StoredHour {
+                    hour: DateTime::parse_from_rfc3339(&hour)
+                        .map_err(|e| AppError::Database(format!("Invalid traffic hour: {}", e)))?
+                        .with_timezone(&Utc),
+                    requests: row.try_get::<i64, _>("requests")?.max(0) as u64,
+                    errors: row.try_get::<i64, _>("errors")?.max(0) as u64,
+                    total_duration_ms: row.try_get::<i64, _>("total_duration_ms")?.max(0) as u64,
+                })
+            })
+            .collect()
+    }
+}
+
+fn window_start() -> DateTime<Utc> {
+    Utc::now() - chrono::Duration::hours(TRAFFIC_HOURS as i64 - 1)
+}
+
+/// Loads the stored heatmap window into `metrics`, then keeps writing the
+/// current and previous hour back every `interval`. Call once at startup,
+/// before the first flush could overwrite stored totals.
+pub async fn spawn_persistence(
+    store: TrafficStore,
+    metrics: MetricsCollector,
+    interval: Duration,
+    supervisor: &Supervisor,
+) -> tokio::task::JoinHandle<()> {
+    match store.load_since(window_start()).await {
+        Ok(hours) => metrics.traffic.write().restore(&hours),
+        Err(e) => tracing::warn!("Failed to load stored traffic totals: {}", e),
+    }
+
+    supervisor.spawn("traffic_persistence", move || {
+        let store = store.clone();
+        let metrics = metrics.clone();
+        async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                // The previous hour is included so requests recorded just
+                // before the hour rolled over are not lost.
+                let since = Utc::now() - chrono::Duration::hours(1);
+                let hours = metrics.traffic.read().stored_since(since);
+                if let Err(e) = store.save(&hours).await {
+                    tracing::warn!("Failed to persist traffic totals: {}", e);
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hours_cover_the_last_day() {
+        let mut history = TrafficHistory::new();
+        let now = Utc.with_ymd_and_hms(2024, 5, 2, 13, 20, 0).unwrap();
+
+        history.record(Utc.with_ymd_and_hms(2024, 5, 1, 9, 0, 0).unwrap(), 10, 200);
+        for _ in 0..50 {
+            history.record(Utc.with_ymd_and_hms(2024, 5, 2, 12, 30, 0).unwrap(), 20, 200);
+        }
+        history.record(Utc.with_ymd_and_hms(2024, 5, 2, 12, 45, 0).unwrap(), 20, 503);
+        history.record(now, 5, 200);
+
+        let hours = history.hours(now);
+        assert_eq!(hours.len(), TRAFFIC_HOURS);
+        assert_eq!(hours[0].hour, Utc.with_ymd_and_hms(2024, 5, 1, 14, 0, 0).unwrap());
+        assert_eq!(hours[22].requests, 51);
+        assert_eq!(hours[22].errors, 1);
+        assert_eq!(hours[22].average_ms, 20.0);
+        assert_eq!(hours[23].requests, 1);
+        assert_eq!(hours.iter().map(|hour| hour.requests).sum::<u64>(), 52);
+    }
+
+    #[tokio::test]
+    async fn test_totals_survive_a_restart() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::database::run_migrations(pool.clone()).await.unwrap();
+        let store = TrafficStore::new(pool);
+        let now = Utc::now();
+
+        let mut before = TrafficHistory::new();
+        before.record(now, 40, 500);
+        before.record(now, 60, 200);
+        store.save(&before.stored_since(window_start())).await.unwrap();
+
+        let mut after = TrafficHistory::new();
+        after.record(now, 100, 200);
+        after.restore(&store.load_since(window_start()).await.unwrap());
+
+        let current = after.hours(now)[TRAFFIC_HOURS - 1];
+        assert_eq!(current.requests, 3);
+        assert_eq!(current.errors, 1);
+        assert_eq!(current.average_ms, 200.0 / 3.0);
+    }
+}
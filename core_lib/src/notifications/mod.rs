@@ -0,0 +1,108 @@
+//! Account notifications delivered through a pluggable [`Notifier`]
+
+pub mod smtp;
+pub mod templates;
+
+pub use smtp::SmtpNotifier;
+pub use templates::{
+    RenderedMessage, TemplateStore, EMAIL_CHANGED, EMAIL_CHANGE_CONFIRMATION, EMAIL_VERIFICATION, NEW_DEVICE_LOGIN,
+    PASSWORD_CHANGED, REFRESH_TOKEN_REUSED, REGISTRATION_WELCOME,
+};
+
+use crate::config::{NotificationConfig, NotifierBackend};
+use crate::error::Result;
+use crate::jobs::{JobPriority, JobQueue, JobRequest, JobType};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// Renders the template `template_id` with `context` and delivers it to
+/// `recipient`. Implementations may be slow; callers enqueue notifications
+/// as background jobs rather than awaiting delivery.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn send(&self, template_id: &str, recipient: &str, context: &Value) -> Result<()>;
+}
+
+/// Logs rendered notifications; the default for development.
+#[derive(Debug, Clone, Default)]
+pub struct LogNotifier {
+    templates: TemplateStore,
+}
+
+impl LogNotifier {
+    pub fn new(templates: TemplateStore) -> Self {
+        Self { templates }
+    }
+}
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    async fn send(&self, template_id: &str, recipient: &str, context: &Value) -> Result<()> {
+        let message = self.templates.render(template_id, context)?;
+        tracing::info!(
+            template = template_id,
+            recipient = recipient,
+            subject = %message.subject,
+            "Notification:\n{}",
+            message.body
+        );
+        Ok(())
+    }
+}
+
+pub fn notifier_from_config(config: &NotificationConfig) -> Result<Arc<dyn Notifier>> {
+    let templates = TemplateStore::from_config(config)?;
+
+    Ok(match config.backend {
+        NotifierBackend::Log => Arc::new(LogNotifier::new(templates)),
+        NotifierBackend::Smtp => Arc::new(SmtpNotifier::new(config, templates)),
+    })
+}
+
+/// Enqueues notifications as `Notification` jobs so delivery happens off the
+/// request path and failed sends are retried by the job workers.
+#[derive(Clone)]
+pub struct NotificationDispatcher {
+    job_queue: JobQueue,
+    max_retries: i32,
+}
+
+impl NotificationDispatcher {
+    pub fn new(job_queue: JobQueue) -> Self {
+        Self {
+            job_queue,
+            max_retries: 3,
+        }
+    }
+
+    pub fn with_max_retries(mut self, max_retries: i32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Queues a notification. Failing to queue is logged and swallowed: a
+    /// missed email must never fail the operation that triggered it.
+    pub async fn dispatch(&self, template_id: &str, recipient: &str, context: Value) {
+        let request = JobRequest {
+            job_type: JobType::Notification,
+            payload: json!({
+                "template_id": template_id,
+                "recipient": recipient,
+                "context": context,
+            }),
+            priority: Some(JobPriority::Normal),
+            max_retries: Some(self.max_retries),
+        };
+
+        if let Err(e) = self.job_queue.submit_job(request).await {
+            tracing::warn!("Failed to queue {} notification for {}: {}", template_id, recipient, e);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! Minimal SMTP client for relaying notifications through a local MTA
+
+use super::templates::{RenderedMessage, TemplateStore};
+use super::Notifier;
+use crate::config::NotificationConfig;
+use crate::error::{AppError, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+/// Sends plain-text mail over unencrypted, unauthenticated SMTP. Intended
+/// for a relay on the same host or private network that handles TLS and
+/// onward delivery.
+#[derive(Debug, Clone)]
+pub struct SmtpNotifier {
+    host: String,
+    port: u16,
+    from_address: String,
+    timeout: Duration,
+    templates: TemplateStore,
+}
+
+impl SmtpNotifier {
+    pub fn new(config: &NotificationConfig, templates: TemplateStore) -> Self {
+        Self {
+            host: config.smtp_host.clone(),
+            port: config.smtp_port,
+            from_address: config.from_address.clone(),
+            timeout: Duration::from_secs(config.smtp_timeout_seconds.max(1)),
+            templates,
+        }
+    }
+
+    async fn deliver(&self, recipient: &str, message: &RenderedMessage) -> Result<()> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        let mut session = SmtpSession::new(stream);
+
+        session.expect(&[220]).await?;
+        session.command("EHLO localhost", &[250]).await?;
+        session.command(&format!("MAIL FROM:<{}>", self.from_address), &[250]).await?;
+        session.command(&format!("RCPT TO:<{}>", recipient), &[250, 251]).await?;
+        session.command("DATA", &[354]).await?;
+        session.send_data(&self.format_message(recipient, message)).await?;
+        session.expect(&[250]).await?;
+        // The message is accepted once DATA completes; a failed QUIT is not worth a retry.
+        let _ = session.command("QUIT", &[221]).await;
+
+        Ok(())
+    }
+
+    fn format_message(&self, recipient: &str, message: &RenderedMessage) -> String {
+        let subject: String = message.subject.chars().filter(|c| !c.is_control()).collect();
+        let domain = self.from_address.rsplit('@').next().unwrap_or("localhost");
+
+        format!(
+            "From: <{from}>\r\nTo: <{to}>\r\nSubject: {subject}\r\nDate: {date}\r\nMessage-ID: <{id}@{domain}>\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n{body}",
+            from = self.from_address,
+            to = recipient,
+            subject = subject,
+            date = chrono::Utc::now().to_rfc2822(),
+            id = uuid::Uuid::new_v4(),
+            domain = domain,
+            body = message.body,
+        )
+    }
+}
+
+#[async_trait]
+impl Notifier for SmtpNotifier {
+    fn name(&self) -> &'static str {
+        "smtp"
+    }
+
+    async fn send(&self, template_id: &str, recipient: &str, context: &Value) -> Result<()> {
+        if recipient.is_empty() || recipient.chars().any(|c| c.is_control() || c == '<' || c == '>') {
+            return Err(AppError::BadRequest(format!("Invalid notification recipient '{}'", recipient)));
+        }
+
+        let message = self.templates.render(template_id, context)?;
+
+        tokio::time::timeout(self.timeout, self.deliver(recipient, &message))
+            .await
+            .map_err(|_| AppError::Job(format!("SMTP delivery to {}:{} timed out", self.host, self.port)))?
+    }
+}
+
+struct SmtpSession {
+    stream: BufReader<TcpStream>,
+}
+
+impl SmtpSession {
+    fn new(stream: TcpStream) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    async fn command(&mut self, line: &str, accepted: &[u16]) -> Result<u16> {
+        self.stream.get_mut().write_all(format!("{}\r\n", line).as_bytes()).await?;
+        self.expect(accepted).await
+    }
+
+    /// Reads a possibly multi-line reply and checks its status code.
+    async fn expect(&mut self, accepted: &[u16]) -> Result<u16> {
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).await? == 0 {
+                return Err(AppError::Job("SMTP server closed the connection".to_string()));
+            }
+
+            let code = line
+                .get(..3)
+                .and_then(|code| code.parse::<u16>().ok())
+                .ok_or_else(|| AppError::Job(format!("Malformed SMTP reply: {}", line.trim_end())))?;
+
+            if line.as_bytes().get(3) == Some(&b'-') {
+                continue;
+            }
+
+            if accepted.contains(&code) {
+                return Ok(code);
+            }
+            return Err(AppError::Job(format!("SMTP server replied: {}", line.trim_end())));
+        }
+    }
+
+    /// Writes the message with CRLF line endings and dot-stuffing, followed
+    /// by the terminating `.` line.
+    async fn send_data(&mut self, message: &str) -> Result<()> {
+        let mut data = String::with_capacity(message.len() + 16);
+        for line in message.replace("\r\n", "\n").split('\n') {
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+        data.push_str(".\r\n");
+
+        self.stream.get_mut().write_all(data.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    /// Accepts one SMTP session and returns the DATA payload it received.
+    async fn fake_smtp_server(listener: TcpListener, reject_recipient: bool) -> Option<String> {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+        stream.get_mut().write_all(b"220 test ESMTP\r\n").await.unwrap();
+
+        let mut data = String::new();
+        let mut in_data = false;
+        loop {
+            let mut line = String::new();
+            if stream.read_line(&mut line).await.unwrap() == 0 {
+                return None;
+            }
+
+            if in_data {
+                if line == ".\r\n" {
+                    in_data = false;
+                    stream.get_mut().write_all(b"250 queued\r\n").await.unwrap();
+                } else {
+                    data.push_str(&line);
+                }
+                continue;
+            }
+
+            let reply: &[u8] = match line.split_whitespace().next().unwrap_or_default() {
+                "EHLO" => b"250-test\r\n250 8BITMIME\r\n",
+                "RCPT" if reject_recipient => b"550 no such user\r\n",
+                "DATA" => {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                }
+                "QUIT" => {
+                    stream.get_mut().write_all(b"221 bye\r\n").await.unwrap();
+                    return Some(data);
+                }
+                _ => b"250 ok\r\n",
+            };
+            stream.get_mut().write_all(reply).await.unwrap();
+        }
+    }
+
+    async fn notifier_for(listener: &TcpListener) -> SmtpNotifier {
+        let config = NotificationConfig {
+            smtp_host: "127.0.0.1".to_string(),
+            smtp_port: listener.local_addr().unwrap().port(),
+            from_address: "accounts@example.com".to_string(),
+            ..NotificationConfig::default()
+        };
+        SmtpNotifier::new(&config, TemplateStore::default())
+    }
+
+    #[tokio::test]
+    async fn test_smtp_delivery() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let notifier = notifier_for(&listener).await;
+        let server = tokio::spawn(fake_smtp_server(listener, false));
+
+        notifier
+            .send("registration_welcome", "alice@example.com", &json!({"username": "alice"}))
+            .await
+            .unwrap();
+
+        let data = server.await.unwrap().unwrap();
+        assert!(data.contains("To: <alice@example.com>\r\n"));
+        assert!(data.contains("Subject: Welcome, alice\r\n"));
+        assert!(data.contains("Hi alice,"));
+    }
+
+    #[tokio::test]
+    async fn test_smtp_rejection_is_an_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let notifier = notifier_for(&listener).await;
+        tokio::spawn(fake_smtp_server(listener, true));
+
+        let result = notifier
+            .send("registration_welcome", "nobody@example.com", &json!({"username": "x"}))
+            .await;
+        assert!(result.unwrap_err().to_string().contains("550"));
+    }
+}
@@ -0,0 +1,227 @@
+//! Notification templates with `{{placeholder}}` substitution
+
+use crate::config::{NotificationConfig, NotificationTemplate};
+use crate::error::{AppError, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const REGISTRATION_WELCOME: &str = "registration_welcome";
+pub const NEW_DEVICE_LOGIN: &str = "new_device_login";
+pub const PASSWORD_CHANGED: &str = "password_changed";
+pub const EMAIL_CHANGE_CONFIRMATION: &str = "email_change_confirmation";
+pub const EMAIL_CHANGED: &str = "email_changed";
+pub const EMAIL_VERIFICATION: &str = "email_verification";
+pub const REFRESH_TOKEN_REUSED: &str = "refresh_token_reused";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedMessage {
+    pub subject: String,
+    pub body: String,
+}
+
+fn builtin_templates() -> Vec<NotificationTemplate> {
+    let template = |id: &str, subject: &str, body: &str| NotificationTemplate {
+        id: id.to_string(),
+        subject: subject.to_string(),
+        body: body.to_string(),
+    };
+
+    vec![
+        template(
+            REGISTRATION_WELCOME,
+            "Welcome, {{username}}",
+            "Hi {{username}},\n\nYour account has been created. You can now sign in with the username {{username}}.\n",
+        ),
+        template(
+            NEW_DEVICE_LOGIN,
+            "New sign-in to your account",
+            "Hi {{username}},\n\nYour account was signed in from a new device at {{time}}.\n\nDevice: {{user_agent}}\nNetwork: {{network}}\n\nIf this wasn't you, change your password and sign out your other sessions.\n",
+        ),
+        template(
+            PASSWORD_CHANGED,
+            "Your password was changed",
+            "Hi {{username}},\n\nThe password for your account was changed at {{time}}.\n\nIf you did not make this change, contact an administrator immediately.\n",
+        ),
+        template(
+            EMAIL_CHANGE_CONFIRMATION,
+            "Confirm your new email address",
+            "Hi {{username}},\n\nTo use {{new_email}} for your account, confirm the change with this code within 24 hours:\n\n{{token}}\n\nIf you did not ask for this, you can ignore this message.\n",
+        ),
+        template(
+            EMAIL_CHANGED,
+            "Your email address was changed",
+            "Hi {{username}},\n\nThe email address for your account was changed to {{new_email}} at {{time}}.\n\nIf you did not make this change, contact an administrator immediately.\n",
+        ),
+        template(
+            EMAIL_VERIFICATION,
+            "Verify your email address",
+            "Hi {{username}},\n\nYour account has been created. Verify this email address with the following code within {{hours}} hours:\n\n{{token}}\n\nIf you did not sign up, you can ignore this message.\n",
+        ),
+        template(
+            REFRESH_TOKEN_REUSED,
+            "A sign-in to your account was ended",
+            "Hi {{username}},\n\nAt {{time}} a sign-in token for your account was used after it had already been replaced, which usually means it was copied. That sign-in has been ended and you will need to sign in again on that device.\n\nIf this keeps happening, change your password and sign out your other sessions.\n",
+        ),
+    ]
+}
+
+/// Built-in templates overridden by `templates_dir` files and then by
+/// templates defined inline in the configuration.
+#[derive(Debug, Clone)]
+pub struct TemplateStore {
+    templates: HashMap<String, NotificationTemplate>,
+}
+
+impl TemplateStore {
+    pub fn from_config(config: &NotificationConfig) -> Result<Self> {
+        let mut templates: HashMap<String, NotificationTemplate> = builtin_templates()
+            .into_iter()
+            .map(|template| (template.id.clone(), template))
+            .collect();
+
+        if let Some(dir) = &config.templates_dir {
+            for template in load_template_dir(dir)? {
+                templates.insert(template.id.clone(), template);
+            }
+        }
+
+        for template in &config.templates {
+            templates.insert(template.id.clone(), template.clone());
+        }
+
+        Ok(Self { templates })
+    }
+
+    pub fn render(&self, template_id: &str, context: &Value) -> Result<RenderedMessage> {
+        let template = self
+            .templates
+            .get(template_id)
+            .ok_or_else(|| AppError::NotFound(format!("Notification template '{}' not found", template_id)))?;
+
+        Ok(RenderedMessage {
+            subject: render(&template.subject, context),
+            body: render(&template.body, context),
+        })
+    }
+}
+
+impl Default for TemplateStore {
+    fn default() -> Self {
+        Self {
+            templates: builtin_templates()
+                .into_iter()
+                .map(|template| (template.id.clone(), template))
+                .collect(),
+        }
+    }
+}
+
+fn load_template_dir(dir: &Path) -> Result<Vec<NotificationTemplate>> {
+    let mut templates = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("txt") {
+            continue;
+        }
+        let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+
+        let contents = std::fs::read_to_string(&path)?;
+        let (first_line, body) = contents.split_once('\n').unwrap_or((contents.as_str(), ""));
+        let subject = first_line.strip_prefix("Subject:").ok_or_else(|| {
+            AppError::Configuration(format!(
+                "Notification template {} must start with a 'Subject:' line",
+                path.display()
+            ))
+        })?;
+
+        templates.push(NotificationTemplate {
+            id: id.to_string(),
+            subject: subject.trim().to_string(),
+            body: body.trim_start_matches('\n').to_string(),
+        });
+    }
+
+    Ok(templates)
+}
+
+/// Replaces each `{{ key }}` with the matching value from `context`; dotted
+/// keys descend into nested objects. Missing keys render as empty strings
+/// and an unterminated `{{` is kept verbatim.
+pub fn render(template: &str, context: &Value) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after_open = &rest[start + 2..];
+
+        let Some(end) = after_open.find("}}") else {
+            output.push_str(&rest[start..]);
+            return output;
+        };
+
+        let key = after_open[..end].trim();
+        let value = key
+            .split('.')
+            .try_fold(context, |value, segment| value.get(segment));
+        match value {
+            Some(Value::String(text)) => output.push_str(text),
+            Some(Value::Null) | None => {}
+            Some(other) => output.push_str(&other.to_string()),
+        }
+
+        rest = &after_open[end + 2..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render_placeholders() {
+        let context = json!({"username": "alice", "count": 3, "device": {"os": "Linux"}});
+
+        assert_eq!(render("Hi {{username}}!", &context), "Hi alice!");
+        assert_eq!(render("{{ count }} on {{device.os}}", &context), "3 on Linux");
+        assert_eq!(render("missing: [{{nope}}]", &context), "missing: []");
+        assert_eq!(render("open {{username", &context), "open {{username");
+    }
+
+    #[test]
+    fn test_template_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("password_changed.txt"),
+            "Subject: Password update for {{username}}\n\nFile body\n",
+        )
+        .unwrap();
+
+        let config = NotificationConfig {
+            templates_dir: Some(dir.path().to_path_buf()),
+            templates: vec![NotificationTemplate {
+                id: REGISTRATION_WELCOME.to_string(),
+                subject: "Inline".to_string(),
+                body: "Inline body".to_string(),
+            }],
+            ..NotificationConfig::default()
+        };
+        let store = TemplateStore::from_config(&config).unwrap();
+        let context = json!({"username": "bob"});
+
+        let changed = store.render(PASSWORD_CHANGED, &context).unwrap();
+        assert_eq!(changed.subject, "Password update for bob");
+        assert_eq!(changed.body, "File body\n");
+        assert_eq!(store.render(REGISTRATION_WELCOME, &context).unwrap().subject, "Inline");
+        assert!(store.render(NEW_DEVICE_LOGIN, &context).unwrap().body.contains("Hi bob"));
+        assert!(store.render("unknown", &context).is_err());
+    }
+}
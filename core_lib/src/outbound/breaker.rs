@@ -0,0 +1,102 @@
+//! Per-destination circuit breaking, so a destination that keeps failing is
+//! left alone for a while instead of tying up workers with timeouts
+
+use crate::clock::SharedClock;
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Most destinations tracked at once. Past this, destinations whose circuit
+/// is closed are forgotten.
+const MAX_TRACKED_DESTINATIONS: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    open_until: Option<DateTime<Utc>>,
+}
+
+/// After `threshold` failures in a row a destination's circuit opens and
+/// calls to it fail at once for `open_for`. The first call after that is let
+/// through: success closes the circuit, failure opens it again.
+#[derive(Debug, Clone)]
+pub struct CircuitBreakers {
+    threshold: u32,
+    open_for: Duration,
+    clock: SharedClock,
+    circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+}
+
+impl CircuitBreakers {
+    pub fn new(threshold: u32, open_for: Duration, clock: SharedClock) -> Self {
+        Self {
+            threshold,
+            open_for,
+            clock,
+            circuits: Arc::default(),
+        }
+    }
+
+    /// Breakers with no history, timed by `clock`.
+    pub fn with_clock(&self, clock: SharedClock) -> Self {
+        Self::new(self.threshold, self.open_for, clock)
+    }
+
+    /// When the circuit of `destination` is open, the time it half-opens.
+    pub fn open_until(&self, destination: &str) -> Option<DateTime<Utc>> {
+        let now = self.clock.now();
+        self.circuits
+            .lock()
+            .get(destination)
+            .and_then(|circuit| circuit.open_until)
+            .filter(|until| *until > now)
+    }
+
+    pub fn record_success(&self, destination: &str) {
+        self.circuits.lock().remove(destination);
+    }
+
+    pub fn record_failure(&self, destination: &str) {
+        let mut circuits = self.circuits.lock();
+        if circuits.len() >= MAX_TRACKED_DESTINATIONS && !circuits.contains_key(destination) {
+            circuits.retain(|_, circuit| circuit.open_until.is_some());
+        }
+
+        let circuit = circuits.entry(destination.to_string()).or_default();
+        circuit.consecutive_failures += 1;
+        if circuit.consecutive_failures >= self.threshold {
+            let open_for = chrono::Duration::from_std(self.open_for).unwrap_or(chrono::Duration::MAX);
+            circuit.open_until = Some(self.clock.now() + open_for);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_circuit_opens_and_half_opens() {
+        let clock = MockClock::new(Utc::now());
+        let breakers = CircuitBreakers::new(2, Duration::from_secs(30), clock.shared());
+
+        breakers.record_failure("a:80");
+        assert_eq!(breakers.open_until("a:80"), None);
+        breakers.record_failure("a:80");
+        assert!(breakers.open_until("a:80").is_some());
+        assert_eq!(breakers.open_until("b:80"), None);
+
+        clock.advance(Duration::from_secs(31));
+        assert_eq!(breakers.open_until("a:80"), None);
+        breakers.record_failure("a:80");
+        assert!(breakers.open_until("a:80").is_some(), "a failed trial call opens the circuit again");
+
+        clock.advance(Duration::from_secs(31));
+        breakers.record_success("a:80");
+        breakers.record_failure("a:80");
+        assert_eq!(breakers.open_until("a:80"), None);
+    }
+}
@@ -0,0 +1,464 @@
+//! Outbound HTTP calls to URLs that users or config supply
+//!
+//! Everything the server sends to other services goes through
+//! [`OutboundHttp`], which gives every caller the same protections:
+//!
+//! - Destinations are resolved first and refused if any address is private,
+//!   loopback, link-local or otherwise internal, unless
+//!   `outbound.allowed_private_cidrs` allows it. The connection goes to the
+//!   vetted addresses, so the name cannot be re-resolved somewhere else in
+//!   between (DNS rebinding). Redirect targets are vetted the same way.
+//! - Connect and request timeouts, a cap on redirects and on response size.
+//! - Retries with exponential backoff for connection failures, 429 and 5xx.
+//! - A circuit breaker per destination, so one that keeps failing is skipped
+//!   for a while.
+//!
+//! Calls, failures and time spent are counted per destination in the
+//! metrics, and refused calls by reason.
+
+mod breaker;
+mod policy;
+mod signing;
+
+pub use breaker::CircuitBreakers;
+pub use policy::{parse_cidrs, Destination, DestinationPolicy, Resolve, SystemResolver};
+pub use signing::{sign, RequestSigner};
+
+use crate::clock::{SharedClock, SystemClock};
+use crate::config::OutboundConfig;
+use crate::error::{AppError, Result};
+use crate::metrics::{names, MetricsCollector, MetricsSink};
+use crate::net::IpCidr;
+use axum::body::{Body, Bytes};
+use chrono::{DateTime, Utc};
+use http::{header, HeaderMap, Method, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{self, OwnedTrustAnchor, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+
+const USER_AGENT: &str = concat!("rust-http-server/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum OutboundError {
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(String),
+    /// The destination resolves to an address the policy refuses.
+    #[error("{0}")]
+    Blocked(String),
+    #[error("{0}")]
+    Resolve(String),
+    #[error("Not calling {destination} after repeated failures until {until}")]
+    CircuitOpen { destination: String, until: DateTime<Utc> },
+    #[error("{0}")]
+    Connect(String),
+    #[error("Timed out after {0:?}")]
+    Timeout(Duration),
+    #[error("{0}")]
+    Request(String),
+    #[error("Gave up after {0} redirects")]
+    TooManyRedirects(u32),
+}
+
+impl OutboundError {
+    /// Whether the same call might succeed if tried again.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            OutboundError::Resolve(_) | OutboundError::Connect(_) | OutboundError::Timeout(_) | OutboundError::Request(_)
+        )
+    }
+}
+
+impl From<OutboundError> for AppError {
+    fn from(error: OutboundError) -> Self {
+        match error {
+            OutboundError::InvalidUrl(_) | OutboundError::Blocked(_) => AppError::BadRequest(error.to_string()),
+            _ => AppError::ServiceUnavailable(error.to_string()),
+        }
+    }
+}
+
+/// One call. Unset limits come from the `outbound` config.
+#[derive(Debug, Clone)]
+pub struct OutboundRequest {
+    method: Method,
+    url: String,
+    headers: Vec<(&'static str, String)>,
+    body: Bytes,
+    timeout: Option<Duration>,
+    max_redirects: Option<u32>,
+    max_retries: Option<u32>,
+}
+
+impl OutboundRequest {
+    pub fn new(method: Method, url: impl Into<String>) -> Self {
+        Self {
+            method,
+            url: url.into(),
+            headers: Vec::new(),
+            body: Bytes::new(),
+            timeout: None,
+            max_redirects: None,
+            max_retries: None,
+        }
+    }
+
+    pub fn get(url: impl Into<String>) -> Self {
+        Self::new(Method::GET, url)
+    }
+
+    pub fn post(url: impl Into<String>) -> Self {
+        Self::new(Method::POST, url)
+    }
+
+    pub fn header(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, content_type: &'static str, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self.header(header::CONTENT_TYPE.as_str(), content_type)
+    }
+
+    /// Signs the body set so far.
+    pub fn signed(mut self, signer: &RequestSigner, timestamp: i64) -> Self {
+        let signature = signer.headers(timestamp, &self.body);
+        self.headers.extend(signature);
+        self
+    }
+
+    /// Limit on each attempt, from connecting to reading the whole response.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Redirects to follow. With none, a redirect is returned as the
+    /// response.
+    pub fn max_redirects(mut self, max_redirects: u32) -> Self {
+        self.max_redirects = Some(max_redirects);
+        self
+    }
+
+    /// Attempts after the first, for callers that retry the call themselves
+    /// or for which repeating it is unsafe.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OutboundResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    /// Where the response came from, after any redirects.
+    pub url: String,
+    /// Attempts made, counting retries on every hop.
+    pub attempts: u32,
+}
+
+/// The shared client for outbound calls; see the [module docs](self). Clones
+/// share their circuit breakers.
+#[derive(Clone)]
+pub struct OutboundHttp {
+    connect_timeout: Duration,
+    request_timeout: Duration,
+    max_redirects: u32,
+    max_retries: u32,
+    retry_backoff: Duration,
+    max_response_bytes: usize,
+    policy: DestinationPolicy,
+    breakers: CircuitBreakers,
+    clock: SharedClock,
+    metrics: Arc<dyn MetricsSink>,
+    tls: TlsConnector,
+}
+
+impl Default for OutboundHttp {
+    fn default() -> Self {
+        Self::new(&OutboundConfig::default(), Vec::new())
+    }
+}
+
+impl OutboundHttp {
+    pub fn from_config(config: &OutboundConfig) -> Result<Self> {
+        Ok(Self::new(config, parse_cidrs(&config.allowed_private_cidrs)?))
+    }
+
+    fn new(config: &OutboundConfig, allowed: Vec<IpCidr>) -> Self {
+        let clock = SystemClock::shared();
+        Self {
+            connect_timeout: Duration::from_millis(config.connect_timeout_ms),
+            request_timeout: Duration::from_secs(config.request_timeout_seconds),
+            max_redirects: config.max_redirects,
+            max_retries: config.max_retries,
+            retry_backoff: Duration::from_millis(config.retry_backoff_ms),
+            max_response_bytes: config.max_response_bytes,
+            policy: DestinationPolicy::new(allowed),
+            breakers: CircuitBreakers::new(
+                config.circuit_failure_threshold,
+                Duration::from_secs(config.circuit_open_seconds),
+                clock.clone(),
+            ),
+            clock,
+            metrics: Arc::new(MetricsCollector::new()),
+            tls: default_tls(),
+        }
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<dyn MetricsSink>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Times circuit breaking and retry backoff. Starts new breakers.
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.breakers = self.breakers.with_clock(clock.clone());
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_resolver(mut self, resolver: Arc<dyn Resolve>) -> Self {
+        self.policy = self.policy.with_resolver(resolver);
+        self
+    }
+
+    /// A client that may also call `allowed`, for features with their own
+    /// allowlist. It shares this client's breakers.
+    pub fn allowing(&self, allowed: &[IpCidr]) -> Self {
+        let mut client = self.clone();
+        client.policy = client.policy.allowing(allowed);
+        client
+    }
+
+    /// Checks that `url` could be called now, without calling it.
+    pub async fn check(&self, url: &str) -> std::result::Result<Destination, OutboundError> {
+        let destination = Destination::parse(url)?;
+        self.policy.resolve(&destination).await?;
+        Ok(destination)
+    }
+
+    /// Makes the call, following redirects and retrying as configured. A
+    /// response with any status is `Ok`; errors mean no usable response
+    /// arrived.
+    pub async fn send(&self, request: OutboundRequest) -> std::result::Result<OutboundResponse, OutboundError> {
+        let max_redirects = request.max_redirects.unwrap_or(self.max_redirects);
+        let mut destination = Destination::parse(&request.url)?;
+        let mut method = request.method.clone();
+        let mut headers = request.headers.clone();
+        let mut body = request.body.clone();
+        let mut attempts = 0;
+        let mut redirects = 0;
+
+        loop {
+            let (mut response, tries) = self.send_with_retries(&request, &method, &destination, &headers, &body).await?;
+            attempts += tries;
+
+            let location = response
+                .headers
+                .get(header::LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .filter(|_| response.status.is_redirection() && max_redirects > 0);
+            let Some(location) = location else {
+                response.url = destination.uri.to_string();
+                response.attempts = attempts;
+                return Ok(response);
+            };
+            if redirects == max_redirects {
+                return Err(OutboundError::TooManyRedirects(redirects));
+            }
+
+            let next = destination.join(location)?;
+            let to_get = response.status == StatusCode::SEE_OTHER
+                || (method == Method::POST
+                    && matches!(response.status, StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND));
+            if to_get {
+                method = Method::GET;
+                body = Bytes::new();
+                headers.retain(|(name, _)| !name.eq_ignore_ascii_case(header::CONTENT_TYPE.as_str()));
+            }
+            if next.host != destination.host {
+                headers.retain(|(name, _)| !name.eq_ignore_ascii_case(header::AUTHORIZATION.as_str()));
+            }
+            destination = next;
+            redirects += 1;
+        }
+    }
+
+    async fn send_with_retries(
+        &self,
+        request: &OutboundRequest,
+        method: &Method,
+        destination: &Destination,
+        headers: &[(&'static str, String)],
+        body: &Bytes,
+    ) -> std::result::Result<(OutboundResponse, u32), OutboundError> {
+        let max_retries = request.max_retries.unwrap_or(self.max_retries);
+        let timeout = request.timeout.unwrap_or(self.request_timeout);
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let result = self.attempt(method, destination, headers, body, timeout).await;
+            let retry = match &result {
+                Ok(response) => response.status.is_server_error() || response.status == StatusCode::TOO_MANY_REQUESTS,
+                Err(e) => e.is_transient(),
+            };
+            if !retry || attempt > max_retries {
+                return result.map(|response| (response, attempt));
+            }
+
+            let backoff = self.retry_backoff.saturating_mul(2u32.saturating_pow(attempt - 1));
+            tracing::debug!("Retrying call to {} in {:?}", destination.key(), backoff);
+            self.clock.sleep(backoff).await;
+        }
+    }
+
+    async fn attempt(
+        &self,
+        method: &Method,
+        destination: &Destination,
+        headers: &[(&'static str, String)],
+        body: &Bytes,
+        timeout: Duration,
+    ) -> std::result::Result<OutboundResponse, OutboundError> {
+        let key = destination.key();
+        if let Some(until) = self.breakers.open_until(&key) {
+            self.metrics.record_counter(names::OUTBOUND_REFUSED, Some("circuit_open"), 1);
+            return Err(OutboundError::CircuitOpen { destination: key, until });
+        }
+
+        let started = Instant::now();
+        let result = match self.policy.resolve(destination).await {
+            Ok(addrs) => tokio::time::timeout(timeout, self.exchange(method, destination, &addrs, headers, body))
+                .await
+                .unwrap_or(Err(OutboundError::Timeout(timeout))),
+            Err(e @ OutboundError::Blocked(_)) => {
+                tracing::warn!("Refused outbound call to {}: {}", key, e);
+                self.metrics.record_counter(names::OUTBOUND_REFUSED, Some("blocked"), 1);
+                return Err(e);
+            }
+            Err(e) => Err(e),
+        };
+
+        let success = matches!(&result, Ok(response) if !response.status.is_server_error());
+        if success {
+            self.breakers.record_success(&key);
+        } else {
+            self.breakers.record_failure(&key);
+        }
+        self.metrics.record_outbound(&key, success, started.elapsed());
+        result
+    }
+
+    /// Connects only to `addrs`, which the policy has vetted.
+    async fn exchange(
+        &self,
+        method: &Method,
+        destination: &Destination,
+        addrs: &[SocketAddr],
+        headers: &[(&'static str, String)],
+        body: &Bytes,
+    ) -> std::result::Result<OutboundResponse, OutboundError> {
+        let stream = self.connect(addrs).await?;
+        if !destination.tls {
+            return self.request(stream, method, destination, headers, body).await;
+        }
+
+        let server_name = ServerName::try_from(destination.host.as_str())
+            .map_err(|e| OutboundError::Connect(format!("Invalid TLS server name: {}", e)))?;
+        let stream = self
+            .tls
+            .connect(server_name, stream)
+            .await
+            .map_err(|e| OutboundError::Connect(format!("TLS handshake failed: {}", e)))?;
+        self.request(stream, method, destination, headers, body).await
+    }
+
+    async fn connect(&self, addrs: &[SocketAddr]) -> std::result::Result<TcpStream, OutboundError> {
+        let mut last_error = "no addresses to connect to".to_string();
+        for addr in addrs {
+            match tokio::time::timeout(self.connect_timeout, TcpStream::connect(addr)).await {
+                Ok(Ok(stream)) => return Ok(stream),
+                Ok(Err(e)) => last_error = format!("Connecting to {} failed: {}", addr, e),
+                Err(_) => last_error = format!("Connecting to {} timed out after {:?}", addr, self.connect_timeout),
+            }
+        }
+        Err(OutboundError::Connect(last_error))
+    }
+
+    async fn request<S>(
+        &self,
+        stream: S,
+        method: &Method,
+        destination: &Destination,
+        headers: &[(&'static str, String)],
+        body: &Bytes,
+    ) -> std::result::Result<OutboundResponse, OutboundError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .map_err(|e| OutboundError::Request(format!("HTTP handshake failed: {}", e)))?;
+        tokio::spawn(async move {
+            let _ = connection.await;
+        });
+
+        let mut builder = http::Request::builder()
+            .method(method.clone())
+            .uri(destination.path_and_query())
+            .header(header::HOST, destination.host_header())
+            .header(header::USER_AGENT, USER_AGENT);
+        for (name, value) in headers {
+            builder = builder.header(*name, value.as_str());
+        }
+        let http_request = builder
+            .body(Body::from(body.clone()))
+            .map_err(|e| OutboundError::Request(format!("Invalid request: {}", e)))?;
+
+        let response = sender
+            .send_request(http_request)
+            .await
+            .map_err(|e| OutboundError::Request(format!("Request failed: {}", e)))?;
+        let (parts, incoming) = response.into_parts();
+        let body = axum::body::to_bytes(Body::new(incoming), self.max_response_bytes)
+            .await
+            .map_err(|e| OutboundError::Request(format!("Reading the response failed: {}", e)))?;
+
+        Ok(OutboundResponse {
+            status: parts.status,
+            headers: parts.headers,
+            body,
+            url: String::new(),
+            attempts: 0,
+        })
+    }
+}
+
+/// Trusts the web PKI roots. Built once, since every state gets a client.
+fn default_tls() -> TlsConnector {
+    static TLS: OnceLock<TlsConnector> = OnceLock::new();
+    TLS.get_or_init(|| {
+        let mut roots = RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(anchor.subject, anchor.spki, anchor.name_constraints)
+        }));
+        let config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        TlsConnector::from(Arc::new(config))
+    })
+    .clone()
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,317 @@
+use super::*;
+use crate::clock::{Clock, MockClock};
+use async_trait::async_trait;
+use axum::extract::State;
+use axum::routing::{any, get};
+use axum::Router;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Answers each lookup with the next scripted set of addresses, repeating
+/// the last one, like a DNS server an attacker controls.
+struct ScriptedResolver {
+    answers: Mutex<VecDeque<Vec<IpAddr>>>,
+}
+
+impl ScriptedResolver {
+    fn new(answers: &[&[&str]]) -> Arc<Self> {
+        let answers = answers
+            .iter()
+            .map(|answer| answer.iter().map(|ip| ip.parse().unwrap()).collect())
+            .collect();
+        Arc::new(Self {
+            answers: Mutex::new(answers),
+        })
+    }
+}
+
+#[async_trait]
+impl Resolve for ScriptedResolver {
+    async fn resolve(&self, _host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        let mut answers = self.answers.lock();
+        let answer = if answers.len() > 1 {
+            answers.pop_front().unwrap()
+        } else {
+            answers.front().cloned().unwrap_or_default()
+        };
+        Ok(answer.into_iter().map(|ip| SocketAddr::new(ip, port)).collect())
+    }
+}
+
+#[derive(Clone, Default)]
+struct Upstream {
+    hits: Arc<AtomicUsize>,
+    requests: Arc<Mutex<Vec<(Method, HeaderMap, Bytes)>>>,
+}
+
+/// Serves a few endpoints on a local port and returns the port.
+async fn upstream() -> (u16, Upstream) {
+    let upstream = Upstream::default();
+    let app = Router::new()
+        .route(
+            "/echo",
+            any(|State(upstream): State<Upstream>, method: Method, headers: HeaderMap, body: Bytes| async move {
+                upstream.hits.fetch_add(1, Ordering::SeqCst);
+                upstream.requests.lock().push((method, headers, body));
+                "ok"
+            }),
+        )
+        .route(
+            "/flaky",
+            get(|State(upstream): State<Upstream>| async move {
+                match upstream.hits.fetch_add(1, Ordering::SeqCst) {
+                    0 | 1 => StatusCode::SERVICE_UNAVAILABLE,
+                    _ => StatusCode::OK,
+                }
+            }),
+        )
+        .route(
+            "/down",
+            get(|State(upstream): State<Upstream>| async move {
+                upstream.hits.fetch_add(1, Ordering::SeqCst);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }),
+        )
+        .route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                "late"
+            }),
+        )
+        .route(
+            "/to-metadata",
+            get(|| async { (StatusCode::FOUND, [(header::LOCATION, "http://169.254.169.254/latest/meta-data/")]) }),
+        )
+        .route(
+            "/see-other",
+            any(|| async { (StatusCode::SEE_OTHER, [(header::LOCATION, "/echo")]) }),
+        )
+        .with_state(upstream.clone());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (port, upstream)
+}
+
+/// A client that may reach loopback, where the test upstream listens, with
+/// names resolved by `resolver`.
+fn client(config: &OutboundConfig, resolver: Arc<ScriptedResolver>) -> (OutboundHttp, Arc<MetricsCollector>, MockClock) {
+    let metrics = Arc::new(MetricsCollector::new());
+    let clock = MockClock::new(Utc::now());
+    let client = OutboundHttp::from_config(config)
+        .unwrap()
+        .with_clock(clock.shared())
+        .with_resolver(resolver)
+        .with_metrics(metrics.clone())
+        .allowing(&["127.0.0.0/8".parse().unwrap()]);
+    (client, metrics, clock)
+}
+
+fn default_client() -> OutboundHttp {
+    OutboundHttp::default().with_clock(MockClock::new(Utc::now()).shared())
+}
+
+#[tokio::test]
+async fn test_dns_rebinding_to_metadata_address_is_blocked() {
+    let (port, upstream) = upstream().await;
+    let resolver = ScriptedResolver::new(&[&["127.0.0.1"], &["169.254.169.254"]]);
+    let (client, metrics, _) = client(&OutboundConfig::default(), resolver);
+    let url = format!("http://rebind.test:{}/echo", port);
+
+    let response = client.send(OutboundRequest::get(&url)).await.unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(upstream.hits.load(Ordering::SeqCst), 1);
+
+    // The same name now points at the metadata service.
+    let err = client.send(OutboundRequest::get(&url)).await.unwrap_err();
+    assert!(matches!(&err, OutboundError::Blocked(message) if message.contains("169.254.169.254")), "{:?}", err);
+    assert!(matches!(client.check(&url).await, Err(OutboundError::Blocked(_))));
+    assert_eq!(upstream.hits.load(Ordering::SeqCst), 1, "nothing was sent after the rebind");
+
+    assert_eq!(metrics.labelled_counter(names::OUTBOUND_REFUSED).get("blocked"), Some(&1));
+    let destinations = metrics.outbound_destinations();
+    assert_eq!(destinations.len(), 1);
+    assert_eq!(destinations[0].destination, format!("rebind.test:{}", port));
+    assert_eq!((destinations[0].calls, destinations[0].failures), (1, 0));
+}
+
+#[tokio::test]
+async fn test_any_internal_address_in_an_answer_is_blocked() {
+    let (port, upstream) = upstream().await;
+    let resolver = ScriptedResolver::new(&[&["127.0.0.1", "169.254.169.254"]]);
+    let (client, _, _) = client(&OutboundConfig::default(), resolver);
+
+    let err = client
+        .send(OutboundRequest::get(format!("http://mixed.test:{}/echo", port)))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, OutboundError::Blocked(_)), "{:?}", err);
+    assert_eq!(upstream.hits.load(Ordering::SeqCst), 0);
+
+    let err = default_client()
+        .send(OutboundRequest::get("http://[::ffff:169.254.169.254]/"))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, OutboundError::Blocked(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn test_ipv6_forms_of_internal_addresses_are_blocked() {
+    let destination = Destination::parse("http://embedded.test/").unwrap();
+    for ip in ["64:ff9b::a9fe:a9fe", "2002:a9fe:a9fe::", "::a9fe:a9fe", "::ffff:a9fe:a9fe"] {
+        let policy = DestinationPolicy::default().with_resolver(ScriptedResolver::new(&[&[ip]]));
+        let err = policy.resolve(&destination).await.unwrap_err();
+        assert!(matches!(err, OutboundError::Blocked(_)), "{}: {:?}", ip, err);
+    }
+
+    // The same forms of a public address are left alone.
+    for ip in ["64:ff9b::5db8:d822", "2002:5db8:d822::1"] {
+        let policy = DestinationPolicy::default().with_resolver(ScriptedResolver::new(&[&[ip]]));
+        assert!(policy.resolve(&destination).await.is_ok(), "{}", ip);
+    }
+}
+
+#[tokio::test]
+async fn test_redirect_to_metadata_address_is_blocked() {
+    let (port, _) = upstream().await;
+    let (client, _, _) = client(&OutboundConfig::default(), ScriptedResolver::new(&[&["127.0.0.1"]]));
+
+    let err = client
+        .send(OutboundRequest::get(format!("http://redirect.test:{}/to-metadata", port)))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, OutboundError::Blocked(_)), "{:?}", err);
+
+    // Without following, the redirect itself is the response.
+    let response = client
+        .send(OutboundRequest::get(format!("http://redirect.test:{}/to-metadata", port)).max_redirects(0))
+        .await
+        .unwrap();
+    assert_eq!(response.status, StatusCode::FOUND);
+}
+
+#[tokio::test]
+async fn test_see_other_is_followed_as_get() {
+    let (port, upstream) = upstream().await;
+    let (client, _, _) = client(&OutboundConfig::default(), ScriptedResolver::new(&[&["127.0.0.1"]]));
+
+    let response = client
+        .send(
+            OutboundRequest::post(format!("http://redirect.test:{}/see-other", port))
+                .body("application/json", r#"{"a":1}"#),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.url, format!("http://redirect.test:{}/echo", port));
+
+    let requests = upstream.requests.lock();
+    let (method, headers, body) = &requests[0];
+    assert_eq!(method, Method::GET);
+    assert!(body.is_empty());
+    assert!(headers.get(header::CONTENT_TYPE).is_none());
+}
+
+#[tokio::test]
+async fn test_retries_server_errors_with_backoff() {
+    let (port, upstream) = upstream().await;
+    let (client, metrics, clock) = client(&OutboundConfig::default(), ScriptedResolver::new(&[&["127.0.0.1"]]));
+    let started = clock.now();
+
+    let response = client
+        .send(OutboundRequest::get(format!("http://flaky.test:{}/flaky", port)))
+        .await
+        .unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.attempts, 3);
+    assert_eq!(upstream.hits.load(Ordering::SeqCst), 3);
+    // 200ms, then 400ms
+    assert_eq!((clock.now() - started).num_milliseconds(), 600);
+
+    let destinations = metrics.outbound_destinations();
+    assert_eq!((destinations[0].calls, destinations[0].failures), (3, 2));
+}
+
+#[tokio::test]
+async fn test_circuit_opens_for_failing_destination() {
+    let (port, upstream) = upstream().await;
+    let config = OutboundConfig {
+        max_retries: 0,
+        circuit_failure_threshold: 2,
+        ..OutboundConfig::default()
+    };
+    let (client, metrics, clock) = client(&config, ScriptedResolver::new(&[&["127.0.0.1"]]));
+    let url = format!("http://down.test:{}/down", port);
+
+    for _ in 0..2 {
+        let response = client.send(OutboundRequest::get(&url)).await.unwrap();
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    let err = client.send(OutboundRequest::get(&url)).await.unwrap_err();
+    assert!(matches!(err, OutboundError::CircuitOpen { .. }), "{:?}", err);
+    assert!(matches!(AppError::from(err), AppError::ServiceUnavailable(_)));
+    assert_eq!(upstream.hits.load(Ordering::SeqCst), 2);
+    assert_eq!(metrics.labelled_counter(names::OUTBOUND_REFUSED).get("circuit_open"), Some(&1));
+
+    // Clones share the breakers; other destinations are unaffected.
+    let other = client.allowing(&[]);
+    assert!(matches!(
+        other.send(OutboundRequest::get(&url)).await,
+        Err(OutboundError::CircuitOpen { .. })
+    ));
+    assert!(other.send(OutboundRequest::get(format!("http://up.test:{}/echo", port))).await.is_ok());
+
+    clock.advance(Duration::from_secs(config.circuit_open_seconds + 1));
+    assert!(client.send(OutboundRequest::get(&url)).await.is_ok());
+    assert_eq!(upstream.hits.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test]
+async fn test_request_timeout() {
+    let (port, _) = upstream().await;
+    let (client, metrics, _) = client(
+        &OutboundConfig {
+            max_retries: 0,
+            ..OutboundConfig::default()
+        },
+        ScriptedResolver::new(&[&["127.0.0.1"]]),
+    );
+
+    let err = client
+        .send(OutboundRequest::get(format!("http://slow.test:{}/slow", port)).timeout(Duration::from_millis(100)))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, OutboundError::Timeout(_)), "{:?}", err);
+    assert_eq!(metrics.outbound_destinations()[0].failures, 1);
+}
+
+#[tokio::test]
+async fn test_signed_request_carries_headers() {
+    let (port, upstream) = upstream().await;
+    let (client, _, _) = client(&OutboundConfig::default(), ScriptedResolver::new(&[&["127.0.0.1"]]));
+    let signer = RequestSigner::new("secret", "x-signature", "x-timestamp");
+
+    client
+        .send(
+            OutboundRequest::post(format!("http://signed.test:{}/echo", port))
+                .header("x-event", "ping")
+                .body("application/json", r#"{"a":1}"#)
+                .signed(&signer, 1_700_000_000),
+        )
+        .await
+        .unwrap();
+
+    let requests = upstream.requests.lock();
+    let (method, headers, body) = &requests[0];
+    assert_eq!(method, Method::POST);
+    assert_eq!(body.as_ref(), br#"{"a":1}"#);
+    assert_eq!(headers[header::HOST], format!("signed.test:{}", port));
+    assert_eq!(headers[header::USER_AGENT], USER_AGENT);
+    assert_eq!(headers["x-event"], "ping");
+    assert_eq!(headers["x-timestamp"], "1700000000");
+    assert_eq!(headers["x-signature"].to_str().unwrap(), sign("secret", 1_700_000_000, body));
+}
@@ -1,16 +1,45 @@
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
 use std::sync::Arc;
-use parking_lot::RwLock;
+use std::time::{Duration, Instant};
+use lru::LruCache;
+use parking_lot::{Mutex, RwLock};
 use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
-use crate::search::{SearchQuery, SearchResult};
+use crate::search::{SearchQuery, SearchResult, SuggestField, Suggestion};
+
+const DEFAULT_SUGGESTION_CAPACITY: usize = 1024;
+const DEFAULT_SUGGESTION_TTL: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Clone)]
 pub struct SearchCache {
     cache: Arc<RwLock<HashMap<SearchCacheKey, SearchCacheEntry>>>,
     max_size: usize,
     ttl_seconds: i64,
+    suggestions: Arc<Mutex<LruCache<SuggestionKey, SuggestionEntry>>>,
+    suggestion_ttl: Duration,
+}
+
+/// Suggestions are cached per namespace, field and lowercased prefix.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct SuggestionKey {
+    namespace: Option<String>,
+    field: SuggestField,
+    prefix: String,
+}
+
+/// When the suggestions were stored, and the suggestions.
+type SuggestionEntry = (Instant, Vec<Suggestion>);
+
+impl SuggestionKey {
+    fn new(field: SuggestField, prefix: &str) -> Self {
+        Self {
+            namespace: crate::tenancy::current(),
+            field,
+            prefix: prefix.to_lowercase(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -32,9 +61,46 @@ impl SearchCache {
             cache: Arc::new(RwLock::new(HashMap::new())),
             max_size,
             ttl_seconds,
+            suggestions: Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(DEFAULT_SUGGESTION_CAPACITY).unwrap(),
+            ))),
+            suggestion_ttl: DEFAULT_SUGGESTION_TTL,
+        }
+    }
+
+    /// Keeps suggestions for `ttl` in an LRU of `capacity` entries instead
+    /// of the defaults.
+    pub fn with_suggestion_cache(mut self, capacity: usize, ttl: Duration) -> Self {
+        self.suggestions = Arc::new(Mutex::new(LruCache::new(
+            NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN),
+        )));
+        self.suggestion_ttl = ttl;
+        self
+    }
+
+    pub fn get_suggestions(&self, field: SuggestField, prefix: &str) -> Option<Vec<Suggestion>> {
+        let key = SuggestionKey::new(field, prefix);
+        let mut suggestions = self.suggestions.lock();
+        match suggestions.get(&key) {
+            Some((stored_at, cached)) if stored_at.elapsed() < self.suggestion_ttl => Some(cached.clone()),
+            Some(_) => {
+                suggestions.pop(&key);
+                None
+            }
+            None => None,
         }
     }
 
+    pub fn put_suggestions(&self, field: SuggestField, prefix: &str, suggestions: Vec<Suggestion>) {
+        self.suggestions
+            .lock()
+            .put(SuggestionKey::new(field, prefix), (Instant::now(), suggestions));
+    }
+
+    pub fn invalidate_suggestions(&self) {
+        self.suggestions.lock().clear();
+    }
+
     pub fn get(&self, query: &SearchQuery) -> Option<SearchResult> {
         let key = self.create_cache_key(query);
         let mut cache = self.cache.write();
@@ -73,6 +139,7 @@ impl SearchCache {
     pub fn invalidate_all(&self) {
         let mut cache = self.cache.write();
         cache.clear();
+        self.invalidate_suggestions();
     }
 
     pub fn invalidate_by_pattern(&self, pattern: &str) {
@@ -145,6 +212,10 @@ impl SearchCache {
         query.fuzzy.hash(&mut hasher);
         query.created_by.hash(&mut hasher);
         query.min_relevance.map(|r| (r * 1000.0) as i64).hash(&mut hasher);
+        query.after_id.hash(&mut hasher);
+        query.max_id.hash(&mut hasher);
+        query.weights.map(|w| [w.name.to_bits(), w.tags.to_bits(), w.description.to_bits()]).hash(&mut hasher);
+        crate::tenancy::current().hash(&mut hasher);
 
         SearchCacheKey {
             query_hash: hasher.finish(),
@@ -256,4 +327,28 @@ mod tests {
         cache.invalidate_all();
         assert!(cache.get(&query).is_none());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_suggestion_cache() {
+        let cache = SearchCache::new(10, 300).with_suggestion_cache(2, Duration::from_secs(60));
+        let report = vec![Suggestion { text: "Report".to_string(), count: 1 }];
+
+        cache.put_suggestions(SuggestField::Name, "Rep", report.clone());
+        assert_eq!(cache.get_suggestions(SuggestField::Name, "rep"), Some(report.clone()));
+        assert_eq!(cache.get_suggestions(SuggestField::Tags, "rep"), None);
+
+        // Least recently used goes first.
+        cache.put_suggestions(SuggestField::Name, "bud", Vec::new());
+        cache.get_suggestions(SuggestField::Name, "rep");
+        cache.put_suggestions(SuggestField::Name, "memo", Vec::new());
+        assert!(cache.get_suggestions(SuggestField::Name, "bud").is_none());
+        assert!(cache.get_suggestions(SuggestField::Name, "rep").is_some());
+
+        cache.invalidate_suggestions();
+        assert!(cache.get_suggestions(SuggestField::Name, "rep").is_none());
+
+        let expiring = SearchCache::new(10, 300).with_suggestion_cache(2, Duration::ZERO);
+        expiring.put_suggestions(SuggestField::Name, "rep", report);
+        assert!(expiring.get_suggestions(SuggestField::Name, "rep").is_none());
+    }
+}
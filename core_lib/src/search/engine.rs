@@ -1,16 +1,20 @@
 use sqlx::{SqlitePool, Row};
 use tracing::{debug, error};
 use regex::Regex;
+use crate::database::InstrumentedPool;
 use crate::error::{AppError, Result};
-use crate::search::{SearchQuery, SearchResult, SearchResultItem, SortField};
+use crate::search::expression::{MatchPlan, QueryExpr};
+use crate::search::{FieldWeights, SearchQuery, SearchResult, SearchResultItem, SortField, SortOrder, Suggestion};
+use crate::validation::ValidationError;
 use crate::database::models::DbItem;
 use crate::store::Item;
 
 #[derive(Clone)]
 pub struct SearchEngine {
-    pool: SqlitePool,
+    pool: InstrumentedPool,
     fuzzy_regex: Regex,
     cache: Option<crate::search::cache::SearchCache>,
+    ranking: crate::config::SearchConfig,
 }
 
 impl SearchEngine {
@@ -18,9 +22,10 @@ impl SearchEngine {
         let fuzzy_regex = Regex::new(r"[a-zA-Z]").unwrap();
         
         Self {
-            pool,
+            pool: pool.into(),
             fuzzy_regex,
             cache: None,
+            ranking: crate::config::SearchConfig::default(),
         }
     }
 
@@ -29,6 +34,22 @@ impl SearchEngine {
         self
     }
 
+    /// Resizes the suggestion cache, when there is a cache.
+    pub fn with_suggestion_cache(mut self, capacity: usize, ttl: std::time::Duration) -> Self {
+        self.cache = self.cache.map(|cache| cache.with_suggestion_cache(capacity, ttl));
+        self
+    }
+
+    /// Default column weights and recency boost for full-text relevance.
+    pub fn with_ranking(mut self, ranking: &crate::config::SearchConfig) -> Self {
+        self.ranking = ranking.clone();
+        self
+    }
+
+    pub fn cache(&self) -> Option<&crate::search::cache::SearchCache> {
+        self.cache.as_ref()
+    }
+
     pub async fn search(&self, query: &SearchQuery) -> Result<SearchResult> {
         debug!("Executing search query: {:?}", query);
 
@@ -39,10 +60,22 @@ impl SearchEngine {
             }
         }
 
-        let (items, total_count) = if query.text.is_some() {
-            self.full_text_search(query).await?
-        } else {
-            self.filter_search(query).await?
+        let expr = match query.text.as_deref() {
+            Some(text) => {
+                let expr = QueryExpr::parse(text).map_err(ValidationError::from)?;
+                Some(if query.fuzzy {
+                    expr.map_terms(&|term| self.process_fuzzy_query(term))
+                } else {
+                    expr
+                })
+            }
+            None => None,
+        };
+        let plan = expr.as_ref().map(QueryExpr::plan);
+
+        let (items, total_count) = match (&expr, &plan) {
+            (Some(expr), Some(plan)) if plan.fts.is_some() => self.full_text_search(query, expr, plan).await?,
+            _ => self.filter_search(query, expr.as_ref(), plan.as_ref()).await?,
         };
 
         let offset = query.offset.unwrap_or(0);
@@ -64,34 +97,27 @@ impl SearchEngine {
         Ok(result)
     }
 
-    async fn full_text_search(&self, query: &SearchQuery) -> Result<(Vec<SearchResultItem>, u64)> {
-        let search_text = query.text.as_ref().unwrap();
-        let processed_text = if query.fuzzy {
-            self.process_fuzzy_query(search_text)
-        } else {
-            search_text.clone()
-        };
-
-        debug!("Full-text search for: '{}' (processed: '{}')", search_text, processed_text);
+    async fn full_text_search(&self, query: &SearchQuery, expr: &QueryExpr, plan: &MatchPlan) -> Result<(Vec<SearchResultItem>, u64)> {
+        debug!("Full-text search for: {:?} (match: {:?})", query.text, plan.fts);
 
-        let fts_query = self.build_fts_query(&processed_text);
-        
-        let (filter_clause, filter_params) = self.build_filter_clause(query);
+        let relevance = self.relevance_sql(query)?;
+        let (filter_clause, filter_params) = self.build_filter_clause(query, Some(plan), Some(&relevance));
         
-        let sort_clause = self.build_sort_clause(&query.sort_criteria);
+        let sort_clause = self.build_sort_clause(query, true);
         
         let limit = query.limit.unwrap_or(50);
         let offset = query.offset.unwrap_or(0);
 
         let search_sql = format!(
             r#"
-            SELECT i.*, fts.rank
+            SELECT i.*, {} AS relevance
             FROM items_fts fts
             JOIN items i ON i.id = fts.rowid
             {}
             {}
             LIMIT ? OFFSET ?
             "#,
+            relevance,
             filter_clause,
             sort_clause
         );
@@ -107,8 +133,6 @@ impl SearchEngine {
         );
 
         let mut search_query = sqlx::query(&search_sql);
-        search_query = search_query.bind(&fts_query);
-        
         for param in &filter_params {
             search_query = search_query.bind(param);
         }
@@ -118,8 +142,6 @@ impl SearchEngine {
         let rows = search_query.fetch_all(&self.pool).await.map_err(AppError::from)?;
 
         let mut count_query = sqlx::query(&count_sql);
-        count_query = count_query.bind(&fts_query);
-        
         for param in &filter_params {
             count_query = count_query.bind(param);
         }
@@ -138,15 +160,16 @@ impl SearchEngine {
                 tags: row.try_get("tags").unwrap_or_else(|_| "[]".to_string()),
                 metadata: row.try_get("metadata").unwrap_or_else(|_| "{}".to_string()),
                 created_by: row.try_get("created_by").ok(),
+                version: row.try_get("version").unwrap_or(1),
             };
 
             let item = db_item.to_api_item();
-            let rank: f64 = row.try_get("rank").unwrap_or(0.0);
+            let relevance: f64 = row.try_get("relevance").unwrap_or(0.0);
             
-            let matched_fields = self.identify_matched_fields(&item, search_text);
+            let matched_fields = expr.matched_fields(&item);
             
             let result_item = SearchResultItem::new(item)
-                .with_relevance(rank)
+                .with_relevance(relevance)
                 .with_matched_fields(matched_fields);
             
             items.push(result_item);
@@ -155,20 +178,25 @@ impl SearchEngine {
         Ok((items, total_count as u64))
     }
 
-    async fn filter_search(&self, query: &SearchQuery) -> Result<(Vec<SearchResultItem>, u64)> {
+    async fn filter_search(
+        &self,
+        query: &SearchQuery,
+        expr: Option<&QueryExpr>,
+        plan: Option<&MatchPlan>,
+    ) -> Result<(Vec<SearchResultItem>, u64)> {
         debug!("Filter-only search");
 
-        let (filter_clause, filter_params) = self.build_filter_clause(query);
+        let (filter_clause, filter_params) = self.build_filter_clause(query, plan, None);
         
-        let sort_clause = self.build_sort_clause(&query.sort_criteria);
+        let sort_clause = self.build_sort_clause(query, false);
         
         let limit = query.limit.unwrap_or(50);
         let offset = query.offset.unwrap_or(0);
 
         let search_sql = format!(
             r#"
-            SELECT *
-            FROM items
+            SELECT i.*
+            FROM items i
             {}
             {}
             LIMIT ? OFFSET ?
@@ -180,7 +208,7 @@ impl SearchEngine {
         let count_sql = format!(
             r#"
             SELECT COUNT(*) as total
-            FROM items
+            FROM items i
             {}
             "#,
             filter_clause
@@ -213,35 +241,37 @@ impl SearchEngine {
                 tags: row.try_get("tags").unwrap_or_else(|_| "[]".to_string()),
                 metadata: row.try_get("metadata").unwrap_or_else(|_| "{}".to_string()),
                 created_by: row.try_get("created_by").ok(),
+                version: row.try_get("version").unwrap_or(1),
             };
 
             let item = db_item.to_api_item();
-            let result_item = SearchResultItem::new(item);
+            let matched_fields = expr.map(|expr| expr.matched_fields(&item)).unwrap_or_default();
+            let result_item = SearchResultItem::new(item).with_matched_fields(matched_fields);
             items.push(result_item);
         }
 
         Ok((items, total_count as u64))
     }
 
-    fn build_fts_query(&self, text: &str) -> String {
-        let cleaned = text.trim();
-        if cleaned.is_empty() {
-            return "*".to_string();
-        }
-
-        if cleaned.contains(' ') {
-            format!("\"{}\"", cleaned.replace('"', ""))
-        } else {
-            cleaned.to_string()
-        }
-    }
-
-    fn build_filter_clause(&self, query: &SearchQuery) -> (String, Vec<String>) {
+    /// The `WHERE` clause for `query`, with the parsed text's `plan` when
+    /// there is text. A plan with an FTS part needs `items_fts fts` joined
+    /// and its `relevance`, which `min_relevance` applies to.
+    fn build_filter_clause(
+        &self,
+        query: &SearchQuery,
+        plan: Option<&MatchPlan>,
+        relevance: Option<&str>,
+    ) -> (String, Vec<String>) {
         let mut conditions = Vec::new();
         let mut params = Vec::new();
 
-        if query.text.is_some() {
-            conditions.push("fts.items_fts MATCH ?".to_string());
+        if let Some(plan) = plan {
+            if let Some(ref fts) = plan.fts {
+                conditions.push("fts.items_fts MATCH ?".to_string());
+                params.push(fts.clone());
+            }
+            conditions.extend(plan.conditions.iter().cloned());
+            params.extend(plan.params.iter().cloned());
         }
 
         if !query.tags.is_empty() {
@@ -282,9 +312,24 @@ impl SearchEngine {
             params.push(created_by.to_string());
         }
 
-        if query.text.is_some() && query.min_relevance.is_some() {
-            conditions.push("fts.rank >= ?".to_string());
-            params.push(query.min_relevance.unwrap().to_string());
+        if let Some(after_id) = query.after_id {
+            conditions.push("i.id > ?".to_string());
+            params.push(after_id.to_string());
+        }
+        if let Some(max_id) = query.max_id {
+            conditions.push("i.id <= ?".to_string());
+            params.push(max_id.to_string());
+        }
+
+        if let Some(namespace) = crate::tenancy::current() {
+            conditions.push("i.namespace = ?".to_string());
+            params.push(namespace);
+        }
+        conditions.push("i.deleted_at IS NULL".to_string());
+
+        if let (Some(relevance), Some(min_relevance)) = (relevance, query.min_relevance) {
+            conditions.push(format!("{} >= CAST(? AS REAL)", relevance));
+            params.push(min_relevance.to_string());
         }
 
         let where_clause = if conditions.is_empty() {
@@ -296,26 +341,68 @@ impl SearchEngine {
         (where_clause, params)
     }
 
-    fn build_sort_clause(&self, sort_criteria: &[crate::search::query::SortCriterion]) -> String {
-        if sort_criteria.is_empty() {
-            return "ORDER BY i.created_at DESC".to_string();
+    /// Relevance is only known for `ranked` full-text searches and is
+    /// ignored otherwise. An id cursor always pages in id order. Every
+    /// ordering ends on the id, in the direction of the last field, so items
+    /// tied on the requested fields keep their place from page to page.
+    fn build_sort_clause(&self, query: &SearchQuery, ranked: bool) -> String {
+        if query.after_id.is_some() {
+            return "ORDER BY i.id ASC".to_string();
         }
 
-        let sort_parts: Vec<String> = sort_criteria.iter()
-            .map(|criterion| {
+        let criteria: Vec<(&str, &SortOrder)> = query.sort_criteria.iter()
+            .filter_map(|criterion| {
                 let field = match criterion.field {
                     SortField::Name => "i.name",
                     SortField::CreatedAt => "i.created_at",
                     SortField::UpdatedAt => "i.updated_at",
-                    SortField::Relevance => "fts.rank",
+                    SortField::Relevance if ranked => "relevance",
+                    SortField::Relevance => return None,
                 };
-                format!("{} {}", field, criterion.order)
+                Some((field, &criterion.order))
             })
             .collect();
 
+        if criteria.is_empty() {
+            return "ORDER BY i.created_at DESC, i.id DESC".to_string();
+        }
+        let (_, last_order) = criteria[criteria.len() - 1];
+        let sort_parts: Vec<String> = criteria
+            .iter()
+            .map(|(field, order)| format!("{} {}", field, order))
+            .chain(std::iter::once(format!("i.id {}", last_order)))
+            .collect();
         format!("ORDER BY {}", sort_parts.join(", "))
     }
 
+    /// SQL for the relevance of a full-text match, higher for better ones:
+    /// the bm25 score with the query's or the configured column weights,
+    /// times the recency boost when there is one.
+    fn relevance_sql(&self, query: &SearchQuery) -> Result<String> {
+        let weights = query.weights.unwrap_or(FieldWeights {
+            name: self.ranking.name_weight,
+            tags: self.ranking.tags_weight,
+            description: self.ranking.description_weight,
+        });
+        if [weights.name, weights.tags, weights.description].iter().any(|weight| !weight.is_finite() || *weight < 0.0) {
+            return Err(ValidationError::field("weights", "invalid_weight", "Search weights must be zero or more").into());
+        }
+
+        // bm25 is lower for better matches and takes weights in column order.
+        let score = format!(
+            "(-bm25(items_fts, {:?}, {:?}, {:?}))",
+            weights.name, weights.description, weights.tags
+        );
+        if self.ranking.recency_weight <= 0.0 {
+            return Ok(score);
+        }
+        let half_life = self.ranking.recency_half_life_days;
+        Ok(format!(
+            "({} * (1.0 + {:?} * {:?} / ({:?} + MAX(0.0, julianday('now') - julianday(i.created_at)))))",
+            score, self.ranking.recency_weight, half_life, half_life
+        ))
+    }
+
     fn process_fuzzy_query(&self, text: &str) -> String {
         let mut fuzzy_text = text.to_lowercase();
         
@@ -326,28 +413,99 @@ impl SearchEngine {
         fuzzy_text
     }
 
-    fn identify_matched_fields(&self, item: &Item, search_text: &str) -> Vec<String> {
-        let mut matched_fields = Vec::new();
-        let search_lower = search_text.to_lowercase();
-
-        if item.name.to_lowercase().contains(&search_lower) {
-            matched_fields.push("name".to_string());
+    /// Items in the current namespace whose name contains any of `terms`,
+    /// best full-text rank first. Only the index and the matching rows are
+    /// read, so this stays cheap however many items there are.
+    pub async fn name_matches(&self, terms: &[String], exclude_id: Option<u64>, limit: usize) -> Result<Vec<Item>> {
+        if terms.is_empty() || limit == 0 {
+            return Ok(Vec::new());
         }
 
-        if let Some(ref description) = item.description {
-            if description.to_lowercase().contains(&search_lower) {
-                matched_fields.push("description".to_string());
-            }
-        }
+        let fts_query = terms
+            .iter()
+            .map(|term| format!("name : \"{}\"", term.replace('"', "")))
+            .collect::<Vec<_>>()
+            .join(" OR ");
 
-        for tag in &item.tags {
-            if tag.to_lowercase().contains(&search_lower) {
-                matched_fields.push("tags".to_string());
-                break;
-            }
-        }
+        let rows = sqlx::query(
+            r#"
+            SELECT i.*
+            FROM items_fts fts
+            JOIN items i ON i.id = fts.rowid
+            WHERE items_fts MATCH ? AND i.id <> ?
+                AND i.namespace = COALESCE(?, i.namespace) AND i.deleted_at IS NULL
+            ORDER BY fts.rank, i.id
+            LIMIT ?
+            "#,
+        )
+        .bind(&fts_query)
+        .bind(exclude_id.map_or(0, |id| id as i64))
+        .bind(crate::tenancy::current())
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| {
+                DbItem {
+                    id: row.try_get("id").unwrap_or(0),
+                    name: row.try_get("name").unwrap_or_default(),
+                    description: row.try_get("description").ok(),
+                    created_at: row.try_get("created_at").unwrap_or_else(|_| chrono::Utc::now()),
+                    updated_at: row.try_get("updated_at").unwrap_or_else(|_| chrono::Utc::now()),
+                    tags: row.try_get("tags").unwrap_or_else(|_| "[]".to_string()),
+                    metadata: row.try_get("metadata").unwrap_or_else(|_| "{}".to_string()),
+                    created_by: row.try_get("created_by").ok(),
+                    version: row.try_get("version").unwrap_or(1),
+                }
+                .to_api_item()
+            })
+            .collect())
+    }
+
+    /// Names in the current namespace containing the words of `prefix`, the
+    /// last as a prefix, with how many items have each. Most used first,
+    /// then best ranked.
+    pub async fn name_suggestions(&self, prefix: &str, limit: usize) -> Result<Vec<Suggestion>> {
+        let Some(fts_query) = crate::search::suggest::name_prefix(prefix).fts() else {
+            return Ok(Vec::new());
+        };
 
-        matched_fields
+        let rows = sqlx::query(
+            r#"
+            SELECT i.name AS text, COUNT(*) AS count
+            FROM items_fts fts
+            JOIN items i ON i.id = fts.rowid
+            WHERE items_fts MATCH ?
+                AND i.namespace = COALESCE(?, i.namespace) AND i.deleted_at IS NULL
+            GROUP BY i.name
+            ORDER BY count DESC, MIN(fts.rank), text
+            LIMIT ?
+            "#,
+        )
+        .bind(&fts_query)
+        .bind(crate::tenancy::current())
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| -> Result<Suggestion> {
+                Ok(Suggestion {
+                    text: row.try_get("text")?,
+                    count: row.try_get::<i64, _>("count")?.max(0) as u64,
+                })
+            })
+            .collect()
+    }
+
+    /// Highest item id assigned so far, or 0 when there are no items.
+    pub async fn max_item_id(&self) -> Result<u64> {
+        let max_id: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(id), 0) FROM items")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(max_id.max(0) as u64)
     }
 
     pub async fn health_check(&self) -> Result<bool> {
@@ -389,6 +547,13 @@ impl SearchEngine {
         }
     }
 
+    /// Drops cached suggestions, which any item write may change.
+    pub fn invalidate_suggestions(&self) {
+        if let Some(ref cache) = self.cache {
+            cache.invalidate_suggestions();
+        }
+    }
+
     pub fn invalidate_cache_pattern(&self, pattern: &str) {
         if let Some(ref cache) = self.cache {
             cache.invalidate_by_pattern(pattern);
@@ -430,45 +595,164 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_build_fts_query() {
+    async fn test_process_fuzzy_query() {
         let pool = setup_test_db().await;
         let engine = SearchEngine::new(pool);
         
-        assert_eq!(engine.build_fts_query("test"), "test");
-        assert_eq!(engine.build_fts_query("test query"), "\"test query\"");
-        assert_eq!(engine.build_fts_query(""), "*");
+        assert_eq!(engine.process_fuzzy_query("teh test"), "the test");
+        assert_eq!(engine.process_fuzzy_query("adn more"), "and more");
+    }
+
+    async fn insert(engine: &SearchEngine, name: &str, description: &str, tags: &[&str]) -> u64 {
+        let tags = serde_json::to_string(tags).unwrap();
+        sqlx::query("INSERT INTO items (name, description, tags, metadata, created_at, updated_at) VALUES (?, ?, ?, '{}', ?, ?)")
+            .bind(name)
+            .bind(description)
+            .bind(tags)
+            .bind(chrono::Utc::now())
+            .bind(chrono::Utc::now())
+            .execute(&engine.pool)
+            .await
+            .unwrap()
+            .last_insert_rowid() as u64
+    }
+
+    async fn search_ids(engine: &SearchEngine, text: &str) -> Vec<u64> {
+        let mut ids: Vec<u64> = engine
+            .search(&SearchQuery::new().with_text(text.to_string()))
+            .await
+            .unwrap()
+            .items
+            .iter()
+            .map(|result| result.item.id)
+            .collect();
+        ids.sort();
+        ids
     }
 
     #[tokio::test]
-    async fn test_process_fuzzy_query() {
-        let pool = setup_test_db().await;
+    async fn test_query_language_precedence_and_quoting() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let pool = get_database_pool(&format!("sqlite:{}", temp_file.path().display())).await.unwrap();
+        run_migrations(pool.clone()).await.unwrap();
         let engine = SearchEngine::new(pool);
-        
-        assert_eq!(engine.process_fuzzy_query("teh test"), "the test");
-        assert_eq!(engine.process_fuzzy_query("adn more"), "and more");
+        let report = insert(&engine, "Quarterly report", "Finance numbers, draft", &["finance"]).await;
+        let budget = insert(&engine, "Budget", "Report for the board", &["finance", "final"]).await;
+        let memo = insert(&engine, "Memo", "Quarterly plans", &["internal"]).await;
+
+        // AND binds tighter than OR.
+        assert_eq!(search_ids(&engine, "memo OR quarterly AND draft").await, vec![report, memo]);
+        assert_eq!(search_ids(&engine, "(memo OR quarterly) AND draft").await, vec![report]);
+
+        // Quoted words must appear together.
+        assert_eq!(search_ids(&engine, r#""quarterly report""#).await, vec![report]);
+        assert_eq!(search_ids(&engine, r#""report quarterly""#).await, Vec::<u64>::new());
+
+        // Field scopes, tags and exclusions.
+        assert_eq!(search_ids(&engine, "name:report AND tags:finance NOT draft").await, Vec::<u64>::new());
+        assert_eq!(search_ids(&engine, "report tags:finance NOT draft").await, vec![budget]);
+        assert_eq!(search_ids(&engine, "tags:finance NOT tags:final").await, vec![report]);
+        assert_eq!(search_ids(&engine, "tags:internal OR name:budget").await, vec![budget, memo]);
+        assert_eq!(search_ids(&engine, "quart*").await, vec![report, memo]);
+
+        let result = engine.search(&SearchQuery::new().with_text("report tags:finance".to_string())).await.unwrap();
+        let matched = &result.items.iter().find(|r| r.item.id == report).unwrap().matched_fields;
+        assert_eq!(matched, &vec!["name".to_string(), "tags".to_string()]);
+
+        let error = engine.search(&SearchQuery::new().with_text("report AND".to_string())).await.unwrap_err();
+        assert!(matches!(error, AppError::Validation(_)));
+        assert!(error.to_string().contains("at position 11"), "{}", error);
+    }
+
+    async fn ranked_ids(engine: &SearchEngine, query: SearchQuery) -> Vec<u64> {
+        let query = query.with_sort(SortField::Relevance, crate::search::SortOrder::Desc);
+        engine.search(&query).await.unwrap().items.iter().map(|result| result.item.id).collect()
     }
 
     #[tokio::test]
-    async fn test_identify_matched_fields() {
-        let pool = setup_test_db().await;
+    async fn test_field_weights_and_recency() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let pool = get_database_pool(&format!("sqlite:{}", temp_file.path().display())).await.unwrap();
+        run_migrations(pool.clone()).await.unwrap();
         let engine = SearchEngine::new(pool);
-        
-        let item = Item {
-            id: 1,
-            name: "Test Item".to_string(),
-            description: Some("This is a test description".to_string()),
-            created_at: chrono::Utc::now(),
-            updated_at: chrono::Utc::now(),
-            tags: vec!["test".to_string(), "example".to_string()],
-            metadata: None,
-        };
+        let described = insert(&engine, "Widget", "A long description that mentions a gadget in passing", &[]).await;
+        let tagged = insert(&engine, "Thing", "Stuff", &["gadget"]).await;
+        let named = insert(&engine, "Gadget", "Something else", &[]).await;
+        for name in ["Alpha", "Beta", "Gamma"] {
+            insert(&engine, name, "Unrelated", &[]).await;
+        }
+        let gadget = || SearchQuery::new().with_text("gadget".to_string());
+
+        // Name, then tags, then description under the default weights.
+        assert_eq!(ranked_ids(&engine, gadget()).await, vec![named, tagged, described]);
+        let description_only = FieldWeights { name: 0.0, tags: 0.0, description: 1.0 };
+        assert_eq!(ranked_ids(&engine, gadget().with_weights(description_only)).await[0], described);
+
+        // min_relevance applies to the final score.
+        let result = engine.search(&gadget().with_sort(SortField::Relevance, crate::search::SortOrder::Desc)).await.unwrap();
+        let scores: Vec<f64> = result.items.iter().map(|result| result.relevance_score.unwrap()).collect();
+        assert!(scores.windows(2).all(|pair| pair[0] > pair[1]), "{:?}", scores);
+        assert_eq!(ranked_ids(&engine, gadget().with_min_relevance(scores[1])).await, vec![named, tagged]);
+
+        // A strong enough recency boost lifts the newest match to the top.
+        sqlx::query("UPDATE items SET created_at = ? WHERE id IN (?, ?)")
+            .bind(chrono::Utc::now() - chrono::Duration::days(3650))
+            .bind(named as i64)
+            .bind(tagged as i64)
+            .execute(&engine.pool)
+            .await
+            .unwrap();
+        let recent = engine.clone().with_ranking(&crate::config::SearchConfig {
+            recency_weight: 100.0,
+            recency_half_life_days: 1.0,
+            ..crate::config::SearchConfig::default()
+        });
+        assert_eq!(ranked_ids(&recent, gadget()).await[0], described);
+        assert_eq!(ranked_ids(&engine, gadget()).await[0], named);
+
+        let invalid = FieldWeights { name: -1.0, ..FieldWeights::default() };
+        assert!(matches!(engine.search(&gadget().with_weights(invalid)).await, Err(AppError::Validation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_sorted_pages_list_each_item_exactly_once() {
+        use crate::database::{CreateItemInput, ItemRepository};
+        use crate::search::SortOrder;
 
-        let matched = engine.identify_matched_fields(&item, "test");
-        assert!(matched.contains(&"name".to_string()));
-        assert!(matched.contains(&"description".to_string()));
-        assert!(matched.contains(&"tags".to_string()));
+        const COUNT: usize = 1000;
+        let temp_file = NamedTempFile::new().unwrap();
+        let pool = get_database_pool(&format!("sqlite:{}", temp_file.path().display())).await.unwrap();
+        run_migrations(pool.clone()).await.unwrap();
+        let repository = ItemRepository::new(pool.clone());
+        let created_at = chrono::Utc::now();
+        for i in 0..COUNT {
+            let input = CreateItemInput {
+                name: format!("item {}", i % 7),
+                description: None,
+                tags: Vec::new(),
+                metadata: None,
+                created_by: None,
+            };
+            repository.create_with_id(((i * 389) % COUNT + 1) as i64, created_at, input).await.unwrap();
+        }
 
-        let matched = engine.identify_matched_fields(&item, "nonexistent");
-        assert!(matched.is_empty());
+        let engine = SearchEngine::new(pool);
+        for field in [SortField::Name, SortField::CreatedAt, SortField::UpdatedAt, SortField::Relevance] {
+            for order in [SortOrder::Asc, SortOrder::Desc] {
+                let mut ids = Vec::new();
+                loop {
+                    let query = SearchQuery::new()
+                        .with_sort(field.clone(), order.clone())
+                        .with_pagination(ids.len() as u64, 64);
+                    let page = engine.search(&query).await.unwrap();
+                    ids.extend(page.items.iter().map(|hit| hit.item.id));
+                    if !page.has_more {
+                        break;
+                    }
+                }
+                let unique: std::collections::HashSet<_> = ids.iter().collect();
+                assert_eq!((ids.len(), unique.len()), (COUNT, COUNT), "{:?} {:?}", field, order);
+            }
+        }
     }
-}
\ No newline at end of file
+}
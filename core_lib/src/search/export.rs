@@ -0,0 +1,532 @@
+//! Search result export
+//!
+//! Small result sets are rendered straight into the response. Larger ones
+//! are collected by a background job, which pages through the matches by
+//! item id up to the highest id at the start of the export, so items
+//! written while it runs are neither repeated nor skipped, and then sorts
+//! them as the search asked.
+//!
+//! Excel workbooks need the `xlsx` feature. Those written by export jobs go
+//! to disk row by row rather than being built in memory.
+
+#[cfg(feature = "xlsx")]
+mod xlsx;
+
+use crate::config::SearchExportConfig;
+use crate::error::{AppError, Result};
+use crate::features::FeatureSwitch;
+use crate::files::{FileManager, FileMetadata, FileUpload};
+use crate::item_metadata::Metadata;
+use crate::search::expression::QueryExpr;
+use crate::search::{DateRange, SearchEngine, SearchQuery, SearchResultItem, SortCriterion, SortField, SortOrder};
+use crate::services::ItemService;
+use crate::store::Item;
+use crate::validation::ValidationError;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+    Yaml,
+    Xlsx,
+}
+
+impl ExportFormat {
+    /// `json`, `csv`, `yaml` or `xlsx`, in any case; anything else is JSON.
+    pub fn parse(format: &str) -> Self {
+        match format.to_lowercase().as_str() {
+            "csv" => ExportFormat::Csv,
+            "yaml" => ExportFormat::Yaml,
+            "xlsx" => ExportFormat::Xlsx,
+            _ => ExportFormat::Json,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Yaml => "yaml",
+            ExportFormat::Xlsx => "xlsx",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Yaml => "text/yaml",
+            ExportFormat::Xlsx => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        }
+    }
+
+    /// Fails with [`AppError::NotImplemented`] for workbooks when the
+    /// server was built without the `xlsx` feature.
+    pub fn ensure_supported(&self) -> Result<()> {
+        if *self == ExportFormat::Xlsx && !cfg!(feature = "xlsx") {
+            return Err(AppError::NotImplemented(XLSX_UNAVAILABLE.to_string()));
+        }
+        Ok(())
+    }
+
+    /// `items` in this format as the body of a download. Workbooks get a
+    /// second sheet listing `parameters`, the export's settings, and when
+    /// it was made.
+    pub fn render_bytes(&self, items: &[Item], safe_csv: bool, parameters: &serde_json::Value) -> Result<Vec<u8>> {
+        if *self != ExportFormat::Xlsx {
+            return Ok(self.render(items, safe_csv)?.into_bytes());
+        }
+
+        #[cfg(feature = "xlsx")]
+        {
+            let items: Vec<Item> = items.iter().map(crate::item_secrets::redacted).collect();
+            xlsx::to_bytes(&items, parameters, chrono::Utc::now())
+        }
+        #[cfg(not(feature = "xlsx"))]
+        {
+            let _ = parameters;
+            Err(AppError::NotImplemented(XLSX_UNAVAILABLE.to_string()))
+        }
+    }
+
+    /// `items` in this text format, with their secrets redacted. With
+    /// `safe_csv`, CSV text cells that a spreadsheet would evaluate as a
+    /// formula are prefixed with `'`.
+    pub fn render(&self, items: &[Item], safe_csv: bool) -> Result<String> {
+        let items: Vec<Item> = items.iter().map(crate::item_secrets::redacted).collect();
+        let items = items.as_slice();
+        match self {
+            ExportFormat::Csv => render_csv(items, safe_csv),
+            ExportFormat::Yaml => serde_yaml::to_string(&ExportedItem::all(items))
+                .map_err(|e| AppError::Other(anyhow::anyhow!("Failed to serialize to YAML: {}", e))),
+            ExportFormat::Json => Ok(serde_json::to_string_pretty(&ExportedItem::all(items))?),
+            ExportFormat::Xlsx => Err(AppError::Other(anyhow::anyhow!(
+                "Workbooks are binary; render them with render_bytes"
+            ))),
+        }
+    }
+}
+
+/// An item with its metadata parsed, for the formats that lay metadata out
+/// themselves rather than writing it as it is stored.
+#[derive(Serialize)]
+struct ExportedItem<'a> {
+    id: u64,
+    name: &'a str,
+    description: Option<&'a str>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    updated_at: chrono::DateTime<chrono::Utc>,
+    tags: &'a [String],
+    metadata: Option<serde_json::Value>,
+    version: u64,
+}
+
+impl<'a> ExportedItem<'a> {
+    fn all(items: &'a [Item]) -> Vec<Self> {
+        items
+            .iter()
+            .map(|item| Self {
+                id: item.id,
+                name: &item.name,
+                description: item.description.as_deref(),
+                created_at: item.created_at,
+                updated_at: item.updated_at,
+                tags: &item.tags,
+                metadata: item.metadata.as_ref().map(Metadata::to_value),
+                version: item.version,
+            })
+            .collect()
+    }
+}
+
+const XLSX_UNAVAILABLE: &str = "Excel exports need the server built with the xlsx feature";
+
+/// Leading characters that make a spreadsheet treat a cell as a formula.
+const FORMULA_PREFIXES: &[char] = &['=', '+', '-', '@', '\t', '\r'];
+
+/// `value` as a cell a spreadsheet shows as text.
+fn neutralize(value: &str, safe: bool) -> Cow<'_, str> {
+    if safe && value.starts_with(FORMULA_PREFIXES) {
+        Cow::Owned(format!("'{}", value))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+fn render_csv(items: &[Item], safe: bool) -> Result<String> {
+    let csv_error = |e: csv::Error| AppError::Other(anyhow::anyhow!("Failed to write CSV: {}", e));
+    // Sized for typical rows so large exports don't keep reallocating.
+    let mut writer = csv::Writer::from_writer(Vec::with_capacity(64 + items.len() * 160));
+    writer
+        .write_record(["id", "name", "description", "tags", "created_at", "updated_at", "metadata"])
+        .map_err(csv_error)?;
+    for item in items {
+        let tags = item.tags.join(";");
+        let metadata = item.metadata.as_ref().map(Metadata::as_str).unwrap_or_default();
+        writer
+            .write_record([
+                item.id.to_string().as_str(),
+                &neutralize(&item.name, safe),
+                &neutralize(item.description.as_deref().unwrap_or_default(), safe),
+                &neutralize(&tags, safe),
+                &item.created_at.to_rfc3339(),
+                &item.updated_at.to_rfc3339(),
+                &neutralize(metadata, safe),
+            ])
+            .map_err(csv_error)?;
+    }
+    let bytes = writer.into_inner().map_err(|e| csv_error(e.into_error().into()))?;
+    String::from_utf8(bytes).map_err(|e| AppError::Other(e.into()))
+}
+
+/// Collects search results for export from whichever store is active and
+/// writes large exports to files.
+#[derive(Clone)]
+pub struct SearchExporter {
+    search_engine: Option<SearchEngine>,
+    items: ItemService,
+    files: Option<FileManager>,
+    config: SearchExportConfig,
+    search_switch: FeatureSwitch,
+}
+
+impl SearchExporter {
+    pub fn new(
+        search_engine: Option<SearchEngine>,
+        items: ItemService,
+        files: Option<FileManager>,
+        config: SearchExportConfig,
+    ) -> Self {
+        Self {
+            search_engine,
+            items,
+            files,
+            config,
+            search_switch: FeatureSwitch::default(),
+        }
+    }
+
+    /// Filters items in memory while `search_switch` is off, for exporters
+    /// that outlive a change to it.
+    pub fn with_search_switch(mut self, search_switch: FeatureSwitch) -> Self {
+        self.search_switch = search_switch;
+        self
+    }
+
+    fn engine(&self) -> Option<&SearchEngine> {
+        self.search_engine.as_ref().filter(|_| self.search_switch.is_enabled())
+    }
+
+    /// The matches of `query` in its order, when there are no more than
+    /// `direct_limit` of them.
+    pub async fn collect_direct(&self, query: &SearchQuery) -> Result<Option<Vec<Item>>> {
+        let Some(engine) = self.engine() else {
+            let items = self.collect_in_memory(query).await?;
+            return Ok((items.len() as u64 <= self.config.direct_limit).then_some(items));
+        };
+
+        let query = query.clone().with_pagination(0, self.config.direct_limit);
+        let result = engine.search(&query).await?;
+        if result.total_count > self.config.direct_limit {
+            return Ok(None);
+        }
+        Ok(Some(result.items.into_iter().map(|result| result.item).collect()))
+    }
+
+    /// Every match of `query` as of the start of the call, in its order.
+    pub async fn collect(&self, query: &SearchQuery) -> Result<Vec<Item>> {
+        let Some(engine) = self.engine() else {
+            return self.collect_in_memory(query).await;
+        };
+
+        let max_id = engine.max_item_id().await?;
+        let mut results: Vec<SearchResultItem> = Vec::new();
+        let mut after_id = 0;
+        loop {
+            let page = query
+                .clone()
+                .with_id_cursor(after_id, Some(max_id))
+                .with_pagination(0, self.config.page_size);
+            let page = engine.search(&page).await?;
+            let Some(last) = page.items.last() else {
+                break;
+            };
+            after_id = last.item.id;
+            let more = page.has_more;
+            results.extend(page.items);
+            if !more {
+                break;
+            }
+        }
+
+        results.sort_by(|a, b| compare(query, a, b));
+        Ok(results.into_iter().map(|result| result.item).collect())
+    }
+
+    /// Collects the matches of `query`, renders them as `format` and stores
+    /// them as a file of `uploaded_by`.
+    pub async fn export_to_file(&self, query: &SearchQuery, format: ExportFormat, uploaded_by: u64) -> Result<(FileMetadata, usize)> {
+        let files = self
+            .files
+            .as_ref()
+            .ok_or_else(|| AppError::Job("File storage is not configured".to_string()))?;
+        format.ensure_supported()?;
+
+        let items = self.collect(query).await?;
+        #[cfg(feature = "xlsx")]
+        if format == ExportFormat::Xlsx {
+            return self.export_workbook(files, query, items, uploaded_by).await;
+        }
+
+        let upload = FileUpload {
+            original_filename: format!("search_export.{}", format.extension()),
+            content_type: format.content_type().to_string(),
+            data: format.render(&items, true)?.into_bytes(),
+            uploaded_by,
+            item_id: None,
+        };
+        let metadata = files.store_generated(upload).await?;
+        Ok((metadata, items.len()))
+    }
+
+    /// Writes `items` as a workbook to a file in storage, away from the
+    /// runtime's threads.
+    #[cfg(feature = "xlsx")]
+    async fn export_workbook(
+        &self,
+        files: &FileManager,
+        query: &SearchQuery,
+        items: Vec<Item>,
+        uploaded_by: u64,
+    ) -> Result<(FileMetadata, usize)> {
+        let count = items.len();
+        let parameters = serde_json::json!({ "format": ExportFormat::Xlsx, "search": query });
+        let path = files.generated_file_path().await?;
+        let written = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || {
+                let items: Vec<Item> = items.iter().map(crate::item_secrets::redacted).collect();
+                xlsx::write(&items, &parameters, chrono::Utc::now(), &path)
+            })
+            .await
+            .map_err(|e| AppError::Other(e.into()))
+            .and_then(|written| written)
+        };
+        let stored = match written {
+            Ok(()) => {
+                let format = ExportFormat::Xlsx;
+                let filename = format!("search_export.{}", format.extension());
+                files.store_generated_file(&path, &filename, format.content_type(), uploaded_by).await
+            }
+            Err(e) => Err(e),
+        };
+        if stored.is_err() {
+            let _ = tokio::fs::remove_file(&path).await;
+        }
+        Ok((stored?, count))
+    }
+
+    async fn collect_in_memory(&self, query: &SearchQuery) -> Result<Vec<Item>> {
+        let results = search_in_memory(&self.items, query).await?;
+        Ok(results.into_iter().map(|result| result.item).collect())
+    }
+}
+
+/// Every match of `query` in the in-memory store, in its order. That store
+/// has no index; its items are filtered one by one.
+pub(crate) async fn search_in_memory(items: &ItemService, query: &SearchQuery) -> Result<Vec<SearchResultItem>> {
+    let expr = match query.text.as_deref() {
+        Some(text) => Some(QueryExpr::parse(text).map_err(ValidationError::from)?),
+        None => None,
+    };
+    let in_range = |range: &Option<DateRange>, at: chrono::DateTime<chrono::Utc>| {
+        range.as_ref().is_none_or(|range| {
+            range.start.is_none_or(|start| at >= start) && range.end.is_none_or(|end| at <= end)
+        })
+    };
+
+    let mut results: Vec<SearchResultItem> = items
+        .get_items(None, None)
+        .await?
+        .into_iter()
+        .filter(|item| query.tags.is_empty() || item.tags.iter().any(|tag| query.tags.contains(tag)))
+        .filter(|item| expr.as_ref().is_none_or(|expr| expr.matches(item)))
+        .filter(|item| in_range(&query.created_date_range, item.created_at))
+        .filter(|item| in_range(&query.updated_date_range, item.updated_at))
+        .map(SearchResultItem::new)
+        .collect();
+    results.sort_by(|a, b| compare(query, a, b));
+    Ok(results)
+}
+
+/// Orders results as the search engine would, newest first when no order
+/// is given, with ties broken by id so repeated exports agree.
+fn compare(query: &SearchQuery, a: &SearchResultItem, b: &SearchResultItem) -> Ordering {
+    const NEWEST_FIRST: &[SortCriterion] = &[SortCriterion {
+        field: SortField::CreatedAt,
+        order: SortOrder::Desc,
+    }];
+    let criteria = if query.sort_criteria.is_empty() { NEWEST_FIRST } else { &query.sort_criteria };
+
+    criteria
+        .iter()
+        .map(|criterion| {
+            let ordering = match criterion.field {
+                SortField::Name => a.item.name.cmp(&b.item.name),
+                SortField::CreatedAt => a.item.created_at.cmp(&b.item.created_at),
+                SortField::UpdatedAt => a.item.updated_at.cmp(&b.item.updated_at),
+                SortField::Relevance => a
+                    .relevance_score
+                    .partial_cmp(&b.relevance_score)
+                    .unwrap_or(Ordering::Equal),
+            };
+            match criterion.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or_else(|| match criteria.last().map(|criterion| &criterion.order) {
+            Some(SortOrder::Desc) => b.item.id.cmp(&a.item.id),
+            _ => a.item.id.cmp(&b.item.id),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::DataStore;
+
+    #[tokio::test]
+    async fn test_memory_export_filters_and_sorts() {
+        let store = DataStore::empty();
+        for (name, tags) in [("Report B", vec!["finance"]), ("Memo", vec!["finance"]), ("Report A", vec![]), ("Report A", vec!["finance"])] {
+            store
+                .create_item(name.to_string(), None, tags.into_iter().map(str::to_string).collect(), None)
+                .unwrap();
+        }
+        let config = SearchExportConfig { direct_limit: 2, page_size: 1 };
+        let exporter = SearchExporter::new(None, ItemService::with_memory_store(store), None, config);
+
+        let query = SearchQuery::new()
+            .with_text("report".to_string())
+            .with_sort(SortField::Name, SortOrder::Asc);
+        let ids: Vec<u64> = exporter.collect(&query).await.unwrap().iter().map(|item| item.id).collect();
+        assert_eq!(ids, vec![3, 4, 1]);
+        assert!(exporter.collect_direct(&query).await.unwrap().is_none());
+
+        let tagged = query.with_tags(vec!["finance".to_string()]);
+        let csv = ExportFormat::Csv.render(&exporter.collect_direct(&tagged).await.unwrap().unwrap(), true).unwrap();
+        assert_eq!(csv.lines().skip(1).map(|line| &line[..2]).collect::<Vec<_>>(), vec!["4,", "1,"]);
+    }
+
+    fn item(id: u64, name: &str, description: Option<&str>, tags: &[&str], metadata: Option<serde_json::Value>) -> Item {
+        let at = chrono::DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z").unwrap().with_timezone(&chrono::Utc);
+        Item {
+            id,
+            name: name.to_string(),
+            description: description.map(str::to_string),
+            created_at: at,
+            updated_at: at,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            metadata: metadata.map(Metadata::from),
+            version: 1,
+        }
+    }
+
+    fn read_csv(csv: &str) -> Vec<Vec<String>> {
+        csv::Reader::from_reader(csv.as_bytes())
+            .records()
+            .map(|record| record.unwrap().iter().map(str::to_string).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_csv_round_trips_adversarial_content() {
+        let items = vec![
+            item(1, "line\nbreak, \"quoted\"", Some("multi\r\nline, with \"quotes\""), &["a,b", "c\"d"], None),
+            item(2, "=HYPERLINK(\"http://evil\",\"click\")", Some("+1"), &["-tag", "ok"], Some(serde_json::json!({"note": "@SUM(A1)"}))),
+            item(3, "plain", None, &[], Some(serde_json::json!({"a": [1, 2]}))),
+        ];
+
+        let raw = read_csv(&ExportFormat::Csv.render(&items, false).unwrap());
+        assert_eq!(raw.len(), 3);
+        assert_eq!(raw[0][1], "line\nbreak, \"quoted\"");
+        assert_eq!(raw[0][2], "multi\r\nline, with \"quotes\"");
+        assert_eq!(raw[0][3], "a,b;c\"d");
+        assert_eq!(raw[1][1], "=HYPERLINK(\"http://evil\",\"click\")");
+        assert_eq!(raw[1][6], "{\"note\":\"@SUM(A1)\"}");
+        assert_eq!(raw[2][2], "");
+        assert_eq!(raw[2][6], "{\"a\":[1,2]}");
+
+        let safe = read_csv(&ExportFormat::Csv.render(&items, true).unwrap());
+        assert_eq!(safe[0][1], raw[0][1]);
+        assert_eq!(safe[1][1], "'=HYPERLINK(\"http://evil\",\"click\")");
+        assert_eq!(safe[1][2], "'+1");
+        assert_eq!(safe[1][3], "'-tag;ok");
+        // Only the start of a cell matters.
+        assert_eq!(safe[1][6], raw[1][6]);
+        assert_eq!(safe[1][0], "2");
+    }
+
+    #[test]
+    fn test_large_csv_export() {
+        let items: Vec<Item> = (1..=20_000)
+            .map(|id| item(id, &format!("Item {}", id), Some("a, \"b\"\nc"), &["x", "y"], None))
+            .collect();
+        let rows = read_csv(&ExportFormat::Csv.render(&items, true).unwrap());
+        assert_eq!(rows.len(), items.len());
+        assert_eq!(rows[19_999][0], "20000");
+        assert_eq!(rows[19_999][2], "a, \"b\"\nc");
+    }
+
+    #[cfg(not(feature = "xlsx"))]
+    #[test]
+    fn test_xlsx_needs_the_feature() {
+        let error = ExportFormat::Xlsx.render_bytes(&[], true, &serde_json::Value::Null).unwrap_err();
+        assert!(matches!(error, AppError::NotImplemented(_)));
+        assert!(ExportFormat::Csv.ensure_supported().is_ok());
+    }
+
+    #[cfg(feature = "xlsx")]
+    fn unzip(workbook: &[u8], name: &str) -> String {
+        use std::io::Read;
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(workbook)).unwrap();
+        let mut xml = String::new();
+        archive.by_name(name).unwrap().read_to_string(&mut xml).unwrap();
+        xml
+    }
+
+    #[cfg(feature = "xlsx")]
+    #[test]
+    fn test_xlsx_has_typed_columns_and_an_export_sheet() {
+        let items = vec![
+            item(7, "=SUM(A1)", Some("caf\u{e9} \u{1f600}"), &["a", "b"], Some(serde_json::json!({"k": 1}))),
+            item(8, "plain", None, &[], None),
+        ];
+        let parameters = serde_json::json!({"format": "xlsx", "search": {"text": "report"}, "unused": null});
+        let workbook = ExportFormat::Xlsx.render_bytes(&items, true, &parameters).unwrap();
+        let items_sheet = unzip(&workbook, "xl/worksheets/sheet1.xml");
+        assert!(items_sheet.contains(r#"state="frozen""#));
+        assert!(items_sheet.contains(r#"<c r="A2"><v>7</v></c>"#));
+        // Formula-like text stays text.
+        assert!(items_sheet.contains(r#"<c r="B2" t="inlineStr"><is><t>=SUM(A1)</t></is></c>"#));
+        assert!(items_sheet.contains("caf\u{e9} \u{1f600}"));
+        assert!(items_sheet.contains(r#"<is><t>a; b</t></is>"#));
+        // 2024-01-01 as an Excel date, in the date format.
+        assert!(items_sheet.contains(r#"<c r="E2" s="2"><v>45292</v></c>"#));
+        assert!(unzip(&workbook, "xl/styles.xml").contains(r#"numFmtId="164" formatCode="yyyy-mm-dd hh:mm:ss""#));
+
+        assert!(unzip(&workbook, "xl/workbook.xml").contains(r#"<sheet name="Export" sheetId="2""#));
+        let strings = unzip(&workbook, "xl/sharedStrings.xml");
+        for text in ["exported_at", "item_count", "search", r#"{"text":"report"}"#] {
+            assert!(strings.contains(&format!("<t>{}</t>", text)), "{} missing from {}", text, strings);
+        }
+        assert!(!strings.contains("unused"));
+    }
+}
@@ -0,0 +1,100 @@
+//! Items as an Excel workbook
+
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use rust_xlsxwriter::{Format, Workbook, Worksheet, XlsxError};
+
+use crate::error::{AppError, Result};
+use crate::store::Item;
+
+const COLUMNS: &[(&str, f64)] = &[
+    ("id", 10.0),
+    ("name", 30.0),
+    ("description", 50.0),
+    ("tags", 30.0),
+    ("created_at", 20.0),
+    ("updated_at", 20.0),
+    ("metadata", 50.0),
+];
+
+const DATE_FORMAT: &str = "yyyy-mm-dd hh:mm:ss";
+
+/// Writes `items` to `path` as a workbook. Rows go to disk as they are
+/// written, so the workbook is never held in memory whole.
+pub(super) fn write(items: &[Item], parameters: &serde_json::Value, exported_at: DateTime<Utc>, path: &Path) -> Result<()> {
+    workbook(items, parameters, exported_at)?.save(path).map_err(xlsx_error)
+}
+
+/// `items` as the bytes of a workbook, for exports small enough to answer
+/// directly.
+pub(super) fn to_bytes(items: &[Item], parameters: &serde_json::Value, exported_at: DateTime<Utc>) -> Result<Vec<u8>> {
+    workbook(items, parameters, exported_at)?.save_to_buffer().map_err(xlsx_error)
+}
+
+/// An `Items` sheet with a typed column per field under a frozen header
+/// row, and an `Export` sheet recording `parameters` and `exported_at`.
+/// Text is always written as text, so no cell is ever taken for a formula.
+fn workbook(items: &[Item], parameters: &serde_json::Value, exported_at: DateTime<Utc>) -> Result<Workbook> {
+    let header = Format::new().set_bold();
+    let date = Format::new().set_num_format(DATE_FORMAT);
+
+    let mut workbook = Workbook::new();
+    let sheet = workbook.add_worksheet_with_constant_memory();
+    sheet.set_name("Items").map_err(xlsx_error)?;
+    for (column, (title, width)) in COLUMNS.iter().enumerate() {
+        sheet.set_column_width(column as u16, *width).map_err(xlsx_error)?;
+        sheet.write_string_with_format(0, column as u16, *title, &header).map_err(xlsx_error)?;
+    }
+    sheet.set_freeze_panes(1, 0).map_err(xlsx_error)?;
+
+    for (index, item) in items.iter().enumerate() {
+        write_item(sheet, index as u32 + 1, item, &date).map_err(xlsx_error)?;
+    }
+
+    let sheet = workbook.add_worksheet();
+    sheet.set_name("Export").map_err(xlsx_error)?;
+    sheet.set_column_width(0, 20.0).map_err(xlsx_error)?;
+    sheet.set_column_width(1, 60.0).map_err(xlsx_error)?;
+    sheet.write_string_with_format(0, 0, "parameter", &header).map_err(xlsx_error)?;
+    sheet.write_string_with_format(0, 1, "value", &header).map_err(xlsx_error)?;
+    sheet.write_string(1, 0, "exported_at").map_err(xlsx_error)?;
+    sheet
+        .write_datetime_with_format(1, 1, exported_at.naive_utc(), &date)
+        .map_err(xlsx_error)?;
+    sheet.write_string(2, 0, "item_count").map_err(xlsx_error)?;
+    sheet.write_number(2, 1, items.len() as f64).map_err(xlsx_error)?;
+
+    let mut row = 3;
+    for (name, value) in parameters.as_object().into_iter().flatten() {
+        if value.is_null() {
+            continue;
+        }
+        let value = match value {
+            serde_json::Value::String(value) => value.clone(),
+            value => value.to_string(),
+        };
+        sheet.write_string(row, 0, name).map_err(xlsx_error)?;
+        sheet.write_string(row, 1, value).map_err(xlsx_error)?;
+        row += 1;
+    }
+
+    Ok(workbook)
+}
+
+fn write_item(sheet: &mut Worksheet, row: u32, item: &Item, date: &Format) -> std::result::Result<(), XlsxError> {
+    sheet.write_number(row, 0, item.id as f64)?;
+    sheet.write_string(row, 1, &item.name)?;
+    sheet.write_string(row, 2, item.description.as_deref().unwrap_or_default())?;
+    sheet.write_string(row, 3, item.tags.join("; "))?;
+    sheet.write_datetime_with_format(row, 4, item.created_at.naive_utc(), date)?;
+    sheet.write_datetime_with_format(row, 5, item.updated_at.naive_utc(), date)?;
+    if let Some(metadata) = &item.metadata {
+        sheet.write_string(row, 6, metadata.as_str())?;
+    }
+    Ok(())
+}
+
+fn xlsx_error(e: XlsxError) -> AppError {
+    AppError::Other(anyhow::anyhow!("Failed to write workbook: {}", e))
+}
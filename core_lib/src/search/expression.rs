@@ -0,0 +1,801 @@
+//! Search query language
+//!
+//! `name:report AND tags:finance NOT draft` parses into a [`QueryExpr`].
+//! Terms are words, `"quoted phrases"` or `prefix*` words, optionally scoped
+//! to `name:`, `description:`, `tags:`, `content:` or `metadata.<key>:`. Terms next to
+//! each other are ANDed; `NOT` binds tighter than `AND`, which binds tighter
+//! than `OR`, and parentheses group. Operators are only recognised in upper
+//! case, so `and` on its own is a word.
+//!
+//! Unscoped, `name:` and `description:` terms go to the full-text index,
+//! which holds the words of tags too, so unscoped terms match those as
+//! well; `tags:` terms match a whole tag and `metadata.<key>:` terms a
+//! whole value, both ignoring case. `content:` terms match items with an
+//! attached file whose extracted text has the words, through `files_fts`;
+//! items held in memory have no files, so nothing matches them there.
+
+use crate::store::Item;
+use crate::validation::ValidationError;
+use std::fmt;
+
+/// Deepest nesting of parentheses and `NOT`s accepted.
+const MAX_DEPTH: usize = 32;
+
+/// What a term is matched against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    /// Name, description or the words of a tag.
+    Any,
+    Name,
+    Description,
+    Tags,
+    /// The extracted text of the item's files.
+    Content,
+    Metadata(String),
+}
+
+/// A parsed search query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryExpr {
+    Term {
+        field: Field,
+        text: String,
+        /// Written in quotes: the words must appear together, in order.
+        phrase: bool,
+        /// Written with a trailing `*`: the last word may continue.
+        prefix: bool,
+    },
+    And(Vec<QueryExpr>),
+    Or(Vec<QueryExpr>),
+    Not(Box<QueryExpr>),
+}
+
+/// Where and why a query failed to parse. Positions count characters from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for ValidationError {
+    fn from(error: ParseError) -> Self {
+        ValidationError::field("q", "query_syntax", error.to_string())
+    }
+}
+
+/// A query split for SQL: an FTS5 `MATCH` expression, when any part of the
+/// query can use the index, and conditions on `items i` for the rest.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MatchPlan {
+    pub fts: Option<String>,
+    pub conditions: Vec<String>,
+    pub params: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Field(Field),
+    Word { text: String, prefix: bool },
+    Phrase(String),
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let position = i + 1;
+        match chars[i] {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push((position, Token::Open));
+                i += 1;
+            }
+            ')' => {
+                tokens.push((position, Token::Close));
+                i += 1;
+            }
+            '"' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&c| c == '"')
+                    .map(|offset| i + 1 + offset)
+                    .ok_or_else(|| ParseError {
+                        position,
+                        message: "Unterminated quote".to_string(),
+                    })?;
+                let text: String = chars[i + 1..end].iter().collect();
+                if !has_words(&text) {
+                    return Err(ParseError {
+                        position,
+                        message: "Empty phrase".to_string(),
+                    });
+                }
+                tokens.push((position, Token::Phrase(text)));
+                i = end + 1;
+            }
+            _ => {
+                let start = i;
+                while i < chars.len() && !chars[i].is_whitespace() && !matches!(chars[i], '(' | ')' | '"' | ':') {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+
+                if i < chars.len() && chars[i] == ':' {
+                    tokens.push((position, Token::Field(field(&word, position)?)));
+                    i += 1;
+                    if i >= chars.len() || chars[i].is_whitespace() || matches!(chars[i], '(' | ')' | ':') {
+                        return Err(ParseError {
+                            position: i + 1,
+                            message: format!("Expected a value after '{}:'", word),
+                        });
+                    }
+                    continue;
+                }
+
+                let token = match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => {
+                        let (text, prefix) = match word.strip_suffix('*') {
+                            Some(stem) => (stem.to_string(), true),
+                            None => (word.clone(), false),
+                        };
+                        if !has_words(&text) {
+                            return Err(ParseError {
+                                position,
+                                message: format!("'{}' contains no letters or digits", word),
+                            });
+                        }
+                        Token::Word { text, prefix }
+                    }
+                };
+                tokens.push((position, token));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn field(name: &str, position: usize) -> Result<Field, ParseError> {
+    match name {
+        "name" => Ok(Field::Name),
+        "description" => Ok(Field::Description),
+        "tags" | "tag" => Ok(Field::Tags),
+        "content" => Ok(Field::Content),
+        _ => match name.strip_prefix("metadata.") {
+            // Matching would tell a searcher what a secret they cannot read is.
+            Some(crate::item_secrets::SECRET_FIELD) => Err(ParseError {
+                position,
+                message: format!("metadata.{} cannot be searched", crate::item_secrets::SECRET_FIELD),
+            }),
+            Some(key) if !key.is_empty() && key.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') => {
+                Ok(Field::Metadata(key.to_string()))
+            }
+            _ => Err(ParseError {
+                position,
+                message: format!(
+                    "Unknown field '{}'; expected name, description, tags, content or metadata.<key>",
+                    name
+                ),
+            }),
+        },
+    }
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    end: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.end, |(position, _)| *position)
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            position: self.position(),
+            message: message.into(),
+        }
+    }
+
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error("Query is nested too deeply"));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn or(&mut self) -> Result<QueryExpr, ParseError> {
+        let mut operands = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.next += 1;
+            operands.push(self.and()?);
+        }
+        Ok(collapse(operands, QueryExpr::Or))
+    }
+
+    fn and(&mut self) -> Result<QueryExpr, ParseError> {
+        let mut operands = vec![self.unary()?];
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.next += 1;
+                    operands.push(self.unary()?);
+                }
+                Some(Token::Or | Token::Close) | None => break,
+                Some(_) => operands.push(self.unary()?),
+            }
+        }
+        Ok(collapse(operands, QueryExpr::And))
+    }
+
+    fn unary(&mut self) -> Result<QueryExpr, ParseError> {
+        if self.peek() == Some(&Token::Not) {
+            self.next += 1;
+            return self.nested(|parser| Ok(QueryExpr::Not(Box::new(parser.unary()?))));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<QueryExpr, ParseError> {
+        let position = self.position();
+        let token = self.peek().cloned();
+        match token {
+            Some(Token::Open) => {
+                self.next += 1;
+                let inner = self.nested(Self::or)?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(ParseError {
+                        position,
+                        message: "Unclosed '('".to_string(),
+                    });
+                }
+                self.next += 1;
+                Ok(inner)
+            }
+            Some(Token::Field(field)) => {
+                self.next += 1;
+                self.term(field)
+            }
+            Some(Token::Word { .. } | Token::Phrase(_)) => self.term(Field::Any),
+            Some(Token::Close) => Err(self.error("Unexpected ')'")),
+            Some(Token::And | Token::Or | Token::Not) | None => {
+                let after = self.next.checked_sub(1).and_then(|previous| self.tokens.get(previous));
+                Err(match after {
+                    Some((_, Token::And)) => self.error("Expected a term after 'AND'"),
+                    Some((_, Token::Or)) => self.error("Expected a term after 'OR'"),
+                    Some((_, Token::Not)) => self.error("Expected a term after 'NOT'"),
+                    _ => self.error("Expected a term"),
+                })
+            }
+        }
+    }
+
+    fn term(&mut self, field: Field) -> Result<QueryExpr, ParseError> {
+        let (text, phrase, prefix) = match self.peek().cloned() {
+            Some(Token::Word { text, prefix }) => (text, false, prefix),
+            Some(Token::Phrase(text)) => (text, true, false),
+            _ => return Err(self.error("Expected a word or phrase")),
+        };
+        self.next += 1;
+        Ok(QueryExpr::Term { field, text, phrase, prefix })
+    }
+}
+
+fn collapse(mut operands: Vec<QueryExpr>, combine: fn(Vec<QueryExpr>) -> QueryExpr) -> QueryExpr {
+    if operands.len() == 1 {
+        operands.remove(0)
+    } else {
+        combine(operands)
+    }
+}
+
+fn has_words(text: &str) -> bool {
+    text.chars().any(char::is_alphanumeric)
+}
+
+/// Lowercase runs of letters and digits, as the full-text index splits text.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// FTS5 string literal.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+impl QueryExpr {
+    pub fn parse(input: &str) -> Result<Self, ParseError> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser {
+            tokens,
+            next: 0,
+            end: input.chars().count() + 1,
+            depth: 0,
+        };
+        if parser.peek().is_none() {
+            return Err(parser.error("Query is empty"));
+        }
+
+        let expr = parser.or()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("Unexpected ')'"));
+        }
+        Ok(expr)
+    }
+
+    /// The same query with `rewrite` applied to the text of every term.
+    pub fn map_terms(self, rewrite: &impl Fn(&str) -> String) -> Self {
+        match self {
+            QueryExpr::Term { field, text, phrase, prefix } => QueryExpr::Term {
+                field,
+                text: rewrite(&text),
+                phrase,
+                prefix,
+            },
+            QueryExpr::And(operands) => QueryExpr::And(operands.into_iter().map(|e| e.map_terms(rewrite)).collect()),
+            QueryExpr::Or(operands) => QueryExpr::Or(operands.into_iter().map(|e| e.map_terms(rewrite)).collect()),
+            QueryExpr::Not(inner) => QueryExpr::Not(Box::new(inner.map_terms(rewrite))),
+        }
+    }
+
+    /// Splits the query into what the full-text index can answer and SQL
+    /// conditions for the rest. Top-level `AND`ed parts stay in the index
+    /// where they can, so results are still ranked.
+    pub fn plan(&self) -> MatchPlan {
+        let conjuncts = match self {
+            QueryExpr::And(operands) => operands.iter().collect(),
+            other => vec![other],
+        };
+
+        let mut plan = MatchPlan::default();
+        let mut positive = Vec::new();
+        let mut negative = Vec::new();
+        for conjunct in conjuncts {
+            match conjunct {
+                QueryExpr::Not(inner) => match inner.fts() {
+                    Some(fts) => negative.push(fts),
+                    None => plan.push_sql(conjunct),
+                },
+                _ => match conjunct.fts() {
+                    Some(fts) => positive.push(fts),
+                    None => plan.push_sql(conjunct),
+                },
+            }
+        }
+
+        if positive.is_empty() {
+            // FTS5 has no unary NOT, so exclusions need something to
+            // subtract from.
+            for fts in negative {
+                plan.conditions.push("i.id NOT IN (SELECT rowid FROM items_fts WHERE items_fts MATCH ?)".to_string());
+                plan.params.push(fts);
+            }
+        } else {
+            let mut fts = positive.join(" AND ");
+            for exclusion in negative {
+                fts.push_str(" NOT ");
+                fts.push_str(&exclusion);
+            }
+            plan.fts = Some(fts);
+        }
+        plan
+    }
+
+    /// The query as an FTS5 expression, when it only involves indexed
+    /// fields and every `NOT` follows something to exclude from.
+    pub(crate) fn fts(&self) -> Option<String> {
+        match self {
+            QueryExpr::Term { field, text, phrase, prefix } => {
+                let column = match field {
+                    Field::Any => "",
+                    Field::Name => "name : ",
+                    Field::Description => "description : ",
+                    Field::Tags | Field::Content | Field::Metadata(_) => return None,
+                };
+                let literal = if *phrase { quote(text) } else { quote(&words(text).join(" ")) };
+                Some(format!("{}{}{}", column, literal, if *prefix { " *" } else { "" }))
+            }
+            QueryExpr::And(operands) => {
+                let mut positive = Vec::new();
+                let mut negative = Vec::new();
+                for operand in operands {
+                    match operand {
+                        QueryExpr::Not(inner) => negative.push(inner.fts()?),
+                        _ => positive.push(operand.fts()?),
+                    }
+                }
+                if positive.is_empty() {
+                    return None;
+                }
+                let mut fts = format!("({}", positive.join(" AND "));
+                for exclusion in negative {
+                    fts.push_str(" NOT ");
+                    fts.push_str(&exclusion);
+                }
+                fts.push(')');
+                Some(fts)
+            }
+            QueryExpr::Or(operands) => {
+                let operands: Option<Vec<String>> = operands.iter().map(QueryExpr::fts).collect();
+                Some(format!("({})", operands?.join(" OR ")))
+            }
+            QueryExpr::Not(_) => None,
+        }
+    }
+
+    /// The query as an FTS5 expression over `files_fts`, when its terms
+    /// are all unscoped or `content:` and every `NOT` follows something to
+    /// exclude from.
+    pub fn content_fts(&self) -> Option<String> {
+        self.as_content()?.fts()
+    }
+
+    /// The same query with every term unscoped, if none was scoped to
+    /// anything but `content:`.
+    fn as_content(&self) -> Option<QueryExpr> {
+        Some(match self {
+            QueryExpr::Term { field: Field::Any | Field::Content, text, phrase, prefix } => QueryExpr::Term {
+                field: Field::Any,
+                text: text.clone(),
+                phrase: *phrase,
+                prefix: *prefix,
+            },
+            QueryExpr::Term { .. } => return None,
+            QueryExpr::And(operands) => QueryExpr::And(operands.iter().map(QueryExpr::as_content).collect::<Option<_>>()?),
+            QueryExpr::Or(operands) => QueryExpr::Or(operands.iter().map(QueryExpr::as_content).collect::<Option<_>>()?),
+            QueryExpr::Not(inner) => QueryExpr::Not(Box::new(inner.as_content()?)),
+        })
+    }
+
+    /// Whether any term is scoped to `content:`.
+    pub fn has_content_terms(&self) -> bool {
+        match self {
+            QueryExpr::Term { field, .. } => *field == Field::Content,
+            QueryExpr::And(operands) | QueryExpr::Or(operands) => operands.iter().any(QueryExpr::has_content_terms),
+            QueryExpr::Not(inner) => inner.has_content_terms(),
+        }
+    }
+
+    /// The query as a condition on `items i`, with its parameters.
+    fn sql(&self, params: &mut Vec<String>) -> String {
+        match self {
+            QueryExpr::Term { field, text, .. } => match field {
+                Field::Tags => {
+                    params.push(text.clone());
+                    "EXISTS (SELECT 1 FROM json_each(i.tags) WHERE lower(json_each.value) = lower(?))".to_string()
+                }
+                Field::Metadata(key) => {
+                    params.push(key.clone());
+                    params.push(text.clone());
+                    "EXISTS (SELECT 1 FROM json_each(i.metadata) WHERE json_each.key = ? AND lower(CASE json_each.type \
+                     WHEN 'true' THEN 'true' WHEN 'false' THEN 'false' ELSE CAST(json_each.value AS TEXT) END) = lower(?))"
+                        .to_string()
+                }
+                Field::Content => {
+                    params.push(self.content_fts().unwrap_or_default());
+                    "i.id IN (SELECT f.item_id FROM files_fts JOIN files f ON f.id = files_fts.file_id \
+                     WHERE files_fts MATCH ? AND f.item_id IS NOT NULL)"
+                        .to_string()
+                }
+                _ => {
+                    params.push(self.fts().unwrap_or_default());
+                    "i.id IN (SELECT rowid FROM items_fts WHERE items_fts MATCH ?)".to_string()
+                }
+            },
+            QueryExpr::And(operands) => {
+                let conditions: Vec<String> = operands.iter().map(|operand| operand.sql(params)).collect();
+                format!("({})", conditions.join(" AND "))
+            }
+            QueryExpr::Or(operands) => {
+                let conditions: Vec<String> = operands.iter().map(|operand| operand.sql(params)).collect();
+                format!("({})", conditions.join(" OR "))
+            }
+            QueryExpr::Not(inner) => format!("NOT {}", inner.sql(params)),
+        }
+    }
+
+    /// Whether `item` satisfies the query, matched as the SQL search would.
+    pub fn matches(&self, item: &Item) -> bool {
+        match self {
+            QueryExpr::Term { field, .. } => match field {
+                Field::Any => {
+                    self.matches_text(&item.name)
+                        || item.description.as_deref().is_some_and(|d| self.matches_text(d))
+                        || item.tags.iter().any(|tag| self.matches_text(tag))
+                }
+                Field::Name => self.matches_text(&item.name),
+                Field::Description => item.description.as_deref().is_some_and(|d| self.matches_text(d)),
+                Field::Tags => item.tags.iter().any(|tag| self.matches_value(tag)),
+                Field::Content => false,
+                Field::Metadata(key) => item
+                    .metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.get(key))
+                    .is_some_and(|value| match &value {
+                        serde_json::Value::String(value) => self.matches_value(value),
+                        serde_json::Value::Number(_) | serde_json::Value::Bool(_) => self.matches_value(&value.to_string()),
+                        _ => false,
+                    }),
+            },
+            QueryExpr::And(operands) => operands.iter().all(|operand| operand.matches(item)),
+            QueryExpr::Or(operands) => operands.iter().any(|operand| operand.matches(item)),
+            QueryExpr::Not(inner) => !inner.matches(item),
+        }
+    }
+
+    /// Fields of `item` containing a term the query asks for, leaving out
+    /// terms under `NOT`.
+    pub fn matched_fields(&self, item: &Item) -> Vec<String> {
+        let mut fields = Vec::new();
+        self.collect_matched_fields(item, &mut fields);
+        ["name", "description", "tags", "metadata"]
+            .into_iter()
+            .filter(|field| fields.contains(field))
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn collect_matched_fields(&self, item: &Item, fields: &mut Vec<&'static str>) {
+        match self {
+            QueryExpr::Term { field, .. } => {
+                let name = self.matches_text(&item.name);
+                let description = item.description.as_deref().is_some_and(|d| self.matches_text(d));
+                match field {
+                    Field::Any => {
+                        if name {
+                            fields.push("name");
+                        }
+                        if description {
+                            fields.push("description");
+                        }
+                        if item.tags.iter().any(|tag| self.matches_text(tag)) {
+                            fields.push("tags");
+                        }
+                    }
+                    Field::Name if name => fields.push("name"),
+                    Field::Description if description => fields.push("description"),
+                    Field::Tags | Field::Metadata(_) if self.matches(item) => {
+                        fields.push(if *field == Field::Tags { "tags" } else { "metadata" })
+                    }
+                    _ => {}
+                }
+            }
+            QueryExpr::And(operands) | QueryExpr::Or(operands) => {
+                for operand in operands {
+                    operand.collect_matched_fields(item, fields);
+                }
+            }
+            QueryExpr::Not(_) => {}
+        }
+    }
+
+    /// Word or phrase match within running text.
+    fn matches_text(&self, haystack: &str) -> bool {
+        let QueryExpr::Term { text, prefix, .. } = self else {
+            return false;
+        };
+        let needle = words(text);
+        let haystack = words(haystack);
+        if needle.is_empty() || needle.len() > haystack.len() {
+            return false;
+        }
+        let last = needle.len() - 1;
+        haystack.windows(needle.len()).any(|window| {
+            window.iter().zip(&needle).enumerate().all(|(i, (word, wanted))| {
+                if i == last && *prefix {
+                    word.starts_with(wanted.as_str())
+                } else {
+                    word == wanted
+                }
+            })
+        })
+    }
+
+    /// Whole-value match, ignoring case.
+    fn matches_value(&self, value: &str) -> bool {
+        let QueryExpr::Term { text, prefix, .. } = self else {
+            return false;
+        };
+        let (value, text) = (value.to_lowercase(), text.to_lowercase());
+        if *prefix {
+            value.starts_with(&text)
+        } else {
+            value == text
+        }
+    }
+}
+
+impl MatchPlan {
+    fn push_sql(&mut self, expr: &QueryExpr) {
+        let condition = expr.sql(&mut self.params);
+        self.conditions.push(condition);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(field: Field, text: &str) -> QueryExpr {
+        QueryExpr::Term { field, text: text.to_string(), phrase: false, prefix: false }
+    }
+
+    fn item(name: &str, description: Option<&str>, tags: &[&str], metadata: Option<serde_json::Value>) -> Item {
+        Item {
+            id: 1,
+            name: name.to_string(),
+            description: description.map(str::to_string),
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            metadata: metadata.map(Into::into),
+            version: 1,
+        }
+    }
+
+    #[test]
+    fn test_precedence() {
+        let expr = QueryExpr::parse("a OR b c NOT d").unwrap();
+        assert_eq!(
+            expr,
+            QueryExpr::Or(vec![
+                term(Field::Any, "a"),
+                QueryExpr::And(vec![
+                    term(Field::Any, "b"),
+                    term(Field::Any, "c"),
+                    QueryExpr::Not(Box::new(term(Field::Any, "d"))),
+                ]),
+            ])
+        );
+
+        let grouped = QueryExpr::parse("(a OR b) AND c").unwrap();
+        assert_eq!(
+            grouped,
+            QueryExpr::And(vec![
+                QueryExpr::Or(vec![term(Field::Any, "a"), term(Field::Any, "b")]),
+                term(Field::Any, "c"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_fields_phrases_and_prefixes() {
+        let expr = QueryExpr::parse(r#"name:"quarterly report" tags:finance metadata.owner:ana rep*"#).unwrap();
+        assert_eq!(
+            expr,
+            QueryExpr::And(vec![
+                QueryExpr::Term { field: Field::Name, text: "quarterly report".to_string(), phrase: true, prefix: false },
+                term(Field::Tags, "finance"),
+                term(Field::Metadata("owner".to_string()), "ana"),
+                QueryExpr::Term { field: Field::Any, text: "rep".to_string(), phrase: false, prefix: true },
+            ])
+        );
+        // Lower-case operators are ordinary words.
+        assert_eq!(
+            QueryExpr::parse("salt and pepper").unwrap(),
+            QueryExpr::And(vec![term(Field::Any, "salt"), term(Field::Any, "and"), term(Field::Any, "pepper")])
+        );
+    }
+
+    #[test]
+    fn test_errors_point_at_the_offending_position() {
+        let error = |input: &str| QueryExpr::parse(input).unwrap_err();
+
+        assert_eq!(error("report AND"), ParseError { position: 11, message: "Expected a term after 'AND'".to_string() });
+        assert_eq!(error("a \"open").position, 3);
+        assert_eq!(error("a \"open").message, "Unterminated quote");
+        assert_eq!(error("(a OR b").position, 1);
+        assert_eq!(error("a b)").position, 4);
+        assert_eq!(error("owner:ana").message, "Unknown field 'owner'; expected name, description, tags, content or metadata.<key>");
+        assert_eq!(error("name: report").position, 6);
+        assert_eq!(error("OR a").message, "Expected a term");
+        assert_eq!(error("a NOT NOT").message, "Expected a term after 'NOT'");
+        assert_eq!(error("   ").message, "Query is empty");
+        assert!(error(&"(".repeat(40)).message.contains("nested"));
+        assert_eq!(
+            ValidationError::from(error("report AND")).to_string(),
+            "Expected a term after 'AND' at position 11"
+        );
+    }
+
+    #[test]
+    fn test_plan_keeps_indexed_terms_in_fts() {
+        let plan = QueryExpr::parse("name:report AND tags:finance NOT draft").unwrap().plan();
+        assert_eq!(plan.fts.as_deref(), Some(r#"name : "report" NOT "draft""#));
+        assert_eq!(plan.conditions.len(), 1);
+        assert!(plan.conditions[0].contains("json_each(i.tags)"));
+        assert_eq!(plan.params, vec!["finance"]);
+
+        let plan = QueryExpr::parse(r#"(budget OR "cash flow") rep*"#).unwrap().plan();
+        assert_eq!(plan.fts.as_deref(), Some(r#"("budget" OR "cash flow") AND "rep" *"#));
+        assert!(plan.conditions.is_empty());
+
+        // Nothing positive for the index: exclusions become conditions.
+        let plan = QueryExpr::parse("tags:finance NOT draft").unwrap().plan();
+        assert_eq!(plan.fts, None);
+        assert_eq!(plan.conditions.len(), 2);
+        assert_eq!(plan.params, vec!["finance", "\"draft\""]);
+
+        // Mixed OR falls back to conditions throughout.
+        let plan = QueryExpr::parse("name:report OR metadata.owner:ana").unwrap().plan();
+        assert_eq!(plan.fts, None);
+        assert_eq!(plan.params, vec!["name : \"report\"", "owner", "ana"]);
+    }
+
+    #[test]
+    fn test_content_terms() {
+        let plan = QueryExpr::parse(r#"name:contract content:"clause 7.2""#).unwrap().plan();
+        assert_eq!(plan.fts.as_deref(), Some(r#"name : "contract""#));
+        assert_eq!(plan.conditions.len(), 1);
+        assert!(plan.conditions[0].contains("files_fts"));
+        assert_eq!(plan.params, vec![r#""clause 7.2""#]);
+
+        let expr = QueryExpr::parse(r#"content:"clause 7.2" NOT draft"#).unwrap();
+        assert!(expr.has_content_terms());
+        assert_eq!(expr.content_fts().as_deref(), Some(r#"("clause 7.2" NOT "draft")"#));
+        assert!(!QueryExpr::parse("clause").unwrap().has_content_terms());
+        assert_eq!(QueryExpr::parse("name:contract content:clause").unwrap().content_fts(), None);
+        assert_eq!(QueryExpr::parse("NOT clause").unwrap().content_fts(), None);
+
+        assert!(!expr.matches(&item("contract", Some("clause 7.2"), &[], None)));
+    }
+
+    #[test]
+    fn test_matches_items() {
+        let report = item(
+            "Quarterly Report",
+            Some("Finance summary, draft"),
+            &["Finance"],
+            Some(serde_json::json!({"owner": "ana", "pages": 12, "final": false})),
+        );
+        let matches = |input: &str| QueryExpr::parse(input).unwrap().matches(&report);
+
+        assert!(matches("name:report AND tags:finance"));
+        assert!(!matches("name:report AND tags:finance NOT draft"));
+        assert!(matches(r#""quarterly report""#));
+        assert!(!matches(r#""report quarterly""#));
+        assert!(matches("quart*"));
+        assert!(matches("tags:fin* metadata.owner:ANA metadata.pages:12 metadata.final:false"));
+        assert!(!matches("tags:fin"));
+        assert!(matches("nothing OR summary"));
+        assert!(!matches("description:quarterly"));
+    }
+
+    #[test]
+    fn test_matched_fields_skip_excluded_terms() {
+        let report = item("Finance Report", Some("Numbers"), &["finance"], None);
+        let expr = QueryExpr::parse("finance NOT numbers").unwrap();
+        assert_eq!(expr.matched_fields(&report), vec!["name", "tags"]);
+    }
+}
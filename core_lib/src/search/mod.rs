@@ -3,9 +3,20 @@ pub mod filters;
 pub mod query;
 pub mod advanced_filters;
 pub mod cache;
+pub mod similarity;
+pub mod expression;
+pub mod suggest;
+pub mod export;
+pub mod unified;
 
 pub use engine::SearchEngine;
 pub use filters::{SearchFilters, DateRange};
-pub use query::{SearchQuery, SearchResult, SearchResultItem, SortField, SortOrder, SortCriterion};
+pub use query::{FieldWeights, SearchEntity, SearchQuery, SearchResult, SearchResultItem, SortField, SortOrder, SortCriterion};
 pub use advanced_filters::{AdvancedFilterBuilder, SearchPatterns};
-pub use cache::{SearchCache, SearchCacheStats};
\ No newline at end of file
+pub use cache::{SearchCache, SearchCacheStats};
+pub use similarity::{DuplicateCandidate, DuplicateDetector, SimilarItem, SimilarityQuery};
+pub use expression::{ParseError, QueryExpr};
+pub use suggest::{SuggestField, SuggestQuery, Suggester, Suggestion};
+pub use export::{ExportFormat, SearchExporter};
+
+pub use unified::{SearchHit, UnifiedSearch, UnifiedSearchResult};
\ No newline at end of file
@@ -1,5 +1,6 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
 use crate::store::Item;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,6 +15,19 @@ pub struct SearchQuery {
     pub fuzzy: bool,
     pub created_by: Option<i64>,
     pub min_relevance: Option<f64>,
+    /// Keyset cursor: only items with a greater id, in id order whatever
+    /// `sort_criteria` says.
+    #[serde(default)]
+    pub after_id: Option<u64>,
+    /// Only items with this id or lower.
+    #[serde(default)]
+    pub max_id: Option<u64>,
+    /// Column weights for this query instead of the engine's.
+    #[serde(default)]
+    pub weights: Option<FieldWeights>,
+    /// What to search for; items alone when empty.
+    #[serde(default)]
+    pub entities: BTreeSet<SearchEntity>,
 }
 
 impl Default for SearchQuery {
@@ -32,6 +46,60 @@ impl Default for SearchQuery {
             fuzzy: false,
             created_by: None,
             min_relevance: None,
+            after_id: None,
+            max_id: None,
+            weights: None,
+            entities: BTreeSet::new(),
+        }
+    }
+}
+
+/// The kinds of records a search can return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchEntity {
+    Items,
+    Files,
+    Users,
+}
+
+impl SearchEntity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SearchEntity::Items => "items",
+            SearchEntity::Files => "files",
+            SearchEntity::Users => "users",
+        }
+    }
+}
+
+impl std::str::FromStr for SearchEntity {
+    type Err = String;
+
+    fn from_str(entity: &str) -> Result<Self, Self::Err> {
+        match entity.trim().to_lowercase().as_str() {
+            "items" => Ok(SearchEntity::Items),
+            "files" => Ok(SearchEntity::Files),
+            "users" => Ok(SearchEntity::Users),
+            other => Err(format!("Unknown search entity '{}'; expected items, files or users", other)),
+        }
+    }
+}
+
+/// How much a match in each indexed column counts towards relevance.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FieldWeights {
+    pub name: f64,
+    pub tags: f64,
+    pub description: f64,
+}
+
+impl Default for FieldWeights {
+    fn default() -> Self {
+        Self {
+            name: 10.0,
+            tags: 5.0,
+            description: 1.0,
         }
     }
 }
@@ -158,6 +226,34 @@ impl SearchQuery {
         self.min_relevance = Some(min_score);
         self
     }
+
+    /// Pages by id instead of position: items after `after_id` and up to
+    /// `max_id`, so rows written between pages are neither repeated nor
+    /// skipped.
+    pub fn with_id_cursor(mut self, after_id: u64, max_id: Option<u64>) -> Self {
+        self.after_id = Some(after_id);
+        self.max_id = max_id;
+        self
+    }
+
+    pub fn with_weights(mut self, weights: FieldWeights) -> Self {
+        self.weights = Some(weights);
+        self
+    }
+
+    pub fn with_entities(mut self, entities: impl IntoIterator<Item = SearchEntity>) -> Self {
+        self.entities = entities.into_iter().collect();
+        self
+    }
+
+    /// The entities to search, items when none were asked for.
+    pub fn searched_entities(&self) -> BTreeSet<SearchEntity> {
+        if self.entities.is_empty() {
+            BTreeSet::from([SearchEntity::Items])
+        } else {
+            self.entities.clone()
+        }
+    }
 }
 
 impl SearchResultItem {
@@ -0,0 +1,273 @@
+//! Near-duplicate detection
+//!
+//! Candidates share at least one name term with the item being checked and
+//! come from the full-text index. Each is scored from the edit distance
+//! between the names, the share of name terms in common and the overlap of
+//! the tags.
+
+use crate::config::DuplicateConfig;
+use crate::error::Result;
+use crate::search::SearchEngine;
+use crate::services::ItemService;
+use crate::store::Item;
+use crate::validation::{unicode, ValidationError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+const NAME_WEIGHT: f64 = 0.5;
+const TERMS_WEIGHT: f64 = 0.25;
+const TAGS_WEIGHT: f64 = 0.25;
+
+/// Most name terms sent to the full-text index.
+const MAX_TERMS: usize = 16;
+
+/// Body of `POST /api/items/check-duplicate`: the item about to be created.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DuplicateCandidate {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl From<&Item> for DuplicateCandidate {
+    fn from(item: &Item) -> Self {
+        Self {
+            name: item.name.clone(),
+            description: item.description.clone(),
+            tags: item.tags.clone(),
+        }
+    }
+}
+
+/// Overrides of the configured threshold and result cap for one request.
+/// The cap can only be lowered.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SimilarityQuery {
+    pub min_similarity: Option<f64>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimilarItem {
+    pub item: Item,
+    /// Weighted score between 0 and 1.
+    pub similarity: f64,
+    pub name_similarity: f64,
+    pub tag_overlap: f64,
+    pub matched_fields: Vec<String>,
+}
+
+/// Finds items similar to a candidate in whichever store is active.
+#[derive(Clone)]
+pub struct DuplicateDetector {
+    search_engine: Option<SearchEngine>,
+    items: ItemService,
+    config: DuplicateConfig,
+}
+
+impl DuplicateDetector {
+    pub fn new(search_engine: Option<SearchEngine>, items: ItemService, config: DuplicateConfig) -> Self {
+        Self { search_engine, items, config }
+    }
+
+    /// Items scoring at least the threshold against `candidate`, best
+    /// first, leaving out `exclude_id`. Their secrets are redacted.
+    pub async fn find(
+        &self,
+        candidate: &DuplicateCandidate,
+        exclude_id: Option<u64>,
+        query: &SimilarityQuery,
+    ) -> Result<Vec<SimilarItem>> {
+        let min_similarity = query.min_similarity.unwrap_or(self.config.min_similarity);
+        if !(0.0..=1.0).contains(&min_similarity) {
+            return Err(ValidationError::field("min_similarity", "range", "min_similarity must be between 0 and 1").into());
+        }
+        if candidate.name.trim().is_empty() {
+            return Err(ValidationError::field("name", "required", "Item name cannot be empty").into());
+        }
+        let limit = query.limit.unwrap_or(self.config.max_results).min(self.config.max_results);
+
+        let candidate_terms = terms(&candidate.name);
+        let items = match &self.search_engine {
+            Some(engine) => {
+                let terms: Vec<String> = candidate_terms.iter().take(MAX_TERMS).cloned().collect();
+                engine.name_matches(&terms, exclude_id, self.config.candidate_limit).await?
+            }
+            // The in-memory store has no index; it is small enough to score
+            // every item.
+            None => self.items.get_items(None, None).await?,
+        };
+
+        let mut similar: Vec<SimilarItem> = items
+            .into_iter()
+            .filter(|item| Some(item.id) != exclude_id)
+            .map(|item| score(candidate, &candidate_terms, item))
+            .filter(|similar| similar.similarity >= min_similarity)
+            .collect();
+        similar.sort_by(|a, b| b.similarity.total_cmp(&a.similarity).then(a.item.id.cmp(&b.item.id)));
+        similar.truncate(limit);
+        similar.iter_mut().for_each(|similar| crate::item_secrets::redact(&mut similar.item));
+        Ok(similar)
+    }
+}
+
+fn score(candidate: &DuplicateCandidate, candidate_terms: &[String], item: Item) -> SimilarItem {
+    let name_similarity = normalized_levenshtein(&normalize(&candidate.name), &normalize(&item.name));
+
+    let item_terms: HashSet<String> = terms(&item.name).into_iter().collect();
+    let shared_terms = candidate_terms.iter().filter(|term| item_terms.contains(*term)).count();
+    let term_overlap = if candidate_terms.is_empty() {
+        0.0
+    } else {
+        shared_terms as f64 / candidate_terms.len() as f64
+    };
+
+    let candidate_tags = tag_set(&candidate.tags);
+    let item_tags = tag_set(&item.tags);
+    let tag_overlap = jaccard(&candidate_tags, &item_tags);
+
+    // Tags only count when either side has some; otherwise two untagged
+    // items with the same name could never reach a high score.
+    let similarity = if candidate_tags.is_empty() && item_tags.is_empty() {
+        (NAME_WEIGHT * name_similarity + TERMS_WEIGHT * term_overlap) / (NAME_WEIGHT + TERMS_WEIGHT)
+    } else {
+        NAME_WEIGHT * name_similarity + TERMS_WEIGHT * term_overlap + TAGS_WEIGHT * tag_overlap
+    };
+
+    let mut matched_fields = Vec::new();
+    if shared_terms > 0 {
+        matched_fields.push("name".to_string());
+    }
+    if let (Some(candidate_description), Some(item_description)) = (&candidate.description, &item.description) {
+        let item_description_terms: HashSet<String> = terms(item_description).into_iter().collect();
+        if terms(candidate_description).iter().any(|term| item_description_terms.contains(term)) {
+            matched_fields.push("description".to_string());
+        }
+    }
+    if tag_overlap > 0.0 {
+        matched_fields.push("tags".to_string());
+    }
+
+    SimilarItem {
+        item,
+        similarity: round(similarity),
+        name_similarity: round(name_similarity),
+        tag_overlap: round(tag_overlap),
+        matched_fields,
+    }
+}
+
+/// Lowercase with runs of whitespace collapsed, so that spacing and case
+/// alone never make two names differ.
+fn normalize(text: &str) -> String {
+    unicode::normalize_line(text)
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Distinct lowercase words of at least two characters, in order.
+fn terms(text: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    normalize(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| term.chars().count() >= 2)
+        .filter(|term| seen.insert(term.to_string()))
+        .map(str::to_string)
+        .collect()
+}
+
+fn tag_set(tags: &[String]) -> HashSet<String> {
+    tags.iter().map(|tag| normalize(tag)).filter(|tag| !tag.is_empty()).collect()
+}
+
+fn jaccard(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+/// One minus the edit distance between `a` and `b` over the length of the
+/// longer, counted in characters.
+fn normalized_levenshtein(a: &str, b: &str) -> f64 {
+    let longest = a.chars().count().max(b.chars().count());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - levenshtein(a, b) as f64 / longest as f64
+}
+
+/// The number of single-character insertions, deletions and substitutions
+/// turning `a` into `b`.
+pub(crate) fn levenshtein(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, a_char) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+fn round(score: f64) -> f64 {
+    (score * 1000.0).round() / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::DataStore;
+
+    #[test]
+    fn test_normalized_levenshtein() {
+        assert_eq!(normalized_levenshtein("kitten", "kitten"), 1.0);
+        assert_eq!(normalized_levenshtein("", ""), 1.0);
+        assert!((normalized_levenshtein("kitten", "sitting") - (1.0 - 3.0 / 7.0)).abs() < 1e-9);
+        assert_eq!(normalized_levenshtein("abc", "xyz"), 0.0);
+        assert_eq!(normalized_levenshtein("café", "cafe"), 0.75);
+    }
+
+    #[test]
+    fn test_terms_are_distinct_lowercase_words() {
+        assert_eq!(terms("Red  red-Widget, a 2X"), vec!["red", "widget", "2x"]);
+    }
+
+    #[tokio::test]
+    async fn test_similar_items_are_ranked_and_thresholded() {
+        let store = DataStore::empty();
+        store.create_item("Wireless Mouse".to_string(), None, vec!["peripheral".to_string()], None).unwrap();
+        store.create_item("Wireless Mouse Pro".to_string(), None, vec!["peripheral".to_string()], None).unwrap();
+        store.create_item("Mechanical Keyboard".to_string(), None, vec!["peripheral".to_string()], None).unwrap();
+        let detector = DuplicateDetector::new(None, ItemService::with_memory_store(store), DuplicateConfig::default());
+
+        let candidate = DuplicateCandidate {
+            name: "wireless mouse".to_string(),
+            description: None,
+            tags: vec!["Peripheral".to_string()],
+        };
+        let similar = detector.find(&candidate, None, &SimilarityQuery::default()).await.unwrap();
+        assert_eq!(similar.iter().map(|s| s.item.id).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(similar[0].similarity, 1.0);
+        assert_eq!(similar[0].matched_fields, vec!["name", "tags"]);
+
+        let similar = detector.find(&candidate, Some(1), &SimilarityQuery { min_similarity: Some(0.0), limit: Some(1) }).await.unwrap();
+        assert_eq!(similar.len(), 1);
+        assert_eq!(similar[0].item.id, 2);
+
+        let invalid = SimilarityQuery { min_similarity: Some(1.5), limit: None };
+        assert!(detector.find(&candidate, None, &invalid).await.is_err());
+    }
+}
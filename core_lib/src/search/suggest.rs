@@ -0,0 +1,164 @@
+//! Typeahead suggestions
+//!
+//! Name suggestions are the names of items whose name contains the typed
+//! words, the last one as a prefix, taken from the full-text index. Tag
+//! suggestions are the tags in use that start with the typed text. Both
+//! come with the number of items carrying them.
+
+use crate::config::SuggestConfig;
+use crate::error::Result;
+use crate::search::expression::{Field, QueryExpr};
+use crate::search::SearchEngine;
+use crate::services::ItemService;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SuggestField {
+    #[default]
+    Name,
+    Tags,
+}
+
+/// Query string of `GET /api/items/suggest`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SuggestQuery {
+    #[serde(default)]
+    pub q: String,
+    #[serde(default)]
+    pub field: SuggestField,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Suggestion {
+    pub text: String,
+    /// Items with this name or tag.
+    pub count: u64,
+}
+
+/// Suggests names and tags from whichever store is active, through the
+/// search engine's suggestion cache when there is one.
+#[derive(Clone)]
+pub struct Suggester {
+    search_engine: Option<SearchEngine>,
+    items: ItemService,
+    config: SuggestConfig,
+}
+
+impl Suggester {
+    pub fn new(search_engine: Option<SearchEngine>, items: ItemService, config: SuggestConfig) -> Self {
+        Self { search_engine, items, config }
+    }
+
+    /// Best suggestions for `query.q`, at most `query.limit` of them and
+    /// never more than the configured maximum.
+    pub async fn suggest(&self, query: &SuggestQuery) -> Result<Vec<Suggestion>> {
+        let prefix = query.q.trim();
+        let limit = query.limit.unwrap_or(self.config.default_limit).min(self.config.max_limit);
+        if limit == 0 || prefix.chars().count() < self.config.min_length || !prefix.chars().any(char::is_alphanumeric) {
+            return Ok(Vec::new());
+        }
+
+        let cache = self.search_engine.as_ref().and_then(SearchEngine::cache);
+        if let Some(mut cached) = cache.and_then(|cache| cache.get_suggestions(query.field, prefix)) {
+            cached.truncate(limit);
+            return Ok(cached);
+        }
+
+        // Fetch as many as any request may ask for, so one cache entry
+        // serves every limit.
+        let mut suggestions = match query.field {
+            SuggestField::Name => self.names(prefix).await?,
+            SuggestField::Tags => self
+                .items
+                .tags_with_prefix(prefix, self.config.max_limit)
+                .await?
+                .into_iter()
+                .map(|tag| Suggestion { text: tag.tag, count: tag.count })
+                .collect(),
+        };
+        if let Some(cache) = cache {
+            cache.put_suggestions(query.field, prefix, suggestions.clone());
+        }
+        suggestions.truncate(limit);
+        Ok(suggestions)
+    }
+
+    async fn names(&self, prefix: &str) -> Result<Vec<Suggestion>> {
+        if let Some(engine) = &self.search_engine {
+            return engine.name_suggestions(prefix, self.config.max_limit).await;
+        }
+
+        // The in-memory store has no index; it is small enough to scan.
+        let expr = name_prefix(prefix);
+        let mut counts: HashMap<String, u64> = HashMap::new();
+        for item in self.items.get_items(None, None).await? {
+            if expr.matches(&item) {
+                *counts.entry(item.name).or_default() += 1;
+            }
+        }
+        let mut suggestions: Vec<Suggestion> =
+            counts.into_iter().map(|(text, count)| Suggestion { text, count }).collect();
+        suggestions.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.text.cmp(&b.text)));
+        suggestions.truncate(self.config.max_limit);
+        Ok(suggestions)
+    }
+}
+
+/// Names containing the words of `prefix` in order, the last as a prefix.
+pub(crate) fn name_prefix(prefix: &str) -> QueryExpr {
+    QueryExpr::Term {
+        field: Field::Name,
+        text: prefix.to_string(),
+        phrase: false,
+        prefix: true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::DataStore;
+
+    #[tokio::test]
+    async fn test_memory_suggestions() {
+        let store = DataStore::empty();
+        for (name, tags) in [
+            ("Quarterly Report", vec!["reports", "finance"]),
+            ("Quarterly Report", vec!["reports"]),
+            ("Report Card", vec!["Research"]),
+            ("Annual review", vec![]),
+        ] {
+            store
+                .create_item(name.to_string(), None, tags.into_iter().map(str::to_string).collect(), None)
+                .unwrap();
+        }
+        let suggester = Suggester::new(None, ItemService::with_memory_store(store), SuggestConfig::default());
+        let suggest = |q: &str, field: SuggestField, limit: Option<usize>| {
+            let query = SuggestQuery { q: q.to_string(), field, limit };
+            let suggester = suggester.clone();
+            async move { suggester.suggest(&query).await.unwrap() }
+        };
+
+        assert_eq!(
+            suggest("rep", SuggestField::Name, None).await,
+            vec![
+                Suggestion { text: "Quarterly Report".to_string(), count: 2 },
+                Suggestion { text: "Report Card".to_string(), count: 1 },
+            ]
+        );
+        assert_eq!(suggest("quarterly re", SuggestField::Name, Some(1)).await.len(), 1);
+        assert_eq!(
+            suggest("RE", SuggestField::Tags, None).await,
+            vec![
+                Suggestion { text: "reports".to_string(), count: 2 },
+                Suggestion { text: "Research".to_string(), count: 1 },
+            ]
+        );
+        // Below the minimum length, or nothing to match on.
+        assert!(suggest("r", SuggestField::Name, None).await.is_empty());
+        assert!(suggest("--", SuggestField::Name, None).await.is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+//! Search across items, files and users
+//!
+//! Items are matched with the query language through the search engine,
+//! files by their original name, or by their extracted text when the query
+//! has `content:` terms, and users by their username. Each kind is
+//! paged on its own and the pages are returned together, items first, with
+//! the number of matches of each kind.
+//!
+//! Anyone may search items. Files need a signed-in user and only their own
+//! files are returned unless they are an admin; users need an admin.
+
+use crate::auth::{AuthService, UserResponse};
+use crate::error::{AppError, Result};
+use crate::files::{FileListQuery, FileManager, FileMetadata};
+use crate::middleware::auth::AuthUser;
+use crate::search::{QueryExpr, SearchEngine, SearchEntity, SearchQuery, SearchResultItem};
+use crate::services::ItemService;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SearchHit {
+    Item(SearchResultItem),
+    File(FileMetadata),
+    User(UserResponse),
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnifiedSearchResult {
+    pub results: Vec<SearchHit>,
+    /// Matches of each searched entity, beyond this page too.
+    pub counts: BTreeMap<SearchEntity, u64>,
+    pub total_count: u64,
+    pub offset: u64,
+    pub limit: u64,
+}
+
+#[derive(Clone)]
+pub struct UnifiedSearch {
+    search_engine: Option<SearchEngine>,
+    items: ItemService,
+    files: Option<FileManager>,
+    auth: Option<AuthService>,
+}
+
+impl UnifiedSearch {
+    pub fn new(
+        search_engine: Option<SearchEngine>,
+        items: ItemService,
+        files: Option<FileManager>,
+        auth: Option<AuthService>,
+    ) -> Self {
+        Self { search_engine, items, files, auth }
+    }
+
+    /// Searches each of `query.searched_entities()` on behalf of `caller`,
+    /// using the query's offset and limit for every one of them.
+    pub async fn search(&self, query: &SearchQuery, caller: Option<&AuthUser>) -> Result<UnifiedSearchResult> {
+        let entities = query.searched_entities();
+        if entities.contains(&SearchEntity::Files) || entities.contains(&SearchEntity::Users) {
+            let caller = caller.ok_or(AppError::Unauthorized)?;
+            if entities.contains(&SearchEntity::Users) && !caller.is_admin() {
+                return Err(AppError::Authorization("Searching users requires admin access".to_string()));
+            }
+        }
+
+        let offset = query.offset.unwrap_or(0);
+        let limit = query.limit.unwrap_or(50);
+        let text = query.text.as_deref().map(str::trim).unwrap_or_default();
+        let mut results = Vec::new();
+        let mut counts = BTreeMap::new();
+
+        for entity in entities {
+            let count = match entity {
+                SearchEntity::Items => {
+                    let (items, count) = self.search_items(query, offset, limit).await?;
+                    results.extend(items.into_iter().map(SearchHit::Item));
+                    count
+                }
+                SearchEntity::Files => {
+                    let files = self
+                        .files
+                        .as_ref()
+                        .ok_or_else(|| AppError::Configuration("File storage is not configured".to_string()))?;
+                    let caller = caller.ok_or(AppError::Unauthorized)?;
+                    let content = QueryExpr::parse(text)
+                        .ok()
+                        .filter(QueryExpr::has_content_terms)
+                        .and_then(|expr| expr.content_fts());
+                    let file_query = FileListQuery {
+                        uploaded_by: (!caller.is_admin()).then_some(caller.user_id as u64),
+                        filename: Some(text.to_string()).filter(|text| !text.is_empty() && content.is_none()),
+                        content,
+                        limit: Some(limit),
+                        offset: Some(offset),
+                        ..FileListQuery::default()
+                    };
+                    let count = files.count_files(file_query.clone()).await?;
+                    results.extend(files.list_files(file_query).await?.into_iter().map(SearchHit::File));
+                    count
+                }
+                SearchEntity::Users => {
+                    let auth = self
+                        .auth
+                        .as_ref()
+                        .ok_or_else(|| AppError::Configuration("Authentication is not configured".to_string()))?;
+                    let (users, count) = auth.search_users(text, None, limit, offset).await?;
+                    results.extend(users.into_iter().map(SearchHit::User));
+                    count
+                }
+            };
+            counts.insert(entity, count);
+        }
+
+        Ok(UnifiedSearchResult {
+            results,
+            total_count: counts.values().sum(),
+            counts,
+            offset,
+            limit,
+        })
+    }
+
+    async fn search_items(&self, query: &SearchQuery, offset: u64, limit: u64) -> Result<(Vec<SearchResultItem>, u64)> {
+        let Some(engine) = &self.search_engine else {
+            let matches = super::export::search_in_memory(&self.items, query).await?;
+            let count = matches.len() as u64;
+            let page = matches.into_iter().skip(offset as usize).take(limit as usize).collect();
+            return Ok((page, count));
+        };
+
+        let result = engine.search(&query.clone().with_pagination(offset, limit)).await?;
+        Ok((result.items, result.total_count))
+    }
+}
@@ -0,0 +1,596 @@
+//! Development fixtures
+//!
+//! `server seed` and `POST /api/admin/seed` load a set of fixtures through
+//! the same services requests go through, so validation and content
+//! indexing apply to them. The built-in set has items with varied tags,
+//! metadata and dates, two users of every role, a few text files and some
+//! finished jobs; a JSON file of the same shape can be loaded instead.
+//! Items and jobs carry their own ids, so tests see the same records on
+//! every run. Neither entry point runs when `RUST_ENV` is `production`.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::SqlitePool;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::audit::{AuditEvent, AuditLog};
+use crate::auth::{AuthService, CreateUserRequest, UserRole};
+use crate::error::{AppError, Result};
+use crate::files::{ContentIndexer, FileListQuery, FileManager, FileUpload};
+use crate::jobs::{Job, JobPriority, JobRepository, JobRepositoryTrait, JobStatus, JobType};
+use crate::services::ItemService;
+
+/// Password of every built-in user.
+pub const FIXTURE_PASSWORD: &str = "Fixture-Passw0rd";
+
+/// Tables emptied by a wipe, children before parents.
+const WIPED_TABLES: &[&str] = &[
+    "item_comments",
+    "files_fts",
+    "files",
+    "item_changes",
+    "items",
+    "jobs",
+    "refresh_tokens",
+    "user_sessions",
+    "users",
+];
+
+/// Whether fixtures may be loaded, which is anywhere but production.
+pub fn seeding_allowed() -> bool {
+    std::env::var("RUST_ENV").map_or(true, |env| env != "production")
+}
+
+/// Whether `url` names a database it is safe to wipe: an in-memory one, or
+/// a file whose name mentions dev, test, demo or local.
+pub fn is_dev_database(url: &str) -> bool {
+    let url = url.to_ascii_lowercase();
+    if url.contains(":memory:") || url.contains("mode=memory") {
+        return true;
+    }
+    let path = url.split('?').next().unwrap_or_default();
+    let name = path.rsplit(['/', '\\', ':']).next().unwrap_or_default();
+    ["dev", "test", "demo", "local"].iter().any(|pattern| name.contains(pattern))
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fixtures {
+    #[serde(default)]
+    pub users: Vec<UserFixture>,
+    #[serde(default)]
+    pub items: Vec<ItemFixture>,
+    #[serde(default)]
+    pub files: Vec<FileFixture>,
+    #[serde(default)]
+    pub jobs: Vec<JobFixture>,
+}
+
+/// A user, matched to existing ones by username.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserFixture {
+    pub username: String,
+    pub email: String,
+    pub password: String,
+    pub role: UserRole,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ItemFixture {
+    pub id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+    /// Defaults to the time of seeding.
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// A text file, matched to existing ones by name and item.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileFixture {
+    pub filename: String,
+    pub content_type: String,
+    pub content: String,
+    #[serde(default)]
+    pub item_id: Option<u64>,
+    /// Username of the uploader, who must exist or be among the fixtures.
+    pub uploaded_by: String,
+}
+
+/// A finished job. Pending jobs are left out, as workers would run them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobFixture {
+    pub id: Uuid,
+    pub job_type: JobType,
+    pub status: JobStatus,
+    #[serde(default)]
+    pub payload: serde_json::Value,
+    #[serde(default)]
+    pub result: Option<serde_json::Value>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+    /// Defaults to the time of seeding.
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl Fixtures {
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(|e| AppError::BadRequest(format!("Invalid fixtures: {}", e)))
+    }
+
+    /// The built-in data set, dated relative to now.
+    pub fn builtin() -> Self {
+        let now = Utc::now();
+        let user = |username: &str, role: UserRole| UserFixture {
+            username: username.to_string(),
+            email: format!("{}@example.com", username.replace('_', ".")),
+            password: FIXTURE_PASSWORD.to_string(),
+            role,
+        };
+        let item = |id: u64, name: &str, description: &str, tags: &[&str], metadata: serde_json::Value, days_ago: i64| {
+            ItemFixture {
+                id,
+                name: name.to_string(),
+                description: Some(description.to_string()),
+                tags: tags.iter().map(|tag| tag.to_string()).collect(),
+                metadata: Some(metadata),
+                created_at: Some(now - Duration::days(days_ago)),
+            }
+        };
+        let file = |filename: &str, content_type: &str, content: &str, item_id: u64| FileFixture {
+            filename: filename.to_string(),
+            content_type: content_type.to_string(),
+            content: content.to_string(),
+            item_id: Some(item_id),
+            uploaded_by: "demo_admin".to_string(),
+        };
+        let job = |n: u128, job_type: JobType, status: JobStatus, payload: serde_json::Value,
+                   result: Option<serde_json::Value>, error: Option<&str>, days_ago: i64| JobFixture {
+            id: Uuid::from_u128(0x5eed_0000_0000_0000_0000_0000_0000_0000 | n),
+            job_type,
+            status,
+            payload,
+            result,
+            error_message: error.map(str::to_string),
+            created_at: Some(now - Duration::days(days_ago)),
+        };
+
+        Self {
+            users: vec![
+                user("demo_admin", UserRole::Admin),
+                user("demo_ops", UserRole::Admin),
+                user("demo_alice", UserRole::User),
+                user("demo_bob", UserRole::User),
+                user("demo_viewer", UserRole::ReadOnly),
+                user("demo_auditor", UserRole::ReadOnly),
+            ],
+            items: vec![
+                item(1001, "Standing desk", "Electric sit-stand desk, 160x80 cm oak top.",
+                    &["furniture", "office"], json!({"price": 649.0, "stock": 12, "vendor": "Fjord"}), 120),
+                item(1002, "Ergonomic chair", "Mesh back with adjustable lumbar support.",
+                    &["furniture", "office", "bestseller"], json!({"price": 389.0, "stock": 30, "vendor": "Fjord"}), 95),
+                item(1003, "USB-C dock", "Dual 4K display dock with 100 W power delivery.",
+                    &["electronics", "accessories"], json!({"price": 179.99, "stock": 54, "vendor": "Voltix"}), 60),
+                item(1004, "Noise-cancelling headphones", "Over-ear, 30 hour battery.",
+                    &["electronics", "audio", "bestseller"], json!({"price": 299.0, "stock": 0, "vendor": "Sonara"}), 45),
+                item(1005, "Mechanical keyboard", "Tenkeyless, hot-swappable brown switches.",
+                    &["electronics", "accessories"], json!({"price": 129.0, "stock": 41, "vendor": "Voltix"}), 30),
+                item(1006, "Monitor arm", "Gas spring arm for screens up to 32 inches.",
+                    &["office", "accessories"], json!({"price": 89.5, "stock": 17, "vendor": "Fjord"}), 21),
+                item(1007, "Desk lamp", "Dimmable LED lamp with warm and cool modes.",
+                    &["lighting", "office"], json!({"price": 54.0, "stock": 63}), 14),
+                item(1008, "Cable tray", "Under-desk steel tray.",
+                    &["office"], json!({"price": 24.0, "stock": 120, "discontinued": true}), 10),
+                item(1009, "Webcam 4K", "Autofocus webcam with privacy shutter.",
+                    &["electronics", "video"], json!({"price": 149.0, "stock": 8, "vendor": "Voltix"}), 5),
+                item(1010, "Whiteboard", "Magnetic whiteboard, 120x90 cm.",
+                    &["office", "supplies"], json!({"price": 79.0, "stock": 5}), 2),
+                item(1011, "Notebook pack", "Five dotted A5 notebooks.",
+                    &["supplies"], json!({"price": 19.0, "stock": 240}), 1),
+                item(1012, "Footrest", "Adjustable tilting footrest.",
+                    &["furniture"], json!({}), 0),
+            ],
+            files: vec![
+                file("desk-assembly.txt", "text/plain",
+                    "Assembly guide for the standing desk. Attach the legs before the motor housing. \
+                     Torque the frame bolts to 8 Nm.", 1001),
+                file("chair-warranty.txt", "text/plain",
+                    "Warranty terms: the gas lift is covered for ten years, the mesh for five.", 1002),
+                file("dock-compatibility.csv", "text/csv",
+                    "laptop,displays,charging\nThinkPad X1,2,yes\nMacBook Pro,2,yes\nXPS 13,1,yes\n", 1003),
+                file("keyboard-layout.json", "application/json",
+                    r#"{"layout": "ANSI", "switches": "brown", "keys": 87}"#, 1005),
+            ],
+            jobs: vec![
+                job(1, JobType::BulkExport, JobStatus::Completed, json!({"format": "csv"}),
+                    Some(json!({"exported_items": 12})), None, 7),
+                job(2, JobType::ReportGeneration, JobStatus::Completed, json!({"report": "inventory"}),
+                    Some(json!({"rows": 12, "out_of_stock": 1})), None, 3),
+                job(3, JobType::BulkImport, JobStatus::Failed, json!({"items": []}),
+                    None, Some("Import file has no items"), 2),
+                job(4, JobType::EmailNotification, JobStatus::Failed, json!({"to": "demo.bob@example.com"}),
+                    None, Some("SMTP connection refused"), 1),
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct SeedOptions {
+    /// Skip fixtures that already exist instead of refusing to run.
+    #[serde(default)]
+    pub idempotent: bool,
+    /// Empty the database first. Only allowed on a [dev database](is_dev_database).
+    #[serde(default)]
+    pub wipe: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeedCounts {
+    pub users: u64,
+    pub items: u64,
+    pub files: u64,
+    pub jobs: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SeedReport {
+    pub wiped: bool,
+    pub created: SeedCounts,
+    /// Fixtures left alone because they already existed.
+    pub skipped: SeedCounts,
+}
+
+/// Loads fixtures into the database.
+#[derive(Clone)]
+pub struct Seeder {
+    items: ItemService,
+    auth: AuthService,
+    files: Option<FileManager>,
+    jobs: JobRepository,
+    pool: SqlitePool,
+    audit_log: AuditLog,
+    database_url: Option<String>,
+}
+
+impl Seeder {
+    pub fn new(
+        items: ItemService,
+        auth: AuthService,
+        files: Option<FileManager>,
+        pool: SqlitePool,
+        audit_log: AuditLog,
+    ) -> Self {
+        Self {
+            items,
+            auth,
+            files,
+            jobs: JobRepository::new(pool.clone()),
+            pool,
+            audit_log,
+            database_url: None,
+        }
+    }
+
+    /// The URL wipes are checked against. Without one, wipes are refused.
+    pub fn with_database_url(mut self, url: &str) -> Self {
+        self.database_url = Some(url.to_string());
+        self
+    }
+
+    /// Loads `fixtures` on behalf of `actor`. Unless `options.idempotent` is
+    /// set, nothing is loaded when any fixture already exists.
+    pub async fn seed(&self, fixtures: &Fixtures, options: SeedOptions, actor: &str) -> Result<SeedReport> {
+        if !seeding_allowed() {
+            return Err(AppError::Authorization("Fixtures are not loaded in production".to_string()));
+        }
+        if let Some(job) = fixtures.jobs.iter().find(|job| !Self::is_finished(&job.status)) {
+            return Err(AppError::BadRequest(format!(
+                "Fixture job {} must be completed, failed or cancelled",
+                job.id
+            )));
+        }
+        if !fixtures.files.is_empty() && self.files.is_none() {
+            return Err(AppError::Configuration("Fixture files need file storage".to_string()));
+        }
+
+        let mut report = SeedReport::default();
+        if options.wipe {
+            self.wipe().await?;
+            report.wiped = true;
+        }
+
+        // The fixtures are loaded in one transaction, so a failing fixture
+        // leaves none of the others behind. Files already written to disk
+        // stay there until file reconciliation removes them.
+        self.items.transaction(self.load(fixtures, &options, &mut report)).await?;
+
+        info!(
+            "Fixtures loaded by {}: {} users, {} items, {} files, {} jobs",
+            actor, report.created.users, report.created.items, report.created.files, report.created.jobs
+        );
+        self.audit_log.record(
+            AuditEvent::new("seed.loaded")
+                .with_actor(actor)
+                .with_details(serde_json::to_value(&report)?),
+        );
+
+        Ok(report)
+    }
+
+    /// Creates the fixtures that do not exist yet, counting them in `report`.
+    async fn load(&self, fixtures: &Fixtures, options: &SeedOptions, report: &mut SeedReport) -> Result<()> {
+        let existing = self.existing(fixtures).await?;
+        if !options.idempotent {
+            let names = existing.names();
+            if !names.is_empty() {
+                return Err(AppError::Conflict(format!(
+                    "Already seeded: {}; use idempotent mode to skip existing records",
+                    names.join(", ")
+                )));
+            }
+        }
+
+        for (fixture, exists) in fixtures.users.iter().zip(&existing.users) {
+            if *exists {
+                report.skipped.users += 1;
+                continue;
+            }
+            self.auth
+                .register_verified_user(CreateUserRequest {
+                    username: fixture.username.clone(),
+                    email: fixture.email.clone(),
+                    password: fixture.password.clone(),
+                    role: Some(fixture.role.clone()),
+                })
+                .await?;
+            report.created.users += 1;
+        }
+
+        let now = Utc::now();
+        for (fixture, exists) in fixtures.items.iter().zip(&existing.items) {
+            if *exists {
+                report.skipped.items += 1;
+                continue;
+            }
+            self.items
+                .create_item_with_id(
+                    fixture.id,
+                    fixture.created_at.unwrap_or(now),
+                    fixture.name.clone(),
+                    fixture.description.clone(),
+                    fixture.tags.clone(),
+                    fixture.metadata.clone(),
+                )
+                .await?;
+            report.created.items += 1;
+        }
+
+        if let Some(files) = &self.files {
+            let indexer = ContentIndexer::new(files.clone(), self.audit_log.clone());
+            for (fixture, exists) in fixtures.files.iter().zip(&existing.files) {
+                if *exists {
+                    report.skipped.files += 1;
+                    continue;
+                }
+                let uploader = self
+                    .auth
+                    .get_user_by_username(&fixture.uploaded_by)
+                    .await?
+                    .ok_or_else(|| AppError::BadRequest(format!(
+                        "Fixture file {} is uploaded by unknown user {}",
+                        fixture.filename, fixture.uploaded_by
+                    )))?;
+                let metadata = files
+                    .store_file(FileUpload {
+                        original_filename: fixture.filename.clone(),
+                        content_type: fixture.content_type.clone(),
+                        data: fixture.content.clone().into_bytes(),
+                        uploaded_by: uploader.id as u64,
+                        item_id: fixture.item_id,
+                    })
+                    .await?;
+                indexer.index_file(metadata.id).await?;
+                report.created.files += 1;
+            }
+        }
+
+        for (fixture, exists) in fixtures.jobs.iter().zip(&existing.jobs) {
+            if *exists {
+                report.skipped.jobs += 1;
+                continue;
+            }
+            self.jobs.create(&Self::finished_job(fixture, now)).await?;
+            report.created.jobs += 1;
+        }
+
+        Ok(())
+    }
+
+    fn is_finished(status: &JobStatus) -> bool {
+        matches!(status, JobStatus::Completed | JobStatus::Failed | JobStatus::Cancelled)
+    }
+
+    fn finished_job(fixture: &JobFixture, now: DateTime<Utc>) -> Job {
+        let created_at = fixture.created_at.unwrap_or(now);
+        Job {
+            id: fixture.id,
+            job_type: fixture.job_type.clone(),
+            status: fixture.status.clone(),
+            payload: fixture.payload.clone(),
+            result_size: fixture
+                .result
+                .as_ref()
+                .and_then(|result| serde_json::to_vec(result).ok())
+                .map(|bytes| bytes.len() as u64),
+            result: fixture.result.clone(),
+            result_file: None,
+            error_message: fixture.error_message.clone(),
+            created_at,
+            queued_at: created_at,
+            started_at: Some(created_at),
+            completed_at: Some(created_at + Duration::seconds(2)),
+            retry_count: 0,
+            max_retries: 0,
+            priority: JobPriority::Normal,
+        }
+    }
+
+    /// Which fixtures are already in the database, in fixture order.
+    async fn existing(&self, fixtures: &Fixtures) -> Result<Existing> {
+        let mut existing = Existing::default();
+
+        for fixture in &fixtures.users {
+            let found = self.auth.get_user_by_username(&fixture.username).await?.is_some();
+            existing.users.push(found);
+            if found {
+                existing.found.push(format!("user {}", fixture.username));
+            }
+        }
+
+        for fixture in &fixtures.items {
+            let found = match self.items.get_item(fixture.id).await {
+                Ok(_) => true,
+                Err(AppError::NotFound(_)) => false,
+                Err(e) => return Err(e),
+            };
+            existing.items.push(found);
+            if found {
+                existing.found.push(format!("item {}", fixture.id));
+            }
+        }
+
+        for fixture in &fixtures.files {
+            let found = match &self.files {
+                Some(files) => files
+                    .list_files(FileListQuery {
+                        item_id: fixture.item_id,
+                        filename: Some(fixture.filename.clone()),
+                        limit: None,
+                        offset: None,
+                        ..Default::default()
+                    })
+                    .await?
+                    .iter()
+                    .any(|file| file.original_filename == fixture.filename && file.item_id == fixture.item_id),
+                None => false,
+            };
+            existing.files.push(found);
+            if found {
+                existing.found.push(format!("file {}", fixture.filename));
+            }
+        }
+
+        for fixture in &fixtures.jobs {
+            let found = self.jobs.get_by_id(fixture.id).await?.is_some();
+            existing.jobs.push(found);
+            if found {
+                existing.found.push(format!("job {}", fixture.id));
+            }
+        }
+
+        Ok(existing)
+    }
+
+    /// Empties the tables fixtures go into, and the stored files.
+    async fn wipe(&self) -> Result<()> {
+        let url = self.database_url.as_deref().unwrap_or_default();
+        if !is_dev_database(url) {
+            return Err(AppError::BadRequest(format!(
+                "Refusing to wipe {:?}; only in-memory databases or ones named for dev, test, demo or local are wiped",
+                url
+            )));
+        }
+
+        if let Some(files) = &self.files {
+            let repository = files.repository();
+            let mut after = None;
+            loop {
+                let rows = repository.content_types_after(after, 500).await?;
+                let Some((last, _)) = rows.last() else {
+                    break;
+                };
+                after = Some(*last);
+                for (file_id, _) in rows {
+                    match files.delete_file(file_id).await {
+                        Ok(()) | Err(AppError::NotFound(_)) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+
+        let mut tx = self.pool.begin().await?;
+        for table in WIPED_TABLES {
+            sqlx::query(&format!("DELETE FROM {}", table)).execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+
+        info!("Wiped {} before seeding", url);
+        self.audit_log.record(AuditEvent::new("seed.wiped").with_details(json!({"database_url": url})));
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Existing {
+    users: Vec<bool>,
+    items: Vec<bool>,
+    files: Vec<bool>,
+    jobs: Vec<bool>,
+    found: Vec<String>,
+}
+
+impl Existing {
+    /// Up to ten of the fixtures found, for error messages.
+    fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.found.iter().take(10).cloned().collect();
+        if self.found.len() > 10 {
+            names.push(format!("{} more", self.found.len() - 10));
+        }
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_dev_database() {
+        assert!(is_dev_database("sqlite::memory:"));
+        assert!(is_dev_database("sqlite:file:seed?mode=memory&cache=shared"));
+        assert!(is_dev_database("sqlite:data/dev.db"));
+        assert!(is_dev_database("sqlite:///tmp/app_test.db?mode=rwc"));
+        assert!(!is_dev_database("sqlite:data/app.db"));
+        assert!(!is_dev_database("sqlite:/srv/dev/app.db"));
+    }
+
+    #[test]
+    fn test_fixtures_from_json() {
+        let fixtures = Fixtures::from_json(
+            r#"{"items": [{"id": 7, "name": "Lamp", "tags": ["lighting"]}],
+                "jobs": [{"id": "00000000-0000-0000-0000-000000000001", "job_type": "BulkExport", "status": "Completed"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(fixtures.items[0].id, 7);
+        assert!(fixtures.users.is_empty());
+        assert_eq!(fixtures.jobs[0].status, JobStatus::Completed);
+
+        assert!(Fixtures::from_json(r#"{"widgets": []}"#).is_err());
+
+        let builtin = Fixtures::builtin();
+        for role in [UserRole::Admin, UserRole::User, UserRole::ReadOnly] {
+            assert_eq!(builtin.users.iter().filter(|user| user.role == role).count(), 2);
+        }
+        assert!(builtin.jobs.iter().all(|job| Seeder::is_finished(&job.status)));
+    }
+}
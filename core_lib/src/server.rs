@@ -0,0 +1,554 @@
+//! Serves the router on one or more listeners over HTTP/1.1 and HTTP/2
+
+use crate::config::{ServerConfig, TlsConfig};
+use crate::error::{AppError, Result};
+use axum::{body::Body, extract::ConnectInfo, Router};
+use futures_util::FutureExt;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::Request;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto::Builder;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::{watch, Semaphore};
+use tokio_rustls::rustls;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+use tracing::{debug, info, warn};
+
+const UNIX_PREFIX: &str = "unix:";
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Peer address reported to handlers for connections on a unix socket,
+/// which are always local.
+const UNIX_PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+/// An address the server accepts connections on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(path) = value.strip_prefix(UNIX_PREFIX) {
+            if path.is_empty() {
+                return Err(format!("Listener '{}' has an empty socket path", value));
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+
+        value
+            .parse()
+            .map(Self::Tcp)
+            .map_err(|_| format!("Listener '{}' is neither ip:port nor unix:/path", value))
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, PathBuf),
+}
+
+impl Listener {
+    async fn bind(addr: &ListenAddr) -> Result<Self> {
+        match addr {
+            ListenAddr::Tcp(addr) => Ok(Self::Tcp(TcpListener::bind(addr).await?)),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                remove_stale_socket(path)?;
+                Ok(Self::Unix(tokio::net::UnixListener::bind(path)?, path.clone()))
+            }
+            #[cfg(not(unix))]
+            ListenAddr::Unix(_) => Err(AppError::Configuration(
+                "Unix socket listeners are only supported on unix".to_string(),
+            )),
+        }
+    }
+
+    fn local_addr(&self) -> Result<ListenAddr> {
+        match self {
+            Self::Tcp(listener) => Ok(ListenAddr::Tcp(listener.local_addr()?)),
+            #[cfg(unix)]
+            Self::Unix(_, path) => Ok(ListenAddr::Unix(path.clone())),
+        }
+    }
+}
+
+/// Removes a socket file left behind by a previous run, which would
+/// otherwise make binding fail. Anything other than a socket is left alone.
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => Ok(std::fs::remove_file(path)?),
+        _ => Ok(()),
+    }
+}
+
+/// Shared by every connection task.
+#[derive(Clone)]
+struct ConnectionContext {
+    app: Router,
+    builder: Arc<ConnectionBuilder>,
+    tls: Option<TlsAcceptor>,
+    signal: Arc<watch::Sender<()>>,
+    /// Dropped when the connection closes, so shutdown can wait for all of
+    /// them.
+    _open: watch::Receiver<()>,
+}
+
+/// Bound listeners plus the connection settings from [`ServerConfig`].
+/// Every listener serves the same router.
+pub struct Server {
+    listeners: Vec<Listener>,
+    builder: ConnectionBuilder,
+    tls: Option<TlsAcceptor>,
+    connection_limit: Arc<Semaphore>,
+    shutdown_timeout: Duration,
+}
+
+impl Server {
+    /// Binds `host:port` and every additional listener, and logs the
+    /// settings connections will be served with.
+    pub async fn bind(config: &ServerConfig) -> Result<Self> {
+        let host: IpAddr = config
+            .host
+            .parse()
+            .map_err(|_| AppError::Configuration(format!("Invalid server host: {}", config.host)))?;
+
+        let mut addrs = vec![ListenAddr::Tcp(SocketAddr::new(host, config.port))];
+        for listener in &config.additional_listeners {
+            addrs.push(listener.parse().map_err(AppError::Configuration)?);
+        }
+
+        let tls = config.tls.as_ref().map(|tls| tls_acceptor(tls, config.http2)).transpose()?;
+
+        let mut listeners = Vec::with_capacity(addrs.len());
+        for addr in &addrs {
+            let listener = Listener::bind(addr).await?;
+            let secure = tls.is_some() && matches!(addr, ListenAddr::Tcp(_));
+            info!("Listening on {}{}", listener.local_addr()?, if secure { " (TLS)" } else { "" });
+            listeners.push(listener);
+        }
+
+        info!(
+            "HTTP/2 {}, max {} concurrent streams, keep-alive timeout {}s, max header size {} bytes, max {} connections",
+            if config.http2 { "enabled" } else { "disabled" },
+            config.http2_max_concurrent_streams,
+            config.keep_alive_timeout_seconds,
+            config.max_header_size_bytes,
+            config.max_connections,
+        );
+
+        Ok(Self {
+            listeners,
+            builder: connection_builder(config),
+            tls,
+            connection_limit: Arc::new(Semaphore::new(config.max_connections.max(1))),
+            shutdown_timeout: Duration::from_secs(config.shutdown_timeout_seconds),
+        })
+    }
+
+    /// The bound addresses, with the actual port for listeners bound to
+    /// port 0.
+    pub fn local_addrs(&self) -> Result<Vec<ListenAddr>> {
+        self.listeners.iter().map(Listener::local_addr).collect()
+    }
+
+    /// Serves `app` until `signal` completes, then stops accepting and waits
+    /// up to `shutdown_timeout_seconds` for open connections to finish.
+    pub async fn serve<F>(self, app: Router, signal: F) -> Result<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (signal_tx, signal_rx) = watch::channel(());
+        let signal_tx = Arc::new(signal_tx);
+        tokio::spawn(async move {
+            signal.await;
+            drop(signal_rx);
+        });
+
+        let (open_tx, open_rx) = watch::channel(());
+        let context = ConnectionContext {
+            app,
+            builder: Arc::new(self.builder),
+            tls: self.tls,
+            signal: signal_tx.clone(),
+            _open: open_rx,
+        };
+
+        let mut socket_paths = Vec::new();
+        let mut accept_loops = tokio::task::JoinSet::new();
+        for listener in self.listeners {
+            #[cfg(unix)]
+            if let Listener::Unix(_, path) = &listener {
+                socket_paths.push(path.clone());
+            }
+            accept_loops.spawn(accept_loop(listener, context.clone(), self.connection_limit.clone()));
+        }
+        drop(context);
+
+        while accept_loops.join_next().await.is_some() {}
+
+        if tokio::time::timeout(self.shutdown_timeout, open_tx.closed()).await.is_err() {
+            warn!(
+                "{} connection(s) still open after {:?}, closing them",
+                open_tx.receiver_count(),
+                self.shutdown_timeout
+            );
+        }
+
+        for path in socket_paths {
+            let _ = std::fs::remove_file(path);
+        }
+
+        Ok(())
+    }
+}
+
+async fn accept_loop(listener: Listener, context: ConnectionContext, connection_limit: Arc<Semaphore>) {
+    loop {
+        let permit = tokio::select! {
+            permit = connection_limit.clone().acquire_owned() => match permit {
+                Ok(permit) => permit,
+                Err(_) => break,
+            },
+            _ = context.signal.closed() => break,
+        };
+
+        let accepted = tokio::select! {
+            accepted = accept(&listener) => accepted,
+            _ = context.signal.closed() => break,
+        };
+
+        let Some((stream, peer)) = accepted else {
+            continue;
+        };
+
+        let context = context.clone();
+        tokio::spawn(async move {
+            match stream {
+                Stream::Tcp(stream) => {
+                    let _ = stream.set_nodelay(true);
+                    match context.tls.clone() {
+                        Some(acceptor) => {
+                            match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                                Ok(Ok(stream)) => serve_connection(stream, peer, context).await,
+                                Ok(Err(e)) => debug!("TLS handshake with {} failed: {}", peer, e),
+                                Err(_) => debug!("TLS handshake with {} timed out", peer),
+                            }
+                        }
+                        None => serve_connection(stream, peer, context).await,
+                    }
+                }
+                #[cfg(unix)]
+                Stream::Unix(stream) => serve_connection(stream, peer, context).await,
+            }
+            drop(permit);
+        });
+    }
+}
+
+enum Stream {
+    Tcp(tokio::net::TcpStream),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+}
+
+async fn accept(listener: &Listener) -> Option<(Stream, SocketAddr)> {
+    let accepted = match listener {
+        Listener::Tcp(listener) => listener.accept().await.map(|(stream, peer)| (Stream::Tcp(stream), peer)),
+        #[cfg(unix)]
+        Listener::Unix(listener, _) => listener.accept().await.map(|(stream, _)| (Stream::Unix(stream), UNIX_PEER)),
+    };
+
+    match accepted {
+        Ok(accepted) => Some(accepted),
+        Err(e) => {
+            // Usually a client that went away or a full descriptor table;
+            // back off briefly rather than spinning.
+            debug!("Failed to accept connection: {}", e);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            None
+        }
+    }
+}
+
+async fn serve_connection<I>(io: I, peer: SocketAddr, context: ConnectionContext)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let app = context.app.clone();
+    let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+        request.extensions_mut().insert(ConnectInfo(peer));
+        app.clone().oneshot(request.map(Body::new))
+    });
+    let io = TokioIo::new(io);
+
+    let result = match context.builder.as_ref() {
+        ConnectionBuilder::Auto(builder) => {
+            let connection = builder.serve_connection_with_upgrades(io, service);
+            until_closed(connection, &context.signal, |connection| connection.graceful_shutdown())
+                .await
+                .map_err(|e| e.to_string())
+        }
+        ConnectionBuilder::Http1(builder) => {
+            let connection = builder.serve_connection(io, service).with_upgrades();
+            until_closed(connection, &context.signal, |connection| connection.graceful_shutdown())
+                .await
+                .map_err(|e| e.to_string())
+        }
+    };
+
+    if let Err(e) = result {
+        debug!("Connection from {} ended with an error: {}", peer, e);
+    }
+}
+
+/// Drives `connection` to completion, shutting it down gracefully once the
+/// server is asked to stop.
+async fn until_closed<C, T, E>(connection: C, signal: &watch::Sender<()>, shutdown: impl Fn(Pin<&mut C>)) -> std::result::Result<T, E>
+where
+    C: Future<Output = std::result::Result<T, E>>,
+{
+    tokio::pin!(connection);
+    let signal = signal.closed().fuse();
+    tokio::pin!(signal);
+
+    loop {
+        tokio::select! {
+            result = connection.as_mut() => return result,
+            _ = &mut signal => shutdown(connection.as_mut()),
+        }
+    }
+}
+
+/// Serves HTTP/1.1 and HTTP/2 (detected from the connection preface), or
+/// HTTP/1.1 only when HTTP/2 is disabled.
+enum ConnectionBuilder {
+    Auto(Builder<TokioExecutor>),
+    Http1(http1::Builder),
+}
+
+fn connection_builder(config: &ServerConfig) -> ConnectionBuilder {
+    let keep_alive = Duration::from_secs(config.keep_alive_timeout_seconds);
+
+    if !config.http2 {
+        let mut builder = http1::Builder::new();
+        builder
+            .timer(TokioTimer::new())
+            .max_buf_size(config.max_header_size_bytes)
+            .keep_alive(!keep_alive.is_zero());
+        if !keep_alive.is_zero() {
+            builder.header_read_timeout(keep_alive);
+        }
+        return ConnectionBuilder::Http1(builder);
+    }
+
+    let mut builder = Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .max_buf_size(config.max_header_size_bytes)
+        .keep_alive(!keep_alive.is_zero());
+    if !keep_alive.is_zero() {
+        builder.http1().header_read_timeout(keep_alive);
+    }
+
+    builder
+        .http2()
+        .timer(TokioTimer::new())
+        .max_concurrent_streams(config.http2_max_concurrent_streams)
+        .max_header_list_size(u32::try_from(config.max_header_size_bytes).unwrap_or(u32::MAX));
+    if !keep_alive.is_zero() {
+        builder.http2().keep_alive_interval(keep_alive).keep_alive_timeout(keep_alive);
+    }
+
+    ConnectionBuilder::Auto(builder)
+}
+
+fn tls_acceptor(config: &TlsConfig, http2: bool) -> Result<TlsAcceptor> {
+    let read_pem = |path: &std::path::Path| -> Result<Vec<rustls_pemfile::Item>> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| AppError::Configuration(format!("Failed to open {}: {}", path.display(), e)))?;
+        rustls_pemfile::read_all(&mut std::io::BufReader::new(file))
+            .map_err(|e| AppError::Configuration(format!("Failed to read {}: {}", path.display(), e)))
+    };
+
+    let certs: Vec<rustls::Certificate> = read_pem(&config.cert_path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            rustls_pemfile::Item::X509Certificate(der) => Some(rustls::Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    if certs.is_empty() {
+        return Err(AppError::Configuration(format!(
+            "No certificates found in {}",
+            config.cert_path.display()
+        )));
+    }
+
+    let key = read_pem(&config.key_path)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(der)
+            | rustls_pemfile::Item::RSAKey(der)
+            | rustls_pemfile::Item::ECKey(der) => Some(rustls::PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| AppError::Configuration(format!("No private key found in {}", config.key_path.display())))?;
+
+    let mut tls = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| AppError::Configuration(format!("Invalid TLS certificate or key: {}", e)))?;
+    tls.alpn_protocols = if http2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+
+    Ok(TlsAcceptor::from(Arc::new(tls)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+
+    fn app() -> Router {
+        Router::new().route(
+            "/whoami",
+            get(|request: axum::extract::Request| async move {
+                let ConnectInfo(peer) = request.extensions().get::<ConnectInfo<SocketAddr>>().copied().unwrap();
+                format!("{:?} {}", request.version(), peer.ip())
+            }),
+        )
+    }
+
+    fn config(additional_listeners: Vec<String>) -> ServerConfig {
+        ServerConfig {
+            port: 0,
+            shutdown_timeout_seconds: 1,
+            additional_listeners,
+            ..ServerConfig::default()
+        }
+    }
+
+    async fn get_body<I>(io: I, http2: bool) -> String
+    where
+        I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let request = Request::get("http://localhost/whoami").body(Body::empty()).unwrap();
+        let response = if http2 {
+            let (mut sender, connection) = hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(io))
+                .await
+                .unwrap();
+            tokio::spawn(connection);
+            sender.send_request(request).await.unwrap()
+        } else {
+            let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(io)).await.unwrap();
+            tokio::spawn(connection);
+            sender.send_request(request).await.unwrap()
+        };
+        let body = axum::body::to_bytes(Body::new(response.into_body()), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    fn tcp_addr(addrs: &[ListenAddr]) -> SocketAddr {
+        match addrs[0] {
+            ListenAddr::Tcp(addr) => addr,
+            ListenAddr::Unix(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_listen_addr_parsing() {
+        assert_eq!("127.0.0.1:3000".parse(), Ok(ListenAddr::Tcp(SocketAddr::from(([127, 0, 0, 1], 3000)))));
+        assert_eq!("unix:/tmp/app.sock".parse(), Ok(ListenAddr::Unix(PathBuf::from("/tmp/app.sock"))));
+        assert!("unix:".parse::<ListenAddr>().is_err());
+        assert!("localhost".parse::<ListenAddr>().is_err());
+        assert_eq!(ListenAddr::Unix(PathBuf::from("/tmp/app.sock")).to_string(), "unix:/tmp/app.sock");
+    }
+
+    #[tokio::test]
+    async fn test_h2c_and_http1_on_the_same_listener() {
+        let server = Server::bind(&config(Vec::new())).await.unwrap();
+        let addr = tcp_addr(&server.local_addrs().unwrap());
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let serving = tokio::spawn(server.serve(app(), async move {
+            let _ = stopped.await;
+        }));
+
+        let h2 = get_body(tokio::net::TcpStream::connect(addr).await.unwrap(), true).await;
+        assert_eq!(h2, "HTTP/2.0 127.0.0.1");
+        let h1 = get_body(tokio::net::TcpStream::connect(addr).await.unwrap(), false).await;
+        assert_eq!(h1, "HTTP/1.1 127.0.0.1");
+
+        stop.send(()).unwrap();
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_http2_can_be_disabled() {
+        let server = Server::bind(&ServerConfig { http2: false, ..config(Vec::new()) }).await.unwrap();
+        let addr = tcp_addr(&server.local_addrs().unwrap());
+        tokio::spawn(server.serve(app(), std::future::pending()));
+
+        let io = TokioIo::new(tokio::net::TcpStream::connect(addr).await.unwrap());
+        let (mut sender, connection) = hyper::client::conn::http2::handshake(TokioExecutor::new(), io).await.unwrap();
+        tokio::spawn(connection);
+        let request = Request::get("http://localhost/whoami").body(Body::empty()).unwrap();
+        assert!(sender.send_request(request).await.is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket_listener_serves_the_same_router() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("server.sock");
+        let server = Server::bind(&config(vec![format!("unix:{}", socket.display())])).await.unwrap();
+        let addrs = server.local_addrs().unwrap();
+        assert_eq!(addrs[1], ListenAddr::Unix(socket.clone()));
+
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let serving = tokio::spawn(server.serve(app(), async move {
+            let _ = stopped.await;
+        }));
+
+        let body = get_body(tokio::net::UnixStream::connect(&socket).await.unwrap(), false).await;
+        assert_eq!(body, "HTTP/1.1 127.0.0.1");
+        let body = get_body(tokio::net::UnixStream::connect(&socket).await.unwrap(), true).await;
+        assert_eq!(body, "HTTP/2.0 127.0.0.1");
+
+        stop.send(()).unwrap();
+        serving.await.unwrap().unwrap();
+        assert!(!socket.exists());
+    }
+}
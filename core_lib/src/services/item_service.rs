@@ -1,15 +1,35 @@
 use crate::{
-    database::{ItemRepository, Repository, CreateItemInput, UpdateItemInput, ListParams},
+    changes::ChangePage,
+    config::{ChangeFeedConfig, ItemConfig},
+    database::{ItemRepository, Repository, CreateItemInput, UpdateItemInput, ListParams, SortOrder, SortSpec, ITEM_SORT},
     store::{DataStore, Item},
+    transaction,
+    trash::PurgeReport,
     error::{AppError, Result},
+    item_limits::MetadataLimits,
+    item_metadata::Metadata,
+    item_names::{DuplicateName, NameConflict, NameRule},
+    item_schema::ItemSchema,
+    item_secrets::{self, ItemSecrets},
+    item_transform::{MetadataBatch, MetadataOutcome, MetadataTransform},
+    models::items::{ItemStats, StatsBreakdowns, TagCount, TagRewrite, VersionConflict},
+    validation::{unicode, ValidationError},
 };
+use chrono::{DateTime, Utc};
 use std::collections::HashMap;
+use std::future::Future;
 
 #[derive(Clone)]
 pub struct ItemService {
     item_repository: Option<ItemRepository>,
     data_store: DataStore,
     use_database: bool,
+    require_version: bool,
+    metadata_limits: MetadataLimits,
+    schema: ItemSchema,
+    secrets: ItemSecrets,
+    source_in_body: bool,
+    names: NameRule,
 }
 
 impl ItemService {
@@ -18,6 +38,12 @@ impl ItemService {
             item_repository: Some(item_repository),
             data_store,
             use_database: true,
+            require_version: false,
+            metadata_limits: MetadataLimits::default(),
+            schema: ItemSchema::default(),
+            secrets: ItemSecrets::default(),
+            source_in_body: true,
+            names: NameRule::default(),
         }
     }
 
@@ -26,17 +52,73 @@ impl ItemService {
             item_repository: None,
             data_store,
             use_database: false,
+            require_version: false,
+            metadata_limits: MetadataLimits::default(),
+            schema: ItemSchema::default(),
+            secrets: ItemSecrets::default(),
+            source_in_body: true,
+            names: NameRule::default(),
+        }
+    }
+
+    /// Applies change feed retention to whichever backend is active.
+    pub fn with_change_feed(mut self, config: &ChangeFeedConfig) -> Self {
+        self.item_repository = self.item_repository.map(|repo| repo.with_change_feed(config));
+        self.data_store = self.data_store.with_change_feed(config);
+        self
+    }
+
+    /// Whether updates must name the version they were made against, how
+    /// large their metadata may be and whether names may repeat.
+    pub fn with_item_config(mut self, config: &ItemConfig) -> Self {
+        self.require_version = config.require_version;
+        self.metadata_limits = MetadataLimits::from_config(config);
+        self.source_in_body = config.source_in_body;
+        self.names = NameRule::from_config(config);
+        self.data_store = self.data_store.with_name_rule(self.names);
+        self
+    }
+
+    pub fn name_rule(&self) -> NameRule {
+        self.names
+    }
+
+    /// Metadata every write must satisfy. The handle is shared, so a schema
+    /// reloaded through it applies to this service and all its clones.
+    pub fn with_item_schema(mut self, schema: ItemSchema) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    /// Seals the secrets items are written with under the configured key.
+    pub fn with_item_secrets(mut self, secrets: ItemSecrets) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    /// Runs `work` as one transaction: the writes it makes are kept if it
+    /// returns `Ok` and undone if it fails. See [`transaction`](crate::transaction)
+    /// for what the memory store stages.
+    pub async fn transaction<T>(&self, work: impl Future<Output = Result<T>>) -> Result<T> {
+        match (&self.item_repository, self.use_database) {
+            (Some(repo), true) => transaction::run_in_database(repo.pool(), work).await,
+            _ => transaction::run_staged(&self.data_store, work).await,
         }
     }
 
     pub async fn get_items(&self, limit: Option<usize>, offset: Option<usize>) -> Result<Vec<Item>> {
+        self.list_items(limit, offset, None).await
+    }
+
+    /// A page of items in the order `sort` asks for. Without one, the
+    /// database lists the newest first and the memory store by id.
+    pub async fn list_items(&self, limit: Option<usize>, offset: Option<usize>, sort: Option<&SortSpec>) -> Result<Vec<Item>> {
         if self.use_database {
             if let Some(repo) = &self.item_repository {
                 let params = ListParams {
                     limit: limit.map(|l| l as i64),
                     offset: offset.map(|o| o as i64),
-                    sort_by: Some("created_at".to_string()),
-                    sort_order: Some(crate::database::SortOrder::Desc),
+                    sort: sort.cloned(),
                 };
                 tracing::debug!("ItemService: calling repo.list with limit={:?}, offset={:?}", params.limit, params.offset);
                 let limit = params.limit;
@@ -48,7 +130,17 @@ impl ItemService {
             }
         }
 
-        self.data_store.get_items(limit, offset)
+        let Some(sort) = sort else {
+            return self.data_store.get_items(limit, offset);
+        };
+        ITEM_SORT.check(sort)?;
+        let mut items = self.data_store.get_items(None, None)?;
+        items.sort_by(|a, b| compare_items(sort, a, b));
+        Ok(items
+            .into_iter()
+            .skip(offset.unwrap_or(0))
+            .take(limit.unwrap_or(usize::MAX))
+            .collect())
     }
 
     pub async fn get_item(&self, id: u64) -> Result<Item> {
@@ -69,52 +161,114 @@ impl ItemService {
         name: String,
         description: Option<String>,
         tags: Vec<String>,
-        metadata: Option<serde_json::Value>,
+        mut metadata: Option<serde_json::Value>,
     ) -> Result<Item> {
+        let (name, description, tags) = self.normalize_text_fields(name, description, tags);
         self.validate_item_input(&name)?;
+        self.validate_metadata(metadata.as_ref())?;
+        self.seal_secret(None, metadata.as_mut()).await?;
 
         if self.use_database {
             if let Some(repo) = &self.item_repository {
+                self.check_name(&name, None).await?;
                 let input = CreateItemInput {
-                    name,
+                    name: name.clone(),
                     description,
                     tags,
                     metadata,
                     created_by: None,
                 };
-                return repo.create(input).await;
+                return match repo.create(input).await {
+                    Err(e) => Err(self.name_taken(e, &name, None).await),
+                    created => created,
+                };
             }
         }
 
         self.data_store.create_item(name, description, tags, metadata)
     }
 
+    /// Creates an item with a chosen id and creation time, validated like
+    /// any other. Only the database keeps chosen ids.
+    pub async fn create_item_with_id(
+        &self,
+        id: u64,
+        created_at: DateTime<Utc>,
+        name: String,
+        description: Option<String>,
+        tags: Vec<String>,
+        mut metadata: Option<serde_json::Value>,
+    ) -> Result<Item> {
+        let (name, description, tags) = self.normalize_text_fields(name, description, tags);
+        self.validate_item_input(&name)?;
+        self.validate_metadata(metadata.as_ref())?;
+        self.seal_secret(None, metadata.as_mut()).await?;
+
+        match (&self.item_repository, self.use_database) {
+            (Some(repo), true) => {
+                self.check_name(&name, None).await?;
+                let input = CreateItemInput {
+                    name: name.clone(),
+                    description,
+                    tags,
+                    metadata,
+                    created_by: None,
+                };
+                match repo.create_with_id(id as i64, created_at, input).await {
+                    Err(e) => Err(self.name_taken(e, &name, None).await),
+                    created => created,
+                }
+            }
+            _ => Err(AppError::Configuration("Items with chosen ids need the database".to_string())),
+        }
+    }
+
     pub async fn update_item(
         &self,
         id: u64,
         name: String,
         description: Option<String>,
         tags: Vec<String>,
-        metadata: Option<serde_json::Value>,
+        mut metadata: Option<serde_json::Value>,
+        expected_version: Option<u64>,
     ) -> Result<Item> {
+        self.ensure_version_given(expected_version)?;
+        let (name, description, tags) = self.normalize_text_fields(name, description, tags);
         self.validate_item_input(&name)?;
+        self.validate_metadata(metadata.as_ref())?;
+        self.seal_secret(Some(id), metadata.as_mut()).await?;
 
         if self.use_database {
             if let Some(repo) = &self.item_repository {
+                self.check_name(&name, Some(id)).await?;
                 let input = UpdateItemInput {
-                    name,
+                    name: name.clone(),
                     description,
                     tags,
                     metadata,
+                    expected_version,
+                };
+                return match repo.update(id as i64, input).await {
+                    Err(e) => Err(self.name_taken(e, &name, Some(id)).await),
+                    updated => updated,
                 };
-                return repo.update(id as i64, input).await;
             }
         }
 
-        self.data_store.update_item(id, name, description, tags, metadata)
+        self.data_store.update_item(id, name, description, tags, metadata, expected_version)
     }
 
-    pub async fn patch_item(&self, id: u64, updates: HashMap<String, serde_json::Value>) -> Result<Item> {
+    pub async fn patch_item(
+        &self,
+        id: u64,
+        mut updates: HashMap<String, serde_json::Value>,
+        expected_version: Option<u64>,
+    ) -> Result<Item> {
+        self.ensure_version_given(expected_version)?;
+        self.normalize_patch(&mut updates);
+        self.validate_patch(&updates)?;
+        self.seal_secret(Some(id), updates.get_mut("metadata")).await?;
+
         if self.use_database {
             if let Some(repo) = &self.item_repository {
                 let current_item = match repo.get_by_id(id as i64).await? {
@@ -122,13 +276,12 @@ impl ItemService {
                     None => return Err(AppError::NotFound(format!("Item with id {} not found", id))),
                 };
 
-                let mut name = current_item.name;
-                let mut description = current_item.description;
-                let mut tags = current_item.tags;
-                let mut metadata = current_item.metadata;
+                let mut name = current_item.name.clone();
+                let mut description = current_item.description.clone();
+                let mut tags = current_item.tags.clone();
+                let mut metadata = current_item.metadata.clone();
 
                 if let Some(new_name) = updates.get("name").and_then(|v| v.as_str()) {
-                    self.validate_item_input(new_name)?;
                     name = new_name.to_string();
                 }
 
@@ -150,22 +303,111 @@ impl ItemService {
                     if new_metadata.is_null() {
                         metadata = None;
                     } else {
-                        metadata = Some(new_metadata.clone());
+                        metadata = Some(Metadata::from_value(new_metadata));
                     }
                 }
 
-                let input = UpdateItemInput {
+                let proposed = Item {
                     name,
                     description,
                     tags,
                     metadata,
+                    ..current_item.clone()
+                };
+                VersionConflict::check(expected_version, &current_item, &proposed)?;
+                if proposed.name != current_item.name {
+                    self.check_name(&proposed.name, Some(id)).await?;
+                }
+
+                let name = proposed.name;
+                let input = UpdateItemInput {
+                    name: name.clone(),
+                    description: proposed.description,
+                    tags: proposed.tags,
+                    metadata: proposed.metadata.as_ref().map(Metadata::to_value),
+                    expected_version,
                 };
 
-                return repo.update(id as i64, input).await;
+                return match repo.update(id as i64, input).await {
+                    Err(e) => Err(self.name_taken(e, &name, Some(id)).await),
+                    updated => updated,
+                };
             }
         }
 
-        self.data_store.patch_item(id, updates)
+        self.data_store.patch_item(id, updates, expected_version)
+    }
+
+    /// Seals the secret in `metadata` about to be written to item `id`, or
+    /// to a new item, taking the stored one when it is sent back redacted.
+    async fn seal_secret(&self, id: Option<u64>, metadata: Option<&mut serde_json::Value>) -> Result<()> {
+        let Some(metadata) = metadata else {
+            return Ok(());
+        };
+        let stored = match id {
+            Some(id) if item_secrets::keeps_stored_secret(metadata) => self.get_item(id).await?.metadata,
+            _ => None,
+        };
+        self.secrets.seal(metadata, stored.as_ref().map(Metadata::to_value).as_ref())
+    }
+
+    /// Refuses `name` for item `id`, or a new item, when another item has
+    /// it. The memory store checks under its own lock instead.
+    async fn check_name(&self, name: &str, id: Option<u64>) -> Result<()> {
+        let (Some(repo), true) = (&self.item_repository, self.names.is_enabled()) else {
+            return Ok(());
+        };
+        match repo.find_by_name(&self.names, name, id.map(|id| id as i64)).await? {
+            Some(item_id) => Err(NameConflict::new(name, item_id as u64).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// `error` from writing `name`, as the name conflict behind it when the
+    /// unique name index refused a write that raced past
+    /// [`check_name`](Self::check_name).
+    async fn name_taken(&self, error: AppError, name: &str, id: Option<u64>) -> AppError {
+        if !matches!(error, AppError::Conflict(_)) {
+            return error;
+        }
+        match self.check_name(name, id).await {
+            Err(conflict @ AppError::NameConflict(_)) => conflict,
+            _ => error,
+        }
+    }
+
+    /// Creates the unique index behind the name rule. Returns false when
+    /// stored items already break the rule, which is then only checked
+    /// before each write.
+    pub async fn ensure_name_index(&self) -> Result<bool> {
+        match (&self.item_repository, self.use_database) {
+            (Some(repo), true) => repo.ensure_name_index(&self.names).await,
+            _ => Ok(true),
+        }
+    }
+
+    /// Stored items sharing a name under the name rule, in every namespace.
+    /// Nothing is reported while the rule is off.
+    pub async fn duplicate_names(&self) -> Result<Vec<DuplicateName>> {
+        if !self.names.is_enabled() {
+            return Ok(Vec::new());
+        }
+        match (&self.item_repository, self.use_database) {
+            (Some(repo), true) => repo.duplicate_names(&self.names).await,
+            _ => self.data_store.duplicate_names(&self.names),
+        }
+    }
+
+    /// Refuses updates that do not name a version when versions are
+    /// mandatory.
+    fn ensure_version_given(&self, expected_version: Option<u64>) -> Result<()> {
+        if self.require_version && expected_version.is_none() {
+            return Err(AppError::PreconditionRequired(
+                "Item updates must send the version they were made against, as If-Match or a \"version\" field".to_string(),
+            ));
+        }
+
+        Ok(())
     }
 
     pub async fn delete_item(&self, id: u64) -> Result<()> {
@@ -178,29 +420,201 @@ impl ItemService {
         self.data_store.delete_item(id)
     }
 
-    pub async fn get_stats(&self) -> Result<serde_json::Value> {
+    /// Items in the trash, most recently deleted first, with when they were
+    /// deleted.
+    pub async fn trashed_items(&self, limit: usize, offset: usize) -> Result<Vec<(Item, DateTime<Utc>)>> {
+        if self.use_database {
+            if let Some(repo) = &self.item_repository {
+                return repo.list_trashed(limit as i64, offset as i64).await;
+            }
+        }
+
+        self.data_store.trashed_items(limit, offset)
+    }
+
+    /// Takes an item out of the trash before it is purged.
+    pub async fn restore_item(&self, id: u64) -> Result<Item> {
         if self.use_database {
             if let Some(repo) = &self.item_repository {
-                let count = repo.count().await?;
-                
-                return Ok(serde_json::json!({
-                    "total_items": count,
-                    "source": "database"
-                }));
+                return match repo.restore(id as i64).await {
+                    Err(AppError::Conflict(_)) if self.names.is_enabled() => Err(AppError::Conflict(format!(
+                        "Item {} cannot be restored while another item has its name",
+                        id
+                    ))),
+                    restored => restored,
+                };
             }
         }
 
-        let mut stats = self.data_store.get_stats()?;
-        if let Some(obj) = stats.as_object_mut() {
-            obj.insert("source".to_string(), serde_json::Value::String("memory".to_string()));
+        self.data_store.restore_item(id)
+    }
+
+    pub async fn get_stats(&self) -> Result<serde_json::Value> {
+        let mut stats = match &self.item_repository {
+            Some(repo) if self.use_database => serde_json::json!({ "total_items": repo.count().await? }),
+            _ => self.data_store.get_stats()?,
+        };
+        if let (true, Some(obj)) = (self.source_in_body, stats.as_object_mut()) {
+            obj.insert("source".to_string(), serde_json::Value::String(self.data_source().to_string()));
         }
         Ok(stats)
     }
 
+    /// Totals plus the selected breakdowns, from whichever backend is active.
+    pub async fn get_detailed_stats(&self, breakdowns: &StatsBreakdowns, top: usize) -> Result<ItemStats> {
+        if self.use_database {
+            if let Some(repo) = &self.item_repository {
+                return repo.stats(breakdowns, top).await;
+            }
+        }
+
+        self.data_store.item_stats(breakdowns, top)
+    }
+
+    /// Items carrying any of `tags`, in id order. The memory store answers
+    /// from its tag index instead of scanning.
+    pub async fn get_items_by_tags(&self, tags: &[String], limit: Option<usize>, offset: Option<usize>) -> Result<Vec<Item>> {
+        if self.use_database {
+            if let Some(repo) = &self.item_repository {
+                let params = ListParams {
+                    limit: limit.map(|l| l as i64),
+                    offset: offset.map(|o| o as i64),
+                    sort: None,
+                };
+                return repo.get_by_tags(tags, params).await;
+            }
+        }
+
+        self.data_store.get_by_tags(tags, limit, offset)
+    }
+
+    /// Number of items carrying `tag`.
+    pub async fn count_by_tag(&self, tag: &str) -> Result<u64> {
+        if self.use_database {
+            if let Some(repo) = &self.item_repository {
+                return repo.count_by_tag(tag).await;
+            }
+        }
+
+        self.data_store.count_by_tag(tag)
+    }
+
+    /// Tags in use with their item counts, most used first.
+    pub async fn tag_counts(&self) -> Result<Vec<TagCount>> {
+        if self.use_database {
+            if let Some(repo) = &self.item_repository {
+                return repo.tag_counts(None).await;
+            }
+        }
+
+        self.data_store.tag_counts()
+    }
+
+    /// Tags in use starting with `prefix`, ignoring case, most used first.
+    pub async fn tags_with_prefix(&self, prefix: &str, limit: usize) -> Result<Vec<TagCount>> {
+        if self.use_database {
+            if let Some(repo) = &self.item_repository {
+                return repo.tags_with_prefix(prefix, limit).await;
+            }
+        }
+
+        self.data_store.tags_with_prefix(prefix, limit)
+    }
+
+    /// Renames or merges tags across all items, returning the items that
+    /// changed (or would change, on a dry run).
+    pub async fn rewrite_tags(&self, rewrite: &TagRewrite, dry_run: bool) -> Result<Vec<Item>> {
+        if self.use_database {
+            if let Some(repo) = &self.item_repository {
+                return repo.rewrite_tags(rewrite, dry_run).await;
+            }
+        }
+
+        self.data_store.rewrite_tags(rewrite, dry_run)
+    }
+
+    /// Applies `transform` to the next batch of items with ids above
+    /// `after_id`, in one transaction. Metadata that would break the limits
+    /// or the schema is reported as a failure and not written.
+    pub async fn transform_metadata(&self, transform: &MetadataTransform, after_id: u64, dry_run: bool) -> Result<MetadataBatch> {
+        let edit = |item: &Item| match transform.apply(item) {
+            MetadataOutcome::Changed(metadata) => match self.validate_metadata(Some(&metadata)) {
+                Ok(()) => MetadataOutcome::Changed(metadata),
+                Err(e) => MetadataOutcome::Failed(e.to_string()),
+            },
+            outcome => outcome,
+        };
+
+        if self.use_database {
+            if let Some(repo) = &self.item_repository {
+                return repo.transform_metadata(after_id as i64, transform.batch_size as i64, dry_run, &edit).await;
+            }
+        }
+
+        self.data_store.transform_metadata(after_id, transform.batch_size, dry_run, &edit)
+    }
+
+    /// Up to `limit` item changes after sequence number `since`.
+    pub async fn changes_since(&self, since: u64, limit: usize) -> Result<ChangePage> {
+        if self.use_database {
+            if let Some(repo) = &self.item_repository {
+                return repo.changes_since(since, limit).await;
+            }
+        }
+
+        self.data_store.changes_since(since, limit)
+    }
+
+    /// Permanently removes items deleted before `cutoff`.
+    pub async fn purge_trash(&self, cutoff: DateTime<Utc>, batch_size: usize, dry_run: bool) -> Result<PurgeReport> {
+        if self.use_database {
+            if let Some(repo) = &self.item_repository {
+                return repo.purge_deleted(cutoff, batch_size, dry_run).await;
+            }
+        }
+
+        self.data_store.purge_deleted(cutoff, batch_size, dry_run)
+    }
+
+    /// Up to `limit` items with ids above `after_id` that hold a secret,
+    /// from every namespace and the trash.
+    pub async fn items_with_secrets(&self, after_id: u64, limit: usize) -> Result<Vec<Item>> {
+        if self.use_database {
+            if let Some(repo) = &self.item_repository {
+                return repo.items_with_secrets(after_id as i64, limit as i64).await;
+            }
+        }
+
+        self.data_store.items_with_secrets(after_id, limit)
+    }
+
+    /// Stores resealed metadata for an item still at `version`, without
+    /// recording a change. Returns whether it was still at that version.
+    pub async fn replace_metadata(&self, id: u64, version: u64, metadata: serde_json::Value) -> Result<bool> {
+        if self.use_database {
+            if let Some(repo) = &self.item_repository {
+                return repo.replace_metadata(id as i64, version, &metadata).await;
+            }
+        }
+
+        self.data_store.replace_metadata(id, version, metadata)
+    }
+
     pub fn is_using_database(&self) -> bool {
         self.use_database && self.item_repository.is_some()
     }
 
+    /// `database` or `memory`, as reported in the `X-Data-Source` header.
+    pub fn data_source(&self) -> &'static str {
+        if self.is_using_database() { "database" } else { "memory" }
+    }
+
+    /// Whether responses still carry the deprecated `source` field; see
+    /// [`ItemConfig::source_in_body`].
+    pub fn source_in_body(&self) -> bool {
+        self.source_in_body
+    }
+
     pub fn repository(&self) -> Option<&ItemRepository> {
         self.item_repository.as_ref()
     }
@@ -209,23 +623,122 @@ impl ItemService {
         &self.data_store
     }
 
+    fn normalize_text_fields(
+        &self,
+        name: String,
+        description: Option<String>,
+        tags: Vec<String>,
+    ) -> (String, Option<String>, Vec<String>) {
+        (
+            self.names.normalize(unicode::normalize_line(&name)),
+            description.as_deref().map(unicode::normalize_multiline),
+            tags.iter().map(|tag| unicode::normalize_line(tag)).collect(),
+        )
+    }
+
+    fn normalize_patch(&self, updates: &mut HashMap<String, serde_json::Value>) {
+        if let Some(serde_json::Value::String(name)) = updates.get_mut("name") {
+            *name = self.names.normalize(unicode::normalize_line(name));
+        }
+
+        if let Some(serde_json::Value::String(description)) = updates.get_mut("description") {
+            *description = unicode::normalize_multiline(description);
+        }
+
+        if let Some(serde_json::Value::Array(tags)) = updates.get_mut("tags") {
+            for tag in tags.iter_mut() {
+                if let serde_json::Value::String(text) = tag {
+                    *text = unicode::normalize_line(text);
+                }
+            }
+        }
+    }
+
+    /// Checks the fields a patch sets before either backend applies it, so
+    /// both refuse the same patches.
+    fn validate_patch(&self, updates: &HashMap<String, serde_json::Value>) -> Result<()> {
+        if let Some(name) = updates.get("name") {
+            let name = name
+                .as_str()
+                .ok_or_else(|| ValidationError::field("name", "type", "Item name must be a string"))?;
+            self.validate_item_input(name)?;
+        }
+
+        if updates.get("description").is_some_and(|description| !description.is_null() && !description.is_string()) {
+            return Err(ValidationError::field("description", "type", "Description must be a string or null").into());
+        }
+
+        let tags_valid = |tags: &serde_json::Value| {
+            tags.is_null() || tags.as_array().is_some_and(|tags| tags.iter().all(serde_json::Value::is_string))
+        };
+        if updates.get("tags").is_some_and(|tags| !tags_valid(tags)) {
+            return Err(ValidationError::field("tags", "type", "Tags must be an array of strings").into());
+        }
+
+        // Metadata is replaced as a whole, so a patch that leaves it out
+        // keeps whatever the item has, conforming or not.
+        if let Some(metadata) = updates.get("metadata") {
+            self.validate_metadata(Some(metadata).filter(|metadata| !metadata.is_null()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks metadata being written against the size limits, then the
+    /// item schema.
+    fn validate_metadata(&self, metadata: Option<&serde_json::Value>) -> Result<()> {
+        if let Some(metadata) = metadata {
+            self.metadata_limits.check(metadata)?;
+        }
+        self.schema
+            .check(metadata)
+            .ensure_valid("Metadata does not match the item schema")?;
+        Ok(())
+    }
+
     fn validate_item_input(&self, name: &str) -> Result<()> {
         if name.trim().is_empty() {
-            return Err(AppError::Validation("Item name cannot be empty".to_string()));
+            return Err(ValidationError::field("name", "required", "Item name cannot be empty").into());
         }
 
-        if name.len() > 255 {
-            return Err(AppError::Validation("Item name too long (maximum 255 characters)".to_string()));
+        if unicode::text_length(name) > 255 {
+            return Err(ValidationError::field("name", "length", "Item name too long (maximum 255 characters)").into());
         }
 
         if name.contains('\0') {
-            return Err(AppError::Validation("Item name cannot contain null bytes".to_string()));
+            return Err(ValidationError::field("name", "null_bytes", "Item name cannot contain null bytes").into());
         }
 
         Ok(())
     }
 }
 
+/// The memory store's version of [`ITEM_SORT`]: `sort` decides first, then
+/// the id in the direction of the last field, the same total order the
+/// database gives.
+fn compare_items(sort: &SortSpec, a: &Item, b: &Item) -> std::cmp::Ordering {
+    sort.keys()
+        .iter()
+        .map(|key| {
+            let ordering = match key.field.as_str() {
+                "name" => a.name.cmp(&b.name),
+                "created_at" => a.created_at.cmp(&b.created_at),
+                "updated_at" => a.updated_at.cmp(&b.updated_at),
+                "version" => a.version.cmp(&b.version),
+                _ => a.id.cmp(&b.id),
+            };
+            match key.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or_else(|| match sort.keys().last().map(|key| key.order) {
+            Some(SortOrder::Desc) => b.id.cmp(&a.id),
+            _ => a.id.cmp(&b.id),
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -270,6 +783,7 @@ mod tests {
             Some("Updated Description".to_string()),
             vec!["updated".to_string()],
             None,
+            None,
         ).await.unwrap();
 
         assert_eq!(updated.name, "Updated Item");
@@ -277,7 +791,7 @@ mod tests {
         let mut patch = HashMap::new();
         patch.insert("name".to_string(), serde_json::Value::String("Patched Item".to_string()));
         
-        let patched = service.patch_item(item.id, patch).await.unwrap();
+        let patched = service.patch_item(item.id, patch, None).await.unwrap();
         assert_eq!(patched.name, "Patched Item");
 
         let items = service.get_items(Some(10), Some(0)).await.unwrap();
@@ -304,6 +818,26 @@ mod tests {
 
         let stats = service.get_stats().await.unwrap();
         assert_eq!(stats["source"], "memory");
+
+        let service = service.with_item_config(&ItemConfig { source_in_body: false, ..ItemConfig::default() });
+        assert!(service.get_stats().await.unwrap().get("source").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_keeps_deleted_items_until_purged() {
+        let service = ItemService::with_memory_store(DataStore::new());
+        service.delete_item(1).await.unwrap();
+        assert!(service.get_item(1).await.is_err());
+
+        let report = service.purge_trash(Utc::now() - chrono::Duration::days(1), 100, false).await.unwrap();
+        assert_eq!(report.items, 0);
+
+        let report = service.purge_trash(Utc::now(), 100, true).await.unwrap();
+        assert_eq!((report.items, report.changes_removed), (1, 1));
+
+        let report = service.purge_trash(Utc::now(), 100, false).await.unwrap();
+        assert_eq!((report.items, report.changes_removed, report.batches), (1, 1, 1));
+        assert_eq!(service.purge_trash(Utc::now(), 100, false).await.unwrap().items, 0);
     }
 
     #[tokio::test]
@@ -313,9 +847,707 @@ mod tests {
             item_repository: None,
             data_store: store,
             use_database: true,
+            require_version: false,
+            metadata_limits: MetadataLimits::default(),
+            schema: ItemSchema::default(),
+            secrets: ItemSecrets::default(),
+            source_in_body: true,
+            names: NameRule::default(),
         };
 
         let items = service.get_items(None, None).await.unwrap();
         assert_eq!(items.len(), 2);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_create_item_normalizes_unicode() {
+        let service = ItemService::with_memory_store(DataStore::empty());
+
+        let item = service.create_item(
+            "Cafe\u{0301}\u{200B}".to_string(),
+            Some("line one\n\tline two\u{0007}".to_string()),
+            vec!["te\u{200D}st".to_string()],
+            None,
+        ).await.unwrap();
+
+        assert_eq!(item.name, "Caf\u{00E9}");
+        assert_eq!(item.description.as_deref(), Some("line one\n\tline two"));
+        assert_eq!(item.tags, vec!["test"]);
+    }
+
+    #[tokio::test]
+    async fn test_item_name_length_counts_characters() {
+        let service = ItemService::with_memory_store(DataStore::empty());
+
+        let japanese = "名".repeat(200);
+        assert!(service.create_item(japanese, None, vec![], None).await.is_ok());
+
+        let emoji = "😀".repeat(256);
+        assert!(service.create_item(emoji, None, vec![], None).await.is_err());
+    }
+
+    async fn add_stats_sample(service: &ItemService) {
+        let sample = [
+            ("Laptop", Some("Portable"), vec!["hardware", "sale"], Some(serde_json::json!({"price": 999}))),
+            ("Mouse", Some("  "), vec!["hardware"], None),
+            ("Manual", None, vec!["docs", "sale", "hardware"], Some(serde_json::json!({}))),
+        ];
+        for (name, description, tags, metadata) in sample {
+            service
+                .create_item(
+                    name.to_string(),
+                    description.map(str::to_string),
+                    tags.into_iter().map(str::to_string).collect(),
+                    metadata,
+                )
+                .await
+                .unwrap();
+        }
+    }
+
+    fn assert_sample_stats(stats: &ItemStats) {
+        assert_eq!(stats.total_items, 3);
+
+        let tags = stats.tags.as_ref().unwrap();
+        assert_eq!(tags.unique_tags, 3);
+        assert_eq!(tags.top.len(), 2);
+        assert_eq!((tags.top[0].tag.as_str(), tags.top[0].count), ("hardware", 3));
+        assert_eq!((tags.top[1].tag.as_str(), tags.top[1].count), ("sale", 2));
+
+        let creators = stats.creators.as_ref().unwrap();
+        assert_eq!(creators.len(), 1);
+        assert_eq!((creators[0].created_by, creators[0].count), (None, 3));
+
+        let daily = stats.created_per_day.as_ref().unwrap();
+        assert_eq!(daily.len(), 30);
+        assert_eq!(daily.last().unwrap().date, chrono::Utc::now().date_naive());
+        assert_eq!(daily.iter().map(|day| day.count).sum::<u64>(), 3);
+
+        let metadata = stats.metadata.as_ref().unwrap();
+        assert_eq!(metadata.items_with_metadata, 1);
+        assert_eq!(metadata.average_size_bytes, r#"{"price":999}"#.len() as f64);
+
+        let descriptions = stats.descriptions.as_ref().unwrap();
+        assert_eq!((descriptions.with_description, descriptions.without_description), (1, 2));
+    }
+
+    #[tokio::test]
+    async fn test_detailed_stats_match_across_backends() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        run_migrations(pool.clone()).await.unwrap();
+
+        let database = ItemService::with_database(ItemRepository::new(pool), DataStore::empty());
+        let memory = ItemService::with_memory_store(DataStore::empty());
+        add_stats_sample(&database).await;
+        add_stats_sample(&memory).await;
+
+        let database_stats = database.get_detailed_stats(&StatsBreakdowns::all(), 2).await.unwrap();
+        let memory_stats = memory.get_detailed_stats(&StatsBreakdowns::all(), 2).await.unwrap();
+        assert_eq!(database_stats.source, "database");
+        assert_eq!(memory_stats.source, "memory");
+        assert_sample_stats(&database_stats);
+        assert_sample_stats(&memory_stats);
+    }
+
+    #[tokio::test]
+    async fn test_detailed_stats_compute_only_requested_breakdowns() {
+        let service = ItemService::with_memory_store(DataStore::new());
+        let breakdowns = StatsBreakdowns::parse("tags, descriptions").unwrap();
+        assert_eq!(breakdowns.key(), "tags,descriptions");
+        assert!(StatsBreakdowns::parse("tags,owners").is_err());
+
+        let stats = service.get_detailed_stats(&breakdowns, 10).await.unwrap();
+        assert!(stats.tags.is_some());
+        assert!(stats.descriptions.is_some());
+        assert!(stats.creators.is_none() && stats.created_per_day.is_none() && stats.metadata.is_none());
+
+        let json = serde_json::to_value(&stats).unwrap();
+        assert!(json.as_object().unwrap().contains_key("created_per_day"));
+        assert!(json["metadata"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_tag_rewrites_match_across_backends() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        run_migrations(pool.clone()).await.unwrap();
+
+        let database = ItemService::with_database(ItemRepository::new(pool), DataStore::empty());
+        let memory = ItemService::with_memory_store(DataStore::empty());
+        for service in [&database, &memory] {
+            for tags in [vec!["proirity", "ops"], vec!["Priority"], vec!["priority", "proirity"], vec!["ops"]] {
+                service
+                    .create_item("Tagged".to_string(), None, tags.into_iter().map(str::to_string).collect(), None)
+                    .await
+                    .unwrap();
+            }
+
+            let merge = TagRewrite::new(vec!["proirity".to_string()], "priority".to_string(), false).unwrap();
+            let preview = service.rewrite_tags(&merge, true).await.unwrap();
+            assert_eq!(preview.iter().map(|item| item.id).collect::<Vec<_>>(), vec![1, 3]);
+            assert_eq!(service.get_item(1).await.unwrap().tags, vec!["proirity", "ops"]);
+
+            let merged = service.rewrite_tags(&merge, false).await.unwrap();
+            assert_eq!(merged.iter().map(|item| item.id).collect::<Vec<_>>(), vec![1, 3]);
+            assert_eq!(service.get_item(1).await.unwrap().tags, vec!["priority", "ops"]);
+            assert_eq!(service.get_item(3).await.unwrap().tags, vec!["priority"]);
+
+            let fold = TagRewrite::new(vec!["priority".to_string()], "priority".to_string(), true).unwrap();
+            let folded = service.rewrite_tags(&fold, false).await.unwrap();
+            assert_eq!(folded.iter().map(|item| item.id).collect::<Vec<_>>(), vec![2]);
+
+            let counts: Vec<(String, u64)> = service
+                .tag_counts()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|count| (count.tag, count.count))
+                .collect();
+            assert_eq!(counts, vec![("priority".to_string(), 3), ("ops".to_string(), 2)]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tag_lookups_match_across_backends() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        run_migrations(pool.clone()).await.unwrap();
+
+        let database = ItemService::with_database(ItemRepository::new(pool), DataStore::empty());
+        let memory = ItemService::with_memory_store(DataStore::empty());
+        let ids = |items: Vec<Item>| items.into_iter().map(|item| item.id).collect::<Vec<_>>();
+        let tags = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        for service in [&database, &memory] {
+            for names in [&["red", "blue"][..], &["blue"], &["green"], &["redder"]] {
+                service.create_item("Tagged".to_string(), None, tags(names), None).await.unwrap();
+            }
+
+            assert_eq!(ids(service.get_items_by_tags(&tags(&["red", "green"]), None, None).await.unwrap()), vec![1, 3]);
+            assert_eq!(ids(service.get_items_by_tags(&tags(&["blue"]), Some(1), Some(1)).await.unwrap()), vec![2]);
+            assert!(service.get_items_by_tags(&[], None, None).await.unwrap().is_empty());
+            assert_eq!(service.count_by_tag("red").await.unwrap(), 1);
+
+            service.update_item(1, "Tagged".to_string(), None, tags(&["green"]), None, None).await.unwrap();
+            let patch = HashMap::from([("tags".to_string(), serde_json::json!(["red"]))]);
+            service.patch_item(2, patch, None).await.unwrap();
+            service.delete_item(3).await.unwrap();
+
+            assert_eq!(ids(service.get_items_by_tags(&tags(&["green"]), None, None).await.unwrap()), vec![1]);
+            assert_eq!(ids(service.get_items_by_tags(&tags(&["red"]), None, None).await.unwrap()), vec![2]);
+            assert_eq!(service.count_by_tag("blue").await.unwrap(), 0);
+
+            let merge = TagRewrite::new(vec!["redder".to_string()], "red".to_string(), false).unwrap();
+            service.rewrite_tags(&merge, false).await.unwrap();
+            assert_eq!(ids(service.get_items_by_tags(&tags(&["red"]), None, None).await.unwrap()), vec![2, 4]);
+            assert_eq!(service.count_by_tag("redder").await.unwrap(), 0);
+        }
+
+        let stats = memory.get_stats().await.unwrap();
+        assert_eq!(stats["tag_index"]["tags"], 2);
+        assert_eq!(stats["tag_index"]["entries"], 3);
+        assert!(stats["tag_index"]["approximate_bytes"].as_u64().unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_tag_index_has_no_stale_ids_after_concurrent_updates() {
+        let service = ItemService::with_memory_store(DataStore::empty());
+        for _ in 0..4 {
+            service.create_item("Cycled".to_string(), None, vec!["start".to_string()], None).await.unwrap();
+        }
+
+        let cycles = (0..32u64).map(|n| {
+            let service = service.clone();
+            tokio::spawn(async move {
+                let id = n % 4 + 1;
+                for round in 0..20 {
+                    let tag = format!("round-{}", round % 3);
+                    if n % 2 == 0 {
+                        let _ = service.update_item(id, "Cycled".to_string(), None, vec![tag], None, None).await;
+                    } else {
+                        let patch = HashMap::from([("tags".to_string(), serde_json::json!([tag]))]);
+                        let _ = service.patch_item(id, patch, None).await;
+                    }
+                }
+            })
+        });
+        for result in futures_util::future::join_all(cycles).await {
+            result.unwrap();
+        }
+
+        let mut indexed = 0;
+        for tag in ["start", "round-0", "round-1", "round-2"] {
+            for item in service.get_items_by_tags(&[tag.to_string()], None, None).await.unwrap() {
+                assert_eq!(item.tags, vec![tag], "item {} is indexed under a tag it no longer has", item.id);
+                indexed += 1;
+            }
+        }
+        assert_eq!(indexed, 4);
+    }
+
+    #[tokio::test]
+    async fn test_change_feed_matches_across_backends() {
+        use crate::changes::ChangeOp;
+
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        run_migrations(pool.clone()).await.unwrap();
+
+        let retention = ChangeFeedConfig {
+            retention_hours: 24,
+            max_entries: 4,
+        };
+        let database = ItemService::with_database(ItemRepository::new(pool), DataStore::empty())
+            .with_change_feed(&retention);
+        let memory = ItemService::with_memory_store(DataStore::empty()).with_change_feed(&retention);
+        for service in [&database, &memory] {
+            let item = service.create_item("Tracked".to_string(), None, vec![], None).await.unwrap();
+            service
+                .update_item(item.id, "Renamed".to_string(), None, vec![], None, None)
+                .await
+                .unwrap();
+            service.delete_item(item.id).await.unwrap();
+            assert!(service.delete_item(item.id).await.is_err());
+
+            let page = service.changes_since(0, 2).await.unwrap();
+            let ops: Vec<(u64, ChangeOp)> = page.changes.iter().map(|change| (change.seq, change.op)).collect();
+            assert_eq!(ops, vec![(1, ChangeOp::Created), (2, ChangeOp::Updated)]);
+            assert_eq!(page.changes[1].item.as_ref().unwrap().name, "Renamed");
+            assert!(page.has_more);
+
+            let page = service.changes_since(page.next_since, 2).await.unwrap();
+            assert_eq!(page.changes.len(), 1);
+            assert_eq!(page.changes[0].op, ChangeOp::Deleted);
+            assert_eq!(page.changes[0].item_id, item.id);
+            assert!(page.changes[0].item.is_none());
+            assert!(!page.has_more);
+
+            for name in ["Second", "Third"] {
+                service.create_item(name.to_string(), None, vec![], None).await.unwrap();
+            }
+            assert!(matches!(service.changes_since(0, 10).await, Err(AppError::Gone(_))));
+            let page = service.changes_since(1, 10).await.unwrap();
+            assert_eq!(page.changes.first().map(|change| change.seq), Some(2));
+            assert_eq!(page.next_since, 5);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_versioned_updates_let_exactly_one_win() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        run_migrations(pool.clone()).await.unwrap();
+
+        let database = ItemService::with_database(ItemRepository::new(pool), DataStore::empty());
+        let memory = ItemService::with_memory_store(DataStore::empty());
+        for service in [database, memory] {
+            let item = service.create_item("Shared".to_string(), None, vec![], None).await.unwrap();
+            assert_eq!(item.version, 1);
+
+            let attempts = (0..8).map(|n| {
+                let service = service.clone();
+                tokio::spawn(async move {
+                    if n % 2 == 0 {
+                        service
+                            .update_item(item.id, format!("Editor {}", n), None, vec![], None, Some(item.version))
+                            .await
+                    } else {
+                        let patch = HashMap::from([("name".to_string(), serde_json::json!(format!("Editor {}", n)))]);
+                        service.patch_item(item.id, patch, Some(item.version)).await
+                    }
+                })
+            });
+            let results = futures_util::future::join_all(attempts).await;
+
+            let mut winners = Vec::new();
+            for result in results {
+                match result.unwrap() {
+                    Ok(updated) => winners.push(updated),
+                    Err(AppError::VersionConflict(conflict)) => {
+                        assert_eq!(conflict.expected_version, 1);
+                        assert_eq!(conflict.current_version, 2);
+                        assert_eq!(conflict.item_id, item.id);
+                    }
+                    Err(other) => panic!("unexpected error: {}", other),
+                }
+            }
+            assert_eq!(winners.len(), 1);
+            assert_eq!(winners[0].version, 2);
+
+            let current = service.get_item(item.id).await.unwrap();
+            assert_eq!(current.name, winners[0].name);
+            assert_eq!(current.version, 2);
+
+            let unversioned = service
+                .update_item(item.id, "Last write".to_string(), None, vec![], None, None)
+                .await
+                .unwrap();
+            assert_eq!(unversioned.version, 3);
+
+            let strict = service.clone().with_item_config(&ItemConfig { require_version: true, ..ItemConfig::default() });
+            let refused = strict.update_item(item.id, "No version".to_string(), None, vec![], None, None).await;
+            assert!(matches!(refused, Err(AppError::PreconditionRequired(_))));
+            let patch = HashMap::from([("tags".to_string(), serde_json::json!(["stale"]))]);
+            match strict.patch_item(item.id, patch, Some(2)).await {
+                Err(AppError::VersionConflict(conflict)) => {
+                    assert_eq!(conflict.current_version, 3);
+                    assert_eq!(conflict.changed_fields, vec!["tags"]);
+                    assert_eq!(conflict.current.name, "Last write");
+                }
+                other => panic!("expected a version conflict, got {:?}", other.map(|item| item.version)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oversized_metadata_is_refused_on_every_write() {
+        let app = crate::test_support::test_app().await;
+        let services = [
+            app.state.item_service.clone(),
+            ItemService::with_memory_store(DataStore::new()),
+        ];
+        let blob = || Some(serde_json::json!({ "blob": "x".repeat(1024 * 1024) }));
+        let too_large = |result: Result<Item>| matches!(result, Err(AppError::PayloadTooLarge(_)));
+
+        for service in services {
+            let item = service.create_item("Small".to_string(), None, Vec::new(), None).await.unwrap();
+
+            assert!(too_large(service.create_item("Big".to_string(), None, Vec::new(), blob()).await));
+            assert!(too_large(service.update_item(item.id, "Big".to_string(), None, Vec::new(), blob(), None).await));
+            let patch = HashMap::from([("metadata".to_string(), blob().unwrap())]);
+            assert!(too_large(service.patch_item(item.id, patch, None).await));
+            if service.is_using_database() {
+                assert!(too_large(
+                    service.create_item_with_id(999, Utc::now(), "Big".to_string(), None, Vec::new(), blob()).await
+                ));
+            }
+
+            let mut deep = serde_json::json!(1);
+            for _ in 0..20 {
+                deep = serde_json::json!({ "nested": deep });
+            }
+            assert!(too_large(service.create_item("Deep".to_string(), None, Vec::new(), Some(deep)).await));
+
+            let stored = service.get_item(item.id).await.unwrap();
+            assert_eq!(stored.metadata, item.metadata);
+            assert_eq!(stored.version, item.version);
+        }
+    }
+
+    /// Walks every page of `service` sorted by `sort` and returns the ids
+    /// in the order seen.
+    async fn walk_pages(service: &ItemService, sort: &SortSpec) -> Vec<u64> {
+        let mut ids = Vec::new();
+        loop {
+            let page = service.list_items(Some(64), Some(ids.len()), Some(sort)).await.unwrap();
+            if page.is_empty() {
+                return ids;
+            }
+            ids.extend(page.iter().map(|item| item.id));
+        }
+    }
+
+    fn every_sort() -> Vec<SortSpec> {
+        ITEM_SORT
+            .names()
+            .into_iter()
+            .flat_map(|field| [SortSpec::by(field, SortOrder::Asc), SortSpec::by(field, SortOrder::Desc)])
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_every_sort_pages_each_item_exactly_once() {
+        const COUNT: u64 = 1000;
+        let temp_file = NamedTempFile::new().unwrap();
+        let pool = get_database_pool(&format!("sqlite:{}", temp_file.path().display())).await.unwrap();
+        run_migrations(pool.clone()).await.unwrap();
+        let service = ItemService::with_database(ItemRepository::new(pool), DataStore::new());
+        let created_at = Utc::now();
+        // Ids are inserted out of order and names repeat, so the requested
+        // field alone leaves most items tied.
+        for i in 0..COUNT {
+            let id = (i * 389) % COUNT + 1;
+            service
+                .create_item_with_id(id, created_at, format!("item {}", i % 7), None, Vec::new(), None)
+                .await
+                .unwrap();
+        }
+        let mut items = service.list_items(Some(COUNT as usize), None, None).await.unwrap();
+        assert_eq!(items.len(), COUNT as usize);
+
+        for sort in every_sort() {
+            let ids = walk_pages(&service, &sort).await;
+            let unique: std::collections::HashSet<_> = ids.iter().collect();
+            assert_eq!((ids.len(), unique.len()), (COUNT as usize, COUNT as usize), "sort={}", sort);
+
+            items.sort_by(|a, b| compare_items(&sort, a, b));
+            let expected: Vec<u64> = items.iter().map(|item| item.id).collect();
+            assert_eq!(ids, expected, "the memory store orders sort={} like the database", sort);
+        }
+
+        let memory = ItemService::with_memory_store(DataStore::empty());
+        for i in 0..COUNT {
+            memory.create_item(format!("item {}", i % 7), None, Vec::new(), None).await.unwrap();
+        }
+        for sort in every_sort() {
+            let ids = walk_pages(&memory, &sort).await;
+            let unique: std::collections::HashSet<_> = ids.iter().collect();
+            assert_eq!((ids.len(), unique.len()), (COUNT as usize, COUNT as usize), "sort={}", sort);
+        }
+    }
+
+    fn unique_names(scope: crate::config::NameScope, case_sensitive: bool) -> ItemConfig {
+        ItemConfig { unique_names: scope, unique_names_case_sensitive: case_sensitive, ..ItemConfig::default() }
+    }
+
+    fn conflicting_id<T: std::fmt::Debug>(result: Result<T>) -> u64 {
+        match result {
+            Err(AppError::NameConflict(conflict)) => conflict.item_id,
+            other => panic!("expected a name conflict, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unique_names_trim_and_fold_ascii_case() {
+        use crate::config::NameScope;
+
+        let temp_file = NamedTempFile::new().unwrap();
+        let pool = get_database_pool(&format!("sqlite:{}", temp_file.path().display())).await.unwrap();
+        run_migrations(pool.clone()).await.unwrap();
+        let database = ItemService::with_database(ItemRepository::new(pool), DataStore::empty());
+        let memory = ItemService::with_memory_store(DataStore::empty());
+
+        for service in [database, memory] {
+            let service = service.with_item_config(&unique_names(NameScope::Global, false));
+            assert!(service.ensure_name_index().await.unwrap());
+
+            let lamp = service.create_item("  Desk Lamp ".to_string(), None, vec![], None).await.unwrap();
+            assert_eq!(lamp.name, "Desk Lamp");
+            let again = service.create_item("desk lamp\t".to_string(), None, vec![], None).await;
+            assert_eq!(conflicting_id(again), lamp.id);
+
+            // Only ASCII letters are folded.
+            service.create_item("Ärger".to_string(), None, vec![], None).await.unwrap();
+            service.create_item("ärger".to_string(), None, vec![], None).await.unwrap();
+
+            let other = service.create_item("Chair".to_string(), None, vec![], None).await.unwrap();
+            let renamed = service.update_item(other.id, "DESK LAMP".to_string(), None, vec![], None, None).await;
+            assert_eq!(conflicting_id(renamed), lamp.id);
+            let patch = HashMap::from([("name".to_string(), serde_json::json!(" Desk lamp"))]);
+            assert_eq!(conflicting_id(service.patch_item(other.id, patch, None).await), lamp.id);
+
+            // An item keeps its own name, and the name is free once it is
+            // in the trash.
+            service.update_item(lamp.id, "desk LAMP".to_string(), None, vec![], None, None).await.unwrap();
+            service.delete_item(lamp.id).await.unwrap();
+            service.update_item(other.id, "Desk Lamp".to_string(), None, vec![], None, None).await.unwrap();
+            assert!(matches!(service.restore_item(lamp.id).await, Err(AppError::NameConflict(_) | AppError::Conflict(_))));
+
+            let exact = service.clone().with_item_config(&unique_names(NameScope::Global, true));
+            assert!(exact.ensure_name_index().await.unwrap());
+            exact.create_item("desk lamp".to_string(), None, vec![], None).await.unwrap();
+            let duplicate = exact.create_item("Desk Lamp".to_string(), None, vec![], None).await;
+            assert_eq!(conflicting_id(duplicate), other.id);
+
+            let off = service.clone().with_item_config(&ItemConfig::default());
+            assert!(off.ensure_name_index().await.unwrap());
+            let repeated = off.create_item(" Desk Lamp ".to_string(), None, vec![], None).await.unwrap();
+            assert_eq!(repeated.name, " Desk Lamp ");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_duplicate_names_let_exactly_one_win() {
+        use crate::config::NameScope;
+
+        // Tasks interleave between the name check and the insert on the one
+        // connection, so the index has to catch what the check lets by.
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        run_migrations(pool.clone()).await.unwrap();
+        let database = ItemService::with_database(ItemRepository::new(pool), DataStore::empty());
+        let memory = ItemService::with_memory_store(DataStore::empty());
+
+        for service in [database, memory] {
+            let service = service.with_item_config(&unique_names(NameScope::Global, false));
+            assert!(service.ensure_name_index().await.unwrap());
+
+            let attempts = (0..16).map(|n| {
+                let service = service.clone();
+                let name = if n % 2 == 0 { "Shared name" } else { "SHARED NAME" };
+                tokio::spawn(async move { service.create_item(name.to_string(), None, vec![], None).await })
+            });
+            let results = futures_util::future::join_all(attempts).await;
+
+            let mut winners = Vec::new();
+            let mut conflicts = Vec::new();
+            for result in results {
+                match result.unwrap() {
+                    Ok(item) => winners.push(item),
+                    other => conflicts.push(conflicting_id(other)),
+                }
+            }
+            assert_eq!(winners.len(), 1);
+            assert_eq!(conflicts.len(), 15);
+            assert!(conflicts.iter().all(|id| *id == winners[0].id));
+            assert!(service.duplicate_names().await.unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unique_names_per_namespace_and_existing_duplicates() {
+        use crate::config::NameScope;
+
+        let temp_file = NamedTempFile::new().unwrap();
+        let pool = get_database_pool(&format!("sqlite:{}", temp_file.path().display())).await.unwrap();
+        run_migrations(pool.clone()).await.unwrap();
+        let service = ItemService::with_database(ItemRepository::new(pool), DataStore::empty());
+
+        let first = service.create_item("Report".to_string(), None, vec![], None).await.unwrap();
+        let second = service.create_item("report".to_string(), None, vec![], None).await.unwrap();
+        let acme = crate::tenancy::scope(
+            "acme".to_string(),
+            service.create_item("Report".to_string(), None, vec![], None),
+        )
+        .await
+        .unwrap();
+
+        // Switching the rule on over duplicates leaves the index out and
+        // reports them, but still refuses new ones.
+        let per_namespace = service.clone().with_item_config(&unique_names(NameScope::Namespace, false));
+        assert!(!per_namespace.ensure_name_index().await.unwrap());
+        let duplicates = per_namespace.duplicate_names().await.unwrap();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].namespace.as_deref(), Some(crate::tenancy::DEFAULT_NAMESPACE));
+        assert_eq!(duplicates[0].item_ids, vec![first.id, second.id]);
+        let third = per_namespace.create_item("REPORT".to_string(), None, vec![], None).await;
+        assert_eq!(conflicting_id(third), first.id);
+
+        per_namespace.delete_item(second.id).await.unwrap();
+        assert!(per_namespace.ensure_name_index().await.unwrap());
+        assert!(per_namespace.duplicate_names().await.unwrap().is_empty());
+        let other_tenant = crate::tenancy::scope(
+            "acme".to_string(),
+            per_namespace.create_item("Summary".to_string(), None, vec![], None),
+        )
+        .await
+        .unwrap();
+        crate::tenancy::scope("beta".to_string(), per_namespace.create_item("Report".to_string(), None, vec![], None))
+            .await
+            .unwrap();
+        let renamed = crate::tenancy::scope(
+            "acme".to_string(),
+            per_namespace.update_item(other_tenant.id, "report".to_string(), None, vec![], None, None),
+        )
+        .await;
+        assert_eq!(conflicting_id(renamed), acme.id);
+
+        let global = service.clone().with_item_config(&unique_names(NameScope::Global, false));
+        assert!(!global.ensure_name_index().await.unwrap());
+        let duplicates = global.duplicate_names().await.unwrap();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].namespace, None);
+        assert_eq!(duplicates[0].item_ids.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_failure_after_the_first_write_leaves_nothing_behind() {
+        // One connection, so a query that escaped the transaction would
+        // wait on it forever.
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        run_migrations(pool.clone()).await.unwrap();
+        let database = ItemService::with_database(ItemRepository::new(pool), DataStore::empty());
+        let memory = ItemService::with_memory_store(DataStore::empty());
+
+        for service in [database, memory] {
+            let kept = service.create_item("Kept".to_string(), None, vec!["kept".to_string()], None).await.unwrap();
+            let changes = service.changes_since(0, 100).await.unwrap().changes.len();
+
+            let mut created = None;
+            let failed = service
+                .transaction(async {
+                    let item = service.create_item("Staged".to_string(), None, vec!["staged".to_string()], None).await?;
+                    created = Some(item.id);
+                    let patch = HashMap::from([("description".to_string(), serde_json::json!("Patched"))]);
+                    service.patch_item(item.id, patch, Some(1)).await?;
+                    service.update_item(kept.id, "Renamed".to_string(), None, vec![], None, Some(1)).await?;
+                    // Writes made so far are visible inside the transaction.
+                    assert_eq!(service.get_item(item.id).await?.description.as_deref(), Some("Patched"));
+                    service.delete_item(kept.id).await?;
+                    Err::<(), _>(AppError::BadRequest("Forced failure".to_string()))
+                })
+                .await;
+            assert!(matches!(failed, Err(AppError::BadRequest(_))), "{:?}", failed);
+
+            let items = service.get_items(None, None).await.unwrap();
+            assert_eq!(items.len(), 1, "{} kept {:?}", service.data_source(), items);
+            assert_eq!(items[0].name, "Kept");
+            assert_eq!(items[0].version, 1);
+            assert!(matches!(service.get_item(created.unwrap()).await, Err(AppError::NotFound(_))));
+            assert_eq!(service.count_by_tag("staged").await.unwrap(), 0);
+            assert_eq!(service.changes_since(0, 100).await.unwrap().changes.len(), changes);
+
+            // The same work, succeeding, keeps everything; a transaction
+            // opened inside it joins it.
+            let item = service
+                .transaction(async {
+                    let item = service.create_item("Committed".to_string(), None, vec!["staged".to_string()], None).await?;
+                    service
+                        .transaction(service.update_item(kept.id, "Renamed".to_string(), None, vec![], None, Some(1)))
+                        .await?;
+                    Ok(item)
+                })
+                .await
+                .unwrap();
+            assert_eq!(service.get_item(item.id).await.unwrap().name, "Committed");
+            assert_eq!(service.get_item(kept.id).await.unwrap().version, 2);
+            assert_eq!(service.count_by_tag("staged").await.unwrap(), 1);
+            assert_eq!(service.changes_since(0, 100).await.unwrap().changes.len(), changes + 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_staged_writes_are_refused_when_the_item_changed_meanwhile() {
+        let service = ItemService::with_memory_store(DataStore::empty());
+        let item = service.create_item("Contended".to_string(), None, vec![], None).await.unwrap();
+
+        let result = service
+            .transaction(async {
+                service.update_item(item.id, "Mine".to_string(), None, vec![], None, None).await?;
+                // Outside the transaction, as another request would be.
+                let other = service.clone();
+                tokio::spawn(async move { other.update_item(item.id, "Theirs".to_string(), None, vec![], None, None).await })
+                    .await
+                    .unwrap()?;
+                service.create_item("Alongside".to_string(), None, vec![], None).await
+            })
+            .await;
+
+        assert!(matches!(result, Err(AppError::Conflict(_))), "{:?}", result);
+        let items = service.get_items(None, None).await.unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].name, "Theirs");
+    }
+}
@@ -0,0 +1,406 @@
+//! Dry run and import of a [`Snapshot`]
+
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::sqlite::SqliteConnection;
+use sqlx::SqlitePool;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use tokio::sync::watch;
+
+use super::rows::{self, TableRow};
+use super::{Manifest, Snapshot, SnapshotCounts, Table, FORMAT_VERSION, UNUSABLE_PASSWORD_HASH};
+use crate::config::{ItemSchemaConfig, NameScope};
+use crate::error::{AppError, Result};
+use crate::item_limits::MetadataLimits;
+use crate::item_names::NameRule;
+use crate::validation::ItemValidator;
+
+/// What a dry run found. An archive can be imported only when both
+/// `errors` and `conflicts` are empty.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportReport {
+    pub importable: bool,
+    pub manifest: Option<Manifest>,
+    pub schema_version: i64,
+    pub counts: SnapshotCounts,
+    /// Archive rows that collide with data already in this instance.
+    pub conflicts: Vec<ImportConflict>,
+    /// Problems with the archive itself, such as a schema mismatch.
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ImportConflict {
+    pub table: &'static str,
+    pub key: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct ImportProgress {
+    /// Table being written.
+    pub stage: String,
+    pub processed: usize,
+    pub total: usize,
+}
+
+/// Archive user ids mapped to the ids they have in this instance.
+type UserIds = HashMap<i64, i64>;
+
+/// Highest migration applied to the database `conn` belongs to.
+pub(super) async fn schema_version(conn: &mut SqliteConnection) -> Result<i64> {
+    let version: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM _migrations")
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(version.unwrap_or(0))
+}
+
+/// Checks `snapshot` against the database, the item schema, the limits
+/// on metadata and the item name rule. Users whose
+/// username and email both match an existing account are taken to be that
+/// account; they are not imported again and their rows refer to the
+/// existing id.
+pub(super) async fn check(
+    conn: &mut SqliteConnection,
+    snapshot: &Snapshot,
+    item_schema: &ItemSchemaConfig,
+    metadata_limits: &MetadataLimits,
+    names: &NameRule,
+) -> Result<(ImportReport, UserIds)> {
+    let manifest = &snapshot.manifest;
+    let mut report = ImportReport {
+        manifest: Some(manifest.clone()),
+        schema_version: schema_version(conn).await?,
+        counts: snapshot.counts(),
+        ..ImportReport::default()
+    };
+    let mut existing_users = UserIds::new();
+
+    if manifest.format_version != FORMAT_VERSION {
+        report.errors.push(format!(
+            "Archive format version {} is not supported; this server reads version {}",
+            manifest.format_version, FORMAT_VERSION
+        ));
+        return Ok((report, existing_users));
+    }
+
+    let (archive, local) = (manifest.schema_version, report.schema_version);
+    if archive > local {
+        report.errors.push(format!(
+            "Archive was exported at schema version {} but this instance is at {}; upgrade this instance to a release that applies migration {} and import again",
+            archive, local, archive
+        ));
+    } else if archive < local {
+        report.errors.push(format!(
+            "Archive was exported at schema version {} but this instance is at {}; upgrade the source instance to schema version {} and export again, or import into a release at schema version {} and upgrade afterwards",
+            archive, local, local, archive
+        ));
+    }
+    if !report.errors.is_empty() {
+        return Ok((report, existing_users));
+    }
+
+    for (table, listed, held) in [
+        (Table::Users, manifest.counts.users, report.counts.users),
+        (Table::Items, manifest.counts.items, report.counts.items),
+        (Table::Files, manifest.counts.files, report.counts.files),
+        (Table::Jobs, manifest.counts.jobs, report.counts.jobs),
+    ] {
+        if listed != held {
+            report.errors.push(format!(
+                "Manifest lists {} {} but the archive holds {}; the archive may be truncated",
+                listed,
+                table.name(),
+                held
+            ));
+        }
+    }
+
+    for table in Table::ALL {
+        let columns = rows::columns(conn, table.name()).await?;
+        let unknown: BTreeSet<&String> = snapshot
+            .rows(table)
+            .iter()
+            .flat_map(|row| row.keys())
+            .filter(|column| !columns.contains(*column))
+            .collect();
+        for column in unknown {
+            report
+                .errors
+                .push(format!("{}.{} is not a column in this instance", table.name(), column));
+        }
+    }
+    if !report.errors.is_empty() {
+        return Ok((report, existing_users));
+    }
+
+    for user in &snapshot.users {
+        let id = user.get("id").and_then(Value::as_i64);
+        let username = user.get("username").and_then(Value::as_str).unwrap_or_default();
+        let email = user.get("email").and_then(Value::as_str).unwrap_or_default();
+
+        let by_username: Option<(i64, String)> = sqlx::query_as("SELECT id, email FROM users WHERE username = ?")
+            .bind(username)
+            .fetch_optional(&mut *conn)
+            .await?;
+        let conflict = match by_username {
+            Some((existing_id, existing_email)) if existing_email == email => {
+                if let Some(id) = id {
+                    existing_users.insert(id, existing_id);
+                }
+                None
+            }
+            Some(_) => Some(format!("Username {} belongs to an account with a different email here", username)),
+            None => rows::exists(conn, "users", "email", &Value::from(email))
+                .await?
+                .then(|| format!("Email {} is used by another account here", email)),
+        };
+        if let Some(reason) = conflict {
+            report.conflicts.push(ImportConflict {
+                table: "users",
+                key: username.to_string(),
+                reason,
+            });
+        }
+    }
+
+    let archive_users: BTreeSet<i64> = snapshot
+        .users
+        .iter()
+        .filter_map(|user| user.get("id").and_then(Value::as_i64))
+        .collect();
+
+    for (table, key) in [(Table::Items, "id"), (Table::Files, "id"), (Table::Jobs, "id")] {
+        for row in snapshot.rows(table) {
+            let value = row.get(key).cloned().unwrap_or(Value::Null);
+            if rows::exists(conn, table.name(), key, &value).await? {
+                report.conflicts.push(ImportConflict {
+                    table: table.name(),
+                    key: display(&value),
+                    reason: format!("{} {} already exists here", singular(table), display(&value)),
+                });
+            }
+        }
+    }
+
+    if names.is_enabled() {
+        check_names(conn, snapshot, names, &mut report).await?;
+    }
+
+    for item in &snapshot.items {
+        let metadata = match item.get("metadata") {
+            Some(Value::String(text)) => serde_json::from_str(text).ok(),
+            Some(Value::Null) | None => None,
+            Some(value) => Some(value.clone()),
+        };
+        if let Some(Err(error)) = metadata.as_ref().map(|metadata| metadata_limits.check(metadata)) {
+            report.errors.push(format!(
+                "Item {} metadata: {}",
+                display(item.get("id").unwrap_or(&Value::Null)),
+                error
+            ));
+            continue;
+        }
+        let result = ItemValidator::validate_metadata_schema(item_schema, metadata.as_ref());
+        let mut errors: Vec<_> = result.errors.into_iter().collect();
+        errors.sort();
+        for (field, messages) in errors {
+            report.errors.push(format!(
+                "Item {} {}: {}",
+                display(item.get("id").unwrap_or(&Value::Null)),
+                field,
+                messages.join("; ")
+            ));
+        }
+    }
+
+    for file in &snapshot.files {
+        let id = display(file.get("id").unwrap_or(&Value::Null));
+        if snapshot.blob(&id).is_none() {
+            report.errors.push(format!("File {} has no data in the archive", id));
+        }
+        if stored_filename(file).is_none() {
+            report.errors.push(format!("File {} has an invalid stored filename", id));
+        }
+        match file.get("uploaded_by").and_then(Value::as_i64) {
+            Some(user) if archive_users.contains(&user) => {}
+            _ => report
+                .errors
+                .push(format!("File {} was uploaded by a user who is not in the archive", id)),
+        }
+    }
+
+    report.importable = report.errors.is_empty() && report.conflicts.is_empty();
+    Ok((report, existing_users))
+}
+
+/// Writes `snapshot` into the database and `storage_path` in a single
+/// transaction, checking it again first. Stored files written before a
+/// failure are removed.
+pub(super) async fn apply(
+    pool: &SqlitePool,
+    storage_path: &Path,
+    snapshot: &Snapshot,
+    item_schema: &ItemSchemaConfig,
+    metadata_limits: &MetadataLimits,
+    names: &NameRule,
+    progress: &watch::Sender<ImportProgress>,
+) -> Result<ImportReport> {
+    let mut tx = pool.begin().await?;
+    let (report, mut user_ids) = check(&mut tx, snapshot, item_schema, metadata_limits, names).await?;
+    if !report.importable {
+        let problems: Vec<String> = report
+            .errors
+            .iter()
+            .cloned()
+            .chain(report.conflicts.iter().map(|conflict| conflict.reason.clone()))
+            .collect();
+        return Err(AppError::BadRequest(format!(
+            "Snapshot cannot be imported: {}",
+            problems.join("; ")
+        )));
+    }
+
+    let total = snapshot.users.len() + snapshot.items.len() + snapshot.files.len() + snapshot.jobs.len();
+    let mut processed = 0;
+    let mut written = Vec::new();
+
+    let result = async {
+        for table in Table::ALL {
+            for row in snapshot.rows(table) {
+                let mut row = row.clone();
+                match table {
+                    Table::Users => {
+                        let archive_id = row.remove("id").and_then(|id| id.as_i64());
+                        if archive_id.is_some_and(|id| user_ids.contains_key(&id)) {
+                            continue;
+                        }
+                        row.entry("password_hash")
+                            .or_insert_with(|| Value::from(UNUSABLE_PASSWORD_HASH));
+                        let id = rows::insert(&mut tx, table.name(), &row).await?;
+                        if let Some(archive_id) = archive_id {
+                            user_ids.insert(archive_id, id);
+                        }
+                    }
+                    Table::Items => {
+                        remap_user(&mut row, "created_by", &user_ids, Value::Null);
+                        rows::insert(&mut tx, table.name(), &row).await?;
+                    }
+                    Table::Files => {
+                        remap_user(&mut row, "uploaded_by", &user_ids, Value::Null);
+                        let id = display(row.get("id").unwrap_or(&Value::Null));
+                        let filename = stored_filename(&row).ok_or(AppError::InternalServerError)?;
+                        let path = storage_path.join(filename);
+                        tokio::fs::create_dir_all(storage_path).await?;
+                        tokio::fs::write(&path, snapshot.blob(&id).unwrap_or_default()).await?;
+                        written.push(path.clone());
+                        row.insert("path".to_string(), Value::from(path.to_string_lossy().into_owned()));
+                        rows::insert(&mut tx, table.name(), &row).await?;
+                    }
+                    Table::Jobs => {
+                        rows::insert(&mut tx, table.name(), &row).await?;
+                    }
+                }
+                processed += 1;
+                progress.send_replace(ImportProgress {
+                    stage: table.name().to_string(),
+                    processed,
+                    total,
+                });
+            }
+        }
+        Ok::<_, AppError>(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => {
+            tx.commit().await?;
+            Ok(report)
+        }
+        Err(e) => {
+            for path in written {
+                let _ = tokio::fs::remove_file(path).await;
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Reports the archive's live items whose name is taken under `names`,
+/// by an item here or an earlier item in the archive.
+async fn check_names(
+    conn: &mut SqliteConnection,
+    snapshot: &Snapshot,
+    names: &NameRule,
+    report: &mut ImportReport,
+) -> Result<()> {
+    let mut archived: HashMap<(Option<String>, String), String> = HashMap::new();
+    let live = snapshot
+        .items
+        .iter()
+        .filter(|item| item.get("deleted_at").is_none_or(Value::is_null));
+    for item in live {
+        let id = display(item.get("id").unwrap_or(&Value::Null));
+        let name = item.get("name").and_then(Value::as_str).unwrap_or_default();
+        let namespace = item
+            .get("namespace")
+            .and_then(Value::as_str)
+            .unwrap_or(crate::tenancy::DEFAULT_NAMESPACE);
+
+        let taken: Option<i64> = sqlx::query_scalar(&names.lookup_query())
+            .bind(name)
+            .bind(None::<i64>)
+            .bind(namespace)
+            .fetch_optional(&mut *conn)
+            .await?;
+        let scope = (names.scope == NameScope::Namespace).then(|| namespace.to_string());
+        let reason = match (taken, archived.get(&(scope.clone(), names.key(name)))) {
+            (Some(existing), _) => format!("Item {} is named {:?} like item {} here", id, name, existing),
+            (None, Some(earlier)) => format!("Item {} is named {:?} like item {} in the archive", id, name, earlier),
+            (None, None) => {
+                archived.insert((scope, names.key(name)), id);
+                continue;
+            }
+        };
+        report.conflicts.push(ImportConflict {
+            table: "items",
+            key: id,
+            reason,
+        });
+    }
+    Ok(())
+}
+
+/// Points `column` at the importing instance's id for the same user.
+fn remap_user(row: &mut TableRow, column: &str, user_ids: &UserIds, unknown: Value) {
+    if let Some(value) = row.get_mut(column) {
+        if let Some(archive_id) = value.as_i64() {
+            *value = user_ids.get(&archive_id).map(|id| Value::from(*id)).unwrap_or(unknown);
+        }
+    }
+}
+
+/// The file's stored name, refused unless it is a single path component.
+fn stored_filename(file: &TableRow) -> Option<PathBuf> {
+    let name = file.get("filename")?.as_str()?;
+    let path = Path::new(name);
+    (path.file_name()? == std::ffi::OsStr::new(name) && !name.starts_with('.')).then(|| path.to_path_buf())
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn singular(table: Table) -> &'static str {
+    match table {
+        Table::Users => "User",
+        Table::Items => "Item",
+        Table::Files => "File",
+        Table::Jobs => "Job",
+    }
+}
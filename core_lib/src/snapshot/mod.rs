@@ -0,0 +1,752 @@
+//! Whole-instance export and import, for cloning one environment into another
+//!
+//! A snapshot is a tar archive holding:
+//!
+//! - `manifest.json`: archive format, database schema version, row counts
+//!   and whether password hashes are included
+//! - `users.ndjson`, `items.ndjson`, `files.ndjson`, `jobs.ndjson`: one row
+//!   per line, across every namespace
+//! - `blobs/<file id>`: the stored data of each file
+//!
+//! Users are exported without password hashes unless asked for, and an
+//! account imported without one cannot log in. Only finished jobs are
+//! exported, so an import never starts work. Imports go through a dry run
+//! first ([`SnapshotService::inspect`]) and then run as a `SnapshotImport`
+//! job.
+//!
+//! Accounts can also be moved on their own, password hashes included, as
+//! an encrypted and signed bundle; see [`users`].
+
+pub mod import;
+pub mod rows;
+pub mod users;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+use tokio::sync::watch;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::cache::CacheManager;
+use crate::clock::{SharedClock, SystemClock};
+use crate::error::{AppError, Result};
+use crate::item_limits::MetadataLimits;
+use crate::item_names::NameRule;
+use crate::item_schema::ItemSchema;
+use rows::TableRow;
+
+pub use import::{ImportConflict, ImportProgress, ImportReport};
+pub use users::{OnConflict, UserImportOptions, UserImportReport};
+
+/// Layout version of the archive itself, independent of the schema.
+pub const FORMAT_VERSION: u32 = 1;
+
+const MANIFEST_ENTRY: &str = "manifest.json";
+const BLOB_DIR: &str = "blobs";
+
+/// Placed in `users.password_hash` for accounts imported without one. It
+/// is not a valid hash, so no password matches it.
+pub const UNUSABLE_PASSWORD_HASH: &str = "!";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Manifest {
+    pub format_version: u32,
+    /// Highest migration applied to the exporting database.
+    pub schema_version: i64,
+    pub app_version: String,
+    pub created_at: DateTime<Utc>,
+    pub includes_password_hashes: bool,
+    pub counts: SnapshotCounts,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SnapshotCounts {
+    pub users: usize,
+    pub items: usize,
+    pub files: usize,
+    pub jobs: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    pub include_password_hashes: bool,
+}
+
+/// The tables a snapshot carries, in the order they are imported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Table {
+    Users,
+    Items,
+    Files,
+    Jobs,
+}
+
+impl Table {
+    pub const ALL: [Table; 4] = [Table::Users, Table::Items, Table::Files, Table::Jobs];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Table::Users => "users",
+            Table::Items => "items",
+            Table::Files => "files",
+            Table::Jobs => "jobs",
+        }
+    }
+
+    fn entry(self) -> String {
+        format!("{}.ndjson", self.name())
+    }
+
+    fn export_query(self) -> &'static str {
+        match self {
+            Table::Users => "SELECT * FROM users ORDER BY id",
+            Table::Items => "SELECT * FROM items ORDER BY id",
+            Table::Files => "SELECT * FROM files ORDER BY created_at, id",
+            Table::Jobs => {
+                "SELECT * FROM jobs WHERE status IN ('Completed', 'Failed', 'Cancelled') ORDER BY created_at, id"
+            }
+        }
+    }
+}
+
+/// A parsed archive.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub manifest: Manifest,
+    pub users: Vec<TableRow>,
+    pub items: Vec<TableRow>,
+    pub files: Vec<TableRow>,
+    pub jobs: Vec<TableRow>,
+    blobs: HashMap<String, Vec<u8>>,
+}
+
+impl Snapshot {
+    pub fn rows(&self, table: Table) -> &[TableRow] {
+        match table {
+            Table::Users => &self.users,
+            Table::Items => &self.items,
+            Table::Files => &self.files,
+            Table::Jobs => &self.jobs,
+        }
+    }
+
+    fn rows_mut(&mut self, table: Table) -> &mut Vec<TableRow> {
+        match table {
+            Table::Users => &mut self.users,
+            Table::Items => &mut self.items,
+            Table::Files => &mut self.files,
+            Table::Jobs => &mut self.jobs,
+        }
+    }
+
+    pub fn counts(&self) -> SnapshotCounts {
+        SnapshotCounts {
+            users: self.users.len(),
+            items: self.items.len(),
+            files: self.files.len(),
+            jobs: self.jobs.len(),
+        }
+    }
+
+    pub fn blob(&self, file_id: &str) -> Option<&[u8]> {
+        self.blobs.get(file_id).map(Vec::as_slice)
+    }
+
+    /// Reads an archive written by [`SnapshotService::export`].
+    pub fn parse(archive: &[u8]) -> Result<Self> {
+        let invalid = |reason: String| AppError::BadRequest(format!("Invalid snapshot archive: {}", reason));
+
+        let mut manifest = None;
+        let mut tables: Vec<(Table, Vec<TableRow>)> = Vec::new();
+        let mut blobs = HashMap::new();
+
+        let mut entries = tar::Archive::new(archive);
+        for entry in entries.entries().map_err(|e| invalid(e.to_string()))? {
+            let mut entry = entry.map_err(|e| invalid(e.to_string()))?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = entry
+                .path()
+                .map_err(|e| invalid(e.to_string()))?
+                .to_string_lossy()
+                .into_owned();
+            let mut data = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut data).map_err(|e| invalid(e.to_string()))?;
+
+            if name == MANIFEST_ENTRY {
+                manifest = Some(serde_json::from_slice(&data).map_err(|e| invalid(format!("{}: {}", name, e)))?);
+            } else if let Some(file_id) = name.strip_prefix(BLOB_DIR).and_then(|rest| rest.strip_prefix('/')) {
+                blobs.insert(file_id.to_string(), data);
+            } else if let Some(table) = Table::ALL.into_iter().find(|table| table.entry() == name) {
+                let text = String::from_utf8(data).map_err(|_| invalid(format!("{} is not UTF-8", name)))?;
+                let rows = text
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(serde_json::from_str::<TableRow>)
+                    .collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(|e| invalid(format!("{}: {}", name, e)))?;
+                tables.push((table, rows));
+            } else {
+                return Err(invalid(format!("unexpected entry {}", name)));
+            }
+        }
+
+        let manifest = manifest.ok_or_else(|| invalid(format!("{} is missing", MANIFEST_ENTRY)))?;
+        let mut snapshot = Self {
+            manifest,
+            users: Vec::new(),
+            items: Vec::new(),
+            files: Vec::new(),
+            jobs: Vec::new(),
+            blobs,
+        };
+        for (table, rows) in tables {
+            *snapshot.rows_mut(table) = rows;
+        }
+        Ok(snapshot)
+    }
+
+    fn write(&self) -> Result<Vec<u8>> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mtime = self.manifest.created_at.timestamp().max(0) as u64;
+        let mut append = |name: &str, data: &[u8]| -> Result<()> {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(mtime);
+            builder.append_data(&mut header, name, data)?;
+            Ok(())
+        };
+
+        append(MANIFEST_ENTRY, &serde_json::to_vec_pretty(&self.manifest)?)?;
+        for table in Table::ALL {
+            let mut lines = Vec::new();
+            for row in self.rows(table) {
+                serde_json::to_writer(&mut lines, row)?;
+                lines.push(b'\n');
+            }
+            append(&table.entry(), &lines)?;
+        }
+        let mut blob_ids: Vec<&String> = self.blobs.keys().collect();
+        blob_ids.sort();
+        for file_id in blob_ids {
+            append(&format!("{}/{}", BLOB_DIR, file_id), &self.blobs[file_id])?;
+        }
+
+        Ok(builder.into_inner()?)
+    }
+}
+
+/// Exports this instance's database and stored files, and imports archives
+/// produced by another instance.
+#[derive(Clone)]
+pub struct SnapshotService {
+    pool: SqlitePool,
+    storage_path: PathBuf,
+    staging_dir: PathBuf,
+    max_archive_bytes: usize,
+    cache_manager: Option<CacheManager>,
+    item_schema: ItemSchema,
+    metadata_limits: MetadataLimits,
+    names: NameRule,
+    clock: SharedClock,
+}
+
+impl SnapshotService {
+    /// Files are restored into `storage_path`; archives waiting for their
+    /// import job are kept in `staging_dir`.
+    pub fn new(pool: SqlitePool, storage_path: impl Into<PathBuf>, staging_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            pool,
+            storage_path: storage_path.into(),
+            staging_dir: staging_dir.into(),
+            max_archive_bytes: 256 * 1024 * 1024,
+            cache_manager: None,
+            item_schema: ItemSchema::default(),
+            metadata_limits: MetadataLimits::default(),
+            names: NameRule::default(),
+            clock: SystemClock::shared(),
+        }
+    }
+
+    pub fn with_max_archive_size(mut self, bytes: usize) -> Self {
+        self.max_archive_bytes = bytes;
+        self
+    }
+
+    /// Cache cleared once an import has been committed.
+    pub fn with_cache_manager(mut self, cache_manager: CacheManager) -> Self {
+        self.cache_manager = Some(cache_manager);
+        self
+    }
+
+    /// Schema that imported items' metadata must satisfy.
+    pub fn with_item_schema(mut self, item_schema: ItemSchema) -> Self {
+        self.item_schema = item_schema;
+        self
+    }
+
+    /// Limits that imported items' metadata must stay within.
+    pub fn with_metadata_limits(mut self, metadata_limits: MetadataLimits) -> Self {
+        self.metadata_limits = metadata_limits;
+        self
+    }
+
+    /// Names imported items must not repeat.
+    pub fn with_name_rule(mut self, names: NameRule) -> Self {
+        self.names = names;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn max_archive_bytes(&self) -> usize {
+        self.max_archive_bytes
+    }
+
+    /// Reads every exported table inside one transaction, so the archive is
+    /// a consistent point-in-time copy, then adds the stored file data.
+    pub async fn export(&self, options: ExportOptions) -> Result<(Manifest, Vec<u8>)> {
+        let mut tx = self.pool.begin().await?;
+        let schema_version = import::schema_version(&mut tx).await?;
+
+        let mut tables = Vec::new();
+        for table in Table::ALL {
+            tables.push(rows::fetch(&mut tx, table.export_query()).await?);
+        }
+        tx.rollback().await?;
+
+        let [mut users, items, mut files, jobs]: [Vec<TableRow>; 4] = tables
+            .try_into()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        if !options.include_password_hashes {
+            for user in &mut users {
+                user.remove("password_hash");
+            }
+        }
+
+        let mut blobs = HashMap::new();
+        for file in &mut files {
+            let id = file.get("id").and_then(|id| id.as_str()).unwrap_or_default().to_string();
+            // Where the data lives is specific to this instance.
+            let path = file.remove("path").and_then(|path| path.as_str().map(str::to_string));
+            let data = match path {
+                Some(path) => tokio::fs::read(&path).await.map_err(|e| {
+                    AppError::NotFound(format!("Stored data for file {} could not be read: {}", id, e))
+                })?,
+                None => return Err(AppError::NotFound(format!("File {} has no stored data", id))),
+            };
+            blobs.insert(id, data);
+        }
+
+        let mut snapshot = Snapshot {
+            manifest: Manifest {
+                format_version: FORMAT_VERSION,
+                schema_version,
+                app_version: env!("CARGO_PKG_VERSION").to_string(),
+                created_at: self.clock.now(),
+                includes_password_hashes: options.include_password_hashes,
+                counts: SnapshotCounts::default(),
+            },
+            users,
+            items,
+            files,
+            jobs,
+            blobs,
+        };
+        snapshot.manifest.counts = snapshot.counts();
+
+        let archive = snapshot.write()?;
+        info!(
+            "Exported snapshot at schema version {}: {} users, {} items, {} files, {} jobs ({} bytes)",
+            schema_version,
+            snapshot.users.len(),
+            snapshot.items.len(),
+            snapshot.files.len(),
+            snapshot.jobs.len(),
+            archive.len()
+        );
+        Ok((snapshot.manifest, archive))
+    }
+
+    /// The dry run: everything an import would check, without writing.
+    pub async fn inspect(&self, archive: &[u8]) -> Result<ImportReport> {
+        let snapshot = Snapshot::parse(archive)?;
+        let mut conn = self.pool.acquire().await?;
+        let (report, _) = import::check(&mut conn, &snapshot, &self.item_schema.current(), &self.metadata_limits, &self.names).await?;
+        Ok(report)
+    }
+
+    /// Keeps `archive` until an import job asks for it by the returned id.
+    pub async fn stage(&self, archive: &[u8]) -> Result<Uuid> {
+        let archive_id = Uuid::new_v4();
+        tokio::fs::create_dir_all(&self.staging_dir).await?;
+        tokio::fs::write(self.staged_path(archive_id), archive).await?;
+        Ok(archive_id)
+    }
+
+    /// Drops a staged archive that will not be imported.
+    pub async fn discard(&self, archive_id: Uuid) {
+        let _ = tokio::fs::remove_file(self.staged_path(archive_id)).await;
+    }
+
+    /// Imports a staged archive in one transaction, reporting progress as
+    /// rows are written. The staged copy is removed whatever the outcome.
+    pub async fn import_staged(
+        &self,
+        archive_id: Uuid,
+        progress: &watch::Sender<ImportProgress>,
+    ) -> Result<ImportReport> {
+        let path = self.staged_path(archive_id);
+        let archive = tokio::fs::read(&path)
+            .await
+            .map_err(|_| AppError::NotFound(format!("Staged snapshot {} not found", archive_id)))?;
+        self.discard(archive_id).await;
+
+        let snapshot = Snapshot::parse(&archive)?;
+        let report = import::apply(
+            &self.pool,
+            &self.storage_path,
+            &snapshot,
+            &self.item_schema.current(),
+            &self.metadata_limits,
+            &self.names,
+            progress,
+        )
+        .await?;
+
+        if let Some(cache_manager) = &self.cache_manager {
+            cache_manager.clear();
+        }
+        info!(
+            "Imported snapshot: {} users, {} items, {} files, {} jobs",
+            report.counts.users, report.counts.items, report.counts.files, report.counts.jobs
+        );
+        Ok(report)
+    }
+
+    /// Every account with its password hash, as a bundle encrypted and
+    /// signed under `passphrase`.
+    pub async fn export_users(&self, passphrase: &str) -> Result<(users::BundleHeader, Vec<u8>)> {
+        let accounts = users::fetch(&self.pool).await?;
+        let (header, bundle) = users::seal(&accounts, passphrase, self.clock.now())?;
+        info!("Exported {} user accounts ({} bytes)", header.users, bundle.len());
+        Ok((header, bundle))
+    }
+
+    /// Checks the signature of a bundle from [`export_users`](Self::export_users)
+    /// and adds its accounts, keeping their password hashes as they are.
+    pub async fn import_users(&self, bundle: &[u8], passphrase: &str, options: &UserImportOptions) -> Result<UserImportReport> {
+        let (_, accounts) = users::open(bundle, passphrase)?;
+        let report = users::insert(&self.pool, &accounts, options).await?;
+
+        if let Some(cache_manager) = &self.cache_manager {
+            cache_manager.clear();
+        }
+        info!(
+            "Imported {} user accounts, skipped {}",
+            report.imported.len(),
+            report.skipped.len()
+        );
+        Ok(report)
+    }
+
+    fn staged_path(&self, archive_id: Uuid) -> PathBuf {
+        self.staging_dir.join(format!("snapshot-{}.tar", archive_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::models::{CreateUserRequest, LoginRequest, UserRole};
+    use crate::files::FileUpload;
+    use crate::test_support::{test_app, TestApp, TEST_PASSWORD};
+
+
+    fn service(app: &TestApp) -> &SnapshotService {
+        app.state.snapshots.as_ref().unwrap()
+    }
+
+    async fn register(app: &TestApp, username: &str, email: &str) -> i64 {
+        let user = app
+            .state
+            .auth_service
+            .as_ref()
+            .unwrap()
+            .register_user(CreateUserRequest {
+                username: username.to_string(),
+                email: email.to_string(),
+                password: TEST_PASSWORD.to_string(),
+                role: Some(UserRole::User),
+            })
+            .await
+            .unwrap();
+        user.id as i64
+    }
+
+    /// Adds a user owning one item and one file, returning the file's id.
+    async fn seed(app: &TestApp) -> Uuid {
+        let alice = register(app, "alice", "alice@example.com").await;
+        let item = app
+            .state
+            .item_service
+            .create_item("Exported item".to_string(), None, vec!["snapshot".to_string()], None)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE items SET created_by = ? WHERE id = ?")
+            .bind(alice)
+            .bind(item.id as i64)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let file = app
+            .state
+            .file_manager
+            .as_ref()
+            .unwrap()
+            .store_file(FileUpload {
+                original_filename: "notes.txt".to_string(),
+                content_type: "text/plain".to_string(),
+                data: b"snapshot contents".to_vec(),
+                uploaded_by: alice as u64,
+                item_id: Some(item.id),
+            })
+            .await
+            .unwrap();
+        file.id
+    }
+
+    async fn empty_app() -> TestApp {
+        let app = test_app().await;
+        sqlx::query("DELETE FROM items").execute(&app.pool).await.unwrap();
+        app
+    }
+
+    async fn import(app: &TestApp, archive: &[u8]) -> Result<ImportReport> {
+        let archive_id = service(app).stage(archive).await?;
+        let (progress, _) = watch::channel(ImportProgress::default());
+        service(app).import_staged(archive_id, &progress).await
+    }
+
+    #[tokio::test]
+    async fn test_export_restores_into_an_empty_instance() {
+        let source = test_app().await;
+        let file_id = seed(&source).await;
+        let (manifest, archive) = service(&source).export(ExportOptions::default()).await.unwrap();
+
+        assert!(!manifest.includes_password_hashes);
+        assert_eq!(manifest.counts.items, 3);
+        assert_eq!(manifest.counts.files, 1);
+        let snapshot = Snapshot::parse(&archive).unwrap();
+        assert!(snapshot.users.iter().all(|user| !user.contains_key("password_hash")));
+        assert!(snapshot.files.iter().all(|file| !file.contains_key("path")));
+
+        let target = empty_app().await;
+        let report = service(&target).inspect(&archive).await.unwrap();
+        assert!(report.importable, "{:?}", report);
+
+        let imported = import(&target, &archive).await.unwrap();
+        assert_eq!(imported.counts, manifest.counts);
+
+        let alice: (i64, String) = sqlx::query_as("SELECT id, password_hash FROM users WHERE username = 'alice'")
+            .fetch_one(&target.pool)
+            .await
+            .unwrap();
+        assert_eq!(alice.1, UNUSABLE_PASSWORD_HASH);
+        let owner: Option<i64> = sqlx::query_scalar("SELECT created_by FROM items WHERE name = 'Exported item'")
+            .fetch_one(&target.pool)
+            .await
+            .unwrap();
+        assert_eq!(owner, Some(alice.0));
+
+        let (metadata, data) = target
+            .state
+            .file_manager
+            .as_ref()
+            .unwrap()
+            .get_file_data(file_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(data, b"snapshot contents");
+        assert_eq!(metadata.uploaded_by, alice.0 as u64);
+
+        let login = target
+            .state
+            .auth_service
+            .as_ref()
+            .unwrap()
+            .login(LoginRequest {
+                username: "alice".to_string(),
+                password: TEST_PASSWORD.to_string(),
+            })
+            .await;
+        assert!(login.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_password_hashes_are_exported_on_request() {
+        let source = test_app().await;
+        seed(&source).await;
+        let (manifest, archive) = service(&source)
+            .export(ExportOptions {
+                include_password_hashes: true,
+            })
+            .await
+            .unwrap();
+        assert!(manifest.includes_password_hashes);
+
+        let target = empty_app().await;
+        import(&target, &archive).await.unwrap();
+        let login = target
+            .state
+            .auth_service
+            .as_ref()
+            .unwrap()
+            .login(LoginRequest {
+                username: "alice".to_string(),
+                password: TEST_PASSWORD.to_string(),
+            })
+            .await;
+        assert!(login.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_reports_schema_mismatch() {
+        let source = test_app().await;
+        let (_, archive) = service(&source).export(ExportOptions::default()).await.unwrap();
+        let target = empty_app().await;
+
+        let mut newer = Snapshot::parse(&archive).unwrap();
+        newer.manifest.schema_version += 1;
+        let report = service(&target).inspect(&newer.write().unwrap()).await.unwrap();
+        assert!(!report.importable);
+        assert!(report.errors[0].contains("upgrade this instance"), "{:?}", report.errors);
+
+        let mut older = Snapshot::parse(&archive).unwrap();
+        older.manifest.schema_version -= 1;
+        let older = older.write().unwrap();
+        let report = service(&target).inspect(&older).await.unwrap();
+        assert!(!report.importable);
+        assert!(report.errors[0].contains("export again"), "{:?}", report.errors);
+
+        let err = import(&target, &older).await.unwrap_err();
+        assert!(matches!(err, AppError::BadRequest(_)));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_reports_conflicts() {
+        let source = test_app().await;
+        seed(&source).await;
+        let (_, archive) = service(&source).export(ExportOptions::default()).await.unwrap();
+
+        let target = test_app().await;
+        register(&target, "alice", "someone-else@example.com").await;
+        let report = service(&target).inspect(&archive).await.unwrap();
+
+        assert!(!report.importable);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        let keys: Vec<(&str, &str)> = report
+            .conflicts
+            .iter()
+            .map(|conflict| (conflict.table, conflict.key.as_str()))
+            .collect();
+        assert_eq!(keys, vec![("users", "alice"), ("items", "1"), ("items", "2")]);
+
+        assert!(import(&target, &archive).await.is_err());
+        let items: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM items")
+            .fetch_one(&target.pool)
+            .await
+            .unwrap();
+        assert_eq!(items, 2);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_reports_names_taken_under_the_name_rule() {
+        let source = test_app().await;
+        for name in ["Lamp", "lamp", "Chair"] {
+            source.state.item_service.create_item(name.to_string(), None, vec![], None).await.unwrap();
+        }
+        let (_, archive) = service(&source).export(ExportOptions::default()).await.unwrap();
+
+        let storage = tempfile::TempDir::new().unwrap();
+        let mut config = crate::test_support::test_config(storage.path());
+        config.items.unique_names = crate::config::NameScope::Global;
+        let target = crate::test_support::test_app_with_config(config, storage).await;
+        sqlx::query("DELETE FROM items").execute(&target.pool).await.unwrap();
+        let chair = target.state.item_service.create_item("CHAIR".to_string(), None, vec![], None).await.unwrap();
+        // Out of the way of the archive's ids.
+        sqlx::query("UPDATE items SET id = 100 WHERE id = ?")
+            .bind(chair.id as i64)
+            .execute(&target.pool)
+            .await
+            .unwrap();
+
+        let report = service(&target).inspect(&archive).await.unwrap();
+        assert!(!report.importable);
+        let reasons: Vec<&str> = report.conflicts.iter().map(|conflict| conflict.reason.as_str()).collect();
+        assert_eq!(reasons.len(), 2, "{:?}", reasons);
+        assert!(reasons[0].contains(r#"named "lamp" like item"#) && reasons[0].ends_with("in the archive"));
+        assert!(reasons[1].ends_with(r#"named "Chair" like item 100 here"#));
+        assert!(import(&target, &archive).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_reports_items_not_matching_the_schema() {
+        use crate::config::{ItemSchemaConfig, MetadataFieldSchema, MetadataFieldType};
+
+        let source = test_app().await;
+        let (_, archive) = service(&source).export(ExportOptions::default()).await.unwrap();
+
+        let target = empty_app().await;
+        target.state.item_schema.update_config(ItemSchemaConfig {
+            fields: [(
+                "department".to_string(),
+                MetadataFieldSchema {
+                    field_type: MetadataFieldType::String,
+                    required: true,
+                    values: Vec::new(),
+                    description: None,
+                },
+            )]
+            .into_iter()
+            .collect(),
+        });
+        let report = service(&target).inspect(&archive).await.unwrap();
+        assert!(!report.importable);
+        assert_eq!(report.errors, vec![
+            "Item 1 metadata.department: is required",
+            "Item 2 metadata.department: is required",
+        ]);
+        assert!(import(&target, &archive).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_reports_oversized_metadata() {
+        let source = test_app().await;
+        let blob = serde_json::json!({ "blob": "x".repeat(1024 * 1024) }).to_string();
+        sqlx::query("UPDATE items SET metadata = ? WHERE id = 1")
+            .bind(&blob)
+            .execute(&source.pool)
+            .await
+            .unwrap();
+        let (_, archive) = service(&source).export(ExportOptions::default()).await.unwrap();
+
+        let target = empty_app().await;
+        let report = service(&target).inspect(&archive).await.unwrap();
+        assert!(!report.importable);
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert!(report.errors[0].starts_with("Item 1 metadata: Payload too large"), "{:?}", report.errors);
+        assert!(import(&target, &archive).await.is_err());
+    }
+}
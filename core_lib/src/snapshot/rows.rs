@@ -0,0 +1,92 @@
+//! Table rows as JSON objects, so archives carry every column a table has
+//! rather than only the fields its repository maps.
+
+use serde_json::{Map, Value};
+use sqlx::sqlite::{SqliteConnection, SqliteRow};
+use sqlx::{Column, Row, TypeInfo, ValueRef};
+use std::collections::HashSet;
+
+use crate::error::{AppError, Result};
+
+pub type TableRow = Map<String, Value>;
+
+/// Runs `query` and returns each row keyed by column name.
+pub async fn fetch(conn: &mut SqliteConnection, query: &str) -> Result<Vec<TableRow>> {
+    let rows = sqlx::query(query).fetch_all(&mut *conn).await?;
+    rows.iter().map(to_json).collect()
+}
+
+fn to_json(row: &SqliteRow) -> Result<TableRow> {
+    let mut object = TableRow::new();
+    for column in row.columns() {
+        let index = column.ordinal();
+        let raw = row.try_get_raw(index)?;
+        let value = if raw.is_null() {
+            Value::Null
+        } else {
+            match raw.type_info().name() {
+                "INTEGER" | "BOOLEAN" => Value::from(row.try_get_unchecked::<i64, _>(index)?),
+                "REAL" => Value::from(row.try_get_unchecked::<f64, _>(index)?),
+                "TEXT" => Value::String(row.try_get_unchecked::<String, _>(index)?),
+                other => {
+                    return Err(AppError::Database(format!(
+                        "Column {} holds a {} value, which snapshots do not support",
+                        column.name(),
+                        other
+                    )))
+                }
+            }
+        };
+        object.insert(column.name().to_string(), value);
+    }
+    Ok(object)
+}
+
+/// The columns `table` has in this database.
+pub async fn columns(conn: &mut SqliteConnection, table: &str) -> Result<HashSet<String>> {
+    let names: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info(?)")
+        .bind(table)
+        .fetch_all(&mut *conn)
+        .await?;
+    Ok(names.into_iter().collect())
+}
+
+/// Inserts `row` into `table` and returns its rowid. Column names must have
+/// been checked against [`columns`] first.
+pub async fn insert(conn: &mut SqliteConnection, table: &str, row: &TableRow) -> Result<i64> {
+    let names = row
+        .keys()
+        .map(|name| format!("\"{}\"", name))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let placeholders = vec!["?"; row.len()].join(", ");
+    let sql = format!("INSERT INTO {} ({}) VALUES ({})", table, names, placeholders);
+
+    let mut query = sqlx::query(&sql);
+    for value in row.values() {
+        query = match value {
+            Value::Null => query.bind(None::<String>),
+            Value::Bool(flag) => query.bind(*flag),
+            Value::Number(number) => match number.as_i64() {
+                Some(integer) => query.bind(integer),
+                None => query.bind(number.as_f64()),
+            },
+            Value::String(text) => query.bind(text.as_str()),
+            nested => query.bind(nested.to_string()),
+        };
+    }
+
+    Ok(query.execute(&mut *conn).await?.last_insert_rowid())
+}
+
+/// Whether a row with `column = value` exists in `table`.
+pub async fn exists(conn: &mut SqliteConnection, table: &str, column: &str, value: &Value) -> Result<bool> {
+    let sql = format!("SELECT EXISTS(SELECT 1 FROM {} WHERE \"{}\" = ?)", table, column);
+    let query = sqlx::query_scalar::<_, bool>(&sql);
+    let query = match value {
+        Value::Number(number) => query.bind(number.as_i64()),
+        Value::String(text) => query.bind(text.as_str()),
+        _ => return Ok(false),
+    };
+    Ok(query.fetch_one(&mut *conn).await?)
+}
@@ -0,0 +1,519 @@
+//! User accounts moved between instances with their password hashes
+//!
+//! Unlike a snapshot, a user bundle carries hashes as they are stored, so
+//! users keep their passwords on the new instance; hashes are never
+//! re-computed on either side. A bundle is three lines of JSON:
+//!
+//! - the header: format, version, user count and the key derivation
+//!   parameters (Argon2id, with a random salt) and AES-256-GCM nonce
+//! - `{"payload": "<hex>"}`: the users as NDJSON, one account per line,
+//!   encrypted with a key derived from the passphrase, with the header as
+//!   associated data
+//! - `{"signature": "<hex>"}`: HMAC-SHA256 of the first two lines, under a
+//!   second key derived from the same passphrase
+//!
+//! Rows are typed rather than copied column by column, so the bundle does
+//! not depend on this instance's database.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chrono::{DateTime, Utc};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use sqlx::{SqliteConnection, SqlitePool};
+use std::str::FromStr;
+
+use crate::auth::models::UserRole;
+use crate::error::{AppError, Result};
+
+pub const BUNDLE_FORMAT: &str = "user-accounts";
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// Passphrases shorter than this are refused when exporting.
+pub const MIN_PASSPHRASE_LENGTH: usize = 12;
+
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+/// One account as it travels in a bundle.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::FromRow)]
+pub struct UserRecord {
+    pub username: String,
+    pub email: String,
+    /// The PHC string exactly as stored.
+    pub password_hash: String,
+    pub role: String,
+    pub created_at: DateTime<Utc>,
+    pub last_login: Option<DateTime<Utc>>,
+    pub is_active: bool,
+    pub namespace: String,
+    pub email_verified: bool,
+    pub display_name: Option<String>,
+    pub timezone: Option<String>,
+}
+
+/// How the passphrase was stretched into keys.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub algorithm: String,
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    /// Hex.
+    pub salt: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundleHeader {
+    pub format: String,
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    pub users: usize,
+    pub kdf: KdfParams,
+    /// Hex.
+    pub nonce: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PayloadLine {
+    payload: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignatureLine {
+    signature: String,
+}
+
+/// What an import does with an account whose username or email is taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    /// Leave the existing account alone and carry on.
+    Skip,
+    /// Refuse the whole import.
+    #[default]
+    Error,
+}
+
+impl FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(OnConflict::Skip),
+            "error" => Ok(OnConflict::Error),
+            other => Err(format!("Unknown conflict policy {}; expected skip or error", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UserImportOptions {
+    pub on_conflict: OnConflict,
+    /// Admin accounts are refused unless this is set.
+    pub allow_admins: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SkippedUser {
+    pub username: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct UserImportReport {
+    pub imported: Vec<String>,
+    pub skipped: Vec<SkippedUser>,
+}
+
+struct BundleKeys {
+    cipher: LessSafeKey,
+    signing: hmac::Key,
+}
+
+impl BundleKeys {
+    fn derive(passphrase: &str, kdf: &KdfParams) -> Result<Self> {
+        if kdf.algorithm != "argon2id" {
+            return Err(AppError::BadRequest(format!(
+                "User bundle keys were derived with {}, which is not supported",
+                kdf.algorithm
+            )));
+        }
+        let salt = hex::decode(&kdf.salt)
+            .map_err(|_| AppError::BadRequest("User bundle salt is not hex".to_string()))?;
+        let params = Params::new(kdf.memory_kib, kdf.iterations, kdf.parallelism, Some(2 * KEY_LEN))
+            .map_err(|e| AppError::BadRequest(format!("Invalid user bundle key parameters: {}", e)))?;
+
+        let mut keys = [0u8; 2 * KEY_LEN];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut keys)
+            .map_err(|e| AppError::BadRequest(format!("Cannot derive user bundle keys: {}", e)))?;
+
+        let cipher = UnboundKey::new(&AES_256_GCM, &keys[..KEY_LEN]).map_err(|_| AppError::InternalServerError)?;
+        Ok(Self {
+            cipher: LessSafeKey::new(cipher),
+            signing: hmac::Key::new(hmac::HMAC_SHA256, &keys[KEY_LEN..]),
+        })
+    }
+}
+
+fn signed_bytes(header_line: &str, payload_line: &str) -> Vec<u8> {
+    format!("{}\n{}", header_line, payload_line).into_bytes()
+}
+
+/// Encrypts and signs `users` under `passphrase`.
+pub fn seal(users: &[UserRecord], passphrase: &str, created_at: DateTime<Utc>) -> Result<(BundleHeader, Vec<u8>)> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "The bundle passphrase must be at least {} characters",
+            MIN_PASSPHRASE_LENGTH
+        )));
+    }
+
+    let rng = SystemRandom::new();
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill(&mut salt).map_err(|_| AppError::InternalServerError)?;
+    rng.fill(&mut nonce).map_err(|_| AppError::InternalServerError)?;
+
+    let defaults = Params::default();
+    let header = BundleHeader {
+        format: BUNDLE_FORMAT.to_string(),
+        version: BUNDLE_VERSION,
+        created_at,
+        users: users.len(),
+        kdf: KdfParams {
+            algorithm: "argon2id".to_string(),
+            memory_kib: defaults.m_cost(),
+            iterations: defaults.t_cost(),
+            parallelism: defaults.p_cost(),
+            salt: hex::encode(salt),
+        },
+        nonce: hex::encode(nonce),
+    };
+    let keys = BundleKeys::derive(passphrase, &header.kdf)?;
+    let header_line = serde_json::to_string(&header)?;
+
+    let mut payload = Vec::new();
+    for user in users {
+        serde_json::to_writer(&mut payload, user)?;
+        payload.push(b'\n');
+    }
+    keys.cipher
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(header_line.as_bytes()),
+            &mut payload,
+        )
+        .map_err(|_| AppError::InternalServerError)?;
+    let payload_line = serde_json::to_string(&PayloadLine {
+        payload: hex::encode(payload),
+    })?;
+
+    let signature = hmac::sign(&keys.signing, &signed_bytes(&header_line, &payload_line));
+    let signature_line = serde_json::to_string(&SignatureLine {
+        signature: hex::encode(signature.as_ref()),
+    })?;
+
+    let bundle = format!("{}\n{}\n{}\n", header_line, payload_line, signature_line).into_bytes();
+    Ok((header, bundle))
+}
+
+/// Checks the signature of `bundle` and decrypts it.
+pub fn open(bundle: &[u8], passphrase: &str) -> Result<(BundleHeader, Vec<UserRecord>)> {
+    let malformed = || AppError::BadRequest("Not a user bundle: expected a header, payload and signature line".to_string());
+    let text = std::str::from_utf8(bundle).map_err(|_| malformed())?;
+    let lines: Vec<&str> = text.lines().filter(|line| !line.trim().is_empty()).collect();
+    let [header_line, payload_line, signature_line] = lines[..] else {
+        return Err(malformed());
+    };
+
+    let header: BundleHeader = serde_json::from_str(header_line).map_err(|_| malformed())?;
+    if header.format != BUNDLE_FORMAT {
+        return Err(malformed());
+    }
+    if header.version != BUNDLE_VERSION {
+        return Err(AppError::BadRequest(format!(
+            "User bundle version {} is not supported; expected {}",
+            header.version, BUNDLE_VERSION
+        )));
+    }
+    let payload: PayloadLine = serde_json::from_str(payload_line).map_err(|_| malformed())?;
+    let signature: SignatureLine = serde_json::from_str(signature_line).map_err(|_| malformed())?;
+
+    let keys = BundleKeys::derive(passphrase, &header.kdf)?;
+    let rejected = || {
+        AppError::BadRequest(
+            "User bundle signature does not match: the passphrase is wrong or the bundle was altered".to_string(),
+        )
+    };
+    let signature = hex::decode(&signature.signature).map_err(|_| rejected())?;
+    hmac::verify(&keys.signing, &signed_bytes(header_line, payload_line), &signature).map_err(|_| rejected())?;
+
+    let nonce = hex::decode(&header.nonce)
+        .ok()
+        .and_then(|nonce| Nonce::try_assume_unique_for_key(&nonce).ok())
+        .ok_or_else(rejected)?;
+    let mut sealed = hex::decode(&payload.payload).map_err(|_| rejected())?;
+    let plain = keys
+        .cipher
+        .open_in_place(nonce, Aad::from(header_line.as_bytes()), &mut sealed)
+        .map_err(|_| rejected())?;
+
+    let users = plain
+        .split(|byte| *byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(serde_json::from_slice::<UserRecord>)
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    if users.len() != header.users {
+        return Err(AppError::BadRequest(format!(
+            "User bundle holds {} accounts but its header says {}",
+            users.len(),
+            header.users
+        )));
+    }
+    Ok((header, users))
+}
+
+/// Every account, across namespaces, in the order they were created.
+pub async fn fetch(pool: &SqlitePool) -> Result<Vec<UserRecord>> {
+    Ok(sqlx::query_as::<_, UserRecord>(
+        "SELECT username, email, password_hash, role, created_at, last_login, is_active, namespace, \
+         email_verified, display_name, timezone FROM users ORDER BY id",
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Inserts `users` in one transaction. Nothing is written when the bundle
+/// holds admin accounts that are not allowed, an unknown role, or, with
+/// [`OnConflict::Error`], an account that collides with an existing one.
+pub async fn insert(pool: &SqlitePool, users: &[UserRecord], options: &UserImportOptions) -> Result<UserImportReport> {
+    let mut admins = Vec::new();
+    for user in users {
+        match user.role.parse::<UserRole>() {
+            Ok(UserRole::Admin) => admins.push(user.username.as_str()),
+            Ok(_) => {}
+            Err(e) => {
+                return Err(AppError::BadRequest(format!("User {} has an invalid role: {}", user.username, e)))
+            }
+        }
+    }
+    if !admins.is_empty() && !options.allow_admins {
+        return Err(AppError::Authorization(format!(
+            "The bundle holds admin accounts ({}); importing them must be allowed explicitly",
+            admins.join(", ")
+        )));
+    }
+
+    let mut tx = pool.begin().await?;
+    let mut report = UserImportReport::default();
+    for user in users {
+        if let Some(reason) = collision(&mut tx, user).await? {
+            match options.on_conflict {
+                OnConflict::Skip => {
+                    report.skipped.push(SkippedUser {
+                        username: user.username.clone(),
+                        reason,
+                    });
+                    continue;
+                }
+                OnConflict::Error => {
+                    return Err(AppError::Conflict(format!("User {} cannot be imported: {}", user.username, reason)))
+                }
+            }
+        }
+
+        sqlx::query(
+            "INSERT INTO users (username, email, password_hash, role, created_at, last_login, is_active, \
+             namespace, email_verified, display_name, timezone) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&user.username)
+        .bind(&user.email)
+        .bind(&user.password_hash)
+        .bind(&user.role)
+        .bind(user.created_at)
+        .bind(user.last_login)
+        .bind(user.is_active)
+        .bind(&user.namespace)
+        .bind(user.email_verified)
+        .bind(&user.display_name)
+        .bind(&user.timezone)
+        .execute(&mut *tx)
+        .await?;
+        report.imported.push(user.username.clone());
+    }
+    tx.commit().await?;
+    Ok(report)
+}
+
+async fn collision(conn: &mut SqliteConnection, user: &UserRecord) -> Result<Option<String>> {
+    let existing: Option<(String, String)> =
+        sqlx::query_as("SELECT username, email FROM users WHERE username = ? OR email = ? LIMIT 1")
+            .bind(&user.username)
+            .bind(&user.email)
+            .fetch_optional(&mut *conn)
+            .await?;
+
+    Ok(existing.map(|(username, _)| {
+        if username == user.username {
+            "username already exists".to_string()
+        } else {
+            format!("email {} belongs to {}", user.email, username)
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::models::{CreateUserRequest, LoginRequest};
+    use crate::snapshot::SnapshotService;
+    use crate::test_support::{test_app, TestApp, TEST_PASSWORD};
+
+    const PASSPHRASE: &str = "correct horse battery staple";
+
+    fn service(app: &TestApp) -> &SnapshotService {
+        app.state.snapshots.as_ref().unwrap()
+    }
+
+    async fn register(app: &TestApp, username: &str, role: UserRole) {
+        app.state
+            .auth_service
+            .as_ref()
+            .unwrap()
+            .register_user(CreateUserRequest {
+                username: username.to_string(),
+                email: format!("{}@example.com", username),
+                password: TEST_PASSWORD.to_string(),
+                role: Some(role),
+            })
+            .await
+            .unwrap();
+    }
+
+    async fn usernames(app: &TestApp) -> Vec<String> {
+        sqlx::query_scalar("SELECT username FROM users ORDER BY username")
+            .fetch_all(&app.pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_exported_user_logs_in_on_another_instance() {
+        let source = test_app().await;
+        register(&source, "alice", UserRole::User).await;
+        let (header, bundle) = service(&source).export_users(PASSPHRASE).await.unwrap();
+        assert_eq!(header.users, 1);
+        assert!(!String::from_utf8_lossy(&bundle).contains("alice"));
+
+        let target = test_app().await;
+        let report = service(&target)
+            .import_users(&bundle, PASSPHRASE, &UserImportOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(report.imported, ["alice"]);
+
+        let hash = "SELECT password_hash FROM users WHERE username = 'alice'";
+        let exported: String = sqlx::query_scalar(hash).fetch_one(&source.pool).await.unwrap();
+        let imported: String = sqlx::query_scalar(hash).fetch_one(&target.pool).await.unwrap();
+        assert_eq!(imported, exported);
+
+        let login = target
+            .state
+            .auth_service
+            .as_ref()
+            .unwrap()
+            .login(LoginRequest {
+                username: "alice".to_string(),
+                password: TEST_PASSWORD.to_string(),
+            })
+            .await;
+        assert!(login.is_ok(), "{:?}", login.err());
+    }
+
+    #[tokio::test]
+    async fn test_altered_bundle_or_wrong_passphrase_is_refused() {
+        let source = test_app().await;
+        register(&source, "alice", UserRole::User).await;
+        let (_, bundle) = service(&source).export_users(PASSPHRASE).await.unwrap();
+        let target = test_app().await;
+        let options = UserImportOptions::default();
+
+        let wrong = service(&target).import_users(&bundle, "not the passphrase", &options).await;
+        assert!(matches!(wrong, Err(AppError::BadRequest(ref message)) if message.contains("signature")));
+
+        let text = String::from_utf8(bundle).unwrap();
+        let altered = text.replacen("\"users\":1", "\"users\":2", 1);
+        assert_ne!(altered, text);
+        let altered = service(&target).import_users(altered.as_bytes(), PASSPHRASE, &options).await;
+        assert!(matches!(altered, Err(AppError::BadRequest(ref message)) if message.contains("signature")));
+
+        assert!(usernames(&target).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_admins_are_imported_only_when_allowed() {
+        let source = test_app().await;
+        register(&source, "alice", UserRole::User).await;
+        register(&source, "root", UserRole::Admin).await;
+        let (_, bundle) = service(&source).export_users(PASSPHRASE).await.unwrap();
+        let target = test_app().await;
+
+        let refused = service(&target)
+            .import_users(&bundle, PASSPHRASE, &UserImportOptions::default())
+            .await;
+        assert!(matches!(refused, Err(AppError::Authorization(ref message)) if message.contains("root")));
+        assert!(usernames(&target).await.is_empty());
+
+        let options = UserImportOptions {
+            allow_admins: true,
+            ..UserImportOptions::default()
+        };
+        let report = service(&target).import_users(&bundle, PASSPHRASE, &options).await.unwrap();
+        assert_eq!(report.imported, ["alice", "root"]);
+        let role: String = sqlx::query_scalar("SELECT role FROM users WHERE username = 'root'")
+            .fetch_one(&target.pool)
+            .await
+            .unwrap();
+        assert_eq!(role, "admin");
+    }
+
+    #[tokio::test]
+    async fn test_collisions_are_skipped_or_refuse_the_import() {
+        let source = test_app().await;
+        register(&source, "alice", UserRole::User).await;
+        register(&source, "bob", UserRole::User).await;
+        let (_, bundle) = service(&source).export_users(PASSPHRASE).await.unwrap();
+
+        let target = test_app().await;
+        register(&target, "alice", UserRole::User).await;
+
+        let refused = service(&target)
+            .import_users(&bundle, PASSPHRASE, &UserImportOptions::default())
+            .await;
+        assert!(matches!(refused, Err(AppError::Conflict(ref message)) if message.contains("alice")));
+        assert_eq!(usernames(&target).await, ["alice"]);
+
+        let options = UserImportOptions {
+            on_conflict: OnConflict::Skip,
+            ..UserImportOptions::default()
+        };
+        let report = service(&target).import_users(&bundle, PASSPHRASE, &options).await.unwrap();
+        assert_eq!(report.imported, ["bob"]);
+        assert_eq!(
+            report.skipped,
+            [SkippedUser {
+                username: "alice".to_string(),
+                reason: "username already exists".to_string(),
+            }]
+        );
+        assert_eq!(usernames(&target).await, ["alice", "bob"]);
+    }
+
+    #[test]
+    fn test_short_passphrases_are_refused() {
+        assert!(matches!(seal(&[], "short", Utc::now()), Err(AppError::BadRequest(_))));
+    }
+}
@@ -0,0 +1,306 @@
+//! What each subsystem made of its configuration when the server started
+//!
+//! Every subsystem records one outcome: OK, FALLBACK when something weaker
+//! stands in for it (the in-memory store for a database that would not
+//! open), FAILED when it runs without it, or DISABLED when it was not asked
+//! for. The report is logged once at startup and served at
+//! `GET /api/admin/startup-report`.
+//!
+//! With `server.strict_startup`, a configured subsystem that fails stops the
+//! server instead, so that a typo in `DATABASE_URL` cannot leave production
+//! quietly running on the memory store.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::error::{AppError, Result};
+
+/// A part of the server whose startup is reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Component {
+    Database,
+    Migrations,
+    Auth,
+    Files,
+    Jobs,
+    Notifications,
+    Webhooks,
+    Cache,
+    Websocket,
+    Search,
+    RateLimitStore,
+}
+
+impl Component {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Component::Database => "database",
+            Component::Migrations => "migrations",
+            Component::Auth => "auth",
+            Component::Files => "files",
+            Component::Jobs => "jobs",
+            Component::Notifications => "notifications",
+            Component::Webhooks => "webhooks",
+            Component::Cache => "cache",
+            Component::Websocket => "websocket",
+            Component::Search => "search",
+            Component::RateLimitStore => "rate_limit_store",
+        }
+    }
+}
+
+impl fmt::Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum StartupStatus {
+    Ok,
+    Fallback,
+    Failed,
+    Disabled,
+}
+
+impl fmt::Display for StartupStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            StartupStatus::Ok => "OK",
+            StartupStatus::Fallback => "FALLBACK",
+            StartupStatus::Failed => "FAILED",
+            StartupStatus::Disabled => "DISABLED",
+        })
+    }
+}
+
+/// What to do about a subsystem that failed to start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Stop the server.
+    Abort,
+    /// Run with the fallback in its place.
+    Fallback,
+    /// Run without it.
+    Continue,
+}
+
+/// Only a subsystem the config asked for can abort a strict startup; one
+/// running on defaults falls back as it always has.
+pub fn decide(configured: bool, strict: bool, has_fallback: bool) -> Decision {
+    match (configured && strict, has_fallback) {
+        (true, _) => Decision::Abort,
+        (false, true) => Decision::Fallback,
+        (false, false) => Decision::Continue,
+    }
+}
+
+/// The value of `server.strict_startup` when it is not set: on in
+/// production.
+pub fn strict_by_default(rust_env: Option<&str>) -> bool {
+    rust_env == Some("production")
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Outcome {
+    pub component: Component,
+    pub status: StartupStatus,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StartupReport {
+    pub strict: bool,
+    pub started_at: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_outcomes")]
+    outcomes: BTreeMap<Component, Outcome>,
+}
+
+fn serialize_outcomes<S: serde::Serializer>(
+    outcomes: &BTreeMap<Component, Outcome>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_seq(outcomes.values())
+}
+
+impl StartupReport {
+    pub fn new(strict: bool) -> Self {
+        Self {
+            strict,
+            started_at: Utc::now(),
+            outcomes: BTreeMap::new(),
+        }
+    }
+
+    pub fn ok(&mut self, component: Component, detail: impl Into<String>) {
+        self.record(component, StartupStatus::Ok, detail.into());
+    }
+
+    pub fn disabled(&mut self, component: Component, detail: impl Into<String>) {
+        self.record(component, StartupStatus::Disabled, detail.into());
+    }
+
+    /// Records that `component` runs on `fallback` because something it
+    /// depends on failed, which is reported against that other component.
+    pub fn fallback(&mut self, component: Component, fallback: impl Into<String>) {
+        self.record(component, StartupStatus::Fallback, fallback.into());
+    }
+
+    /// Records that `component` failed with `error` and decides what
+    /// happens next. An abort is returned as an error, after logging the
+    /// report so far; otherwise `fallback` names what stands in for the
+    /// component, if anything does.
+    pub fn failed(
+        &mut self,
+        component: Component,
+        configured: bool,
+        error: impl fmt::Display,
+        fallback: Option<&str>,
+    ) -> Result<Decision> {
+        let decision = decide(configured, self.strict, fallback.is_some());
+        match (decision, fallback) {
+            (Decision::Fallback, Some(fallback)) => {
+                self.record(component, StartupStatus::Fallback, format!("{}; using {}", error, fallback));
+            }
+            _ => self.record(component, StartupStatus::Failed, error.to_string()),
+        }
+
+        if decision == Decision::Abort {
+            tracing::error!("{}", self);
+            return Err(AppError::Configuration(format!(
+                "{} failed to start and strict_startup does not allow falling back: {}",
+                component, error
+            )));
+        }
+        Ok(decision)
+    }
+
+    pub fn status(&self, component: Component) -> Option<StartupStatus> {
+        self.outcomes.get(&component).map(|outcome| outcome.status)
+    }
+
+    pub fn outcomes(&self) -> impl Iterator<Item = &Outcome> {
+        self.outcomes.values()
+    }
+
+    /// Whether anything fell back or failed.
+    pub fn is_degraded(&self) -> bool {
+        self.outcomes()
+            .any(|outcome| matches!(outcome.status, StartupStatus::Fallback | StartupStatus::Failed))
+    }
+
+    /// Logs the report, as a warning when the server is degraded.
+    pub fn log(&self) {
+        if self.is_degraded() {
+            tracing::warn!("{}", self);
+        } else {
+            tracing::info!("{}", self);
+        }
+    }
+
+    fn record(&mut self, component: Component, status: StartupStatus, detail: String) {
+        self.outcomes.insert(component, Outcome { component, status, detail });
+    }
+}
+
+impl fmt::Display for StartupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Startup report (strict: {})", if self.strict { "on" } else { "off" })?;
+        for outcome in self.outcomes() {
+            write!(f, "\n  {:<17}{:<10}{}", outcome.component.name(), outcome.status, outcome.detail)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decision_matrix() {
+        let cases = [
+            // configured, strict, has_fallback
+            ((true, true, true), Decision::Abort),
+            ((true, true, false), Decision::Abort),
+            ((true, false, true), Decision::Fallback),
+            ((true, false, false), Decision::Continue),
+            ((false, true, true), Decision::Fallback),
+            ((false, true, false), Decision::Continue),
+            ((false, false, true), Decision::Fallback),
+            ((false, false, false), Decision::Continue),
+        ];
+        for ((configured, strict, has_fallback), expected) in cases {
+            assert_eq!(
+                decide(configured, strict, has_fallback),
+                expected,
+                "configured: {}, strict: {}, has_fallback: {}",
+                configured,
+                strict,
+                has_fallback
+            );
+        }
+    }
+
+    #[test]
+    fn test_strict_startup_defaults_to_production() {
+        assert!(strict_by_default(Some("production")));
+        assert!(!strict_by_default(Some("development")));
+        assert!(!strict_by_default(None));
+    }
+
+    #[test]
+    fn test_lenient_report_records_fallbacks() {
+        let mut report = StartupReport::new(false);
+        report.ok(Component::Cache, "in memory");
+        let decision = report
+            .failed(Component::Database, true, "unable to open database file", Some("the in-memory store"))
+            .unwrap();
+        assert_eq!(decision, Decision::Fallback);
+        assert_eq!(report.status(Component::Database), Some(StartupStatus::Fallback));
+
+        let decision = report.failed(Component::Auth, true, "JWT secret too short", None).unwrap();
+        assert_eq!(decision, Decision::Continue);
+        assert_eq!(report.status(Component::Auth), Some(StartupStatus::Failed));
+        assert!(report.is_degraded());
+
+        let printed = report.to_string();
+        assert!(printed.contains("database         FALLBACK  unable to open database file; using the in-memory store"));
+        assert!(printed.contains("auth             FAILED    JWT secret too short"));
+    }
+
+    #[test]
+    fn test_strict_report_aborts_on_configured_failures() {
+        let mut report = StartupReport::new(true);
+        let error = report
+            .failed(Component::Database, true, "unable to open database file", Some("the in-memory store"))
+            .unwrap_err();
+        assert!(matches!(error, AppError::Configuration(message) if message.contains("database failed to start")));
+        assert_eq!(report.status(Component::Database), Some(StartupStatus::Failed));
+
+        let decision = report
+            .failed(Component::RateLimitStore, false, "locked", Some("in-memory limits"))
+            .unwrap();
+        assert_eq!(decision, Decision::Fallback);
+    }
+
+    #[test]
+    fn test_report_serializes_in_component_order() {
+        let mut report = StartupReport::new(false);
+        report.disabled(Component::Jobs, "jobs.enabled is off");
+        report.ok(Component::Database, "sqlite:./data/app.db");
+        assert!(!report.is_degraded());
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["strict"], false);
+        assert_eq!(json["outcomes"][0]["component"], "database");
+        assert_eq!(json["outcomes"][0]["status"], "OK");
+        assert_eq!(json["outcomes"][1]["component"], "jobs");
+        assert_eq!(json["outcomes"][1]["status"], "DISABLED");
+    }
+}
@@ -0,0 +1,485 @@
+//! Assembles an [`AppState`] from explicit configuration, without reading
+//! environment variables
+
+use crate::auth::{AuthService, JwtService, UserRepository};
+use crate::cache::CacheManager;
+use crate::clock::{SharedClock, SystemClock};
+use crate::comments::{CommentRepository, CommentService};
+use crate::config::{AppConfig, RateLimitBackend};
+use crate::database::{run_migrations, DatabaseManager, ItemRepository};
+use crate::error::{AppError, Result};
+use crate::features::{FeatureStore, FeatureSwitches};
+use crate::files::{validation::FileValidationConfig, FileManager, FileManagerConfig, FileRepository};
+use crate::ids::{RandomIds, SharedIdGenerator};
+use crate::item_limits::MetadataLimits;
+use crate::item_names::NameRule;
+use crate::item_secrets::ItemSecrets;
+use crate::jobs::{JobQueue, JobRepository};
+use crate::metrics::{sink_from_config, MetricsSink};
+use crate::middleware::rate_limit::RateLimiter;
+use crate::middleware::rate_limit_store::SqliteRateLimitStore;
+use crate::monitoring::SystemMonitor;
+use crate::notifications::{notifier_from_config, NotificationDispatcher};
+use crate::outbound::OutboundHttp;
+use crate::snapshot::SnapshotService;
+use crate::startup::{Component, StartupReport};
+use crate::validation::AnomalyTracker;
+use crate::webhooks::{WebhookDeliverer, WebhookRepository, WebhookService};
+use crate::websocket::WebSocketManager;
+use crate::AppState;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Builds the state the server runs with, configured entirely from an
+/// [`AppConfig`]. With a database pool every component is enabled (jobs only
+/// if `jobs.enabled`, webhooks only with jobs and `webhooks.enabled`);
+/// without one the state uses the in-memory store with cache, websocket and
+/// health checks but no auth, files, jobs or webhooks.
+///
+/// A component that fails to start is recorded in the startup report, which
+/// decides whether the build stops or carries on without it. The default
+/// report is strict, so the build returns the first failure.
+///
+/// The clock and id generator are handed to every component whose behaviour
+/// depends on them, so tests can control token expiry, cache TTLs, rate limit
+/// windows, retry backoff and generated ids.
+pub struct AppStateBuilder {
+    config: AppConfig,
+    pool: Option<SqlitePool>,
+    clock: SharedClock,
+    ids: SharedIdGenerator,
+    metrics: Option<Arc<dyn MetricsSink>>,
+    report: StartupReport,
+}
+
+impl AppStateBuilder {
+    pub fn new(config: AppConfig) -> Self {
+        Self {
+            config,
+            pool: None,
+            clock: SystemClock::shared(),
+            ids: RandomIds::shared(),
+            metrics: None,
+            report: StartupReport::new(true),
+        }
+    }
+
+    /// Backs the state with `pool`. Migrations are run when building.
+    pub fn with_database(mut self, pool: SqlitePool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_id_generator(mut self, ids: SharedIdGenerator) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Metrics sink to share with the caller; one is created from
+    /// `metrics` config otherwise.
+    pub fn with_metrics(mut self, metrics: Arc<dyn MetricsSink>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Continues `report`, which may already hold what the caller started,
+    /// such as opening the database. A lenient report lets the build fall
+    /// back where a component fails.
+    pub fn with_startup_report(mut self, report: StartupReport) -> Self {
+        self.report = report;
+        self
+    }
+
+    pub async fn build(mut self) -> Result<AppState> {
+        let mut report = std::mem::replace(&mut self.report, StartupReport::new(true));
+        let config = &self.config;
+        let metrics = match &self.metrics {
+            Some(metrics) => metrics.clone(),
+            None => sink_from_config(&config.metrics)?,
+        };
+        let rate_limiter = self.rate_limiter(&mut report).await?;
+        let cache_manager = CacheManager::new(config.cache.clone()).with_clock(self.clock.clone());
+        let outbound = OutboundHttp::from_config(&config.outbound)?.with_clock(self.clock.clone());
+        let item_secrets = ItemSecrets::from_config(&config.item_secrets)?;
+
+        let pool = match self.pool.clone() {
+            Some(pool) => match run_migrations(pool.clone()).await {
+                Ok(()) => Some(pool),
+                Err(e) => {
+                    report.failed(Component::Migrations, true, &e, Some("the in-memory store"))?;
+                    report.fallback(Component::Database, "the in-memory store, since migrations failed");
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let state = match pool {
+            Some(pool) => {
+                let state = AppState::with_database(DatabaseManager::new(pool.clone()), ItemRepository::new(pool.clone()));
+                let features = FeatureSwitches::from_config(&config.features)
+                    .with_store(FeatureStore::new(pool.clone()).with_clock(self.clock.clone()))
+                    .await;
+                let features = match features {
+                    Ok(features) => features,
+                    Err(e) => {
+                        tolerate(&report, "to load saved feature switches", e)?;
+                        FeatureSwitches::from_config(&config.features)
+                    }
+                };
+                let state = self.configure(state, features, metrics, rate_limiter, outbound, item_secrets);
+                self.build_with_database(state, pool, &cache_manager, &mut report).await?
+            }
+            None => self
+                .configure(
+                    AppState::default(),
+                    FeatureSwitches::from_config(&config.features),
+                    metrics,
+                    rate_limiter,
+                    outbound,
+                    item_secrets,
+                )
+                .with_websocket(
+                    WebSocketManager::new(None)
+                        .with_config(config.websocket.clone())
+                        .with_origin_allowlist(&config.cors),
+                ),
+        };
+
+        let state = state
+            .with_cache_manager(cache_manager)
+            .with_health_config(&config.health)
+            .with_system_monitor(SystemMonitor::new().with_storage_paths(config.storage_paths()))
+            .with_validation_config(config.validation.clone())
+            .with_anomaly_tracker(AnomalyTracker::new(config.security.clone()));
+        complete_startup_report(&mut report, &state, config);
+        Ok(state.with_startup_report(report))
+    }
+
+    /// The settings every state gets, with or without a database.
+    fn configure(
+        &self,
+        state: AppState,
+        features: FeatureSwitches,
+        metrics: Arc<dyn MetricsSink>,
+        rate_limiter: RateLimiter,
+        outbound: OutboundHttp,
+        item_secrets: ItemSecrets,
+    ) -> AppState {
+        let config = &self.config;
+        state
+            .with_features(features)
+            .with_change_feed(&config.changes)
+            .with_item_config(&config.items)
+            .with_item_schema(&config.item_schema)
+            .with_item_secrets(item_secrets)
+            .with_trash_config(&config.trash)
+            .with_integrity_config(&config.integrity)
+            .with_duplicate_config(&config.duplicates)
+            .with_suggest_config(&config.suggest)
+            .with_search_config(&config.search)
+            .with_search_export_config(&config.search_export)
+            .with_pagination_config(&config.pagination)
+            .with_metrics(metrics)
+            .with_rate_limiter(rate_limiter)
+            .with_outbound(outbound)
+    }
+
+    /// Limits shared through SQLite when `rate_limit.backend` asks for it.
+    async fn rate_limiter(&self, report: &mut StartupReport) -> Result<RateLimiter> {
+        let config = &self.config.rate_limit;
+        let rate_limiter = RateLimiter::new(config.clone()).with_clock(self.clock.clone());
+        if !config.enable || config.backend != RateLimitBackend::Sqlite {
+            return Ok(rate_limiter);
+        }
+
+        let store_url = config.shared_store_url.as_deref().unwrap_or(&self.config.database.url);
+        match SqliteRateLimitStore::connect(store_url).await {
+            Ok(store) => {
+                report.ok(Component::RateLimitStore, store_url);
+                Ok(rate_limiter.with_store(Arc::new(store)))
+            }
+            Err(e) => {
+                report.failed(Component::RateLimitStore, true, &e, Some("in-memory limits"))?;
+                Ok(rate_limiter)
+            }
+        }
+    }
+
+    async fn build_with_database(
+        &self,
+        mut state: AppState,
+        pool: SqlitePool,
+        cache_manager: &CacheManager,
+        report: &mut StartupReport,
+    ) -> Result<AppState> {
+        let config = &self.config;
+        if let Err(e) = state.migrate_to_database_if_needed().await {
+            report.failed(
+                Component::Migrations,
+                true,
+                format!("moving in-memory items to the database: {}", e),
+                None,
+            )?;
+        }
+        let comments = CommentService::new(
+            CommentRepository::new(pool.clone()),
+            state.item_service.clone(),
+            self.clock.clone(),
+        );
+        state = state.with_comments(comments);
+
+        let jwt_service = match JwtService::with_secret(&config.auth.jwt_secret) {
+            Ok(jwt_service) => Some(
+                jwt_service
+                    .with_token_expiry(
+                        chrono::Duration::hours(config.auth.jwt_expiration_hours as i64),
+                        chrono::Duration::days(config.auth.jwt_refresh_expiration_days as i64),
+                    )
+                    .with_clock(self.clock.clone()),
+            ),
+            Err(e) => {
+                report.failed(Component::Auth, true, &e, None)?;
+                None
+            }
+        };
+
+        let file_manager = FileManager::new(
+            FileManagerConfig {
+                storage_path: config.files.upload_dir.clone(),
+                validation: FileValidationConfig {
+                    max_file_size: config.files.max_file_size_mb * 1024 * 1024,
+                    ..FileValidationConfig::default()
+                },
+                min_free_space: config.files.min_free_space_mb * 1024 * 1024,
+                cache_control: config.files.cache_control.clone(),
+                reconcile: config.files.reconcile.clone(),
+                ..FileManagerConfig::default()
+            },
+            FileRepository::new(pool.clone()),
+        )
+        .with_id_generator(self.ids.clone());
+        match file_manager.initialize().await {
+            Ok(()) => state = state.with_file_manager(file_manager),
+            Err(e) => {
+                report.failed(Component::Files, true, &e, None)?;
+            }
+        }
+
+        let snapshots = SnapshotService::new(
+            pool.clone(),
+            config.files.upload_dir.clone(),
+            config.files.temp_dir.clone(),
+        )
+        .with_max_archive_size(config.snapshots.max_archive_size_mb as usize * 1024 * 1024)
+        .with_cache_manager(cache_manager.clone())
+        .with_item_schema(state.item_schema.clone())
+        .with_metadata_limits(MetadataLimits::from_config(&config.items))
+        .with_name_rule(NameRule::from_config(&config.items))
+        .with_clock(self.clock.clone());
+        state = state.with_snapshots(snapshots.clone());
+
+        state = state.with_websocket(
+            WebSocketManager::new(jwt_service.clone())
+                .with_config(config.websocket.clone())
+                .with_origin_allowlist(&config.cors),
+        );
+
+        let job_queue = if config.jobs.enabled {
+            let (with_jobs, job_queue) = self.start_jobs(state, pool.clone(), snapshots, report).await?;
+            state = with_jobs;
+            job_queue
+        } else {
+            None
+        };
+
+        if let Err(e) = state.prepare_item_names().await {
+            tolerate(report, "to index item names for items.unique_names", e)?;
+        }
+        if let Err(e) = state.check_integrity_at_startup().await {
+            tolerate(report, "to check the references between tables", e)?;
+        }
+
+        if let Some(jwt_service) = jwt_service {
+            let mut auth_service = AuthService::new(UserRepository::new(pool), jwt_service)
+                .with_metrics(state.metrics.clone())
+                .with_audit_log(state.audit_log.clone());
+            match config.auth.argon2_params() {
+                Ok(params) => auth_service = auth_service.with_argon2_params(params),
+                Err(e) => {
+                    report.failed(Component::Auth, true, &e, Some("the default password hashing parameters"))?;
+                }
+            }
+            match (config.notifications.enabled, job_queue) {
+                (true, Some(job_queue)) => {
+                    auth_service = auth_service
+                        .with_notifications(
+                            NotificationDispatcher::new(job_queue).with_max_retries(config.jobs.retry_attempts as i32),
+                        )
+                        .with_email_verification(&config.auth);
+                }
+                (true, None) => {
+                    report.failed(Component::Notifications, true, "needs the job queue, which is disabled", None)?;
+                }
+                (false, _) => {}
+            }
+            state = state.with_auth(auth_service);
+        }
+
+        Ok(state)
+    }
+
+    /// Starts the job queue with everything its jobs run on, and the
+    /// webhooks delivered through it. There is no queue when it can't be
+    /// created.
+    async fn start_jobs(
+        &self,
+        mut state: AppState,
+        pool: SqlitePool,
+        snapshots: SnapshotService,
+        report: &mut StartupReport,
+    ) -> Result<(AppState, Option<JobQueue>)> {
+        let config = &self.config;
+        let job_repository = JobRepository::new(pool.clone());
+        if let Err(e) = job_repository.create_table().await {
+            report.failed(Component::Jobs, true, &e, None)?;
+            return Ok((state, None));
+        }
+        let mut job_queue = match state.create_job_queue_with_websocket(job_repository).await {
+            Ok(job_queue) => job_queue,
+            Err(e) => {
+                report.failed(Component::Jobs, true, &e, Some("a queue without WebSocket progress events"))?;
+                JobQueue::new(JobRepository::new(pool.clone()))
+            }
+        }
+        .with_retry_delay(Duration::from_secs(config.jobs.retry_delay_seconds))
+        .with_clock(self.clock.clone())
+        .with_id_generator(self.ids.clone());
+
+        if config.notifications.enabled {
+            match notifier_from_config(&config.notifications) {
+                Ok(notifier) => {
+                    report.ok(Component::Notifications, format!("{} notifier", notifier.name()));
+                    job_queue = job_queue.with_notifier(notifier);
+                }
+                Err(e) => {
+                    report.failed(Component::Notifications, true, &e, None)?;
+                }
+            }
+        }
+        let webhook_repository = WebhookRepository::new(pool);
+        if config.webhooks.enabled {
+            match WebhookDeliverer::new(webhook_repository.clone(), state.outbound.clone(), &config.webhooks, self.clock.clone()) {
+                Ok(deliverer) => job_queue = job_queue.with_webhooks(deliverer),
+                Err(e) => {
+                    report.failed(Component::Webhooks, true, &e, None)?;
+                }
+            }
+        }
+        job_queue = job_queue
+            .with_snapshots(Arc::new(snapshots))
+            .with_trash(Arc::new(state.trash_purger()))
+            .with_schema_checker(Arc::new(state.schema_checker()))
+            .with_name_checker(Arc::new(state.name_checker()))
+            .with_secret_rekeyer(Arc::new(state.secret_rekeyer()))
+            .with_metadata_transformer(Arc::new(state.metadata_transformer()))
+            .with_events(state.events.clone())
+            .with_exports(Arc::new(state.search_exporter()));
+        if let Some(reconciler) = state.file_reconciler() {
+            job_queue = job_queue.with_file_reconciler(Arc::new(reconciler));
+        }
+        if let Some(checker) = state.integrity_checker() {
+            job_queue = job_queue.with_integrity_checker(Arc::new(checker));
+        }
+        if let Some(indexer) = state.content_indexer() {
+            job_queue = job_queue.with_content_indexer(Arc::new(indexer));
+        }
+        if let Some(file_manager) = state.file_manager.clone() {
+            job_queue = job_queue.with_result_store(file_manager, config.jobs.max_inline_result_bytes);
+        }
+        match job_queue.start_workers(config.jobs.max_workers).await {
+            Ok(()) if report.status(Component::Jobs).is_none() => {
+                report.ok(Component::Jobs, format!("{} workers", config.jobs.max_workers));
+            }
+            Ok(()) => {}
+            Err(e) => {
+                report.failed(Component::Jobs, true, format!("starting workers: {}", e), None)?;
+            }
+        }
+        state = state.with_job_queue(job_queue.clone());
+
+        if config.webhooks.enabled {
+            match WebhookService::new(
+                webhook_repository,
+                job_queue.clone(),
+                state.outbound.clone(),
+                &config.webhooks,
+                self.clock.clone(),
+                self.ids.clone(),
+            ) {
+                Ok(webhooks) => state = state.with_webhooks(webhooks),
+                Err(e) => {
+                    report.failed(Component::Webhooks, true, &e, None)?;
+                }
+            }
+        }
+        Ok((state, Some(job_queue)))
+    }
+}
+
+/// A failure in a startup step that isn't a component of its own: the
+/// error when the report is strict, a warning otherwise.
+fn tolerate(report: &StartupReport, step: &str, error: AppError) -> Result<()> {
+    if report.strict {
+        return Err(error);
+    }
+    tracing::warn!("Failed {}: {}", step, error);
+    Ok(())
+}
+
+/// Reports every component the build didn't, as OK when the state has it
+/// and DISABLED otherwise.
+fn complete_startup_report(report: &mut StartupReport, state: &AppState, config: &AppConfig) {
+    let database = state.db_manager.is_some();
+    let upload_dir = config.files.upload_dir.display().to_string();
+    let components = [
+        (Component::Database, database, "sqlite", "in-memory store"),
+        (Component::Migrations, database, "applied", "no database"),
+        (Component::Auth, state.auth_service.is_some(), "password and token auth", "needs the database"),
+        (Component::Files, state.file_manager.is_some(), upload_dir.as_str(), "needs the database"),
+        (Component::Jobs, state.job_queue.is_some(), "workers started", "jobs.enabled is off or no database"),
+        (
+            Component::Notifications,
+            config.notifications.enabled && state.job_queue.is_some(),
+            "via the job queue",
+            "notifications.enabled is off or no job queue",
+        ),
+        (Component::Webhooks, state.webhooks.is_some(), "via the job queue", "webhooks.enabled is off or no job queue"),
+        (Component::Cache, state.cache_manager.is_some(), "in memory", "not configured"),
+        (Component::Websocket, state.websocket_manager.is_some(), "accepting connections", "not configured"),
+        (Component::Search, state.search_engine.is_some(), "full-text index", "in-memory filtering"),
+    ];
+
+    for (component, present, ok, disabled) in components {
+        if report.status(component).is_some() {
+            continue;
+        }
+        if present {
+            report.ok(component, ok);
+        } else {
+            report.disabled(component, disabled);
+        }
+    }
+}
+
+impl AppState {
+    pub fn builder(config: AppConfig) -> AppStateBuilder {
+        AppStateBuilder::new(config)
+    }
+}
@@ -1,9 +1,27 @@
 //! In-memory data store for the application
 
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
 use serde::{Deserialize, Serialize};
+use crate::changes::{ChangeLog, ChangeOp, ChangePage};
+use crate::config::ChangeFeedConfig;
 use crate::error::{AppError, Result};
+use crate::transaction;
+use crate::trash::PurgeReport;
+use crate::item_metadata::Metadata;
+use crate::item_names::{DuplicateName, NameConflict, NameRule};
+use crate::item_transform::{MetadataBatch, MetadataOutcome};
+use crate::models::items::{
+    daily_counts, CreatorCount, DescriptionStats, ItemStats, MetadataStats, StatsBreakdowns, TagCount, TagRewrite,
+    TagStats, VersionConflict,
+};
+
+/// Version of a newly created item; every update adds one.
+pub const INITIAL_ITEM_VERSION: u64 = 1;
+
+fn initial_item_version() -> u64 {
+    INITIAL_ITEM_VERSION
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Item {
@@ -13,13 +31,94 @@ pub struct Item {
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
     pub tags: Vec<String>,
-    pub metadata: Option<serde_json::Value>,
+    pub metadata: Option<Metadata>,
+    #[serde(default = "initial_item_version")]
+    pub version: u64,
 }
 
+/// Deleted items with the time they were deleted, until purged.
+type Trash = HashMap<u64, (Item, chrono::DateTime<chrono::Utc>)>;
+
 #[derive(Clone)]
 pub struct DataStore {
     items: Arc<RwLock<HashMap<u64, Item>>>,
+    /// Only changed while holding the `items` write lock, so that anyone
+    /// holding `items` sees the two agree.
+    tag_index: Arc<RwLock<TagIndex>>,
     next_id: Arc<RwLock<u64>>,
+    changes: Arc<RwLock<ChangeLog>>,
+    trash: Arc<RwLock<Trash>>,
+    names: NameRule,
+}
+
+/// Item writes made to the memory store inside a
+/// [transaction](crate::transaction), held back until it commits. Reads of
+/// single items inside the transaction see them; listings, searches and
+/// stats only see what has been committed.
+#[derive(Default)]
+pub struct StagedWrites {
+    writes: Vec<StagedWrite>,
+    /// Each item written, as the writes leave it; `None` once deleted.
+    items: HashMap<u64, Option<Item>>,
+    /// The version each item written had in the store, which it must still
+    /// have on commit; `None` for items the transaction created.
+    versions: HashMap<u64, Option<u64>>,
+}
+
+enum StagedWrite {
+    Created(Item),
+    Updated(Item),
+    Deleted(u64),
+}
+
+impl StagedWrites {
+    /// Adds `write`, made to `current`, or to a new item when `None`.
+    fn stage(&mut self, current: Option<&Item>, write: StagedWrite) {
+        let (id, item) = match &write {
+            StagedWrite::Created(item) | StagedWrite::Updated(item) => (item.id, Some(item.clone())),
+            StagedWrite::Deleted(id) => (*id, None),
+        };
+        self.versions.entry(id).or_insert(current.map(|item| item.version));
+        self.items.insert(id, item);
+        self.writes.push(write);
+    }
+}
+
+fn lock_staged(staged: &Mutex<StagedWrites>) -> std::sync::MutexGuard<'_, StagedWrites> {
+    staged.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// `item` with the fields present in `updates` changed, as a patch does.
+fn apply_patch(item: &Item, updates: &HashMap<String, serde_json::Value>) -> Item {
+    let mut item = item.clone();
+    
+    if let Some(name) = updates.get("name").and_then(|v| v.as_str()) {
+        item.name = name.to_string();
+    }
+    
+    if let Some(desc) = updates.get("description") {
+        if desc.is_null() {
+            item.description = None;
+        } else if let Some(desc_str) = desc.as_str() {
+            item.description = Some(desc_str.to_string());
+        }
+    }
+    
+    if let Some(tags) = updates.get("tags").and_then(|v| v.as_array()) {
+        item.tags = tags.iter()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect();
+    }
+    
+    if let Some(metadata) = updates.get("metadata") {
+        if metadata.is_null() {
+            item.metadata = None;
+        } else {
+            item.metadata = Some(Metadata::from_value(metadata));
+        }
+    }
+
+    item
 }
 
 impl DataStore {
@@ -33,7 +132,8 @@ impl DataStore {
             created_at: chrono::Utc::now(),
             updated_at: chrono::Utc::now(),
             tags: vec!["sample".to_string(), "demo".to_string()],
-            metadata: Some(serde_json::json!({"category": "electronics", "price": 99.99})),
+            metadata: Some(Metadata::from(serde_json::json!({"category": "electronics", "price": 99.99}))),
+            version: INITIAL_ITEM_VERSION,
         });
         
         initial_items.insert(2, Item {
@@ -44,19 +144,196 @@ impl DataStore {
             updated_at: chrono::Utc::now(),
             tags: vec!["demo".to_string()],
             metadata: None,
+            version: INITIAL_ITEM_VERSION,
         });
 
+        let tag_index = TagIndex::build(&initial_items);
         Self {
             items: Arc::new(RwLock::new(initial_items)),
+            tag_index: Arc::new(RwLock::new(tag_index)),
             next_id: Arc::new(RwLock::new(3)),
+            changes: Arc::new(RwLock::new(ChangeLog::default())),
+            trash: Arc::new(RwLock::new(HashMap::new())),
+            names: NameRule::default(),
         }
     }
 
     pub fn empty() -> Self {
         Self {
             items: Arc::new(RwLock::new(HashMap::new())),
+            tag_index: Arc::new(RwLock::new(TagIndex::default())),
             next_id: Arc::new(RwLock::new(1)),
+            changes: Arc::new(RwLock::new(ChangeLog::default())),
+            trash: Arc::new(RwLock::new(HashMap::new())),
+            names: NameRule::default(),
+        }
+    }
+
+    /// Applies `config` to the change log, which every clone of this store
+    /// shares.
+    pub fn with_change_feed(self, config: &ChangeFeedConfig) -> Self {
+        if let Ok(mut changes) = self.changes.write() {
+            changes.set_retention(config.clone());
+        }
+        self
+    }
+
+    /// Names this handle refuses to repeat. The store has no namespaces,
+    /// so a namespace scope applies across all items.
+    pub fn with_name_rule(mut self, names: NameRule) -> Self {
+        self.names = names;
+        self
+    }
+
+    /// Refuses `name` when an item other than `id` has it, checked while
+    /// the caller holds the items lock.
+    fn check_name(&self, items: &HashMap<u64, Item>, name: &str, id: Option<u64>) -> Result<()> {
+        self.check_name_among(items.values(), name, id)
+    }
+
+    fn check_name_among<'a>(&self, items: impl Iterator<Item = &'a Item>, name: &str, id: Option<u64>) -> Result<()> {
+        if !self.names.is_enabled() {
+            return Ok(());
+        }
+        let taken = items
+            .filter(|item| Some(item.id) != id && self.names.matches(&item.name, name))
+            .map(|item| item.id)
+            .min();
+        match taken {
+            Some(item_id) => Err(NameConflict::new(name, item_id).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether `other` is a handle on the same items as this store.
+    pub(crate) fn is_same_store(&self, other: &DataStore) -> bool {
+        Arc::ptr_eq(&self.items, &other.items)
+    }
+
+    /// Item `id` as the staged writes leave it.
+    fn staged_item(&self, staged: &StagedWrites, id: u64) -> Result<Item> {
+        match staged.items.get(&id) {
+            Some(Some(item)) => Ok(item.clone()),
+            Some(None) => Err(AppError::NotFound(format!("Item with id {} not found", id))),
+            None => self.items.read()
+                .map_err(|_| AppError::InternalServerError)?
+                .get(&id)
+                .cloned()
+                .ok_or_else(|| AppError::NotFound(format!("Item with id {} not found", id))),
+        }
+    }
+
+    /// As [`check_name`](Self::check_name), against the items as the
+    /// staged writes leave them.
+    fn check_staged_name(&self, staged: &StagedWrites, name: &str, id: Option<u64>) -> Result<()> {
+        let items = self.items.read()
+            .map_err(|_| AppError::InternalServerError)?;
+        let untouched = items.values().filter(|item| !staged.items.contains_key(&item.id));
+        self.check_name_among(untouched.chain(staged.items.values().flatten()), name, id)
+    }
+
+    fn stage_update(&self, staged: &mut StagedWrites, current: Item, mut proposed: Item, expected_version: Option<u64>) -> Result<Item> {
+        VersionConflict::check(expected_version, &current, &proposed)?;
+        proposed.updated_at = chrono::Utc::now();
+        proposed.version += 1;
+        staged.stage(Some(&current), StagedWrite::Updated(proposed.clone()));
+        Ok(proposed)
+    }
+
+    /// Applies `staged` under a single write lock, or nothing of it when an
+    /// item it touched has been changed since, or a name it sets has been
+    /// taken since.
+    pub(crate) fn commit_staged(&self, staged: StagedWrites) -> Result<()> {
+        let mut items = self.items.write()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        for (id, version) in &staged.versions {
+            if items.get(id).map(|item| item.version) != *version {
+                return Err(AppError::Conflict(format!(
+                    "Item {} was changed by another request before this one finished; nothing was saved",
+                    id
+                )));
+            }
+        }
+        for item in staged.items.values().flatten() {
+            let untouched = items.values().filter(|other| !staged.items.contains_key(&other.id));
+            self.check_name_among(untouched.chain(staged.items.values().flatten()), &item.name, Some(item.id))?;
+        }
+
+        for write in staged.writes {
+            match write {
+                StagedWrite::Created(item) => {
+                    self.tag_index_mut()?.insert(item.id, &item.tags);
+                    items.insert(item.id, item.clone());
+                    self.record_change(ChangeOp::Created, item.id, Some(&item))?;
+                }
+                StagedWrite::Updated(item) => {
+                    let Some(current) = items.get_mut(&item.id) else {
+                        continue;
+                    };
+                    self.tag_index_mut()?.replace(item.id, &current.tags, &item.tags);
+                    *current = item.clone();
+                    self.record_change(ChangeOp::Updated, item.id, Some(&item))?;
+                }
+                StagedWrite::Deleted(id) => {
+                    let Some(item) = items.remove(&id) else {
+                        continue;
+                    };
+                    self.tag_index_mut()?.remove(id, &item.tags);
+                    self.trash.write()
+                        .map_err(|_| AppError::InternalServerError)?
+                        .insert(id, (item, chrono::Utc::now()));
+                    self.record_change(ChangeOp::Deleted, id, None)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Items sharing a name under `rule`, in id order.
+    pub fn duplicate_names(&self, rule: &NameRule) -> Result<Vec<DuplicateName>> {
+        let items = self.items.read()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        let mut sorted: Vec<&Item> = items.values().collect();
+        sorted.sort_by_key(|item| item.id);
+        let mut groups: HashMap<String, DuplicateName> = HashMap::new();
+        for item in sorted {
+            groups.entry(rule.key(&item.name))
+                .or_insert_with(|| DuplicateName { name: item.name.clone(), namespace: None, item_ids: Vec::new() })
+                .item_ids
+                .push(item.id);
         }
+
+        let mut duplicates: Vec<DuplicateName> = groups.into_values()
+            .filter(|duplicate| duplicate.item_ids.len() > 1)
+            .collect();
+        duplicates.sort_by_key(|duplicate| duplicate.item_ids[0]);
+        Ok(duplicates)
+    }
+
+    /// Up to `limit` changes recorded after sequence number `since`.
+    pub fn changes_since(&self, since: u64, limit: usize) -> Result<ChangePage> {
+        let changes = self.changes.read()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        changes.since(since, limit)
+    }
+
+    /// Records a change while the caller still holds the items lock, so the
+    /// log orders changes the same way they were applied.
+    fn record_change(&self, op: ChangeOp, item_id: u64, item: Option<&Item>) -> Result<()> {
+        let mut changes = self.changes.write()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        changes.record(op, item_id, item.cloned());
+        Ok(())
+    }
+
+    fn tag_index_mut(&self) -> Result<std::sync::RwLockWriteGuard<'_, TagIndex>> {
+        self.tag_index.write()
+            .map_err(|_| AppError::InternalServerError)
     }
 
     pub fn get_items(&self, limit: Option<usize>, offset: Option<usize>) -> Result<Vec<Item>> {
@@ -77,6 +354,10 @@ impl DataStore {
     }
 
     pub fn get_item(&self, id: u64) -> Result<Item> {
+        if let Some(staged) = transaction::staged_writes(self) {
+            return self.staged_item(&lock_staged(&staged), id);
+        }
+
         let items = self.items.read()
             .map_err(|_| AppError::InternalServerError)?;
         
@@ -85,10 +366,59 @@ impl DataStore {
             .ok_or_else(|| AppError::NotFound(format!("Item with id {} not found", id)))
     }
 
+    /// Items carrying any of `tags`, in id order, found through the tag
+    /// index rather than by scanning every item.
+    pub fn get_by_tags(&self, tags: &[String], limit: Option<usize>, offset: Option<usize>) -> Result<Vec<Item>> {
+        let items = self.items.read()
+            .map_err(|_| AppError::InternalServerError)?;
+        let index = self.tag_index.read()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        let ids: BTreeSet<u64> = tags.iter()
+            .filter_map(|tag| index.ids(tag))
+            .flatten()
+            .copied()
+            .collect();
+        Ok(ids
+            .into_iter()
+            .skip(offset.unwrap_or(0))
+            .take(limit.unwrap_or(usize::MAX))
+            .filter_map(|id| items.get(&id).cloned())
+            .collect())
+    }
+
+    /// Number of items carrying `tag`.
+    pub fn count_by_tag(&self, tag: &str) -> Result<u64> {
+        let index = self.tag_index.read()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        Ok(index.ids(tag).map_or(0, |ids| ids.len() as u64))
+    }
+
     pub fn create_item(&self, name: String, description: Option<String>, tags: Vec<String>, metadata: Option<serde_json::Value>) -> Result<Item> {
+        if let Some(staged) = transaction::staged_writes(self) {
+            let mut staged = lock_staged(&staged);
+            self.check_staged_name(&staged, &name, None)?;
+            let item = self.new_item(name, description, tags, metadata)?;
+            staged.stage(None, StagedWrite::Created(item.clone()));
+            return Ok(item);
+        }
+
         let mut items = self.items.write()
             .map_err(|_| AppError::InternalServerError)?;
+        self.check_name(&items, &name, None)?;
         
+        let item = self.new_item(name, description, tags, metadata)?;
+        let id = item.id;
+        
+        self.tag_index_mut()?.insert(id, &item.tags);
+        items.insert(id, item.clone());
+        self.record_change(ChangeOp::Created, id, Some(&item))?;
+        Ok(item)
+    }
+
+    /// A new item with the next id, not yet stored.
+    fn new_item(&self, name: String, description: Option<String>, tags: Vec<String>, metadata: Option<serde_json::Value>) -> Result<Item> {
         let mut next_id = self.next_id.write()
             .map_err(|_| AppError::InternalServerError)?;
         
@@ -96,99 +426,494 @@ impl DataStore {
         *next_id += 1;
         
         let now = chrono::Utc::now();
-        let item = Item {
+        Ok(Item {
             id,
             name,
             description,
             created_at: now,
             updated_at: now,
             tags,
-            metadata,
-        };
-        
-        items.insert(id, item.clone());
-        Ok(item)
+            metadata: metadata.map(Metadata::from),
+            version: INITIAL_ITEM_VERSION,
+        })
     }
 
-    pub fn update_item(&self, id: u64, name: String, description: Option<String>, tags: Vec<String>, metadata: Option<serde_json::Value>) -> Result<Item> {
+    /// Replaces the item's fields. With `expected_version`, fails with a
+    /// conflict instead when the item is at another version.
+    pub fn update_item(
+        &self,
+        id: u64,
+        name: String,
+        description: Option<String>,
+        tags: Vec<String>,
+        metadata: Option<serde_json::Value>,
+        expected_version: Option<u64>,
+    ) -> Result<Item> {
+        if let Some(staged) = transaction::staged_writes(self) {
+            let mut staged = lock_staged(&staged);
+            self.check_staged_name(&staged, &name, Some(id))?;
+            let current = self.staged_item(&staged, id)?;
+            let proposed = Item {
+                name,
+                description,
+                tags,
+                metadata: metadata.map(Metadata::from),
+                ..current.clone()
+            };
+            return self.stage_update(&mut staged, current, proposed, expected_version);
+        }
+
         let mut items = self.items.write()
             .map_err(|_| AppError::InternalServerError)?;
+        self.check_name(&items, &name, Some(id))?;
         
         let item = items.get_mut(&id)
             .ok_or_else(|| AppError::NotFound(format!("Item with id {} not found", id)))?;
         
-        item.name = name;
-        item.description = description;
-        item.tags = tags;
-        item.metadata = metadata;
+        let proposed = Item {
+            name,
+            description,
+            tags,
+            metadata: metadata.map(Metadata::from),
+            ..item.clone()
+        };
+        VersionConflict::check(expected_version, item, &proposed)?;
+        
+        self.tag_index_mut()?.replace(id, &item.tags, &proposed.tags);
+        *item = proposed;
         item.updated_at = chrono::Utc::now();
+        item.version += 1;
         
-        Ok(item.clone())
+        let item = item.clone();
+        self.record_change(ChangeOp::Updated, id, Some(&item))?;
+        Ok(item)
     }
 
-    pub fn patch_item(&self, id: u64, updates: HashMap<String, serde_json::Value>) -> Result<Item> {
+    /// As [`update_item`](Self::update_item), changing only the fields
+    /// present in `updates`.
+    pub fn patch_item(&self, id: u64, updates: HashMap<String, serde_json::Value>, expected_version: Option<u64>) -> Result<Item> {
+        if let Some(staged) = transaction::staged_writes(self) {
+            let mut staged = lock_staged(&staged);
+            if let Some(name) = updates.get("name").and_then(|v| v.as_str()) {
+                self.check_staged_name(&staged, name, Some(id))?;
+            }
+            let current = self.staged_item(&staged, id)?;
+            let proposed = apply_patch(&current, &updates);
+            return self.stage_update(&mut staged, current, proposed, expected_version);
+        }
+
         let mut items = self.items.write()
             .map_err(|_| AppError::InternalServerError)?;
-        
-        let item = items.get_mut(&id)
-            .ok_or_else(|| AppError::NotFound(format!("Item with id {} not found", id)))?;
-        
         if let Some(name) = updates.get("name").and_then(|v| v.as_str()) {
-            item.name = name.to_string();
+            self.check_name(&items, name, Some(id))?;
         }
         
-        if let Some(desc) = updates.get("description") {
-            if desc.is_null() {
-                item.description = None;
-            } else if let Some(desc_str) = desc.as_str() {
-                item.description = Some(desc_str.to_string());
-            }
-        }
-        
-        if let Some(tags) = updates.get("tags").and_then(|v| v.as_array()) {
-            item.tags = tags.iter()
-                .filter_map(|v| v.as_str().map(String::from))
-                .collect();
-        }
-        
-        if let Some(metadata) = updates.get("metadata") {
-            if metadata.is_null() {
-                item.metadata = None;
-            } else {
-                item.metadata = Some(metadata.clone());
-            }
-        }
+        let current = items.get_mut(&id)
+            .ok_or_else(|| AppError::NotFound(format!("Item with id {} not found", id)))?;
+        let mut item = apply_patch(current, &updates);
         
+        VersionConflict::check(expected_version, current, &item)?;
         item.updated_at = chrono::Utc::now();
+        item.version += 1;
+        self.tag_index_mut()?.replace(id, &current.tags, &item.tags);
+        *current = item.clone();
         
-        Ok(item.clone())
+        self.record_change(ChangeOp::Updated, id, Some(&item))?;
+        Ok(item)
     }
 
+    /// Moves the item to the trash until [`purge_deleted`](Self::purge_deleted)
+    /// removes it.
     pub fn delete_item(&self, id: u64) -> Result<()> {
+        if let Some(staged) = transaction::staged_writes(self) {
+            let mut staged = lock_staged(&staged);
+            let current = self.staged_item(&staged, id)?;
+            staged.stage(Some(&current), StagedWrite::Deleted(id));
+            return Ok(());
+        }
+
         let mut items = self.items.write()
             .map_err(|_| AppError::InternalServerError)?;
         
-        items.remove(&id)
+        let item = items.remove(&id)
             .ok_or_else(|| AppError::NotFound(format!("Item with id {} not found", id)))?;
+        self.tag_index_mut()?.remove(id, &item.tags);
+        self.trash.write()
+            .map_err(|_| AppError::InternalServerError)?
+            .insert(id, (item, chrono::Utc::now()));
+        self.record_change(ChangeOp::Deleted, id, None)?;
         
         Ok(())
     }
 
+    /// Items in the trash, most recently deleted first, with when they were
+    /// deleted.
+    pub fn trashed_items(&self, limit: usize, offset: usize) -> Result<Vec<(Item, chrono::DateTime<chrono::Utc>)>> {
+        let trash = self.trash.read()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        let mut trashed: Vec<_> = trash.values().cloned().collect();
+        trashed.sort_by(|a, b| b.1.cmp(&a.1).then(b.0.id.cmp(&a.0.id)));
+        Ok(trashed.into_iter().skip(offset).take(limit).collect())
+    }
+
+    /// Takes an item out of the trash, recording it as created again.
+    pub fn restore_item(&self, id: u64) -> Result<Item> {
+        let mut items = self.items.write()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        let mut trash = self.trash.write()
+            .map_err(|_| AppError::InternalServerError)?;
+        let (item, _) = trash.get(&id)
+            .ok_or_else(|| AppError::NotFound(format!("Item with id {} is not in the trash", id)))?;
+        self.check_name(&items, &item.name, Some(id))?;
+        let (item, _) = trash.remove(&id).ok_or(AppError::InternalServerError)?;
+        drop(trash);
+        self.tag_index_mut()?.insert(id, &item.tags);
+        items.insert(id, item.clone());
+        self.record_change(ChangeOp::Created, id, Some(&item))?;
+
+        Ok(item)
+    }
+
+    /// Permanently removes items deleted before `cutoff` and their change
+    /// log entries. A dry run only counts them.
+    pub fn purge_deleted(&self, cutoff: chrono::DateTime<chrono::Utc>, batch_size: usize, dry_run: bool) -> Result<PurgeReport> {
+        let mut trash = self.trash.write()
+            .map_err(|_| AppError::InternalServerError)?;
+        let mut changes = self.changes.write()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        let expired: HashSet<u64> = trash.iter()
+            .filter(|(_, (_, deleted_at))| *deleted_at < cutoff)
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut report = PurgeReport::new(cutoff, dry_run);
+        report.items = expired.len() as u64;
+        report.batches = report.items.div_ceil(batch_size as u64);
+        if dry_run {
+            report.changes_removed = changes.count_for(&expired) as u64;
+        } else {
+            report.changes_removed = changes.forget(&expired) as u64;
+            trash.retain(|id, _| !expired.contains(id));
+        }
+
+        Ok(report)
+    }
+
     pub fn get_stats(&self) -> Result<serde_json::Value> {
         let items = self.items.read()
             .map_err(|_| AppError::InternalServerError)?;
+        let index = self.tag_index.read()
+            .map_err(|_| AppError::InternalServerError)?;
         
-        let total_items = items.len();
-        let tags: std::collections::HashSet<String> = items.values()
-            .flat_map(|item| item.tags.iter().cloned())
-            .collect();
+        let tags: Vec<&String> = index.items_by_tag.keys().collect();
         
         Ok(serde_json::json!({
-            "total_items": total_items,
+            "total_items": items.len(),
             "unique_tags": tags.len(),
             "tags": tags,
+            "tag_index": {
+                "tags": index.items_by_tag.len(),
+                "entries": index.entries(),
+                "approximate_bytes": index.approximate_bytes(),
+            },
         }))
     }
+
+    /// Tags in use with the number of items carrying each, most used first.
+    pub fn tag_counts(&self) -> Result<Vec<TagCount>> {
+        let items = self.items.read()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        Ok(rank_tags(&items))
+    }
+
+    /// Tags in use starting with `prefix`, ignoring case, most used first.
+    pub fn tags_with_prefix(&self, prefix: &str, limit: usize) -> Result<Vec<TagCount>> {
+        let prefix = prefix.to_lowercase();
+        Ok(self
+            .tag_counts()?
+            .into_iter()
+            .filter(|tag| tag.tag.to_lowercase().starts_with(&prefix))
+            .take(limit)
+            .collect())
+    }
+
+    /// Applies `rewrite` to every item carrying one of its source tags under
+    /// a single write lock, as the database does in one transaction.
+    pub fn rewrite_tags(&self, rewrite: &TagRewrite, dry_run: bool) -> Result<Vec<Item>> {
+        let mut items = self.items.write()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        let now = chrono::Utc::now();
+        let mut ids: Vec<u64> = items.keys().copied().collect();
+        ids.sort_unstable();
+
+        let mut changed = Vec::new();
+        for id in ids {
+            let Some(item) = items.get_mut(&id) else {
+                continue;
+            };
+            let Some(tags) = rewrite.apply(&item.tags) else {
+                continue;
+            };
+            if dry_run {
+                let mut preview = item.clone();
+                preview.tags = tags;
+                changed.push(preview);
+            } else {
+                self.tag_index_mut()?.replace(id, &item.tags, &tags);
+                item.tags = tags;
+                item.updated_at = now;
+                item.version += 1;
+                self.record_change(ChangeOp::Updated, id, Some(item))?;
+                changed.push(item.clone());
+            }
+        }
+
+        Ok(changed)
+    }
+
+    /// Runs `edit` over up to `limit` items with ids above `after_id`, in id
+    /// order, storing the metadata it changes. The write lock is held for
+    /// the whole batch, so no other write can come between reading an item
+    /// and writing it back.
+    pub fn transform_metadata(
+        &self,
+        after_id: u64,
+        limit: usize,
+        dry_run: bool,
+        edit: &(dyn Fn(&Item) -> MetadataOutcome + Send + Sync),
+    ) -> Result<MetadataBatch> {
+        let mut items = self.items.write()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        let now = chrono::Utc::now();
+        let mut ids: Vec<u64> = items.keys().copied().filter(|id| *id > after_id).collect();
+        ids.sort_unstable();
+        ids.truncate(limit);
+
+        let mut batch = MetadataBatch {
+            scanned: ids.len(),
+            last_id: ids.last().copied(),
+            ..MetadataBatch::default()
+        };
+        for id in ids {
+            let Some(item) = items.get_mut(&id) else {
+                continue;
+            };
+            let metadata = match edit(item) {
+                MetadataOutcome::Skipped => continue,
+                MetadataOutcome::Unchanged => {
+                    batch.unchanged += 1;
+                    continue;
+                }
+                MetadataOutcome::Failed(error) => {
+                    batch.failed.push((id, error));
+                    continue;
+                }
+                MetadataOutcome::Changed(metadata) => metadata,
+            };
+
+            let before = item.clone();
+            if dry_run {
+                let mut preview = item.clone();
+                preview.metadata = Some(Metadata::from_value(&metadata));
+                batch.changed.push((before, preview));
+            } else {
+                item.metadata = Some(Metadata::from(metadata));
+                item.updated_at = now;
+                item.version += 1;
+                self.record_change(ChangeOp::Updated, id, Some(item))?;
+                batch.changed.push((before, item.clone()));
+            }
+        }
+
+        Ok(batch)
+    }
+
+    /// Up to `limit` items with ids above `after_id` whose metadata holds a
+    /// secret, in id order, including those in the trash.
+    pub fn items_with_secrets(&self, after_id: u64, limit: usize) -> Result<Vec<Item>> {
+        let items = self.items.read()
+            .map_err(|_| AppError::InternalServerError)?;
+        let trash = self.trash.read()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        let has_secret = |item: &&Item| {
+            item.id > after_id
+                && item.metadata.as_ref().and_then(|m| m.get("secret")).is_some_and(|s| !s.is_null())
+        };
+        let mut found: Vec<Item> = items.values()
+            .chain(trash.values().map(|(item, _)| item))
+            .filter(has_secret)
+            .cloned()
+            .collect();
+        found.sort_by_key(|item| item.id);
+        found.truncate(limit);
+        Ok(found)
+    }
+
+    /// Stores `metadata` for the item, in the store or the trash, if it is
+    /// still at `version`, leaving its version, update time and the change
+    /// log alone. Returns whether the item was still at that version.
+    pub fn replace_metadata(&self, id: u64, version: u64, metadata: serde_json::Value) -> Result<bool> {
+        let mut items = self.items.write()
+            .map_err(|_| AppError::InternalServerError)?;
+        let mut trash = self.trash.write()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        let item = match items.get_mut(&id) {
+            Some(item) => Some(item),
+            None => trash.get_mut(&id).map(|(item, _)| item),
+        };
+        match item {
+            Some(item) if item.version == version => {
+                item.metadata = Some(Metadata::from(metadata));
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// The same statistics the database computes with grouped queries.
+    /// Items kept in memory have no creator.
+    pub fn item_stats(&self, breakdowns: &StatsBreakdowns, top: usize) -> Result<ItemStats> {
+        let items = self.items.read()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        let mut stats = ItemStats::new(items.len() as u64, "memory");
+
+        if breakdowns.tags {
+            let mut ranked = rank_tags(&items);
+            let unique_tags = ranked.len() as u64;
+            ranked.truncate(top);
+            stats.tags = Some(TagStats { unique_tags, top: ranked });
+        }
+
+        if breakdowns.creators {
+            stats.creators = Some(if items.is_empty() {
+                Vec::new()
+            } else {
+                vec![CreatorCount { created_by: None, username: None, count: items.len() as u64 }]
+            });
+        }
+
+        if breakdowns.daily {
+            let mut counts = HashMap::new();
+            for item in items.values() {
+                *counts.entry(item.created_at.date_naive()).or_default() += 1;
+            }
+            stats.created_per_day = Some(daily_counts(chrono::Utc::now().date_naive(), &counts));
+        }
+
+        if breakdowns.metadata {
+            let sizes: Vec<usize> = items
+                .values()
+                .filter_map(|item| item.metadata.as_ref())
+                .filter(|metadata| !matches!(metadata.as_str(), "null" | "{}"))
+                .map(|metadata| metadata.as_str().len())
+                .collect();
+            stats.metadata = Some(MetadataStats {
+                items_with_metadata: sizes.len() as u64,
+                average_size_bytes: if sizes.is_empty() {
+                    0.0
+                } else {
+                    sizes.iter().sum::<usize>() as f64 / sizes.len() as f64
+                },
+            });
+        }
+
+        if breakdowns.descriptions {
+            let with_description = items
+                .values()
+                .filter(|item| item.description.as_deref().is_some_and(|description| !description.trim().is_empty()))
+                .count() as u64;
+            stats.descriptions = Some(DescriptionStats {
+                with_description,
+                without_description: items.len() as u64 - with_description,
+            });
+        }
+
+        Ok(stats)
+    }
+}
+
+/// Every tag with its item count, ordered as the database orders them.
+fn rank_tags(items: &HashMap<u64, Item>) -> Vec<TagCount> {
+    let mut counts: HashMap<&str, u64> = HashMap::new();
+    for tag in items.values().flat_map(|item| item.tags.iter()) {
+        *counts.entry(tag.as_str()).or_default() += 1;
+    }
+    let mut ranked: Vec<TagCount> = counts
+        .iter()
+        .map(|(tag, count)| TagCount { tag: tag.to_string(), count: *count })
+        .collect();
+    ranked.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+    ranked
+}
+
+/// Ids of the items carrying each tag.
+#[derive(Debug, Default)]
+struct TagIndex {
+    items_by_tag: HashMap<String, HashSet<u64>>,
+}
+
+impl TagIndex {
+    fn build(items: &HashMap<u64, Item>) -> Self {
+        let mut index = Self::default();
+        for item in items.values() {
+            index.insert(item.id, &item.tags);
+        }
+        index
+    }
+
+    fn ids(&self, tag: &str) -> Option<&HashSet<u64>> {
+        self.items_by_tag.get(tag)
+    }
+
+    fn insert(&mut self, id: u64, tags: &[String]) {
+        for tag in tags {
+            self.items_by_tag.entry(tag.clone()).or_default().insert(id);
+        }
+    }
+
+    /// Drops tags no item carries any more, so that the index never holds
+    /// more tags than the items do.
+    fn remove(&mut self, id: u64, tags: &[String]) {
+        for tag in tags {
+            if let Some(ids) = self.items_by_tag.get_mut(tag) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.items_by_tag.remove(tag);
+                }
+            }
+        }
+    }
+
+    fn replace(&mut self, id: u64, old: &[String], new: &[String]) {
+        self.remove(id, old);
+        self.insert(id, new);
+    }
+
+    /// Item ids held across all tags.
+    fn entries(&self) -> usize {
+        self.items_by_tag.values().map(HashSet::len).sum()
+    }
+
+    /// Rough heap usage: the tag strings, their sets, and a control byte
+    /// per hash table slot.
+    fn approximate_bytes(&self) -> usize {
+        let slot = std::mem::size_of::<(String, HashSet<u64>)>() + 1;
+        self.items_by_tag.capacity() * slot
+            + self
+                .items_by_tag
+                .iter()
+                .map(|(tag, ids)| tag.capacity() + ids.capacity() * (std::mem::size_of::<u64>() + 1))
+                .sum::<usize>()
+    }
 }
 
 impl Default for DataStore {
@@ -0,0 +1,276 @@
+//! Supervision of the server's background loops
+//!
+//! [`Supervisor::spawn`] runs a loop on the runtime and starts it again,
+//! after a pause that doubles each time, when it panics. A task that panics
+//! more than [`RestartPolicy::max_restarts`] times within
+//! [`RestartPolicy::window`] is left dead: the `background_tasks` health
+//! check turns Degraded and admins are sent a `TaskDead` event. The state of
+//! every task is listed at `GET /api/system/tasks`.
+
+use chrono::{DateTime, Utc};
+use futures_util::FutureExt;
+use parking_lot::RwLock;
+use serde::Serialize;
+use std::any::Any;
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+use crate::websocket::{WebSocketManager, WebSocketMessage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Running,
+    /// Panicked, and waiting out its backoff before starting again.
+    Restarting,
+    /// Returned on its own; it is not started again.
+    Finished,
+    /// Used up its restart budget.
+    Dead,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskStatus {
+    pub name: String,
+    pub state: TaskState,
+    /// Restarts since the task was spawned.
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub last_restart_at: Option<DateTime<Utc>>,
+}
+
+/// How often a panicking task is started again.
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    /// Pause before the first restart; each restart within the window
+    /// doubles it.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Restarts allowed within `window` before the task is given up on.
+    pub max_restarts: u32,
+    pub window: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            max_restarts: 5,
+            window: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+/// Spawns background tasks and keeps a registry of their states. Clones
+/// share the registry.
+#[derive(Clone, Default)]
+pub struct Supervisor {
+    tasks: Arc<RwLock<BTreeMap<String, TaskStatus>>>,
+    policy: RestartPolicy,
+    alerts: Option<WebSocketManager>,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_policy(mut self, policy: RestartPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sends admins a `TaskDead` event when a task is given up on.
+    pub fn with_alerts(mut self, websocket_manager: WebSocketManager) -> Self {
+        self.alerts = Some(websocket_manager);
+        self
+    }
+
+    /// Runs the future `task` makes under the name `name`, making a fresh
+    /// one each time it has to be restarted. A second task with the same
+    /// name replaces the first in the registry.
+    pub fn spawn<F, Fut>(&self, name: impl Into<String>, task: F) -> JoinHandle<()>
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let name = name.into();
+        self.tasks.write().insert(
+            name.clone(),
+            TaskStatus {
+                name: name.clone(),
+                state: TaskState::Running,
+                restarts: 0,
+                last_error: None,
+                started_at: Utc::now(),
+                last_restart_at: None,
+            },
+        );
+
+        let supervisor = self.clone();
+        tokio::spawn(async move {
+            let policy = supervisor.policy.clone();
+            let mut recent_restarts = VecDeque::new();
+            let mut backoff = policy.initial_backoff;
+
+            loop {
+                let outcome = match std::panic::catch_unwind(AssertUnwindSafe(&task)) {
+                    Ok(future) => AssertUnwindSafe(future).catch_unwind().await,
+                    Err(panic) => Err(panic),
+                };
+                let error = match outcome {
+                    Ok(()) => {
+                        info!("Background task {} finished", name);
+                        supervisor.update(&name, |status| status.state = TaskState::Finished);
+                        return;
+                    }
+                    Err(panic) => panic_message(panic.as_ref()),
+                };
+
+                let now = Instant::now();
+                recent_restarts.retain(|at: &Instant| now.duration_since(*at) < policy.window);
+                if recent_restarts.is_empty() {
+                    backoff = policy.initial_backoff;
+                }
+                if recent_restarts.len() as u32 >= policy.max_restarts {
+                    supervisor.give_up(&name, error).await;
+                    return;
+                }
+                recent_restarts.push_back(now);
+
+                warn!("Background task {} panicked, restarting in {:?}: {}", name, backoff, error);
+                supervisor.update(&name, |status| {
+                    status.state = TaskState::Restarting;
+                    status.last_error = Some(error);
+                });
+
+                tokio::time::sleep(backoff).await;
+                backoff = backoff.saturating_mul(2).min(policy.max_backoff);
+
+                supervisor.update(&name, |status| {
+                    status.state = TaskState::Running;
+                    status.restarts += 1;
+                    status.last_restart_at = Some(Utc::now());
+                });
+            }
+        })
+    }
+
+    /// Every task spawned so far, by name.
+    pub fn statuses(&self) -> Vec<TaskStatus> {
+        self.tasks.read().values().cloned().collect()
+    }
+
+    pub fn status(&self, name: &str) -> Option<TaskStatus> {
+        self.tasks.read().get(name).cloned()
+    }
+
+    fn update(&self, name: &str, change: impl FnOnce(&mut TaskStatus)) {
+        if let Some(status) = self.tasks.write().get_mut(name) {
+            change(status);
+        }
+    }
+
+    async fn give_up(&self, name: &str, error: String) {
+        let mut restarts = 0;
+        self.update(name, |status| {
+            status.state = TaskState::Dead;
+            status.last_error = Some(error.clone());
+            restarts = status.restarts;
+        });
+        error!(
+            "Background task {} panicked again after {} restarts and will not be restarted: {}",
+            name, restarts, error
+        );
+
+        if let Some(websocket_manager) = &self.alerts {
+            websocket_manager
+                .send_to_admins(WebSocketMessage::TaskDead {
+                    name: name.to_string(),
+                    restarts,
+                    error,
+                })
+                .await;
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "task panicked".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn quick_policy(max_restarts: u32) -> RestartPolicy {
+        RestartPolicy {
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+            max_restarts,
+            window: Duration::from_secs(60),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panicking_task_is_restarted_until_its_budget_runs_out() {
+        let supervisor = Supervisor::new().with_policy(quick_policy(3));
+        let runs = Arc::new(AtomicU32::new(0));
+
+        let handle = supervisor.spawn("flaky", {
+            let runs = runs.clone();
+            move || {
+                let runs = runs.clone();
+                async move {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    panic!("lock poisoned");
+                }
+            }
+        });
+        handle.await.unwrap();
+
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+        let status = supervisor.status("flaky").unwrap();
+        assert_eq!(status.state, TaskState::Dead);
+        assert_eq!(status.restarts, 3);
+        assert_eq!(status.last_error.as_deref(), Some("lock poisoned"));
+    }
+
+    #[tokio::test]
+    async fn test_task_recovers_after_a_panic() {
+        let supervisor = Supervisor::new().with_policy(quick_policy(3));
+        let runs = Arc::new(AtomicU32::new(0));
+
+        let handle = supervisor.spawn("recovers", {
+            let runs = runs.clone();
+            move || {
+                let runs = runs.clone();
+                async move {
+                    if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                        panic!("first run fails: {}", 42);
+                    }
+                }
+            }
+        });
+        handle.await.unwrap();
+
+        let status = supervisor.status("recovers").unwrap();
+        assert_eq!(status.state, TaskState::Finished);
+        assert_eq!(status.restarts, 1);
+        assert_eq!(status.last_error.as_deref(), Some("first run fails: 42"));
+        assert_eq!(supervisor.statuses().len(), 1);
+    }
+}
@@ -0,0 +1,99 @@
+//! Namespaces that keep each tenant's items, files, jobs and webhooks apart
+//!
+//! Every request runs inside the namespace of its caller (see
+//! [`namespace_middleware`](crate::middleware::namespace::namespace_middleware)),
+//! and the repositories restrict their queries to [`current`]. Work started
+//! outside a request, such as job workers and startup tasks, is unscoped and
+//! sees every namespace.
+
+use crate::error::{AppError, Result};
+use std::future::Future;
+
+/// Namespace of callers without a tenant claim, and of all data created
+/// before namespaces existed.
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// Header through which admins act in another tenant's namespace.
+pub const NAMESPACE_HEADER: &str = "x-namespace";
+
+const MAX_NAMESPACE_LEN: usize = 64;
+
+tokio::task_local! {
+    static CURRENT_NAMESPACE: String;
+}
+
+/// Runs `future` with repository queries restricted to `namespace`.
+pub async fn scope<F: Future>(namespace: String, future: F) -> F::Output {
+    CURRENT_NAMESPACE.scope(namespace, future).await
+}
+
+/// The namespace the current task is restricted to, or `None` when it is
+/// unscoped.
+pub fn current() -> Option<String> {
+    CURRENT_NAMESPACE.try_with(Clone::clone).ok()
+}
+
+/// The namespace new rows are created in.
+pub fn current_or_default() -> String {
+    current().unwrap_or_else(|| DEFAULT_NAMESPACE.to_string())
+}
+
+/// Namespaces are 1-64 lowercase ASCII letters, digits, `-` or `_`.
+pub fn validate_namespace(namespace: &str) -> Result<()> {
+    let valid = !namespace.is_empty()
+        && namespace.len() <= MAX_NAMESPACE_LEN
+        && namespace
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_');
+    if !valid {
+        return Err(AppError::BadRequest(format!(
+            "Invalid namespace '{}': use 1-{} lowercase letters, digits, '-' or '_'",
+            namespace, MAX_NAMESPACE_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// `key` made specific to the current namespace. Keys in the default
+/// namespace are unchanged, so single-tenant caches look as they always
+/// have; others gain a `#namespace` suffix, which cannot occur in a request
+/// target.
+pub fn scoped_cache_key(key: String) -> String {
+    match current() {
+        Some(namespace) if namespace != DEFAULT_NAMESPACE => format!("{}#{}", key, namespace),
+        _ => key,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scope_sets_current_namespace() {
+        assert_eq!(current(), None);
+        assert_eq!(current_or_default(), DEFAULT_NAMESPACE);
+        assert_eq!(scoped_cache_key("items".to_string()), "items");
+
+        scope("acme".to_string(), async {
+            assert_eq!(current().as_deref(), Some("acme"));
+            assert_eq!(scoped_cache_key("items".to_string()), "items#acme");
+        })
+        .await;
+
+        scope(DEFAULT_NAMESPACE.to_string(), async {
+            assert_eq!(scoped_cache_key("items".to_string()), "items");
+        })
+        .await;
+    }
+
+    #[test]
+    fn test_validate_namespace() {
+        assert!(validate_namespace("acme").is_ok());
+        assert!(validate_namespace("team_2-eu").is_ok());
+        assert!(validate_namespace("").is_err());
+        assert!(validate_namespace("Acme").is_err());
+        assert!(validate_namespace("a b").is_err());
+        assert!(validate_namespace(&"a".repeat(65)).is_err());
+    }
+}
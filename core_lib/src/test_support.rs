@@ -0,0 +1,185 @@
+//! Fixtures for tests that need a fully wired [`AppState`]. Available to this
+//! crate's tests and, behind the `test_support` feature, to other crates.
+
+use crate::auth::models::{CreateUserRequest, LoginRequest, UserRole};
+use crate::clock::MockClock;
+use crate::config::AppConfig;
+use crate::ids::SequentialIds;
+use crate::state_builder::AppStateBuilder;
+use crate::AppState;
+use chrono::{TimeZone, Utc};
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::SqlitePool;
+use tempfile::TempDir;
+
+mod harness;
+
+pub use harness::{assert_golden, TestRequest, TestResponse, TestServer, TestWebSocket, DEFAULT_PEER};
+
+pub const TEST_JWT_SECRET: &str = "test-support-secret-at-least-32-characters";
+
+/// Password [`TestApp::login_as`] registers users with.
+pub const TEST_PASSWORD: &str = "Tr0ub4dor&Zebra9";
+
+/// Configuration for an in-memory state: fast password hashing, uploads in
+/// `storage_dir`, a single job worker and notifications off, so the only
+/// jobs are the ones a test submits.
+pub fn test_config(storage_dir: &std::path::Path) -> AppConfig {
+    let mut config = AppConfig::default();
+    config.database.url = "sqlite::memory:".to_string();
+    config.auth.jwt_secret = TEST_JWT_SECRET.to_string();
+    config.auth.password_hash_memory_kib = 4096;
+    config.auth.password_hash_iterations = 1;
+    config.auth.password_hash_parallelism = 1;
+    config.files.upload_dir = storage_dir.to_path_buf();
+    config.files.temp_dir = storage_dir.join("tmp");
+    config.jobs.max_workers = 1;
+    config.notifications.enabled = false;
+    config
+}
+
+/// A state backed by an in-memory database, with a clock and id generator
+/// the test controls. Uploads are removed when it is dropped.
+pub struct TestApp {
+    pub state: AppState,
+    pub clock: MockClock,
+    pub ids: SequentialIds,
+    pub pool: SqlitePool,
+    _storage: TempDir,
+}
+
+/// Builds a [`TestApp`] from [`test_config`]. The clock starts at
+/// 2024-01-01T00:00:00Z.
+pub async fn test_app() -> TestApp {
+    let storage = TempDir::new().expect("failed to create upload directory");
+    let config = test_config(storage.path());
+    test_app_with_config(config, storage).await
+}
+
+/// As [`test_app`], with a config the test has adjusted. Uploads go to
+/// `config.files.upload_dir`; `storage` is kept alive alongside the state.
+pub async fn test_app_with_config(config: AppConfig, storage: TempDir) -> TestApp {
+    // One connection that never expires, since each new connection to
+    // `sqlite::memory:` would open an empty database.
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .expect("failed to open in-memory database");
+
+    let clock = MockClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap());
+    let ids = SequentialIds::new();
+
+    let state = AppStateBuilder::new(config)
+        .with_database(pool.clone())
+        .with_clock(clock.shared())
+        .with_id_generator(ids.shared())
+        .build()
+        .await
+        .expect("failed to build test state");
+
+    TestApp {
+        state,
+        clock,
+        ids,
+        pool,
+        _storage: storage,
+    }
+}
+
+impl TestApp {
+    /// Registers `username` with `role` and returns an access token for it.
+    pub async fn login_as(&self, username: &str, role: UserRole) -> String {
+        let auth = self.state.auth_service.as_ref().expect("auth service");
+        auth.register_user(CreateUserRequest {
+            username: username.to_string(),
+            email: format!("{}@example.com", username),
+            password: TEST_PASSWORD.to_string(),
+            role: Some(role),
+        })
+        .await
+        .expect("failed to register test user");
+
+        auth.login(LoginRequest {
+            username: username.to_string(),
+            password: TEST_PASSWORD.to_string(),
+        })
+        .await
+        .expect("failed to log in test user")
+        .access_token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::SequentialIds;
+    use crate::jobs::{JobRequest, JobType};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_app_is_fully_wired_and_deterministic() {
+        let app = test_app().await;
+        let auth = app.state.auth_service.as_ref().expect("auth service");
+        assert!(app.state.item_service.is_using_database());
+        assert!(app.state.file_manager.is_some());
+        assert!(app.state.cache_manager.is_some());
+
+        let token = app.login_as("fixture", UserRole::User).await;
+        assert!(auth.jwt_service().validate_token(&token).is_ok());
+
+        app.clock.advance(Duration::from_secs(25 * 3600));
+        assert!(auth.jwt_service().validate_token(&token).is_err());
+
+        let job_id = app
+            .state
+            .job_queue
+            .as_ref()
+            .expect("job queue")
+            .submit_job(JobRequest {
+                job_type: JobType::ReportGeneration,
+                payload: serde_json::json!({}),
+                priority: None,
+                max_retries: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(job_id, SequentialIds::nth(1));
+    }
+
+    #[tokio::test]
+    async fn test_builder_falls_back_only_under_a_lenient_report() {
+        use crate::startup::{Component, StartupReport, StartupStatus};
+
+        let storage = TempDir::new().unwrap();
+        let mut config = test_config(storage.path());
+        config.auth.jwt_secret = "too short".to_string();
+        let pool = || async {
+            SqlitePoolOptions::new()
+                .max_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+                .connect("sqlite::memory:")
+                .await
+                .unwrap()
+        };
+
+        let strict = AppStateBuilder::new(config.clone()).with_database(pool().await).build().await;
+        assert!(strict.is_err());
+
+        let state = AppStateBuilder::new(config)
+            .with_database(pool().await)
+            .with_startup_report(StartupReport::new(false))
+            .build()
+            .await
+            .unwrap();
+        assert!(state.auth_service.is_none());
+        assert!(state.job_queue.is_some());
+        let report = state.startup_report.as_ref().unwrap();
+        assert_eq!(report.status(Component::Auth), Some(StartupStatus::Failed));
+        assert_eq!(report.status(Component::Files), Some(StartupStatus::Ok));
+        assert!(report.is_degraded());
+    }
+}
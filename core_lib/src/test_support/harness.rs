@@ -0,0 +1,278 @@
+//! Drives the full [`create_app_with_config`] router, middleware included,
+//! against a [`TestApp`].
+
+use super::{test_app_with_config, test_config, TestApp};
+use crate::auth::models::UserRole;
+use crate::config::AppConfig;
+use crate::metrics::MetricsCollector;
+use crate::{create_app_with_config, AppState, DataStore, ItemService};
+use axum::body::{Body, Bytes};
+use axum::extract::ConnectInfo;
+use axum::http::{header, HeaderMap, Method, Request, StatusCode};
+use axum::Router;
+use serde_json::Value;
+use std::net::SocketAddr;
+use tempfile::TempDir;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tower::ServiceExt;
+
+pub type TestWebSocket = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Address requests come from unless a test picks another.
+pub const DEFAULT_PEER: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 40000);
+
+/// The application router over a [`TestApp`], configured as in production
+/// apart from the fixtures in [`test_config`].
+pub struct TestServer {
+    pub app: TestApp,
+    router: Router,
+}
+
+impl TestServer {
+    pub async fn new() -> Self {
+        let storage = TempDir::new().expect("failed to create upload directory");
+        let config = test_config(storage.path());
+        Self::start(config, storage).await
+    }
+
+    /// As [`TestServer::new`], with `adjust` applied to the configuration
+    /// first.
+    pub async fn with_config(adjust: impl FnOnce(&mut AppConfig)) -> Self {
+        let storage = TempDir::new().expect("failed to create upload directory");
+        let mut config = test_config(storage.path());
+        adjust(&mut config);
+        Self::start(config, storage).await
+    }
+
+    /// As [`TestServer::new`], with items kept in an empty in-memory store
+    /// rather than the database, for comparing the two backends.
+    pub async fn with_memory_items() -> Self {
+        let storage = TempDir::new().expect("failed to create upload directory");
+        let config = test_config(storage.path());
+        let mut app = test_app_with_config(config.clone(), storage).await;
+        app.state.item_service = ItemService::with_memory_store(DataStore::empty());
+        let router = create_app_with_config(app.state.clone(), config);
+        Self { app, router }
+    }
+
+    async fn start(config: AppConfig, storage: TempDir) -> Self {
+        let app = test_app_with_config(config.clone(), storage).await;
+        let router = create_app_with_config(app.state.clone(), config);
+        Self { app, router }
+    }
+
+    pub fn state(&self) -> &AppState {
+        &self.app.state
+    }
+
+    /// The in-memory metrics the test server keeps by default.
+    pub fn metrics(&self) -> &MetricsCollector {
+        self.app
+            .state
+            .metrics
+            .in_memory()
+            .expect("the test server keeps metrics in memory")
+    }
+
+    pub fn request(&self, method: Method, uri: &str) -> TestRequest<'_> {
+        TestRequest {
+            server: self,
+            builder: Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::USER_AGENT, "test-harness"),
+            body: Body::empty(),
+            peer: DEFAULT_PEER,
+        }
+    }
+
+    pub fn get(&self, uri: &str) -> TestRequest<'_> {
+        self.request(Method::GET, uri)
+    }
+
+    pub fn post(&self, uri: &str) -> TestRequest<'_> {
+        self.request(Method::POST, uri)
+    }
+
+    pub fn put(&self, uri: &str) -> TestRequest<'_> {
+        self.request(Method::PUT, uri)
+    }
+
+    pub fn patch(&self, uri: &str) -> TestRequest<'_> {
+        self.request(Method::PATCH, uri)
+    }
+
+    pub fn delete(&self, uri: &str) -> TestRequest<'_> {
+        self.request(Method::DELETE, uri)
+    }
+
+    /// Registers `username` with `role` and returns an access token for it.
+    pub async fn login_as(&self, username: &str, role: UserRole) -> String {
+        self.app.login_as(username, role).await
+    }
+
+    /// Opens a WebSocket to `path`, authenticated with `token` when given.
+    /// Upgrades need a real connection, so this serves the router on a
+    /// loopback port for the rest of the test.
+    pub async fn websocket(&self, path: &str, token: Option<&str>) -> TestWebSocket {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = self.router.clone();
+        tokio::spawn(async move {
+            axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+                .unwrap();
+        });
+
+        let mut request = format!("ws://{}{}", addr, path).into_client_request().unwrap();
+        if let Some(token) = token {
+            request
+                .headers_mut()
+                .insert("sec-websocket-protocol", format!("bearer, {}", token).parse().unwrap());
+        }
+        let (socket, _) = tokio_tungstenite::connect_async(request)
+            .await
+            .expect("WebSocket upgrade failed");
+        socket
+    }
+}
+
+/// A request being built against a [`TestServer`].
+pub struct TestRequest<'a> {
+    server: &'a TestServer,
+    builder: axum::http::request::Builder,
+    body: Body,
+    peer: SocketAddr,
+}
+
+impl TestRequest<'_> {
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.builder = self.builder.header(name, value);
+        self
+    }
+
+    pub fn bearer(self, token: &str) -> Self {
+        self.header(header::AUTHORIZATION.as_str(), &format!("Bearer {}", token))
+    }
+
+    pub fn json(mut self, body: &Value) -> Self {
+        self.builder = self.builder.header(header::CONTENT_TYPE, "application/json");
+        self.body = Body::from(body.to_string());
+        self
+    }
+
+    pub fn body(mut self, content_type: &str, body: impl Into<Body>) -> Self {
+        self.builder = self.builder.header(header::CONTENT_TYPE, content_type);
+        self.body = body.into();
+        self
+    }
+
+    /// Sends the request as if from `peer`, which rate limiting keys on.
+    pub fn from_peer(mut self, peer: SocketAddr) -> Self {
+        self.peer = peer;
+        self
+    }
+
+    pub async fn send(self) -> TestResponse {
+        let response = self.dispatch().await;
+        let (parts, body) = response.into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        TestResponse {
+            status: parts.status,
+            headers: parts.headers,
+            body,
+        }
+    }
+
+    /// The response status, leaving the body unread, for responses that
+    /// stream without end such as event streams.
+    pub async fn send_status(self) -> StatusCode {
+        self.dispatch().await.status()
+    }
+
+    async fn dispatch(self) -> axum::response::Response {
+        let mut request = self.builder.body(self.body).expect("invalid test request");
+        request.extensions_mut().insert(ConnectInfo(self.peer));
+        self.server.router.clone().oneshot(request).await.unwrap()
+    }
+}
+
+/// A fully read response.
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl TestResponse {
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body)
+            .unwrap_or_else(|e| panic!("response body is not JSON ({}): {}", e, self.text()))
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    /// The `X-Cache` status: `HIT` or `MISS` when the cache looked the request
+    /// up, otherwise `BYPASS`.
+    pub fn cache_status(&self) -> Option<&str> {
+        self.header("x-cache")
+    }
+}
+
+/// Fields that differ between runs and are blanked before comparing with a
+/// golden file.
+const VOLATILE_FIELDS: [&str; 8] = [
+    "created_at",
+    "updated_at",
+    "last_login",
+    "timestamp",
+    "retrieved_at",
+    "expires_at",
+    "access_token",
+    "refresh_token",
+];
+
+/// Compares `value` with `tests/golden/<name>.json`. Run with
+/// `UPDATE_GOLDEN=1` to write the file instead.
+pub fn assert_golden(name: &str, value: &Value) {
+    let mut value = value.clone();
+    redact(&mut value);
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.json", name));
+    let actual = serde_json::to_string_pretty(&value).unwrap() + "\n";
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("missing golden file {} ({}); run with UPDATE_GOLDEN=1", path.display(), e));
+    assert_eq!(actual, expected, "response differs from {}", path.display());
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                if VOLATILE_FIELDS.contains(&name.as_str()) {
+                    *field = Value::String("[redacted]".to_string());
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
@@ -0,0 +1,280 @@
+//! Request-scoped transactions
+//!
+//! [`ItemService::transaction`](crate::services::ItemService::transaction)
+//! runs a piece of work, typically the body of a handler, as one
+//! transaction: its writes are kept when it returns `Ok` and undone when it
+//! returns an error, so a late failure leaves nothing of the earlier writes
+//! behind.
+//!
+//! With the database, the transaction belongs to the task running the work.
+//! Every query made through an [`InstrumentedPool`] on the same database
+//! while the work runs goes to it, whichever repository makes it, and the
+//! transactions repositories open for themselves become savepoints inside
+//! it. Queries through a plain `SqlitePool` are left out. The memory store
+//! has no transactions, so it stages item creates, updates, patches and
+//! deletes instead and applies them together on commit (see
+//! [`StagedWrites`]).
+//!
+//! Work started inside a transaction joins it rather than opening another.
+//! Effects outside the data, such as cache invalidation and websocket
+//! broadcasts, go through [`after_commit`] so that writes which are undone
+//! are never announced.
+//!
+//! [`InstrumentedPool`]: crate::database::InstrumentedPool
+
+use crate::error::Result;
+use crate::store::{DataStore, StagedWrites};
+use futures_util::future::BoxFuture;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection};
+use sqlx::{Sqlite, SqlitePool, TransactionManager};
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+type SqliteTransaction = sqlx::Transaction<'static, Sqlite>;
+type SqliteTransactionManager = <Sqlite as sqlx::Database>::TransactionManager;
+
+/// The open database transaction, taken out when it ends.
+pub(crate) type SharedTransaction = Arc<AsyncMutex<Option<SqliteTransaction>>>;
+
+tokio::task_local! {
+    static CURRENT: Arc<Transaction>;
+}
+
+/// The transaction the current task's work runs in.
+struct Transaction {
+    backend: Backend,
+    after_commit: Mutex<Vec<BoxFuture<'static, ()>>>,
+}
+
+enum Backend {
+    Database {
+        /// Shared by every clone of the pool, so it tells databases apart.
+        options: Arc<SqliteConnectOptions>,
+        transaction: SharedTransaction,
+    },
+    Staged {
+        store: DataStore,
+        writes: Arc<Mutex<StagedWrites>>,
+    },
+}
+
+impl Transaction {
+    fn new(backend: Backend) -> Arc<Self> {
+        Arc::new(Self {
+            backend,
+            after_commit: Mutex::new(Vec::new()),
+        })
+    }
+
+    async fn commit(&self) -> Result<()> {
+        match &self.backend {
+            Backend::Database { transaction, .. } => {
+                if let Some(transaction) = transaction.lock().await.take() {
+                    transaction.commit().await?;
+                }
+            }
+            Backend::Staged { store, writes } => {
+                let writes = std::mem::take(&mut *writes.lock().unwrap_or_else(|e| e.into_inner()));
+                store.commit_staged(writes)?;
+            }
+        }
+
+        let effects = std::mem::take(&mut *self.after_commit.lock().unwrap_or_else(|e| e.into_inner()));
+        for effect in effects {
+            effect.await;
+        }
+        Ok(())
+    }
+
+    async fn rollback(&self) {
+        if let Backend::Database { transaction, .. } = &self.backend {
+            if let Some(transaction) = transaction.lock().await.take() {
+                if let Err(e) = transaction.rollback().await {
+                    tracing::warn!("Failed to roll back request transaction: {}", e);
+                }
+            }
+        }
+        // Staged writes are dropped with the transaction.
+    }
+
+    /// Runs `work` as this transaction, committing when it succeeds.
+    async fn run<T>(self: Arc<Self>, work: impl Future<Output = Result<T>>) -> Result<T> {
+        match CURRENT.scope(self.clone(), work).await {
+            Ok(value) => {
+                self.commit().await?;
+                Ok(value)
+            }
+            Err(error) => {
+                self.rollback().await;
+                Err(error)
+            }
+        }
+    }
+}
+
+/// Runs `work` in a transaction on the database behind `pool`, or in the
+/// one the task already has open on it.
+pub async fn run_in_database<T>(pool: &SqlitePool, work: impl Future<Output = Result<T>>) -> Result<T> {
+    if database(pool).is_some() {
+        return work.await;
+    }
+
+    let transaction = pool.begin().await?;
+    Transaction::new(Backend::Database {
+        options: pool.connect_options(),
+        transaction: Arc::new(AsyncMutex::new(Some(transaction))),
+    })
+    .run(work)
+    .await
+}
+
+/// Runs `work` with the item writes it makes to `store` staged, applying
+/// them when it succeeds, or joins the staging already open on it.
+pub async fn run_staged<T>(store: &DataStore, work: impl Future<Output = Result<T>>) -> Result<T> {
+    if staged_writes(store).is_some() {
+        return work.await;
+    }
+
+    Transaction::new(Backend::Staged {
+        store: store.clone(),
+        writes: Arc::new(Mutex::new(StagedWrites::default())),
+    })
+    .run(work)
+    .await
+}
+
+/// Whether the current task is running inside a transaction.
+pub fn is_active() -> bool {
+    CURRENT.try_with(|_| ()).is_ok()
+}
+
+/// Runs `effect` once the current transaction commits, or straight away
+/// outside one. Nothing runs if the transaction is rolled back.
+pub async fn after_commit(effect: impl Future<Output = ()> + Send + 'static) {
+    let mut effect: Option<BoxFuture<'static, ()>> = Some(Box::pin(effect));
+    let _ = CURRENT.try_with(|transaction| {
+        if let Some(effect) = effect.take() {
+            transaction.after_commit.lock().unwrap_or_else(|e| e.into_inner()).push(effect);
+        }
+    });
+    if let Some(effect) = effect {
+        effect.await;
+    }
+}
+
+/// The transaction the current task has open on `pool`'s database.
+pub(crate) fn database(pool: &SqlitePool) -> Option<SharedTransaction> {
+    CURRENT
+        .try_with(|current| match &current.backend {
+            Backend::Database { options, transaction } if Arc::ptr_eq(options, &pool.connect_options()) => {
+                Some(transaction.clone())
+            }
+            _ => None,
+        })
+        .ok()
+        .flatten()
+}
+
+/// The writes the current task is staging for `store`.
+pub(crate) fn staged_writes(store: &DataStore) -> Option<Arc<Mutex<StagedWrites>>> {
+    CURRENT
+        .try_with(|current| match &current.backend {
+            Backend::Staged { store: staged, writes } if staged.is_same_store(store) => Some(writes.clone()),
+            _ => None,
+        })
+        .ok()
+        .flatten()
+}
+
+/// A transaction a repository opened for itself: its own, or a savepoint in
+/// the request's transaction when one is open on the database.
+pub enum PoolTransaction {
+    Own(SqliteTransaction),
+    Savepoint(Savepoint),
+}
+
+/// A savepoint holding the request's transaction until it is released or
+/// rolled back, which it is when dropped.
+pub struct Savepoint {
+    transaction: OwnedMutexGuard<Option<SqliteTransaction>>,
+    open: bool,
+}
+
+impl PoolTransaction {
+    pub(crate) async fn begin(pool: &SqlitePool) -> sqlx::Result<Self> {
+        if let Some(shared) = database(pool) {
+            let mut transaction = shared.lock_owned().await;
+            if let Some(conn) = transaction.as_deref_mut() {
+                SqliteTransactionManager::begin(conn).await?;
+                return Ok(Self::Savepoint(Savepoint { transaction, open: true }));
+            }
+        }
+        Ok(Self::Own(pool.begin().await?))
+    }
+
+    pub async fn commit(self) -> sqlx::Result<()> {
+        match self {
+            Self::Own(transaction) => transaction.commit().await,
+            Self::Savepoint(mut savepoint) => {
+                savepoint.open = false;
+                SqliteTransactionManager::commit(&mut savepoint).await
+            }
+        }
+    }
+
+    pub async fn rollback(self) -> sqlx::Result<()> {
+        match self {
+            Self::Own(transaction) => transaction.rollback().await,
+            Self::Savepoint(mut savepoint) => {
+                savepoint.open = false;
+                SqliteTransactionManager::rollback(&mut savepoint).await
+            }
+        }
+    }
+}
+
+impl Deref for PoolTransaction {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &SqliteConnection {
+        match self {
+            Self::Own(transaction) => transaction,
+            Self::Savepoint(savepoint) => savepoint,
+        }
+    }
+}
+
+impl DerefMut for PoolTransaction {
+    fn deref_mut(&mut self) -> &mut SqliteConnection {
+        match self {
+            Self::Own(transaction) => transaction,
+            Self::Savepoint(savepoint) => savepoint,
+        }
+    }
+}
+
+impl Deref for Savepoint {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &SqliteConnection {
+        self.transaction.as_deref().expect("request transaction ended under a savepoint")
+    }
+}
+
+impl DerefMut for Savepoint {
+    fn deref_mut(&mut self) -> &mut SqliteConnection {
+        self.transaction.as_deref_mut().expect("request transaction ended under a savepoint")
+    }
+}
+
+impl Drop for Savepoint {
+    fn drop(&mut self) {
+        if self.open {
+            if let Some(conn) = self.transaction.as_deref_mut() {
+                SqliteTransactionManager::start_rollback(conn);
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! Trash retention
+//!
+//! Deleting an item only moves it to the trash. Items that have been there
+//! longer than [`TrashConfig::retention_days`] are purged for good by a
+//! recurring `TrashPurge` job, or on demand through
+//! `POST /api/items/trash/purge`.
+
+use crate::audit::{AuditEvent, AuditLog};
+use crate::config::TrashConfig;
+use crate::error::Result;
+use crate::services::ItemService;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+/// What a purge removed, or would remove on a dry run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PurgeReport {
+    pub dry_run: bool,
+    /// Items deleted before this time were purged.
+    pub cutoff: DateTime<Utc>,
+    pub items: u64,
+    /// Files that were attached to a purged item and are now unattached.
+    pub files_detached: u64,
+    pub changes_removed: u64,
+    #[serde(default)]
+    pub comments_removed: u64,
+    pub batches: u64,
+}
+
+impl PurgeReport {
+    pub fn new(cutoff: DateTime<Utc>, dry_run: bool) -> Self {
+        Self {
+            dry_run,
+            cutoff,
+            items: 0,
+            files_detached: 0,
+            changes_removed: 0,
+            comments_removed: 0,
+            batches: 0,
+        }
+    }
+}
+
+/// Purges the trash according to [`TrashConfig`], recording every run in the
+/// audit log.
+#[derive(Clone)]
+pub struct TrashPurger {
+    items: ItemService,
+    audit_log: AuditLog,
+    config: TrashConfig,
+}
+
+impl TrashPurger {
+    pub fn new(items: ItemService, audit_log: AuditLog, config: TrashConfig) -> Self {
+        Self { items, audit_log, config }
+    }
+
+    pub fn cutoff(&self) -> DateTime<Utc> {
+        Utc::now() - Duration::days(self.config.retention_days as i64)
+    }
+
+    /// Purges items past retention on behalf of `actor`.
+    pub async fn purge(&self, dry_run: bool, actor: &str) -> Result<PurgeReport> {
+        let report = self
+            .items
+            .purge_trash(self.cutoff(), self.config.batch_size, dry_run)
+            .await?;
+
+        info!(
+            "Trash purge by {} (dry_run: {}): {} items, {} files detached, {} changes and {} comments removed in {} batches",
+            actor, dry_run, report.items, report.files_detached, report.changes_removed, report.comments_removed, report.batches
+        );
+        self.audit_log.record(
+            AuditEvent::new("items.trash_purged")
+                .with_actor(actor)
+                .with_details(serde_json::to_value(&report)?),
+        );
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,231 @@
+//! Per-IP anomaly tracking and temporary blocklist
+
+use crate::config::SecurityConfig;
+use chrono::{DateTime, Utc};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+type ScoreWindow = HashMap<IpAddr, Vec<(Instant, u32)>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockEntry {
+    pub ip: IpAddr,
+    pub score: u32,
+    pub reasons: Vec<String>,
+    pub blocked_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl BlockEntry {
+    pub fn is_expired(&self) -> bool {
+        Utc::now() >= self.expires_at
+    }
+}
+
+/// Accumulates anomaly scores per client IP over a sliding window and blocks
+/// clients whose total reaches the configured threshold. Trusted proxies are
+/// never scored or blocked.
+#[derive(Clone)]
+pub struct AnomalyTracker {
+    config: SecurityConfig,
+    trusted_proxies: Arc<Vec<IpAddr>>,
+    scores: Arc<Mutex<ScoreWindow>>,
+    blocks: Arc<RwLock<HashMap<IpAddr, BlockEntry>>>,
+}
+
+impl AnomalyTracker {
+    pub fn new(config: SecurityConfig) -> Self {
+        let trusted_proxies = config
+            .trusted_proxies
+            .iter()
+            .filter_map(|ip| match ip.parse() {
+                Ok(addr) => Some(addr),
+                Err(_) => {
+                    tracing::warn!("Ignoring invalid trusted proxy address: {}", ip);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            config,
+            trusted_proxies: Arc::new(trusted_proxies),
+            scores: Arc::new(Mutex::new(HashMap::new())),
+            blocks: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub fn config(&self) -> &SecurityConfig {
+        &self.config
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enable_anomaly_blocking
+    }
+
+    pub fn is_trusted_proxy(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.contains(ip)
+    }
+
+    /// Resolves the client address for a connection. Requests arriving through
+    /// a trusted proxy are attributed to the right-most untrusted address in
+    /// `X-Forwarded-For`; anything else is attributed to the peer itself.
+    pub fn client_ip(&self, peer: IpAddr, forwarded_for: Option<&str>) -> IpAddr {
+        if !self.is_trusted_proxy(&peer) {
+            return peer;
+        }
+
+        forwarded_for
+            .into_iter()
+            .flat_map(|header| header.split(','))
+            .rev()
+            .filter_map(|hop| hop.trim().parse::<IpAddr>().ok())
+            .find(|ip| !self.is_trusted_proxy(ip))
+            .unwrap_or(peer)
+    }
+
+    /// Returns the active block for `ip`, dropping it if it has expired.
+    pub fn active_block(&self, ip: &IpAddr) -> Option<BlockEntry> {
+        {
+            let blocks = self.blocks.read();
+            match blocks.get(ip) {
+                Some(entry) if !entry.is_expired() => return Some(entry.clone()),
+                Some(_) => {}
+                None => return None,
+            }
+        }
+
+        self.blocks.write().remove(ip);
+        None
+    }
+
+    /// Adds `score` to the client's window. Returns the new block entry if this
+    /// request pushed the client over the threshold.
+    pub fn record(&self, ip: IpAddr, score: u32, reasons: &[String]) -> Option<BlockEntry> {
+        if score == 0 || !self.is_enabled() || self.is_trusted_proxy(&ip) {
+            return None;
+        }
+
+        let window = Duration::from_secs(self.config.anomaly_window_seconds);
+        let now = Instant::now();
+
+        let total = {
+            let mut scores = self.scores.lock();
+            let entries = scores.entry(ip).or_default();
+            entries.retain(|(at, _)| now.duration_since(*at) < window);
+            entries.push((now, score));
+            entries.iter().map(|(_, s)| *s).sum::<u32>()
+        };
+
+        if total < self.config.anomaly_threshold || self.active_block(&ip).is_some() {
+            return None;
+        }
+
+        let blocked_at = Utc::now();
+        let entry = BlockEntry {
+            ip,
+            score: total,
+            reasons: reasons.to_vec(),
+            blocked_at,
+            expires_at: blocked_at + chrono::Duration::seconds(self.config.block_duration_seconds as i64),
+        };
+
+        self.scores.lock().remove(&ip);
+        self.blocks.write().insert(ip, entry.clone());
+
+        Some(entry)
+    }
+
+    pub fn blocks(&self) -> Vec<BlockEntry> {
+        let mut blocks: Vec<BlockEntry> = self
+            .blocks
+            .read()
+            .values()
+            .filter(|entry| !entry.is_expired())
+            .cloned()
+            .collect();
+        blocks.sort_by_key(|entry| std::cmp::Reverse(entry.blocked_at));
+        blocks
+    }
+
+    pub fn unblock(&self, ip: &IpAddr) -> Option<BlockEntry> {
+        self.scores.lock().remove(ip);
+        self.blocks.write().remove(ip)
+    }
+
+    pub fn cleanup_expired(&self) {
+        let window = Duration::from_secs(self.config.anomaly_window_seconds);
+        let now = Instant::now();
+
+        self.scores.lock().retain(|_, entries| {
+            entries.retain(|(at, _)| now.duration_since(*at) < window);
+            !entries.is_empty()
+        });
+
+        self.blocks.write().retain(|_, entry| !entry.is_expired());
+    }
+}
+
+impl Default for AnomalyTracker {
+    fn default() -> Self {
+        Self::new(SecurityConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(threshold: u32) -> AnomalyTracker {
+        AnomalyTracker::new(SecurityConfig {
+            anomaly_threshold: threshold,
+            trusted_proxies: vec!["10.0.0.1".to_string()],
+            ..SecurityConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_blocks_after_threshold() {
+        let tracker = tracker(5);
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+
+        assert!(tracker.record(ip, 3, &["path".to_string()]).is_none());
+        assert!(tracker.active_block(&ip).is_none());
+
+        let entry = tracker.record(ip, 2, &["user_agent".to_string()]).unwrap();
+        assert_eq!(entry.score, 5);
+        assert!(tracker.active_block(&ip).is_some());
+        assert_eq!(tracker.blocks().len(), 1);
+
+        assert!(tracker.unblock(&ip).is_some());
+        assert!(tracker.active_block(&ip).is_none());
+        assert!(tracker.blocks().is_empty());
+    }
+
+    #[test]
+    fn test_trusted_proxy_never_blocked() {
+        let tracker = tracker(1);
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+
+        assert!(tracker.record(proxy, 100, &[]).is_none());
+        assert!(tracker.active_block(&proxy).is_none());
+    }
+
+    #[test]
+    fn test_client_ip_behind_trusted_proxy() {
+        let tracker = tracker(1);
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let direct: IpAddr = "198.51.100.2".parse().unwrap();
+
+        assert_eq!(
+            tracker.client_ip(proxy, Some("1.2.3.4, 203.0.113.9, 10.0.0.1")),
+            "203.0.113.9".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(tracker.client_ip(proxy, None), proxy);
+        assert_eq!(tracker.client_ip(direct, Some("203.0.113.9")), direct);
+    }
+}
@@ -0,0 +1,226 @@
+//! The validation error produced by every validator in the crate
+
+use super::{FieldValidationError, ValidationResult};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+/// Why an input was rejected. Request, item, search and file validation all
+/// report failures as this type, and [`AppError::Validation`] carries it, so
+/// callers can enumerate the offending fields instead of parsing a message.
+///
+/// [`AppError::Validation`]: crate::error::AppError::Validation
+#[derive(Error, Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ValidationError {
+    /// One or more fields of a request failed their rules. Displays as the
+    /// summary followed by the messages per field as JSON.
+    #[error("{summary}: {}", FieldsJson(.fields))]
+    Fields {
+        summary: String,
+        fields: Vec<FieldValidationError>,
+    },
+
+    /// A single rule failed.
+    #[error("{message}")]
+    Rule {
+        field: Option<String>,
+        code: String,
+        message: String,
+    },
+
+    #[error("File too large: {size} bytes (max: {max_size} bytes)")]
+    FileTooLarge { size: u64, max_size: u64 },
+
+    #[error("Invalid file type: {content_type} (allowed: {allowed:?})")]
+    InvalidFileType { content_type: String, allowed: Vec<String> },
+
+    #[error("Filename too long: {length} characters (max: {max_length})")]
+    FilenameTooLong { length: usize, max_length: usize },
+
+    #[error("Invalid filename: {filename}")]
+    InvalidFilename { filename: String },
+
+    #[error("Empty file not allowed")]
+    EmptyFile,
+
+    #[error("Suspicious file content detected")]
+    SuspiciousContent,
+}
+
+/// The messages per field as a JSON object, written straight into the
+/// formatter.
+struct FieldsJson<'a>(&'a [FieldValidationError]);
+
+impl std::fmt::Display for FieldsJson<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let messages: BTreeMap<&str, &[String]> = self
+            .0
+            .iter()
+            .map(|field| (field.field.as_str(), field.errors.as_slice()))
+            .collect();
+        serde_json::to_writer(FormatterWriter(f), &messages).map_err(|_| std::fmt::Error)
+    }
+}
+
+struct FormatterWriter<'a, 'b>(&'a mut std::fmt::Formatter<'b>);
+
+impl std::io::Write for FormatterWriter<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // serde_json only ever writes whole UTF-8 sequences.
+        let text = std::str::from_utf8(buf).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        self.0.write_str(text).map_err(std::io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ValidationError {
+    /// A failed rule on a named field.
+    pub fn field(field: &str, code: &str, message: impl Into<String>) -> Self {
+        Self::Rule {
+            field: Some(field.to_string()),
+            code: code.to_string(),
+            message: message.into(),
+        }
+    }
+
+    /// Every failure with the field it concerns. File failures are reported
+    /// against `file`; rules not tied to a field are left out.
+    pub fn field_errors(&self) -> Vec<FieldValidationError> {
+        let single = |field: &str, code: &str| {
+            vec![FieldValidationError {
+                field: field.to_string(),
+                value: None,
+                errors: vec![self.to_string()],
+                error_codes: vec![code.to_string()],
+            }]
+        };
+
+        match self {
+            Self::Fields { fields, .. } => fields.clone(),
+            Self::Rule { field: Some(field), code, .. } => single(field, code),
+            Self::Rule { field: None, .. } => Vec::new(),
+            Self::FileTooLarge { .. } => single("file", "file_too_large"),
+            Self::InvalidFileType { .. } => single("file", "invalid_file_type"),
+            Self::FilenameTooLong { .. } => single("file", "filename_too_long"),
+            Self::InvalidFilename { .. } => single("file", "invalid_filename"),
+            Self::EmptyFile => single("file", "empty_file"),
+            Self::SuspiciousContent => single("file", "suspicious_content"),
+        }
+    }
+}
+
+impl ValidationResult {
+    /// `Ok` when valid, otherwise the failures as a [`ValidationError`]
+    /// whose message starts with `summary`. Consumes the result so that the
+    /// messages are moved into the error rather than copied.
+    pub fn ensure_valid(self, summary: &str) -> Result<(), ValidationError> {
+        if self.is_valid {
+            return Ok(());
+        }
+
+        let mut field_errors = self.field_errors;
+        let mut fields: Vec<FieldValidationError> = self
+            .errors
+            .into_iter()
+            .map(|(field, messages)| {
+                let (value, error_codes) = field_errors
+                    .remove(&field)
+                    .map(|error| (error.value, error.error_codes))
+                    .unwrap_or_default();
+                FieldValidationError {
+                    field,
+                    value,
+                    errors: messages,
+                    error_codes,
+                }
+            })
+            .collect();
+        fields.sort_by(|a, b| a.field.cmp(&b.field));
+
+        Err(ValidationError::Fields {
+            summary: summary.to_string(),
+            fields,
+        })
+    }
+}
+
+impl From<validator::ValidationError> for ValidationError {
+    fn from(error: validator::ValidationError) -> Self {
+        Self::Rule {
+            field: None,
+            message: error
+                .message
+                .as_ref()
+                .map_or_else(|| error.code.to_string(), |message| message.to_string()),
+            code: error.code.to_string(),
+        }
+    }
+}
+
+impl From<validator::ValidationErrors> for ValidationError {
+    fn from(errors: validator::ValidationErrors) -> Self {
+        match ValidationResult::from_validation_errors(errors).ensure_valid("Validation failed") {
+            Err(error) => error,
+            Ok(()) => Self::Fields {
+                summary: "Validation failed".to_string(),
+                fields: Vec::new(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validator::Validate;
+
+    #[derive(Validate)]
+    struct Signup {
+        #[validate(length(min = 3, message = "Username is too short"))]
+        username: String,
+        #[validate(email)]
+        email: String,
+    }
+
+    #[test]
+    fn test_result_errors_keep_fields_and_message_format() {
+        let mut result = ValidationResult::success();
+        result.add_error("tags", "Too many tags");
+        result.add_error("name", "Name is required");
+        result.add_error("name", "Name contains invalid characters");
+
+        let error = result.ensure_valid("Validation failed").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            r#"Validation failed: {"name":["Name is required","Name contains invalid characters"],"tags":["Too many tags"]}"#
+        );
+
+        let fields = error.field_errors();
+        assert_eq!(fields.iter().map(|field| field.field.as_str()).collect::<Vec<_>>(), vec!["name", "tags"]);
+        assert_eq!(fields[0].errors.len(), 2);
+        assert!(ValidationResult::success().ensure_valid("Validation failed").is_ok());
+    }
+
+    #[test]
+    fn test_validator_crate_errors_convert() {
+        let rule = ValidationError::from(validator::ValidationError::new("Email cannot be empty"));
+        assert_eq!(rule.to_string(), "Email cannot be empty");
+        assert!(rule.field_errors().is_empty());
+
+        let signup = Signup { username: "ab".to_string(), email: "not-an-email".to_string() };
+        let error = ValidationError::from(signup.validate().unwrap_err());
+        let fields = error.field_errors();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].field, "email");
+        assert_eq!(fields[0].error_codes, vec!["email"]);
+        assert_eq!(fields[1].errors, vec!["Username is too short"]);
+
+        let file = ValidationError::EmptyFile.field_errors();
+        assert_eq!((file[0].field.as_str(), file[0].error_codes[0].as_str()), ("file", "empty_file"));
+    }
+}
@@ -203,11 +203,7 @@ macro_rules! validation_context {
 macro_rules! validate_with_context_or_error {
     ($validatable:expr, $context:expr) => {{
         let validation_result = $validatable.validate_with_context($context);
-        if !validation_result.is_valid {
-            return Err($crate::error::AppError::BadRequest(
-                serde_json::to_string(&validation_result.errors).unwrap_or_else(|_| "Validation failed".to_string())
-            ));
-        }
+        validation_result.ensure_valid("Validation failed")?;
     }};
 }
 
@@ -1,24 +1,66 @@
 //! Validation middleware for automatic input checking
 
-use super::{ValidationResult, ValidationContext, ContextValidatable, SecurityValidator, SecurityContext};
+use super::{ValidationResult, ValidationError, ValidationContext, ContextValidatable, SecurityValidator, SecurityContext};
+use crate::audit::AuditEvent;
+use crate::config::ValidationConfig;
 use crate::error::{AppError, Result};
+use crate::AppState;
 use axum::{
-    extract::{Request, ConnectInfo},
-    http::{HeaderMap, Method, Uri},
+    body::HttpBody,
+    extract::{Request, ConnectInfo, State},
+    http::{header, HeaderMap, Method, Uri},
     middleware::Next,
     response::Response,
 };
-use std::{collections::HashMap, net::SocketAddr};
+use std::{collections::{HashMap, HashSet}, net::SocketAddr};
 use tracing::{warn, debug};
 
+/// Media types accepted in the body of API writes.
+const API_MEDIA_TYPES: &[&str] = &[
+    "application/json",
+    "application/x-ndjson",
+    "application/ndjson",
+    "application/msgpack",
+    "application/x-msgpack",
+];
+
+/// Write routes whose bodies aren't API payloads, with the media types each
+/// accepts instead. An empty list accepts any.
+const MEDIA_TYPE_EXCEPTIONS: &[(&str, &[&str])] = &[
+    ("/api/files/upload", &["multipart/form-data"]),
+    ("/api/form", &["application/json", "application/x-www-form-urlencoded", "multipart/form-data"]),
+    (crate::handlers::admin::SNAPSHOT_IMPORT_PATH, &[]),
+    (crate::handlers::introspect::INTROSPECT_PATH, &["application/x-www-form-urlencoded"]),
+];
+
+/// Checks the shape of every request before it reaches a handler: header
+/// count and size against `validation.max_header_count` and
+/// `validation.max_header_bytes` (431), a body length given both ways
+/// (400), and the media type of API writes (415). Each refusal is counted
+/// as a security event. Requests that pass have their duplicate query
+/// parameters collapsed by [`dedupe_query`].
 pub async fn validation_middleware(
+    State(state): State<AppState>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
     method: Method,
     uri: Uri,
-    request: Request,
+    mut request: Request,
     next: Next,
 ) -> std::result::Result<Response, AppError> {
+    let has_body = !request.body().is_end_stream();
+    let checked = check_headers(&headers, &state.validation_config)
+        .map_err(|e| (e, "headers_too_large"))
+        .and_then(|_| check_body_length(&headers).map_err(|e| (e, "ambiguous_body_length")))
+        .and_then(|_| {
+            check_media_type(&method, uri.path(), &headers, has_body).map_err(|e| (e, "unsupported_media_type"))
+        });
+    if let Err((error, event)) = checked {
+        state.metrics.record_security_event(event);
+        debug!("Refusing {} {} from {}: {}", method, uri.path(), addr.ip(), error);
+        return Err(error);
+    }
+
     let ip_address = addr.ip().to_string();
     let user_agent = headers
         .get("user-agent")
@@ -67,10 +109,205 @@ pub async fn validation_middleware(
         debug!("Rate limiting check for path: {}", uri.path());
     }
 
+    if let Some(deduped) = uri.query().and_then(dedupe_query) {
+        state.metrics.record_security_event("duplicate_query_params");
+        let path_and_query = if deduped.is_empty() {
+            uri.path().to_string()
+        } else {
+            format!("{}?{}", uri.path(), deduped)
+        };
+        let mut parts = uri.into_parts();
+        if let Ok(path_and_query) = path_and_query.parse() {
+            parts.path_and_query = Some(path_and_query);
+            if let Ok(uri) = Uri::from_parts(parts) {
+                *request.uri_mut() = uri;
+            }
+        }
+    }
+
     let response = next.run(request).await;
     Ok(response)
 }
 
+fn check_headers(headers: &HeaderMap, config: &ValidationConfig) -> Result<()> {
+    if headers.len() > config.max_header_count {
+        return Err(AppError::HeadersTooLarge(format!(
+            "{} headers sent (max: {})",
+            headers.len(),
+            config.max_header_count
+        )));
+    }
+
+    let bytes: usize = headers.iter().map(|(name, value)| name.as_str().len() + value.len()).sum();
+    if bytes > config.max_header_bytes {
+        return Err(AppError::HeadersTooLarge(format!(
+            "{} bytes of headers sent (max: {} bytes)",
+            bytes, config.max_header_bytes
+        )));
+    }
+
+    Ok(())
+}
+
+/// A request giving both a Content-Length and a Transfer-Encoding can be
+/// read differently by a proxy and by this server, so it is refused.
+fn check_body_length(headers: &HeaderMap) -> Result<()> {
+    if headers.contains_key(header::CONTENT_LENGTH) && headers.contains_key(header::TRANSFER_ENCODING) {
+        return Err(AppError::AmbiguousBodyLength(
+            "Send either Content-Length or Transfer-Encoding, not both".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// API writes carrying a body must declare one of [`API_MEDIA_TYPES`], or
+/// for the routes in [`MEDIA_TYPE_EXCEPTIONS`] one of theirs.
+fn check_media_type(method: &Method, path: &str, headers: &HeaderMap, has_body: bool) -> Result<()> {
+    if !has_body
+        || !matches!(*method, Method::POST | Method::PUT | Method::PATCH)
+        || !(path.starts_with("/api/") || path.starts_with("/auth/"))
+    {
+        return Ok(());
+    }
+
+    let accepted = MEDIA_TYPE_EXCEPTIONS
+        .iter()
+        .find(|(route, _)| *route == path)
+        .map_or(API_MEDIA_TYPES, |(_, accepted)| *accepted);
+    if accepted.is_empty() {
+        return Ok(());
+    }
+
+    let media_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok());
+    match media_type {
+        Some(media_type) if accepted.contains(&media_type.essence_str()) => Ok(()),
+        Some(media_type) => Err(AppError::UnsupportedMediaType(format!(
+            "{} is not accepted here; send one of {}",
+            media_type.essence_str(),
+            accepted.join(", ")
+        ))),
+        None => Err(AppError::UnsupportedMediaType(format!(
+            "A Content-Type header is required; send one of {}",
+            accepted.join(", ")
+        ))),
+    }
+}
+
+/// `query` with every parameter repeated under the same name kept only at
+/// its first occurrence, or `None` when nothing is repeated. Names are
+/// compared as written, and list parameters written with brackets, such as
+/// `tags[]=a&tags[]=b`, are kept whole.
+pub fn dedupe_query(query: &str) -> Option<String> {
+    let mut seen = HashSet::new();
+    let mut kept = Vec::new();
+    let mut dropped = false;
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let name = pair.split('=').next().unwrap_or_default();
+        let is_list = name.contains('[') || name.to_ascii_lowercase().contains("%5b");
+        if is_list || seen.insert(name) {
+            kept.push(pair);
+        } else {
+            dropped = true;
+        }
+    }
+
+    dropped.then(|| kept.join("&"))
+}
+
+/// Health and readiness probes are never scored or blocked.
+pub fn is_health_path(path: &str) -> bool {
+    path == "/health" || path.starts_with("/health/") || path == "/ready" || path == "/live"
+}
+
+/// Scores each request for anomalies and rejects clients on the blocklist
+/// with 403 until their block expires or an admin lifts it.
+pub async fn anomaly_blocking_middleware(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    request: Request,
+    next: Next,
+) -> std::result::Result<Response, AppError> {
+    let tracker = &state.anomaly_tracker;
+
+    let peer = match connect_info {
+        Some(ConnectInfo(addr)) if tracker.is_enabled() && !is_health_path(request.uri().path()) => addr.ip(),
+        _ => return Ok(next.run(request).await),
+    };
+
+    let forwarded_for = request
+        .headers()
+        .get("x-forwarded-for")
+        .and_then(|h| h.to_str().ok());
+    let client_ip = tracker.client_ip(peer, forwarded_for);
+
+    if tracker.is_trusted_proxy(&client_ip) {
+        return Ok(next.run(request).await);
+    }
+
+    if let Some(block) = tracker.active_block(&client_ip) {
+        state.metrics.record_security_event("blocked_request");
+        debug!("Rejecting request from blocked client {}", client_ip);
+        return Err(AppError::Authorization(format!(
+            "Client is temporarily blocked until {}",
+            block.expires_at.to_rfc3339()
+        )));
+    }
+
+    let headers = request.headers();
+    let security_context = SecurityContext {
+        ip_address: Some(client_ip.to_string()),
+        user_agent: headers
+            .get("user-agent")
+            .and_then(|h| h.to_str().ok())
+            .map(|s| s.to_string()),
+        referer: headers
+            .get("referer")
+            .and_then(|h| h.to_str().ok())
+            .map(|s| s.to_string()),
+        request_path: request.uri().path().to_string(),
+        request_method: request.method().to_string(),
+        headers: headers
+            .iter()
+            .filter_map(|(name, value)| value.to_str().ok().map(|v| (name.to_string(), v.to_string())))
+            .collect(),
+    };
+
+    let score = SecurityValidator::anomaly_score(
+        &security_context,
+        request.uri().query(),
+        tracker.config().max_user_agent_length,
+    );
+
+    if !score.is_clean() {
+        state.metrics.record_security_event("anomaly_detected");
+        debug!("Anomaly score {} for {}: {:?}", score.score, client_ip, score.reasons);
+
+        if let Some(block) = tracker.record(client_ip, score.score, &score.reasons) {
+            state.metrics.record_security_event("client_blocked");
+            warn!(
+                "Blocking client {} until {} (anomaly score {})",
+                client_ip, block.expires_at, block.score
+            );
+            state.audit_log.record(
+                AuditEvent::new("security.client_blocked")
+                    .with_actor("anomaly_detector")
+                    .with_target(client_ip.to_string())
+                    .with_details(serde_json::json!({
+                        "score": block.score,
+                        "reasons": block.reasons,
+                        "expires_at": block.expires_at,
+                    })),
+            );
+        }
+    }
+
+    Ok(next.run(request).await)
+}
+
 pub async fn json_validation_middleware<T>(
     payload: T,
     context: ValidationContext,
@@ -78,14 +315,7 @@ pub async fn json_validation_middleware<T>(
 where
     T: ContextValidatable,
 {
-    let validation_result = payload.validate_with_context(&context);
-    
-    if !validation_result.is_valid {
-        return Err(AppError::BadRequest(format!(
-            "Validation failed: {}",
-            serde_json::to_string(&validation_result.errors).unwrap_or_default()
-        )));
-    }
+    payload.validate_with_context(&context).ensure_valid("Validation failed")?;
     
     Ok(payload)
 }
@@ -101,12 +331,7 @@ pub async fn file_upload_validation_middleware(
         content,
     );
     
-    if !validation_result.is_valid {
-        return Err(AppError::BadRequest(format!(
-            "File upload validation failed: {}",
-            serde_json::to_string(&validation_result.errors).unwrap_or_default()
-        )));
-    }
+    validation_result.ensure_valid("File upload validation failed")?;
     
     Ok(())
 }
@@ -144,10 +369,12 @@ pub struct ValidationResponse;
 
 impl ValidationResponse {
     pub fn validation_error(result: ValidationResult) -> AppError {
-        AppError::BadRequest(format!(
-            "Validation failed: {}",
-            serde_json::to_string(&result).unwrap_or_else(|_| "Unknown validation error".to_string())
-        ))
+        let summary = "Validation failed";
+        let error = result.ensure_valid(summary).err().unwrap_or_else(|| ValidationError::Fields {
+            summary: summary.to_string(),
+            fields: Vec::new(),
+        });
+        AppError::Validation(error)
     }
     
     pub fn security_error(message: &str) -> AppError {
@@ -155,7 +382,11 @@ impl ValidationResponse {
     }
     
     pub fn file_error(message: &str) -> AppError {
-        AppError::BadRequest(format!("File validation failed: {}", message))
+        AppError::Validation(ValidationError::field(
+            "file",
+            "invalid_file",
+            format!("File validation failed: {}", message),
+        ))
     }
 }
 
@@ -252,4 +483,84 @@ mod tests {
         assert!(validate_content_type(Some("application/xml"), allowed).is_err());
         assert!(validate_content_type(None, allowed).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_dedupe_query() {
+        assert_eq!(dedupe_query("page=1&page_size=5"), None);
+        assert_eq!(dedupe_query("page=1&page=2&q=a&page=3"), Some("page=1&q=a".to_string()));
+        assert_eq!(dedupe_query("tags[]=a&tags[]=b&tags%5B%5D=c"), None);
+        assert_eq!(dedupe_query("flag&flag&x=1"), Some("flag&x=1".to_string()));
+        assert_eq!(dedupe_query("&&"), None);
+    }
+
+    #[test]
+    fn test_check_headers() {
+        let config = ValidationConfig {
+            max_header_count: 2,
+            max_header_bytes: 32,
+            ..ValidationConfig::default()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert("accept", HeaderValue::from_static("*/*"));
+        headers.append("x-tag", HeaderValue::from_static("a"));
+        assert!(check_headers(&headers, &config).is_ok());
+
+        headers.append("x-tag", HeaderValue::from_static("b"));
+        assert!(matches!(check_headers(&headers, &config), Err(AppError::HeadersTooLarge(_))));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-long", HeaderValue::from_static("a value well past thirty-two bytes"));
+        assert!(matches!(check_headers(&headers, &config), Err(AppError::HeadersTooLarge(_))));
+    }
+
+    #[test]
+    fn test_check_body_length() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from_static("5"));
+        assert!(check_body_length(&headers).is_ok());
+
+        headers.insert(header::TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+        assert!(matches!(check_body_length(&headers), Err(AppError::AmbiguousBodyLength(_))));
+    }
+
+    #[test]
+    fn test_check_media_type() {
+        let with_type = |content_type: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap());
+            headers
+        };
+        let unsupported = |result: Result<()>| matches!(result, Err(AppError::UnsupportedMediaType(_)));
+
+        assert!(check_media_type(&Method::POST, "/api/items", &with_type("application/json; charset=utf-8"), true).is_ok());
+        assert!(check_media_type(&Method::PUT, "/api/items/1", &with_type("application/x-ndjson"), true).is_ok());
+        assert!(check_media_type(&Method::PATCH, "/api/items/1", &with_type("application/msgpack"), true).is_ok());
+        assert!(unsupported(check_media_type(&Method::POST, "/api/items", &with_type("text/plain"), true)));
+        assert!(unsupported(check_media_type(&Method::POST, "/auth/login", &HeaderMap::new(), true)));
+
+        assert!(check_media_type(&Method::POST, "/api/items/search/export", &HeaderMap::new(), false).is_ok());
+        assert!(check_media_type(&Method::GET, "/api/items", &with_type("text/plain"), true).is_ok());
+        assert!(check_media_type(&Method::POST, "/ws", &with_type("text/plain"), true).is_ok());
+
+        assert!(check_media_type(&Method::POST, "/api/files/upload", &with_type("multipart/form-data; boundary=x"), true).is_ok());
+        assert!(unsupported(check_media_type(&Method::POST, "/api/files/upload", &with_type("application/json"), true)));
+        assert!(check_media_type(&Method::POST, "/api/form", &with_type("application/x-www-form-urlencoded"), true).is_ok());
+        assert!(check_media_type(
+            &Method::POST,
+            crate::handlers::admin::SNAPSHOT_IMPORT_PATH,
+            &with_type("application/x-tar"),
+            true
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_is_health_path() {
+        assert!(is_health_path("/health"));
+        assert!(is_health_path("/health/database"));
+        assert!(is_health_path("/ready"));
+        assert!(is_health_path("/live"));
+        assert!(!is_health_path("/healthz-admin"));
+        assert!(!is_health_path("/api/items"));
+    }
+}
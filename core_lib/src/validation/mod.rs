@@ -5,15 +5,21 @@ pub mod validators;
 pub mod middleware;
 pub mod macros;
 pub mod security;
+pub mod unicode;
+pub mod anomaly;
+pub mod error;
 
 pub use rules::*;
 pub use validators::*;
 pub use middleware::*;
 pub use security::*;
+pub use anomaly::{AnomalyTracker, BlockEntry};
+pub use error::ValidationError;
 
+use crate::config::{FieldPolicy, ValidationConfig};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use validator::{Validate, ValidationError, ValidationErrors};
+use validator::{Validate, ValidationErrors};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ValidationResult {
@@ -22,7 +28,7 @@ pub struct ValidationResult {
     pub field_errors: HashMap<String, FieldValidationError>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FieldValidationError {
     pub field: String,
     pub value: Option<String>,
@@ -116,7 +122,7 @@ where T: Validate, {
     }
 }
 
-pub type CustomValidator<T> = fn(&T) -> Result<(), ValidationError>;
+pub type CustomValidator<T> = fn(&T) -> Result<(), validator::ValidationError>;
 
 #[derive(Debug, Clone)]
 pub struct ValidationContext {
@@ -124,6 +130,7 @@ pub struct ValidationContext {
     pub user_role: Option<String>,
     pub request_ip: Option<String>,
     pub additional_data: HashMap<String, serde_json::Value>,
+    pub validation_config: ValidationConfig,
 }
 
 impl Default for ValidationContext {
@@ -133,10 +140,29 @@ impl Default for ValidationContext {
             user_role: None,
             request_ip: None,
             additional_data: HashMap::new(),
+            validation_config: ValidationConfig::default(),
         }
     }
 }
 
+impl ValidationContext {
+    pub fn with_validation_config(mut self, validation_config: ValidationConfig) -> Self {
+        self.validation_config = validation_config;
+        self
+    }
+
+    pub fn policy_for(&self, field: &str) -> FieldPolicy {
+        self.validation_config.policy_for(field)
+    }
+}
+
 pub trait ContextValidatable {
     fn validate_with_context(&self, context: &ValidationContext) -> ValidationResult;
+}
+
+/// Rewrites fields before validation runs: text is NFC-normalized with
+/// invisible characters stripped, and fields whose policy is `sanitize` are
+/// escaped instead of rejected.
+pub trait Sanitizable {
+    fn sanitize_with_context(&mut self, context: &ValidationContext);
 }
\ No newline at end of file
@@ -1,11 +1,18 @@
 //! Security-focused validation utilities
 
-use super::{ValidationResult};
+use super::{ValidationResult, ValidationContext};
+use crate::config::FieldPolicy;
 use lazy_static::lazy_static;
+use parking_lot::RwLock;
 use regex::Regex;
 use std::collections::HashSet;
+use std::sync::Arc;
 use validator::ValidationError;
 
+/// A custom security rule receives the field key (e.g. `item.description`)
+/// and the value being validated.
+pub type SecurityRule = Arc<dyn Fn(&str, &str) -> Result<(), ValidationError> + Send + Sync>;
+
 lazy_static! {
     static ref LDAP_INJECTION_PATTERNS: Vec<Regex> = vec![
         Regex::new(r"(?i)(\*\)|&\||!\|)").unwrap(),
@@ -43,6 +50,8 @@ lazy_static! {
         set
     };
 
+    static ref CUSTOM_RULES: RwLock<Vec<(String, SecurityRule)>> = RwLock::new(Vec::new());
+
     static ref RATE_LIMIT_KEYS: HashSet<&'static str> = {
         let mut set = HashSet::new();
         set.insert("/auth/login");
@@ -76,9 +85,168 @@ impl Default for SecurityContext {
     }
 }
 
+/// Request-level anomaly score. Each tripped rule adds its weight to the
+/// total; `reasons` lists what was found, for logs and the audit trail.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AnomalyScore {
+    pub score: u32,
+    pub reasons: Vec<String>,
+}
+
+impl AnomalyScore {
+    fn add(&mut self, weight: u32, reason: impl Into<String>) {
+        self.score += weight;
+        self.reasons.push(reason.into());
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.score == 0
+    }
+}
+
+const RULE_WEIGHT: u32 = 1;
+const LONG_USER_AGENT_WEIGHT: u32 = 2;
+const QUERY_NULL_BYTE_WEIGHT: u32 = 3;
+
 pub struct SecurityValidator;
 
 impl SecurityValidator {
+    /// Registers a named rule that runs for every policy-checked field unless
+    /// that field's policy is `off`. Registering an existing name replaces it.
+    pub fn register_rule<F>(name: &str, rule: F)
+    where
+        F: Fn(&str, &str) -> Result<(), ValidationError> + Send + Sync + 'static,
+    {
+        let mut rules = CUSTOM_RULES.write();
+        rules.retain(|(existing, _)| existing != name);
+        rules.push((name.to_string(), Arc::new(rule)));
+    }
+
+    pub fn unregister_rule(name: &str) -> bool {
+        let mut rules = CUSTOM_RULES.write();
+        let before = rules.len();
+        rules.retain(|(existing, _)| existing != name);
+        rules.len() != before
+    }
+
+    pub fn registered_rules() -> Vec<String> {
+        CUSTOM_RULES.read().iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    pub fn validate_custom_rules(field: &str, input: &str) -> Result<(), ValidationError> {
+        let rules: Vec<SecurityRule> = CUSTOM_RULES.read().iter().map(|(_, rule)| rule.clone()).collect();
+        for rule in rules {
+            rule(field, input)?;
+        }
+        Ok(())
+    }
+
+    /// Checks a single value against the policy configured for `field`.
+    ///
+    /// `strict` runs the built-in SQL/XSS patterns plus custom rules,
+    /// `sanitize` expects the value to have been escaped already and only runs
+    /// custom rules, and `off` skips all checks. Errors are recorded under `key`.
+    pub fn validate_field(
+        result: &mut ValidationResult,
+        context: &ValidationContext,
+        field: &str,
+        key: &str,
+        value: &str,
+        message: &str,
+    ) {
+        let policy = context.policy_for(field);
+
+        if policy == FieldPolicy::Strict {
+            if Self::validate_sql_injection(value).is_err() {
+                result.add_error(key, message);
+            }
+            if Self::validate_xss(value).is_err() {
+                result.add_error(key, message);
+            }
+        }
+
+        if policy != FieldPolicy::Off {
+            if let Err(err) = Self::validate_custom_rules(field, value) {
+                result.add_error(key, &err.to_string());
+            }
+        }
+    }
+
+    /// Like [`validate_field`](Self::validate_field), but walks every string
+    /// inside a JSON document such as item metadata.
+    pub fn validate_json_field(
+        result: &mut ValidationResult,
+        context: &ValidationContext,
+        field: &str,
+        key: &str,
+        value: &serde_json::Value,
+        message: &str,
+    ) {
+        match context.policy_for(field) {
+            FieldPolicy::Strict => {
+                let serialized = value.to_string();
+                if Self::validate_sql_injection(&serialized).is_err() {
+                    result.add_error(key, message);
+                }
+                if Self::validate_xss(&serialized).is_err() {
+                    result.add_error(key, message);
+                }
+                Self::validate_json_custom_rules(result, field, key, value);
+            }
+            FieldPolicy::Sanitize => Self::validate_json_custom_rules(result, field, key, value),
+            FieldPolicy::Off => {}
+        }
+    }
+
+    fn validate_json_custom_rules(result: &mut ValidationResult, field: &str, key: &str, value: &serde_json::Value) {
+        match value {
+            serde_json::Value::String(text) => {
+                if let Err(err) = Self::validate_custom_rules(field, text) {
+                    result.add_error(key, &err.to_string());
+                }
+            }
+            serde_json::Value::Array(values) => {
+                for value in values {
+                    Self::validate_json_custom_rules(result, field, key, value);
+                }
+            }
+            serde_json::Value::Object(map) => {
+                for value in map.values() {
+                    Self::validate_json_custom_rules(result, field, key, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Escapes HTML so markup is kept as inert text instead of being rejected.
+    pub fn sanitize_html(input: &str) -> String {
+        super::middleware::sanitize_input(input)
+    }
+
+    /// Escapes every string value (not keys) inside a JSON document.
+    pub fn sanitize_json_strings(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(text) => *text = Self::sanitize_html(text),
+            serde_json::Value::Array(values) => values.iter_mut().for_each(Self::sanitize_json_strings),
+            serde_json::Value::Object(map) => map.values_mut().for_each(Self::sanitize_json_strings),
+            _ => {}
+        }
+    }
+
+    /// Escapes `value` in place when the policy for `field` is `sanitize`.
+    pub fn sanitize_field(context: &ValidationContext, field: &str, value: &mut String) {
+        if context.policy_for(field) == FieldPolicy::Sanitize {
+            *value = Self::sanitize_html(value);
+        }
+    }
+
+    pub fn sanitize_json_field(context: &ValidationContext, field: &str, value: &mut serde_json::Value) {
+        if context.policy_for(field) == FieldPolicy::Sanitize {
+            Self::sanitize_json_strings(value);
+        }
+    }
+
     pub fn validate_sql_injection(input: &str) -> Result<(), ValidationError> {
         super::rules::validate_no_sql_injection(input)
     }
@@ -135,7 +303,10 @@ impl SecurityValidator {
             "upgrade-insecure-requests", "sec-fetch-site", "sec-fetch-mode",
             "sec-fetch-user", "sec-fetch-dest", "sec-ch-ua", "sec-ch-ua-mobile",
             "sec-ch-ua-platform", "dnt", "upgrade", "origin", "referer", "if-none-match",
-            "if-modified-since", "content-length", "content-type"
+            "if-modified-since", "content-length", "content-type",
+            // Random base64 keys and the bearer token of a WebSocket upgrade.
+            "sec-websocket-key", "sec-websocket-version", "sec-websocket-protocol",
+            "sec-websocket-extensions"
         ];
 
         for (name, value) in headers {
@@ -308,6 +479,49 @@ impl SecurityValidator {
 
         result
     }
+
+    /// Scores a whole request rather than a single field: one point per rule
+    /// tripped by [`validate_request_security`](Self::validate_request_security)
+    /// or by the query string, plus extra weight for an oversized user agent
+    /// and for null bytes in the query string.
+    pub fn anomaly_score(
+        context: &SecurityContext,
+        query: Option<&str>,
+        max_user_agent_length: usize,
+    ) -> AnomalyScore {
+        let mut score = AnomalyScore::default();
+
+        let request_result = Self::validate_request_security(context);
+        for (field, messages) in &request_result.errors {
+            for message in messages {
+                score.add(RULE_WEIGHT, format!("{}: {}", field, message));
+            }
+        }
+
+        if let Some(user_agent) = &context.user_agent {
+            if user_agent.len() > max_user_agent_length {
+                score.add(
+                    LONG_USER_AGENT_WEIGHT,
+                    format!("user_agent: {} bytes exceeds {}", user_agent.len(), max_user_agent_length),
+                );
+            }
+        }
+
+        if let Some(query) = query.filter(|q| !q.is_empty()) {
+            if query.contains('\0') || query.to_ascii_lowercase().contains("%00") {
+                score.add(QUERY_NULL_BYTE_WEIGHT, "query: null byte");
+            }
+
+            let query_result = Self::validate_input_security(query);
+            for messages in query_result.errors.values() {
+                for message in messages {
+                    score.add(RULE_WEIGHT, format!("query: {}", message));
+                }
+            }
+        }
+
+        score
+    }
 }
 
 pub struct IpSecurityValidator;
@@ -369,6 +583,27 @@ mod tests {
         assert!(SecurityValidator::validate_path_traversal("../../../etc/passwd").is_err());
     }
 
+    #[test]
+    fn test_anomaly_score() {
+        let clean = SecurityContext {
+            user_agent: Some("Mozilla/5.0".to_string()),
+            request_path: "/api/items".to_string(),
+            request_method: "GET".to_string(),
+            ..Default::default()
+        };
+        assert!(SecurityValidator::anomaly_score(&clean, Some("limit=10"), 512).is_clean());
+
+        let hostile = SecurityContext {
+            user_agent: Some(format!("sqlmap/1.0 {}", "A".repeat(600))),
+            request_path: "/api/../etc/passwd".to_string(),
+            request_method: "GET".to_string(),
+            ..Default::default()
+        };
+        let score = SecurityValidator::anomaly_score(&hostile, Some("name=abc%00"), 512);
+        assert_eq!(score.score, RULE_WEIGHT * 2 + LONG_USER_AGENT_WEIGHT + QUERY_NULL_BYTE_WEIGHT);
+        assert_eq!(score.reasons.len(), 4);
+    }
+
     #[test]
     fn test_user_agent_validation() {
         assert!(SecurityValidator::validate_user_agent("Mozilla/5.0").is_ok());
@@ -384,6 +619,29 @@ mod tests {
         assert!(SecurityValidator::validate_file_signature("test.jpg", &fake_jpeg).is_err());
     }
 
+    #[test]
+    fn test_custom_rule_registration() {
+        SecurityValidator::register_rule("no_forbidden_word", |field, value| {
+            if field == "test.custom_rule" && value.contains("forbidden") {
+                return Err(ValidationError::new("Value contains a forbidden word"));
+            }
+            Ok(())
+        });
+        assert!(SecurityValidator::registered_rules().contains(&"no_forbidden_word".to_string()));
+
+        let context = ValidationContext::default();
+        let mut result = ValidationResult::success();
+        SecurityValidator::validate_field(&mut result, &context, "test.custom_rule", "value", "a forbidden value", "invalid");
+        assert!(result.errors.contains_key("value"));
+
+        let mut result = ValidationResult::success();
+        SecurityValidator::validate_field(&mut result, &context, "test.custom_rule", "value", "an allowed value", "invalid");
+        assert!(result.is_valid);
+
+        assert!(SecurityValidator::unregister_rule("no_forbidden_word"));
+        assert!(!SecurityValidator::unregister_rule("no_forbidden_word"));
+    }
+
     #[test]
     fn test_private_ip_detection() {
         assert!(IpSecurityValidator::is_private_ip("192.168.1.1"));
@@ -0,0 +1,98 @@
+//! Unicode normalization and length helpers shared by validators and services
+//!
+//! Lengths are measured in Unicode scalar values (`char`s), which is what the
+//! `validator` derive macros count and what SQLite's `length()` reports for
+//! text columns. Grapheme clusters are not used, so an emoji with a skin-tone
+//! modifier counts as two characters.
+
+use unicode_normalization::UnicodeNormalization;
+
+const ZERO_WIDTH_CHARS: [char; 5] = ['\u{200B}', '\u{200C}', '\u{200D}', '\u{2060}', '\u{FEFF}'];
+
+const BIDI_CONTROL_CHARS: [char; 9] = [
+    '\u{202A}', '\u{202B}', '\u{202C}', '\u{202D}', '\u{202E}',
+    '\u{2066}', '\u{2067}', '\u{2068}', '\u{2069}',
+];
+
+pub fn text_length(input: &str) -> usize {
+    input.chars().count()
+}
+
+pub fn normalize_nfc(input: &str) -> String {
+    input.nfc().collect()
+}
+
+pub fn is_zero_width(c: char) -> bool {
+    ZERO_WIDTH_CHARS.contains(&c)
+}
+
+pub fn is_bidi_control(c: char) -> bool {
+    BIDI_CONTROL_CHARS.contains(&c)
+}
+
+pub fn contains_bidi_control(input: &str) -> bool {
+    input.chars().any(is_bidi_control)
+}
+
+/// Removes zero-width and control characters. Multi-line fields keep tabs and
+/// newlines; single-line fields drop them too.
+pub fn strip_invisible(input: &str, multiline: bool) -> String {
+    input
+        .chars()
+        .filter(|&c| {
+            if multiline && (c == '\t' || c == '\n') {
+                return true;
+            }
+            !c.is_control() && !is_zero_width(c)
+        })
+        .collect()
+}
+
+/// NFC-normalizes a single-line value and strips invisible characters.
+pub fn normalize_line(input: &str) -> String {
+    strip_invisible(&normalize_nfc(input), false)
+}
+
+/// NFC-normalizes a multi-line value, keeping tabs and newlines.
+pub fn normalize_multiline(input: &str) -> String {
+    strip_invisible(&normalize_nfc(input), true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_length_counts_chars_not_bytes() {
+        let japanese = "日本".repeat(100);
+        assert_eq!(japanese.len(), 600);
+        assert_eq!(text_length(&japanese), 200);
+
+        let emoji = "😀".repeat(255);
+        assert_eq!(text_length(&emoji), 255);
+        assert_eq!(text_length("👍🏽"), 2);
+    }
+
+    #[test]
+    fn test_nfc_merges_combining_characters() {
+        let decomposed = "Cafe\u{0301}";
+        let composed = "Caf\u{00E9}";
+        assert_ne!(decomposed, composed);
+        assert_eq!(normalize_nfc(decomposed), composed);
+        assert_eq!(text_length(&normalize_nfc(decomposed)), 4);
+    }
+
+    #[test]
+    fn test_strip_invisible_characters() {
+        assert_eq!(normalize_line("ad\u{200B}min\u{0007}"), "admin");
+        assert_eq!(normalize_line("tab\tseparated\n"), "tabseparated");
+        assert_eq!(normalize_multiline("line one\n\tline\u{FEFF} two\r"), "line one\n\tline two");
+    }
+
+    #[test]
+    fn test_bidi_control_detection() {
+        assert!(contains_bidi_control("invoice\u{202E}fdp.exe"));
+        assert!(contains_bidi_control("\u{2067}name"));
+        assert!(!contains_bidi_control("שלום.txt"));
+    }
+}
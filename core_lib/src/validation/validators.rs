@@ -1,6 +1,7 @@
 //! Specific validators for data models
 
-use super::{ValidationResult, ValidationContext, ContextValidatable, Validatable, rules::*};
+use super::{ValidationResult, ValidationContext, ContextValidatable, Validatable, SecurityValidator, rules::*, unicode};
+use crate::config::{ItemSchemaConfig, MetadataFieldType};
 use crate::models::request::{JsonPayload, FormPayload};
 use serde::{Deserialize, Serialize};
 use validator::{Validate, ValidationError};
@@ -8,13 +9,9 @@ use validator::{Validate, ValidationError};
 #[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct ItemValidator {
     #[validate(length(min = 1, max = 255, message = "Name must be between 1 and 255 characters"))]
-    #[validate(custom(function = "validate_no_sql_injection", message = "Name contains invalid characters"))]
-    #[validate(custom(function = "validate_no_xss", message = "Name contains invalid characters"))]
     pub name: String,
 
     #[validate(length(max = 2000, message = "Description must not exceed 2000 characters"))]
-    #[validate(custom(function = "validate_no_sql_injection", message = "Description contains invalid characters"))]
-    #[validate(custom(function = "validate_no_xss", message = "Description contains invalid characters"))]
     pub description: Option<String>,
 
     #[validate(length(max = 50, message = "Too many tags"))]
@@ -29,22 +26,22 @@ impl ItemValidator {
     }
 
     pub fn validate_tags(&self) -> ValidationResult {
+        self.validate_tags_with_context(&ValidationContext::default())
+    }
+
+    pub fn validate_tags_with_context(&self, context: &ValidationContext) -> ValidationResult {
         let mut result = ValidationResult::success();
 
         if let Some(tags) = &self.tags {
             for (i, tag) in tags.iter().enumerate() {
+                let key = format!("tags[{}]", i);
                 if tag.is_empty() {
-                    result.add_error(&format!("tags[{}]", i), "Tag cannot be empty");
-                }
-                if tag.len() > 50 {
-                    result.add_error(&format!("tags[{}]", i), "Tag must not exceed 50 characters");
-                }
-                if let Err(_) = validate_no_sql_injection(tag) {
-                    result.add_error(&format!("tags[{}]", i), "Tag contains invalid characters");
+                    result.add_error(&key, "Tag cannot be empty");
                 }
-                if let Err(_) = validate_no_xss(tag) {
-                    result.add_error(&format!("tags[{}]", i), "Tag contains invalid characters");
+                if unicode::text_length(tag) > 50 {
+                    result.add_error(&key, "Tag must not exceed 50 characters");
                 }
+                SecurityValidator::validate_field(&mut result, context, "item.tags", &key, tag, "Tag contains invalid characters");
             }
         }
 
@@ -52,26 +49,52 @@ impl ItemValidator {
     }
 
     pub fn validate_metadata(&self) -> ValidationResult {
+        self.validate_metadata_with_context(&ValidationContext::default())
+    }
+
+    pub fn validate_metadata_with_context(&self, context: &ValidationContext) -> ValidationResult {
         let mut result = ValidationResult::success();
 
         if let Some(metadata) = &self.metadata {
-            match serde_json::to_string(metadata) {
-                Ok(serialized) => {
-                    if serialized.len() > 10000 {
-                        result.add_error("metadata", "Metadata is too large (max 10KB)");
+            SecurityValidator::validate_json_field(&mut result, context, "item.metadata", "metadata", metadata, "Metadata contains potentially dangerous content");
+        }
+
+        result
+    }
+
+    /// Checks `metadata` against the deployment's item schema, reporting
+    /// each offending key as `metadata.<key>`. Keys the schema does not
+    /// mention are left alone.
+    pub fn validate_metadata_schema(schema: &ItemSchemaConfig, metadata: Option<&serde_json::Value>) -> ValidationResult {
+        let mut result = ValidationResult::success();
+        let object = metadata.and_then(|metadata| metadata.as_object());
+
+        for (key, field) in &schema.fields {
+            let field_key = format!("metadata.{}", key);
+            let value = match object.and_then(|object| object.get(key)).filter(|value| !value.is_null()) {
+                Some(value) => value,
+                None => {
+                    if field.required {
+                        result.add_error(&field_key, "is required");
                     }
+                    continue;
                 }
-                Err(_) => {
-                    result.add_error("metadata", "Invalid metadata format");
-                }
-            }
+            };
 
-            let metadata_str = metadata.to_string();
-            if let Err(_) = validate_no_sql_injection(&metadata_str) {
-                result.add_error("metadata", "Metadata contains potentially dangerous content");
-            }
-            if let Err(_) = validate_no_xss(&metadata_str) {
-                result.add_error("metadata", "Metadata contains potentially dangerous content");
+            match field.field_type {
+                MetadataFieldType::String if !value.is_string() => {
+                    result.add_error(&field_key, "must be a string");
+                }
+                MetadataFieldType::Number if !value.is_number() => {
+                    result.add_error(&field_key, "must be a number");
+                }
+                MetadataFieldType::Enum if !value.as_str().is_some_and(|value| field.values.iter().any(|allowed| allowed == value)) => {
+                    result.add_error(&field_key, &format!("must be one of {}", field.values.join(", ")));
+                }
+                MetadataFieldType::Date if !value.as_str().is_some_and(is_date) => {
+                    result.add_error(&field_key, "must be a date (YYYY-MM-DD) or an RFC 3339 timestamp");
+                }
+                _ => {}
             }
         }
 
@@ -79,14 +102,24 @@ impl ItemValidator {
     }
 }
 
+fn is_date(value: &str) -> bool {
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").is_ok() || chrono::DateTime::parse_from_rfc3339(value).is_ok()
+}
+
 impl ContextValidatable for ItemValidator {
-    fn validate_with_context(&self, _context: &ValidationContext) -> ValidationResult {
+    fn validate_with_context(&self, context: &ValidationContext) -> ValidationResult {
         let mut result = self.validate_comprehensive();
+
+        SecurityValidator::validate_field(&mut result, context, "item.name", "name", &self.name, "Name contains invalid characters");
+
+        if let Some(desc) = &self.description {
+            SecurityValidator::validate_field(&mut result, context, "item.description", "description", desc, "Description contains invalid characters");
+        }
         
-        let tags_result = self.validate_tags();
+        let tags_result = self.validate_tags_with_context(context);
         result.merge(tags_result);
         
-        let metadata_result = self.validate_metadata();
+        let metadata_result = self.validate_metadata_with_context(context);
         result.merge(metadata_result);
         
         result
@@ -173,6 +206,9 @@ impl FileUploadValidator {
             if let Err(err) = validate_file_extension(&self.filename) {
                 result.add_error("filename", &err.to_string());
             }
+            if unicode::contains_bidi_control(&self.filename) || self.filename.chars().any(char::is_control) {
+                result.add_error("filename", "Filename contains control characters");
+            }
             if let Err(_) = validate_no_xss(&self.filename) {
                 result.add_error("filename", "Filename contains invalid characters");
             }
@@ -281,7 +317,7 @@ impl SearchQueryValidator {
                 if tag.is_empty() {
                     result.add_error(&format!("tags[{}]", i), "Tag cannot be empty");
                 }
-                if tag.len() > 50 {
+                if unicode::text_length(tag) > 50 {
                     result.add_error(&format!("tags[{}]", i), "Tag is too long");
                 }
                 if let Err(_) = validate_no_sql_injection(tag) {
@@ -311,7 +347,7 @@ impl ContextValidatable for JsonPayload {
 
         if self.message.is_empty() {
             result.add_error("message", "Message cannot be empty");
-        } else if self.message.len() > 1000 {
+        } else if unicode::text_length(&self.message) > 1000 {
             result.add_error("message", "Message is too long");
         } else {
             if let Err(_) = validate_no_sql_injection(&self.message) {
@@ -352,7 +388,7 @@ impl ContextValidatable for FormPayload {
 
         if self.name.is_empty() {
             result.add_error("name", "Name cannot be empty");
-        } else if self.name.len() > 100 {
+        } else if unicode::text_length(&self.name) > 100 {
             result.add_error("name", "Name is too long");
         } else {
             if let Err(_) = validate_no_xss(&self.name) {
@@ -365,7 +401,7 @@ impl ContextValidatable for FormPayload {
         }
 
         if let Some(message) = &self.message {
-            if message.len() > 2000 {
+            if unicode::text_length(message) > 2000 {
                 result.add_error("message", "Message is too long");
             } else {
                 if let Err(_) = validate_no_sql_injection(message) {
@@ -412,6 +448,45 @@ mod tests {
         assert!(result.errors.contains_key("name"));
     }
 
+    #[test]
+    fn test_metadata_schema() {
+        use crate::config::MetadataFieldSchema;
+
+        let field = |field_type, required, values: &[&str]| MetadataFieldSchema {
+            field_type,
+            required,
+            values: values.iter().map(|value| value.to_string()).collect(),
+            description: None,
+        };
+        let schema = ItemSchemaConfig {
+            fields: [
+                ("department".to_string(), field(MetadataFieldType::Enum, true, &["sales", "support"])),
+                ("cost".to_string(), field(MetadataFieldType::Number, false, &[])),
+                ("due".to_string(), field(MetadataFieldType::Date, false, &[])),
+                ("owner".to_string(), field(MetadataFieldType::String, false, &[])),
+            ]
+            .into_iter()
+            .collect(),
+        };
+
+        let valid = serde_json::json!({"department": "sales", "cost": 12.5, "due": "2024-02-29", "extra": true});
+        assert!(ItemValidator::validate_metadata_schema(&schema, Some(&valid)).is_valid);
+
+        let result = ItemValidator::validate_metadata_schema(&schema, None);
+        assert_eq!(result.errors["metadata.department"], vec!["is required"]);
+        assert_eq!(result.errors.len(), 1);
+
+        let invalid = serde_json::json!({"department": "legal", "cost": "12", "due": "2024-02-30", "owner": 7});
+        let result = ItemValidator::validate_metadata_schema(&schema, Some(&invalid));
+        assert_eq!(result.errors["metadata.department"], vec!["must be one of sales, support"]);
+        assert_eq!(result.errors["metadata.cost"], vec!["must be a number"]);
+        assert!(result.errors.contains_key("metadata.due"));
+        assert_eq!(result.errors["metadata.owner"], vec!["must be a string"]);
+
+        let timestamp = serde_json::json!({"department": "support", "due": "2024-02-29T10:00:00Z", "cost": null});
+        assert!(ItemValidator::validate_metadata_schema(&schema, Some(&timestamp)).is_valid);
+    }
+
     #[test]
     fn test_user_registration_validator() {
         let validator = UserRegistrationValidator::new(
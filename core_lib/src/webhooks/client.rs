@@ -0,0 +1,26 @@
+//! The headers webhook deliveries carry and what an attempt came to. The
+//! requests themselves go out through [`crate::outbound::OutboundHttp`].
+
+use std::time::Duration;
+
+pub use crate::outbound::sign;
+
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+
+/// What a single delivery attempt came to. Any 2xx response is a success;
+/// redirects are not followed.
+#[derive(Debug, Clone)]
+pub struct DeliveryOutcome {
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub duration: Duration,
+}
+
+impl DeliveryOutcome {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none() && self.status_code.is_some_and(|code| (200..300).contains(&code))
+    }
+}
@@ -0,0 +1,121 @@
+//! Outbound webhooks: subscriptions to item events, delivered as signed
+//! POST requests by the job workers
+
+pub mod client;
+pub mod repository;
+pub mod service;
+
+pub use repository::WebhookRepository;
+pub use service::{WebhookDeliverer, WebhookService};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Item changes a subscription can ask to be told about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum WebhookEvent {
+    #[serde(rename = "item.created")]
+    ItemCreated,
+    #[serde(rename = "item.updated")]
+    ItemUpdated,
+    #[serde(rename = "item.deleted")]
+    ItemDeleted,
+}
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 3] = [Self::ItemCreated, Self::ItemUpdated, Self::ItemDeleted];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ItemCreated => "item.created",
+            Self::ItemUpdated => "item.updated",
+            Self::ItemDeleted => "item.deleted",
+        }
+    }
+}
+
+impl fmt::Display for WebhookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for WebhookEvent {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|event| event.as_str() == value)
+            .ok_or_else(|| format!("Unknown webhook event '{}'", value))
+    }
+}
+
+/// A URL that is sent the events it subscribed to. The secret signs each
+/// delivery and is never returned by the API.
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookSubscription {
+    pub id: i64,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub events: Vec<WebhookEvent>,
+    /// Only items carrying at least one of these tags are delivered. Empty
+    /// matches every item.
+    pub tags: Vec<String>,
+    /// Cleared after too many consecutive failed deliveries.
+    pub enabled: bool,
+    pub consecutive_failures: u32,
+    pub created_by: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub disabled_at: Option<DateTime<Utc>>,
+}
+
+impl WebhookSubscription {
+    pub fn matches(&self, event: WebhookEvent, item_tags: &[String]) -> bool {
+        self.enabled
+            && self.events.contains(&event)
+            && (self.tags.is_empty() || self.tags.iter().any(|tag| item_tags.contains(tag)))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<WebhookEvent>,
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+}
+
+/// One attempt at delivering an event to a subscription. Retries of the
+/// same event share a `delivery_id`.
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub delivery_id: String,
+    pub event: WebhookEvent,
+    pub attempt: u32,
+    pub status_code: Option<u16>,
+    pub success: bool,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+    pub attempted_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct WebhookDeliveryQuery {
+    pub limit: Option<u32>,
+}
+
+impl WebhookDeliveryQuery {
+    pub const DEFAULT_LIMIT: u32 = 50;
+    pub const MAX_LIMIT: u32 = 500;
+
+    pub fn limit(&self) -> u32 {
+        self.limit.unwrap_or(Self::DEFAULT_LIMIT).clamp(1, Self::MAX_LIMIT)
+    }
+}
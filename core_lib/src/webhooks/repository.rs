@@ -0,0 +1,342 @@
+use chrono::{DateTime, Utc};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Row, SqlitePool};
+
+use super::{WebhookDelivery, WebhookEvent, WebhookSubscription};
+use crate::database::InstrumentedPool;
+use crate::error::{AppError, Result};
+
+const SUBSCRIPTION_COLUMNS: &str =
+    "id, url, secret, events, tags, enabled, consecutive_failures, created_by, created_at, disabled_at";
+
+/// Subscriptions only see events from their own namespace.
+const NAMESPACE_FILTER: &str = "namespace = COALESCE(?, namespace)";
+
+/// A subscription to insert; the id and failure state are assigned by the
+/// database.
+#[derive(Debug, Clone)]
+pub struct NewWebhook {
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<WebhookEvent>,
+    pub tags: Vec<String>,
+    pub created_by: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A delivery attempt to record against a subscription.
+#[derive(Debug, Clone)]
+pub struct NewDelivery {
+    pub webhook_id: i64,
+    pub delivery_id: String,
+    pub event: WebhookEvent,
+    pub attempt: u32,
+    pub status_code: Option<u16>,
+    pub success: bool,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+    pub attempted_at: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct WebhookRepository {
+    pool: InstrumentedPool,
+}
+
+impl WebhookRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool: pool.into() }
+    }
+
+    pub async fn create(&self, webhook: NewWebhook) -> Result<WebhookSubscription> {
+        let result = sqlx::query(
+            "INSERT INTO webhooks (url, secret, events, tags, created_by, created_at, namespace) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&webhook.url)
+        .bind(&webhook.secret)
+        .bind(serde_json::to_string(&webhook.events)?)
+        .bind(serde_json::to_string(&webhook.tags)?)
+        .bind(webhook.created_by)
+        .bind(webhook.created_at)
+        .bind(crate::tenancy::current_or_default())
+        .execute(&self.pool)
+        .await?;
+
+        self.get(result.last_insert_rowid())
+            .await?
+            .ok_or_else(|| AppError::Database("Created webhook could not be read back".to_string()))
+    }
+
+    pub async fn get(&self, id: i64) -> Result<Option<WebhookSubscription>> {
+        let row = sqlx::query(&format!(
+            "SELECT {} FROM webhooks WHERE id = ? AND {}",
+            SUBSCRIPTION_COLUMNS, NAMESPACE_FILTER
+        ))
+        .bind(id)
+        .bind(crate::tenancy::current())
+        .fetch_optional(&self.pool)
+        .await?;
+        row.map(|row| subscription_from_row(&row)).transpose()
+    }
+
+    pub async fn list(&self) -> Result<Vec<WebhookSubscription>> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM webhooks WHERE {} ORDER BY id",
+            SUBSCRIPTION_COLUMNS, NAMESPACE_FILTER
+        ))
+        .bind(crate::tenancy::current())
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter().map(subscription_from_row).collect()
+    }
+
+    /// Deletes the subscription and its delivery history. Returns whether it
+    /// existed.
+    pub async fn delete(&self, id: i64) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query(&format!("DELETE FROM webhooks WHERE id = ? AND {}", NAMESPACE_FILTER))
+            .bind(id)
+            .bind(crate::tenancy::current())
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query("DELETE FROM webhook_deliveries WHERE webhook_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Enabled subscriptions that want `event` for an item with `tags`.
+    pub async fn subscribers(&self, event: WebhookEvent, tags: &[String]) -> Result<Vec<WebhookSubscription>> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM webhooks WHERE enabled = 1 AND {} ORDER BY id",
+            SUBSCRIPTION_COLUMNS, NAMESPACE_FILTER
+        ))
+        .bind(crate::tenancy::current())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(subscription_from_row)
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .filter(|webhook| webhook.matches(event, tags))
+            .collect())
+    }
+
+    /// Records the attempt and updates the subscription's failure streak: a
+    /// success resets it, and reaching `max_consecutive_failures` disables
+    /// the subscription. Returns the subscription as updated, if it still
+    /// exists.
+    pub async fn record_attempt(
+        &self,
+        delivery: NewDelivery,
+        max_consecutive_failures: u32,
+    ) -> Result<Option<WebhookSubscription>> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO webhook_deliveries
+                (webhook_id, delivery_id, event, attempt, status_code, success, error, duration_ms, attempted_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(delivery.webhook_id)
+        .bind(&delivery.delivery_id)
+        .bind(delivery.event.as_str())
+        .bind(delivery.attempt as i64)
+        .bind(delivery.status_code.map(i64::from))
+        .bind(delivery.success)
+        .bind(&delivery.error)
+        .bind(delivery.duration_ms as i64)
+        .bind(delivery.attempted_at)
+        .execute(&mut *tx)
+        .await?;
+
+        if delivery.success {
+            sqlx::query("UPDATE webhooks SET consecutive_failures = 0 WHERE id = ?")
+                .bind(delivery.webhook_id)
+                .execute(&mut *tx)
+                .await?;
+        } else {
+            sqlx::query(
+                r#"
+                UPDATE webhooks
+                SET consecutive_failures = consecutive_failures + 1,
+                    enabled = CASE WHEN consecutive_failures + 1 >= ? THEN 0 ELSE enabled END,
+                    disabled_at = CASE
+                        WHEN enabled = 1 AND consecutive_failures + 1 >= ? THEN ?
+                        ELSE disabled_at
+                    END
+                WHERE id = ?
+                "#,
+            )
+            .bind(max_consecutive_failures as i64)
+            .bind(max_consecutive_failures as i64)
+            .bind(delivery.attempted_at)
+            .bind(delivery.webhook_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        self.get(delivery.webhook_id).await
+    }
+
+    /// The most recent attempts for a subscription, newest first.
+    pub async fn deliveries(&self, webhook_id: i64, limit: u32) -> Result<Vec<WebhookDelivery>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, webhook_id, delivery_id, event, attempt, status_code, success, error, duration_ms, attempted_at
+            FROM webhook_deliveries
+            WHERE webhook_id = ?
+            ORDER BY id DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(webhook_id)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(WebhookDelivery {
+                    id: row.try_get("id")?,
+                    webhook_id: row.try_get("webhook_id")?,
+                    delivery_id: row.try_get("delivery_id")?,
+                    event: parse_event(row.try_get("event")?)?,
+                    attempt: row.try_get::<i64, _>("attempt")? as u32,
+                    status_code: row.try_get::<Option<i64>, _>("status_code")?.map(|code| code as u16),
+                    success: row.try_get("success")?,
+                    error: row.try_get("error")?,
+                    duration_ms: row.try_get::<i64, _>("duration_ms")? as u64,
+                    attempted_at: row.try_get("attempted_at")?,
+                })
+            })
+            .collect()
+    }
+}
+
+fn subscription_from_row(row: &SqliteRow) -> Result<WebhookSubscription> {
+    Ok(WebhookSubscription {
+        id: row.try_get("id")?,
+        url: row.try_get("url")?,
+        secret: row.try_get("secret")?,
+        events: serde_json::from_str(row.try_get("events")?)?,
+        tags: serde_json::from_str(row.try_get("tags")?)?,
+        enabled: row.try_get("enabled")?,
+        consecutive_failures: row.try_get::<i64, _>("consecutive_failures")? as u32,
+        created_by: row.try_get("created_by")?,
+        created_at: row.try_get("created_at")?,
+        disabled_at: row.try_get("disabled_at")?,
+    })
+}
+
+fn parse_event(value: &str) -> Result<WebhookEvent> {
+    value.parse().map_err(AppError::Database)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn repository() -> WebhookRepository {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::database::run_migrations(pool.clone()).await.unwrap();
+        WebhookRepository::new(pool)
+    }
+
+    fn attempt(webhook_id: i64, success: bool) -> NewDelivery {
+        NewDelivery {
+            webhook_id,
+            delivery_id: "d-1".to_string(),
+            event: WebhookEvent::ItemCreated,
+            attempt: 1,
+            status_code: Some(if success { 200 } else { 500 }),
+            success,
+            error: (!success).then(|| "Endpoint responded with status 500".to_string()),
+            duration_ms: 3,
+            attempted_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_filter_by_event_and_tag() {
+        let repo = repository().await;
+        let tagged = repo
+            .create(NewWebhook {
+                url: "https://a.example.com/".to_string(),
+                secret: "s".to_string(),
+                events: vec![WebhookEvent::ItemCreated],
+                tags: vec!["urgent".to_string()],
+                created_by: Some(1),
+                created_at: Utc::now(),
+            })
+            .await
+            .unwrap();
+        let all = repo
+            .create(NewWebhook {
+                url: "https://b.example.com/".to_string(),
+                secret: "s".to_string(),
+                events: WebhookEvent::ALL.to_vec(),
+                tags: Vec::new(),
+                created_by: None,
+                created_at: Utc::now(),
+            })
+            .await
+            .unwrap();
+
+        let ids = |subs: Vec<WebhookSubscription>| subs.into_iter().map(|s| s.id).collect::<Vec<_>>();
+        let urgent = vec!["urgent".to_string()];
+        assert_eq!(ids(repo.subscribers(WebhookEvent::ItemCreated, &urgent).await.unwrap()), vec![tagged.id, all.id]);
+        assert_eq!(ids(repo.subscribers(WebhookEvent::ItemCreated, &[]).await.unwrap()), vec![all.id]);
+        assert_eq!(ids(repo.subscribers(WebhookEvent::ItemDeleted, &urgent).await.unwrap()), vec![all.id]);
+    }
+
+    #[tokio::test]
+    async fn test_consecutive_failures_disable_and_success_resets() {
+        let repo = repository().await;
+        let webhook = repo
+            .create(NewWebhook {
+                url: "https://a.example.com/".to_string(),
+                secret: "s".to_string(),
+                events: vec![WebhookEvent::ItemCreated],
+                tags: Vec::new(),
+                created_by: None,
+                created_at: Utc::now(),
+            })
+            .await
+            .unwrap();
+
+        repo.record_attempt(attempt(webhook.id, false), 2).await.unwrap();
+        let updated = repo.record_attempt(attempt(webhook.id, true), 2).await.unwrap().unwrap();
+        assert_eq!(updated.consecutive_failures, 0);
+
+        repo.record_attempt(attempt(webhook.id, false), 2).await.unwrap();
+        let updated = repo.record_attempt(attempt(webhook.id, false), 2).await.unwrap().unwrap();
+        assert!(!updated.enabled);
+        assert!(updated.disabled_at.is_some());
+        assert!(repo.subscribers(WebhookEvent::ItemCreated, &[]).await.unwrap().is_empty());
+
+        let deliveries = repo.deliveries(webhook.id, 3).await.unwrap();
+        assert_eq!(deliveries.len(), 3);
+        assert!(!deliveries[0].success);
+        assert!(deliveries[1].id > deliveries[2].id);
+
+        assert!(repo.delete(webhook.id).await.unwrap());
+        assert!(repo.deliveries(webhook.id, 10).await.unwrap().is_empty());
+        assert!(!repo.delete(webhook.id).await.unwrap());
+    }
+}
@@ -0,0 +1,448 @@
+use super::client::{DeliveryOutcome, DELIVERY_HEADER, EVENT_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use super::repository::{NewDelivery, NewWebhook, WebhookRepository};
+use super::{CreateWebhookRequest, WebhookDelivery, WebhookEvent, WebhookSubscription};
+use crate::clock::SharedClock;
+use crate::config::WebhookConfig;
+use crate::error::{AppError, Result};
+use crate::ids::SharedIdGenerator;
+use crate::jobs::{JobPriority, JobQueue, JobRequest, JobType};
+use crate::outbound::{parse_cidrs, OutboundError, OutboundHttp, OutboundRequest, RequestSigner};
+use crate::store::Item;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const MIN_SECRET_LENGTH: usize = 16;
+const MAX_URL_LENGTH: usize = 2048;
+
+/// Manages subscriptions and turns item events into `WebhookDelivery` jobs,
+/// one per matching subscription, so delivery and its retries happen on the
+/// job workers.
+#[derive(Clone)]
+pub struct WebhookService {
+    repository: WebhookRepository,
+    job_queue: JobQueue,
+    outbound: OutboundHttp,
+    clock: SharedClock,
+    ids: SharedIdGenerator,
+    max_attempts: u32,
+}
+
+impl WebhookService {
+    pub fn new(
+        repository: WebhookRepository,
+        job_queue: JobQueue,
+        outbound: OutboundHttp,
+        config: &WebhookConfig,
+        clock: SharedClock,
+        ids: SharedIdGenerator,
+    ) -> Result<Self> {
+        Ok(Self {
+            repository,
+            job_queue,
+            outbound: outbound.allowing(&parse_cidrs(&config.allowed_private_cidrs)?),
+            clock,
+            ids,
+            max_attempts: config.max_attempts,
+        })
+    }
+
+    /// Validates and stores a subscription. The URL must resolve only to
+    /// addresses the outbound policy allows.
+    pub async fn create(&self, request: CreateWebhookRequest, created_by: Option<i64>) -> Result<WebhookSubscription> {
+        if request.url.len() > MAX_URL_LENGTH {
+            return Err(AppError::BadRequest(format!(
+                "Webhook URL must be at most {} characters",
+                MAX_URL_LENGTH
+            )));
+        }
+        self.outbound.check(&request.url).await.map_err(|e| {
+            let reason = match e {
+                OutboundError::InvalidUrl(reason) => reason,
+                e => e.to_string(),
+            };
+            AppError::BadRequest(format!("Invalid webhook URL: {}", reason))
+        })?;
+
+        if request.secret.len() < MIN_SECRET_LENGTH {
+            return Err(AppError::BadRequest(format!(
+                "Webhook secret must be at least {} characters",
+                MIN_SECRET_LENGTH
+            )));
+        }
+
+        let mut events = request.events;
+        events.sort_by_key(|event| event.as_str());
+        events.dedup();
+        if events.is_empty() {
+            return Err(AppError::BadRequest("At least one event is required".to_string()));
+        }
+
+        let mut tags: Vec<String> = request
+            .tags
+            .unwrap_or_default()
+            .into_iter()
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        tags.sort();
+        tags.dedup();
+
+        self.repository
+            .create(NewWebhook {
+                url: request.url.trim().to_string(),
+                secret: request.secret,
+                events,
+                tags,
+                created_by,
+                created_at: self.clock.now(),
+            })
+            .await
+    }
+
+    pub async fn list(&self) -> Result<Vec<WebhookSubscription>> {
+        self.repository.list().await
+    }
+
+    pub async fn get(&self, id: i64) -> Result<WebhookSubscription> {
+        self.repository
+            .get(id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Webhook with id {} not found", id)))
+    }
+
+    pub async fn delete(&self, id: i64) -> Result<()> {
+        if !self.repository.delete(id).await? {
+            return Err(AppError::NotFound(format!("Webhook with id {} not found", id)));
+        }
+        Ok(())
+    }
+
+    pub async fn deliveries(&self, id: i64, limit: u32) -> Result<Vec<WebhookDelivery>> {
+        self.get(id).await?;
+        self.repository.deliveries(id, limit).await
+    }
+
+    /// Queues a delivery of `event` for `item` to every matching
+    /// subscription. Failures are logged and swallowed: a webhook must never
+    /// fail the item operation that triggered it.
+    pub async fn publish_item(&self, event: WebhookEvent, item: &Item) {
+        let subscribers = match self.repository.subscribers(event, &item.tags).await {
+            Ok(subscribers) => subscribers,
+            Err(e) => {
+                tracing::warn!("Failed to look up webhook subscribers for {}: {}", event, e);
+                return;
+            }
+        };
+
+        for webhook in subscribers {
+            let delivery_id = self.ids.uuid().to_string();
+            let request = JobRequest {
+                job_type: JobType::WebhookDelivery,
+                payload: json!({
+                    "webhook_id": webhook.id,
+                    "delivery_id": delivery_id,
+                    "event": event,
+                    "body": {
+                        "id": delivery_id,
+                        "event": event,
+                        "occurred_at": self.clock.now(),
+                        "data": item,
+                    },
+                }),
+                priority: Some(JobPriority::Normal),
+                max_retries: Some(self.max_attempts as i32),
+            };
+
+            if let Err(e) = self.job_queue.submit_job(request).await {
+                tracing::warn!("Failed to queue {} webhook delivery to {}: {}", event, webhook.id, e);
+            }
+        }
+    }
+}
+
+/// Performs `WebhookDelivery` jobs: posts the payload, records the attempt,
+/// and fails the job when the endpoint did not accept it so the worker
+/// retries with backoff.
+pub struct WebhookDeliverer {
+    repository: WebhookRepository,
+    outbound: OutboundHttp,
+    timeout: Duration,
+    clock: SharedClock,
+    max_consecutive_failures: u32,
+}
+
+impl WebhookDeliverer {
+    pub fn new(
+        repository: WebhookRepository,
+        outbound: OutboundHttp,
+        config: &WebhookConfig,
+        clock: SharedClock,
+    ) -> Result<Arc<Self>> {
+        Ok(Arc::new(Self {
+            repository,
+            outbound: outbound.allowing(&parse_cidrs(&config.allowed_private_cidrs)?),
+            timeout: Duration::from_secs(config.timeout_seconds),
+            clock,
+            max_consecutive_failures: config.max_consecutive_failures,
+        }))
+    }
+
+    /// Delivers the job payload built by [`WebhookService::publish_item`].
+    /// `attempt` counts from 1.
+    pub async fn deliver(&self, payload: &Value, attempt: u32) -> Result<Value> {
+        let webhook_id = payload
+            .get("webhook_id")
+            .and_then(Value::as_i64)
+            .ok_or_else(|| AppError::Job("Missing webhook_id in payload".to_string()))?;
+        let delivery_id = payload
+            .get("delivery_id")
+            .and_then(Value::as_str)
+            .ok_or_else(|| AppError::Job("Missing delivery_id in payload".to_string()))?;
+        let event: WebhookEvent = payload
+            .get("event")
+            .cloned()
+            .map(serde_json::from_value)
+            .transpose()?
+            .ok_or_else(|| AppError::Job("Missing event in payload".to_string()))?;
+        let body = payload
+            .get("body")
+            .ok_or_else(|| AppError::Job("Missing body in payload".to_string()))?;
+
+        let webhook = match self.repository.get(webhook_id).await? {
+            Some(webhook) if webhook.enabled => webhook,
+            Some(_) => return Ok(json!({ "webhook_id": webhook_id, "skipped": "webhook is disabled" })),
+            None => return Ok(json!({ "webhook_id": webhook_id, "skipped": "webhook was deleted" })),
+        };
+
+        let attempted_at = self.clock.now();
+        // The job queue retries failed deliveries with its own backoff, so
+        // the client makes a single attempt. A host re-pointed at a refused
+        // address since the subscription was created fails like any other.
+        let request = OutboundRequest::post(&webhook.url)
+            .header(EVENT_HEADER, event.as_str())
+            .header(DELIVERY_HEADER, delivery_id)
+            .body("application/json", serde_json::to_vec(body)?)
+            .signed(
+                &RequestSigner::new(webhook.secret.as_str(), SIGNATURE_HEADER, TIMESTAMP_HEADER),
+                attempted_at.timestamp(),
+            )
+            .timeout(self.timeout)
+            .max_redirects(0)
+            .max_retries(0);
+        let started = Instant::now();
+        let (status_code, error) = match self.outbound.send(request).await {
+            Ok(response) if response.status.is_success() => (Some(response.status.as_u16()), None),
+            Ok(response) => (
+                Some(response.status.as_u16()),
+                Some(format!("Endpoint responded with status {}", response.status.as_u16())),
+            ),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let outcome = DeliveryOutcome {
+            status_code,
+            error,
+            duration: started.elapsed(),
+        };
+        let success = outcome.is_success();
+
+        let updated = self
+            .repository
+            .record_attempt(
+                NewDelivery {
+                    webhook_id,
+                    delivery_id: delivery_id.to_string(),
+                    event,
+                    attempt,
+                    status_code: outcome.status_code,
+                    success,
+                    error: outcome.error.clone(),
+                    duration_ms: outcome.duration.as_millis() as u64,
+                    attempted_at,
+                },
+                self.max_consecutive_failures,
+            )
+            .await?;
+
+        match outcome.error {
+            None => Ok(json!({
+                "webhook_id": webhook_id,
+                "delivery_id": delivery_id,
+                "status_code": outcome.status_code,
+                "delivered": true,
+            })),
+            Some(error) => {
+                if updated.is_some_and(|webhook| !webhook.enabled) {
+                    tracing::warn!(
+                        "Disabled webhook {} after {} consecutive failed deliveries",
+                        webhook_id,
+                        self.max_consecutive_failures
+                    );
+                }
+                Err(AppError::Job(format!("Webhook {} delivery failed: {}", webhook_id, error)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{test_app_with_config, test_config, TestApp};
+    use crate::webhooks::client::sign;
+    use axum::body::Bytes;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+    use parking_lot::Mutex;
+    use tempfile::TempDir;
+
+    const SECRET: &str = "whsec-0123456789abcdef";
+
+    type Received = Arc<Mutex<Vec<(HeaderMap, Bytes)>>>;
+
+    /// Serves `POST /hook` on a local port, answering with `status` and
+    /// keeping every request it receives.
+    async fn receiver(status: StatusCode) -> (String, Received) {
+        let received: Received = Arc::default();
+        let store = received.clone();
+        let app = Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: Bytes| async move {
+                store.lock().push((headers, body));
+                status
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, received)
+    }
+
+    async fn app_allowing_loopback() -> TestApp {
+        let storage = TempDir::new().unwrap();
+        let mut config = test_config(storage.path());
+        config.webhooks.allowed_private_cidrs = vec!["127.0.0.0/8".to_string()];
+        config.webhooks.max_attempts = 3;
+        config.webhooks.max_consecutive_failures = 3;
+        test_app_with_config(config, storage).await
+    }
+
+    fn subscription(url: &str, events: Vec<WebhookEvent>, tags: Option<Vec<String>>) -> CreateWebhookRequest {
+        CreateWebhookRequest {
+            url: url.to_string(),
+            secret: SECRET.to_string(),
+            events,
+            tags,
+        }
+    }
+
+    async fn eventually(mut done: impl FnMut() -> bool) {
+        for _ in 0..100 {
+            if done() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_matching_events_are_delivered_signed() {
+        let app = app_allowing_loopback().await;
+        let webhooks = app.state.webhooks.as_ref().expect("webhooks enabled");
+        let (url, received) = receiver(StatusCode::NO_CONTENT).await;
+
+        let webhook = webhooks
+            .create(subscription(&url, vec![WebhookEvent::ItemCreated], Some(vec!["urgent".to_string()])), Some(1))
+            .await
+            .unwrap();
+
+        let untagged = app.state.item_service.create_item("plain".to_string(), None, Vec::new(), None).await.unwrap();
+        webhooks.publish_item(WebhookEvent::ItemCreated, &untagged).await;
+        let item = app
+            .state
+            .item_service
+            .create_item("tagged".to_string(), None, vec!["urgent".to_string()], None)
+            .await
+            .unwrap();
+        webhooks.publish_item(WebhookEvent::ItemUpdated, &item).await;
+        webhooks.publish_item(WebhookEvent::ItemCreated, &item).await;
+
+        eventually(|| !received.lock().is_empty()).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let received = received.lock().clone();
+        assert_eq!(received.len(), 1);
+
+        let (headers, body) = &received[0];
+        let timestamp: i64 = headers[TIMESTAMP_HEADER].to_str().unwrap().parse().unwrap();
+        assert_eq!(headers[SIGNATURE_HEADER].to_str().unwrap(), sign(SECRET, timestamp, body));
+        assert_eq!(headers["x-webhook-event"], "item.created");
+        let payload: Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(payload["event"], "item.created");
+        assert_eq!(payload["data"]["name"], "tagged");
+
+        let mut deliveries = Vec::new();
+        for _ in 0..50 {
+            deliveries = webhooks.deliveries(webhook.id, 10).await.unwrap();
+            if !deliveries.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(deliveries.len(), 1);
+        assert!(deliveries[0].success);
+        assert_eq!(deliveries[0].status_code, Some(204));
+        assert_eq!(deliveries[0].delivery_id, payload["id"]);
+    }
+
+    #[tokio::test]
+    async fn test_failing_endpoint_is_retried_then_disabled() {
+        let app = app_allowing_loopback().await;
+        let webhooks = app.state.webhooks.as_ref().expect("webhooks enabled");
+        let (url, received) = receiver(StatusCode::INTERNAL_SERVER_ERROR).await;
+
+        let webhook = webhooks.create(subscription(&url, WebhookEvent::ALL.to_vec(), None), None).await.unwrap();
+        let item = app.state.item_service.create_item("doomed".to_string(), None, Vec::new(), None).await.unwrap();
+        webhooks.publish_item(WebhookEvent::ItemDeleted, &item).await;
+
+        let mut current = webhook.clone();
+        for _ in 0..100 {
+            current = webhooks.get(webhook.id).await.unwrap();
+            if !current.enabled {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!current.enabled);
+        assert_eq!(current.consecutive_failures, 3);
+        assert_eq!(received.lock().len(), 3);
+
+        let deliveries = webhooks.deliveries(webhook.id, 10).await.unwrap();
+        assert_eq!(deliveries.iter().map(|d| d.attempt).collect::<Vec<_>>(), vec![3, 2, 1]);
+        assert!(deliveries.iter().all(|d| !d.success && d.status_code == Some(500)));
+        assert!(deliveries.iter().all(|d| d.delivery_id == deliveries[0].delivery_id));
+    }
+
+    #[tokio::test]
+    async fn test_create_validates_target_and_secret() {
+        let app = crate::test_support::test_app().await;
+        let webhooks = app.state.webhooks.as_ref().expect("webhooks enabled");
+
+        for url in ["http://127.0.0.1:9/hook", "http://169.254.169.254/", "gopher://example.com/"] {
+            let err = webhooks
+                .create(subscription(url, vec![WebhookEvent::ItemCreated], None), None)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, AppError::BadRequest(_)), "{}: {:?}", url, err);
+        }
+
+        let mut weak = subscription("https://93.184.216.34/hook", vec![WebhookEvent::ItemCreated], None);
+        weak.secret = "short".to_string();
+        assert!(webhooks.create(weak, None).await.is_err());
+        assert!(webhooks
+            .create(subscription("https://93.184.216.34/hook", Vec::new(), None), None)
+            .await
+            .is_err());
+        assert!(webhooks.list().await.unwrap().is_empty());
+    }
+}
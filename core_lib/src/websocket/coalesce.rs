@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::config::WebSocketConfig;
+use crate::websocket::messages::WebSocketMessage;
+
+/// The kind of item event a burst is made of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Topic {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl Topic {
+    fn of(message: &WebSocketMessage) -> Option<(Topic, u64)> {
+        match message {
+            WebSocketMessage::ItemCreated(item) => Some((Topic::Created, item.id)),
+            WebSocketMessage::ItemUpdated(item) => Some((Topic::Updated, item.id)),
+            WebSocketMessage::ItemDeleted { id } => Some((Topic::Deleted, *id)),
+            _ => None,
+        }
+    }
+
+    fn summary(self, count: usize, ids: Vec<u64>) -> WebSocketMessage {
+        match self {
+            Topic::Created => WebSocketMessage::ItemsCreated { count, ids },
+            Topic::Updated => WebSocketMessage::ItemsUpdated { count, ids },
+            Topic::Deleted => WebSocketMessage::ItemsDeleted { count, ids },
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Batch {
+    generation: u64,
+    topic: Topic,
+    first: WebSocketMessage,
+    count: usize,
+    ids: Vec<u64>,
+}
+
+impl Batch {
+    /// A batch of one is sent as the event it holds.
+    fn into_message(self) -> WebSocketMessage {
+        if self.count == 1 {
+            self.first
+        } else {
+            self.topic.summary(self.count, self.ids)
+        }
+    }
+}
+
+/// What [`Coalescer::push`] decided.
+#[derive(Debug, Default)]
+pub(crate) struct Push {
+    /// Messages to deliver now, in order.
+    pub ready: Vec<WebSocketMessage>,
+    /// A batch was started; call [`Coalescer::flush`] with this generation
+    /// at the given instant.
+    pub flush_at: Option<(Instant, u64)>,
+}
+
+/// Merges bursts of item events into summary events.
+///
+/// The first item event after a quiet window goes out at once. Item events
+/// of the same kind that follow within the window are held and sent as one
+/// summary when it closes. Any other event, or an item event of another
+/// kind, sends the held batch first so clients see events in the order they
+/// were raised.
+#[derive(Debug, Default)]
+pub(crate) struct Coalescer {
+    pending: Option<Batch>,
+    window_ends: Option<Instant>,
+    generation: u64,
+    last_metrics: Option<serde_json::Value>,
+}
+
+impl Coalescer {
+    pub fn push(&mut self, message: WebSocketMessage, now: Instant, config: &WebSocketConfig) -> Push {
+        let mut push = Push::default();
+
+        let topic = Topic::of(&message).filter(|_| {
+            config.coalesce_window_ms > 0
+                && !config.coalesce_exempt_events.iter().any(|event| event == message.message_type())
+        });
+        let Some((topic, id)) = topic else {
+            push.ready.extend(self.take());
+            push.ready.push(message);
+            return push;
+        };
+
+        if let Some(batch) = self.pending.as_mut().filter(|batch| batch.topic == topic) {
+            batch.count += 1;
+            if batch.ids.len() < config.coalesce_max_ids {
+                batch.ids.push(id);
+            }
+            return push;
+        }
+        push.ready.extend(self.take());
+
+        match self.window_ends {
+            Some(window_ends) if now < window_ends => {
+                self.generation += 1;
+                let ids = if config.coalesce_max_ids > 0 { vec![id] } else { Vec::new() };
+                self.pending = Some(Batch {
+                    generation: self.generation,
+                    topic,
+                    first: message,
+                    count: 1,
+                    ids,
+                });
+                push.flush_at = Some((window_ends, self.generation));
+            }
+            _ => {
+                self.window_ends = Some(now + Duration::from_millis(config.coalesce_window_ms));
+                push.ready.push(message);
+            }
+        }
+
+        push
+    }
+
+    /// Closes the batch started as `generation`, unless something else
+    /// already sent it. Events that keep arriving are batched for another
+    /// window.
+    pub fn flush(&mut self, generation: u64, now: Instant, config: &WebSocketConfig) -> Option<WebSocketMessage> {
+        if self.pending.as_ref()?.generation != generation {
+            return None;
+        }
+        self.window_ends = Some(now + Duration::from_millis(config.coalesce_window_ms));
+        self.take()
+    }
+
+    /// Takes the held batch, if any, so it can be sent ahead of an event
+    /// that bypasses coalescing.
+    pub fn take(&mut self) -> Option<WebSocketMessage> {
+        self.pending.take().map(Batch::into_message)
+    }
+
+    /// Whether `message` is a `MetricsUpdate` identical to the previous one,
+    /// ignoring the fields that move with the clock alone.
+    pub fn is_repeated_metrics(&mut self, message: &WebSocketMessage) -> bool {
+        if !matches!(message, WebSocketMessage::MetricsUpdate(_)) {
+            return false;
+        }
+        let Ok(mut value) = serde_json::to_value(message) else {
+            return false;
+        };
+        if let Some(data) = value.get_mut("data").and_then(serde_json::Value::as_object_mut) {
+            data.remove("uptime_seconds");
+            data.remove("requests_per_second");
+        }
+        if self.last_metrics.as_ref() == Some(&value) {
+            return true;
+        }
+        self.last_metrics = Some(value);
+        false
+    }
+}
@@ -1,24 +1,53 @@
 use axum::{
     extract::{
-        ws::{WebSocketUpgrade, WebSocket},
-        Query, State,
+        ws::{close_code, WebSocketUpgrade, WebSocket},
+        ConnectInfo, Extension, Query, State,
     },
-    response::Response,
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
 };
 use tracing::{info, warn};
 
-use crate::websocket::manager::WebSocketManager;
+use crate::error::AppError;
+use crate::middleware::concurrency::ConnectionSlot;
+use crate::websocket::manager::{ClientInfo, WebSocketManager};
 use crate::AppState;
 
+const PROTOCOL_LIMIT_FACTOR: usize = 4;
+
+/// Subprotocol marker; browsers pass the token as the entry that follows it,
+/// e.g. `new WebSocket(url, ["bearer", token])`.
+const BEARER_PROTOCOL: &str = "bearer";
+
 #[derive(serde::Deserialize)]
 pub struct WebSocketQuery {
     token: Option<String>,
 }
 
+fn subprotocol_token(headers: &HeaderMap) -> Option<String> {
+    let protocols = headers
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+
+    protocols
+        .iter()
+        .position(|protocol| protocol.eq_ignore_ascii_case(BEARER_PROTOCOL))
+        .and_then(|index| protocols.get(index + 1))
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_string())
+}
+
 pub async fn websocket_handler(
     ws: WebSocketUpgrade,
     Query(params): Query<WebSocketQuery>,
+    headers: HeaderMap,
     State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<std::net::SocketAddr>>,
+    slot: Option<Extension<ConnectionSlot>>,
 ) -> Response {
     info!("WebSocket connection request received");
 
@@ -31,19 +60,67 @@ pub async fn websocket_handler(
         }
     };
 
-    ws.on_upgrade(move |socket| handle_socket(socket, ws_manager, params.token))
+    if !ws_manager.is_enabled() {
+        warn!("Refusing WebSocket upgrade: WebSockets are switched off");
+        return AppError::ServiceUnavailable("WebSockets are switched off".to_string()).into_response();
+    }
+
+    let origin = headers.get(header::ORIGIN).and_then(|value| value.to_str().ok());
+    if !ws_manager.is_origin_allowed(origin) {
+        warn!("Rejecting WebSocket upgrade from disallowed origin: {:?}", origin);
+        return ws.on_upgrade(|socket| WebSocketManager::reject(socket, close_code::POLICY, "Origin not allowed"));
+    }
+
+    let token = match (subprotocol_token(&headers), params.token) {
+        (Some(token), _) => Some(token),
+        (None, Some(token)) if ws_manager.config().allow_query_token => {
+            warn!("WebSocket token supplied in query string; this is deprecated, use the bearer subprotocol instead");
+            Some(token)
+        }
+        (None, Some(_)) => {
+            warn!("Rejecting WebSocket upgrade with query-string token");
+            return ws.on_upgrade(|socket| {
+                WebSocketManager::reject(socket, close_code::POLICY, "Query-string tokens are disabled")
+            });
+        }
+        (None, None) => None,
+    };
+
+    let forwarded_for = headers.get("x-forwarded-for").and_then(|h| h.to_str().ok());
+    let client = ClientInfo {
+        remote_ip: connect_info.map(|ci| state.anomaly_tracker.client_ip(ci.0.ip(), forwarded_for).to_string()),
+        user_agent: headers
+            .get(header::USER_AGENT)
+            .and_then(|h| h.to_str().ok())
+            .map(|s| s.to_string()),
+    };
+
+    // Messages modestly over the configured size still reach the manager so the
+    // client gets a precise `message_too_large` error frame; anything far larger
+    // is refused by the protocol layer before it is buffered.
+    let hard_limit = ws_manager.config().message_buffer_size.saturating_mul(PROTOCOL_LIMIT_FACTOR);
+
+    ws.protocols([BEARER_PROTOCOL])
+        .max_message_size(hard_limit)
+        .max_frame_size(hard_limit)
+        .on_upgrade(move |socket| async move {
+            // Counts against the client's concurrency limit until closed.
+            let _slot = slot;
+            handle_socket(socket, ws_manager, token, client).await
+        })
 }
 
 async fn handle_socket(
     socket: WebSocket,
     ws_manager: WebSocketManager,
     token: Option<String>,
+    client: ClientInfo,
 ) {
     info!("WebSocket connection established");
 
-    if let Err(e) = ws_manager.handle_connection(socket, token).await {
+    if let Err(e) = ws_manager.handle_connection(socket, token, client).await {
         warn!("WebSocket connection error: {}", e);
     }
 
     info!("WebSocket connection closed");
-}
\ No newline at end of file
+}
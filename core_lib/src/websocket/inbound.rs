@@ -0,0 +1,147 @@
+//! Validation and rate limiting for client-to-server WebSocket messages
+
+use std::time::{Duration, Instant};
+
+use crate::websocket::messages::WebSocketMessage;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InboundError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl InboundError {
+    fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<InboundError> for WebSocketMessage {
+    fn from(error: InboundError) -> Self {
+        WebSocketMessage::protocol_error(error.code, error.message)
+    }
+}
+
+/// Parses a text frame from a client, reporting exactly why a message was
+/// rejected: too large, not JSON, missing or unknown `type`, a type clients
+/// may not send, or a payload that does not match the type's schema.
+pub fn parse_client_message(text: &str, max_size: usize) -> Result<WebSocketMessage, InboundError> {
+    if text.len() > max_size {
+        return Err(InboundError::new(
+            "message_too_large",
+            format!("Message is {} bytes (max: {} bytes)", text.len(), max_size),
+        ));
+    }
+
+    let value: serde_json::Value = serde_json::from_str(text)
+        .map_err(|e| InboundError::new("malformed_json", format!("Message is not valid JSON: {}", e)))?;
+
+    let object = value
+        .as_object()
+        .ok_or_else(|| InboundError::new("invalid_message", "Message must be a JSON object"))?;
+
+    let message_type = match object.get("type") {
+        Some(serde_json::Value::String(message_type)) => message_type.clone(),
+        Some(_) => return Err(InboundError::new("invalid_type", "Field `type` must be a string")),
+        None => return Err(InboundError::new("missing_field", "Missing required field `type`")),
+    };
+
+    if !WebSocketMessage::MESSAGE_TYPES.contains(&message_type.as_str()) {
+        return Err(InboundError::new(
+            "unknown_type",
+            format!("Unknown message type `{}`", message_type),
+        ));
+    }
+
+    if !WebSocketMessage::CLIENT_MESSAGE_TYPES.contains(&message_type.as_str()) {
+        return Err(InboundError::new(
+            "unsupported_type",
+            format!(
+                "Message type `{}` cannot be sent by clients (allowed: {})",
+                message_type,
+                WebSocketMessage::CLIENT_MESSAGE_TYPES.join(", ")
+            ),
+        ));
+    }
+
+    serde_json::from_value(value).map_err(|e| {
+        InboundError::new(
+            "invalid_payload",
+            format!("Invalid `{}` message: {}", message_type, e),
+        )
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateDecision {
+    Allow,
+    /// First message over the limit in this window; the client gets a warning.
+    Warn,
+    /// Further messages over the limit in a window that was already warned.
+    Drop,
+    /// The client stayed over the limit for too many consecutive windows.
+    Disconnect,
+}
+
+/// Fixed one-second window counter for a single connection. A limit of zero
+/// disables inbound rate limiting.
+#[derive(Debug)]
+pub struct InboundRateLimiter {
+    limit_per_second: u32,
+    max_violations: u32,
+    window_start: Instant,
+    count: u32,
+    violations: u32,
+    warned: bool,
+}
+
+impl InboundRateLimiter {
+    const WINDOW: Duration = Duration::from_secs(1);
+
+    pub fn new(limit_per_second: u32, max_violations: u32) -> Self {
+        Self {
+            limit_per_second,
+            max_violations: max_violations.max(1),
+            window_start: Instant::now(),
+            count: 0,
+            violations: 0,
+            warned: false,
+        }
+    }
+
+    pub fn check(&mut self, now: Instant) -> RateDecision {
+        if self.limit_per_second == 0 {
+            return RateDecision::Allow;
+        }
+
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed >= Self::WINDOW {
+            if !self.warned || elapsed >= Self::WINDOW * 2 {
+                self.violations = 0;
+            }
+            self.window_start = now;
+            self.count = 0;
+            self.warned = false;
+        }
+
+        self.count += 1;
+        if self.count <= self.limit_per_second {
+            return RateDecision::Allow;
+        }
+
+        if self.warned {
+            return RateDecision::Drop;
+        }
+
+        self.warned = true;
+        self.violations += 1;
+        if self.violations >= self.max_violations {
+            RateDecision::Disconnect
+        } else {
+            RateDecision::Warn
+        }
+    }
+}
@@ -1,38 +1,173 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::{broadcast, Mutex, RwLock};
 use uuid::Uuid;
-use axum::extract::ws::{WebSocket, Message};
+use axum::extract::ws::{close_code, CloseFrame, WebSocket, Message};
 use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
 use tracing::{info, warn, error, debug};
 use chrono::{DateTime, Utc};
 
-use crate::websocket::messages::{WebSocketMessage, WebSocketEvent};
+use crate::websocket::coalesce::Coalescer;
+use crate::websocket::messages::{EventEnvelope, OutboundEvent, PresenceChange, WebSocketMessage, WebSocketEvent, PROTOCOL_VERSION};
+use crate::websocket::inbound::{parse_client_message, InboundRateLimiter, RateDecision};
+use crate::websocket::queue::{outbound_channel, OutboundError, OutboundSender};
+use crate::websocket::replay::{Replay, ReplayBuffer};
+use crate::auth::models::UserRole;
 use crate::auth::JwtService;
+use crate::config::{CorsConfig, SlowConsumerPolicy, WebSocketConfig};
 use crate::error::{AppError, Result};
+use crate::features::FeatureSwitch;
+use crate::middleware::cors::OriginPattern;
 
 #[derive(Debug)]
 pub struct WebSocketConnection {
     pub id: Uuid,
     pub user_id: Option<u64>,
+    pub is_admin: bool,
     pub connected_at: DateTime<Utc>,
-    pub sender: mpsc::UnboundedSender<WebSocketMessage>,
+    pub sender: OutboundSender,
+    pub client: ClientInfo,
+    /// Topics the client subscribed to; empty means every topic but
+    /// `presence` and the per-item `item:<id>` topics.
+    pub topics: Vec<String>,
+    pub frames: Arc<FrameCounters>,
+}
+
+/// Who is on the other end of a connection, as seen in the upgrade request.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClientInfo {
+    pub remote_ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Frames exchanged with a client, excluding pings and pongs at the
+/// protocol level.
+#[derive(Debug, Default)]
+pub struct FrameCounters {
+    pub sent: AtomicU64,
+    pub received: AtomicU64,
+}
+
+/// A connection as listed by the admin API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionInfo {
+    pub connection_id: Uuid,
+    pub user_id: Option<u64>,
+    pub remote_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub connected_at: DateTime<Utc>,
+    pub topics: Vec<String>,
+    pub frames_sent: u64,
+    pub frames_received: u64,
+    pub queue_depth: usize,
+}
+
+/// Per-connection lag: how far behind a client is and how many events it lost.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionLag {
+    pub connection_id: Uuid,
+    pub user_id: Option<u64>,
+    pub queue_depth: usize,
+    pub dropped_events: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebSocketStats {
+    pub connections: usize,
+    pub max_connections: usize,
+    pub queue_capacity: usize,
+    pub slow_consumer_policy: SlowConsumerPolicy,
+    pub total_queued: usize,
+    pub max_queue_depth: usize,
+    pub dropped_events: u64,
+    pub slow_consumer_disconnects: u64,
+    pub rejected_connections: u64,
+    pub lagging_connections: Vec<ConnectionLag>,
+    /// Connections receiving each topic.
+    #[serde(default)]
+    pub topics: BTreeMap<String, usize>,
 }
 
 impl WebSocketConnection {
-    pub fn new(user_id: Option<u64>, sender: mpsc::UnboundedSender<WebSocketMessage>) -> Self {
+    pub fn new(user_id: Option<u64>, sender: OutboundSender) -> Self {
         Self {
             id: Uuid::new_v4(),
             user_id,
+            is_admin: false,
             connected_at: Utc::now(),
             sender,
+            client: ClientInfo::default(),
+            topics: Vec::new(),
+            frames: Arc::new(FrameCounters::default()),
+        }
+    }
+
+    pub fn with_client(mut self, client: ClientInfo) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn with_admin(mut self, is_admin: bool) -> Self {
+        self.is_admin = is_admin;
+        self
+    }
+
+    /// Whether a broadcast of `message` should reach this connection.
+    pub fn receives(&self, message: &WebSocketMessage) -> bool {
+        if let Some(item_id) = message.item_scope() {
+            return self.topics.iter().any(|topic| WebSocketMessage::item_topic(topic) == Some(item_id));
+        }
+        message.topic().is_none_or(|topic| self.receives_topic(topic))
+    }
+
+    /// Whether events on `topic`, a named or `item:<id>` topic, reach this
+    /// connection, and so may be replayed to it.
+    fn receives_replay_topic(&self, topic: &str) -> bool {
+        if WebSocketMessage::item_topic(topic).is_some() {
+            return self.topics.iter().any(|t| t == topic);
+        }
+        WebSocketMessage::TOPICS.contains(&topic) && self.receives_topic(topic)
+    }
+
+    fn receives_topic(&self, topic: &str) -> bool {
+        let subscribed = self.topics.iter().any(|t| t == topic);
+        match topic {
+            "presence" => self.is_admin && subscribed,
+            "admin" => self.is_admin && (self.topics.is_empty() || subscribed),
+            _ => self.topics.is_empty() || subscribed,
+        }
+    }
+
+    pub fn info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            connection_id: self.id,
+            user_id: self.user_id,
+            remote_ip: self.client.remote_ip.clone(),
+            user_agent: self.client.user_agent.clone(),
+            connected_at: self.connected_at,
+            topics: self.topics.clone(),
+            frames_sent: self.frames.sent.load(Ordering::Relaxed),
+            frames_received: self.frames.received.load(Ordering::Relaxed),
+            queue_depth: self.sender.depth(),
         }
     }
 
     pub fn send(&self, message: WebSocketMessage) -> Result<()> {
-        self.sender.send(message)
-            .map_err(|_| AppError::WebSocket("Failed to send message to connection".to_string()))?;
-        Ok(())
+        self.sender.send(message).map_err(|e| match e {
+            OutboundError::Closed => AppError::WebSocket("Failed to send message to connection".to_string()),
+            OutboundError::Overflow => AppError::WebSocket("Connection outbound queue overflowed".to_string()),
+        })
+    }
+
+    pub fn lag(&self) -> ConnectionLag {
+        ConnectionLag {
+            connection_id: self.id,
+            user_id: self.user_id,
+            queue_depth: self.sender.depth(),
+            dropped_events: self.sender.dropped(),
+        }
     }
 }
 
@@ -40,6 +175,24 @@ impl WebSocketConnection {
 pub struct WebSocketManager {
     connections: Arc<RwLock<HashMap<Uuid, WebSocketConnection>>>,
     jwt_service: Option<JwtService>,
+    config: WebSocketConfig,
+    retired_dropped_events: Arc<AtomicU64>,
+    slow_consumer_disconnects: Arc<AtomicU64>,
+    rejected_connections: Arc<AtomicU64>,
+    allowed_origins: Option<Arc<Vec<OriginPattern>>>,
+    /// Copies of every broadcast, for listeners other than WebSocket
+    /// connections.
+    events: broadcast::Sender<WebSocketMessage>,
+    /// Held item events; locked for the whole of a delivery so clients see
+    /// events in the order they were raised.
+    coalescer: Arc<Mutex<Coalescer>>,
+    /// Recent events per topic, numbered while the coalescer is locked so
+    /// their numbers follow delivery order.
+    replay: Arc<parking_lot::Mutex<ReplayBuffer>>,
+    /// Every event as it is kept for replay, for event streams to follow.
+    replayed_events: broadcast::Sender<OutboundEvent>,
+    /// Nothing is sent while this is off.
+    switch: FeatureSwitch,
 }
 
 impl WebSocketManager {
@@ -47,9 +200,173 @@ impl WebSocketManager {
         Self {
             connections: Arc::new(RwLock::new(HashMap::new())),
             jwt_service,
+            config: WebSocketConfig::default(),
+            retired_dropped_events: Arc::new(AtomicU64::new(0)),
+            slow_consumer_disconnects: Arc::new(AtomicU64::new(0)),
+            rejected_connections: Arc::new(AtomicU64::new(0)),
+            allowed_origins: None,
+            events: broadcast::channel(WebSocketConfig::default().outbound_queue_size.max(1)).0,
+            coalescer: Arc::new(Mutex::new(Coalescer::default())),
+            replay: Arc::new(parking_lot::Mutex::new(ReplayBuffer::from_config(&WebSocketConfig::default()))),
+            replayed_events: broadcast::channel(WebSocketConfig::default().outbound_queue_size.max(1)).0,
+            switch: FeatureSwitch::default(),
+        }
+    }
+
+    pub fn with_config(mut self, config: WebSocketConfig) -> Self {
+        self.events = broadcast::channel(config.outbound_queue_size.max(1)).0;
+        self.replay = Arc::new(parking_lot::Mutex::new(ReplayBuffer::from_config(&config)));
+        self.replayed_events = broadcast::channel(config.outbound_queue_size.max(1)).0;
+        self.config = config;
+        self
+    }
+
+    /// While `switch` is off, upgrades and event streams are refused and
+    /// events are dropped rather than sent or kept for replay.
+    pub fn with_switch(mut self, switch: FeatureSwitch) -> Self {
+        self.switch = switch;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.switch.is_enabled()
+    }
+
+    /// Restricts upgrades to the origins allowed by the global CORS policy.
+    /// Permissive CORS mode leaves every origin allowed.
+    pub fn with_origin_allowlist(mut self, cors: &CorsConfig) -> Self {
+        self.allowed_origins = if cors.enable_permissive_mode {
+            None
+        } else {
+            Some(Arc::new(
+                cors.allowed_origins
+                    .iter()
+                    .filter_map(|origin| origin.parse().ok())
+                    .collect(),
+            ))
+        };
+        self
+    }
+
+    /// Requests without an `Origin` header come from non-browser clients and
+    /// are not subject to the allowlist.
+    pub fn is_origin_allowed(&self, origin: Option<&str>) -> bool {
+        match (&self.allowed_origins, origin) {
+            (Some(patterns), Some(origin)) => patterns.iter().any(|pattern| pattern.matches(origin)),
+            _ => true,
+        }
+    }
+
+    /// Validates an access token and returns the user it identifies.
+    pub fn authenticate(&self, token: &str) -> Result<u64> {
+        self.identify(token).map(|(user_id, _)| user_id)
+    }
+
+    /// Like [`authenticate`](Self::authenticate), also telling whether the
+    /// user is an admin.
+    fn identify(&self, token: &str) -> Result<(u64, bool)> {
+        let jwt_service = self
+            .jwt_service
+            .as_ref()
+            .ok_or_else(|| AppError::Authentication("WebSocket authentication is not available".to_string()))?;
+
+        let claims = jwt_service.validate_access_token(token)?;
+        let user_id = claims
+            .sub
+            .parse::<u64>()
+            .map_err(|_| AppError::Authentication("Invalid user ID in token".to_string()))?;
+        Ok((user_id, claims.role.parse::<UserRole>() == Ok(UserRole::Admin)))
+    }
+
+    /// Closes a socket that was upgraded only to tell the client why it is
+    /// being refused.
+    pub async fn reject(mut socket: WebSocket, code: u16, reason: &str) {
+        let _ = socket
+            .send(Message::Close(Some(CloseFrame {
+                code,
+                reason: reason.to_string().into(),
+            })))
+            .await;
+    }
+
+    pub async fn set_connection_user(&self, connection_id: &Uuid, user_id: u64, is_admin: bool) {
+        if let Some(connection) = self.connections.write().await.get_mut(connection_id) {
+            connection.user_id = Some(user_id);
+            connection.is_admin = is_admin;
+        }
+    }
+
+    /// Limits the connection to `topics`. Only admins may subscribe to
+    /// `presence` and `admin`.
+    pub async fn set_connection_topics(&self, connection_id: &Uuid, mut topics: Vec<String>) -> Result<()> {
+        if let Some(topic) = topics.iter().find(|topic| {
+            !WebSocketMessage::TOPICS.contains(&topic.as_str()) && WebSocketMessage::item_topic(topic).is_none()
+        }) {
+            return Err(AppError::BadRequest(format!(
+                "Unknown topic '{}'; expected one of {} or item:<id>",
+                topic,
+                WebSocketMessage::TOPICS.join(", ")
+            )));
+        }
+        topics.sort();
+        topics.dedup();
+
+        let mut connections = self.connections.write().await;
+        let connection = connections
+            .get_mut(connection_id)
+            .ok_or_else(|| AppError::NotFound("WebSocket connection not found".to_string()))?;
+        if !connection.is_admin {
+            if let Some(topic) = topics.iter().find(|topic| WebSocketMessage::ADMIN_TOPICS.contains(&topic.as_str())) {
+                return Err(AppError::Authorization(format!("Only admins may subscribe to {}", topic)));
+            }
+        }
+        connection.topics = topics;
+        Ok(())
+    }
+
+    /// Waits up to the grace period for an `Authenticate` message, answering
+    /// anything else with `authentication_required`.
+    async fn await_authentication(&self, socket: &mut WebSocket) -> Option<(u64, bool)> {
+        let deadline = tokio::time::Instant::now()
+            + std::time::Duration::from_secs(self.config.auth_grace_period_seconds);
+
+        loop {
+            let msg = match tokio::time::timeout_at(deadline, socket.recv()).await {
+                Ok(Some(Ok(msg))) => msg,
+                _ => return None,
+            };
+
+            let reply = match msg {
+                Message::Text(text) => match parse_client_message(&text, self.config.message_buffer_size) {
+                    Ok(WebSocketMessage::Authenticate { token }) => match self.identify(&token) {
+                        Ok(identity) => return Some(identity),
+                        Err(e) => {
+                            warn!("WebSocket authentication failed: {}", e);
+                            WebSocketMessage::protocol_error("authentication_failed", e.to_string())
+                        }
+                    },
+                    Ok(_) => WebSocketMessage::protocol_error(
+                        "authentication_required",
+                        "Send an Authenticate message before any other message",
+                    ),
+                    Err(err) => err.into(),
+                },
+                Message::Close(_) => return None,
+                _ => continue,
+            };
+
+            if let Some(json) = encode(&reply.into(), PROTOCOL_VERSION) {
+                if socket.send(Message::Text(json)).await.is_err() {
+                    return None;
+                }
+            }
         }
     }
 
+    pub fn config(&self) -> &WebSocketConfig {
+        &self.config
+    }
+
     pub async fn add_connection(&self, connection: WebSocketConnection) {
         let connection_id = connection.id;
         let mut connections = self.connections.write().await;
@@ -57,9 +374,24 @@ impl WebSocketManager {
         info!("WebSocket connection added: {}", connection_id);
     }
 
+    /// Adds the connection unless the manager is already at
+    /// `max_connections`, in which case the connection is handed back.
+    pub async fn try_add_connection(&self, connection: WebSocketConnection) -> std::result::Result<(), WebSocketConnection> {
+        let connection_id = connection.id;
+        let mut connections = self.connections.write().await;
+        if connections.len() >= self.config.max_connections {
+            self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+            return Err(connection);
+        }
+        connections.insert(connection_id, connection);
+        info!("WebSocket connection added: {}", connection_id);
+        Ok(())
+    }
+
     pub async fn remove_connection(&self, connection_id: &Uuid) {
         let mut connections = self.connections.write().await;
-        if connections.remove(connection_id).is_some() {
+        if let Some(connection) = connections.remove(connection_id) {
+            self.retire(&connection);
             info!("WebSocket connection removed: {}", connection_id);
         }
     }
@@ -69,37 +401,233 @@ impl WebSocketManager {
         connections.len()
     }
 
+    pub async fn stats(&self) -> WebSocketStats {
+        let connections = self.connections.read().await;
+
+        let mut lags: Vec<ConnectionLag> = connections.values().map(|c| c.lag()).collect();
+        let total_queued = lags.iter().map(|lag| lag.queue_depth).sum();
+        let max_queue_depth = lags.iter().map(|lag| lag.queue_depth).max().unwrap_or(0);
+        let live_dropped: u64 = lags.iter().map(|lag| lag.dropped_events).sum();
+
+        let topics = WebSocketMessage::TOPICS
+            .iter()
+            .map(|topic| {
+                let count = connections.values().filter(|c| c.receives_topic(topic)).count();
+                (topic.to_string(), count)
+            })
+            .collect();
+
+        lags.retain(|lag| lag.queue_depth > 0 || lag.dropped_events > 0);
+        lags.sort_by(|a, b| b.queue_depth.cmp(&a.queue_depth).then(b.dropped_events.cmp(&a.dropped_events)));
+
+        WebSocketStats {
+            connections: connections.len(),
+            max_connections: self.config.max_connections,
+            queue_capacity: self.config.outbound_queue_size,
+            slow_consumer_policy: self.config.slow_consumer_policy,
+            total_queued,
+            max_queue_depth,
+            dropped_events: live_dropped + self.retired_dropped_events.load(Ordering::Relaxed),
+            slow_consumer_disconnects: self.slow_consumer_disconnects.load(Ordering::Relaxed),
+            rejected_connections: self.rejected_connections.load(Ordering::Relaxed),
+            lagging_connections: lags,
+            topics,
+        }
+    }
+
+    /// Every open connection, oldest first.
+    pub async fn connections(&self) -> Vec<ConnectionInfo> {
+        let connections = self.connections.read().await;
+        let mut infos: Vec<ConnectionInfo> = connections.values().map(WebSocketConnection::info).collect();
+        infos.sort_by(|a, b| a.connected_at.cmp(&b.connected_at).then(a.connection_id.cmp(&b.connection_id)));
+        infos
+    }
+
+    /// Closes a connection after sending what is already queued for it,
+    /// with `reason` in the close frame. Returns the connection, or `None`
+    /// if it was not open.
+    pub async fn disconnect(&self, connection_id: &Uuid, reason: &str) -> Option<ConnectionInfo> {
+        let connection = self.connections.write().await.remove(connection_id)?;
+        connection.sender.disconnect(reason);
+        self.retire(&connection);
+        info!("WebSocket connection {} disconnected: {}", connection_id, reason);
+        Some(connection.info())
+    }
+
+    /// Sends `event` to every connection. Bursts of item events are merged
+    /// into summaries as configured by `coalesce_window_ms`, and a metrics
+    /// snapshot identical to the previous one is not sent at all.
     pub async fn broadcast(&self, event: WebSocketEvent) {
+        if !self.is_enabled() {
+            return;
+        }
         let message = WebSocketMessage::from(event);
-        let connections = self.connections.read().await;
-        let mut failed_connections = Vec::new();
+        let mut coalescer = self.coalescer.lock().await;
+        if coalescer.is_repeated_metrics(&message) {
+            return;
+        }
+        // Only fails when nobody is subscribed.
+        let _ = self.events.send(message.clone());
 
-        for (connection_id, connection) in connections.iter() {
-            if let Err(_) = connection.send(message.clone()) {
-                warn!("Failed to send message to connection: {}", connection_id);
-                failed_connections.push(*connection_id);
-            }
+        let push = coalescer.push(message, tokio::time::Instant::now(), &self.config);
+        for message in push.ready {
+            self.deliver(&self.record(message), |_| true).await;
         }
+        drop(coalescer);
 
-        drop(connections);
-        if !failed_connections.is_empty() {
-            let mut connections = self.connections.write().await;
-            for connection_id in failed_connections {
-                connections.remove(&connection_id);
-                info!("Removed failed connection: {}", connection_id);
-            }
+        if let Some((flush_at, generation)) = push.flush_at {
+            let manager = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep_until(flush_at).await;
+                let mut coalescer = manager.coalescer.lock().await;
+                if let Some(message) = coalescer.flush(generation, tokio::time::Instant::now(), &manager.config) {
+                    manager.deliver(&manager.record(message), |_| true).await;
+                }
+            });
         }
     }
 
+    /// Receives every event passed to [`broadcast`](Self::broadcast) from
+    /// now on. A subscriber more than `outbound_queue_size` events behind
+    /// misses the oldest ones.
+    pub fn subscribe(&self) -> broadcast::Receiver<WebSocketMessage> {
+        self.events.subscribe()
+    }
+
     pub async fn broadcast_to_user(&self, user_id: u64, event: WebSocketEvent) {
-        let message = WebSocketMessage::from(event);
+        self.send_to_user(user_id, WebSocketMessage::from(event)).await;
+    }
+
+    /// Sends `message` to every connection authenticated as `user_id`. It is
+    /// not kept for replay, which would show it to others.
+    pub async fn send_to_user(&self, user_id: u64, message: WebSocketMessage) {
+        self.deliver_now(message, false, |connection| connection.user_id == Some(user_id)).await;
+    }
+
+    /// Sends `message` to every connection authenticated as an admin.
+    pub async fn send_to_admins(&self, message: WebSocketMessage) {
+        self.deliver_now(message, true, |connection| connection.is_admin).await;
+    }
+
+    /// Sends `message` to every connection subscribed to its topic, without
+    /// coalescing. Item-scoped messages such as `ItemCommentAdded` reach
+    /// only the subscribers of their item.
+    pub async fn publish(&self, message: WebSocketMessage) {
+        if !self.is_enabled() {
+            return;
+        }
+        // Only fails when nobody is subscribed.
+        let _ = self.events.send(message.clone());
+        self.deliver_now(message, true, |_| true).await;
+    }
+
+    /// Delivers `message` without coalescing, after any held batch. A
+    /// `replayable` message with a topic is kept for replay.
+    async fn deliver_now<F>(&self, message: WebSocketMessage, replayable: bool, filter: F)
+    where
+        F: Fn(&WebSocketConnection) -> bool,
+    {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut coalescer = self.coalescer.lock().await;
+        if let Some(held) = coalescer.take() {
+            self.deliver(&self.record(held), |_| true).await;
+        }
+        let event = if replayable { self.record(message) } else { message.into() };
+        self.deliver(&event, filter).await;
+    }
+
+    async fn announce_presence(&self, change: PresenceChange, connection_id: Uuid, user_id: Option<u64>) {
+        let message = WebSocketMessage::Presence { change, connection_id, user_id };
+        self.deliver_now(message, true, |connection| connection.id != connection_id).await;
+    }
+
+    /// Numbers `message` and keeps it for replay if it has a topic. Callers
+    /// hold the coalescer lock.
+    fn record(&self, message: WebSocketMessage) -> OutboundEvent {
+        let mut replay = self.replay.lock();
+        let event = replay.record(message.into(), tokio::time::Instant::now());
+        if event.seq.is_some() {
+            // Only fails when nobody is following.
+            let _ = self.replayed_events.send(event.clone());
+        }
+        event
+    }
+
+    /// Queues on the connection the events on `topic` after `seq`, followed
+    /// by `Resumed`, before any later event. When some of them are no longer
+    /// kept, or there are more than its queue holds, the connection is sent
+    /// `ResyncRequired` instead.
+    pub async fn resume(&self, connection_id: &Uuid, topic: &str, seq: u64) -> Result<()> {
+        // Nothing is delivered while the coalescer is locked.
+        let _coalescer = self.coalescer.lock().await;
+        let connections = self.connections.read().await;
+        let connection = connections
+            .get(connection_id)
+            .ok_or_else(|| AppError::NotFound("WebSocket connection not found".to_string()))?;
+        if !connection.receives_replay_topic(topic) {
+            return Err(AppError::BadRequest(format!(
+                "Connection does not receive topic '{}'; subscribe to it first",
+                topic
+            )));
+        }
+
+        let (replay, latest_seq) = {
+            let mut buffer = self.replay.lock();
+            (buffer.since(topic, seq, tokio::time::Instant::now()), buffer.latest_seq())
+        };
+        let reply = match replay {
+            Replay::Events(events) => {
+                let replayed = events.len();
+                if connection.sender.replay(topic, events) {
+                    debug!("Replayed {} {} events to WebSocket connection {}", replayed, topic, connection_id);
+                    WebSocketMessage::Resumed { topic: topic.to_string(), replayed }
+                } else {
+                    WebSocketMessage::ResyncRequired { topic: topic.to_string(), seq: latest_seq }
+                }
+            }
+            Replay::ResyncRequired { seq } => WebSocketMessage::ResyncRequired { topic: topic.to_string(), seq },
+        };
+        connection.send(reply)
+    }
+
+    /// The events on `topic` after `seq`, when given, and a receiver for
+    /// every event kept from then on, for streams outside WebSocket
+    /// connections. No event is in both or missing from both.
+    pub fn follow(&self, topic: &str, seq: Option<u64>) -> (Replay, broadcast::Receiver<OutboundEvent>) {
+        let mut replay = self.replay.lock();
+        let receiver = self.replayed_events.subscribe();
+        let backlog = match seq {
+            Some(seq) => replay.since(topic, seq, tokio::time::Instant::now()),
+            None => Replay::Events(Vec::new()),
+        };
+        (backlog, receiver)
+    }
+
+    /// Queues `event` on every matching connection subscribed to its topic.
+    /// Queuing never waits on a client, so one slow consumer cannot hold up
+    /// delivery to the others.
+    async fn deliver<F>(&self, event: &OutboundEvent, filter: F)
+    where
+        F: Fn(&WebSocketConnection) -> bool,
+    {
         let connections = self.connections.read().await;
         let mut failed_connections = Vec::new();
 
-        for (connection_id, connection) in connections.iter() {
-            if connection.user_id == Some(user_id) {
-                if let Err(_) = connection.send(message.clone()) {
-                    warn!("Failed to send message to user {} connection: {}", user_id, connection_id);
+        for (connection_id, connection) in connections.iter().filter(|(_, c)| c.receives(&event.message) && filter(c)) {
+            match connection.sender.send(event.clone()) {
+                Ok(()) => {}
+                Err(OutboundError::Overflow) => {
+                    self.slow_consumer_disconnects.fetch_add(1, Ordering::Relaxed);
+                    warn!(
+                        "Disconnecting slow WebSocket consumer {} ({} queued {} events)",
+                        connection_id, connection.sender.capacity(), event.message.message_type()
+                    );
+                    failed_connections.push(*connection_id);
+                }
+                Err(OutboundError::Closed) => {
+                    warn!("Failed to send message to connection: {}", connection_id);
                     failed_connections.push(*connection_id);
                 }
             }
@@ -109,92 +637,221 @@ impl WebSocketManager {
         if !failed_connections.is_empty() {
             let mut connections = self.connections.write().await;
             for connection_id in failed_connections {
-                connections.remove(&connection_id);
-                info!("Removed failed connection: {}", connection_id);
+                if let Some(connection) = connections.remove(&connection_id) {
+                    self.retire(&connection);
+                    info!("Removed failed connection: {}", connection_id);
+                }
             }
         }
     }
 
+    fn retire(&self, connection: &WebSocketConnection) {
+        self.retired_dropped_events.fetch_add(connection.sender.dropped(), Ordering::Relaxed);
+    }
+
     pub async fn handle_connection(
         &self,
         socket: WebSocket,
         token: Option<String>,
+        client: ClientInfo,
     ) -> Result<()> {
-        let user_id = if let (Some(jwt_service), Some(token)) = (&self.jwt_service, token) {
-            match jwt_service.validate_token(&token) {
-                Ok(claims) => {
-                    debug!("WebSocket connection authenticated for user: {}", claims.sub);
-                    Some(claims.sub.parse::<u64>().unwrap_or(0))
+        let mut socket = socket;
+        let mut identity = match token {
+            Some(token) => match self.identify(&token) {
+                Ok(identity) => {
+                    debug!("WebSocket connection authenticated for user: {}", identity.0);
+                    Some(identity)
                 }
                 Err(e) => {
                     warn!("WebSocket authentication failed: {}", e);
+                    Self::reject(socket, close_code::POLICY, "Invalid or expired token").await;
                     return Err(AppError::Authentication("Invalid JWT token".to_string()));
                 }
-            }
-        } else {
-            None
+            },
+            None => None,
         };
 
-        let (mut sender, mut receiver) = socket.split();
-        let (tx, mut rx) = mpsc::unbounded_channel::<WebSocketMessage>();
+        if identity.is_none() && self.config.require_authentication {
+            match self.await_authentication(&mut socket).await {
+                Some(authenticated) => {
+                    debug!("WebSocket connection authenticated for user: {}", authenticated.0);
+                    identity = Some(authenticated);
+                }
+                None => {
+                    Self::reject(socket, close_code::POLICY, "Authentication required").await;
+                    return Ok(());
+                }
+            }
+        }
 
-        let connection = WebSocketConnection::new(user_id, tx);
+        let (tx, mut rx) = outbound_channel(self.config.outbound_queue_size, self.config.slow_consumer_policy);
+        let reply_tx = tx.clone();
+        let user_id = identity.map(|(user_id, _)| user_id);
+        let connection = WebSocketConnection::new(user_id, tx)
+            .with_admin(identity.is_some_and(|(_, is_admin)| is_admin))
+            .with_client(client);
         let connection_id = connection.id;
+        let frames = connection.frames.clone();
+
+        if self.try_add_connection(connection).await.is_err() {
+            warn!("Rejecting WebSocket connection: limit of {} reached", self.config.max_connections);
+            let reason = format!("Connection limit of {} reached; try again later", self.config.max_connections);
+            Self::reject(socket, close_code::AGAIN, &reason).await;
+            return Ok(());
+        }
 
-        let _ = connection.send(WebSocketMessage::Connected { connection_id });
+        if let Some(user_id) = user_id {
+            let _ = reply_tx.send(WebSocketMessage::Authenticated { user_id });
+        }
+        let _ = reply_tx.send(WebSocketMessage::Connected { connection_id });
+        self.announce_presence(PresenceChange::Joined, connection_id, user_id).await;
 
-        self.add_connection(connection).await;
+        let (mut sender, mut receiver) = socket.split();
 
-        let _manager_clone = self.clone();
+        // Clients get the newest protocol until they ask for an older one.
+        let protocol_version = Arc::new(AtomicU32::new(PROTOCOL_VERSION));
+        let outgoing_version = protocol_version.clone();
+        let outgoing_frames = frames.clone();
         let outgoing_task = tokio::spawn(async move {
-            while let Some(message) = rx.recv().await {
-                let json = match message.to_json() {
-                    Ok(json) => json,
-                    Err(e) => {
-                        error!("Failed to serialize WebSocket message: {}", e);
-                        continue;
-                    }
+            while let Some(event) = rx.recv_event().await {
+                let Some(json) = encode(&event, outgoing_version.load(Ordering::Relaxed)) else {
+                    continue;
                 };
 
                 if sender.send(Message::Text(json)).await.is_err() {
                     debug!("WebSocket connection closed, stopping outgoing message handler");
                     break;
                 }
+                outgoing_frames.sent.fetch_add(1, Ordering::Relaxed);
+            }
+
+            let reason = if rx.is_overflowed() {
+                Some("Outbound queue overflowed; reconnect to resync".to_string())
+            } else {
+                rx.close_reason()
+            };
+            if let Some(reason) = reason {
+                let _ = sender
+                    .send(Message::Close(Some(CloseFrame {
+                        code: close_code::POLICY,
+                        reason: reason.into(),
+                    })))
+                    .await;
             }
         });
 
-        let manager_clone = self.clone();
+        let max_message_size = self.config.message_buffer_size;
+        let mut rate_limiter = InboundRateLimiter::new(
+            self.config.max_messages_per_second,
+            self.config.max_rate_limit_violations,
+        );
+        let manager = self.clone();
+        let auth_deadline = std::time::Instant::now()
+            + std::time::Duration::from_secs(self.config.auth_grace_period_seconds);
+        let mut authenticated = user_id.is_some();
         let incoming_task = tokio::spawn(async move {
             while let Some(msg) = receiver.next().await {
+                let msg = match msg {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        warn!("WebSocket error: {}", e);
+                        break;
+                    }
+                };
+
+                if matches!(msg, Message::Text(_) | Message::Binary(_)) {
+                    frames.received.fetch_add(1, Ordering::Relaxed);
+                    match rate_limiter.check(std::time::Instant::now()) {
+                        RateDecision::Allow => {}
+                        RateDecision::Drop => continue,
+                        RateDecision::Warn => {
+                            warn!("WebSocket connection {} exceeded the inbound message rate", connection_id);
+                            let _ = reply_tx.send(WebSocketMessage::protocol_error(
+                                "rate_limited",
+                                "Too many messages; slow down or the connection will be closed",
+                            ));
+                            continue;
+                        }
+                        RateDecision::Disconnect => {
+                            warn!("Disconnecting WebSocket connection {} for sustained message flooding", connection_id);
+                            let _ = reply_tx.send(WebSocketMessage::protocol_error(
+                                "rate_limit_exceeded",
+                                "Message rate limit exceeded repeatedly; closing connection",
+                            ));
+                            break;
+                        }
+                    }
+                }
+
                 match msg {
-                    Ok(Message::Text(text)) => {
-                        debug!("Received WebSocket message: {}", text);
-                        
-                        if let Ok(message) = WebSocketMessage::from_json(&text) {
-                            match message {
-                                WebSocketMessage::Ping => {
-                                    let connections = manager_clone.connections.read().await;
-                                    if let Some(connection) = connections.get(&connection_id) {
-                                        let _ = connection.send(WebSocketMessage::Pong);
-                                    }
-                                }
-                                _ => {
-                                    debug!("Received unhandled WebSocket message type");
+                    Message::Text(text) => {
+                        debug!("Received WebSocket message ({} bytes)", text.len());
+
+                        match parse_client_message(&text, max_message_size) {
+                            Ok(WebSocketMessage::Ping) => {
+                                let _ = reply_tx.send(WebSocketMessage::Pong);
+                            }
+                            Ok(WebSocketMessage::Subscribe { version, topics }) => {
+                                let reply = if version == 0 {
+                                    WebSocketMessage::protocol_error("unsupported_version", "Protocol versions start at 1")
+                                } else if let Err(e) = manager.set_connection_topics(&connection_id, topics).await {
+                                    WebSocketMessage::protocol_error("invalid_topic", e.to_string())
+                                } else {
+                                    let version = version.min(PROTOCOL_VERSION);
+                                    protocol_version.store(version, Ordering::Relaxed);
+                                    debug!("WebSocket connection {} uses protocol version {}", connection_id, version);
+                                    WebSocketMessage::Subscribed { version }
+                                };
+                                let _ = reply_tx.send(reply);
+                            }
+                            Ok(WebSocketMessage::ResumeFrom { topic, seq }) => {
+                                if let Err(e) = manager.resume(&connection_id, &topic, seq).await {
+                                    let _ = reply_tx.send(WebSocketMessage::protocol_error("invalid_topic", e.to_string()));
                                 }
                             }
+                            Ok(WebSocketMessage::Authenticate { token }) => {
+                                let reply = if authenticated {
+                                    WebSocketMessage::protocol_error("already_authenticated", "Connection is already authenticated")
+                                } else if std::time::Instant::now() > auth_deadline {
+                                    WebSocketMessage::protocol_error(
+                                        "authentication_window_closed",
+                                        "Authenticate must be sent within the grace period after connecting",
+                                    )
+                                } else {
+                                    match manager.identify(&token) {
+                                        Ok((user_id, is_admin)) => {
+                                            manager.set_connection_user(&connection_id, user_id, is_admin).await;
+                                            authenticated = true;
+                                            debug!("WebSocket connection {} authenticated for user: {}", connection_id, user_id);
+                                            WebSocketMessage::Authenticated { user_id }
+                                        }
+                                        Err(e) => {
+                                            warn!("WebSocket authentication failed: {}", e);
+                                            WebSocketMessage::protocol_error("authentication_failed", e.to_string())
+                                        }
+                                    }
+                                };
+                                let _ = reply_tx.send(reply);
+                            }
+                            Ok(message) => {
+                                debug!("Received {} message from client", message.message_type());
+                            }
+                            Err(err) => {
+                                debug!("Rejected WebSocket message from {}: {}", connection_id, err.message);
+                                let _ = reply_tx.send(WebSocketMessage::from(err));
+                            }
                         }
                     }
-                    Ok(Message::Binary(_)) => {
-                        debug!("Received binary WebSocket message (not supported)");
+                    Message::Binary(_) => {
+                        let _ = reply_tx.send(WebSocketMessage::protocol_error(
+                            "unsupported_format",
+                            "Binary messages are not supported; send JSON text frames",
+                        ));
                     }
-                    Ok(Message::Close(_)) => {
+                    Message::Close(_) => {
                         debug!("WebSocket connection closed by client");
                         break;
                     }
-                    Err(e) => {
-                        warn!("WebSocket error: {}", e);
-                        break;
-                    }
                     _ => {}
                 }
             }
@@ -209,7 +866,22 @@ impl WebSocketManager {
             }
         }
 
+        // The client may have authenticated after connecting.
+        let user_id = self.connections.read().await.get(&connection_id).map_or(user_id, |c| c.user_id);
         self.remove_connection(&connection_id).await;
+        self.announce_presence(PresenceChange::Left, connection_id, user_id).await;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// `event` wrapped for a client of protocol `version`, or `None` when that
+/// version cannot express it or it fails to serialize.
+fn encode(event: &OutboundEvent, version: u32) -> Option<String> {
+    match EventEnvelope::new(event, version).and_then(|envelope| envelope.map(|e| e.to_json()).transpose()) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize outbound {} message: {}", event.message.message_type(), e);
+            None
+        }
+    }
+}
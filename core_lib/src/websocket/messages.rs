@@ -1,5 +1,7 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
+use crate::comments::Comment;
 use crate::store::Item;
 use crate::metrics::MetricsSnapshot;
 use crate::jobs::JobResponse;
@@ -10,16 +12,66 @@ pub enum WebSocketMessage {
     ItemCreated(Item),
     ItemUpdated(Item),
     ItemDeleted { id: u64 },
+    /// A burst of `ItemCreated` events merged into one. `ids` lists at most
+    /// the configured number of them; `count` is the full number.
+    ItemsCreated { count: usize, ids: Vec<u64> },
+    ItemsUpdated { count: usize, ids: Vec<u64> },
+    ItemsDeleted { count: usize, ids: Vec<u64> },
     MetricsUpdate(MetricsSnapshot),
     JobStarted(JobResponse),
     JobCompleted(JobResponse),
     JobFailed(JobResponse),
     JobCancelled(JobResponse),
     JobRetrying(JobResponse),
+    /// A client connected or disconnected. Sent to admins subscribed to the
+    /// `presence` topic.
+    Presence { change: PresenceChange, connection_id: Uuid, user_id: Option<u64> },
+    /// Bytes of an upload the server has received so far. `total` is the
+    /// declared length of the whole request, multipart framing included,
+    /// when the client sent one.
+    UploadProgress { upload_id: Uuid, bytes_received: u64, total: Option<u64> },
+    UploadCompleted { upload_id: Uuid, file_id: Uuid },
+    UploadFailed { upload_id: Uuid, error: String },
+    /// An admin changed a user's role. Sent to admins.
+    RoleChanged { user_id: u64, username: String, old_role: String, new_role: String, changed_by: String },
+    /// A background task kept panicking and will not be restarted. Sent to
+    /// admins.
+    TaskDead { name: String, restarts: u32, error: String },
+    /// A comment was added to an item. Sent only to clients subscribed to
+    /// that item's `item:<id>` topic.
+    ItemCommentAdded(Comment),
     Connected { connection_id: Uuid },
+    Authenticate { token: String },
+    Authenticated { user_id: u64 },
+    /// Sent by a client to ask for events in at most this protocol version,
+    /// from the given topics. No topics means every topic but `presence`.
+    Subscribe {
+        version: u32,
+        #[serde(default)]
+        topics: Vec<String>,
+    },
+    /// The protocol version the connection's events will use.
+    Subscribed { version: u32 },
+    /// Sent by a reconnecting client to get the events on `topic` numbered
+    /// after `seq`, the last one it saw.
+    ResumeFrom { topic: String, seq: u64 },
+    /// The missed events on `topic` have been sent, `replayed` of them; live
+    /// events follow.
+    Resumed { topic: String, replayed: usize },
+    /// Events after the requested one are no longer kept. The client should
+    /// reload `topic` in full and resume from `seq`.
+    ResyncRequired { topic: String, seq: u64 },
     Ping,
     Pong,
     Error { message: String },
+    ProtocolError { code: String, message: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceChange {
+    Joined,
+    Left,
 }
 
 #[derive(Debug, Clone)]
@@ -62,6 +114,135 @@ impl From<WebSocketEvent> for WebSocketMessage {
 }
 
 impl WebSocketMessage {
+    /// Every `type` tag the protocol knows about.
+    pub const MESSAGE_TYPES: &'static [&'static str] = &[
+        "ItemCreated", "ItemUpdated", "ItemDeleted",
+        "ItemsCreated", "ItemsUpdated", "ItemsDeleted", "MetricsUpdate",
+        "JobStarted", "JobCompleted", "JobFailed", "JobCancelled", "JobRetrying",
+        "Presence", "UploadProgress", "UploadCompleted", "UploadFailed", "RoleChanged", "TaskDead", "ItemCommentAdded", "Connected", "Authenticate", "Authenticated", "Subscribe", "Subscribed",
+        "ResumeFrom", "Resumed", "ResyncRequired",
+        "Ping", "Pong", "Error", "ProtocolError",
+    ];
+
+    /// Topics a client can subscribe to, besides one `item:<id>` topic per
+    /// item.
+    pub const TOPICS: &'static [&'static str] = &["items", "metrics", "jobs", "presence", "admin"];
+
+    /// Topics only admins receive.
+    pub const ADMIN_TOPICS: &'static [&'static str] = &["presence", "admin"];
+
+    /// The subset of message types clients are allowed to send.
+    pub const CLIENT_MESSAGE_TYPES: &'static [&'static str] = &["Authenticate", "Subscribe", "ResumeFrom", "Ping", "Pong"];
+
+    pub fn protocol_error(code: &str, message: impl Into<String>) -> Self {
+        WebSocketMessage::ProtocolError {
+            code: code.to_string(),
+            message: message.into(),
+        }
+    }
+
+    pub fn message_type(&self) -> &'static str {
+        match self {
+            WebSocketMessage::ItemCreated(_) => "ItemCreated",
+            WebSocketMessage::ItemUpdated(_) => "ItemUpdated",
+            WebSocketMessage::ItemDeleted { .. } => "ItemDeleted",
+            WebSocketMessage::ItemsCreated { .. } => "ItemsCreated",
+            WebSocketMessage::ItemsUpdated { .. } => "ItemsUpdated",
+            WebSocketMessage::ItemsDeleted { .. } => "ItemsDeleted",
+            WebSocketMessage::MetricsUpdate(_) => "MetricsUpdate",
+            WebSocketMessage::JobStarted(_) => "JobStarted",
+            WebSocketMessage::JobCompleted(_) => "JobCompleted",
+            WebSocketMessage::JobFailed(_) => "JobFailed",
+            WebSocketMessage::JobCancelled(_) => "JobCancelled",
+            WebSocketMessage::JobRetrying(_) => "JobRetrying",
+            WebSocketMessage::Presence { .. } => "Presence",
+            WebSocketMessage::UploadProgress { .. } => "UploadProgress",
+            WebSocketMessage::UploadCompleted { .. } => "UploadCompleted",
+            WebSocketMessage::UploadFailed { .. } => "UploadFailed",
+            WebSocketMessage::RoleChanged { .. } => "RoleChanged",
+            WebSocketMessage::TaskDead { .. } => "TaskDead",
+            WebSocketMessage::ItemCommentAdded(_) => "ItemCommentAdded",
+            WebSocketMessage::Connected { .. } => "Connected",
+            WebSocketMessage::Authenticate { .. } => "Authenticate",
+            WebSocketMessage::Authenticated { .. } => "Authenticated",
+            WebSocketMessage::Subscribe { .. } => "Subscribe",
+            WebSocketMessage::Subscribed { .. } => "Subscribed",
+            WebSocketMessage::ResumeFrom { .. } => "ResumeFrom",
+            WebSocketMessage::Resumed { .. } => "Resumed",
+            WebSocketMessage::ResyncRequired { .. } => "ResyncRequired",
+            WebSocketMessage::Ping => "Ping",
+            WebSocketMessage::Pong => "Pong",
+            WebSocketMessage::Error { .. } => "Error",
+            WebSocketMessage::ProtocolError { .. } => "ProtocolError",
+        }
+    }
+
+    /// The topic an event is published under. Replies to a client have none
+    /// and always reach it.
+    pub fn topic(&self) -> Option<&'static str> {
+        match self {
+            WebSocketMessage::ItemCreated(_)
+            | WebSocketMessage::ItemUpdated(_)
+            | WebSocketMessage::ItemDeleted { .. }
+            | WebSocketMessage::ItemsCreated { .. }
+            | WebSocketMessage::ItemsUpdated { .. }
+            | WebSocketMessage::ItemsDeleted { .. } => Some("items"),
+            WebSocketMessage::MetricsUpdate(_) => Some("metrics"),
+            WebSocketMessage::JobStarted(_)
+            | WebSocketMessage::JobCompleted(_)
+            | WebSocketMessage::JobFailed(_)
+            | WebSocketMessage::JobCancelled(_)
+            | WebSocketMessage::JobRetrying(_) => Some("jobs"),
+            WebSocketMessage::Presence { .. } => Some("presence"),
+            WebSocketMessage::RoleChanged { .. } | WebSocketMessage::TaskDead { .. } => Some("admin"),
+            _ => None,
+        }
+    }
+
+    /// The item whose `item:<id>` topic an event is published under, for
+    /// events only that item's subscribers receive.
+    pub fn item_scope(&self) -> Option<u64> {
+        match self {
+            WebSocketMessage::ItemCommentAdded(comment) => Some(comment.item_id),
+            _ => None,
+        }
+    }
+
+    /// The topic an event is numbered and kept for replay under: its
+    /// `item:<id>` topic for item-scoped events, otherwise [`topic`](Self::topic).
+    pub fn replay_topic(&self) -> Option<String> {
+        match self.item_scope() {
+            Some(item_id) => Some(format!("item:{}", item_id)),
+            None => self.topic().map(str::to_string),
+        }
+    }
+
+    /// The item an `item:<id>` topic names.
+    pub fn item_topic(topic: &str) -> Option<u64> {
+        topic.strip_prefix("item:")?.parse().ok().filter(|id| *id > 0)
+    }
+
+    /// The first protocol version with this message.
+    pub fn since_version(&self) -> u32 {
+        match self {
+            WebSocketMessage::Subscribed { .. } => 2,
+            WebSocketMessage::ItemsCreated { .. }
+            | WebSocketMessage::ItemsUpdated { .. }
+            | WebSocketMessage::ItemsDeleted { .. } => 3,
+            WebSocketMessage::Presence { .. } => 4,
+            WebSocketMessage::UploadProgress { .. }
+            | WebSocketMessage::UploadCompleted { .. }
+            | WebSocketMessage::UploadFailed { .. } => 5,
+            WebSocketMessage::RoleChanged { .. } => 6,
+            WebSocketMessage::ItemCommentAdded(_) => 7,
+            WebSocketMessage::TaskDead { .. } => 8,
+            WebSocketMessage::ResumeFrom { .. }
+            | WebSocketMessage::Resumed { .. }
+            | WebSocketMessage::ResyncRequired { .. } => 9,
+            _ => 1,
+        }
+    }
+
     pub fn to_json(&self) -> Result<String, serde_json::Error> {
         serde_json::to_string(self)
     }
@@ -69,4 +250,110 @@ impl WebSocketMessage {
     pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
         serde_json::from_str(json)
     }
-}
\ No newline at end of file
+}
+
+/// Newest event protocol version. Bump it whenever the payload of an event
+/// changes shape, and teach [`EventEnvelope::new`] to convert back or to
+/// leave out what older versions cannot express.
+///
+/// 1. The original payloads.
+/// 2. Items carry their `version`, and `Subscribed` confirms the version a
+///    client asked for.
+/// 3. Bursts of item events may arrive merged as `ItemsCreated`,
+///    `ItemsUpdated` and `ItemsDeleted`. Older clients do not receive them.
+/// 4. `Presence` tells admins when clients connect and disconnect.
+/// 5. `UploadProgress`, `UploadCompleted` and `UploadFailed` follow a
+///    user's own uploads.
+/// 6. `RoleChanged` tells admins when a user's role changes.
+/// 7. `ItemCommentAdded` reaches clients subscribed to `item:<id>` topics.
+/// 8. `TaskDead` tells admins when a background task has been given up on.
+/// 9. Events published under a topic carry `topic` and `seq`, and a client
+///    that reconnects can send `ResumeFrom` to catch up on what it missed.
+pub const PROTOCOL_VERSION: u32 = 9;
+
+/// A message on its way to clients, with the id and time it was raised. A
+/// broadcast keeps the same id on every connection it is queued on.
+#[derive(Debug, Clone)]
+pub struct OutboundEvent {
+    pub event_id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    /// Where the event falls among those kept for replay; `None` for events
+    /// that are not kept, such as replies and messages to a single user.
+    pub seq: Option<u64>,
+    pub message: WebSocketMessage,
+}
+
+impl From<WebSocketMessage> for OutboundEvent {
+    fn from(message: WebSocketMessage) -> Self {
+        Self {
+            event_id: Uuid::new_v4(),
+            timestamp: Utc::now(),
+            seq: None,
+            message,
+        }
+    }
+}
+
+/// What goes over the wire. `type` and `data` are the message as before, so
+/// clients that ignore the other fields keep working.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventEnvelope {
+    pub version: u32,
+    pub event_id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+    /// The topic and number to resume from after this event; see
+    /// [`WebSocketMessage::ResumeFrom`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+}
+
+impl EventEnvelope {
+    /// `event` as a client of protocol `version` understands it, or `None`
+    /// when that version cannot express it.
+    pub fn new(event: &OutboundEvent, version: u32) -> Result<Option<Self>, serde_json::Error> {
+        let version = version.clamp(1, PROTOCOL_VERSION);
+        if event.message.since_version() > version {
+            return Ok(None);
+        }
+
+        let mut value = serde_json::to_value(&event.message)?;
+        let mut data = value.get_mut("data").map(serde_json::Value::take);
+
+        if version < 2 {
+            if let (WebSocketMessage::ItemCreated(_) | WebSocketMessage::ItemUpdated(_), Some(item)) =
+                (&event.message, data.as_mut().and_then(serde_json::Value::as_object_mut))
+            {
+                item.remove("version");
+            }
+        }
+
+        Ok(Some(Self {
+            version,
+            event_id: event.event_id,
+            timestamp: event.timestamp,
+            event_type: event.message.message_type().to_string(),
+            data,
+            topic: event.seq.and_then(|_| event.message.replay_topic()),
+            seq: event.seq,
+        }))
+    }
+
+    /// The message inside, when its payload matches the current protocol.
+    pub fn message(&self) -> Result<WebSocketMessage, serde_json::Error> {
+        let mut value = serde_json::json!({ "type": self.event_type });
+        if let Some(data) = &self.data {
+            value["data"] = data.clone();
+        }
+        serde_json::from_value(value)
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+}
@@ -1,10 +1,18 @@
+mod coalesce;
 pub mod handler;
+pub mod inbound;
 pub mod manager;
 pub mod messages;
+pub mod queue;
+pub mod replay;
+pub mod sse;
 
 #[cfg(test)]
 mod tests;
 
 pub use handler::websocket_handler;
-pub use manager::{WebSocketManager, WebSocketConnection};
-pub use messages::{WebSocketMessage, WebSocketEvent};
\ No newline at end of file
+pub use sse::sse_handler;
+pub use manager::{WebSocketManager, WebSocketConnection, WebSocketStats, ConnectionLag, ConnectionInfo, ClientInfo};
+pub use queue::{outbound_channel, OutboundSender, OutboundReceiver};
+pub use replay::{Replay, ReplayBuffer};
+pub use messages::{EventEnvelope, OutboundEvent, PresenceChange, WebSocketMessage, WebSocketEvent, PROTOCOL_VERSION};
\ No newline at end of file
@@ -0,0 +1,218 @@
+//! Bounded per-connection outbound queue
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+
+use crate::config::SlowConsumerPolicy;
+use crate::websocket::messages::{OutboundEvent, WebSocketMessage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboundError {
+    /// The receiving side is gone.
+    Closed,
+    /// The queue was full and the connection was closed under the
+    /// `disconnect` policy.
+    Overflow,
+}
+
+#[derive(Debug)]
+struct Shared {
+    queue: Mutex<VecDeque<OutboundEvent>>,
+    notify: Notify,
+    capacity: usize,
+    policy: SlowConsumerPolicy,
+    dropped: AtomicU64,
+    senders: AtomicUsize,
+    closed: AtomicBool,
+    overflowed: AtomicBool,
+    close_reason: Mutex<Option<String>>,
+}
+
+impl Shared {
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.notify.notify_one();
+    }
+}
+
+/// Sending half of an outbound queue. Sending never waits on the consumer:
+/// when the queue is full the configured [`SlowConsumerPolicy`] decides
+/// whether the oldest queued event is discarded or the connection is closed.
+#[derive(Debug)]
+pub struct OutboundSender {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+pub struct OutboundReceiver {
+    shared: Arc<Shared>,
+}
+
+pub fn outbound_channel(capacity: usize, policy: SlowConsumerPolicy) -> (OutboundSender, OutboundReceiver) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(VecDeque::new()),
+        notify: Notify::new(),
+        capacity: capacity.max(1),
+        policy,
+        dropped: AtomicU64::new(0),
+        senders: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+        overflowed: AtomicBool::new(false),
+        close_reason: Mutex::new(None),
+    });
+
+    (
+        OutboundSender { shared: shared.clone() },
+        OutboundReceiver { shared },
+    )
+}
+
+impl OutboundSender {
+    pub fn send(&self, event: impl Into<OutboundEvent>) -> Result<(), OutboundError> {
+        if self.is_closed() {
+            return Err(OutboundError::Closed);
+        }
+
+        {
+            let mut queue = self.shared.queue.lock();
+            if queue.len() >= self.shared.capacity {
+                self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                match self.shared.policy {
+                    SlowConsumerPolicy::DropOldest => {
+                        queue.pop_front();
+                    }
+                    SlowConsumerPolicy::Disconnect => {
+                        drop(queue);
+                        self.shared.overflowed.store(true, Ordering::Release);
+                        self.shared.close();
+                        return Err(OutboundError::Overflow);
+                    }
+                }
+            }
+            queue.push_back(event.into());
+        }
+
+        self.shared.notify.notify_one();
+        Ok(())
+    }
+
+    /// Queues `events`, the missed events on `topic`, in place of any
+    /// numbered events on that topic still waiting, so the client gets each
+    /// once and in order. Returns false, queuing nothing, when they would not
+    /// fit.
+    pub fn replay(&self, topic: &str, events: Vec<OutboundEvent>) -> bool {
+        if self.is_closed() {
+            return false;
+        }
+
+        {
+            let mut queue = self.shared.queue.lock();
+            let is_replaced = |event: &OutboundEvent| {
+                event.seq.is_some() && event.message.replay_topic().as_deref() == Some(topic)
+            };
+            let kept = queue.iter().filter(|event| !is_replaced(event)).count();
+            if kept + events.len() > self.shared.capacity {
+                return false;
+            }
+            queue.retain(|event| !is_replaced(event));
+            queue.extend(events);
+        }
+
+        self.shared.notify.notify_one();
+        true
+    }
+
+    pub fn depth(&self) -> usize {
+        self.shared.queue.lock().len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Acquire)
+    }
+
+    /// Closes the connection once the events already queued are sent,
+    /// telling the client `reason` in the close frame.
+    pub fn disconnect(&self, reason: impl Into<String>) {
+        *self.shared.close_reason.lock() = Some(reason.into());
+        self.shared.close();
+    }
+}
+
+impl Clone for OutboundSender {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::AcqRel);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for OutboundSender {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.close();
+        }
+    }
+}
+
+impl OutboundReceiver {
+    /// Waits for the next message. Returns `None` once every sender is gone
+    /// and the queue is drained, or immediately after an overflow disconnect.
+    pub async fn recv(&mut self) -> Option<WebSocketMessage> {
+        self.recv_event().await.map(|event| event.message)
+    }
+
+    /// Like [`recv`](Self::recv), keeping the event's id and timestamp.
+    pub async fn recv_event(&mut self) -> Option<OutboundEvent> {
+        loop {
+            if let Some(event) = self.try_recv_event() {
+                return Some(event);
+            }
+            if self.shared.closed.load(Ordering::Acquire) {
+                return None;
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+
+    /// True when the connection was closed because the queue overflowed under
+    /// the `disconnect` policy.
+    pub fn is_overflowed(&self) -> bool {
+        self.shared.overflowed.load(Ordering::Acquire)
+    }
+
+    /// The reason given to [`OutboundSender::disconnect`], if the connection
+    /// was closed that way.
+    pub fn close_reason(&self) -> Option<String> {
+        self.shared.close_reason.lock().clone()
+    }
+
+    pub fn try_recv(&mut self) -> Option<WebSocketMessage> {
+        self.try_recv_event().map(|event| event.message)
+    }
+
+    pub fn try_recv_event(&mut self) -> Option<OutboundEvent> {
+        if self.is_overflowed() {
+            return None;
+        }
+        self.shared.queue.lock().pop_front()
+    }
+}
+
+impl Drop for OutboundReceiver {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+    }
+}
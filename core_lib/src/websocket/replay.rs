@@ -0,0 +1,162 @@
+//! Recent events per topic, for clients catching up after a reconnect
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::config::WebSocketConfig;
+use crate::websocket::messages::OutboundEvent;
+
+/// How often events past their age are cleared from every topic, rather
+/// than only from the topic being written.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a client resuming after an event has to do.
+#[derive(Debug)]
+pub enum Replay {
+    /// Apply these events, oldest first; none means nothing was missed.
+    Events(Vec<OutboundEvent>),
+    /// Some missed events are no longer kept. Reload the topic in full and
+    /// resume from `seq`.
+    ResyncRequired { seq: u64 },
+}
+
+#[derive(Debug, Default)]
+struct TopicLog {
+    events: VecDeque<(Instant, OutboundEvent)>,
+    /// Newest event of the topic no longer kept.
+    evicted_through: u64,
+}
+
+impl TopicLog {
+    fn expire(&mut self, cutoff: Option<Instant>) {
+        let Some(cutoff) = cutoff else { return };
+        while self.events.front().is_some_and(|(at, _)| *at < cutoff) {
+            self.evict();
+        }
+    }
+
+    fn evict(&mut self) {
+        if let Some((_, event)) = self.events.pop_front() {
+            self.evicted_through = event.seq.unwrap_or(self.evicted_through);
+        }
+    }
+}
+
+/// The newest `capacity` events of each topic, each kept for at most
+/// `max_age`.
+///
+/// Events are numbered from one sequence shared by every topic, starting at
+/// the time the buffer was created in microseconds. Numbers given out before
+/// a restart are therefore always below those given out after it, and a
+/// client resuming from one is told to resync instead of being sent events
+/// it cannot place.
+#[derive(Debug)]
+pub struct ReplayBuffer {
+    capacity: usize,
+    max_age: Duration,
+    topics: HashMap<String, TopicLog>,
+    /// First number this buffer gave out.
+    first_seq: u64,
+    next_seq: u64,
+    /// Newest event of any topic whose log has been dropped entirely.
+    forgotten_through: u64,
+    last_sweep: Instant,
+}
+
+impl ReplayBuffer {
+    pub fn new(capacity: usize, max_age: Duration) -> Self {
+        let first_seq = u64::try_from(chrono::Utc::now().timestamp_micros()).unwrap_or(0).max(1);
+        Self {
+            capacity,
+            max_age,
+            topics: HashMap::new(),
+            first_seq,
+            next_seq: first_seq,
+            forgotten_through: 0,
+            last_sweep: Instant::now(),
+        }
+    }
+
+    pub fn from_config(config: &WebSocketConfig) -> Self {
+        Self::new(config.replay_buffer_size, Duration::from_secs(config.replay_max_age_seconds))
+    }
+
+    /// The number of the newest event kept, or just below the first number
+    /// if there has been none.
+    pub fn latest_seq(&self) -> u64 {
+        self.next_seq - 1
+    }
+
+    /// Numbers `event` and keeps a copy under its topic. Events without a
+    /// topic, and every event while the buffer is disabled, pass through
+    /// unnumbered.
+    pub fn record(&mut self, mut event: OutboundEvent, now: Instant) -> OutboundEvent {
+        if self.capacity == 0 {
+            return event;
+        }
+        let Some(topic) = event.message.replay_topic() else {
+            return event;
+        };
+
+        event.seq = Some(self.next_seq);
+        self.next_seq += 1;
+
+        let log = self.topics.entry(topic).or_default();
+        log.events.push_back((now, event.clone()));
+        while log.events.len() > self.capacity {
+            log.evict();
+        }
+
+        if now.saturating_duration_since(self.last_sweep) >= SWEEP_INTERVAL {
+            self.sweep(now);
+        }
+        event
+    }
+
+    /// The events on `topic` after `seq`, or a resync when any of them is
+    /// no longer kept or `seq` was not given out by this buffer.
+    pub fn since(&mut self, topic: &str, seq: u64, now: Instant) -> Replay {
+        let latest = self.latest_seq();
+        let resync = Replay::ResyncRequired { seq: latest };
+        if self.capacity == 0 || seq > latest || seq < self.first_seq - 1 {
+            return resync;
+        }
+
+        let cutoff = now.checked_sub(self.max_age);
+        match self.topics.get_mut(topic) {
+            Some(log) => {
+                log.expire(cutoff);
+                if seq < log.evicted_through {
+                    return resync;
+                }
+                Replay::Events(
+                    log.events
+                        .iter()
+                        .filter(|(_, event)| event.seq.is_some_and(|event_seq| event_seq > seq))
+                        .map(|(_, event)| event.clone())
+                        .collect(),
+                )
+            }
+            None if seq < self.forgotten_through => resync,
+            None => Replay::Events(Vec::new()),
+        }
+    }
+
+    /// Clears expired events from every topic, dropping topics left empty
+    /// so that per-item topics do not accumulate.
+    fn sweep(&mut self, now: Instant) {
+        self.last_sweep = now;
+        let cutoff = now.checked_sub(self.max_age);
+        let mut forgotten_through = self.forgotten_through;
+        self.topics.retain(|_, log| {
+            log.expire(cutoff);
+            if log.events.is_empty() {
+                forgotten_through = forgotten_through.max(log.evicted_through);
+            }
+            !log.events.is_empty()
+        });
+        self.forgotten_through = forgotten_through;
+    }
+}
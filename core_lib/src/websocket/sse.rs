@@ -0,0 +1,112 @@
+//! Server-sent events: the events on one topic as an event stream
+
+use std::convert::Infallible;
+
+use axum::{
+    extract::{Extension, Query, State},
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures_util::stream::{self, Stream, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, info};
+
+use crate::error::{AppError, Result};
+use crate::middleware::auth::AuthUser;
+use crate::websocket::messages::{EventEnvelope, OutboundEvent, WebSocketMessage, PROTOCOL_VERSION};
+use crate::websocket::replay::Replay;
+use crate::AppState;
+
+pub const LAST_EVENT_ID: &str = "last-event-id";
+
+#[derive(Debug, serde::Deserialize)]
+pub struct EventStreamQuery {
+    /// `items` unless given.
+    topic: Option<String>,
+}
+
+/// Streams the events on one topic, each with its number as the event id.
+/// A client reconnecting with `Last-Event-ID` first gets the events it
+/// missed, or a `ResyncRequired` event when they are no longer kept. A
+/// client that falls too far behind is disconnected and catches up the
+/// same way.
+pub async fn sse_handler(
+    State(state): State<AppState>,
+    user: Option<Extension<AuthUser>>,
+    Query(query): Query<EventStreamQuery>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = std::result::Result<Event, Infallible>>>> {
+    let manager = state
+        .websocket_manager
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound("Event streams are not available".to_string()))?;
+    if !manager.is_enabled() {
+        return Err(AppError::ServiceUnavailable("Event streams are switched off".to_string()));
+    }
+
+    let topic = query.topic.unwrap_or_else(|| "items".to_string());
+    if !WebSocketMessage::TOPICS.contains(&topic.as_str()) && WebSocketMessage::item_topic(&topic).is_none() {
+        return Err(AppError::BadRequest(format!(
+            "Unknown topic '{}'; expected one of {} or item:<id>",
+            topic,
+            WebSocketMessage::TOPICS.join(", ")
+        )));
+    }
+    if WebSocketMessage::ADMIN_TOPICS.contains(&topic.as_str()) && !user.is_some_and(|Extension(user)| user.is_admin()) {
+        return Err(AppError::Authorization(format!("Only admins may follow {}", topic)));
+    }
+
+    let last_seq = headers
+        .get(LAST_EVENT_ID)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .ok_or_else(|| AppError::BadRequest("Last-Event-ID must be an event number".to_string()))
+        })
+        .transpose()?;
+    info!("GET /api/events - topic: {}, last event: {:?}", topic, last_seq);
+
+    let (backlog, receiver) = manager.follow(&topic, last_seq);
+    let backlog = match backlog {
+        Replay::Events(events) => events,
+        Replay::ResyncRequired { seq } => {
+            let mut resync = OutboundEvent::from(WebSocketMessage::ResyncRequired { topic: topic.clone(), seq });
+            resync.seq = Some(seq);
+            vec![resync]
+        }
+    };
+
+    let live = stream::unfold((receiver, topic), |(mut receiver, topic)| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) if event.message.replay_topic().as_deref() == Some(topic.as_str()) => {
+                    return Some((event, (receiver, topic)));
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => {
+                    debug!("Event stream for {} fell {} events behind; closing it", topic, missed);
+                    return None;
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let events = stream::iter(backlog)
+        .chain(live)
+        .filter_map(|event| std::future::ready(frame(&event).map(Ok)));
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// `event` as an SSE event named after its type, with the envelope clients
+/// of the WebSocket endpoint receive as data.
+fn frame(event: &OutboundEvent) -> Option<Event> {
+    let envelope = EventEnvelope::new(event, PROTOCOL_VERSION).ok()??;
+    let mut frame = Event::default().event(envelope.event_type.clone()).json_data(&envelope).ok()?;
+    if let Some(seq) = event.seq {
+        frame = frame.id(seq.to_string());
+    }
+    Some(frame)
+}
@@ -8,14 +8,27 @@ mod tests {
     use crate::store::Item;
     use crate::metrics::MetricsSnapshot;
     use crate::jobs::models::{JobResponse, JobType, JobStatus, JobPriority};
-    use tokio::sync::mpsc;
+    use crate::websocket::queue::{outbound_channel, OutboundSender, OutboundReceiver};
+    use crate::config::SlowConsumerPolicy;
     use uuid::Uuid;
     use std::env;
     use std::collections::HashMap;
 
+    fn test_channel() -> (OutboundSender, OutboundReceiver) {
+        outbound_channel(16, SlowConsumerPolicy::DropOldest)
+    }
+
+    /// A manager that delivers every event on its own.
+    fn uncoalesced_manager() -> WebSocketManager {
+        WebSocketManager::new(None).with_config(crate::config::WebSocketConfig {
+            coalesce_window_ms: 0,
+            ..Default::default()
+        })
+    }
+
     #[tokio::test]
     async fn test_websocket_connection_creation() {
-        let (tx, _rx) = mpsc::unbounded_channel();
+        let (tx, _rx) = test_channel();
         let connection = WebSocketConnection::new(Some(1), tx);
         
         assert_eq!(connection.user_id, Some(1));
@@ -24,7 +37,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_websocket_connection_send() {
-        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (tx, mut rx) = test_channel();
         let connection = WebSocketConnection::new(Some(1), tx);
         
         let message = WebSocketMessage::Ping;
@@ -51,7 +64,7 @@ mod tests {
     #[tokio::test]
     async fn test_add_and_remove_connection() {
         let manager = WebSocketManager::new(None);
-        let (tx, _rx) = mpsc::unbounded_channel();
+        let (tx, _rx) = test_channel();
         let connection = WebSocketConnection::new(Some(1), tx);
         let connection_id = connection.id;
         
@@ -65,8 +78,8 @@ mod tests {
     #[tokio::test]
     async fn test_broadcast_to_all_connections() {
         let manager = WebSocketManager::new(None);
-        let (tx1, mut rx1) = mpsc::unbounded_channel();
-        let (tx2, mut rx2) = mpsc::unbounded_channel();
+        let (tx1, mut rx1) = test_channel();
+        let (tx2, mut rx2) = test_channel();
         
         let connection1 = WebSocketConnection::new(Some(1), tx1);
         let connection2 = WebSocketConnection::new(Some(2), tx2);
@@ -82,6 +95,7 @@ mod tests {
             updated_at: chrono::Utc::now(),
             tags: vec!["test".to_string()],
             metadata: None,
+            version: 1,
         };
         
         let event = WebSocketEvent::ItemCreated(item.clone());
@@ -97,8 +111,8 @@ mod tests {
     #[tokio::test]
     async fn test_broadcast_to_specific_user() {
         let manager = WebSocketManager::new(None);
-        let (tx1, mut rx1) = mpsc::unbounded_channel();
-        let (tx2, mut rx2) = mpsc::unbounded_channel();
+        let (tx1, mut rx1) = test_channel();
+        let (tx2, mut rx2) = test_channel();
         
         let connection1 = WebSocketConnection::new(Some(1), tx1);
         let connection2 = WebSocketConnection::new(Some(2), tx2);
@@ -114,6 +128,7 @@ mod tests {
             updated_at: chrono::Utc::now(),
             tags: vec!["test".to_string()],
             metadata: None,
+            version: 1,
         };
         
         let event = WebSocketEvent::ItemCreated(item.clone());
@@ -122,7 +137,19 @@ mod tests {
         let msg1 = rx1.recv().await.unwrap();
         assert!(matches!(msg1, WebSocketMessage::ItemCreated(_)));
         
-        assert!(rx2.try_recv().is_err());
+        assert!(rx2.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_receive_broadcasts_only() {
+        let manager = WebSocketManager::new(None);
+        let mut events = manager.subscribe();
+
+        manager.broadcast_to_user(1, WebSocketEvent::ItemDeleted(1)).await;
+        manager.broadcast(WebSocketEvent::ItemDeleted(2)).await;
+
+        assert!(matches!(events.recv().await.unwrap(), WebSocketMessage::ItemDeleted { id: 2 }));
+        assert!(events.try_recv().is_err());
     }
 
     #[test]
@@ -145,6 +172,7 @@ mod tests {
             updated_at: chrono::Utc::now(),
             tags: vec!["test".to_string()],
             metadata: None,
+            version: 1,
         };
         
         let message = WebSocketMessage::ItemCreated(item.clone());
@@ -197,6 +225,7 @@ mod tests {
             updated_at: chrono::Utc::now(),
             tags: vec!["test".to_string()],
             metadata: None,
+            version: 1,
         };
         
         let event = WebSocketEvent::ItemCreated(item.clone());
@@ -262,10 +291,24 @@ mod tests {
             uptime_seconds: 3600,
             requests_per_second: 10.0,
             error_rate: 0.05,
-            last_hour_response_times: vec![],
+            response_time_series: vec![],
+            response_time_percentiles: Default::default(),
             system_metrics: None,
             performance_metrics: None,
             health_status_changes: vec![],
+            security_events: HashMap::new(),
+            websocket: None,
+            slow_requests: 0,
+            timed_out_requests: 0,
+            coalesced_requests: 0,
+            coalesce_timeouts: 0,
+            password_rehashes: 0,
+            in_flight_requests: 0,
+            shed_requests: HashMap::new(),
+            concurrency: Default::default(),
+            rpc_methods: vec![],
+            outbound_destinations: vec![],
+            outbound_refused: HashMap::new(),
         };
         
         let message = WebSocketMessage::MetricsUpdate(metrics.clone());
@@ -290,6 +333,8 @@ mod tests {
             started_at: Some(chrono::Utc::now()),
             completed_at: None,
             result: None,
+            result_size: None,
+            result_file: None,
             error_message: None,
             retry_count: 0,
             max_retries: 3,
@@ -332,4 +377,1035 @@ mod tests {
         let result = WebSocketMessage::from_json(invalid_json);
         assert!(result.is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_client_message_rejections() {
+        use crate::websocket::inbound::parse_client_message;
+
+        assert!(matches!(parse_client_message(r#"{"type":"Ping"}"#, 1024), Ok(WebSocketMessage::Ping)));
+
+        let oversized = format!(r#"{{"type":"Ping","pad":"{}"}}"#, "x".repeat(2048));
+        assert_eq!(parse_client_message(&oversized, 1024).unwrap_err().code, "message_too_large");
+
+        assert_eq!(parse_client_message("{ invalid json }", 1024).unwrap_err().code, "malformed_json");
+        assert_eq!(parse_client_message("[1, 2, 3]", 1024).unwrap_err().code, "invalid_message");
+        assert_eq!(parse_client_message(r#"{"data":{}}"#, 1024).unwrap_err().code, "missing_field");
+        assert_eq!(parse_client_message(r#"{"type":42}"#, 1024).unwrap_err().code, "invalid_type");
+
+        let unknown = parse_client_message(r#"{"type":"Pingg"}"#, 1024).unwrap_err();
+        assert_eq!(unknown.code, "unknown_type");
+        assert!(unknown.message.contains("Pingg"));
+
+        let server_only = parse_client_message(r#"{"type":"ItemDeleted","data":{"id":1}}"#, 1024).unwrap_err();
+        assert_eq!(server_only.code, "unsupported_type");
+    }
+
+    #[test]
+    fn test_parse_client_message_bad_payload() {
+        use crate::websocket::inbound::parse_client_message;
+
+        let err = parse_client_message(r#"{"type":"Pong","data":5}"#, 1024).unwrap_err();
+        assert_eq!(err.code, "invalid_payload");
+        assert!(err.message.contains("Pong"));
+
+        let frame: WebSocketMessage = parse_client_message("nope", 1024).unwrap_err().into();
+        assert!(matches!(frame, WebSocketMessage::ProtocolError { ref code, .. } if code == "malformed_json"));
+    }
+
+    #[test]
+    fn test_inbound_rate_limiter_flooding() {
+        use crate::websocket::inbound::{InboundRateLimiter, RateDecision};
+        use std::time::{Duration, Instant};
+
+        let start = Instant::now();
+        let mut limiter = InboundRateLimiter::new(5, 2);
+
+        for _ in 0..5 {
+            assert_eq!(limiter.check(start), RateDecision::Allow);
+        }
+        assert_eq!(limiter.check(start), RateDecision::Warn);
+        assert_eq!(limiter.check(start), RateDecision::Drop);
+
+        let next_second = start + Duration::from_millis(1100);
+        for _ in 0..5 {
+            assert_eq!(limiter.check(next_second), RateDecision::Allow);
+        }
+        assert_eq!(limiter.check(next_second), RateDecision::Disconnect);
+    }
+
+    #[test]
+    fn test_inbound_rate_limiter_recovers_after_quiet_window() {
+        use crate::websocket::inbound::{InboundRateLimiter, RateDecision};
+        use std::time::{Duration, Instant};
+
+        let start = Instant::now();
+        let mut limiter = InboundRateLimiter::new(2, 2);
+
+        limiter.check(start);
+        limiter.check(start);
+        assert_eq!(limiter.check(start), RateDecision::Warn);
+
+        let quiet = start + Duration::from_millis(3500);
+        assert_eq!(limiter.check(quiet), RateDecision::Allow);
+        assert_eq!(limiter.check(quiet), RateDecision::Allow);
+        assert_eq!(limiter.check(quiet), RateDecision::Warn);
+
+        let mut unlimited = InboundRateLimiter::new(0, 1);
+        for _ in 0..1000 {
+            assert_eq!(unlimited.check(start), RateDecision::Allow);
+        }
+    }
+
+    async fn spawn_websocket_server(config: crate::config::WebSocketConfig) -> std::net::SocketAddr {
+        serve_websocket_manager(WebSocketManager::new(None).with_config(config)).await
+    }
+
+    async fn serve_websocket_manager(manager: WebSocketManager) -> std::net::SocketAddr {
+        let state = crate::AppState::default().with_websocket(manager);
+        let app = axum::Router::new()
+            .route("/ws", axum::routing::get(crate::websocket::websocket_handler))
+            .with_state(state);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        addr
+    }
+
+    async fn next_server_message<S>(stream: &mut S) -> Option<WebSocketMessage>
+    where
+        S: futures_util::Stream<Item = Result<tokio_tungstenite::tungstenite::Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+    {
+        use futures_util::StreamExt;
+
+        loop {
+            let next = tokio::time::timeout(std::time::Duration::from_secs(5), stream.next()).await.ok()??;
+            match next.ok()? {
+                tokio_tungstenite::tungstenite::Message::Text(text) => {
+                    return WebSocketMessage::from_json(&text).ok();
+                }
+                tokio_tungstenite::tungstenite::Message::Close(_) => return None,
+                _ => continue,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_websocket_rejects_malformed_and_oversized_messages() {
+        use futures_util::SinkExt;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let config = crate::config::WebSocketConfig {
+            message_buffer_size: 256,
+            ..Default::default()
+        };
+        let addr = spawn_websocket_server(config).await;
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+
+        assert!(matches!(next_server_message(&mut socket).await, Some(WebSocketMessage::Connected { .. })));
+
+        socket.send(Message::Text("{ not json".to_string())).await.unwrap();
+        assert!(matches!(
+            next_server_message(&mut socket).await,
+            Some(WebSocketMessage::ProtocolError { ref code, .. }) if code == "malformed_json"
+        ));
+
+        socket.send(Message::Text(format!(r#"{{"type":"Ping","pad":"{}"}}"#, "x".repeat(512)))).await.unwrap();
+        assert!(matches!(
+            next_server_message(&mut socket).await,
+            Some(WebSocketMessage::ProtocolError { ref code, .. }) if code == "message_too_large"
+        ));
+
+        socket.send(Message::Text(r#"{"type":"Ping"}"#.to_string())).await.unwrap();
+        assert!(matches!(next_server_message(&mut socket).await, Some(WebSocketMessage::Pong)));
+
+        socket.send(Message::Text("x".repeat(256 * 8))).await.unwrap();
+        assert!(next_server_message(&mut socket).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_websocket_disconnects_flooding_client() {
+        use futures_util::SinkExt;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let config = crate::config::WebSocketConfig {
+            max_messages_per_second: 5,
+            max_rate_limit_violations: 1,
+            ..Default::default()
+        };
+        let addr = spawn_websocket_server(config).await;
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+
+        assert!(matches!(next_server_message(&mut socket).await, Some(WebSocketMessage::Connected { .. })));
+
+        for _ in 0..20 {
+            if socket.send(Message::Text(r#"{"type":"Ping"}"#.to_string())).await.is_err() {
+                break;
+            }
+        }
+
+        let mut pongs = 0;
+        let mut disconnect_frame = false;
+        while let Some(message) = next_server_message(&mut socket).await {
+            match message {
+                WebSocketMessage::Pong => pongs += 1,
+                WebSocketMessage::ProtocolError { code, .. } if code == "rate_limit_exceeded" => disconnect_frame = true,
+                _ => {}
+            }
+        }
+
+        assert!(pongs <= 5);
+        assert!(disconnect_frame);
+    }
+
+    #[tokio::test]
+    async fn test_slow_consumer_does_not_delay_others() {
+        let manager = uncoalesced_manager();
+        let (slow_tx, mut slow_rx) = outbound_channel(4, SlowConsumerPolicy::DropOldest);
+        let (fast_tx, mut fast_rx) = outbound_channel(4, SlowConsumerPolicy::DropOldest);
+
+        manager.add_connection(WebSocketConnection::new(Some(1), slow_tx)).await;
+        manager.add_connection(WebSocketConnection::new(Some(2), fast_tx)).await;
+
+        let fast_reader = tokio::spawn(async move {
+            let mut received = Vec::new();
+            while received.len() < 20 {
+                match fast_rx.recv().await {
+                    Some(WebSocketMessage::ItemDeleted { id }) => received.push(id),
+                    Some(_) => {}
+                    None => break,
+                }
+            }
+            received
+        });
+
+        let started = std::time::Instant::now();
+        for id in 0..20 {
+            manager.broadcast(WebSocketEvent::ItemDeleted(id)).await;
+            tokio::task::yield_now().await;
+        }
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+
+        let received = tokio::time::timeout(std::time::Duration::from_secs(5), fast_reader)
+            .await
+            .expect("fast consumer was delayed by the slow one")
+            .unwrap();
+        assert_eq!(received, (0..20).collect::<Vec<u64>>());
+
+        let stats = manager.stats().await;
+        assert_eq!(stats.connections, 2);
+        assert_eq!(stats.max_queue_depth, 4);
+        assert_eq!(stats.dropped_events, 16);
+        assert_eq!(stats.lagging_connections.len(), 1);
+        assert_eq!(stats.lagging_connections[0].user_id, Some(1));
+
+        let mut kept = Vec::new();
+        while let Some(WebSocketMessage::ItemDeleted { id }) = slow_rx.try_recv() {
+            kept.push(id);
+        }
+        assert_eq!(kept, vec![16, 17, 18, 19]);
+    }
+
+    #[tokio::test]
+    async fn test_slow_consumer_disconnect_policy() {
+        let manager = uncoalesced_manager();
+        let (slow_tx, mut slow_rx) = outbound_channel(2, SlowConsumerPolicy::Disconnect);
+        let (fast_tx, mut fast_rx) = outbound_channel(16, SlowConsumerPolicy::Disconnect);
+
+        manager.add_connection(WebSocketConnection::new(Some(1), slow_tx)).await;
+        manager.add_connection(WebSocketConnection::new(Some(2), fast_tx)).await;
+
+        for id in 0..3 {
+            manager.broadcast(WebSocketEvent::ItemDeleted(id)).await;
+        }
+
+        assert_eq!(manager.connection_count().await, 1);
+        assert!(slow_rx.is_overflowed());
+        assert!(slow_rx.recv().await.is_none());
+
+        for id in 0..3 {
+            assert!(matches!(fast_rx.recv().await, Some(WebSocketMessage::ItemDeleted { id: got }) if got == id));
+        }
+
+        let stats = manager.stats().await;
+        assert_eq!(stats.slow_consumer_disconnects, 1);
+        assert_eq!(stats.dropped_events, 1);
+    }
+
+    #[tokio::test]
+    async fn test_outbound_queue_closes_when_senders_dropped() {
+        let (tx, mut rx) = test_channel();
+        let extra = tx.clone();
+        tx.send(WebSocketMessage::Ping).unwrap();
+        drop(tx);
+        extra.send(WebSocketMessage::Pong).unwrap();
+        drop(extra);
+
+        assert!(matches!(rx.recv().await, Some(WebSocketMessage::Ping)));
+        assert!(matches!(rx.recv().await, Some(WebSocketMessage::Pong)));
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_websocket_connection_limit() {
+        use futures_util::StreamExt;
+
+        let config = crate::config::WebSocketConfig {
+            max_connections: 1,
+            ..Default::default()
+        };
+        let addr = spawn_websocket_server(config).await;
+
+        let (mut first, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+        assert!(matches!(next_server_message(&mut first).await, Some(WebSocketMessage::Connected { .. })));
+
+        let (mut second, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+        let frame = tokio::time::timeout(std::time::Duration::from_secs(5), second.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        match frame {
+            tokio_tungstenite::tungstenite::Message::Close(Some(close)) => {
+                assert_eq!(u16::from(close.code), 1013);
+                assert!(close.reason.contains("Connection limit"));
+            }
+            other => panic!("Expected close frame, got {:?}", other),
+        }
+    }
+
+    fn test_jwt_service() -> JwtService {
+        env::set_var("JWT_SECRET", "test_secret_key_for_websocket_tests_12345678901234567890");
+        JwtService::new().unwrap()
+    }
+
+    fn access_token(jwt_service: &JwtService, user_id: i64) -> String {
+        let user = crate::auth::models::User {
+            id: user_id,
+            username: format!("user{}", user_id),
+            email: format!("user{}@example.com", user_id),
+            password_hash: String::new(),
+            role: "user".to_string(),
+            created_at: chrono::Utc::now(),
+            last_login: None,
+            is_active: true,
+            namespace: "default".to_string(),
+            profile: Default::default(),
+            pending_email: None,
+            email_verified: true,
+        };
+        jwt_service.generate_access_token(&user).unwrap()
+    }
+
+    async fn expect_close_code<S>(stream: &mut S) -> (u16, String)
+    where
+        S: futures_util::Stream<Item = Result<tokio_tungstenite::tungstenite::Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+    {
+        use futures_util::StreamExt;
+
+        loop {
+            let frame = tokio::time::timeout(std::time::Duration::from_secs(5), stream.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            match frame {
+                tokio_tungstenite::tungstenite::Message::Close(Some(close)) => {
+                    return (u16::from(close.code), close.reason.to_string());
+                }
+                tokio_tungstenite::tungstenite::Message::Text(_) => continue,
+                other => panic!("Expected close frame, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_websocket_subprotocol_token() {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let jwt_service = test_jwt_service();
+        let token = access_token(&jwt_service, 42);
+        let addr = serve_websocket_manager(WebSocketManager::new(Some(jwt_service))).await;
+
+        let mut request = format!("ws://{}/ws", addr).into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("sec-websocket-protocol", format!("bearer, {}", token).parse().unwrap());
+        let (mut socket, response) = tokio_tungstenite::connect_async(request).await.unwrap();
+
+        assert_eq!(response.headers()["sec-websocket-protocol"], "bearer");
+        assert!(matches!(
+            next_server_message(&mut socket).await,
+            Some(WebSocketMessage::Authenticated { user_id: 42 })
+        ));
+        assert!(matches!(next_server_message(&mut socket).await, Some(WebSocketMessage::Connected { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_websocket_authenticate_message() {
+        use futures_util::SinkExt;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let jwt_service = test_jwt_service();
+        let token = access_token(&jwt_service, 7);
+        let config = crate::config::WebSocketConfig {
+            require_authentication: true,
+            ..Default::default()
+        };
+        let addr = serve_websocket_manager(WebSocketManager::new(Some(jwt_service)).with_config(config)).await;
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+
+        socket.send(Message::Text(r#"{"type":"Ping"}"#.to_string())).await.unwrap();
+        match next_server_message(&mut socket).await {
+            Some(WebSocketMessage::ProtocolError { code, .. }) => assert_eq!(code, "authentication_required"),
+            other => panic!("Expected authentication_required, got {:?}", other),
+        }
+
+        socket.send(Message::Text(r#"{"type":"Authenticate","data":{"token":"garbage"}}"#.to_string())).await.unwrap();
+        match next_server_message(&mut socket).await {
+            Some(WebSocketMessage::ProtocolError { code, .. }) => assert_eq!(code, "authentication_failed"),
+            other => panic!("Expected authentication_failed, got {:?}", other),
+        }
+
+        let authenticate = WebSocketMessage::Authenticate { token }.to_json().unwrap();
+        socket.send(Message::Text(authenticate.clone())).await.unwrap();
+        assert!(matches!(
+            next_server_message(&mut socket).await,
+            Some(WebSocketMessage::Authenticated { user_id: 7 })
+        ));
+        assert!(matches!(next_server_message(&mut socket).await, Some(WebSocketMessage::Connected { .. })));
+
+        socket.send(Message::Text(authenticate)).await.unwrap();
+        match next_server_message(&mut socket).await {
+            Some(WebSocketMessage::ProtocolError { code, .. }) => assert_eq!(code, "already_authenticated"),
+            other => panic!("Expected already_authenticated, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_websocket_authentication_grace_period_expires() {
+        let config = crate::config::WebSocketConfig {
+            require_authentication: true,
+            auth_grace_period_seconds: 1,
+            ..Default::default()
+        };
+        let addr = serve_websocket_manager(WebSocketManager::new(Some(test_jwt_service())).with_config(config)).await;
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+        let (code, reason) = expect_close_code(&mut socket).await;
+        assert_eq!(code, 1008);
+        assert!(reason.contains("Authentication required"));
+    }
+
+    #[tokio::test]
+    async fn test_websocket_rejects_disallowed_origin() {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let cors = crate::config::CorsConfig {
+            enable_permissive_mode: false,
+            allowed_origins: vec!["https://app.example.com".to_string()],
+            ..Default::default()
+        };
+        let addr = serve_websocket_manager(WebSocketManager::new(None).with_origin_allowlist(&cors)).await;
+
+        let mut request = format!("ws://{}/ws", addr).into_client_request().unwrap();
+        request.headers_mut().insert("origin", "https://evil.example.net".parse().unwrap());
+        let (mut socket, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+        let (code, reason) = expect_close_code(&mut socket).await;
+        assert_eq!(code, 1008);
+        assert!(reason.contains("Origin not allowed"));
+
+        let mut request = format!("ws://{}/ws", addr).into_client_request().unwrap();
+        request.headers_mut().insert("origin", "https://app.example.com".parse().unwrap());
+        let (mut socket, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+        assert!(matches!(next_server_message(&mut socket).await, Some(WebSocketMessage::Connected { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_websocket_query_token_can_be_disabled() {
+        let jwt_service = test_jwt_service();
+        let token = access_token(&jwt_service, 3);
+        let config = crate::config::WebSocketConfig {
+            allow_query_token: false,
+            ..Default::default()
+        };
+        let addr = serve_websocket_manager(WebSocketManager::new(Some(jwt_service)).with_config(config)).await;
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws?token={}", addr, token))
+            .await
+            .unwrap();
+        let (code, _) = expect_close_code(&mut socket).await;
+        assert_eq!(code, 1008);
+    }
+
+    fn envelope_item() -> Item {
+        Item {
+            id: 7,
+            name: "Envelope".to_string(),
+            description: None,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+            tags: vec!["v2".to_string()],
+            metadata: None,
+            version: 3,
+        }
+    }
+
+    #[test]
+    fn test_v1_clients_read_v2_envelopes() {
+        use crate::websocket::{EventEnvelope, OutboundEvent, PROTOCOL_VERSION};
+
+        /// What a dashboard written against protocol 1 deserializes.
+        #[derive(serde::Deserialize)]
+        struct V1Item {
+            id: u64,
+            name: String,
+            tags: Vec<String>,
+        }
+        #[derive(serde::Deserialize)]
+        #[serde(tag = "type", content = "data")]
+        enum V1Message {
+            ItemCreated(V1Item),
+        }
+
+        let event = OutboundEvent::from(WebSocketMessage::ItemCreated(envelope_item()));
+        let current = EventEnvelope::new(&event, PROTOCOL_VERSION).unwrap().unwrap();
+        let json = current.to_json().unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["version"], PROTOCOL_VERSION);
+        assert_eq!(value["type"], "ItemCreated");
+        assert_eq!(value["data"]["version"], 3);
+        assert_eq!(value["event_id"], event.event_id.to_string());
+
+        let V1Message::ItemCreated(item) = serde_json::from_str(&json).unwrap();
+        assert_eq!((item.id, item.name.as_str(), item.tags.len()), (7, "Envelope", 1));
+        assert!(matches!(WebSocketMessage::from_json(&json).unwrap(), WebSocketMessage::ItemCreated(item) if item.version == 3));
+
+        let v1 = EventEnvelope::new(&event, 1).unwrap().unwrap();
+        assert_eq!(v1.event_id, current.event_id);
+        assert_eq!(v1.version, 1);
+        assert!(v1.data.as_ref().unwrap().get("version").is_none());
+        let V1Message::ItemCreated(item) = serde_json::from_str(&v1.to_json().unwrap()).unwrap();
+        assert_eq!(item.id, 7);
+        assert!(matches!(v1.message().unwrap(), WebSocketMessage::ItemCreated(item) if item.version == 1));
+
+        let subscribed = OutboundEvent::from(WebSocketMessage::Subscribed { version: 1 });
+        assert!(EventEnvelope::new(&subscribed, 1).unwrap().is_none());
+        let summary = OutboundEvent::from(WebSocketMessage::ItemsCreated { count: 2, ids: vec![1, 2] });
+        assert!(EventEnvelope::new(&summary, 2).unwrap().is_none());
+        assert!(EventEnvelope::new(&summary, 3).unwrap().is_some());
+        let ping = EventEnvelope::new(&WebSocketMessage::Ping.into(), 1).unwrap().unwrap();
+        assert_eq!(ping.to_json().unwrap().matches("\"data\"").count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_websocket_negotiates_protocol_version() {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message;
+
+        let manager = WebSocketManager::new(None);
+        let addr = serve_websocket_manager(manager.clone()).await;
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+        assert!(matches!(next_server_message(&mut socket).await, Some(WebSocketMessage::Connected { .. })));
+
+        socket.send(Message::Text(r#"{"type":"Subscribe","data":{"version":9}}"#.to_string())).await.unwrap();
+        assert!(matches!(next_server_message(&mut socket).await, Some(WebSocketMessage::Subscribed { version }) if version == crate::websocket::PROTOCOL_VERSION));
+        socket.send(Message::Text(r#"{"type":"Subscribe","data":{"version":0}}"#.to_string())).await.unwrap();
+        assert!(matches!(
+            next_server_message(&mut socket).await,
+            Some(WebSocketMessage::ProtocolError { ref code, .. }) if code == "unsupported_version"
+        ));
+
+        // Version 1 has no Subscribed, so the next frame is the item.
+        socket.send(Message::Text(r#"{"type":"Subscribe","data":{"version":1}}"#.to_string())).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        manager.broadcast(WebSocketEvent::ItemCreated(envelope_item())).await;
+        let frame = tokio::time::timeout(std::time::Duration::from_secs(5), socket.next()).await.unwrap().unwrap().unwrap();
+        let envelope: serde_json::Value = serde_json::from_str(frame.to_text().unwrap()).unwrap();
+        assert_eq!(envelope["version"], 1);
+        assert_eq!(envelope["type"], "ItemCreated");
+        assert!(Uuid::parse_str(envelope["event_id"].as_str().unwrap()).is_ok());
+        assert!(envelope["timestamp"].is_string());
+        assert_eq!(envelope["data"]["id"], 7);
+        assert!(envelope["data"].get("version").is_none());
+    }
+
+    fn drain(rx: &mut OutboundReceiver) -> Vec<WebSocketMessage> {
+        std::iter::from_fn(|| rx.try_recv()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_coalesces_item_bursts() {
+        let manager = WebSocketManager::new(None);
+        let mut receivers = Vec::new();
+        for user_id in 1..=2 {
+            let (tx, rx) = outbound_channel(2048, SlowConsumerPolicy::Disconnect);
+            manager.add_connection(WebSocketConnection::new(Some(user_id), tx)).await;
+            receivers.push(rx);
+        }
+
+        for id in 0..1000 {
+            let mut item = envelope_item();
+            item.id = id;
+            manager.broadcast(WebSocketEvent::ItemCreated(item)).await;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(400)).await;
+
+        assert_eq!(manager.connection_count().await, 2);
+        for rx in &mut receivers {
+            let frames = drain(rx);
+            assert!(frames.len() <= 3, "{} frames for 1000 events", frames.len());
+            assert!(matches!(&frames[0], WebSocketMessage::ItemCreated(item) if item.id == 0));
+            match &frames[1] {
+                WebSocketMessage::ItemsCreated { count, ids } => {
+                    assert_eq!(*count, 999);
+                    assert_eq!(ids.len(), 100);
+                    assert_eq!(ids[0], 1);
+                }
+                other => panic!("expected ItemsCreated, got {}", other.message_type()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_coalescing_preserves_order() {
+        let manager = WebSocketManager::new(None);
+        let (tx, mut rx) = outbound_channel(64, SlowConsumerPolicy::Disconnect);
+        manager.add_connection(WebSocketConnection::new(Some(1), tx)).await;
+
+        for id in 1..=3 {
+            let mut item = envelope_item();
+            item.id = id;
+            manager.broadcast(WebSocketEvent::ItemCreated(item)).await;
+        }
+        let job = JobResponse {
+            id: Uuid::new_v4(),
+            job_type: JobType::BulkImport,
+            status: JobStatus::Completed,
+            created_at: chrono::Utc::now(),
+            started_at: None,
+            completed_at: Some(chrono::Utc::now()),
+            result: None,
+            result_size: None,
+            result_file: None,
+            error_message: None,
+            retry_count: 0,
+            max_retries: 3,
+            priority: JobPriority::Normal,
+        };
+        manager.broadcast(WebSocketEvent::JobCompleted(job)).await;
+        manager.broadcast(WebSocketEvent::ItemDeleted(4)).await;
+        tokio::time::sleep(std::time::Duration::from_millis(400)).await;
+
+        let frames = drain(&mut rx);
+        let types: Vec<&str> = frames.iter().map(|m| m.message_type()).collect();
+        assert_eq!(types, ["ItemCreated", "ItemsCreated", "JobCompleted", "ItemDeleted"]);
+        assert!(matches!(&frames[1], WebSocketMessage::ItemsCreated { count: 2, ids } if ids == &[2, 3]));
+        assert!(matches!(frames[3], WebSocketMessage::ItemDeleted { id: 4 }));
+    }
+
+    #[tokio::test]
+    async fn test_coalescing_exempt_events_and_repeated_metrics() {
+        let manager = WebSocketManager::new(None).with_config(crate::config::WebSocketConfig {
+            coalesce_exempt_events: vec!["ItemDeleted".to_string()],
+            ..Default::default()
+        });
+        let (tx, mut rx) = outbound_channel(64, SlowConsumerPolicy::Disconnect);
+        manager.add_connection(WebSocketConnection::new(Some(1), tx)).await;
+
+        for id in 0..5 {
+            manager.broadcast(WebSocketEvent::ItemDeleted(id)).await;
+        }
+        let ids: Vec<u64> = drain(&mut rx)
+            .into_iter()
+            .filter_map(|m| match m {
+                WebSocketMessage::ItemDeleted { id } => Some(id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ids, (0..5).collect::<Vec<u64>>());
+
+        let metrics = crate::metrics::MetricsCollector::new();
+        let mut snapshot = metrics.get_snapshot(0);
+        manager.broadcast(WebSocketEvent::MetricsUpdate(snapshot.clone())).await;
+        snapshot.uptime_seconds += 5;
+        manager.broadcast(WebSocketEvent::MetricsUpdate(snapshot.clone())).await;
+        snapshot.total_requests += 1;
+        manager.broadcast(WebSocketEvent::MetricsUpdate(snapshot)).await;
+
+        let totals: Vec<u64> = drain(&mut rx)
+            .into_iter()
+            .filter_map(|m| match m {
+                WebSocketMessage::MetricsUpdate(snapshot) => Some(snapshot.total_requests),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(totals, [0, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_connections_receive_subscribed_topics_only() {
+        let manager = uncoalesced_manager();
+        let (all_tx, mut all_rx) = test_channel();
+        let (jobs_tx, mut jobs_rx) = test_channel();
+        let (admin_tx, mut admin_rx) = test_channel();
+        let all = WebSocketConnection::new(Some(1), all_tx);
+        let jobs = WebSocketConnection::new(Some(2), jobs_tx);
+        let admin = WebSocketConnection::new(Some(3), admin_tx).with_admin(true);
+        let (jobs_id, admin_id) = (jobs.id, admin.id);
+        for connection in [all, jobs, admin] {
+            manager.add_connection(connection).await;
+        }
+
+        manager.set_connection_topics(&jobs_id, vec!["jobs".to_string()]).await.unwrap();
+        manager
+            .set_connection_topics(&admin_id, vec!["presence".to_string(), "items".to_string()])
+            .await
+            .unwrap();
+        assert!(manager.set_connection_topics(&jobs_id, vec!["presence".to_string()]).await.is_err());
+        assert!(manager.set_connection_topics(&jobs_id, vec!["weather".to_string()]).await.is_err());
+
+        manager.broadcast(WebSocketEvent::ItemDeleted(1)).await;
+        assert!(matches!(all_rx.try_recv(), Some(WebSocketMessage::ItemDeleted { id: 1 })));
+        assert!(jobs_rx.try_recv().is_none());
+        assert!(matches!(admin_rx.try_recv(), Some(WebSocketMessage::ItemDeleted { id: 1 })));
+
+        let stats = manager.stats().await;
+        assert_eq!(stats.topics["items"], 2);
+        assert_eq!(stats.topics["jobs"], 2);
+        assert_eq!(stats.topics["metrics"], 1);
+        assert_eq!(stats.topics["presence"], 1);
+
+        let listed = manager.connections().await;
+        assert_eq!(listed.len(), 3);
+        assert_eq!(listed.iter().find(|c| c.connection_id == jobs_id).unwrap().topics, ["jobs"]);
+
+        let disconnected = manager.disconnect(&jobs_id, "Maintenance").await.unwrap();
+        assert_eq!(disconnected.user_id, Some(2));
+        assert!(jobs_rx.recv().await.is_none());
+        assert_eq!(jobs_rx.close_reason().as_deref(), Some("Maintenance"));
+        assert!(manager.disconnect(&jobs_id, "Maintenance").await.is_none());
+        assert_eq!(manager.connection_count().await, 2);
+    }
+
+    fn numbered(rx: &mut OutboundReceiver) -> Vec<(Option<u64>, WebSocketMessage)> {
+        std::iter::from_fn(|| rx.try_recv_event()).map(|event| (event.seq, event.message)).collect()
+    }
+
+    /// A connection on `manager` that has seen one item event, and the
+    /// number of that event.
+    async fn seen_item_event(manager: &WebSocketManager) -> u64 {
+        let (tx, mut rx) = test_channel();
+        let connection = WebSocketConnection::new(Some(1), tx);
+        let connection_id = connection.id;
+        manager.add_connection(connection).await;
+        manager.broadcast(WebSocketEvent::ItemDeleted(1)).await;
+        let seq = rx.try_recv_event().unwrap().seq.unwrap();
+        manager.remove_connection(&connection_id).await;
+        seq
+    }
+
+    #[test]
+    fn test_replay_buffer_covers_recent_events_only() {
+        use crate::websocket::messages::OutboundEvent;
+        use crate::websocket::replay::{Replay, ReplayBuffer};
+        use std::time::Duration;
+
+        let ids = |replay: Replay| match replay {
+            Replay::Events(events) => events
+                .into_iter()
+                .map(|event| match event.message {
+                    WebSocketMessage::ItemDeleted { id } => id,
+                    other => panic!("unexpected {}", other.message_type()),
+                })
+                .collect::<Vec<_>>(),
+            Replay::ResyncRequired { seq } => panic!("resync from {}", seq),
+        };
+
+        let start = tokio::time::Instant::now();
+        let mut buffer = ReplayBuffer::new(3, Duration::from_secs(60));
+        let seqs: Vec<u64> = (1..=5)
+            .map(|id| buffer.record(OutboundEvent::from(WebSocketMessage::ItemDeleted { id }), start).seq.unwrap())
+            .collect();
+        assert!(seqs.windows(2).all(|pair| pair[1] == pair[0] + 1));
+        assert!(buffer.record(WebSocketMessage::Pong.into(), start).seq.is_none());
+        assert_eq!(buffer.latest_seq(), seqs[4]);
+
+        assert_eq!(ids(buffer.since("items", seqs[2], start)), [4, 5]);
+        assert_eq!(ids(buffer.since("items", seqs[1], start)), [3, 4, 5]);
+        assert!(ids(buffer.since("items", seqs[4], start)).is_empty());
+        assert!(ids(buffer.since("jobs", seqs[0], start)).is_empty());
+
+        // Event 2 was evicted, so a client that last saw event 1 resyncs.
+        assert!(matches!(buffer.since("items", seqs[0], start), Replay::ResyncRequired { seq } if seq == seqs[4]));
+        // Numbers this buffer never gave out, as after a restart.
+        assert!(matches!(buffer.since("items", seqs[4] + 1, start), Replay::ResyncRequired { .. }));
+        assert!(matches!(buffer.since("items", 1, start), Replay::ResyncRequired { .. }));
+
+        let later = start + Duration::from_secs(61);
+        assert!(matches!(buffer.since("items", seqs[2], later), Replay::ResyncRequired { .. }));
+        assert!(ids(buffer.since("items", seqs[4], later)).is_empty());
+
+        let mut disabled = ReplayBuffer::new(0, Duration::from_secs(60));
+        let event = disabled.record(OutboundEvent::from(WebSocketMessage::ItemDeleted { id: 1 }), start);
+        assert!(event.seq.is_none());
+        assert!(matches!(disabled.since("items", disabled.latest_seq(), start), Replay::ResyncRequired { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_resume_replays_missed_events_before_live_ones() {
+        let manager = uncoalesced_manager();
+        let seen = seen_item_event(&manager).await;
+        for id in 2..=4 {
+            manager.broadcast(WebSocketEvent::ItemDeleted(id)).await;
+        }
+
+        let (tx, mut rx) = test_channel();
+        let connection = WebSocketConnection::new(Some(1), tx);
+        let connection_id = connection.id;
+        manager.add_connection(connection).await;
+        // Raised after the client reconnected but before it asked to resume.
+        manager.broadcast(WebSocketEvent::ItemDeleted(5)).await;
+        manager.resume(&connection_id, "items", seen).await.unwrap();
+        manager.broadcast(WebSocketEvent::ItemDeleted(6)).await;
+
+        let frames = numbered(&mut rx);
+        let seqs: Vec<u64> = frames.iter().filter_map(|(seq, _)| *seq).collect();
+        assert_eq!(seqs, (seen + 1..=seen + 5).collect::<Vec<_>>());
+        let ids: Vec<String> = frames
+            .iter()
+            .map(|(_, message)| match message {
+                WebSocketMessage::ItemDeleted { id } => id.to_string(),
+                WebSocketMessage::Resumed { topic, replayed } => format!("{}:{}", topic, replayed),
+                other => other.message_type().to_string(),
+            })
+            .collect();
+        assert_eq!(ids, ["2", "3", "4", "5", "items:4", "6"]);
+
+        assert!(manager.resume(&connection_id, "weather", seen).await.is_err());
+        manager.set_connection_topics(&connection_id, vec!["jobs".to_string()]).await.unwrap();
+        assert!(manager.resume(&connection_id, "items", seen).await.is_err());
+        assert!(manager.resume(&connection_id, "presence", seen).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resume_past_buffer_requires_resync() {
+        let manager = WebSocketManager::new(None).with_config(crate::config::WebSocketConfig {
+            coalesce_window_ms: 0,
+            replay_buffer_size: 2,
+            ..Default::default()
+        });
+        let seen = seen_item_event(&manager).await;
+        for id in 2..=4 {
+            manager.broadcast(WebSocketEvent::ItemDeleted(id)).await;
+        }
+
+        let (tx, mut rx) = test_channel();
+        let connection = WebSocketConnection::new(Some(1), tx);
+        let connection_id = connection.id;
+        manager.add_connection(connection).await;
+        manager.resume(&connection_id, "items", seen).await.unwrap();
+        assert!(matches!(
+            rx.try_recv(),
+            Some(WebSocketMessage::ResyncRequired { topic, seq }) if topic == "items" && seq == seen + 3
+        ));
+
+        // More missed events than the connection's queue holds.
+        let (tx, mut rx) = outbound_channel(1, SlowConsumerPolicy::DropOldest);
+        let connection = WebSocketConnection::new(Some(1), tx);
+        let connection_id = connection.id;
+        manager.add_connection(connection).await;
+        manager.resume(&connection_id, "items", seen + 1).await.unwrap();
+        assert!(matches!(rx.try_recv(), Some(WebSocketMessage::ResyncRequired { seq, .. }) if seq == seen + 3));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_resume_keeps_order_with_concurrent_live_events() {
+        let manager = uncoalesced_manager();
+        let seen = seen_item_event(&manager).await;
+        for id in 2..=50 {
+            manager.broadcast(WebSocketEvent::ItemDeleted(id)).await;
+        }
+
+        let (tx, mut rx) = outbound_channel(4096, SlowConsumerPolicy::Disconnect);
+        let connection = WebSocketConnection::new(Some(1), tx);
+        let connection_id = connection.id;
+        manager.add_connection(connection).await;
+
+        let live = {
+            let manager = manager.clone();
+            tokio::spawn(async move {
+                for id in 51..=250 {
+                    manager.broadcast(WebSocketEvent::ItemDeleted(id)).await;
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        manager.resume(&connection_id, "items", seen).await.unwrap();
+        live.await.unwrap();
+
+        let frames = numbered(&mut rx);
+        let seqs: Vec<u64> = frames.iter().filter_map(|(seq, _)| *seq).collect();
+        assert_eq!(seqs, (seen + 1..=seen + 249).collect::<Vec<_>>());
+        let ids: Vec<u64> = frames
+            .iter()
+            .filter_map(|(_, message)| match message {
+                WebSocketMessage::ItemDeleted { id } => Some(*id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ids, (2..=250).collect::<Vec<_>>());
+        assert_eq!(frames.iter().filter(|(_, message)| matches!(message, WebSocketMessage::Resumed { .. })).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_websocket_client_resumes_after_reconnecting() {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message;
+
+        let manager = uncoalesced_manager();
+        let addr = serve_websocket_manager(manager.clone()).await;
+        let url = format!("ws://{}/ws", addr);
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        assert!(matches!(next_server_message(&mut socket).await, Some(WebSocketMessage::Connected { .. })));
+        manager.broadcast(WebSocketEvent::ItemDeleted(1)).await;
+        let frame = tokio::time::timeout(std::time::Duration::from_secs(5), socket.next()).await.unwrap().unwrap().unwrap();
+        let envelope: serde_json::Value = serde_json::from_str(frame.to_text().unwrap()).unwrap();
+        assert_eq!(envelope["topic"], "items");
+        let seen = envelope["seq"].as_u64().unwrap();
+        socket.close(None).await.unwrap();
+
+        manager.broadcast(WebSocketEvent::ItemDeleted(2)).await;
+        manager.broadcast(WebSocketEvent::ItemDeleted(3)).await;
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        assert!(matches!(next_server_message(&mut socket).await, Some(WebSocketMessage::Connected { .. })));
+        let resume = serde_json::json!({"type": "ResumeFrom", "data": {"topic": "items", "seq": seen}});
+        socket.send(Message::Text(resume.to_string())).await.unwrap();
+        assert!(matches!(next_server_message(&mut socket).await, Some(WebSocketMessage::ItemDeleted { id: 2 })));
+        assert!(matches!(next_server_message(&mut socket).await, Some(WebSocketMessage::ItemDeleted { id: 3 })));
+        assert!(matches!(next_server_message(&mut socket).await, Some(WebSocketMessage::Resumed { replayed: 2, .. })));
+
+        let resume = serde_json::json!({"type": "ResumeFrom", "data": {"topic": "weather", "seq": seen}});
+        socket.send(Message::Text(resume.to_string())).await.unwrap();
+        assert!(matches!(
+            next_server_message(&mut socket).await,
+            Some(WebSocketMessage::ProtocolError { ref code, .. }) if code == "invalid_topic"
+        ));
+    }
+
+    /// The next `count` events of an SSE body as `(id, event, data)`.
+    async fn next_sse_events(
+        body: &mut (impl futures_util::Stream<Item = Result<axum::body::Bytes, axum::Error>> + Unpin),
+        buffered: &mut String,
+        count: usize,
+    ) -> Vec<(Option<String>, String, serde_json::Value)> {
+        use futures_util::StreamExt;
+
+        let mut events = Vec::new();
+        while events.len() < count {
+            let Some(end) = buffered.find("\n\n") else {
+                let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), body.next())
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .unwrap();
+                buffered.push_str(std::str::from_utf8(&chunk).unwrap());
+                continue;
+            };
+            let block: String = buffered.drain(..end + 2).collect();
+            let field = |name: &str| {
+                block.lines().find_map(|line| line.strip_prefix(name).map(|value| value.trim_start().to_string()))
+            };
+            // Keep-alive comments have no event.
+            if let (Some(event), Some(data)) = (field("event:"), field("data:")) {
+                events.push((field("id:"), event, serde_json::from_str(&data).unwrap()));
+            }
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn test_event_stream_resumes_from_last_event_id() {
+        use axum::body::Body;
+        use axum::http::{Request, StatusCode};
+        use tower::ServiceExt;
+
+        let manager = WebSocketManager::new(None).with_config(crate::config::WebSocketConfig {
+            coalesce_window_ms: 0,
+            replay_buffer_size: 3,
+            ..Default::default()
+        });
+        let state = crate::AppState::default().with_websocket(manager.clone());
+        let app = axum::Router::new()
+            .route("/api/events", axum::routing::get(crate::websocket::sse_handler))
+            .with_state(state);
+        let get = |uri: &str, last_event_id: Option<u64>| {
+            let mut request = Request::builder().uri(uri).header("accept", "text/event-stream");
+            if let Some(id) = last_event_id {
+                request = request.header("last-event-id", id.to_string());
+            }
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        let seen = seen_item_event(&manager).await;
+        manager.broadcast(WebSocketEvent::ItemDeleted(2)).await;
+        manager.broadcast(WebSocketEvent::ItemDeleted(3)).await;
+
+        let response = get("/api/events", Some(seen)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body().into_data_stream();
+        let mut buffered = String::new();
+        let missed = next_sse_events(&mut body, &mut buffered, 2).await;
+        assert_eq!(missed[0].0, Some((seen + 1).to_string()));
+        assert_eq!((missed[0].1.as_str(), &missed[0].2["data"]["id"]), ("ItemDeleted", &serde_json::json!(2)));
+        assert_eq!(missed[1].0, Some((seen + 2).to_string()));
+        assert_eq!(missed[1].2["seq"], seen + 2);
+
+        manager.broadcast(WebSocketEvent::JobStarted(JobResponse {
+            id: Uuid::new_v4(),
+            job_type: JobType::BulkImport,
+            status: JobStatus::Running,
+            created_at: chrono::Utc::now(),
+            started_at: None,
+            completed_at: None,
+            result: None,
+            result_size: None,
+            result_file: None,
+            error_message: None,
+            retry_count: 0,
+            max_retries: 3,
+            priority: JobPriority::Normal,
+        }))
+        .await;
+        manager.broadcast(WebSocketEvent::ItemDeleted(4)).await;
+        let live = next_sse_events(&mut body, &mut buffered, 1).await;
+        assert_eq!(live[0].0, Some((seen + 4).to_string()));
+        assert_eq!(live[0].2["data"]["id"], 4);
+
+        // With room for three, the items topic now keeps only events 4 to 6.
+        manager.broadcast(WebSocketEvent::ItemDeleted(5)).await;
+        manager.broadcast(WebSocketEvent::ItemDeleted(6)).await;
+        let response = get("/api/events?topic=items", Some(seen)).await.unwrap();
+        let mut body = response.into_body().into_data_stream();
+        let mut buffered = String::new();
+        let resync = next_sse_events(&mut body, &mut buffered, 1).await;
+        assert_eq!(resync[0].1, "ResyncRequired");
+        assert_eq!(resync[0].0, Some((seen + 6).to_string()));
+        assert_eq!(resync[0].2["data"]["seq"], seen + 6);
+
+        assert_eq!(get("/api/events?topic=weather", None).await.unwrap().status(), StatusCode::BAD_REQUEST);
+        assert_eq!(get("/api/events?topic=presence", None).await.unwrap().status(), StatusCode::FORBIDDEN);
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/api/events").header("last-event-id", "abc").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,75 @@
+//! Heap allocations on the hot request path, counted with dhat. Kept in its
+//! own test binary because dhat replaces the global allocator.
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{Request, StatusCode};
+use core_lib::{create_app_with_config, AppConfig, AppState, RateLimiter};
+use std::net::SocketAddr;
+use tower::ServiceExt;
+
+#[global_allocator]
+static ALLOC: dhat::Alloc = dhat::Alloc;
+
+const WARM_UP_REQUESTS: u64 = 20;
+const MEASURED_REQUESTS: u64 = 200;
+
+/// A cached `GET /api/items/:id` took 692 allocations (about 325 KB) before
+/// the state, access log, metric labels and validation errors stopped
+/// copying per request, and about 433 after. The budget holds the 30% cut.
+const MAX_ALLOCATIONS_PER_REQUEST: u64 = 692 * 7 / 10;
+
+#[test]
+fn test_get_item_allocations() {
+    // A failed budget assertion saves a heap profile; keep it out of the tree.
+    let _profiler = dhat::Profiler::builder()
+        .testing()
+        .file_name(std::env::temp_dir().join("dhat-heap.json"))
+        .build();
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+    let mut config = AppConfig::default();
+    config.rate_limit.requests_per_minute = usize::MAX / 2;
+    let state = AppState::default().with_rate_limiter(RateLimiter::new(config.rate_limit.clone()));
+    let app = create_app_with_config(state, config);
+
+    let get_item = || {
+        let mut request = Request::get("/api/items/1")
+            .header("user-agent", "allocation-tests")
+            .body(Body::empty())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        }
+    };
+
+    runtime.block_on(async {
+        for _ in 0..WARM_UP_REQUESTS {
+            get_item().await;
+        }
+    });
+
+    let before = dhat::HeapStats::get();
+    runtime.block_on(async {
+        for _ in 0..MEASURED_REQUESTS {
+            get_item().await;
+        }
+    });
+    let after = dhat::HeapStats::get();
+
+    let per_request = (after.total_blocks - before.total_blocks) / MEASURED_REQUESTS;
+    let bytes_per_request = (after.total_bytes - before.total_bytes) / MEASURED_REQUESTS;
+    println!("GET /api/items/:id: {} allocations, {} bytes per request", per_request, bytes_per_request);
+    dhat::assert!(
+        per_request <= MAX_ALLOCATIONS_PER_REQUEST,
+        "{} allocations per request, budget {}",
+        per_request,
+        MAX_ALLOCATIONS_PER_REQUEST
+    );
+}
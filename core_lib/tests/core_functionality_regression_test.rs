@@ -6,6 +6,7 @@ use core_lib::{
     cache::CacheManager,
     websocket::WebSocketManager,
     config::CacheConfig,
+    SystemMonitor,
 };
 use tempfile::NamedTempFile;
 use std::env;
@@ -36,6 +37,7 @@ async fn setup_full_system() -> core_lib::AppState {
         default_ttl_seconds: 300,
         cleanup_interval_seconds: 60,
         enable_stats: true,
+        ..CacheConfig::default()
     };
     let cache_manager = CacheManager::new(cache_config);
     
@@ -48,7 +50,7 @@ async fn setup_full_system() -> core_lib::AppState {
         .with_cache_manager(cache_manager)
         .with_websocket(websocket_manager)
         .with_health_checker()
-        .with_system_monitor();
+        .with_system_monitor(SystemMonitor::new());
     
     state.migrate_to_database_if_needed().await.unwrap();
     
@@ -79,6 +81,7 @@ async fn test_original_item_crud_still_works() {
         Some("Updated description".to_string()),
         vec!["updated".to_string(), "regression".to_string()],
         Some(serde_json::json!({"updated": true})),
+        None,
     ).await.unwrap();
     
     assert_eq!(updated_item.name, "Updated Regression Test Item");
@@ -125,7 +128,7 @@ async fn test_metrics_collection_with_all_features() {
         ).await.unwrap();
     }
     
-    let metrics = state.metrics.get_snapshot(10);
+    let metrics = state.metrics.snapshot().unwrap();
     
     assert!(metrics.total_requests >= 0);
     assert!(metrics.successful_requests >= 0);
@@ -176,6 +179,7 @@ async fn test_websocket_manager_basic_functionality() {
             updated_at: chrono::Utc::now(),
             tags: vec!["test".to_string()],
             metadata: None,
+            version: 1,
         };
         
         let event = core_lib::websocket::WebSocketEvent::ItemCreated(item);
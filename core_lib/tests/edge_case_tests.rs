@@ -6,6 +6,7 @@ use core_lib::{
     cache::CacheManager,
     websocket::WebSocketManager,
     config::CacheConfig,
+    SystemMonitor,
 };
 use tempfile::NamedTempFile;
 use std::env;
@@ -36,6 +37,7 @@ async fn setup_test_system() -> core_lib::AppState {
         default_ttl_seconds: 1,
         cleanup_interval_seconds: 1,
         enable_stats: true,
+        ..CacheConfig::default()
     };
     let cache_manager = CacheManager::new(cache_config);
     
@@ -48,7 +50,7 @@ async fn setup_test_system() -> core_lib::AppState {
         .with_cache_manager(cache_manager)
         .with_websocket(websocket_manager)
         .with_health_checker()
-        .with_system_monitor();
+        .with_system_monitor(SystemMonitor::new());
     
     state.migrate_to_database_if_needed().await.unwrap();
     state
@@ -190,6 +192,7 @@ async fn test_websocket_edge_cases() {
         updated_at: chrono::Utc::now(),
         tags: vec!["test".to_string()],
         metadata: None,
+        version: 1,
     };
     
     let event = core_lib::websocket::WebSocketEvent::ItemCreated(item);
@@ -203,6 +206,7 @@ async fn test_websocket_edge_cases() {
         updated_at: chrono::Utc::now(),
         tags: vec!["test".to_string()],
         metadata: None,
+        version: 1,
     };
     
     let event2 = core_lib::websocket::WebSocketEvent::ItemCreated(item2);
@@ -403,6 +407,7 @@ async fn test_data_consistency() {
                 Some(format!("Updated description {}", i)),
                 vec![format!("update{}", i), "consistency".to_string()],
                 Some(serde_json::json!({"update_index": i})),
+                None,
             ).await
         });
         update_handles.push(handle);
@@ -0,0 +1,2642 @@
+//! Golden tests through the full router and middleware stack.
+
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use core_lib::auth::models::UserRole;
+use core_lib::error::AppError;
+use core_lib::events::{DomainEvent, EventSubscriber};
+use core_lib::files::ContentIndexStatus;
+use core_lib::jobs::JobStatus;
+use core_lib::supervisor::RestartPolicy;
+use core_lib::test_support::{assert_golden, TestServer, TestWebSocket, TEST_PASSWORD};
+use core_lib::websocket::WebSocketMessage;
+use futures_util::StreamExt;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+
+#[tokio::test]
+async fn test_item_crud_round_trip() {
+    let server = TestServer::new().await;
+
+    let created = server
+        .post("/api/items")
+        .json(&json!({"name": "Golden Item", "description": "Round trip", "tags": ["golden"]}))
+        .send()
+        .await;
+    assert_eq!(created.status, StatusCode::CREATED);
+    assert_golden("item_created", &created.json());
+    let id = created.json()["data"]["id"].as_u64().unwrap();
+    let uri = format!("/api/items/{}", id);
+
+    let fetched = server.get(&uri).send().await;
+    assert_eq!(fetched.status, StatusCode::OK);
+    assert_eq!(fetched.json()["data"], created.json()["data"]);
+
+    let updated = server
+        .put(&uri)
+        .header("if-match", "\"1\"")
+        .json(&json!({"name": "Golden Item v2", "description": "Replaced", "tags": ["golden", "v2"]}))
+        .send()
+        .await;
+    assert_eq!(updated.status, StatusCode::OK, "{}", updated.text());
+    assert_golden("item_updated", &updated.json());
+
+    let patched = server
+        .patch(&uri)
+        .json(&json!({"description": "Patched"}))
+        .send()
+        .await;
+    assert_eq!(patched.status, StatusCode::OK, "{}", patched.text());
+    assert_eq!(patched.json()["data"]["description"], "Patched");
+    assert_eq!(patched.json()["data"]["name"], "Golden Item v2");
+
+    let deleted = server.delete(&uri).send().await;
+    assert_eq!(deleted.status, StatusCode::NO_CONTENT);
+
+    let missing = server.get(&uri).send().await;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+    assert_golden("item_not_found", &missing.json());
+
+    assert_eq!(server.metrics().endpoint_requests("/api/items"), 1);
+    assert_eq!(server.metrics().endpoint_requests("/api/items/:id"), 5);
+    let statuses: Vec<u16> = server
+        .metrics()
+        .recent_responses("/api/items/:id")
+        .iter()
+        .map(|response| response.status)
+        .collect();
+    assert_eq!(statuses, vec![200, 200, 200, 204, 404]);
+}
+
+#[tokio::test]
+async fn test_item_errors_match_across_backends() {
+    use axum::http::Method;
+
+    // (method, target, body, If-Match, expected status). `{id}` is an
+    // existing item, `{missing}` an id no item has.
+    let cases = [
+        (Method::GET, "{missing}", None, None, StatusCode::NOT_FOUND),
+        (Method::PUT, "{missing}", Some(json!({"name": "Renamed"})), None, StatusCode::NOT_FOUND),
+        (Method::PATCH, "{missing}", Some(json!({"name": "Renamed"})), None, StatusCode::NOT_FOUND),
+        (Method::PATCH, "{missing}", Some(json!({"name": "Renamed"})), Some("\"1\""), StatusCode::NOT_FOUND),
+        (Method::DELETE, "{missing}", None, None, StatusCode::NOT_FOUND),
+        (Method::POST, "", Some(json!({"name": ""})), None, StatusCode::BAD_REQUEST),
+        (Method::PUT, "{id}", Some(json!({"name": ""})), None, StatusCode::BAD_REQUEST),
+        (Method::PATCH, "{id}", Some(json!({"name": ""})), None, StatusCode::BAD_REQUEST),
+        (Method::PATCH, "{id}", Some(json!({"name": 5})), None, StatusCode::BAD_REQUEST),
+        (Method::PATCH, "{id}", Some(json!({"description": ["not", "text"]})), None, StatusCode::BAD_REQUEST),
+        (Method::PATCH, "{id}", Some(json!({"tags": "one"})), None, StatusCode::BAD_REQUEST),
+        (Method::PUT, "{id}", Some(json!({"name": "Renamed", "version": 99})), None, StatusCode::CONFLICT),
+        (Method::PATCH, "{id}", Some(json!({"name": "Renamed"})), Some("\"99\""), StatusCode::CONFLICT),
+    ];
+
+    for server in [TestServer::new().await, TestServer::with_memory_items().await] {
+        let backend = if server.state().item_service.is_using_database() { "database" } else { "memory" };
+        for prefix in ["/api/items", "/api/v1/items", "/api/v2/items"] {
+            let created = server.post("/api/items").json(&json!({"name": "Target"})).send().await;
+            assert_eq!(created.status, StatusCode::CREATED, "{}", created.text());
+            let id = created.json()["data"]["id"].as_u64().unwrap();
+
+            for (method, target, body, if_match, expected) in &cases {
+                let target = target.replace("{id}", &format!("/{}", id)).replace("{missing}", "/987654");
+                let uri = format!("{}{}", prefix, target);
+                let mut request = server.request(method.clone(), &uri);
+                if let Some(body) = body {
+                    request = request.json(body);
+                }
+                if let Some(if_match) = if_match {
+                    request = request.header("if-match", if_match);
+                }
+                let response = request.send().await;
+                assert_eq!(
+                    response.status, *expected,
+                    "{} {} on the {} backend: {}", method, uri, backend, response.text()
+                );
+            }
+
+            let unchanged = server.get(&format!("/api/items/{}", id)).send().await;
+            assert_eq!(unchanged.json()["data"]["name"], "Target", "{} backend", backend);
+            assert_eq!(unchanged.json()["data"]["version"], 1, "{} backend", backend);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_cache_serves_reads_until_a_write() {
+    let server = TestServer::new().await;
+
+    let first = server.get("/api/items/1").send().await;
+    assert_eq!(first.cache_status(), Some("MISS"));
+    assert!(server.state().is_response_cached("/api/items/1"));
+    let second = server.get("/api/items/1").send().await;
+    assert_eq!(second.cache_status(), Some("HIT"));
+    assert_eq!(second.body, first.body);
+
+    let list = server.get("/api/items").send().await;
+    assert_eq!(list.cache_status(), Some("MISS"));
+    assert!(server.state().is_response_cached("/api/items"));
+
+    // Authenticated reads bypass the cache entirely.
+    let token = server.login_as("reader", UserRole::User).await;
+    let authenticated = server.get("/api/items/1").bearer(&token).send().await;
+    assert_eq!(authenticated.status, StatusCode::OK);
+    assert_eq!(authenticated.cache_status(), Some("BYPASS"));
+
+    let deleted = server.delete("/api/items/1").send().await;
+    assert_eq!(deleted.status, StatusCode::NO_CONTENT);
+    assert!(!server.state().is_response_cached("/api/items/1"));
+    assert!(!server.state().is_response_cached("/api/items"));
+
+    let after = server.get("/api/items/1").send().await;
+    assert_eq!(after.status, StatusCode::NOT_FOUND);
+    assert_eq!(after.cache_status(), Some("MISS"));
+    let list = server.get("/api/items").send().await;
+    assert_eq!(list.cache_status(), Some("MISS"));
+    assert_eq!(list.json()["data"]["count"], 1);
+}
+
+#[tokio::test]
+async fn test_responses_say_where_they_came_from() {
+    let server = TestServer::with_config(|config| config.server.instance_id = "api-7".to_string()).await;
+
+    let list = server.get("/api/items").send().await;
+    assert_eq!(list.header("x-data-source"), Some("database"));
+    assert_eq!(list.json()["data"]["source"], "database");
+    assert_eq!(list.header("x-served-by"), Some("api-7"));
+    assert!(list.header("x-response-time-ms").unwrap().parse::<u64>().is_ok());
+    assert_eq!(server.get("/api/items").send().await.cache_status(), Some("HIT"));
+    assert_eq!(server.get("/health").send().await.cache_status(), Some("BYPASS"));
+
+    // Statistics have their own cache, which the header reports like the body.
+    for expected in ["MISS", "HIT"] {
+        let stats = server.get("/api/stats").send().await;
+        assert_eq!(stats.cache_status(), Some(expected));
+        assert_eq!(stats.json()["data"]["cache"], expected.to_lowercase());
+    }
+
+    let server = TestServer::with_config(|config| config.items.source_in_body = false).await;
+    let list = server.get("/api/items").send().await;
+    assert_eq!(list.header("x-data-source"), Some("database"));
+    assert!(list.json()["data"].get("source").is_none());
+}
+
+#[tokio::test]
+async fn test_rate_limit_rejects_with_429() {
+    let server = TestServer::with_config(|config| {
+        config.rate_limit.requests_per_minute = 3;
+        config.rate_limit.burst_size = 0;
+    })
+    .await;
+    let peer = SocketAddr::from(([10, 0, 0, 7], 50000));
+
+    let mut statuses = Vec::new();
+    for _ in 0..4 {
+        let response = server.get("/api/stats").from_peer(peer).send().await;
+        statuses.push(response.status);
+        if response.status == StatusCode::TOO_MANY_REQUESTS {
+            assert_golden("rate_limited", &response.json());
+            assert!(response.header("retry-after").is_some());
+        } else {
+            assert_eq!(response.header("x-ratelimit-limit"), Some("3"));
+        }
+    }
+    assert_eq!(
+        statuses,
+        vec![StatusCode::OK, StatusCode::OK, StatusCode::OK, StatusCode::TOO_MANY_REQUESTS]
+    );
+
+    let other = server.get("/api/stats").from_peer(SocketAddr::from(([10, 0, 0, 8], 50000))).send().await;
+    assert_eq!(other.status, StatusCode::OK);
+
+    let health = server.get("/health").from_peer(peer).send().await;
+    assert_eq!(health.header("x-ratelimit-tier"), Some("exempt"));
+
+    let rejected = server.metrics().recent_responses("/api/stats");
+    assert_eq!(rejected.iter().filter(|response| response.status == 429).count(), 1);
+}
+
+#[tokio::test]
+async fn test_stats_are_cached_until_items_change() {
+    let server = TestServer::new().await;
+    let stats = |response: &core_lib::test_support::TestResponse| {
+        let data = response.json()["data"].clone();
+        (data["cache"].as_str().unwrap().to_string(), data["computed_at"].clone(), data["total_items"].clone())
+    };
+
+    let first = server.get("/api/stats").send().await;
+    assert_eq!(first.status, StatusCode::OK);
+    assert_eq!(first.header("cache-control"), Some("no-store"));
+    let (cache, computed_at, total) = stats(&first);
+    assert_eq!(cache, "miss");
+    assert_eq!(first.json()["data"]["generated_at"], computed_at);
+
+    let second = server.get("/api/stats").send().await;
+    assert_eq!(stats(&second), ("hit".to_string(), computed_at.clone(), total.clone()));
+
+    let created = server.post("/api/items").json(&json!({"name": "Counted"})).send().await;
+    assert_eq!(created.status, StatusCode::CREATED);
+    let (cache, _, after) = stats(&server.get("/api/stats").send().await);
+    assert_eq!(cache, "miss");
+    assert_eq!(after, json!(total.as_u64().unwrap() + 1));
+
+    let user = server.login_as("stats_user", UserRole::User).await;
+    let admin = server.login_as("stats_admin", UserRole::Admin).await;
+    let anonymous = server.get("/api/stats?refresh=true").send().await;
+    assert_eq!(anonymous.status, StatusCode::FORBIDDEN);
+    let forbidden = server.get("/api/stats?refresh=true").bearer(&user).send().await;
+    assert_eq!(forbidden.status, StatusCode::FORBIDDEN);
+    let refreshed = server.get("/api/stats?refresh=true").bearer(&admin).send().await;
+    assert_eq!(refreshed.status, StatusCode::OK);
+    assert_eq!(stats(&refreshed).0, "miss");
+    assert_eq!(stats(&server.get("/api/stats").send().await).0, "hit");
+}
+
+#[tokio::test]
+async fn test_malformed_requests_are_refused_before_handlers() {
+    let server = TestServer::with_config(|config| config.validation.max_header_count = 20).await;
+    let events = || server.metrics().get_snapshot(0).security_events;
+
+    let plain = server.post("/api/items").body("text/plain", "name=Plain").send().await;
+    assert_eq!(plain.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert!(plain.json()["error"].as_str().unwrap().contains("application/json"));
+
+    let mut crowded = server.get("/api/items");
+    for n in 0..20 {
+        crowded = crowded.header(&format!("x-filler-{}", n), "1");
+    }
+    assert_eq!(crowded.send().await.status, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+
+    let framed = server
+        .post("/api/items")
+        .json(&json!({"name": "Framed"}))
+        .header("content-length", "17")
+        .header("transfer-encoding", "chunked")
+        .send()
+        .await;
+    assert_eq!(framed.status, StatusCode::BAD_REQUEST);
+    assert!(framed.json()["error"].as_str().unwrap().contains("Transfer-Encoding"));
+
+    let events = events();
+    assert_eq!(events.get("unsupported_media_type"), Some(&1));
+    assert_eq!(events.get("headers_too_large"), Some(&1));
+    assert_eq!(events.get("ambiguous_body_length"), Some(&1));
+    assert_eq!(server.metrics().get_snapshot(0).security_events.get("duplicate_query_params"), None);
+
+    // The first of repeated parameters wins.
+    let listed = server.get("/api/items?page_size=1&page_size=50").send().await;
+    assert_eq!(listed.status, StatusCode::OK, "{}", listed.text());
+    assert_eq!(listed.json()["data"]["page_size"], 1);
+    assert_eq!(server.metrics().get_snapshot(0).security_events.get("duplicate_query_params"), Some(&1));
+}
+
+#[tokio::test]
+async fn test_auth_protected_routes() {
+    let server = TestServer::new().await;
+    let user = server.login_as("golden_user", UserRole::User).await;
+    let admin = server.login_as("golden_admin", UserRole::Admin).await;
+
+    let anonymous = server.get("/auth/me").send().await;
+    assert_eq!(anonymous.status, StatusCode::UNAUTHORIZED);
+    assert_golden("auth_me_unauthorized", &anonymous.json());
+
+    let me = server.get("/auth/me").bearer(&user).send().await;
+    assert_eq!(me.status, StatusCode::OK);
+    assert_golden("auth_me", &me.json());
+
+    let forbidden = server.get("/api/admin/security/blocks").bearer(&user).send().await;
+    assert_eq!(forbidden.status, StatusCode::FORBIDDEN);
+    assert_golden("admin_forbidden", &forbidden.json());
+
+    let allowed = server.get("/api/admin/security/blocks").bearer(&admin).send().await;
+    assert_eq!(allowed.status, StatusCode::OK);
+
+    let invalid = server.get("/auth/me").bearer("not-a-token").send().await;
+    assert_eq!(invalid.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_websocket_upgrade_through_router() {
+    let server = TestServer::new().await;
+    let token = server.login_as("socket_user", UserRole::User).await;
+    let mut socket = server.websocket("/ws", Some(&token)).await;
+
+    let mut messages = Vec::new();
+    while messages.len() < 2 {
+        let frame = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("timed out waiting for a WebSocket message")
+            .expect("WebSocket closed")
+            .unwrap();
+        if let Message::Text(text) = frame {
+            messages.push(WebSocketMessage::from_json(&text).unwrap());
+        }
+    }
+    assert!(matches!(messages[0], WebSocketMessage::Authenticated { user_id: 1 }));
+    assert!(matches!(messages[1], WebSocketMessage::Connected { .. }));
+}
+
+#[tokio::test]
+async fn test_csv_export_escapes_quotes_and_joins_tags() {
+    let server = TestServer::new().await;
+    let item = server
+        .state()
+        .item_service
+        .create_item(
+            "Quoted".to_string(),
+            Some("A \"quoted\" word".to_string()),
+            vec!["a".to_string(), "b".to_string()],
+            None,
+        )
+        .await
+        .unwrap();
+
+    let export = server.get("/api/items/export?format=csv").send().await;
+    assert_eq!(export.status, StatusCode::OK);
+    assert_eq!(export.header("content-type"), Some("text/csv"));
+    let text = export.text();
+    let mut lines = text.lines();
+    assert_eq!(lines.next(), Some("id,name,description,tags,created_at,updated_at,metadata"));
+    let row = lines
+        .find(|line| line.starts_with(&format!("{},", item.id)))
+        .expect("exported row for the new item");
+    assert_eq!(
+        row,
+        format!(
+            "{},Quoted,\"A \"\"quoted\"\" word\",a;b,{},{},{{}}",
+            item.id,
+            item.created_at.to_rfc3339(),
+            item.updated_at.to_rfc3339()
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_csv_export_neutralizes_formulas_unless_disabled() {
+    let server = TestServer::new().await;
+    let item = server
+        .state()
+        .item_service
+        .create_item("=HYPERLINK(\"http://x\")".to_string(), None, vec![], None)
+        .await
+        .unwrap();
+    let row = |text: String| {
+        text.lines()
+            .find(|line| line.starts_with(&format!("{},", item.id)))
+            .map(|line| line.split(',').nth(1).unwrap().to_string())
+            .unwrap()
+    };
+
+    let safe = server.get("/api/items/export?format=csv").send().await;
+    assert_eq!(safe.status, StatusCode::OK);
+    assert_eq!(row(safe.text()), "\"'=HYPERLINK(\"\"http://x\"\")\"");
+
+    let raw = server.get("/api/items/export?format=csv&safe_csv=false").send().await;
+    assert_eq!(raw.status, StatusCode::OK);
+    assert_eq!(row(raw.text()), "\"=HYPERLINK(\"\"http://x\"\")\"");
+}
+
+#[tokio::test]
+async fn test_open_websocket_counts_against_client_concurrency() {
+    let server = TestServer::with_config(|config| {
+        config.concurrency.per_client_limit = 1;
+        config.concurrency.per_client_queue = 0;
+    })
+    .await;
+    let socket = server.websocket("/ws", None).await;
+
+    // The socket and these requests all come from 127.0.0.1.
+    let blocked = server.get("/api/items").send().await;
+    assert_eq!(blocked.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(blocked.header("content-type"), Some("application/problem+json"));
+    assert_eq!(blocked.json()["reason"], "queue_full");
+    assert!(blocked.header("retry-after").is_some());
+
+    let other = server.get("/api/items").from_peer(SocketAddr::from(([10, 0, 0, 9], 50000))).send().await;
+    assert_eq!(other.status, StatusCode::OK);
+    let health = server.get("/health").send().await;
+    assert_eq!(health.status, StatusCode::OK);
+
+    let concurrency = server.metrics().concurrency();
+    assert_eq!(concurrency.in_flight, 1);
+    assert_eq!(concurrency.rejected.get("queue_full"), Some(&1));
+
+    drop(socket);
+    let mut admitted = false;
+    for _ in 0..50 {
+        if server.get("/api/items").send().await.status == StatusCode::OK {
+            admitted = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(admitted, "slot was not released when the socket closed");
+    assert_eq!(server.metrics().concurrency().in_flight, 0);
+}
+
+#[tokio::test]
+async fn test_search_query_language() {
+    let server = TestServer::new().await;
+    let create = |name: &str, description: &str, tags: &[&str]| {
+        server.state().item_service.create_item(
+            name.to_string(),
+            Some(description.to_string()),
+            tags.iter().map(|tag| tag.to_string()).collect(),
+            None,
+        )
+    };
+    let report = create("Quarterly report", "Finance numbers, draft", &["finance"]).await.unwrap().id;
+    let budget = create("Budget", "Report for the board", &["finance"]).await.unwrap().id;
+    let memo = create("Memo", "Quarterly plans", &["internal"]).await.unwrap().id;
+
+    assert_eq!(search_ids(&server, "memo%20OR%20quarterly%20AND%20draft").await, vec![report, memo]);
+    assert_eq!(search_ids(&server, "(memo%20OR%20quarterly)%20AND%20draft").await, vec![report]);
+    assert_eq!(search_ids(&server, "%22quarterly%20report%22").await, vec![report]);
+    assert_eq!(search_ids(&server, "%22report%20quarterly%22").await, Vec::<u64>::new());
+    assert_eq!(search_ids(&server, "report%20tags:finance%20NOT%20draft").await, vec![budget]);
+
+    let invalid = server.get("/api/items/search?q=report%20AND%20(draft").send().await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+    assert!(invalid.text().contains("Unclosed '(' at position 12"), "{}", invalid.text());
+}
+
+async fn search_ids(server: &TestServer, q: &str) -> Vec<u64> {
+    let response = server.get(&format!("/api/items/search?q={}", q)).send().await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let mut ids: Vec<u64> = response.json()["data"]["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["item"]["id"].as_u64().unwrap())
+        .collect();
+    ids.sort();
+    ids
+}
+
+#[tokio::test]
+async fn test_suggest_names_and_tags() {
+    let server = TestServer::new().await;
+    for (name, tags) in [("Quarterly Report", vec!["reports"]), ("Quarterly Report", vec!["reports", "Research"])] {
+        let created = server.post("/api/items").json(&json!({"name": name, "tags": tags})).send().await;
+        assert_eq!(created.status, StatusCode::CREATED);
+    }
+
+    let names = server.get("/api/items/suggest?q=quarterly%20rep&field=name").send().await;
+    assert_eq!(names.status, StatusCode::OK, "{}", names.text());
+    assert_eq!(names.json()["data"]["suggestions"], json!([{"text": "Quarterly Report", "count": 2}]));
+
+    // A new item shows up at once despite the cached answer.
+    let names = server.get("/api/items/suggest?q=rep").send().await;
+    assert_eq!(names.json()["data"]["suggestions"].as_array().unwrap().len(), 1);
+    let created = server.post("/api/items").json(&json!({"name": "Report Card"})).send().await;
+    assert_eq!(created.status, StatusCode::CREATED);
+    let names = server.get("/api/items/suggest?q=rep").send().await;
+    assert_eq!(
+        names.json()["data"]["suggestions"],
+        json!([{"text": "Quarterly Report", "count": 2}, {"text": "Report Card", "count": 1}])
+    );
+    let limited = server.get("/api/items/suggest?q=rep&limit=1").send().await;
+    assert_eq!(limited.json()["data"]["suggestions"].as_array().unwrap().len(), 1);
+
+    let tags = server.get("/api/items/suggest?q=RE&field=tags").send().await;
+    assert_eq!(
+        tags.json()["data"]["suggestions"],
+        json!([{"text": "reports", "count": 2}, {"text": "Research", "count": 1}])
+    );
+
+    let short = server.get("/api/items/suggest?q=r").send().await;
+    assert_eq!(short.json()["data"]["suggestions"], json!([]));
+    let invalid = server.get("/api/items/suggest?q=rep&field=owner").send().await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_search_export_direct_and_as_job() {
+    let server = TestServer::with_config(|config| {
+        config.search_export.direct_limit = 2;
+        config.search_export.page_size = 1;
+    })
+    .await;
+    for name in ["Report C", "Report A", "Memo", "Report B"] {
+        let created = server.post("/api/items").json(&json!({"name": name})).send().await;
+        assert_eq!(created.status, StatusCode::CREATED);
+    }
+
+    let direct = server.post("/api/items/search/export?q=memo&format=csv").send().await;
+    assert_eq!(direct.status, StatusCode::OK, "{}", direct.text());
+    assert_eq!(direct.header("content-type"), Some("text/csv"));
+    assert_eq!(direct.text().lines().count(), 2);
+
+    let uri = "/api/items/search/export?q=report&sort_by=name&sort_order=asc&format=csv";
+    let anonymous = server.post(uri).send().await;
+    assert_eq!(anonymous.status, StatusCode::UNAUTHORIZED);
+    let token = server.login_as("exporter", UserRole::User).await;
+    let accepted = server.post(uri).bearer(&token).send().await;
+    assert_eq!(accepted.status, StatusCode::ACCEPTED, "{}", accepted.text());
+    let job_id = accepted.json()["data"]["job_id"].as_str().unwrap().parse().unwrap();
+
+    let job_queue = server.state().job_queue.as_ref().unwrap();
+    let mut job = job_queue.get_job_status(job_id).await.unwrap().unwrap();
+    for _ in 0..100 {
+        if matches!(job.status, JobStatus::Completed | JobStatus::Failed) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        job = job_queue.get_job_status(job_id).await.unwrap().unwrap();
+    }
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error_message);
+    let result = job.result.unwrap();
+    assert_eq!(result["exported_count"], 3);
+
+    let file = server.get(result["download_url"].as_str().unwrap()).send().await;
+    assert_eq!(file.status, StatusCode::OK);
+    assert_eq!(file.header("content-type"), Some("text/csv"));
+    let names: Vec<String> = file
+        .text()
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').nth(1).unwrap().to_string())
+        .collect();
+    assert_eq!(names, ["Report A", "Report B", "Report C"]);
+}
+
+#[cfg(feature = "xlsx")]
+#[tokio::test]
+async fn test_search_export_as_xlsx() {
+    const XLSX: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+    let server = TestServer::with_config(|config| config.search_export.direct_limit = 1).await;
+    for name in ["Report A", "Report B"] {
+        let created = server.post("/api/items").json(&json!({"name": name})).send().await;
+        assert_eq!(created.status, StatusCode::CREATED);
+    }
+
+    let direct = server.get("/api/items/export?format=xlsx").send().await;
+    assert_eq!(direct.status, StatusCode::OK, "{}", direct.text());
+    assert_eq!(direct.header("content-type"), Some(XLSX));
+    assert_eq!(direct.header("content-disposition"), Some("attachment; filename=\"items_export.xlsx\""));
+    assert!(direct.body.starts_with(b"PK"));
+
+    let token = server.login_as("exporter", UserRole::User).await;
+    let accepted = server.post("/api/items/search/export?q=report&format=xlsx").bearer(&token).send().await;
+    assert_eq!(accepted.status, StatusCode::ACCEPTED, "{}", accepted.text());
+    let job_id = accepted.json()["data"]["job_id"].as_str().unwrap().parse().unwrap();
+
+    let job_queue = server.state().job_queue.as_ref().unwrap();
+    let mut job = job_queue.get_job_status(job_id).await.unwrap().unwrap();
+    for _ in 0..100 {
+        if matches!(job.status, JobStatus::Completed | JobStatus::Failed) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        job = job_queue.get_job_status(job_id).await.unwrap().unwrap();
+    }
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error_message);
+    let result = job.result.unwrap();
+    assert_eq!(result["exported_count"], 2);
+    assert_eq!(result["filename"], "search_export.xlsx");
+
+    let file = server.get(result["download_url"].as_str().unwrap()).send().await;
+    assert_eq!(file.status, StatusCode::OK);
+    assert_eq!(file.header("content-type"), Some(XLSX));
+    assert_eq!(file.body.len() as u64, result["size"].as_u64().unwrap());
+    let archive = zip::ZipArchive::new(std::io::Cursor::new(file.body.to_vec())).unwrap();
+    assert!(archive.file_names().any(|name| name == "xl/worksheets/sheet2.xml"));
+}
+
+#[cfg(not(feature = "xlsx"))]
+#[tokio::test]
+async fn test_xlsx_export_needs_the_feature() {
+    let server = TestServer::new().await;
+
+    let export = server.get("/api/items/export?format=xlsx").send().await;
+    assert_eq!(export.status, StatusCode::NOT_IMPLEMENTED, "{}", export.text());
+    let search = server.post("/api/items/search/export?q=report&format=xlsx").send().await;
+    assert_eq!(search.status, StatusCode::NOT_IMPLEMENTED, "{}", search.text());
+}
+
+#[tokio::test]
+async fn test_large_job_results_are_stored_as_files() {
+    let server = TestServer::with_config(|config| config.jobs.max_inline_result_bytes = 16).await;
+    let submitted = server
+        .post("/api/jobs")
+        .json(&json!({ "job_type": "BulkImport", "payload": { "data": [{ "name": "x" }] } }))
+        .send()
+        .await;
+    assert_eq!(submitted.status, StatusCode::CREATED, "{}", submitted.text());
+    let job_id = submitted.json()["data"]["job_id"].as_str().unwrap().parse().unwrap();
+
+    let result_uri = format!("/api/jobs/{}/result", job_id);
+    let job_queue = server.state().job_queue.as_ref().unwrap();
+    let mut job = job_queue.get_job_status(job_id).await.unwrap().unwrap();
+    if job.status != JobStatus::Completed {
+        assert_eq!(server.get(&result_uri).send().await.status, StatusCode::BAD_REQUEST);
+    }
+    for _ in 0..100 {
+        if matches!(job.status, JobStatus::Completed | JobStatus::Failed) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        job = job_queue.get_job_status(job_id).await.unwrap().unwrap();
+    }
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error_message);
+    assert!(job.result.is_none());
+    let result_file = job.result_file.clone().unwrap();
+    assert_eq!(job.result_size, Some(result_file.size));
+    let stored: Option<String> = sqlx::query_scalar("SELECT result FROM jobs WHERE id = ?")
+        .bind(job_id.to_string())
+        .fetch_one(&server.app.pool)
+        .await
+        .unwrap();
+    assert!(stored.is_none());
+
+    let result = server.get(&result_uri).send().await;
+    assert_eq!(result.status, StatusCode::OK, "{}", result.text());
+    assert_eq!(result.header("content-type"), Some("application/json"));
+    assert_eq!(result.text().len() as u64, result_file.size);
+    assert_eq!(result.json()["imported_count"], 1);
+
+    let listed = server.get("/api/jobs").send().await;
+    assert_eq!(listed.status, StatusCode::OK);
+    let entry = &listed.json()["data"]["jobs"][0];
+    assert!(entry.get("result").is_none(), "{}", entry);
+    assert_eq!(entry["result_size"], result_file.size);
+
+    let path = job_queue.result_path(&job).unwrap();
+    assert!(path.exists());
+    assert_eq!(job_queue.cleanup_old_jobs(0).await.unwrap(), 1);
+    assert!(!path.exists());
+    assert_eq!(server.get(&result_uri).send().await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_unified_search_across_entities() {
+    let server = TestServer::new().await;
+    let user = server.login_as("report_writer", UserRole::User).await;
+    let admin = server.login_as("report_admin", UserRole::Admin).await;
+    let created = server.post("/api/items").json(&json!({"name": "Annual report"})).send().await;
+    assert_eq!(created.status, StatusCode::CREATED);
+
+    let files = server.state().file_manager.as_ref().unwrap();
+    for (token, filename) in [(&user, "report_2024.pdf"), (&admin, "report_draft.pdf"), (&admin, "notes.txt")] {
+        let me = server.get("/auth/me").bearer(token).send().await;
+        let upload = core_lib::files::FileUpload {
+            original_filename: filename.to_string(),
+            content_type: "application/octet-stream".to_string(),
+            data: b"content".to_vec(),
+            uploaded_by: me.json()["id"].as_u64().unwrap(),
+            item_id: None,
+        };
+        files.store_generated(upload).await.unwrap();
+    }
+
+    let items = server.get("/api/search?q=report").send().await;
+    assert_eq!(items.status, StatusCode::OK, "{}", items.text());
+    assert_eq!(items.json()["data"]["counts"], json!({"items": 1}));
+    assert_eq!(items.json()["data"]["results"][0]["type"], "item");
+
+    let uri = "/api/search?q=report&entities=items,files";
+    assert_eq!(server.get(uri).send().await.status, StatusCode::UNAUTHORIZED);
+    let own = server.get(uri).bearer(&user).send().await;
+    assert_eq!(own.status, StatusCode::OK, "{}", own.text());
+    assert_eq!(own.json()["data"]["counts"], json!({"items": 1, "files": 1}));
+    assert_eq!(own.json()["data"]["results"][1]["original_filename"], "report_2024.pdf");
+    let all = server.get(uri).bearer(&admin).send().await;
+    assert_eq!(all.json()["data"]["counts"], json!({"items": 1, "files": 2}));
+
+    let users_uri = "/api/search?q=REPORT_&entities=users";
+    assert_eq!(server.get(users_uri).bearer(&user).send().await.status, StatusCode::FORBIDDEN);
+    let users = server.get(users_uri).bearer(&admin).send().await;
+    assert_eq!(users.status, StatusCode::OK, "{}", users.text());
+    assert_eq!(users.json()["data"]["total_count"], 2);
+    assert_eq!(users.json()["data"]["results"][0]["type"], "user");
+    assert_eq!(users.json()["data"]["results"][0]["username"], "report_admin");
+
+    let invalid = server.get("/api/search?q=report&entities=orders").send().await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_file_storage_health_gates_readiness() {
+    let server = TestServer::with_config(|config| config.health.failure_threshold = 1).await;
+    let files = server.state().file_manager.as_ref().unwrap();
+
+    let healthy = server.get("/health/files").send().await;
+    assert_eq!(healthy.status, StatusCode::OK, "{}", healthy.text());
+    assert_eq!(healthy.json()["data"]["details"]["writable"], true);
+    assert_eq!(server.get("/ready").send().await.status, StatusCode::OK);
+
+    let full = files.ensure_space(u64::MAX).unwrap_err();
+    assert!(matches!(full, AppError::InsufficientStorage(_)));
+    assert_eq!(full.into_response().status(), StatusCode::INSUFFICIENT_STORAGE);
+
+    std::fs::remove_dir_all(files.storage_path()).unwrap();
+    let unhealthy = server.get("/health/files").send().await;
+    assert_eq!(unhealthy.status, StatusCode::SERVICE_UNAVAILABLE, "{}", unhealthy.text());
+    let ready = server.get("/ready").send().await;
+    assert_eq!(ready.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(ready.text().contains("files_unhealthy"), "{}", ready.text());
+}
+
+#[tokio::test]
+async fn test_profile_self_service() {
+    let server = TestServer::new().await;
+    let token = server.login_as("profiled", UserRole::User).await;
+    let other = server.login_as("stranger", UserRole::User).await;
+    let admin = server.login_as("profile_admin", UserRole::Admin).await;
+    let me = server.get("/auth/me").bearer(&token).send().await.json();
+    let my_id = me["id"].as_u64().unwrap();
+    let other_id = server.get("/auth/me").bearer(&other).send().await.json()["id"].as_u64().unwrap();
+    assert_eq!(me["display_name"], json!(null));
+    assert_eq!(me["notification_preferences"], json!({"new_device_login": true, "password_changed": true}));
+
+    let updated = server
+        .patch("/auth/me")
+        .bearer(&token)
+        .json(&json!({
+            "display_name": "Pro Filed",
+            "timezone": "America/Argentina/Buenos_Aires",
+            "notification_preferences": {"new_device_login": false}
+        }))
+        .send()
+        .await;
+    assert_eq!(updated.status, StatusCode::OK, "{}", updated.text());
+    let updated = updated.json();
+    assert_eq!(updated["display_name"], "Pro Filed");
+    assert_eq!(updated["timezone"], "America/Argentina/Buenos_Aires");
+    assert_eq!(updated["notification_preferences"]["new_device_login"], false);
+    assert_eq!(updated["notification_preferences"]["password_changed"], true);
+
+    for (body, status) in [
+        (json!({"display_name": "<script>alert(1)</script>"}), StatusCode::BAD_REQUEST),
+        (json!({"display_name": "x".repeat(101)}), StatusCode::BAD_REQUEST),
+        (json!({"timezone": "Mars/Olympus Mons"}), StatusCode::BAD_REQUEST),
+        (json!({"role": "admin"}), StatusCode::FORBIDDEN),
+        (json!({"is_active": false}), StatusCode::FORBIDDEN),
+        (json!({"email": "elsewhere@example.com"}), StatusCode::BAD_REQUEST),
+        (json!({"email": "stranger@example.com", "current_password": TEST_PASSWORD}), StatusCode::CONFLICT),
+    ] {
+        let response = server.patch("/auth/me").bearer(&token).json(&body).send().await;
+        assert_eq!(response.status, status, "{} -> {}", body, response.text());
+    }
+
+    let files = server.state().file_manager.as_ref().unwrap();
+    let store = |name: &str, content_type: &str, owner: u64| core_lib::files::FileUpload {
+        original_filename: name.to_string(),
+        content_type: content_type.to_string(),
+        data: b"pixels".to_vec(),
+        uploaded_by: owner,
+        item_id: None,
+    };
+    let mine = files.store_generated(store("me.png", "image/png", my_id)).await.unwrap();
+    let notes = files.store_generated(store("notes.txt", "text/plain", my_id)).await.unwrap();
+    let theirs = files.store_generated(store("them.png", "image/png", other_id)).await.unwrap();
+    for file_id in [notes.id, theirs.id, uuid::Uuid::new_v4()] {
+        let response = server.patch("/auth/me").bearer(&token).json(&json!({"avatar_file_id": file_id})).send().await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST, "{}", response.text());
+    }
+    let avatar = server.patch("/auth/me").bearer(&token).json(&json!({"avatar_file_id": mine.id})).send().await;
+    assert_eq!(avatar.status, StatusCode::OK, "{}", avatar.text());
+    assert_eq!(avatar.json()["avatar_file_id"], mine.id.to_string());
+
+    // Notifications are off here, so an email change applies at once.
+    let moved = server
+        .patch("/auth/me")
+        .bearer(&token)
+        .json(&json!({"email": "moved@example.com", "current_password": TEST_PASSWORD, "display_name": null}))
+        .send()
+        .await;
+    assert_eq!(moved.status, StatusCode::OK, "{}", moved.text());
+    assert_eq!(moved.json()["email"], "moved@example.com");
+    assert_eq!(moved.json()["display_name"], json!(null));
+    assert_eq!(moved.json()["role"], "user");
+
+    let seen_by_admin = server.get(&format!("/auth/users/{}", my_id)).bearer(&admin).send().await.json();
+    assert_eq!(seen_by_admin["email"], "moved@example.com");
+    assert_eq!(seen_by_admin["timezone"], "America/Argentina/Buenos_Aires");
+    assert_eq!(seen_by_admin["avatar_file_id"], mine.id.to_string());
+}
+
+#[tokio::test]
+async fn test_file_head_and_conditional_get() {
+    let server = TestServer::new().await;
+    let token = server.login_as("logo_owner", UserRole::User).await;
+    let me = server.get("/auth/me").bearer(&token).send().await;
+    let files = server.state().file_manager.as_ref().unwrap();
+    let upload = core_lib::files::FileUpload {
+        original_filename: "logo.png".to_string(),
+        content_type: "image/png".to_string(),
+        data: b"not really a png".to_vec(),
+        uploaded_by: me.json()["id"].as_u64().unwrap(),
+        item_id: None,
+    };
+    let stored = files.store_generated(upload).await.unwrap();
+    let serve = format!("/api/files/{}/serve", stored.id);
+    let download = format!("/api/files/{}/download", stored.id);
+
+    let full = server.get(&serve).send().await;
+    assert_eq!(full.status, StatusCode::OK, "{}", full.text());
+    assert_eq!(full.text(), "not really a png");
+    let etag = full.header("etag").unwrap().to_string();
+    assert_eq!(etag, format!("\"{}\"", stored.sha256.as_deref().unwrap()));
+    let last_modified = full.header("last-modified").unwrap().to_string();
+    assert_eq!(full.header("cache-control"), Some("public, max-age=31536000, immutable"));
+
+    for uri in [&serve, &download] {
+        let head = server.request(axum::http::Method::HEAD, uri).send().await;
+        assert_eq!(head.status, StatusCode::OK, "{}", head.text());
+        assert!(head.text().is_empty());
+        assert_eq!(head.header("content-length"), Some("16"));
+        assert_eq!(head.header("content-type"), Some("image/png"));
+        assert_eq!(head.header("etag"), Some(etag.as_str()));
+        assert_eq!(head.header("last-modified"), Some(last_modified.as_str()));
+    }
+    let head = server.request(axum::http::Method::HEAD, &download).send().await;
+    assert_eq!(head.header("content-disposition"), Some("attachment; filename=\"logo.png\""));
+
+    // With the blob gone, only answers that never open the file succeed.
+    std::fs::remove_dir_all(files.storage_path()).unwrap();
+    let cached = server.get(&serve).header("if-none-match", &format!("\"other\", {}", etag)).send().await;
+    assert_eq!(cached.status, StatusCode::NOT_MODIFIED);
+    assert!(cached.text().is_empty());
+    assert_eq!(cached.header("etag"), Some(etag.as_str()));
+    let cached = server.get(&download).header("if-modified-since", &last_modified).send().await;
+    assert_eq!(cached.status, StatusCode::NOT_MODIFIED);
+    let head = server.request(axum::http::Method::HEAD, &serve).send().await;
+    assert_eq!(head.status, StatusCode::OK);
+
+    let stale = server.get(&serve).header("if-none-match", "\"other\"").send().await;
+    assert_ne!(stale.status, StatusCode::NOT_MODIFIED);
+    assert_ne!(stale.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_published_files_are_served_by_content_hash() {
+    let server = TestServer::new().await;
+    let owner = server.login_as("asset_owner", UserRole::User).await;
+    let other = server.login_as("asset_other", UserRole::User).await;
+    let owner_id = server.get("/auth/me").bearer(&owner).send().await.json()["id"].as_u64().unwrap();
+    let files = server.state().file_manager.as_ref().unwrap();
+    let store = |name: &str, content_type: &str, data: &[u8]| {
+        files.store_generated(core_lib::files::FileUpload {
+            original_filename: name.to_string(),
+            content_type: content_type.to_string(),
+            data: data.to_vec(),
+            uploaded_by: owner_id,
+            item_id: None,
+        })
+    };
+    let banner = store("banner.png", "image/png", b"banner pixels").await.unwrap();
+    let reupload = store("banner-v2.png", "image/png", b"banner pixels").await.unwrap();
+    let notes = store("notes.txt", "text/plain", b"notes").await.unwrap();
+    let vector = store("logo.svg", "image/svg+xml", b"<svg/>").await.unwrap();
+    let publish = |id: uuid::Uuid| format!("/api/files/{}/publish", id);
+
+    assert_eq!(server.post(&publish(banner.id)).send().await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(server.post(&publish(banner.id)).bearer(&other).send().await.status, StatusCode::FORBIDDEN);
+    for unsafe_type in [notes.id, vector.id] {
+        let refused = server.post(&publish(unsafe_type)).bearer(&owner).send().await;
+        assert_eq!(refused.status, StatusCode::BAD_REQUEST, "{}", refused.text());
+    }
+
+    let published = server.post(&publish(banner.id)).bearer(&owner).send().await;
+    assert_eq!(published.status, StatusCode::OK, "{}", published.text());
+    let url = published.json()["data"]["url"].as_str().unwrap().to_string();
+    assert_eq!(url, format!("/public/assets/{}.png", banner.sha256.as_deref().unwrap()));
+    let audit = server.state().audit_log.recent(Some("files.published"), 10);
+    assert_eq!(audit[0].target.as_deref(), Some(banner.id.to_string().as_str()));
+
+    let asset = server.get(&url).send().await;
+    assert_eq!(asset.status, StatusCode::OK, "{}", asset.text());
+    assert_eq!(asset.text(), "banner pixels");
+    assert_eq!(asset.header("content-type"), Some("image/png"));
+    assert_eq!(asset.header("cache-control"), Some("public, max-age=31536000, immutable"));
+    let etag = asset.header("etag").unwrap().to_string();
+    let cached = server.get(&url).header("if-none-match", &etag).send().await;
+    assert_eq!(cached.status, StatusCode::NOT_MODIFIED);
+    let jpeg = url.replace(".png", ".jpg");
+    assert_eq!(server.get(&jpeg).send().await.status, StatusCode::NOT_FOUND);
+    assert_eq!(server.get(&format!("/public/assets/{}.png", "0".repeat(64))).send().await.status, StatusCode::NOT_FOUND);
+
+    // The same image uploaded again shares the URL, which stays up until
+    // both copies are unpublished.
+    let again = server.post(&publish(reupload.id)).bearer(&owner).send().await;
+    assert_eq!(again.json()["data"]["url"], url);
+    let unpublished = server.delete(&publish(banner.id)).bearer(&owner).send().await;
+    assert_eq!(unpublished.status, StatusCode::OK, "{}", unpublished.text());
+    assert_eq!(server.get(&url).send().await.status, StatusCode::OK);
+    assert_eq!(server.delete(&publish(banner.id)).bearer(&owner).send().await.status, StatusCode::NOT_FOUND);
+    assert_eq!(server.delete(&publish(reupload.id)).bearer(&other).send().await.status, StatusCode::FORBIDDEN);
+    server.delete(&publish(reupload.id)).bearer(&owner).send().await;
+    assert_eq!(server.get(&url).send().await.status, StatusCode::GONE);
+
+    // Contents that no longer match the hash are never served or shared.
+    server.post(&publish(banner.id)).bearer(&owner).send().await;
+    let path: String = sqlx::query_scalar("SELECT path FROM files WHERE id = ?")
+        .bind(banner.id.to_string())
+        .fetch_one(&server.app.pool)
+        .await
+        .unwrap();
+    std::fs::write(&path, b"swapped pixels").unwrap();
+    assert_eq!(server.get(&url).send().await.status, StatusCode::INTERNAL_SERVER_ERROR);
+    let clash = server.post(&publish(reupload.id)).bearer(&owner).send().await;
+    assert_eq!(clash.status, StatusCode::CONFLICT, "{}", clash.text());
+}
+
+fn multipart_file(filename: &str, content_type: &str, data: &str) -> (String, String) {
+    let boundary = "test-boundary-7MA4YWxkTrZu0gW";
+    let body = format!(
+        "--{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n{}\r\n--{b}--\r\n",
+        filename,
+        content_type,
+        data,
+        b = boundary
+    );
+    (format!("multipart/form-data; boundary={}", boundary), body)
+}
+
+#[tokio::test]
+async fn test_upload_progress_events() {
+    let server = TestServer::new().await;
+    let token = server.login_as("uploader", UserRole::User).await;
+    let mut socket = server.websocket("/ws", Some(&token)).await;
+    assert!(matches!(next_message(&mut socket).await, WebSocketMessage::Authenticated { .. }));
+    assert!(matches!(next_message(&mut socket).await, WebSocketMessage::Connected { .. }));
+
+    let upload_id = uuid::Uuid::new_v4();
+    let (content_type, body) = multipart_file("notes.txt", "text/plain", "Meeting notes");
+    let uploaded = server
+        .post("/api/files/upload")
+        .bearer(&token)
+        .header("x-upload-id", &upload_id.to_string())
+        .header("content-length", &body.len().to_string())
+        .body(&content_type, body)
+        .send()
+        .await;
+    assert_eq!(uploaded.status, StatusCode::OK, "{}", uploaded.text());
+    assert_eq!(uploaded.header("x-upload-id"), Some(upload_id.to_string().as_str()));
+    let file_id = uploaded.json()["id"].as_str().unwrap().parse::<uuid::Uuid>().unwrap();
+
+    match next_message(&mut socket).await {
+        WebSocketMessage::UploadProgress { upload_id: id, bytes_received, total } => {
+            assert_eq!(id, upload_id);
+            assert_eq!(bytes_received, 13);
+            assert!(total.unwrap() > 13, "the total includes the multipart framing");
+        }
+        other => panic!("expected UploadProgress, got {}", other.message_type()),
+    }
+    loop {
+        match next_message(&mut socket).await {
+            WebSocketMessage::UploadProgress { .. } => continue,
+            WebSocketMessage::UploadCompleted { upload_id: id, file_id: completed } => {
+                assert_eq!((id, completed), (upload_id, file_id));
+                break;
+            }
+            other => panic!("expected UploadCompleted, got {}", other.message_type()),
+        }
+    }
+
+    let (content_type, body) = multipart_file("page.txt", "text/plain", "<a onclick=steal()>hi</a>");
+    let rejected = server.post("/api/files/upload").bearer(&token).body(&content_type, body).send().await;
+    assert_eq!(rejected.status, StatusCode::BAD_REQUEST, "{}", rejected.text());
+    let generated_id = rejected.header("x-upload-id").unwrap().parse::<uuid::Uuid>().unwrap();
+    loop {
+        match next_message(&mut socket).await {
+            // The `file_uploaded` broadcast for the first upload.
+            WebSocketMessage::UploadProgress { .. } | WebSocketMessage::Error { .. } => continue,
+            WebSocketMessage::UploadFailed { upload_id: id, error } => {
+                assert_eq!(id, generated_id);
+                assert!(!error.is_empty());
+                break;
+            }
+            other => panic!("expected UploadFailed, got {}", other.message_type()),
+        }
+    }
+    let files = server.state().file_manager.as_ref().unwrap();
+    let incoming = std::fs::read_dir(files.storage_path().join(".incoming")).unwrap();
+    assert_eq!(incoming.count(), 0, "rejected uploads leave nothing behind");
+
+    let (content_type, body) = multipart_file("notes.txt", "text/plain", "Meeting notes");
+    let invalid_id = server
+        .post("/api/files/upload")
+        .bearer(&token)
+        .header("x-upload-id", "not-a-uuid")
+        .body(&content_type, body)
+        .send()
+        .await;
+    assert_eq!(invalid_id.status, StatusCode::BAD_REQUEST);
+}
+
+async fn next_message(socket: &mut TestWebSocket) -> WebSocketMessage {
+    loop {
+        let frame = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("timed out waiting for a WebSocket message")
+            .expect("WebSocket closed")
+            .unwrap();
+        if let Message::Text(text) = frame {
+            return WebSocketMessage::from_json(&text).unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_websocket_connections_admin_api() {
+    use futures_util::SinkExt;
+
+    let server = TestServer::new().await;
+    let admin = server.login_as("presence_admin", UserRole::Admin).await;
+    let user = server.login_as("presence_user", UserRole::User).await;
+
+    let mut admin_socket = server.websocket("/ws", Some(&admin)).await;
+    assert!(matches!(next_message(&mut admin_socket).await, WebSocketMessage::Authenticated { .. }));
+    assert!(matches!(next_message(&mut admin_socket).await, WebSocketMessage::Connected { .. }));
+    let subscribe = json!({"type": "Subscribe", "data": {"version": 4, "topics": ["presence"]}});
+    admin_socket.send(Message::Text(subscribe.to_string())).await.unwrap();
+    assert!(matches!(next_message(&mut admin_socket).await, WebSocketMessage::Subscribed { version: 4 }));
+
+    let mut user_socket = server.websocket("/ws", Some(&user)).await;
+    assert!(matches!(next_message(&mut user_socket).await, WebSocketMessage::Authenticated { .. }));
+    let WebSocketMessage::Connected { connection_id } = next_message(&mut user_socket).await else {
+        panic!("expected Connected");
+    };
+    match next_message(&mut admin_socket).await {
+        WebSocketMessage::Presence { change, connection_id: joined, user_id } => {
+            assert_eq!(change, core_lib::websocket::PresenceChange::Joined);
+            assert_eq!(joined, connection_id);
+            assert_eq!(user_id, Some(2));
+        }
+        other => panic!("expected Presence, got {}", other.message_type()),
+    }
+
+    let forbidden = server.get("/api/websocket/connections").bearer(&user).send().await;
+    assert_eq!(forbidden.status, StatusCode::FORBIDDEN);
+
+    let listed = server.get("/api/websocket/connections?page=2&page_size=1").bearer(&admin).send().await;
+    assert_eq!(listed.status, StatusCode::OK);
+    let data = &listed.json()["data"];
+    assert_eq!(data["total"], 2);
+    let connection = &data["connections"][0];
+    assert_eq!(connection["connection_id"], connection_id.to_string());
+    assert_eq!(connection["remote_ip"], "127.0.0.1");
+    assert_eq!(connection["frames_received"], 0);
+    assert!(connection["frames_sent"].as_u64().unwrap() >= 2);
+
+    let metrics = server.get("/api/metrics").send().await;
+    assert_eq!(metrics.json()["data"]["websocket"]["topics"]["presence"], 1);
+
+    let uri = format!("/api/websocket/connections/{}?reason=Maintenance", connection_id);
+    let disconnected = server.delete(&uri).bearer(&admin).send().await;
+    assert_eq!(disconnected.status, StatusCode::OK);
+    let close = loop {
+        match tokio::time::timeout(Duration::from_secs(5), user_socket.next()).await.unwrap() {
+            Some(Ok(Message::Close(Some(close)))) => break close,
+            Some(Ok(_)) => continue,
+            other => panic!("expected close frame, got {:?}", other),
+        }
+    };
+    assert_eq!(u16::from(close.code), 1008);
+    assert_eq!(close.reason, "Maintenance");
+    assert!(matches!(
+        next_message(&mut admin_socket).await,
+        WebSocketMessage::Presence { change: core_lib::websocket::PresenceChange::Left, .. }
+    ));
+
+    let missing = server.delete(&uri).bearer(&admin).send().await;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_request_capture() {
+    let disabled = TestServer::new().await;
+    let admin = disabled.login_as("capture_admin", UserRole::Admin).await;
+    let refused = disabled.post("/api/admin/captures").bearer(&admin).json(&json!({})).send().await;
+    assert_eq!(refused.status, StatusCode::BAD_REQUEST);
+
+    let server = TestServer::with_config(|config| {
+        config.capture.enabled = true;
+        config.capture.max_body_bytes = 16;
+    })
+    .await;
+    let admin = server.login_as("capture_admin", UserRole::Admin).await;
+    let user = server.login_as("capture_user", UserRole::User).await;
+    let forbidden = server.post("/api/admin/captures").bearer(&user).json(&json!({})).send().await;
+    assert_eq!(forbidden.status, StatusCode::FORBIDDEN);
+    let too_long = server
+        .post("/api/admin/captures")
+        .bearer(&admin)
+        .json(&json!({ "duration_seconds": 7200 }))
+        .send()
+        .await;
+    assert_eq!(too_long.status, StatusCode::BAD_REQUEST);
+
+    let started = server
+        .post("/api/admin/captures")
+        .bearer(&admin)
+        .json(&json!({ "duration_seconds": 1 }))
+        .send()
+        .await;
+    assert_eq!(started.status, StatusCode::CREATED, "{}", started.text());
+    assert_eq!(started.json()["data"]["started_by"], "capture_admin");
+
+    let created = server
+        .post("/api/items?source=capture")
+        .bearer(&user)
+        .json(&json!({ "name": "Captured item with a long name" }))
+        .send()
+        .await;
+    assert_eq!(created.status, StatusCode::CREATED);
+    let login = server
+        .post("/auth/login")
+        .json(&json!({ "username_or_email": "capture_user", "password": TEST_PASSWORD }))
+        .send()
+        .await;
+    assert_eq!(login.status, StatusCode::OK, "{}", login.text());
+
+    let har = server.get("/api/admin/captures").bearer(&admin).send().await;
+    assert_eq!(har.status, StatusCode::OK, "{}", har.text());
+    let har = har.json();
+    let entries = har["log"]["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 2, "{}", har);
+    let item = &entries[0];
+    assert_eq!(item["request"]["method"], "POST");
+    assert_eq!(item["request"]["url"], "/api/items?source=capture");
+    assert_eq!(item["request"]["queryString"][0]["value"], "capture");
+    assert_eq!(item["response"]["status"], 201);
+    assert_eq!(item["request"]["postData"]["text"], r#"{"name":"Capture"#);
+    assert_eq!(item["request"]["postData"]["comment"], "body truncated");
+    assert_eq!(item["comment"], "user: capture_user");
+    let authorization = item["request"]["headers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|header| header["name"] == "authorization")
+        .unwrap();
+    assert_eq!(authorization["value"], "[REDACTED]");
+    let login = &entries[1];
+    assert_eq!(login["request"]["url"], "/auth/login");
+    assert_eq!(login["request"]["postData"]["text"], "");
+    assert_eq!(login["request"]["postData"]["comment"], "body not recorded");
+    assert_eq!(login["response"]["content"]["text"], "");
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    server.get("/api/items").send().await;
+    let har = server.get("/api/admin/captures").bearer(&admin).send().await.json();
+    assert_eq!(har["log"]["entries"].as_array().unwrap().len(), 2);
+    let stopped = server.state().audit_log.recent(Some("capture.stopped"), 10);
+    assert_eq!(stopped.len(), 1);
+    assert_eq!(stopped[0].details["reason"], "expired");
+
+    let filtered = server
+        .post("/api/admin/captures")
+        .bearer(&admin)
+        .json(&json!({ "path_prefix": "/api/items", "user": "capture_user" }))
+        .send()
+        .await;
+    assert_eq!(filtered.status, StatusCode::CREATED);
+    server.get("/api/items").send().await;
+    server.get("/health").bearer(&user).send().await;
+    server.get("/api/items").bearer(&user).send().await;
+    let stop = server.delete("/api/admin/captures").bearer(&admin).send().await;
+    assert_eq!(stop.status, StatusCode::OK, "{}", stop.text());
+    assert_eq!(stop.json()["data"]["recorded"], 1);
+    assert_eq!(server.delete("/api/admin/captures").bearer(&admin).send().await.status, StatusCode::NOT_FOUND);
+
+    let audit = server.state().audit_log.recent(None, 100);
+    let transitions: Vec<(&str, Option<&str>)> = audit
+        .iter()
+        .rev()
+        .filter(|event| event.action.starts_with("capture."))
+        .map(|event| (event.action.as_str(), event.actor.as_deref()))
+        .collect();
+    assert_eq!(
+        transitions,
+        [
+            ("capture.started", Some("capture_admin")),
+            ("capture.stopped", None),
+            ("capture.started", Some("capture_admin")),
+            ("capture.stopped", Some("capture_admin")),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_file_reconciliation() {
+    let server = TestServer::new().await;
+    let admin = server.login_as("reconcile_admin", UserRole::Admin).await;
+    let user = server.login_as("reconcile_user", UserRole::User).await;
+    let me = server.get("/auth/me").bearer(&user).send().await;
+    let files = server.state().file_manager.as_ref().unwrap();
+    let mut stored = Vec::new();
+    for name in ["kept.txt", "lost.txt"] {
+        let upload = core_lib::files::FileUpload {
+            original_filename: name.to_string(),
+            content_type: "text/plain".to_string(),
+            data: b"reconcile me".to_vec(),
+            uploaded_by: me.json()["id"].as_u64().unwrap(),
+            item_id: None,
+        };
+        stored.push(files.store_generated(upload).await.unwrap());
+    }
+    let lost_path: String = sqlx::query_scalar("SELECT path FROM files WHERE id = ?")
+        .bind(stored[1].id.to_string())
+        .fetch_one(&server.app.pool)
+        .await
+        .unwrap();
+    std::fs::remove_file(&lost_path).unwrap();
+
+    let old_orphan = files.storage_path().join("old-orphan.bin");
+    let recent_orphan = files.storage_path().join("recent-orphan.bin");
+    std::fs::write(&old_orphan, b"left behind").unwrap();
+    std::fs::write(&recent_orphan, b"mid-write").unwrap();
+    let two_days_ago = std::time::SystemTime::now() - Duration::from_secs(48 * 3600);
+    std::fs::File::options().write(true).open(&old_orphan).unwrap().set_modified(two_days_ago).unwrap();
+
+    let forbidden = server.post("/api/admin/files/reconcile").bearer(&user).send().await;
+    assert_eq!(forbidden.status, StatusCode::FORBIDDEN);
+    let direct = server
+        .post("/api/jobs")
+        .bearer(&admin)
+        .json(&json!({ "job_type": "FileReconciliation", "payload": {} }))
+        .send()
+        .await;
+    assert_eq!(direct.status, StatusCode::BAD_REQUEST, "{}", direct.text());
+
+    let dry_run = server.post("/api/admin/files/reconcile?dry_run=true").bearer(&admin).send().await;
+    assert_eq!(dry_run.status, StatusCode::OK, "{}", dry_run.text());
+    let report = &dry_run.json()["data"];
+    assert_eq!(report["dry_run"], true);
+    assert_eq!(report["orphaned_files"], 2);
+    assert_eq!(report["orphaned_files_deleted"], 1);
+    assert_eq!(report["orphaned_files_too_recent"], 1);
+    assert_eq!(report["rows_scanned"], 2);
+    assert_eq!(report["dangling_rows_marked"], 1);
+    assert_eq!(report["dangling_row_samples"][0], stored[1].id.to_string());
+    assert!(old_orphan.exists());
+    let lost = format!("/api/files/{}/serve", stored[1].id);
+    assert_ne!(server.get(&lost).send().await.status, StatusCode::GONE);
+
+    let run = server.post("/api/admin/files/reconcile").bearer(&admin).send().await;
+    assert_eq!(run.status, StatusCode::OK, "{}", run.text());
+    assert_eq!(run.json()["data"]["orphaned_files_deleted"], 1);
+    assert!(!old_orphan.exists());
+    assert!(recent_orphan.exists());
+    assert_eq!(server.get(&lost).send().await.status, StatusCode::GONE);
+    let kept = server.get(&format!("/api/files/{}/serve", stored[0].id)).send().await;
+    assert_eq!(kept.status, StatusCode::OK);
+
+    // Marked rows stay dangling but are not marked again.
+    let again = server.post("/api/admin/files/reconcile").bearer(&admin).send().await;
+    assert_eq!(again.json()["data"]["dangling_rows"], 1);
+    assert_eq!(again.json()["data"]["dangling_rows_marked"], 0);
+
+    let health = server.get("/health/files").send().await;
+    assert_eq!(health.json()["data"]["details"]["last_reconciliation"]["dry_run"], false);
+}
+
+#[tokio::test]
+async fn test_unknown_query_params_warn_by_default() {
+    let server = TestServer::new().await;
+
+    let typo = server.get("/api/items?page_sise=10&page=1&pge=2&pge=3").send().await;
+    assert_eq!(typo.status, StatusCode::OK, "{}", typo.text());
+    assert_eq!(typo.header("x-unknown-params"), Some("page_sise, pge"));
+
+    let known = server.get("/api/items?page_size=10&envelope=false").send().await;
+    assert_eq!(known.status, StatusCode::OK, "{}", known.text());
+    assert_eq!(known.header("x-unknown-params"), None);
+
+    // Parameters of either query type of the search export are accepted.
+    let export = server.post("/api/items/search/export?q=report&format=csv").send().await;
+    assert_eq!(export.status, StatusCode::OK, "{}", export.text());
+    assert_eq!(export.header("x-unknown-params"), None);
+}
+
+#[tokio::test]
+async fn test_unknown_query_params_rejected_when_configured() {
+    let server = TestServer::with_config(|config| {
+        config.validation.unknown_query_params = core_lib::config::UnknownParamsPolicy::Reject;
+    })
+    .await;
+
+    let typo = server.get("/api/items?page_sise=10&srot_order=asc&srot_order=desc").send().await;
+    assert_eq!(typo.status, StatusCode::BAD_REQUEST);
+    let error = typo.json()["error"].as_str().unwrap().to_string();
+    assert!(error.starts_with("Unknown query parameters"), "{}", error);
+    assert!(error.contains("\"page_sise\":[\"Unknown query parameter; did you mean page_size?\"]"), "{}", error);
+    assert!(error.contains("did you mean sort_order?"), "{}", error);
+
+    let search = server.get("/api/items/search?q=x&limt=5").send().await;
+    assert_eq!(search.status, StatusCode::BAD_REQUEST);
+    assert!(search.text().contains("did you mean limit?"), "{}", search.text());
+
+    let array = server.get("/api/items/export?tags[]=a&tag[]=b").send().await;
+    assert_eq!(array.status, StatusCode::BAD_REQUEST);
+    assert!(array.text().contains("\\\"tag\\\""), "{}", array.text());
+    assert!(!array.text().contains("\\\"tags\\\""), "{}", array.text());
+
+    let known = server.get("/api/items?page=1&page_size=5").send().await;
+    assert_eq!(known.status, StatusCode::OK, "{}", known.text());
+}
+
+#[tokio::test]
+async fn test_unverified_users_cannot_write_items() {
+    let server = TestServer::with_config(|config| config.notifications.enabled = true).await;
+    let token = server.login_as("unverified", UserRole::User).await;
+    let me = server.get("/auth/me").bearer(&token).send().await.json();
+    assert_eq!(me["email_verified"], false);
+
+    let blocked = server.post("/api/items").bearer(&token).json(&json!({"name": "Too soon"})).send().await;
+    assert_eq!(blocked.status, StatusCode::FORBIDDEN);
+    assert!(blocked.text().contains("Verify your email address"), "{}", blocked.text());
+    assert_eq!(server.get("/api/items").bearer(&token).send().await.status, StatusCode::OK);
+    let anonymous = server.post("/api/items").json(&json!({"name": "Anonymous"})).send().await;
+    assert_eq!(anonymous.status, StatusCode::CREATED, "{}", anonymous.text());
+
+    let job_queue = server.state().job_queue.clone().unwrap();
+    let mut sent = Vec::new();
+    for job in job_queue.list_jobs(Default::default()).await.unwrap().jobs {
+        let job = job_queue.get_job_status(job.id).await.unwrap().unwrap();
+        if job.payload["template_id"] == "email_verification" {
+            sent.push(job.payload);
+        }
+    }
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0]["recipient"], "unverified@example.com");
+    let verification_token = sent[0]["context"]["token"].as_str().unwrap();
+
+    let verify = |token: &str| server.post("/auth/verify-email").json(&json!({"token": token})).send();
+    assert_eq!(verify("0badc0de").await.status, StatusCode::BAD_REQUEST);
+    let verified = verify(verification_token).await;
+    assert_eq!(verified.status, StatusCode::OK, "{}", verified.text());
+    assert_eq!(verify(verification_token).await.status, StatusCode::BAD_REQUEST);
+
+    let resend = || server.post("/auth/verify-email/resend").json(&json!({"email": "someone@example.com"})).send();
+    assert_eq!(resend().await.status, StatusCode::ACCEPTED);
+    assert_eq!(resend().await.status, StatusCode::TOO_MANY_REQUESTS);
+
+    // The old token still carries the claim until it is replaced.
+    let stale = server.post("/api/items").bearer(&token).json(&json!({"name": "Stale"})).send().await;
+    assert_eq!(stale.status, StatusCode::FORBIDDEN);
+    let login = server
+        .post("/auth/login")
+        .json(&json!({"username_or_email": "unverified", "password": TEST_PASSWORD}))
+        .send()
+        .await;
+    assert_eq!(login.status, StatusCode::OK, "{}", login.text());
+    let fresh = login.json()["access_token"].as_str().unwrap().to_string();
+    let created = server.post("/api/items").bearer(&fresh).json(&json!({"name": "Verified"})).send().await;
+    assert_eq!(created.status, StatusCode::CREATED, "{}", created.text());
+
+    // Accounts created by an admin are verified unless asked otherwise.
+    let admin = server.login_as("verifier_admin", UserRole::Admin).await;
+    let invited = server
+        .post("/api/admin/users")
+        .bearer(&admin)
+        .json(&json!({"username": "invited", "email": "invited@example.com", "password": TEST_PASSWORD}))
+        .send()
+        .await;
+    assert_eq!(invited.status, StatusCode::CREATED, "{}", invited.text());
+    assert_eq!(invited.json()["data"]["email_verified"], true);
+    let user = server.login_as("plain_user", UserRole::User).await;
+    let refused = server
+        .post("/api/admin/users")
+        .bearer(&user)
+        .json(&json!({"username": "sneaky", "email": "sneaky@example.com", "password": TEST_PASSWORD}))
+        .send()
+        .await;
+    assert_eq!(refused.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_role_changes_need_recent_auth_and_keep_an_admin() {
+    let server = TestServer::new().await;
+    let admin = server.login_as("access_admin", UserRole::Admin).await;
+    let user = server.login_as("access_user", UserRole::User).await;
+    let admin_id = server.get("/auth/me").bearer(&admin).send().await.json()["id"].as_u64().unwrap();
+    let user_id = server.get("/auth/me").bearer(&user).send().await.json()["id"].as_u64().unwrap();
+    let user_uri = format!("/api/admin/users/{}", user_id);
+    let admin_uri = format!("/api/admin/users/{}", admin_id);
+
+    let mut admin_socket = server.websocket("/ws", Some(&admin)).await;
+    assert!(matches!(next_message(&mut admin_socket).await, WebSocketMessage::Authenticated { .. }));
+    assert!(matches!(next_message(&mut admin_socket).await, WebSocketMessage::Connected { .. }));
+
+    let promote = json!({"role": "admin"});
+    let refused = server.patch(&user_uri).bearer(&user).json(&promote).send().await;
+    assert_eq!(refused.status, StatusCode::FORBIDDEN);
+
+    server.app.clock.advance(Duration::from_secs(600));
+    let stale = server.patch(&user_uri).bearer(&admin).json(&promote).send().await;
+    assert_eq!(stale.status, StatusCode::FORBIDDEN);
+    assert!(stale.text().contains("re-authenticate"), "{}", stale.text());
+
+    let reauth = |password: &str| {
+        server.post("/auth/reauthenticate").bearer(&admin).json(&json!({"password": password})).send()
+    };
+    assert_eq!(reauth("wrong-password").await.status, StatusCode::UNAUTHORIZED);
+    let fresh = reauth(TEST_PASSWORD).await;
+    assert_eq!(fresh.status, StatusCode::OK, "{}", fresh.text());
+    let admin = fresh.json()["access_token"].as_str().unwrap().to_string();
+
+    let promoted = server.patch(&user_uri).bearer(&admin).json(&promote).send().await;
+    assert_eq!(promoted.status, StatusCode::OK, "{}", promoted.text());
+    assert_eq!(promoted.json()["data"]["role"], "admin");
+    match next_message(&mut admin_socket).await {
+        WebSocketMessage::RoleChanged { user_id: changed, username, old_role, new_role, changed_by } => {
+            assert_eq!(changed, user_id);
+            assert_eq!(username, "access_user");
+            assert_eq!((old_role.as_str(), new_role.as_str()), ("user", "admin"));
+            assert_eq!(changed_by, "access_admin");
+        }
+        other => panic!("expected RoleChanged, got {}", other.message_type()),
+    }
+    let audit = server.state().audit_log.recent(Some("users.role_changed"), 10);
+    assert_eq!(audit.len(), 1);
+    assert_eq!(audit[0].details["new_role"], "admin");
+    // Tokens minted for the old role stop working.
+    assert_eq!(server.get("/auth/me").bearer(&user).send().await.status, StatusCode::UNAUTHORIZED);
+
+    let deleted = server.delete(&user_uri).bearer(&admin).send().await;
+    assert_eq!(deleted.status, StatusCode::NO_CONTENT, "{}", deleted.text());
+    let demote_self = server.patch(&admin_uri).bearer(&admin).json(&json!({"role": "user"})).send().await;
+    assert_eq!(demote_self.status, StatusCode::CONFLICT, "{}", demote_self.text());
+    let deactivate_self = server.patch(&admin_uri).bearer(&admin).json(&json!({"is_active": false})).send().await;
+    assert_eq!(deactivate_self.status, StatusCode::CONFLICT);
+    assert_eq!(server.delete(&admin_uri).bearer(&admin).send().await.status, StatusCode::CONFLICT);
+    assert_eq!(server.delete("/api/admin/users/9999").bearer(&admin).send().await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_token_introspection_and_gateway_validation() {
+    let server = TestServer::with_config(|config| {
+        config.auth.introspection_clients = vec![core_lib::config::ServiceCredential {
+            name: "billing".to_string(),
+            key: "billing-secret".to_string(),
+        }];
+        config.auth.introspect_requests_per_minute = 5;
+        config.auth.validate_requests_per_minute = 4;
+    })
+    .await;
+    server.login_as("introspected", UserRole::User).await;
+    let login = server
+        .post("/auth/login")
+        .json(&json!({"username_or_email": "introspected", "password": TEST_PASSWORD}))
+        .send()
+        .await
+        .json();
+    let access = login["access_token"].as_str().unwrap().to_string();
+    let refresh = login["refresh_token"].as_str().unwrap().to_string();
+
+    let introspect = |key: Option<&str>, token: &str| {
+        let mut request = server
+            .post("/auth/introspect")
+            .body("application/x-www-form-urlencoded", format!("token={}&token_type_hint=access_token", token));
+        if let Some(key) = key {
+            request = request.header("x-api-key", key);
+        }
+        request.send()
+    };
+    assert_eq!(introspect(None, &access).await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(introspect(Some("guess"), &access).await.status, StatusCode::UNAUTHORIZED);
+
+    let active = introspect(Some("billing-secret"), &access).await;
+    assert_eq!(active.status, StatusCode::OK, "{}", active.text());
+    let active = active.json();
+    assert_eq!(active["active"], true);
+    assert_eq!(active["username"], "introspected");
+    assert_eq!(active["role"], "user");
+    assert_eq!(active["scope"], "read write");
+    assert_eq!(active["token_type"], "access");
+    assert!(active["exp"].as_u64().unwrap() > active["iat"].as_u64().unwrap());
+    let user_id = active["sub"].as_str().unwrap().to_string();
+    assert_eq!(introspect(Some("billing-secret"), &refresh).await.json()["token_type"], "refresh");
+    assert_eq!(introspect(Some("billing-secret"), "not-a-token").await.json(), json!({"active": false}));
+
+    let validated = server.get("/auth/validate").bearer(&access).send().await;
+    assert_eq!(validated.status, StatusCode::OK);
+    assert_eq!(validated.headers["x-user-id"], user_id.as_str());
+    assert_eq!(validated.headers["x-user-role"], "user");
+    assert_eq!(server.get("/auth/validate").send().await.status, StatusCode::UNAUTHORIZED);
+
+    let auth = server.state().auth_service.clone().unwrap();
+    auth.revoke_other_sessions(user_id.parse().unwrap(), None).await.unwrap();
+    assert_eq!(introspect(Some("billing-secret"), &access).await.json(), json!({"active": false}));
+    assert_eq!(server.get("/auth/validate").bearer(&access).send().await.status, StatusCode::UNAUTHORIZED);
+
+    // Each endpoint counts against its own limit, whatever the global one.
+    assert_eq!(introspect(Some("billing-secret"), &access).await.status, StatusCode::OK);
+    assert_eq!(introspect(Some("billing-secret"), &access).await.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(server.get("/auth/validate").send().await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(server.get("/auth/validate").send().await.status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn test_login_and_refresh_are_limited_per_address() {
+    let server = TestServer::with_config(|config| {
+        config.auth.login_requests_per_minute = 2;
+        config.auth.refresh_requests_per_minute = 2;
+    })
+    .await;
+    server.login_as("limited", UserRole::User).await;
+    let other: SocketAddr = "198.51.100.9:4000".parse().unwrap();
+    let login = |peer: Option<SocketAddr>| {
+        let mut request = server
+            .post("/auth/login")
+            .json(&json!({"username_or_email": "limited", "password": TEST_PASSWORD}));
+        if let Some(peer) = peer {
+            request = request.from_peer(peer);
+        }
+        request.send()
+    };
+
+    let first = login(None).await;
+    assert_eq!(first.status, StatusCode::OK, "{}", first.text());
+    assert_eq!(login(None).await.status, StatusCode::OK);
+    assert_eq!(login(None).await.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(login(Some(other)).await.status, StatusCode::OK);
+
+    let refresh = |token: &str| server.post("/auth/refresh").json(&json!({"refresh_token": token})).send();
+    let rotated = refresh(first.json()["refresh_token"].as_str().unwrap()).await;
+    assert_eq!(rotated.status, StatusCode::OK, "{}", rotated.text());
+    let next = rotated.json()["refresh_token"].as_str().unwrap().to_string();
+    assert_ne!(next, first.json()["refresh_token"].as_str().unwrap());
+    assert_eq!(refresh(&next).await.status, StatusCode::OK);
+    assert_eq!(refresh(&next).await.status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn test_item_comments() {
+    use futures_util::SinkExt;
+
+    let server = TestServer::new().await;
+    let admin = server.login_as("comment_admin", UserRole::Admin).await;
+    let alice = server.login_as("comment_alice", UserRole::User).await;
+    let bob = server.login_as("comment_bob", UserRole::User).await;
+    let reader = server.login_as("comment_reader", UserRole::ReadOnly).await;
+
+    let create_item = |name: &'static str| server.post("/api/items").bearer(&alice).json(&json!({"name": name})).send();
+    let item_id = create_item("Discussed").await.json()["data"]["id"].as_u64().unwrap();
+    let other_id = create_item("Quiet").await.json()["data"]["id"].as_u64().unwrap();
+    let comments_uri = format!("/api/items/{}/comments", item_id);
+    let other_uri = format!("/api/items/{}/comments", other_id);
+
+    let mut watchers = Vec::new();
+    for watched in [item_id, other_id] {
+        let mut socket = server.websocket("/ws", Some(&bob)).await;
+        assert!(matches!(next_message(&mut socket).await, WebSocketMessage::Authenticated { .. }));
+        assert!(matches!(next_message(&mut socket).await, WebSocketMessage::Connected { .. }));
+        let subscribe = json!({"type": "Subscribe", "data": {"version": 7, "topics": [format!("item:{}", watched)]}});
+        socket.send(Message::Text(subscribe.to_string())).await.unwrap();
+        // Item events from before the subscription may still be on their way.
+        while !matches!(next_message(&mut socket).await, WebSocketMessage::Subscribed { version: 7 }) {}
+        watchers.push(socket);
+    }
+
+    let comment = |token: Option<&str>, body: &str| {
+        let request = server.post(&comments_uri).json(&json!({"body": body}));
+        match token {
+            Some(token) => request.bearer(token).send(),
+            None => request.send(),
+        }
+    };
+    assert_eq!(comment(None, "Hello").await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(comment(Some(&reader), "Hello").await.status, StatusCode::FORBIDDEN);
+    assert_eq!(comment(Some(&alice), "   ").await.status, StatusCode::BAD_REQUEST);
+    assert_eq!(comment(Some(&alice), "<script>alert(1)</script>").await.status, StatusCode::BAD_REQUEST);
+    assert_eq!(comment(Some(&alice), &"x".repeat(2001)).await.status, StatusCode::BAD_REQUEST);
+    let missing = server.post("/api/items/9999/comments").bearer(&alice).json(&json!({"body": "Hi"})).send().await;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+
+    let first = comment(Some(&alice), "First!").await;
+    assert_eq!(first.status, StatusCode::CREATED, "{}", first.text());
+    let first = first.json()["data"].clone();
+    assert_eq!(first["author"], "comment_alice");
+    assert_eq!(first["item_id"], item_id);
+    match next_message(&mut watchers[0]).await {
+        WebSocketMessage::ItemCommentAdded(added) => assert_eq!(added.body, "First!"),
+        other => panic!("expected ItemCommentAdded, got {}", other.message_type()),
+    }
+    let second = comment(Some(&bob), "Second").await.json()["data"].clone();
+    let on_other = server.post(&other_uri).bearer(&bob).json(&json!({"body": "Elsewhere"})).send().await;
+    assert_eq!(on_other.status, StatusCode::CREATED);
+    // The other item's watcher only hears about its own item.
+    match next_message(&mut watchers[1]).await {
+        WebSocketMessage::ItemCommentAdded(added) => assert_eq!(added.item_id, other_id),
+        other => panic!("expected ItemCommentAdded, got {}", other.message_type()),
+    }
+
+    let page = server.get(&format!("{}?page=2&page_size=1", comments_uri)).send().await.json();
+    assert_eq!(page["data"]["total"], 2);
+    assert_eq!(page["data"]["comments"][0]["body"], "Second");
+
+    let first_uri = format!("{}/{}", comments_uri, first["id"]);
+    let edit = |token: &str, body: &str| server.patch(&first_uri).bearer(token).json(&json!({"body": body})).send();
+    assert_eq!(edit(&bob, "Hijacked").await.status, StatusCode::FORBIDDEN);
+    let edited = edit(&alice, "First, edited").await;
+    assert_eq!(edited.status, StatusCode::OK, "{}", edited.text());
+    assert_eq!(edited.json()["data"]["body"], "First, edited");
+    assert!(edited.json()["data"]["edited_at"].is_string());
+    let wrong_item = format!("{}/{}", other_uri, first["id"]);
+    assert_eq!(server.delete(&wrong_item).bearer(&alice).send().await.status, StatusCode::NOT_FOUND);
+
+    let listed = server.get("/api/items?include=comment_counts").send().await;
+    assert_eq!(listed.status, StatusCode::OK, "{}", listed.text());
+    let counts: std::collections::HashMap<u64, u64> = listed.json()["data"]["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| (item["id"].as_u64().unwrap(), item["comment_count"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(counts[&item_id], 2);
+    assert_eq!(counts[&other_id], 1);
+    assert!(server.get("/api/items").send().await.json()["data"]["items"][0].get("comment_count").is_none());
+    assert_eq!(server.get("/api/items?include=everything").send().await.status, StatusCode::BAD_REQUEST);
+
+    assert_eq!(server.delete(&first_uri).bearer(&bob).send().await.status, StatusCode::FORBIDDEN);
+    assert_eq!(server.delete(&first_uri).bearer(&alice).send().await.status, StatusCode::NO_CONTENT);
+    let second_uri = format!("{}/{}", comments_uri, second["id"]);
+    assert_eq!(server.delete(&second_uri).bearer(&admin).send().await.status, StatusCode::NO_CONTENT);
+    let audit = server.state().audit_log.recent(Some("item.comment_removed"), 10);
+    assert_eq!(audit.len(), 1, "only removing someone else's comment is audited");
+    assert_eq!(audit[0].details["author"], "comment_bob");
+
+    server.delete(&format!("/api/items/{}", other_id)).bearer(&alice).send().await;
+    assert_eq!(server.get(&other_uri).send().await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_item_metadata_schema() {
+    use core_lib::config::{MetadataFieldSchema, MetadataFieldType};
+
+    let field = |field_type, required, values: &[&str]| MetadataFieldSchema {
+        field_type,
+        required,
+        values: values.iter().map(|value| value.to_string()).collect(),
+        description: None,
+    };
+    let department = field(MetadataFieldType::Enum, true, &["sales", "support"]);
+    let server = TestServer::with_config(|config| {
+        config.item_schema.fields.insert("department".to_string(), department.clone());
+        config.item_schema.fields.insert("due".to_string(), field(MetadataFieldType::Date, false, &[]));
+    })
+    .await;
+    let admin = server.login_as("schema_admin", UserRole::Admin).await;
+
+    let schema = server.get("/api/items/schema").send().await;
+    assert_eq!(schema.status, StatusCode::OK);
+    assert_eq!(schema.json()["data"]["fields"]["department"]["type"], "enum");
+    assert_eq!(schema.json()["data"]["fields"]["department"]["values"], json!(["sales", "support"]));
+    assert_eq!(schema.json()["data"]["fields"]["due"]["required"], false);
+
+    let create = |body: serde_json::Value| server.post("/api/items").json(&body).send();
+    let missing = create(json!({"name": "No department"})).await;
+    assert_eq!(missing.status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        missing.json()["error"],
+        r#"Metadata does not match the item schema: {"metadata.department":["is required"]}"#
+    );
+    let wrong = create(json!({"name": "Legal", "metadata": {"department": "legal", "due": "soon"}})).await;
+    assert_eq!(wrong.status, StatusCode::BAD_REQUEST);
+    assert!(wrong.text().contains("must be one of sales, support"), "{}", wrong.text());
+    assert!(wrong.text().contains("metadata.due"), "{}", wrong.text());
+
+    let created = create(json!({"name": "Sales item", "metadata": {"department": "sales", "due": "2024-03-01"}})).await;
+    assert_eq!(created.status, StatusCode::CREATED, "{}", created.text());
+    let uri = format!("/api/items/{}", created.json()["data"]["id"]);
+
+    let put = server.put(&uri).json(&json!({"name": "Sales item", "metadata": {"due": "2024-03-01"}})).send().await;
+    assert_eq!(put.status, StatusCode::BAD_REQUEST);
+    let patch = |body: serde_json::Value| server.patch(&uri).json(&body).send();
+    assert_eq!(patch(json!({"metadata": {"department": "legal"}})).await.status, StatusCode::BAD_REQUEST);
+    assert_eq!(patch(json!({"metadata": null})).await.status, StatusCode::BAD_REQUEST);
+    let renamed = patch(json!({"name": "Renamed"})).await;
+    assert_eq!(renamed.status, StatusCode::OK, "{}", renamed.text());
+
+    // A reloaded schema applies to new writes only: the existing item is
+    // still served, and a patch leaving its metadata alone still succeeds.
+    let mut reloaded = server.state().item_schema.current().as_ref().clone();
+    reloaded.fields.insert("cost".to_string(), field(MetadataFieldType::Number, true, &[]));
+    server.state().item_schema.update_config(reloaded);
+    assert_eq!(server.get(&uri).send().await.status, StatusCode::OK);
+    assert_eq!(patch(json!({"description": "Still editable"})).await.status, StatusCode::OK);
+    let costless = create(json!({"name": "Costless", "metadata": {"department": "support"}})).await;
+    assert_eq!(costless.status, StatusCode::BAD_REQUEST);
+    assert!(costless.json()["error"].as_str().unwrap().ends_with(r#"{"metadata.cost":["is required"]}"#));
+    let costed = create(json!({"name": "Costed", "metadata": {"department": "support", "cost": 12}})).await;
+    assert_eq!(costed.status, StatusCode::CREATED, "{}", costed.text());
+
+    let anonymous = server.post("/api/admin/items/schema/validate").send().await;
+    assert_eq!(anonymous.status, StatusCode::UNAUTHORIZED);
+    let accepted = server.post("/api/admin/items/schema/validate").bearer(&admin).send().await;
+    assert_eq!(accepted.status, StatusCode::ACCEPTED, "{}", accepted.text());
+    let job_id = accepted.json()["data"]["job_id"].as_str().unwrap().parse().unwrap();
+
+    let job_queue = server.state().job_queue.as_ref().unwrap();
+    let mut job = job_queue.get_job_status(job_id).await.unwrap().unwrap();
+    for _ in 0..100 {
+        if matches!(job.status, JobStatus::Completed | JobStatus::Failed) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        job = job_queue.get_job_status(job_id).await.unwrap().unwrap();
+    }
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error_message);
+    let report = job.result.unwrap();
+    let listed = report["items"].as_array().unwrap();
+    assert_eq!(report["nonconforming"], listed.len());
+    assert!(report["checked"].as_u64().unwrap() > report["nonconforming"].as_u64().unwrap());
+    assert!(listed.iter().all(|item| item["name"] != "Costed"));
+    let renamed = listed.iter().find(|item| item["name"] == "Renamed").unwrap();
+    assert_eq!(renamed["errors"], json!({"metadata.cost": ["is required"]}));
+}
+
+#[tokio::test]
+async fn test_item_metadata_transform_job() {
+    let server = TestServer::new().await;
+    let admin = server.login_as("metadata_admin", UserRole::Admin).await;
+    let user = server.login_as("metadata_user", UserRole::User).await;
+    let created = server
+        .post("/api/items")
+        .bearer(&user)
+        .json(&json!({"name": "Lamp", "tags": ["lighting"], "metadata": {"colour": "grey"}}))
+        .send()
+        .await;
+    assert_eq!(created.status, StatusCode::CREATED, "{}", created.text());
+    let uri = format!("/api/items/{}", created.json()["data"]["id"]);
+    assert_eq!(server.get(&uri).send().await.json()["data"]["metadata"], json!({"colour": "grey"}));
+
+    let transform = |dry_run: bool| {
+        json!({
+            "operations": [
+                {"op": "rename_key", "from": "colour", "to": "color"},
+                {"op": "map_values", "key": "color", "values": {"grey": "gray"}}
+            ],
+            "filter": {"tags": ["lighting"]},
+            "dry_run": dry_run
+        })
+    };
+    let forbidden = server.post("/api/admin/items/metadata/transform").bearer(&user).json(&transform(true)).send().await;
+    assert_eq!(forbidden.status, StatusCode::FORBIDDEN);
+    let invalid = server
+        .post("/api/admin/items/metadata/transform")
+        .bearer(&admin)
+        .json(&json!({"operations": [{"op": "delete_key", "key": "secret"}]}))
+        .send()
+        .await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST, "{}", invalid.text());
+    let direct = server
+        .post("/api/jobs")
+        .bearer(&admin)
+        .json(&json!({"job_type": "MetadataTransform", "payload": {}}))
+        .send()
+        .await;
+    assert_eq!(direct.status, StatusCode::BAD_REQUEST);
+
+    let preview = run_metadata_transform(&server, &admin, transform(true)).await;
+    assert_eq!(preview["changed"], 1);
+    assert_eq!(preview["samples"][0]["before"], json!({"colour": "grey"}));
+    assert_eq!(preview["samples"][0]["after"], json!({"color": "gray"}));
+    assert_eq!(server.get(&uri).send().await.json()["data"]["metadata"], json!({"colour": "grey"}));
+
+    let report = run_metadata_transform(&server, &admin, transform(false)).await;
+    assert_eq!((report["matched"].as_u64(), report["changed"].as_u64()), (Some(1), Some(1)));
+    let item = server.get(&uri).send().await.json();
+    assert_eq!(item["data"]["metadata"], json!({"color": "gray"}));
+    assert_eq!(item["data"]["version"], 2);
+}
+
+async fn run_metadata_transform(server: &TestServer, admin: &str, body: serde_json::Value) -> serde_json::Value {
+    let accepted = server.post("/api/admin/items/metadata/transform").bearer(admin).json(&body).send().await;
+    assert_eq!(accepted.status, StatusCode::ACCEPTED, "{}", accepted.text());
+    let job_id = accepted.json()["data"]["job_id"].as_str().unwrap().parse().unwrap();
+
+    let job_queue = server.state().job_queue.as_ref().unwrap();
+    let mut job = job_queue.get_job_status(job_id).await.unwrap().unwrap();
+    for _ in 0..100 {
+        if matches!(job.status, JobStatus::Completed | JobStatus::Failed) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        job = job_queue.get_job_status(job_id).await.unwrap().unwrap();
+    }
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error_message);
+    job.result.unwrap()
+}
+
+#[tokio::test]
+async fn test_unique_item_names() {
+    use core_lib::config::NameScope;
+
+    let server = TestServer::with_config(|config| config.items.unique_names = NameScope::Global).await;
+    let admin = server.login_as("names_admin", UserRole::Admin).await;
+    let create = |name: &str| server.post("/api/items").json(&json!({"name": name})).send();
+
+    let lamp = create(" Desk lamp ").await;
+    assert_eq!(lamp.status, StatusCode::CREATED, "{}", lamp.text());
+    assert_eq!(lamp.json()["data"]["name"], "Desk lamp");
+    let lamp_id = lamp.json()["data"]["id"].as_u64().unwrap();
+
+    let taken = create("DESK LAMP").await;
+    assert_eq!(taken.status, StatusCode::CONFLICT);
+    assert_eq!(taken.json()["conflicting_id"], lamp_id);
+
+    let chair = create("Chair").await;
+    let uri = format!("/api/items/{}", chair.json()["data"]["id"]);
+    let renamed = server.patch(&uri).json(&json!({"name": "desk lamp"})).send().await;
+    assert_eq!(renamed.status, StatusCode::CONFLICT);
+    assert_eq!(renamed.json()["conflicting_id"], lamp_id);
+    let replaced = server.put(&uri).json(&json!({"name": "Desk lamp"})).send().await;
+    assert_eq!(replaced.status, StatusCode::CONFLICT);
+
+    let submit = || {
+        server
+            .post("/api/form")
+            .body("application/x-www-form-urlencoded", "name=Ada&email=ada%40example.com")
+            .send()
+    };
+    let first = submit().await;
+    assert_eq!(first.status, StatusCode::OK, "{}", first.text());
+    let again = submit().await;
+    assert_eq!(again.status, StatusCode::CONFLICT);
+    assert_eq!(again.json()["conflicting_id"], first.json()["data"]["created_item"]["id"]);
+
+    let accepted = server.post("/api/admin/items/names/validate").bearer(&admin).send().await;
+    assert_eq!(accepted.status, StatusCode::ACCEPTED, "{}", accepted.text());
+    let job_id = accepted.json()["data"]["job_id"].as_str().unwrap().parse().unwrap();
+    let job_queue = server.state().job_queue.as_ref().unwrap();
+    let mut job = job_queue.get_job_status(job_id).await.unwrap().unwrap();
+    for _ in 0..100 {
+        if matches!(job.status, JobStatus::Completed | JobStatus::Failed) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        job = job_queue.get_job_status(job_id).await.unwrap().unwrap();
+    }
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error_message);
+    assert_eq!(job.result.unwrap()["duplicate_names"], 0);
+}
+
+/// A side effect added without touching any handler.
+struct Recorder {
+    seen: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    fail: bool,
+}
+
+#[async_trait::async_trait]
+impl EventSubscriber for Recorder {
+    fn name(&self) -> &str {
+        if self.fail { "failing" } else { "recorder" }
+    }
+
+    async fn handle(&self, events: &[DomainEvent]) -> core_lib::error::Result<()> {
+        if self.fail {
+            return Err(AppError::ServiceUnavailable("down".to_string()));
+        }
+        let mut seen = self.seen.lock().unwrap();
+        seen.extend(events.iter().map(|event| format!("{} {}", event.name(), event.entity())));
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_new_event_subscribers_see_every_write() {
+    let server = TestServer::new().await;
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    server.state().events.subscribe(Recorder { seen: seen.clone(), fail: false });
+    server.state().events.subscribe(Recorder { seen: seen.clone(), fail: true });
+
+    let created = server.post("/api/items").json(&json!({"name": "Observed"})).send().await;
+    assert_eq!(created.status, StatusCode::CREATED, "{}", created.text());
+    let id = created.json()["data"]["id"].as_u64().unwrap();
+    let updated = server.put(&format!("/api/items/{}", id)).json(&json!({"name": "Renamed"})).send().await;
+    assert_eq!(updated.status, StatusCode::OK, "{}", updated.text());
+    let deleted = server.delete(&format!("/api/items/{}", id)).send().await;
+    assert_eq!(deleted.status, StatusCode::NO_CONTENT);
+    let form = server
+        .post("/api/form")
+        .body("application/x-www-form-urlencoded", "name=Visitor&email=visitor%40example.com")
+        .send()
+        .await;
+    assert_eq!(form.status, StatusCode::OK, "{}", form.text());
+    let form_id = form.json()["data"]["created_item"]["id"].as_u64().unwrap();
+
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            format!("item.created item:{}", id),
+            format!("item.updated item:{}", id),
+            format!("item.deleted item:{}", id),
+            format!("item.created item:{}", form_id),
+        ]
+    );
+
+    // The failing subscriber failed the writes of no one, and is counted.
+    let admin = server.login_as("events_admin", UserRole::Admin).await;
+    let stats = server.get("/api/admin/events").bearer(&admin).send().await;
+    assert_eq!(stats.status, StatusCode::OK, "{}", stats.text());
+    let subscribers = stats.json()["data"]["subscribers"].as_array().unwrap().clone();
+    let counts = |name: &str| {
+        let stats = subscribers.iter().find(|s| s["name"] == name).unwrap();
+        (stats["delivered"].as_u64().unwrap(), stats["failed"].as_u64().unwrap())
+    };
+    assert_eq!(counts("recorder"), (4, 0));
+    assert_eq!(counts("failing"), (0, 4));
+    assert_eq!(counts("cache").1, 0);
+    let deletions = server.state().audit_log.recent(Some("item.deleted"), 10);
+    assert_eq!(deletions[0].target.as_deref(), Some(format!("item:{}", id).as_str()));
+}
+
+#[tokio::test]
+async fn test_integrity_check_repairs_broken_references() {
+    let server = TestServer::new().await;
+    let admin = server.login_as("integrity_admin", UserRole::Admin).await;
+
+    // The startup check found nothing.
+    let health = server.get("/health/integrity").send().await;
+    assert_eq!(health.json()["data"]["status"], "Healthy", "{}", health.text());
+    assert_eq!(health.json()["data"]["details"]["last_check"]["broken"], 0);
+
+    let pool = server.state().db_manager.as_ref().unwrap().pool().clone();
+    for sql in [
+        "PRAGMA foreign_keys = OFF",
+        "INSERT INTO item_comments (item_id, author, body, created_at) VALUES (404, 'ghost', 'Lost', '2024-01-01T00:00:00Z')",
+        "PRAGMA foreign_keys = ON",
+    ] {
+        sqlx::query(sql).execute(&pool).await.unwrap();
+    }
+
+    let user = server.login_as("integrity_user", UserRole::User).await;
+    let refused = server.post("/api/admin/integrity/check").bearer(&user).send().await;
+    assert_eq!(refused.status, StatusCode::FORBIDDEN);
+
+    let accepted = server.post("/api/admin/integrity/check?repair=true").bearer(&admin).send().await;
+    assert_eq!(accepted.status, StatusCode::ACCEPTED, "{}", accepted.text());
+    let job_id = accepted.json()["data"]["job_id"].as_str().unwrap().parse().unwrap();
+    let job_queue = server.state().job_queue.as_ref().unwrap();
+    let mut job = job_queue.get_job_status(job_id).await.unwrap().unwrap();
+    for _ in 0..100 {
+        if matches!(job.status, JobStatus::Completed | JobStatus::Failed) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        job = job_queue.get_job_status(job_id).await.unwrap().unwrap();
+    }
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error_message);
+    let report = job.result.unwrap();
+    assert_eq!((report["broken"].as_u64(), report["repaired"].as_u64()), (Some(1), Some(1)));
+
+    let comments: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM item_comments").fetch_one(&pool).await.unwrap();
+    assert_eq!(comments, 0);
+    assert_eq!(server.state().audit_log.recent(Some("integrity.repaired"), 10).len(), 1);
+
+    let health = server.get("/health/integrity").send().await;
+    assert_eq!(health.json()["data"]["status"], "Healthy");
+    assert!(health.json()["data"]["message"].as_str().unwrap().contains("found 1 broken references and repaired 1"));
+}
+
+#[tokio::test]
+async fn test_file_content_search() {
+    let server = TestServer::new().await;
+    let admin = server.login_as("contracts_admin", UserRole::Admin).await;
+    let agreement = server
+        .state()
+        .item_service
+        .create_item("Supplier agreement".to_string(), None, vec![], None)
+        .await
+        .unwrap()
+        .id;
+
+    let contract = format!("/api/files/upload?item_id={}", agreement);
+    let contract = upload(&server, &admin, &contract, "contract.txt", "text/plain", "Termination is governed by clause 7.2 of this agreement.").await;
+    let prices = upload(&server, &admin, "/api/files/upload", "prices.csv", "text/csv", "sku,price\nwidget,7").await;
+    let broken = upload(&server, &admin, "/api/files/upload", "broken.json", "application/json", "{\"clause\": ").await;
+    assert_eq!(contract["content_index"], "pending");
+
+    // Extraction runs as a job; wait for each file to leave `pending`.
+    let file_manager = server.state().file_manager.as_ref().unwrap();
+    let mut statuses = Vec::new();
+    for file in [&contract, &prices, &broken] {
+        let id = file["id"].as_str().unwrap().parse().unwrap();
+        let mut status = None;
+        for _ in 0..250 {
+            status = file_manager.get_file_metadata(id).await.unwrap().unwrap().content_index;
+            if status != Some(ContentIndexStatus::Pending) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        statuses.push(status);
+    }
+    assert_eq!(
+        statuses,
+        vec![Some(ContentIndexStatus::Indexed), Some(ContentIndexStatus::Indexed), Some(ContentIndexStatus::NotIndexed)]
+    );
+
+    let found = server.get("/api/files?search_content=true&q=%22clause%207.2%22").send().await;
+    assert_eq!(found.status, StatusCode::OK, "{}", found.text());
+    assert_eq!(found.json()["total"], 1);
+    let hit = &found.json()["files"][0];
+    assert_eq!(hit["id"], contract["id"]);
+    assert_eq!(hit["snippet"], "Termination is governed by [clause 7.2] of this agreement.");
+
+    let invalid = server.get("/api/files?search_content=true&q=name:clause").send().await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+    assert_eq!(server.get("/api/files?search_content=true").send().await.status, StatusCode::BAD_REQUEST);
+
+    // `content:` finds items through their files.
+    assert_eq!(search_ids(&server, "content:%22clause%207.2%22").await, vec![agreement]);
+    let files = server
+        .get("/api/search?q=content:widget&entities=files")
+        .bearer(&admin)
+        .send()
+        .await;
+    assert_eq!(files.json()["data"]["counts"], json!({"files": 1}));
+    assert_eq!(files.json()["data"]["results"][0]["id"], prices["id"]);
+
+    let deleted = server
+        .delete(&format!("/api/files/{}", contract["id"].as_str().unwrap()))
+        .bearer(&admin)
+        .send()
+        .await;
+    assert_eq!(deleted.status, StatusCode::NO_CONTENT);
+    let gone = server.get("/api/files?search_content=true&q=clause").send().await;
+    assert_eq!(gone.json()["total"], 0);
+
+    let reindex = server.post("/api/admin/files/reindex").bearer(&admin).send().await;
+    assert_eq!(reindex.status, StatusCode::ACCEPTED, "{}", reindex.text());
+    let job_id = reindex.json()["data"]["job_id"].as_str().unwrap().parse().unwrap();
+    let job_queue = server.state().job_queue.as_ref().unwrap();
+    let mut job = job_queue.get_job_status(job_id).await.unwrap().unwrap();
+    for _ in 0..250 {
+        if job.status == JobStatus::Completed || job.status == JobStatus::Failed {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        job = job_queue.get_job_status(job_id).await.unwrap().unwrap();
+    }
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error_message);
+    assert_eq!(job.result.unwrap(), json!({"files_scanned": 2, "indexed": 1, "not_indexed": 1}));
+}
+
+#[tokio::test]
+async fn test_seed_fixtures() {
+    let server = TestServer::new().await;
+    let admin = server.login_as("seed_admin", UserRole::Admin).await;
+    let fixtures = json!({
+        "users": [{"username": "fixture_viewer", "email": "viewer@example.com", "password": "Fixture-Passw0rd", "role": "readonly"}],
+        "items": [
+            {"id": 500, "name": "Fixture lamp", "tags": ["lighting"], "created_at": "2024-03-01T12:00:00Z"},
+            {"id": 501, "name": "Fixture desk", "metadata": {"stock": 3}}
+        ],
+        "files": [{"filename": "lamp-manual.txt", "content_type": "text/plain", "content": "Replace the bulb yearly.", "item_id": 500, "uploaded_by": "fixture_viewer"}],
+        "jobs": [{"id": "00000000-0000-0000-0000-0000000005ee", "job_type": "BulkExport", "status": "Completed", "result": {"exported_items": 2}}]
+    });
+
+    let viewer = server.login_as("seed_viewer", UserRole::ReadOnly).await;
+    let refused = server.post("/api/admin/seed").bearer(&viewer).json(&json!({"fixtures": fixtures})).send().await;
+    assert_eq!(refused.status, StatusCode::FORBIDDEN);
+
+    let seeded = server.post("/api/admin/seed").bearer(&admin).json(&json!({"fixtures": fixtures})).send().await;
+    assert_eq!(seeded.status, StatusCode::CREATED, "{}", seeded.text());
+    assert_eq!(seeded.json()["data"]["created"], json!({"users": 1, "items": 2, "files": 1, "jobs": 1}));
+
+    let lamp = server.state().item_service.get_item(500).await.unwrap();
+    assert_eq!(lamp.name, "Fixture lamp");
+    assert_eq!(lamp.created_at.to_rfc3339(), "2024-03-01T12:00:00+00:00");
+    assert_eq!(search_ids(&server, "content:bulb").await, vec![500]);
+    let job = server.state().job_queue.as_ref().unwrap().get_job_status(uuid::Uuid::from_u128(0x5ee)).await.unwrap().unwrap();
+    assert_eq!(job.status, JobStatus::Completed);
+    assert_eq!(job.result, Some(json!({"exported_items": 2})));
+
+    let again = server.post("/api/admin/seed").bearer(&admin).json(&json!({"fixtures": fixtures})).send().await;
+    assert_eq!(again.status, StatusCode::CONFLICT, "{}", again.text());
+    let idempotent = server
+        .post("/api/admin/seed")
+        .bearer(&admin)
+        .json(&json!({"fixtures": fixtures, "idempotent": true}))
+        .send()
+        .await;
+    assert_eq!(idempotent.status, StatusCode::CREATED, "{}", idempotent.text());
+    assert_eq!(idempotent.json()["data"]["created"], json!({"users": 0, "items": 0, "files": 0, "jobs": 0}));
+    assert_eq!(idempotent.json()["data"]["skipped"], json!({"users": 1, "items": 2, "files": 1, "jobs": 1}));
+
+    // A fixture failing late leaves none of the earlier ones behind.
+    let broken = json!({
+        "users": [{"username": "fixture_editor", "email": "editor@example.com", "password": "Fixture-Passw0rd", "role": "user"}],
+        "items": [{"id": 502, "name": "Fixture shelf"}],
+        "files": [{"filename": "shelf.txt", "content_type": "text/plain", "content": "Oak.", "item_id": 502, "uploaded_by": "nobody"}]
+    });
+    let failed = server.post("/api/admin/seed").bearer(&admin).json(&json!({"fixtures": broken})).send().await;
+    assert_eq!(failed.status, StatusCode::BAD_REQUEST, "{}", failed.text());
+    assert!(server.state().item_service.get_item(502).await.is_err());
+    let auth = server.state().auth_service.as_ref().unwrap();
+    assert!(auth.get_user_by_username("fixture_editor").await.unwrap().is_none());
+
+    let pending = json!({"jobs": [{"id": "00000000-0000-0000-0000-0000000005ef", "job_type": "BulkExport", "status": "Pending"}]});
+    let pending = server.post("/api/admin/seed").bearer(&admin).json(&json!({"fixtures": pending})).send().await;
+    assert_eq!(pending.status, StatusCode::BAD_REQUEST);
+
+    let builtin = server.post("/api/admin/seed").bearer(&admin).send().await;
+    assert_eq!(builtin.status, StatusCode::CREATED, "{}", builtin.text());
+    assert_eq!(builtin.json()["data"]["created"], json!({"users": 6, "items": 12, "files": 4, "jobs": 4}));
+}
+
+#[tokio::test]
+async fn test_admin_page_with_cookie_session() {
+    let server = TestServer::new().await;
+    let admin = server.login_as("page_admin", UserRole::Admin).await;
+    server.login_as("page_user", UserRole::User).await;
+
+    let anonymous = server.get("/admin").send().await;
+    assert!(anonymous.status.is_redirection());
+    assert_eq!(anonymous.header("location"), Some("/admin/login"));
+    let login_page = server.get("/admin/login").send().await;
+    assert_eq!(login_page.status, StatusCode::OK);
+    assert!(!login_page.header("content-security-policy").unwrap().contains("unsafe-inline"));
+
+    let sign_in = |username: &str| {
+        server
+            .post("/admin/session")
+            .json(&json!({"username": username, "password": TEST_PASSWORD}))
+            .send()
+    };
+    assert_eq!(sign_in("page_user").await.status, StatusCode::FORBIDDEN);
+    let signed_in = sign_in("page_admin").await;
+    assert_eq!(signed_in.status, StatusCode::OK, "{}", signed_in.text());
+    let session_cookie = signed_in.header("set-cookie").unwrap().split(';').next().unwrap().to_string();
+    assert!(session_cookie.starts_with("admin_session="));
+    assert!(signed_in.header("set-cookie").unwrap().contains("HttpOnly"));
+
+    let page = server.get("/admin").header("cookie", &session_cookie).send().await;
+    assert_eq!(page.status, StatusCode::OK, "{}", page.text());
+    assert!(page.text().contains("Signed in as page_admin"));
+    assert!(page.text().contains(r#"<section id="jobs" class="panel">"#));
+    assert!(!page.text().contains("onclick"));
+    let csp = page.header("content-security-policy").unwrap();
+    assert!(csp.contains("script-src 'self';"), "{}", csp);
+    assert_eq!(server.get("/admin/assets/admin.js").send().await.status, StatusCode::OK);
+    assert_eq!(server.get("/admin/assets/missing.js").send().await.status, StatusCode::NOT_FOUND);
+
+    let created = server.post("/api/items").bearer(&admin).json(&json!({"name": "Page lamp"})).send().await;
+    assert_eq!(created.status, StatusCode::CREATED, "{}", created.text());
+    let id = created.json()["data"]["id"].as_u64().unwrap();
+    let listed = server.get("/api/items").header("cookie", &session_cookie).send().await;
+    assert_eq!(listed.status, StatusCode::OK);
+    assert_eq!(listed.cache_status(), Some("BYPASS"));
+
+    let item_uri = format!("/api/items/{}", id);
+    let forged = server.delete(&item_uri).header("cookie", &session_cookie).send().await;
+    assert_eq!(forged.status, StatusCode::FORBIDDEN);
+    assert!(forged.text().contains("CSRF"), "{}", forged.text());
+
+    let issued = server.get("/admin/csrf-token").send().await;
+    let token = issued.json()["data"]["token"].as_str().unwrap().to_string();
+    let cookies = format!("{}; csrf_token={}", session_cookie, token);
+    let deleted = server.delete(&item_uri).header("cookie", &cookies).header("x-csrf-token", &token).send().await;
+    assert!(deleted.status.is_success(), "{}", deleted.text());
+
+    let trash = server.get("/api/items/trash?limit=10").header("cookie", &session_cookie).send().await;
+    assert_eq!(trash.status, StatusCode::OK, "{}", trash.text());
+    assert_eq!(trash.json()["data"]["items"][0]["id"], id);
+    assert!(trash.json()["data"]["items"][0]["deleted_at"].is_string());
+    let restore_uri = format!("/api/items/trash/{}/restore", id);
+    let restored = server.post(&restore_uri).header("cookie", &cookies).header("x-csrf-token", &token).send().await;
+    assert_eq!(restored.status, StatusCode::OK, "{}", restored.text());
+    assert_eq!(server.state().item_service.get_item(id).await.unwrap().name, "Page lamp");
+    let again = server.post(&restore_uri).header("cookie", &cookies).header("x-csrf-token", &token).send().await;
+    assert_eq!(again.status, StatusCode::NOT_FOUND);
+
+    let users = server.get("/api/admin/users?limit=10").header("cookie", &session_cookie).send().await;
+    assert_eq!(users.status, StatusCode::OK, "{}", users.text());
+    assert_eq!(users.json()["data"]["total"], 2);
+
+    let signed_out = server.delete("/admin/session").header("cookie", &cookies).header("x-csrf-token", &token).send().await;
+    assert_eq!(signed_out.status, StatusCode::NO_CONTENT);
+    assert!(signed_out.header("set-cookie").unwrap().contains("Max-Age=0"));
+    let stale = server.get("/api/admin/users").header("cookie", &session_cookie).send().await;
+    assert_eq!(stale.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_pagination_link_headers() {
+    let server = TestServer::new().await;
+    let admin = server.login_as("links_admin", UserRole::Admin).await;
+    for name in ["Tent", "Stove", "Lantern"] {
+        let created = server
+            .post("/api/items")
+            .bearer(&admin)
+            .json(&json!({"name": name, "tags": ["outdoor gear", "café"]}))
+            .send()
+            .await;
+        assert_eq!(created.status, StatusCode::CREATED, "{}", created.text());
+    }
+
+    let search = server
+        .get("/api/items/search?tags=outdoor%20gear,caf%C3%A9&offset=1&limit=1&sort_by=name")
+        .bearer(&admin)
+        .send()
+        .await;
+    assert_eq!(search.status, StatusCode::OK, "{}", search.text());
+    let page = |offset: u64| {
+        format!("/api/items/search?tags=outdoor%20gear,caf%C3%A9&offset={}&limit=1&sort_by=name", offset)
+    };
+    assert_eq!(
+        search.header("link").unwrap(),
+        format!(
+            "<{}>; rel=\"first\", <{}>; rel=\"prev\", <{}>; rel=\"next\", <{}>; rel=\"last\"",
+            page(0),
+            page(0),
+            page(2),
+            page(2)
+        )
+    );
+    assert_eq!(search.header("x-total-count"), Some("3"));
+    assert_eq!(search.header("x-page"), Some("2"));
+    assert_eq!(search.header("x-per-page"), Some("1"));
+    let next = server.get(&page(2)).bearer(&admin).send().await;
+    assert_eq!(next.json()["data"]["items"].as_array().unwrap().len(), 1);
+    assert!(!next.header("link").unwrap().contains("rel=\"next\""));
+
+    let items = server.get("/api/v1/items?page_size=3").bearer(&admin).send().await;
+    assert_eq!(
+        items.header("link").unwrap(),
+        "</api/v1/items?page_size=3&page=1>; rel=\"first\", </api/v1/items?page_size=3&page=2>; rel=\"next\""
+    );
+    assert_eq!(items.json()["data"]["count"], 3);
+    let second = server.get("/api/v1/items?page_size=3&page=2").bearer(&admin).send().await;
+    assert!(!second.header("link").unwrap().contains("rel=\"next\""));
+    assert_eq!(second.header("x-total-count"), None);
+
+    let changes = server.get("/api/items/changes?limit=2").bearer(&admin).send().await;
+    let next_since = changes.json()["next_since"].as_u64().unwrap();
+    assert!(changes
+        .header("link")
+        .unwrap()
+        .ends_with(&format!("</api/items/changes?limit=2&since={}>; rel=\"next\"", next_since)));
+
+    let files = server.get("/api/files?limit=10").bearer(&admin).send().await;
+    assert_eq!(files.status, StatusCode::OK, "{}", files.text());
+    assert_eq!(files.header("x-total-count"), Some("0"));
+    assert_eq!(
+        files.header("link").unwrap(),
+        "</api/files?limit=10&offset=0>; rel=\"first\", </api/files?limit=10&offset=0>; rel=\"last\""
+    );
+
+    let jobs = server.get("/api/jobs?limit=5&status=completed").bearer(&admin).send().await;
+    assert_eq!(jobs.status, StatusCode::OK, "{}", jobs.text());
+    assert!(jobs.header("link").unwrap().starts_with("</api/jobs?limit=5&status=completed&offset=0>; rel=\"first\""));
+}
+
+#[tokio::test]
+async fn test_list_page_sizes_follow_pagination_config() {
+    let server = TestServer::new().await;
+    let admin = server.login_as("sizes_admin", UserRole::Admin).await;
+    for (uri, message) in [
+        ("/api/items?page_size=101", "page_size must be at most 100"),
+        ("/api/v2/items?page_size=1000", "page_size must be at most 100"),
+        ("/api/items/search?q=lamp&limit=500", "limit must be at most 100"),
+        ("/api/search?q=lamp&limit=101", "limit must be at most 100"),
+        ("/api/jobs?limit=101", "limit must be at most 100"),
+        ("/api/files?limit=101", "limit must be at most 100"),
+    ] {
+        let refused = server.get(uri).bearer(&admin).send().await;
+        assert_eq!(refused.status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert!(refused.text().contains(message), "{}: {}", uri, refused.text());
+    }
+    let files = server.get("/api/files").bearer(&admin).send().await;
+    assert_eq!(files.json()["limit"], 50);
+
+    let server = TestServer::with_config(|config| {
+        config.pagination.default_page_size = 1;
+        config.pagination.max_page_size = 2;
+        config.pagination.max_export_items = 1;
+        config.pagination.clamp_oversized = true;
+    })
+    .await;
+    let admin = server.login_as("clamp_admin", UserRole::Admin).await;
+    let default_page = server.get("/api/items").bearer(&admin).send().await;
+    assert_eq!(default_page.json()["data"]["page_size"], 1);
+    let clamped = server.get("/api/items?page_size=500").bearer(&admin).send().await;
+    assert_eq!(clamped.status, StatusCode::OK, "{}", clamped.text());
+    assert_eq!(clamped.json()["data"]["count"], 2);
+    let export = server.get("/api/items/export").bearer(&admin).send().await;
+    assert_eq!(export.json().as_array().unwrap().len(), 1);
+
+    let server = TestServer::with_config(|config| config.pagination.max_export_items = 1).await;
+    let export = server.get("/api/items/export").send().await;
+    assert_eq!(export.status, StatusCode::BAD_REQUEST);
+    assert!(export.text().contains("limited to 1 items"), "{}", export.text());
+}
+
+#[tokio::test]
+async fn test_dead_background_task_degrades_health() {
+    let server = TestServer::new().await;
+    let admin = server.login_as("task_admin", UserRole::Admin).await;
+    let mut socket = server.websocket("/ws", Some(&admin)).await;
+
+    let healthy = server.get("/health/background_tasks").send().await;
+    assert_eq!(healthy.json()["data"]["status"], "Healthy", "{}", healthy.text());
+
+    let supervisor = server.state().supervisor.clone().with_policy(RestartPolicy {
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(1),
+        max_restarts: 2,
+        window: Duration::from_secs(60),
+    });
+    supervisor
+        .spawn("poisoned_loop", || async { panic!("lock poisoned") })
+        .await
+        .unwrap();
+
+    let tasks = server.get("/api/system/tasks").send().await;
+    assert_eq!(tasks.status, StatusCode::OK);
+    assert_eq!(tasks.json()["data"]["dead_count"], 1);
+    let task = tasks.json()["data"]["tasks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|task| task["name"] == "poisoned_loop")
+        .cloned()
+        .unwrap();
+    assert_eq!(task["state"], "dead");
+    assert_eq!(task["restarts"], 2);
+    assert_eq!(task["last_error"], "lock poisoned");
+
+    let health = server.get("/health").send().await;
+    assert_eq!(health.json()["data"]["overall_status"], "Degraded", "{}", health.text());
+    assert_eq!(health.json()["data"]["components"]["background_tasks"]["status"], "Degraded");
+
+    loop {
+        match next_message(&mut socket).await {
+            WebSocketMessage::TaskDead { name, restarts, error } => {
+                assert_eq!((name.as_str(), restarts, error.as_str()), ("poisoned_loop", 2, "lock poisoned"));
+                break;
+            }
+            _ => continue,
+        }
+    }
+}
+
+async fn upload(
+    server: &TestServer,
+    token: &str,
+    uri: &str,
+    filename: &str,
+    content_type: &str,
+    data: &str,
+) -> serde_json::Value {
+    let (content_type, body) = multipart_file(filename, content_type, data);
+    let uploaded = server.post(uri).bearer(token).body(&content_type, body).send().await;
+    assert_eq!(uploaded.status, StatusCode::OK, "{}", uploaded.text());
+    uploaded.json()
+}
+
+/// Path parameters filled in with values that exist nowhere, so requests
+/// that get past a guard reach the handler without changing anything.
+fn concrete_path(template: &str) -> String {
+    template
+        .split('/')
+        .map(|segment| match segment {
+            ":ip" => "192.0.2.1",
+            segment if segment.starts_with(':') => "424242",
+            segment => segment,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[tokio::test]
+async fn test_every_route_enforces_its_guard() {
+    let server = TestServer::with_config(|config| {
+        config.rate_limit.enable = false;
+        config.security.enable_anomaly_blocking = false;
+    })
+    .await;
+    let user = server.login_as("guard_user", UserRole::User).await;
+    let admin = server.login_as("guard_admin", UserRole::Admin).await;
+    let callers = [
+        (None, None),
+        (Some(UserRole::User), Some(user.as_str())),
+        (Some(UserRole::Admin), Some(admin.as_str())),
+    ];
+
+    let routes = core_lib::route_inventory();
+    let mut seen = std::collections::HashSet::new();
+    let mut mismatches = Vec::new();
+    for route in &routes {
+        assert!(seen.insert((&route.method, &route.path)), "{} {} is registered twice", route.method, route.path);
+
+        let method = axum::http::Method::from_bytes(route.method.as_bytes()).unwrap();
+        let uri = concrete_path(&route.path);
+        for (role, token) in &callers {
+            let mut request = server.request(method.clone(), &uri).json(&json!({}));
+            if let Some(token) = token {
+                request = request.bearer(token);
+            }
+            let status = request.send_status().await;
+
+            let expected = match (route.guard.admits(role.as_ref()), role) {
+                (true, _) => !matches!(
+                    status,
+                    StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::METHOD_NOT_ALLOWED
+                ),
+                (false, None) => status == StatusCode::UNAUTHORIZED,
+                (false, Some(_)) => status == StatusCode::FORBIDDEN,
+            };
+            if !expected {
+                mismatches.push(format!("{} {} ({:?}) as {:?}: {}", route.method, uri, route.guard, role, status));
+            }
+        }
+    }
+    assert!(mismatches.is_empty(), "routes not guarded as declared:\n{}", mismatches.join("\n"));
+}
+
+#[tokio::test]
+async fn test_route_inventory_is_listed_for_admins() {
+    let server = TestServer::new().await;
+    let user = server.login_as("routes_user", UserRole::User).await;
+    let admin = server.login_as("routes_admin", UserRole::Admin).await;
+
+    assert_eq!(server.get("/api/admin/routes").send().await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(server.get("/api/admin/routes").bearer(&user).send().await.status, StatusCode::FORBIDDEN);
+
+    let response = server.get("/api/admin/routes").bearer(&admin).send().await;
+    assert_eq!(response.status, StatusCode::OK);
+    let data = &response.json()["data"];
+    let routes = data["routes"].as_array().unwrap();
+    assert_eq!(data["total"], routes.len());
+    assert_eq!(routes.len(), core_lib::route_inventory().len());
+    for expected in [
+        json!({"method": "GET", "path": "/api/admin/routes", "guard": "admin"}),
+        json!({"method": "GET", "path": "/api/items/:id/comments", "guard": "public"}),
+        json!({"method": "POST", "path": "/api/items/:id/comments", "guard": "authenticated"}),
+        json!({"method": "POST", "path": "/api/cache/clear", "guard": "admin"}),
+    ] {
+        assert!(routes.contains(&expected), "missing {}", expected);
+    }
+}
+
+#[tokio::test]
+async fn test_subsystems_can_be_switched_off_at_runtime() {
+    let server = TestServer::with_config(|config| config.health.cache_ttl_ms = 0).await;
+    let admin = server.login_as("features_admin", UserRole::Admin).await;
+    let job = json!({ "job_type": "BulkImport", "payload": { "data": [{ "name": "x" }] } });
+    let switch = |name: &str, enabled: bool| {
+        server
+            .post(&format!("/api/admin/features/{}", name))
+            .bearer(&admin)
+            .json(&json!({ "enabled": enabled }))
+    };
+
+    let switched = switch("jobs", false).send().await;
+    assert_eq!(switched.status, StatusCode::OK, "{}", switched.text());
+    assert_eq!(switched.json()["data"]["was_enabled"], true);
+    assert_eq!(switch("websockets", false).send_status().await, StatusCode::OK);
+    assert_eq!(switch("files", false).send_status().await, StatusCode::NOT_FOUND);
+
+    assert_eq!(server.post("/api/jobs").json(&job).send_status().await, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(server.get("/api/events?topic=items").send_status().await, StatusCode::SERVICE_UNAVAILABLE);
+
+    let root = server.get("/").send().await.json();
+    assert_eq!(root["data"]["features"], json!({ "jobs": false, "search": true, "websockets": false }));
+    assert_eq!(root["data"]["websocket_enabled"], false);
+    assert!(root["data"]["endpoints"].get("jobs").is_none());
+
+    let health = server.get("/health").send().await;
+    assert_eq!(health.status, StatusCode::OK);
+    let features = &health.json()["data"]["components"]["features"];
+    assert_eq!(features["status"], "Degraded", "{}", features);
+    assert_eq!(features["message"], "Switched off: jobs, websockets");
+
+    assert_eq!(switch("jobs", true).send_status().await, StatusCode::OK);
+    assert_eq!(switch("websockets", true).send_status().await, StatusCode::OK);
+    assert_eq!(server.post("/api/jobs").json(&job).send_status().await, StatusCode::CREATED);
+    let listed = server.get("/api/admin/features").bearer(&admin).send().await;
+    assert_eq!(listed.json()["data"]["features"], json!({ "jobs": true, "search": true, "websockets": true }));
+    let health = server.get("/health").send().await.json();
+    assert_eq!(health["data"]["components"]["features"]["status"], "Healthy");
+}
+
+#[tokio::test]
+async fn test_startup_report_lists_every_subsystem() {
+    let server = TestServer::new().await;
+    let admin = server.login_as("startup_admin", UserRole::Admin).await;
+    let user = server.login_as("startup_user", UserRole::User).await;
+    assert_eq!(server.get("/api/admin/startup-report").bearer(&user).send_status().await, StatusCode::FORBIDDEN);
+
+    let response = server.get("/api/admin/startup-report").bearer(&admin).send().await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let data = &response.json()["data"];
+    assert_eq!(data["degraded"], false);
+    assert_eq!(data["report"]["strict"], true);
+    let outcomes = data["report"]["outcomes"].as_array().unwrap();
+    let status = |component: &str| {
+        outcomes
+            .iter()
+            .find(|outcome| outcome["component"] == component)
+            .map(|outcome| outcome["status"].clone())
+            .unwrap_or_else(|| panic!("no outcome for {}", component))
+    };
+    for component in ["database", "migrations", "auth", "files", "jobs", "cache", "websocket", "search"] {
+        assert_eq!(status(component), "OK", "{}", component);
+    }
+    assert_eq!(outcomes.len(), 10);
+}
+
+#[tokio::test]
+async fn test_users_move_to_another_instance_with_their_passwords() {
+    const PASSPHRASE: &str = "correct horse battery staple";
+    let source = TestServer::new().await;
+    let source_admin = source.login_as("move_admin", UserRole::Admin).await;
+    let carol = source.login_as("carol", UserRole::User).await;
+
+    let export = |token: &str| {
+        source
+            .post("/api/admin/users/export")
+            .bearer(token)
+            .header("x-bundle-passphrase", PASSPHRASE)
+            .send()
+    };
+    assert_eq!(export(&carol).await.status, StatusCode::FORBIDDEN);
+    let bundle = export(&source_admin).await;
+    assert_eq!(bundle.status, StatusCode::OK, "{}", bundle.text());
+    assert_eq!(bundle.header("content-type"), Some("application/x-ndjson"));
+    assert!(!bundle.text().contains("carol"));
+
+    let target = TestServer::new().await;
+    let target_admin = target.login_as("move_admin", UserRole::Admin).await;
+    let import = |query: &str, passphrase: &str| {
+        target
+            .post(&format!("/api/admin/users/import{}", query))
+            .bearer(&target_admin)
+            .header("x-bundle-passphrase", passphrase)
+            .body("application/x-ndjson", bundle.body.clone())
+            .send()
+    };
+
+    assert_eq!(import("", "not the passphrase").await.status, StatusCode::BAD_REQUEST);
+    assert_eq!(import("?on_conflict=skip", PASSPHRASE).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(import("?allow_admins=true", PASSPHRASE).await.status, StatusCode::CONFLICT);
+
+    let imported = import("?allow_admins=true&on_conflict=skip", PASSPHRASE).await;
+    assert_eq!(imported.status, StatusCode::OK, "{}", imported.text());
+    let report = &imported.json()["data"];
+    assert_eq!(report["imported"], json!(["carol"]));
+    assert_eq!(report["skipped"][0]["username"], "move_admin");
+
+    let login = target
+        .post("/auth/login")
+        .json(&json!({"username_or_email": "carol", "password": TEST_PASSWORD}))
+        .send()
+        .await;
+    assert_eq!(login.status, StatusCode::OK, "{}", login.text());
+}
+
+#[tokio::test]
+async fn test_list_preferences_apply_to_lists_unless_overridden() {
+    let server = TestServer::new().await;
+    let dana = server.login_as("dana", UserRole::User).await;
+    let erin = server.login_as("erin", UserRole::User).await;
+    for n in 1..=3 {
+        let created = server.post("/api/items").json(&json!({"name": format!("Zz {}", n)})).send().await;
+        assert_eq!(created.status, StatusCode::CREATED, "{}", created.text());
+    }
+
+    let current = server.get("/auth/me/preferences").bearer(&dana).send().await;
+    assert_eq!(current.status, StatusCode::OK, "{}", current.text());
+    assert_eq!(current.json()["items"], json!({"page_size": null, "sort": null, "include": []}));
+
+    for invalid in [
+        json!({"items": {"page_size": 100_000}}),
+        json!({"jobs": {"sort": "colour"}}),
+        json!({"files": {"include": ["comment_counts"]}}),
+    ] {
+        let refused = server.put("/auth/me/preferences").bearer(&dana).json(&invalid).send().await;
+        assert_eq!(refused.status, StatusCode::BAD_REQUEST, "{}", invalid);
+    }
+
+    let saved = server
+        .put("/auth/me/preferences")
+        .bearer(&dana)
+        .json(&json!({"items": {"page_size": 2, "sort": "-name"}, "jobs": {"page_size": 1}}))
+        .send()
+        .await;
+    assert_eq!(saved.status, StatusCode::OK, "{}", saved.text());
+    assert_eq!(saved.json()["items"]["sort"], "-name");
+
+    let listed = server.get("/api/items").bearer(&dana).send().await.json();
+    assert_eq!(listed["data"]["page_size"], 2);
+    assert_eq!(listed["data"]["items"][0]["name"], "Zz 3");
+
+    let other = server.get("/api/items").bearer(&erin).send().await.json();
+    assert_eq!(other["data"]["page_size"], 50);
+
+    let explicit = server.get("/api/items?page_size=3&sort=name").bearer(&dana).send().await.json();
+    assert_eq!(explicit["data"]["page_size"], 3);
+    assert_ne!(explicit["data"]["items"][0]["name"], "Zz 3");
+
+    let jobs = server.get("/api/jobs").bearer(&dana).send().await;
+    assert_eq!(jobs.status, StatusCode::OK, "{}", jobs.text());
+    assert_eq!(jobs.json()["data"]["limit"], 1);
+}
@@ -10,19 +10,21 @@ use tempfile::NamedTempFile;
 use sqlx::Row;
 use std::env;
 
-async fn setup_test_database() -> sqlx::SqlitePool {
+/// A migrated database, with the file holding it, which is deleted when
+/// dropped.
+async fn setup_test_database() -> (sqlx::SqlitePool, NamedTempFile) {
     let temp_file = NamedTempFile::new().unwrap();
     let database_url = format!("sqlite:{}", temp_file.path().display());
     
     let pool = get_database_pool(&database_url).await.unwrap();
     run_migrations(pool.clone()).await.unwrap();
     
-    pool
+    (pool, temp_file)
 }
 
 #[tokio::test]
 async fn test_basic_database_operations() {
-    let pool = setup_test_database().await;
+    let (pool, _db) = setup_test_database().await;
     
     let result = sqlx::query("SELECT 1").fetch_one(&pool).await;
     assert!(result.is_ok());
@@ -41,7 +43,7 @@ async fn test_basic_database_operations() {
 
 #[tokio::test]
 async fn test_item_repository_operations() {
-    let pool = setup_test_database().await;
+    let (pool, _db) = setup_test_database().await;
     let item_repository = ItemRepository::new(pool.clone());
     
     let create_input = CreateItemInput {
@@ -68,6 +70,7 @@ async fn test_item_repository_operations() {
         description: Some("Updated Description".to_string()),
         tags: vec!["updated".to_string()],
         metadata: Some(serde_json::json!({"updated": true})),
+        expected_version: None,
     };
     
     let updated_item = item_repository.update(created_item.id as i64, update_input).await.unwrap();
@@ -92,7 +95,7 @@ async fn test_item_repository_operations() {
 
 #[tokio::test]
 async fn test_user_repository_operations() {
-    let pool = setup_test_database().await;
+    let (pool, _db) = setup_test_database().await;
     let user_repository = UserRepository::new(pool.clone());
     
     user_repository.ensure_tables_exist().await.unwrap();
@@ -104,7 +107,7 @@ async fn test_user_repository_operations() {
         role: Some(core_lib::auth::models::UserRole::User),
     };
     
-    let created_user = user_repository.create_user(&create_request, "hashed_password").await.unwrap();
+    let created_user = user_repository.create_user(&create_request, "hashed_password", true).await.unwrap();
     assert_eq!(created_user.username, "testuser");
     assert_eq!(created_user.email, "test@example.com");
     assert!(created_user.id > 0);
@@ -143,7 +146,7 @@ async fn test_user_repository_operations() {
 
 #[tokio::test]
 async fn test_auth_service_integration() {
-    let pool = setup_test_database().await;
+    let (pool, _db) = setup_test_database().await;
     
     env::set_var("JWT_SECRET", "test_secret_key_for_auth_integration_1234567890123456789012345678901234567890");
     let jwt_service = JwtService::new().unwrap();
@@ -190,7 +193,7 @@ async fn test_auth_service_integration() {
 
 #[tokio::test]
 async fn test_database_transactions() {
-    let pool = setup_test_database().await;
+    let (pool, _db) = setup_test_database().await;
     let item_repository = ItemRepository::new(pool.clone());
     
     let mut tx = pool.begin().await.unwrap();
@@ -237,7 +240,7 @@ async fn test_database_transactions() {
 
 #[tokio::test]
 async fn test_concurrent_database_operations() {
-    let pool = setup_test_database().await;
+    let (pool, _db) = setup_test_database().await;
     let item_repository = ItemRepository::new(pool.clone());
     
     let mut handles = Vec::new();
@@ -279,7 +282,7 @@ async fn test_concurrent_database_operations() {
 
 #[tokio::test]
 async fn test_database_manager_health_check() {
-    let pool = setup_test_database().await;
+    let (pool, _db) = setup_test_database().await;
     let db_manager = DatabaseManager::new(pool);
     
     let health_result = db_manager.health_check().await;
@@ -110,4 +110,102 @@ async fn test_email_validation_fixes() {
         Ok(_) => println!("✅ PASSED: Valid email accepted"),
         Err(e) => println!("❌ FAILED: Valid email rejected: {:?}", e),
     }
-}
\ No newline at end of file
+}
+mod protected_routes {
+    use super::*;
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{Request, StatusCode},
+        Router,
+    };
+    use std::net::SocketAddr;
+    use core_lib::{auth::models::LoginRequest, create_app_with_config, AppConfig, AppState};
+    use tower::ServiceExt;
+
+    /// Serves the full application router; the returned file keeps the
+    /// database alive for the duration of the test.
+    async fn wired_app() -> (Router, AuthService, NamedTempFile) {
+        let temp_file = NamedTempFile::new().unwrap();
+        let pool = get_database_pool(&format!("sqlite:{}", temp_file.path().display())).await.unwrap();
+        run_migrations(pool.clone()).await.unwrap();
+
+        env::set_var("JWT_SECRET", "test_secret_key_1234567890123456789012345678901234567890");
+        let auth_service = AuthService::new(UserRepository::new(pool), JwtService::new().unwrap());
+
+        let mut config = AppConfig::default();
+        config.rate_limit.enable = false;
+        let app = create_app_with_config(AppState::default().with_auth(auth_service.clone()), config);
+        (app, auth_service, temp_file)
+    }
+
+    async fn register_and_login(auth_service: &AuthService, username: &str, role: UserRole) -> (i64, String) {
+        let user = auth_service
+            .register_user(CreateUserRequest {
+                username: username.to_string(),
+                email: format!("{}@example.com", username),
+                password: "StrongPass123!".to_string(),
+                role: Some(role),
+            })
+            .await
+            .unwrap();
+        let login = auth_service
+            .login(LoginRequest {
+                username: username.to_string(),
+                password: "StrongPass123!".to_string(),
+            })
+            .await
+            .unwrap();
+        (user.id, login.access_token)
+    }
+
+    fn get(uri: &str, token: Option<&str>) -> Request<Body> {
+        let mut request = Request::get(uri).header("user-agent", "auth-route-tests");
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let mut request = request.body(Body::empty()).unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+        request
+    }
+
+    #[tokio::test]
+    async fn test_me_uses_real_app_state() {
+        let (app, auth_service, _db) = wired_app().await;
+        let (_, token) = register_and_login(&auth_service, "meuser", UserRole::User).await;
+
+        let response = app.clone().oneshot(get("/auth/me", Some(&token))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let user: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(user["username"], "meuser");
+
+        let response = app.clone().oneshot(get("/auth/me", Some("not-a-jwt"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app.oneshot(get("/auth/me", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_user_lookup_requires_self_or_admin() {
+        let (app, auth_service, _db) = wired_app().await;
+        let (alice_id, alice_token) = register_and_login(&auth_service, "alice", UserRole::User).await;
+        let (bob_id, _) = register_and_login(&auth_service, "bob", UserRole::User).await;
+        let (_, admin_token) = register_and_login(&auth_service, "admin", UserRole::Admin).await;
+
+        let own = app.clone().oneshot(get(&format!("/auth/users/{}", alice_id), Some(&alice_token))).await.unwrap();
+        assert_eq!(own.status(), StatusCode::OK);
+
+        let other = app.clone().oneshot(get(&format!("/auth/users/{}", bob_id), Some(&alice_token))).await.unwrap();
+        assert_eq!(other.status(), StatusCode::FORBIDDEN);
+
+        let as_admin = app.clone().oneshot(get(&format!("/auth/users/{}", bob_id), Some(&admin_token))).await.unwrap();
+        assert_eq!(as_admin.status(), StatusCode::OK);
+
+        let anonymous = app.oneshot(get(&format!("/auth/users/{}", bob_id), None)).await.unwrap();
+        assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -1,8 +1,8 @@
 //! Main entry point for the HTTP server binary
 
 use anyhow::Result;
-use core_lib::{create_app_with_config, run_server, AppState, AppConfig, DatabaseManager, ItemRepository, get_database_pool, run_migrations, WebSocketManager, JwtService, FileManager, FileRepository, FileManagerConfig, CacheManager, AuthService, UserRepository, JobQueue};
-use std::net::SocketAddr;
+use core_lib::startup::{Component, StartupReport};
+use core_lib::{create_app_with_config, get_database_pool, AppConfig, AppState, AppStateBuilder};
 use tracing::info;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
@@ -10,8 +10,12 @@ use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 async fn main() -> Result<()> {
     init_tracing();
 
-    let config = AppConfig::load()
+    let mut config = AppConfig::load()
         .map_err(|e| anyhow::anyhow!("Failed to load configuration: {}", e))?;
+    // Deployments that predate `auth.jwt_secret` set the secret this way.
+    if let Ok(secret) = std::env::var("JWT_SECRET") {
+        config.auth.jwt_secret = secret;
+    }
 
     info!("Configuration loaded successfully");
     info!("Server will bind to: {}", config.bind_address());
@@ -20,136 +24,84 @@ async fn main() -> Result<()> {
     config.create_directories()
         .map_err(|e| anyhow::anyhow!("Failed to create directories: {}", e))?;
 
-    let addr: SocketAddr = config.bind_address().parse()
-        .map_err(|e| anyhow::anyhow!("Invalid bind address: {}", e))?;
-
     info!("Initializing Rust HTTP Server");
     info!("Environment: {}", std::env::var("RUST_ENV").unwrap_or_else(|_| "development".to_string()));
 
-    let rate_limiter = if config.rate_limit.enable {
-        core_lib::middleware::rate_limit::RateLimiter::new(config.rate_limit.clone())
-    } else {
-        core_lib::middleware::rate_limit::RateLimiter::new(core_lib::config::RateLimitConfig::default())
-    };
+    let mut startup = StartupReport::new(config.server.strict_startup());
+    core_lib::database::instrumented::set_slow_query_threshold(std::time::Duration::from_millis(
+        config.database.slow_query_threshold_ms,
+    ));
 
-    let state = if config.database.url != "sqlite::memory:" && !config.database.url.is_empty() {
+    let pool = if config.database.url != "sqlite::memory:" && !config.database.url.is_empty() {
         info!("Initializing database connection: {}", config.database.url);
-        
-        match initialize_database(&config.database.url).await {
-            Ok((db_manager, item_repository, file_manager, user_repository, job_repository)) => {
-                info!("Database initialized successfully");
-                let mut state = AppState::with_database(db_manager.clone(), item_repository).with_rate_limiter(rate_limiter.clone());
-                
-                if let Err(e) = state.migrate_to_database_if_needed().await {
-                    tracing::warn!("Failed to migrate data to database: {}", e);
-                }
-                
-                let jwt_service = match JwtService::new() {
-                    Ok(jwt) => {
-                        info!("JWT service initialized");
-                        jwt
-                    }
-                    Err(e) => {
-                        tracing::warn!("Failed to initialize JWT service: {}", e);
-                        JwtService::new().unwrap_or_else(|_| panic!("Failed to create default JWT service"))
-                    }
-                };
-                
-                let auth_service = AuthService::new(user_repository, jwt_service.clone());
-                state = state.with_auth(auth_service);
-                info!("Auth service initialized");
-                
-                state = state.with_file_manager(file_manager);
-                info!("File manager initialized");
-                
-                let websocket_manager = WebSocketManager::new(Some(jwt_service));
-                state = state.with_websocket(websocket_manager.clone());
-                info!("WebSocket manager initialized");
-                
-                let job_queue = state.create_job_queue_with_websocket(job_repository).await
-                    .unwrap_or_else(|e| {
-                        tracing::warn!("Failed to create job queue: {}", e);
-                        JobQueue::new(core_lib::jobs::JobRepository::new(db_manager.pool().clone()))
-                    });
-                state = state.with_job_queue(job_queue);
-                info!("Job queue initialized");
-                
-                let cache_manager = CacheManager::default();
-                state = state.with_cache_manager(cache_manager);
-                info!("Cache manager initialized");
-                
-                state = state.with_health_checker();
-                info!("Health checker initialized");
-                
-                state = state.with_system_monitor();
-                info!("System monitor initialized");
-                
-                state
-            }
+        match get_database_pool(&config.database.url).await {
+            Ok(pool) => Some(pool),
             Err(e) => {
-                tracing::warn!("Failed to initialize database, falling back to in-memory store: {}", e);
-                let mut state = AppState::default().with_rate_limiter(rate_limiter.clone());
-                
-                let websocket_manager = WebSocketManager::new(None);
-                state = state.with_websocket(websocket_manager);
-                info!("WebSocket manager initialized (no auth)");
-                
-                let cache_manager = CacheManager::default();
-                state = state.with_cache_manager(cache_manager);
-                info!("Cache manager initialized");
-                
-                state = state.with_health_checker();
-                info!("Health checker initialized");
-                
-                state = state.with_system_monitor();
-                info!("System monitor initialized");
-                
-                state
+                tracing::warn!("Failed to open the database, falling back to in-memory store: {}", e);
+                startup.failed(Component::Database, true, &e, Some("the in-memory store"))?;
+                None
             }
         }
     } else {
         info!("Using in-memory data store");
-        let mut state = AppState::default().with_rate_limiter(rate_limiter.clone());
-        
-        let websocket_manager = WebSocketManager::new(None);
-        state = state.with_websocket(websocket_manager);
-        info!("WebSocket manager initialized (no auth)");
-        
-        let cache_manager = CacheManager::default();
-        state = state.with_cache_manager(cache_manager);
-        info!("Cache manager initialized");
-        
-        state = state.with_health_checker();
-        info!("Health checker initialized");
-        
-        state = state.with_system_monitor();
-        info!("System monitor initialized");
-        
-        state
+        None
     };
 
+    let mut builder = AppStateBuilder::new(config.clone()).with_startup_report(startup);
+    if let Some(pool) = pool {
+        builder = builder.with_database(pool);
+    }
+    let state = builder.build().await?;
+    if let Some(startup) = &state.startup_report {
+        startup.log();
+    }
+
+    if let (Some(db_manager), Some(collector), true) = (
+        &state.db_manager,
+        state.metrics.in_memory(),
+        config.metrics.traffic_persist_interval_seconds > 0,
+    ) {
+        core_lib::monitoring::traffic::spawn_persistence(
+            core_lib::monitoring::traffic::TrafficStore::new(db_manager.pool().clone()),
+            collector.clone(),
+            std::time::Duration::from_secs(config.metrics.traffic_persist_interval_seconds),
+            &state.supervisor,
+        )
+        .await;
+    }
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("seed") {
+        return run_seed(&state, &config.database.url, &args[1..]).await;
+    }
+    if args.first().map(String::as_str) == Some("export-users") {
+        return run_export_users(&state, &config, &args[1..]).await;
+    }
+    if args.first().map(String::as_str) == Some("import-users") {
+        return run_import_users(&state, &config, &args[1..]).await;
+    }
+
     info!("App: {} v{}", state.app_name, state.version);
     info!("Data storage: {}", if state.item_service.is_using_database() { "SQLite Database" } else { "In-Memory Store" });
 
-    if let Some(ws_manager) = &state.websocket_manager {
-        let ws_manager_clone = ws_manager.clone();
-        let metrics_clone = state.metrics.clone();
-        let item_service_clone = state.item_service.clone();
+    // Only the in-memory backend has snapshots to broadcast.
+    if let (Some(ws_manager), true) = (&state.websocket_manager, state.metrics.in_memory().is_some()) {
+        let ws_manager = ws_manager.clone();
+        let metrics = state.metrics.clone();
         
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(5));
-            loop {
-                interval.tick().await;
-                
-                if ws_manager_clone.connection_count().await > 0 {
-                    let item_count = match item_service_clone.get_stats().await {
-                        Ok(stats) => stats.get("total_items").and_then(|v| v.as_u64()).unwrap_or(0) as usize,
-                        Err(_) => 0,
-                    };
+        state.supervisor.spawn("metrics_broadcast", move || {
+            let ws_manager_clone = ws_manager.clone();
+            let metrics_clone = metrics.clone();
+            async move {
+                let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(5));
+                loop {
+                    interval.tick().await;
                     
-                    let metrics_snapshot = metrics_clone.get_snapshot(item_count);
-                    let event = core_lib::websocket::WebSocketEvent::MetricsUpdate(metrics_snapshot);
-                    ws_manager_clone.broadcast(event).await;
+                    if ws_manager_clone.connection_count().await > 0 {
+                        let Some(metrics_snapshot) = metrics_clone.snapshot() else { continue };
+                        let event = core_lib::websocket::WebSocketEvent::MetricsUpdate(metrics_snapshot);
+                        ws_manager_clone.broadcast(event).await;
+                    }
                 }
             }
         });
@@ -157,53 +109,284 @@ async fn main() -> Result<()> {
         info!("Started metrics broadcasting task (every 5 seconds)");
     }
 
+    if let (Some(health_checker), true) = (&state.health_checker, config.health.check_interval_seconds > 0) {
+        let check_interval = config.health.check_interval_seconds;
+        health_checker.clone().spawn_monitor(&state.supervisor, tokio::time::Duration::from_secs(check_interval));
+        info!("Started health monitor (every {} seconds)", check_interval);
+    }
+
     if config.rate_limit.enable {
         let cleanup_interval = config.rate_limit.cleanup_interval_seconds;
-        let rate_limiter_cleanup = rate_limiter.clone();
+        let rate_limiter = state.rate_limiter.clone();
         
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(cleanup_interval));
-            loop {
-                interval.tick().await;
-                rate_limiter_cleanup.cleanup_expired();
-                tracing::debug!("Rate limiter cleanup completed");
+        state.supervisor.spawn("rate_limiter_cleanup", move || {
+            let rate_limiter_cleanup = rate_limiter.clone();
+            async move {
+                let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(cleanup_interval));
+                loop {
+                    interval.tick().await;
+                    rate_limiter_cleanup.cleanup_expired().await;
+                    tracing::debug!("Rate limiter cleanup completed");
+                }
             }
         });
         
         info!("Started rate limiter cleanup task (every {} seconds)", cleanup_interval);
     }
 
-    let app = create_app_with_config(state, config.clone());
+    if config.server.config_reload_interval_seconds > 0 {
+        let reload_interval = config.server.config_reload_interval_seconds;
+        let rate_limiter_reload = state.rate_limiter.clone();
+        let item_schema_reload = state.item_schema.clone();
+
+        core_lib::config::spawn_config_watcher(
+            &state.supervisor,
+            core_lib::config::CONFIG_FILE,
+            tokio::time::Duration::from_secs(reload_interval),
+            move |new_config| {
+                rate_limiter_reload.update_config(new_config.rate_limit);
+                info!("Rate limit tiers and exemptions reloaded");
+                item_schema_reload.update_config(new_config.item_schema);
+                info!("Item metadata schema reloaded; it applies to new writes only");
+            },
+        );
+
+        info!("Watching {} for changes (every {} seconds)", core_lib::config::CONFIG_FILE, reload_interval);
+    }
+
+    if config.security.enable_anomaly_blocking {
+        let cleanup_interval = config.rate_limit.cleanup_interval_seconds;
+        let anomaly_tracker = state.anomaly_tracker.clone();
+
+        state.supervisor.spawn("anomaly_tracker_cleanup", move || {
+            let anomaly_tracker = anomaly_tracker.clone();
+            async move {
+                let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(cleanup_interval));
+                loop {
+                    interval.tick().await;
+                    anomaly_tracker.cleanup_expired();
+                    tracing::debug!("Anomaly tracker cleanup completed");
+                }
+            }
+        });
+
+        info!("Started anomaly tracker cleanup task (every {} seconds)", cleanup_interval);
+    }
+
+    if let Some(reconciler) = state.file_reconciler().filter(|_| config.files.reconcile.interval_minutes > 0) {
+        let reconcile_interval = tokio::time::Duration::from_secs(config.files.reconcile.interval_minutes * 60);
+
+        match &state.job_queue {
+            Some(job_queue) => {
+                job_queue.spawn_recurring(
+                    &state.supervisor,
+                    "file_reconciliation",
+                    core_lib::JobRequest {
+                        job_type: core_lib::JobType::FileReconciliation,
+                        payload: Default::default(),
+                        priority: Some(core_lib::JobPriority::Low),
+                        max_retries: Some(0),
+                    },
+                    reconcile_interval,
+                );
+            }
+            None => {
+                state.supervisor.spawn("file_reconciliation", move || {
+                    let reconciler = reconciler.clone();
+                    async move {
+                        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + reconcile_interval, reconcile_interval);
+                        loop {
+                            interval.tick().await;
+                            if let Err(e) = reconciler.reconcile(false, "scheduler").await {
+                                tracing::warn!("File reconciliation failed: {}", e);
+                            }
+                        }
+                    }
+                });
+            }
+        }
+
+        info!(
+            "Started file reconciliation task (every {} minutes)",
+            config.files.reconcile.interval_minutes
+        );
+    }
+
+    if config.trash.purge_interval_minutes > 0 {
+        let purge_interval = tokio::time::Duration::from_secs(config.trash.purge_interval_minutes * 60);
+
+        match &state.job_queue {
+            Some(job_queue) => {
+                job_queue.spawn_recurring(
+                    &state.supervisor,
+                    "trash_purge",
+                    core_lib::JobRequest {
+                        job_type: core_lib::JobType::TrashPurge,
+                        payload: Default::default(),
+                        priority: Some(core_lib::JobPriority::Low),
+                        max_retries: Some(0),
+                    },
+                    purge_interval,
+                );
+            }
+            None => {
+                let purger = state.trash_purger();
+                state.supervisor.spawn("trash_purge", move || {
+                    let purger = purger.clone();
+                    async move {
+                        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + purge_interval, purge_interval);
+                        loop {
+                            interval.tick().await;
+                            if let Err(e) = purger.purge(false, "scheduler").await {
+                                tracing::warn!("Trash purge failed: {}", e);
+                            }
+                        }
+                    }
+                });
+            }
+        }
+
+        info!(
+            "Started trash purge task (every {} minutes, retention {} days)",
+            config.trash.purge_interval_minutes, config.trash.retention_days
+        );
+    }
+
+    #[cfg(feature = "grpc")]
+    {
+        let app = create_app_with_config(state.clone(), config.clone());
+        core_lib::run_server_with_grpc(app, state, &config.server).await?;
+    }
 
-    run_server(app, addr).await?;
+    #[cfg(not(feature = "grpc"))]
+    {
+        if config.server.grpc_port.is_some() {
+            tracing::warn!("server.grpc_port is set, but this build has no gRPC support (feature \"grpc\")");
+        }
+        let app = create_app_with_config(state, config.clone());
+        core_lib::run_server_with_config(app, &config.server).await?;
+    }
 
     info!("Server shutdown complete");
     Ok(())
 }
 
-async fn initialize_database(database_url: &str) -> Result<(DatabaseManager, ItemRepository, FileManager, UserRepository, core_lib::jobs::JobRepository)> {
-    let pool = get_database_pool(database_url).await
-        .map_err(|e| anyhow::anyhow!("Failed to create database pool: {}", e))?;
-
-    run_migrations(pool.clone()).await
-        .map_err(|e| anyhow::anyhow!("Failed to run database migrations: {}", e))?;
-
-    let db_manager = DatabaseManager::new(pool.clone());
-    let item_repository = ItemRepository::new(pool.clone());
-    let user_repository = UserRepository::new(pool.clone());
-    let job_repository = core_lib::jobs::JobRepository::new(pool.clone());
-    
-    job_repository.create_table().await
-        .map_err(|e| anyhow::anyhow!("Failed to initialize job repository: {}", e))?;
-    
-    let file_repository = FileRepository::new(pool);
-    let file_manager_config = FileManagerConfig::default();
-    let file_manager = FileManager::new(file_manager_config, file_repository);
-    
-    file_manager.initialize().await
-        .map_err(|e| anyhow::anyhow!("Failed to initialize file manager: {}", e))?;
-
-    Ok((db_manager, item_repository, file_manager, user_repository, job_repository))
+/// `server seed [--fixtures <file>] [--idempotent] [--wipe]` loads fixtures
+/// into the configured database and exits.
+async fn run_seed(state: &AppState, database_url: &str, args: &[String]) -> Result<()> {
+    let mut fixtures_path = None;
+    let mut options = core_lib::seed::SeedOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fixtures" => {
+                fixtures_path = Some(args.next().ok_or_else(|| anyhow::anyhow!("--fixtures needs a file"))?);
+            }
+            "--idempotent" => options.idempotent = true,
+            "--wipe" => options.wipe = true,
+            other => anyhow::bail!("Unknown seed option {}; expected --fixtures <file>, --idempotent or --wipe", other),
+        }
+    }
+
+    if !core_lib::seed::seeding_allowed() {
+        anyhow::bail!("Refusing to load fixtures with RUST_ENV=production");
+    }
+    let seeder = state
+        .seeder()
+        .ok_or_else(|| anyhow::anyhow!("Seeding requires the database, which {} did not open", database_url))?
+        .with_database_url(database_url);
+
+    let fixtures = match fixtures_path {
+        Some(path) => {
+            let json = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read fixtures {}: {}", path, e))?;
+            core_lib::seed::Fixtures::from_json(&json).map_err(|e| anyhow::anyhow!("{}", e))?
+        }
+        None => core_lib::seed::Fixtures::builtin(),
+    };
+
+    let report = seeder
+        .seed(&fixtures, options, "cli")
+        .await
+        .map_err(|e| anyhow::anyhow!("Seeding failed: {}", e))?;
+    info!(
+        "Seeding done{}: created {:?}, skipped {:?}",
+        if report.wiped { " after wipe" } else { "" },
+        report.created,
+        report.skipped
+    );
+    Ok(())
+}
+
+/// The passphrase of a user bundle, from the environment so that it stays
+/// out of the shell history and the process list.
+const BUNDLE_PASSPHRASE_VAR: &str = "BUNDLE_PASSPHRASE";
+
+fn bundle_passphrase() -> Result<String> {
+    std::env::var(BUNDLE_PASSPHRASE_VAR)
+        .ok()
+        .filter(|passphrase| !passphrase.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("Set {} to the bundle passphrase", BUNDLE_PASSPHRASE_VAR))
+}
+
+fn snapshot_service<'a>(state: &'a AppState, config: &AppConfig) -> Result<&'a core_lib::snapshot::SnapshotService> {
+    state.snapshots.as_ref().ok_or_else(|| {
+        anyhow::anyhow!("Moving users requires the database, which {} did not open", config.database.url)
+    })
+}
+
+/// `server export-users --out <file>` writes every user account, password
+/// hashes included, to a bundle encrypted under `BUNDLE_PASSPHRASE`.
+async fn run_export_users(state: &AppState, config: &AppConfig, args: &[String]) -> Result<()> {
+    let mut out_path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => out_path = Some(args.next().ok_or_else(|| anyhow::anyhow!("--out needs a file"))?),
+            other => anyhow::bail!("Unknown export-users option {}; expected --out <file>", other),
+        }
+    }
+    let out_path = out_path.ok_or_else(|| anyhow::anyhow!("export-users needs --out <file>"))?;
+
+    let (header, bundle) = snapshot_service(state, config)?
+        .export_users(&bundle_passphrase()?)
+        .await
+        .map_err(|e| anyhow::anyhow!("Exporting users failed: {}", e))?;
+    std::fs::write(out_path, &bundle).map_err(|e| anyhow::anyhow!("Failed to write {}: {}", out_path, e))?;
+    info!("Exported {} users to {}", header.users, out_path);
+    Ok(())
+}
+
+/// `server import-users --in <file> [--on-conflict skip|error]
+/// [--allow-admins]` adds the accounts in a bundle from `export-users`,
+/// keeping their password hashes.
+async fn run_import_users(state: &AppState, config: &AppConfig, args: &[String]) -> Result<()> {
+    let mut in_path = None;
+    let mut options = core_lib::snapshot::UserImportOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--in" => in_path = Some(args.next().ok_or_else(|| anyhow::anyhow!("--in needs a file"))?),
+            "--on-conflict" => {
+                let policy = args.next().ok_or_else(|| anyhow::anyhow!("--on-conflict needs skip or error"))?;
+                options.on_conflict = policy.parse().map_err(|e: String| anyhow::anyhow!(e))?;
+            }
+            "--allow-admins" => options.allow_admins = true,
+            other => anyhow::bail!(
+                "Unknown import-users option {}; expected --in <file>, --on-conflict skip|error or --allow-admins",
+                other
+            ),
+        }
+    }
+    let in_path = in_path.ok_or_else(|| anyhow::anyhow!("import-users needs --in <file>"))?;
+
+    let bundle = std::fs::read(in_path).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", in_path, e))?;
+    let report = snapshot_service(state, config)?
+        .import_users(&bundle, &bundle_passphrase()?, &options)
+        .await
+        .map_err(|e| anyhow::anyhow!("Importing users failed: {}", e))?;
+    info!("Imported users {:?}, skipped {:?}", report.imported, report.skipped);
+    Ok(())
 }
 
 fn init_tracing() {
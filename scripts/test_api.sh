@@ -637,15 +637,15 @@ run_test "Cache Health" \
     "contains:healthy" \
     "Should return cache health status"
 
-run_test "Cache Invalidation" \
-    "curl -s -X POST $BASE_URL/api/cache/invalidate -H 'Content-Type: application/json' -d '{\"pattern\": \"*\"}'" \
-    "contains:entries_removed" \
-    "Should invalidate cache entries"
+run_test "Cache Invalidation Requires Admin" \
+    "curl -s -X POST $BASE_URL/api/cache/invalidate -H 'Content-Type: application/json' -d '{\"pattern\": \"items:\"}'" \
+    "contains:Authentication required" \
+    "Should refuse to invalidate cache entries without an admin token"
 
-run_test "Cache Clear" \
+run_test "Cache Clear Requires Admin" \
     "curl -s -X POST $BASE_URL/api/cache/clear" \
-    "contains:entries_removed" \
-    "Should clear all cache entries"
+    "contains:Authentication required" \
+    "Should refuse to clear the cache without an admin token"
 
 echo
 echo "📝 FORM HANDLING TESTS"
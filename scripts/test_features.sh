@@ -617,15 +617,15 @@ if [[ -n "$CACHE_CHECK" && "$CACHE_CHECK" != "null" ]]; then
     fi
     TOTAL_TESTS=$((TOTAL_TESTS + 1))
     
-    run_test "Cache Invalidation" \
-        "curl -s -X POST '$BASE_URL/api/cache/invalidate' -H 'Content-Type: application/json' -d '{\"pattern\": \"items:*\"}'" \
-        "json_valid" \
-        "Should invalidate cache entries by pattern"
+    run_test "Cache Invalidation Requires Admin" \
+        "curl -s -X POST '$BASE_URL/api/cache/invalidate' -H 'Content-Type: application/json' -d '{\"pattern\": \"items:\"}'" \
+        "contains:Authentication required" \
+        "Should refuse to invalidate cache entries without an admin token"
     
-    run_test "Cache Clear" \
+    run_test "Cache Clear Requires Admin" \
         "curl -s -X POST '$BASE_URL/api/cache/clear'" \
-        "json_valid" \
-        "Should clear all cache entries"
+        "contains:Authentication required" \
+        "Should refuse to clear the cache without an admin token"
     
 else
     echo "   ⚠️  Cache management not available - skipping cache tests"
@@ -1286,12 +1286,12 @@ fi
 TOTAL_TESTS=$((TOTAL_TESTS + 1))
 
 if [[ -n "$JWT_TOKEN" && "$JWT_TOKEN" != "null" && "$JWT_TOKEN" != "" ]]; then
-    run_test "Cache Invalidation" \
-        "curl -s -X POST $BASE_URL/api/cache/invalidate -H 'Authorization: Bearer $JWT_TOKEN' -H 'Content-Type: application/json' -d '{\"pattern\": \"items:*\"}'" \
-        "contains:pattern" \
-        "Should invalidate cache entries"
+    run_test "Cache Invalidation Requires Admin" \
+        "curl -s -X POST $BASE_URL/api/cache/invalidate -H 'Authorization: Bearer $JWT_TOKEN' -H 'Content-Type: application/json' -d '{\"pattern\": \"items:\"}'" \
+        "contains:Admin access required" \
+        "Should refuse to invalidate cache entries for a regular user"
 else
-    skip_test "Cache Invalidation" "No valid JWT token available"
+    skip_test "Cache Invalidation Requires Admin" "No valid JWT token available"
 fi
 
 echo